@@ -1,13 +1,55 @@
 //! Command implementations for the Canopy CLI.
 
-use canopy_client::{ClientRuntime, IndexResult};
-use canopy_core::QueryParams;
+use canopy_client::{
+    client_context, ClientContext, ClientRuntime, IndexResult, RetryPolicy, SessionLog,
+};
+use canopy_core::protocol::AddRepoRequest;
+use canopy_core::{AutoInit, LineWindow, NodeType, QueryParams};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
 
-use crate::output::print_query_result;
-use crate::QueryArgs;
+use crate::output::{
+    print_bench_report, print_index_plan, print_query_result, print_replay_report,
+};
+use crate::render::ContentRenderer;
+use crate::{ExpandArgs, ExploreArgs, QueryArgs, SymbolsArgs};
+
+/// Auto-init policy from the global flags, applied to every runtime
+static AUTO_INIT: OnceLock<AutoInit> = OnceLock::new();
+
+static RETRY_POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+/// Session of this invocation: `CANOPY_SESSION_ID`, else a fresh id
+static SESSION_ID: OnceLock<String> = OnceLock::new();
+
+pub(crate) fn set_auto_init(auto_init: AutoInit) {
+    let _ = AUTO_INIT.set(auto_init);
+}
+
+pub(crate) fn set_retry_policy(policy: RetryPolicy) {
+    let _ = RETRY_POLICY.set(policy);
+}
 
 pub(crate) fn make_runtime(service_url: Option<&str>, api_key: Option<String>) -> ClientRuntime {
-    ClientRuntime::new(service_url, api_key)
+    let mut runtime = ClientRuntime::new(service_url, api_key);
+    runtime.set_auto_init(AUTO_INIT.get().copied().unwrap_or_else(AutoInit::off));
+    runtime.set_retry_policy(RETRY_POLICY.get().cloned().unwrap_or_default());
+    runtime.set_client_context(ClientContext::new(
+        Some("canopy-cli"),
+        Some(SESSION_ID.get_or_init(client_context::session_id_from_env)),
+    ));
+    runtime
+}
+
+/// Runtime for query/expand commands, with the session log attached when requested.
+fn make_logging_runtime(
+    service_url: Option<&str>,
+    api_key: Option<String>,
+    session_log: Option<&Path>,
+) -> ClientRuntime {
+    let mut runtime = make_runtime(service_url, api_key);
+    runtime.set_session_log(session_log.map(SessionLog::new));
+    runtime
 }
 
 pub(crate) fn detect_repo_root(
@@ -30,33 +72,115 @@ pub(crate) fn detect_repo_root(
     }
 }
 
-pub(crate) fn cmd_init(root: Option<std::path::PathBuf>) -> canopy_core::Result<()> {
-    use canopy_core::RepoIndex;
+pub(crate) fn cmd_init(
+    root: Option<std::path::PathBuf>,
+    preset: Option<canopy_core::Preset>,
+    force: bool,
+) -> canopy_core::Result<()> {
+    use canopy_core::{Preset, RepoIndex};
     use colored::Colorize;
 
     let repo_root = detect_repo_root(root)?;
-    RepoIndex::init(&repo_root)?;
+    let (preset, source) = match preset {
+        Some(preset) => (preset, "selected"),
+        None => (Preset::detect(&repo_root), "detected"),
+    };
+    let backup = RepoIndex::init_with_preset(&repo_root, preset, force)?;
 
-    println!("{} .canopy/config.toml", "Created".green());
+    if let Some(backup) = backup {
+        let name = backup.file_name().unwrap_or_default().to_string_lossy();
+        println!("{} old config to .canopy/{}", "Moved".yellow(), name);
+    }
+    println!(
+        "{} .canopy/config.toml ({} preset, {})",
+        "Created".green(),
+        preset,
+        source
+    );
     println!("{} .canopy/ to .gitignore", "Added".green());
     Ok(())
 }
 
+pub(crate) fn cmd_list_presets(json: bool) -> canopy_core::Result<()> {
+    use canopy_core::Preset;
+
+    if json {
+        let presets: Vec<serde_json::Value> = Preset::ALL
+            .into_iter()
+            .map(|preset| {
+                serde_json::json!({
+                    "name": preset.name(),
+                    "description": preset.description(),
+                    "default_glob": preset.config().indexing.default_glob,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&presets)?);
+    } else {
+        for preset in Preset::ALL {
+            println!(
+                "{:<8} {} ({})",
+                preset.name(),
+                preset.description(),
+                preset.config().indexing.default_glob
+            );
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn cmd_plan_index(
+    root: Option<std::path::PathBuf>,
+    glob: Option<String>,
+    verbose: bool,
+    json: bool,
+    service_url: Option<&str>,
+    api_key: Option<String>,
+) -> canopy_core::Result<()> {
+    let repo_root = detect_repo_root(root)?;
+    let mut runtime = make_runtime(service_url, api_key);
+    let plan = runtime.plan_index(&repo_root, glob.as_deref())?;
+    print_index_plan(&plan, verbose, json)
+}
+
 pub(crate) fn cmd_index(
     root: Option<std::path::PathBuf>,
     glob: Option<String>,
+    stdin_paths: bool,
+    // `--git-history`, with its `--since` window
+    git_history: Option<Option<std::time::Duration>>,
     json: bool,
     service_url: Option<&str>,
     api_key: Option<String>,
 ) -> canopy_core::Result<()> {
     use colored::Colorize;
+    use std::io::BufRead;
 
     let repo_root = detect_repo_root(root)?;
     let mut runtime = make_runtime(service_url, api_key);
-    let result = runtime.index(&repo_root, glob.as_deref())?;
+    if let Some(since) = git_history.filter(|_| runtime.is_service_mode()) {
+        // Refused before the service is asked to reindex
+        runtime.index_history(&repo_root, since)?;
+    }
+    let result = if stdin_paths {
+        let mut paths = Vec::new();
+        for line in std::io::stdin().lock().lines() {
+            let line = line?;
+            let line = line.trim();
+            if !line.is_empty() {
+                paths.push(std::path::PathBuf::from(line));
+            }
+        }
+        runtime.index_paths(&repo_root, &paths)?
+    } else {
+        runtime.index(&repo_root, glob.as_deref())?
+    };
 
     match result {
-        IndexResult::Local(stats) => {
+        IndexResult::Local(mut stats) => {
+            if let Some(since) = git_history {
+                stats.commits_indexed += runtime.index_history(&repo_root, since)?;
+            }
             if json {
                 println!("{}", serde_json::to_string_pretty(&stats)?);
             } else {
@@ -66,11 +190,67 @@ pub(crate) fn cmd_index(
                     stats.files_indexed,
                     stats.total_tokens
                 );
+                if stats.files_degraded > 0 {
+                    println!(
+                        "{}: {} files failed to parse, indexed as plain text (see `canopy status --verbose`)",
+                        "Degraded".yellow(),
+                        stats.files_degraded
+                    );
+                }
+                if stats.fts_bytes_skipped > 0 {
+                    println!(
+                        "{}: {:.1} MB of node content full-text indexed as samples ({:.1} MB written)",
+                        "Sampled".yellow(),
+                        stats.fts_bytes_skipped as f64 / 1_000_000.0,
+                        stats.fts_bytes_written as f64 / 1_000_000.0
+                    );
+                }
+                if stats.files_generated > 0 {
+                    println!(
+                        "{}: {} files ({} tokens), left out of queries unless --include-generated",
+                        "Generated".yellow(),
+                        stats.files_generated,
+                        stats.generated_tokens
+                    );
+                }
                 println!(
-                    "{}: {} files (cache hit)",
+                    "{}: {} files (cache hit: {} ttl, {} mtime, {} hash)",
                     "Skipped".yellow(),
-                    stats.files_skipped
+                    stats.files_skipped,
+                    stats.skipped.ttl,
+                    stats.skipped.mtime,
+                    stats.skipped.hash
                 );
+                if stats.files_removed > 0 {
+                    println!(
+                        "{}: {} files no longer on disk",
+                        "Removed".yellow(),
+                        stats.files_removed
+                    );
+                }
+                for error in &stats.errors {
+                    println!("{}: {} ({})", "Not indexed".red(), error.path, error.reason);
+                }
+                if stats.moves_fixed > 0 {
+                    println!(
+                        "{}: {} moved files fixed up (old handles aliased)",
+                        "Moves".green(),
+                        stats.moves_fixed
+                    );
+                }
+                if stats.commits_indexed > 0 {
+                    println!(
+                        "{}: {} commits added to the git-history layer",
+                        "History".green(),
+                        stats.commits_indexed
+                    );
+                }
+                if stats.fts_optimized {
+                    println!(
+                        "{}: full-text index merged after repeated reindexing",
+                        "Optimized".green()
+                    );
+                }
                 println!(
                     "{}: .canopy/index.db ({:.1} MB)",
                     "Index".blue(),
@@ -106,19 +286,39 @@ pub(crate) fn cmd_query(
     json: bool,
     service_url: Option<&str>,
     api_key: Option<String>,
+    session_log: Option<&Path>,
 ) -> canopy_core::Result<()> {
     let repo_root = detect_repo_root(root)?;
-    let mut runtime = make_runtime(service_url, api_key);
+    let mut runtime = make_logging_runtime(service_url, api_key, session_log);
+
+    let params = query_params(&args)?;
+    runtime.set_reranker(query_reranker(&repo_root, args.rerank_cmd.as_deref()));
+
+    let result = runtime.query(&repo_root, params)?;
+    print_query_result(&result, &ContentRenderer::new(args.render, json), json)?;
+    if result.exists == Some(false) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-    let params = if let Some(ref qs) = args.query {
+/// Params from the positional DSL query, or from the structured flags.
+fn query_params(args: &QueryArgs) -> canopy_core::Result<QueryParams> {
+    if let Some(ref qs) = args.query {
         if args.pattern.is_none() && args.symbol.is_none() && args.parent.is_none() {
             // Warn if structured flags are set but will be ignored in DSL mode
             let ignored: Vec<&str> = [
                 args.section.as_ref().map(|_| "--section"),
+                args.section_path.as_ref().map(|_| "--section-path"),
                 args.glob.as_ref().map(|_| "--glob"),
+                args.path_contains.as_ref().map(|_| "--path"),
+                args.infer_glob.then_some("--infer-glob"),
                 args.kind.as_ref().map(|_| "--kind"),
                 args.r#match.as_ref().map(|_| "--match"),
                 args.patterns.as_ref().map(|_| "--patterns"),
+                args.local_limit.as_ref().map(|_| "--local-limit"),
+                args.service_limit.as_ref().map(|_| "--service-limit"),
+                args.merge_strategy.as_ref().map(|_| "--merge-strategy"),
             ]
             .into_iter()
             .flatten()
@@ -131,47 +331,138 @@ pub(crate) fn cmd_query(
             }
             let mut params = QueryParams::new();
             params.dsl = Some(qs.clone());
+            params.modified_within = args.recent.clone();
+            params.exclude_patterns = exclude_patterns(args);
             params.limit = args.limit;
             params.expand_budget = args.expand_budget;
-            params
-        } else {
-            build_query_params(&args)?
+            params.mode = query_mode(args);
+            params.group_references = args.group_references;
+            params.include_generated = args.include_generated.then_some(true);
+            params.explain = args.explain.then_some(true);
+            params.priors = query_priors(args)?;
+            params.node_type_priors()?;
+            return Ok(params);
         }
+    }
+    build_query_params(args)
+}
+
+/// `--prior TYPE=WEIGHT` flags as a map; names and weights are checked by
+/// [`canopy_core::QueryParams::node_type_priors`].
+fn query_priors(
+    args: &QueryArgs,
+) -> canopy_core::Result<Option<std::collections::BTreeMap<String, f64>>> {
+    if args.priors.is_empty() {
+        return Ok(None);
+    }
+    args.priors
+        .iter()
+        .map(|prior| {
+            prior
+                .split_once('=')
+                .and_then(|(name, weight)| {
+                    Some((name.trim().to_string(), weight.trim().parse().ok()?))
+                })
+                .ok_or_else(|| canopy_core::CanopyError::QueryParse {
+                    position: 0,
+                    message: format!(
+                        "--prior expects TYPE=WEIGHT (e.g. section=2.0), got '{prior}'"
+                    ),
+                })
+        })
+        .collect::<canopy_core::Result<_>>()
+        .map(Some)
+}
+
+fn query_mode(args: &QueryArgs) -> canopy_core::QueryMode {
+    use canopy_core::QueryMode;
+
+    if args.count {
+        QueryMode::Count
+    } else if args.exists {
+        QueryMode::Exists
     } else {
-        build_query_params(&args)?
-    };
+        QueryMode::Handles
+    }
+}
 
-    let result = runtime.query(&repo_root, params)?;
-    print_query_result(&result, json)
+/// `--exclude-pattern` values, if any were given.
+fn exclude_patterns(args: &QueryArgs) -> Option<Vec<String>> {
+    (!args.exclude_patterns.is_empty()).then(|| args.exclude_patterns.clone())
+}
+
+/// `--rerank-cmd`, else the repo's `[rerank] command`, if either is set.
+fn query_reranker(
+    repo_root: &Path,
+    rerank_cmd: Option<&str>,
+) -> Option<std::sync::Arc<dyn canopy_core::Reranker>> {
+    use canopy_core::query::ExternalReranker;
+
+    let config = canopy_core::Config::load(&repo_root.join(".canopy/config.toml"))
+        .map(|c| c.rerank)
+        .unwrap_or_default();
+    let reranker = match rerank_cmd {
+        Some(cmd) => ExternalReranker::new(cmd)
+            .with_timeout(std::time::Duration::from_millis(config.timeout_ms)),
+        None => ExternalReranker::from_config(&config)?,
+    };
+    Some(std::sync::Arc::new(reranker))
 }
 
 pub(crate) fn build_query_params(
     args: &QueryArgs,
 ) -> canopy_core::Result<canopy_core::QueryParams> {
-    use canopy_core::{MatchMode, QueryParams};
+    use canopy_core::{MatchMode, MergeStrategy, QueryParams};
 
     let mut params = QueryParams::new();
     params.pattern = args.pattern.clone();
     params.patterns = args.patterns.clone();
     params.symbol = args.symbol.clone();
     params.section = args.section.clone();
+    params.section_path = args.section_path.clone();
     params.parent = args.parent.clone();
     params.glob = args.glob.clone();
+    params.path_contains = args.path_contains.clone();
+    params.infer_glob = args.infer_glob.then_some(true);
+    params.modified_within = args.recent.clone();
+    params.exclude_patterns = exclude_patterns(args);
+    params.include_generated = args.include_generated.then_some(true);
+    params.explain = args.explain.then_some(true);
     params.limit = args.limit;
+    params.local_limit = args.local_limit;
+    params.service_limit = args.service_limit;
+    params.merge_strategy = args
+        .merge_strategy
+        .as_deref()
+        .and_then(MergeStrategy::parse);
     params.expand_budget = args.expand_budget;
+    params.mode = query_mode(args);
 
     if let Some(ref k) = args.kind {
         params.kind = QueryParams::parse_kind(k);
     }
 
+    if !args.ref_types.is_empty() {
+        // Values are restricted to known names by clap
+        params.ref_types = Some(
+            args.ref_types
+                .iter()
+                .filter_map(|t| canopy_core::RefType::parse(t))
+                .collect(),
+        );
+    }
+
     if let Some(ref m) = args.r#match {
         params.match_mode = MatchMode::parse(m);
     }
 
+    params.priors = query_priors(args)?;
+    params.node_type_priors()?;
+
     if !params.has_search_target() {
         return Err(canopy_core::CanopyError::QueryParse {
             position: 0,
-            message: "Must provide either a query s-expression or --pattern/--symbol/--parent flag"
+            message: "Must provide either a query s-expression or --pattern/--symbol/--parent/--path flag"
                 .to_string(),
         });
     }
@@ -179,18 +470,59 @@ pub(crate) fn build_query_params(
     Ok(params)
 }
 
+/// Expanded content kept under `.canopy/` so `expand --diff` has a baseline
+/// from the previous invocation.
+const EXPANDED_CACHE_FILE: &str = "expanded.json";
+const EXPANDED_CACHE_MAX_BYTES: usize = 2 * 1024 * 1024;
+
 pub(crate) fn cmd_expand(
     root: Option<std::path::PathBuf>,
-    handle_ids: &[String],
+    args: ExpandArgs,
     json: bool,
     service_url: Option<&str>,
     api_key: Option<String>,
+    session_log: Option<&Path>,
 ) -> canopy_core::Result<()> {
+    use canopy_client::{ExpandDelta, ExpandedContentCache};
     use colored::Colorize;
 
+    let mut handle_ids = Vec::with_capacity(args.handle_ids.len());
+    let mut windows = HashMap::new();
+    for arg in &args.handle_ids {
+        let (id, window) = LineWindow::split_handle_arg(arg)?;
+        if let Some(window) = window {
+            windows.insert(id.to_string(), window);
+        }
+        handle_ids.push(id.to_string());
+    }
+    if args.diff && !windows.is_empty() {
+        return Err(canopy_core::CanopyError::InvalidHandle(
+            "--diff compares whole nodes; drop the line ranges".to_string(),
+        ));
+    }
+
     let repo_root = detect_repo_root(root)?;
-    let mut runtime = make_runtime(service_url, api_key);
-    let outcome = runtime.expand(&repo_root, handle_ids)?;
+    let mut runtime = make_logging_runtime(service_url, api_key, session_log);
+    let canopy_dir = repo_root.join(".canopy");
+    let cache_path = canopy_dir.join(EXPANDED_CACHE_FILE);
+    runtime.set_expanded_contents(ExpandedContentCache::load(
+        &cache_path,
+        EXPANDED_CACHE_MAX_BYTES,
+    ));
+    let outcome = if windows.is_empty() {
+        runtime.expand(&repo_root, &handle_ids, args.diff)?
+    } else {
+        runtime.expand_lines(&repo_root, &handle_ids, &windows)?
+    };
+    if canopy_dir.is_dir() {
+        if let Err(err) = runtime.expanded_contents().save(&cache_path) {
+            eprintln!(
+                "{}: failed to save expand cache: {}",
+                "Warning".yellow(),
+                err
+            );
+        }
+    }
 
     if json {
         let json_val = serde_json::json!({
@@ -198,12 +530,64 @@ pub(crate) fn cmd_expand(
                 serde_json::json!({ "handle_id": id, "content": content })
             }).collect::<Vec<_>>(),
             "failed_ids": outcome.failed_ids,
+            "comparisons": outcome.comparisons,
+            "unresolved_pins": outcome.unresolved_pins,
+            "notes": outcome.notes,
         });
         println!("{}", serde_json::to_string_pretty(&json_val)?);
     } else {
-        for (handle_id, content) in &outcome.contents {
-            println!("{}", format!("// {}", handle_id).dimmed());
-            println!("{}", content);
+        let renderer = ContentRenderer::new(args.render, json);
+        let mut locations = if renderer.is_plain() {
+            HashMap::new()
+        } else {
+            expanded_locations(&mut runtime, &repo_root, &outcome.contents)
+        };
+        // A cut node starts at its window, clamped to the node
+        for (id, window) in &windows {
+            if outcome.notes.contains_key(id) {
+                continue;
+            }
+            if let Some((_, Some(first_line))) = locations.get_mut(id) {
+                *first_line = (*first_line).max(window.start);
+            }
+        }
+        for (i, (handle_id, content)) in outcome.contents.iter().enumerate() {
+            match outcome.comparisons.get(i).map(|c| &c.delta) {
+                Some(ExpandDelta::Unchanged) => {
+                    println!("{}", format!("// {} unchanged", handle_id).dimmed());
+                }
+                Some(ExpandDelta::Diff { diff }) => {
+                    println!("{}", format!("// {} changed", handle_id).dimmed());
+                    for line in diff.lines() {
+                        if line.starts_with('+') {
+                            println!("{}", line.green());
+                        } else if line.starts_with('-') {
+                            println!("{}", line.red());
+                        } else if line.starts_with("@@") {
+                            println!("{}", line.cyan());
+                        } else {
+                            println!("{}", line);
+                        }
+                    }
+                }
+                Some(ExpandDelta::Full) | None => {
+                    match windows.get(handle_id) {
+                        Some(window) => {
+                            println!("{}", format!("// {} lines {}", handle_id, window).dimmed())
+                        }
+                        None => println!("{}", format!("// {}", handle_id).dimmed()),
+                    }
+                    if let Some(note) = outcome.notes.get(handle_id) {
+                        println!("{}", format!("// {}", note).dimmed());
+                    }
+                    match locations.get(handle_id) {
+                        Some((file_path, first_line)) => {
+                            println!("{}", renderer.render(content, file_path, *first_line))
+                        }
+                        None => println!("{}", content),
+                    }
+                }
+            }
             println!();
         }
         if !outcome.failed_ids.is_empty() {
@@ -213,196 +597,1292 @@ pub(crate) fn cmd_expand(
                 outcome.failed_ids.join(", ")
             );
         }
+        for pin in &outcome.unresolved_pins {
+            eprintln!(
+                "{}: pinned {} no longer resolves: {}",
+                "Warning".yellow(),
+                pin.handle_id,
+                pin.reason
+            );
+        }
     }
     Ok(())
 }
 
-pub(crate) fn cmd_status(root: Option<std::path::PathBuf>, json: bool) -> canopy_core::Result<()> {
-    use canopy_core::RepoIndex;
-    use colored::Colorize;
-
-    let repo_root = detect_repo_root(root)?;
-    let index = RepoIndex::open(&repo_root)?;
-    let status = index.status()?;
+/// File and first line of each expanded handle, for [`ContentRenderer`].
+///
+/// Expand returns bare content, so the file comes from validation and the
+/// line from the local index, or failing that from finding the content in
+/// the file. Handles neither turns up are left out.
+fn expanded_locations(
+    runtime: &mut ClientRuntime,
+    repo_root: &Path,
+    contents: &[(String, String)],
+) -> HashMap<String, (String, Option<usize>)> {
+    let ids: Vec<String> = contents.iter().map(|(id, _)| id.clone()).collect();
+    let Ok(validations) = runtime.validate_handles(repo_root, &ids) else {
+        return HashMap::new();
+    };
+    let index = repo_root
+        .join(".canopy")
+        .is_dir()
+        .then(|| runtime.open_local_index(repo_root).ok())
+        .flatten();
 
-    if json {
-        println!("{}", serde_json::to_string_pretty(&status)?);
-    } else {
-        println!(
-            "{}: .canopy/index.db ({:.1} MB)",
-            "Index".blue(),
-            status.index_size_bytes as f64 / 1_000_000.0
-        );
-        println!("{}: {} indexed", "Files".blue(), status.files_indexed);
-        println!("{}: {}", "Tokens".blue(), status.total_tokens);
-        println!("{}: v{}", "Schema".blue(), status.schema_version);
-        if let Some(last) = status.last_indexed {
-            println!("{}: {}", "Last indexed".blue(), last);
-        }
+    let mut locations = HashMap::new();
+    for ((id, content), validation) in contents.iter().zip(validations) {
+        let Some(file_path) = validation.file_path else {
+            continue;
+        };
+        let indexed = index.as_ref().and_then(|index| {
+            let nodes = canopy_client::lock_index(index)
+                .file_nodes(&file_path)
+                .ok()?;
+            nodes
+                .into_iter()
+                .find(|node| node.handle.id.to_string() == *id)
+                .map(|node| node.handle.line_range.0)
+        });
+        let first_line = indexed.or_else(|| {
+            let source = std::fs::read_to_string(repo_root.join(&file_path)).ok()?;
+            let offset = source.find(content.as_str())?;
+            Some(source[..offset].matches('\n').count() + 1)
+        });
+        locations.insert(id.clone(), (file_path, first_line));
     }
-    Ok(())
+    locations
 }
 
-pub(crate) fn cmd_feedback_stats(
+pub(crate) fn cmd_explore(
     root: Option<std::path::PathBuf>,
+    args: ExploreArgs,
     json: bool,
-    lookback_days: Option<f64>,
+    service_url: Option<&str>,
+    api_key: Option<String>,
+    session_log: Option<&Path>,
 ) -> canopy_core::Result<()> {
-    use canopy_core::feedback::FeedbackStore;
     use colored::Colorize;
 
     let repo_root = detect_repo_root(root)?;
-    let store = FeedbackStore::open(&repo_root)?;
-    let metrics = store.compute_metrics(lookback_days.unwrap_or(7.0))?;
+    let mut runtime = make_logging_runtime(service_url, api_key, session_log);
+    let params = query_params(&args.query)?;
+    runtime.set_reranker(query_reranker(&repo_root, args.query.rerank_cmd.as_deref()));
+
+    let exploration = runtime.explore(
+        &repo_root,
+        params,
+        args.budget,
+        args.max_handles,
+        args.max_per_file,
+    )?;
 
     if json {
-        println!(
-            "{}",
-            serde_json::to_string_pretty(&serde_json::json!({
-                "glob_hit_rate_at_k": metrics.glob_hit_rate_at_k,
-                "handle_expand_accept_rate": metrics.handle_expand_accept_rate,
-                "avg_tokens_per_expand": metrics.avg_tokens_per_expand,
-                "sample_count": metrics.sample_count,
-            }))?
-        );
-    } else {
-        println!("{}:", "Feedback".blue());
-        println!(
-            "  {} {:.3}",
-            "glob_hit_rate_at_k".green(),
-            metrics.glob_hit_rate_at_k
+        println!("{}", serde_json::to_string_pretty(&exploration)?);
+        return Ok(());
+    }
+
+    let renderer = ContentRenderer::new(args.query.render, json);
+    let pack = &exploration.pack;
+    println!(
+        "{} of {} matches in the pack, {} expanded",
+        pack.selected_count,
+        pack.total_matches,
+        exploration.expanded.len()
+    );
+    println!();
+    for handle in &exploration.expanded {
+        let header = format!(
+            "// {} {}:{}-{}{}",
+            handle.handle_id,
+            handle.file_path,
+            handle.line_range.0,
+            handle.line_range.1,
+            if handle.truncated { " (truncated)" } else { "" }
         );
+        println!("{}", header.dimmed());
         println!(
-            "  {} {:.3}",
-            "handle_expand_accept_rate".green(),
-            metrics.handle_expand_accept_rate
+            "{}",
+            renderer.render(
+                &handle.content,
+                &handle.file_path,
+                Some(handle.line_range.0)
+            )
         );
-        println!(
-            "  {} {:.1}",
-            "avg_tokens_per_expand".green(),
-            metrics.avg_tokens_per_expand
+        println!();
+    }
+    if !exploration.failed_ids.is_empty() {
+        eprintln!(
+            "{}: failed to expand: {}",
+            "Warning".yellow(),
+            exploration.failed_ids.join(", ")
         );
-        println!("  {} {}", "sample_count".green(), metrics.sample_count);
     }
-
+    println!(
+        "{}",
+        format!(
+            "{} of {} tokens spent",
+            exploration.tokens_spent, exploration.token_budget
+        )
+        .bold()
+    );
+    println!("{}", exploration.next_step);
     Ok(())
 }
 
-pub(crate) fn cmd_invalidate(
+pub(crate) fn cmd_pin(
     root: Option<std::path::PathBuf>,
-    glob: Option<String>,
+    handle_ids: &[String],
     json: bool,
+    service_url: Option<&str>,
+    api_key: Option<String>,
 ) -> canopy_core::Result<()> {
-    use canopy_core::RepoIndex;
     use colored::Colorize;
 
     let repo_root = detect_repo_root(root)?;
-    let mut index = RepoIndex::open(&repo_root)?;
-    let count = index.invalidate(glob.as_deref())?;
+    let mut runtime = make_runtime(service_url, api_key);
+    let pins = runtime.pin_handles(&repo_root, handle_ids)?;
 
     if json {
-        println!("{}", serde_json::json!({ "files_removed": count }));
+        println!("{}", serde_json::to_string_pretty(&pins)?);
     } else {
-        println!(
-            "{}: {} files removed from index",
-            "Invalidated".yellow(),
-            count
-        );
+        for pin in &pins {
+            println!(
+                "{} {} {} ({})",
+                "Pinned".green(),
+                pin.handle_id,
+                pin.name.as_deref().unwrap_or(pin.node_type.as_str()),
+                pin.file_path
+            );
+        }
     }
     Ok(())
 }
 
-pub(crate) fn cmd_repos(
-    service_url: Option<&str>,
+pub(crate) fn cmd_pins(
+    root: Option<std::path::PathBuf>,
     json: bool,
+    service_url: Option<&str>,
     api_key: Option<String>,
 ) -> canopy_core::Result<()> {
+    use canopy_client::PinStatus;
     use colored::Colorize;
 
-    let runtime = make_runtime(service_url, api_key);
-    let repos = runtime.list_repos()?;
+    let repo_root = detect_repo_root(root)?;
+    let mut runtime = make_runtime(service_url, api_key);
+    let pins = runtime.list_pins(&repo_root)?;
 
     if json {
-        println!("{}", serde_json::to_string_pretty(&repos)?);
-    } else {
-        if repos.is_empty() {
-            println!("No repos registered with the service.");
-        } else {
-            for repo in &repos {
-                let status_str = format!("{:?}", repo.status).to_lowercase();
-                let gen = format!("gen {}", repo.generation);
-                let sha = repo
-                    .commit_sha
-                    .as_ref()
-                    .map(|s| format!(" @ {}", &s[..8.min(s.len())]))
-                    .unwrap_or_default();
+        println!("{}", serde_json::to_string_pretty(&pins)?);
+        return Ok(());
+    }
+    if pins.is_empty() {
+        println!("No pinned handles");
+    }
+    for pin in &pins {
+        let label = pin.name.as_deref().unwrap_or(pin.node_type.as_str());
+        match (pin.status, &pin.current_id) {
+            (PinStatus::Fresh, _) => {
                 println!(
-                    "{}: {} [{}] ({}{}){}",
-                    repo.repo_id.cyan(),
-                    repo.name,
-                    status_str,
-                    gen,
-                    sha,
-                    if repo.repo_root != repo.name {
-                        format!(" — {}", repo.repo_root.dimmed())
-                    } else {
-                        String::new()
-                    }
+                    "{} {} {} ({})",
+                    "fresh".green(),
+                    pin.handle_id,
+                    label,
+                    pin.file_path
                 );
             }
+            (PinStatus::Remapped, Some(current)) => println!(
+                "{} {} {} ({}) -> {}",
+                "remapped".cyan(),
+                pin.handle_id,
+                label,
+                pin.file_path,
+                current
+            ),
+            _ => println!(
+                "{} {} {}: {}",
+                "missing".red(),
+                pin.handle_id,
+                label,
+                pin.reason.as_deref().unwrap_or("no longer resolves")
+            ),
         }
-        println!("({} repos)", repos.len());
     }
     Ok(())
 }
 
-pub(crate) fn cmd_reindex(
-    service_url: Option<&str>,
-    repo: String,
-    glob: Option<String>,
+pub(crate) fn cmd_validate(
+    root: Option<std::path::PathBuf>,
+    handle_ids: &[String],
     json: bool,
+    service_url: Option<&str>,
     api_key: Option<String>,
 ) -> canopy_core::Result<()> {
+    use canopy_core::HandleStatus;
     use colored::Colorize;
 
-    let runtime = make_runtime(service_url, api_key);
-    let response = runtime.reindex_by_id(&repo, glob.as_deref())?;
+    let repo_root = detect_repo_root(root)?;
+    let mut runtime = make_runtime(service_url, api_key);
+    let handles = runtime.validate_handles(&repo_root, handle_ids)?;
 
     if json {
-        println!(
-            "{}",
-            serde_json::to_string_pretty(&serde_json::json!({
-                "generation": response.generation,
-                "status": response.status,
-                "commit_sha": response.commit_sha,
-            }))?
-        );
-    } else {
-        println!("{}: generation {}", "Reindex".green(), response.generation);
-        println!("{}: {}", "Status".blue(), response.status);
-        if let Some(sha) = &response.commit_sha {
-            println!("{}: {}", "Commit".blue(), sha);
+        println!("{}", serde_json::to_string_pretty(&handles)?);
+        return Ok(());
+    }
+    for handle in &handles {
+        let status = match handle.status {
+            HandleStatus::Fresh => "fresh".green(),
+            HandleStatus::Stale => "stale".yellow(),
+            HandleStatus::Missing => "missing".red(),
+        };
+        match (&handle.file_path, &handle.content_hash) {
+            (Some(path), Some(hash)) => {
+                println!("{} {} ({}) #{}", status, handle.handle_id, path, hash)
+            }
+            (Some(path), None) => println!("{} {} ({})", status, handle.handle_id, path),
+            _ => println!("{} {}", status, handle.handle_id),
         }
     }
     Ok(())
 }
 
-pub(crate) fn cmd_service_status(
-    service_url: Option<&str>,
+/// Degraded files listed by `canopy status --verbose`.
+const STATUS_MAX_PARSE_WARNINGS: usize = 20;
+
+pub(crate) fn cmd_status(
+    root: Option<std::path::PathBuf>,
+    scope: Option<&str>,
+    by_dir: Option<usize>,
+    verbose: bool,
+    detailed: bool,
     json: bool,
-    api_key: Option<String>,
 ) -> canopy_core::Result<()> {
+    use canopy_core::RepoIndex;
     use colored::Colorize;
 
-    let runtime = make_runtime(service_url, api_key);
-    let status = runtime.service_status()?;
-
-    if json {
-        println!(
-            "{}",
-            serde_json::to_string_pretty(&serde_json::json!({
-                "service": status.service,
-                "repos": status.repos,
+    let repo_root = detect_repo_root(root)?;
+    let index = RepoIndex::open(&repo_root)?;
+    if let Some(depth) = by_dir {
+        let tree = index.token_tree(depth)?;
+        if json {
+            println!("{}", serde_json::to_string_pretty(&tree)?);
+        } else {
+            println!(
+                "{}: {} files, {} tokens",
+                "Index".blue(),
+                tree.files_indexed,
+                tree.total_tokens
+            );
+            print_token_tree(&tree.dirs, tree.total_tokens, 1);
+        }
+        return Ok(());
+    }
+    if let Some(scope) = scope {
+        let status = index.status_scoped(scope)?;
+        if json {
+            println!("{}", serde_json::to_string_pretty(&status)?);
+            return Ok(());
+        }
+        println!("{}: {}", "Scope".blue(), status.scope);
+        println!("{}: {} indexed", "Files".blue(), status.files_indexed);
+        println!("{}: {}", "Tokens".blue(), status.total_tokens);
+        if let Some(last) = &status.last_indexed {
+            println!("{}: {}", "Last indexed".blue(), last);
+        }
+        if !status.by_type.is_empty() {
+            print_node_breakdown(&canopy_core::NodeBreakdown {
+                by_type: status.by_type,
+                largest: Vec::new(),
+            });
+        }
+        return Ok(());
+    }
+    let status = if detailed {
+        index.status_detailed()?
+    } else {
+        index.status()?
+    };
+    let warnings = if verbose && status.files_degraded > 0 {
+        index.parse_warnings(STATUS_MAX_PARSE_WARNINGS)?
+    } else {
+        Vec::new()
+    };
+    let migrations = if verbose {
+        index.applied_migrations()?
+    } else {
+        Vec::new()
+    };
+
+    if json {
+        let mut value = serde_json::to_value(&status)?;
+        if verbose {
+            value["parse_warnings"] = serde_json::to_value(&warnings)?;
+            value["migrations"] = serde_json::to_value(&migrations)?;
+            value["symbol_cache"] = serde_json::to_value(index.symbol_cache_stats())?;
+        }
+        println!("{}", serde_json::to_string_pretty(&value)?);
+    } else {
+        println!(
+            "{}: .canopy/index.db ({:.1} MB)",
+            "Index".blue(),
+            status.index_size_bytes as f64 / 1_000_000.0
+        );
+        println!("{}: {} indexed", "Files".blue(), status.files_indexed);
+        println!("{}: {}", "Tokens".blue(), status.total_tokens);
+        if status.files_generated > 0 {
+            println!(
+                "{}: {} files ({:.0}% of tokens)",
+                "Generated".blue(),
+                status.files_generated,
+                token_share(status.generated_tokens, status.total_tokens)
+            );
+        }
+        if status.nodes_fts_sampled > 0 {
+            println!(
+                "{}: {} nodes full-text indexed from a sample (over max_fts_bytes_per_node)",
+                "FTS sampled".blue(),
+                status.nodes_fts_sampled
+            );
+        }
+        println!("{}: v{}", "Schema".blue(), status.index_schema_version);
+        for migration in &migrations {
+            println!(
+                "  migrated to v{}: {} ({})",
+                migration.version,
+                migration.description,
+                migration.applied_ago()
+            );
+        }
+        if status.shards > 0 {
+            println!("{}: {}", "Shards".blue(), status.shards);
+        }
+        if verbose {
+            let cache = index.symbol_cache_stats();
+            println!(
+                "{}: {} entries, ~{:.1} MB{}{}",
+                "Symbol cache".blue(),
+                cache.entries,
+                cache.estimated_bytes as f64 / 1_000_000.0,
+                match cache.max_bytes {
+                    0 => String::new(),
+                    max => format!(" of {:.1} MB", max as f64 / 1_000_000.0),
+                },
+                if cache.partial {
+                    ", partial (other symbols are looked up in the database)"
+                } else {
+                    ", complete"
+                }
+            );
+        }
+        if let Some(last) = status.last_indexed {
+            println!("{}: {}", "Last indexed".blue(), last);
+        }
+        if !status.annotations.is_empty() {
+            let counts: Vec<String> = status
+                .annotations
+                .iter()
+                .map(|(marker, count)| format!("{} {}", marker, count))
+                .collect();
+            println!("{}: {}", "Annotations".blue(), counts.join(", "));
+        }
+        if let Some(warning) = &status.mtime_warning {
+            println!("{}: {}", "Mtimes".yellow(), warning);
+        }
+        if let Some(warning) = &status.churn_warning {
+            println!("{}: {}", "Churn".yellow(), warning);
+        }
+        if status.files_degraded > 0 {
+            println!(
+                "{}: {} files indexed as plain text after parse errors{}",
+                "Degraded".yellow(),
+                status.files_degraded,
+                if verbose { "" } else { " (--verbose to list)" }
+            );
+            for warning in &warnings {
+                println!("  {}: {}", warning.path, warning.reason);
+            }
+            if warnings.len() < status.files_degraded && verbose {
+                println!("  ... and {} more", status.files_degraded - warnings.len());
+            }
+        }
+        if let Some(breakdown) = &status.node_breakdown {
+            print_node_breakdown(breakdown);
+        }
+        if let Some(files) = status.churning_files.as_ref().filter(|f| !f.is_empty()) {
+            println!();
+            println!("{}:", "Most reindexed files".blue());
+            for file in files {
+                println!(
+                    "  {:>6}x  {} ({} tokens)",
+                    file.reindex_count, file.path, file.token_count
+                );
+            }
+        }
+        if let Some(last) = &status.last_fts_optimize {
+            println!("{}: {}", "FTS last optimized".blue(), last);
+        }
+    }
+    Ok(())
+}
+
+/// `part` as a percentage of `total`.
+fn token_share(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+/// One line per directory, indented by level: tokens, share of the
+/// repo's tokens, and file count.
+fn print_token_tree(dirs: &[canopy_core::DirTokens], repo_tokens: usize, level: usize) {
+    for dir in dirs {
+        println!(
+            "{:>10}  {:>5.1}%  {:indent$}{}/ ({} files)",
+            dir.total_tokens,
+            token_share(dir.total_tokens, repo_tokens),
+            "",
+            dir.path,
+            dir.files_indexed,
+            indent = (level - 1) * 2
+        );
+        print_token_tree(&dir.dirs, repo_tokens, level + 1);
+    }
+}
+
+fn print_node_breakdown(breakdown: &canopy_core::NodeBreakdown) {
+    use colored::Colorize;
+
+    println!();
+    println!(
+        "{:<12} {:>8} {:>10} {:>8}",
+        "Node type".blue(),
+        "nodes",
+        "tokens",
+        "avg"
+    );
+    for stats in &breakdown.by_type {
+        println!(
+            "{:<12} {:>8} {:>10} {:>8}",
+            stats.node_type.as_str(),
+            stats.nodes,
+            stats.total_tokens,
+            stats.avg_tokens
+        );
+    }
+    if !breakdown.largest.is_empty() {
+        println!();
+        println!("{}:", "Largest nodes".blue());
+        for node in &breakdown.largest {
+            println!(
+                "  {:>8}  {}:{}-{} ({})",
+                node.token_count,
+                node.file_path,
+                node.line_range.0,
+                node.line_range.1,
+                node.node_type.as_str()
+            );
+        }
+    }
+}
+
+pub(crate) fn cmd_summary(
+    root: Option<std::path::PathBuf>,
+    max_tokens: Option<usize>,
+    json: bool,
+    service_url: Option<&str>,
+    api_key: Option<String>,
+) -> canopy_core::Result<()> {
+    use colored::Colorize;
+
+    let repo_root = detect_repo_root(root)?;
+    let mut runtime = make_runtime(service_url, api_key);
+    let summary = runtime.repo_summary(&repo_root, max_tokens)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        return Ok(());
+    }
+
+    println!(
+        "{}: {} files, {} tokens",
+        "Repo".blue(),
+        summary.total_files,
+        summary.total_tokens
+    );
+    if !summary.languages.is_empty() {
+        let languages: Vec<String> = summary
+            .languages
+            .iter()
+            .map(|l| format!("{} {} ({} files)", l.language, l.tokens, l.files))
+            .collect();
+        println!("{}: {}", "Languages".blue(), languages.join(", "));
+    }
+    if !summary.directories.is_empty() {
+        println!("{}:", "Directories".blue());
+        for dir in &summary.directories {
+            println!(
+                "  {:<24} {:>5} files {:>8} tokens  fn {} class {} struct {}",
+                dir.path, dir.files, dir.tokens, dir.functions, dir.classes, dir.structs
+            );
+        }
+    }
+    if !summary.largest_files.is_empty() {
+        println!("{}:", "Largest files".blue());
+        for file in &summary.largest_files {
+            println!("  {:<40} {:>8} tokens", file.path, file.tokens);
+        }
+    }
+    if let Some(readme) = &summary.readme {
+        println!(
+            "{}: {} {}:{}-{} {}",
+            "README".blue(),
+            readme.id,
+            readme.file_path,
+            readme.line_range.0,
+            readme.line_range.1,
+            readme.preview.lines().next().unwrap_or("").dimmed()
+        );
+    }
+    if summary.truncated {
+        println!(
+            "{}",
+            format!(
+                "(trimmed to ~{} tokens; raise --max-tokens for more)",
+                summary.token_count
+            )
+            .dimmed()
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn cmd_related(
+    root: Option<std::path::PathBuf>,
+    path: &str,
+    limit: Option<usize>,
+    json: bool,
+    service_url: Option<&str>,
+    api_key: Option<String>,
+) -> canopy_core::Result<()> {
+    use colored::Colorize;
+
+    let repo_root = detect_repo_root(root)?;
+    let path = repo_relative(&repo_root, path);
+    let mut runtime = make_runtime(service_url, api_key);
+    let related = runtime.related_files(&repo_root, &path, limit)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&related)?);
+        return Ok(());
+    }
+
+    if related.files.is_empty() {
+        println!("No files share symbols with {}", related.path);
+        return Ok(());
+    }
+    println!(
+        "{}: {} of {} related files",
+        related.path.blue(),
+        related.files.len(),
+        related.total_related
+    );
+    for file in &related.files {
+        let dir = if file.same_directory {
+            " (same directory)".dimmed().to_string()
+        } else {
+            String::new()
+        };
+        println!("  {:>6.2}  {}{}", file.score, file.path, dir);
+        let shared = |symbols: &[canopy_core::SharedSymbol]| {
+            symbols
+                .iter()
+                .map(|s| format!("{} ×{}", s.name, s.refs))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        if !file.defines.is_empty() {
+            println!("          {} {}", "uses".dimmed(), shared(&file.defines));
+        }
+        if !file.references.is_empty() {
+            println!(
+                "          {} {}",
+                "used by".dimmed(),
+                shared(&file.references)
+            );
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn cmd_symbols(
+    root: Option<std::path::PathBuf>,
+    args: &SymbolsArgs,
+    json: bool,
+    service_url: Option<&str>,
+    api_key: Option<String>,
+) -> canopy_core::Result<()> {
+    use colored::Colorize;
+
+    let repo_root = detect_repo_root(root)?;
+    // clap only accepts valid type names
+    let node_types: Vec<NodeType> = args
+        .node_types
+        .iter()
+        .filter_map(|t| NodeType::parse(t))
+        .collect();
+    let mut runtime = make_runtime(service_url, api_key);
+    let page = runtime.list_symbols(
+        &repo_root,
+        args.prefix.as_deref(),
+        &node_types,
+        args.after.as_deref(),
+        args.limit,
+    )?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&page)?);
+        return Ok(());
+    }
+
+    if page.symbols.is_empty() {
+        println!("No symbols");
+        return Ok(());
+    }
+    for symbol in &page.symbols {
+        println!(
+            "  {} {} {}:{} {}",
+            symbol.name,
+            format!("[{}]", symbol.node_type.as_str()).dimmed(),
+            symbol.file_path.blue(),
+            symbol.line_start,
+            symbol.handle_id.to_string().dimmed()
+        );
+    }
+    if let Some(next) = &page.next_after {
+        println!("{}", format!("(more: --after {next:?})").dimmed());
+    }
+    Ok(())
+}
+
+/// `path` relative to `repo_root` when it names an existing file from the
+/// current directory, else as given.
+fn repo_relative(repo_root: &Path, path: &str) -> String {
+    let (Ok(file), Ok(root)) = (Path::new(path).canonicalize(), repo_root.canonicalize()) else {
+        return path.to_string();
+    };
+    match file.strip_prefix(&root) {
+        Ok(relative) => relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        Err(_) => path.to_string(),
+    }
+}
+
+pub(crate) fn cmd_snapshot(
+    root: Option<std::path::PathBuf>,
+    name: Option<&str>,
+    json: bool,
+) -> canopy_core::Result<()> {
+    use canopy_core::RepoIndex;
+    use colored::Colorize;
+
+    let repo_root = detect_repo_root(root)?;
+    let index = RepoIndex::open(&repo_root)?;
+    let path = index.write_snapshot(name)?;
+
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({ "path": path.display().to_string() })
+        );
+    } else {
+        println!("{} {}", "Wrote".green(), path.display());
+    }
+    Ok(())
+}
+
+pub(crate) fn cmd_diff_symbols(
+    root: Option<std::path::PathBuf>,
+    since: &str,
+    json: bool,
+) -> canopy_core::Result<()> {
+    use canopy_core::{DeltaAnchor, RepoIndex};
+    use colored::Colorize;
+
+    let repo_root = detect_repo_root(root)?;
+    let index = RepoIndex::open(&repo_root)?;
+    let delta = index.symbol_delta(&DeltaAnchor::resolve(&repo_root, since))?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&delta)?);
+        return Ok(());
+    }
+    for (marker, symbols) in [
+        ("+".green(), &delta.added),
+        ("-".red(), &delta.removed),
+        ("~".yellow(), &delta.changed),
+    ] {
+        for symbol in symbols {
+            println!(
+                "{} {} {} ({})",
+                marker,
+                symbol.node_type.as_str(),
+                symbol.name,
+                symbol.file_path
+            );
+        }
+    }
+    for symbol in &delta.renamed {
+        println!(
+            "{} {} {} ({} -> {})",
+            ">".cyan(),
+            symbol.node_type.as_str(),
+            symbol.name,
+            symbol.from_path,
+            symbol.to_path
+        );
+    }
+    println!(
+        "({} added, {} removed, {} changed, {} renamed since {})",
+        delta.added.len(),
+        delta.removed.len(),
+        delta.changed.len(),
+        delta.renamed.len(),
+        delta.since
+    );
+    Ok(())
+}
+
+pub(crate) fn cmd_show_commit(
+    root: Option<std::path::PathBuf>,
+    sha: &str,
+    path: Option<&str>,
+    max_tokens: Option<usize>,
+    json: bool,
+) -> canopy_core::Result<()> {
+    use canopy_core::HistoryConfig;
+    use colored::Colorize;
+
+    let repo_root = detect_repo_root(root)?;
+    let max_tokens = match max_tokens {
+        Some(max_tokens) => max_tokens,
+        None => HistoryConfig::for_repo(&repo_root)?.max_diff_tokens,
+    };
+    let commit = canopy_core::show_commit(&repo_root, sha, path, max_tokens)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&commit)?);
+        return Ok(());
+    }
+    println!(
+        "{} {} ({}, {})",
+        "commit".yellow(),
+        commit.commit.sha,
+        commit.commit.author,
+        commit.commit.date
+    );
+    println!();
+    println!("    {}", commit.commit.subject);
+    for line in commit.body.lines() {
+        println!("    {}", line);
+    }
+    println!();
+    for file in &commit.files {
+        println!("{} {}", "touched".dimmed(), file);
+    }
+    if !commit.diff.is_empty() {
+        println!();
+        print!("{}", commit.diff);
+    }
+    if commit.truncated {
+        println!(
+            "... ({} at {} tokens; raise --max-tokens or narrow --path)",
+            "truncated".yellow(),
+            commit.diff_tokens
+        );
+    }
+    if commit.redactions > 0 {
+        println!("({} secrets redacted)", commit.redactions);
+    }
+    Ok(())
+}
+
+pub(crate) fn cmd_feedback_stats(
+    root: Option<std::path::PathBuf>,
+    json: bool,
+    lookback_days: Option<f64>,
+    by_session: bool,
+) -> canopy_core::Result<()> {
+    use canopy_core::feedback::FeedbackStore;
+    use colored::Colorize;
+
+    let repo_root = detect_repo_root(root)?;
+    let store = FeedbackStore::open(&repo_root)?;
+    let lookback_days = lookback_days.unwrap_or(7.0);
+    if by_session {
+        let sessions = store.compute_session_metrics(lookback_days)?;
+        if json {
+            let sessions: Vec<serde_json::Value> = sessions
+                .iter()
+                .map(|s| {
+                    let mut value = feedback_metrics_json(&s.metrics);
+                    value["session_id"] = serde_json::json!(s.session_id);
+                    value["client_name"] = serde_json::json!(s.client_name);
+                    value
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&sessions)?);
+        } else if sessions.is_empty() {
+            println!("No feedback in the last {lookback_days} days");
+        } else {
+            println!("{}:", "Feedback by session".blue());
+            for s in &sessions {
+                let m = &s.metrics;
+                println!(
+                    "  {} {} queries, expand accept {:.3} (manual {:.3}), {} tokens avoided",
+                    format!(
+                        "{} [{}]",
+                        s.session_id.as_deref().unwrap_or("(no session)"),
+                        s.client_name.as_deref().unwrap_or("unknown client")
+                    )
+                    .green(),
+                    m.sample_count,
+                    m.handle_expand_accept_rate,
+                    m.manual_expand_accept_rate,
+                    m.tokens_avoided()
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    let metrics = store.compute_metrics(lookback_days)?;
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&feedback_metrics_json(&metrics))?
+        );
+    } else {
+        println!("{}:", "Feedback".blue());
+        println!(
+            "  {} {:.3}",
+            "glob_hit_rate_at_k".green(),
+            metrics.glob_hit_rate_at_k
+        );
+        println!(
+            "  {} {:.3}",
+            "handle_expand_accept_rate".green(),
+            metrics.handle_expand_accept_rate
+        );
+        println!(
+            "  {} {:.3}",
+            "manual_expand_accept_rate".green(),
+            metrics.manual_expand_accept_rate
+        );
+        println!(
+            "  {} {:.3}",
+            "auto_expand_share".green(),
+            metrics.auto_expand_share
+        );
+        println!(
+            "  {} {:.1}",
+            "avg_tokens_per_expand".green(),
+            metrics.avg_tokens_per_expand
+        );
+        println!("  {} {}", "sample_count".green(), metrics.sample_count);
+        println!(
+            "  {} {} ({} returned vs {} in whole files)",
+            "tokens_avoided".green(),
+            metrics.tokens_avoided(),
+            metrics.returned_tokens,
+            metrics.file_tokens
+        );
+    }
+
+    Ok(())
+}
+
+fn feedback_metrics_json(metrics: &canopy_core::feedback::FeedbackMetrics) -> serde_json::Value {
+    serde_json::json!({
+        "glob_hit_rate_at_k": metrics.glob_hit_rate_at_k,
+        "handle_expand_accept_rate": metrics.handle_expand_accept_rate,
+        "manual_expand_accept_rate": metrics.manual_expand_accept_rate,
+        "auto_expand_share": metrics.auto_expand_share,
+        "avg_tokens_per_expand": metrics.avg_tokens_per_expand,
+        "sample_count": metrics.sample_count,
+        "file_tokens": metrics.file_tokens,
+        "returned_tokens": metrics.returned_tokens,
+        "tokens_avoided": metrics.tokens_avoided(),
+    })
+}
+
+pub(crate) fn cmd_feedback_prune(
+    root: Option<std::path::PathBuf>,
+    json: bool,
+    days: Option<u64>,
+) -> canopy_core::Result<()> {
+    use canopy_core::feedback::FeedbackStore;
+    use canopy_core::FeedbackConfig;
+    use colored::Colorize;
+
+    let repo_root = detect_repo_root(root)?;
+    let config = FeedbackConfig::for_repo(&repo_root)?;
+    let store = FeedbackStore::open_with_config(&repo_root, &config)?;
+    // Opening already pruned up to its budget; report that too
+    let mut report = store.auto_pruned().copied().unwrap_or_default();
+    report.merge(&store.prune(days.unwrap_or(config.retention_days), config.max_rows)?);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!(
+            "{} {} query events, {} expand events{}",
+            "Pruned".green(),
+            report.query_events,
+            report.expand_events,
+            if report.vacuumed { " (vacuumed)" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn cmd_invalidate(
+    root: Option<std::path::PathBuf>,
+    glob: Option<String>,
+    dry_run: bool,
+    verbose: bool,
+    json: bool,
+) -> canopy_core::Result<()> {
+    use canopy_core::RepoIndex;
+    use colored::Colorize;
+
+    let repo_root = detect_repo_root(root)?;
+    let mut index = RepoIndex::open(&repo_root)?;
+    if dry_run {
+        let plan = index.plan_invalidate(glob.as_deref())?;
+        return print_index_plan(&plan, verbose, json);
+    }
+    let count = index.invalidate(glob.as_deref())?;
+
+    if json {
+        println!("{}", serde_json::json!({ "files_removed": count }));
+    } else {
+        println!(
+            "{}: {} files removed from index",
+            "Invalidated".yellow(),
+            count
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn cmd_fixup_moves(
+    root: Option<std::path::PathBuf>,
+    verbose: bool,
+    json: bool,
+) -> canopy_core::Result<()> {
+    use canopy_core::RepoIndex;
+    use colored::Colorize;
+
+    let repo_root = detect_repo_root(root)?;
+    let mut index = RepoIndex::open(&repo_root)?;
+    let report = index.fixup_moves()?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!(
+        "{}: {} moves, {} handles aliased, {} rows purged",
+        "Fixed up".green(),
+        report.moves_processed,
+        report.aliases_created,
+        report.rows_purged
+    );
+    if report.files_removed > 0 {
+        println!(
+            "{}: {} files gone from disk",
+            "Removed".yellow(),
+            report.files_removed
+        );
+    }
+    if verbose {
+        for moved in &report.moves {
+            let note = if moved.indexed {
+                format!("{} aliases", moved.aliases)
+            } else {
+                "ignored, not indexed".to_string()
+            };
+            println!("  {} -> {} ({})", moved.from, moved.to, note.dimmed());
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn cmd_schema(name: &str) -> canopy_core::Result<()> {
+    let schema = canopy_core::output_schema(name).expect("clap only accepts schema types");
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
+
+pub(crate) fn cmd_shard(
+    root: Option<std::path::PathBuf>,
+    apply: bool,
+    json: bool,
+) -> canopy_core::Result<()> {
+    use canopy_core::RepoIndex;
+    use colored::Colorize;
+
+    let repo_root = detect_repo_root(root)?;
+    let mut index = RepoIndex::open(&repo_root)?;
+    let stats = index.reshard(apply)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+    } else {
+        let label = if stats.applied {
+            "Moved".green()
+        } else {
+            "Would move".yellow()
+        };
+        println!("{}: {} files", label, stats.files_moved);
+        for shard in &stats.shards {
+            println!("  {}: {}/", "Shard".blue(), shard);
+        }
+        if !stats.applied && stats.files_moved > 0 {
+            println!("Run 'canopy shard --apply' to migrate.");
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn cmd_replay(
+    log: &Path,
+    service_url: Option<&str>,
+    json: bool,
+    api_key: Option<String>,
+) -> canopy_core::Result<()> {
+    let records = SessionLog::read(log)?;
+    let mut runtime = make_runtime(service_url, api_key);
+    let report = runtime.replay(&records);
+    print_replay_report(&report, json)
+}
+
+pub(crate) fn cmd_bench_queries(
+    root: Option<std::path::PathBuf>,
+    queries: &Path,
+    compare: Option<&Path>,
+    out: Option<&Path>,
+    json: bool,
+    service_url: Option<&str>,
+    api_key: Option<String>,
+) -> canopy_core::Result<()> {
+    use canopy_client::{compare_snapshots, BenchSnapshot, BenchSuite};
+    use colored::Colorize;
+
+    let repo_root = detect_repo_root(root)?;
+    let suite = BenchSuite::load(queries)?;
+    // Read up front so a bad path fails before the suite runs
+    let baseline: Option<BenchSnapshot> = compare
+        .map(|path| -> canopy_core::Result<_> {
+            Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+        })
+        .transpose()?;
+
+    let mut runtime = make_runtime(service_url, api_key);
+    let snapshot = runtime.bench_queries(&repo_root, &suite);
+    let out = match (out, &baseline) {
+        (Some(path), _) => Some(path.to_path_buf()),
+        (None, None) => Some(queries.with_extension("json")),
+        (None, Some(_)) => None,
+    };
+    if let Some(path) = &out {
+        std::fs::write(path, serde_json::to_string_pretty(&snapshot)?)?;
+    }
+
+    let Some(baseline) = baseline else {
+        if json {
+            let path = out.as_ref().map(|p| p.display().to_string());
+            println!(
+                "{}",
+                serde_json::json!({ "path": path, "queries": snapshot.queries.len() })
+            );
+        } else {
+            for query in &snapshot.queries {
+                match &query.error {
+                    Some(err) => println!("{} {}: {}", "error".red(), query.name, err),
+                    None => println!(
+                        "{} {} handles, {} tokens, {}ms",
+                        query.name.bold(),
+                        query.handles.len(),
+                        query.total_tokens,
+                        query.duration_ms
+                    ),
+                }
+            }
+            if let Some(path) = &out {
+                println!("{} {}", "Wrote".green(), path.display());
+            }
+        }
+        return Ok(());
+    };
+
+    let report = compare_snapshots(&suite, &baseline, &snapshot);
+    print_bench_report(&report, json)?;
+    if report.has_regressions() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+pub(crate) fn cmd_repos(
+    service_url: Option<&str>,
+    json: bool,
+    api_key: Option<String>,
+) -> canopy_core::Result<()> {
+    use colored::Colorize;
+
+    let runtime = make_runtime(service_url, api_key);
+    let repos = runtime.list_repos()?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&repos)?);
+    } else {
+        if repos.is_empty() {
+            println!("No repos registered with the service.");
+        } else {
+            for repo in &repos {
+                let status_str = format!("{:?}", repo.status).to_lowercase();
+                let gen = format!("gen {}", repo.generation);
+                let sha = repo
+                    .commit_sha
+                    .as_ref()
+                    .map(|s| format!(" @ {}", &s[..8.min(s.len())]))
+                    .unwrap_or_default();
+                println!(
+                    "{}: {} [{}] ({}{}){}",
+                    repo.repo_id.cyan(),
+                    repo.name,
+                    status_str,
+                    gen,
+                    sha,
+                    if repo.repo_root != repo.name {
+                        format!(" — {}", repo.repo_root.dimmed())
+                    } else {
+                        String::new()
+                    }
+                );
+                if let Some(policy) = &repo.policy {
+                    for (label, patterns) in [("allow", &policy.allow), ("deny", &policy.deny)] {
+                        if !patterns.is_empty() {
+                            println!("  {} {}", label.dimmed(), patterns.join(", "));
+                        }
+                    }
+                }
+            }
+        }
+        println!("({} repos)", repos.len());
+    }
+    Ok(())
+}
+
+pub(crate) fn cmd_add_repo_url(
+    service_url: Option<&str>,
+    req: AddRepoRequest,
+    json: bool,
+    api_key: Option<String>,
+) -> canopy_core::Result<()> {
+    use colored::Colorize;
+
+    let runtime = make_runtime(service_url, api_key);
+    let added = runtime.add_repo_by_url(&req)?;
+    // A failed clone still registers the repo, in error status
+    let shard = runtime
+        .list_repos()?
+        .into_iter()
+        .find(|shard| shard.repo_id == added.repo_id);
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "repo_id": added.repo_id,
+                "name": added.name,
+                "repo": shard,
+            }))?
+        );
+    } else {
+        println!(
+            "{}: {} ({})",
+            "Added".green(),
+            added.name,
+            added.repo_id.cyan()
+        );
+        if let Some(shard) = &shard {
+            match &shard.error_message {
+                Some(message) => println!("{}: {}", "Clone failed".red(), message),
+                None => println!(
+                    "{}: {} — run `canopy reindex {}` to index it",
+                    "Checkout".blue(),
+                    shard.repo_root,
+                    added.repo_id
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn cmd_reindex(
+    service_url: Option<&str>,
+    repo: String,
+    glob: Option<String>,
+    json: bool,
+    api_key: Option<String>,
+) -> canopy_core::Result<()> {
+    use colored::Colorize;
+
+    let runtime = make_runtime(service_url, api_key);
+    let response = runtime.reindex_by_id(&repo, glob.as_deref())?;
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "generation": response.generation,
+                "status": response.status,
+                "commit_sha": response.commit_sha,
+            }))?
+        );
+    } else {
+        println!("{}: generation {}", "Reindex".green(), response.generation);
+        println!("{}: {}", "Status".blue(), response.status);
+        if let Some(sha) = &response.commit_sha {
+            println!("{}: {}", "Commit".blue(), sha);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn cmd_service_status(
+    service_url: Option<&str>,
+    json: bool,
+    api_key: Option<String>,
+) -> canopy_core::Result<()> {
+    use colored::Colorize;
+
+    let runtime = make_runtime(service_url, api_key);
+    let status = runtime.service_status()?;
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "service": status.service,
+                "repos": status.repos,
             }))?
         );
     } else {
@@ -410,14 +1890,364 @@ pub(crate) fn cmd_service_status(
         println!("{}: {} repos", "Repos".blue(), status.repos.len());
         for repo in &status.repos {
             let status_str = format!("{:?}", repo.status).to_lowercase();
+            let warmed = repo
+                .last_warmup_at
+                .map(|at| {
+                    let now = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map_or(at, |d| d.as_secs() as i64);
+                    format!(", warmed {}s ago", (now - at).max(0))
+                })
+                .unwrap_or_default();
             println!(
-                "  {} — {} [{}] gen {}",
+                "  {} — {} [{}] gen {}{}",
                 repo.name.cyan(),
                 repo.repo_root.dimmed(),
                 status_str,
-                repo.generation
+                repo.generation,
+                warmed
+            );
+        }
+    }
+    Ok(())
+}
+
+/// How long `canopy service run` waits for the started service to answer
+#[cfg(feature = "service")]
+const SERVICE_START_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long `canopy service stop` waits for the service to exit
+#[cfg(feature = "service")]
+const SERVICE_STOP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// `path` from `local_service`, which is `None` without a home directory.
+#[cfg(feature = "service")]
+fn local_service_file(path: Option<std::path::PathBuf>) -> canopy_core::Result<std::path::PathBuf> {
+    path.ok_or_else(|| canopy_core::CanopyError::ServiceError {
+        code: "no_state_dir".to_string(),
+        message: "No per-user state directory (HOME is unset)".to_string(),
+        hint: "Set HOME or XDG_STATE_HOME".to_string(),
+    })
+}
+
+/// The canopy-service binary: next to this executable, else from PATH.
+#[cfg(feature = "service")]
+fn service_binary() -> std::path::PathBuf {
+    let name = format!("canopy-service{}", std::env::consts::EXE_SUFFIX);
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(&name)))
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| std::path::PathBuf::from(name))
+}
+
+/// Start canopy-service for this machine (unless one is running), record it
+/// in the state file and register the current repo.
+#[cfg(feature = "service")]
+pub(crate) fn cmd_service_run(
+    root: Option<std::path::PathBuf>,
+    foreground: bool,
+    port: Option<u16>,
+    json: bool,
+) -> canopy_core::Result<()> {
+    use canopy_client::local_service::{log_path, state_path, LocalServiceState};
+    use canopy_core::CanopyError;
+    use std::process::{Command, Stdio};
+
+    let repo_root = detect_repo_root(root)?;
+    let state_path = local_service_file(state_path())?;
+    if let Some(state) = LocalServiceState::load(&state_path)?.filter(|s| s.is_running()) {
+        if !state.is_healthy() {
+            return Err(CanopyError::ServiceError {
+                code: "service_unavailable".to_string(),
+                message: format!(
+                    "canopy-service (pid {}) is recorded at {} but not answering",
+                    state.pid, state.url
+                ),
+                hint: "Run `canopy service stop`, then start it again".to_string(),
+            });
+        }
+        let repo_id = register_with_local_service(&state.url, &repo_root)?;
+        print_local_service(&state, &repo_id, false, json)?;
+        return Ok(());
+    }
+
+    let port = match port {
+        Some(port) => port,
+        None => std::net::TcpListener::bind("127.0.0.1:0")?
+            .local_addr()?
+            .port(),
+    };
+    let mut command = Command::new(service_binary());
+    command
+        .args(["--port", &port.to_string(), "--shutdown-drain-secs", "0"])
+        .stdin(Stdio::null());
+    if !foreground {
+        let log_path = local_service_file(log_path())?;
+        if let Some(dir) = log_path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let log = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)?;
+        command.stdout(log.try_clone()?).stderr(log);
+        // Its own process group, so the terminal's Ctrl-C doesn't reach it
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    }
+    let mut child = command.spawn().map_err(|e| CanopyError::ServiceError {
+        code: "service_start_failed".to_string(),
+        message: format!("Failed to start {}: {e}", service_binary().display()),
+        hint: "Install canopy-service next to canopy or on PATH".to_string(),
+    })?;
+
+    let state = LocalServiceState {
+        url: format!("http://127.0.0.1:{port}"),
+        pid: child.id(),
+        started_at: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+    };
+    let started = std::time::Instant::now();
+    while !state.is_healthy() {
+        if started.elapsed() >= SERVICE_START_TIMEOUT || child.try_wait()?.is_some() {
+            let _ = child.kill();
+            return Err(CanopyError::ServiceError {
+                code: "service_start_failed".to_string(),
+                message: format!("canopy-service did not come up on port {port}"),
+                hint: "See `canopy service logs`".to_string(),
+            });
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    state.save(&state_path)?;
+    let repo_id = register_with_local_service(&state.url, &repo_root)?;
+    print_local_service(&state, &repo_id, true, json)?;
+
+    if foreground {
+        child.wait()?;
+        let _ = std::fs::remove_file(&state_path);
+    }
+    Ok(())
+}
+
+/// Register `repo_root` with the service and start indexing it.
+#[cfg(feature = "service")]
+fn register_with_local_service(url: &str, repo_root: &Path) -> canopy_core::Result<String> {
+    let mut client = canopy_client::ServiceClient::new(url, None);
+    let repo_id = client.resolve_repo_id(repo_root)?;
+    client.reindex(&repo_id, None)?;
+    Ok(repo_id)
+}
+
+#[cfg(feature = "service")]
+fn print_local_service(
+    state: &canopy_client::local_service::LocalServiceState,
+    repo_id: &str,
+    started: bool,
+    json: bool,
+) -> canopy_core::Result<()> {
+    use colored::Colorize;
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "url": state.url,
+                "pid": state.pid,
+                "started_at": state.started_at,
+                "started": started,
+                "repo_id": repo_id,
+            }))?
+        );
+    } else {
+        let verb = if started { "Started" } else { "Running" };
+        println!(
+            "{}: canopy-service at {} (pid {})",
+            verb.green(),
+            state.url,
+            state.pid
+        );
+        println!("{}: {} (indexing)", "Registered".blue(), repo_id);
+    }
+    Ok(())
+}
+
+/// `/status` of the service in use, which without --service-url is the one
+/// `canopy service run` started.
+#[cfg(feature = "service")]
+pub(crate) fn cmd_local_service_status(
+    service_url: Option<&str>,
+    json: bool,
+    api_key: Option<String>,
+) -> canopy_core::Result<()> {
+    if service_url.is_none() {
+        if json {
+            println!("{}", serde_json::json!({ "service": null }));
+        } else {
+            println!("No local canopy-service is running (start one with `canopy service run`)");
+        }
+        return Ok(());
+    }
+    cmd_service_status(service_url, json, api_key)
+}
+
+/// Stop the service recorded in the state file and remove the record.
+#[cfg(feature = "service")]
+pub(crate) fn cmd_service_stop(json: bool) -> canopy_core::Result<()> {
+    use canopy_client::local_service::{pid_alive, state_path, LocalServiceState};
+    use canopy_core::CanopyError;
+    use colored::Colorize;
+
+    let state_path = local_service_file(state_path())?;
+    let state = LocalServiceState::load(&state_path)?.filter(|s| s.is_running());
+    if let Some(state) = &state {
+        let pid = state.pid.to_string();
+        #[cfg(unix)]
+        let signal = std::process::Command::new("kill")
+            .args(["-TERM", &pid])
+            .status()?;
+        #[cfg(not(unix))]
+        let signal = std::process::Command::new("taskkill")
+            .args(["/PID", &pid])
+            .status()?;
+        if !signal.success() {
+            return Err(CanopyError::ServiceError {
+                code: "service_stop_failed".to_string(),
+                message: format!("Could not signal canopy-service (pid {pid})"),
+                hint: "Stop the process manually".to_string(),
+            });
+        }
+        let started = std::time::Instant::now();
+        while pid_alive(state.pid) {
+            if started.elapsed() >= SERVICE_STOP_TIMEOUT {
+                return Err(CanopyError::ServiceError {
+                    code: "service_stop_failed".to_string(),
+                    message: format!("canopy-service (pid {pid}) is still running"),
+                    hint: "Stop the process manually".to_string(),
+                });
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    }
+    let _ = std::fs::remove_file(&state_path);
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "stopped": state.as_ref().map(|s| s.pid),
+            }))?
+        );
+    } else if let Some(state) = state {
+        println!("{}: canopy-service (pid {})", "Stopped".green(), state.pid);
+    } else {
+        println!("No local canopy-service is running");
+    }
+    Ok(())
+}
+
+/// Print the last `lines` of the service log, then with `follow` keep
+/// printing what is appended.
+#[cfg(feature = "service")]
+pub(crate) fn cmd_service_logs(lines: usize, follow: bool) -> canopy_core::Result<()> {
+    use canopy_client::local_service::log_path;
+    use std::io::{Read, Seek, SeekFrom};
+
+    let log_path = local_service_file(log_path())?;
+    let content = match std::fs::read_to_string(&log_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            println!("No service log at {}", log_path.display());
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let all: Vec<&str> = content.lines().collect();
+    for line in &all[all.len().saturating_sub(lines)..] {
+        println!("{line}");
+    }
+    if !follow {
+        return Ok(());
+    }
+
+    let mut file = std::fs::File::open(&log_path)?;
+    let mut offset = file.seek(SeekFrom::Start(content.len() as u64))?;
+    loop {
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        let len = file.metadata()?.len();
+        if len < offset {
+            // Truncated or replaced by a new run
+            offset = file.seek(SeekFrom::Start(0))?;
+        }
+        let mut appended = String::new();
+        file.read_to_string(&mut appended)?;
+        print!("{appended}");
+        offset += appended.len() as u64;
+    }
+}
+
+pub(crate) fn cmd_warmup(
+    root: Option<std::path::PathBuf>,
+    service_url: Option<&str>,
+    repos: Vec<String>,
+    json: bool,
+    api_key: Option<String>,
+) -> canopy_core::Result<()> {
+    use canopy_core::RepoIndex;
+    use colored::Colorize;
+
+    if service_url.is_none() {
+        let repo_root = detect_repo_root(root)?;
+        let started = std::time::Instant::now();
+        let index = RepoIndex::open(&repo_root)?;
+        let report = index.warm_up()?;
+        let total_ms = started.elapsed().as_millis() as u64;
+        if json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&serde_json::json!({
+                    "report": report,
+                    "total_ms": total_ms,
+                }))?
             );
+        } else {
+            println!(
+                "{}: {} symbols, {} nodes, {} databases in {} ms",
+                "Warmed".green(),
+                report.symbols,
+                report.nodes,
+                report.databases,
+                total_ms
+            );
+        }
+        return Ok(());
+    }
+
+    let runtime = make_runtime(service_url, api_key);
+    let response = runtime.warmup_by_ids((!repos.is_empty()).then_some(repos))?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&response)?);
+    } else {
+        for repo in &response.repos {
+            match (&repo.report, &repo.error) {
+                (Some(report), _) => println!(
+                    "{}: {} symbols, {} nodes, {} readers in {} ms",
+                    repo.repo_id.cyan(),
+                    report.symbols,
+                    report.nodes,
+                    repo.readers,
+                    repo.total_ms
+                ),
+                (None, error) => println!(
+                    "{}: {}",
+                    repo.repo_id.cyan(),
+                    error.as_deref().unwrap_or("not warmed").red()
+                ),
+            }
         }
+        println!("({} repos)", response.repos.len());
     }
     Ok(())
 }
@@ -2,14 +2,23 @@
 
 mod commands;
 mod output;
+mod render;
+mod watch;
 
+use canopy_core::protocol::AddRepoRequest;
 use clap::{Parser, Subcommand};
 
 use commands::{
-    cmd_expand, cmd_feedback_stats, cmd_index, cmd_init, cmd_invalidate, cmd_query, cmd_reindex,
-    cmd_repos, cmd_service_status, cmd_status,
+    cmd_add_repo_url, cmd_bench_queries, cmd_diff_symbols, cmd_expand, cmd_explore,
+    cmd_feedback_prune, cmd_feedback_stats, cmd_fixup_moves, cmd_index, cmd_init, cmd_invalidate,
+    cmd_list_presets, cmd_pin, cmd_pins, cmd_plan_index, cmd_query, cmd_reindex, cmd_related,
+    cmd_replay, cmd_repos, cmd_schema, cmd_service_status, cmd_shard, cmd_show_commit,
+    cmd_snapshot, cmd_status, cmd_summary, cmd_symbols, cmd_validate, cmd_warmup,
 };
+#[cfg(feature = "service")]
+use commands::{cmd_local_service_status, cmd_service_logs, cmd_service_run, cmd_service_stop};
 use output::print_error_and_exit;
+use render::RenderArgs;
 
 #[derive(Parser)]
 #[command(name = "canopy")]
@@ -23,14 +32,39 @@ struct Cli {
     #[arg(long, global = true)]
     json: bool,
 
-    /// Service URL for remote queries (e.g., http://localhost:3000)
+    /// Service URL for remote queries (e.g., http://localhost:3000). Defaults
+    /// to a running `canopy service run`; an empty value forces local mode
     #[arg(long, global = true, env = "CANOPY_SERVICE_URL")]
     service_url: Option<String>,
 
-    /// API key for service admin routes (also reads CANOPY_API_KEY env var)
+    /// API key for a secured service (also reads CANOPY_API_KEY env var, then
+    /// api_key in .canopy/credentials.toml)
     #[arg(long, global = true, env = "CANOPY_API_KEY")]
     api_key: Option<String>,
 
+    /// Attempts per service request, including the first (default 3; 1
+    /// disables retries)
+    #[arg(long, global = true, env = "CANOPY_SERVICE_RETRIES")]
+    service_retries: Option<String>,
+
+    /// Timeout of each service request attempt, e.g. "30s" or "500ms"
+    /// (default 30s)
+    #[arg(long, global = true, env = "CANOPY_SERVICE_TIMEOUT")]
+    service_timeout: Option<String>,
+
+    /// Append query/expand activity to an NDJSON session log (also reads CANOPY_SESSION_LOG)
+    #[arg(long, global = true, env = "CANOPY_SESSION_LOG")]
+    session_log: Option<std::path::PathBuf>,
+
+    /// Initialize .canopy/ when a command needs an index and there is none
+    /// (also reads CANOPY_AUTO_INIT)
+    #[arg(long, global = true)]
+    auto_init: bool,
+
+    /// When auto-initializing, also add .canopy/ to .gitignore
+    #[arg(long, global = true)]
+    update_gitignore: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -38,37 +72,190 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Create .canopy/ and config.toml
-    Init,
+    Init {
+        /// Config preset: rust, node, python, go or mixed (detected from root files if omitted)
+        #[arg(long)]
+        preset: Option<canopy_core::Preset>,
+        /// Replace an existing config.toml, keeping the old one as config.toml.bak
+        #[arg(long)]
+        force: bool,
+        /// List available presets and exit
+        #[arg(long)]
+        list_presets: bool,
+    },
 
     /// Index files matching glob pattern
     Index {
         /// Glob pattern (default from config)
         glob: Option<String>,
+
+        /// Index exactly the paths read from stdin, one per line, instead of
+        /// walking a glob
+        #[arg(long, conflicts_with = "glob")]
+        stdin_paths: bool,
+
+        /// Report what would be indexed, reindexed and skipped, without writing
+        #[arg(long, conflicts_with = "stdin_paths")]
+        dry_run: bool,
+
+        /// With --dry-run: list every file, not just the counts
+        #[arg(long, requires = "dry_run")]
+        verbose: bool,
+
+        /// Also ingest commit messages and touched files for `(commits ...)`,
+        /// even without `[history] enabled`
+        #[arg(long, conflicts_with = "dry_run")]
+        git_history: bool,
+
+        /// With --git-history: how far back to ingest, e.g. 90d (default:
+        /// `[history] since`)
+        #[arg(long, requires = "git_history", value_name = "DURATION", value_parser = parse_since)]
+        since: Option<std::time::Duration>,
+    },
+
+    /// Reindex files as they change on disk, until Ctrl-C
+    Watch {
+        /// Glob of files to reindex (default from config)
+        #[arg(long)]
+        glob: Option<String>,
+
+        /// Quiet period after the last change before reindexing, e.g. 500ms
+        /// or 2s
+        #[arg(long, default_value = "500ms", value_name = "DURATION", value_parser = parse_debounce)]
+        debounce: std::time::Duration,
+
+        /// Don't print a line per reindexed batch
+        #[arg(long)]
+        quiet: bool,
     },
 
     /// Run query and show handles
     Query {
         #[command(flatten)]
-        args: QueryArgs,
+        args: Box<QueryArgs>,
+    },
+
+    /// Build an evidence pack and expand its top handles within a token budget
+    Explore {
+        #[command(flatten)]
+        args: Box<ExploreArgs>,
     },
 
     /// Expand handles to content
     Expand {
-        /// Handle IDs to expand
+        #[command(flatten)]
+        args: ExpandArgs,
+    },
+
+    /// Pin handles so expand keeps following their nodes across reindexes
+    Pin {
+        /// Handle IDs to pin
+        #[arg(required = true)]
+        handle_ids: Vec<String>,
+    },
+
+    /// List pinned handles and whether each still resolves
+    Pins,
+
+    /// Check whether handles still match their files, without expanding them
+    Validate {
+        /// Handle IDs to check
+        #[arg(required = true)]
         handle_ids: Vec<String>,
     },
 
     /// Show index stats
-    Status,
+    Status {
+        /// Only files under this path (a directory covers everything below
+        /// it) or matching this glob
+        scope: Option<String>,
+
+        /// Tokens per directory, as a tree N levels deep (default: 1)
+        #[arg(
+            long,
+            value_name = "DEPTH",
+            num_args = 0..=1,
+            default_missing_value = "1",
+            conflicts_with = "scope"
+        )]
+        by_dir: Option<usize>,
+
+        /// List files that failed to parse and were indexed as plain chunks,
+        /// and the schema migrations applied to the index
+        #[arg(long)]
+        verbose: bool,
+
+        /// Break tokens down by node type and list the largest nodes
+        #[arg(long)]
+        detailed: bool,
+    },
+
+    /// Budgeted repo overview: layout, sizes, languages, README intro
+    Summary {
+        /// Approximate token budget for the summary (default: 1500)
+        #[arg(long)]
+        max_tokens: Option<usize>,
+    },
+
+    /// Files most related to a file by the symbols they share
+    Related {
+        /// Indexed file, repo-relative or as a path from here
+        path: String,
+        /// Max related files (default: 10)
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+
+    /// Index-wide symbol names in name order, for autocomplete
+    Symbols {
+        #[command(flatten)]
+        args: SymbolsArgs,
+    },
 
     /// Force reindex of files
     Invalidate {
         /// Glob pattern to invalidate (all if omitted)
         glob: Option<String>,
+
+        /// Report what would be removed, without writing
+        #[arg(long)]
+        dry_run: bool,
+
+        /// With --dry-run: list every file, not just the counts
+        #[arg(long, requires = "dry_run")]
+        verbose: bool,
+    },
+
+    /// Purge rows of files that moved and alias their old handles to the new paths
+    FixupMoves {
+        /// List every move, not just the counts
+        #[arg(long)]
+        verbose: bool,
+    },
+
+    /// Move indexed files into per-directory shards per `[indexing] shard_by`
+    Shard {
+        /// Perform the migration (default: only report what would move)
+        #[arg(long)]
+        apply: bool,
     },
 
-    /// List repos registered with the service
-    Repos,
+    /// List repos registered with the service, or register one it clones
+    Repos {
+        /// Have the service clone this git URL (shallow) and keep it updated
+        /// on reindex; needs an admin API key
+        #[arg(long, value_name = "URL")]
+        add_url: Option<String>,
+        /// With --add-url: branch to follow (default: the remote's default)
+        #[arg(long, requires = "add_url")]
+        branch: Option<String>,
+        /// With --add-url: sparse-checkout only this directory (repeatable)
+        #[arg(long = "sparse", value_name = "DIR", requires = "add_url")]
+        sparse_paths: Vec<String>,
+        /// With --add-url: repo name (default: last URL segment)
+        #[arg(long, requires = "add_url")]
+        name: Option<String>,
+    },
 
     /// Trigger reindex on the service
     Reindex {
@@ -82,12 +269,135 @@ enum Commands {
     /// Show service status
     ServiceStatus,
 
+    /// Run a background canopy-service shared by this machine's checkouts
+    #[cfg(feature = "service")]
+    Service {
+        #[command(subcommand)]
+        command: ServiceCommand,
+    },
+
+    /// Preload index caches: the local index, or the service's repos when a
+    /// service URL is set
+    Warmup {
+        /// Service mode: repo ID to warm (repeatable; all ready repos if omitted)
+        #[arg(long = "repo")]
+        repos: Vec<String>,
+    },
+
+    /// Re-run queries from a session log and diff the returned handles
+    Replay {
+        /// Session log written via --session-log / CANOPY_SESSION_LOG
+        log: std::path::PathBuf,
+        /// Replay against this service URL instead of the local index
+        #[arg(long)]
+        against_service: Option<String>,
+    },
+
+    /// Run a suite of named queries and snapshot the results, or diff them
+    /// against an earlier snapshot
+    BenchQueries {
+        /// Suite TOML: [[query]] tables of query params plus a name
+        queries: std::path::PathBuf,
+        /// Snapshot to diff against; exits 1 when a threshold is exceeded
+        #[arg(long)]
+        compare: Option<std::path::PathBuf>,
+        /// Where to write the snapshot (default: the suite path with a .json
+        /// extension; with --compare, none unless given)
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+    },
+
+    /// Record the indexed symbols as an anchor for `diff-symbols`
+    Snapshot {
+        /// Snapshot name under .canopy/snapshots/ (default: snapshot-<unix time>)
+        #[arg(long)]
+        name: Option<String>,
+    },
+
+    /// List symbols added, removed, changed or renamed since a snapshot or git ref
+    DiffSymbols {
+        /// Snapshot name or path, or a git ref (branch, tag, commit)
+        #[arg(long)]
+        since: String,
+    },
+
+    /// Show a commit's message and diff, token-capped
+    ShowCommit {
+        /// Commit sha, or any revision git resolves to a commit
+        sha: String,
+        /// Only the diff of this path
+        #[arg(long)]
+        path: Option<String>,
+        /// Approximate token cap on the diff (default: `[history] max_diff_tokens`)
+        #[arg(long)]
+        max_tokens: Option<usize>,
+    },
+
     /// Show local feedback metrics
     FeedbackStats {
         /// Lookback window in days (default: 7)
         #[arg(long)]
         lookback_days: Option<f64>,
     },
+
+    /// Manage the local feedback store
+    Feedback {
+        #[command(subcommand)]
+        command: FeedbackCommand,
+    },
+
+    /// Print the JSON Schema of a JSON output (the shape `--json` prints)
+    Schema {
+        #[arg(value_parser = clap::builder::PossibleValuesParser::new(canopy_core::SCHEMA_TYPES))]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum FeedbackCommand {
+    /// Show feedback metrics, optionally per agent session
+    Show {
+        /// Lookback window in days (default: 7)
+        #[arg(long)]
+        lookback_days: Option<f64>,
+        /// One row per session (`CANOPY_SESSION_ID` or MCP `session_id`), busiest first
+        #[arg(long)]
+        by_session: bool,
+    },
+    /// Delete old feedback events now, instead of waiting for the next open
+    Prune {
+        /// Keep this many days of events (default: `[feedback] retention_days`)
+        #[arg(long)]
+        days: Option<u64>,
+    },
+}
+
+#[cfg(feature = "service")]
+#[derive(Subcommand)]
+enum ServiceCommand {
+    /// Start canopy-service in the background (if it isn't running) and
+    /// register this repo; other commands then use it without --service-url
+    Run {
+        /// Run in this terminal instead, until interrupted
+        #[arg(long)]
+        foreground: bool,
+        /// Port to listen on (default: a free one)
+        #[arg(long)]
+        port: Option<u16>,
+    },
+    /// Stop the background service
+    Stop,
+    /// Show the background service's status
+    Status,
+    /// Print the background service's log
+    Logs {
+        /// Lines from the end to print
+        #[arg(short = 'n', long, default_value_t = 50)]
+        lines: usize,
+        /// Keep printing lines as they are written
+        #[arg(short, long)]
+        follow: bool,
+    },
 }
 
 #[derive(clap::Args)]
@@ -111,18 +421,54 @@ pub(crate) struct QueryArgs {
     #[arg(long)]
     pub(crate) section: Option<String>,
 
+    /// Search nested sections by heading path suffix ("auth > configuration" or "auth/configuration")
+    #[arg(long)]
+    pub(crate) section_path: Option<String>,
+
     /// Filter by parent symbol (e.g., class name for methods)
     #[arg(long)]
     pub(crate) parent: Option<String>,
 
-    /// Query kind: definition, reference, or any (default)
-    #[arg(short, long, value_parser = ["definition", "reference", "any"])]
+    /// Query kind: definition, reference, annotation, commit, or any (default)
+    #[arg(short, long, value_parser = ["definition", "reference", "annotation", "commit", "any"])]
     pub(crate) kind: Option<String>,
 
+    /// With --kind reference, keep only these reference kinds (repeatable)
+    #[arg(long = "ref-type", value_name = "TYPE", value_parser = ["call", "import", "type"])]
+    pub(crate) ref_types: Vec<String>,
+
+    /// With --kind reference, fold refs with identical previews into one
+    /// (default: imports only)
+    #[arg(long, value_name = "BOOL")]
+    pub(crate) group_references: Option<bool>,
+
     /// Filter by file glob pattern
     #[arg(short, long)]
     pub(crate) glob: Option<String>,
 
+    /// Only files whose path contains this text, ignoring case (no glob
+    /// syntax; alone, lists the matching files)
+    #[arg(long = "path", value_name = "FRAGMENT")]
+    pub(crate) path_contains: Option<String>,
+
+    /// Without --glob or --path, search first in the directories the query
+    /// terms name (e.g. src/websocket/ for "websocket"), then everywhere if
+    /// nothing matches there
+    #[arg(long)]
+    pub(crate) infer_glob: bool,
+
+    /// Also search files flagged as generated (protobuf output, bundles, `@generated`)
+    #[arg(long)]
+    pub(crate) include_generated: bool,
+
+    /// Only files changed within this window (e.g. 48h, 7d, 2w)
+    #[arg(long, value_name = "WINDOW")]
+    pub(crate) recent: Option<String>,
+
+    /// Leave out results whose content matches this pattern (repeatable)
+    #[arg(long = "exclude-pattern", value_name = "PATTERN")]
+    pub(crate) exclude_patterns: Vec<String>,
+
     /// Multi-pattern match mode: any (default) or all
     #[arg(long, value_name = "MODE", value_parser = ["any", "all"])]
     pub(crate) r#match: Option<String>,
@@ -134,48 +480,372 @@ pub(crate) struct QueryArgs {
     /// Override default result limit
     #[arg(long)]
     pub(crate) limit: Option<usize>,
+
+    /// Service mode: results taken from the local dirty-file index before merging (default: --limit)
+    #[arg(long)]
+    pub(crate) local_limit: Option<usize>,
+
+    /// Service mode: results taken from the service before merging (default: --limit)
+    #[arg(long)]
+    pub(crate) service_limit: Option<usize>,
+
+    /// Service mode: how local and service results are ordered after merging
+    #[arg(long, value_name = "STRATEGY", value_parser = ["rank_interleave", "local_first", "service_first"])]
+    pub(crate) merge_strategy: Option<String>,
+
+    /// Ranking multiplier for a node type as TYPE=WEIGHT (repeatable), e.g.
+    /// `--prior section=2.0 --prior function=0.5`; replaces the priors
+    /// learned from feedback
+    #[arg(long = "prior", value_name = "TYPE=WEIGHT")]
+    pub(crate) priors: Vec<String>,
+
+    /// Rerank candidates with this command (overrides `[rerank] command` in config)
+    #[arg(long, value_name = "CMD")]
+    pub(crate) rerank_cmd: Option<String>,
+
+    /// After the results, show which search produced each one and, for
+    /// misses, what to check
+    #[arg(long)]
+    pub(crate) explain: bool,
+
+    /// Only count matches, per combined search, without returning handles
+    #[arg(long, conflicts_with = "exists")]
+    pub(crate) count: bool,
+
+    /// Only check whether anything matches; exits 1 when nothing does
+    #[arg(long)]
+    pub(crate) exists: bool,
+
+    #[command(flatten)]
+    pub(crate) render: RenderArgs,
+}
+
+#[derive(clap::Args)]
+pub(crate) struct ExpandArgs {
+    /// Handle IDs to expand; `h1a2b:10-40` keeps only those lines of the node
+    pub(crate) handle_ids: Vec<String>,
+
+    /// Show a diff against the content last expanded for each handle
+    /// (or "unchanged"), falling back to full content without a baseline
+    #[arg(long)]
+    pub(crate) diff: bool,
+
+    #[command(flatten)]
+    pub(crate) render: RenderArgs,
+}
+
+#[derive(clap::Args)]
+pub(crate) struct SymbolsArgs {
+    /// Only names starting with this (case-insensitive)
+    #[arg(long)]
+    pub(crate) prefix: Option<String>,
+
+    /// Only this node type (repeatable)
+    #[arg(
+        long = "type",
+        value_name = "TYPE",
+        value_parser = ["section", "code_block", "paragraph", "function", "class", "struct", "method", "chunk"]
+    )]
+    pub(crate) node_types: Vec<String>,
+
+    /// Resume after the cursor a previous page printed
+    #[arg(long)]
+    pub(crate) after: Option<String>,
+
+    /// Max symbols (default: 100)
+    #[arg(long)]
+    pub(crate) limit: Option<usize>,
+}
+
+#[derive(clap::Args)]
+pub(crate) struct ExploreArgs {
+    #[command(flatten)]
+    pub(crate) query: QueryArgs,
+
+    /// Tokens of expanded content to spend; the last handle that doesn't fit is truncated
+    #[arg(long, default_value_t = 4000)]
+    pub(crate) budget: usize,
+
+    /// Maximum ranked handles in the evidence pack
+    #[arg(long, default_value_t = 8)]
+    pub(crate) max_handles: usize,
+
+    /// Maximum handles selected from a single file
+    #[arg(long, default_value_t = 2)]
+    pub(crate) max_per_file: usize,
+}
+
+/// `--since` window such as "90d" or "2w".
+fn parse_since(s: &str) -> Result<std::time::Duration, String> {
+    canopy_core::config::parse_duration(s)
+        .ok_or_else(|| format!("expected a duration like 90d, 48h or 2w, got {s:?}"))
+}
+
+/// `--debounce` window such as "500ms" or "2s".
+fn parse_debounce(s: &str) -> Result<std::time::Duration, String> {
+    let window = match s.trim().strip_suffix("ms") {
+        Some(ms) => ms.parse().ok().map(std::time::Duration::from_millis),
+        None => canopy_core::config::parse_duration(s),
+    };
+    window.ok_or_else(|| format!("expected a duration like 500ms or 2s, got {s:?}"))
+}
+
+/// Service API key: `--api-key`/CANOPY_API_KEY, falling back to the repo's
+/// credentials file. The file is only read when a service is in use, so a
+/// broken one never gets in the way of local commands.
+fn resolve_api_key(cli: &Cli) -> canopy_core::Result<Option<String>> {
+    let uses_service = cli.service_url.is_some()
+        || matches!(
+            cli.command,
+            Commands::Replay {
+                against_service: Some(_),
+                ..
+            }
+        );
+    if !uses_service {
+        return Ok(cli.api_key.clone());
+    }
+    let repo_root = commands::detect_repo_root(cli.root.clone()).ok();
+    canopy_client::credentials::resolve_api_key(cli.api_key.clone(), repo_root.as_deref())
 }
 
 fn main() {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    cli.service_url = canopy_client::local_service::resolve_service_url(cli.service_url.take());
 
     let json = cli.json;
-    let api_key = cli.api_key;
+    let api_key = match resolve_api_key(&cli) {
+        Ok(key) => key,
+        Err(e) => print_error_and_exit(e, json),
+    };
+    let session_log = cli.session_log.as_deref();
+    let auto_init = if cli.auto_init {
+        canopy_core::AutoInit::on()
+    } else {
+        canopy_core::AutoInit::from_env(false)
+    };
+    commands::set_auto_init(auto_init.with_update_gitignore(cli.update_gitignore));
+    match canopy_client::RetryPolicy::parse(
+        cli.service_retries.as_deref(),
+        cli.service_timeout.as_deref(),
+    ) {
+        Ok(policy) => commands::set_retry_policy(policy),
+        Err(e) => print_error_and_exit(e, json),
+    }
     let result = match cli.command {
-        Commands::Init => cmd_init(cli.root),
-        Commands::Index { glob } => cmd_index(
+        Commands::Init {
+            preset,
+            force,
+            list_presets,
+        } => {
+            if list_presets {
+                cmd_list_presets(cli.json)
+            } else {
+                cmd_init(cli.root, preset, force)
+            }
+        }
+        Commands::Index {
+            glob,
+            dry_run: true,
+            verbose,
+            ..
+        } => cmd_plan_index(
+            cli.root,
+            glob,
+            verbose,
+            cli.json,
+            cli.service_url.as_deref(),
+            api_key,
+        ),
+        Commands::Index {
+            glob,
+            stdin_paths,
+            git_history,
+            since,
+            ..
+        } => cmd_index(
             cli.root,
             glob,
+            stdin_paths,
+            git_history.then_some(since),
             cli.json,
             cli.service_url.as_deref(),
             api_key,
         ),
+        Commands::Watch {
+            glob,
+            debounce,
+            quiet,
+        } => watch::cmd_watch(cli.root, glob, debounce, quiet, cli.json),
         Commands::Query { args } => cmd_query(
+            cli.root,
+            *args,
+            cli.json,
+            cli.service_url.as_deref(),
+            api_key,
+            session_log,
+        ),
+        Commands::Explore { args } => cmd_explore(
+            cli.root,
+            *args,
+            cli.json,
+            cli.service_url.as_deref(),
+            api_key,
+            session_log,
+        ),
+        Commands::Expand { args } => cmd_expand(
             cli.root,
             args,
             cli.json,
             cli.service_url.as_deref(),
             api_key,
+            session_log,
+        ),
+        Commands::Pin { handle_ids } => cmd_pin(
+            cli.root,
+            &handle_ids,
+            cli.json,
+            cli.service_url.as_deref(),
+            api_key,
         ),
-        Commands::Expand { handle_ids } => cmd_expand(
+        Commands::Pins => cmd_pins(cli.root, cli.json, cli.service_url.as_deref(), api_key),
+        Commands::Validate { handle_ids } => cmd_validate(
             cli.root,
             &handle_ids,
             cli.json,
             cli.service_url.as_deref(),
             api_key,
         ),
-        Commands::Status => cmd_status(cli.root, cli.json),
-        Commands::Invalidate { glob } => cmd_invalidate(cli.root, glob, cli.json),
-        Commands::Repos => cmd_repos(cli.service_url.as_deref(), cli.json, api_key),
+        Commands::Status {
+            scope,
+            by_dir,
+            verbose,
+            detailed,
+        } => cmd_status(
+            cli.root,
+            scope.as_deref(),
+            by_dir,
+            verbose,
+            detailed,
+            cli.json,
+        ),
+        Commands::Summary { max_tokens } => cmd_summary(
+            cli.root,
+            max_tokens,
+            cli.json,
+            cli.service_url.as_deref(),
+            api_key,
+        ),
+        Commands::Related { path, limit } => cmd_related(
+            cli.root,
+            &path,
+            limit,
+            cli.json,
+            cli.service_url.as_deref(),
+            api_key,
+        ),
+        Commands::Symbols { args } => cmd_symbols(
+            cli.root,
+            &args,
+            cli.json,
+            cli.service_url.as_deref(),
+            api_key,
+        ),
+        Commands::Invalidate {
+            glob,
+            dry_run,
+            verbose,
+        } => cmd_invalidate(cli.root, glob, dry_run, verbose, cli.json),
+        Commands::FixupMoves { verbose } => cmd_fixup_moves(cli.root, verbose, cli.json),
+        Commands::Shard { apply } => cmd_shard(cli.root, apply, cli.json),
+        Commands::Repos {
+            add_url: Some(git_url),
+            branch,
+            sparse_paths,
+            name,
+        } => cmd_add_repo_url(
+            cli.service_url.as_deref(),
+            AddRepoRequest {
+                git_url: Some(git_url),
+                branch,
+                sparse_paths: (!sparse_paths.is_empty()).then_some(sparse_paths),
+                name,
+                path: None,
+                policy: None,
+            },
+            cli.json,
+            api_key,
+        ),
+        Commands::Repos { add_url: None, .. } => {
+            cmd_repos(cli.service_url.as_deref(), cli.json, api_key)
+        }
         Commands::Reindex { repo, glob } => {
             cmd_reindex(cli.service_url.as_deref(), repo, glob, cli.json, api_key)
         }
         Commands::ServiceStatus => {
             cmd_service_status(cli.service_url.as_deref(), cli.json, api_key)
         }
+        #[cfg(feature = "service")]
+        Commands::Service { command } => match command {
+            ServiceCommand::Run { foreground, port } => {
+                cmd_service_run(cli.root, foreground, port, cli.json)
+            }
+            ServiceCommand::Stop => cmd_service_stop(cli.json),
+            ServiceCommand::Status => {
+                cmd_local_service_status(cli.service_url.as_deref(), cli.json, api_key)
+            }
+            ServiceCommand::Logs { lines, follow } => cmd_service_logs(lines, follow),
+        },
+        Commands::Warmup { repos } => cmd_warmup(
+            cli.root,
+            cli.service_url.as_deref(),
+            repos,
+            cli.json,
+            api_key,
+        ),
+        Commands::Replay {
+            log,
+            against_service,
+        } => cmd_replay(
+            &log,
+            against_service.as_deref().or(cli.service_url.as_deref()),
+            cli.json,
+            api_key,
+        ),
+        Commands::BenchQueries {
+            queries,
+            compare,
+            out,
+        } => cmd_bench_queries(
+            cli.root,
+            &queries,
+            compare.as_deref(),
+            out.as_deref(),
+            cli.json,
+            cli.service_url.as_deref(),
+            api_key,
+        ),
+        Commands::Snapshot { name } => cmd_snapshot(cli.root, name.as_deref(), cli.json),
+        Commands::DiffSymbols { since } => cmd_diff_symbols(cli.root, &since, cli.json),
+        Commands::ShowCommit {
+            sha,
+            path,
+            max_tokens,
+        } => cmd_show_commit(cli.root, &sha, path.as_deref(), max_tokens, cli.json),
         Commands::FeedbackStats { lookback_days } => {
-            cmd_feedback_stats(cli.root, cli.json, lookback_days)
+            cmd_feedback_stats(cli.root, cli.json, lookback_days, false)
         }
+        Commands::Feedback {
+            command:
+                FeedbackCommand::Show {
+                    lookback_days,
+                    by_session,
+                },
+        } => cmd_feedback_stats(cli.root, cli.json, lookback_days, by_session),
+        Commands::Feedback {
+            command: FeedbackCommand::Prune { days },
+        } => cmd_feedback_prune(cli.root, cli.json, days),
+        Commands::Schema { name } => cmd_schema(&name),
     };
 
     if let Err(e) = result {
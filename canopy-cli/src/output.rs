@@ -2,13 +2,28 @@
 
 use colored::Colorize;
 
+use crate::render::ContentRenderer;
+
 /// Print query results in text or JSON format.
 pub(crate) fn print_query_result(
     result: &canopy_core::QueryResult,
+    renderer: &ContentRenderer,
     json: bool,
 ) -> canopy_core::Result<()> {
     if json {
         println!("{}", serde_json::to_string_pretty(&result)?);
+    } else if let Some(exists) = result.exists {
+        match &result.witness_path {
+            Some(path) if exists => println!("{} ({})", "yes".green(), path),
+            _ => println!("{}", "no".red()),
+        }
+    } else if let Some(counts) = &result.match_counts {
+        if counts.len() > 1 {
+            for (search, count) in counts {
+                println!("{}: {}", search, count);
+            }
+        }
+        println!("{}", format!("{} matches", result.total_matches).bold());
     } else if let Some(refs) = &result.ref_handles {
         for reference in refs {
             let qualifier = reference
@@ -35,6 +50,52 @@ pub(crate) fn print_query_result(
             if !source.is_empty() {
                 println!("{}", source);
             }
+            if reference.occurrence_count > 0 {
+                let kind = match reference.ref_type {
+                    canopy_core::RefType::Call => "calls",
+                    canopy_core::RefType::Import => "imports",
+                    canopy_core::RefType::TypeRef => "type refs",
+                };
+                println!(
+                    "  {}",
+                    format!("(+{} more identical {})", reference.occurrence_count, kind).dimmed()
+                );
+            }
+        }
+        if let Some(counts) = &result.ref_type_counts {
+            let counts: Vec<String> = counts
+                .iter()
+                .map(|(ref_type, count)| format!("{} {}", ref_type, count))
+                .collect();
+            if !counts.is_empty() {
+                println!("{}", format!("by type: {}", counts.join(", ")).dimmed());
+            }
+        }
+    } else if let Some(annotations) = &result.annotations {
+        for annotation in annotations {
+            let source = annotation
+                .source_handle
+                .as_ref()
+                .map(|h| format!(" in {}", h.to_string().cyan()))
+                .unwrap_or_default();
+            println!(
+                "{}: {}:{} {}{}",
+                "annotation".cyan(),
+                annotation.file_path,
+                annotation.line,
+                annotation.text,
+                source
+            );
+        }
+    } else if let Some(commits) = &result.commits {
+        for commit in commits {
+            println!(
+                "{} {} {} ({} files)",
+                commit.sha[..commit.sha.len().min(12)].cyan(),
+                commit.date,
+                commit.subject,
+                commit.files_touched
+            );
         }
     } else {
         for handle in &result.handles {
@@ -48,28 +109,67 @@ pub(crate) fn print_query_result(
                     handle.line_range.1,
                     handle.token_count,
                 );
-                println!("{}", content);
+                println!(
+                    "{}",
+                    renderer.render(content, &handle.file_path, Some(handle.line_range.0))
+                );
                 println!();
             } else {
-                // Not expanded: show preview
+                // Not expanded: show preview, at the matched line when known
+                let location = match handle.match_line {
+                    Some(line) => format!(
+                        "{}:{} ({}-{})",
+                        handle.file_path, line, handle.line_range.0, handle.line_range.1
+                    ),
+                    None => format!(
+                        "{}:{}-{}",
+                        handle.file_path, handle.line_range.0, handle.line_range.1
+                    ),
+                };
                 println!(
-                    "{}: {}:{}-{} [{} tokens] {:?}",
+                    "{}: {} [{} tokens]{} {:?}",
                     handle.id.to_string().cyan(),
-                    handle.file_path,
-                    handle.line_range.0,
-                    handle.line_range.1,
+                    location,
                     handle.token_count,
+                    if handle.possibly_stale {
+                        " (possibly stale)".yellow().to_string()
+                    } else {
+                        String::new()
+                    },
                     handle.preview
                 );
             }
         }
     }
 
+    if !json && result.handles.is_empty() && !result.suggestions.is_empty() {
+        let names: Vec<String> = result
+            .suggestions
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                if i == 0 {
+                    let noun = if s.definitions == 1 {
+                        "definition"
+                    } else {
+                        "definitions"
+                    };
+                    format!("{} ({} {})", s.name.cyan(), s.definitions, noun)
+                } else {
+                    format!("{} ({})", s.name.cyan(), s.definitions)
+                }
+            })
+            .collect();
+        println!("No results. Did you mean: {}?", names.join(", "));
+    }
+
     let shown = result
         .ref_handles
         .as_ref()
         .map(|r| r.len())
-        .unwrap_or_else(|| result.handles.len());
+        .or(result.annotations.as_ref().map(|a| a.len()))
+        .or(result.commits.as_ref().map(|c| c.len()))
+        .unwrap_or(result.handles.len());
     if result.truncated {
         println!(
             "... ({} showing {} of {} results)",
@@ -78,13 +178,71 @@ pub(crate) fn print_query_result(
             result.total_matches
         );
     }
+    if result.suppressed_service_handles > 0 {
+        println!(
+            "({} service handles superseded by local changes)",
+            result.suppressed_service_handles
+        );
+    }
+    if result.suppressed_by_policy > 0 {
+        println!(
+            "({} results hidden by the repo's path policy)",
+            result.suppressed_by_policy
+        );
+    }
+    if result.suppressed_generated > 0 {
+        println!(
+            "({} matches in generated files hidden; --include-generated to show)",
+            result.suppressed_generated
+        );
+    }
+    if result.excluded_matches > 0 {
+        println!(
+            "({} matches left out by --exclude-pattern)",
+            result.excluded_matches
+        );
+    }
+    if result.redactions > 0 {
+        println!(
+            "({} secrets redacted from expanded content)",
+            result.redactions
+        );
+    }
+    if result.compat_mode {
+        println!(
+            "(read-only from an index written by a newer canopy; upgrade to index or see all of it)"
+        );
+    }
+    if let Some(sources) = result.sources.as_ref().filter(|s| s.local > 0) {
+        let truncated: Vec<&str> = [
+            sources.local_truncated.then_some("local"),
+            sources.service_truncated.then_some("service"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        println!(
+            "({} local, {} service{})",
+            sources.local,
+            sources.service,
+            if truncated.is_empty() {
+                String::new()
+            } else {
+                format!("; {} truncated", truncated.join(" and "))
+            }
+        );
+    }
+    if let Some(glob) = &result.inferred_glob {
+        println!("{}: {}", "Scoped to inferred glob".yellow(), glob);
+    }
     if let Some(note) = &result.expand_note {
         println!("{}: {}", "Note".yellow(), note);
     }
     println!(
-        "({} results, {} tokens{})",
+        "({} results, {} tokens, {} in previews{})",
         shown,
         result.total_tokens,
+        result.preview_tokens,
         if result.auto_expanded {
             ", auto-expanded"
         } else {
@@ -97,6 +255,285 @@ pub(crate) fn print_query_result(
             result.expanded_count, result.expanded_tokens
         );
     }
+    if let Some(savings) = result.savings.as_ref().filter(|s| s.tokens_avoided() > 0) {
+        let files = savings.files.len();
+        println!(
+            "(~{} tokens avoided vs reading {} {})",
+            approx_tokens(savings.tokens_avoided()),
+            files,
+            if files == 1 { "file" } else { "files" }
+        );
+    }
+    if !json {
+        for error in &result.pattern_errors {
+            eprintln!(
+                "{}: pattern {:?} failed: {}",
+                "Warning".yellow(),
+                error.pattern,
+                error.message
+            );
+        }
+        if let Some(explain) = &result.explain {
+            print_explain(explain, &result.handles);
+        }
+    }
+    Ok(())
+}
+
+/// Explain mode, after the results: searches run, then what found each
+/// handle, then the miss checklist.
+fn print_explain(explain: &canopy_core::QueryExplain, handles: &[canopy_core::Handle]) {
+    println!();
+    println!("{}:", "Explain".blue());
+    for search in &explain.searches {
+        let mut details = vec![format!("{} of limit {}", search.returned, search.limit)];
+        if let Some(fts) = &search.fts_query {
+            details.push(format!("MATCH {fts}"));
+        }
+        if let Some(glob) = &search.glob {
+            details.push(format!("glob {glob}"));
+        }
+        if let Some(filter) = &search.filter {
+            details.push(format!("where {filter}"));
+        }
+        if let Some(scan) = &search.scan {
+            details.push(format!(
+                "{} rows scanned in {:.1}ms{}",
+                scan.rows_examined,
+                scan.elapsed_us as f64 / 1000.0,
+                if scan.capped { ", capped" } else { "" }
+            ));
+        }
+        println!(
+            "  {} {:?} ({})",
+            search.via.as_str().cyan(),
+            search.input,
+            details.join(", ")
+        );
+    }
+    if explain.glob_filtered > 0 {
+        println!(
+            "  {} candidates rejected by glob before the limit",
+            explain.glob_filtered
+        );
+    }
+    if let Some(priors) = &explain.node_type_priors {
+        let priors: Vec<String> = priors
+            .iter()
+            .map(|(node_type, weight)| format!("{node_type} {weight:.2}"))
+            .collect();
+        println!("  expansion priors: {}", priors.join(", "));
+    }
+    if explain.reranked {
+        println!("  reordered by the reranker");
+    }
+    for handle in handles {
+        if let Some(via) = handle.via {
+            println!(
+                "  {} {}:{}-{}",
+                format!("via {}", via.as_str()).dimmed(),
+                handle.file_path,
+                handle.line_range.0,
+                handle.line_range.1
+            );
+        }
+    }
+    for item in &explain.checklist {
+        println!("  - {item}");
+    }
+}
+
+/// Compact token count: `950`, `4.2k`, `41k`.
+fn approx_tokens(tokens: usize) -> String {
+    match tokens {
+        0..=999 => tokens.to_string(),
+        1_000..=9_999 => format!("{:.1}k", tokens as f64 / 1000.0),
+        _ => format!("{}k", (tokens + 500) / 1000),
+    }
+}
+
+/// Print a session replay report in text or JSON format.
+pub(crate) fn print_replay_report(
+    report: &canopy_client::ReplayReport,
+    json: bool,
+) -> canopy_core::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(report)?);
+        return Ok(());
+    }
+
+    for query in &report.queries {
+        if let Some(err) = &query.error {
+            println!(
+                "{} #{} {}: {}",
+                "error".red(),
+                query.index,
+                query.query_text,
+                err
+            );
+            continue;
+        }
+        let marker = if query.is_changed() {
+            "changed".yellow()
+        } else {
+            "same".green()
+        };
+        println!(
+            "{} #{} {} (+{} -{}, {} -> {} tokens)",
+            marker,
+            query.index,
+            query.query_text,
+            query.added.len(),
+            query.missing.len(),
+            query.old_tokens,
+            query.new_tokens
+        );
+        for id in &query.added {
+            println!("    + {}", id.cyan());
+        }
+        for id in &query.missing {
+            println!("    - {}", id.dimmed());
+        }
+    }
+
+    println!(
+        "({} queries replayed, {} changed, {} -> {} tokens)",
+        report.queries_replayed,
+        report.queries_changed,
+        report.old_total_tokens,
+        report.new_total_tokens
+    );
+    Ok(())
+}
+
+/// Print a query benchmark comparison in text or JSON format.
+pub(crate) fn print_bench_report(
+    report: &canopy_client::BenchReport,
+    json: bool,
+) -> canopy_core::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(report)?);
+        return Ok(());
+    }
+
+    for query in &report.queries {
+        let marker = if !query.regressions.is_empty() {
+            "regressed".red()
+        } else if query.is_changed() {
+            "changed".yellow()
+        } else {
+            "same".green()
+        };
+        println!(
+            "{} {} (+{} -{} ~{}, tokens {:+}, {:+}ms)",
+            marker,
+            query.name.bold(),
+            query.added.len(),
+            query.removed.len(),
+            query.moved.len(),
+            query.token_delta(),
+            query.ms_delta()
+        );
+        if let Some(err) = &query.error {
+            println!("    {}: {}", "error".red(), err);
+        }
+        for handle in &query.added {
+            println!(
+                "    + {} {}:{}-{}",
+                handle.id.cyan(),
+                handle.file_path,
+                handle.line_range.0,
+                handle.line_range.1
+            );
+        }
+        for handle in &query.removed {
+            println!(
+                "    - {} {}:{}-{}",
+                handle.id.dimmed(),
+                handle.file_path,
+                handle.line_range.0,
+                handle.line_range.1
+            );
+        }
+        for moved in &query.moved {
+            println!(
+                "    ~ {} #{} -> #{}",
+                moved.id, moved.old_rank, moved.new_rank
+            );
+        }
+        for regression in &query.regressions {
+            println!("    {} {}", "!".red(), regression);
+        }
+    }
+    for name in &report.dropped_queries {
+        println!("{} {} (not in the suite)", "dropped".dimmed(), name);
+    }
+
+    println!(
+        "({} queries compared, {} changed, {} regressed)",
+        report.queries_compared, report.queries_changed, report.queries_regressed
+    );
+    Ok(())
+}
+
+/// Print an index or invalidate dry run: counts, and with `verbose` every file.
+pub(crate) fn print_index_plan(
+    plan: &canopy_core::IndexPlan,
+    verbose: bool,
+    json: bool,
+) -> canopy_core::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(plan)?);
+        return Ok(());
+    }
+
+    let list = |paths: &[String]| {
+        if verbose {
+            for path in paths {
+                println!("  {}", path);
+            }
+        }
+    };
+    if !plan.new_files.is_empty() {
+        println!("{}: {} files", "Would index".green(), plan.new_files.len());
+        list(&plan.new_files);
+    }
+    if !plan.reindexed.is_empty() {
+        println!(
+            "{}: {} files",
+            "Would reindex".green(),
+            plan.reindexed.len()
+        );
+        list(&plan.reindexed);
+    }
+    if !plan.skipped.is_empty() {
+        println!(
+            "{}: {} files (cache hit: {} ttl, {} mtime, {} hash)",
+            "Would skip".yellow(),
+            plan.skipped.len(),
+            plan.skip_counts.ttl,
+            plan.skip_counts.mtime,
+            plan.skip_counts.hash
+        );
+        if verbose {
+            for skip in &plan.skipped {
+                println!("  {} ({})", skip.path, skip.reason.name());
+            }
+        }
+    }
+    if !plan.removed.is_empty() {
+        println!("{}: {} files", "Would remove".yellow(), plan.removed.len());
+        list(&plan.removed);
+    }
+    if !plan.unreadable.is_empty() {
+        println!("{}: {} files", "Unreadable".red(), plan.unreadable.len());
+        list(&plan.unreadable);
+    }
+    println!(
+        "{}: {:+} tokens (estimated)",
+        "Delta".blue(),
+        plan.token_delta
+    );
     Ok(())
 }
 
@@ -111,6 +548,18 @@ pub(crate) fn print_error_and_exit(e: canopy_core::CanopyError, json: bool) -> !
             } => {
                 serde_json::json!({ "code": code, "message": message, "hint": hint })
             }
+            canopy_core::CanopyError::PinnedHandleMissing {
+                handle_id,
+                file_path,
+                reason,
+            } => serde_json::json!({
+                "code": "pinned_handle_missing",
+                "message": e.to_string(),
+                "hint": "Query for the node again and pin the new handle",
+                "handle_id": handle_id,
+                "file_path": file_path,
+                "reason": reason,
+            }),
             _ => {
                 serde_json::json!({ "code": "error", "message": e.to_string(), "hint": "" })
             }
@@ -0,0 +1,186 @@
+//! Human rendering of node content for `expand` and auto-expanded `query`
+//! output: optional line numbers and syntax highlighting.
+//!
+//! JSON output never goes through here, so MCP and `--json` callers get the
+//! content byte for byte.
+
+use std::io::IsTerminal;
+
+/// Line numbering and highlighting flags shared by `expand` and `query`.
+#[derive(clap::Args, Debug, Clone, Copy, Default)]
+pub(crate) struct RenderArgs {
+    /// Prefix content lines with their line number in the source file
+    #[arg(long)]
+    pub(crate) line_numbers: bool,
+
+    /// Syntax-highlight content by file extension (needs the `highlight`
+    /// feature)
+    #[arg(long)]
+    pub(crate) highlight: bool,
+}
+
+/// Prints node content the way a person reads it in a terminal.
+pub(crate) struct ContentRenderer {
+    line_numbers: bool,
+    #[cfg(feature = "highlight")]
+    highlighter: Option<highlight::Highlighter>,
+}
+
+impl ContentRenderer {
+    /// Renderer for `args`, with everything off under `--json` or when
+    /// stdout isn't a terminal.
+    pub(crate) fn new(args: RenderArgs, json: bool) -> Self {
+        let args = if json || !std::io::stdout().is_terminal() {
+            RenderArgs::default()
+        } else {
+            args
+        };
+        #[cfg(not(feature = "highlight"))]
+        if args.highlight {
+            eprintln!("[canopy] --highlight ignored: built without the `highlight` feature");
+        }
+        Self {
+            line_numbers: args.line_numbers,
+            #[cfg(feature = "highlight")]
+            highlighter: args.highlight.then(highlight::Highlighter::new),
+        }
+    }
+
+    /// Whether content is printed as is, with no need for its location.
+    pub(crate) fn is_plain(&self) -> bool {
+        #[cfg(feature = "highlight")]
+        if self.highlighter.is_some() {
+            return false;
+        }
+        !self.line_numbers
+    }
+
+    /// `content` of a node in `file_path` whose first line is `first_line`
+    /// in the file. Without a known first line, no numbers are shown.
+    pub(crate) fn render(
+        &self,
+        content: &str,
+        file_path: &str,
+        first_line: Option<usize>,
+    ) -> String {
+        #[cfg(feature = "highlight")]
+        let highlighted = self
+            .highlighter
+            .as_ref()
+            .map(|h| h.highlight(content, file_path));
+        #[cfg(feature = "highlight")]
+        let content = highlighted.as_deref().unwrap_or(content);
+        #[cfg(not(feature = "highlight"))]
+        let _ = file_path;
+
+        match first_line.filter(|_| self.line_numbers) {
+            Some(first_line) => number_lines(content, first_line),
+            None => content.to_string(),
+        }
+    }
+}
+
+/// Prefix each line of `content` with its line number, counting from
+/// `first_line`, right-aligned to the widest number.
+fn number_lines(content: &str, first_line: usize) -> String {
+    let last_line = first_line + content.lines().count().saturating_sub(1);
+    let width = last_line.to_string().len();
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| format!("{:>width$} | {}", first_line + i, line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(feature = "highlight")]
+mod highlight {
+    use std::path::Path;
+    use syntect::easy::HighlightLines;
+    use syntect::highlighting::{Theme, ThemeSet};
+    use syntect::parsing::SyntaxSet;
+    use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+
+    const THEME: &str = "base16-ocean.dark";
+
+    pub(super) struct Highlighter {
+        syntaxes: SyntaxSet,
+        theme: Theme,
+    }
+
+    impl Highlighter {
+        pub(super) fn new() -> Self {
+            let mut themes = ThemeSet::load_defaults();
+            Self {
+                syntaxes: SyntaxSet::load_defaults_newlines(),
+                theme: themes.themes.remove(THEME).unwrap_or_default(),
+            }
+        }
+
+        /// `content` with terminal color escapes, the language guessed from
+        /// the extension of `file_path`; unknown languages come back as is.
+        pub(super) fn highlight(&self, content: &str, file_path: &str) -> String {
+            let syntax = Path::new(file_path)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .and_then(|ext| self.syntaxes.find_syntax_by_extension(ext));
+            let Some(syntax) = syntax else {
+                return content.to_string();
+            };
+            let mut lines = HighlightLines::new(syntax, &self.theme);
+            let mut out = String::with_capacity(content.len() * 2);
+            for line in LinesWithEndings::from(content) {
+                match lines.highlight_line(line, &self.syntaxes) {
+                    Ok(ranges) => out.push_str(&as_24_bit_terminal_escaped(&ranges, false)),
+                    Err(_) => return content.to_string(),
+                }
+            }
+            // Reset colors so they don't leak into whatever is printed next
+            out.push_str("\x1b[0m");
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renderer(line_numbers: bool) -> ContentRenderer {
+        ContentRenderer {
+            line_numbers,
+            #[cfg(feature = "highlight")]
+            highlighter: None,
+        }
+    }
+
+    const CONTENT: &str = "pub fn multiply(a: i32, b: i32) -> i32 {\n    a * b\n}";
+
+    #[test]
+    fn numbers_start_at_the_nodes_first_line() {
+        let rendered = renderer(true).render(CONTENT, "src/lib.rs", Some(9));
+        assert_eq!(
+            rendered,
+            " 9 | pub fn multiply(a: i32, b: i32) -> i32 {\n10 |     a * b\n11 | }"
+        );
+    }
+
+    #[test]
+    fn content_is_untouched_without_line_numbers_or_a_first_line() {
+        assert_eq!(
+            renderer(false).render(CONTENT, "src/lib.rs", Some(9)),
+            CONTENT
+        );
+        assert_eq!(renderer(true).render(CONTENT, "src/lib.rs", None), CONTENT);
+    }
+
+    #[test]
+    fn json_turns_everything_off() {
+        let args = RenderArgs {
+            line_numbers: true,
+            highlight: true,
+        };
+        let rendered = ContentRenderer::new(args, true).render(CONTENT, "src/lib.rs", Some(9));
+        assert_eq!(rendered, CONTENT);
+    }
+}
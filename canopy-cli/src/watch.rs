@@ -0,0 +1,231 @@
+//! `canopy watch`: reindex files as they change on disk.
+//!
+//! Filesystem events are collected until none has arrived for the debounce
+//! window, then each changed path the index walk would list (see
+//! [`WalkFilter`]) is reindexed on its own with [`RepoIndex::index_file`].
+//! A directory created, deleted or renamed in one go can arrive as a single
+//! event for the directory: a new one is walked for the files it holds, and
+//! one gone from disk takes its indexed files with it.
+//! Ctrl-C stops the loop between batches, so the database is closed with no
+//! write in flight.
+
+use canopy_core::{CanopyError, RepoIndex, WalkFilter};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::commands::detect_repo_root;
+
+/// What the watch loop is woken up by
+enum Wakeup {
+    Changed(Event),
+    WatchError(notify::Error),
+    Stop,
+}
+
+/// Files one batch reindexed and removed, and the paths that failed
+#[derive(Default)]
+struct Batch {
+    indexed: usize,
+    removed: usize,
+    failed: Vec<(String, String)>,
+}
+
+pub(crate) fn cmd_watch(
+    root: Option<PathBuf>,
+    glob: Option<String>,
+    debounce: Duration,
+    quiet: bool,
+    json: bool,
+) -> canopy_core::Result<()> {
+    use colored::Colorize;
+
+    let repo_root = detect_repo_root(root)?;
+    let mut index = RepoIndex::open(&repo_root)?;
+    let glob = glob.unwrap_or_else(|| index.config().default_glob().to_string());
+    // A bad glob fails now rather than at the first change
+    index.walk_filter(&glob)?;
+    let canonical_root = repo_root.canonicalize()?;
+
+    let (tx, rx) = mpsc::channel();
+    let events = tx.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let _ = events.send(match event {
+            Ok(event) => Wakeup::Changed(event),
+            Err(err) => Wakeup::WatchError(err),
+        });
+    })
+    .map_err(watch_error)?;
+    watcher
+        .watch(&repo_root, RecursiveMode::Recursive)
+        .map_err(watch_error)?;
+    ctrlc::set_handler(move || {
+        let _ = tx.send(Wakeup::Stop);
+    })
+    .map_err(|e| CanopyError::Io(std::io::Error::other(e)))?;
+
+    if !quiet && !json {
+        eprintln!(
+            "{} {} for changes to {} (Ctrl-C to stop)",
+            "Watching".green(),
+            repo_root.display(),
+            glob
+        );
+    }
+
+    let mut pending: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut deadline: Option<Instant> = None;
+    loop {
+        let wakeup = match deadline {
+            None => rx.recv().ok(),
+            Some(at) => match rx.recv_timeout(at.saturating_duration_since(Instant::now())) {
+                Ok(wakeup) => Some(wakeup),
+                Err(RecvTimeoutError::Timeout) => {
+                    let paths = std::mem::take(&mut pending);
+                    deadline = None;
+                    let batch = reindex(&mut index, &glob, &[&repo_root, &canonical_root], &paths)?;
+                    report(&batch, quiet, json)?;
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => None,
+            },
+        };
+        match wakeup {
+            Some(Wakeup::Changed(event)) => {
+                if matches!(event.kind, EventKind::Access(_)) {
+                    continue;
+                }
+                pending.extend(event.paths);
+                deadline = Some(Instant::now() + debounce);
+            }
+            Some(Wakeup::WatchError(err)) => {
+                eprintln!("{}: {}", "Warning".yellow(), err);
+            }
+            // Changes still pending are left for the next run
+            Some(Wakeup::Stop) | None => break,
+        }
+    }
+
+    // Stop watching, then close the database with no write in flight
+    drop(watcher);
+    drop(index);
+    if !quiet && !json {
+        eprintln!("{}", "Stopped watching".dimmed());
+    }
+    Ok(())
+}
+
+/// Reindex the changed `paths` (absolute, under one of `roots`) that the
+/// index walk for `glob` would list, and drop those gone from disk.
+fn reindex(
+    index: &mut RepoIndex,
+    glob: &str,
+    roots: &[&Path],
+    paths: &BTreeSet<PathBuf>,
+) -> canopy_core::Result<Batch> {
+    // Built per batch, so edits to .gitignore apply from the next change
+    let filter = index.walk_filter(glob)?;
+    let mut files = BTreeSet::new();
+    for path in paths {
+        let Some(relative) = roots.iter().find_map(|root| path.strip_prefix(root).ok()) else {
+            continue;
+        };
+        if own_write(relative) {
+            continue;
+        }
+        if path.is_dir() {
+            collect_new_dir(&filter, path, relative, &mut files);
+        } else if !path.exists() || filter.accepts(relative) {
+            // Whatever is gone leaves the index, file or directory alike
+            files.insert(relative.to_path_buf());
+        }
+    }
+
+    let mut batch = Batch::default();
+    for relative in files {
+        match index.index_file(&relative) {
+            Ok(stats) => {
+                batch.indexed += stats.files_indexed;
+                batch.removed += stats.files_removed;
+            }
+            // Created and deleted again within the batch
+            Err(CanopyError::FileNotFound(_)) => {}
+            Err(err) => batch
+                .failed
+                .push((relative.display().to_string(), err.to_string())),
+        }
+    }
+    Ok(batch)
+}
+
+/// Whether `relative` is under `.canopy/` or `.git/`, where the index and
+/// git write, never to be reindexed.
+fn own_write(relative: &Path) -> bool {
+    matches!(
+        relative.components().next(),
+        Some(Component::Normal(first)) if first == ".canopy" || first == ".git"
+    )
+}
+
+/// Add the files under the directory `dir` (absolute, at `relative`) that
+/// `filter` accepts to `files`. Symlinked directories are not followed.
+fn collect_new_dir(
+    filter: &WalkFilter,
+    dir: &Path,
+    relative: &Path,
+    files: &mut BTreeSet<PathBuf>,
+) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let relative = relative.join(entry.file_name());
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() => collect_new_dir(filter, &entry.path(), &relative, files),
+            Ok(kind) if kind.is_file() && filter.accepts(&relative) => {
+                files.insert(relative);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn report(batch: &Batch, quiet: bool, json: bool) -> canopy_core::Result<()> {
+    use colored::Colorize;
+
+    for (path, err) in &batch.failed {
+        eprintln!(
+            "{}: failed to reindex {}: {}",
+            "Warning".yellow(),
+            path,
+            err
+        );
+    }
+    if quiet || batch.indexed + batch.removed == 0 {
+        return Ok(());
+    }
+    if json {
+        let line = serde_json::json!({
+            "files_indexed": batch.indexed,
+            "files_removed": batch.removed,
+        });
+        println!("{}", serde_json::to_string(&line)?);
+    } else {
+        println!(
+            "reindexed {} {}, {} removed",
+            batch.indexed,
+            if batch.indexed == 1 { "file" } else { "files" },
+            batch.removed
+        );
+    }
+    Ok(())
+}
+
+fn watch_error(err: notify::Error) -> CanopyError {
+    CanopyError::Io(std::io::Error::other(format!(
+        "failed to watch the repo: {}",
+        err
+    )))
+}
@@ -0,0 +1,109 @@
+//! `canopy watch` against a live checkout: edits and deletions are
+//! reindexed in debounced batches, directories moved in or out whole are
+//! followed, ignored files left alone, and Ctrl-C closes the index cleanly.
+#![cfg(unix)]
+
+use canopy_core::{RepoIndex, FILE_DISCOVERY_ENV};
+use canopy_testutil::FixtureRepoBuilder;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+#[test]
+fn watch_reindexes_changes_until_interrupted() {
+    let repo = FixtureRepoBuilder::new()
+        .file("src/a.rs", "pub fn alpha() {}\n")
+        .file("src/b.rs", "pub fn beta() {}\n")
+        .file("src/legacy/one.rs", "pub fn legacy_one() {}\n")
+        .file("src/legacy/two.rs", "pub fn legacy_two() {}\n")
+        .file(".gitignore", ".canopy/\nscratch/\n")
+        .with_git_history()
+        .build();
+    RepoIndex::init(repo.path()).unwrap();
+    RepoIndex::open(repo.path())
+        .unwrap()
+        .index("**/*.rs")
+        .unwrap();
+
+    let home = tempfile::TempDir::new().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_canopy"))
+        .args([
+            "--json",
+            "watch",
+            "--glob",
+            "**/*.rs",
+            "--debounce",
+            "100ms",
+        ])
+        .current_dir(repo.path())
+        .env("HOME", home.path())
+        .env(FILE_DISCOVERY_ENV, "builtin")
+        .env_remove("CANOPY_SERVICE_URL")
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run canopy watch");
+    let (tx, rx) = mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            let _ = tx.send(line);
+        }
+    });
+    // Give the watcher time to register before the first change
+    std::thread::sleep(Duration::from_millis(500));
+
+    repo.write("src/a.rs", "pub fn gamma() {}\n");
+    repo.write("scratch/notes.rs", "pub fn scratch() {}\n");
+    repo.remove_file("src/b.rs");
+
+    let totals = |want: (u64, u64)| {
+        let (mut indexed, mut removed) = (0, 0);
+        while indexed < want.0 || removed < want.1 {
+            let line = rx
+                .recv_timeout(Duration::from_secs(10))
+                .expect("no reindex batch reported");
+            let batch: serde_json::Value = serde_json::from_str(&line).unwrap();
+            indexed += batch["files_indexed"].as_u64().unwrap();
+            removed += batch["files_removed"].as_u64().unwrap();
+        }
+        (indexed, removed)
+    };
+    assert_eq!(totals((1, 1)), (1, 1));
+
+    // Renames across the repo boundary arrive as one event per directory
+    let outside = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(outside.path().join("incoming/deep")).unwrap();
+    std::fs::write(
+        outside.path().join("incoming/deep/three.rs"),
+        "pub fn incoming_three() {}\n",
+    )
+    .unwrap();
+    std::fs::rename(
+        repo.path().join("src/legacy"),
+        outside.path().join("legacy"),
+    )
+    .unwrap();
+    std::fs::rename(
+        outside.path().join("incoming"),
+        repo.path().join("src/incoming"),
+    )
+    .unwrap();
+    assert_eq!(totals((1, 2)), (1, 2));
+
+    let status = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(child.wait().unwrap().success());
+    // The last connection closed, so SQLite folded the WAL back in
+    assert!(!repo.path().join(".canopy/index.db-wal").exists());
+
+    let index = RepoIndex::open(repo.path()).unwrap();
+    assert_eq!(index.search_code("gamma", 10).unwrap().len(), 1);
+    assert!(index.search_code("beta", 10).unwrap().is_empty());
+    assert!(index.search_code("scratch", 10).unwrap().is_empty());
+    assert!(index.search_code("legacy_one", 10).unwrap().is_empty());
+    assert_eq!(index.search_code("incoming_three", 10).unwrap().len(), 1);
+}
@@ -0,0 +1,32 @@
+//! Which session and tool runtime calls belong to.
+//!
+//! Set on `ClientRuntime` with `set_client_context`, the context is recorded
+//! with feedback events and sent to the service as X-Canopy-Session and
+//! X-Canopy-Client headers.
+
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use canopy_core::protocol::ClientContext;
+
+/// Environment variable naming the session calls belong to.
+pub const SESSION_ID_ENV: &str = "CANOPY_SESSION_ID";
+
+/// `CANOPY_SESSION_ID` when set, else a new session id.
+pub fn session_id_from_env() -> String {
+    std::env::var(SESSION_ID_ENV)
+        .ok()
+        .and_then(|id| ClientContext::normalize(&id))
+        .unwrap_or_else(new_session_id)
+}
+
+/// A new random-enough session id: 16 hex digits from the process id and
+/// the current time.
+pub fn new_session_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let digest = Sha256::digest(format!("{}:{nanos}", std::process::id()));
+    hex::encode(&digest[..8])
+}
@@ -0,0 +1,110 @@
+//! API key lookup for a secured canopy-service.
+//!
+//! The key comes from the first of: the `--api-key` flag, the
+//! `CANOPY_API_KEY` environment variable, or `api_key` in the repo's
+//! `.canopy/credentials.toml`. The credentials file lives inside `.canopy/`,
+//! which `canopy init` already adds to `.gitignore`.
+
+use canopy_core::CanopyError;
+use serde::Deserialize;
+use std::path::Path;
+
+/// Environment variable holding the service API key
+pub const API_KEY_ENV: &str = "CANOPY_API_KEY";
+
+/// Per-repo credentials file, relative to the repo root
+pub const CREDENTIALS_FILE: &str = ".canopy/credentials.toml";
+
+#[derive(Debug, Default, Deserialize)]
+struct Credentials {
+    api_key: Option<String>,
+}
+
+/// Resolve the service API key for `repo_root`. Empty values count as unset.
+pub fn resolve_api_key(
+    flag: Option<String>,
+    repo_root: Option<&Path>,
+) -> Result<Option<String>, CanopyError> {
+    resolve_with_env(flag, std::env::var(API_KEY_ENV).ok(), repo_root)
+}
+
+fn resolve_with_env(
+    flag: Option<String>,
+    env: Option<String>,
+    repo_root: Option<&Path>,
+) -> Result<Option<String>, CanopyError> {
+    if let Some(key) = non_empty(flag).or_else(|| non_empty(env)) {
+        return Ok(Some(key));
+    }
+    match repo_root {
+        Some(root) => read_credentials(root),
+        None => Ok(None),
+    }
+}
+
+/// `api_key` from `<repo_root>/.canopy/credentials.toml`, if the file exists.
+pub fn read_credentials(repo_root: &Path) -> Result<Option<String>, CanopyError> {
+    let path = repo_root.join(CREDENTIALS_FILE);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let credentials: Credentials = toml::from_str(&content)
+        .map_err(|e| CanopyError::ConfigParse(format!("{}: {}", path.display(), e)))?;
+    Ok(non_empty(credentials.api_key))
+}
+
+fn non_empty(key: Option<String>) -> Option<String> {
+    key.map(|k| k.trim().to_string()).filter(|k| !k.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo_with_credentials(content: &str) -> tempfile::TempDir {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join(".canopy")).unwrap();
+        std::fs::write(dir.path().join(CREDENTIALS_FILE), content).unwrap();
+        dir
+    }
+
+    #[test]
+    fn flag_then_env_then_credentials_file() {
+        let dir = repo_with_credentials("api_key = \"from-file\"\n");
+        let root = Some(dir.path());
+        let resolve = |flag: Option<&str>, env: Option<&str>| {
+            resolve_with_env(flag.map(String::from), env.map(String::from), root).unwrap()
+        };
+
+        assert_eq!(
+            resolve(Some("from-flag"), Some("from-env")).as_deref(),
+            Some("from-flag")
+        );
+        assert_eq!(resolve(None, Some("from-env")).as_deref(), Some("from-env"));
+        assert_eq!(resolve(None, None).as_deref(), Some("from-file"));
+        assert_eq!(resolve(Some(""), Some("  ")).as_deref(), Some("from-file"));
+    }
+
+    #[test]
+    fn missing_credentials_file_means_no_key() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert_eq!(
+            resolve_with_env(None, None, Some(dir.path())).unwrap(),
+            None
+        );
+        assert_eq!(resolve_with_env(None, None, None).unwrap(), None);
+        assert_eq!(
+            read_credentials(repo_with_credentials("api_key = \"\"\n").path()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn malformed_credentials_file_is_a_config_error() {
+        let dir = repo_with_credentials("api_key = [\n");
+        let err = read_credentials(dir.path()).unwrap_err();
+        assert!(matches!(err, CanopyError::ConfigParse(ref m) if m.contains("credentials.toml")));
+    }
+}
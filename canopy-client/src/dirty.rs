@@ -1,9 +1,23 @@
 //! Dirty file detection and local index overlay
+//!
+//! Rebuilds of the local index are debounced: within [`DIRTY_DEBOUNCE_ENV`]
+//! of the last rebuild, a dirty set that is unchanged or only shrank since
+//! is served from the index as it stands (see [`DirtyRebuilder`]).
 
-use canopy_core::CanopyError;
+use canopy_core::{CanopyError, PathSet, PathStyle};
+use serde::Serialize;
 use sha2::{Digest, Sha256};
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// Environment variable for the dirty rebuild debounce window, e.g. "2s" or
+/// "500ms"; "0s" rebuilds whenever the dirty fingerprint changes
+pub const DIRTY_DEBOUNCE_ENV: &str = "CANOPY_DIRTY_DEBOUNCE";
+
+/// Debounce window when [`DIRTY_DEBOUNCE_ENV`] is unset or invalid
+pub const DEFAULT_DIRTY_DEBOUNCE: Duration = Duration::from_secs(2);
 
 /// Status of a dirty (uncommitted) file
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -36,9 +50,20 @@ impl DirtyState {
         self.files.iter().any(|f| f.path == path)
     }
 
-    /// Get set of dirty file paths
-    pub fn dirty_paths(&self) -> std::collections::HashSet<String> {
-        self.files.iter().map(|f| f.path.clone()).collect()
+    /// Get set of dirty file paths, compared under `style`
+    pub fn dirty_paths(&self, style: PathStyle) -> PathSet {
+        PathSet::from_paths(style, self.files.iter().map(|f| &f.path))
+    }
+
+    /// Get set of dirty paths deleted from the working tree
+    pub fn deleted_paths(&self, style: PathStyle) -> PathSet {
+        PathSet::from_paths(
+            style,
+            self.files
+                .iter()
+                .filter(|f| f.status == DirtyStatus::Deleted)
+                .map(|f| &f.path),
+        )
     }
 
     /// Check if there are any dirty files
@@ -213,32 +238,154 @@ pub fn compute_fingerprint(dirty_files: &[DirtyFile], repo_root: &Path) -> Strin
 
 /// Rebuild local index for dirty files only
 ///
-/// 1. Invalidate dirty paths in the index
-/// 2. Re-index only the dirty files (non-deleted ones)
+/// 1. Keep the dirty paths whose content differs from the indexed copy
+/// 2. Re-index each of those on its own with [`RepoIndex::index_file`],
+///    which drops the rows of the ones deleted from disk; nothing walks the
+///    tree
+///
+/// [`RepoIndex::index_file`]: canopy_core::RepoIndex::index_file
+///
+/// Returns the number of files reindexed or removed from the index.
 pub fn rebuild_local_index(
     index: &mut canopy_core::RepoIndex,
     dirty: &DirtyState,
     _repo_root: &Path,
-) -> canopy_core::Result<()> {
+) -> canopy_core::Result<usize> {
     if dirty.is_clean() {
-        return Ok(());
+        return Ok(0);
+    }
+
+    let paths: Vec<String> = dirty.files.iter().map(|f| f.path.clone()).collect();
+    let changed: HashSet<String> = index.changed_paths(&paths)?.into_iter().collect();
+    let mut reindexed = 0;
+    for file in dirty.files.iter().filter(|f| changed.contains(&f.path)) {
+        let stats = index.index_file(Path::new(&file.path))?;
+        reindexed += stats.files_indexed + stats.files_removed;
     }
 
-    // Invalidate all dirty file paths
-    for file in &dirty.files {
-        // Use glob to invalidate specific files
-        index.invalidate(Some(&file.path))?;
+    Ok(reindexed)
+}
+
+/// Dirty rebuild counters of a [`DirtyRebuilder`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DirtyRebuildStats {
+    /// Local index rebuilds performed
+    pub rebuilds: u64,
+    /// Rebuilds skipped because one had just run for the same dirty files
+    pub debounced: u64,
+    /// Files reindexed (or removed) across all rebuilds
+    pub files_reindexed: u64,
+}
+
+/// The last rebuild of one repo's dirty files
+#[derive(Debug, Clone)]
+struct LastRebuild {
+    at: Instant,
+    paths: HashSet<String>,
+}
+
+/// Decides when a repo's local index is rebuilt for its dirty files.
+///
+/// A rebuild runs when the dirty fingerprint changed since the last one,
+/// except within `window` of the previous rebuild while the dirty set is the
+/// same or a subset of it: rapid edits to files already overlaid then reuse
+/// the local index instead of reparsing on every query. Once the window has
+/// passed, the next query picks up the edits.
+#[derive(Debug)]
+pub struct DirtyRebuilder {
+    window: Duration,
+    last: HashMap<PathBuf, LastRebuild>,
+    stats: DirtyRebuildStats,
+}
+
+impl Default for DirtyRebuilder {
+    fn default() -> Self {
+        Self::new(DEFAULT_DIRTY_DEBOUNCE)
     }
+}
 
-    // Re-index non-deleted dirty files
-    for file in &dirty.files {
-        if file.status != DirtyStatus::Deleted {
-            // Index just this file's glob pattern
-            index.index(&file.path)?;
+impl DirtyRebuilder {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last: HashMap::new(),
+            stats: DirtyRebuildStats::default(),
         }
     }
 
-    Ok(())
+    /// A rebuilder with the window from [`DIRTY_DEBOUNCE_ENV`].
+    pub fn from_env() -> Self {
+        let window = std::env::var(DIRTY_DEBOUNCE_ENV)
+            .ok()
+            .and_then(|value| parse_window(&value))
+            .unwrap_or(DEFAULT_DIRTY_DEBOUNCE);
+        Self::new(window)
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    pub fn stats(&self) -> DirtyRebuildStats {
+        self.stats
+    }
+
+    /// Bring `index` up to date with `dirty`, unless it already is or the
+    /// rebuild is debounced.
+    pub fn refresh(
+        &mut self,
+        index: &mut canopy_core::RepoIndex,
+        dirty: &DirtyState,
+        repo_root: &Path,
+    ) -> canopy_core::Result<()> {
+        self.refresh_at(index, dirty, repo_root, Instant::now())
+    }
+
+    fn refresh_at(
+        &mut self,
+        index: &mut canopy_core::RepoIndex,
+        dirty: &DirtyState,
+        repo_root: &Path,
+        now: Instant,
+    ) -> canopy_core::Result<()> {
+        if dirty.is_clean() || !needs_rebuild(dirty, repo_root) {
+            return Ok(());
+        }
+        let paths: HashSet<String> = dirty.files.iter().map(|f| f.path.clone()).collect();
+        if let Some(last) = self.last.get(repo_root) {
+            let recent = now.saturating_duration_since(last.at) < self.window;
+            if recent && paths.is_subset(&last.paths) {
+                // The fingerprint is left stale so the next query past the
+                // window rebuilds
+                self.stats.debounced += 1;
+                return Ok(());
+            }
+        }
+
+        let reindexed = rebuild_local_index(index, dirty, repo_root)?;
+        save_fingerprint(dirty, repo_root)?;
+        self.stats.rebuilds += 1;
+        self.stats.files_reindexed += reindexed as u64;
+        self.last
+            .insert(repo_root.to_path_buf(), LastRebuild { at: now, paths });
+        Ok(())
+    }
+}
+
+/// Parse a debounce window: "500ms", or a duration like "2s"; zero disables.
+fn parse_window(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if value == "0" {
+        return Some(Duration::ZERO);
+    }
+    match value.strip_suffix("ms") {
+        Some(millis) => Some(Duration::from_millis(millis.trim().parse().ok()?)),
+        None => canopy_core::config::parse_duration(value),
+    }
 }
 
 /// Path to the cached fingerprint file
@@ -265,6 +412,7 @@ pub fn save_fingerprint(dirty: &DirtyState, repo_root: &Path) -> canopy_core::Re
 #[cfg(test)]
 mod tests {
     use super::*;
+    use canopy_testutil::FixtureRepoBuilder;
 
     #[test]
     fn test_parse_ordinary_modified() {
@@ -322,7 +470,7 @@ mod tests {
 
     #[test]
     fn test_parse_multiple() {
-        let output = "1 .M N... 100644 100644 100644 abc123 def456 src/a.rs\0? new.txt\01 D. N... 100644 000000 000000 abc123 0000000 src/old.rs\0";
+        let output = "1 .M N... 100644 100644 100644 abc123 def456 src/a.rs\0? new.txt\x001 D. N... 100644 000000 000000 abc123 0000000 src/old.rs\0";
         let files = parse_porcelain_v2(output);
         assert_eq!(files.len(), 3);
         assert_eq!(files[0].status, DirtyStatus::Modified);
@@ -384,4 +532,132 @@ mod tests {
         let fp2 = compute_fingerprint(&files, &tmp);
         assert_eq!(fp1, fp2); // Same files → same fingerprint
     }
+
+    fn modified(paths: &[&str], fingerprint: &str) -> DirtyState {
+        DirtyState {
+            files: paths
+                .iter()
+                .map(|path| DirtyFile {
+                    path: path.to_string(),
+                    status: DirtyStatus::Modified,
+                })
+                .collect(),
+            fingerprint: fingerprint.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_rebuilds_are_debounced_while_dirty_set_does_not_grow() {
+        let repo = FixtureRepoBuilder::new()
+            .file("a.rs", "fn a() {}\n")
+            .file("b.rs", "fn b() {}\n")
+            .build();
+        let root = repo.path();
+        canopy_core::RepoIndex::init(root).unwrap();
+        let mut index = canopy_core::RepoIndex::open(root).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let mut rebuilder = DirtyRebuilder::new(Duration::from_secs(2));
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut refresh = |state: DirtyState, now: Instant| {
+            rebuilder.refresh_at(&mut index, &state, root, now).unwrap();
+            rebuilder.stats()
+        };
+        let stats = |rebuilds, debounced, files_reindexed| DirtyRebuildStats {
+            rebuilds,
+            debounced,
+            files_reindexed,
+        };
+
+        std::fs::write(root.join("a.rs"), "fn a2() {}\n").unwrap();
+        assert_eq!(refresh(modified(&["a.rs"], "1"), at(0)), stats(1, 0, 1));
+        // Unchanged fingerprint: already up to date, nothing to debounce
+        assert_eq!(refresh(modified(&["a.rs"], "1"), at(0)), stats(1, 0, 1));
+        // Same file edited again within the window
+        std::fs::write(root.join("a.rs"), "fn a3() {}\n").unwrap();
+        assert_eq!(refresh(modified(&["a.rs"], "2"), at(1)), stats(1, 1, 1));
+        // A newly dirty file rebuilds; both differ from the index
+        std::fs::write(root.join("b.rs"), "fn b2() {}\n").unwrap();
+        assert_eq!(
+            refresh(modified(&["a.rs", "b.rs"], "3"), at(1)),
+            stats(2, 1, 3)
+        );
+        // The dirty set shrinking within the window is debounced
+        assert_eq!(refresh(modified(&["b.rs"], "4"), at(2)), stats(2, 2, 3));
+        // Past the window it rebuilds, reindexing nothing already current
+        assert_eq!(refresh(modified(&["b.rs"], "4"), at(4)), stats(3, 2, 3));
+    }
+
+    #[test]
+    fn test_zero_window_rebuilds_on_every_change() {
+        let repo = FixtureRepoBuilder::new()
+            .file("a.rs", "fn a() {}\n")
+            .build();
+        let root = repo.path();
+        canopy_core::RepoIndex::init(root).unwrap();
+        let mut index = canopy_core::RepoIndex::open(root).unwrap();
+
+        let mut rebuilder = DirtyRebuilder::new(Duration::ZERO);
+        let now = Instant::now();
+        for (i, body) in ["fn a1() {}\n", "fn a2() {}\n"].iter().enumerate() {
+            std::fs::write(root.join("a.rs"), body).unwrap();
+            let state = modified(&["a.rs"], &i.to_string());
+            rebuilder.refresh_at(&mut index, &state, root, now).unwrap();
+        }
+        assert_eq!(rebuilder.stats().rebuilds, 2);
+        assert_eq!(rebuilder.stats().debounced, 0);
+        assert_eq!(index.search_code("a2", 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_detects_edits_to_a_committed_fixture_and_reindexes_them() {
+        let repo = canopy_testutil::small().with_git_history().build();
+        let root = repo.path();
+        canopy_core::RepoIndex::init(root).unwrap();
+        let mut index = canopy_core::RepoIndex::open(root).unwrap();
+        index.index("**/*").unwrap();
+        // init ignores `.canopy/` in a new .gitignore
+        repo.commit("ignore the index");
+        assert!(detect_dirty(root).unwrap().is_clean());
+
+        repo.write("src/session/store.rs", "pub fn rotate_session_store() {}\n");
+        repo.write("src/session/audit.rs", "pub fn audit_session() {}\n");
+        repo.remove_file("app/billing/service.py");
+        let dirty = detect_dirty(root).unwrap();
+        let status = |path: &str| {
+            dirty
+                .files
+                .iter()
+                .find(|f| f.path == path)
+                .map(|f| f.status.clone())
+        };
+        assert_eq!(status("src/session/store.rs"), Some(DirtyStatus::Modified));
+        assert_eq!(status("src/session/audit.rs"), Some(DirtyStatus::Added));
+        assert_eq!(status("app/billing/service.py"), Some(DirtyStatus::Deleted));
+
+        let mut rebuilder = DirtyRebuilder::new(Duration::ZERO);
+        rebuilder
+            .refresh_at(&mut index, &dirty, root, Instant::now())
+            .unwrap();
+        assert_eq!(
+            index.search_code("rotate_session_store", 10).unwrap().len(),
+            1
+        );
+        assert_eq!(index.search_code("audit_session", 10).unwrap().len(), 1);
+        assert!(index
+            .search_code("load_billing_record", 10)
+            .unwrap()
+            .iter()
+            .all(|h| h.file_path != "app/billing/service.py"));
+    }
+
+    #[test]
+    fn test_parse_window() {
+        assert_eq!(parse_window("2s"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_window("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_window("0"), Some(Duration::ZERO));
+        assert_eq!(parse_window("0s"), Some(Duration::ZERO));
+        assert_eq!(parse_window("soon"), None);
+    }
 }
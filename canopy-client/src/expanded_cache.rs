@@ -0,0 +1,375 @@
+//! Recently expanded content, for compare-mode expand.
+//!
+//! Agents re-expand the same handle to check whether an edit landed. Keeping
+//! the content they last saw (keyed by repo, handle and sha256) lets expand
+//! answer "unchanged" or return a unified diff instead of the full node.
+//!
+//! Like provenance, this is a best-effort cache: a missing baseline just means
+//! the full content is sent.
+
+use canopy_core::{ExpandComparison, ExpandDelta};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::path::Path;
+
+/// Default bound on cached content, summed over entries.
+pub const EXPANDED_CONTENT_MAX_BYTES: usize = 8 * 1024 * 1024;
+/// Lines of unchanged context around each diff hunk.
+const DIFF_CONTEXT_LINES: usize = 3;
+/// Largest changed region (old lines x new lines) worth diffing; beyond this
+/// the full content is sent instead.
+const MAX_DIFF_CELLS: usize = 1_000_000;
+
+/// Hex sha256 of `content`, the hash callers pass back as a baseline.
+pub fn content_hash(content: &str) -> String {
+    hex::encode(Sha256::digest(content.as_bytes()))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedContent {
+    repo: String,
+    handle_id: String,
+    hash: String,
+    content: String,
+}
+
+/// Expanded content keyed by (repo, handle_id, hash), evicted oldest-first
+/// once the total size exceeds `max_bytes`.
+#[derive(Debug)]
+pub struct ExpandedContentCache {
+    entries: VecDeque<CachedContent>,
+    bytes: usize,
+    max_bytes: usize,
+}
+
+impl Default for ExpandedContentCache {
+    fn default() -> Self {
+        Self::new(EXPANDED_CONTENT_MAX_BYTES)
+    }
+}
+
+impl ExpandedContentCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            bytes: 0,
+            max_bytes,
+        }
+    }
+
+    /// Load a cache saved by [`save`](Self::save). A missing or unreadable
+    /// file yields an empty cache.
+    pub fn load(path: &Path, max_bytes: usize) -> Self {
+        let mut cache = Self::new(max_bytes);
+        let entries: Vec<CachedContent> = std::fs::read(path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        for entry in entries {
+            cache.push(entry);
+        }
+        cache
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_vec(&self.entries)?;
+        std::fs::write(path, json)
+    }
+
+    /// Remember `content` as the latest expansion of `handle_id`; returns its hash.
+    pub fn insert(&mut self, repo: &str, handle_id: &str, content: &str) -> String {
+        let hash = content_hash(content);
+        if let Some(pos) = self
+            .entries
+            .iter()
+            .position(|e| e.repo == repo && e.handle_id == handle_id && e.hash == hash)
+        {
+            // Re-inserting moves the entry to the back so it is the latest
+            if let Some(entry) = self.entries.remove(pos) {
+                self.entries.push_back(entry);
+            }
+            return hash;
+        }
+        self.push(CachedContent {
+            repo: repo.to_string(),
+            handle_id: handle_id.to_string(),
+            hash: hash.clone(),
+            content: content.to_string(),
+        });
+        hash
+    }
+
+    fn push(&mut self, entry: CachedContent) {
+        if entry.content.len() > self.max_bytes {
+            return;
+        }
+        self.bytes += entry.content.len();
+        self.entries.push_back(entry);
+        while self.bytes > self.max_bytes {
+            match self.entries.pop_front() {
+                Some(evicted) => self.bytes -= evicted.content.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Cached content for `handle_id` with the given hash.
+    pub fn content(&self, repo: &str, handle_id: &str, hash: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| e.repo == repo && e.handle_id == handle_id && e.hash == hash)
+            .map(|e| e.content.as_str())
+    }
+
+    /// Hash of the most recently cached content for `handle_id`.
+    pub fn latest_hash(&self, repo: &str, handle_id: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| e.repo == repo && e.handle_id == handle_id)
+            .map(|e| e.hash.as_str())
+    }
+
+    /// Compare `content` against `baseline` (or, when `None`, the latest
+    /// cached expansion of the handle).
+    pub fn compare(
+        &self,
+        repo: &str,
+        handle_id: &str,
+        content: &str,
+        baseline: Option<&str>,
+    ) -> ExpandComparison {
+        let content_hash = content_hash(content);
+        let baseline_hash = baseline
+            .map(|b| b.trim().to_ascii_lowercase())
+            .or_else(|| self.latest_hash(repo, handle_id).map(String::from));
+        let delta = match baseline_hash.as_deref() {
+            Some(baseline) if baseline == content_hash => ExpandDelta::Unchanged,
+            Some(baseline) => match self
+                .content(repo, handle_id, baseline)
+                .and_then(|old| unified_diff(old, content))
+            {
+                // A diff bigger than the content saves nothing
+                Some(diff) if diff.len() < content.len() => ExpandDelta::Diff { diff },
+                _ => ExpandDelta::Full,
+            },
+            None => ExpandDelta::Full,
+        };
+        ExpandComparison {
+            handle_id: handle_id.to_string(),
+            content_hash,
+            baseline_hash,
+            delta,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Line-based unified diff from `old` to `new` with `-`/`+` hunk headers
+/// relative to the node's first line. `None` if the changed region is too
+/// large to diff cheaply.
+pub fn unified_diff(old: &str, new: &str) -> Option<String> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    // Trim the common prefix and suffix so the LCS only covers the edit
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (mid_a, mid_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    if mid_a.len().saturating_mul(mid_b.len()) > MAX_DIFF_CELLS {
+        return None;
+    }
+
+    let mut ops = vec![DiffOp::Equal; prefix];
+    ops.extend(lcs_ops(mid_a, mid_b));
+    ops.extend(std::iter::repeat_n(DiffOp::Equal, suffix));
+
+    // Line position in old/new before each op
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for op in &ops {
+        positions.push((i, j));
+        match op {
+            DiffOp::Equal => {
+                i += 1;
+                j += 1;
+            }
+            DiffOp::Delete => i += 1,
+            DiffOp::Insert => j += 1,
+        }
+    }
+    positions.push((i, j));
+
+    let changes: Vec<usize> = (0..ops.len())
+        .filter(|&k| ops[k] != DiffOp::Equal)
+        .collect();
+    let mut out = String::new();
+    let mut k = 0;
+    while k < changes.len() {
+        let start = changes[k].saturating_sub(DIFF_CONTEXT_LINES);
+        let mut last = changes[k];
+        while k + 1 < changes.len() && changes[k + 1] <= last + 2 * DIFF_CONTEXT_LINES + 1 {
+            k += 1;
+            last = changes[k];
+        }
+        let end = (last + DIFF_CONTEXT_LINES + 1).min(ops.len());
+
+        let (a_start, b_start) = positions[start];
+        let (a_end, b_end) = positions[end];
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(a_start, a_end - a_start),
+            hunk_range(b_start, b_end - b_start)
+        ));
+        for (op, &(i, j)) in ops[start..end].iter().zip(&positions[start..end]) {
+            match op {
+                DiffOp::Equal => out.push_str(&format!(" {}\n", a[i])),
+                DiffOp::Delete => out.push_str(&format!("-{}\n", a[i])),
+                DiffOp::Insert => out.push_str(&format!("+{}\n", b[j])),
+            }
+        }
+        k += 1;
+    }
+    Some(out)
+}
+
+fn hunk_range(start: usize, count: usize) -> String {
+    // Unified diff numbers lines from 1; an empty range names the line before it
+    if count == 0 {
+        format!("{start},0")
+    } else {
+        format!("{},{count}", start + 1)
+    }
+}
+
+/// Edit script turning `a` into `b` via a longest-common-subsequence table.
+fn lcs_ops(a: &[&str], b: &[&str]) -> Vec<DiffOp> {
+    let (n, m) = (a.len(), b.len());
+    // table[i][j]: LCS length of a[i..] and b[j..]
+    let mut table = vec![0u32; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[at(i, j)] = if a[i] == b[j] {
+                table[at(i + 1, j + 1)] + 1
+            } else {
+                table[at(i + 1, j)].max(table[at(i, j + 1)])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a[i] == b[j] {
+            ops.push(DiffOp::Equal);
+            i += 1;
+            j += 1;
+        } else if table[at(i + 1, j)] >= table[at(i, j + 1)] {
+            ops.push(DiffOp::Delete);
+            i += 1;
+        } else {
+            ops.push(DiffOp::Insert);
+            j += 1;
+        }
+    }
+    ops.extend(std::iter::repeat_n(DiffOp::Delete, n - i));
+    ops.extend(std::iter::repeat_n(DiffOp::Insert, m - j));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(lines: usize) -> String {
+        (1..=lines).map(|i| format!("line {i}\n")).collect()
+    }
+
+    #[test]
+    fn diff_emits_one_hunk_with_context() {
+        let old = numbered(20);
+        let new = old.replace("line 10\n", "line ten\n");
+        let diff = unified_diff(&old, &new).unwrap();
+        assert_eq!(
+            diff,
+            "@@ -7,7 +7,7 @@\n line 7\n line 8\n line 9\n-line 10\n+line ten\n line 11\n line 12\n line 13\n"
+        );
+    }
+
+    #[test]
+    fn diff_separates_distant_hunks_and_handles_pure_inserts() {
+        let old = numbered(30);
+        let new = old
+            .replace("line 2\n", "line 2\nadded\n")
+            .replace("line 28\n", "");
+        let diff = unified_diff(&old, &new).unwrap();
+        assert_eq!(diff.matches("@@ -").count(), 2, "{diff}");
+        assert!(diff.starts_with("@@ -1,5 +1,6 @@\n"), "{diff}");
+        assert!(diff.contains("+added\n") && diff.contains("-line 28\n"));
+        assert_eq!(
+            unified_diff("", "first\n").unwrap(),
+            "@@ -0,0 +1,1 @@\n+first\n"
+        );
+    }
+
+    #[test]
+    fn compare_reports_unchanged_diff_or_full() {
+        let mut cache = ExpandedContentCache::default();
+        let old = numbered(40);
+        let hash = cache.insert("/repo", "h1", &old);
+
+        let same = cache.compare("/repo", "h1", &old, None);
+        assert_eq!(same.delta, ExpandDelta::Unchanged);
+        assert_eq!(same.content_hash, hash);
+
+        let new = old.replace("line 20\n", "line twenty\n");
+        let changed = cache.compare("/repo", "h1", &new, Some(&hash.to_uppercase()));
+        assert_eq!(changed.baseline_hash.as_deref(), Some(hash.as_str()));
+        match changed.delta {
+            ExpandDelta::Diff { diff } => assert!(diff.contains("+line twenty")),
+            other => panic!("expected diff, got {other:?}"),
+        }
+
+        // Unknown baseline content, or no baseline at all, falls back to full
+        let unknown = cache.compare("/repo", "h1", &new, Some(&content_hash("other")));
+        assert_eq!(unknown.delta, ExpandDelta::Full);
+        assert_eq!(
+            cache.compare("/repo", "h2", &new, None).delta,
+            ExpandDelta::Full
+        );
+    }
+
+    #[test]
+    fn cache_evicts_oldest_by_bytes_and_round_trips() {
+        let mut cache = ExpandedContentCache::new(10);
+        let first = cache.insert("/repo", "h1", "aaaaaa");
+        let second = cache.insert("/repo", "h2", "bbbbbb");
+        assert!(cache.content("/repo", "h1", &first).is_none());
+        assert_eq!(cache.content("/repo", "h2", &second), Some("bbbbbb"));
+        cache.insert("/repo", "h3", "this content is over the limit");
+        assert!(cache.latest_hash("/repo", "h3").is_none());
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("expanded.json");
+        cache.save(&path).unwrap();
+        let loaded = ExpandedContentCache::load(&path, 10);
+        assert_eq!(loaded.latest_hash("/repo", "h2"), Some(second.as_str()));
+        assert!(ExpandedContentCache::load(&dir.path().join("missing"), 10)
+            .latest_hash("/repo", "h2")
+            .is_none());
+    }
+}
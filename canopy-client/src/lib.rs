@@ -3,14 +3,33 @@
 //! Provides the `ClientRuntime` that owns both standalone and service modes,
 //! so CLI and MCP stay in sync without leaking mode branching to callers.
 
+pub mod client_context;
+pub mod credentials;
 pub mod dirty;
+pub mod expanded_cache;
+pub mod local_service;
 pub mod merge;
+pub mod pins;
 pub mod predict;
 pub mod provenance;
+pub mod retry;
 pub mod runtime;
 pub mod service_client;
+pub mod session_log;
 
-pub use canopy_core::ExpandOutcome;
+pub use canopy_core::{ExpandComparison, ExpandDelta, ExpandOutcome};
+pub use client_context::ClientContext;
+pub use dirty::DirtyRebuildStats;
+pub use expanded_cache::ExpandedContentCache;
+pub use pins::{Pin, PinStatus};
 pub use provenance::HandleProvenance;
-pub use runtime::{ClientRuntime, IndexResult};
-pub use service_client::{ReindexResponse, ServiceClient, ServiceStatus};
+pub use retry::RetryPolicy;
+pub use runtime::{
+    compare_snapshots, lock_index, BenchReport, BenchSnapshot, BenchSuite, ClientRuntime,
+    Exploration, ExploredHandle, GenerationChange, HandleRefresh, IndexRegistry, IndexResult,
+    RefreshReport, RefreshStatus, ReplayQueryDiff, ReplayReport, SharedIndex,
+};
+pub use service_client::{
+    ReindexResponse, RepoWarmup, ServiceClient, ServiceStatus, WarmupResponse,
+};
+pub use session_log::{SessionLog, SessionRecord};
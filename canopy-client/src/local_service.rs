@@ -0,0 +1,230 @@
+//! Discovery of the per-user background service started by `canopy service run`.
+//!
+//! `run` writes the service's URL and pid to a state file
+//! (`$XDG_STATE_HOME/canopy/service.json`, else
+//! `~/.local/state/canopy/service.json`). CLI and MCP invocations without a
+//! `--service-url`/`CANOPY_SERVICE_URL` use that URL while the pid is alive
+//! and the service answers `/healthz`; a file left behind by a dead process
+//! is removed. An explicit URL always wins, and an empty one turns discovery
+//! off.
+
+use canopy_core::CanopyError;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Environment variable overriding the state file's path
+pub const LOCAL_SERVICE_STATE_ENV: &str = "CANOPY_LOCAL_SERVICE_STATE";
+
+/// State file name inside the per-user state directory
+pub const STATE_FILE: &str = "service.json";
+
+/// Service log file name, next to the state file
+pub const LOG_FILE: &str = "service.log";
+
+/// How long discovery waits for `/healthz` before going without a service
+const HEALTH_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// What `canopy service run` records about the service it started.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocalServiceState {
+    pub url: String,
+    pub pid: u32,
+    /// UNIX seconds
+    pub started_at: u64,
+}
+
+impl LocalServiceState {
+    /// The state recorded at `path`, if there is one.
+    pub fn load(path: &Path) -> Result<Option<Self>, CanopyError> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(serde_json::from_str(&content)?))
+    }
+
+    /// Record the state at `path`, creating its directory.
+    pub fn save(&self, path: &Path) -> Result<(), CanopyError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Whether the recorded process still exists.
+    pub fn is_running(&self) -> bool {
+        pid_alive(self.pid)
+    }
+
+    /// Whether the service answers `/healthz`.
+    pub fn is_healthy(&self) -> bool {
+        let Ok(client) = reqwest::blocking::Client::builder()
+            .timeout(HEALTH_TIMEOUT)
+            .build()
+        else {
+            return false;
+        };
+        client
+            .get(format!("{}/healthz", self.url.trim_end_matches('/')))
+            .send()
+            .is_ok_and(|resp| resp.status().is_success())
+    }
+}
+
+/// Per-user directory holding the state and log files.
+pub fn state_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_STATE_HOME").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir).join("canopy"));
+    }
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".local/state/canopy"))
+}
+
+/// The state file: `CANOPY_LOCAL_SERVICE_STATE`, else [`STATE_FILE`] in
+/// [`state_dir`].
+pub fn state_path() -> Option<PathBuf> {
+    match std::env::var_os(LOCAL_SERVICE_STATE_ENV).filter(|p| !p.is_empty()) {
+        Some(path) => Some(PathBuf::from(path)),
+        None => state_dir().map(|dir| dir.join(STATE_FILE)),
+    }
+}
+
+/// The service log written by a background `canopy service run`, next to
+/// the state file.
+pub fn log_path() -> Option<PathBuf> {
+    state_path().map(|path| path.with_file_name(LOG_FILE))
+}
+
+/// The service URL to use: `explicit` (flag or env) when given, else the
+/// running local service, if any. An empty `explicit` means no service.
+pub fn resolve_service_url(explicit: Option<String>) -> Option<String> {
+    resolve_with_state(explicit, state_path().as_deref())
+}
+
+fn resolve_with_state(explicit: Option<String>, state: Option<&Path>) -> Option<String> {
+    match explicit {
+        Some(url) => Some(url.trim().to_string()).filter(|url| !url.is_empty()),
+        None => state.and_then(discover_at),
+    }
+}
+
+/// URL of the service recorded at `path` when it is running and healthy.
+/// A record whose process is gone is deleted.
+pub fn discover_at(path: &Path) -> Option<String> {
+    let state = LocalServiceState::load(path).ok().flatten()?;
+    if !state.is_running() {
+        let _ = std::fs::remove_file(path);
+        return None;
+    }
+    state.is_healthy().then_some(state.url)
+}
+
+/// Whether a process with `pid` exists.
+#[cfg(target_os = "linux")]
+pub fn pid_alive(pid: u32) -> bool {
+    Path::new(&format!("/proc/{pid}")).exists()
+}
+
+/// Whether a process with `pid` exists.
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn pid_alive(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Whether a process with `pid` exists.
+#[cfg(not(unix))]
+pub fn pid_alive(pid: u32) -> bool {
+    std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {pid}"), "/NH"])
+        .output()
+        .is_ok_and(|out| String::from_utf8_lossy(&out.stdout).contains(&pid.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// A server answering every request with 200, as `/healthz` does.
+    fn healthy_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf);
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+            }
+        });
+        url
+    }
+
+    fn state(url: &str, pid: u32) -> LocalServiceState {
+        LocalServiceState {
+            url: url.to_string(),
+            pid,
+            started_at: 0,
+        }
+    }
+
+    #[test]
+    fn discovers_a_running_healthy_service() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("canopy").join(STATE_FILE);
+        assert_eq!(discover_at(&path), None);
+
+        let url = healthy_server();
+        state(&url, std::process::id()).save(&path).unwrap();
+        assert_eq!(discover_at(&path), Some(url.clone()));
+        assert_eq!(resolve_with_state(None, Some(&path)), Some(url));
+
+        // Alive but not answering: not used, but left for `service stop`
+        state("http://127.0.0.1:9", std::process::id())
+            .save(&path)
+            .unwrap();
+        assert_eq!(discover_at(&path), None);
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn state_of_a_dead_process_is_removed() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(STATE_FILE);
+        let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+            .arg("--list")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+
+        state(&healthy_server(), pid).save(&path).unwrap();
+        assert_eq!(discover_at(&path), None);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn explicit_url_always_wins() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(STATE_FILE);
+        state(&healthy_server(), std::process::id())
+            .save(&path)
+            .unwrap();
+
+        assert_eq!(
+            resolve_with_state(Some("http://remote:3000".to_string()), Some(&path)).as_deref(),
+            Some("http://remote:3000")
+        );
+        // An empty URL opts out of discovery
+        assert_eq!(resolve_with_state(Some(" ".to_string()), Some(&path)), None);
+        assert_eq!(resolve_with_state(None, None), None);
+    }
+}
@@ -1,86 +1,328 @@
 //! Merge logic for combining local and service query results
 
-use canopy_core::QueryResult;
-use std::collections::HashSet;
+use canopy_core::{
+    Handle, MergeStrategy, PathSet, QueryExplain, QueryMode, QueryResult, SchemaVersion,
+    SourceCounts, TokenSavings,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 /// Merge local and service query results
 ///
 /// Rules:
-/// - Dirty paths: drop ALL service handles for that file (not just overlapping).
-///   When lines shift due to edits, both overlapping and non-overlapping service
-///   handles can be stale.
+/// - Dirty paths the local result covers: drop ALL service handles for that file
+///   (not just overlapping). When lines shift due to edits, both overlapping and
+///   non-overlapping service handles can be stale.
+/// - Dirty paths with no local handles: keep the service handles but flag them
+///   `possibly_stale`, since the local index may simply lack the file (e.g. a
+///   fresh clone). Deleted paths are always dropped.
 /// - Files not in dirty set: keep service handles as-is
+///
+/// Each side arrives already capped by its own limit (`local_limit` /
+/// `service_limit`) and in the core result ordering (see [`QueryResult`]),
+/// which the merge keeps within each source. Local handles for a dirty file
+/// take the slot of the first service handle they replace; the other local
+/// handles are placed by `strategy` (see [`MergeStrategy`]), with ties going
+/// to the service. The overall `limit` is then enforced on that order.
 pub fn merge_results(
     local: QueryResult,
     service: QueryResult,
-    dirty_paths: &HashSet<String>,
+    dirty_paths: &PathSet,
+    deleted_paths: &PathSet,
+    limit: Option<usize>,
+    strategy: MergeStrategy,
 ) -> QueryResult {
-    let mut merged_handles = Vec::new();
+    let style = dirty_paths.style();
     let mut seen_handle_ids = HashSet::new();
+    let mut locally_covered = PathSet::new(style);
 
     // Keep local handles only for dirty files (service owns clean files).
+    let mut local_handles = Vec::new();
     for handle in &local.handles {
         if dirty_paths.contains(&handle.file_path) && seen_handle_ids.insert(handle.id.to_string())
         {
-            merged_handles.push(handle.clone());
+            locally_covered.insert(&handle.file_path);
+            local_handles.push(handle.clone());
         }
     }
+    let local_count = local_handles.len();
 
-    // Add service handles only for non-dirty files
-    for service_handle in &service.handles {
-        if !dirty_paths.contains(&service_handle.file_path)
-            && seen_handle_ids.insert(service_handle.id.to_string())
-        {
-            merged_handles.push(service_handle.clone());
+    // Rank by rerank score only when every handle on both sides has one;
+    // otherwise by position, normalized so short and long lists interleave evenly
+    let by_score = local_handles
+        .iter()
+        .chain(&service.handles)
+        .all(|h| h.rerank_score.is_some());
+    let service_len = service.handles.len();
+    let service_key = |rank: usize, handle: &Handle| match handle.rerank_score {
+        Some(score) if by_score => -score,
+        _ => rank as f64 / service_len as f64,
+    };
+
+    // Add service handles for non-dirty files, and flagged ones for uncovered
+    // dirty files; a replaced file's first service slot is where its local
+    // handles go
+    let mut entries = Vec::with_capacity(local_count + service_len);
+    let mut override_slots: HashMap<String, f64> = HashMap::new();
+    let mut suppressed_service_handles = 0;
+    for (rank, service_handle) in service.handles.iter().enumerate() {
+        let path = service_handle.file_path.as_str();
+        let dirty = dirty_paths.contains(path);
+        if dirty && (locally_covered.contains(path) || deleted_paths.contains(path)) {
+            suppressed_service_handles += 1;
+            override_slots
+                .entry(style.key(path))
+                .or_insert_with(|| service_key(rank, service_handle));
+            continue;
+        }
+        if seen_handle_ids.insert(service_handle.id.to_string()) {
+            let mut handle = service_handle.clone();
+            handle.possibly_stale |= dirty;
+            entries.push(MergeEntry {
+                key: service_key(rank, &handle),
+                local: false,
+                order: rank,
+                handle,
+            });
         }
     }
 
-    let total_tokens: usize = merged_handles.iter().map(|h| h.token_count).sum();
-    let total_matches = merged_handles.len();
-    let expanded_count = merged_handles
-        .iter()
-        .filter(|h| h.content.is_some())
-        .count();
-    let expanded_tokens: usize = merged_handles
-        .iter()
-        .filter(|h| h.content.is_some())
-        .map(|h| h.token_count)
-        .sum();
-    let expanded_handle_ids: Vec<String> = merged_handles
-        .iter()
-        .filter(|h| h.content.is_some())
-        .map(|h| h.id.to_string())
-        .collect();
+    for (order, handle) in local_handles.into_iter().enumerate() {
+        let key = match override_slots.get(&style.key(&handle.file_path)) {
+            Some(&slot) => slot,
+            None => match (strategy, handle.rerank_score) {
+                (MergeStrategy::LocalFirst, _) => f64::NEG_INFINITY,
+                (MergeStrategy::ServiceFirst, _) => f64::INFINITY,
+                (MergeStrategy::RankInterleave, Some(score)) if by_score => -score,
+                (MergeStrategy::RankInterleave, _) => order as f64 / local_count as f64,
+            },
+        };
+        entries.push(MergeEntry {
+            key,
+            local: true,
+            order,
+            handle,
+        });
+    }
+    entries.sort_by(|a, b| {
+        a.key
+            .total_cmp(&b.key)
+            .then(a.local.cmp(&b.local))
+            .then(a.order.cmp(&b.order))
+    });
+
+    let total_matches = entries.len();
+    let kept = limit.map_or(total_matches, |limit| limit.min(total_matches));
+    entries.truncate(kept);
+    let kept_local = entries.iter().filter(|e| e.local).count();
+    let kept_service = kept - kept_local;
+    let sources = SourceCounts {
+        local: kept_local,
+        service: kept_service,
+        local_truncated: local.truncated || kept_local < local_count,
+        service_truncated: service.truncated || kept_service < total_matches - local_count,
+    };
+    let merged_handles: Vec<Handle> = entries.into_iter().map(|e| e.handle).collect();
+
+    let counts = ResultCounts::of(&merged_handles);
 
     // Truncated means the merged set may be incomplete — true if either source
     // was truncated, since we cannot know if dropped results would survive merge.
-    let truncated = local.truncated || service.truncated;
+    let truncated = sources.local_truncated || sources.service_truncated;
 
     // auto_expanded is meaningful only when all merged handles were expanded
     // by upstream (not by the merge itself); preserve the upstream signal.
-    let auto_expanded = expanded_count > 0 && expanded_count == total_matches;
+    let auto_expanded = counts.expanded_count > 0 && counts.expanded_count == kept;
 
     // expand_note: prefer service note (it has richer context), fallback to local.
     let expand_note = service.expand_note.or(local.expand_note);
 
-    QueryResult {
+    // Suggestions only make sense when neither side found anything
+    let suggestions = if merged_handles.is_empty() {
+        if local.suggestions.is_empty() {
+            service.suggestions
+        } else {
+            local.suggestions
+        }
+    } else {
+        Vec::new()
+    };
+
+    // Ref type counts are totals over the service's full index; dirty-file
+    // deltas are small and not worth a per-path recount.
+    let ref_type_counts = service.ref_type_counts.or(local.ref_type_counts);
+
+    // Both sides ran the same patterns, so a malformed one fails on each
+    let mut pattern_errors = service.pattern_errors;
+    for error in local.pattern_errors {
+        if !pattern_errors.iter().any(|e| e.pattern == error.pattern) {
+            pattern_errors.push(error);
+        }
+    }
+
+    let explain = merge_explain(local.explain, service.explain, merged_handles.is_empty());
+
+    let local_files = local.savings.map(|s| s.files).unwrap_or_default();
+    let service_files = service.savings.map(|s| s.files).unwrap_or_default();
+
+    let mut merged = QueryResult {
+        schema_version: SchemaVersion,
         handles: merged_handles,
         ref_handles: merge_ref_handles(local.ref_handles, service.ref_handles, dirty_paths),
-        total_tokens,
+        ref_type_counts,
+        annotations: merge_annotations(local.annotations, service.annotations, dirty_paths),
+        // Uncommitted edits have no history of their own
+        commits: service.commits.or(local.commits),
+        total_tokens: counts.total_tokens,
+        preview_tokens: counts.preview_tokens,
         truncated,
         total_matches,
         auto_expanded,
         expand_note,
-        expanded_count,
-        expanded_tokens,
-        expanded_handle_ids,
+        expanded_count: counts.expanded_count,
+        expanded_tokens: counts.expanded_tokens,
+        expanded_handle_ids: counts.expanded_handle_ids,
+        suppressed_service_handles,
+        // Only service results are policy-filtered; local ones are the caller's own
+        suppressed_by_policy: service.suppressed_by_policy,
+        // The local index covers a few dirty files, the service the whole repo
+        suppressed_generated: service.suppressed_generated.max(local.suppressed_generated),
+        excluded_matches: service.excluded_matches + local.excluded_matches,
+        redactions: service.redactions + local.redactions,
+        suggestions,
+        savings: None,
+        sources: Some(sources),
+        mode: QueryMode::Handles,
+        exists: None,
+        witness_path: None,
+        match_counts: None,
+        pattern_errors,
+        explain,
+        compat_mode: local.compat_mode || service.compat_mode,
+        truncated_scan: local.truncated_scan || service.truncated_scan,
+        inferred_glob: service.inferred_glob.or(local.inferred_glob),
+    };
+    merged.savings = merge_savings(&merged, &local_files, &service_files, dirty_paths);
+    merged
+}
+
+/// Service results when there is nothing local to merge in (clean tree, or an
+/// empty local index): capped at the overall `limit`, since `service_limit`
+/// may have let the service return more, and annotated with source counts.
+pub fn service_only(mut service: QueryResult, limit: Option<usize>) -> QueryResult {
+    let returned = service.handles.len();
+    let kept = limit.map_or(returned, |limit| limit.min(returned));
+    if kept < returned {
+        service.handles.truncate(kept);
+        let counts = ResultCounts::of(&service.handles);
+        service.total_tokens = counts.total_tokens;
+        service.preview_tokens = counts.preview_tokens;
+        service.expanded_count = counts.expanded_count;
+        service.expanded_tokens = counts.expanded_tokens;
+        service.expanded_handle_ids = counts.expanded_handle_ids;
+        service.auto_expanded &= counts.expanded_count == kept;
+        service.truncated = true;
+        let files = service.savings.take().map(|s| s.files).unwrap_or_default();
+        service.savings = merge_savings(&service, &BTreeMap::new(), &files, &PathSet::default());
+    }
+    service.sources = Some(SourceCounts {
+        local: 0,
+        service: kept,
+        local_truncated: false,
+        service_truncated: service.truncated,
+    });
+    service
+}
+
+/// A handle awaiting its place in the merged order.
+struct MergeEntry {
+    /// Ascending sort key: negated rerank score, or normalized rank position
+    key: f64,
+    local: bool,
+    /// Position within its own source, for stable ties
+    order: usize,
+    handle: Handle,
+}
+
+/// Token and expansion totals over a final handle list.
+struct ResultCounts {
+    total_tokens: usize,
+    preview_tokens: usize,
+    expanded_count: usize,
+    expanded_tokens: usize,
+    expanded_handle_ids: Vec<String>,
+}
+
+impl ResultCounts {
+    fn of(handles: &[Handle]) -> Self {
+        let expanded: Vec<&Handle> = handles.iter().filter(|h| h.content.is_some()).collect();
+        Self {
+            total_tokens: handles.iter().map(|h| h.token_count).sum(),
+            preview_tokens: handles.iter().map(|h| h.preview_tokens).sum(),
+            expanded_count: expanded.len(),
+            expanded_tokens: expanded.iter().map(|h| h.token_count).sum(),
+            expanded_handle_ids: expanded.iter().map(|h| h.id.to_string()).collect(),
+        }
+    }
+}
+
+/// Savings over the merged files, each counted once: the local size for
+/// dirty paths, the service's otherwise.
+fn merge_savings(
+    merged: &QueryResult,
+    local_files: &BTreeMap<String, usize>,
+    service_files: &BTreeMap<String, usize>,
+    dirty_paths: &PathSet,
+) -> Option<TokenSavings> {
+    let files: BTreeMap<String, usize> = merged
+        .file_paths()
+        .into_iter()
+        .filter_map(|path| {
+            let (first, second) = if dirty_paths.contains(path) {
+                (local_files, service_files)
+            } else {
+                (service_files, local_files)
+            };
+            let tokens = first.get(path).or_else(|| second.get(path))?;
+            Some((path.to_string(), *tokens))
+        })
+        .collect();
+    if files.is_empty() {
+        return None;
+    }
+    Some(TokenSavings::new(files, merged.returned_tokens()))
+}
+
+/// Both sides' searches, local ones after the service's; the miss
+/// checklists only when the merged result is empty too.
+fn merge_explain(
+    local: Option<QueryExplain>,
+    service: Option<QueryExplain>,
+    empty: bool,
+) -> Option<QueryExplain> {
+    let (mut service, local) = match (service, local) {
+        (Some(service), Some(local)) => (service, local),
+        (service, local) => return service.or(local),
+    };
+    service.searches.extend(local.searches);
+    service.glob_filtered += local.glob_filtered;
+    service.reranked |= local.reranked;
+    service.node_type_priors = service.node_type_priors.or(local.node_type_priors);
+    if empty {
+        for item in local.checklist {
+            if !service.checklist.contains(&item) {
+                service.checklist.push(item);
+            }
+        }
+    } else {
+        service.checklist.clear();
     }
+    Some(service)
 }
 
 fn merge_ref_handles(
     local: Option<Vec<canopy_core::RefHandle>>,
     service: Option<Vec<canopy_core::RefHandle>>,
-    dirty_paths: &HashSet<String>,
+    dirty_paths: &PathSet,
 ) -> Option<Vec<canopy_core::RefHandle>> {
     let mut seen = HashSet::new();
     let mut dedupe = |rows: Vec<canopy_core::RefHandle>| -> Vec<canopy_core::RefHandle> {
@@ -101,7 +343,7 @@ fn merge_ref_handles(
             // Drop service ref_handles for dirty paths.
             let filtered: Vec<_> = s
                 .into_iter()
-                .filter(|r| !dirty_paths.contains(&r.file_path))
+                .filter_map(|r| r.retain_paths(|path| !dirty_paths.contains(path)))
                 .collect();
             l.extend(filtered);
             let merged = dedupe(l);
@@ -123,7 +365,7 @@ fn merge_ref_handles(
         (None, Some(s)) => {
             let filtered: Vec<_> = s
                 .into_iter()
-                .filter(|r| !dirty_paths.contains(&r.file_path))
+                .filter_map(|r| r.retain_paths(|path| !dirty_paths.contains(path)))
                 .collect();
             let merged = dedupe(filtered);
             if merged.is_empty() {
@@ -136,10 +378,39 @@ fn merge_ref_handles(
     }
 }
 
+/// Same policy as ref handles: local rows for dirty paths, service rows otherwise.
+fn merge_annotations(
+    local: Option<Vec<canopy_core::AnnotationHandle>>,
+    service: Option<Vec<canopy_core::AnnotationHandle>>,
+    dirty_paths: &PathSet,
+) -> Option<Vec<canopy_core::AnnotationHandle>> {
+    if local.is_none() && service.is_none() {
+        return None;
+    }
+    let mut merged: Vec<_> = local
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|a| dirty_paths.contains(&a.file_path))
+        .chain(
+            service
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|a| !dirty_paths.contains(&a.file_path)),
+        )
+        .collect();
+    merged.sort_by(|a, b| (&a.file_path, a.line).cmp(&(&b.file_path, b.line)));
+    merged.dedup_by(|a, b| a.file_path == b.file_path && a.line == b.line);
+    if merged.is_empty() {
+        None
+    } else {
+        Some(merged)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use canopy_core::{Handle, NodeType, Span};
+    use canopy_core::{NodeType, PathStyle, PatternError, Span};
 
     fn make_handle(file: &str, start: usize, end: usize) -> Handle {
         Handle::new(
@@ -157,7 +428,7 @@ mod tests {
 
     #[test]
     fn test_dirty_file_drops_all_service_handles() {
-        let dirty: HashSet<String> = ["src/a.rs".to_string()].into();
+        let dirty = PathSet::from_paths(PathStyle::default(), ["src/a.rs"]);
         let local = QueryResult {
             handles: vec![make_handle("src/a.rs", 1, 5)],
             total_tokens: 100,
@@ -173,21 +444,62 @@ mod tests {
             total_matches: 2,
             ..QueryResult::default()
         };
-        let merged = merge_results(local, service, &dirty);
+        let merged = merge_results(
+            local,
+            service,
+            &dirty,
+            &PathSet::default(),
+            None,
+            MergeStrategy::default(),
+        );
         // Only the local handle survives; both service handles for dirty file are dropped
         assert_eq!(merged.handles.len(), 1);
         assert_eq!(merged.handles[0].file_path, "src/a.rs");
+        assert_eq!(merged.suppressed_service_handles, 2);
     }
 
     #[test]
     fn test_merge_empty() {
         let local = QueryResult::default();
         let service = QueryResult::default();
-        let dirty = HashSet::new();
-        let result = merge_results(local, service, &dirty);
+        let dirty = PathSet::default();
+        let result = merge_results(
+            local,
+            service,
+            &dirty,
+            &PathSet::default(),
+            None,
+            MergeStrategy::default(),
+        );
         assert!(result.handles.is_empty());
     }
 
+    #[test]
+    fn test_merge_unions_pattern_errors_by_pattern() {
+        let error = |pattern: &str| PatternError {
+            pattern: pattern.to_string(),
+            message: "fts5: syntax error".to_string(),
+        };
+        let local = QueryResult {
+            pattern_errors: vec![error("a AND"), error("NOT")],
+            ..QueryResult::default()
+        };
+        let service = QueryResult {
+            pattern_errors: vec![error("a AND")],
+            ..QueryResult::default()
+        };
+        let result = merge_results(
+            local,
+            service,
+            &PathSet::default(),
+            &PathSet::default(),
+            None,
+            MergeStrategy::default(),
+        );
+        let patterns: Vec<_> = result.pattern_errors.iter().map(|e| &e.pattern).collect();
+        assert_eq!(patterns, ["a AND", "NOT"]);
+    }
+
     #[test]
     fn test_merge_drops_all_service_handles_for_dirty_files() {
         let local = QueryResult {
@@ -205,10 +517,17 @@ mod tests {
             total_matches: 2,
             ..QueryResult::default()
         };
-        let mut dirty = HashSet::new();
-        dirty.insert("src/dirty.rs".to_string());
+        let mut dirty = PathSet::default();
+        dirty.insert("src/dirty.rs");
 
-        let result = merge_results(local, service, &dirty);
+        let result = merge_results(
+            local,
+            service,
+            &dirty,
+            &PathSet::default(),
+            None,
+            MergeStrategy::default(),
+        );
         assert_eq!(result.handles.len(), 2); // 1 local + 1 clean service
         assert_eq!(result.handles[0].file_path, "src/dirty.rs"); // local
         assert_eq!(result.handles[1].file_path, "src/clean.rs"); // service
@@ -229,8 +548,15 @@ mod tests {
             total_matches: 2,
             ..QueryResult::default()
         };
-        let dirty = HashSet::new();
-        let result = merge_results(local, service, &dirty);
+        let dirty = PathSet::default();
+        let result = merge_results(
+            local,
+            service,
+            &dirty,
+            &PathSet::default(),
+            None,
+            MergeStrategy::default(),
+        );
         assert_eq!(result.handles.len(), 2);
         assert_eq!(result.handles[0].file_path, "src/a.rs");
         assert_eq!(result.handles[1].file_path, "src/b.rs");
@@ -253,10 +579,486 @@ mod tests {
             total_matches: 2,
             ..QueryResult::default()
         };
-        let mut dirty = HashSet::new();
-        dirty.insert("src/dirty.rs".to_string());
+        let mut dirty = PathSet::default();
+        dirty.insert("src/dirty.rs");
 
-        let result = merge_results(local, service, &dirty);
+        let result = merge_results(
+            local,
+            service,
+            &dirty,
+            &PathSet::default(),
+            None,
+            MergeStrategy::default(),
+        );
         assert_eq!(result.handles.len(), 2); // dirty local + deduped clean service
     }
+
+    #[test]
+    fn test_merge_annotations_prefers_local_for_dirty_paths() {
+        let annotation = |path: &str, line: usize, text: &str| canopy_core::AnnotationHandle {
+            file_path: path.to_string(),
+            line,
+            marker: "TODO".to_string(),
+            text: text.to_string(),
+            source_handle: None,
+        };
+        let local = QueryResult {
+            annotations: Some(vec![annotation("src/dirty.rs", 4, "TODO new")]),
+            ..QueryResult::default()
+        };
+        let service = QueryResult {
+            annotations: Some(vec![
+                annotation("src/dirty.rs", 2, "TODO stale"),
+                annotation("src/clean.rs", 9, "TODO kept"),
+            ]),
+            ..QueryResult::default()
+        };
+        let dirty = PathSet::from_paths(PathStyle::default(), ["src/dirty.rs"]);
+
+        let merged = merge_results(
+            local,
+            service,
+            &dirty,
+            &PathSet::default(),
+            None,
+            MergeStrategy::default(),
+        )
+        .annotations
+        .unwrap();
+        let texts: Vec<&str> = merged.iter().map(|a| a.text.as_str()).collect();
+        assert_eq!(texts, vec!["TODO kept", "TODO new"]);
+    }
+
+    #[test]
+    fn test_grouped_service_refs_drop_dirty_occurrences() {
+        let occurrence = |path: &str| canopy_core::RefOccurrence {
+            file_path: path.to_string(),
+            line: 1,
+        };
+        let import = canopy_core::RefHandle {
+            file_path: "src/dirty.rs".to_string(),
+            span: 0..26,
+            line_range: (1, 1),
+            name: "Widget".to_string(),
+            qualifier: None,
+            ref_type: canopy_core::RefType::Import,
+            source_handle: None,
+            preview: "use crate::widget::Widget;".to_string(),
+            occurrences: vec![occurrence("src/a.rs"), occurrence("src/other_dirty.rs")],
+            occurrence_count: 5,
+        };
+        let service = QueryResult {
+            ref_handles: Some(vec![import]),
+            ..QueryResult::default()
+        };
+        let dirty =
+            PathSet::from_paths(PathStyle::default(), ["src/dirty.rs", "src/other_dirty.rs"]);
+
+        let merged = merge_results(
+            QueryResult::default(),
+            service,
+            &dirty,
+            &PathSet::default(),
+            None,
+            MergeStrategy::default(),
+        )
+        .ref_handles
+        .unwrap();
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].file_path, "src/a.rs");
+        assert!(merged[0].occurrences.is_empty());
+        assert_eq!(merged[0].occurrence_count, 3, "unlisted occurrences remain");
+    }
+
+    #[test]
+    fn test_merge_savings_counts_each_file_once() {
+        let savings = |files: &[(&str, usize)]| {
+            Some(TokenSavings::new(
+                files.iter().map(|(p, t)| (p.to_string(), *t)).collect(),
+                0,
+            ))
+        };
+        let local = QueryResult {
+            handles: vec![make_handle("src/dirty.rs", 1, 10)],
+            savings: savings(&[("src/dirty.rs", 700), ("src/clean.rs", 111)]),
+            ..QueryResult::default()
+        };
+        let service = QueryResult {
+            handles: vec![
+                make_handle("src/dirty.rs", 20, 30),
+                make_handle("src/clean.rs", 1, 10),
+                make_handle("src/clean.rs", 20, 30),
+            ],
+            savings: savings(&[("src/dirty.rs", 500), ("src/clean.rs", 300)]),
+            ..QueryResult::default()
+        };
+        let dirty = PathSet::from_paths(PathStyle::default(), ["src/dirty.rs"]);
+
+        let merged = merge_results(
+            local,
+            service,
+            &dirty,
+            &PathSet::default(),
+            None,
+            MergeStrategy::default(),
+        );
+        let savings = merged.savings.clone().unwrap();
+        assert_eq!(savings.files["src/dirty.rs"], 700);
+        assert_eq!(savings.files["src/clean.rs"], 300);
+        assert_eq!(savings.file_tokens, 1000);
+        assert_eq!(savings.returned_tokens, merged.returned_tokens());
+    }
+
+    #[test]
+    fn test_case_insensitive_dirty_paths_match_service_spelling() {
+        let style = PathStyle {
+            separator: '\\',
+            case_insensitive: true,
+        };
+        // git reports the path one way, the service indexed it another
+        let dirty = PathSet::from_paths(style, ["src\\Auth.rs"]);
+        let local = QueryResult {
+            handles: vec![make_handle("src/Auth.rs", 1, 5)],
+            total_matches: 1,
+            ..QueryResult::default()
+        };
+        let service = QueryResult {
+            handles: vec![
+                make_handle("SRC/auth.rs", 1, 5),
+                make_handle("src/other.rs", 1, 5),
+            ],
+            total_matches: 2,
+            ..QueryResult::default()
+        };
+        let merged = merge_results(
+            local,
+            service,
+            &dirty,
+            &PathSet::new(style),
+            None,
+            MergeStrategy::default(),
+        );
+        assert_eq!(merged.suppressed_service_handles, 1);
+        let paths: Vec<&str> = merged
+            .handles
+            .iter()
+            .map(|h| h.file_path.as_str())
+            .collect();
+        assert_eq!(paths, vec!["src/Auth.rs", "src/other.rs"]);
+    }
+
+    #[test]
+    fn test_uncovered_dirty_file_keeps_service_handles_flagged() {
+        let dirty = PathSet::from_paths(PathStyle::default(), ["src/a.rs", "src/gone.rs"]);
+        let deleted = PathSet::from_paths(PathStyle::default(), ["src/gone.rs"]);
+        let service = QueryResult {
+            handles: vec![
+                make_handle("src/a.rs", 1, 5),
+                make_handle("src/gone.rs", 1, 5),
+                make_handle("src/b.rs", 1, 5),
+            ],
+            total_matches: 3,
+            ..QueryResult::default()
+        };
+
+        // Local index has nothing for src/a.rs (e.g. fresh clone)
+        let merged = merge_results(
+            QueryResult::default(),
+            service,
+            &dirty,
+            &deleted,
+            None,
+            MergeStrategy::default(),
+        );
+        assert_eq!(merged.handles.len(), 2);
+        assert_eq!(merged.handles[0].file_path, "src/a.rs");
+        assert!(merged.handles[0].possibly_stale);
+        assert_eq!(merged.handles[1].file_path, "src/b.rs");
+        assert!(!merged.handles[1].possibly_stale);
+        // Only the deleted file's handle is suppressed
+        assert_eq!(merged.suppressed_service_handles, 1);
+    }
+
+    #[test]
+    fn test_overall_limit_keeps_dirty_local_first() {
+        let dirty = PathSet::from_paths(PathStyle::default(), ["src/dirty.rs"]);
+        let local = QueryResult {
+            handles: vec![
+                make_handle("src/dirty.rs", 1, 5),
+                make_handle("src/dirty.rs", 6, 9),
+            ],
+            ..QueryResult::default()
+        };
+        let service = QueryResult {
+            handles: vec![
+                make_handle("src/a.rs", 1, 5),
+                make_handle("src/b.rs", 1, 5),
+                make_handle("src/c.rs", 1, 5),
+            ],
+            ..QueryResult::default()
+        };
+        let merged = merge_results(
+            local,
+            service,
+            &dirty,
+            &PathSet::default(),
+            Some(3),
+            MergeStrategy::LocalFirst,
+        );
+        let files: Vec<&str> = merged
+            .handles
+            .iter()
+            .map(|h| h.file_path.as_str())
+            .collect();
+        assert_eq!(files, ["src/dirty.rs", "src/dirty.rs", "src/a.rs"]);
+        assert_eq!(
+            merged.sources,
+            Some(SourceCounts {
+                local: 2,
+                service: 1,
+                local_truncated: false,
+                service_truncated: true,
+            })
+        );
+        assert!(merged.truncated);
+        assert_eq!(merged.total_matches, 5);
+        assert_eq!(merged.total_tokens, 300);
+    }
+
+    #[test]
+    fn test_limit_below_local_count_cuts_local_and_all_service() {
+        let dirty = PathSet::from_paths(PathStyle::default(), ["src/dirty.rs"]);
+        let local = QueryResult {
+            handles: vec![
+                make_handle("src/dirty.rs", 1, 5),
+                make_handle("src/dirty.rs", 6, 9),
+            ],
+            ..QueryResult::default()
+        };
+        let service = QueryResult {
+            handles: vec![make_handle("src/a.rs", 1, 5)],
+            ..QueryResult::default()
+        };
+        let merged = merge_results(
+            local,
+            service,
+            &dirty,
+            &PathSet::default(),
+            Some(1),
+            MergeStrategy::LocalFirst,
+        );
+        let sources = merged.sources.unwrap();
+        assert_eq!((sources.local, sources.service), (1, 0));
+        assert!(sources.local_truncated && sources.service_truncated);
+    }
+
+    #[test]
+    fn test_source_truncation_reported_independently() {
+        let dirty = PathSet::from_paths(PathStyle::default(), ["src/dirty.rs"]);
+        let local = QueryResult {
+            handles: vec![make_handle("src/dirty.rs", 1, 5)],
+            truncated: true,
+            ..QueryResult::default()
+        };
+        let service = QueryResult {
+            handles: vec![make_handle("src/a.rs", 1, 5)],
+            ..QueryResult::default()
+        };
+        let merged = merge_results(
+            local,
+            service,
+            &dirty,
+            &PathSet::default(),
+            Some(10),
+            MergeStrategy::default(),
+        );
+        let sources = merged.sources.unwrap();
+        assert!(sources.local_truncated);
+        assert!(!sources.service_truncated);
+        assert!(merged.truncated);
+    }
+
+    /// `path:start_line` of each merged handle, in order.
+    fn order(result: &QueryResult) -> Vec<String> {
+        result
+            .handles
+            .iter()
+            .map(|h| format!("{}:{}", h.file_path, h.line_range.0))
+            .collect()
+    }
+
+    /// Service ranks a dirty file second; locally that file has two handles
+    /// and a new dirty file sits between them.
+    fn interleave_fixture() -> (QueryResult, QueryResult, PathSet) {
+        let dirty = PathSet::from_paths(PathStyle::default(), ["src/dirty.rs", "src/new.rs"]);
+        let local = QueryResult {
+            handles: vec![
+                make_handle("src/dirty.rs", 1, 5),
+                make_handle("src/new.rs", 1, 5),
+                make_handle("src/dirty.rs", 6, 9),
+            ],
+            ..QueryResult::default()
+        };
+        let service = QueryResult {
+            handles: vec![
+                make_handle("src/a.rs", 1, 5),
+                make_handle("src/dirty.rs", 2, 5),
+                make_handle("src/b.rs", 1, 5),
+                make_handle("src/c.rs", 1, 5),
+            ],
+            ..QueryResult::default()
+        };
+        (local, service, dirty)
+    }
+
+    #[test]
+    fn test_merge_strategies_keep_source_order_and_override_slots() {
+        let merged = |strategy| {
+            let (local, service, dirty) = interleave_fixture();
+            order(&merge_results(
+                local,
+                service,
+                &dirty,
+                &PathSet::default(),
+                None,
+                strategy,
+            ))
+        };
+        assert_eq!(
+            merged(MergeStrategy::RankInterleave),
+            [
+                "src/a.rs:1",
+                "src/dirty.rs:1",
+                "src/dirty.rs:6",
+                "src/new.rs:1",
+                "src/b.rs:1",
+                "src/c.rs:1"
+            ]
+        );
+        assert_eq!(
+            merged(MergeStrategy::LocalFirst),
+            [
+                "src/new.rs:1",
+                "src/a.rs:1",
+                "src/dirty.rs:1",
+                "src/dirty.rs:6",
+                "src/b.rs:1",
+                "src/c.rs:1"
+            ]
+        );
+        assert_eq!(
+            merged(MergeStrategy::ServiceFirst),
+            [
+                "src/a.rs:1",
+                "src/dirty.rs:1",
+                "src/dirty.rs:6",
+                "src/b.rs:1",
+                "src/c.rs:1",
+                "src/new.rs:1"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rank_interleave_ties_go_to_service() {
+        let dirty = PathSet::from_paths(PathStyle::default(), ["src/new.rs"]);
+        let local = QueryResult {
+            handles: vec![make_handle("src/new.rs", 1, 5)],
+            ..QueryResult::default()
+        };
+        let service = QueryResult {
+            handles: vec![make_handle("src/a.rs", 1, 5), make_handle("src/b.rs", 1, 5)],
+            ..QueryResult::default()
+        };
+        let merged = merge_results(
+            local,
+            service,
+            &dirty,
+            &PathSet::default(),
+            Some(2),
+            MergeStrategy::RankInterleave,
+        );
+        assert_eq!(order(&merged), ["src/a.rs:1", "src/new.rs:1"]);
+        let sources = merged.sources.unwrap();
+        assert_eq!((sources.local, sources.service), (1, 1));
+        assert!(sources.service_truncated && !sources.local_truncated);
+    }
+
+    #[test]
+    fn test_rank_interleave_uses_scores_when_all_handles_have_one() {
+        let (mut local, mut service, dirty) = interleave_fixture();
+        for (handle, score) in local.handles.iter_mut().zip([0.99, 0.6, 0.98]) {
+            handle.rerank_score = Some(score);
+        }
+        for (handle, score) in service.handles.iter_mut().zip([0.9, 0.5, 0.7, 0.1]) {
+            handle.rerank_score = Some(score);
+        }
+        let merged = merge_results(
+            local.clone(),
+            service.clone(),
+            &dirty,
+            &PathSet::default(),
+            None,
+            MergeStrategy::RankInterleave,
+        );
+        // The dirty overrides take the service's 0.5 slot, not their own scores
+        assert_eq!(
+            order(&merged),
+            [
+                "src/a.rs:1",
+                "src/b.rs:1",
+                "src/new.rs:1",
+                "src/dirty.rs:1",
+                "src/dirty.rs:6",
+                "src/c.rs:1"
+            ]
+        );
+
+        // One unscored handle falls back to rank positions
+        service.handles[3].rerank_score = None;
+        let merged = merge_results(
+            local,
+            service,
+            &dirty,
+            &PathSet::default(),
+            None,
+            MergeStrategy::RankInterleave,
+        );
+        assert_eq!(order(&merged)[1], "src/dirty.rs:1");
+    }
+
+    #[test]
+    fn test_service_only_caps_at_overall_limit() {
+        let mut expanded = make_handle("src/a.rs", 1, 5);
+        expanded.content = Some("fn a() {}".to_string());
+        let service = QueryResult {
+            handles: vec![
+                expanded,
+                make_handle("src/b.rs", 1, 5),
+                make_handle("src/c.rs", 1, 5),
+            ],
+            total_tokens: 300,
+            total_matches: 3,
+            ..QueryResult::default()
+        };
+
+        let uncapped = service_only(service.clone(), None);
+        assert_eq!(uncapped.handles.len(), 3);
+        assert!(!uncapped.truncated);
+
+        let capped = service_only(service, Some(1));
+        assert_eq!(capped.handles.len(), 1);
+        assert_eq!(capped.total_tokens, 100);
+        assert_eq!(capped.expanded_count, 1);
+        assert!(capped.truncated);
+        assert_eq!(
+            capped.sources,
+            Some(SourceCounts {
+                local: 0,
+                service: 1,
+                local_truncated: false,
+                service_truncated: true,
+            })
+        );
+    }
 }
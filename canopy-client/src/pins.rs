@@ -0,0 +1,286 @@
+//! Pinned handles, kept usable across reindexes.
+//!
+//! Handle ids hash the node's span, so an edit above a function gives it a
+//! new id and strands any plan that referenced the old one. A pin records
+//! enough about the node (file, type, name, content hash) to find it again:
+//! after a reindex the runtime re-resolves each pin, and expand follows the
+//! pinned id to the node's current one. Pins live in `.canopy/pins.json`.
+
+use canopy_core::{CanopyError, IndexedNode, NodeType};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Pin file, relative to the repo root
+pub const PINS_FILE: &str = ".canopy/pins.json";
+
+/// Whether a pin still leads to its node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PinStatus {
+    /// The pinned id still names the node
+    Fresh,
+    /// The node moved; expand follows `current_id`
+    Remapped,
+    /// The file or node is gone
+    Missing,
+}
+
+/// A pinned handle and how to find its node again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pin {
+    /// The id that was pinned, which expand keeps accepting
+    pub handle_id: String,
+    /// The node's id as of the last resolve; `None` once it is missing
+    pub current_id: Option<String>,
+    pub file_path: String,
+    pub node_type: NodeType,
+    /// Symbol or heading name, when the node has one
+    pub name: Option<String>,
+    /// Enclosing class/impl, for methods
+    pub parent_name: Option<String>,
+    /// Hex SHA-256 of the node's content as of the last resolve
+    pub fingerprint: String,
+    pub status: PinStatus,
+    /// Why the pin is missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub pinned_at: i64,
+}
+
+impl Pin {
+    /// A fresh pin on `node`.
+    pub fn new(node: &IndexedNode, pinned_at: i64) -> Self {
+        let id = node.handle.id.to_string();
+        Self {
+            handle_id: id.clone(),
+            current_id: Some(id),
+            file_path: node.handle.file_path.clone(),
+            node_type: node.handle.node_type,
+            name: node.name.clone(),
+            parent_name: node.parent_name.clone(),
+            fingerprint: node.content_hash.clone().unwrap_or_default(),
+            status: PinStatus::Fresh,
+            reason: None,
+            pinned_at,
+        }
+    }
+
+    /// The node among `nodes` (the pinned file's current nodes) this pin
+    /// refers to: same type and name, preferring the same parent and then
+    /// unchanged content; for unnamed nodes, unchanged content alone.
+    pub fn best_match<'a>(&self, nodes: &'a [IndexedNode]) -> Option<&'a IndexedNode> {
+        let same_type = |n: &&IndexedNode| n.handle.node_type == self.node_type;
+        let unchanged =
+            |n: &&IndexedNode| n.content_hash.as_deref() == Some(self.fingerprint.as_str());
+
+        if let Some(name) = &self.name {
+            let named: Vec<&IndexedNode> = nodes
+                .iter()
+                .filter(same_type)
+                .filter(|n| n.name.as_deref() == Some(name.as_str()))
+                .collect();
+            let same_parent = |n: &&&IndexedNode| n.parent_name == self.parent_name;
+            let found = named
+                .iter()
+                .filter(same_parent)
+                .find(|n| unchanged(n))
+                .or_else(|| named.iter().find(same_parent))
+                .or_else(|| named.iter().find(|n| unchanged(n)))
+                .or(named.first());
+            if let Some(node) = found {
+                return Some(node);
+            }
+        }
+        nodes.iter().filter(same_type).find(unchanged)
+    }
+
+    /// Point the pin at `node`, or mark it missing for `reason`.
+    pub fn resolve(&mut self, node: Result<&IndexedNode, String>) {
+        match node {
+            Ok(node) => {
+                let id = node.handle.id.to_string();
+                self.status = if id == self.handle_id {
+                    PinStatus::Fresh
+                } else {
+                    PinStatus::Remapped
+                };
+                self.current_id = Some(id);
+                if let Some(hash) = &node.content_hash {
+                    self.fingerprint = hash.clone();
+                }
+                self.reason = None;
+            }
+            Err(reason) => {
+                self.status = PinStatus::Missing;
+                self.current_id = None;
+                self.reason = Some(reason);
+            }
+        }
+    }
+
+    /// Why no node matches, for a pin whose file has `nodes`.
+    pub fn missing_reason(&self, file_exists: bool, nodes: &[IndexedNode]) -> String {
+        if !file_exists {
+            return format!("{} was deleted", self.file_path);
+        }
+        if nodes.is_empty() {
+            return format!("{} is no longer indexed", self.file_path);
+        }
+        match &self.name {
+            Some(name) => format!(
+                "no {} named {} in {}",
+                self.node_type.as_str(),
+                name,
+                self.file_path
+            ),
+            None => format!(
+                "the pinned {} changed and no longer matches any node in {}",
+                self.node_type.as_str(),
+                self.file_path
+            ),
+        }
+    }
+}
+
+/// A repo's pins, in the order they were pinned.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PinSet {
+    pins: Vec<Pin>,
+}
+
+impl PinSet {
+    /// Pins saved for `repo_root`; none if the file doesn't exist yet.
+    pub fn load(repo_root: &Path) -> Result<Self, CanopyError> {
+        match std::fs::read(pins_path(repo_root)) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, repo_root: &Path) -> Result<(), CanopyError> {
+        let path = pins_path(repo_root);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn pins(&self) -> &[Pin] {
+        &self.pins
+    }
+
+    pub fn pins_mut(&mut self) -> &mut [Pin] {
+        &mut self.pins
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    /// The pin for `handle_id`, the id it was pinned under.
+    pub fn get(&self, handle_id: &str) -> Option<&Pin> {
+        self.pins.iter().find(|p| p.handle_id == handle_id)
+    }
+
+    /// Add `pin`, replacing an earlier pin of the same id.
+    pub fn insert(&mut self, pin: Pin) {
+        match self.pins.iter_mut().find(|p| p.handle_id == pin.handle_id) {
+            Some(existing) => *existing = pin,
+            None => self.pins.push(pin),
+        }
+    }
+}
+
+fn pins_path(repo_root: &Path) -> PathBuf {
+    repo_root.join(PINS_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canopy_core::Handle;
+
+    fn node(name: Option<&str>, parent: Option<&str>, start: usize, hash: &str) -> IndexedNode {
+        let span = start..start + 10;
+        IndexedNode {
+            handle: Handle::new(
+                "src/lib.rs".to_string(),
+                NodeType::Function,
+                span,
+                (1, 1),
+                3,
+                String::new(),
+            ),
+            name: name.map(String::from),
+            parent_name: parent.map(String::from),
+            content_hash: Some(hash.to_string()),
+        }
+    }
+
+    #[test]
+    fn best_match_prefers_name_then_parent_then_content() {
+        let pinned = node(Some("run"), Some("Worker"), 0, "aaa");
+        let pin = Pin::new(&pinned, 0);
+
+        // Same name and parent wins even with new content
+        let nodes = vec![
+            node(Some("run"), Some("Other"), 20, "aaa"),
+            node(Some("run"), Some("Worker"), 40, "bbb"),
+        ];
+        assert_eq!(pin.best_match(&nodes).unwrap().handle.span.start, 40);
+
+        // Without a same-parent candidate, unchanged content decides
+        let nodes = vec![
+            node(Some("run"), Some("A"), 20, "ccc"),
+            node(Some("run"), Some("B"), 40, "aaa"),
+        ];
+        assert_eq!(pin.best_match(&nodes).unwrap().handle.span.start, 40);
+
+        // A renamed node is only found by unchanged content
+        let nodes = vec![node(Some("go"), Some("Worker"), 60, "aaa")];
+        assert_eq!(pin.best_match(&nodes).unwrap().handle.span.start, 60);
+        let nodes = vec![node(Some("go"), Some("Worker"), 60, "ddd")];
+        assert!(pin.best_match(&nodes).is_none());
+    }
+
+    #[test]
+    fn resolve_tracks_status() {
+        let pinned = node(Some("run"), None, 0, "aaa");
+        let mut pin = Pin::new(&pinned, 0);
+        assert_eq!(pin.status, PinStatus::Fresh);
+
+        let moved = node(Some("run"), None, 30, "bbb");
+        pin.resolve(Ok(&moved));
+        assert_eq!(pin.status, PinStatus::Remapped);
+        assert_eq!(pin.current_id, Some(moved.handle.id.to_string()));
+        assert_eq!(pin.fingerprint, "bbb");
+
+        pin.resolve(Err("src/lib.rs was deleted".to_string()));
+        assert_eq!(pin.status, PinStatus::Missing);
+        assert_eq!(pin.current_id, None);
+
+        pin.resolve(Ok(&pinned));
+        assert_eq!(pin.status, PinStatus::Fresh);
+        assert_eq!(pin.reason, None);
+    }
+
+    #[test]
+    fn pin_set_round_trips_and_replaces_by_id() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert!(PinSet::load(dir.path()).unwrap().is_empty());
+
+        let mut pins = PinSet::default();
+        let first = node(Some("run"), None, 0, "aaa");
+        pins.insert(Pin::new(&first, 1));
+        pins.insert(Pin::new(&first, 2));
+        pins.insert(Pin::new(&node(Some("stop"), None, 20, "bbb"), 3));
+        pins.save(dir.path()).unwrap();
+
+        let loaded = PinSet::load(dir.path()).unwrap();
+        assert_eq!(loaded.pins().len(), 2);
+        assert_eq!(loaded.pins()[0].pinned_at, 2);
+        assert!(loaded.get(&first.handle.id.to_string()).is_some());
+    }
+}
@@ -4,6 +4,7 @@
 //! Service-side evidence constants live in `canopy-service/src/evidence.rs`.
 
 use canopy_core::feedback::FeedbackStore;
+use canopy_core::query::infer_glob::KEYWORD_PATTERNS;
 
 /// Repos with more files than this use predictive (scoped) indexing instead of full index.
 pub const LARGE_REPO_THRESHOLD: usize = 1000;
@@ -11,118 +12,6 @@ pub const LARGE_REPO_THRESHOLD: usize = 1000;
 /// Maximum files to index in a single predictive pass.
 pub const MAX_PREDICTIVE_FILES: usize = 500;
 
-/// Keyword to directory pattern mappings
-/// Patterns use ** for recursive matching, will be combined with extensions
-const KEYWORD_PATTERNS: &[(&[&str], &[&str])] = &[
-    // Auth-related
-    (
-        &[
-            "auth",
-            "login",
-            "logout",
-            "session",
-            "jwt",
-            "oauth",
-            "password",
-            "credential",
-        ],
-        &[
-            "**/auth/**",
-            "**/login/**",
-            "**/session/**",
-            "**/authentication/**",
-        ],
-    ),
-    // Database-related
-    (
-        &[
-            "database",
-            "db",
-            "query",
-            "sql",
-            "orm",
-            "repository",
-            "migration",
-        ],
-        &[
-            "**/db/**",
-            "**/database/**",
-            "**/repositories/**",
-            "**/repo/**",
-        ],
-    ),
-    // API-related
-    (
-        &[
-            "api",
-            "endpoint",
-            "route",
-            "controller",
-            "handler",
-            "rest",
-            "graphql",
-        ],
-        &[
-            "**/api/**",
-            "**/routes/**",
-            "**/controllers/**",
-            "**/handlers/**",
-            "**/endpoints/**",
-        ],
-    ),
-    // Config-related
-    (
-        &["config", "configuration", "env", "settings", "options"],
-        &["**/config/**", "**/settings/**", "**/conf/**"],
-    ),
-    // Middleware
-    (
-        &["middleware", "interceptor", "filter", "guard"],
-        &[
-            "**/middleware/**",
-            "**/middlewares/**",
-            "**/interceptors/**",
-            "**/guards/**",
-        ],
-    ),
-    // Workflow/execution
-    (
-        &[
-            "workflow",
-            "execution",
-            "engine",
-            "runner",
-            "worker",
-            "job",
-            "queue",
-        ],
-        &[
-            "**/workflow/**",
-            "**/workflows/**",
-            "**/execution/**",
-            "**/engine/**",
-            "**/workers/**",
-            "**/jobs/**",
-        ],
-    ),
-    // Core/shared
-    (
-        &["core", "shared", "common", "utils", "helpers", "lib"],
-        &[
-            "**/core/**",
-            "**/shared/**",
-            "**/common/**",
-            "**/utils/**",
-            "**/lib/**",
-        ],
-    ),
-    // Service layer
-    (
-        &["service", "services"],
-        &["**/services/**", "**/service/**"],
-    ),
-];
-
 /// Predict glob patterns based on query keywords
 /// Returns patterns like "**/auth/**/*.ts" ready for walk_files()
 pub fn predict_globs(query: &str, extensions: &[String]) -> Vec<String> {
@@ -229,7 +118,7 @@ pub fn extract_extensions_from_glob(glob: &str) -> Vec<String> {
 /// Extract query text from MCP args for prediction
 pub fn extract_query_text(args: &serde_json::Value) -> String {
     // Try multiple fields that might contain searchable text
-    let fields = ["pattern", "symbol", "section", "query"];
+    let fields = ["pattern", "symbol", "section", "section_path", "query"];
     for field in fields {
         if let Some(val) = args.get(field).and_then(|v| v.as_str()) {
             if !val.is_empty() {
@@ -324,6 +213,10 @@ mod tests {
                     files_indexed: 1,
                     handles_returned: 1,
                     total_tokens: 50,
+                    file_tokens: 0,
+                    returned_tokens: 0,
+                    match_count: None,
+                    client: Default::default(),
                 })
                 .unwrap();
 
@@ -348,6 +241,7 @@ mod tests {
                     node_type: NodeType::Function,
                     token_count: 50,
                     auto_expanded: false,
+                    client: Default::default(),
                 })
                 .unwrap();
         }
@@ -361,6 +255,10 @@ mod tests {
                     files_indexed: 1,
                     handles_returned: 1,
                     total_tokens: 50,
+                    file_tokens: 0,
+                    returned_tokens: 0,
+                    match_count: None,
+                    client: Default::default(),
                 })
                 .unwrap();
 
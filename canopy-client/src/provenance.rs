@@ -64,13 +64,14 @@ impl ProvenanceTracker {
             .get(&(repo_key.to_string(), handle_id.to_string()))
     }
 
-    /// Record the query event ID that produced a handle.
+    /// Record the query event that produced a handle, by the feedback
+    /// writer's ticket for it.
     pub fn record_query_event(&mut self, repo_key: &str, handle_id: &str, event_id: i64) {
         let key = (repo_key.to_string(), handle_id.to_string());
         self.recent_query_events.insert(key, event_id);
     }
 
-    /// Look up the query event ID for a handle.
+    /// Look up the query event ticket for a handle.
     pub fn query_event_id(&self, repo_key: &str, handle_id: &str) -> Option<i64> {
         self.recent_query_events
             .get(&(repo_key.to_string(), handle_id.to_string()))
@@ -0,0 +1,172 @@
+//! Retries for service requests over flaky networks.
+//!
+//! Idempotent calls (query, evidence pack, expand, status, repo listing and
+//! resolving a path to a repo id) are retried on connect errors, timeouts,
+//! dropped connections and 5xx responses. Calls that start work on the
+//! service (reindex, cloning a repo by URL) are retried only when the
+//! connection failed before anything was sent. Waits between attempts double
+//! from [`RetryPolicy::base_delay`], with jitter, up to
+//! [`RetryPolicy::max_delay`].
+//!
+//! CLI and MCP share one configuration path: `--service-retries` /
+//! [`SERVICE_RETRIES_ENV`] and `--service-timeout` / [`SERVICE_TIMEOUT_ENV`],
+//! parsed by [`RetryPolicy::parse`].
+
+use canopy_core::CanopyError;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Environment variable for the attempts per service request
+pub const SERVICE_RETRIES_ENV: &str = "CANOPY_SERVICE_RETRIES";
+
+/// Environment variable for the per-attempt timeout, e.g. "30s" or "500ms"
+pub const SERVICE_TIMEOUT_ENV: &str = "CANOPY_SERVICE_TIMEOUT";
+
+/// Environment variable that, when set, logs every retry to stderr
+pub const DEBUG_ENV: &str = "CANOPY_DEBUG";
+
+/// Whether a call may be repeated without side effects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallClass {
+    /// Retried on connect errors, timeouts, dropped connections and 5xx responses
+    Idempotent,
+    /// Retried only on connect errors, before the request was sent
+    NonIdempotent,
+}
+
+/// How [`ServiceClient`](crate::ServiceClient) retries failed requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts per request, including the first; 1 disables retries
+    pub attempts: u32,
+    /// Timeout of each attempt
+    pub timeout: Duration,
+    /// Wait before the first retry
+    pub base_delay: Duration,
+    /// Longest wait between attempts
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            timeout: Duration::from_secs(30),
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The default policy with `attempts` and `timeout` overridden where
+    /// given, as the `--service-retries` and `--service-timeout` values.
+    pub fn parse(attempts: Option<&str>, timeout: Option<&str>) -> Result<Self, CanopyError> {
+        let mut policy = Self::default();
+        if let Some(attempts) = attempts.map(str::trim).filter(|a| !a.is_empty()) {
+            policy.attempts =
+                attempts.parse().ok().filter(|&n| n >= 1).ok_or_else(|| {
+                    invalid_setting("service retries", attempts, "an integer >= 1")
+                })?;
+        }
+        if let Some(timeout) = timeout.map(str::trim).filter(|t| !t.is_empty()) {
+            policy.timeout = parse_timeout(timeout).ok_or_else(|| {
+                invalid_setting("service timeout", timeout, "e.g. \"30s\" or \"500ms\"")
+            })?;
+        }
+        Ok(policy)
+    }
+
+    /// Wait before attempt `attempt + 1`, after `attempt` (1-based) failed:
+    /// the doubled base delay, capped, scaled by a random factor in [0.5, 1].
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let doubled = self
+            .base_delay
+            .saturating_mul(1u32 << attempt.saturating_sub(1).min(16));
+        let capped = doubled.min(self.max_delay);
+        let jitter = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        capped.mul_f64(0.5 + (jitter % 1000) as f64 / 2000.0)
+    }
+}
+
+/// Whether a transport error of a `class` call may be retried.
+pub(crate) fn is_retryable_error(class: CallClass, err: &reqwest::Error) -> bool {
+    match class {
+        // A connection dropped mid-request is a request error
+        CallClass::Idempotent => err.is_connect() || err.is_timeout() || err.is_request(),
+        CallClass::NonIdempotent => err.is_connect(),
+    }
+}
+
+/// Whether a `class` call answered with `status` may be retried.
+pub(crate) fn is_retryable_status(class: CallClass, status: reqwest::StatusCode) -> bool {
+    class == CallClass::Idempotent && status.is_server_error()
+}
+
+/// "500ms", or a whole duration such as "30s" or "2m".
+fn parse_timeout(value: &str) -> Option<Duration> {
+    let timeout = match value.strip_suffix("ms") {
+        Some(millis) => Duration::from_millis(millis.trim().parse().ok()?),
+        None => canopy_core::config::parse_duration(value)?,
+    };
+    (!timeout.is_zero()).then_some(timeout)
+}
+
+fn invalid_setting(name: &str, value: &str, expected: &str) -> CanopyError {
+    CanopyError::ServiceError {
+        code: "invalid_config".to_string(),
+        message: format!("Invalid {name} '{value}'"),
+        hint: format!("Expected {expected}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_overrides_defaults() {
+        assert_eq!(
+            RetryPolicy::parse(None, None).unwrap(),
+            RetryPolicy::default()
+        );
+        let policy = RetryPolicy::parse(Some("5"), Some("500ms")).unwrap();
+        assert_eq!(policy.attempts, 5);
+        assert_eq!(policy.timeout, Duration::from_millis(500));
+        assert_eq!(
+            RetryPolicy::parse(None, Some("2m")).unwrap().timeout,
+            Duration::from_secs(120)
+        );
+
+        for (attempts, timeout) in [(Some("0"), None), (Some("x"), None), (None, Some("0s"))] {
+            let err = RetryPolicy::parse(attempts, timeout).unwrap_err();
+            assert!(
+                matches!(err, CanopyError::ServiceError { ref code, .. } if code == "invalid_config")
+            );
+        }
+    }
+
+    #[test]
+    fn delays_double_with_jitter_up_to_the_cap() {
+        let policy = RetryPolicy::default();
+        for attempt in 1..=8 {
+            let full = (policy.base_delay * 2u32.pow(attempt - 1)).min(policy.max_delay);
+            let delay = policy.delay(attempt);
+            assert!(delay >= full / 2 && delay <= full, "{attempt}: {delay:?}");
+        }
+        assert!(is_retryable_status(
+            CallClass::Idempotent,
+            reqwest::StatusCode::BAD_GATEWAY
+        ));
+        assert!(!is_retryable_status(
+            CallClass::NonIdempotent,
+            reqwest::StatusCode::BAD_GATEWAY
+        ));
+        assert!(!is_retryable_status(
+            CallClass::Idempotent,
+            reqwest::StatusCode::NOT_FOUND
+        ));
+    }
+}
@@ -0,0 +1,584 @@
+//! Query benchmarks: run a fixed suite of named queries, snapshot the results
+//! and diff two snapshots, e.g. before and after a refactor or a ranking change.
+//!
+//! The suite is TOML: `[[query]]` tables hold a `name`, the [`QueryParams`]
+//! fields inline, and optional `require` rank checks; `[thresholds]` sets when
+//! a changed result counts as a regression.
+//!
+//! ```toml
+//! [thresholds]
+//! max_removed = 0
+//! max_token_increase_pct = 25.0
+//!
+//! [[query]]
+//! name = "config definition"
+//! symbol = "Config"
+//! kind = "definition"
+//! require = [{ file = "src/config.rs", within = 3 }]
+//! ```
+
+use crate::session_log::now_ts;
+use canopy_core::{CanopyError, QueryParams};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Instant;
+
+use super::ClientRuntime;
+
+/// Named queries and the thresholds their results are held to.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BenchSuite {
+    #[serde(default)]
+    pub thresholds: BenchThresholds,
+    #[serde(default, rename = "query")]
+    pub queries: Vec<BenchQuery>,
+}
+
+/// When a compared query counts as a regression; unset limits never trip.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BenchThresholds {
+    /// Handles a query may lose against the old snapshot
+    pub max_removed: Option<usize>,
+    /// Growth of a query's total tokens, in percent of the old total
+    pub max_token_increase_pct: Option<f64>,
+    /// Growth of a query's run time, in milliseconds
+    pub max_slowdown_ms: Option<u64>,
+}
+
+/// One named query of a [`BenchSuite`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct BenchQuery {
+    pub name: String,
+    /// Rank checks on the current results
+    #[serde(default)]
+    pub require: Vec<RankRequirement>,
+    #[serde(flatten)]
+    pub params: QueryParams,
+}
+
+/// A handle from `file` must be among the first `within` results.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RankRequirement {
+    /// Repo-relative path, as handles report it
+    pub file: String,
+    pub within: usize,
+}
+
+impl BenchSuite {
+    /// Parse a suite file.
+    pub fn load(path: &Path) -> canopy_core::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Self::parse(&content).map_err(|err| match err {
+            CanopyError::ConfigParse(msg) => {
+                CanopyError::ConfigParse(format!("{}: {msg}", path.display()))
+            }
+            other => other,
+        })
+    }
+
+    /// Parse suite TOML; query names must be present and unique.
+    pub fn parse(content: &str) -> canopy_core::Result<Self> {
+        let suite: Self =
+            toml::from_str(content).map_err(|e| CanopyError::ConfigParse(e.to_string()))?;
+        let mut names = HashSet::new();
+        for query in &suite.queries {
+            if query.name.trim().is_empty() {
+                return Err(CanopyError::ConfigParse("query without a name".to_string()));
+            }
+            if !names.insert(query.name.as_str()) {
+                return Err(CanopyError::ConfigParse(format!(
+                    "duplicate query name {:?}",
+                    query.name
+                )));
+            }
+        }
+        Ok(suite)
+    }
+}
+
+/// Results of every query of a suite at one point in time.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BenchSnapshot {
+    pub created_at: i64,
+    pub repo: String,
+    pub queries: Vec<BenchQueryResult>,
+}
+
+/// Results of one named query, in rank order.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BenchQueryResult {
+    pub name: String,
+    pub query_text: String,
+    pub handles: Vec<BenchHandle>,
+    pub total_tokens: usize,
+    pub duration_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A returned handle, as much as a diff needs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchHandle {
+    pub id: String,
+    pub file_path: String,
+    pub line_range: (usize, usize),
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+}
+
+/// A handle returned by both snapshots at different ranks (1-based).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RankMove {
+    pub id: String,
+    pub old_rank: usize,
+    pub new_rank: usize,
+}
+
+/// Diff of one query between two snapshots.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BenchQueryDiff {
+    pub name: String,
+    pub query_text: String,
+    /// No result under this name in the old snapshot
+    pub new_query: bool,
+    /// Handles returned now but not before, in rank order
+    pub added: Vec<BenchHandle>,
+    /// Handles returned before but not now, in their old rank order
+    pub removed: Vec<BenchHandle>,
+    pub moved: Vec<RankMove>,
+    pub old_tokens: usize,
+    pub new_tokens: usize,
+    pub old_ms: u64,
+    pub new_ms: u64,
+    /// Thresholds and requirements this query breaks
+    pub regressions: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BenchQueryDiff {
+    pub fn is_changed(&self) -> bool {
+        self.new_query
+            || self.error.is_some()
+            || !self.added.is_empty()
+            || !self.removed.is_empty()
+            || !self.moved.is_empty()
+    }
+
+    pub fn token_delta(&self) -> i64 {
+        self.new_tokens as i64 - self.old_tokens as i64
+    }
+
+    pub fn ms_delta(&self) -> i64 {
+        self.new_ms as i64 - self.old_ms as i64
+    }
+}
+
+/// Per-query diffs of a suite against an old snapshot.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BenchReport {
+    pub queries: Vec<BenchQueryDiff>,
+    pub queries_compared: usize,
+    pub queries_changed: usize,
+    /// Queries with at least one regression
+    pub queries_regressed: usize,
+    /// Old snapshot queries no longer in the suite
+    pub dropped_queries: Vec<String>,
+}
+
+impl BenchReport {
+    pub fn has_regressions(&self) -> bool {
+        self.queries_regressed > 0
+    }
+}
+
+impl ClientRuntime {
+    /// Run every query of `suite` against `repo_path` and snapshot the results.
+    ///
+    /// Like replay, this bypasses feedback recording and the session log, so
+    /// benchmarking never shifts the ranking it measures.
+    pub fn bench_queries(&mut self, repo_path: &Path, suite: &BenchSuite) -> BenchSnapshot {
+        let queries = suite
+            .queries
+            .iter()
+            .map(|query| {
+                let start = Instant::now();
+                let outcome = self.replay_query(repo_path, query.params.clone());
+                let duration_ms = start.elapsed().as_millis() as u64;
+                let mut result = BenchQueryResult {
+                    name: query.name.clone(),
+                    query_text: query.params.to_text(),
+                    duration_ms,
+                    ..Default::default()
+                };
+                match outcome {
+                    Ok(found) => {
+                        result.total_tokens = found.total_tokens;
+                        result.handles = found
+                            .handles
+                            .iter()
+                            .map(|h| BenchHandle {
+                                id: h.id.to_string(),
+                                file_path: h.file_path.clone(),
+                                line_range: h.line_range,
+                                score: h.rerank_score,
+                            })
+                            .collect();
+                    }
+                    Err(err) => result.error = Some(err.to_string()),
+                }
+                result
+            })
+            .collect();
+
+        BenchSnapshot {
+            created_at: now_ts(),
+            repo: repo_path.to_string_lossy().to_string(),
+            queries,
+        }
+    }
+}
+
+/// Diff `new` against `old` query by query (matched by name), checking each
+/// query against the thresholds and requirements of `suite`.
+pub fn compare_snapshots(
+    suite: &BenchSuite,
+    old: &BenchSnapshot,
+    new: &BenchSnapshot,
+) -> BenchReport {
+    let old_by_name: HashMap<&str, &BenchQueryResult> =
+        old.queries.iter().map(|q| (q.name.as_str(), q)).collect();
+    let requirements: HashMap<&str, &[RankRequirement]> = suite
+        .queries
+        .iter()
+        .map(|q| (q.name.as_str(), q.require.as_slice()))
+        .collect();
+
+    let mut report = BenchReport::default();
+    for current in &new.queries {
+        let previous = old_by_name.get(current.name.as_str()).copied();
+        let mut diff = diff_query(previous, current);
+        diff.regressions = regressions(
+            &suite.thresholds,
+            requirements
+                .get(current.name.as_str())
+                .copied()
+                .unwrap_or_default(),
+            previous,
+            current,
+        );
+
+        report.queries_compared += 1;
+        if diff.is_changed() {
+            report.queries_changed += 1;
+        }
+        if !diff.regressions.is_empty() {
+            report.queries_regressed += 1;
+        }
+        report.queries.push(diff);
+    }
+
+    let current_names: HashSet<&str> = new.queries.iter().map(|q| q.name.as_str()).collect();
+    report.dropped_queries = old
+        .queries
+        .iter()
+        .filter(|q| !current_names.contains(q.name.as_str()))
+        .map(|q| q.name.clone())
+        .collect();
+    report
+}
+
+fn diff_query(old: Option<&BenchQueryResult>, new: &BenchQueryResult) -> BenchQueryDiff {
+    let mut diff = BenchQueryDiff {
+        name: new.name.clone(),
+        query_text: new.query_text.clone(),
+        new_query: old.is_none(),
+        new_tokens: new.total_tokens,
+        new_ms: new.duration_ms,
+        error: new.error.clone(),
+        ..Default::default()
+    };
+    let Some(old) = old else {
+        diff.added = new.handles.clone();
+        return diff;
+    };
+    diff.old_tokens = old.total_tokens;
+    diff.old_ms = old.duration_ms;
+
+    let old_ranks = ranks(&old.handles);
+    let new_ranks = ranks(&new.handles);
+    diff.added = new
+        .handles
+        .iter()
+        .filter(|h| !old_ranks.contains_key(h.id.as_str()))
+        .cloned()
+        .collect();
+    diff.removed = old
+        .handles
+        .iter()
+        .filter(|h| !new_ranks.contains_key(h.id.as_str()))
+        .cloned()
+        .collect();
+    diff.moved = new
+        .handles
+        .iter()
+        .filter_map(|h| {
+            let old_rank = *old_ranks.get(h.id.as_str())?;
+            let new_rank = new_ranks[h.id.as_str()];
+            (old_rank != new_rank).then(|| RankMove {
+                id: h.id.clone(),
+                old_rank,
+                new_rank,
+            })
+        })
+        .collect();
+    diff
+}
+
+/// 1-based rank of each handle id (first occurrence).
+fn ranks(handles: &[BenchHandle]) -> HashMap<&str, usize> {
+    let mut ranks = HashMap::new();
+    for (i, handle) in handles.iter().enumerate() {
+        ranks.entry(handle.id.as_str()).or_insert(i + 1);
+    }
+    ranks
+}
+
+fn regressions(
+    thresholds: &BenchThresholds,
+    requirements: &[RankRequirement],
+    old: Option<&BenchQueryResult>,
+    new: &BenchQueryResult,
+) -> Vec<String> {
+    let mut found = Vec::new();
+    if let Some(err) = &new.error {
+        if old.is_none_or(|old| old.error.is_none()) {
+            found.push(format!("query failed: {err}"));
+        }
+        return found;
+    }
+
+    for requirement in requirements {
+        let rank = new
+            .handles
+            .iter()
+            .position(|h| h.file_path == requirement.file)
+            .map(|i| i + 1);
+        match rank {
+            Some(rank) if rank <= requirement.within => {}
+            Some(rank) => found.push(format!(
+                "{} ranked {rank}, required within {}",
+                requirement.file, requirement.within
+            )),
+            None => found.push(format!(
+                "{} not returned, required within {}",
+                requirement.file, requirement.within
+            )),
+        }
+    }
+
+    let Some(old) = old.filter(|old| old.error.is_none()) else {
+        return found;
+    };
+    if let Some(max_removed) = thresholds.max_removed {
+        let new_ids: HashSet<&str> = new.handles.iter().map(|h| h.id.as_str()).collect();
+        let removed = old
+            .handles
+            .iter()
+            .filter(|h| !new_ids.contains(h.id.as_str()))
+            .count();
+        if removed > max_removed {
+            found.push(format!("{removed} handles removed (max {max_removed})"));
+        }
+    }
+    if let Some(max_pct) = thresholds.max_token_increase_pct {
+        if old.total_tokens > 0 && new.total_tokens > old.total_tokens {
+            let pct =
+                (new.total_tokens - old.total_tokens) as f64 * 100.0 / old.total_tokens as f64;
+            if pct > max_pct {
+                found.push(format!(
+                    "tokens grew {pct:.1}% ({} -> {}, max {max_pct}%)",
+                    old.total_tokens, new.total_tokens
+                ));
+            }
+        }
+    }
+    if let Some(max_ms) = thresholds.max_slowdown_ms {
+        let slowdown = new.duration_ms.saturating_sub(old.duration_ms);
+        if slowdown > max_ms {
+            found.push(format!("{slowdown}ms slower (max {max_ms}ms)"));
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle(id: &str, file: &str) -> BenchHandle {
+        BenchHandle {
+            id: id.to_string(),
+            file_path: file.to_string(),
+            line_range: (1, 2),
+            score: None,
+        }
+    }
+
+    fn result(name: &str, handles: Vec<BenchHandle>, total_tokens: usize) -> BenchQueryResult {
+        BenchQueryResult {
+            name: name.to_string(),
+            query_text: format!("(symbol {name:?})"),
+            handles,
+            total_tokens,
+            duration_ms: 5,
+            error: None,
+        }
+    }
+
+    fn snapshot(queries: Vec<BenchQueryResult>) -> BenchSnapshot {
+        BenchSnapshot {
+            created_at: 0,
+            repo: "/repo".to_string(),
+            queries,
+        }
+    }
+
+    #[test]
+    fn suite_parses_params_requirements_and_thresholds() {
+        let suite = BenchSuite::parse(
+            r#"
+[thresholds]
+max_removed = 1
+max_token_increase_pct = 20.0
+
+[[query]]
+name = "config"
+symbol = "Config"
+kind = "definition"
+limit = 5
+require = [{ file = "src/config.rs", within = 3 }]
+
+[[query]]
+name = "auth text"
+patterns = ["auth", "login"]
+match_mode = "all"
+"#,
+        )
+        .unwrap();
+        assert_eq!(suite.thresholds.max_removed, Some(1));
+        assert_eq!(suite.queries.len(), 2);
+        assert_eq!(suite.queries[0].params.symbol.as_deref(), Some("Config"));
+        assert_eq!(suite.queries[0].params.limit, Some(5));
+        assert_eq!(suite.queries[0].require[0].within, 3);
+        assert_eq!(suite.queries[1].params.patterns.as_ref().unwrap().len(), 2);
+
+        let duplicate = "[[query]]\nname = \"a\"\nsymbol = \"x\"\n[[query]]\nname = \"a\"\n";
+        assert!(matches!(
+            BenchSuite::parse(duplicate),
+            Err(CanopyError::ConfigParse(msg)) if msg.contains("duplicate")
+        ));
+        assert!(BenchSuite::parse("[thresholds]\nmax_lost = 1\n").is_err());
+    }
+
+    #[test]
+    fn compare_reports_added_removed_and_moved_handles() {
+        let suite = BenchSuite::default();
+        let old = snapshot(vec![
+            result(
+                "q",
+                vec![
+                    handle("h1", "a.rs"),
+                    handle("h2", "b.rs"),
+                    handle("h3", "c.rs"),
+                ],
+                300,
+            ),
+            result("gone", vec![], 0),
+        ]);
+        let new = snapshot(vec![
+            result(
+                "q",
+                vec![
+                    handle("h2", "b.rs"),
+                    handle("h1", "a.rs"),
+                    handle("h4", "d.rs"),
+                ],
+                350,
+            ),
+            result("fresh", vec![handle("h9", "z.rs")], 10),
+        ]);
+
+        let report = compare_snapshots(&suite, &old, &new);
+        assert_eq!(report.queries_compared, 2);
+        assert_eq!(report.queries_changed, 2);
+        assert!(!report.has_regressions());
+        assert_eq!(report.dropped_queries, vec!["gone"]);
+
+        let q = &report.queries[0];
+        assert_eq!(q.added, vec![handle("h4", "d.rs")]);
+        assert_eq!(q.removed, vec![handle("h3", "c.rs")]);
+        assert_eq!(
+            q.moved,
+            vec![
+                RankMove {
+                    id: "h2".to_string(),
+                    old_rank: 2,
+                    new_rank: 1
+                },
+                RankMove {
+                    id: "h1".to_string(),
+                    old_rank: 1,
+                    new_rank: 2
+                },
+            ]
+        );
+        assert_eq!(q.token_delta(), 50);
+        assert!(report.queries[1].new_query);
+    }
+
+    #[test]
+    fn thresholds_and_requirements_flag_regressions() {
+        let mut suite = BenchSuite::parse(
+            r#"
+[thresholds]
+max_removed = 0
+max_token_increase_pct = 10.0
+
+[[query]]
+name = "q"
+symbol = "x"
+require = [{ file = "a.rs", within = 1 }, { file = "missing.rs", within = 3 }]
+"#,
+        )
+        .unwrap();
+        let old = snapshot(vec![result(
+            "q",
+            vec![handle("h1", "a.rs"), handle("h2", "b.rs")],
+            100,
+        )]);
+        let new = snapshot(vec![result(
+            "q",
+            vec![handle("h3", "c.rs"), handle("h1", "a.rs")],
+            120,
+        )]);
+
+        let report = compare_snapshots(&suite, &old, &new);
+        assert!(report.has_regressions());
+        let regressions = &report.queries[0].regressions;
+        assert_eq!(regressions.len(), 4, "{regressions:?}");
+        assert!(regressions[0].starts_with("a.rs ranked 2"));
+        assert!(regressions[1].starts_with("missing.rs not returned"));
+        assert!(regressions[2].starts_with("1 handles removed"));
+        assert!(regressions[3].starts_with("tokens grew 20.0%"));
+
+        // Unchanged results under loose limits pass
+        suite.thresholds = BenchThresholds::default();
+        suite.queries[0].require.truncate(0);
+        assert!(!compare_snapshots(&suite, &old, &old).has_regressions());
+    }
+}
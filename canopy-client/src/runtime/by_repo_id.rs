@@ -0,0 +1,236 @@
+//! Service calls addressed by repo_id, for callers without a local checkout.
+//!
+//! Path-based calls resolve the repo through its local path and overlay
+//! dirty files from the working tree. Here there is no working tree: the
+//! named shard answers as indexed, nothing is merged, and provenance is
+//! tracked under [`repo_id_key`] instead of a canonical path. Query and
+//! expand feedback is recorded by the service, as in path-based service
+//! mode; there is no local feedback store to write to.
+
+use canopy_core::protocol::EvidencePackConfig;
+use canopy_core::{
+    CanopyError, EvidencePack, ExpandOutcome, HandleSource, QueryMode, QueryParams, QueryResult,
+    RepoShard,
+};
+use std::collections::{BTreeMap, HashSet};
+
+use super::explore::{empty_outcome, plan_expansion};
+use super::{ClientRuntime, Exploration, ENSURE_READY_TIMEOUT};
+use crate::merge;
+
+/// Provenance tracker key of a repo addressed by id. Canonical paths are
+/// absolute, so the prefix keeps the two apart.
+fn repo_id_key(repo_id: &str) -> String {
+    format!("repo_id:{repo_id}")
+}
+
+/// DSL queries always run locally, so they need a checkout.
+fn reject_dsl(params: &QueryParams) -> canopy_core::Result<()> {
+    if params.dsl.is_some() {
+        return Err(CanopyError::QueryParse {
+            position: 0,
+            message: "DSL queries run against a local index; use structured params with repo_id"
+                .to_string(),
+        });
+    }
+    Ok(())
+}
+
+impl ClientRuntime {
+    /// Query the service shard `repo_id` directly. Err(NoServiceConfigured)
+    /// in standalone mode.
+    pub fn query_by_repo_id(
+        &mut self,
+        repo_id: &str,
+        params: QueryParams,
+    ) -> canopy_core::Result<QueryResult> {
+        let service = self.require_service()?;
+        reject_dsl(&params)?;
+        let limit = params.limit;
+        let query_text = params.to_text();
+        service.ensure_ready(repo_id, ENSURE_READY_TIMEOUT)?;
+        let service_result = service.query(repo_id, params.for_source(&HandleSource::Service))?;
+
+        let gen = service_result.handles.first().and_then(|h| h.generation);
+        if let Some(gen) = gen {
+            let old_gen = self.cache.repo_generations.insert(repo_id.to_string(), gen);
+            if old_gen.is_some_and(|old| old != gen) {
+                self.tracker.invalidate_repo(repo_id);
+            }
+        }
+        self.record_provenance_under(
+            &repo_id_key(repo_id),
+            &service_result.handles,
+            HandleSource::Service,
+            gen,
+            Some(repo_id.to_string()),
+        );
+
+        let mut result = if service_result.mode.is_handles() {
+            merge::service_only(service_result, limit)
+        } else {
+            service_result
+        };
+        self.rerank_service_result(&query_text, &mut result);
+        Ok(result)
+    }
+
+    /// Expand handles of the service shard `repo_id`. Pins are ignored: they
+    /// live in a local checkout. Fails only if every handle fails.
+    pub fn expand_by_repo_id(
+        &mut self,
+        repo_id: &str,
+        handle_ids: &[String],
+    ) -> canopy_core::Result<ExpandOutcome> {
+        self.expand_by_repo_id_inner(repo_id, handle_ids, false)
+    }
+
+    fn expand_by_repo_id_inner(
+        &mut self,
+        repo_id: &str,
+        handle_ids: &[String],
+        auto_expanded: bool,
+    ) -> canopy_core::Result<ExpandOutcome> {
+        let service = self.require_service()?;
+        let expand = |ids: &[String], gen| {
+            if auto_expanded {
+                service.auto_expand(repo_id, ids, gen)
+            } else {
+                service.expand(repo_id, ids, gen)
+            }
+        };
+        let key = repo_id_key(repo_id);
+
+        let mut seen = HashSet::new();
+        let ids: Vec<String> = handle_ids
+            .iter()
+            .filter(|id| seen.insert(id.as_str()))
+            .cloned()
+            .collect();
+        let generation = |id: &str| self.tracker.get(&key, id).and_then(|p| p.generation);
+
+        let mut contents = Vec::new();
+        let mut failed_ids = Vec::new();
+        let batch_gen = ids.first().and_then(|id| generation(id));
+        match expand(&ids, batch_gen) {
+            Ok(expanded) => contents = expanded,
+            Err(e) if ids.len() == 1 => return Err(e),
+            Err(_) => {
+                // Batch failed (e.g., mixed generations) — per-handle fallback
+                for id in &ids {
+                    match expand(std::slice::from_ref(id), generation(id)) {
+                        Ok(expanded) => contents.extend(expanded),
+                        Err(_) => failed_ids.push(id.clone()),
+                    }
+                }
+            }
+        }
+        if contents.is_empty() && !failed_ids.is_empty() {
+            return Err(CanopyError::HandleNotFound(failed_ids.join(", ")));
+        }
+
+        for (id, _) in &contents {
+            self.tracker.mark_expanded(&key, id);
+        }
+        Ok(ExpandOutcome {
+            contents,
+            failed_ids,
+            comparisons: Vec::new(),
+            unresolved_pins: Vec::new(),
+            notes: BTreeMap::new(),
+        })
+    }
+
+    /// Build an evidence pack on the service shard `repo_id`.
+    pub fn evidence_pack_by_repo_id(
+        &mut self,
+        repo_id: &str,
+        params: QueryParams,
+        max_handles: usize,
+        max_per_file: usize,
+        plan: Option<bool>,
+    ) -> canopy_core::Result<EvidencePack> {
+        let service = self.require_service()?;
+        reject_dsl(&params)?;
+        let mut params = params
+            .with_mode(QueryMode::Handles)
+            .for_source(&HandleSource::Service);
+        params.expand_budget = Some(0);
+        let config = EvidencePackConfig {
+            max_handles: Some(max_handles.clamp(1, 64)),
+            max_per_file: Some(max_per_file.clamp(1, 8)),
+            plan,
+        };
+        service.ensure_ready(repo_id, ENSURE_READY_TIMEOUT)?;
+        let mut pack = service.evidence_pack(repo_id, params, config)?;
+
+        let key = repo_id_key(repo_id);
+        pack.reorder_expand_suggestions(|id| self.tracker.was_recently_expanded(&key, id));
+        self.record_pack_provenance_under(&key, &pack, Some(repo_id.to_string()));
+        Ok(pack)
+    }
+
+    /// [`explore`](Self::explore) on the service shard `repo_id`.
+    pub fn explore_by_repo_id(
+        &mut self,
+        repo_id: &str,
+        params: QueryParams,
+        token_budget: usize,
+        max_handles: usize,
+        max_per_file: usize,
+    ) -> canopy_core::Result<Exploration> {
+        let pack =
+            self.evidence_pack_by_repo_id(repo_id, params, max_handles, max_per_file, None)?;
+        let planned = plan_expansion(&pack, token_budget);
+        let outcome = if planned.is_empty() {
+            empty_outcome()
+        } else {
+            self.expand_by_repo_id_inner(repo_id, &planned, true)?
+        };
+        Ok(Exploration::assemble(pack, outcome, token_budget))
+    }
+
+    /// The service's registration of `repo_id`: status, generation, commit.
+    pub fn repo_status_by_id(&self, repo_id: &str) -> canopy_core::Result<RepoShard> {
+        self.require_service()?
+            .list_repos()?
+            .into_iter()
+            .find(|shard| shard.repo_id == repo_id)
+            .ok_or_else(|| CanopyError::ServiceError {
+                code: "repo_not_found".to_string(),
+                message: format!("Repo {} not found in service", repo_id),
+                hint: "List registered repos with 'canopy repos'".to_string(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standalone_mode_rejects_repo_id_calls() {
+        let mut rt = ClientRuntime::new(None, None);
+        let params = QueryParams::pattern("auth");
+        assert!(matches!(
+            rt.query_by_repo_id("repo-1", params.clone()),
+            Err(CanopyError::NoServiceConfigured)
+        ));
+        assert!(matches!(
+            rt.expand_by_repo_id("repo-1", &["h1".to_string()]),
+            Err(CanopyError::NoServiceConfigured)
+        ));
+        assert!(matches!(
+            rt.evidence_pack_by_repo_id("repo-1", params, 8, 2, None),
+            Err(CanopyError::NoServiceConfigured)
+        ));
+        assert!(matches!(
+            rt.explore_by_repo_id("repo-1", QueryParams::pattern("auth"), 4000, 8, 2),
+            Err(CanopyError::NoServiceConfigured)
+        ));
+        assert!(matches!(
+            rt.repo_status_by_id("repo-1"),
+            Err(CanopyError::NoServiceConfigured)
+        ));
+    }
+}
@@ -1,10 +1,57 @@
 //! Expand orchestration — batch local, batch service, and unknown-provenance expansion.
 
-use crate::service_client::is_error_code;
+use crate::service_client::{is_error_code, ServiceClient};
 use canopy_core::index::ExpandedHandleDetail;
-use std::path::Path;
+use canopy_core::protocol::{ExpandHandle, ExpandedContent};
+use canopy_core::{HandleStatus, LineWindow};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 
-use super::{ClientRuntime, ENSURE_READY_TIMEOUT};
+use super::{lock_index, ClientRuntime, ENSURE_READY_TIMEOUT};
+
+/// What an expand has produced so far, across the provenance partitions.
+#[derive(Default)]
+pub(super) struct Expansion {
+    pub(super) contents: Vec<(String, String)>,
+    pub(super) failed_ids: Vec<String>,
+    /// Expanded id -> why its line window was ignored
+    pub(super) notes: BTreeMap<String, String>,
+}
+
+impl Expansion {
+    fn add_details(&mut self, details: Vec<ExpandedHandleDetail>) {
+        for d in details {
+            if let Some(note) = d.note {
+                self.notes.insert(d.handle_id.clone(), note);
+            }
+            self.contents.push((d.handle_id, d.content));
+        }
+    }
+
+    fn add_contents(&mut self, contents: Vec<ExpandedContent>) {
+        for c in contents {
+            if let Some(note) = c.note {
+                self.notes.insert(c.handle_id.clone(), note);
+            }
+            self.contents.push((c.handle_id, c.content));
+        }
+    }
+}
+
+/// `ids` as service expand handles of `generation`, with their windows.
+fn expand_handles(
+    ids: &[String],
+    generation: Option<u64>,
+    windows: &HashMap<String, LineWindow>,
+) -> Vec<ExpandHandle> {
+    ids.iter()
+        .map(|id| ExpandHandle {
+            id: id.clone(),
+            generation,
+            lines: windows.get(id).copied(),
+        })
+        .collect()
+}
 
 impl ClientRuntime {
     /// Expand local handles: try batch first, fall back to per-handle on failure.
@@ -12,71 +59,131 @@ impl ClientRuntime {
         &self,
         repo_path: &Path,
         ids: Vec<String>,
-        contents: &mut Vec<(String, String)>,
-        failed_ids: &mut Vec<String>,
+        windows: &HashMap<String, LineWindow>,
+        out: &mut Expansion,
     ) {
         if ids.is_empty() {
             return;
         }
-        match self.expand_local(repo_path, &ids) {
-            Ok(mut c) => contents.append(&mut c),
+        match self.expand_local_windowed(repo_path, &ids, windows) {
+            Ok(details) => out.add_details(details),
             Err(_) => {
                 for id in ids {
-                    match self.expand_local(repo_path, std::slice::from_ref(&id)) {
-                        Ok(c) => contents.extend(c),
-                        Err(_) => failed_ids.push(id),
+                    match self.expand_local_windowed(repo_path, std::slice::from_ref(&id), windows)
+                    {
+                        Ok(details) => out.add_details(details),
+                        Err(_) => out.failed_ids.push(id),
                     }
                 }
             }
         }
     }
 
+    /// Reindex the local files behind any stale handles among `local_ids`
+    /// and `unknown_ids`, so compare-mode expands diff against what's on
+    /// disk now. Best-effort: a failure leaves the handles to fail as usual.
+    pub(super) fn refresh_stale_local(
+        &mut self,
+        repo_path: &Path,
+        local_ids: &[String],
+        unknown_ids: &[String],
+    ) {
+        if self.service.is_some() || (local_ids.is_empty() && unknown_ids.is_empty()) {
+            return;
+        }
+        let ids: Vec<String> = local_ids.iter().chain(unknown_ids).cloned().collect();
+        let Ok(validations) = self.validate_handles(repo_path, &ids) else {
+            return;
+        };
+        let mut stale: Vec<PathBuf> = validations
+            .into_iter()
+            .filter(|v| v.status == HandleStatus::Stale)
+            .filter_map(|v| v.file_path)
+            .map(|path| repo_path.join(path))
+            .collect();
+        stale.sort();
+        stale.dedup();
+        if !stale.is_empty() {
+            let _ = self.index_paths(repo_path, &stale);
+        }
+    }
+
     /// Expand service handles: resolve repo, ensure ready, batch expand with fallbacks.
     pub(super) fn expand_service_batch(
         &mut self,
         repo_path: &Path,
         service_ids: Vec<(String, Option<u64>, Option<String>)>,
-        contents: &mut Vec<(String, String)>,
-        failed_ids: &mut Vec<String>,
+        auto_expanded: bool,
+        windows: &HashMap<String, LineWindow>,
+        out: &mut Expansion,
     ) {
         if service_ids.is_empty() {
             return;
         }
 
         let Some(service) = self.service.as_mut() else {
-            failed_ids.extend(service_ids.into_iter().map(|(id, _, _)| id));
+            out.failed_ids
+                .extend(service_ids.into_iter().map(|(id, _, _)| id));
             return;
         };
 
         let Some(repo_id) = service.resolve_ready(repo_path, ENSURE_READY_TIMEOUT).ok() else {
-            failed_ids.extend(service_ids.into_iter().map(|(id, _, _)| id));
+            out.failed_ids
+                .extend(service_ids.into_iter().map(|(id, _, _)| id));
             return;
         };
 
-        let all_ids: Vec<String> = service_ids.iter().map(|(id, _, _)| id.clone()).collect();
-        let batch_gen = service_ids.first().and_then(|(_, g, _)| *g);
+        let handles: Vec<ExpandHandle> = service_ids
+            .iter()
+            .map(|(id, generation, _)| ExpandHandle {
+                id: id.clone(),
+                generation: *generation,
+                lines: windows.get(id).copied(),
+            })
+            .collect();
+        let expand = |service: &ServiceClient, repo_id: &str, ids: &[String], gen| {
+            service.expand_handles(repo_id, &expand_handles(ids, gen, windows), auto_expanded)
+        };
 
-        match service.expand(&repo_id, &all_ids, batch_gen) {
-            Ok(mut c) => contents.append(&mut c),
+        match service.expand_handles(&repo_id, &handles, auto_expanded) {
+            Ok(c) => out.add_contents(c),
             Err(e) if is_error_code(&e, "repo_not_found") => {
                 let resolved = service.invalidate_and_resolve(repo_path).ok();
                 for (id, gen, _) in &service_ids {
                     if let Some(ref rid) = resolved {
-                        if let Ok(c) = service.expand(rid, std::slice::from_ref(id), *gen) {
-                            contents.extend(c);
+                        if let Ok(c) = expand(service, rid, std::slice::from_ref(id), *gen) {
+                            out.add_contents(c);
                             continue;
                         }
                     }
-                    failed_ids.push(id.clone());
+                    out.failed_ids.push(id.clone());
+                }
+            }
+            Err(e) if is_error_code(&e, "stale_generation") => {
+                // A generation the service no longer retains: expand each
+                // generation on its own, so only the gone ones' handles fail
+                let mut by_generation: BTreeMap<(Option<u64>, &str), Vec<String>> = BTreeMap::new();
+                for (id, gen, rid) in &service_ids {
+                    let target_id = rid.as_deref().unwrap_or(&repo_id);
+                    by_generation
+                        .entry((*gen, target_id))
+                        .or_default()
+                        .push(id.clone());
+                }
+                for ((gen, target_id), ids) in by_generation {
+                    match expand(service, target_id, &ids, gen) {
+                        Ok(c) => out.add_contents(c),
+                        Err(_) => out.failed_ids.extend(ids),
+                    }
                 }
             }
             Err(_) => {
-                // Batch failed (e.g., mixed generations) — per-handle fallback
+                // Batch failed (e.g., handles of another repo) — per-handle fallback
                 for (id, gen, rid) in &service_ids {
                     let target_id = rid.as_deref().unwrap_or(&repo_id);
-                    match service.expand(target_id, std::slice::from_ref(id), *gen) {
-                        Ok(c) => contents.extend(c),
-                        Err(_) => failed_ids.push(id.clone()),
+                    match expand(service, target_id, std::slice::from_ref(id), *gen) {
+                        Ok(c) => out.add_contents(c),
+                        Err(_) => out.failed_ids.push(id.clone()),
                     }
                 }
             }
@@ -88,35 +195,40 @@ impl ClientRuntime {
         &mut self,
         repo_path: &Path,
         ids: Vec<String>,
-        contents: &mut Vec<(String, String)>,
-        failed_ids: &mut Vec<String>,
+        auto_expanded: bool,
+        windows: &HashMap<String, LineWindow>,
+        out: &mut Expansion,
     ) {
         for id in ids {
-            if let Ok(c) = self.expand_local(repo_path, std::slice::from_ref(&id)) {
-                contents.extend(c);
+            let ids = std::slice::from_ref(&id);
+            if let Ok(details) = self.expand_local_windowed(repo_path, ids, windows) {
+                out.add_details(details);
                 continue;
             }
             if let Some(service) = &mut self.service {
                 if let Ok(repo_id) = service.resolve_repo_id(repo_path) {
                     if service.ensure_ready(&repo_id, ENSURE_READY_TIMEOUT).is_ok() {
-                        if let Ok(c) = service.expand(&repo_id, std::slice::from_ref(&id), None) {
-                            contents.extend(c);
+                        let handles = expand_handles(ids, None, windows);
+                        if let Ok(c) = service.expand_handles(&repo_id, &handles, auto_expanded) {
+                            out.add_contents(c);
                             continue;
                         }
                     }
                 }
             }
-            failed_ids.push(id);
+            out.failed_ids.push(id);
         }
     }
 
-    pub(super) fn expand_local(
+    fn expand_local_windowed(
         &self,
         repo_path: &Path,
         handle_ids: &[String],
-    ) -> canopy_core::Result<Vec<(String, String)>> {
+        windows: &HashMap<String, LineWindow>,
+    ) -> canopy_core::Result<Vec<ExpandedHandleDetail>> {
         let index = self.open_local_index(repo_path)?;
-        index.expand(handle_ids)
+        let index = lock_index(&index);
+        index.expand_with_windows(handle_ids, windows)
     }
 
     pub(super) fn expand_local_details(
@@ -125,6 +237,7 @@ impl ClientRuntime {
         handle_ids: &[String],
     ) -> canopy_core::Result<Vec<ExpandedHandleDetail>> {
         let index = self.open_local_index(repo_path)?;
+        let index = lock_index(&index);
         index.expand_with_details(handle_ids)
     }
 }
@@ -0,0 +1,355 @@
+//! Explore: an evidence pack plus the expansions that fit a token budget,
+//! in one call.
+//!
+//! Handles are expanded in rank order — expand suggestions first, then the
+//! rest of the pack — until the next one doesn't fit; that one is cut at a
+//! line boundary to the budget left. Expansions are recorded in feedback as
+//! automatic. Service handles expand at the generation the pack was built
+//! from, so a reindex in between fails the expand instead of mixing
+//! generations.
+
+use canopy_core::parse::estimate_tokens;
+use canopy_core::{EvidencePack, ExpandOutcome, NodeType, QueryParams};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use super::ClientRuntime;
+
+/// A truncated handle is only worth sending with at least this many tokens.
+const MIN_PARTIAL_TOKENS: usize = 32;
+
+/// An evidence pack and the handle contents expanded within a budget.
+#[derive(Debug, Clone, Serialize)]
+pub struct Exploration {
+    pub pack: EvidencePack,
+    /// Expanded handles in rank order; only the last can be truncated.
+    pub expanded: Vec<ExploredHandle>,
+    /// Handles planned for expansion that could not be expanded.
+    pub failed_ids: Vec<String>,
+    pub token_budget: usize,
+    /// Tokens of expanded content, never more than `token_budget`.
+    pub tokens_spent: usize,
+    pub tokens_remaining: usize,
+    /// Pack handles left unexpanded, in rank order; the truncated handle
+    /// is not included.
+    pub unexpanded: Vec<String>,
+    /// What to do with the remaining budget.
+    pub next_step: String,
+}
+
+/// Content of one handle expanded by explore.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExploredHandle {
+    pub handle_id: String,
+    pub file_path: String,
+    pub node_type: NodeType,
+    /// Lines covered by `content`: the handle's range, or its start when truncated.
+    pub line_range: (usize, usize),
+    pub token_count: usize,
+    /// Cut to fit the budget; expand the handle for the whole node.
+    pub truncated: bool,
+    pub content: String,
+}
+
+impl ClientRuntime {
+    /// Build an evidence pack and expand its handles in rank order within
+    /// `token_budget` tokens of content.
+    pub fn explore(
+        &mut self,
+        repo_path: &Path,
+        params: QueryParams,
+        token_budget: usize,
+        max_handles: usize,
+        max_per_file: usize,
+    ) -> canopy_core::Result<Exploration> {
+        let pack = self.evidence_pack(repo_path, params, max_handles, max_per_file, None)?;
+        let planned = plan_expansion(&pack, token_budget);
+        let outcome = if planned.is_empty() {
+            empty_outcome()
+        } else {
+            self.expand_inner(repo_path, &planned, None, &HashMap::new(), true)?
+        };
+        Ok(Exploration::assemble(pack, outcome, token_budget))
+    }
+}
+
+pub(super) fn empty_outcome() -> ExpandOutcome {
+    ExpandOutcome {
+        contents: Vec::new(),
+        failed_ids: Vec::new(),
+        comparisons: Vec::new(),
+        unresolved_pins: Vec::new(),
+        notes: BTreeMap::new(),
+    }
+}
+
+/// Pack handle ids in rank order: expand suggestions, then the rest.
+fn ranked_ids(pack: &EvidencePack) -> Vec<String> {
+    let mut seen = HashSet::new();
+    pack.expand_suggestion
+        .iter()
+        .chain(pack.handles.iter().map(|h| &h.id))
+        .filter(|id| seen.insert(id.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// Handles to expand by their indexed token counts: whole while they fit,
+/// then the first that doesn't if enough budget is left to truncate it.
+pub(super) fn plan_expansion(pack: &EvidencePack, token_budget: usize) -> Vec<String> {
+    let mut remaining = token_budget;
+    let mut planned = Vec::new();
+    for id in ranked_ids(pack) {
+        let Some(handle) = pack.handles.iter().find(|h| h.id == id) else {
+            continue;
+        };
+        if handle.token_count <= remaining {
+            remaining -= handle.token_count;
+            planned.push(id);
+        } else {
+            if remaining >= MIN_PARTIAL_TOKENS {
+                planned.push(id);
+            }
+            break;
+        }
+    }
+    planned
+}
+
+impl Exploration {
+    /// Fit the expanded contents to the budget by their actual token counts,
+    /// which can differ from the pack's if files changed since indexing.
+    pub(super) fn assemble(
+        pack: EvidencePack,
+        outcome: ExpandOutcome,
+        token_budget: usize,
+    ) -> Self {
+        let mut remaining = token_budget;
+        let mut expanded = Vec::new();
+        let mut delivered = HashSet::new();
+        for id in ranked_ids(&pack) {
+            let Some((_, content)) = outcome.contents.iter().find(|(c, _)| *c == id) else {
+                continue;
+            };
+            let Some(handle) = pack.handles.iter().find(|h| h.id == id) else {
+                continue;
+            };
+            let tokens = estimate_tokens(content);
+            if tokens <= remaining {
+                remaining -= tokens;
+                delivered.insert(id.clone());
+                expanded.push(ExploredHandle {
+                    handle_id: id,
+                    file_path: handle.file_path.clone(),
+                    node_type: handle.node_type,
+                    line_range: handle.line_range,
+                    token_count: tokens,
+                    truncated: false,
+                    content: content.clone(),
+                });
+                continue;
+            }
+
+            if let Some((content, lines)) = truncate_to_tokens(content, remaining) {
+                let token_count = estimate_tokens(&content);
+                remaining -= token_count;
+                delivered.insert(id.clone());
+                let start = handle.line_range.0;
+                expanded.push(ExploredHandle {
+                    handle_id: id,
+                    file_path: handle.file_path.clone(),
+                    node_type: handle.node_type,
+                    line_range: (start, start + lines - 1),
+                    token_count,
+                    truncated: true,
+                    content,
+                });
+            }
+            break;
+        }
+
+        let unexpanded: Vec<String> = ranked_ids(&pack)
+            .into_iter()
+            .filter(|id| !delivered.contains(id) && !outcome.failed_ids.contains(id))
+            .collect();
+        let tokens_spent = token_budget - remaining;
+        let next_step = next_step(&pack, &expanded, &unexpanded, remaining);
+        Self {
+            pack,
+            expanded,
+            failed_ids: outcome.failed_ids,
+            token_budget,
+            tokens_spent,
+            tokens_remaining: remaining,
+            unexpanded,
+            next_step,
+        }
+    }
+}
+
+/// The longest run of leading lines of `content` within `max_tokens`, and
+/// its line count; None if not even the first line fits.
+fn truncate_to_tokens(content: &str, max_tokens: usize) -> Option<(String, usize)> {
+    let lines: Vec<&str> = content.lines().collect();
+    let fits = |n: usize| estimate_tokens(&lines[..n].join("\n")) <= max_tokens;
+    // Token counts only grow with more lines, so binary search the cut
+    let (mut lo, mut hi) = (0, lines.len());
+    while lo < hi {
+        let mid = (lo + hi).div_ceil(2);
+        if fits(mid) {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    (lo > 0).then(|| (lines[..lo].join("\n"), lo))
+}
+
+fn next_step(
+    pack: &EvidencePack,
+    expanded: &[ExploredHandle],
+    unexpanded: &[String],
+    remaining: usize,
+) -> String {
+    if pack.handles.is_empty() {
+        return pack.guidance.next_step.clone();
+    }
+    let mut step = String::new();
+    if let Some(cut) = expanded.last().filter(|h| h.truncated) {
+        let full = pack
+            .handles
+            .iter()
+            .find(|h| h.id == cut.handle_id)
+            .map_or(0, |h| h.token_count);
+        step.push_str(&format!(
+            "{} was cut to fit the budget; expand it for the whole node (~{} tokens). ",
+            cut.handle_id, full
+        ));
+    }
+    if unexpanded.is_empty() {
+        step.push_str(&format!(
+            "Every pack handle is expanded with {} tokens to spare; synthesize from them.",
+            remaining
+        ));
+    } else {
+        let tokens: usize = pack
+            .handles
+            .iter()
+            .filter(|h| unexpanded.contains(&h.id))
+            .map(|h| h.token_count)
+            .sum();
+        step.push_str(&format!(
+            "{} handles (~{} tokens) left unexpanded with {} tokens of budget left; \
+             raise token_budget or expand the ones you need.",
+            unexpanded.len(),
+            tokens,
+            remaining
+        ));
+    }
+    step
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canopy_core::RepoIndex;
+
+    fn repo_with_functions() -> std::path::PathBuf {
+        let root = canopy_core::temp_test_dir("explore-test");
+        RepoIndex::init(&root).unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        for i in 0..6 {
+            let body = format!("    let session_{i} = session_store.load(token);\n").repeat(20 + i);
+            std::fs::write(
+                root.join(format!("src/session_{i}.rs")),
+                format!("fn refresh_session_{i}(token: &str) {{\n{body}}}\n"),
+            )
+            .unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn explore_stays_within_budget() {
+        let repo = repo_with_functions();
+        let mut rt = ClientRuntime::new(None, None);
+        rt.index(&repo, Some("**/*.rs")).unwrap();
+
+        for budget in [0, 40, 300, 1000, 100_000] {
+            let exploration = rt
+                .explore(&repo, QueryParams::pattern("session_store"), budget, 8, 2)
+                .unwrap();
+            let spent: usize = exploration
+                .expanded
+                .iter()
+                .map(|h| estimate_tokens(&h.content))
+                .sum();
+            assert_eq!(spent, exploration.tokens_spent);
+            assert!(spent <= budget, "spent {spent} of {budget}");
+            assert_eq!(exploration.tokens_remaining, budget - spent);
+            // Only the last handle can be cut short
+            let cut = exploration.expanded.iter().filter(|h| h.truncated).count();
+            assert!(cut <= 1);
+            if cut == 1 {
+                assert!(exploration.expanded.last().unwrap().truncated);
+            }
+            assert_eq!(
+                exploration.expanded.len() + exploration.unexpanded.len(),
+                exploration.pack.handles.len()
+            );
+        }
+    }
+
+    #[test]
+    fn explore_truncates_the_handle_that_does_not_fit() {
+        let repo = repo_with_functions();
+        let mut rt = ClientRuntime::new(None, None);
+        rt.index(&repo, Some("**/*.rs")).unwrap();
+
+        let whole = rt
+            .explore(
+                &repo,
+                QueryParams::pattern("session_store"),
+                1_000_000,
+                8,
+                2,
+            )
+            .unwrap();
+        let first = &whole.expanded[0];
+        assert!(!first.truncated);
+        assert!(whole.unexpanded.is_empty());
+
+        let budget = first.token_count + first.token_count / 2;
+        let partial = rt
+            .explore(&repo, QueryParams::pattern("session_store"), budget, 8, 2)
+            .unwrap();
+        assert_eq!(partial.expanded.len(), 2);
+        let cut = &partial.expanded[1];
+        assert!(cut.truncated);
+        assert!(cut.line_range.1 >= cut.line_range.0);
+        assert_eq!(
+            cut.content.lines().count(),
+            cut.line_range.1 - cut.line_range.0 + 1
+        );
+        assert!(partial.next_step.contains(&cut.handle_id));
+
+        // Explored handles count as recently expanded
+        let canonical = super::super::canonical_path(&repo);
+        assert!(rt
+            .tracker
+            .was_recently_expanded(&canonical, &first.handle_id));
+    }
+
+    #[test]
+    fn truncation_keeps_whole_lines() {
+        let content = "fn a() {\n    one();\n    two();\n}";
+        assert_eq!(truncate_to_tokens(content, 0), None);
+        let (cut, lines) =
+            truncate_to_tokens(content, estimate_tokens("fn a() {\n    one();")).unwrap();
+        assert_eq!(lines, 2);
+        assert_eq!(cut, "fn a() {\n    one();");
+        let (all, lines) = truncate_to_tokens(content, 1000).unwrap();
+        assert_eq!((all.as_str(), lines), (content, 4));
+    }
+}
@@ -1,14 +1,18 @@
 //! Feedback recording and provenance helpers.
+//!
+//! Events are queued for the background writer (see [`super::feedback_writer`]);
+//! the store opened here is only read, for priors and glob scores.
 
 use crate::provenance::{HandleProvenance, ProvenanceTracker};
 use canopy_core::{
     feedback::{ExpandEvent, FeedbackStore, QueryEvent, QueryHandle, NODE_TYPE_PRIOR_CACHE_TTL},
-    EvidencePack, HandleSource, NodeType, QueryResult,
+    EvidencePack, Handle, HandleSource, NodeType, QueryResult,
 };
 use std::collections::HashMap;
 use std::path::Path;
 use std::time::Instant;
 
+use super::feedback_writer::FeedbackWrite;
 use super::{canonical_path, ClientRuntime};
 
 impl ClientRuntime {
@@ -17,6 +21,9 @@ impl ClientRuntime {
         if !self.feedback.stores.contains_key(&canonical) {
             match FeedbackStore::open(repo_path) {
                 Ok(store) => {
+                    if let Some(report) = store.auto_pruned() {
+                        eprintln!("[canopy] feedback: {}", report);
+                    }
                     self.feedback.stores.insert(canonical.clone(), store);
                 }
                 Err(err) => {
@@ -83,6 +90,10 @@ impl ClientRuntime {
             files_indexed,
             handles_returned: result.handles.len(),
             total_tokens: result.total_tokens,
+            file_tokens: result.savings.as_ref().map_or(0, |s| s.file_tokens),
+            returned_tokens: result.savings.as_ref().map_or(0, |s| s.returned_tokens),
+            match_count: (!result.mode.is_handles()).then_some(result.total_matches),
+            client: self.client.clone(),
         };
 
         let query_handles: Vec<QueryHandle> = result
@@ -96,51 +107,45 @@ impl ClientRuntime {
             })
             .collect();
 
-        let query_event_id: i64;
-        {
-            let Some(store) = self.feedback_store_for_repo(repo_path) else {
-                return;
-            };
-
-            match store.record_query_event(&query_event) {
-                Ok(id) => query_event_id = id,
-                Err(err) => {
-                    eprintln!("[canopy] feedback: failed to record query event: {}", err);
-                    return;
-                }
-            }
+        let auto_expanded = result
+            .handles
+            .iter()
+            .filter(|h| h.content.is_some())
+            .map(|handle| ExpandEvent {
+                query_event_id: None,
+                handle_id: handle.id.to_string(),
+                file_path: handle.file_path.clone(),
+                node_type: handle.node_type,
+                token_count: handle.token_count,
+                auto_expanded: true,
+                client: self.client.clone(),
+            })
+            .collect();
 
-            if let Err(err) = store.record_query_handles(query_event_id, &query_handles) {
-                eprintln!("[canopy] feedback: failed to record query handles: {}", err);
-            }
-
-            for handle in result.handles.iter().filter(|h| h.content.is_some()) {
-                let event = ExpandEvent {
-                    query_event_id: Some(query_event_id),
-                    handle_id: handle.id.to_string(),
-                    file_path: handle.file_path.clone(),
-                    node_type: handle.node_type,
-                    token_count: handle.token_count,
-                    auto_expanded: true,
-                };
-                if let Err(err) = store.record_expand_event(&event) {
-                    eprintln!(
-                        "[canopy] feedback: failed to record auto-expand event: {}",
-                        err
-                    );
-                }
-            }
-        }
+        let query_event_id = self.feedback.writer.next_ticket();
+        self.feedback.writer.send(
+            Path::new(&canonical),
+            FeedbackWrite::Query {
+                ticket: query_event_id,
+                event: Box::new(query_event),
+                handles: query_handles,
+                auto_expanded,
+            },
+        );
 
         for handle in &result.handles {
             self.remember_recent_query_event(&canonical, &handle.id.to_string(), query_event_id);
         }
     }
 
+    /// Record expand events. `delivered_tokens` overrides the node's token
+    /// count for handles sent as a diff or reported unchanged.
     pub(super) fn record_feedback_for_expand(
         &mut self,
         repo_path: &Path,
         contents: &[(String, String)],
+        delivered_tokens: &HashMap<String, usize>,
+        auto_expanded: bool,
     ) {
         if contents.is_empty() {
             return;
@@ -187,6 +192,10 @@ impl ClientRuntime {
                     content,
                     &local_metadata,
                 );
+                let token_count = delivered_tokens
+                    .get(handle_id)
+                    .copied()
+                    .unwrap_or(token_count);
                 let query_event_id = self.tracker.query_event_id(&canonical, handle_id);
                 ExpandEvent {
                     query_event_id,
@@ -194,7 +203,8 @@ impl ClientRuntime {
                     file_path,
                     node_type,
                     token_count,
-                    auto_expanded: false,
+                    auto_expanded,
+                    client: self.client.clone(),
                 }
             })
             .collect();
@@ -203,14 +213,9 @@ impl ClientRuntime {
             return;
         }
 
-        let Some(store) = self.feedback_store_for_repo(repo_path) else {
-            return;
-        };
-        for event in events {
-            if let Err(err) = store.record_expand_event(&event) {
-                eprintln!("[canopy] feedback: failed to record expand event: {}", err);
-            }
-        }
+        self.feedback
+            .writer
+            .send(Path::new(&canonical), FeedbackWrite::Expand(events));
     }
 
     pub(super) fn record_provenance_for_result(
@@ -221,10 +226,39 @@ impl ClientRuntime {
         generation: Option<u64>,
         repo_id: Option<String>,
     ) {
-        let canonical = canonical_path(repo_path);
-        for handle in &result.handles {
+        self.record_provenance_for_handles(repo_path, &result.handles, source, generation, repo_id);
+    }
+
+    pub(super) fn record_provenance_for_handles<'a>(
+        &mut self,
+        repo_path: &Path,
+        handles: impl IntoIterator<Item = &'a Handle>,
+        source: HandleSource,
+        generation: Option<u64>,
+        repo_id: Option<String>,
+    ) {
+        self.record_provenance_under(
+            &canonical_path(repo_path),
+            handles,
+            source,
+            generation,
+            repo_id,
+        );
+    }
+
+    /// [`record_provenance_for_handles`](Self::record_provenance_for_handles)
+    /// under a tracker key rather than a repo path.
+    pub(super) fn record_provenance_under<'a>(
+        &mut self,
+        repo_key: &str,
+        handles: impl IntoIterator<Item = &'a Handle>,
+        source: HandleSource,
+        generation: Option<u64>,
+        repo_id: Option<String>,
+    ) {
+        for handle in handles {
             self.tracker.record(
-                &canonical,
+                repo_key,
                 &handle.id.to_string(),
                 HandleProvenance {
                     source: source.clone(),
@@ -244,14 +278,22 @@ impl ClientRuntime {
         pack: &EvidencePack,
         service_repo_id: Option<String>,
     ) {
-        let canonical = canonical_path(repo_path);
+        self.record_pack_provenance_under(&canonical_path(repo_path), pack, service_repo_id);
+    }
+
+    pub(super) fn record_pack_provenance_under(
+        &mut self,
+        repo_key: &str,
+        pack: &EvidencePack,
+        service_repo_id: Option<String>,
+    ) {
         for handle in &pack.handles {
             let repo_id = match handle.source {
                 HandleSource::Service => service_repo_id.clone(),
                 HandleSource::Local => None,
             };
             self.tracker.record(
-                &canonical,
+                repo_key,
                 &handle.id,
                 HandleProvenance {
                     source: handle.source.clone(),
@@ -0,0 +1,282 @@
+//! Background writer for feedback events.
+//!
+//! Recording a query's feedback takes several sqlite inserts, so rather than
+//! writing them before the query returns, the runtime queues events for a
+//! writer thread. The thread writes each repo's queued events in one
+//! transaction, once [`BATCH_SIZE`] events are waiting or [`FLUSH_INTERVAL`]
+//! after the oldest arrived. Readers of the feedback store (priors, metrics)
+//! may lag that far behind.
+
+use canopy_core::capped_map::CappedMap;
+use canopy_core::feedback::{ExpandEvent, FeedbackStore, QueryEvent, QueryHandle};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+/// Queued events that trigger a write without waiting for the timer
+pub(super) const BATCH_SIZE: usize = 64;
+
+/// Longest an event waits in the queue before it is written
+pub(super) const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Longest dropping the runtime waits for queued events to be written
+pub(super) const SHUTDOWN_WAIT: Duration = Duration::from_secs(2);
+
+/// Write failures are reported at most once per interval
+const ERROR_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Query tickets remembered for linking later expand events to their query
+const TICKET_CAP: usize = 10_000;
+
+/// Feedback for one repo, queued for the writer thread.
+pub(super) enum FeedbackWrite {
+    Query {
+        /// Stands in for the query event's row id, which the writer assigns
+        ticket: i64,
+        event: Box<QueryEvent>,
+        handles: Vec<QueryHandle>,
+        /// `query_event_id` is filled in by the writer
+        auto_expanded: Vec<ExpandEvent>,
+    },
+    /// `query_event_id` holds the producing query's ticket, if known
+    Expand(Vec<ExpandEvent>),
+}
+
+enum Message {
+    Write { repo: PathBuf, write: FeedbackWrite },
+    Flush(Sender<()>),
+}
+
+/// Handle to the writer thread, started on the first write.
+pub(super) struct FeedbackWriter {
+    sender: Option<Sender<Message>>,
+    next_ticket: i64,
+}
+
+impl FeedbackWriter {
+    pub(super) fn new() -> Self {
+        Self {
+            sender: None,
+            next_ticket: 1,
+        }
+    }
+
+    /// A fresh ticket for a query event about to be queued.
+    pub(super) fn next_ticket(&mut self) -> i64 {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        ticket
+    }
+
+    /// Queue `write` for `repo` without waiting on any I/O.
+    pub(super) fn send(&mut self, repo: &Path, write: FeedbackWrite) {
+        let sender = self.sender.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            std::thread::spawn(move || Worker::new().run(receiver));
+            sender
+        });
+        let message = Message::Write {
+            repo: repo.to_path_buf(),
+            write,
+        };
+        if sender.send(message).is_err() {
+            // The thread only exits once every sender is gone, so this means
+            // it panicked. Start a new one on the next write.
+            self.sender = None;
+        }
+    }
+
+    /// Wait up to `timeout` for everything queued so far to be written.
+    /// Returns `false` if the wait timed out.
+    pub(super) fn flush(&self, timeout: Duration) -> bool {
+        let Some(sender) = &self.sender else {
+            return true;
+        };
+        let (done, wait) = mpsc::channel();
+        if sender.send(Message::Flush(done)).is_err() {
+            return false;
+        }
+        wait.recv_timeout(timeout).is_ok()
+    }
+}
+
+impl Drop for FeedbackWriter {
+    fn drop(&mut self) {
+        if !self.flush(SHUTDOWN_WAIT) {
+            eprintln!(
+                "[canopy] feedback: gave up waiting for queued events after {:?}",
+                SHUTDOWN_WAIT
+            );
+        }
+    }
+}
+
+/// The writer thread's state.
+struct Worker {
+    stores: HashMap<PathBuf, FeedbackStore>,
+    /// Query ticket -> `query_events` row id
+    tickets: CappedMap<i64, i64>,
+    pending: Vec<(PathBuf, FeedbackWrite)>,
+    errors: ErrorLog,
+}
+
+impl Worker {
+    fn new() -> Self {
+        Self {
+            stores: HashMap::new(),
+            tickets: CappedMap::new(TICKET_CAP),
+            pending: Vec::new(),
+            errors: ErrorLog::default(),
+        }
+    }
+
+    fn run(mut self, receiver: Receiver<Message>) {
+        // When the oldest pending event is due to be written
+        let mut deadline: Option<Instant> = None;
+        loop {
+            let message = match deadline {
+                Some(at) => {
+                    match receiver.recv_timeout(at.saturating_duration_since(Instant::now())) {
+                        Ok(message) => Some(message),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                None => match receiver.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => break,
+                },
+            };
+
+            match message {
+                Some(Message::Write { repo, write }) => {
+                    self.pending.push((repo, write));
+                    deadline.get_or_insert_with(|| Instant::now() + FLUSH_INTERVAL);
+                    if self.pending.len() < BATCH_SIZE {
+                        continue;
+                    }
+                }
+                Some(Message::Flush(done)) => {
+                    self.write_pending();
+                    deadline = None;
+                    let _ = done.send(());
+                    continue;
+                }
+                None => {}
+            }
+            self.write_pending();
+            deadline = None;
+        }
+        self.write_pending();
+    }
+
+    /// Write pending events, one transaction per repo.
+    fn write_pending(&mut self) {
+        let mut by_repo: Vec<(PathBuf, Vec<FeedbackWrite>)> = Vec::new();
+        for (repo, write) in self.pending.drain(..) {
+            match by_repo.iter_mut().find(|(r, _)| *r == repo) {
+                Some((_, writes)) => writes.push(write),
+                None => by_repo.push((repo, vec![write])),
+            }
+        }
+
+        for (repo, writes) in by_repo {
+            if !self.stores.contains_key(&repo) {
+                match FeedbackStore::open(&repo) {
+                    Ok(store) => {
+                        if let Some(report) = store.auto_pruned() {
+                            eprintln!("[canopy] feedback: {}", report);
+                        }
+                        self.stores.insert(repo.clone(), store);
+                    }
+                    Err(err) => {
+                        self.errors.report("failed to open store", err);
+                        continue;
+                    }
+                }
+            }
+            let store = &self.stores[&repo];
+            let tickets = &self.tickets;
+            match store.in_transaction(|store| write_batch(store, tickets, writes)) {
+                Ok(assigned) => {
+                    for (ticket, id) in assigned {
+                        self.tickets.insert(ticket, id);
+                    }
+                }
+                Err(err) => self.errors.report("failed to record events", err),
+            }
+        }
+    }
+}
+
+/// Insert `writes`, returning the row id assigned to each query ticket.
+fn write_batch(
+    store: &FeedbackStore,
+    tickets: &CappedMap<i64, i64>,
+    writes: Vec<FeedbackWrite>,
+) -> canopy_core::Result<Vec<(i64, i64)>> {
+    // Tickets from this batch aren't in `tickets` until it commits
+    let mut assigned: Vec<(i64, i64)> = Vec::new();
+    for write in writes {
+        match write {
+            FeedbackWrite::Query {
+                ticket,
+                event,
+                handles,
+                auto_expanded,
+            } => {
+                let id = store.record_query_event(&event)?;
+                store.record_query_handles(id, &handles)?;
+                for mut expand in auto_expanded {
+                    expand.query_event_id = Some(id);
+                    store.record_expand_event(&expand)?;
+                }
+                assigned.push((ticket, id));
+            }
+            FeedbackWrite::Expand(events) => {
+                for mut expand in events {
+                    expand.query_event_id = expand.query_event_id.and_then(|ticket| {
+                        assigned
+                            .iter()
+                            .find(|(t, _)| *t == ticket)
+                            .map(|(_, id)| *id)
+                            .or_else(|| tickets.get(&ticket).copied())
+                    });
+                    store.record_expand_event(&expand)?;
+                }
+            }
+        }
+    }
+    Ok(assigned)
+}
+
+/// Reports write failures at most once per [`ERROR_LOG_INTERVAL`].
+#[derive(Default)]
+struct ErrorLog {
+    last_reported: Option<Instant>,
+    suppressed: usize,
+}
+
+impl ErrorLog {
+    fn report(&mut self, what: &str, err: impl Display) {
+        if self
+            .last_reported
+            .is_some_and(|at| at.elapsed() < ERROR_LOG_INTERVAL)
+        {
+            self.suppressed += 1;
+            return;
+        }
+        if self.suppressed > 0 {
+            eprintln!(
+                "[canopy] feedback: {}: {} ({} more failures since the last report)",
+                what, err, self.suppressed
+            );
+        } else {
+            eprintln!("[canopy] feedback: {}: {}", what, err);
+        }
+        self.last_reported = Some(Instant::now());
+        self.suppressed = 0;
+    }
+}
@@ -4,25 +4,57 @@
 //! In service mode, queries are dispatched to canopy-service.
 //! DSL queries always run locally (the DSL engine is not exposed by the service).
 
+mod bench;
+mod by_repo_id;
 mod expand;
+mod explore;
 mod feedback;
+mod feedback_writer;
+mod pins;
 mod query_dispatch;
+mod refresh;
+mod replay;
+mod shared_index;
 
+pub use bench::{
+    compare_snapshots, BenchHandle, BenchQuery, BenchQueryDiff, BenchQueryResult, BenchReport,
+    BenchSnapshot, BenchSuite, BenchThresholds, RankMove, RankRequirement,
+};
+pub use explore::{Exploration, ExploredHandle};
+pub use refresh::{HandleRefresh, RefreshReport, RefreshStatus};
+pub use replay::{ReplayQueryDiff, ReplayReport};
+pub use shared_index::{lock_index, IndexRegistry, SharedIndex};
+
+use crate::dirty::{DirtyRebuildStats, DirtyRebuilder};
+use crate::expanded_cache::ExpandedContentCache;
 use crate::predict::{
     extract_extensions_from_glob, predict_globs, predict_globs_with_feedback, LARGE_REPO_THRESHOLD,
     MAX_PREDICTIVE_FILES,
 };
-use crate::provenance::ProvenanceTracker;
-use crate::service_client::{is_error_code, ReindexResponse, ServiceClient, ServiceStatus};
+use crate::provenance::{HandleProvenance, ProvenanceTracker};
+use crate::retry::RetryPolicy;
+use crate::service_client::{
+    is_error_code, AddRepoRequest, AddRepoResponse, ReindexResponse, ServiceClient, ServiceStatus,
+    WarmupResponse,
+};
+use crate::session_log::{now_ts, SessionLog, SessionRecord};
+use canopy_core::protocol::{ClientContext, ReferencesRequest, SymbolsRequest};
 use canopy_core::{
-    build_evidence_pack, feedback::FeedbackStore, EvidencePack, ExpandOutcome, HandleSource,
-    IndexStats, NodeType, QueryParams, QueryResult, RepoIndex, RepoShard,
+    build_evidence_pack_with_priors, feedback::FeedbackStore, AutoInit, CanopyError,
+    EvidenceConfig, EvidencePack, ExpandComparison, ExpandDelta, ExpandOutcome, FileReferences,
+    HandleSource, HandleValidation, IndexPlan, IndexStats, LineWindow, NodeType, PathStyle,
+    QueryMode, QueryParams, QueryResult, RefType, RelatedFiles, RepoIndex, RepoShard, RepoSummary,
+    Reranker, SymbolPage, DEFAULT_REFERENCE_FILES_LIMIT, DEFAULT_RELATED_LIMIT,
+    DEFAULT_SUMMARY_TOKENS, DEFAULT_SYMBOL_LIMIT,
 };
-use std::collections::{HashMap, HashSet};
-use std::path::Path;
-use std::time::Instant;
+use expand::Expansion;
+use feedback_writer::FeedbackWriter;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-const ENSURE_READY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+const ENSURE_READY_TIMEOUT: Duration = Duration::from_secs(300);
 
 /// Result of an index/reindex operation
 pub enum IndexResult {
@@ -38,16 +70,29 @@ struct PendingPredictiveContext {
 
 /// Repo-local feedback stores and predictive indexing context.
 struct FeedbackContext {
-    /// Repo-local feedback DB handles (lazy-opened)
+    /// Repo-local feedback DB handles for reads (lazy-opened)
     stores: HashMap<String, FeedbackStore>,
+    /// Queues feedback writes off the query path
+    writer: FeedbackWriter,
     /// Predictive context staged between predictive_index_for_query() and query()
     pending_predictive: HashMap<String, PendingPredictiveContext>,
 }
 
+/// A service generation bump observed while querying a repo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenerationChange {
+    pub repo_id: String,
+    pub repo_root: PathBuf,
+    pub old_generation: u64,
+    pub new_generation: u64,
+}
+
 /// Cached per-repo metadata: generation tracking and node type priors.
 struct CacheContext {
     /// Track last-known generation per repo to detect changes
     repo_generations: HashMap<String, u64>,
+    /// Bumps seen since the last `take_generation_changes()`
+    generation_changes: Vec<GenerationChange>,
     /// Cached node type priors per repo
     node_type_priors: HashMap<String, (Instant, HashMap<NodeType, f64>)>,
 }
@@ -57,6 +102,19 @@ pub struct ClientRuntime {
     tracker: ProvenanceTracker,
     feedback: FeedbackContext,
     cache: CacheContext,
+    session_log: Option<SessionLog>,
+    reranker: Option<Arc<dyn Reranker>>,
+    /// Shared local indexes, one per repo
+    indexes: IndexRegistry,
+    /// Content of recent expansions, baselines for compare-mode expand
+    expanded_contents: ExpandedContentCache,
+    /// Whether opening a repo without `.canopy/` initializes it
+    auto_init: AutoInit,
+    /// Session and tool behind current calls, recorded with feedback and
+    /// sent to the service
+    client: ClientContext,
+    /// Debounces local index rebuilds for dirty files in service mode
+    dirty: DirtyRebuilder,
 }
 
 impl ClientRuntime {
@@ -72,15 +130,111 @@ impl ClientRuntime {
             tracker: ProvenanceTracker::new(),
             feedback: FeedbackContext {
                 stores: HashMap::new(),
+                writer: FeedbackWriter::new(),
                 pending_predictive: HashMap::new(),
             },
             cache: CacheContext {
                 repo_generations: HashMap::new(),
+                generation_changes: Vec::new(),
                 node_type_priors: HashMap::new(),
             },
+            session_log: None,
+            reranker: None,
+            indexes: IndexRegistry::new(),
+            expanded_contents: ExpandedContentCache::default(),
+            auto_init: AutoInit::from_env(true),
+            client: ClientContext::default(),
+            dirty: DirtyRebuilder::from_env(),
+        }
+    }
+
+    /// Attribute subsequent calls to `client`: feedback events record it and
+    /// service requests carry it as headers.
+    pub fn set_client_context(&mut self, client: ClientContext) {
+        if let Some(service) = self.service.as_mut() {
+            service.set_client_context(client.clone());
+        }
+        self.client = client;
+    }
+
+    /// The context set by [`set_client_context`](Self::set_client_context).
+    pub fn client_context(&self) -> &ClientContext {
+        &self.client
+    }
+
+    /// Enable (or disable) the session transcript for subsequent query/expand calls.
+    pub fn set_session_log(&mut self, session_log: Option<SessionLog>) {
+        self.session_log = session_log;
+    }
+
+    /// Rerank subsequent query results. Local queries rerank candidates before the
+    /// limit; service results are reranked after the dirty-file merge.
+    pub fn set_reranker(&mut self, reranker: Option<Arc<dyn Reranker>>) {
+        self.reranker = reranker;
+    }
+
+    /// Retry failed service requests per `policy` (see [`RetryPolicy`]).
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        if let Some(service) = self.service.as_mut() {
+            service.set_retry_policy(policy);
         }
     }
 
+    /// Retries the service client has made, across all calls.
+    pub fn service_retry_count(&self) -> u64 {
+        self.service.as_ref().map_or(0, ServiceClient::retry_count)
+    }
+
+    /// Debounce window for dirty-file rebuilds of the local index (see
+    /// [`DirtyRebuilder`]); zero rebuilds whenever the dirty files change.
+    pub fn set_dirty_debounce(&mut self, window: Duration) {
+        self.dirty.set_window(window);
+    }
+
+    /// Dirty-file rebuilds performed and debounced, across all calls.
+    pub fn dirty_rebuild_stats(&self) -> DirtyRebuildStats {
+        self.dirty.stats()
+    }
+
+    /// Whether local commands initialize repos that lack `.canopy/`. On by
+    /// default, unless `CANOPY_AUTO_INIT` turns it off.
+    pub fn set_auto_init(&mut self, auto_init: AutoInit) {
+        self.auto_init = auto_init;
+    }
+
+    /// Share local indexes with other runtimes in this process (see [`IndexRegistry`]).
+    pub fn set_index_registry(&mut self, indexes: IndexRegistry) {
+        self.indexes = indexes;
+    }
+
+    /// Replace the cache of recently expanded content, e.g. with one loaded
+    /// from disk so compare-mode expand works across CLI invocations.
+    pub fn set_expanded_contents(&mut self, cache: ExpandedContentCache) {
+        self.expanded_contents = cache;
+    }
+
+    pub fn expanded_contents(&self) -> &ExpandedContentCache {
+        &self.expanded_contents
+    }
+
+    /// The registry holding this runtime's local indexes.
+    pub fn index_registry(&self) -> IndexRegistry {
+        self.indexes.clone()
+    }
+
+    /// Drain the service generation bumps detected by queries since the last call.
+    pub fn take_generation_changes(&mut self) -> Vec<GenerationChange> {
+        std::mem::take(&mut self.cache.generation_changes)
+    }
+
+    /// Wait until feedback recorded so far has been written, up to a few
+    /// seconds; returns `false` on timeout. Queries never wait for feedback
+    /// writes, so callers that read the feedback store straight after one
+    /// (mostly tests) call this first.
+    pub fn flush_feedback(&self) -> bool {
+        self.feedback.writer.flush(feedback_writer::SHUTDOWN_WAIT)
+    }
+
     pub fn is_service_mode(&self) -> bool {
         self.service.is_some()
     }
@@ -94,12 +248,20 @@ impl ClientRuntime {
         repo_path: &Path,
         params: QueryParams,
     ) -> canopy_core::Result<QueryResult> {
+        let start = Instant::now();
         let query_text = params.to_text();
+        let logged_params = self.session_log.as_ref().map(|_| params.clone());
 
         let is_dsl = params.dsl.is_some();
 
         let result = if self.service.is_some() && !is_dsl {
-            self.query_service(repo_path, params)?
+            let changes_before = self.cache.generation_changes.len();
+            let mut result = self.query_service(repo_path, params)?;
+            self.rerank_service_result(&query_text, &mut result);
+            if self.cache.generation_changes.len() > changes_before {
+                self.refresh_pins_quietly(repo_path);
+            }
+            result
         } else {
             if self.service.is_some() && is_dsl {
                 eprintln!("Warning: DSL query bypasses service mode, using local index");
@@ -108,6 +270,16 @@ impl ClientRuntime {
         };
 
         self.record_feedback_for_query(repo_path, &query_text, &result);
+        if let (Some(log), Some(params)) = (self.session_log.as_mut(), logged_params) {
+            log.append(&SessionRecord::Query {
+                ts: now_ts(),
+                repo: canonical_path(repo_path),
+                params: Box::new(params),
+                handle_ids: result.handles.iter().map(|h| h.id.to_string()).collect(),
+                total_tokens: result.total_tokens,
+                duration_ms: start.elapsed().as_millis() as u64,
+            });
+        }
         Ok(result)
     }
 
@@ -124,6 +296,9 @@ impl ClientRuntime {
     ) -> canopy_core::Result<EvidencePack> {
         let max_handles = max_handles.clamp(1, 64);
         let max_per_file = max_per_file.clamp(1, 8);
+        let params = params.with_mode(QueryMode::Handles);
+        // Checked up front so a typo fails the same way in both modes
+        let priors = params.node_type_priors()?;
         let config = canopy_core::protocol::EvidencePackConfig {
             max_handles: Some(max_handles),
             max_per_file: Some(max_per_file),
@@ -132,7 +307,7 @@ impl ClientRuntime {
 
         if let Some(service) = self.service.as_mut() {
             if params.dsl.is_none() {
-                let mut params = params.clone();
+                let mut params = params.for_source(&HandleSource::Service);
                 params.expand_budget = Some(0);
 
                 let active_repo_id = service.resolve_ready(repo_path, ENSURE_READY_TIMEOUT)?;
@@ -155,10 +330,23 @@ impl ClientRuntime {
             }
         }
 
+        let calibration = EvidenceConfig::for_repo(repo_path)?.calibration()?;
         let fallback_params = params.pattern_fallback();
+        // Generated files only make it into a pack when nothing else matches
+        let generated_params = params
+            .include_generated
+            .is_none()
+            .then(|| params.clone().with_include_generated(true));
         let query_text = params.to_text();
         let result = self.query(repo_path, params)?;
-        let mut pack = build_evidence_pack(&result, &query_text, max_handles, max_per_file);
+        let mut pack = build_evidence_pack_with_priors(
+            &result,
+            &query_text,
+            max_handles,
+            max_per_file,
+            priors.clone(),
+            &calibration,
+        );
         self.rewrite_expand_suggestions(repo_path, &mut pack);
         self.record_provenance_for_evidence_pack(repo_path, &pack, None);
 
@@ -166,11 +354,13 @@ impl ClientRuntime {
             if let Some(fallback) = fallback_params {
                 let fallback_text = fallback.to_text();
                 let fallback_result = self.query(repo_path, fallback)?;
-                let fallback_pack = build_evidence_pack(
+                let fallback_pack = build_evidence_pack_with_priors(
                     &fallback_result,
                     &fallback_text,
                     max_handles,
                     max_per_file,
+                    priors.clone(),
+                    &calibration,
                 );
                 if fallback_pack.selected_count > 0 {
                     let mut fallback_pack = fallback_pack;
@@ -180,6 +370,22 @@ impl ClientRuntime {
                 }
             }
         }
+        if pack.selected_count == 0 && result.suppressed_generated > 0 {
+            if let Some(generated) = generated_params {
+                let generated_result = self.query(repo_path, generated)?;
+                let mut generated_pack = build_evidence_pack_with_priors(
+                    &generated_result,
+                    &query_text,
+                    max_handles,
+                    max_per_file,
+                    priors,
+                    &calibration,
+                );
+                self.rewrite_expand_suggestions(repo_path, &mut generated_pack);
+                self.record_provenance_for_evidence_pack(repo_path, &generated_pack, None);
+                pack = generated_pack;
+            }
+        }
         Ok(pack)
     }
 
@@ -189,11 +395,71 @@ impl ClientRuntime {
     /// Local handles → batch index.expand
     /// Unknown handles → try one-by-one: local first, then service
     /// Returns ExpandOutcome with partial results; fails only if ALL handles fail
+    ///
+    /// With `compare`, each handle is compared against the content this
+    /// runtime last expanded for it; see [`expand_against`](Self::expand_against).
     pub fn expand(
         &mut self,
         repo_path: &Path,
         handle_ids: &[String],
+        compare: bool,
+    ) -> canopy_core::Result<ExpandOutcome> {
+        let baselines = compare.then(HashMap::new);
+        self.expand_inner(
+            repo_path,
+            handle_ids,
+            baselines.as_ref(),
+            &HashMap::new(),
+            false,
+        )
+    }
+
+    /// Expand, cutting the handles in `windows` to those lines of their
+    /// node (clamped to it). A window outside its node leaves the node whole,
+    /// with an entry in [`ExpandOutcome::notes`]. Cut content isn't kept as
+    /// a compare-mode baseline.
+    pub fn expand_lines(
+        &mut self,
+        repo_path: &Path,
+        handle_ids: &[String],
+        windows: &HashMap<String, LineWindow>,
     ) -> canopy_core::Result<ExpandOutcome> {
+        self.expand_inner(repo_path, handle_ids, None, windows, false)
+    }
+
+    /// Compare-mode expand against caller-supplied baseline hashes
+    /// (handle_id → sha256 of the content last seen). Handles without an
+    /// entry fall back to the runtime's last expansion.
+    ///
+    /// `comparisons` reports each handle as unchanged, as a unified diff when
+    /// the baseline content is still cached, or as full content otherwise.
+    /// Feedback records the tokens actually delivered, not the node size.
+    pub fn expand_against(
+        &mut self,
+        repo_path: &Path,
+        handle_ids: &[String],
+        baselines: &HashMap<String, String>,
+    ) -> canopy_core::Result<ExpandOutcome> {
+        self.expand_inner(
+            repo_path,
+            handle_ids,
+            Some(baselines),
+            &HashMap::new(),
+            false,
+        )
+    }
+
+    /// `auto_expanded` marks expansions made on the caller's behalf, as
+    /// explore does, so feedback can tell them from requested ones.
+    fn expand_inner(
+        &mut self,
+        repo_path: &Path,
+        handle_ids: &[String],
+        baselines: Option<&HashMap<String, String>>,
+        windows: &HashMap<String, LineWindow>,
+        auto_expanded: bool,
+    ) -> canopy_core::Result<ExpandOutcome> {
+        let start = Instant::now();
         let canonical = canonical_path(repo_path);
 
         let mut seen_ids = HashSet::new();
@@ -203,12 +469,15 @@ impl ClientRuntime {
             .cloned()
             .collect();
 
+        // Pinned handles expand as their node's current id
+        let mut followed = self.follow_pins(repo_path, &unique_handle_ids);
+
         // Partition by provenance
         let mut local_ids: Vec<String> = Vec::new();
         let mut service_ids: Vec<(String, Option<u64>, Option<String>)> = Vec::new();
         let mut unknown_ids: Vec<String> = Vec::new();
 
-        for id in &unique_handle_ids {
+        for id in &followed.targets {
             if let Some(prov) = self.tracker.get(&canonical, id) {
                 match prov.source {
                     HandleSource::Local => local_ids.push(id.clone()),
@@ -221,13 +490,80 @@ impl ClientRuntime {
             }
         }
 
+        // A stale file would fail to expand; compare against its current
+        // content instead
+        if baselines.is_some() {
+            self.refresh_stale_local(repo_path, &local_ids, &unknown_ids);
+        }
+
         // Expand each partition
-        let mut contents: Vec<(String, String)> = Vec::new();
-        let mut failed_ids: Vec<String> = Vec::new();
+        let target_windows = followed.target_windows(windows);
+        let mut out = Expansion::default();
+
+        self.expand_local_batch(repo_path, local_ids, &target_windows, &mut out);
+        self.expand_service_batch(
+            repo_path,
+            service_ids,
+            auto_expanded,
+            &target_windows,
+            &mut out,
+        );
+        self.expand_unknown(
+            repo_path,
+            unknown_ids,
+            auto_expanded,
+            &target_windows,
+            &mut out,
+        );
+        self.retry_moved_pins(repo_path, &mut followed, windows, &mut out);
+        let Expansion {
+            mut contents,
+            mut failed_ids,
+            notes,
+        } = out;
+
+        // Report followed pins under the id they were requested as
+        for (id, _) in &mut contents {
+            *id = followed.requested_id(id).to_string();
+        }
+        for id in &mut failed_ids {
+            *id = followed.requested_id(id).to_string();
+        }
+        failed_ids.extend(followed.unresolved.iter().map(|p| p.handle_id.clone()));
+        let notes: BTreeMap<String, String> = notes
+            .into_iter()
+            .map(|(id, note)| (followed.requested_id(&id).to_string(), note))
+            .collect();
 
-        self.expand_local_batch(repo_path, local_ids, &mut contents, &mut failed_ids);
-        self.expand_service_batch(repo_path, service_ids, &mut contents, &mut failed_ids);
-        self.expand_unknown(repo_path, unknown_ids, &mut contents, &mut failed_ids);
+        // Compare before caching so the previous expansion is the baseline
+        let comparisons: Vec<ExpandComparison> = match baselines {
+            Some(baselines) => contents
+                .iter()
+                .map(|(id, content)| {
+                    let baseline = baselines.get(id).map(String::as_str);
+                    self.expanded_contents
+                        .compare(&canonical, id, content, baseline)
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        for (id, content) in &contents {
+            // A cut node is no baseline for the whole one
+            if !windows.contains_key(id) {
+                self.expanded_contents.insert(&canonical, id, content);
+            }
+        }
+        let delivered_tokens: HashMap<String, usize> = comparisons
+            .iter()
+            .filter_map(|c| match &c.delta {
+                ExpandDelta::Unchanged => Some((c.handle_id.clone(), 0)),
+                ExpandDelta::Diff { diff } => Some((
+                    c.handle_id.clone(),
+                    canopy_core::parse::estimate_tokens(diff),
+                )),
+                ExpandDelta::Full => None,
+            })
+            .collect();
 
         // Record feedback
         self.record_recently_expanded(repo_path, &contents);
@@ -243,12 +579,36 @@ impl ClientRuntime {
                         .map(|_| (id.clone(), content.clone()))
                 })
                 .collect();
-            self.record_feedback_for_expand(repo_path, &local_contents);
+            self.record_feedback_for_expand(
+                repo_path,
+                &local_contents,
+                &delivered_tokens,
+                auto_expanded,
+            );
         } else {
-            self.record_feedback_for_expand(repo_path, &contents);
+            self.record_feedback_for_expand(repo_path, &contents, &delivered_tokens, auto_expanded);
+        }
+
+        if let Some(log) = self.session_log.as_mut() {
+            log.append(&SessionRecord::Expand {
+                ts: now_ts(),
+                repo: canonical.clone(),
+                handle_ids: contents.iter().map(|(id, _)| id.clone()).collect(),
+                failed_ids: failed_ids.clone(),
+                duration_ms: start.elapsed().as_millis() as u64,
+            });
         }
 
         if contents.is_empty() && !failed_ids.is_empty() {
+            if let [pin] = followed.unresolved.as_slice() {
+                if failed_ids.len() == 1 {
+                    return Err(canopy_core::CanopyError::PinnedHandleMissing {
+                        handle_id: pin.handle_id.clone(),
+                        file_path: pin.file_path.clone(),
+                        reason: pin.reason.clone(),
+                    });
+                }
+            }
             return Err(canopy_core::CanopyError::HandleNotFound(
                 failed_ids.join(", "),
             ));
@@ -257,6 +617,9 @@ impl ClientRuntime {
         Ok(ExpandOutcome {
             contents,
             failed_ids,
+            comparisons,
+            unresolved_pins: followed.unresolved,
+            notes,
         })
     }
 
@@ -271,20 +634,288 @@ impl ClientRuntime {
             let response = service.reindex(&repo_id, glob.map(String::from))?;
             Ok(IndexResult::Service(response))
         } else {
-            let mut index = self.open_local_index(repo_path)?;
+            let index = self.open_local_index(repo_path)?;
+            let mut index = lock_index(&index);
             let default_glob = index.config().default_glob().to_string();
             let glob = glob.unwrap_or(&default_glob);
             let stats = index.index(glob)?;
+            drop(index);
+            self.refresh_pins_quietly(repo_path);
+            Ok(IndexResult::Local(stats))
+        }
+    }
+
+    /// What [`index`](Self::index) would change, without writing anything.
+    ///
+    /// Only local indexes can be previewed: in service mode the service
+    /// indexes its own checkout, so this fails with `dry_run_unsupported`.
+    pub fn plan_index(
+        &mut self,
+        repo_path: &Path,
+        glob: Option<&str>,
+    ) -> canopy_core::Result<IndexPlan> {
+        if self.service.is_some() {
+            return Err(CanopyError::ServiceError {
+                code: "dry_run_unsupported".to_string(),
+                message: "Dry runs preview the local index, not the service's".to_string(),
+                hint: "Run without a service URL to preview a local index".to_string(),
+            });
+        }
+        let index = self.open_local_index(repo_path)?;
+        let index = lock_index(&index);
+        let default_glob = index.config().default_glob().to_string();
+        index.plan_index(glob.unwrap_or(&default_glob))
+    }
+
+    /// Ingest commits from the last `window` (default: `[history] since`)
+    /// into the local index's git-history layer. Returns the commits added.
+    ///
+    /// The service ingests history itself when the repo config enables
+    /// `[history]`, so in service mode this fails with
+    /// `git_history_unsupported`.
+    pub fn index_history(
+        &mut self,
+        repo_path: &Path,
+        window: Option<Duration>,
+    ) -> canopy_core::Result<usize> {
+        if self.service.is_some() {
+            return Err(CanopyError::ServiceError {
+                code: "git_history_unsupported".to_string(),
+                message: "Git history is ingested by the service, not on request".to_string(),
+                hint: "Set `[history] enabled = true` in the repo's .canopy/config.toml"
+                    .to_string(),
+            });
+        }
+        let index = self.open_local_index(repo_path)?;
+        let mut index = lock_index(&index);
+        let window = match window {
+            Some(window) => window,
+            None => index.config().history_window()?,
+        };
+        index.index_history(window)
+    }
+
+    /// Index exactly `paths` instead of walking a glob.
+    ///
+    /// The service is sent paths relative to `repo_path` where they can be,
+    /// since its checkout may live elsewhere.
+    pub fn index_paths(
+        &mut self,
+        repo_path: &Path,
+        paths: &[PathBuf],
+    ) -> canopy_core::Result<IndexResult> {
+        if let Some(service) = &mut self.service {
+            let repo_id = service.resolve_repo_id(repo_path)?;
+            let paths = paths
+                .iter()
+                .map(|path| {
+                    path.strip_prefix(repo_path)
+                        .unwrap_or(path)
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect();
+            let response = service.reindex_paths(&repo_id, paths)?;
+            Ok(IndexResult::Service(response))
+        } else {
+            let index = self.open_local_index(repo_path)?;
+            let stats = lock_index(&index).index_paths(paths)?;
+            self.refresh_pins_quietly(repo_path);
             Ok(IndexResult::Local(stats))
         }
     }
 
+    /// Budgeted repository overview for bootstrapping an agent session.
+    ///
+    /// The README handle, if any, is tracked like a query result so it can be
+    /// expanded afterwards.
+    pub fn repo_summary(
+        &mut self,
+        repo_path: &Path,
+        max_tokens: Option<usize>,
+    ) -> canopy_core::Result<RepoSummary> {
+        let (summary, repo_id) = if let Some(service) = self.service.as_mut() {
+            let active_repo_id = service.resolve_ready(repo_path, ENSURE_READY_TIMEOUT)?;
+            match service.summary(&active_repo_id, max_tokens) {
+                Ok(summary) => (summary, Some(active_repo_id)),
+                Err(e) if is_error_code(&e, "repo_not_found") => {
+                    let new_id = service.invalidate_and_resolve(repo_path)?;
+                    service.ensure_ready(&new_id, ENSURE_READY_TIMEOUT)?;
+                    (service.summary(&new_id, max_tokens)?, Some(new_id))
+                }
+                Err(e) => return Err(e),
+            }
+        } else {
+            let index = self.open_local_index(repo_path)?;
+            let summary =
+                lock_index(&index).repo_summary(max_tokens.unwrap_or(DEFAULT_SUMMARY_TOKENS))?;
+            (summary, None)
+        };
+
+        if let Some(readme) = &summary.readme {
+            self.tracker.record(
+                &canonical_path(repo_path),
+                &readme.id.to_string(),
+                HandleProvenance {
+                    source: readme.source.clone(),
+                    generation: readme.generation,
+                    repo_id,
+                    file_path: readme.file_path.clone(),
+                    node_type: readme.node_type,
+                    token_count: readme.token_count,
+                },
+            );
+        }
+        Ok(summary)
+    }
+
+    /// Files most related to `path` (repo-relative) by the symbols they share.
+    pub fn related_files(
+        &mut self,
+        repo_path: &Path,
+        path: &str,
+        limit: Option<usize>,
+    ) -> canopy_core::Result<RelatedFiles> {
+        if let Some(service) = self.service.as_mut() {
+            let active_repo_id = service.resolve_ready(repo_path, ENSURE_READY_TIMEOUT)?;
+            return match service.related(&active_repo_id, path, limit) {
+                Err(e) if is_error_code(&e, "repo_not_found") => {
+                    let new_id = service.invalidate_and_resolve(repo_path)?;
+                    service.ensure_ready(&new_id, ENSURE_READY_TIMEOUT)?;
+                    service.related(&new_id, path, limit)
+                }
+                other => other,
+            };
+        }
+        let index = self.open_local_index(repo_path)?;
+        let related =
+            lock_index(&index).related_files(path, limit.unwrap_or(DEFAULT_RELATED_LIMIT))?;
+        Ok(related)
+    }
+
+    /// References to `symbol` of `ref_types` (all when empty) in files
+    /// matching `glob`, grouped by file. A service that can't be reached, or
+    /// predates the references route, is skipped for the local index.
+    pub fn references(
+        &mut self,
+        repo_path: &Path,
+        symbol: &str,
+        ref_types: &[RefType],
+        glob: Option<&str>,
+        limit: Option<usize>,
+    ) -> canopy_core::Result<FileReferences> {
+        if let Some(service) = self.service.as_mut() {
+            let request = |repo: &str| ReferencesRequest {
+                repo: repo.to_string(),
+                symbol: symbol.to_string(),
+                ref_types: ref_types.to_vec(),
+                glob: glob.map(str::to_string),
+                limit,
+            };
+            let from_service = service
+                .resolve_ready(repo_path, ENSURE_READY_TIMEOUT)
+                .and_then(
+                    |active_repo_id| match service.references(&request(&active_repo_id)) {
+                        Err(e) if is_error_code(&e, "repo_not_found") => {
+                            let new_id = service.invalidate_and_resolve(repo_path)?;
+                            service.ensure_ready(&new_id, ENSURE_READY_TIMEOUT)?;
+                            service.references(&request(&new_id))
+                        }
+                        other => other,
+                    },
+                );
+            match from_service {
+                Err(e) if service_path_unavailable(&e) => {}
+                other => return other,
+            }
+        }
+        let index = self.open_local_index(repo_path)?;
+        let references = lock_index(&index).references_by_file(
+            symbol,
+            ref_types,
+            glob,
+            limit.unwrap_or(DEFAULT_REFERENCE_FILES_LIMIT),
+        )?;
+        Ok(references)
+    }
+
+    /// A page of the repo's named nodes in name order, narrowed to names
+    /// starting with `prefix` and to `node_types` (empty = all); `after` is
+    /// the `next_after` of the previous page.
+    pub fn list_symbols(
+        &mut self,
+        repo_path: &Path,
+        prefix: Option<&str>,
+        node_types: &[NodeType],
+        after: Option<&str>,
+        limit: Option<usize>,
+    ) -> canopy_core::Result<SymbolPage> {
+        if let Some(service) = self.service.as_mut() {
+            let request = |repo: &str| SymbolsRequest {
+                repo: repo.to_string(),
+                prefix: prefix.map(str::to_string),
+                node_types: node_types.to_vec(),
+                after: after.map(str::to_string),
+                limit,
+            };
+            let active_repo_id = service.resolve_ready(repo_path, ENSURE_READY_TIMEOUT)?;
+            return match service.symbols(&request(&active_repo_id)) {
+                Err(e) if is_error_code(&e, "repo_not_found") => {
+                    let new_id = service.invalidate_and_resolve(repo_path)?;
+                    service.ensure_ready(&new_id, ENSURE_READY_TIMEOUT)?;
+                    service.symbols(&request(&new_id))
+                }
+                other => other,
+            };
+        }
+        let index = self.open_local_index(repo_path)?;
+        let node_types = (!node_types.is_empty()).then_some(node_types);
+        let page = lock_index(&index).list_symbols(
+            prefix,
+            node_types,
+            after,
+            limit.unwrap_or(DEFAULT_SYMBOL_LIMIT),
+        )?;
+        Ok(page)
+    }
+
+    /// Whether each of `handle_ids` is fresh, stale or missing, without
+    /// expanding any of them.
+    pub fn validate_handles(
+        &mut self,
+        repo_path: &Path,
+        handle_ids: &[String],
+    ) -> canopy_core::Result<Vec<HandleValidation>> {
+        if let Some(service) = self.service.as_mut() {
+            let active_repo_id = service.resolve_ready(repo_path, ENSURE_READY_TIMEOUT)?;
+            let response = match service.validate(&active_repo_id, handle_ids) {
+                Err(e) if is_error_code(&e, "repo_not_found") => {
+                    let new_id = service.invalidate_and_resolve(repo_path)?;
+                    service.ensure_ready(&new_id, ENSURE_READY_TIMEOUT)?;
+                    service.validate(&new_id, handle_ids)
+                }
+                other => other,
+            }?;
+            return Ok(response.handles);
+        }
+        let index = self.open_local_index(repo_path)?;
+        let validations = lock_index(&index).validate_handles(handle_ids)?;
+        Ok(validations)
+    }
+
     /// Service admin: list repos. Err(NoServiceConfigured) in standalone.
     pub fn list_repos(&self) -> canopy_core::Result<Vec<RepoShard>> {
         let service = self.require_service()?;
         service.list_repos()
     }
 
+    /// Service admin: register a repo the service clones from a git URL.
+    /// Err(NoServiceConfigured) in standalone.
+    pub fn add_repo_by_url(&self, req: &AddRepoRequest) -> canopy_core::Result<AddRepoResponse> {
+        let service = self.require_service()?;
+        service.add_repo_url(req)
+    }
+
     /// Service admin: status. Err(NoServiceConfigured) in standalone.
     pub fn service_status(&self) -> canopy_core::Result<ServiceStatus> {
         let service = self.require_service()?;
@@ -301,6 +932,16 @@ impl ClientRuntime {
         service.reindex(repo_id, glob.map(String::from))
     }
 
+    /// Service admin: warm up repos by repo_id, or every ready repo when
+    /// None. Err(NoServiceConfigured) in standalone.
+    pub fn warmup_by_ids(
+        &self,
+        repo_ids: Option<Vec<String>>,
+    ) -> canopy_core::Result<WarmupResponse> {
+        let service = self.require_service()?;
+        service.warmup(repo_ids)
+    }
+
     /// Predictive index with specific query text (used by MCP tool_query)
     pub fn predictive_index_for_query(
         &mut self,
@@ -335,22 +976,21 @@ impl ClientRuntime {
                 predicted_globs.iter().take(5).collect::<Vec<_>>()
             );
 
-            let mut total_indexed = 0;
-            let mut file_to_glob: HashMap<String, String> = HashMap::new();
-            for glob in &predicted_globs {
-                if let Ok(files) = index.walk_files(glob) {
-                    for file in files {
-                        let path = file.to_string_lossy().to_string();
-                        file_to_glob.entry(path).or_insert_with(|| glob.clone());
-                    }
-                }
-                if let Ok(stats) = index.index(glob) {
-                    total_indexed += stats.files_indexed;
-                }
-                if total_indexed >= MAX_PREDICTIVE_FILES {
-                    break;
-                }
-            }
+            // Overlapping globs resolve to one file set, each file credited to
+            // the first glob that matched it
+            let matched = index.walk_globs(&predicted_globs, MAX_PREDICTIVE_FILES);
+            let paths: Vec<PathBuf> = matched
+                .iter()
+                .map(|(path, _)| PathBuf::from(path))
+                .collect();
+            let file_to_glob: HashMap<String, String> = matched
+                .into_iter()
+                .map(|(path, glob)| (path, predicted_globs[glob].clone()))
+                .collect();
+            let total_indexed = match index.index_paths(&paths) {
+                Ok(stats) => stats.files_indexed,
+                Err(_) => 0,
+            };
 
             if total_indexed > 0 {
                 eprintln!("[canopy] Predictively indexed {} new files", total_indexed);
@@ -377,27 +1017,46 @@ impl ClientRuntime {
         Ok(())
     }
 
-    fn open_local_index(&self, repo_path: &Path) -> canopy_core::Result<RepoIndex> {
-        RepoIndex::open_or_init(repo_path)
+    /// The shared local index for `repo_path`, opened on first use (and
+    /// initialized, when the auto-init policy allows).
+    pub fn open_local_index(&self, repo_path: &Path) -> canopy_core::Result<SharedIndex> {
+        self.indexes.open(repo_path, self.auto_init)
     }
 }
 
+/// Key of a repo's per-repo state (provenance, pins, feedback, indexes).
+/// Case-folded where the filesystem is case-insensitive, so two spellings
+/// of one checkout share their state.
 fn canonical_path(path: &Path) -> String {
-    std::fs::canonicalize(path)
+    let canonical = std::fs::canonicalize(path)
         .unwrap_or_else(|_| path.to_path_buf())
         .to_string_lossy()
-        .to_string()
+        .to_string();
+    if PathStyle::host().case_insensitive {
+        canonical.to_lowercase()
+    } else {
+        canonical
+    }
+}
+
+/// The service couldn't answer at all: it is down, or too old to have the
+/// route (a bare 404, not one of its error envelopes).
+fn service_path_unavailable(err: &CanopyError) -> bool {
+    ["connection_error", "service_unreachable", "http_404"]
+        .iter()
+        .any(|code| is_error_code(err, code))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::provenance::HandleProvenance;
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
 
-    fn temp_repo() -> std::path::PathBuf {
-        let root = canopy_core::temp_test_dir("runtime-test");
-        RepoIndex::init(&root).unwrap();
-        root
+    fn temp_repo() -> FixtureRepo {
+        let repo = FixtureRepoBuilder::new().build();
+        RepoIndex::init(repo.path()).unwrap();
+        repo
     }
 
     #[test]
@@ -412,6 +1071,27 @@ mod tests {
         assert!(rt.is_service_mode());
     }
 
+    #[test]
+    fn test_plan_index_previews_local_indexes_only() {
+        let fixture = temp_repo();
+        let root = fixture.path();
+        std::fs::write(root.join("a.rs"), "fn a() {}\n").unwrap();
+        let mut rt = ClientRuntime::new(None, None);
+        let plan = rt.plan_index(root, Some("**/*.rs")).unwrap();
+        assert_eq!(plan.new_files, ["a.rs"]);
+        assert_eq!(
+            lock_index(&rt.open_local_index(root).unwrap())
+                .status()
+                .unwrap()
+                .files_indexed,
+            0
+        );
+
+        let mut rt = ClientRuntime::new(Some("http://localhost:3000"), None);
+        let err = rt.plan_index(root, None).unwrap_err();
+        assert!(is_error_code(&err, "dry_run_unsupported"));
+    }
+
     #[test]
     fn test_list_repos_without_service() {
         let rt = ClientRuntime::new(None, None);
@@ -471,21 +1151,184 @@ mod tests {
 
     #[test]
     fn test_expand_feedback_records_without_provenance() {
-        let repo = temp_repo();
+        let fixture = temp_repo();
+        let repo = fixture.path();
         let mut rt = ClientRuntime::new(None, None);
         let handle_id = "h000000000000000000000000".to_string();
         let contents = vec![(handle_id, "fn hello_world() {}".to_string())];
 
-        rt.record_feedback_for_expand(&repo, &contents);
+        rt.record_feedback_for_expand(repo, &contents, &HashMap::new(), false);
+        assert!(rt.flush_feedback());
 
-        let store = FeedbackStore::open(&repo).unwrap();
+        let store = FeedbackStore::open(repo).unwrap();
         let metrics = store.compute_metrics(1.0).unwrap();
         assert!(metrics.avg_tokens_per_expand > 0.0);
     }
 
+    fn repo_with_file(name: &str, content: &str) -> FixtureRepo {
+        let repo = FixtureRepoBuilder::new()
+            .file(format!("src/{name}"), content)
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        repo
+    }
+
+    #[test]
+    fn test_query_feedback_lands_after_flush() {
+        let fixture = repo_with_file(
+            "lib.rs",
+            "pub fn feedback_target() {}
+",
+        );
+        let repo = fixture.path();
+        let mut rt = ClientRuntime::new(None, None);
+        rt.index(repo, Some("**/*.rs")).unwrap();
+
+        let result = rt
+            .query(repo, QueryParams::symbol("feedback_target"))
+            .unwrap();
+        assert_eq!(result.handles.len(), 1);
+        let ids = vec![result.handles[0].id.to_string()];
+        rt.expand(repo, &ids, false).unwrap();
+        assert!(rt.flush_feedback());
+
+        let metrics = FeedbackStore::open(repo)
+            .unwrap()
+            .compute_metrics(1.0)
+            .unwrap();
+        assert_eq!(metrics.sample_count, 1);
+        // The expand is linked to the query that returned the handle
+        assert_eq!(metrics.handle_expand_accept_rate, 1.0);
+    }
+
+    #[test]
+    fn test_query_does_not_wait_for_feedback_writes() {
+        let fixture = repo_with_file(
+            "lib.rs",
+            "pub fn unblocked() {}
+",
+        );
+        let repo = fixture.path();
+        let mut rt = ClientRuntime::new(None, None);
+        rt.index(repo, Some("**/*.rs")).unwrap();
+        rt.query(repo, QueryParams::symbol("unblocked")).unwrap();
+        assert!(rt.flush_feedback());
+
+        // Hold the feedback DB's write lock: an inline write would wait out
+        // the store's 5s busy timeout before the query could return
+        let blocker = FeedbackStore::open(repo).unwrap();
+        let event = canopy_core::feedback::QueryEvent {
+            query_text: "blocker".to_string(),
+            predicted_globs: None,
+            files_indexed: 0,
+            handles_returned: 0,
+            total_tokens: 0,
+            file_tokens: 0,
+            returned_tokens: 0,
+            match_count: None,
+            client: Default::default(),
+        };
+        let elapsed = blocker
+            .in_transaction(|store| {
+                store.record_query_event(&event)?;
+                let start = Instant::now();
+                rt.query(repo, QueryParams::symbol("unblocked"))?;
+                Ok(start.elapsed())
+            })
+            .unwrap();
+        assert!(
+            elapsed < std::time::Duration::from_secs(1),
+            "query took {elapsed:?} with the feedback DB locked"
+        );
+
+        // Once the lock is released the queued event is written
+        assert!(rt.flush_feedback());
+        let metrics = blocker.compute_metrics(1.0).unwrap();
+        assert_eq!(metrics.sample_count, 3);
+    }
+
+    /// Rewrite `file` with its mtime `ahead_secs` in the future, so reindex
+    /// sees it changed.
+    fn rewrite(file: &Path, content: &str, ahead_secs: u64) {
+        std::fs::write(file, content).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(file)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(ahead_secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_pinned_handle_follows_node_across_reindex() {
+        let fixture = repo_with_file(
+            "lib.rs",
+            "pub fn first() {}\n\npub fn pinned_target() -> u32 {\n    42\n}\n",
+        );
+        let repo = fixture.path();
+        let file = repo.join("src/lib.rs");
+        let mut rt = ClientRuntime::new(None, None);
+        rt.index(repo, Some("**/*.rs")).unwrap();
+
+        let result = rt
+            .query(repo, QueryParams::symbol("pinned_target"))
+            .unwrap();
+        let pinned_id = result.handles[0].id.to_string();
+        let pins = rt
+            .pin_handles(repo, std::slice::from_ref(&pinned_id))
+            .unwrap();
+        assert_eq!(pins[0].name.as_deref(), Some("pinned_target"));
+
+        // Insert code above the function so its span (and id) shifts
+        rewrite(
+            &file,
+            "pub fn first() {}\n\npub fn inserted() {\n    first();\n}\n\n\
+             pub fn pinned_target() -> u32 {\n    42\n}\n",
+            60,
+        );
+        rt.index(repo, Some("**/*.rs")).unwrap();
+
+        let pins = rt.list_pins(repo).unwrap();
+        assert_eq!(pins[0].status, crate::pins::PinStatus::Remapped);
+        let current_id = pins[0].current_id.clone().unwrap();
+        assert_ne!(current_id, pinned_id);
+
+        // A fresh runtime reads the remap from disk
+        let mut rt = ClientRuntime::new(None, None);
+        let outcome = rt
+            .expand(repo, std::slice::from_ref(&pinned_id), false)
+            .unwrap();
+        assert_eq!(outcome.contents.len(), 1);
+        assert_eq!(outcome.contents[0].0, pinned_id);
+        assert!(outcome.contents[0].1.starts_with("pub fn pinned_target()"));
+
+        // Deleting the function leaves a structured error
+        rewrite(&file, "pub fn first() {}\n", 120);
+        rt.index(repo, Some("**/*.rs")).unwrap();
+        match rt.expand(repo, std::slice::from_ref(&pinned_id), false) {
+            Err(canopy_core::CanopyError::PinnedHandleMissing {
+                handle_id,
+                file_path,
+                reason,
+            }) => {
+                assert_eq!(handle_id, pinned_id);
+                assert_eq!(file_path, "src/lib.rs");
+                assert!(
+                    reason.contains("no function named pinned_target"),
+                    "{reason}"
+                );
+            }
+            other => panic!(
+                "expected PinnedHandleMissing, got {:?}",
+                other.map(|o| o.contents)
+            ),
+        }
+    }
+
     #[test]
     fn test_standalone_query_returns_results() {
-        let repo = temp_repo();
+        let fixture = temp_repo();
+        let repo = fixture.path();
         // Write a Rust file to index
         let src_dir = repo.join("src");
         std::fs::create_dir_all(&src_dir).unwrap();
@@ -496,27 +1339,125 @@ mod tests {
         .unwrap();
 
         let mut rt = ClientRuntime::new(None, None);
-        rt.index(&repo, Some("**/*.rs")).unwrap();
+        rt.index(repo, Some("**/*.rs")).unwrap();
 
         let params = QueryParams::symbol("authenticate");
-        let result = rt.query(&repo, params).unwrap();
+        let result = rt.query(repo, params).unwrap();
         assert!(!result.handles.is_empty());
         assert_eq!(result.handles[0].file_path, "src/auth.rs");
     }
 
+    #[test]
+    fn test_references_fall_back_to_the_local_index_without_a_service() {
+        let fixture = temp_repo();
+        let repo = fixture.path();
+        std::fs::write(
+            repo.join("server.rs"),
+            "pub fn serve() {\n    dispatch();\n    dispatch();\n}\n",
+        )
+        .unwrap();
+        std::fs::write(repo.join("client.rs"), "pub fn send() { dispatch(); }\n").unwrap();
+        ClientRuntime::new(None, None)
+            .index(repo, Some("**/*.rs"))
+            .unwrap();
+
+        // Nothing listens once the listener is dropped
+        let url = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let mut rt = ClientRuntime::new(Some(&url), None);
+        rt.set_retry_policy(RetryPolicy {
+            attempts: 1,
+            ..RetryPolicy::default()
+        });
+        let references = rt
+            .references(repo, "dispatch", &[RefType::Call], None, Some(1))
+            .unwrap();
+        assert_eq!(references.files.len(), 1);
+        assert_eq!(references.files[0].file_path, "server.rs");
+        assert_eq!(references.files[0].lines, [2, 3]);
+        assert_eq!(references.total_files, 2);
+        assert!(references.truncated);
+    }
+
+    #[test]
+    fn test_auto_init_policy_decides_whether_uninitialized_repos_open() {
+        let repo = canopy_core::temp_test_dir("runtime-auto-init");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        std::fs::write(repo.join("lib.rs"), "pub fn bootstrap() {}\n").unwrap();
+
+        let mut rt = ClientRuntime::new(None, None);
+        rt.set_auto_init(AutoInit::off());
+        let err = rt.index(&repo, Some("**/*.rs")).err().unwrap();
+        assert!(matches!(err, canopy_core::CanopyError::NotInitialized));
+        assert!(!repo.join(".canopy").exists());
+
+        rt.set_auto_init(AutoInit::on());
+        rt.index(&repo, Some("**/*.rs")).unwrap();
+        let result = rt.query(&repo, QueryParams::symbol("bootstrap")).unwrap();
+        assert_eq!(result.handles.len(), 1);
+        assert!(!repo.join(".gitignore").exists());
+    }
+
     #[test]
     fn test_standalone_query_empty_index_returns_empty() {
-        let repo = temp_repo();
+        let fixture = temp_repo();
+        let repo = fixture.path();
         let mut rt = ClientRuntime::new(None, None);
 
         let params = QueryParams::symbol("nonexistent");
-        let result = rt.query(&repo, params).unwrap();
+        let result = rt.query(repo, params).unwrap();
         assert!(result.handles.is_empty());
     }
 
+    #[test]
+    fn test_session_log_records_query_and_expand() {
+        let fixture = temp_repo();
+        let repo = fixture.path();
+        let src_dir = repo.join("src");
+        std::fs::create_dir_all(&src_dir).unwrap();
+        std::fs::write(src_dir.join("lib.rs"), "pub fn logged_fn() {}\n").unwrap();
+        let log_path = repo.join("session.ndjson");
+
+        let mut rt = ClientRuntime::new(None, None);
+        rt.set_session_log(Some(SessionLog::new(&log_path)));
+        rt.index(repo, Some("**/*.rs")).unwrap();
+
+        let result = rt.query(repo, QueryParams::symbol("logged_fn")).unwrap();
+        let handle_ids: Vec<String> = result.handles.iter().map(|h| h.id.to_string()).collect();
+        rt.expand(repo, &handle_ids, false).unwrap();
+
+        let records = SessionLog::read(&log_path).unwrap();
+        assert_eq!(records.len(), 2);
+        match &records[0] {
+            SessionRecord::Query {
+                params,
+                handle_ids: logged_ids,
+                ..
+            } => {
+                assert_eq!(params.symbol.as_deref(), Some("logged_fn"));
+                assert_eq!(logged_ids, &handle_ids);
+            }
+            other => panic!("expected query record, got {other:?}"),
+        }
+        match &records[1] {
+            SessionRecord::Expand {
+                handle_ids: expanded,
+                failed_ids,
+                ..
+            } => {
+                assert_eq!(expanded, &handle_ids);
+                assert!(failed_ids.is_empty());
+            }
+            other => panic!("expected expand record, got {other:?}"),
+        }
+    }
+
     #[test]
     fn test_standalone_expand_after_query() {
-        let repo = temp_repo();
+        let fixture = temp_repo();
+        let repo = fixture.path();
         let src_dir = repo.join("src");
         std::fs::create_dir_all(&src_dir).unwrap();
         std::fs::write(
@@ -526,16 +1467,198 @@ mod tests {
         .unwrap();
 
         let mut rt = ClientRuntime::new(None, None);
-        rt.index(&repo, Some("**/*.rs")).unwrap();
+        rt.index(repo, Some("**/*.rs")).unwrap();
 
         let params = QueryParams::symbol("Config");
-        let result = rt.query(&repo, params).unwrap();
+        let result = rt.query(repo, params).unwrap();
         assert!(!result.handles.is_empty());
 
         // Expand the first handle
         let handle_ids: Vec<String> = result.handles.iter().map(|h| h.id.to_string()).collect();
-        let outcome = rt.expand(&repo, &handle_ids).unwrap();
+        let outcome = rt.expand(repo, &handle_ids, false).unwrap();
         assert!(!outcome.contents.is_empty());
         assert!(outcome.contents[0].1.contains("Config"));
     }
+
+    #[test]
+    fn test_compare_expand_returns_diff_then_unchanged() {
+        let fixture = temp_repo();
+        let repo = fixture.path();
+        let src_dir = repo.join("src");
+        std::fs::create_dir_all(&src_dir).unwrap();
+        let body: String = (0..12).map(|i| format!("    let v{i} = {i};\n")).collect();
+        let file = src_dir.join("lib.rs");
+        std::fs::write(&file, format!("pub fn tracked() {{\n{body}}}\n")).unwrap();
+
+        let mut rt = ClientRuntime::new(None, None);
+        rt.index(repo, Some("**/*.rs")).unwrap();
+        let result = rt.query(repo, QueryParams::symbol("tracked")).unwrap();
+        let ids = vec![result.handles[0].id.to_string()];
+
+        // No baseline yet: full content
+        let first = rt.expand(repo, &ids, true).unwrap();
+        assert_eq!(first.comparisons[0].delta, ExpandDelta::Full);
+        let first_hash = first.comparisons[0].content_hash.clone();
+
+        // Same-length edit keeps the handle id stable
+        std::fs::write(
+            &file,
+            format!(
+                "pub fn tracked() {{\n{}}}\n",
+                body.replace("v5 = 5", "v5 = 7")
+            ),
+        )
+        .unwrap();
+        // Later mtime so the reindex doesn't skip the file as unchanged
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+            .unwrap();
+        rt.index(repo, Some("**/*.rs")).unwrap();
+        let second = rt.expand(repo, &ids, true).unwrap();
+        assert_eq!(
+            second.comparisons[0].baseline_hash.as_deref(),
+            Some(first_hash.as_str())
+        );
+        match &second.comparisons[0].delta {
+            ExpandDelta::Diff { diff } => {
+                assert!(diff.contains("-    let v5 = 5;") && diff.contains("+    let v5 = 7;"));
+            }
+            other => panic!("expected diff, got {other:?}"),
+        }
+
+        let third = rt.expand(repo, &ids, true).unwrap();
+        assert_eq!(third.comparisons[0].delta, ExpandDelta::Unchanged);
+
+        // An explicit baseline overrides the remembered one
+        let baselines = HashMap::from([(ids[0].clone(), first_hash)]);
+        let against = rt.expand_against(repo, &ids, &baselines).unwrap();
+        assert!(matches!(
+            against.comparisons[0].delta,
+            ExpandDelta::Diff { .. }
+        ));
+        assert!(rt.expand(repo, &ids, false).unwrap().comparisons.is_empty());
+    }
+
+    #[test]
+    fn test_expand_lines_cuts_without_touching_compare_baselines() {
+        let fixture = temp_repo();
+        let repo = fixture.path();
+        let body: String = (0..12).map(|i| format!("    let v{i} = {i};\n")).collect();
+        std::fs::write(
+            repo.join("lib.rs"),
+            format!("pub fn tracked() {{\n{body}}}\n"),
+        )
+        .unwrap();
+
+        let mut rt = ClientRuntime::new(None, None);
+        rt.index(repo, Some("**/*.rs")).unwrap();
+        let result = rt.query(repo, QueryParams::symbol("tracked")).unwrap();
+        let id = result.handles[0].id.to_string();
+        let ids = vec![id.clone()];
+
+        let windows = HashMap::from([(id.clone(), LineWindow { start: 3, end: 4 })]);
+        let cut = rt.expand_lines(repo, &ids, &windows).unwrap();
+        assert_eq!(cut.contents[0].1, "    let v1 = 1;\n    let v2 = 2;\n");
+        assert!(cut.notes.is_empty());
+
+        let outside = HashMap::from([(id.clone(), LineWindow { start: 40, end: 50 })]);
+        let whole = rt.expand_lines(repo, &ids, &outside).unwrap();
+        assert!(whole.contents[0].1.starts_with("pub fn tracked()"));
+        assert!(whole.notes[&id].contains("40-50"), "{:?}", whole.notes);
+
+        // Neither expansion became a compare baseline
+        let first = rt.expand(repo, &ids, true).unwrap();
+        assert_eq!(first.comparisons[0].delta, ExpandDelta::Full);
+    }
+
+    #[test]
+    fn test_compare_expand_refreshes_stale_handles_first() {
+        let fixture = temp_repo();
+        let repo = fixture.path();
+        let body: String = (0..12).map(|i| format!("    let v{i} = {i};\n")).collect();
+        let file = repo.join("lib.rs");
+        std::fs::write(&file, format!("pub fn tracked() {{\n{body}}}\n")).unwrap();
+
+        let mut rt = ClientRuntime::new(None, None);
+        rt.index(repo, Some("**/*.rs")).unwrap();
+        let result = rt.query(repo, QueryParams::symbol("tracked")).unwrap();
+        let ids = vec![result.handles[0].id.to_string()];
+        rt.expand(repo, &ids, true).unwrap();
+
+        // Edited but not reindexed: a plain expand can't serve it
+        let edited = body.replace("v5 = 5", "v5 = 7");
+        std::fs::write(&file, format!("pub fn tracked() {{\n{edited}}}\n")).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+            .unwrap();
+        let validations = rt.validate_handles(repo, &ids).unwrap();
+        assert_eq!(validations[0].status, canopy_core::HandleStatus::Stale);
+
+        let compared = rt.expand(repo, &ids, true).unwrap();
+        assert!(matches!(
+            compared.comparisons[0].delta,
+            ExpandDelta::Diff { .. }
+        ));
+        let validations = rt.validate_handles(repo, &ids).unwrap();
+        assert_eq!(validations[0].status, canopy_core::HandleStatus::Fresh);
+    }
+
+    #[test]
+    fn test_predictive_indexing_dedupes_overlapping_globs() {
+        let fixture = temp_repo();
+        let repo = fixture.path();
+        let filler = repo.join("vendor");
+        std::fs::create_dir_all(&filler).unwrap();
+        for i in 0..=LARGE_REPO_THRESHOLD {
+            std::fs::write(filler.join(format!("f{i}.rs")), "fn filler() {}\n").unwrap();
+        }
+        // Matched by both the auth and the session globs
+        let nested = repo.join("src/auth/session");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(nested.join("login.rs"), "fn login() {}\n").unwrap();
+        std::fs::write(repo.join("src/auth/token.rs"), "fn token() {}\n").unwrap();
+
+        let mut rt = ClientRuntime::new(None, None);
+        let mut index = RepoIndex::open(repo).unwrap();
+        rt.predictive_index_for_query(repo, &mut index, "auth session")
+            .unwrap();
+
+        let pending = &rt.feedback.pending_predictive[&canonical_path(repo)];
+        let mut files: Vec<&str> = pending.file_to_glob.keys().map(String::as_str).collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec!["src/auth/session/login.rs", "src/auth/token.rs"]
+        );
+        assert_eq!(pending.files_indexed, 2);
+        assert_eq!(index.status().unwrap().files_indexed, 2);
+
+        let first_matching = |path: &str| {
+            pending
+                .predicted_globs
+                .iter()
+                .find(|glob| {
+                    index
+                        .walk_files(glob)
+                        .unwrap()
+                        .iter()
+                        .any(|f| f.ends_with(path))
+                })
+                .unwrap()
+                .clone()
+        };
+        let login = &pending.file_to_glob["src/auth/session/login.rs"];
+        assert!(login.contains("auth"), "{login}");
+        assert_eq!(*login, first_matching("src/auth/session/login.rs"));
+        assert_eq!(
+            pending.file_to_glob["src/auth/token.rs"],
+            first_matching("src/auth/token.rs")
+        );
+    }
 }
@@ -0,0 +1,226 @@
+//! Pin resolution against the local index, and following pins on expand.
+
+use crate::pins::{Pin, PinSet, PinStatus};
+use crate::session_log::now_ts;
+use canopy_core::{CanopyError, HandleId, IndexedNode, LineWindow, UnresolvedPin};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use super::expand::Expansion;
+use super::{canonical_path, lock_index, ClientRuntime};
+
+/// Requested handle ids with pins swapped for their nodes' current ids.
+#[derive(Default)]
+pub(super) struct FollowedPins {
+    /// Ids to expand, in request order
+    pub(super) targets: Vec<String>,
+    /// Expanded id -> the pinned id it was requested as
+    pub(super) requested_as: HashMap<String, String>,
+    /// Requested ids that are pinned and still resolve
+    pub(super) pinned: HashSet<String>,
+    /// Pins whose node is gone; not in `targets`
+    pub(super) unresolved: Vec<UnresolvedPin>,
+}
+
+impl FollowedPins {
+    /// The id `expanded_id` was requested as.
+    pub(super) fn requested_id<'a>(&'a self, expanded_id: &'a str) -> &'a str {
+        self.requested_as
+            .get(expanded_id)
+            .map_or(expanded_id, String::as_str)
+    }
+
+    /// `windows`, keyed by requested id, rekeyed by the id each expands as.
+    pub(super) fn target_windows(
+        &self,
+        windows: &HashMap<String, LineWindow>,
+    ) -> HashMap<String, LineWindow> {
+        if windows.is_empty() {
+            return HashMap::new();
+        }
+        let mut expanded_as: HashMap<String, LineWindow> = windows.clone();
+        for (expanded, requested) in &self.requested_as {
+            if let Some(window) = windows.get(requested) {
+                expanded_as.insert(expanded.clone(), *window);
+            }
+        }
+        expanded_as
+    }
+
+    fn merge(&mut self, other: FollowedPins) {
+        self.requested_as.extend(other.requested_as);
+        self.pinned.extend(other.pinned);
+        self.unresolved.extend(other.unresolved);
+    }
+}
+
+impl ClientRuntime {
+    /// Pin handles so they keep expanding to the same node after reindexes
+    /// and service generation bumps.
+    ///
+    /// Pins are resolved against the local index; in service mode the
+    /// pinned files are indexed locally as needed. Returns the new pins.
+    pub fn pin_handles(
+        &mut self,
+        repo_path: &Path,
+        handle_ids: &[String],
+    ) -> canopy_core::Result<Vec<Pin>> {
+        let canonical = canonical_path(repo_path);
+        let mut pins = PinSet::load(repo_path)?;
+        let mut pinned = Vec::new();
+
+        for id in handle_ids {
+            let id = id.parse::<HandleId>()?.to_string();
+            let file_path = match self.tracker.get(&canonical, &id) {
+                Some(prov) => prov.file_path.clone(),
+                None => self
+                    .expand_local_details(repo_path, std::slice::from_ref(&id))
+                    .ok()
+                    .and_then(|mut details| details.pop())
+                    .map(|d| d.file_path)
+                    .ok_or_else(|| CanopyError::HandleNotFound(id.clone()))?,
+            };
+
+            let index = self.open_local_index(repo_path)?;
+            let mut index = lock_index(&index);
+            let mut nodes = index.file_nodes(&file_path)?;
+            if !nodes.iter().any(|n| n.handle.id.to_string() == id) {
+                // A service handle's file may not be in the local index yet
+                index.index(&file_path)?;
+                nodes = index.file_nodes(&file_path)?;
+            }
+            let node = nodes
+                .iter()
+                .find(|n| n.handle.id.to_string() == id)
+                .ok_or_else(|| {
+                    CanopyError::HandleNotFound(format!(
+                        "{id} (not in the local index of {file_path}; query again and pin the new handle)"
+                    ))
+                })?;
+            let pin = Pin::new(node, now_ts());
+            pins.insert(pin.clone());
+            pinned.push(pin);
+        }
+
+        pins.save(repo_path)?;
+        Ok(pinned)
+    }
+
+    /// Pins of `repo_path`, each re-resolved so its status is current.
+    pub fn list_pins(&mut self, repo_path: &Path) -> canopy_core::Result<Vec<Pin>> {
+        self.refresh_pins(repo_path)
+    }
+
+    /// Re-resolve every pin of `repo_path` to the node it now refers to.
+    ///
+    /// Runs after local reindexes and detected generation bumps, and when a
+    /// pinned handle fails to expand.
+    pub fn refresh_pins(&mut self, repo_path: &Path) -> canopy_core::Result<Vec<Pin>> {
+        let mut pins = PinSet::load(repo_path)?;
+        if pins.is_empty() {
+            return Ok(Vec::new());
+        }
+        let service_mode = self.service.is_some();
+        let index = self.open_local_index(repo_path)?;
+        let mut index = lock_index(&index);
+
+        let mut files: HashMap<String, (bool, Vec<IndexedNode>)> = HashMap::new();
+        for pin in pins.pins_mut() {
+            if !files.contains_key(&pin.file_path) {
+                let exists = repo_path.join(&pin.file_path).is_file();
+                // Nothing else keeps a service-mode local index current
+                if service_mode && exists {
+                    index.index(&pin.file_path)?;
+                }
+                let nodes = if exists {
+                    index.file_nodes(&pin.file_path)?
+                } else {
+                    Vec::new()
+                };
+                files.insert(pin.file_path.clone(), (exists, nodes));
+            }
+            let (exists, nodes) = &files[&pin.file_path];
+            let found = match pin.best_match(nodes) {
+                Some(node) => Ok(node),
+                None => Err(pin.missing_reason(*exists, nodes)),
+            };
+            pin.resolve(found);
+        }
+
+        pins.save(repo_path)?;
+        Ok(pins.pins().to_vec())
+    }
+
+    /// [`refresh_pins`](Self::refresh_pins) for hooks that must not fail.
+    pub(super) fn refresh_pins_quietly(&mut self, repo_path: &Path) {
+        if let Err(err) = self.refresh_pins(repo_path) {
+            eprintln!("[canopy] pins: failed to re-resolve pins: {}", err);
+        }
+    }
+
+    /// Swap pinned ids in `handle_ids` for their nodes' current ids.
+    pub(super) fn follow_pins(&self, repo_path: &Path, handle_ids: &[String]) -> FollowedPins {
+        let pins = match PinSet::load(repo_path) {
+            Ok(pins) => pins,
+            Err(err) => {
+                eprintln!("[canopy] pins: ignoring unreadable pin file: {}", err);
+                PinSet::default()
+            }
+        };
+        let mut followed = FollowedPins::default();
+        for id in handle_ids {
+            let Some(pin) = pins.get(id) else {
+                followed.targets.push(id.clone());
+                continue;
+            };
+            match (&pin.status, &pin.current_id) {
+                (PinStatus::Missing, _) | (_, None) => followed.unresolved.push(UnresolvedPin {
+                    handle_id: id.clone(),
+                    file_path: pin.file_path.clone(),
+                    reason: pin
+                        .reason
+                        .clone()
+                        .unwrap_or_else(|| format!("{} no longer resolves", id)),
+                }),
+                (_, Some(current)) => {
+                    if current != id {
+                        followed.requested_as.insert(current.clone(), id.clone());
+                    }
+                    followed.pinned.insert(id.clone());
+                    followed.targets.push(current.clone());
+                }
+            }
+        }
+        followed
+    }
+
+    /// Re-resolve pins and retry pinned handles that failed to expand or
+    /// were missing, in case their node moved (or came back) since the pins
+    /// were last resolved.
+    pub(super) fn retry_moved_pins(
+        &mut self,
+        repo_path: &Path,
+        followed: &mut FollowedPins,
+        windows: &HashMap<String, LineWindow>,
+        out: &mut Expansion,
+    ) {
+        let retry: Vec<String> = out
+            .failed_ids
+            .iter()
+            .map(|id| followed.requested_id(id).to_string())
+            .filter(|id| followed.pinned.contains(id))
+            .chain(followed.unresolved.iter().map(|p| p.handle_id.clone()))
+            .collect();
+        if retry.is_empty() || self.refresh_pins(repo_path).is_err() {
+            return;
+        }
+        out.failed_ids
+            .retain(|id| !retry.iter().any(|r| r == followed.requested_id(id)));
+        followed.unresolved.clear();
+        let again = self.follow_pins(repo_path, &retry);
+        let targets = again.targets.clone();
+        followed.merge(again);
+        let target_windows = followed.target_windows(windows);
+        self.expand_unknown(repo_path, targets, false, &target_windows, out);
+    }
+}
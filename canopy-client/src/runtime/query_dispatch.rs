@@ -3,10 +3,16 @@
 use crate::dirty;
 use crate::merge;
 use crate::service_client::{is_error_code, ServiceClient};
-use canopy_core::{HandleSource, QueryParams, QueryResult};
+use canopy_core::{HandleSource, PathStyle, QueryParams, QueryResult};
 use std::path::Path;
 
-use super::{ClientRuntime, ENSURE_READY_TIMEOUT};
+use super::{lock_index, ClientRuntime, GenerationChange, ENSURE_READY_TIMEOUT};
+
+/// Note attached to service results when no local index exists to overlay dirty files.
+const EMPTY_LOCAL_INDEX_NOTE: &str = "Local index is empty; dirty files were not re-queried locally and their service handles may be stale. Run 'canopy index' to enable the dirty-file overlay.";
+
+/// Note attached to service count/exists results when files are dirty.
+const DIRTY_COUNT_NOTE: &str = "uncommitted file(s) were not re-counted locally; the service answered from its indexed commit.";
 
 impl ClientRuntime {
     pub(super) fn require_service(&self) -> canopy_core::Result<&ServiceClient> {
@@ -23,7 +29,7 @@ impl ClientRuntime {
         let service = self.service.as_mut().unwrap();
         let repo_id = service.resolve_ready(repo_path, ENSURE_READY_TIMEOUT)?;
 
-        match service.query(&repo_id, params.clone()) {
+        match service.query(&repo_id, params.for_source(&HandleSource::Service)) {
             Ok(service_result) => {
                 self.merge_with_dirty(repo_path, &repo_id, service_result, Some(params))
             }
@@ -44,7 +50,7 @@ impl ClientRuntime {
         params: QueryParams,
     ) -> canopy_core::Result<QueryResult> {
         let service = self.service.as_ref().unwrap();
-        let service_result = service.query(repo_id, params.clone())?;
+        let service_result = service.query(repo_id, params.for_source(&HandleSource::Service))?;
         self.merge_with_dirty(repo_path, repo_id, service_result, Some(params))
     }
 
@@ -52,30 +58,72 @@ impl ClientRuntime {
         &mut self,
         repo_path: &Path,
         repo_id: &str,
-        service_result: QueryResult,
+        mut service_result: QueryResult,
         local_params: Option<QueryParams>,
     ) -> canopy_core::Result<QueryResult> {
+        // Count and exists results have no handles to overlay dirty files on
+        if !service_result.mode.is_handles() {
+            let dirty_files = dirty::detect_dirty(repo_path)?.files.len();
+            if dirty_files > 0 {
+                let note = format!("{dirty_files} {DIRTY_COUNT_NOTE}");
+                service_result.expand_note = Some(match service_result.expand_note.take() {
+                    Some(existing) => format!("{existing} {note}"),
+                    None => note,
+                });
+            }
+            return Ok(service_result);
+        }
+        // Commits come from the service's git history; dirty files add none
+        if service_result.commits.is_some() {
+            return Ok(service_result);
+        }
+
+        // Per-source limits were applied to each query; this caps the merge
+        let limit = local_params.as_ref().and_then(|p| p.limit);
+        let merge_strategy = local_params
+            .as_ref()
+            .and_then(|p| p.merge_strategy)
+            .unwrap_or_default();
+
         // Detect dirty files
         let dirty_state = dirty::detect_dirty(repo_path)?;
-        let dirty_paths = dirty_state.dirty_paths();
+        // Service paths and git's can differ in case on a case-insensitive checkout
+        let path_style = if dirty_state.is_clean() {
+            PathStyle::host()
+        } else {
+            lock_index(&self.open_local_index(repo_path)?).path_style()
+        };
+        let dirty_paths = dirty_state.dirty_paths(path_style);
 
         // Rebuild local index for dirty files if needed
         if !dirty_state.is_clean() && dirty::needs_rebuild(&dirty_state, repo_path) {
-            let mut index = self.open_local_index(repo_path)?;
-            dirty::rebuild_local_index(&mut index, &dirty_state, repo_path)?;
-            dirty::save_fingerprint(&dirty_state, repo_path)?;
+            let index = self.open_local_index(repo_path)?;
+            self.dirty
+                .refresh(&mut lock_index(&index), &dirty_state, repo_path)?;
         }
 
-        // Query local index if there are dirty files
+        // Query local index if there are dirty files. An empty local index (e.g. a
+        // fresh clone that was never indexed) cannot override anything, so the
+        // service results are returned as-is with a note instead.
+        let mut empty_local_index = false;
         let local_result = if !dirty_state.is_clean() {
             if let Some(params) = local_params {
                 let index = self.open_local_index(repo_path)?;
-                let query = params.to_query()?;
-                let mut options = params.to_options();
-                options.node_type_priors = self.load_node_type_priors(repo_path);
-                Some(canopy_core::query::execute_query_with_options(
-                    &query, &index, options,
-                )?)
+                if lock_index(&index).status()?.files_indexed == 0 {
+                    empty_local_index = true;
+                    None
+                } else {
+                    let params = params.for_source(&HandleSource::Local);
+                    let mut options = params.to_options();
+                    if options.node_type_priors.is_none() {
+                        options.node_type_priors = self.load_node_type_priors(repo_path);
+                    }
+                    Some(canopy_core::query::execute_params(
+                        &params,
+                        &lock_index(&index),
+                        options,
+                    )?)
+                }
             } else {
                 None
             }
@@ -96,28 +144,73 @@ impl ClientRuntime {
         // Update generation tracking
         if let Some(gen) = gen {
             let old_gen = self.cache.repo_generations.insert(repo_id.to_string(), gen);
-            if old_gen.is_some() && old_gen != Some(gen) {
+            if let Some(old_gen) = old_gen.filter(|old| *old != gen) {
                 self.tracker.invalidate_repo(repo_id);
+                self.cache.generation_changes.push(GenerationChange {
+                    repo_id: repo_id.to_string(),
+                    repo_root: repo_path.to_path_buf(),
+                    old_generation: old_gen,
+                    new_generation: gen,
+                });
             }
         }
 
         let result = if let Some(local_result) = local_result {
-            // Record local handle provenance
-            self.record_provenance_for_result(
+            let merged = merge::merge_results(
+                local_result,
+                service_result,
+                &dirty_paths,
+                &dirty_state.deleted_paths(path_style),
+                limit,
+                merge_strategy,
+            );
+            // Record provenance only for local handles that survived the merge:
+            // the local index also holds clean files, whose handles must keep
+            // routing to the service
+            self.record_provenance_for_handles(
                 repo_path,
-                &local_result,
+                merged
+                    .handles
+                    .iter()
+                    .filter(|h| h.source == HandleSource::Local),
                 HandleSource::Local,
                 None,
                 None,
             );
-            merge::merge_results(local_result, service_result, &dirty_paths)
+            merged
+        } else if empty_local_index {
+            let mut result = merge::service_only(service_result, limit);
+            for handle in &mut result.handles {
+                handle.possibly_stale |= dirty_paths.contains(&handle.file_path);
+            }
+            let note = EMPTY_LOCAL_INDEX_NOTE.to_string();
+            result.expand_note = Some(match result.expand_note.take() {
+                Some(existing) => format!("{existing} {note}"),
+                None => note,
+            });
+            result
         } else {
-            service_result
+            merge::service_only(service_result, limit)
         };
 
         Ok(result)
     }
 
+    /// Apply the registered reranker to a merged service result.
+    pub(super) fn rerank_service_result(&self, query_text: &str, result: &mut QueryResult) {
+        let Some(reranker) = self.reranker.as_deref() else {
+            return;
+        };
+        if let Some(warning) =
+            canopy_core::apply_reranker(reranker, query_text, &mut result.handles)
+        {
+            result.expand_note = Some(match result.expand_note.take() {
+                Some(existing) => format!("{existing} {warning}"),
+                None => warning,
+            });
+        }
+    }
+
     pub(super) fn query_standalone(
         &mut self,
         repo_path: &Path,
@@ -128,12 +221,250 @@ impl ClientRuntime {
         // - MCP calls predictive_index_for_query() before runtime.query()
         // - QueryOnly and Predictive policies just query what's already indexed
         let index = self.open_local_index(repo_path)?;
-        let query = params.to_query()?;
         let mut options = params.to_options();
-        options.node_type_priors = self.load_node_type_priors(repo_path);
-        let result = canopy_core::query::execute_query_with_options(&query, &index, options)?;
+        // Priors passed with the query replace the learned ones
+        if options.node_type_priors.is_none() {
+            options.node_type_priors = self.load_node_type_priors(repo_path);
+        }
+        options.reranker = self.reranker.clone();
+        let result = canopy_core::query::execute_params(&params, &lock_index(&index), options)?;
 
         self.record_provenance_for_result(repo_path, &result, HandleSource::Local, None, None);
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canopy_core::{Handle, MatchMode, NodeType, RepoIndex, Span};
+    use std::process::Command;
+
+    fn git(root: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=canopy", "-c", "user.email=canopy@test"])
+            .args(args)
+            .current_dir(root)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    }
+
+    /// A committed checkout with no `.canopy/`, as after a fresh clone.
+    fn fresh_clone() -> tempfile::TempDir {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join("src/a.rs"), "fn needle_a() {}\n").unwrap();
+        std::fs::write(root.join("src/b.rs"), "fn needle_b() {}\n").unwrap();
+        std::fs::write(root.join("src/c.rs"), "fn needle_c() {}\n").unwrap();
+        git(root, &["init", "-q"]);
+        git(root, &["add", "."]);
+        git(root, &["commit", "-q", "-m", "init"]);
+        dir
+    }
+
+    fn service_handle(file: &str) -> Handle {
+        let mut handle = Handle::new(
+            file.to_string(),
+            NodeType::Function,
+            Span { start: 0, end: 16 },
+            (1, 1),
+            5,
+            "fn needle() {}".to_string(),
+        );
+        handle.source = HandleSource::Service;
+        handle
+    }
+
+    fn service_result() -> QueryResult {
+        let handles = vec![
+            service_handle("src/a.rs"),
+            service_handle("src/b.rs"),
+            service_handle("src/c.rs"),
+        ];
+        QueryResult {
+            total_matches: handles.len(),
+            handles,
+            ..QueryResult::default()
+        }
+    }
+
+    #[test]
+    fn fresh_clone_keeps_uncovered_dirty_service_handles_as_possibly_stale() {
+        let dir = fresh_clone();
+        let root = dir.path();
+        // a.rs still matches locally; c.rs no longer does
+        std::fs::write(root.join("src/a.rs"), "fn needle_a() { changed() }\n").unwrap();
+        std::fs::write(root.join("src/c.rs"), "fn renamed_c() {}\n").unwrap();
+
+        let mut rt = ClientRuntime::new(None, None);
+        let result = rt
+            .merge_with_dirty(
+                root,
+                "repo-1",
+                service_result(),
+                Some(
+                    QueryParams::patterns(vec!["needle_a".into(), "needle_c".into()])
+                        .with_match_mode(MatchMode::Any),
+                ),
+            )
+            .unwrap();
+
+        let by_file = |file: &str| -> Vec<&Handle> {
+            result
+                .handles
+                .iter()
+                .filter(|h| h.file_path == file)
+                .collect()
+        };
+        // Local covers a.rs: its service handle is replaced by the local one
+        let a = by_file("src/a.rs");
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].source, HandleSource::Local);
+        assert_eq!(result.suppressed_service_handles, 1);
+        // c.rs is dirty but has no local match: the service handle is kept, flagged
+        let c = by_file("src/c.rs");
+        assert_eq!(c.len(), 1);
+        assert!(c[0].possibly_stale);
+        // b.rs is clean
+        assert!(!by_file("src/b.rs")[0].possibly_stale);
+    }
+
+    #[test]
+    fn per_source_limits_cap_each_side_before_merge() {
+        let dir = fresh_clone();
+        let root = dir.path();
+        std::fs::write(
+            root.join("src/a.rs"),
+            "fn needle_a() {}\nfn calls_a() { needle_a() }\n",
+        )
+        .unwrap();
+
+        let mut rt = ClientRuntime::new(None, None);
+        let result = rt
+            .merge_with_dirty(
+                root,
+                "repo-1",
+                service_result(),
+                Some(
+                    QueryParams::pattern("needle_a")
+                        .with_limit(2)
+                        .with_local_limit(1),
+                ),
+            )
+            .unwrap();
+
+        // One slot for the dirty-local override, the rest filled by the service
+        let sources = result.sources.clone().unwrap();
+        assert_eq!((sources.local, sources.service), (1, 1));
+        assert!(sources.local_truncated, "local query matched two functions");
+        assert!(sources.service_truncated, "c.rs cut by the overall limit");
+        assert!(result.truncated);
+        assert_eq!(result.handles[0].source, HandleSource::Local);
+        assert_eq!(result.handles[0].file_path, "src/a.rs");
+        assert_eq!(result.handles[1].file_path, "src/b.rs");
+    }
+
+    #[test]
+    fn empty_local_index_returns_service_results_with_note() {
+        let dir = fresh_clone();
+        let root = dir.path();
+        std::fs::write(root.join("src/a.rs"), "fn needle_a() { changed() }\n").unwrap();
+
+        // Initialized but never indexed, with the dirty rebuild already recorded
+        RepoIndex::init(root).unwrap();
+        let dirty_state = dirty::detect_dirty(root).unwrap();
+        dirty::save_fingerprint(&dirty_state, root).unwrap();
+
+        let mut rt = ClientRuntime::new(None, None);
+        let result = rt
+            .merge_with_dirty(
+                root,
+                "repo-1",
+                service_result(),
+                Some(QueryParams::pattern("needle_a")),
+            )
+            .unwrap();
+
+        assert_eq!(result.handles.len(), 3);
+        assert_eq!(result.suppressed_service_handles, 0);
+        assert!(result
+            .handles
+            .iter()
+            .all(|h| h.source == HandleSource::Service));
+        let dirty: Vec<_> = result.handles.iter().filter(|h| h.possibly_stale).collect();
+        assert_eq!(dirty.len(), 1);
+        assert_eq!(dirty[0].file_path, "src/a.rs");
+        assert!(result
+            .expand_note
+            .as_deref()
+            .is_some_and(|note| note.contains("Local index is empty")));
+    }
+
+    /// Rewrite `path` with a later mtime, so the dirty fingerprint changes
+    /// even within the same second.
+    fn edit(path: &Path, contents: &str, offset_secs: u64) {
+        std::fs::write(path, contents).unwrap();
+        let mtime = std::time::SystemTime::now() + std::time::Duration::from_secs(offset_secs);
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+    }
+
+    #[test]
+    fn rapid_queries_debounce_dirty_rebuilds() {
+        let dir = fresh_clone();
+        let root = dir.path();
+        RepoIndex::init(root).unwrap();
+        RepoIndex::open(root).unwrap().index("**/*.rs").unwrap();
+        // Commit the .gitignore init adds so only the edits below are dirty
+        git(root, &["add", "."]);
+        git(root, &["commit", "-q", "-m", "canopy"]);
+
+        let mut rt = ClientRuntime::new(None, None);
+        rt.set_dirty_debounce(std::time::Duration::from_secs(3600));
+        let query = |rt: &mut ClientRuntime| {
+            rt.merge_with_dirty(
+                root,
+                "repo-1",
+                service_result(),
+                Some(QueryParams::pattern("needle")),
+            )
+            .unwrap()
+        };
+
+        edit(&root.join("src/a.rs"), "fn needle_a() { one() }\n", 10);
+        query(&mut rt);
+        query(&mut rt);
+        edit(&root.join("src/a.rs"), "fn needle_a() { two() }\n", 20);
+        let result = query(&mut rt);
+        let stats = rt.dirty_rebuild_stats();
+        assert_eq!((stats.rebuilds, stats.debounced), (1, 1));
+        // Served from the index as of the first rebuild
+        let local: Vec<_> = result
+            .handles
+            .iter()
+            .filter(|h| h.source == HandleSource::Local)
+            .collect();
+        assert_eq!(local.len(), 1);
+        assert!(local[0].preview.contains("one()"));
+
+        edit(&root.join("src/b.rs"), "fn needle_b() { three() }\n", 30);
+        query(&mut rt);
+        let stats = rt.dirty_rebuild_stats();
+        assert_eq!((stats.rebuilds, stats.debounced), (2, 1));
+        assert_eq!(stats.files_reindexed, 3);
+
+        // Without a window every change rebuilds
+        rt.set_dirty_debounce(std::time::Duration::ZERO);
+        edit(&root.join("src/b.rs"), "fn needle_b() { four() }\n", 40);
+        query(&mut rt);
+        assert_eq!(rt.dirty_rebuild_stats().rebuilds, 3);
+        assert_eq!(rt.dirty_rebuild_stats().files_reindexed, 4);
+    }
+}
@@ -0,0 +1,258 @@
+//! Targeted refresh: reindex just the files behind given handles.
+
+use crate::pins::Pin;
+use crate::provenance::HandleProvenance;
+use canopy_core::{HandleSource, HandleStatus, IndexedNode};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use super::{canonical_path, lock_index, ClientRuntime, IndexResult, ENSURE_READY_TIMEOUT};
+
+/// What [`ClientRuntime::refresh_handles`] did for one handle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefreshStatus {
+    /// The file changed and was reindexed
+    Refreshed,
+    /// The file hasn't changed since indexing; the handle is still good
+    Unchanged,
+    /// The file is gone, and its rows with it
+    FileMissing,
+    /// No file is known for the handle, or its node is gone after the reindex
+    NotFound,
+}
+
+/// One handle's outcome, keyed by the id it was asked for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HandleRefresh {
+    pub status: RefreshStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_path: Option<String>,
+    /// The node's id after the reindex, when it moved to a new one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_handle_id: Option<String>,
+}
+
+/// Result of [`ClientRuntime::refresh_handles`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RefreshReport {
+    pub handles: BTreeMap<String, HandleRefresh>,
+    /// Distinct files reindexed or removed
+    pub files_reindexed: usize,
+    /// Service generation serving the refreshed files; absent in standalone mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation: Option<u64>,
+}
+
+impl HandleRefresh {
+    fn new(status: RefreshStatus, file_path: Option<String>) -> Self {
+        Self {
+            status,
+            file_path,
+            new_handle_id: None,
+        }
+    }
+}
+
+impl ClientRuntime {
+    /// Reindex exactly the files behind `handle_ids`, and say for each
+    /// handle what became of it.
+    ///
+    /// Files come from validation (the service's in service mode), falling
+    /// back to the handle's provenance; files still matching their index are
+    /// left alone. A node whose id changed is found again in the file's new
+    /// nodes by name, type and parent, like a pin. In service mode the
+    /// service reindexes the files through `/reindex` with `paths`, and the
+    /// local index is refreshed alongside to match nodes, so new ids are only
+    /// found for nodes the local index held before.
+    pub fn refresh_handles(
+        &mut self,
+        repo_path: &Path,
+        handle_ids: &[String],
+    ) -> canopy_core::Result<RefreshReport> {
+        let canonical = canonical_path(repo_path);
+        let validations = self.validate_handles(repo_path, handle_ids)?;
+        let index = self.open_local_index(repo_path)?;
+
+        let mut report = RefreshReport::default();
+        // (requested id, file, its node before the reindex)
+        let mut targets: Vec<(String, String, Option<IndexedNode>)> = Vec::new();
+        for validation in validations {
+            let id = validation.handle_id;
+            let file_path = validation.file_path.or_else(|| {
+                self.tracker
+                    .get(&canonical, &id)
+                    .map(|p| p.file_path.clone())
+            });
+            let Some(file_path) = file_path else {
+                report
+                    .handles
+                    .insert(id, HandleRefresh::new(RefreshStatus::NotFound, None));
+                continue;
+            };
+            if validation.status == HandleStatus::Fresh {
+                let unchanged = HandleRefresh::new(RefreshStatus::Unchanged, Some(file_path));
+                report.handles.insert(id, unchanged);
+                continue;
+            }
+            let old = lock_index(&index)
+                .file_nodes(&file_path)?
+                .into_iter()
+                .find(|node| node.handle.id.to_string() == id);
+            targets.push((id, file_path, old));
+        }
+        if targets.is_empty() {
+            return Ok(report);
+        }
+
+        let files: BTreeSet<String> = targets.iter().map(|(_, path, _)| path.clone()).collect();
+        let paths: Vec<PathBuf> = files.iter().map(|path| repo_path.join(path)).collect();
+        report.files_reindexed = files.len();
+        let service_repo = match self.index_paths(repo_path, &paths)? {
+            IndexResult::Local(_) => None,
+            IndexResult::Service(_) => {
+                let service = self
+                    .service
+                    .as_mut()
+                    .ok_or(canopy_core::CanopyError::NoServiceConfigured)?;
+                let repo_id = service.resolve_ready(repo_path, ENSURE_READY_TIMEOUT)?;
+                report.generation = service
+                    .list_repos()?
+                    .into_iter()
+                    .find(|repo| repo.repo_id == repo_id)
+                    .map(|repo| repo.generation.value());
+                // The service may have written the same database, behind the
+                // back of the open instance's symbol cache
+                self.indexes.evict(repo_path);
+                lock_index(&self.open_local_index(repo_path)?).index_paths(&paths)?;
+                Some(repo_id)
+            }
+        };
+        let index = self.open_local_index(repo_path)?;
+
+        let mut nodes: HashMap<String, Vec<IndexedNode>> = HashMap::new();
+        for file in files {
+            let current = lock_index(&index).file_nodes(&file)?;
+            nodes.insert(file, current);
+        }
+        for (id, file_path, old) in targets {
+            if !repo_path.join(&file_path).is_file() {
+                let missing = HandleRefresh::new(RefreshStatus::FileMissing, Some(file_path));
+                report.handles.insert(id, missing);
+                continue;
+            }
+            let current = &nodes[&file_path];
+            let found = match current.iter().find(|n| n.handle.id.to_string() == id) {
+                Some(node) => Some(node),
+                None => old.and_then(|old| Pin::new(&old, 0).best_match(current)),
+            };
+            let Some(node) = found else {
+                let gone = HandleRefresh::new(RefreshStatus::NotFound, Some(file_path));
+                report.handles.insert(id, gone);
+                continue;
+            };
+
+            let new_id = node.handle.id.to_string();
+            self.tracker.record(
+                &canonical,
+                &new_id,
+                HandleProvenance {
+                    source: if service_repo.is_some() {
+                        HandleSource::Service
+                    } else {
+                        HandleSource::Local
+                    },
+                    generation: report.generation,
+                    repo_id: service_repo.clone(),
+                    file_path: file_path.clone(),
+                    node_type: node.handle.node_type,
+                    token_count: node.handle.token_count,
+                },
+            );
+            let mut refreshed = HandleRefresh::new(RefreshStatus::Refreshed, Some(file_path));
+            refreshed.new_handle_id = (new_id != id).then_some(new_id);
+            report.handles.insert(id, refreshed);
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canopy_core::{QueryParams, RepoIndex};
+    use std::time::{Duration, SystemTime};
+
+    fn rewrite(file: &Path, content: &str) {
+        std::fs::write(file, content).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(file)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+    }
+
+    #[test]
+    fn stale_handles_refresh_to_ids_that_expand() {
+        let repo = canopy_core::temp_test_dir("refresh-test");
+        RepoIndex::init(&repo).unwrap();
+        std::fs::create_dir_all(repo.join("src")).unwrap();
+        let lib = repo.join("src/lib.rs");
+        std::fs::write(
+            &lib,
+            "pub fn first() {}\n\npub fn target() -> u32 {\n    1\n}\n",
+        )
+        .unwrap();
+        std::fs::write(repo.join("src/other.rs"), "pub fn untouched() {}\n").unwrap();
+        std::fs::write(repo.join("src/gone.rs"), "pub fn doomed() {}\n").unwrap();
+        let mut rt = ClientRuntime::new(None, None);
+        rt.index(&repo, Some("**/*.rs")).unwrap();
+        let id = |rt: &mut ClientRuntime, symbol: &str| {
+            rt.query(&repo, QueryParams::symbol(symbol))
+                .unwrap()
+                .handles[0]
+                .id
+                .to_string()
+        };
+        let target = id(&mut rt, "target");
+        let untouched = id(&mut rt, "untouched");
+        let doomed = id(&mut rt, "doomed");
+
+        // Shift `target` so its span, and so its id, changes
+        rewrite(
+            &lib,
+            "pub fn first() {}\n\npub fn inserted() {}\n\npub fn target() -> u32 {\n    2\n}\n",
+        );
+        std::fs::remove_file(repo.join("src/gone.rs")).unwrap();
+        let err = rt.expand(&repo, std::slice::from_ref(&target), false);
+        assert!(err.is_err(), "stale handle should not expand");
+
+        let ids = [
+            target.clone(),
+            untouched.clone(),
+            doomed.clone(),
+            "h_0".into(),
+        ];
+        let report = rt.refresh_handles(&repo, &ids).unwrap();
+        assert_eq!(report.files_reindexed, 2);
+        assert_eq!(report.generation, None);
+
+        let refreshed = &report.handles[&target];
+        assert_eq!(refreshed.status, RefreshStatus::Refreshed);
+        assert_eq!(refreshed.file_path.as_deref(), Some("src/lib.rs"));
+        let new_id = refreshed.new_handle_id.clone().expect("target moved");
+        assert_ne!(new_id, target);
+        let outcome = rt.expand(&repo, &[new_id], false).unwrap();
+        assert!(
+            outcome.contents[0].1.contains('2'),
+            "{:?}",
+            outcome.contents
+        );
+
+        assert_eq!(report.handles[&untouched].status, RefreshStatus::Unchanged);
+        assert_eq!(report.handles[&doomed].status, RefreshStatus::FileMissing);
+        assert_eq!(report.handles["h_0"].status, RefreshStatus::NotFound);
+    }
+}
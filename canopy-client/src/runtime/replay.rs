@@ -0,0 +1,181 @@
+//! Session log replay: re-run logged queries and diff returned handle sets.
+
+use crate::session_log::SessionRecord;
+use canopy_core::{QueryParams, QueryResult};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+use super::ClientRuntime;
+
+/// Handle-set diff for one replayed query.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayQueryDiff {
+    /// Position of the query among the log's query records (0-based).
+    pub index: usize,
+    pub repo: String,
+    pub query_text: String,
+    /// Handles returned now but not in the original session.
+    pub added: Vec<String>,
+    /// Handles returned in the original session but not now.
+    pub missing: Vec<String>,
+    pub old_tokens: usize,
+    pub new_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ReplayQueryDiff {
+    pub fn is_changed(&self) -> bool {
+        self.error.is_some() || !self.added.is_empty() || !self.missing.is_empty()
+    }
+}
+
+/// Summary of a full replay run.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReplayReport {
+    pub queries: Vec<ReplayQueryDiff>,
+    pub queries_replayed: usize,
+    pub queries_changed: usize,
+    pub old_total_tokens: usize,
+    pub new_total_tokens: usize,
+}
+
+impl ClientRuntime {
+    /// Re-execute logged queries in order and diff their handle sets.
+    ///
+    /// Replay bypasses feedback recording and the session log so evaluating a
+    /// ranking change never skews the stats it is being compared against.
+    pub fn replay(&mut self, records: &[SessionRecord]) -> ReplayReport {
+        let mut report = ReplayReport::default();
+
+        let queries = records.iter().filter_map(|record| match record {
+            SessionRecord::Query {
+                repo,
+                params,
+                handle_ids,
+                total_tokens,
+                ..
+            } => Some((repo, params, handle_ids, *total_tokens)),
+            SessionRecord::Expand { .. } => None,
+        });
+
+        for (index, (repo, params, old_ids, old_tokens)) in queries.enumerate() {
+            let outcome = self.replay_query(Path::new(repo), params.as_ref().clone());
+            let (new_ids, new_tokens, error) = match outcome {
+                Ok(result) => {
+                    let ids: Vec<String> =
+                        result.handles.iter().map(|h| h.id.to_string()).collect();
+                    (ids, result.total_tokens, None)
+                }
+                Err(err) => (Vec::new(), 0, Some(err.to_string())),
+            };
+
+            let diff = diff_handle_ids(old_ids, &new_ids);
+            let entry = ReplayQueryDiff {
+                index,
+                repo: repo.clone(),
+                query_text: params.to_text(),
+                added: diff.0,
+                missing: diff.1,
+                old_tokens,
+                new_tokens,
+                error,
+            };
+
+            report.queries_replayed += 1;
+            report.old_total_tokens += old_tokens;
+            report.new_total_tokens += new_tokens;
+            if entry.is_changed() {
+                report.queries_changed += 1;
+            }
+            report.queries.push(entry);
+        }
+
+        report
+    }
+
+    pub(super) fn replay_query(
+        &mut self,
+        repo_path: &Path,
+        params: QueryParams,
+    ) -> canopy_core::Result<QueryResult> {
+        if self.service.is_some() && params.dsl.is_none() {
+            self.query_service(repo_path, params)
+        } else {
+            self.query_standalone(repo_path, params)
+        }
+    }
+}
+
+/// (added, missing) handle ids, preserving the order they were returned in.
+fn diff_handle_ids(old_ids: &[String], new_ids: &[String]) -> (Vec<String>, Vec<String>) {
+    let old_set: HashSet<&String> = old_ids.iter().collect();
+    let new_set: HashSet<&String> = new_ids.iter().collect();
+    let added = new_ids
+        .iter()
+        .filter(|id| !old_set.contains(id))
+        .cloned()
+        .collect();
+    let missing = old_ids
+        .iter()
+        .filter(|id| !new_set.contains(id))
+        .cloned()
+        .collect();
+    (added, missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canopy_core::RepoIndex;
+
+    #[test]
+    fn diff_handle_ids_reports_added_and_missing() {
+        let old = vec!["h1".to_string(), "h2".to_string()];
+        let new = vec!["h2".to_string(), "h3".to_string()];
+        let (added, missing) = diff_handle_ids(&old, &new);
+        assert_eq!(added, vec!["h3"]);
+        assert_eq!(missing, vec!["h1"]);
+    }
+
+    #[test]
+    fn replay_detects_changed_results() {
+        let repo = canopy_core::temp_test_dir("replay-test");
+        RepoIndex::init(&repo).unwrap();
+        std::fs::create_dir_all(repo.join("src")).unwrap();
+        std::fs::write(repo.join("src/lib.rs"), "pub fn replayed() {}\n").unwrap();
+
+        let mut rt = ClientRuntime::new(None, None);
+        rt.index(&repo, Some("**/*.rs")).unwrap();
+        let result = rt.query(&repo, QueryParams::symbol("replayed")).unwrap();
+        let live_ids: Vec<String> = result.handles.iter().map(|h| h.id.to_string()).collect();
+
+        let repo_str = repo.to_string_lossy().to_string();
+        let records = vec![
+            SessionRecord::Query {
+                ts: 0,
+                repo: repo_str.clone(),
+                params: Box::new(QueryParams::symbol("replayed")),
+                handle_ids: live_ids,
+                total_tokens: result.total_tokens,
+                duration_ms: 0,
+            },
+            SessionRecord::Query {
+                ts: 0,
+                repo: repo_str,
+                params: Box::new(QueryParams::symbol("replayed")),
+                handle_ids: vec!["hstale".to_string()],
+                total_tokens: 0,
+                duration_ms: 0,
+            },
+        ];
+
+        let report = rt.replay(&records);
+        assert_eq!(report.queries_replayed, 2);
+        assert_eq!(report.queries_changed, 1);
+        assert!(!report.queries[0].is_changed());
+        assert_eq!(report.queries[1].missing, vec!["hstale"]);
+        assert_eq!(report.queries[1].added.len(), 1);
+    }
+}
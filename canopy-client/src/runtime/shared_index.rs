@@ -0,0 +1,252 @@
+//! Per-repo shared `RepoIndex` handles.
+//!
+//! Opening a `RepoIndex` reloads its whole symbol cache, and two open instances
+//! never see each other's cache updates. The registry keeps one instance per
+//! canonical repo path behind a mutex: writers (index, invalidate, dirty rebuild)
+//! hold the lock for the whole operation, readers only around a query or expand.
+
+use canopy_core::{AutoInit, RepoIndex};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::canonical_path;
+
+/// A `RepoIndex` shared by every caller of one repo.
+pub type SharedIndex = Arc<Mutex<RepoIndex>>;
+
+/// Lock a shared index. A panic in another holder doesn't make the index
+/// unusable; the registry reopens poisoned entries on the next lookup.
+pub fn lock_index(index: &SharedIndex) -> MutexGuard<'_, RepoIndex> {
+    index.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Open-once cache of `RepoIndex` instances keyed by canonical repo path.
+///
+/// Cloning is cheap and clones share the same instances, so several runtimes
+/// serving one process can reuse a single symbol cache per repo.
+#[derive(Clone, Default)]
+pub struct IndexRegistry {
+    entries: Arc<Mutex<HashMap<String, RegistryEntry>>>,
+}
+
+struct RegistryEntry {
+    index: SharedIndex,
+    db_path: PathBuf,
+    stamp: Option<DbStamp>,
+}
+
+impl IndexRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The shared index for `repo_path`, initializing `.canopy/` first when
+    /// it is missing and `auto_init` allows it.
+    ///
+    /// Reopens when the database file was replaced or removed since it was
+    /// opened (e.g. an index copied in from elsewhere), or when a previous
+    /// holder panicked mid-operation.
+    pub fn open(&self, repo_path: &Path, auto_init: AutoInit) -> canopy_core::Result<SharedIndex> {
+        let key = canonical_path(repo_path);
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(entry) = entries.get(&key) {
+            let current = entry.stamp.is_some() && db_stamp(&entry.db_path) == entry.stamp;
+            if current && !entry.index.is_poisoned() {
+                return Ok(Arc::clone(&entry.index));
+            }
+        }
+
+        let (index, created) = RepoIndex::open_with_auto_init(repo_path, auto_init)?;
+        if let Some(created) = created {
+            eprintln!("[canopy] {}", created);
+        }
+        let db_path = index.db_path().to_path_buf();
+        let shared = Arc::new(Mutex::new(index));
+        entries.insert(
+            key,
+            RegistryEntry {
+                index: Arc::clone(&shared),
+                stamp: db_stamp(&db_path),
+                db_path,
+            },
+        );
+        Ok(shared)
+    }
+
+    /// Drop the cached instance for `repo_path`; the next `open` reloads it.
+    pub fn evict(&self, repo_path: &Path) {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&canonical_path(repo_path));
+    }
+}
+
+/// Identity of a database file, which changes when the file is replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DbStamp(u64, u64);
+
+#[cfg(unix)]
+fn db_stamp(path: &Path) -> Option<DbStamp> {
+    use std::os::unix::fs::MetadataExt;
+    let meta = std::fs::metadata(path).ok()?;
+    Some(DbStamp(meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn db_stamp(path: &Path) -> Option<DbStamp> {
+    let created = std::fs::metadata(path).ok()?.created().ok()?;
+    let since = created.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some(DbStamp(since.as_secs(), since.subsec_nanos() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientRuntime;
+    use canopy_core::QueryParams;
+    use std::thread;
+
+    fn repo_with_files(count: usize) -> tempfile::TempDir {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        for i in 0..count {
+            std::fs::write(
+                dir.path().join(format!("src/base_{i}.rs")),
+                format!("pub fn base_fn_{i}() {{}}\n"),
+            )
+            .unwrap();
+        }
+        RepoIndex::init(dir.path()).unwrap();
+        dir
+    }
+
+    fn runtime_with(registry: &IndexRegistry) -> ClientRuntime {
+        let mut rt = ClientRuntime::new(None, None);
+        rt.set_index_registry(registry.clone());
+        rt
+    }
+
+    #[test]
+    fn open_reuses_one_instance_per_repo() {
+        let repo = repo_with_files(1);
+        let registry = IndexRegistry::new();
+        let a = registry.open(repo.path(), AutoInit::on()).unwrap();
+        let b = registry
+            .open(&repo.path().join("src/.."), AutoInit::on())
+            .unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+
+        registry.evict(repo.path());
+        let c = registry.open(repo.path(), AutoInit::on()).unwrap();
+        assert!(!Arc::ptr_eq(&a, &c));
+    }
+
+    #[test]
+    fn concurrent_query_and_index_share_the_cache() {
+        let repo = repo_with_files(4);
+        let registry = IndexRegistry::new();
+        runtime_with(&registry)
+            .index(repo.path(), Some("**/*.rs"))
+            .unwrap();
+        let shared = registry.open(repo.path(), AutoInit::on()).unwrap();
+
+        let writers: Vec<_> = (0..4)
+            .map(|t| {
+                let registry = registry.clone();
+                let root = repo.path().to_path_buf();
+                thread::spawn(move || {
+                    let mut rt = runtime_with(&registry);
+                    for round in 0..3 {
+                        let name = format!("added_{t}_{round}");
+                        std::fs::write(
+                            root.join(format!("src/{name}.rs")),
+                            format!("pub fn {name}() {{}}\n"),
+                        )
+                        .unwrap();
+                        rt.index(&root, Some(&format!("src/{name}.rs"))).unwrap();
+                        let found = rt.query(&root, QueryParams::symbol(&name)).unwrap();
+                        assert_eq!(found.handles.len(), 1, "{name} visible after indexing");
+                    }
+                })
+            })
+            .collect();
+        let readers: Vec<_> = (0..4)
+            .map(|t| {
+                let registry = registry.clone();
+                let root = repo.path().to_path_buf();
+                thread::spawn(move || {
+                    let mut rt = runtime_with(&registry);
+                    for _ in 0..10 {
+                        let base = rt
+                            .query(&root, QueryParams::symbol(format!("base_fn_{t}")))
+                            .unwrap();
+                        assert_eq!(base.handles.len(), 1);
+                        let ids = vec![base.handles[0].id.to_string()];
+                        let outcome = rt.expand(&root, &ids, false).unwrap();
+                        assert!(outcome.contents[0].1.contains("base_fn_"));
+                    }
+                })
+            })
+            .collect();
+        for handle in writers.into_iter().chain(readers) {
+            handle.join().expect("no worker panicked");
+        }
+
+        // Every symbol indexed by any thread is in the one shared cache
+        let after = registry.open(repo.path(), AutoInit::on()).unwrap();
+        assert!(Arc::ptr_eq(&shared, &after), "no reopen per call");
+        let mut rt = runtime_with(&registry);
+        for t in 0..4 {
+            for round in 0..3 {
+                let name = format!("added_{t}_{round}");
+                assert_eq!(
+                    rt.query(repo.path(), QueryParams::symbol(&name))
+                        .unwrap()
+                        .handles
+                        .len(),
+                    1
+                );
+            }
+        }
+        assert_eq!(lock_index(&after).status().unwrap().files_indexed, 16);
+    }
+
+    #[test]
+    fn replaced_database_is_reopened() {
+        let repo = repo_with_files(1);
+        let registry = IndexRegistry::new();
+        let mut rt = runtime_with(&registry);
+        rt.index(repo.path(), Some("**/*.rs")).unwrap();
+        let before = registry.open(repo.path(), AutoInit::on()).unwrap();
+
+        // Build a different index elsewhere and move its database into place
+        let other = repo_with_files(1);
+        std::fs::write(
+            other.path().join("src/imported.rs"),
+            "pub fn imported() {}\n",
+        )
+        .unwrap();
+        RepoIndex::open(other.path())
+            .unwrap()
+            .index("**/*.rs")
+            .unwrap();
+        let db = repo.path().join(".canopy/index.db");
+        let _ = std::fs::remove_file(db.with_extension("db-wal"));
+        let _ = std::fs::remove_file(db.with_extension("db-shm"));
+        std::fs::copy(
+            other.path().join(".canopy/index.db"),
+            db.with_extension("new"),
+        )
+        .unwrap();
+        std::fs::rename(db.with_extension("new"), &db).unwrap();
+
+        let after = registry.open(repo.path(), AutoInit::on()).unwrap();
+        assert!(!Arc::ptr_eq(&before, &after));
+        let found = rt
+            .query(repo.path(), QueryParams::symbol("imported"))
+            .unwrap();
+        assert_eq!(found.handles.len(), 1);
+    }
+}
@@ -1,37 +1,79 @@
 //! HTTP client for canopy-service
 
+use crate::retry::{is_retryable_error, is_retryable_status, CallClass, RetryPolicy, DEBUG_ENV};
 use canopy_core::protocol::{
-    AddRepoRequest, AddRepoResponse, EvidencePackConfig, EvidencePackRequest, ExpandHandle,
-    ExpandRequest, ExpandResponse, QueryRequest, ReindexRequest,
+    ClientContext, EvidencePackConfig, EvidencePackRequest, ExpandHandle, ExpandRequest,
+    ExpandResponse, ExpandedContent, PruneGenerationsRequest, QueryRequest, ReferencesRequest,
+    ReindexRequest, RelatedRequest, SummaryRequest, SymbolsRequest, ValidateRequest,
+    ValidateResponse, WarmupRequest, CLIENT_HEADER, SESSION_HEADER,
 };
 use canopy_core::{
-    CanopyError, ErrorEnvelope, EvidencePack, QueryParams, QueryResult, RepoShard, ShardStatus,
+    CanopyError, ErrorEnvelope, EvidencePack, FileReferences, QueryParams, QueryResult,
+    RelatedFiles, RepoShard, RepoSummary, ShardStatus, SymbolPage,
 };
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 // Re-export shared types for callers that depend on them via this crate.
-pub use canopy_core::protocol::{ReindexResponse, ServiceStatus};
+pub use canopy_core::protocol::{
+    AddRepoRequest, AddRepoResponse, PruneGenerationsResponse, PrunedGeneration, ReindexResponse,
+    RepoWarmup, ServiceStatus, WarmupResponse,
+};
 
 pub struct ServiceClient {
     base_url: String,
     client: reqwest::blocking::Client,
-    /// API key for admin routes (sent as X-Api-Key header)
+    /// API key for guarded routes (sent as X-Api-Key header). The service
+    /// guards query and admin routes alike; ops routes such as /status never
+    /// see the key.
     api_key: Option<String>,
+    /// Sent with every request as X-Canopy-Session and X-Canopy-Client
+    client_context: ClientContext,
     /// Cache: canonical path → repo_id
     repo_id_cache: HashMap<String, String>,
+    retry: RetryPolicy,
+    /// Retries made so far, across all calls
+    retries: AtomicU64,
 }
 
 impl ServiceClient {
     pub fn new(base_url: &str, api_key: Option<String>) -> Self {
+        Self::with_retry_policy(base_url, api_key, RetryPolicy::default())
+    }
+
+    pub fn with_retry_policy(base_url: &str, api_key: Option<String>, retry: RetryPolicy) -> Self {
         Self {
             base_url: base_url.trim_end_matches('/').to_string(),
-            client: reqwest::blocking::Client::new(),
+            client: http_client(&retry),
             api_key,
+            client_context: ClientContext::default(),
             repo_id_cache: HashMap::new(),
+            retry,
+            retries: AtomicU64::new(0),
         }
     }
 
+    /// Retry subsequent requests per `retry`.
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.client = http_client(&retry);
+        self.retry = retry;
+    }
+
+    /// Attribute subsequent requests to `client_context`.
+    pub fn set_client_context(&mut self, client_context: ClientContext) {
+        self.client_context = client_context;
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// Retries made so far, across all calls.
+    pub fn retry_count(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
     /// Resolve a local repo path to a service repo_id.
     ///
     /// Canonicalizes the path, then checks cache. On cache miss (or on
@@ -70,16 +112,12 @@ impl ServiceClient {
     fn add_repo(&self, canonical_path: &str) -> Result<String, CanopyError> {
         let url = format!("{}/repos/add", self.base_url);
         let req = AddRepoRequest {
-            path: canonical_path.to_string(),
-            name: None,
+            path: Some(canonical_path.to_string()),
+            ..AddRepoRequest::default()
         };
-        let mut builder = self.client.post(&url).json(&req);
-        builder = self.apply_api_key(builder);
-        let resp = builder.send().map_err(Self::connection_error)?;
-
-        if !resp.status().is_success() {
-            return self.handle_error(resp);
-        }
+        let resp = self.send(CallClass::Idempotent, || {
+            self.apply_headers(self.client.post(&url).json(&req))
+        })?;
 
         let body: AddRepoResponse = resp.json().map_err(Self::parse_error)?;
 
@@ -160,16 +198,9 @@ impl ServiceClient {
             repo: repo_id.to_string(),
             params,
         };
-        let resp = self
-            .client
-            .post(&url)
-            .json(&req)
-            .send()
-            .map_err(Self::connection_error)?;
-
-        if !resp.status().is_success() {
-            return self.handle_error(resp);
-        }
+        let resp = self.send(CallClass::Idempotent, || {
+            self.apply_headers(self.client.post(&url).json(&req))
+        })?;
 
         resp.json::<QueryResult>().map_err(Self::parse_error)
     }
@@ -187,16 +218,9 @@ impl ServiceClient {
             params,
             config,
         };
-        let resp = self
-            .client
-            .post(&url)
-            .json(&req)
-            .send()
-            .map_err(Self::connection_error)?;
-
-        if !resp.status().is_success() {
-            return self.handle_error(resp);
-        }
+        let resp = self.send(CallClass::Idempotent, || {
+            self.apply_headers(self.client.post(&url).json(&req))
+        })?;
 
         resp.json::<EvidencePack>().map_err(Self::parse_error)
     }
@@ -208,43 +232,159 @@ impl ServiceClient {
         handle_ids: &[String],
         generation: Option<u64>,
     ) -> Result<Vec<(String, String)>, CanopyError> {
+        self.post_expand(repo_id, handle_ids, generation, false)
+    }
+
+    /// [`expand`](Self::expand) on the caller's behalf: the service records
+    /// the expansions in feedback as automatic.
+    pub fn auto_expand(
+        &self,
+        repo_id: &str,
+        handle_ids: &[String],
+        generation: Option<u64>,
+    ) -> Result<Vec<(String, String)>, CanopyError> {
+        self.post_expand(repo_id, handle_ids, generation, true)
+    }
+
+    fn post_expand(
+        &self,
+        repo_id: &str,
+        handle_ids: &[String],
+        generation: Option<u64>,
+        auto_expanded: bool,
+    ) -> Result<Vec<(String, String)>, CanopyError> {
+        let handles: Vec<ExpandHandle> = handle_ids
+            .iter()
+            .map(|id| ExpandHandle {
+                id: id.clone(),
+                generation,
+                lines: None,
+            })
+            .collect();
+        Ok(self
+            .expand_handles(repo_id, &handles, auto_expanded)?
+            .into_iter()
+            .map(|c| (c.handle_id, c.content))
+            .collect())
+    }
+
+    /// Expand handles of possibly different generations in one request; the
+    /// service reads each from its generation's index while it is retained,
+    /// cut to the handle's `lines` when given.
+    pub fn expand_handles(
+        &self,
+        repo_id: &str,
+        handles: &[ExpandHandle],
+        auto_expanded: bool,
+    ) -> Result<Vec<ExpandedContent>, CanopyError> {
         let url = format!("{}/expand", self.base_url);
         let req = ExpandRequest {
             repo: repo_id.to_string(),
-            handles: handle_ids
-                .iter()
-                .map(|id| ExpandHandle {
-                    id: id.clone(),
-                    generation,
-                })
-                .collect(),
+            handles: handles.to_vec(),
+            auto_expanded,
         };
-        let resp = self
-            .client
-            .post(&url)
-            .json(&req)
-            .send()
-            .map_err(Self::connection_error)?;
-
-        if !resp.status().is_success() {
-            return self.handle_error(resp);
-        }
+        let resp = self.send(CallClass::Idempotent, || {
+            self.apply_headers(self.client.post(&url).json(&req))
+        })?;
 
         let body: ExpandResponse = resp.json().map_err(Self::parse_error)?;
 
-        Ok(body
-            .contents
-            .into_iter()
-            .map(|c| (c.handle_id, c.content))
-            .collect())
+        Ok(body.contents)
+    }
+
+    /// Budgeted repository overview.
+    pub fn summary(
+        &self,
+        repo_id: &str,
+        max_tokens: Option<usize>,
+    ) -> Result<RepoSummary, CanopyError> {
+        let url = format!("{}/summary", self.base_url);
+        let req = SummaryRequest {
+            repo: repo_id.to_string(),
+            max_tokens,
+        };
+        let resp = self.send(CallClass::Idempotent, || {
+            self.apply_headers(self.client.post(&url).json(&req))
+        })?;
+
+        resp.json().map_err(Self::parse_error)
+    }
+
+    /// Files most related to `path` by shared symbols.
+    pub fn related(
+        &self,
+        repo_id: &str,
+        path: &str,
+        limit: Option<usize>,
+    ) -> Result<RelatedFiles, CanopyError> {
+        let url = format!("{}/related", self.base_url);
+        let req = RelatedRequest {
+            repo: repo_id.to_string(),
+            path: path.to_string(),
+            limit,
+        };
+        let resp = self.send(CallClass::Idempotent, || {
+            self.apply_headers(self.client.post(&url).json(&req))
+        })?;
+
+        resp.json().map_err(Self::parse_error)
+    }
+
+    /// References to a symbol grouped by file.
+    pub fn references(&self, req: &ReferencesRequest) -> Result<FileReferences, CanopyError> {
+        let url = format!("{}/references", self.base_url);
+        let resp = self.send(CallClass::Idempotent, || {
+            self.apply_headers(self.client.post(&url).json(req))
+        })?;
+
+        resp.json().map_err(Self::parse_error)
+    }
+
+    /// A page of the repo's symbols in name order.
+    pub fn symbols(&self, req: &SymbolsRequest) -> Result<SymbolPage, CanopyError> {
+        let url = format!("{}/symbols", self.base_url);
+        let resp = self.send(CallClass::Idempotent, || {
+            self.apply_headers(self.client.post(&url).json(req))
+        })?;
+
+        resp.json().map_err(Self::parse_error)
+    }
+
+    /// Fresh/stale/missing status of handles of the live generation.
+    pub fn validate(
+        &self,
+        repo_id: &str,
+        handle_ids: &[String],
+    ) -> Result<ValidateResponse, CanopyError> {
+        let url = format!("{}/validate", self.base_url);
+        let req = ValidateRequest {
+            repo: repo_id.to_string(),
+            handle_ids: handle_ids.to_vec(),
+        };
+        let resp = self.send(CallClass::Idempotent, || {
+            self.apply_headers(self.client.post(&url).json(&req))
+        })?;
+
+        resp.json().map_err(Self::parse_error)
+    }
+
+    /// Have the service clone `git_url` and manage the checkout itself; it
+    /// is registered (in `error` status if the clone failed) but not indexed.
+    pub fn add_repo_url(&self, req: &AddRepoRequest) -> Result<AddRepoResponse, CanopyError> {
+        let url = format!("{}/repos/add", self.base_url);
+        let resp = self.send(CallClass::NonIdempotent, || {
+            self.apply_headers(self.client.post(&url).json(req))
+        })?;
+
+        resp.json().map_err(Self::parse_error)
     }
 
     pub fn list_repos(&self) -> Result<Vec<RepoShard>, CanopyError> {
-        self.get_json("/repos")
+        self.get_json("/repos", true)
     }
 
     pub fn status(&self) -> Result<ServiceStatus, CanopyError> {
-        self.get_json("/status")
+        self.get_json("/status", false)
     }
 
     pub fn reindex(
@@ -252,42 +392,144 @@ impl ServiceClient {
         repo_id: &str,
         glob: Option<String>,
     ) -> Result<ReindexResponse, CanopyError> {
-        let url = format!("{}/reindex", self.base_url);
-        let req = ReindexRequest {
+        self.send_reindex(&ReindexRequest {
             repo: repo_id.to_string(),
             glob,
-        };
-        let mut builder = self.client.post(&url).json(&req);
-        builder = self.apply_api_key(builder);
-        let resp = builder.send().map_err(Self::connection_error)?;
+            paths: Vec::new(),
+        })
+    }
 
-        if !resp.status().is_success() {
-            return self.handle_error(resp);
-        }
+    /// Reindex exactly `paths` (repo-relative) rather than a glob.
+    pub fn reindex_paths(
+        &self,
+        repo_id: &str,
+        paths: Vec<String>,
+    ) -> Result<ReindexResponse, CanopyError> {
+        self.send_reindex(&ReindexRequest {
+            repo: repo_id.to_string(),
+            glob: None,
+            paths,
+        })
+    }
+
+    fn send_reindex(&self, req: &ReindexRequest) -> Result<ReindexResponse, CanopyError> {
+        let url = format!("{}/reindex", self.base_url);
+        let resp = self.send(CallClass::NonIdempotent, || {
+            self.apply_headers(self.client.post(&url).json(req))
+        })?;
 
         resp.json().map_err(Self::parse_error)
     }
 
-    fn get_json<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, CanopyError> {
-        let url = format!("{}{}", self.base_url, path);
-        let req = self.apply_api_key(self.client.get(&url));
-        let resp = req.send().map_err(Self::connection_error)?;
+    /// Preload `repo_ids`' indexes, or every ready repo's when None.
+    pub fn warmup(&self, repo_ids: Option<Vec<String>>) -> Result<WarmupResponse, CanopyError> {
+        let url = format!("{}/warmup", self.base_url);
+        let req = WarmupRequest { repo_ids };
+        let resp = self.send(CallClass::Idempotent, || {
+            self.apply_headers(self.client.post(&url).json(&req))
+        })?;
 
-        if !resp.status().is_success() {
-            return self.handle_error(resp);
-        }
+        resp.json().map_err(Self::parse_error)
+    }
+
+    /// Drop retained generations beyond `retain` (the service's own limit
+    /// when None), for `repo_id` or every repo.
+    pub fn prune_generations(
+        &self,
+        repo_id: Option<&str>,
+        retain: Option<usize>,
+    ) -> Result<PruneGenerationsResponse, CanopyError> {
+        let url = format!("{}/generations/prune", self.base_url);
+        let req = PruneGenerationsRequest {
+            repo: repo_id.map(str::to_string),
+            retain,
+        };
+        let resp = self.send(CallClass::NonIdempotent, || {
+            self.apply_headers(self.client.post(&url).json(&req))
+        })?;
+
+        resp.json().map_err(Self::parse_error)
+    }
+
+    fn get_json<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        guarded: bool,
+    ) -> Result<T, CanopyError> {
+        let url = format!("{}{}", self.base_url, path);
+        let resp = self.send(CallClass::Idempotent, || {
+            let req = self.apply_client_context(self.client.get(&url));
+            if guarded {
+                self.apply_api_key(req)
+            } else {
+                req
+            }
+        })?;
 
         resp.json().map_err(Self::parse_error)
     }
 
-    fn connection_error(e: reqwest::Error) -> CanopyError {
+    /// Send the request `build` makes, retrying it as the policy allows for
+    /// `class`, and return the successful response. A 5xx that outlasts the
+    /// retries is returned as the service reported it.
+    fn send(
+        &self,
+        class: CallClass,
+        build: impl Fn() -> reqwest::blocking::RequestBuilder,
+    ) -> Result<reqwest::blocking::Response, CanopyError> {
+        let mut attempt = 1;
+        loop {
+            let retrying = attempt < self.retry.attempts;
+            let reason = match build().send() {
+                Ok(resp) if resp.status().is_success() => return Ok(resp),
+                Ok(resp) if retrying && is_retryable_status(class, resp.status()) => {
+                    format!("HTTP {}", resp.status())
+                }
+                Ok(resp) => return self.handle_error(resp),
+                Err(e) if retrying && is_retryable_error(class, &e) => error_chain(&e),
+                Err(e) if is_retryable_error(class, &e) => {
+                    return Err(Self::unreachable(&e, attempt))
+                }
+                Err(e) => return Err(Self::connection_error(&e)),
+            };
+            let delay = self.retry.delay(attempt);
+            self.retries.fetch_add(1, Ordering::Relaxed);
+            if std::env::var_os(DEBUG_ENV).is_some() {
+                eprintln!(
+                    "[canopy] service: attempt {}/{} failed ({}); retrying in {:?}",
+                    attempt, self.retry.attempts, reason, delay
+                );
+            }
+            std::thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    fn connection_error(e: &reqwest::Error) -> CanopyError {
         CanopyError::ServiceError {
             code: "connection_error".to_string(),
-            message: e.to_string(),
+            message: error_chain(e),
             hint: "Is canopy-service running?".to_string(),
         }
     }
 
+    /// The service could not be reached in `attempts` tries.
+    fn unreachable(e: &reqwest::Error, attempts: u32) -> CanopyError {
+        let tries = if attempts == 1 { "attempt" } else { "attempts" };
+        CanopyError::ServiceError {
+            code: "service_unreachable".to_string(),
+            message: format!(
+                "Service unreachable after {} {}: {}",
+                attempts,
+                tries,
+                error_chain(e)
+            ),
+            hint: "Is canopy-service running? Raise --service-retries or --service-timeout \
+                   on a flaky network"
+                .to_string(),
+        }
+    }
+
     fn parse_error(e: impl std::fmt::Display) -> CanopyError {
         CanopyError::ServiceError {
             code: "parse_error".to_string(),
@@ -296,6 +538,27 @@ impl ServiceClient {
         }
     }
 
+    /// Attach the client context headers and, if configured, the API key.
+    fn apply_headers(
+        &self,
+        builder: reqwest::blocking::RequestBuilder,
+    ) -> reqwest::blocking::RequestBuilder {
+        self.apply_api_key(self.apply_client_context(builder))
+    }
+
+    fn apply_client_context(
+        &self,
+        mut builder: reqwest::blocking::RequestBuilder,
+    ) -> reqwest::blocking::RequestBuilder {
+        if let Some(session_id) = &self.client_context.session_id {
+            builder = builder.header(SESSION_HEADER, session_id);
+        }
+        if let Some(client_name) = &self.client_context.client_name {
+            builder = builder.header(CLIENT_HEADER, client_name);
+        }
+        builder
+    }
+
     /// Attach X-Api-Key header to a request if an API key is configured.
     fn apply_api_key(
         &self,
@@ -308,9 +571,36 @@ impl ServiceClient {
         }
     }
 
+    /// 401 from the guard, with a hint that says whether a key was sent and
+    /// where one can be configured.
+    fn unauthorized(&self, message: String) -> CanopyError {
+        let problem = if self.api_key.is_some() {
+            "The service rejected the X-Api-Key header;"
+        } else {
+            "The service requires an X-Api-Key header;"
+        };
+        CanopyError::ServiceError {
+            code: "unauthorized".to_string(),
+            message,
+            hint: format!(
+                "{problem} pass --api-key, set {}, or add api_key to {}",
+                crate::credentials::API_KEY_ENV,
+                crate::credentials::CREDENTIALS_FILE
+            ),
+        }
+    }
+
     fn handle_error<T>(&self, resp: reqwest::blocking::Response) -> Result<T, CanopyError> {
         let status = resp.status();
+        if status == reqwest::StatusCode::UNAUTHORIZED {
+            let message = resp
+                .json::<ErrorEnvelope>()
+                .map(|envelope| envelope.message)
+                .unwrap_or_else(|_| "Missing or invalid API key".to_string());
+            return Err(self.unauthorized(message));
+        }
         match resp.json::<ErrorEnvelope>() {
+            Ok(envelope) if envelope.code == "forbidden" => Err(forbidden(envelope)),
             Ok(envelope) => Err(CanopyError::ServiceError {
                 code: envelope.code,
                 message: envelope.message,
@@ -325,6 +615,41 @@ impl ServiceClient {
     }
 }
 
+/// A known key whose role doesn't cover the route: the service's hint names
+/// the roles that do, and ours says where a different key goes.
+fn forbidden(envelope: ErrorEnvelope) -> CanopyError {
+    CanopyError::ServiceError {
+        code: envelope.code,
+        message: envelope.message,
+        hint: format!(
+            "{}; pass it with --api-key, set {}, or change api_key in {}",
+            envelope.hint,
+            crate::credentials::API_KEY_ENV,
+            crate::credentials::CREDENTIALS_FILE
+        ),
+    }
+}
+
+/// HTTP client applying `retry`'s per-attempt timeout.
+fn http_client(retry: &RetryPolicy) -> reqwest::blocking::Client {
+    reqwest::blocking::Client::builder()
+        .timeout(retry.timeout)
+        .build()
+        .unwrap_or_else(|_| reqwest::blocking::Client::new())
+}
+
+/// `e` and its sources, which name the actual cause (refused, reset, ...).
+fn error_chain(e: &reqwest::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
 /// Check if a service error has a specific error code
 pub fn is_error_code(err: &CanopyError, code: &str) -> bool {
     matches!(err, CanopyError::ServiceError { code: c, .. } if c == code)
@@ -394,19 +719,25 @@ mod tests {
                 ExpandHandle {
                     id: "h1".to_string(),
                     generation: Some(5),
+                    lines: Some("10-40".parse().unwrap()),
                 },
                 ExpandHandle {
                     id: "h2".to_string(),
                     generation: None,
+                    lines: None,
                 },
             ],
+            auto_expanded: false,
         };
         let json = serde_json::to_value(&req).unwrap();
         assert_eq!(json["repo"], "my-repo");
         assert_eq!(json["handles"][0]["id"], "h1");
         assert_eq!(json["handles"][0]["generation"], 5);
+        assert_eq!(json["handles"][0]["lines"], "10-40");
         assert_eq!(json["handles"][1]["id"], "h2");
         assert!(json["handles"][1].get("generation").is_none());
+        assert!(json["handles"][1].get("lines").is_none());
+        assert!(json.get("auto_expanded").is_none());
     }
 
     #[test]
@@ -451,8 +782,8 @@ mod tests {
     #[test]
     fn add_repo_request_skips_none_name() {
         let req = AddRepoRequest {
-            path: "/home/user/repo".to_string(),
-            name: None,
+            path: Some("/home/user/repo".to_string()),
+            ..AddRepoRequest::default()
         };
         let json = serde_json::to_value(&req).unwrap();
         assert_eq!(json["path"], "/home/user/repo");
@@ -476,4 +807,144 @@ mod tests {
         assert!(config.get("max_per_file").is_none());
         assert!(config.get("plan").is_none());
     }
+
+    // -- retries ------------------------------------------------------------
+
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    #[derive(Clone, Copy)]
+    enum Failure {
+        /// Answer 503
+        Unavailable,
+        /// Read the request, then close without answering
+        Drop,
+    }
+
+    /// Read one request's headers and body.
+    fn read_request(stream: &mut TcpStream) {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        while let Ok(n) = stream.read(&mut chunk) {
+            if n == 0 {
+                return;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&buf);
+            if let Some(end) = text.find("\r\n\r\n") {
+                let length = text[..end]
+                    .lines()
+                    .find_map(|l| {
+                        l.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|v| v.trim().parse::<usize>().unwrap_or(0))
+                    })
+                    .unwrap_or(0);
+                if buf.len() >= end + 4 + length {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// A server that fails the first `failures` requests, then answers 200
+    /// with `body`. Returns its URL and the requests it received.
+    fn flaky_server(
+        failures: usize,
+        failure: Failure,
+        body: &'static str,
+    ) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let seen = requests.clone();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                read_request(&mut stream);
+                let n = seen.fetch_add(1, Ordering::SeqCst);
+                let response = match failure {
+                    _ if n >= failures => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    ),
+                    Failure::Unavailable => "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                    Failure::Drop => continue,
+                };
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (url, requests)
+    }
+
+    const REINDEX_OK: &str = r#"{"generation": 2, "status": "indexing"}"#;
+
+    fn fast_retries(attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            attempts,
+            timeout: std::time::Duration::from_secs(5),
+            base_delay: std::time::Duration::from_millis(1),
+            max_delay: std::time::Duration::from_millis(4),
+        }
+    }
+
+    fn client(url: &str, attempts: u32) -> ServiceClient {
+        ServiceClient::with_retry_policy(url, None, fast_retries(attempts))
+    }
+
+    #[test]
+    fn idempotent_calls_retry_5xx_and_dropped_connections() {
+        for failure in [Failure::Unavailable, Failure::Drop] {
+            let (url, requests) = flaky_server(2, failure, "[]");
+            let client = client(&url, 3);
+            assert!(client.list_repos().unwrap().is_empty());
+            assert_eq!(requests.load(Ordering::SeqCst), 3);
+            assert_eq!(client.retry_count(), 2);
+        }
+    }
+
+    #[test]
+    fn non_idempotent_calls_do_not_retry_once_sent() {
+        let (url, requests) = flaky_server(1, Failure::Unavailable, REINDEX_OK);
+        let err = client(&url, 3).reindex("repo", None).err().unwrap();
+        assert!(is_error_code(&err, "http_503"), "{err}");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let (url, requests) = flaky_server(1, Failure::Drop, REINDEX_OK);
+        let client = client(&url, 3);
+        let err = client.reindex("repo", None).err().unwrap();
+        assert!(is_error_code(&err, "connection_error"), "{err}");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(client.retry_count(), 0);
+        // The next call goes through
+        assert_eq!(client.reindex("repo", None).unwrap().generation, 2);
+    }
+
+    #[test]
+    fn exhausted_retries_report_service_unreachable() {
+        // Nothing listens once the listener is dropped: every class retries
+        // a refused connection, since nothing was sent
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let client = client(&url, 3);
+        let err = client.reindex("repo", None).err().unwrap();
+        assert!(is_error_code(&err, "service_unreachable"), "{err}");
+        assert!(err.to_string().contains("after 3 attempts"), "{err}");
+        assert_eq!(client.retry_count(), 2);
+        assert!(is_error_code(
+            &client.list_repos().err().unwrap(),
+            "service_unreachable"
+        ));
+        assert_eq!(client.retry_count(), 4);
+
+        // 5xx past the last attempt is the service's own answer
+        let (url, requests) = flaky_server(5, Failure::Unavailable, "[]");
+        let err = self::client(&url, 2).list_repos().err().unwrap();
+        assert!(is_error_code(&err, "http_503"), "{err}");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
 }
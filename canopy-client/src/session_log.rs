@@ -0,0 +1,212 @@
+//! Opt-in session transcript of query/expand activity.
+//!
+//! When enabled (`CANOPY_SESSION_LOG=path` or `--session-log`), `ClientRuntime`
+//! appends one JSON record per query/expand so a session can be audited and
+//! replayed against a newer index. File contents are never logged.
+//!
+//! Like provenance, the log is best-effort: write failures are reported once on
+//! stderr and never fail the calling operation.
+
+use canopy_core::QueryParams;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Environment variable that enables the session log.
+pub const SESSION_LOG_ENV: &str = "CANOPY_SESSION_LOG";
+
+/// Rotate the log once it grows past this size.
+pub const DEFAULT_SESSION_LOG_MAX_BYTES: u64 = 8 * 1024 * 1024;
+
+/// One line of the session log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SessionRecord {
+    Query {
+        ts: i64,
+        repo: String,
+        params: Box<QueryParams>,
+        handle_ids: Vec<String>,
+        total_tokens: usize,
+        duration_ms: u64,
+    },
+    Expand {
+        ts: i64,
+        repo: String,
+        handle_ids: Vec<String>,
+        failed_ids: Vec<String>,
+        duration_ms: u64,
+    },
+}
+
+/// Append-only NDJSON writer with single-file rotation (`<path>.1`).
+pub struct SessionLog {
+    path: PathBuf,
+    max_bytes: u64,
+    warned: bool,
+}
+
+impl SessionLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_bytes: DEFAULT_SESSION_LOG_MAX_BYTES,
+            warned: false,
+        }
+    }
+
+    /// Session log from `CANOPY_SESSION_LOG`, if set and non-empty.
+    pub fn from_env() -> Option<Self> {
+        std::env::var(SESSION_LOG_ENV)
+            .ok()
+            .filter(|p| !p.is_empty())
+            .map(Self::new)
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a record. Errors are reported once on stderr and otherwise ignored.
+    pub fn append(&mut self, record: &SessionRecord) {
+        if let Err(err) = self.try_append(record) {
+            if !self.warned {
+                eprintln!(
+                    "[canopy] session log disabled for {}: {}",
+                    self.path.display(),
+                    err
+                );
+                self.warned = true;
+            }
+        }
+    }
+
+    fn try_append(&self, record: &SessionRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+
+        if let Ok(meta) = fs::metadata(&self.path) {
+            if meta.len() + line.len() as u64 > self.max_bytes {
+                fs::rename(&self.path, rotated_path(&self.path))?;
+            }
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())
+    }
+
+    /// Read all records from a session log, skipping malformed lines.
+    pub fn read(path: &Path) -> canopy_core::Result<Vec<SessionRecord>> {
+        let file = fs::File::open(path)?;
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Ok(record) = serde_json::from_str(&line) {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+pub(crate) fn now_ts() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query_record(pattern: &str) -> SessionRecord {
+        SessionRecord::Query {
+            ts: 1,
+            repo: "/tmp/repo".to_string(),
+            params: Box::new(QueryParams::pattern(pattern)),
+            handle_ids: vec!["h1".to_string()],
+            total_tokens: 10,
+            duration_ms: 2,
+        }
+    }
+
+    #[test]
+    fn append_and_read_roundtrip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("session.ndjson");
+        let mut log = SessionLog::new(&path);
+
+        log.append(&query_record("auth"));
+        log.append(&SessionRecord::Expand {
+            ts: 2,
+            repo: "/tmp/repo".to_string(),
+            handle_ids: vec!["h1".to_string()],
+            failed_ids: vec![],
+            duration_ms: 1,
+        });
+
+        let records = SessionLog::read(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        match &records[0] {
+            SessionRecord::Query { params, .. } => {
+                assert_eq!(params.pattern.as_deref(), Some("auth"))
+            }
+            other => panic!("expected query record, got {other:?}"),
+        }
+        assert!(matches!(records[1], SessionRecord::Expand { .. }));
+    }
+
+    #[test]
+    fn append_rotates_when_over_max_bytes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("session.ndjson");
+        let mut log = SessionLog::new(&path).with_max_bytes(200);
+
+        for i in 0..5 {
+            log.append(&query_record(&format!("pattern_{i}")));
+        }
+
+        assert!(rotated_path(&path).exists());
+        assert!(fs::metadata(&path).unwrap().len() <= 200);
+    }
+
+    #[test]
+    fn append_to_unwritable_path_does_not_panic() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut log = SessionLog::new(dir.path().join("missing/dir/session.ndjson"));
+        log.append(&query_record("auth"));
+        log.append(&query_record("auth"));
+        assert!(log.warned);
+    }
+
+    #[test]
+    fn read_skips_malformed_lines() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("session.ndjson");
+        let valid = serde_json::to_string(&query_record("auth")).unwrap();
+        fs::write(&path, format!("not json\n{valid}\n\n")).unwrap();
+
+        let records = SessionLog::read(&path).unwrap();
+        assert_eq!(records.len(), 1);
+    }
+}
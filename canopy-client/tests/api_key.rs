@@ -0,0 +1,156 @@
+//! ServiceClient against a service started with `--api-key`: guarded routes
+//! need the X-Api-Key header, and a missing or wrong key surfaces as an
+//! `unauthorized` ServiceError instead of a bare HTTP status. With
+//! `--api-keys-file`, a key without the route's role is `forbidden`.
+
+mod common;
+
+use canopy_client::credentials::read_credentials;
+use canopy_client::service_client::is_error_code;
+use canopy_client::ClientRuntime;
+use canopy_core::{CanopyError, QueryParams};
+use common::{FixtureRepo, TestService, READY_TIMEOUT};
+
+const KEY: &str = "test-secret";
+
+fn secured_service() -> TestService {
+    TestService::start_with_args(&["--api-key", KEY])
+}
+
+fn unauthorized_hint(err: &CanopyError) -> &str {
+    match err {
+        CanopyError::ServiceError { code, hint, .. } if code == "unauthorized" => hint,
+        other => panic!("expected unauthorized, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_correct_key_reaches_admin_and_query_routes() {
+    let repo = FixtureRepo::rust_sample();
+    let svc = secured_service();
+    let mut client = svc.client_with_key(Some(KEY));
+
+    let repo_id = client.resolve_repo_id(repo.path()).expect("add repo");
+    client.reindex(&repo_id, None).expect("reindex");
+    client.ensure_ready(&repo_id, READY_TIMEOUT).expect("ready");
+    assert!(client
+        .list_repos()
+        .unwrap()
+        .iter()
+        .any(|r| r.repo_id == repo_id));
+
+    let result = client
+        .query(&repo_id, QueryParams::symbol("multiply"))
+        .expect("query");
+    assert_eq!(result.handles.len(), 1);
+    let contents = client
+        .expand(&repo_id, &[result.handles[0].id.to_string()], None)
+        .expect("expand");
+    assert!(contents[0].1.contains("a * b"));
+
+    // Ops routes stay public and never need the key
+    svc.client_with_key(None).status().expect("public status");
+}
+
+#[test]
+fn test_missing_key_is_unauthorized() {
+    let repo = FixtureRepo::rust_sample();
+    let svc = secured_service();
+    let mut client = svc.client_with_key(None);
+
+    let err = client.list_repos().unwrap_err();
+    let hint = unauthorized_hint(&err);
+    assert!(hint.contains("requires an X-Api-Key header"), "{hint}");
+    assert!(hint.contains("--api-key"), "{hint}");
+    assert!(hint.contains("CANOPY_API_KEY"), "{hint}");
+    assert!(hint.contains(".canopy/credentials.toml"), "{hint}");
+
+    let err = client.resolve_repo_id(repo.path()).unwrap_err();
+    unauthorized_hint(&err);
+    let err = client.reindex("any", None).unwrap_err();
+    unauthorized_hint(&err);
+}
+
+#[test]
+fn test_wrong_key_is_unauthorized() {
+    let repo = FixtureRepo::rust_sample();
+    let svc = secured_service();
+    let repo_id = svc
+        .client_with_key(Some(KEY))
+        .resolve_repo_id(repo.path())
+        .unwrap();
+
+    let client = svc.client_with_key(Some("not-the-key"));
+    let err = client.list_repos().unwrap_err();
+    let hint = unauthorized_hint(&err);
+    assert!(hint.contains("rejected the X-Api-Key header"), "{hint}");
+
+    let err = client
+        .query(&repo_id, QueryParams::symbol("multiply"))
+        .unwrap_err();
+    assert!(is_error_code(&err, "unauthorized"), "got {err:?}");
+}
+
+#[test]
+fn test_runtime_uses_key_from_credentials_file() {
+    let repo = FixtureRepo::rust_sample();
+    let svc = secured_service();
+    std::fs::create_dir_all(repo.path().join(".canopy")).unwrap();
+    std::fs::write(
+        repo.path().join(".canopy/credentials.toml"),
+        format!("api_key = \"{KEY}\"\n"),
+    )
+    .unwrap();
+
+    let key = read_credentials(repo.path()).unwrap();
+    let mut rt = ClientRuntime::new(Some(&svc.base_url), key);
+    rt.index(repo.path(), None).expect("index with file key");
+    let result = rt
+        .query(repo.path(), QueryParams::symbol("Database"))
+        .expect("query with file key");
+    assert_eq!(result.handles.len(), 1);
+}
+
+#[test]
+fn test_key_without_role_is_forbidden() {
+    let repo = FixtureRepo::rust_sample();
+    let keys_dir = tempfile::TempDir::new().unwrap();
+    let keys = keys_dir.path().join("keys.toml");
+    std::fs::write(
+        &keys,
+        "[[keys]]\nname = \"dash\"\nkey = \"read-secret\"\nrole = \"read\"\n",
+    )
+    .unwrap();
+    let svc = TestService::start_with_args(&[
+        "--api-key",
+        KEY,
+        "--api-keys-file",
+        keys.to_str().unwrap(),
+    ]);
+    let mut admin = svc.client_with_key(Some(KEY));
+    let repo_id = admin.resolve_repo_id(repo.path()).expect("add repo");
+    admin.reindex(&repo_id, None).expect("reindex");
+    admin.ensure_ready(&repo_id, READY_TIMEOUT).expect("ready");
+
+    let reader = svc.client_with_key(Some("read-secret"));
+    let result = reader
+        .query(&repo_id, QueryParams::symbol("multiply"))
+        .expect("read key queries");
+    assert_eq!(result.handles.len(), 1);
+
+    let err = reader.reindex(&repo_id, None).unwrap_err();
+    match &err {
+        CanopyError::ServiceError {
+            code,
+            message,
+            hint,
+        } if code == "forbidden" => {
+            assert!(message.contains("role read"), "{message}");
+            assert!(hint.contains("reindex or admin"), "{hint}");
+            assert!(hint.contains("CANOPY_API_KEY"), "{hint}");
+        }
+        other => panic!("expected forbidden, got {other:?}"),
+    }
+    let err = reader.list_repos().unwrap_err();
+    assert!(is_error_code(&err, "forbidden"), "got {err:?}");
+}
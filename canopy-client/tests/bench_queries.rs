@@ -0,0 +1,101 @@
+//! Query benchmarks across two index states: snapshot a suite, change a file
+//! and reindex it, then compare the re-run against the snapshot.
+
+mod common;
+
+use canopy_client::runtime::{lock_index, ClientRuntime};
+use canopy_client::{compare_snapshots, BenchSnapshot, BenchSuite};
+use canopy_core::RepoIndex;
+use common::FixtureRepo;
+use std::path::Path;
+
+const AUTH_BEFORE: &str = r#"
+pub fn validate_token(token: &str) -> bool {
+    !token.is_empty()
+}
+
+pub fn start_session(user: &str) -> String {
+    format!("session:{user}")
+}
+"#;
+
+/// `validate_token` moves out of auth.rs and a second session function appears.
+const AUTH_AFTER: &str = r#"
+pub fn start_session(user: &str) -> String {
+    format!("session:{user}")
+}
+
+pub fn end_session(session: &str) {
+    drop(session);
+}
+"#;
+
+fn suite() -> BenchSuite {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/bench/queries.toml");
+    BenchSuite::load(&path).unwrap()
+}
+
+#[test]
+fn test_bench_queries_diff_two_index_states() {
+    let repo = FixtureRepo::new(&[
+        ("src/auth.rs", AUTH_BEFORE),
+        (
+            "src/config.rs",
+            "pub struct Config {\n    session_ttl: u64,\n}\n",
+        ),
+    ]);
+    RepoIndex::init(repo.path()).unwrap();
+    let mut rt = ClientRuntime::new(None, None);
+    rt.index(repo.path(), Some("**/*.rs")).unwrap();
+    let suite = suite();
+
+    let before = rt.bench_queries(repo.path(), &suite);
+    assert_eq!(before.queries.len(), 3);
+    assert!(before.queries.iter().all(|q| q.error.is_none()));
+    assert_eq!(before.queries[0].handles[0].file_path, "src/config.rs");
+
+    // Snapshots round-trip through the JSON file the CLI writes
+    let json = serde_json::to_string(&before).unwrap();
+    let before: BenchSnapshot = serde_json::from_str(&json).unwrap();
+
+    // Same index state: nothing changes, nothing regresses
+    let same = compare_snapshots(&suite, &before, &rt.bench_queries(repo.path(), &suite));
+    assert_eq!(same.queries_changed, 0, "{same:#?}");
+    assert!(!same.has_regressions());
+
+    repo.write("src/auth.rs", AUTH_AFTER);
+    // Invalidated first: the rewrite may land within the mtime tick of the
+    // original, which an mtime check alone would skip
+    let index = rt.open_local_index(repo.path()).unwrap();
+    assert_eq!(
+        lock_index(&index).invalidate(Some("src/auth.rs")).unwrap(),
+        1
+    );
+    rt.index_paths(repo.path(), &[repo.path().join("src/auth.rs")])
+        .unwrap();
+    let after = rt.bench_queries(repo.path(), &suite);
+    let report = compare_snapshots(&suite, &before, &after);
+
+    assert_eq!(report.queries_compared, 3);
+    let config = &report.queries[0];
+    assert!(!config.is_changed(), "{config:#?}");
+    assert!(config.regressions.is_empty());
+
+    let session = &report.queries[1];
+    assert!(session.is_changed());
+    assert!(
+        session.added.iter().all(|h| h.file_path == "src/auth.rs") && !session.added.is_empty(),
+        "{session:#?}"
+    );
+    // start_session moved up the file, so its handle id changed too
+    assert_eq!(session.regressions, vec!["1 handles removed (max 0)"]);
+
+    let token = &report.queries[2];
+    assert!(!token.removed.is_empty());
+    assert!(token
+        .regressions
+        .iter()
+        .any(|r| r.starts_with("src/auth.rs not returned")));
+    assert!(token.regressions.iter().any(|r| r.contains("removed")));
+    assert_eq!(report.queries_regressed, 2, "{report:#?}");
+}
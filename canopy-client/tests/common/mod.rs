@@ -0,0 +1,130 @@
+//! Shared fixtures for tests that run against a real canopy-service.
+//!
+//! `TestService` starts the service binary on an ephemeral port with its own
+//! temp working/state directory; `FixtureRepo` (from canopy-testutil) is a
+//! temp git repo whose files tests can commit, dirty or delete. File
+//! discovery is pinned to the builtin walker so results don't depend on
+//! `fd`/`rg` being installed.
+
+#![allow(dead_code)]
+
+use canopy_client::runtime::ClientRuntime;
+use canopy_client::ServiceClient;
+use canopy_core::FILE_DISCOVERY_ENV;
+pub use canopy_testutil::FixtureRepo;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// How long to wait for the service to come up or a repo to become ready.
+pub const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A canopy-service child process, killed on drop.
+pub struct TestService {
+    process: Child,
+    pub base_url: String,
+    /// Working directory of the service process; nothing it writes outside
+    /// registered repos lands in the developer's checkout or home.
+    pub state_dir: TempDir,
+}
+
+impl TestService {
+    pub fn start() -> Self {
+        Self::start_with_args(&[])
+    }
+
+    pub fn start_with_args(extra_args: &[&str]) -> Self {
+        let port = free_port();
+        let base_url = format!("http://127.0.0.1:{}", port);
+        let bin = service_binary();
+        assert!(bin.exists(), "canopy-service binary not found at {:?}", bin);
+
+        let state_dir = TempDir::new().unwrap();
+        let process = Command::new(&bin)
+            .args(["--port", &port.to_string()])
+            .args(extra_args)
+            .current_dir(state_dir.path())
+            .env("HOME", state_dir.path())
+            .env(FILE_DISCOVERY_ENV, "builtin")
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Failed to start canopy-service");
+
+        let svc = Self {
+            process,
+            base_url,
+            state_dir,
+        };
+        assert!(svc.wait_until_up(READY_TIMEOUT), "Service failed to start");
+        svc
+    }
+
+    fn wait_until_up(&self, timeout: Duration) -> bool {
+        let client = reqwest::blocking::Client::new();
+        let start = std::time::Instant::now();
+        while start.elapsed() < timeout {
+            if client
+                .get(format!("{}/status", self.base_url))
+                .send()
+                .is_ok()
+            {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        false
+    }
+
+    pub fn client(&self) -> ServiceClient {
+        ServiceClient::new(&self.base_url, None)
+    }
+
+    /// Client sending `api_key`, for a service started with `--api-key`.
+    pub fn client_with_key(&self, api_key: Option<&str>) -> ServiceClient {
+        ServiceClient::new(&self.base_url, api_key.map(String::from))
+    }
+
+    pub fn runtime(&self) -> ClientRuntime {
+        ClientRuntime::new(Some(&self.base_url), None)
+    }
+
+    /// Register `repo`, index it and wait until it is ready. Returns the repo_id.
+    pub fn register(&self, repo: &FixtureRepo) -> String {
+        let mut client = self.client();
+        let repo_id = client.resolve_repo_id(repo.path()).unwrap();
+        client.reindex(&repo_id, None).unwrap();
+        client.ensure_ready(&repo_id, READY_TIMEOUT).unwrap();
+        repo_id
+    }
+}
+
+impl Drop for TestService {
+    fn drop(&mut self) {
+        self.process.kill().ok();
+        self.process.wait().ok();
+    }
+}
+
+fn free_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// Find the canopy-service binary next to the test binary.
+fn service_binary() -> PathBuf {
+    workspace_binary("canopy-service")
+}
+
+/// Find the `canopy` CLI binary next to the test binary.
+pub fn cli_binary() -> PathBuf {
+    workspace_binary("canopy")
+}
+
+fn workspace_binary(name: &str) -> PathBuf {
+    let mut path = std::env::current_exe().unwrap();
+    path.pop(); // test binary name
+    path.pop(); // deps/
+    path.push(name);
+    path
+}
@@ -0,0 +1,221 @@
+//! End-to-end service workflow: a real canopy-service, a real git repo, and
+//! ClientRuntime driven through index → query → evidence pack → expand →
+//! dirty edit → merged query → reindex → expand across the generation bump,
+//! plus the error envelopes returned once the repo disappears.
+
+mod common;
+
+use canopy_client::runtime::IndexResult;
+use canopy_client::service_client::is_error_code;
+use canopy_core::{CanopyError, ErrorEnvelope, HandleSource, QueryParams};
+use canopy_testutil::git;
+use common::{FixtureRepo, TestService, READY_TIMEOUT};
+
+const DIRTY_MAIN: &str = r#"
+fn hello_world() {
+    println!("Hello, modified world!");
+}
+
+fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+fn new_local_function() {
+    // only exists in the dirty working tree
+}
+
+struct Config {
+    name: String,
+    port: u16,
+}
+"#;
+
+fn head_sha(repo: &FixtureRepo) -> String {
+    let out = git(repo.path(), &["rev-parse", "HEAD"]);
+    String::from_utf8_lossy(&out.stdout).trim().to_string()
+}
+
+#[test]
+fn test_service_workflow_end_to_end() {
+    let repo = FixtureRepo::rust_sample();
+    // No generation history, so a reindex makes the old handles stale
+    let svc = TestService::start_with_args(&["--retain-generations", "1"]);
+    let mut rt = svc.runtime();
+    assert!(rt.is_service_mode());
+
+    // Index: registers the repo and starts a service-side reindex
+    match rt.index(repo.path(), None).expect("index failed") {
+        IndexResult::Service(resp) => assert_eq!(resp.status, "indexing"),
+        IndexResult::Local(_) => panic!("service-mode index ran locally"),
+    }
+
+    // Query waits for the shard to become ready; handles carry service provenance
+    let result = rt
+        .query(repo.path(), QueryParams::symbol("multiply"))
+        .expect("query failed");
+    assert_eq!(result.handles.len(), 1);
+    let multiply = result.handles[0].clone();
+    assert_eq!(multiply.source, HandleSource::Service);
+    assert_eq!(
+        multiply.commit_sha.as_deref(),
+        Some(head_sha(&repo).as_str())
+    );
+    let first_generation = multiply.generation.expect("service handle generation");
+    assert!(rt.take_generation_changes().is_empty());
+
+    // Evidence pack is built server-side from the same generation
+    let pack = rt
+        .evidence_pack(repo.path(), QueryParams::pattern("multiply"), 8, 2, None)
+        .expect("evidence pack failed");
+    assert!(pack.selected_count >= 1);
+    assert!(pack
+        .handles
+        .iter()
+        .all(|h| h.source == HandleSource::Service && h.generation == Some(first_generation)));
+    assert!(pack.expand_suggestion.contains(&multiply.id.to_string()));
+
+    // Expand routes service handles to the service
+    let outcome = rt
+        .expand(repo.path(), &[multiply.id.to_string()], false)
+        .expect("expand failed");
+    assert!(outcome.failed_ids.is_empty());
+    assert!(outcome.contents[0].1.contains("a * b"));
+
+    // Dirty edit: the merged query takes main.rs from the local overlay and
+    // keeps service handles for the clean lib.rs
+    repo.write("src/main.rs", DIRTY_MAIN);
+    let merged = rt
+        .query(repo.path(), QueryParams::pattern("i32"))
+        .expect("merged query failed");
+    let main_sources: Vec<_> = merged
+        .handles
+        .iter()
+        .filter(|h| h.file_path.ends_with("main.rs"))
+        .map(|h| h.source.clone())
+        .collect();
+    let lib_sources: Vec<_> = merged
+        .handles
+        .iter()
+        .filter(|h| h.file_path.ends_with("lib.rs"))
+        .map(|h| h.source.clone())
+        .collect();
+    assert!(!main_sources.is_empty() && !lib_sources.is_empty());
+    assert!(main_sources.iter().all(|s| *s == HandleSource::Local));
+    assert!(lib_sources.iter().all(|s| *s == HandleSource::Service));
+
+    let local = rt
+        .query(repo.path(), QueryParams::symbol("new_local_function"))
+        .expect("local query failed");
+    assert_eq!(local.handles.len(), 1);
+    let local_handle = local.handles[0].clone();
+    assert_eq!(local_handle.source, HandleSource::Local);
+
+    // Local provenance routes expansion to the local index, which has the
+    // working-tree content
+    let outcome = rt
+        .expand(repo.path(), &[local_handle.id.to_string()], false)
+        .expect("local expand failed");
+    assert!(outcome.contents[0]
+        .1
+        .contains("only exists in the dirty working tree"));
+
+    // Commit and reindex: the generation moves on
+    repo.commit("add new_local_function");
+    match rt.index(repo.path(), None).expect("reindex failed") {
+        IndexResult::Service(resp) => assert_eq!(resp.generation, first_generation),
+        IndexResult::Local(_) => panic!("service-mode reindex ran locally"),
+    }
+    let mut client = svc.client();
+    let repo_id = client.resolve_repo_id(repo.path()).unwrap();
+    client.ensure_ready(&repo_id, READY_TIMEOUT).unwrap();
+
+    // Handles from the old generation are rejected as stale...
+    let err = client
+        .expand(&repo_id, &[multiply.id.to_string()], Some(first_generation))
+        .unwrap_err();
+    assert!(is_error_code(&err, "stale_generation"), "got {err:?}");
+    let Err(err) = rt.expand(repo.path(), &[multiply.id.to_string()], false) else {
+        panic!("stale service handle expanded");
+    };
+    assert!(matches!(err, CanopyError::HandleNotFound(_)), "got {err:?}");
+
+    // ...until a fresh query picks up the new generation
+    let requery = rt
+        .query(repo.path(), QueryParams::symbol("multiply"))
+        .expect("requery failed");
+    let refreshed = requery.handles[0].clone();
+    assert_eq!(refreshed.id, multiply.id, "unchanged node keeps its id");
+    let second_generation = refreshed.generation.unwrap();
+    assert!(second_generation > first_generation);
+    assert_eq!(
+        refreshed.commit_sha.as_deref(),
+        Some(head_sha(&repo).as_str())
+    );
+
+    let changes = rt.take_generation_changes();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].old_generation, first_generation);
+    assert_eq!(changes[0].new_generation, second_generation);
+
+    let outcome = rt
+        .expand(repo.path(), &[refreshed.id.to_string()], false)
+        .expect("expand after reindex failed");
+    assert!(outcome.failed_ids.is_empty());
+
+    // The committed function is now served by the service
+    let committed = rt
+        .query(repo.path(), QueryParams::symbol("new_local_function"))
+        .expect("query after commit failed");
+    assert_eq!(committed.handles.len(), 1);
+    assert_eq!(committed.handles[0].source, HandleSource::Service);
+}
+
+#[test]
+fn test_service_errors_after_repo_removed() {
+    let repo = FixtureRepo::rust_sample();
+    let svc = TestService::start();
+    let repo_id = svc.register(&repo);
+    let repo_path = repo.path().to_path_buf();
+
+    let mut rt = svc.runtime();
+    let result = rt
+        .query(&repo_path, QueryParams::symbol("Config"))
+        .expect("query failed");
+    let config_id = result.handles[0].id.to_string();
+
+    repo.remove();
+
+    // A reindex of the vanished repo fails in the background and marks the shard
+    let client = svc.client();
+    client.reindex(&repo_id, None).expect("reindex accepted");
+    let err = client.ensure_ready(&repo_id, READY_TIMEOUT).unwrap_err();
+    assert!(is_error_code(&err, "index_error"), "got {err:?}");
+
+    let err = client
+        .query(&repo_id, QueryParams::symbol("Config"))
+        .unwrap_err();
+    assert!(is_error_code(&err, "repo_not_ready"), "got {err:?}");
+
+    let err = client
+        .query("no-such-repo", QueryParams::symbol("Config"))
+        .unwrap_err();
+    assert!(is_error_code(&err, "repo_not_found"), "got {err:?}");
+
+    // Re-registering the path is rejected with a structured envelope
+    let resp = reqwest::blocking::Client::new()
+        .post(format!("{}/repos/add", svc.base_url))
+        .json(&serde_json::json!({ "path": repo_path.to_string_lossy() }))
+        .send()
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 400);
+    let envelope: ErrorEnvelope = resp.json().unwrap();
+    assert_eq!(envelope.code, "invalid_repo");
+    assert!(!envelope.hint.is_empty());
+
+    // The runtime surfaces the failure instead of retrying forever
+    assert!(rt.query(&repo_path, QueryParams::symbol("Config")).is_err());
+    let Err(err) = rt.expand(&repo_path, std::slice::from_ref(&config_id), false) else {
+        panic!("handle from a removed repo expanded");
+    };
+    assert!(matches!(err, CanopyError::HandleNotFound(_)), "got {err:?}");
+}
@@ -0,0 +1,147 @@
+//! One logical query, four ways in: a DSL string, structured params, CLI
+//! flags and the service's query route. Each must count its results the same
+//! way, so `limit`, `truncated` and `total_matches` agree across them.
+
+mod common;
+
+use canopy_core::{MatchMode, QueryKind, QueryParams, QueryResult, RepoIndex, FILE_DISCOVERY_ENV};
+use canopy_testutil::presets::CROWDED_INSIDE;
+use common::{cli_binary, TestService};
+use std::path::Path;
+use std::process::Command;
+
+/// A query as structured params, as a DSL expression and as CLI flags.
+struct Case {
+    params: QueryParams,
+    dsl: &'static str,
+    flags: &'static [&'static str],
+}
+
+fn cases() -> Vec<Case> {
+    vec![
+        // A glob checked after the symbol search
+        Case {
+            params: QueryParams::symbol("handler").with_glob("src/**"),
+            dsl: r#"(in-file "src/**" (code "handler"))"#,
+            flags: &["--symbol", "handler", "--glob", "src/**"],
+        },
+        // match: all, where most of the first pattern's hits lack the second
+        Case {
+            params: QueryParams::patterns(vec!["handler".into(), "src_only".into()])
+                .with_match_mode(MatchMode::All),
+            dsl: r#"(intersect (grep "handler") (grep "src_only"))"#,
+            flags: &["--patterns", "handler", "src_only", "--match", "all"],
+        },
+        // References rather than handles
+        Case {
+            params: QueryParams::symbol("dispatch").with_kind(QueryKind::Reference),
+            dsl: r#"(refs "dispatch")"#,
+            flags: &["--symbol", "dispatch", "--kind", "reference"],
+        },
+    ]
+}
+
+/// Results returned, `truncated` and `total_matches`.
+fn counted(result: &QueryResult) -> (usize, bool, usize) {
+    let returned = match &result.ref_handles {
+        Some(refs) => refs.len(),
+        None => result.handles.len(),
+    };
+    (returned, result.truncated, result.total_matches)
+}
+
+fn dsl_params(query: String) -> QueryParams {
+    QueryParams {
+        dsl: Some(query),
+        ..QueryParams::new()
+    }
+}
+
+/// `canopy` run standalone in `root`, ignoring any service the environment
+/// points at.
+fn canopy(root: &Path, home: &Path, args: &[&str]) -> std::process::Output {
+    let output = Command::new(cli_binary())
+        .args(args)
+        .current_dir(root)
+        .env("HOME", home)
+        .env(FILE_DISCOVERY_ENV, "builtin")
+        .env_remove("CANOPY_SERVICE_URL")
+        .output()
+        .expect("failed to run canopy");
+    assert!(
+        output.status.success(),
+        "canopy {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+fn cli_query(root: &Path, home: &Path, flags: &[&str], limit: usize) -> QueryResult {
+    let limit = limit.to_string();
+    let mut args = vec!["--json", "query"];
+    args.extend_from_slice(flags);
+    args.extend(["--limit", limit.as_str()]);
+    let output = canopy(root, home, &args);
+    // The result comes first; summary lines may follow it
+    serde_json::Deserializer::from_slice(&output.stdout)
+        .into_iter()
+        .next()
+        .expect("canopy --json query output")
+        .expect("a query result")
+}
+
+#[test]
+fn every_entry_point_counts_limited_results_alike() {
+    let repo = canopy_testutil::crowded_glob().with_git_history().build();
+    let svc = TestService::start();
+    let repo_id = svc.register(&repo);
+    let service = svc.client();
+
+    // The service indexed the checkout in place; the CLI and the params
+    // read that same index
+    let home = tempfile::TempDir::new().unwrap();
+    canopy(repo.path(), home.path(), &["index"]);
+    let index = RepoIndex::open(repo.path()).unwrap();
+
+    for case in cases() {
+        for limit in [5, 16] {
+            let params = case.params.clone().with_limit(limit);
+            let expected = counted(&index.query_params(params.clone()).unwrap());
+            let entries = [
+                (
+                    "dsl",
+                    index.query_params(dsl_params(format!("(limit {limit} {})", case.dsl))),
+                ),
+                (
+                    "dsl with limit",
+                    index.query_params(dsl_params(case.dsl.to_string()).with_limit(limit)),
+                ),
+                (
+                    "cli",
+                    Ok(cli_query(repo.path(), home.path(), case.flags, limit)),
+                ),
+                ("service", service.query(&repo_id, params)),
+            ];
+            for (entry, result) in entries {
+                assert_eq!(
+                    counted(&result.unwrap()),
+                    expected,
+                    "{entry}: {} with limit {limit}",
+                    case.dsl
+                );
+            }
+        }
+    }
+
+    // Filtering after the search doesn't eat into the limit
+    let glob = index
+        .query_params(cases()[0].params.clone().with_limit(16))
+        .unwrap();
+    assert_eq!(counted(&glob), (CROWDED_INSIDE, false, CROWDED_INSIDE));
+    let capped = index
+        .query_params(cases()[1].params.clone().with_limit(5))
+        .unwrap();
+    assert_eq!(counted(&capped).0, 5);
+    assert!(capped.truncated);
+}
@@ -4,201 +4,15 @@
 //! that ClientRuntime correctly queries the service, detects dirty files, and
 //! merges results.
 
-use canopy_client::runtime::ClientRuntime;
-use canopy_client::ExpandOutcome;
-use canopy_core::{HandleSource, QueryParams};
-use std::process::Command;
-use std::time::Duration;
-use tempfile::TempDir;
+mod common;
 
-// ---------------------------------------------------------------------------
-// Helpers
-// ---------------------------------------------------------------------------
-
-fn git(root: &std::path::Path, args: &[&str]) -> std::process::Output {
-    let output = Command::new("git")
-        .args(args)
-        .current_dir(root)
-        .output()
-        .unwrap();
-    assert!(
-        output.status.success(),
-        "git {} failed: {}",
-        args.join(" "),
-        String::from_utf8_lossy(&output.stderr)
-    );
-    output
-}
-
-/// Create a test git repo with two Rust files.
-fn create_test_repo() -> TempDir {
-    let dir = TempDir::new().unwrap();
-    let root = dir.path();
-
-    git(root, &["init"]);
-    git(root, &["config", "user.email", "test@test.com"]);
-    git(root, &["config", "user.name", "Test"]);
-    git(root, &["config", "commit.gpgsign", "false"]);
-
-    std::fs::create_dir_all(root.join("src")).unwrap();
-    std::fs::write(
-        root.join("src/main.rs"),
-        r#"
-fn hello_world() {
-    println!("Hello, world!");
-}
-
-fn add(a: i32, b: i32) -> i32 {
-    a + b
-}
-
-struct Config {
-    name: String,
-    port: u16,
-}
-"#,
-    )
-    .unwrap();
-
-    std::fs::write(
-        root.join("src/lib.rs"),
-        r#"
-pub fn multiply(a: i32, b: i32) -> i32 {
-    a * b
-}
-
-pub struct Database {
-    url: String,
-}
-"#,
-    )
-    .unwrap();
-
-    git(root, &["add", "."]);
-    git(root, &["commit", "-m", "init"]);
-
-    dir
-}
-
-fn free_port() -> u16 {
-    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
-    listener.local_addr().unwrap().port()
-}
-
-/// Find the canopy-service binary next to the test binary.
-fn service_binary() -> std::path::PathBuf {
-    let mut path = std::env::current_exe().unwrap();
-    path.pop(); // test binary name
-    path.pop(); // deps/
-    path.push("canopy-service");
-    path
-}
-
-fn wait_for_service(base_url: &str, timeout: Duration) -> bool {
-    let client = reqwest::blocking::Client::new();
-    let start = std::time::Instant::now();
-    while start.elapsed() < timeout {
-        if client.get(&format!("{}/status", base_url)).send().is_ok() {
-            return true;
-        }
-        std::thread::sleep(Duration::from_millis(100));
-    }
-    false
-}
-
-/// Start a canopy-service, register+reindex a repo, and return (service, port, repo_id).
-struct TestService {
-    _process: std::process::Child,
-    base_url: String,
-    #[allow(dead_code)]
-    repo_id: String,
-    _repo_dir: TempDir,
-    repo_path: std::path::PathBuf,
-}
-
-impl TestService {
-    fn start(repo_dir: TempDir) -> Self {
-        let port = free_port();
-        let base_url = format!("http://127.0.0.1:{}", port);
-        let bin = service_binary();
-        assert!(bin.exists(), "canopy-service binary not found at {:?}", bin);
-
-        let process = Command::new(&bin)
-            .args(["--port", &port.to_string()])
-            .spawn()
-            .expect("Failed to start canopy-service");
-
-        assert!(
-            wait_for_service(&base_url, Duration::from_secs(5)),
-            "Service failed to start"
-        );
-
-        let client = reqwest::blocking::Client::new();
-
-        // Add repo
-        let resp: serde_json::Value = client
-            .post(&format!("{}/repos/add", base_url))
-            .json(&serde_json::json!({
-                "path": repo_dir.path().to_string_lossy().to_string(),
-                "name": "test-repo"
-            }))
-            .send()
-            .unwrap()
-            .json()
-            .unwrap();
-        let repo_id = resp["repo_id"].as_str().unwrap().to_string();
-
-        // Reindex
-        client
-            .post(&format!("{}/reindex", base_url))
-            .json(&serde_json::json!({ "repo": &repo_id }))
-            .send()
-            .unwrap();
-
-        // Wait for ready
-        let mut ready = false;
-        for _ in 0..50 {
-            std::thread::sleep(Duration::from_millis(200));
-            let resp: serde_json::Value = client
-                .get(&format!("{}/repos", base_url))
-                .send()
-                .unwrap()
-                .json()
-                .unwrap();
-            if let Some(repos) = resp.as_array() {
-                if let Some(repo) = repos
-                    .iter()
-                    .find(|r| r["repo_id"].as_str() == Some(&repo_id))
-                {
-                    if repo["status"].as_str() == Some("ready") {
-                        ready = true;
-                        break;
-                    }
-                }
-            }
-        }
-        assert!(ready, "Repo never became ready");
-
-        let repo_path = repo_dir.path().to_path_buf();
-        TestService {
-            _process: process,
-            base_url,
-            repo_id,
-            _repo_dir: repo_dir,
-            repo_path,
-        }
-    }
-
-    fn runtime(&self) -> ClientRuntime {
-        ClientRuntime::new(Some(&self.base_url), None)
-    }
-}
-
-impl Drop for TestService {
-    fn drop(&mut self) {
-        self._process.kill().ok();
-    }
-}
+use canopy_client::service_client::is_error_code;
+use canopy_client::{ClientContext, ExpandOutcome, RefreshStatus};
+use canopy_core::feedback::FeedbackStore;
+use canopy_core::{HandleSource, NodeType, QueryParams, RefType};
+use common::{FixtureRepo, TestService};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
 
 // ---------------------------------------------------------------------------
 // Tests
@@ -206,13 +20,14 @@ impl Drop for TestService {
 
 #[test]
 fn test_service_query_returns_service_handles() {
-    let repo = create_test_repo();
-    let svc = TestService::start(repo);
+    let repo = FixtureRepo::rust_sample();
+    let svc = TestService::start();
+    svc.register(&repo);
     let mut rt = svc.runtime();
     assert!(rt.is_service_mode());
 
     let params = QueryParams::symbol("hello_world".to_string());
-    let result = rt.query(&svc.repo_path, params).expect("query failed");
+    let result = rt.query(repo.path(), params).expect("query failed");
 
     assert!(!result.handles.is_empty(), "Expected at least one handle");
     for handle in &result.handles {
@@ -224,13 +39,14 @@ fn test_service_query_returns_service_handles() {
 
 #[test]
 fn test_dirty_merge_replaces_handles_for_modified_file() {
-    let repo = create_test_repo();
-    let svc = TestService::start(repo);
+    let repo = FixtureRepo::rust_sample();
+    let svc = TestService::start();
+    svc.register(&repo);
     let mut rt = svc.runtime();
 
     // Modify src/main.rs locally (without committing)
     std::fs::write(
-        svc.repo_path.join("src/main.rs"),
+        repo.path().join("src/main.rs"),
         r#"
 fn hello_world() {
     println!("Hello, modified world!");
@@ -254,7 +70,7 @@ struct Config {
 
     // Query for a symbol in the modified file
     let params = QueryParams::pattern("hello_world".to_string());
-    let result = rt.query(&svc.repo_path, params).expect("query failed");
+    let result = rt.query(repo.path(), params).expect("query failed");
 
     // Handles for the dirty file (src/main.rs) should be Local
     let main_handles: Vec<_> = result
@@ -275,13 +91,14 @@ struct Config {
 
 #[test]
 fn test_dirty_merge_keeps_service_handles_for_clean_files() {
-    let repo = create_test_repo();
-    let svc = TestService::start(repo);
+    let repo = FixtureRepo::rust_sample();
+    let svc = TestService::start();
+    svc.register(&repo);
     let mut rt = svc.runtime();
 
     // Modify only main.rs
     std::fs::write(
-        svc.repo_path.join("src/main.rs"),
+        repo.path().join("src/main.rs"),
         r#"
 fn hello_world() {
     println!("Modified!");
@@ -292,7 +109,7 @@ fn hello_world() {
 
     // Query for a symbol in the clean file (lib.rs)
     let params = QueryParams::symbol("multiply".to_string());
-    let result = rt.query(&svc.repo_path, params).expect("query failed");
+    let result = rt.query(repo.path(), params).expect("query failed");
 
     // Handles for the clean file (src/lib.rs) should be Service
     let lib_handles: Vec<_> = result
@@ -314,19 +131,20 @@ fn hello_world() {
 
 #[test]
 fn test_expand_service_handles() {
-    let repo = create_test_repo();
-    let svc = TestService::start(repo);
+    let repo = FixtureRepo::rust_sample();
+    let svc = TestService::start();
+    svc.register(&repo);
     let mut rt = svc.runtime();
 
     // Query to get handles
     let params = QueryParams::symbol("Config".to_string());
-    let result = rt.query(&svc.repo_path, params).expect("query failed");
+    let result = rt.query(repo.path(), params).expect("query failed");
     assert!(!result.handles.is_empty());
 
     // Expand the first handle
     let handle_ids: Vec<String> = result.handles.iter().map(|h| h.id.to_string()).collect();
     let outcome: ExpandOutcome = rt
-        .expand(&svc.repo_path, &handle_ids)
+        .expand(repo.path(), &handle_ids, false)
         .expect("expand failed");
 
     assert!(
@@ -340,3 +158,439 @@ fn test_expand_service_handles() {
         .any(|(_, content)| content.contains("Config"));
     assert!(has_config, "Expanded content should contain Config");
 }
+
+#[test]
+fn test_explore_expands_service_handles_within_budget() {
+    let repo = FixtureRepo::rust_sample();
+    let svc = TestService::start();
+    let repo_id = svc.register(&repo);
+    let mut rt = svc.runtime();
+
+    for budget in [10_000, 40] {
+        let params = QueryParams::pattern("a".to_string());
+        let exploration = rt
+            .explore(repo.path(), params, budget, 8, 2)
+            .expect("explore failed");
+        assert!(!exploration.pack.handles.is_empty());
+        assert!(exploration
+            .pack
+            .handles
+            .iter()
+            .all(|h| h.source == HandleSource::Service && h.generation.is_some()));
+        assert!(exploration.failed_ids.is_empty());
+        assert!(exploration.tokens_spent <= budget);
+        if budget == 10_000 {
+            assert_eq!(
+                exploration.expanded.len(),
+                exploration.pack.handles.len(),
+                "a generous budget expands the whole pack"
+            );
+            assert!(exploration.expanded.iter().all(|h| !h.truncated));
+        }
+    }
+
+    let by_id = rt
+        .explore_by_repo_id(&repo_id, QueryParams::symbol("Config"), 10_000, 8, 2)
+        .expect("explore by repo_id failed");
+    assert!(by_id
+        .expanded
+        .iter()
+        .any(|h| h.content.contains("struct Config")));
+}
+
+#[test]
+fn test_repo_id_queries_skip_the_local_checkout() {
+    let repo = FixtureRepo::rust_sample();
+    let svc = TestService::start();
+    let repo_id = svc.register(&repo);
+    let mut rt = svc.runtime();
+
+    // A dirty working tree must not be merged in: the caller has no checkout
+    std::fs::write(
+        repo.path().join("src/extra.rs"),
+        "fn caller() {\n    hello_world(); // only in the working tree\n}\n",
+    )
+    .unwrap();
+
+    let result = rt
+        .query_by_repo_id(&repo_id, QueryParams::pattern("hello_world".to_string()))
+        .expect("query failed");
+    assert!(!result.handles.is_empty());
+    assert!(result
+        .handles
+        .iter()
+        .all(|h| h.source == HandleSource::Service && h.file_path != "src/extra.rs"));
+    let sources = result.sources.expect("source counts");
+    assert_eq!(sources.local, 0);
+
+    let ids: Vec<String> = result.handles.iter().map(|h| h.id.to_string()).collect();
+    let outcome = rt.expand_by_repo_id(&repo_id, &ids).expect("expand failed");
+    assert!(outcome.failed_ids.is_empty());
+    assert!(outcome
+        .contents
+        .iter()
+        .all(|(_, content)| !content.contains("only in the working tree")));
+
+    let pack = rt
+        .evidence_pack_by_repo_id(
+            &repo_id,
+            QueryParams::symbol("Config".to_string()),
+            8,
+            2,
+            None,
+        )
+        .expect("evidence pack failed");
+    assert!(pack.selected_count > 0);
+    assert_eq!(rt.repo_status_by_id(&repo_id).unwrap().repo_id, repo_id);
+    assert!(rt.repo_status_by_id("no-such-repo").is_err());
+
+    // By path, the same query merges the untracked file in
+    let by_path = rt
+        .query(repo.path(), QueryParams::pattern("hello_world".to_string()))
+        .expect("query failed");
+    assert!(by_path
+        .handles
+        .iter()
+        .any(|h| h.source == HandleSource::Local && h.file_path == "src/extra.rs"));
+}
+
+#[test]
+fn test_warmup_preloads_registered_repos() {
+    let repo = FixtureRepo::rust_sample();
+    let svc = TestService::start();
+    let repo_id = svc.register(&repo);
+    let client = svc.client();
+
+    let response = client.warmup(None).expect("warmup failed");
+    let warmed = response
+        .repos
+        .iter()
+        .find(|r| r.repo_id == repo_id)
+        .expect("registered repo was not warmed");
+    assert!(warmed.error.is_none(), "{:?}", warmed.error);
+    assert!(warmed.readers >= 1);
+    assert!(warmed.report.as_ref().unwrap().symbols > 0);
+
+    let shard = client
+        .list_repos()
+        .unwrap()
+        .into_iter()
+        .find(|s| s.repo_id == repo_id)
+        .unwrap();
+    assert!(shard.last_warmup_at.is_some());
+
+    let unknown = client
+        .warmup(Some(vec!["no-such-repo".to_string()]))
+        .expect("unknown repos are reported, not raised");
+    assert!(unknown.repos[0].error.is_some());
+}
+
+#[test]
+fn test_warmup_on_start_warms_each_repo_once_ready() {
+    let repo = FixtureRepo::rust_sample();
+    let svc = TestService::start_with_args(&["--warmup-on-start"]);
+    let repo_id = svc.register(&repo);
+    let client = svc.client();
+
+    // The warm-up runs after the reindex reports ready
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    loop {
+        let shard = client
+            .list_repos()
+            .unwrap()
+            .into_iter()
+            .find(|s| s.repo_id == repo_id)
+            .unwrap();
+        if shard.last_warmup_at.is_some() {
+            break;
+        }
+        assert!(
+            std::time::Instant::now() < deadline,
+            "repo was never warmed"
+        );
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_evidence_pack_applies_query_priors() {
+    let repo = FixtureRepo::new(&[
+        (
+            "docs/retries.md",
+            "# Retry policy\n\nFailed uploads retry three times with backoff.\n",
+        ),
+        (
+            "src/retry.rs",
+            "pub fn retry_upload(attempts: u32) -> bool {\n    // retry with backoff\n    attempts < 3\n}\n",
+        ),
+    ]);
+    let svc = TestService::start();
+    svc.register(&repo);
+    let mut rt = svc.runtime();
+
+    let top_type = |rt: &mut canopy_client::ClientRuntime, params: QueryParams| {
+        let pack = rt
+            .evidence_pack(repo.path(), params, 8, 4, None)
+            .expect("evidence pack failed");
+        pack.handles[0].node_type
+    };
+
+    assert_eq!(
+        top_type(&mut rt, QueryParams::pattern("retry")),
+        NodeType::Function
+    );
+    let mut boosted = QueryParams::pattern("retry");
+    boosted.priors = Some(BTreeMap::from([
+        ("section".to_string(), 2.0),
+        ("function".to_string(), 0.5),
+    ]));
+    assert_eq!(top_type(&mut rt, boosted), NodeType::Section);
+
+    let mut typo = QueryParams::pattern("retry");
+    typo.priors = Some(BTreeMap::from([("fucntion".to_string(), 2.0)]));
+    let err = rt
+        .evidence_pack(repo.path(), typo, 8, 4, None)
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("expected one of"), "{err}");
+}
+
+#[test]
+fn test_list_symbols_pages_through_the_service() {
+    let repo = FixtureRepo::new(&[
+        (
+            "src/handlers.rs",
+            "pub fn handle_login() {}\npub fn handle_logout() {}\npub struct Handler;\n",
+        ),
+        ("src/other.rs", "pub fn unrelated() {}\n"),
+    ]);
+    let svc = TestService::start();
+    svc.register(&repo);
+    let mut rt = svc.runtime();
+
+    let mut names = Vec::new();
+    let mut after = None;
+    loop {
+        let page = rt
+            .list_symbols(repo.path(), Some("HAND"), &[], after.as_deref(), Some(1))
+            .expect("list symbols failed");
+        names.extend(page.symbols.into_iter().map(|s| s.name));
+        match page.next_after {
+            Some(next) => after = Some(next),
+            None => break,
+        }
+    }
+    assert_eq!(names, ["handle_login", "handle_logout", "Handler"]);
+
+    let structs = rt
+        .list_symbols(repo.path(), None, &[NodeType::Struct], None, None)
+        .expect("list symbols failed");
+    assert_eq!(structs.symbols.len(), 1);
+    assert_eq!(structs.symbols[0].file_path, "src/handlers.rs");
+}
+
+#[test]
+fn test_references_group_by_file_through_the_service() {
+    let repo = FixtureRepo::new(&[
+        ("src/lib.rs", "pub fn dispatch() {}\n"),
+        (
+            "src/server.rs",
+            "pub fn serve() {\n    dispatch();\n    dispatch();\n}\n",
+        ),
+        ("tests/server.rs", "use crate::dispatch;\n"),
+    ]);
+    let svc = TestService::start();
+    svc.register(&repo);
+    let mut rt = svc.runtime();
+
+    let all = rt
+        .references(repo.path(), "dispatch", &[], None, None)
+        .expect("references failed");
+    assert_eq!(all.total_files, 2);
+    assert_eq!(all.files[0].file_path, "src/server.rs");
+    assert_eq!(all.files[0].lines, [2, 3]);
+
+    let imports = rt
+        .references(
+            repo.path(),
+            "dispatch",
+            &[RefType::Import],
+            Some("tests/**"),
+            None,
+        )
+        .expect("references failed");
+    assert_eq!(imports.files.len(), 1);
+    assert_eq!(imports.files[0].file_path, "tests/server.rs");
+    assert_eq!(
+        imports.files[0].ref_types,
+        BTreeMap::from([("import".to_string(), 1)])
+    );
+}
+
+#[test]
+fn test_client_context_reaches_service_metrics_and_feedback() {
+    let repo = FixtureRepo::rust_sample();
+    let svc = TestService::start();
+    let repo_id = svc.register(&repo);
+    let mut rt = svc.runtime();
+    rt.set_client_context(ClientContext::new(Some("itest-agent"), Some("sess-42")));
+
+    let result = rt
+        .query(repo.path(), QueryParams::symbol("Config".to_string()))
+        .expect("query failed");
+    let handle_ids: Vec<String> = result.handles.iter().map(|h| h.id.to_string()).collect();
+    rt.expand(repo.path(), &handle_ids, false)
+        .expect("expand failed");
+
+    // Many sessions land in a bounded number of metrics buckets
+    let mut client = svc.client();
+    for i in 0..40 {
+        client.set_client_context(ClientContext::new(None, Some(&format!("burst-{i}"))));
+        client
+            .query(&repo_id, QueryParams::symbol("add".to_string()))
+            .expect("query failed");
+    }
+
+    let metrics: serde_json::Value = reqwest::blocking::get(format!("{}/metrics", svc.base_url))
+        .unwrap()
+        .json()
+        .unwrap();
+    let analytics = &metrics["analytics"];
+    assert!(
+        analytics["requests_by_client"]["itest-agent"]
+            .as_u64()
+            .unwrap()
+            >= 2
+    );
+    let buckets = analytics["requests_by_session_bucket"].as_object().unwrap();
+    assert!(buckets.len() <= 16, "{buckets:?}");
+    let total: u64 = buckets.values().map(|n| n.as_u64().unwrap()).sum();
+    assert!(total >= 42, "{buckets:?}");
+    assert!(!analytics.to_string().contains("sess-42"));
+
+    // The service recorded the query under the session, with its client
+    let store = FeedbackStore::open(repo.path()).unwrap();
+    let sessions = store.compute_session_metrics(1.0).unwrap();
+    let session = sessions
+        .iter()
+        .find(|s| s.session_id.as_deref() == Some("sess-42"))
+        .expect("no feedback for sess-42");
+    assert_eq!(session.client_name.as_deref(), Some("itest-agent"));
+    assert!(session.metrics.sample_count >= 1);
+    assert!(session.metrics.handle_expand_accept_rate > 0.0);
+}
+
+#[test]
+fn test_retained_generations_expand_until_pruned() {
+    let repo = FixtureRepo::rust_sample();
+    let svc = TestService::start_with_args(&["--retain-generations", "2"]);
+    let repo_id = svc.register(&repo);
+    let client = svc.client();
+
+    // The handle (and the live index) of each generation as it is built
+    let hello_handle = |generation: u64| {
+        let result = client
+            .query(&repo_id, QueryParams::symbol("hello_world"))
+            .unwrap();
+        let handle = result.handles.into_iter().next().expect("hello_world");
+        assert_eq!(handle.generation, Some(generation));
+        (handle.id.to_string(), handle.generation)
+    };
+    let reindex = |body: &str, step: u64| {
+        repo.write(
+            "src/main.rs",
+            &format!("\nfn hello_world() {{\n    {body}\n}}\n"),
+        );
+        // Later than `write`'s own push, so edits within one second still differ
+        std::fs::File::options()
+            .write(true)
+            .open(repo.path().join("src/main.rs"))
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60 + 10 * step))
+            .unwrap();
+        repo.commit(body);
+        client.reindex(&repo_id, None).unwrap();
+        client
+            .ensure_ready(&repo_id, common::READY_TIMEOUT)
+            .unwrap();
+    };
+    let expand = |(id, generation): &(String, Option<u64>)| {
+        client.expand(&repo_id, std::slice::from_ref(id), *generation)
+    };
+    let retained = || {
+        let shard = client
+            .list_repos()
+            .unwrap()
+            .into_iter()
+            .find(|shard| shard.repo_id == repo_id)
+            .unwrap();
+        shard
+            .retained_generations
+            .iter()
+            .map(|g| {
+                assert!(g.size_bytes > 0);
+                assert!(g.commit_sha.is_some());
+                g.generation.value()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Each rewrite changes hello_world's span, and with it the handle id
+    let first = hello_handle(1);
+    reindex("first_edit();", 1);
+    let second = hello_handle(2);
+    assert_eq!(retained(), [1]);
+    let old = expand(&first).expect("generation 1 is retained");
+    assert!(old[0].1.contains("Hello, world!"), "{:?}", old);
+    assert!(expand(&second).unwrap()[0].1.contains("first_edit"));
+
+    reindex("second_edit();", 2);
+    assert_eq!(retained(), [2]);
+    let err = expand(&first).unwrap_err();
+    assert!(is_error_code(&err, "stale_generation"), "{err:?}");
+    assert!(expand(&second).unwrap()[0].1.contains("first_edit"));
+    assert!(!repo.path().join(".canopy/generations/1").exists());
+
+    let pruned = client.prune_generations(Some(&repo_id), Some(1)).unwrap();
+    assert_eq!(pruned.pruned.len(), 1);
+    assert!(pruned.freed_bytes > 0);
+    assert!(retained().is_empty());
+    assert!(is_error_code(
+        &expand(&second).unwrap_err(),
+        "stale_generation"
+    ));
+}
+
+#[test]
+fn test_refresh_handles_reindexes_files_on_the_service() {
+    let repo = FixtureRepo::rust_sample();
+    let svc = TestService::start();
+    svc.register(&repo);
+    let mut rt = svc.runtime();
+
+    let result = rt
+        .query(repo.path(), QueryParams::symbol("multiply"))
+        .expect("query failed");
+    let old_generation = result.handles[0].generation.unwrap();
+    let id = result.handles[0].id.to_string();
+    repo.write(
+        "src/lib.rs",
+        "\npub fn multiply(a: i32, b: i32) -> i32 {\n    a * b * 1\n}\n",
+    );
+
+    let report = rt
+        .refresh_handles(repo.path(), std::slice::from_ref(&id))
+        .expect("refresh failed");
+    assert_eq!(report.handles[&id].status, RefreshStatus::Refreshed);
+    assert_eq!(report.files_reindexed, 1);
+    assert!(report.generation.unwrap() > old_generation, "{report:?}");
+
+    // The service now answers from the refreshed file
+    let result = rt
+        .query(repo.path(), QueryParams::symbol("multiply"))
+        .expect("query failed");
+    let outcome = rt
+        .expand(repo.path(), &[result.handles[0].id.to_string()], false)
+        .expect("expand failed");
+    assert!(outcome.contents[0].1.contains("a * b * 1"));
+}
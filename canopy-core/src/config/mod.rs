@@ -0,0 +1,911 @@
+//! Configuration for canopy
+
+mod presets;
+
+use crate::query::CalibrationConfig;
+use crate::CanopyError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+pub use presets::Preset;
+
+/// Generate default configuration as a TOML string.
+///
+/// Single source of truth: values come from the `Default` impls on each config
+/// section.  The TOML is serialised at runtime so it can never silently diverge
+/// from the defaults used when a field is missing from a user-provided file.
+pub fn default_config_toml() -> String {
+    toml::to_string_pretty(&Config::default()).expect("Config serialization cannot fail")
+}
+
+/// Canopy configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub core: CoreConfig,
+    #[serde(default)]
+    pub indexing: IndexingConfig,
+    #[serde(default)]
+    pub fts: FtsConfig,
+    #[serde(default)]
+    pub ignore: IgnoreConfig,
+    #[serde(default)]
+    pub rerank: RerankConfig,
+    #[serde(default)]
+    pub annotations: AnnotationsConfig,
+    #[serde(default)]
+    pub generated: GeneratedConfig,
+    #[serde(default)]
+    pub feedback: FeedbackConfig,
+    #[serde(default)]
+    pub ranking: RankingConfig,
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub evidence: EvidenceConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoreConfig {
+    /// Legacy freshness window. Indexing skip checks use `[indexing] stat_ttl`
+    /// and `verify` instead.
+    #[serde(default = "default_ttl")]
+    pub ttl: String,
+    #[serde(default = "default_encoding")]
+    pub encoding: String,
+    #[serde(default = "default_result_limit")]
+    pub default_result_limit: usize,
+    /// Minimum similarity (0-1) for "did you mean" symbol suggestions
+    #[serde(default = "default_suggestion_threshold")]
+    pub suggestion_threshold: f64,
+    /// Most whole-file handles a `(file "glob")` query returns per page
+    #[serde(default = "default_file_max_files")]
+    pub file_max_files: usize,
+    /// Token budget for one page of whole-file handles; the first file is
+    /// always returned, however large
+    #[serde(default = "default_file_max_tokens")]
+    pub file_max_tokens: usize,
+    /// Estimated bytes the in-memory symbol cache of each index database may
+    /// hold. Past it the cache keeps the shortest names and looks the rest
+    /// up in the database. 0 disables the cap.
+    #[serde(default = "default_symbol_cache_max_bytes")]
+    pub symbol_cache_max_bytes: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexingConfig {
+    #[serde(default = "default_glob")]
+    pub default_glob: String,
+    #[serde(default = "default_chunk_threshold")]
+    pub chunk_threshold: usize,
+    #[serde(default = "default_chunk_lines")]
+    pub chunk_lines: usize,
+    #[serde(default = "default_chunk_overlap")]
+    pub chunk_overlap: usize,
+    #[serde(default = "default_preview_bytes")]
+    pub preview_bytes: usize,
+    /// Directory patterns (e.g. `services/*`) whose matches each get their own
+    /// database under `.canopy/shards/`. Empty keeps a single `index.db`.
+    #[serde(default)]
+    pub shard_by: Vec<String>,
+    /// Descend into symlinked directories and index symlinked files. Files are
+    /// stored under their repo-relative symlink path.
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    /// Skip files outright (no stat, no hash) while their index row is younger
+    /// than this. `"0s"` checks every file on every run.
+    #[serde(default = "default_stat_ttl")]
+    pub stat_ttl: String,
+    /// How long fd or rg may take to list files before the walk fails; a
+    /// hung tool (say, on a dead network mount) is killed at this point
+    #[serde(default = "default_discovery_timeout")]
+    pub discovery_timeout: String,
+    /// Evidence required to skip reparsing once `stat_ttl` has lapsed
+    #[serde(default)]
+    pub verify: VerifyMode,
+    /// On reindex of a changed file, keep the stored rows of nodes whose
+    /// content is unchanged and only rewrite the rest (and their FTS rows).
+    /// Off rewrites every node of the file.
+    #[serde(default)]
+    pub incremental_nodes: bool,
+    /// Deepest key of JSON, YAML and TOML files indexed as its own node
+    /// (1 indexes top-level keys only)
+    #[serde(default = "default_config_key_depth")]
+    pub config_key_depth: usize,
+    /// Most key nodes per config file; the shallowest keys are kept
+    #[serde(default = "default_config_max_keys")]
+    pub config_max_keys: usize,
+    /// Markdown sections whose own text exceeds this many tokens are split
+    /// at block boundaries into `Heading (part i/n)` sections. 0 never splits.
+    #[serde(default = "default_max_section_tokens")]
+    pub max_section_tokens: usize,
+    /// Nodes with more content than this are full-text indexed from a sample
+    /// (head, tail, and lines introducing new identifiers). 0 indexes it all.
+    #[serde(default = "default_max_fts_bytes_per_node")]
+    pub max_fts_bytes_per_node: usize,
+    /// Compare paths case-folded in globs, invalidate and dirty-file merging.
+    /// Unset follows the host: on for Windows and macOS, off elsewhere.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub case_insensitive_paths: Option<bool>,
+    /// Record each file's last commit time, so recency filters see when a
+    /// file last changed rather than when it was checked out
+    #[serde(default)]
+    pub git_commit_times: bool,
+}
+
+/// What proves an indexed file unchanged, so it need not be reparsed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyMode {
+    /// An unchanged mtime is enough
+    Mtime,
+    /// The content hash must match. Reads every file, but catches rewrites that
+    /// keep the mtime (e.g. files restored from a cache archive).
+    Hash,
+    /// An unchanged mtime, or failing that a matching content hash
+    #[default]
+    MtimeThenHash,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FtsConfig {
+    #[serde(default = "default_tokenizer")]
+    pub tokenizer: String,
+    /// Merge the FTS segments (FTS5 `optimize`) once this many tokens have
+    /// been reparsed since the last merge. 0 disables.
+    #[serde(default = "default_optimize_after_tokens")]
+    pub optimize_after_tokens: usize,
+    /// Most candidate rows one full-text search reads before it stops and
+    /// answers from those, flagging the result `truncated_scan`. 0 disables.
+    #[serde(default = "default_max_fts_candidates")]
+    pub max_fts_candidates: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IgnoreConfig {
+    #[serde(default = "default_ignore_patterns")]
+    pub patterns: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RerankConfig {
+    /// Shell command that reorders query candidates (see `query::rerank`).
+    /// Unset disables reranking.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Give up on the command and keep the original order after this long
+    #[serde(default = "default_rerank_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnnotationsConfig {
+    /// Comment markers indexed as annotations. Matched case-sensitively as
+    /// whole words inside comments.
+    #[serde(default = "default_annotation_markers")]
+    pub markers: Vec<String>,
+}
+
+/// How indexing recognizes generated files (protobuf outputs, bundles, ...),
+/// which queries leave out unless `include_generated` is set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeneratedConfig {
+    /// Marker comments (e.g. `@generated`) that flag a file when they appear
+    /// in its first lines. Matched case-sensitively.
+    #[serde(default = "default_generated_markers")]
+    pub markers: Vec<String>,
+    /// File name globs (or path globs, when they contain `/`) that flag a file
+    #[serde(default = "default_generated_patterns")]
+    pub patterns: Vec<String>,
+    /// Flag files whose average line is longer than this many bytes, as
+    /// minified or bundled code is. 0 disables the check.
+    #[serde(default = "default_generated_max_avg_line_length")]
+    pub max_avg_line_length: usize,
+}
+
+/// How much query/expand history `.canopy/feedback.db` keeps. Enforced
+/// whenever the store is opened, and by `canopy feedback prune`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedbackConfig {
+    /// Drop events older than this many days
+    #[serde(default = "default_feedback_retention_days")]
+    pub retention_days: u64,
+    /// Keep at most this many query events, and as many expand events,
+    /// dropping the oldest first
+    #[serde(default = "default_feedback_max_rows")]
+    pub max_rows: usize,
+}
+
+/// How results mixing definitions, members, reference sources and content
+/// hits share a query's limit (see `query::result_class`). Each class gets
+/// its weighted share; the defaults heavily favor definitions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RankingConfig {
+    #[serde(default = "default_definition_weight")]
+    pub definition_weight: f64,
+    #[serde(default = "default_member_weight")]
+    pub member_weight: f64,
+    #[serde(default = "default_reference_weight")]
+    pub reference_weight: f64,
+    #[serde(default = "default_content_weight")]
+    pub content_weight: f64,
+}
+
+/// Secrets masked in content leaving canopy: expanded handles and previews
+/// (see `redaction`). Previews are masked when indexed, so changes to this
+/// section reach them on the next reindex.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedactionConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Label -> regex. A match becomes `[REDACTED:<label>]`; when the regex
+    /// has a group named `secret`, only that group is replaced.
+    #[serde(default = "default_redaction_patterns")]
+    pub patterns: BTreeMap<String, String>,
+    /// Path globs whose content is never redacted (e.g. `docs/**`)
+    #[serde(default)]
+    pub allow_paths: Vec<String>,
+}
+
+/// The git-history layer: commit messages and touched files of recent
+/// commits, searched with `(commits "...")` (see `index::history`). Off by
+/// default; nothing is stored until it is enabled or `canopy index
+/// --git-history` runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryConfig {
+    /// Ingest new commits on every index run
+    #[serde(default)]
+    pub enabled: bool,
+    /// How far back commits are kept, e.g. `"90d"`; older ones are dropped
+    #[serde(default = "default_history_since")]
+    pub since: String,
+    /// Token cap on the diff `canopy show-commit` returns
+    #[serde(default = "default_history_max_diff_tokens")]
+    pub max_diff_tokens: usize,
+}
+
+/// Calibration of evidence pack confidence and `stop_querying` (see
+/// [`CalibrationConfig`]). `preset` picks the starting values; every other
+/// key overrides one of them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvidenceConfig {
+    /// `default`, `strict` or `lenient`
+    #[serde(default = "default_evidence_preset")]
+    pub preset: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub medium_threshold: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub high_threshold: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_threshold: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_handles_for_high: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_term_coverage: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncation_penalty: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overflow_penalty: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub definition_boost: Option<f64>,
+}
+
+// Default value functions
+fn default_ttl() -> String {
+    "1h".to_string()
+}
+fn default_encoding() -> String {
+    "cl100k_base".to_string()
+}
+fn default_result_limit() -> usize {
+    100
+}
+fn default_suggestion_threshold() -> f64 {
+    0.6
+}
+fn default_file_max_files() -> usize {
+    50
+}
+fn default_file_max_tokens() -> usize {
+    50_000
+}
+fn default_symbol_cache_max_bytes() -> usize {
+    256 * 1024 * 1024
+}
+fn default_stat_ttl() -> String {
+    "0s".to_string()
+}
+fn default_discovery_timeout() -> String {
+    "30s".to_string()
+}
+fn default_glob() -> String {
+    "**/*.{rs,py,ipynb,js,ts,tsx,jsx,go,md,txt,json,yaml,yml,toml}".to_string()
+}
+fn default_chunk_threshold() -> usize {
+    1_000_000
+}
+fn default_chunk_lines() -> usize {
+    50
+}
+fn default_chunk_overlap() -> usize {
+    10
+}
+fn default_preview_bytes() -> usize {
+    100
+}
+fn default_config_key_depth() -> usize {
+    3
+}
+fn default_config_max_keys() -> usize {
+    500
+}
+fn default_max_section_tokens() -> usize {
+    1200
+}
+fn default_max_fts_bytes_per_node() -> usize {
+    32 * 1024
+}
+fn default_tokenizer() -> String {
+    "unicode61".to_string()
+}
+fn default_optimize_after_tokens() -> usize {
+    5_000_000
+}
+fn default_max_fts_candidates() -> usize {
+    50_000
+}
+fn default_rerank_timeout_ms() -> u64 {
+    5_000
+}
+fn default_annotation_markers() -> Vec<String> {
+    ["TODO", "FIXME", "HACK", "XXX"]
+        .iter()
+        .map(|m| m.to_string())
+        .collect()
+}
+fn default_generated_markers() -> Vec<String> {
+    ["@generated", "DO NOT EDIT"]
+        .iter()
+        .map(|m| m.to_string())
+        .collect()
+}
+fn default_generated_patterns() -> Vec<String> {
+    [
+        "*_pb2.py",
+        "*_pb2_grpc.py",
+        "*.pb.go",
+        "*.pb.ts",
+        "*.bundle.js",
+        "*.generated.*",
+    ]
+    .iter()
+    .map(|p| p.to_string())
+    .collect()
+}
+fn default_generated_max_avg_line_length() -> usize {
+    500
+}
+fn default_definition_weight() -> f64 {
+    8.0
+}
+fn default_member_weight() -> f64 {
+    4.0
+}
+fn default_reference_weight() -> f64 {
+    2.0
+}
+fn default_content_weight() -> f64 {
+    1.0
+}
+fn default_feedback_retention_days() -> u64 {
+    90
+}
+fn default_feedback_max_rows() -> usize {
+    500_000
+}
+fn default_evidence_preset() -> String {
+    "default".to_string()
+}
+fn default_history_since() -> String {
+    "90d".to_string()
+}
+fn default_history_max_diff_tokens() -> usize {
+    2_000
+}
+fn default_true() -> bool {
+    true
+}
+fn default_redaction_patterns() -> BTreeMap<String, String> {
+    [
+        ("aws_access_key", r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b"),
+        (
+            "aws_secret_key",
+            r#"(?i)aws_secret_access_key["']?\s*[:=]\s*["']?(?P<secret>[A-Za-z0-9/+=]{40})"#,
+        ),
+        (
+            "bearer_token",
+            r"(?i)\bbearer\s+(?P<secret>[A-Za-z0-9\-._~+/]{16,}=*)",
+        ),
+        ("github_token", r"\bgh[pousr]_[A-Za-z0-9]{36,}\b"),
+        (
+            "private_key",
+            r"-----BEGIN [A-Z ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z ]*PRIVATE KEY-----",
+        ),
+    ]
+    .into_iter()
+    .map(|(label, pattern)| (label.to_string(), pattern.to_string()))
+    .collect()
+}
+fn default_ignore_patterns() -> Vec<String> {
+    vec![
+        ".git".to_string(),
+        ".canopy".to_string(),
+        "node_modules".to_string(),
+        "target".to_string(),
+        "__pycache__".to_string(),
+        ".venv".to_string(),
+        "venv".to_string(),
+        "*.min.js".to_string(),
+        "*.min.css".to_string(),
+        ".DS_Store".to_string(),
+        "*.lock".to_string(),
+        "package-lock.json".to_string(),
+        "npm-shrinkwrap.json".to_string(),
+        "pnpm-lock.yaml".to_string(),
+        "Cargo.lock".to_string(),
+    ]
+}
+
+impl Default for CoreConfig {
+    fn default() -> Self {
+        Self {
+            ttl: default_ttl(),
+            encoding: default_encoding(),
+            default_result_limit: default_result_limit(),
+            suggestion_threshold: default_suggestion_threshold(),
+            file_max_files: default_file_max_files(),
+            file_max_tokens: default_file_max_tokens(),
+            symbol_cache_max_bytes: default_symbol_cache_max_bytes(),
+        }
+    }
+}
+
+impl Default for IndexingConfig {
+    fn default() -> Self {
+        Self {
+            default_glob: default_glob(),
+            chunk_threshold: default_chunk_threshold(),
+            chunk_lines: default_chunk_lines(),
+            chunk_overlap: default_chunk_overlap(),
+            preview_bytes: default_preview_bytes(),
+            shard_by: Vec::new(),
+            follow_symlinks: false,
+            stat_ttl: default_stat_ttl(),
+            discovery_timeout: default_discovery_timeout(),
+            verify: VerifyMode::default(),
+            incremental_nodes: false,
+            config_key_depth: default_config_key_depth(),
+            config_max_keys: default_config_max_keys(),
+            max_section_tokens: default_max_section_tokens(),
+            max_fts_bytes_per_node: default_max_fts_bytes_per_node(),
+            case_insensitive_paths: None,
+            git_commit_times: false,
+        }
+    }
+}
+
+impl Default for FtsConfig {
+    fn default() -> Self {
+        Self {
+            tokenizer: default_tokenizer(),
+            optimize_after_tokens: default_optimize_after_tokens(),
+            max_fts_candidates: default_max_fts_candidates(),
+        }
+    }
+}
+
+impl Default for IgnoreConfig {
+    fn default() -> Self {
+        Self {
+            patterns: default_ignore_patterns(),
+        }
+    }
+}
+
+impl Default for RerankConfig {
+    fn default() -> Self {
+        Self {
+            command: None,
+            timeout_ms: default_rerank_timeout_ms(),
+        }
+    }
+}
+
+impl Default for AnnotationsConfig {
+    fn default() -> Self {
+        Self {
+            markers: default_annotation_markers(),
+        }
+    }
+}
+
+impl Default for GeneratedConfig {
+    fn default() -> Self {
+        Self {
+            markers: default_generated_markers(),
+            patterns: default_generated_patterns(),
+            max_avg_line_length: default_generated_max_avg_line_length(),
+        }
+    }
+}
+
+impl Default for FeedbackConfig {
+    fn default() -> Self {
+        Self {
+            retention_days: default_feedback_retention_days(),
+            max_rows: default_feedback_max_rows(),
+        }
+    }
+}
+
+impl Default for RankingConfig {
+    fn default() -> Self {
+        Self {
+            definition_weight: default_definition_weight(),
+            member_weight: default_member_weight(),
+            reference_weight: default_reference_weight(),
+            content_weight: default_content_weight(),
+        }
+    }
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            patterns: default_redaction_patterns(),
+            allow_paths: Vec::new(),
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            since: default_history_since(),
+            max_diff_tokens: default_history_max_diff_tokens(),
+        }
+    }
+}
+
+impl Default for EvidenceConfig {
+    fn default() -> Self {
+        Self {
+            preset: default_evidence_preset(),
+            medium_threshold: None,
+            high_threshold: None,
+            stop_threshold: None,
+            min_handles_for_high: None,
+            min_term_coverage: None,
+            truncation_penalty: None,
+            overflow_penalty: None,
+            definition_boost: None,
+        }
+    }
+}
+
+impl FeedbackConfig {
+    /// The `[feedback]` section of the repo's `.canopy/config.toml`, or the
+    /// defaults when there is no config file.
+    pub fn for_repo(repo_root: &Path) -> crate::Result<Self> {
+        Ok(Config::for_repo(repo_root)?.feedback)
+    }
+}
+
+impl HistoryConfig {
+    /// The `[history]` section of the repo's `.canopy/config.toml`, or the
+    /// defaults when there is no config file.
+    pub fn for_repo(repo_root: &Path) -> crate::Result<Self> {
+        Ok(Config::for_repo(repo_root)?.history)
+    }
+}
+
+impl EvidenceConfig {
+    /// The `[evidence]` section of the repo's `.canopy/config.toml`, or the
+    /// defaults when there is no config file.
+    pub fn for_repo(repo_root: &Path) -> crate::Result<Self> {
+        Ok(Config::for_repo(repo_root)?.evidence)
+    }
+
+    /// The named preset with this section's overrides applied
+    pub fn calibration(&self) -> crate::Result<CalibrationConfig> {
+        let mut calibration = CalibrationConfig::preset(&self.preset).ok_or_else(|| {
+            CanopyError::ConfigParse(format!(
+                "[evidence] preset: expected one of {}, got {:?}",
+                CalibrationConfig::PRESETS.join(", "),
+                self.preset
+            ))
+        })?;
+        let overrides = [
+            (&mut calibration.medium_threshold, self.medium_threshold),
+            (&mut calibration.high_threshold, self.high_threshold),
+            (&mut calibration.stop_threshold, self.stop_threshold),
+            (&mut calibration.min_term_coverage, self.min_term_coverage),
+            (&mut calibration.truncation_penalty, self.truncation_penalty),
+            (&mut calibration.overflow_penalty, self.overflow_penalty),
+            (&mut calibration.definition_boost, self.definition_boost),
+        ];
+        for (value, setting) in overrides {
+            if let Some(setting) = setting {
+                *value = setting;
+            }
+        }
+        if let Some(min_handles) = self.min_handles_for_high {
+            calibration.min_handles_for_high = min_handles;
+        }
+        Ok(calibration)
+    }
+}
+
+impl Config {
+    /// Load config from a TOML file
+    pub fn load(path: &Path) -> crate::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Self::from_toml(&content)
+    }
+
+    /// The repo's `.canopy/config.toml`, or the defaults when there is no
+    /// config file.
+    pub fn for_repo(repo_root: &Path) -> crate::Result<Self> {
+        let config_path = repo_root.join(".canopy").join("config.toml");
+        if config_path.exists() {
+            Self::load(&config_path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Parse config from TOML string
+    pub fn from_toml(content: &str) -> crate::Result<Self> {
+        toml::from_str(content).map_err(|e| CanopyError::ConfigParse(e.to_string()))
+    }
+
+    /// Get TTL as Duration
+    pub fn ttl_duration(&self) -> Duration {
+        parse_duration(&self.core.ttl).unwrap_or(Duration::from_secs(3600))
+    }
+
+    /// Get `[indexing] stat_ttl` as Duration (zero if unparseable)
+    pub fn stat_ttl_duration(&self) -> Duration {
+        parse_duration(&self.indexing.stat_ttl).unwrap_or(Duration::ZERO)
+    }
+
+    /// Get `[indexing] discovery_timeout` as Duration (30s if unparseable or zero)
+    pub fn discovery_timeout_duration(&self) -> Duration {
+        parse_duration(&self.indexing.discovery_timeout)
+            .filter(|timeout| !timeout.is_zero())
+            .unwrap_or(Duration::from_secs(30))
+    }
+
+    /// Get `[history] since` as Duration
+    pub fn history_window(&self) -> crate::Result<Duration> {
+        parse_duration(&self.history.since).ok_or_else(|| {
+            CanopyError::ConfigParse(format!(
+                "[history] since: expected a duration like \"90d\", got {:?}",
+                self.history.since
+            ))
+        })
+    }
+
+    /// Get the default glob pattern
+    pub fn default_glob(&self) -> &str {
+        &self.indexing.default_glob
+    }
+}
+
+/// Parse duration string (e.g., "1h", "30m", "1d", "2w")
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (unit_at, _) = s.char_indices().last()?;
+
+    let (num_str, unit) = s.split_at(unit_at);
+    let num: u64 = num_str.parse().ok()?;
+
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 7 * 86400,
+        _ => return None,
+    };
+    num.checked_mul(unit_secs).map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config_roundtrips() {
+        let toml = default_config_toml();
+        let config = Config::from_toml(&toml).unwrap();
+        assert_eq!(config.core.ttl, "1h");
+        assert_eq!(config.core.default_result_limit, 100);
+        assert_eq!(config.core.suggestion_threshold, 0.6);
+        assert_eq!(config.indexing.chunk_threshold, 1_000_000);
+        assert!(config.indexing.shard_by.is_empty());
+        assert!(!config.indexing.follow_symlinks);
+        assert_eq!(config.stat_ttl_duration(), Duration::ZERO);
+        assert_eq!(config.indexing.verify, VerifyMode::MtimeThenHash);
+        assert!(config.rerank.command.is_none());
+        assert_eq!(config.rerank.timeout_ms, 5_000);
+    }
+
+    #[test]
+    fn test_shard_by_parses_from_indexing_section() {
+        let config =
+            Config::from_toml("[indexing]\nshard_by = [\"services/*\", \"libs/*\"]\n").unwrap();
+        assert_eq!(config.indexing.shard_by, vec!["services/*", "libs/*"]);
+        assert_eq!(config.indexing.chunk_lines, 50);
+    }
+
+    #[test]
+    fn test_verify_mode_parses_snake_case() {
+        let config =
+            Config::from_toml("[indexing]\nstat_ttl = \"10m\"\nverify = \"hash\"\n").unwrap();
+        assert_eq!(config.indexing.verify, VerifyMode::Hash);
+        assert_eq!(config.stat_ttl_duration(), Duration::from_secs(600));
+        assert!(Config::from_toml("[indexing]\nverify = \"sometimes\"\n").is_err());
+    }
+
+    #[test]
+    fn test_annotation_markers_are_configurable() {
+        assert_eq!(
+            Config::default().annotations.markers,
+            vec!["TODO", "FIXME", "HACK", "XXX"]
+        );
+        let config =
+            Config::from_toml("[annotations]\nmarkers = [\"TODO\", \"SAFETY\"]\n").unwrap();
+        assert_eq!(config.annotations.markers, vec!["TODO", "SAFETY"]);
+    }
+
+    #[test]
+    fn test_generated_detection_is_configurable() {
+        let defaults = Config::default().generated;
+        assert!(defaults.markers.contains(&"@generated".to_string()));
+        assert_eq!(defaults.max_avg_line_length, 500);
+        let config =
+            Config::from_toml("[generated]\npatterns = [\"gen/**\"]\nmax_avg_line_length = 0\n")
+                .unwrap();
+        assert_eq!(config.generated.patterns, vec!["gen/**"]);
+        assert_eq!(config.generated.max_avg_line_length, 0);
+        assert_eq!(config.generated.markers, defaults.markers);
+    }
+
+    #[test]
+    fn test_feedback_retention_is_configurable() {
+        let defaults = Config::default().feedback;
+        assert_eq!(defaults.retention_days, 90);
+        assert_eq!(defaults.max_rows, 500_000);
+        let config = Config::from_toml("[feedback]\nretention_days = 7\n").unwrap();
+        assert_eq!(config.feedback.retention_days, 7);
+        assert_eq!(config.feedback.max_rows, defaults.max_rows);
+    }
+
+    #[test]
+    fn test_redaction_is_configurable() {
+        let defaults = Config::from_toml(&default_config_toml()).unwrap().redaction;
+        assert!(defaults.enabled);
+        assert_eq!(defaults.patterns, default_redaction_patterns());
+        assert!(defaults.allow_paths.is_empty());
+        let config =
+            Config::from_toml("[redaction]\nenabled = false\nallow_paths = [\"docs/**\"]\n")
+                .unwrap();
+        assert!(!config.redaction.enabled);
+        assert_eq!(config.redaction.allow_paths, vec!["docs/**"]);
+        assert_eq!(config.redaction.patterns, defaults.patterns);
+    }
+
+    #[test]
+    fn test_evidence_presets_take_overrides() {
+        let defaults = Config::default().evidence.calibration().unwrap();
+        assert_eq!(defaults, CalibrationConfig::default());
+
+        let config =
+            Config::from_toml("[evidence]\npreset = \"strict\"\nmin_handles_for_high = 5\n")
+                .unwrap();
+        let strict = config.evidence.calibration().unwrap();
+        assert_eq!(strict.min_handles_for_high, 5);
+        assert_eq!(
+            strict.stop_threshold,
+            CalibrationConfig::preset("strict").unwrap().stop_threshold
+        );
+
+        let bad = Config::from_toml("[evidence]\npreset = \"paranoid\"\n").unwrap();
+        assert!(matches!(
+            bad.evidence.calibration(),
+            Err(CanopyError::ConfigParse(_))
+        ));
+    }
+
+    #[test]
+    fn test_history_is_opt_in() {
+        let defaults = Config::from_toml(&default_config_toml()).unwrap();
+        assert!(!defaults.history.enabled);
+        assert_eq!(
+            defaults.history_window().unwrap(),
+            Duration::from_secs(90 * 86400)
+        );
+        let config = Config::from_toml("[history]\nenabled = true\nsince = \"2w\"\n").unwrap();
+        assert!(config.history.enabled);
+        assert_eq!(config.history.max_diff_tokens, 2_000);
+        assert_eq!(
+            config.history_window().unwrap(),
+            Duration::from_secs(1_209_600)
+        );
+        let bad = Config::from_toml("[history]\nsince = \"forever\"\n").unwrap();
+        assert!(matches!(
+            bad.history_window(),
+            Err(CanopyError::ConfigParse(_))
+        ));
+    }
+
+    #[test]
+    fn test_sections_load_from_the_repo_config_or_default() {
+        let repo = tempfile::TempDir::new().unwrap();
+        assert!(!HistoryConfig::for_repo(repo.path()).unwrap().enabled);
+        assert_eq!(
+            EvidenceConfig::for_repo(repo.path()).unwrap().preset,
+            "default"
+        );
+
+        std::fs::create_dir_all(repo.path().join(".canopy")).unwrap();
+        std::fs::write(
+            repo.path().join(".canopy/config.toml"),
+            "[history]\nenabled = true\n\n[evidence]\npreset = \"strict\"\n\n\
+             [feedback]\nretention_days = 7\n",
+        )
+        .unwrap();
+        assert!(HistoryConfig::for_repo(repo.path()).unwrap().enabled);
+        assert_eq!(
+            EvidenceConfig::for_repo(repo.path()).unwrap().preset,
+            "strict"
+        );
+        assert_eq!(
+            FeedbackConfig::for_repo(repo.path())
+                .unwrap()
+                .retention_days,
+            7
+        );
+
+        std::fs::write(repo.path().join(".canopy/config.toml"), "[history\n").unwrap();
+        assert!(matches!(
+            Config::for_repo(repo.path()),
+            Err(CanopyError::ConfigParse(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("2d"), Some(Duration::from_secs(172800)));
+        assert_eq!(parse_duration("2w"), Some(Duration::from_secs(1_209_600)));
+        assert_eq!(parse_duration("invalid"), None);
+        assert_eq!(parse_duration("7д"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    #[test]
+    fn test_ttl_duration() {
+        let config = Config::default();
+        assert_eq!(config.ttl_duration(), Duration::from_secs(3600));
+    }
+}
@@ -0,0 +1,311 @@
+//! Ecosystem presets for `canopy init`.
+//!
+//! Each preset is plain data layered over [`Config::default`]: the index glob,
+//! extra ignore patterns for the ecosystem's build output, and result sizing.
+//! Detection looks only at marker files in the repo root, so the CLI and the
+//! service's repo registration pick the same preset for the same checkout.
+
+use super::Config;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A named starting configuration for a repo's ecosystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    Rust,
+    Node,
+    Python,
+    Go,
+    /// No single ecosystem: the generic defaults
+    Mixed,
+}
+
+/// Overrides for one ecosystem. [`Preset::Mixed`] has none.
+struct PresetSpec {
+    preset: Preset,
+    /// Root files whose presence selects this preset during detection
+    markers: &'static [&'static str],
+    default_glob: &'static str,
+    /// Added to the default ignore list
+    ignore: &'static [&'static str],
+    preview_bytes: usize,
+    default_result_limit: usize,
+}
+
+const PRESETS: &[PresetSpec] = &[
+    PresetSpec {
+        preset: Preset::Rust,
+        markers: &["Cargo.toml"],
+        default_glob: "**/*.{rs,toml,md}",
+        ignore: &["target"],
+        preview_bytes: 120,
+        default_result_limit: 100,
+    },
+    PresetSpec {
+        preset: Preset::Node,
+        markers: &["package.json", "tsconfig.json"],
+        default_glob: "**/*.{js,jsx,mjs,cjs,ts,tsx,mts,cts,json,md}",
+        ignore: &[
+            "node_modules",
+            "dist",
+            "build",
+            "out",
+            "coverage",
+            ".next",
+            ".nuxt",
+            ".turbo",
+            ".cache",
+            "*.map",
+            "yarn.lock",
+            "pnpm-lock.yaml",
+        ],
+        preview_bytes: 100,
+        default_result_limit: 50,
+    },
+    PresetSpec {
+        preset: Preset::Python,
+        markers: &[
+            "pyproject.toml",
+            "setup.py",
+            "setup.cfg",
+            "requirements.txt",
+        ],
+        default_glob: "**/*.{py,pyi,ipynb,toml,cfg,md,rst}",
+        ignore: &[
+            ".tox",
+            ".nox",
+            ".mypy_cache",
+            ".pytest_cache",
+            ".ruff_cache",
+            "build",
+            "dist",
+            "*.egg-info",
+            ".eggs",
+        ],
+        preview_bytes: 120,
+        default_result_limit: 100,
+    },
+    PresetSpec {
+        preset: Preset::Go,
+        markers: &["go.mod"],
+        default_glob: "**/*.{go,mod,md,yaml,yml}",
+        ignore: &["vendor", "bin", "go.sum"],
+        preview_bytes: 120,
+        default_result_limit: 100,
+    },
+];
+
+impl Preset {
+    /// Every preset, in `--list-presets` order
+    pub const ALL: [Preset; 5] = [
+        Preset::Rust,
+        Preset::Node,
+        Preset::Python,
+        Preset::Go,
+        Preset::Mixed,
+    ];
+
+    fn spec(self) -> Option<&'static PresetSpec> {
+        PRESETS.iter().find(|spec| spec.preset == self)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Rust => "rust",
+            Preset::Node => "node",
+            Preset::Python => "python",
+            Preset::Go => "go",
+            Preset::Mixed => "mixed",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Preset::Rust => "Cargo crates and workspaces",
+            Preset::Node => "JavaScript/TypeScript packages and monorepos",
+            Preset::Python => "Python packages and applications",
+            Preset::Go => "Go modules",
+            Preset::Mixed => "Several or no recognized ecosystems; generic defaults",
+        }
+    }
+
+    /// Pick a preset from marker files in `repo_root`. A root matching no
+    /// ecosystem, or more than one, is [`Preset::Mixed`].
+    pub fn detect(repo_root: &Path) -> Preset {
+        let mut found = PRESETS.iter().filter(|spec| {
+            spec.markers
+                .iter()
+                .any(|marker| repo_root.join(marker).is_file())
+        });
+        match (found.next(), found.next()) {
+            (Some(spec), None) => spec.preset,
+            _ => Preset::Mixed,
+        }
+    }
+
+    /// The configuration this preset writes.
+    pub fn config(self) -> Config {
+        let mut config = Config::default();
+        let Some(spec) = self.spec() else {
+            return config;
+        };
+        config.indexing.default_glob = spec.default_glob.to_string();
+        config.indexing.preview_bytes = spec.preview_bytes;
+        config.core.default_result_limit = spec.default_result_limit;
+        for pattern in spec.ignore {
+            if !config.ignore.patterns.iter().any(|p| p == pattern) {
+                config.ignore.patterns.push(pattern.to_string());
+            }
+        }
+        config
+    }
+
+    /// `config.toml` contents: the preset's config, with the other presets'
+    /// globs as commented-out alternatives.
+    pub fn config_toml(self) -> String {
+        let body =
+            toml::to_string_pretty(&self.config()).expect("Config serialization cannot fail");
+        let mut header = format!(
+            "# Generated by `canopy init --preset {}` ({}).\n#\n\
+             # Alternative [indexing] default_glob values from other presets:\n",
+            self.name(),
+            self.description()
+        );
+        for other in Preset::ALL.into_iter().filter(|p| *p != self) {
+            header.push_str(&format!(
+                "#   default_glob = \"{}\"  # {}\n",
+                other.config().indexing.default_glob,
+                other.name()
+            ));
+        }
+        format!("{header}\n{body}")
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Preset::ALL
+            .into_iter()
+            .find(|preset| preset.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<&str> = Preset::ALL.iter().map(|p| p.name()).collect();
+                format!(
+                    "unknown preset '{s}' (expected one of: {})",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn glob_matches(preset: Preset, path: &str) -> bool {
+        globset::Glob::new(&preset.config().indexing.default_glob)
+            .unwrap()
+            .compile_matcher()
+            .is_match(path)
+    }
+
+    #[test]
+    fn preset_globs_match_ecosystem_files() {
+        let fixtures: &[(Preset, &[&str])] = &[
+            (
+                Preset::Rust,
+                &["src/main.rs", "crates/core/src/lib.rs", "Cargo.toml"],
+            ),
+            (
+                Preset::Node,
+                &[
+                    "src/App.tsx",
+                    "packages/ui/src/Button.jsx",
+                    "scripts/build.mjs",
+                    "server/index.cts",
+                    "package.json",
+                ],
+            ),
+            (
+                Preset::Python,
+                &[
+                    "app/main.py",
+                    "stubs/app.pyi",
+                    "notebooks/eda.ipynb",
+                    "pyproject.toml",
+                    "setup.cfg",
+                ],
+            ),
+            (Preset::Go, &["cmd/server/main.go", "go.mod"]),
+            (Preset::Mixed, &["src/lib.rs", "web/App.tsx", "tool.py"]),
+        ];
+        for (preset, files) in fixtures {
+            for file in *files {
+                assert!(glob_matches(*preset, file), "{preset} should match {file}");
+            }
+        }
+        assert!(!glob_matches(Preset::Rust, "web/App.tsx"));
+        assert!(!glob_matches(Preset::Go, "src/lib.rs"));
+    }
+
+    #[test]
+    fn preset_ignores_ecosystem_build_dirs() {
+        let node = Preset::Node.config();
+        for dir in ["node_modules", "dist", ".next"] {
+            assert!(node.ignore.patterns.iter().any(|p| p == dir));
+        }
+        // Preset ignores extend the defaults rather than replacing them
+        assert!(node.ignore.patterns.iter().any(|p| p == ".git"));
+        assert!(Preset::Python
+            .config()
+            .ignore
+            .patterns
+            .iter()
+            .any(|p| p == "*.egg-info"));
+    }
+
+    #[test]
+    fn detect_uses_root_markers() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert_eq!(Preset::detect(dir.path()), Preset::Mixed);
+
+        fs::write(dir.path().join("package.json"), "{}").unwrap();
+        assert_eq!(Preset::detect(dir.path()), Preset::Node);
+
+        fs::write(dir.path().join("Cargo.toml"), "[package]").unwrap();
+        assert_eq!(Preset::detect(dir.path()), Preset::Mixed);
+    }
+
+    #[test]
+    fn config_toml_roundtrips_with_commented_alternatives() {
+        for preset in Preset::ALL {
+            let toml = preset.config_toml();
+            let config = Config::from_toml(&toml).unwrap();
+            assert_eq!(
+                config.indexing.default_glob,
+                preset.config().indexing.default_glob
+            );
+            assert_eq!(toml.matches("#   default_glob").count(), 4);
+        }
+    }
+
+    #[test]
+    fn preset_names_parse() {
+        for preset in Preset::ALL {
+            assert_eq!(preset.name().parse::<Preset>().unwrap(), preset);
+        }
+        assert_eq!("Node".parse::<Preset>().unwrap(), Preset::Node);
+        assert!("java".parse::<Preset>().unwrap_err().contains("rust, node"));
+    }
+}
@@ -21,6 +21,17 @@ pub enum NodeType {
 }
 
 impl NodeType {
+    pub const ALL: [NodeType; 8] = [
+        NodeType::Section,
+        NodeType::CodeBlock,
+        NodeType::Paragraph,
+        NodeType::Function,
+        NodeType::Class,
+        NodeType::Struct,
+        NodeType::Method,
+        NodeType::Chunk,
+    ];
+
     pub fn as_int(self) -> u8 {
         self as u8
     }
@@ -51,6 +62,11 @@ impl NodeType {
             Self::Chunk => "chunk",
         }
     }
+
+    /// Parse a name as returned by [`as_str`](Self::as_str).
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.as_str() == name)
+    }
 }
 
 /// A node extracted from a file
@@ -68,14 +84,39 @@ pub struct DocumentNode {
     pub parent_node_type: Option<NodeType>,
     /// Parent node span (if applicable)
     pub parent_span: Option<Span>,
+    /// Notebook cell the node came from (index into the notebook's `cells`);
+    /// its `line_range` is then relative to that cell
+    pub cell: Option<usize>,
 }
 
+impl DocumentNode {
+    /// Metadata JSON for storage, with `cell` added for notebook nodes
+    pub fn metadata_json(&self) -> String {
+        let json = self.metadata.to_json();
+        let Some(cell) = self.cell else {
+            return json;
+        };
+        match serde_json::from_str::<serde_json::Value>(&json) {
+            Ok(serde_json::Value::Object(mut map)) => {
+                map.insert("cell".to_string(), cell.into());
+                serde_json::Value::Object(map).to_string()
+            }
+            _ => json,
+        }
+    }
+}
+
+/// Joins the headings of a section's ancestry in `heading_path`.
+pub const HEADING_PATH_SEPARATOR: &str = " > ";
+
 /// Type-specific metadata
 #[derive(Debug, Clone)]
 pub enum NodeMetadata {
     Section {
         heading: String,
         level: u8,
+        /// Enclosing headings and this one, joined by [`HEADING_PATH_SEPARATOR`]
+        heading_path: String,
     },
     CodeBlock {
         language: Option<String>,
@@ -104,10 +145,15 @@ impl NodeMetadata {
     /// Serialize metadata to JSON for storage
     pub fn to_json(&self) -> String {
         match self {
-            Self::Section { heading, level } => serde_json::json!({
+            Self::Section {
+                heading,
+                level,
+                heading_path,
+            } => serde_json::json!({
                 "type": "section",
                 "heading": heading,
-                "level": level
+                "level": level,
+                "heading_path": heading_path
             })
             .to_string(),
             Self::CodeBlock { language } => serde_json::json!({
@@ -151,10 +197,18 @@ impl NodeMetadata {
         let v: serde_json::Value = serde_json::from_str(json).ok()?;
 
         match node_type {
-            NodeType::Section => Some(Self::Section {
-                heading: v.get("heading")?.as_str()?.to_string(),
-                level: v.get("level")?.as_u64()? as u8,
-            }),
+            NodeType::Section => {
+                let heading = v.get("heading")?.as_str()?.to_string();
+                let heading_path = v
+                    .get("heading_path")
+                    .and_then(|p| p.as_str())
+                    .map_or_else(|| heading.clone(), String::from);
+                Some(Self::Section {
+                    heading,
+                    level: v.get("level")?.as_u64()? as u8,
+                    heading_path,
+                })
+            }
             NodeType::CodeBlock => Some(Self::CodeBlock {
                 language: v.get("language").and_then(|l| l.as_str()).map(String::from),
             }),
@@ -185,6 +239,14 @@ impl NodeMetadata {
         }
     }
 
+    /// Full heading path of a section ("Deployment > Auth > Configuration").
+    pub fn heading_path(&self) -> Option<&str> {
+        match self {
+            Self::Section { heading_path, .. } => Some(heading_path),
+            _ => None,
+        }
+    }
+
     /// Get searchable text content from metadata (for symbol search)
     pub fn searchable_name(&self) -> Option<&str> {
         match self {
@@ -210,6 +272,8 @@ pub enum RefType {
 }
 
 impl RefType {
+    pub const ALL: [RefType; 3] = [RefType::Call, RefType::Import, RefType::TypeRef];
+
     pub fn as_str(self) -> &'static str {
         match self {
             Self::Call => "call",
@@ -218,11 +282,12 @@ impl RefType {
         }
     }
 
+    /// Parse a ref type name; `type` is accepted as shorthand for `type_ref`.
     pub fn parse(s: &str) -> Option<Self> {
         match s {
             "call" => Some(Self::Call),
             "import" => Some(Self::Import),
-            "type_ref" => Some(Self::TypeRef),
+            "type_ref" | "type" => Some(Self::TypeRef),
             _ => None,
         }
     }
@@ -243,6 +308,19 @@ pub struct Reference {
     pub line_range: (usize, usize),
 }
 
+/// A marker comment (TODO, FIXME, ...) extracted from source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    /// The configured marker that matched, as written in `[annotations] markers`
+    pub marker: String,
+    /// Comment text from the marker to the end of the comment
+    pub text: String,
+    /// Byte span of `text` in source
+    pub span: Span,
+    /// Line number (1-indexed)
+    pub line: usize,
+}
+
 /// Parsed file with nodes and references
 #[derive(Debug)]
 pub struct ParsedFile {
@@ -251,10 +329,22 @@ pub struct ParsedFile {
     pub content_hash: [u8; 32],
     pub nodes: Vec<DocumentNode>,
     pub refs: Vec<Reference>,
+    pub annotations: Vec<Annotation>,
     pub total_tokens: usize,
     /// File mtime captured at read time (seconds since UNIX epoch).
     /// Used to avoid TOCTOU race between parse and DB write.
     pub mtime: i64,
+    /// Why structural parsing failed, when `nodes` are the plain-chunk fallback
+    pub parse_warning: Option<String>,
+    /// Flagged by the indexer's `[generated]` heuristics; parsing leaves it unset
+    pub generated: bool,
+}
+
+impl ParsedFile {
+    /// Lines in the file, counting an empty file as one line
+    pub fn line_count(&self) -> usize {
+        self.source.lines().count().max(1)
+    }
 }
 
 #[cfg(test)]
@@ -321,13 +411,19 @@ mod tests {
         let meta = NodeMetadata::Section {
             heading: "Introduction".to_string(),
             level: 2,
+            heading_path: "Guide > Introduction".to_string(),
         };
         let json = meta.to_json();
         let recovered = NodeMetadata::from_json(&json, NodeType::Section).unwrap();
         match recovered {
-            NodeMetadata::Section { heading, level } => {
+            NodeMetadata::Section {
+                heading,
+                level,
+                heading_path,
+            } => {
                 assert_eq!(heading, "Introduction");
                 assert_eq!(level, 2);
+                assert_eq!(heading_path, "Guide > Introduction");
             }
             _ => panic!("Expected Section metadata"),
         }
@@ -416,6 +512,7 @@ mod tests {
         let meta = NodeMetadata::Section {
             heading: "Intro".to_string(),
             level: 1,
+            heading_path: "Intro".to_string(),
         };
         let json = meta.to_json();
         // Parsing as Function should fail because "name" field is missing
@@ -461,6 +558,7 @@ mod tests {
             NodeMetadata::Section {
                 heading: "Intro".to_string(),
                 level: 1,
+                heading_path: "Intro".to_string(),
             }
             .searchable_name(),
             Some("Intro")
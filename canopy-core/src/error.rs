@@ -12,6 +12,26 @@ pub struct ErrorEnvelope {
     pub code: String,
     pub message: String,
     pub hint: String,
+    /// Per-field problems, set for `invalid_request` errors
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+/// One rejected field in a request body.
+#[derive(Debug, Clone, PartialEq, Serialize, serde::Deserialize)]
+pub struct FieldError {
+    /// Dotted path to the field, e.g. `config.max_handles` or `handles[0].id`
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
 }
 
 impl ErrorEnvelope {
@@ -24,6 +44,7 @@ impl ErrorEnvelope {
             code: code.into(),
             message: message.into(),
             hint: hint.into(),
+            errors: Vec::new(),
         }
     }
 
@@ -45,7 +66,10 @@ pub enum CanopyError {
     #[error("Invalid handle ID: {0}")]
     InvalidHandle(String),
 
-    #[error("Stale index: file {} changed since indexing (reindex required)", .path.display())]
+    #[error(
+        "Stale index: file {} changed since indexing (reindex required; canopy_refresh_handles reindexes just the files behind given handles)",
+        .path.display()
+    )]
     StaleIndex { path: PathBuf },
 
     #[error("Query parse error at position {position}: {message}")]
@@ -60,9 +84,12 @@ pub enum CanopyError {
     #[error("File not found: {}", .0.display())]
     FileNotFound(PathBuf),
 
-    #[error("Not a canopy repo (no .canopy directory). Run 'canopy init' first.")]
+    #[error("Not a canopy repo (no .canopy directory). Run 'canopy init' first, or pass --auto-init (CANOPY_AUTO_INIT=1).")]
     NotInitialized,
 
+    #[error("Refusing to auto-initialize {}: not a git repo root and it holds {entries} entries. Run 'canopy init' there if it is the repo.", .path.display())]
+    AutoInitRefused { path: PathBuf, entries: usize },
+
     #[error("Config already exists at {}", .0.display())]
     ConfigExists(PathBuf),
 
@@ -78,8 +105,20 @@ pub enum CanopyError {
     #[error("Tree-sitter parse error for {}: {message}", .path.display())]
     TreeSitterParse { path: PathBuf, message: String },
 
-    #[error("Schema version mismatch: database is v{found}, expected v{expected}. Run 'canopy invalidate' then 'canopy index' to reindex.")]
-    SchemaVersionMismatch { found: i32, expected: i32 },
+    #[error(
+        "Schema version mismatch: database is v{found}, expected v{expected}, and {reason}. {hint}"
+    )]
+    SchemaVersionMismatch {
+        found: i32,
+        expected: i32,
+        /// Why the database can't be migrated in place
+        reason: String,
+        /// Binary that last set the database's schema, e.g. "canopy 0.5.0",
+        /// when it recorded itself
+        written_by: Option<String>,
+        /// What to do about it
+        hint: String,
+    },
 
     #[error("Stale generation: expected {expected}, found {found}")]
     StaleGeneration { expected: u64, found: u64 },
@@ -96,6 +135,32 @@ pub enum CanopyError {
 
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
+
+    #[error("Reranker error: {0}")]
+    Rerank(String),
+
+    #[error("Git error: {0}")]
+    Git(String),
+
+    #[error("Invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+
+    #[error("Path resolves outside the repository: {}", .0.display())]
+    PathOutsideRepo(PathBuf),
+
+    #[error("External tool `{command}` timed out after {}s", .timeout.as_secs_f64())]
+    ExternalToolTimeout {
+        command: String,
+        timeout: std::time::Duration,
+    },
+
+    #[error("Pinned handle {handle_id} no longer resolves: {reason}")]
+    PinnedHandleMissing {
+        handle_id: String,
+        file_path: String,
+        /// What disappeared, e.g. "src/auth.rs was deleted"
+        reason: String,
+    },
 }
 
 #[cfg(test)]
@@ -134,6 +199,17 @@ mod tests {
         assert_eq!(recovered.code, "e1");
         assert_eq!(recovered.message, "msg");
         assert_eq!(recovered.hint, "hint");
+        assert!(!json.contains("errors"));
+    }
+
+    #[test]
+    fn error_envelope_field_errors_roundtrip() {
+        let mut env = ErrorEnvelope::new("invalid_request", "bad body", "fix it");
+        env.errors
+            .push(FieldError::new("limit", "must be at most 500"));
+        let json = serde_json::to_string(&env).unwrap();
+        let recovered: ErrorEnvelope = serde_json::from_str(&json).unwrap();
+        assert_eq!(recovered.errors, env.errors);
     }
 
     #[test]
@@ -194,10 +270,14 @@ mod tests {
         let err = CanopyError::SchemaVersionMismatch {
             found: 1,
             expected: 3,
+            reason: "the FTS tokenizer changed".to_string(),
+            written_by: None,
+            hint: "Delete .canopy/index.db and run 'canopy index' to reindex.".to_string(),
         };
         let msg = format!("{}", err);
         assert!(msg.contains("v1"));
         assert!(msg.contains("v3"));
+        assert!(msg.contains("the FTS tokenizer changed"));
         assert!(msg.contains("reindex"));
     }
 
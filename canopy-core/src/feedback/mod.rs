@@ -4,19 +4,28 @@ mod store;
 #[cfg(test)]
 mod tests;
 
-pub use store::FeedbackStore;
+pub use store::{FeedbackStore, PruneReport};
 
 use crate::handle::Handle;
+use crate::protocol::ClientContext;
 use crate::NodeType;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// TTL for cached node-type prior distributions (shared by client and service).
 pub const NODE_TYPE_PRIOR_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(3600);
 
-pub(crate) const RETENTION_DAYS: i64 = 30;
-pub(crate) const QUERY_EVENTS_CAP: i64 = 10_000;
-pub(crate) const EXPAND_EVENTS_CAP: i64 = 50_000;
+/// Rows deleted per pruning transaction, so pruning never holds the write
+/// lock long enough to stall writers
+pub(crate) const PRUNE_BATCH_ROWS: i64 = 10_000;
+/// Rows pruning on open deletes at most; the next open carries on
+pub(crate) const OPEN_PRUNE_MAX_ROWS: usize = 100_000;
+/// Share of the rows a prune must delete before the file is vacuumed
+pub(crate) const VACUUM_DELETED_SHARE: f64 = 0.25;
+/// Most recent query (and expand) events metrics look at
+pub(crate) const METRICS_MAX_EVENTS: i64 = 50_000;
 pub(crate) const TOP_K_GLOBS: usize = 5;
+/// Most sessions a per-session breakdown lists, busiest first
+pub(crate) const METRICS_MAX_SESSIONS: i64 = 50;
 
 #[derive(Debug, Clone)]
 pub struct QueryEvent {
@@ -25,6 +34,14 @@ pub struct QueryEvent {
     pub files_indexed: usize,
     pub handles_returned: usize,
     pub total_tokens: usize,
+    /// Whole-file tokens of the result files (see [`crate::TokenSavings`])
+    pub file_tokens: usize,
+    /// Tokens the result actually returned
+    pub returned_tokens: usize,
+    /// Count and exists modes: the matches counted in place of handles
+    pub match_count: Option<usize>,
+    /// Session and tool that asked
+    pub client: ClientContext,
 }
 
 #[derive(Debug, Clone)]
@@ -57,14 +74,42 @@ pub struct ExpandEvent {
     pub node_type: NodeType,
     pub token_count: usize,
     pub auto_expanded: bool,
+    /// Session and tool that asked
+    pub client: ClientContext,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct FeedbackMetrics {
     pub glob_hit_rate_at_k: f64,
+    /// Share of returned handles expanded from their query, automatically or not
     pub handle_expand_accept_rate: f64,
+    /// Share of returned handles the agent chose to expand
+    pub manual_expand_accept_rate: f64,
+    /// Share of expansions filled in by a query's expand budget
+    pub auto_expand_share: f64,
     pub avg_tokens_per_expand: f64,
     pub sample_count: usize,
+    /// Whole-file tokens of result files, summed over the window's queries
+    pub file_tokens: usize,
+    /// Tokens returned, summed over the window's queries
+    pub returned_tokens: usize,
+}
+
+/// [`FeedbackMetrics`] of one session's events.
+#[derive(Debug, Clone, Default)]
+pub struct SessionMetrics {
+    /// None for events recorded without a session
+    pub session_id: Option<String>,
+    /// Client of the session's most recent query
+    pub client_name: Option<String>,
+    pub metrics: FeedbackMetrics,
+}
+
+impl FeedbackMetrics {
+    /// Tokens not spent reading result files whole, over the window.
+    pub fn tokens_avoided(&self) -> usize {
+        self.file_tokens.saturating_sub(self.returned_tokens)
+    }
 }
 
 pub(crate) fn now_ts() -> i64 {
@@ -1,19 +1,90 @@
 use super::{
-    now_ts, ExpandEvent, FeedbackMetrics, QueryEvent, QueryHandle, EXPAND_EVENTS_CAP,
-    QUERY_EVENTS_CAP, RETENTION_DAYS, TOP_K_GLOBS,
+    now_ts, ExpandEvent, FeedbackMetrics, QueryEvent, QueryHandle, SessionMetrics,
+    METRICS_MAX_EVENTS, METRICS_MAX_SESSIONS, OPEN_PRUNE_MAX_ROWS, PRUNE_BATCH_ROWS, TOP_K_GLOBS,
+    VACUUM_DELETED_SHARE,
 };
+use crate::config::FeedbackConfig;
 use crate::NodeType;
 use rusqlite::{params, Connection};
+use serde::Serialize;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
 use std::path::Path;
 
+/// Whether the query handle `qh` was expanded from its query, by any means.
+const EXPANDED: &str = "EXISTS (
+    SELECT 1 FROM expand_events ee
+    WHERE ee.query_event_id = qh.query_event_id AND ee.handle_id = qh.handle_id
+)";
+
+/// Whether the query handle `qh` was expanded from its query by the agent,
+/// not filled in by the query's expand budget.
+const MANUALLY_EXPANDED: &str = "EXISTS (
+    SELECT 1 FROM expand_events ee
+    WHERE ee.query_event_id = qh.query_event_id AND ee.handle_id = qh.handle_id
+      AND ee.auto_expanded = 0
+)";
+
+/// Which sessions' events metrics cover.
+#[derive(Clone, Copy)]
+enum Sessions<'a> {
+    All,
+    /// One session; None for events recorded without one
+    Only(Option<&'a str>),
+}
+
+/// Rows a prune deleted. Query handles go with their query events.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PruneReport {
+    pub query_events: usize,
+    pub expand_events: usize,
+    /// Whether enough was deleted to vacuum the file afterwards
+    pub vacuumed: bool,
+}
+
+impl PruneReport {
+    pub fn is_empty(&self) -> bool {
+        self.query_events == 0 && self.expand_events == 0
+    }
+
+    /// Add what another prune of the same store deleted.
+    pub fn merge(&mut self, other: &PruneReport) {
+        self.query_events += other.query_events;
+        self.expand_events += other.expand_events;
+        self.vacuumed |= other.vacuumed;
+    }
+}
+
+impl fmt::Display for PruneReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pruned {} query events and {} expand events",
+            self.query_events, self.expand_events
+        )?;
+        if self.vacuumed {
+            write!(f, " (vacuumed)")?;
+        }
+        Ok(())
+    }
+}
+
 pub struct FeedbackStore {
     pub(super) conn: Connection,
+    auto_pruned: Option<PruneReport>,
 }
 
 impl FeedbackStore {
+    /// Open the repo's store, enforcing the `[feedback]` retention settings
+    /// of its `.canopy/config.toml` (defaults when there is none).
     pub fn open(repo_root: &Path) -> crate::Result<Self> {
+        Self::open_with_config(repo_root, &FeedbackConfig::for_repo(repo_root)?)
+    }
+
+    /// Open the repo's store and prune it to `config`. Opening deletes at
+    /// most [`OPEN_PRUNE_MAX_ROWS`] rows; the next open carries on.
+    pub fn open_with_config(repo_root: &Path, config: &FeedbackConfig) -> crate::Result<Self> {
         let canopy_dir = repo_root.join(".canopy");
         fs::create_dir_all(&canopy_dir)?;
         let db_path = canopy_dir.join("feedback.db");
@@ -33,7 +104,13 @@ impl FeedbackStore {
                 predicted_globs TEXT,
                 files_indexed INTEGER DEFAULT 0,
                 handles_returned INTEGER DEFAULT 0,
-                total_tokens INTEGER DEFAULT 0
+                total_tokens INTEGER DEFAULT 0,
+                file_tokens INTEGER DEFAULT 0,
+                returned_tokens INTEGER DEFAULT 0,
+                match_count INTEGER,
+                manual_expanded_count INTEGER DEFAULT 0,
+                session_id TEXT,
+                client_name TEXT
             );
 
             CREATE TABLE IF NOT EXISTS query_handles (
@@ -55,11 +132,14 @@ impl FeedbackStore {
                 node_type INTEGER NOT NULL,
                 token_count INTEGER NOT NULL,
                 auto_expanded INTEGER NOT NULL DEFAULT 0,
-                expanded_at INTEGER NOT NULL
+                expanded_at INTEGER NOT NULL,
+                session_id TEXT,
+                client_name TEXT
             );
 
             CREATE INDEX IF NOT EXISTS idx_query_handles_handle ON query_handles(handle_id);
             CREATE INDEX IF NOT EXISTS idx_query_handles_glob ON query_handles(first_match_glob);
+            CREATE INDEX IF NOT EXISTS idx_query_handles_query_event ON query_handles(query_event_id);
             CREATE INDEX IF NOT EXISTS idx_expand_events_query_event ON expand_events(query_event_id);
             CREATE INDEX IF NOT EXISTS idx_expand_events_handle ON expand_events(handle_id);
             CREATE INDEX IF NOT EXISTS idx_query_events_ts ON query_events(timestamp);
@@ -67,11 +147,35 @@ impl FeedbackStore {
             ",
         )?;
 
-        let store = Self { conn };
-        store.prune()?;
+        let mut store = Self {
+            conn,
+            auto_pruned: None,
+        };
+        store.add_missing_columns()?;
+        store.conn.execute_batch(
+            "CREATE INDEX IF NOT EXISTS idx_query_events_session
+             ON query_events(session_id, timestamp)",
+        )?;
+        let report =
+            store.prune_within(config.retention_days, config.max_rows, OPEN_PRUNE_MAX_ROWS)?;
+        store.auto_pruned = (!report.is_empty()).then_some(report);
         Ok(store)
     }
 
+    /// What pruning deleted when the store was opened, if anything.
+    pub fn auto_pruned(&self) -> Option<&PruneReport> {
+        self.auto_pruned.as_ref()
+    }
+
+    /// Run `f` in one transaction, committed only if it succeeds. Batching
+    /// writes this way costs one sync instead of one per insert.
+    pub fn in_transaction<T>(&self, f: impl FnOnce(&Self) -> crate::Result<T>) -> crate::Result<T> {
+        let tx = self.conn.unchecked_transaction()?;
+        let value = f(self)?;
+        tx.commit()?;
+        Ok(value)
+    }
+
     pub fn record_query_event(&self, event: &QueryEvent) -> crate::Result<i64> {
         let predicted_globs = match &event.predicted_globs {
             Some(globs) if !globs.is_empty() => Some(serde_json::to_string(globs)?),
@@ -79,8 +183,8 @@ impl FeedbackStore {
         };
 
         self.conn.execute(
-            "INSERT INTO query_events (timestamp, query_text, predicted_globs, files_indexed, handles_returned, total_tokens)
-             VALUES (?, ?, ?, ?, ?, ?)",
+            "INSERT INTO query_events (timestamp, query_text, predicted_globs, files_indexed, handles_returned, total_tokens, file_tokens, returned_tokens, match_count, session_id, client_name)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
             params![
                 now_ts(),
                 event.query_text,
@@ -88,12 +192,62 @@ impl FeedbackStore {
                 event.files_indexed as i64,
                 event.handles_returned as i64,
                 event.total_tokens as i64,
+                event.file_tokens as i64,
+                event.returned_tokens as i64,
+                event.match_count.map(|c| c as i64),
+                event.client.session_id,
+                event.client.client_name,
             ],
         )?;
 
         Ok(self.conn.last_insert_rowid())
     }
 
+    /// Columns added after a table was first created; older stores gain them
+    /// on open, backfilled from the events already recorded where possible.
+    fn add_missing_columns(&self) -> crate::Result<()> {
+        let manual_backfill = format!(
+            "UPDATE query_events SET manual_expanded_count = (
+                SELECT COUNT(DISTINCT qh.handle_id) FROM query_handles qh
+                WHERE qh.query_event_id = query_events.id AND {MANUALLY_EXPANDED}
+            )"
+        );
+        let added: [(&str, &str, &str, Option<&str>); 8] = [
+            ("query_events", "file_tokens", "INTEGER DEFAULT 0", None),
+            ("query_events", "returned_tokens", "INTEGER DEFAULT 0", None),
+            ("query_events", "match_count", "INTEGER", None),
+            (
+                "query_events",
+                "manual_expanded_count",
+                "INTEGER DEFAULT 0",
+                Some(&manual_backfill),
+            ),
+            ("query_events", "session_id", "TEXT", None),
+            ("query_events", "client_name", "TEXT", None),
+            ("expand_events", "session_id", "TEXT", None),
+            ("expand_events", "client_name", "TEXT", None),
+        ];
+        let mut existing: HashMap<&str, Vec<String>> = HashMap::new();
+        for (table, column, decl, backfill) in added {
+            if !existing.contains_key(table) {
+                let columns = self
+                    .conn
+                    .prepare(&format!("SELECT name FROM pragma_table_info('{table}')"))?
+                    .query_map([], |row| row.get(0))?
+                    .collect::<Result<_, _>>()?;
+                existing.insert(table, columns);
+            }
+            if !existing[table].iter().any(|c| c == column) {
+                self.conn
+                    .execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"))?;
+                if let Some(backfill) = backfill {
+                    self.conn.execute_batch(backfill)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn record_query_handles(
         &self,
         query_event_id: i64,
@@ -125,11 +279,28 @@ impl FeedbackStore {
         Ok(())
     }
 
+    /// Record an expansion. The first manual expansion of a handle a query
+    /// returned also counts towards that query's `manual_expanded_count`.
     pub fn record_expand_event(&self, event: &ExpandEvent) -> crate::Result<()> {
+        if let (Some(query_event_id), false) = (event.query_event_id, event.auto_expanded) {
+            self.conn.execute(
+                "UPDATE query_events SET manual_expanded_count = manual_expanded_count + 1
+                 WHERE id = ?1
+                   AND EXISTS (
+                       SELECT 1 FROM query_handles
+                       WHERE query_event_id = ?1 AND handle_id = ?2
+                   )
+                   AND NOT EXISTS (
+                       SELECT 1 FROM expand_events
+                       WHERE query_event_id = ?1 AND handle_id = ?2 AND auto_expanded = 0
+                   )",
+                params![query_event_id, event.handle_id],
+            )?;
+        }
         self.conn.execute(
             "INSERT INTO expand_events
-             (query_event_id, handle_id, file_path, node_type, token_count, auto_expanded, expanded_at)
-             VALUES (?, ?, ?, ?, ?, ?, ?)",
+             (query_event_id, handle_id, file_path, node_type, token_count, auto_expanded, expanded_at, session_id, client_name)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
             params![
                 event.query_event_id,
                 event.handle_id,
@@ -138,6 +309,8 @@ impl FeedbackStore {
                 event.token_count as i64,
                 if event.auto_expanded { 1 } else { 0 },
                 now_ts(),
+                event.client.session_id,
+                event.client.client_name,
             ],
         )?;
         Ok(())
@@ -199,24 +372,17 @@ impl FeedbackStore {
         Ok(scores)
     }
 
+    /// Per node type, the share of returned handles the agent chose to
+    /// expand. Auto-expansions are nobody's choice, so they don't count.
     pub fn get_node_type_priors(&self) -> crate::Result<HashMap<NodeType, f64>> {
         let mut priors = HashMap::new();
-        let mut stmt = self.conn.prepare(
+        let mut stmt = self.conn.prepare(&format!(
             "SELECT qh.node_type,
                     COUNT(*) AS returned_count,
-                    SUM(
-                        CASE
-                            WHEN EXISTS (
-                                SELECT 1
-                                FROM expand_events ee
-                                WHERE ee.query_event_id = qh.query_event_id
-                                  AND ee.handle_id = qh.handle_id
-                            ) THEN 1 ELSE 0
-                        END
-                    ) AS expanded_count
+                    SUM(CASE WHEN {MANUALLY_EXPANDED} THEN 1 ELSE 0 END) AS expanded_count
              FROM query_handles qh
-             GROUP BY qh.node_type",
-        )?;
+             GROUP BY qh.node_type"
+        ))?;
 
         let rows = stmt.query_map([], |row| {
             let node_type: i64 = row.get(0)?;
@@ -238,53 +404,109 @@ impl FeedbackStore {
         Ok(priors)
     }
 
+    /// Metrics over the last `lookback_days`, or over the last
+    /// [`METRICS_MAX_EVENTS`] query and expand events when there are more.
     pub fn compute_metrics(&self, lookback_days: f64) -> crate::Result<FeedbackMetrics> {
-        let cutoff = now_ts() - (lookback_days.max(0.0) * 86_400.0) as i64;
+        self.metrics_for(lookback_days, Sessions::All)
+    }
 
-        let sample_count: i64 = self.conn.query_row(
-            "SELECT COUNT(*) FROM query_events WHERE timestamp >= ?",
-            params![cutoff],
-            |row| row.get(0),
-        )?;
+    /// [`compute_metrics`](Self::compute_metrics) per session, for the
+    /// [`METRICS_MAX_SESSIONS`] sessions with the most queries in the window.
+    /// Events recorded without a session are grouped under `None`.
+    pub fn compute_session_metrics(
+        &self,
+        lookback_days: f64,
+    ) -> crate::Result<Vec<SessionMetrics>> {
+        let lookback = now_ts() - (lookback_days.max(0.0) * 86_400.0) as i64;
+        let cutoff = self.window_start("query_events", "timestamp", lookback)?;
+        // With MAX(), SQLite takes the bare client_name from the newest row
+        let sessions: Vec<(Option<String>, Option<String>)> = self
+            .conn
+            .prepare(
+                "SELECT session_id, client_name, MAX(timestamp) FROM query_events
+                 WHERE timestamp >= ?
+                 GROUP BY session_id
+                 ORDER BY COUNT(*) DESC, session_id
+                 LIMIT ?",
+            )?
+            .query_map(params![cutoff, METRICS_MAX_SESSIONS], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<_, _>>()?;
+        sessions
+            .into_iter()
+            .map(|(session_id, client_name)| {
+                let metrics =
+                    self.metrics_for(lookback_days, Sessions::Only(session_id.as_deref()))?;
+                Ok(SessionMetrics {
+                    session_id,
+                    client_name,
+                    metrics,
+                })
+            })
+            .collect()
+    }
 
-        let (returned_count, expanded_count): (i64, i64) = self.conn.query_row(
-            "SELECT
-                COUNT(*),
-                SUM(
-                    CASE
-                        WHEN EXISTS (
-                            SELECT 1
-                            FROM expand_events ee
-                            WHERE ee.query_event_id = qh.query_event_id
-                              AND ee.handle_id = qh.handle_id
-                        ) THEN 1 ELSE 0
-                    END
-                )
-             FROM query_handles qh
-             JOIN query_events qe ON qe.id = qh.query_event_id
-             WHERE qe.timestamp >= ?",
-            params![cutoff],
-            |row| Ok((row.get(0)?, row.get::<_, Option<i64>>(1)?.unwrap_or(0))),
+    fn metrics_for(
+        &self,
+        lookback_days: f64,
+        sessions: Sessions<'_>,
+    ) -> crate::Result<FeedbackMetrics> {
+        let lookback = now_ts() - (lookback_days.max(0.0) * 86_400.0) as i64;
+        let cutoff = self.window_start("query_events", "timestamp", lookback)?;
+        let expand_cutoff = self.window_start("expand_events", "expanded_at", lookback)?;
+        // ?2 selects every session, else only session ?3 (IS matches NULL)
+        let (all, session) = match sessions {
+            Sessions::All => (true, None),
+            Sessions::Only(session) => (false, session),
+        };
+        let (sample_count, file_tokens, returned_tokens): (i64, i64, i64) = self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(file_tokens), 0), COALESCE(SUM(returned_tokens), 0)
+             FROM query_events WHERE timestamp >= ?1 AND (?2 OR session_id IS ?3)",
+            params![cutoff, all, session],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
         )?;
 
-        let avg_tokens_per_expand: Option<f64> = self.conn.query_row(
-            "SELECT AVG(token_count)
-             FROM expand_events
-             WHERE expanded_at >= ?",
-            params![cutoff],
-            |row| row.get(0),
+        let (returned_count, expanded_count, manual_count): (i64, i64, i64) = self.conn.query_row(
+            &format!(
+                "SELECT
+                        COUNT(*),
+                        SUM(CASE WHEN {EXPANDED} THEN 1 ELSE 0 END),
+                        SUM(CASE WHEN {MANUALLY_EXPANDED} THEN 1 ELSE 0 END)
+                     FROM query_handles qh
+                     JOIN query_events qe ON qe.id = qh.query_event_id
+                     WHERE qe.timestamp >= ?1 AND (?2 OR qe.session_id IS ?3)"
+            ),
+            params![cutoff, all, session],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get::<_, Option<i64>>(1)?.unwrap_or(0),
+                    row.get::<_, Option<i64>>(2)?.unwrap_or(0),
+                ))
+            },
         )?;
 
+        let (avg_tokens_per_expand, expand_count, auto_count): (Option<f64>, i64, i64) =
+            self.conn.query_row(
+                "SELECT AVG(token_count), COUNT(*), COALESCE(SUM(auto_expanded), 0)
+                 FROM expand_events
+                 WHERE expanded_at >= ?1 AND (?2 OR session_id IS ?3)",
+                params![expand_cutoff, all, session],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )?;
+
         let mut glob_denominator = 0usize;
         let mut glob_hits = 0usize;
 
         let mut stmt_events = self.conn.prepare(
             "SELECT id, predicted_globs
              FROM query_events
-             WHERE timestamp >= ?
+             WHERE timestamp >= ?1
+               AND (?2 OR session_id IS ?3)
                AND predicted_globs IS NOT NULL",
         )?;
-        let event_rows = stmt_events.query_map(params![cutoff], |row| {
+        let event_rows = stmt_events.query_map(params![cutoff, all, session], |row| {
             let id: i64 = row.get(0)?;
             let predicted_globs: String = row.get(1)?;
             Ok((id, predicted_globs))
@@ -317,11 +539,14 @@ impl FeedbackStore {
             }
         }
 
-        let handle_expand_accept_rate = if returned_count > 0 {
-            expanded_count as f64 / returned_count as f64
-        } else {
-            0.0
+        let rate = |count: i64, total: i64| {
+            if total > 0 {
+                count as f64 / total as f64
+            } else {
+                0.0
+            }
         };
+        let handle_expand_accept_rate = rate(expanded_count, returned_count);
         let glob_hit_rate_at_k = if glob_denominator > 0 {
             glob_hits as f64 / glob_denominator as f64
         } else {
@@ -331,50 +556,98 @@ impl FeedbackStore {
         Ok(FeedbackMetrics {
             glob_hit_rate_at_k,
             handle_expand_accept_rate,
+            manual_expand_accept_rate: rate(manual_count, returned_count),
+            auto_expand_share: rate(auto_count, expand_count),
             avg_tokens_per_expand: avg_tokens_per_expand.unwrap_or(0.0),
             sample_count: sample_count.max(0) as usize,
+            file_tokens: file_tokens.max(0) as usize,
+            returned_tokens: returned_tokens.max(0) as usize,
         })
     }
 
-    pub(super) fn prune(&self) -> crate::Result<()> {
-        let cutoff = now_ts() - RETENTION_DAYS * 86_400;
-        self.conn.execute(
-            "DELETE FROM query_events WHERE timestamp < ?",
-            params![cutoff],
-        )?;
-        self.conn.execute(
-            "DELETE FROM expand_events WHERE expanded_at < ?",
-            params![cutoff],
-        )?;
+    /// The later of `cutoff` and the timestamp of the
+    /// [`METRICS_MAX_EVENTS`]th newest row of `table`, so a window starting
+    /// there reads a bounded number of rows off the timestamp index.
+    fn window_start(&self, table: &str, ts_column: &str, cutoff: i64) -> crate::Result<i64> {
+        let nth: Option<i64> = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT {ts_column} FROM {table} ORDER BY {ts_column} DESC LIMIT 1 OFFSET ?"
+                ),
+                params![METRICS_MAX_EVENTS - 1],
+                |row| row.get(0),
+            )
+            .map(Some)
+            .or_else(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                e => Err(e),
+            })?;
+        Ok(nth.map_or(cutoff, |ts| ts.max(cutoff)))
+    }
 
-        self.conn.execute(
-            "DELETE FROM query_events
-             WHERE id IN (
-                SELECT id
-                FROM query_events
-                ORDER BY timestamp ASC
-                LIMIT (
-                    SELECT CASE WHEN COUNT(*) > ? THEN COUNT(*) - ? ELSE 0 END
-                    FROM query_events
-                )
-             )",
-            params![QUERY_EVENTS_CAP, QUERY_EVENTS_CAP],
-        )?;
+    /// Delete events older than `retention_days`, then the oldest of each
+    /// table beyond `max_rows`, and vacuum when that was a large share of
+    /// the store.
+    pub fn prune(&self, retention_days: u64, max_rows: usize) -> crate::Result<PruneReport> {
+        self.prune_within(retention_days, max_rows, usize::MAX)
+    }
 
-        self.conn.execute(
-            "DELETE FROM expand_events
-             WHERE id IN (
-                SELECT id
-                FROM expand_events
-                ORDER BY expanded_at ASC
-                LIMIT (
-                    SELECT CASE WHEN COUNT(*) > ? THEN COUNT(*) - ? ELSE 0 END
-                    FROM expand_events
-                )
-             )",
-            params![EXPAND_EVENTS_CAP, EXPAND_EVENTS_CAP],
-        )?;
+    /// [`prune`](Self::prune), deleting at most `budget` rows.
+    fn prune_within(
+        &self,
+        retention_days: u64,
+        max_rows: usize,
+        budget: usize,
+    ) -> crate::Result<PruneReport> {
+        let retention_secs = i64::try_from(retention_days)
+            .unwrap_or(i64::MAX)
+            .saturating_mul(86_400);
+        let cutoff = now_ts().saturating_sub(retention_secs);
+        let mut budget = budget;
+        let mut before = 0;
+        let mut prune_table = |table: &str, ts_column: &str| -> crate::Result<usize> {
+            let (count, expired): (i64, i64) = self.conn.query_row(
+                &format!("SELECT COUNT(*), COALESCE(SUM({ts_column} < ?), 0) FROM {table}"),
+                params![cutoff],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            before += count.max(0) as usize;
+            // Expired rows are the oldest, so deleting oldest-first covers both
+            let over_cap = (count.max(0) as usize).saturating_sub(max_rows);
+            let mut remaining = (expired.max(0) as usize).max(over_cap).min(budget);
+            let mut deleted = 0;
+            while remaining > 0 {
+                let batch = remaining.min(PRUNE_BATCH_ROWS as usize);
+                let n = self.conn.execute(
+                    &format!(
+                        "DELETE FROM {table} WHERE id IN (
+                            SELECT id FROM {table} ORDER BY {ts_column} ASC, id ASC LIMIT ?
+                         )"
+                    ),
+                    params![batch as i64],
+                )?;
+                if n == 0 {
+                    break;
+                }
+                deleted += n;
+                remaining = remaining.saturating_sub(n);
+            }
+            budget -= deleted.min(budget);
+            Ok(deleted)
+        };
+        let mut report = PruneReport {
+            query_events: prune_table("query_events", "timestamp")?,
+            expand_events: prune_table("expand_events", "expanded_at")?,
+            vacuumed: false,
+        };
 
-        Ok(())
+        let deleted = report.query_events + report.expand_events;
+        if deleted > 0 && deleted as f64 >= before as f64 * VACUUM_DELETED_SHARE {
+            // Fails while another connection reads the file; the freed pages
+            // get reused either way, so the next large prune tries again
+            report.vacuumed = self.conn.execute_batch("VACUUM").is_ok();
+        }
+        Ok(report)
     }
 }
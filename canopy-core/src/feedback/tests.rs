@@ -1,4 +1,6 @@
 use super::*;
+use crate::config::FeedbackConfig;
+use crate::protocol::ClientContext;
 use crate::NodeType;
 use rusqlite::params;
 
@@ -18,6 +20,10 @@ fn record_and_score_roundtrip() {
             files_indexed: 10,
             handles_returned: 1,
             total_tokens: 120,
+            file_tokens: 0,
+            returned_tokens: 0,
+            match_count: None,
+            client: ClientContext::default(),
         })
         .unwrap();
 
@@ -42,6 +48,7 @@ fn record_and_score_roundtrip() {
             node_type: NodeType::Function,
             token_count: 120,
             auto_expanded: false,
+            client: ClientContext::default(),
         })
         .unwrap();
 
@@ -58,7 +65,8 @@ fn record_and_score_roundtrip() {
 fn prune_removes_expired_rows_and_cascades_handles() {
     let repo_root = temp_repo();
     let store = FeedbackStore::open(&repo_root).unwrap();
-    let old_ts = now_ts() - (RETENTION_DAYS + 1) * 86_400;
+    let config = FeedbackConfig::default();
+    let old_ts = now_ts() - (config.retention_days as i64 + 1) * 86_400;
 
     store
         .conn
@@ -104,7 +112,9 @@ fn prune_removes_expired_rows_and_cascades_handles() {
         )
         .unwrap();
 
-    store.prune().unwrap();
+    let report = store.prune(config.retention_days, config.max_rows).unwrap();
+    assert_eq!(report.query_events, 1);
+    assert_eq!(report.expand_events, 1);
 
     let query_count: i64 = store
         .conn
@@ -137,6 +147,90 @@ fn prune_removes_expired_rows_and_cascades_handles() {
     assert_eq!(expand_count, 0);
 }
 
+fn count_rows(store: &FeedbackStore, table: &str) -> i64 {
+    store
+        .conn
+        .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+}
+
+#[test]
+fn opening_an_oversized_store_prunes_it_to_the_config() {
+    let repo_root = temp_repo();
+    let config = FeedbackConfig {
+        retention_days: 10,
+        max_rows: 50,
+    };
+    let store = FeedbackStore::open_with_config(&repo_root, &config).unwrap();
+    assert!(store.auto_pruned().is_none());
+
+    // 20 expired events, then 80 recent ones, each with a handle and an
+    // expansion of it
+    let now = now_ts();
+    store
+        .in_transaction(|store| {
+            for i in 0..100i64 {
+                let ts = if i < 20 { now - 11 * 86_400 } else { now - 100 + i };
+                store.conn.execute(
+                    "INSERT INTO query_events (timestamp, query_text, predicted_globs, handles_returned, total_tokens, file_tokens, returned_tokens)
+                     VALUES (?, 'q', '[\"src/**/*.rs\"]', 1, 10, 100, 10)",
+                    params![ts],
+                )?;
+                let id = store.conn.last_insert_rowid();
+                store.conn.execute(
+                    "INSERT INTO query_handles
+                     (query_event_id, handle_id, file_path, node_type, token_count, first_match_glob, returned_at)
+                     VALUES (?, ?, 'src/a.rs', ?, 10, 'src/**/*.rs', ?)",
+                    params![id, format!("h{i}"), NodeType::Function.as_int() as i64, ts],
+                )?;
+                store.conn.execute(
+                    "INSERT INTO expand_events
+                     (query_event_id, handle_id, file_path, node_type, token_count, auto_expanded, expanded_at)
+                     VALUES (?, ?, 'src/a.rs', ?, 10, 0, ?)",
+                    params![id, format!("h{i}"), NodeType::Function.as_int() as i64, ts],
+                )?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    drop(store);
+
+    let store = FeedbackStore::open_with_config(&repo_root, &config).unwrap();
+    let report = *store.auto_pruned().unwrap();
+    assert_eq!(report.query_events, 50);
+    assert_eq!(report.expand_events, 50);
+    assert!(report.vacuumed);
+    assert_eq!(count_rows(&store, "query_events"), 50);
+    assert_eq!(count_rows(&store, "query_handles"), 50);
+    assert_eq!(count_rows(&store, "expand_events"), 50);
+    // The newest rows are the ones kept
+    let oldest: i64 = store
+        .conn
+        .query_row("SELECT MIN(timestamp) FROM query_events", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(oldest, now - 50);
+
+    let metrics = store.compute_metrics(30.0).unwrap();
+    assert_eq!(metrics.sample_count, 50);
+    assert_eq!(metrics.returned_tokens, 500);
+    assert!((metrics.handle_expand_accept_rate - 1.0).abs() < 1e-9);
+    assert!((metrics.glob_hit_rate_at_k - 1.0).abs() < 1e-9);
+
+    // Within the config, reopening deletes nothing; a shorter manual
+    // retention drops the rest
+    drop(store);
+    let store = FeedbackStore::open_with_config(&repo_root, &config).unwrap();
+    assert!(store.auto_pruned().is_none());
+    let report = store.prune(0, config.max_rows).unwrap();
+    assert_eq!(report.query_events, 50);
+    assert_eq!(count_rows(&store, "query_events"), 0);
+    assert_eq!(store.compute_metrics(30.0).unwrap().sample_count, 0);
+}
+
 #[test]
 fn glob_scores_decay_with_age() {
     let repo_root = temp_repo();
@@ -350,3 +444,328 @@ fn node_type_priors_distinguish_types() {
     assert!(fn_prior > 0.0, "Function prior should be > 0: {}", fn_prior);
     assert_eq!(st_prior, 0.0, "Struct prior should be 0 (never expanded)");
 }
+
+#[test]
+fn metrics_accumulate_token_savings() {
+    let repo_root = temp_repo();
+    let store = FeedbackStore::open(&repo_root).unwrap();
+
+    for (file_tokens, returned_tokens) in [(4000, 100), (900, 300)] {
+        store
+            .record_query_event(&QueryEvent {
+                query_text: "auth".to_string(),
+                predicted_globs: None,
+                files_indexed: 0,
+                handles_returned: 2,
+                total_tokens: returned_tokens,
+                file_tokens,
+                returned_tokens,
+                match_count: None,
+                client: ClientContext::default(),
+            })
+            .unwrap();
+    }
+
+    let metrics = store.compute_metrics(7.0).unwrap();
+    assert_eq!(metrics.file_tokens, 4900);
+    assert_eq!(metrics.returned_tokens, 400);
+    assert_eq!(metrics.tokens_avoided(), 4500);
+}
+
+#[test]
+fn open_adds_savings_columns_to_older_stores() {
+    let repo_root = temp_repo();
+    std::fs::create_dir_all(repo_root.join(".canopy")).unwrap();
+    let conn = rusqlite::Connection::open(repo_root.join(".canopy/feedback.db")).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE query_events (
+            id INTEGER PRIMARY KEY,
+            timestamp INTEGER NOT NULL,
+            query_text TEXT NOT NULL,
+            predicted_globs TEXT,
+            files_indexed INTEGER DEFAULT 0,
+            handles_returned INTEGER DEFAULT 0,
+            total_tokens INTEGER DEFAULT 0
+        );",
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO query_events (timestamp, query_text) VALUES (?, 'old')",
+        params![now_ts()],
+    )
+    .unwrap();
+    drop(conn);
+
+    let store = FeedbackStore::open(&repo_root).unwrap();
+    let metrics = store.compute_metrics(7.0).unwrap();
+    assert_eq!(metrics.sample_count, 1);
+    assert_eq!(metrics.file_tokens, 0);
+}
+
+#[test]
+fn failed_transaction_writes_nothing() {
+    let repo_root = temp_repo();
+    let store = FeedbackStore::open(&repo_root).unwrap();
+    let event = QueryEvent {
+        query_text: "batched".to_string(),
+        predicted_globs: None,
+        files_indexed: 0,
+        handles_returned: 0,
+        total_tokens: 0,
+        file_tokens: 0,
+        returned_tokens: 0,
+        match_count: None,
+        client: ClientContext::default(),
+    };
+    let count = |store: &FeedbackStore| -> i64 {
+        store
+            .conn
+            .query_row("SELECT COUNT(*) FROM query_events", [], |row| row.get(0))
+            .unwrap()
+    };
+
+    let failed: crate::Result<()> = store.in_transaction(|store| {
+        store.record_query_event(&event)?;
+        Err(crate::CanopyError::Io(std::io::Error::other("disk full")))
+    });
+    assert!(failed.is_err());
+    assert_eq!(count(&store), 0);
+
+    store
+        .in_transaction(|store| {
+            store.record_query_event(&event)?;
+            store.record_query_event(&event)
+        })
+        .unwrap();
+    assert_eq!(count(&store), 2);
+}
+
+fn handle(id: &str, node_type: NodeType) -> QueryHandle {
+    QueryHandle {
+        handle_id: id.to_string(),
+        file_path: "src/lib.rs".to_string(),
+        node_type,
+        token_count: 40,
+        first_match_glob: None,
+    }
+}
+
+fn expand(query_event_id: i64, id: &str, node_type: NodeType, auto_expanded: bool) -> ExpandEvent {
+    ExpandEvent {
+        query_event_id: Some(query_event_id),
+        handle_id: id.to_string(),
+        file_path: "src/lib.rs".to_string(),
+        node_type,
+        token_count: 40,
+        auto_expanded,
+        client: ClientContext::default(),
+    }
+}
+
+fn manual_expanded_count(store: &FeedbackStore, query_event_id: i64) -> i64 {
+    store
+        .conn
+        .query_row(
+            "SELECT manual_expanded_count FROM query_events WHERE id = ?",
+            params![query_event_id],
+            |row| row.get(0),
+        )
+        .unwrap()
+}
+
+#[test]
+fn auto_expansions_are_rated_apart_from_manual_ones() {
+    let repo_root = temp_repo();
+    let store = FeedbackStore::open(&repo_root).unwrap();
+    let qid = store
+        .record_query_event(&QueryEvent {
+            query_text: "session".to_string(),
+            predicted_globs: None,
+            files_indexed: 0,
+            handles_returned: 4,
+            total_tokens: 160,
+            file_tokens: 0,
+            returned_tokens: 160,
+            match_count: None,
+            client: ClientContext::default(),
+        })
+        .unwrap();
+    store
+        .record_query_handles(
+            qid,
+            &[
+                handle("hf1", NodeType::Function),
+                handle("hf2", NodeType::Function),
+                handle("hs1", NodeType::Struct),
+                handle("hs2", NodeType::Struct),
+            ],
+        )
+        .unwrap();
+
+    // The expand budget filled both functions; the agent then opened one
+    // struct, twice, and one handle the query never returned
+    for event in [
+        expand(qid, "hf1", NodeType::Function, true),
+        expand(qid, "hf2", NodeType::Function, true),
+        expand(qid, "hs1", NodeType::Struct, false),
+        expand(qid, "hs1", NodeType::Struct, false),
+        expand(qid, "elsewhere", NodeType::Struct, false),
+    ] {
+        store.record_expand_event(&event).unwrap();
+    }
+
+    let metrics = store.compute_metrics(7.0).unwrap();
+    assert!((metrics.handle_expand_accept_rate - 0.75).abs() < 1e-9);
+    assert!((metrics.manual_expand_accept_rate - 0.25).abs() < 1e-9);
+    assert!((metrics.auto_expand_share - 0.4).abs() < 1e-9);
+    assert_eq!(manual_expanded_count(&store, qid), 1);
+
+    // Priors learn only from what the agent chose
+    let priors = store.get_node_type_priors().unwrap();
+    assert_eq!(priors[&NodeType::Function], 0.0);
+    assert!((priors[&NodeType::Struct] - 0.5).abs() < 1e-9);
+}
+
+#[test]
+fn open_backfills_manual_expanded_counts() {
+    let repo_root = temp_repo();
+    std::fs::create_dir_all(repo_root.join(".canopy")).unwrap();
+    let conn = rusqlite::Connection::open(repo_root.join(".canopy/feedback.db")).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE query_events (
+            id INTEGER PRIMARY KEY,
+            timestamp INTEGER NOT NULL,
+            query_text TEXT NOT NULL,
+            predicted_globs TEXT,
+            files_indexed INTEGER DEFAULT 0,
+            handles_returned INTEGER DEFAULT 0,
+            total_tokens INTEGER DEFAULT 0,
+            file_tokens INTEGER DEFAULT 0,
+            returned_tokens INTEGER DEFAULT 0,
+            match_count INTEGER
+        );
+        CREATE TABLE query_handles (
+            id INTEGER PRIMARY KEY,
+            query_event_id INTEGER NOT NULL REFERENCES query_events(id) ON DELETE CASCADE,
+            handle_id TEXT NOT NULL,
+            file_path TEXT NOT NULL,
+            node_type INTEGER NOT NULL,
+            token_count INTEGER NOT NULL,
+            first_match_glob TEXT,
+            returned_at INTEGER NOT NULL
+        );
+        CREATE TABLE expand_events (
+            id INTEGER PRIMARY KEY,
+            query_event_id INTEGER REFERENCES query_events(id) ON DELETE SET NULL,
+            handle_id TEXT NOT NULL,
+            file_path TEXT NOT NULL,
+            node_type INTEGER NOT NULL,
+            token_count INTEGER NOT NULL,
+            auto_expanded INTEGER NOT NULL DEFAULT 0,
+            expanded_at INTEGER NOT NULL
+        );",
+    )
+    .unwrap();
+    let ts = now_ts();
+    conn.execute(
+        "INSERT INTO query_events (id, timestamp, query_text) VALUES (1, ?, 'old')",
+        params![ts],
+    )
+    .unwrap();
+    for id in ["h1", "h2", "h3"] {
+        conn.execute(
+            "INSERT INTO query_handles (query_event_id, handle_id, file_path, node_type, token_count, returned_at)
+             VALUES (1, ?, 'f.rs', 0, 10, ?)",
+            params![id, ts],
+        )
+        .unwrap();
+    }
+    for (id, auto) in [("h1", 1), ("h2", 0), ("h2", 0), ("h3", 0)] {
+        conn.execute(
+            "INSERT INTO expand_events (query_event_id, handle_id, file_path, node_type, token_count, auto_expanded, expanded_at)
+             VALUES (1, ?, 'f.rs', 0, 10, ?, ?)",
+            params![id, auto, ts],
+        )
+        .unwrap();
+    }
+    drop(conn);
+
+    let store = FeedbackStore::open(&repo_root).unwrap();
+    assert_eq!(manual_expanded_count(&store, 1), 2);
+
+    // New manual expansions keep counting from there
+    store
+        .record_expand_event(&expand(1, "h1", NodeType::Function, false))
+        .unwrap();
+    assert_eq!(manual_expanded_count(&store, 1), 3);
+}
+
+#[test]
+fn metrics_break_down_by_session() {
+    let repo_root = temp_repo();
+    let store = FeedbackStore::open(&repo_root).unwrap();
+    let query = |client: &ClientContext| {
+        store
+            .record_query_event(&QueryEvent {
+                query_text: "q".to_string(),
+                predicted_globs: None,
+                files_indexed: 0,
+                handles_returned: 1,
+                total_tokens: 40,
+                file_tokens: 0,
+                returned_tokens: 40,
+                match_count: None,
+                client: client.clone(),
+            })
+            .unwrap()
+    };
+    let busy = ClientContext::new(Some("agent-a"), Some("s-busy"));
+    let quiet = ClientContext::new(Some("agent-b"), Some("s-quiet"));
+    for i in 0..3 {
+        let qid = query(&busy);
+        let id = format!("busy{i}");
+        store
+            .record_query_handles(qid, &[handle(&id, NodeType::Function)])
+            .unwrap();
+        if i == 0 {
+            store
+                .record_expand_event(&ExpandEvent {
+                    client: busy.clone(),
+                    ..expand(qid, &id, NodeType::Function, false)
+                })
+                .unwrap();
+        }
+    }
+    let qid = query(&quiet);
+    store
+        .record_query_handles(qid, &[handle("quiet", NodeType::Function)])
+        .unwrap();
+    query(&ClientContext::default());
+
+    let sessions = store.compute_session_metrics(7.0).unwrap();
+    let ids: Vec<Option<&str>> = sessions.iter().map(|s| s.session_id.as_deref()).collect();
+    assert_eq!(ids[0], Some("s-busy"));
+    assert_eq!(sessions.len(), 3);
+    assert!(ids.contains(&None) && ids.contains(&Some("s-quiet")));
+
+    let busy = &sessions[0];
+    assert_eq!(busy.client_name.as_deref(), Some("agent-a"));
+    assert_eq!(busy.metrics.sample_count, 3);
+    assert!((busy.metrics.handle_expand_accept_rate - 1.0 / 3.0).abs() < 1e-9);
+    assert!((busy.metrics.avg_tokens_per_expand - 40.0).abs() < 1e-9);
+    let quiet = sessions
+        .iter()
+        .find(|s| s.session_id.as_deref() == Some("s-quiet"))
+        .unwrap();
+    assert_eq!(quiet.metrics.sample_count, 1);
+    assert_eq!(quiet.metrics.handle_expand_accept_rate, 0.0);
+    assert_eq!(quiet.metrics.avg_tokens_per_expand, 0.0);
+
+    // The whole-store metrics still see every session
+    assert_eq!(store.compute_metrics(7.0).unwrap().sample_count, 5);
+    let expand_session: Option<String> = store
+        .conn
+        .query_row("SELECT session_id FROM expand_events", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(expand_session.as_deref(), Some("s-busy"));
+}
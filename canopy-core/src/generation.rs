@@ -1,5 +1,6 @@
 //! Generation tracking types for canopy-service
 
+use crate::protocol::PathPolicy;
 use serde::{Deserialize, Serialize};
 
 /// Monotonically increasing generation counter for staleness detection
@@ -73,6 +74,26 @@ pub struct RepoShard {
     /// Error message from last failed operation (if status is Error)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error_message: Option<String>,
+    /// Unix seconds of the last warm-up since the service started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_warmup_at: Option<i64>,
+    /// Paths the service may surface from this repo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<PathPolicy>,
+    /// Earlier generations kept on disk for expanding their handles, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retained_generations: Vec<RetainedGeneration>,
+}
+
+/// A snapshot of an earlier generation's index that still serves `/expand`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetainedGeneration {
+    pub generation: Generation,
+    /// Commit the generation was built from; expansions read changed files
+    /// back from it
+    pub commit_sha: Option<String>,
+    /// Disk used by the snapshot
+    pub size_bytes: u64,
 }
 
 #[cfg(test)]
@@ -132,10 +153,18 @@ mod tests {
             generation: Generation::from_value(3),
             status: ShardStatus::Ready,
             error_message: None,
+            last_warmup_at: None,
+            policy: None,
+            retained_generations: vec![RetainedGeneration {
+                generation: Generation::from_value(2),
+                commit_sha: Some("cafef00d".to_string()),
+                size_bytes: 4096,
+            }],
         };
         let json = serde_json::to_string(&shard).unwrap();
         let back: RepoShard = serde_json::from_str(&json).unwrap();
         assert_eq!(back.repo_id, "abc123");
         assert_eq!(back.generation.value(), 3);
+        assert_eq!(back.retained_generations, shard.retained_generations);
     }
 }
@@ -1,22 +1,336 @@
 //! Shared git utilities used by both client and service.
+//!
+//! Every command runs through [`crate::process`]: git resolved once on
+//! `PATH`, a scrubbed environment, and a deadline of
+//! [`GIT_TIMEOUT_ENV`](crate::GIT_TIMEOUT_ENV) (10s by default). A
+//! command that fails or times out reads as git being unavailable.
 
+use crate::process::{self, git_timeout, OutputLimits};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader};
 use std::path::Path;
 use std::process::Command;
 
+/// A git command run in `repo_root`, or None when git isn't installed.
+fn git(repo_root: &Path) -> Option<Command> {
+    let mut cmd = process::tool_command("git")?;
+    cmd.current_dir(repo_root);
+    Some(cmd)
+}
+
+/// Records of a successful git command's stdout ended by `separator`.
+fn git_records(mut cmd: Command, args: &[&str], separator: u8) -> Option<Vec<Vec<u8>>> {
+    cmd.args(args);
+    let output = process::capture(cmd, separator, OutputLimits::timeout(git_timeout())).ok()?;
+    output.success.then_some(output.records)
+}
+
+/// Paths from NUL-separated records.
+fn paths(records: Vec<Vec<u8>>) -> Vec<String> {
+    records
+        .iter()
+        .map(|p| String::from_utf8_lossy(p).into_owned())
+        .collect()
+}
+
+/// The first line of a successful git command's stdout, trimmed.
+fn git_line(cmd: Command, args: &[&str]) -> Option<String> {
+    let records = git_records(cmd, args, b'\n')?;
+    Some(String::from_utf8_lossy(records.first()?).trim().to_string())
+}
+
 /// Get the HEAD commit SHA for a repo, or None if not a git repo / git unavailable.
 pub fn head_commit_sha(repo_root: &Path) -> Option<String> {
-    Command::new("git")
-        .args(["rev-parse", "HEAD"])
-        .current_dir(repo_root)
-        .output()
-        .ok()
-        .and_then(|o| {
-            if o.status.success() {
-                Some(String::from_utf8_lossy(&o.stdout).trim().to_string())
-            } else {
-                None
+    git_line(git(repo_root)?, &["rev-parse", "HEAD"])
+}
+
+/// Resolve `rev` to a full commit SHA, or None if it does not name a commit.
+pub fn resolve_commit(repo_root: &Path, rev: &str) -> Option<String> {
+    let commit = format!("{rev}^{{commit}}");
+    git_line(
+        git(repo_root)?,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            "--end-of-options",
+            &commit,
+        ],
+    )
+}
+
+/// Repo-relative paths of every file tracked at `commit`.
+pub fn tracked_files_at(repo_root: &Path, commit: &str) -> Option<Vec<String>> {
+    let records = git_records(
+        git(repo_root)?,
+        &["ls-tree", "-r", "-z", "--name-only", commit],
+        0,
+    )?;
+    Some(paths(records))
+}
+
+/// Contents of `paths` at `commit`, read through one `git cat-file --batch`.
+/// Paths missing at `commit` are left out.
+pub fn read_files_at(
+    repo_root: &Path,
+    commit: &str,
+    paths: &[String],
+) -> Option<Vec<(String, Vec<u8>)>> {
+    let mut cmd = git(repo_root)?;
+    cmd.args(["cat-file", "--batch"]);
+    let requests: String = paths.iter().map(|p| format!("{commit}:{p}\n")).collect();
+    let paths = paths.to_vec();
+    let (files, _) = process::run(
+        cmd,
+        Some(requests.into_bytes()),
+        git_timeout(),
+        move |stdout| read_batch(BufReader::new(stdout), &paths),
+    )
+    .ok()?;
+    files
+}
+
+/// Responses of `git cat-file --batch` to one request per entry of `paths`.
+fn read_batch(mut stdout: impl BufRead, paths: &[String]) -> Option<Vec<(String, Vec<u8>)>> {
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        let mut header = String::new();
+        if stdout.read_line(&mut header).ok()? == 0 {
+            break;
+        }
+        // "<sha> <type> <size>", or "<request> missing"
+        let header = header.trim_end();
+        if header.ends_with(" missing") || header.ends_with(" ambiguous") {
+            continue;
+        }
+        let mut fields = header.split_whitespace();
+        let (Some(_), Some(kind), Some(size)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let size: usize = size.parse().ok()?;
+        let mut content = vec![0; size];
+        stdout.read_exact(&mut content).ok()?;
+        let mut newline = [0u8; 1];
+        stdout.read_exact(&mut newline).ok()?;
+        if kind == "blob" {
+            files.push((path.clone(), content));
+        }
+    }
+    Some(files)
+}
+
+/// Time (UNIX seconds) of the last commit touching each of `paths`, found
+/// in one walk back from HEAD that stops once every path has turned up.
+/// Paths are relative to `repo_root`, which may be a subdirectory of the
+/// repo; untracked paths are left out.
+pub fn last_commit_times(repo_root: &Path, paths: &[String]) -> Option<HashMap<String, i64>> {
+    let mut cmd = git(repo_root)?;
+    cmd.args(["-c", "core.quotePath=false", "log", "--relative"])
+        .args(["--no-renames", "--name-only", "--format=%x00%ct", "HEAD"]);
+    let mut wanted: HashSet<String> = paths.iter().cloned().collect();
+
+    // Newest commits come first: "\0<time>", a blank line, then the paths.
+    // Stopping early closes the pipe, which ends git
+    let ((times, all_found), status) = process::run(cmd, None, git_timeout(), move |stdout| {
+        let mut times = HashMap::new();
+        let mut commit_time = None;
+        for line in BufReader::new(stdout).split(b'\n') {
+            if wanted.is_empty() {
+                break;
+            }
+            let Ok(line) = line else { break };
+            if let Some(time) = line.strip_prefix(b"\0") {
+                commit_time = String::from_utf8_lossy(time).trim().parse::<i64>().ok();
+                continue;
             }
-        })
+            let path = String::from_utf8_lossy(&line);
+            if let (Some(time), true) = (commit_time, wanted.remove(path.as_ref())) {
+                times.insert(path.into_owned(), time);
+            }
+        }
+        (times, wanted.is_empty())
+    })
+    .ok()?;
+    (status.success() || all_found).then_some(times)
+}
+
+/// Tracked paths under `repo_root` whose working-tree or staged content
+/// differs from HEAD.
+pub fn changed_since_head(repo_root: &Path) -> Option<Vec<String>> {
+    let records = git_records(
+        git(repo_root)?,
+        &[
+            "diff",
+            "--name-only",
+            "--relative",
+            "--no-renames",
+            "-z",
+            "HEAD",
+        ],
+        0,
+    )?;
+    Some(paths(records))
+}
+
+/// `(from, to)` of the files git sees renamed between commit `since` and
+/// `until` (the working tree and index when `None`), relative to
+/// `repo_root`. Renames are detected with git's default similarity, so a
+/// file edited as it moved still counts.
+pub fn renames_since(
+    repo_root: &Path,
+    since: &str,
+    until: Option<&str>,
+) -> Option<Vec<(String, String)>> {
+    let mut args = vec![
+        "-c",
+        "core.quotePath=false",
+        "diff",
+        "--relative",
+        "--find-renames",
+        "--diff-filter=R",
+        "--name-status",
+        "-z",
+        "--end-of-options",
+        since,
+    ];
+    args.extend(until);
+    let records = paths(git_records(git(repo_root)?, &args, 0)?);
+
+    // "R<score>", then the old and new path
+    let mut renames = Vec::new();
+    let mut records = records.into_iter();
+    while let Some(status) = records.next() {
+        let (Some(from), Some(to)) = (records.next(), records.next()) else {
+            break;
+        };
+        if status.starts_with('R') {
+            renames.push((from, to));
+        }
+    }
+    Some(renames)
+}
+
+/// A commit as [`commits_since`] and [`read_commit`] read it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggedCommit {
+    pub sha: String,
+    pub author: String,
+    /// Committer time, UNIX seconds
+    pub timestamp: i64,
+    /// Committer date, `YYYY-MM-DD`
+    pub date: String,
+    pub subject: String,
+    pub body: String,
+    /// Paths the commit touched, relative to `repo_root`
+    pub files: Vec<String>,
+}
+
+/// Fields of a [`LoggedCommit`], each record opened by RS and the fields
+/// ended by US; the touched paths follow the last one.
+const LOG_FORMAT: &str = "--format=%x1e%H%x1f%an%x1f%ct%x1f%cs%x1f%s%x1f%b%x1f";
+
+/// Commits reachable from HEAD with a committer time at or after `since`
+/// (UNIX seconds), newest first, that touch something under `repo_root`.
+pub fn commits_since(repo_root: &Path, since: i64) -> Option<Vec<LoggedCommit>> {
+    let since = format!("--since=@{since}");
+    let records = git_records(
+        git(repo_root)?,
+        &[
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "--relative",
+            "--no-renames",
+            "--name-only",
+            LOG_FORMAT,
+            &since,
+            "HEAD",
+            "--",
+            ".",
+        ],
+        0x1e,
+    )?;
+    Some(records.iter().filter_map(|r| logged_commit(r)).collect())
+}
+
+/// The commit `sha` names, with the paths it touched under `repo_root`.
+pub fn read_commit(repo_root: &Path, sha: &str) -> Option<LoggedCommit> {
+    let records = git_records(
+        git(repo_root)?,
+        &[
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "-1",
+            "--relative",
+            "--no-renames",
+            "--name-only",
+            LOG_FORMAT,
+            "--end-of-options",
+            sha,
+        ],
+        0x1e,
+    )?;
+    logged_commit(records.first()?)
+}
+
+/// Patch of commit `sha` against its parent, limited to `path` (a file or
+/// directory relative to `repo_root`) or else to everything under
+/// `repo_root`, as lines. Reading stops after `max_bytes`; the flag tells
+/// whether it did.
+pub fn commit_patch(
+    repo_root: &Path,
+    sha: &str,
+    path: Option<&str>,
+    max_bytes: usize,
+) -> Option<(Vec<String>, bool)> {
+    let mut cmd = git(repo_root)?;
+    cmd.args(["-c", "core.quotePath=false", "show", "--relative"])
+        .args(["--no-renames", "--no-color", "--no-ext-diff", "--format="])
+        .args(["--end-of-options", sha, "--", path.unwrap_or(".")]);
+    let limits = OutputLimits {
+        max_bytes,
+        ..OutputLimits::timeout(git_timeout())
+    };
+    let output = process::capture(cmd, b'\n', limits).ok()?;
+    // Stopping early closes the pipe, which may fail git
+    (output.success || output.truncated).then(|| {
+        let lines = output
+            .records
+            .iter()
+            .map(|line| String::from_utf8_lossy(line).into_owned())
+            .collect();
+        (lines, output.truncated)
+    })
+}
+
+/// One [`LOG_FORMAT`] record, opening separator stripped.
+fn logged_commit(record: &[u8]) -> Option<LoggedCommit> {
+    let text = String::from_utf8_lossy(record);
+    let mut fields = text.splitn(7, '\x1f');
+    let sha = fields.next()?.trim().to_string();
+    let author = fields.next()?.to_string();
+    let timestamp = fields.next()?.trim().parse().ok()?;
+    let date = fields.next()?.to_string();
+    let subject = fields.next()?.to_string();
+    let body = fields.next()?.trim_end().to_string();
+    let files = fields
+        .next()
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect();
+    Some(LoggedCommit {
+        sha,
+        author,
+        timestamp,
+        date,
+        subject,
+        body,
+        files,
+    })
 }
 
 #[cfg(test)]
@@ -55,4 +369,27 @@ mod tests {
         let sha = head_commit_sha(Path::new("/nonexistent/path/that/does/not/exist"));
         assert!(sha.is_none(), "should return None for nonexistent path");
     }
+
+    #[test]
+    fn read_files_at_skips_missing_paths() {
+        let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .parent()
+            .unwrap()
+            .to_path_buf();
+        let head = resolve_commit(&repo_root, "HEAD").unwrap();
+        assert!(resolve_commit(&repo_root, "no-such-ref-anywhere").is_none());
+        assert!(tracked_files_at(&repo_root, &head)
+            .unwrap()
+            .contains(&"Cargo.toml".to_string()));
+
+        let files = read_files_at(
+            &repo_root,
+            &head,
+            &["missing/file.rs".to_string(), "Cargo.toml".to_string()],
+        )
+        .unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, "Cargo.toml");
+        assert!(String::from_utf8_lossy(&files[0].1).contains("[workspace]"));
+    }
 }
@@ -0,0 +1,212 @@
+//! Annotation (TODO/FIXME marker comment) search and counts.
+
+use crate::handle::{AnnotationHandle, Handle, HandleId};
+use crate::query::split_terms;
+use rusqlite::params;
+use std::collections::BTreeMap;
+
+use super::search::{collect_row_results, handle_from_row, HANDLE_SELECT};
+use super::RepoIndex;
+
+impl RepoIndex {
+    /// Annotations matching `pattern`, ordered by path and line.
+    ///
+    /// Every term of `pattern` must appear in the text or name the marker
+    /// (case-insensitive); an empty pattern matches all annotations.
+    pub fn search_annotations(
+        &self,
+        pattern: &str,
+        glob: Option<&str>,
+        limit: usize,
+    ) -> crate::Result<Vec<AnnotationHandle>> {
+        let matcher = glob.map(|g| self.path_style.glob(g)).transpose()?;
+        let terms = split_terms(pattern);
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT f.path, a.line, a.marker, a.text, n.handle_id
+             FROM annotations a
+             JOIN files f ON a.file_id = f.id
+             LEFT JOIN nodes n ON a.node_id = n.id
+             WHERE 1{}
+             ORDER BY f.path, a.line",
+            self.scope_filter()
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            let line: i64 = row.get(1)?;
+            let handle_id: Option<String> = row.get(4)?;
+            Ok(AnnotationHandle {
+                file_path: row.get(0)?,
+                line: line.max(0) as usize,
+                marker: row.get(2)?,
+                text: row.get(3)?,
+                source_handle: handle_id.map(HandleId::from_raw),
+            })
+        })?;
+
+        let mut annotations = Vec::new();
+        for row in rows {
+            let annotation = row?;
+            if matcher
+                .as_ref()
+                .is_some_and(|m| !m.is_match(&annotation.file_path))
+            {
+                continue;
+            }
+            if !annotation_matches(&annotation.marker, &annotation.text, &terms) {
+                continue;
+            }
+            annotations.push(annotation);
+            if annotations.len() >= limit {
+                break;
+            }
+        }
+        Ok(annotations)
+    }
+
+    /// Enclosing nodes of annotations matching `pattern`, for composite queries.
+    pub fn search_annotation_sources(
+        &self,
+        pattern: &str,
+        limit: usize,
+    ) -> crate::Result<Vec<Handle>> {
+        let terms = split_terms(pattern);
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {HANDLE_SELECT}, a.marker, a.text
+             FROM annotations a
+             JOIN nodes n ON a.node_id = n.id
+             JOIN files f ON n.file_id = f.id
+             WHERE 1{}
+             ORDER BY f.path, a.line",
+            self.scope_filter()
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            let marker: String = row.get(11)?;
+            let text: String = row.get(12)?;
+            Ok((handle_from_row(row)?, marker, text))
+        })?;
+
+        let mut handles: Vec<Handle> = Vec::new();
+        for row in rows {
+            let (handle, marker, text) = row?;
+            if !annotation_matches(&marker, &text, &terms)
+                || handles.iter().any(|h| h.id == handle.id)
+            {
+                continue;
+            }
+            handles.push(handle);
+            if handles.len() >= limit {
+                break;
+            }
+        }
+        Ok(handles)
+    }
+
+    /// Annotation counts by marker for this database only.
+    pub(super) fn local_annotation_counts(&self) -> crate::Result<BTreeMap<String, usize>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT marker, COUNT(*) FROM annotations GROUP BY marker")?;
+        let rows = collect_row_results(stmt.query_map(params![], |row| {
+            let count: i64 = row.get(1)?;
+            Ok((row.get::<_, String>(0)?, count.max(0) as usize))
+        })?)?;
+        Ok(rows.into_iter().collect())
+    }
+}
+
+pub(super) fn annotation_matches(marker: &str, text: &str, terms: &[String]) -> bool {
+    let text = text.to_lowercase();
+    terms
+        .iter()
+        .all(|term| text.contains(term.as_str()) || marker.eq_ignore_ascii_case(term))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_helpers::setup_repo;
+    use super::*;
+    use std::fs;
+
+    fn annotated_repo() -> (canopy_testutil::FixtureRepo, RepoIndex) {
+        let dir = setup_repo(0);
+        fs::write(
+            dir.path().join("src/auth.rs"),
+            "// TODO: rotate auth keys on a schedule\n\
+             fn login() {\n    // FIXME(auth): session expiry is ignored\n    check();\n}\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("src/db.py"),
+            "def connect():\n    pass  # TODO retry with backoff\n# HACK: auth token in env\n",
+        )
+        .unwrap();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.{rs,py}").unwrap();
+        (dir, index)
+    }
+
+    #[test]
+    fn annotations_found_inside_and_outside_nodes() {
+        let (_dir, index) = annotated_repo();
+
+        let all = index.search_annotations("", None, 100).unwrap();
+        let summary: Vec<(&str, usize, &str)> = all
+            .iter()
+            .map(|a| (a.file_path.as_str(), a.line, a.marker.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("src/auth.rs", 1, "TODO"),
+                ("src/auth.rs", 3, "FIXME"),
+                ("src/db.py", 2, "TODO"),
+                ("src/db.py", 3, "HACK"),
+            ]
+        );
+
+        // The top-of-file TODO sits outside any function; the FIXME is inside login()
+        assert!(all[0].source_handle.is_none());
+        let inside = all[1].source_handle.as_ref().unwrap();
+        let expanded = index.expand(&[inside.to_string()]).unwrap();
+        assert!(expanded[0].1.starts_with("fn login()"));
+    }
+
+    #[test]
+    fn annotation_search_filters_by_terms_marker_and_glob() {
+        let (_dir, index) = annotated_repo();
+
+        let auth = index.search_annotations("auth", None, 100).unwrap();
+        assert_eq!(auth.len(), 3);
+
+        let fixme = index.search_annotations("fixme auth", None, 100).unwrap();
+        assert_eq!(fixme.len(), 1);
+        assert_eq!(fixme[0].text, "FIXME(auth): session expiry is ignored");
+
+        let python = index
+            .search_annotations("auth", Some("**/*.py"), 100)
+            .unwrap();
+        assert_eq!(python.len(), 1);
+        assert_eq!(python[0].marker, "HACK");
+
+        assert_eq!(index.search_annotations("", None, 2).unwrap().len(), 2);
+
+        let sources = index.search_annotation_sources("session", 10).unwrap();
+        assert_eq!(sources.len(), 1);
+        assert!(sources[0].preview.contains("login"));
+    }
+
+    #[test]
+    fn status_counts_annotations_by_marker_and_invalidate_clears_them() {
+        let (_dir, mut index) = annotated_repo();
+
+        let counts = index.status().unwrap().annotations;
+        assert_eq!(counts.get("TODO"), Some(&2));
+        assert_eq!(counts.get("FIXME"), Some(&1));
+        assert_eq!(counts.get("HACK"), Some(&1));
+
+        index.invalidate(Some("**/*.py")).unwrap();
+        let counts = index.status().unwrap().annotations;
+        assert_eq!(counts.get("TODO"), Some(&1));
+        assert!(!counts.contains_key("HACK"));
+    }
+}
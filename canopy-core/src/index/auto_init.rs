@@ -0,0 +1,210 @@
+//! Initializing `.canopy/` on first use instead of through `canopy init`.
+//!
+//! Whether a caller may do so is an [`AutoInit`] policy: the MCP server
+//! allows it by default, the CLI only with `--auto-init`, and
+//! `CANOPY_AUTO_INIT` overrides either default. Auto-init writes `.canopy/`
+//! and nothing else; `.gitignore` is only touched when the policy says so.
+//! It also refuses directories that don't look like a repo root, so a wrong
+//! path doesn't end up indexing `$HOME`.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::{InitOptions, RepoIndex};
+use crate::config::Preset;
+use crate::error::CanopyError;
+
+/// Environment variable overriding a caller's auto-init default: `1`/`true`/
+/// `on` allows it, `0`/`false`/`off` forbids it.
+pub const AUTO_INIT_ENV: &str = "CANOPY_AUTO_INIT";
+
+/// Entries a directory that isn't a git repo root may hold and still be
+/// auto-initialized
+pub const AUTO_INIT_MAX_ENTRIES: usize = 200;
+
+/// Whether opening an uninitialized repo initializes it, and how.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AutoInit {
+    pub enabled: bool,
+    /// Add `.canopy/` to the repo's `.gitignore` when initializing
+    pub update_gitignore: bool,
+}
+
+impl AutoInit {
+    pub fn on() -> Self {
+        Self {
+            enabled: true,
+            update_gitignore: false,
+        }
+    }
+
+    pub fn off() -> Self {
+        Self::default()
+    }
+
+    /// `on` unless `CANOPY_AUTO_INIT` says otherwise.
+    pub fn from_env(on: bool) -> Self {
+        let enabled = std::env::var(AUTO_INIT_ENV)
+            .ok()
+            .and_then(|value| parse_switch(&value))
+            .unwrap_or(on);
+        Self {
+            enabled,
+            update_gitignore: false,
+        }
+    }
+
+    pub fn with_update_gitignore(mut self, update_gitignore: bool) -> Self {
+        self.update_gitignore = update_gitignore;
+        self
+    }
+}
+
+fn parse_switch(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "on" | "yes" => Some(true),
+        "0" | "false" | "off" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// What an auto-init created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoInitReport {
+    pub canopy_dir: PathBuf,
+    pub gitignore_updated: bool,
+}
+
+impl fmt::Display for AutoInitReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "initialized {}", self.canopy_dir.display())?;
+        if self.gitignore_updated {
+            write!(f, " and added it to .gitignore")
+        } else {
+            write!(f, " (.gitignore left untouched)")
+        }
+    }
+}
+
+impl RepoIndex {
+    /// Open `repo_root`'s index, first initializing it when `.canopy/` is
+    /// missing and `auto_init` allows it. Returns what was created, if
+    /// anything; `NotInitialized` when auto-init is off.
+    pub fn open_with_auto_init(
+        repo_root: &Path,
+        auto_init: AutoInit,
+    ) -> crate::Result<(Self, Option<AutoInitReport>)> {
+        let canopy_dir = repo_root.join(".canopy");
+        if canopy_dir.exists() || !auto_init.enabled {
+            return Ok((Self::open(repo_root)?, None));
+        }
+        check_auto_init_target(repo_root)?;
+        let options = InitOptions {
+            write_gitignore: auto_init.update_gitignore,
+            write_default_config: true,
+        };
+        Self::init_with_options(repo_root, Preset::detect(repo_root), false, options)?;
+        let report = AutoInitReport {
+            canopy_dir,
+            gitignore_updated: auto_init.update_gitignore,
+        };
+        Ok((Self::open(repo_root)?, Some(report)))
+    }
+}
+
+/// Refuse to auto-initialize a directory that isn't a git repo root and
+/// holds more than [`AUTO_INIT_MAX_ENTRIES`] entries.
+fn check_auto_init_target(repo_root: &Path) -> crate::Result<()> {
+    if repo_root.join(".git").exists() {
+        return Ok(());
+    }
+    let entries = std::fs::read_dir(repo_root)?
+        .take(AUTO_INIT_MAX_ENTRIES + 1)
+        .count();
+    if entries > AUTO_INIT_MAX_ENTRIES {
+        return Err(CanopyError::AutoInitRefused {
+            path: repo_root.to_path_buf(),
+            entries,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn repo(git: bool, files: usize) -> tempfile::TempDir {
+        let dir = tempfile::TempDir::new().unwrap();
+        if git {
+            fs::create_dir(dir.path().join(".git")).unwrap();
+        }
+        for i in 0..files {
+            fs::write(dir.path().join(format!("f{i}.rs")), "fn f() {}\n").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn auto_init_creates_canopy_and_only_touches_gitignore_when_asked() {
+        let dir = repo(true, 1);
+        let (_, report) = RepoIndex::open_with_auto_init(dir.path(), AutoInit::on()).unwrap();
+        let report = report.unwrap();
+        assert_eq!(report.canopy_dir, dir.path().join(".canopy"));
+        assert!(!report.gitignore_updated);
+        assert!(dir.path().join(".canopy/config.toml").exists());
+        assert!(!dir.path().join(".gitignore").exists());
+        // Already initialized: nothing more to report
+        let (_, report) = RepoIndex::open_with_auto_init(dir.path(), AutoInit::on()).unwrap();
+        assert!(report.is_none());
+
+        let dir = repo(true, 1);
+        let policy = AutoInit::on().with_update_gitignore(true);
+        let (_, report) = RepoIndex::open_with_auto_init(dir.path(), policy).unwrap();
+        assert!(report.unwrap().gitignore_updated);
+        let gitignore = fs::read_to_string(dir.path().join(".gitignore")).unwrap();
+        assert!(gitignore.contains(".canopy/"));
+    }
+
+    #[test]
+    fn auto_init_off_leaves_uninitialized_repos_alone() {
+        let dir = repo(true, 1);
+        let err = RepoIndex::open_with_auto_init(dir.path(), AutoInit::off())
+            .err()
+            .unwrap();
+        assert!(matches!(err, CanopyError::NotInitialized));
+        assert!(!dir.path().join(".canopy").exists());
+
+        // An explicitly initialized repo opens either way
+        RepoIndex::init(dir.path()).unwrap();
+        assert!(RepoIndex::open_with_auto_init(dir.path(), AutoInit::off()).is_ok());
+    }
+
+    #[test]
+    fn auto_init_refuses_large_directories_outside_git_repos() {
+        let crowded = repo(false, AUTO_INIT_MAX_ENTRIES + 1);
+        let err = RepoIndex::open_with_auto_init(crowded.path(), AutoInit::on())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err,
+            CanopyError::AutoInitRefused { entries, .. } if entries == AUTO_INIT_MAX_ENTRIES + 1
+        ));
+        assert!(!crowded.path().join(".canopy").exists());
+
+        // A git root of any size, or a small directory, is fine
+        let big_repo = repo(true, AUTO_INIT_MAX_ENTRIES + 1);
+        assert!(RepoIndex::open_with_auto_init(big_repo.path(), AutoInit::on()).is_ok());
+        let small = repo(false, 3);
+        assert!(RepoIndex::open_with_auto_init(small.path(), AutoInit::on()).is_ok());
+    }
+
+    #[test]
+    fn switch_values_parse() {
+        assert_eq!(parse_switch("1"), Some(true));
+        assert_eq!(parse_switch(" On "), Some(true));
+        assert_eq!(parse_switch("false"), Some(false));
+        assert_eq!(parse_switch("maybe"), None);
+    }
+}
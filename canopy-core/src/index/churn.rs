@@ -0,0 +1,240 @@
+//! Files that are reparsed on nearly every indexing run, and the FTS bloat
+//! they cause.
+//!
+//! A reparse deletes a file's rows from `content_fts` and `symbol_fts` and
+//! inserts them again. Files that change constantly (version headers,
+//! lockfiles that slipped past the ignores) do so every run, and FTS5's
+//! segments fragment and grow while the logical content stays the same.
+//!
+//! Each `files` row counts its reparses since `churn_since`, restarting the
+//! count once that is more than [`CHURN_WINDOW_SECS`] ago;
+//! [`RepoIndex::status_detailed`] lists the most-reparsed files, and status
+//! warns about files reparsed more than [`CHURN_WARN_REINDEXES`] times. The
+//! tokens of every reparse also add up in `meta`, and once they pass
+//! `[fts] optimize_after_tokens` the next indexing run merges the FTS
+//! segments with FTS5's `optimize` command.
+
+use rusqlite::{params, OptionalExtension, Transaction};
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::RepoIndex;
+
+/// Window a file's reparse count covers
+pub const CHURN_WINDOW_SECS: i64 = 7 * 86_400;
+
+/// Reparses within the window past which status suggests ignoring a file
+pub const CHURN_WARN_REINDEXES: usize = 20;
+
+/// Files listed in [`IndexStatus::churning_files`](super::IndexStatus::churning_files)
+pub const CHURNING_FILES: usize = 10;
+
+/// `meta` key of the tokens reparsed since the last optimize
+const REINDEXED_TOKENS_META_KEY: &str = "fts_reindexed_tokens";
+
+/// `meta` key of the last optimize (UNIX seconds)
+const LAST_OPTIMIZE_META_KEY: &str = "fts_last_optimize";
+
+/// A file reparsed within the churn window.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChurningFile {
+    pub path: String,
+    /// Reparses since the window started
+    pub reindex_count: usize,
+    pub token_count: usize,
+}
+
+/// A reparsed file's `(reindex_count, churn_since)` given its stored values;
+/// a file indexed for the first time starts at `(0, None)`.
+pub(super) fn next_churn(stored: Option<(i64, Option<i64>)>, now: i64) -> (i64, Option<i64>) {
+    match stored {
+        None => (0, None),
+        Some((count, Some(since))) if now - since <= CHURN_WINDOW_SECS => (count + 1, Some(since)),
+        Some(_) => (1, Some(now)),
+    }
+}
+
+/// Count `tokens` of reparsed content towards the next optimize.
+pub(super) fn add_reindexed_tokens(tx: &Transaction<'_>, tokens: usize) -> crate::Result<()> {
+    tx.execute(
+        "INSERT INTO meta (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = CAST(value AS INTEGER) + excluded.value",
+        params![REINDEXED_TOKENS_META_KEY, tokens as i64],
+    )?;
+    Ok(())
+}
+
+fn window_start() -> i64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    now - CHURN_WINDOW_SECS
+}
+
+impl RepoIndex {
+    /// Merge the FTS segments when the tokens reparsed since the last merge
+    /// pass `[fts] optimize_after_tokens`. Returns whether it ran.
+    pub(super) fn optimize_fts_if_due(&self, now_secs: i64) -> crate::Result<bool> {
+        let threshold = self.config.fts.optimize_after_tokens;
+        if threshold == 0 {
+            return Ok(false);
+        }
+        let reindexed: i64 = self
+            .conn
+            .query_row(
+                "SELECT CAST(value AS INTEGER) FROM meta WHERE key = ?",
+                params![REINDEXED_TOKENS_META_KEY],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or(0);
+        if (reindexed.max(0) as usize) < threshold {
+            return Ok(false);
+        }
+        self.conn.execute_batch(
+            "INSERT INTO content_fts(content_fts) VALUES('optimize');
+             INSERT INTO symbol_fts(symbol_fts) VALUES('optimize');",
+        )?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, '0'), (?2, ?3)",
+            params![
+                REINDEXED_TOKENS_META_KEY,
+                LAST_OPTIMIZE_META_KEY,
+                now_secs.to_string()
+            ],
+        )?;
+        Ok(true)
+    }
+
+    /// When this database's FTS segments were last merged (UNIX seconds).
+    pub(super) fn local_last_fts_optimize(&self) -> crate::Result<Option<i64>> {
+        let value: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?",
+                params![LAST_OPTIMIZE_META_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(value.and_then(|v| v.parse().ok()))
+    }
+
+    /// This database's most-reparsed files within the window, most first.
+    pub(super) fn local_churning_files(&self, limit: usize) -> crate::Result<Vec<ChurningFile>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, reindex_count, token_count FROM files
+             WHERE reindex_count > 0 AND churn_since >= ?
+             ORDER BY reindex_count DESC, token_count DESC, path
+             LIMIT ?",
+        )?;
+        let rows = stmt.query_map(params![window_start(), limit as i64], |row| {
+            Ok(ChurningFile {
+                path: row.get(0)?,
+                reindex_count: row.get::<_, i64>(1)?.max(0) as usize,
+                token_count: row.get::<_, i64>(2)?.max(0) as usize,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Files of this database reparsed more than [`CHURN_WARN_REINDEXES`]
+    /// times within the window, and the most-reparsed one.
+    pub(super) fn local_churn_offenders(&self) -> crate::Result<(usize, Option<ChurningFile>)> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM files WHERE reindex_count > ? AND churn_since >= ?",
+            params![CHURN_WARN_REINDEXES as i64, window_start()],
+            |row| row.get(0),
+        )?;
+        if count == 0 {
+            return Ok((0, None));
+        }
+        Ok((count as usize, self.local_churning_files(1)?.pop()))
+    }
+}
+
+/// Status warning for `offenders` files reparsed too often, `worst` first.
+pub(super) fn churn_warning(offenders: usize, worst: &ChurningFile) -> String {
+    let others = match offenders {
+        0 | 1 => String::new(),
+        n => format!(" (and {} more)", n - 1),
+    };
+    format!(
+        "{} was reindexed {} times in the last {} days{}; if it changes on every run, \
+         add it to [ignore] patterns",
+        worst.path,
+        worst.reindex_count,
+        CHURN_WINDOW_SECS / 86_400,
+        others
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::test_helpers::setup_repo;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn churn_counts_restart_after_the_window() {
+        let now = 1_000_000_000;
+        assert_eq!(next_churn(None, now), (0, None));
+        // Files indexed before churn tracking start a window on their first reparse
+        assert_eq!(next_churn(Some((0, None)), now), (1, Some(now)));
+        assert_eq!(
+            next_churn(Some((3, Some(now - 60))), now),
+            (4, Some(now - 60))
+        );
+        let stale = now - CHURN_WINDOW_SECS - 1;
+        assert_eq!(next_churn(Some((30, Some(stale))), now), (1, Some(now)));
+    }
+
+    #[test]
+    fn repeated_reindexing_is_counted_warned_about_and_optimized() {
+        let dir = setup_repo(2);
+        fs::write(
+            dir.path().join(".canopy/config.toml"),
+            "[fts]\noptimize_after_tokens = 200\n",
+        )
+        .unwrap();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        let churning = dir.path().join("src/version.rs");
+
+        let mut optimized_at = None;
+        for run in 0..=CHURN_WARN_REINDEXES + 1 {
+            let body = format!("pub const BUILD: u32 = {run};\n{}", "// pad\n".repeat(40));
+            fs::write(&churning, body).unwrap();
+            // Same-second rewrites would keep the mtime and be skipped
+            fs::File::options()
+                .write(true)
+                .open(&churning)
+                .unwrap()
+                .set_modified(SystemTime::now() + Duration::from_secs(60 * (run as u64 + 1)))
+                .unwrap();
+            let stats = index.index("**/*.rs").unwrap();
+            if stats.fts_optimized && optimized_at.is_none() {
+                optimized_at = Some(run);
+            }
+        }
+
+        // The first run indexed the file; the rest reparsed it
+        let status = index.status_detailed().unwrap();
+        let top = &status.churning_files.as_ref().unwrap()[0];
+        assert_eq!(top.path, "src/version.rs");
+        assert_eq!(top.reindex_count, CHURN_WARN_REINDEXES + 1);
+        assert!(top.token_count > 0);
+        // Unchanged files were never reparsed
+        assert_eq!(status.churning_files.as_ref().unwrap().len(), 1);
+        let warning = status.churn_warning.unwrap();
+        assert!(warning.contains("src/version.rs") && warning.contains("[ignore]"));
+        assert!(index.status().unwrap().churn_warning.is_some());
+
+        // Reparsed tokens reached the threshold part way through, and reset
+        let run = optimized_at.expect("optimize should have run");
+        assert!(run > 1 && run < CHURN_WARN_REINDEXES, "{run}");
+        assert!(status.last_fts_optimize.is_some());
+        assert!(index.local_last_fts_optimize().unwrap().is_some());
+    }
+}
@@ -0,0 +1,139 @@
+//! Point-in-time copies of an index, for serving handles of an older build.
+//!
+//! A copy holds the catch-all database and every shard database as they
+//! were, written with `VACUUM INTO` so readers of the live index aren't
+//! blocked. Files read back at expansion time come from the working tree
+//! while they are unchanged, and from the copy's commit once they aren't.
+
+use std::fs;
+use std::path::Path;
+
+use super::sharding::{shard_file_name, ShardRouter, SHARDS_DIR};
+use super::RepoIndex;
+use crate::error::CanopyError;
+
+/// Catch-all database inside a copy directory.
+const COPY_DB: &str = "index.db";
+
+impl RepoIndex {
+    /// Write a copy of this index (and its shards) into `dir`, replacing
+    /// anything there. Returns the bytes written.
+    pub fn copy_to(&self, dir: &Path) -> crate::Result<u64> {
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        fs::create_dir_all(dir)?;
+
+        let mut targets = vec![(self, dir.join(COPY_DB))];
+        if !self.shards.shards.is_empty() {
+            let shards_dir = dir.join(SHARDS_DIR);
+            fs::create_dir_all(&shards_dir)?;
+            for shard in &self.shards.shards {
+                targets.push((
+                    &shard.index,
+                    shards_dir.join(shard_file_name(&shard.prefix)),
+                ));
+            }
+        }
+
+        let mut bytes = 0;
+        for (index, path) in targets {
+            index
+                .conn
+                .execute("VACUUM INTO ?1", [path.to_string_lossy().as_ref()])?;
+            bytes += fs::metadata(&path)?.len();
+        }
+        Ok(bytes)
+    }
+
+    /// Open a copy written by [`copy_to`](Self::copy_to). `commit_sha` is the
+    /// commit the copied index was built from; without it, handles in files
+    /// changed since then expand to `StaleIndex`.
+    pub fn open_copy(
+        repo_root: &Path,
+        dir: &Path,
+        commit_sha: Option<String>,
+    ) -> crate::Result<Self> {
+        let db_path = dir.join(COPY_DB);
+        if !db_path.exists() {
+            return Err(CanopyError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no index copy at {}", dir.display()),
+            )));
+        }
+        let config = Self::load_config(repo_root)?;
+        let mut index = Self::open_db(repo_root, db_path, config)?;
+        index.shards =
+            ShardRouter::open_in(repo_root, &dir.join(SHARDS_DIR), &index.config, false)?;
+        index.source_commit = commit_sha;
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::time::{Duration, SystemTime};
+
+    fn git(root: &Path, args: &[&str]) {
+        let output = Command::new("git")
+            .args(["-c", "user.name=canopy", "-c", "user.email=canopy@test"])
+            .args(args)
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed");
+    }
+
+    fn commit_edit(root: &Path, content: &str) {
+        let path = root.join("src/lib.rs");
+        fs::write(&path, content).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        git(root, &["add", "-A"]);
+        git(root, &["commit", "-q", "-m", "edit"]);
+    }
+
+    #[test]
+    fn copy_expands_handles_of_the_older_build() {
+        let repo = tempfile::TempDir::new().unwrap();
+        let root = repo.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "fn original() { first(); }\n").unwrap();
+        fs::write(root.join("src/other.rs"), "fn untouched() {}\n").unwrap();
+        RepoIndex::init(root).unwrap();
+        git(root, &["init", "-q"]);
+        git(root, &["add", "-A"]);
+        git(root, &["commit", "-q", "-m", "base"]);
+        let base = crate::git::head_commit_sha(root);
+
+        let mut index = RepoIndex::open(root).unwrap();
+        index.index("**/*.rs").unwrap();
+        let old = index.search_code("original", 1).unwrap().remove(0).id;
+        let kept = index.search_code("untouched", 1).unwrap().remove(0).id;
+
+        let copies = tempfile::TempDir::new().unwrap();
+        let dir = copies.path().join("1");
+        assert!(index.copy_to(&dir).unwrap() > 0);
+
+        commit_edit(root, "fn replaced() { second(); }\n");
+        index.index("**/*.rs").unwrap();
+        assert!(index.expand(&[old.to_string()]).is_err());
+
+        let copy = RepoIndex::open_copy(root, &dir, base).unwrap();
+        let expanded = copy.expand(&[old.to_string(), kept.to_string()]).unwrap();
+        assert_eq!(expanded[0].1, "fn original() { first(); }");
+        assert_eq!(expanded[1].1, "fn untouched() {}");
+
+        // Without the commit, the changed file can't be recovered
+        let blind = RepoIndex::open_copy(root, &dir, None).unwrap();
+        let err = blind.expand(&[old.to_string()]).unwrap_err();
+        assert!(matches!(err, CanopyError::StaleIndex { .. }), "{err:?}");
+        assert!(RepoIndex::open_copy(root, &copies.path().join("2"), None).is_err());
+    }
+}
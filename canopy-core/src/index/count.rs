@@ -0,0 +1,507 @@
+//! Count and exists modes: how many nodes a query matches, or whether any
+//! does, without building handles.
+//!
+//! Plain lookups become one `SELECT COUNT(*)` (or `LIMIT 1`) statement, with
+//! unions and intersections composed as compound selects. Searches SQL can't
+//! express (glob filters, annotation terms, `(file ...)`) read only the
+//! matching ids and paths.
+
+use crate::document::{NodeType, RefType, HEADING_PATH_SEPARATOR};
+use crate::error::CanopyError;
+use crate::query::executor::combined_commits_error;
+use crate::query::{split_terms, PathPredicate, Query, QueryMode};
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, OptionalExtension};
+use std::collections::BTreeMap;
+
+use super::annotations::annotation_matches;
+use super::search::{
+    code_type_params, escape_fts5_query, escape_like, ref_type_clause, ref_type_names,
+    split_heading_path,
+};
+use super::symbols::prefix_filter;
+use super::{PathScope, RecentScope, RepoIndex};
+
+/// Columns every match select returns, so selects compose with UNION/INTERSECT
+const MATCH_COLUMNS: &str = "n.handle_id AS id, f.path AS path";
+
+const NODES_FROM: &str = "nodes n JOIN files f ON n.file_id = f.id";
+
+/// Most selects composed into one compound statement; SQLite caps compound
+/// selects at 500 terms
+const MAX_COMPOUND_TERMS: usize = 100;
+
+/// Matches of a query in one database.
+#[derive(Debug, Default)]
+pub(crate) struct MatchCount {
+    pub(crate) count: usize,
+    /// Exists mode: a file holding a match
+    pub(crate) witness: Option<String>,
+}
+
+/// The nodes a query matches, as SQL or as rows already read.
+enum Matches {
+    /// A select of `(id, path)`
+    Sql { sql: String, params: Vec<Value> },
+    /// Match id -> file path
+    Rows(BTreeMap<String, String>),
+}
+
+impl RepoIndex {
+    /// How many nodes `query` matches here or, in exists mode, whether any
+    /// does, reading at most one row when SQL alone decides. With `permits`
+    /// the matching paths are read and checked instead.
+    pub(crate) fn count_query(
+        &self,
+        query: &Query,
+        mode: QueryMode,
+        permits: Option<&dyn PathPredicate>,
+    ) -> crate::Result<MatchCount> {
+        if let Some(permits) = permits {
+            let mut rows = self.read_rows(self.matches(query)?)?;
+            rows.retain(|_, path| permits.permits(path));
+            return Ok(permitted_count(rows.into_values(), mode));
+        }
+        if mode == QueryMode::Exists {
+            let witness = self.first_match(query)?;
+            return Ok(MatchCount {
+                count: usize::from(witness.is_some()),
+                witness,
+            });
+        }
+        let count = match self.matches(query)? {
+            Matches::Sql { sql, params } => self.count_rows(&sql, params)?,
+            Matches::Rows(rows) => rows.len(),
+        };
+        Ok(MatchCount {
+            count,
+            witness: None,
+        })
+    }
+
+    /// References to `symbol` (only `ref_types` when non-empty), counted the
+    /// way [`search_references`](Self::search_references) returns them.
+    pub(crate) fn count_references(
+        &self,
+        symbol: &str,
+        ref_types: &[RefType],
+        mode: QueryMode,
+        permits: Option<&dyn PathPredicate>,
+    ) -> crate::Result<MatchCount> {
+        let type_names = ref_type_names(ref_types);
+        let mut params = vec![Value::Text(symbol.to_lowercase())];
+        params.extend(type_names.iter().map(|t| Value::Text(t.to_string())));
+        let from = format!(
+            "FROM refs r JOIN files f ON r.file_id = f.id WHERE r.name_lower = ?{}{}",
+            ref_type_clause(type_names.len()),
+            self.scope_filter()
+        );
+
+        if let Some(permits) = permits {
+            let mut stmt = self.conn.prepare(&format!("SELECT f.path {from}"))?;
+            let paths = stmt
+                .query_map(params_from_iter(params), |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            let permitted = paths.into_iter().filter(|path| permits.permits(path));
+            return Ok(permitted_count(permitted, mode));
+        }
+        if mode == QueryMode::Exists {
+            let witness: Option<String> = self
+                .conn
+                .query_row(
+                    &format!("SELECT f.path {from} LIMIT 1"),
+                    params_from_iter(params),
+                    |row| row.get(0),
+                )
+                .optional()?;
+            return Ok(MatchCount {
+                count: usize::from(witness.is_some()),
+                witness,
+            });
+        }
+        let count: i64 = self.conn.query_row(
+            &format!("SELECT COUNT(*) {from}"),
+            params_from_iter(params),
+            |row| row.get(0),
+        )?;
+        Ok(MatchCount {
+            count: count.max(0) as usize,
+            witness: None,
+        })
+    }
+
+    fn first_match(&self, query: &Query) -> crate::Result<Option<String>> {
+        // A glob over SQL matches stops at the first path it accepts
+        if let Query::InFile(glob, inner) = query {
+            let matcher = self.path_style.glob(glob)?;
+            return match self.matches(inner)? {
+                Matches::Sql { sql, params } => {
+                    let mut stmt = self.conn.prepare(&format!("SELECT path FROM ({sql})"))?;
+                    let mut rows = stmt.query(params_from_iter(params))?;
+                    while let Some(row) = rows.next()? {
+                        let path: String = row.get(0)?;
+                        if matcher.is_match(&path) {
+                            return Ok(Some(path));
+                        }
+                    }
+                    Ok(None)
+                }
+                Matches::Rows(rows) => Ok(rows.into_values().find(|path| matcher.is_match(path))),
+            };
+        }
+
+        match self.matches(query)? {
+            Matches::Sql { sql, params } => Ok(self
+                .conn
+                .query_row(
+                    &format!("SELECT path FROM ({sql}) LIMIT 1"),
+                    params_from_iter(params),
+                    |row| row.get(0),
+                )
+                .optional()?),
+            Matches::Rows(rows) => Ok(rows.into_values().next()),
+        }
+    }
+
+    /// The nodes `query` matches, mirroring the search each query runs in
+    /// handles mode.
+    fn matches(&self, query: &Query) -> crate::Result<Matches> {
+        let section = Value::Integer(NodeType::Section.as_int() as i64);
+        Ok(match query {
+            Query::Grep(pattern) => self.select_matches(
+                "content_fts fts
+                 JOIN fts_node_map m ON fts.rowid = m.fts_rowid
+                 JOIN nodes n ON m.node_id = n.id
+                 JOIN files f ON n.file_id = f.id",
+                "content_fts MATCH ?",
+                vec![Value::Text(escape_fts5_query(pattern))],
+            ),
+
+            Query::Section(heading) => self.select_matches(
+                NODES_FROM,
+                "n.node_type = ? AND LOWER(json_extract(n.metadata, '$.heading')) LIKE ?",
+                vec![
+                    section,
+                    Value::Text(format!("%{}%", heading.to_lowercase())),
+                ],
+            ),
+
+            Query::SectionPath(path) => {
+                let segments = split_heading_path(path);
+                if segments.is_empty() {
+                    return Ok(Matches::Rows(BTreeMap::new()));
+                }
+                let suffix = segments.join(HEADING_PATH_SEPARATOR).to_lowercase();
+                let nested = format!("%{}{}", HEADING_PATH_SEPARATOR, escape_like(&suffix));
+                self.select_matches(
+                    NODES_FROM,
+                    "n.node_type = ?
+                     AND (LOWER(n.heading_path) = ? OR LOWER(n.heading_path) LIKE ? ESCAPE '\\')",
+                    vec![section, Value::Text(suffix), Value::Text(nested)],
+                )
+            }
+
+            // Like search_code: fuzzy only when nothing matches exactly
+            Query::Code(symbol) if !self.has_exact_symbol(symbol)? => {
+                let mut params = vec![Value::Text(escape_fts5_query(symbol))];
+                params.extend(code_type_values());
+                self.select_matches(
+                    "symbol_fts fts
+                     JOIN symbol_fts_map m ON fts.rowid = m.fts_rowid
+                     JOIN nodes n ON m.node_id = n.id
+                     JOIN files f ON n.file_id = f.id",
+                    "symbol_fts MATCH ? AND n.node_type IN (?, ?, ?, ?)",
+                    params,
+                )
+            }
+
+            Query::Code(symbol) | Query::Definition(symbol) => {
+                let mut params = vec![Value::Text(symbol.to_lowercase())];
+                params.extend(code_type_values());
+                self.select_matches(
+                    NODES_FROM,
+                    "n.name_lower = ? AND n.node_type IN (?, ?, ?, ?)",
+                    params,
+                )
+            }
+
+            Query::Children(parent) => self.select_matches(
+                NODES_FROM,
+                "n.parent_name_lower = ?",
+                vec![Value::Text(parent.to_lowercase())],
+            ),
+
+            Query::ChildrenNamed(parent, symbol) => self.select_matches(
+                NODES_FROM,
+                "n.parent_name_lower = ? AND n.name_lower = ?",
+                vec![
+                    Value::Text(parent.to_lowercase()),
+                    Value::Text(symbol.to_lowercase()),
+                ],
+            ),
+
+            // Nested reference queries match the nodes the references are in
+            Query::References(symbol, ref_types) => {
+                let type_names = ref_type_names(ref_types);
+                let mut params = vec![Value::Text(symbol.to_lowercase())];
+                params.extend(type_names.iter().map(|t| Value::Text(t.to_string())));
+                Matches::Sql {
+                    sql: format!(
+                        "SELECT DISTINCT {MATCH_COLUMNS}
+                         FROM refs r
+                         JOIN nodes n ON r.source_node_id = n.id
+                         JOIN files f ON n.file_id = f.id
+                         WHERE r.name_lower = ?{}{}",
+                        ref_type_clause(type_names.len()),
+                        self.scope_filter()
+                    ),
+                    params,
+                }
+            }
+
+            Query::Annotations(pattern) => {
+                let terms = split_terms(pattern);
+                let mut stmt = self.conn.prepare(&format!(
+                    "SELECT n.handle_id, f.path, a.marker, a.text
+                     FROM annotations a
+                     JOIN nodes n ON a.node_id = n.id
+                     JOIN files f ON n.file_id = f.id
+                     WHERE 1{}",
+                    self.scope_filter()
+                ))?;
+                let mut rows = stmt.query([])?;
+                let mut matched = BTreeMap::new();
+                while let Some(row) = rows.next()? {
+                    let marker: String = row.get(2)?;
+                    let text: String = row.get(3)?;
+                    if annotation_matches(&marker, &text, &terms) {
+                        matched.insert(row.get(0)?, row.get(1)?);
+                    }
+                }
+                Matches::Rows(matched)
+            }
+
+            // Whole-file handles aren't nodes; key them apart so they never
+            // intersect with node matches, as in handles mode
+            Query::File(glob, _) => {
+                let matcher = self.path_style.glob(glob)?;
+                let mut stmt = self.conn.prepare(&format!(
+                    "SELECT f.path FROM files f WHERE 1{}",
+                    self.scope_filter()
+                ))?;
+                let mut rows = stmt.query([])?;
+                let mut matched = BTreeMap::new();
+                while let Some(row) = rows.next()? {
+                    let path: String = row.get(0)?;
+                    if matcher.is_match(&path) {
+                        matched.insert(format!("file:{path}"), path);
+                    }
+                }
+                Matches::Rows(matched)
+            }
+
+            // Related files are whole files, keyed like `(file ...)` matches
+            Query::Related(path) => {
+                let related = match self.related_files(path, usize::MAX) {
+                    Err(CanopyError::FileNotFound(_)) => Vec::new(),
+                    other => other?.files,
+                };
+                Matches::Rows(
+                    related
+                        .into_iter()
+                        .map(|file| (format!("file:{}", file.path), file.path))
+                        .collect(),
+                )
+            }
+
+            Query::Symbols(prefix) => {
+                let (filter, params) = prefix_filter(prefix);
+                self.select_matches(NODES_FROM, &filter, params)
+            }
+
+            Query::Commits(_) => return Err(combined_commits_error()),
+
+            Query::InFile(glob, inner) => {
+                let matcher = self.path_style.glob(glob)?;
+                let mut rows = self.read_rows(self.matches(inner)?)?;
+                rows.retain(|_, path| matcher.is_match(path));
+                Matches::Rows(rows)
+            }
+
+            Query::Union(queries) => {
+                self.combine(queries, "UNION", |acc, rows| acc.extend(rows))?
+            }
+
+            Query::Intersect(queries) => self.combine(queries, "INTERSECT", |acc, rows| {
+                acc.retain(|id, _| rows.contains_key(id))
+            })?,
+
+            // The filter is baked into the SQL, so it outlives the scope
+            Query::Recent(window, inner) => {
+                let _scope = RecentScope::new([self], *window);
+                self.matches(inner)?
+            }
+
+            Query::PathContains(fragment, inner) => {
+                let _scope = PathScope::new([self], fragment);
+                self.matches(inner)?
+            }
+
+            // Exclusions are counted with the full-text test, even when a
+            // handles query checks a few candidates by substring instead
+            Query::Exclude(patterns, inner) => {
+                let excluded =
+                    Query::Union(patterns.iter().map(|p| Query::Grep(p.clone())).collect());
+                match (self.matches(inner)?, self.matches(&excluded)?) {
+                    (
+                        Matches::Sql { sql, mut params },
+                        Matches::Sql {
+                            sql: excluded_sql,
+                            params: excluded_params,
+                        },
+                    ) => {
+                        params.extend(excluded_params);
+                        Matches::Sql {
+                            sql: format!(
+                                "SELECT id, path FROM ({sql}) EXCEPT SELECT id, path FROM ({excluded_sql})"
+                            ),
+                            params,
+                        }
+                    }
+                    (matches, excluded) => {
+                        let mut rows = self.read_rows(matches)?;
+                        let excluded = self.read_rows(excluded)?;
+                        rows.retain(|id, _| !excluded.contains_key(id));
+                        Matches::Rows(rows)
+                    }
+                }
+            }
+
+            Query::Limit(n, inner) => match self.matches(inner)? {
+                Matches::Sql { sql, mut params } => {
+                    params.push(Value::Integer(*n as i64));
+                    Matches::Sql {
+                        // Wrapped again: compound members can't carry a LIMIT
+                        sql: format!("SELECT id, path FROM (SELECT id, path FROM ({sql}) LIMIT ?)"),
+                        params,
+                    }
+                }
+                Matches::Rows(rows) => Matches::Rows(rows.into_iter().take(*n).collect()),
+            },
+        })
+    }
+
+    /// `queries` joined by `operator` in SQL when each is a select, else
+    /// read and folded with `fold`.
+    fn combine(
+        &self,
+        queries: &[Query],
+        operator: &str,
+        fold: impl Fn(&mut BTreeMap<String, String>, BTreeMap<String, String>),
+    ) -> crate::Result<Matches> {
+        let parts = queries
+            .iter()
+            .map(|q| self.matches(q))
+            .collect::<crate::Result<Vec<_>>>()?;
+        if parts.is_empty() {
+            return Ok(Matches::Rows(BTreeMap::new()));
+        }
+
+        let all_sql = parts.iter().all(|m| matches!(m, Matches::Sql { .. }));
+        if all_sql && parts.len() <= MAX_COMPOUND_TERMS {
+            let mut selects = Vec::new();
+            let mut all_params = Vec::new();
+            for part in parts {
+                if let Matches::Sql { sql, params } = part {
+                    selects.push(format!("SELECT id, path FROM ({sql})"));
+                    all_params.extend(params);
+                }
+            }
+            return Ok(Matches::Sql {
+                sql: selects.join(&format!(" {operator} ")),
+                params: all_params,
+            });
+        }
+
+        let mut parts = parts.into_iter();
+        let mut acc = self.read_rows(parts.next().expect("checked non-empty"))?;
+        for part in parts {
+            let rows = self.read_rows(part)?;
+            fold(&mut acc, rows);
+        }
+        Ok(Matches::Rows(acc))
+    }
+
+    fn read_rows(&self, matches: Matches) -> crate::Result<BTreeMap<String, String>> {
+        match matches {
+            Matches::Sql { sql, params } => {
+                let mut stmt = self
+                    .conn
+                    .prepare(&format!("SELECT id, path FROM ({sql})"))?;
+                let rows = stmt.query_map(params_from_iter(params), |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?;
+                Ok(rows.collect::<Result<_, _>>()?)
+            }
+            Matches::Rows(rows) => Ok(rows),
+        }
+    }
+
+    /// Matching rows of `from`, inside the current search scope.
+    fn select_matches(&self, from: &str, filter: &str, params: Vec<Value>) -> Matches {
+        Matches::Sql {
+            sql: format!(
+                "SELECT {MATCH_COLUMNS} FROM {from} WHERE {filter}{}",
+                self.scope_filter()
+            ),
+            params,
+        }
+    }
+
+    fn count_rows(&self, sql: &str, params: Vec<Value>) -> crate::Result<usize> {
+        let count: i64 = self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM ({sql})"),
+            params_from_iter(params),
+            |row| row.get(0),
+        )?;
+        Ok(count.max(0) as usize)
+    }
+
+    fn has_exact_symbol(&self, symbol: &str) -> crate::Result<bool> {
+        let mut params = vec![Value::Text(symbol.to_lowercase())];
+        params.extend(code_type_values());
+        Ok(self.conn.query_row(
+            &format!(
+                "SELECT EXISTS(
+                     SELECT 1 FROM {NODES_FROM}
+                     WHERE n.name_lower = ? AND n.node_type IN (?, ?, ?, ?){}
+                 )",
+                self.scope_filter()
+            ),
+            params_from_iter(params),
+            |row| row.get(0),
+        )?)
+    }
+}
+
+/// The count of `paths`, one per match, or in exists mode the first as the
+/// witness.
+fn permitted_count(mut paths: impl Iterator<Item = String>, mode: QueryMode) -> MatchCount {
+    if mode == QueryMode::Exists {
+        let witness = paths.next();
+        return MatchCount {
+            count: usize::from(witness.is_some()),
+            witness,
+        };
+    }
+    MatchCount {
+        count: paths.count(),
+        witness: None,
+    }
+}
+
+fn code_type_values() -> impl Iterator<Item = Value> {
+    code_type_params()
+        .into_iter()
+        .map(|t| Value::Integer(t as i64))
+}
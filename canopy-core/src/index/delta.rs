@@ -0,0 +1,641 @@
+//! Symbol-level deltas between the current index and an earlier anchor.
+//!
+//! An anchor is either a snapshot written by [`RepoIndex::write_snapshot`]
+//! (file hashes plus per-symbol fingerprints under `.canopy/snapshots/`) or a
+//! git ref, whose files are read with `git cat-file` and parsed on the fly.
+//! The current side is always what the index holds, so run `canopy index`
+//! first for a delta against the working tree.
+
+use super::file_discovery::ignore_glob_set;
+use super::search::{handle_from_row, HANDLE_SELECT};
+use super::RepoIndex;
+use crate::document::NodeType;
+use crate::error::CanopyError;
+use crate::handle::Handle;
+use crate::parse::parse_file_with_hash;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// Node types that count as symbols for deltas
+const SYMBOL_TYPES: [NodeType; 4] = [
+    NodeType::Function,
+    NodeType::Class,
+    NodeType::Struct,
+    NodeType::Method,
+];
+
+/// Minimum fingerprint similarity for a symbol that left one file and appeared
+/// in another to be reported as renamed rather than removed + added
+const RENAME_SIMILARITY: f64 = 0.75;
+
+/// What a [`SymbolDelta`] is computed against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeltaAnchor {
+    /// A snapshot file written by `canopy snapshot`
+    Snapshot(PathBuf),
+    /// A git revision (branch, tag, SHA, `HEAD~3`, ...)
+    GitRef(String),
+}
+
+impl DeltaAnchor {
+    /// Interpret `spec` as a snapshot path, a snapshot name under
+    /// `.canopy/snapshots/`, or otherwise a git ref.
+    pub fn resolve(repo_root: &Path, spec: &str) -> Self {
+        let path = Path::new(spec);
+        if path.is_file() {
+            return Self::Snapshot(path.to_path_buf());
+        }
+        let named = snapshots_dir(repo_root).join(format!("{spec}.json"));
+        if named.is_file() {
+            return Self::Snapshot(named);
+        }
+        Self::GitRef(spec.to_string())
+    }
+}
+
+/// Stored fingerprint of one symbol
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotSymbol {
+    pub name: String,
+    pub node_type: NodeType,
+    /// Truncated SHA-256 of the symbol's source; any edit changes it
+    pub content_hash: String,
+    /// 64-bit simhash of the symbol's tokens; small edits change few bits
+    pub simhash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    /// Hex SHA-256 of the file contents
+    pub hash: String,
+    pub symbols: Vec<SnapshotSymbol>,
+}
+
+/// Compact listing of the indexed symbols at one point in time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SymbolSnapshot {
+    pub version: u32,
+    pub created_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit_sha: Option<String>,
+    pub files: BTreeMap<String, SnapshotFile>,
+}
+
+/// A symbol that was added, removed or changed.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeltaSymbol {
+    pub name: String,
+    pub node_type: NodeType,
+    pub file_path: String,
+    /// Current handle; `None` for removed symbols
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handle: Option<Handle>,
+}
+
+/// A symbol that disappeared from one file and reappeared in another.
+#[derive(Debug, Clone, Serialize)]
+pub struct RenamedSymbol {
+    pub name: String,
+    pub node_type: NodeType,
+    pub from_path: String,
+    pub to_path: String,
+    /// Fingerprint similarity in [0, 1]; 1.0 is an unchanged move
+    pub similarity: f64,
+    pub handle: Handle,
+}
+
+/// Symbols added, removed, changed or renamed since an anchor.
+#[derive(Debug, Clone, Serialize)]
+pub struct SymbolDelta {
+    /// The anchor as given: snapshot path or git ref
+    pub since: String,
+    /// Commit the anchor refers to, when known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since_commit: Option<String>,
+    pub added: Vec<DeltaSymbol>,
+    pub removed: Vec<DeltaSymbol>,
+    pub changed: Vec<DeltaSymbol>,
+    pub renamed: Vec<RenamedSymbol>,
+}
+
+impl SymbolDelta {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.renamed.is_empty()
+    }
+}
+
+/// A symbol in the current index
+struct CurrentSymbol {
+    record: SnapshotSymbol,
+    handle: Handle,
+}
+
+struct CurrentFile {
+    hash: String,
+    symbols: Vec<CurrentSymbol>,
+}
+
+impl RepoIndex {
+    /// Snapshot the indexed symbols to `.canopy/snapshots/<name>.json`.
+    ///
+    /// `name` defaults to `snapshot-<unix seconds>`. Returns the file written.
+    pub fn write_snapshot(&self, name: Option<&str>) -> crate::Result<PathBuf> {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let name = match name {
+            Some(name) => {
+                if name.is_empty()
+                    || !name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+                    || name.starts_with('.')
+                {
+                    return Err(CanopyError::InvalidSnapshot(format!(
+                        "bad name '{name}' (use letters, digits, '.', '_' or '-')"
+                    )));
+                }
+                name.to_string()
+            }
+            None => format!("snapshot-{created_at}"),
+        };
+
+        let snapshot = SymbolSnapshot {
+            version: SNAPSHOT_VERSION,
+            created_at,
+            commit_sha: crate::git::head_commit_sha(&self.repo_root),
+            files: self
+                .current_symbols()?
+                .into_iter()
+                .map(|(path, file)| {
+                    let symbols = file.symbols.into_iter().map(|s| s.record).collect();
+                    (
+                        path,
+                        SnapshotFile {
+                            hash: file.hash,
+                            symbols,
+                        },
+                    )
+                })
+                .collect(),
+        };
+
+        let dir = snapshots_dir(&self.repo_root);
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{name}.json"));
+        fs::write(&path, serde_json::to_vec(&snapshot)?)?;
+        Ok(path)
+    }
+
+    /// Symbols added, removed, changed or renamed between `since` and the index.
+    pub fn symbol_delta(&self, since: &DeltaAnchor) -> crate::Result<SymbolDelta> {
+        let current = self.current_symbols()?;
+        let (label, since_commit, before) = match since {
+            DeltaAnchor::Snapshot(path) => {
+                let snapshot: SymbolSnapshot = serde_json::from_slice(&fs::read(path)?)?;
+                if snapshot.version != SNAPSHOT_VERSION {
+                    return Err(CanopyError::InvalidSnapshot(format!(
+                        "unsupported version {} in {}",
+                        snapshot.version,
+                        path.display()
+                    )));
+                }
+                (
+                    path.display().to_string(),
+                    snapshot.commit_sha,
+                    snapshot.files,
+                )
+            }
+            DeltaAnchor::GitRef(rev) => {
+                let commit = crate::git::resolve_commit(&self.repo_root, rev)
+                    .ok_or_else(|| CanopyError::Git(format!("unknown revision '{rev}'")))?;
+                let files = self.symbols_at_commit(&commit, &current)?;
+                (rev.clone(), Some(commit), files)
+            }
+        };
+
+        // Files the index doesn't cover but that still exist on disk were
+        // simply not indexed, not deleted
+        let before = before
+            .into_iter()
+            .filter(|(path, _)| current.contains_key(path) || !self.repo_root.join(path).exists())
+            .collect();
+
+        let mut delta = diff_symbols(&before, current);
+        delta.since = label;
+        delta.since_commit = since_commit;
+        Ok(delta)
+    }
+
+    /// Indexed symbols with fingerprints, by file, across all shards.
+    fn current_symbols(&self) -> crate::Result<BTreeMap<String, CurrentFile>> {
+        let mut files = BTreeMap::new();
+        let type_list = SYMBOL_TYPES
+            .iter()
+            .map(|t| t.as_int().to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        for index in self.all_indexes() {
+            let mut stmt = index.conn.prepare("SELECT path, content_hash FROM files")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?;
+            for row in rows {
+                let (path, hash) = row?;
+                files.insert(
+                    path,
+                    CurrentFile {
+                        hash: hex::encode(hash),
+                        symbols: Vec::new(),
+                    },
+                );
+            }
+
+            let mut stmt = index.conn.prepare(&format!(
+                "SELECT {HANDLE_SELECT}, n.name, fts.content
+                 FROM nodes n
+                 JOIN files f ON n.file_id = f.id
+                 JOIN fts_node_map m ON m.node_id = n.id
+                 JOIN content_fts fts ON fts.rowid = m.fts_rowid
+                 WHERE n.name IS NOT NULL AND n.node_type IN ({type_list})
+                 ORDER BY f.path, n.start_byte"
+            ))?;
+            let rows = stmt.query_map([], |row| {
+                let handle = handle_from_row(row)?;
+                let name: String = row.get(11)?;
+                let content: String = row.get(12)?;
+                Ok((handle, name, content))
+            })?;
+            for row in rows {
+                let (handle, name, content) = row?;
+                let record = fingerprint(name, handle.node_type, &content);
+                if let Some(file) = files.get_mut(&handle.file_path) {
+                    file.symbols.push(CurrentSymbol { record, handle });
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Symbols of the indexable files at `commit`, parsed from git objects.
+    ///
+    /// Files whose contents match the index are taken from `current` unparsed.
+    fn symbols_at_commit(
+        &self,
+        commit: &str,
+        current: &BTreeMap<String, CurrentFile>,
+    ) -> crate::Result<BTreeMap<String, SnapshotFile>> {
+        let glob = globset::Glob::new(self.config.default_glob())
+            .map_err(|e| CanopyError::GlobPattern(e.to_string()))?
+            .compile_matcher();
+        let ignore = ignore_glob_set(&self.config.ignore.patterns)?;
+        let paths: Vec<String> = crate::git::tracked_files_at(&self.repo_root, commit)
+            .ok_or_else(|| CanopyError::Git(format!("cannot list files at {commit}")))?
+            .into_iter()
+            .filter(|p| current.contains_key(p) || (glob.is_match(p) && !ignore.is_match(p)))
+            .collect();
+        let blobs = crate::git::read_files_at(&self.repo_root, commit, &paths)
+            .ok_or_else(|| CanopyError::Git(format!("cannot read files at {commit}")))?;
+
+        let mut files = BTreeMap::new();
+        for (path, content) in blobs {
+            let hash: [u8; 32] = Sha256::digest(&content).into();
+            let hash_hex = hex::encode(hash);
+            if let Some(file) = current.get(&path).filter(|f| f.hash == hash_hex) {
+                let symbols = file.symbols.iter().map(|s| s.record.clone()).collect();
+                files.insert(
+                    path,
+                    SnapshotFile {
+                        hash: hash_hex,
+                        symbols,
+                    },
+                );
+                continue;
+            }
+            let Ok(source) = String::from_utf8(content) else {
+                continue;
+            };
+            let parsed = parse_file_with_hash(Path::new(&path), &source, &self.config, hash, 0);
+            let symbols = parsed
+                .nodes
+                .iter()
+                .filter(|node| SYMBOL_TYPES.contains(&node.node_type))
+                .filter_map(|node| {
+                    let name = node.metadata.searchable_name()?;
+                    Some(fingerprint(
+                        name.to_string(),
+                        node.node_type,
+                        &source[node.span.clone()],
+                    ))
+                })
+                .collect();
+            files.insert(
+                path,
+                SnapshotFile {
+                    hash: hash_hex,
+                    symbols,
+                },
+            );
+        }
+        Ok(files)
+    }
+}
+
+fn snapshots_dir(repo_root: &Path) -> PathBuf {
+    repo_root.join(".canopy").join("snapshots")
+}
+
+fn fingerprint(name: String, node_type: NodeType, content: &str) -> SnapshotSymbol {
+    let digest = Sha256::digest(content.as_bytes());
+    SnapshotSymbol {
+        name,
+        node_type,
+        content_hash: hex::encode(&digest[..8]),
+        simhash: format!("{:016x}", simhash(content)),
+    }
+}
+
+/// Charikar simhash over whitespace-separated tokens (FNV-1a per token).
+fn simhash(content: &str) -> u64 {
+    let mut weights = [0i32; 64];
+    for token in content.split_whitespace() {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in token.bytes() {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if hash >> bit & 1 == 1 { 1 } else { -1 };
+        }
+    }
+    weights
+        .iter()
+        .enumerate()
+        .filter(|(_, w)| **w > 0)
+        .fold(0, |acc, (bit, _)| acc | 1 << bit)
+}
+
+fn simhash_similarity(a: &str, b: &str) -> f64 {
+    match (u64::from_str_radix(a, 16), u64::from_str_radix(b, 16)) {
+        (Ok(a), Ok(b)) => 1.0 - f64::from((a ^ b).count_ones()) / 64.0,
+        _ => 0.0,
+    }
+}
+
+/// Compare `before` against the current index.
+fn diff_symbols(
+    before: &BTreeMap<String, SnapshotFile>,
+    current: BTreeMap<String, CurrentFile>,
+) -> SymbolDelta {
+    let mut delta = SymbolDelta {
+        since: String::new(),
+        since_commit: None,
+        added: Vec::new(),
+        removed: Vec::new(),
+        changed: Vec::new(),
+        renamed: Vec::new(),
+    };
+    let mut added: Vec<CurrentSymbol> = Vec::new();
+    let mut removed: Vec<(String, SnapshotSymbol)> = Vec::new();
+
+    let mut paths: Vec<String> = before.keys().chain(current.keys()).cloned().collect();
+    paths.sort();
+    paths.dedup();
+    let mut current = current;
+    for path in paths {
+        let old = before.get(&path);
+        let new = current.remove(&path);
+        if let (Some(old), Some(new)) = (old, new.as_ref()) {
+            if old.hash == new.hash {
+                continue;
+            }
+        }
+        let old_symbols = old.map(|f| f.symbols.as_slice()).unwrap_or_default();
+        let new_symbols = new.map(|f| f.symbols).unwrap_or_default();
+
+        // Pair symbols within the file by (type, name): identical content first,
+        // then any remaining same-named pair is an edit
+        let mut unmatched_old: Vec<Option<&SnapshotSymbol>> =
+            old_symbols.iter().map(Some).collect();
+        let mut edited: Vec<CurrentSymbol> = Vec::new();
+        let mut unmatched_new: Vec<CurrentSymbol> = Vec::new();
+        for symbol in new_symbols {
+            let same_key = |o: &Option<&SnapshotSymbol>| {
+                o.is_some_and(|o| {
+                    o.name == symbol.record.name && o.node_type == symbol.record.node_type
+                })
+            };
+            if let Some(slot) = unmatched_old.iter_mut().find(|o| {
+                same_key(o) && o.is_some_and(|o| o.content_hash == symbol.record.content_hash)
+            }) {
+                *slot = None;
+            } else {
+                unmatched_new.push(symbol);
+            }
+        }
+        for symbol in unmatched_new {
+            let same_key = |o: &Option<&SnapshotSymbol>| {
+                o.is_some_and(|o| {
+                    o.name == symbol.record.name && o.node_type == symbol.record.node_type
+                })
+            };
+            if let Some(slot) = unmatched_old.iter_mut().find(|o| same_key(o)) {
+                *slot = None;
+                edited.push(symbol);
+            } else {
+                added.push(symbol);
+            }
+        }
+        delta
+            .changed
+            .extend(edited.into_iter().map(|s| DeltaSymbol {
+                name: s.record.name,
+                node_type: s.record.node_type,
+                file_path: s.handle.file_path.clone(),
+                handle: Some(s.handle),
+            }));
+        removed.extend(
+            unmatched_old
+                .into_iter()
+                .flatten()
+                .map(|s| (path.clone(), s.clone())),
+        );
+    }
+
+    // Same name gone from one file and present in another: best matches first
+    let mut candidates: Vec<(f64, usize, usize)> = Vec::new();
+    for (ai, a) in added.iter().enumerate() {
+        for (ri, (path, r)) in removed.iter().enumerate() {
+            if r.name == a.record.name
+                && r.node_type == a.record.node_type
+                && *path != a.handle.file_path
+            {
+                let similarity = simhash_similarity(&r.simhash, &a.record.simhash);
+                if similarity >= RENAME_SIMILARITY {
+                    candidates.push((similarity, ai, ri));
+                }
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut renamed_added = HashMap::new();
+    let mut renamed_removed = HashSet::new();
+    for (similarity, ai, ri) in candidates {
+        if renamed_added.contains_key(&ai) || renamed_removed.contains(&ri) {
+            continue;
+        }
+        renamed_added.insert(ai, (ri, similarity));
+        renamed_removed.insert(ri);
+    }
+
+    for (ai, symbol) in added.into_iter().enumerate() {
+        if let Some(&(ri, similarity)) = renamed_added.get(&ai) {
+            delta.renamed.push(RenamedSymbol {
+                name: symbol.record.name,
+                node_type: symbol.record.node_type,
+                from_path: removed[ri].0.clone(),
+                to_path: symbol.handle.file_path.clone(),
+                similarity,
+                handle: symbol.handle,
+            });
+        } else {
+            delta.added.push(DeltaSymbol {
+                name: symbol.record.name,
+                node_type: symbol.record.node_type,
+                file_path: symbol.handle.file_path.clone(),
+                handle: Some(symbol.handle),
+            });
+        }
+    }
+    delta.removed = removed
+        .into_iter()
+        .enumerate()
+        .filter(|(ri, _)| !renamed_removed.contains(ri))
+        .map(|(_, (path, s))| DeltaSymbol {
+            name: s.name,
+            node_type: s.node_type,
+            file_path: path,
+            handle: None,
+        })
+        .collect();
+    delta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn git(root: &Path, args: &[&str]) {
+        let output = Command::new("git")
+            .args(["-c", "user.name=canopy", "-c", "user.email=canopy@test"])
+            .args(args)
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed");
+    }
+
+    fn names(symbols: &[DeltaSymbol]) -> Vec<&str> {
+        symbols.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[test]
+    fn simhash_similarity_tolerates_small_edits() {
+        let a = format!(
+            "{:016x}",
+            simhash(
+                "fn parse(input: &str) -> Result<Ast> { let tokens = lex(input); build(tokens) }"
+            )
+        );
+        let b = format!(
+            "{:016x}",
+            simhash(
+                "fn parse(input: &str) -> Result<Ast> { let tokens = lex(input)?; build(tokens) }"
+            )
+        );
+        let c = format!(
+            "{:016x}",
+            simhash("struct Config { path: PathBuf, verbose: bool, retries: u32 }")
+        );
+        assert!(simhash_similarity(&a, &b) >= RENAME_SIMILARITY);
+        assert!(simhash_similarity(&a, &c) < RENAME_SIMILARITY);
+        assert_eq!(simhash_similarity(&a, &a), 1.0);
+    }
+
+    #[test]
+    fn symbol_delta_against_git_ref_and_snapshot() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(
+            root.join("src/lib.rs"),
+            "fn keep() { work(); }\nfn edit_me() { one(); }\nfn drop_me() { gone(); }\n\
+             fn wander(input: &str) -> usize { let n = input.len(); n * 2 + offset() }\n",
+        )
+        .unwrap();
+        fs::write(root.join("src/other.rs"), "fn untouched() {}\n").unwrap();
+        RepoIndex::init(root).unwrap();
+        git(root, &["init", "-q"]);
+        git(root, &["add", "."]);
+        git(root, &["commit", "-q", "-m", "base"]);
+
+        let mut index = RepoIndex::open(root).unwrap();
+        index.index("**/*.rs").unwrap();
+        let snapshot = index.write_snapshot(Some("base")).unwrap();
+        assert_eq!(snapshot, root.join(".canopy/snapshots/base.json"));
+
+        fs::write(
+            root.join("src/lib.rs"),
+            "fn keep() { work(); }\nfn edit_me() { one(); two(); }\nfn fresh() {}\n",
+        )
+        .unwrap();
+        // Rewrites within the same second keep the mtime the index compares against
+        fs::File::options()
+            .write(true)
+            .open(root.join("src/lib.rs"))
+            .unwrap()
+            .set_modified(SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+        fs::write(
+            root.join("src/moved.rs"),
+            "fn wander(input: &str) -> usize { let n = input.len(); n * 2 + offset() }\n",
+        )
+        .unwrap();
+        index.index("**/*.rs").unwrap();
+
+        for spec in ["HEAD", "base"] {
+            let anchor = DeltaAnchor::resolve(root, spec);
+            let delta = index.symbol_delta(&anchor).unwrap();
+            assert_eq!(names(&delta.added), ["fresh"], "{spec}");
+            assert_eq!(names(&delta.removed), ["drop_me"], "{spec}");
+            assert_eq!(names(&delta.changed), ["edit_me"], "{spec}");
+            assert_eq!(delta.renamed.len(), 1, "{spec}");
+            assert_eq!(delta.renamed[0].name, "wander");
+            assert_eq!(delta.renamed[0].from_path, "src/lib.rs");
+            assert_eq!(delta.renamed[0].to_path, "src/moved.rs");
+            assert_eq!(delta.renamed[0].similarity, 1.0);
+            assert!(delta.added[0].handle.is_some());
+            assert!(delta.removed[0].handle.is_none());
+            assert!(delta.since_commit.is_some());
+        }
+        assert!(matches!(
+            DeltaAnchor::resolve(root, "base"),
+            DeltaAnchor::Snapshot(_)
+        ));
+
+        let unknown = index.symbol_delta(&DeltaAnchor::GitRef("no-such-branch".into()));
+        assert!(matches!(unknown, Err(CanopyError::Git(_))));
+        assert!(index.write_snapshot(Some("../escape")).is_err());
+    }
+}
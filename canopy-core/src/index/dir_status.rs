@@ -0,0 +1,348 @@
+//! Status restricted to part of the repo: totals for the files under a path
+//! or glob, and per-directory token totals, for budgeting by subsystem.
+
+use crate::document::NodeType;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use super::expand::time_ago;
+use super::node_stats::NodeTypeStats;
+use super::RepoIndex;
+
+/// Status of the files under one scope, from [`RepoIndex::status_scoped`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScopedStatus {
+    /// The path or glob, as given
+    pub scope: String,
+    pub files_indexed: usize,
+    pub total_tokens: usize,
+    /// Nodes per node type, most tokens first
+    pub by_type: Vec<NodeTypeStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_indexed: Option<String>,
+}
+
+/// Per-directory token totals, from [`RepoIndex::token_tree`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenTree {
+    /// Deepest directory level listed
+    pub depth: usize,
+    pub files_indexed: usize,
+    pub total_tokens: usize,
+    /// Top-level directories, most tokens first. Files at the repo root
+    /// count toward the totals above but no entry.
+    pub dirs: Vec<DirTokens>,
+}
+
+/// One directory of a [`TokenTree`]; its totals include every level below.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DirTokens {
+    /// Repo-relative, without a trailing `/`
+    pub path: String,
+    pub files_indexed: usize,
+    pub total_tokens: usize,
+    /// Subdirectories, most tokens first; empty at the tree's depth
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dirs: Vec<DirTokens>,
+}
+
+/// Whether `scope` is a glob rather than a plain path
+fn is_glob(scope: &str) -> bool {
+    scope.contains(['*', '?', '[', '{'])
+}
+
+impl DirTokens {
+    /// Add a directory's own files to it and the levels below it, down to
+    /// `components`.
+    fn add(&mut self, components: &[&str], files: usize, tokens: usize) {
+        self.files_indexed += files;
+        self.total_tokens += tokens;
+        let Some((name, rest)) = components.split_first() else {
+            return;
+        };
+        let path = if self.path.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", self.path, name)
+        };
+        let child = match self.dirs.iter().position(|d| d.path == path) {
+            Some(pos) => &mut self.dirs[pos],
+            None => {
+                self.dirs.push(DirTokens {
+                    path,
+                    ..DirTokens::default()
+                });
+                self.dirs.last_mut().expect("just pushed")
+            }
+        };
+        child.add(rest, files, tokens);
+    }
+
+    fn sort(&mut self) {
+        self.dirs.sort_by(|a, b| {
+            b.total_tokens
+                .cmp(&a.total_tokens)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        for dir in &mut self.dirs {
+            dir.sort();
+        }
+    }
+}
+
+impl RepoIndex {
+    /// [`status`](Self::status) totals for the files under `scope`: a
+    /// repo-relative path (a directory covers everything below it) or a
+    /// glob matched as `canopy index` matches one.
+    pub fn status_scoped(&self, scope: &str) -> crate::Result<ScopedStatus> {
+        let normalized = self.path_style.normalize(scope);
+        let normalized = normalized.trim_end_matches('/');
+        let (glob, route) = if is_glob(normalized) {
+            (normalized.to_string(), normalized.to_string())
+        } else {
+            (
+                format!("{{{normalized},{normalized}/**}}"),
+                format!("{normalized}/**"),
+            )
+        };
+        let matcher = self.path_style.glob(&glob)?;
+
+        let mut status = ScopedStatus {
+            scope: scope.to_string(),
+            ..ScopedStatus::default()
+        };
+        let mut last_indexed: Option<i64> = None;
+        let mut by_type: HashMap<u8, (usize, usize)> = HashMap::new();
+        for index in self.query_targets(self.invalidate_route(Some(&route))) {
+            let mut stmt = index
+                .conn
+                .prepare("SELECT id, path, token_count, indexed_at FROM files")?;
+            let files = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, Option<i64>>(3)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut in_scope = Vec::new();
+            for (id, path, tokens, indexed_at) in files {
+                if !matcher.is_match(&path) {
+                    continue;
+                }
+                status.files_indexed += 1;
+                status.total_tokens += tokens.max(0) as usize;
+                last_indexed = last_indexed.max(indexed_at);
+                in_scope.push(id);
+            }
+            if in_scope.is_empty() {
+                continue;
+            }
+            in_scope.sort_unstable();
+
+            let mut stmt = index.conn.prepare(
+                "SELECT file_id, node_type, COUNT(*), COALESCE(SUM(token_count), 0)
+                 FROM nodes GROUP BY file_id, node_type",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, u8>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })?;
+            for row in rows {
+                let (file_id, node_type, nodes, tokens) = row?;
+                if in_scope.binary_search(&file_id).is_ok() {
+                    let entry = by_type.entry(node_type).or_default();
+                    entry.0 += nodes.max(0) as usize;
+                    entry.1 += tokens.max(0) as usize;
+                }
+            }
+        }
+
+        status.by_type = by_type
+            .into_iter()
+            .filter_map(|(node_type, (nodes, total_tokens))| {
+                Some(NodeTypeStats {
+                    node_type: NodeType::from_int(node_type)?,
+                    nodes,
+                    total_tokens,
+                    avg_tokens: total_tokens / nodes.max(1),
+                })
+            })
+            .collect();
+        status.by_type.sort_by(|a, b| {
+            b.total_tokens
+                .cmp(&a.total_tokens)
+                .then(a.node_type.as_int().cmp(&b.node_type.as_int()))
+        });
+        status.last_indexed = last_indexed.map(time_ago);
+        Ok(status)
+    }
+
+    /// Files and tokens per directory, `depth` levels down (at least one).
+    ///
+    /// Each database is grouped once by directory in SQL; deeper directories
+    /// are then folded into their ancestor at `depth`.
+    pub fn token_tree(&self, depth: usize) -> crate::Result<TokenTree> {
+        let depth = depth.max(1);
+        let mut by_dir: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+        for index in self.all_indexes() {
+            // rtrim by every non-'/' character of the path leaves its directory
+            let mut stmt = index.conn.prepare(
+                "SELECT rtrim(path, replace(path, '/', '')) AS dir,
+                        COUNT(*), COALESCE(SUM(token_count), 0)
+                 FROM files GROUP BY dir",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })?;
+            for row in rows {
+                let (dir, files, tokens) = row?;
+                let entry = by_dir.entry(dir).or_default();
+                entry.0 += files.max(0) as usize;
+                entry.1 += tokens.max(0) as usize;
+            }
+        }
+
+        let mut root = DirTokens::default();
+        for (dir, (files, tokens)) in &by_dir {
+            let components: Vec<&str> = dir.split('/').filter(|c| !c.is_empty()).collect();
+            root.add(&components[..components.len().min(depth)], *files, *tokens);
+        }
+        root.sort();
+        Ok(TokenTree {
+            depth,
+            files_indexed: root.files_indexed,
+            total_tokens: root.total_tokens,
+            dirs: root.dirs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+
+    fn nested_repo() -> (FixtureRepo, RepoIndex) {
+        let repo = FixtureRepoBuilder::new()
+            .files(&[
+                ("README.md", "# Demo\n\nA nested fixture.\n"),
+                ("src/lib.rs", "pub fn root() {}\n"),
+                (
+                    "src/auth/login.rs",
+                    "pub fn login(user: &str) -> bool {\n    !user.is_empty()\n}\n",
+                ),
+                (
+                    "src/auth/token/jwt.rs",
+                    "pub fn sign() -> String {\n    String::new()\n}\n",
+                ),
+                ("src/db/pool.rs", "pub struct Pool {\n    size: usize,\n}\n"),
+                ("docs/guide.md", "# Guide\n\nRead the auth docs.\n"),
+            ])
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*").unwrap();
+        (repo, index)
+    }
+
+    fn sum(dirs: &[DirTokens]) -> (usize, usize) {
+        dirs.iter().fold((0, 0), |(files, tokens), d| {
+            (files + d.files_indexed, tokens + d.total_tokens)
+        })
+    }
+
+    #[test]
+    fn token_tree_sums_to_the_global_totals() {
+        let (_dir, index) = nested_repo();
+        let status = index.status().unwrap();
+        let (root_files, root_tokens) = index
+            .indexed_paths()
+            .unwrap()
+            .iter()
+            .filter(|path| !path.contains('/'))
+            .fold((0, 0), |(files, tokens), path| {
+                (
+                    files + 1,
+                    tokens + index.status_scoped(path).unwrap().total_tokens,
+                )
+            });
+        assert!(root_files >= 1, "README.md sits at the root");
+
+        for depth in [1, 2, 3] {
+            let tree = index.token_tree(depth).unwrap();
+            assert_eq!(tree.total_tokens, status.total_tokens);
+            assert_eq!(tree.files_indexed, status.files_indexed);
+            assert_eq!(
+                sum(&tree.dirs),
+                (
+                    status.files_indexed - root_files,
+                    status.total_tokens - root_tokens
+                )
+            );
+        }
+
+        let tree = index.token_tree(2).unwrap();
+        let src = tree.dirs.iter().find(|d| d.path == "src").unwrap();
+        assert_eq!(src.files_indexed, 4);
+        let children: Vec<&str> = src.dirs.iter().map(|d| d.path.as_str()).collect();
+        assert!(children.contains(&"src/auth") && children.contains(&"src/db"));
+        // src/lib.rs is src's own file, not a child's
+        assert_eq!(sum(&src.dirs).0, 3);
+        let auth = src.dirs.iter().find(|d| d.path == "src/auth").unwrap();
+        assert_eq!(auth.files_indexed, 2);
+        assert!(auth.dirs.is_empty(), "depth 2 stops above src/auth/token");
+        assert_eq!(index.token_tree(3).unwrap().depth, 3);
+    }
+
+    #[test]
+    fn scopes_match_walk_semantics() {
+        let (_dir, index) = nested_repo();
+
+        let auth = index.status_scoped("src/auth").unwrap();
+        assert_eq!(auth.files_indexed, 2);
+        assert_eq!(
+            index.status_scoped("src/auth/").unwrap(),
+            ScopedStatus {
+                scope: "src/auth/".to_string(),
+                ..auth.clone()
+            }
+        );
+        assert!(auth.last_indexed.is_some());
+        let functions = auth
+            .by_type
+            .iter()
+            .find(|s| s.node_type == NodeType::Function)
+            .unwrap();
+        assert_eq!(functions.nodes, 2);
+
+        let tree = index.token_tree(2).unwrap();
+        let src = tree.dirs.iter().find(|d| d.path == "src").unwrap();
+        let auth_dir = src.dirs.iter().find(|d| d.path == "src/auth").unwrap();
+        assert_eq!(auth.total_tokens, auth_dir.total_tokens);
+
+        // A prefix is a directory, not a string prefix
+        assert_eq!(index.status_scoped("src/au").unwrap().files_indexed, 0);
+
+        for glob in ["src/**/*.rs", "**/*.md", "src/*.rs", "*/auth/**"] {
+            let walked = index.walk_files(glob).unwrap().len();
+            let scoped = index.status_scoped(glob).unwrap();
+            assert_eq!(scoped.files_indexed, walked, "{glob}");
+        }
+        let everything = index.status_scoped("**").unwrap();
+        assert_eq!(
+            everything.total_tokens,
+            index.status().unwrap().total_tokens
+        );
+    }
+}
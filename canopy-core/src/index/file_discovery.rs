@@ -1,9 +1,14 @@
 //! File discovery backends: fd, ripgrep, ignore crate.
 
-use super::RepoIndex;
+use super::paths::path_from_bytes;
+use super::{PathStyle, RepoIndex};
+use crate::config::Config;
 use crate::error::CanopyError;
-use ignore::WalkBuilder;
-use std::path::PathBuf;
+use crate::process::{self, OutputLimits, PROBE_TIMEOUT};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::{Match, WalkBuilder};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::OnceLock;
 
@@ -15,21 +20,57 @@ pub enum FileDiscovery {
     Ignore,
 }
 
+/// Environment variable that pins the discovery backend (`fd`, `rg`/`ripgrep`,
+/// or `ignore`/`builtin`), e.g. so tests behave the same with or without the
+/// external tools installed.
+pub const FILE_DISCOVERY_ENV: &str = "CANOPY_FILE_DISCOVERY";
+
+/// Maximum directory depth walked when following symlinks.
+const MAX_SYMLINK_DEPTH: usize = 64;
+
+/// Globs [`RepoIndex::walk_globs`] walks at once.
+const GLOB_WALK_THREADS: usize = 4;
+
+/// Most paths kept from one fd or rg walk; a walk listing more falls back to
+/// the builtin walker
+const MAX_DISCOVERY_PATHS: usize = 1_000_000;
+
+/// Most bytes of paths kept from one fd or rg walk, likewise
+const MAX_DISCOVERY_BYTES: usize = 64 * 1024 * 1024;
+
 /// Cached detection result — avoids repeated process spawns.
 static DETECTED_BACKEND: OnceLock<FileDiscovery> = OnceLock::new();
 
 impl FileDiscovery {
     /// Detect the best available file discovery tool (cached after first call).
+    ///
+    /// `CANOPY_FILE_DISCOVERY` overrides probing when set to a known backend.
     pub fn detect() -> Self {
-        *DETECTED_BACKEND.get_or_init(Self::probe)
+        *DETECTED_BACKEND.get_or_init(|| {
+            std::env::var(FILE_DISCOVERY_ENV)
+                .ok()
+                .and_then(|name| Self::from_name(&name))
+                .unwrap_or_else(Self::probe)
+        })
+    }
+
+    /// Parse a backend name as accepted by `CANOPY_FILE_DISCOVERY`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "fd" => Some(Self::Fd),
+            "rg" | "ripgrep" => Some(Self::Ripgrep),
+            "ignore" | "ignore-crate" | "builtin" => Some(Self::Ignore),
+            _ => None,
+        }
     }
 
-    /// Probe for available tools without caching.
+    /// Probe for available tools without caching. A tool that doesn't
+    /// answer `--version` within [`PROBE_TIMEOUT`] counts as missing.
     fn probe() -> Self {
-        if Command::new("fd").arg("--version").output().is_ok() {
+        if responds("fd") {
             return Self::Fd;
         }
-        if Command::new("rg").arg("--version").output().is_ok() {
+        if responds("rg") {
             return Self::Ripgrep;
         }
         Self::Ignore
@@ -48,21 +89,214 @@ impl FileDiscovery {
 impl RepoIndex {
     /// Walk files matching glob, respecting .gitignore
     /// Uses fd > ripgrep > ignore crate (in order of preference)
+    ///
+    /// With `[indexing] follow_symlinks`, symlinked directories are descended
+    /// (depth-capped) and paths reached through a directory cycle are dropped.
     pub fn walk_files(&self, glob: &str) -> crate::Result<Vec<PathBuf>> {
+        self.walker().walk(glob)
+    }
+
+    /// Files matching any of `globs`, as repo-relative display paths, each
+    /// listed once with the position of the first glob in `globs` that
+    /// matches it. Globs are walked a few at a time, concurrently, each
+    /// walk bounded by what is left of `max_files`; a glob that fails to walk
+    /// contributes nothing.
+    pub fn walk_globs(&self, globs: &[String], max_files: usize) -> Vec<(String, usize)> {
+        let walker = self.walker();
+        let mut seen = HashSet::new();
+        let mut files = Vec::new();
+        for (batch, chunk) in globs.chunks(GLOB_WALK_THREADS).enumerate() {
+            let remaining = max_files - files.len();
+            let walks: Vec<Vec<PathBuf>> = std::thread::scope(|scope| {
+                let handles: Vec<_> = chunk
+                    .iter()
+                    .map(|glob| {
+                        scope.spawn(move || {
+                            let mut found = walker.walk(glob).unwrap_or_default();
+                            found.truncate(remaining);
+                            found
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap_or_default())
+                    .collect()
+            });
+
+            // Merged in glob order, so the first listed glob keeps a file
+            for (offset, found) in walks.into_iter().enumerate() {
+                for file in found {
+                    let relative = self.relative_display_path(&file);
+                    if seen.insert(relative.clone()) {
+                        files.push((relative, batch * GLOB_WALK_THREADS + offset));
+                        if files.len() == max_files {
+                            return files;
+                        }
+                    }
+                }
+            }
+        }
+        files
+    }
+
+    /// A [`WalkFilter`] for `glob`, reading the repo's gitignore files as
+    /// they are now.
+    pub fn walk_filter(&self, glob: &str) -> crate::Result<WalkFilter> {
+        let git = self.repo_root.join(".git").exists();
+        let mut excludes = Vec::new();
+        if git {
+            let (global, _) = GitignoreBuilder::new(&self.repo_root).build_global();
+            excludes.push(global);
+            // Rooted at the repo, not at `.git/info/`
+            let mut exclude = GitignoreBuilder::new(&self.repo_root);
+            exclude.add(self.repo_root.join(".git/info/exclude"));
+            excludes.push(exclude.build().unwrap_or_else(|_| Gitignore::empty()));
+        }
+        Ok(WalkFilter {
+            repo_root: self.repo_root.clone(),
+            path_style: self.path_style,
+            glob: self.path_style.glob(glob)?,
+            ignore_set: ignore_glob_set(&self.config.ignore.patterns)?,
+            git,
+            excludes,
+        })
+    }
+
+    fn walker(&self) -> Walker<'_> {
+        Walker {
+            repo_root: &self.repo_root,
+            config: &self.config,
+            path_style: self.path_style,
+        }
+    }
+}
+
+/// Whether single paths are ones [`RepoIndex::walk_files`] lists for a glob:
+/// matching it, clear of `[ignore] patterns` and, in a git checkout, not
+/// gitignored. For callers told about files one at a time, such as a file
+/// watcher, rather than walking for them.
+pub struct WalkFilter {
+    repo_root: PathBuf,
+    path_style: PathStyle,
+    glob: globset::GlobMatcher,
+    ignore_set: globset::GlobSet,
+    git: bool,
+    /// The global excludes file and `.git/info/exclude`
+    excludes: Vec<Gitignore>,
+}
+
+impl WalkFilter {
+    /// Whether the file at `relative` (to the repo root) would be walked.
+    /// It need not exist: a deleted file is judged by its path alone.
+    pub fn accepts(&self, relative: &Path) -> bool {
+        self.glob.is_match(self.path_style.display(relative))
+            && !self.ignore_set.is_match(relative)
+            && !self.gitignored(relative)
+    }
+
+    /// Whether the excludes or a `.gitignore` between the repo root and
+    /// `relative` ignore it; the deepest file with a say wins, as in git.
+    fn gitignored(&self, relative: &Path) -> bool {
+        if !self.git {
+            return false;
+        }
+        let path = self.repo_root.join(relative);
+        let mut verdict = Match::None;
+        let dirs: Vec<&Path> = relative.ancestors().skip(1).collect();
+        let gitignores: Vec<Gitignore> = dirs
+            .iter()
+            .rev()
+            .map(|dir| self.repo_root.join(dir).join(".gitignore"))
+            .filter(|file| file.is_file())
+            .map(|file| Gitignore::new(file).0)
+            .collect();
+        for gitignore in self.excludes.iter().chain(&gitignores) {
+            match gitignore.matched_path_or_any_parents(&path, false) {
+                Match::None => {}
+                matched => verdict = matched.map(|_| ()),
+            }
+        }
+        verdict.is_ignore()
+    }
+}
+
+/// What a walk needs from a [`RepoIndex`], shareable across the threads of
+/// [`RepoIndex::walk_globs`] (the index itself isn't `Sync`).
+#[derive(Clone, Copy)]
+struct Walker<'a> {
+    repo_root: &'a Path,
+    config: &'a Config,
+    path_style: PathStyle,
+}
+
+impl Walker<'_> {
+    fn walk(&self, glob: &str) -> crate::Result<Vec<PathBuf>> {
         let discovery = FileDiscovery::detect();
 
-        match discovery {
+        let files = match discovery {
             FileDiscovery::Fd => self.walk_files_fd(glob),
             FileDiscovery::Ripgrep => self.walk_files_rg(glob),
             FileDiscovery::Ignore => self.walk_files_ignore(glob),
+        }?;
+
+        if self.config.indexing.follow_symlinks {
+            Ok(self.drop_symlink_cycles(files))
+        } else {
+            Ok(files)
         }
     }
 
+    /// Drop files whose ancestor directories resolve to the same canonical
+    /// directory twice, i.e. paths that only exist by looping through a symlink.
+    fn drop_symlink_cycles(&self, files: Vec<PathBuf>) -> Vec<PathBuf> {
+        let mut canonical_dirs: HashMap<PathBuf, Option<PathBuf>> = HashMap::new();
+        let mut canonical = |dir: &Path| -> Option<PathBuf> {
+            canonical_dirs
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| std::fs::canonicalize(dir).ok())
+                .clone()
+        };
+
+        files
+            .into_iter()
+            .filter(|file| {
+                let Ok(relative) = file.strip_prefix(self.repo_root) else {
+                    return true;
+                };
+                if relative.components().count() > MAX_SYMLINK_DEPTH {
+                    return false;
+                }
+
+                let mut visited: Vec<PathBuf> = canonical(self.repo_root).into_iter().collect();
+                let mut dir = self.repo_root.to_path_buf();
+                for component in relative.parent().into_iter().flat_map(Path::components) {
+                    dir.push(component);
+                    let Some(resolved) = canonical(&dir) else {
+                        return false;
+                    };
+                    if visited.contains(&resolved) {
+                        return false;
+                    }
+                    visited.push(resolved);
+                }
+                true
+            })
+            .collect()
+    }
+
     /// Walk files using fd (fastest)
     fn walk_files_fd(&self, glob: &str) -> crate::Result<Vec<PathBuf>> {
-        let mut cmd = Command::new("fd");
+        let Some(mut cmd) = process::tool_command("fd") else {
+            return self.walk_files_ignore(glob);
+        };
         cmd.arg("--type").arg("f");
+        cmd.arg("--print0"); // NUL-separated, so non-UTF-8 names survive intact
         cmd.arg("--hidden"); // Include hidden, let .gitignore handle it
+        if self.config.indexing.follow_symlinks {
+            cmd.arg("--follow");
+            cmd.arg("--max-depth").arg(MAX_SYMLINK_DEPTH.to_string());
+        }
 
         // Use glob pattern for filtering (supports directory patterns like **/auth/**/*.ts)
         // -p enables full path matching (not just filename)
@@ -74,30 +308,23 @@ impl RepoIndex {
         }
 
         // Search in repo root
-        cmd.arg(&self.repo_root);
+        cmd.arg(self.repo_root);
 
-        let output = cmd.output().map_err(CanopyError::Io)?;
-
-        if !output.status.success() {
-            // Fallback to ignore crate on error
-            return self.walk_files_ignore(glob);
-        }
-
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let files: Vec<PathBuf> = stdout
-            .lines()
-            .filter(|line| !line.is_empty())
-            .map(PathBuf::from)
-            .collect();
-
-        Ok(files)
+        self.walk_external(cmd, glob, self.discovery_limits())
     }
 
     /// Walk files using ripgrep --files
     fn walk_files_rg(&self, glob: &str) -> crate::Result<Vec<PathBuf>> {
-        let mut cmd = Command::new("rg");
+        let Some(mut cmd) = process::tool_command("rg") else {
+            return self.walk_files_ignore(glob);
+        };
         cmd.arg("--files");
+        cmd.arg("--null"); // NUL-separated, so non-UTF-8 names survive intact
         cmd.arg("--hidden"); // Include hidden, let .gitignore handle it
+        if self.config.indexing.follow_symlinks {
+            cmd.arg("--follow");
+            cmd.arg("--max-depth").arg(MAX_SYMLINK_DEPTH.to_string());
+        }
 
         // Use glob pattern for filtering (supports directory patterns like **/auth/**/*.ts)
         cmd.arg("--glob").arg(glob);
@@ -109,59 +336,57 @@ impl RepoIndex {
         }
 
         // Search in repo root
-        cmd.arg(&self.repo_root);
+        cmd.arg(self.repo_root);
 
-        let output = cmd.output().map_err(CanopyError::Io)?;
+        self.walk_external(cmd, glob, self.discovery_limits())
+    }
 
-        if !output.status.success() {
-            // Fallback to ignore crate on error
+    /// Paths a NUL-separated fd or rg walk lists, bytes untouched. A walk
+    /// that fails or lists more than `limits` allow falls back to the ignore
+    /// crate; one still running at the timeout is an error.
+    fn walk_external(
+        &self,
+        cmd: Command,
+        glob: &str,
+        limits: OutputLimits,
+    ) -> crate::Result<Vec<PathBuf>> {
+        let output = process::capture(cmd, 0, limits)?;
+        if !output.success || output.truncated {
             return self.walk_files_ignore(glob);
         }
+        Ok(output
+            .records
+            .iter()
+            .map(|path| path_from_bytes(path))
+            .collect())
+    }
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let files: Vec<PathBuf> = stdout
-            .lines()
-            .filter(|line| !line.is_empty())
-            .map(PathBuf::from)
-            .collect();
-
-        Ok(files)
+    fn discovery_limits(&self) -> OutputLimits {
+        OutputLimits {
+            timeout: self.config.discovery_timeout_duration(),
+            max_records: MAX_DISCOVERY_PATHS,
+            max_bytes: MAX_DISCOVERY_BYTES,
+        }
     }
 
     /// Walk files using ignore crate (fallback)
     fn walk_files_ignore(&self, glob: &str) -> crate::Result<Vec<PathBuf>> {
-        let mut builder = WalkBuilder::new(&self.repo_root);
+        let mut builder = WalkBuilder::new(self.repo_root);
         builder.hidden(false);
         builder.git_ignore(true);
         builder.git_global(true);
         builder.git_exclude(true);
+        let follow_symlinks = self.config.indexing.follow_symlinks;
+        if follow_symlinks {
+            // walkdir reports ancestor loops as errors, which are skipped below
+            builder.follow_links(true);
+            builder.max_depth(Some(MAX_SYMLINK_DEPTH));
+        }
 
         // Build glob matcher for inclusion
-        let mut glob_builder = globset::GlobSetBuilder::new();
-        glob_builder
-            .add(globset::Glob::new(glob).map_err(|e| CanopyError::GlobPattern(e.to_string()))?);
-        let glob_set = glob_builder
-            .build()
-            .map_err(|e| CanopyError::GlobPattern(e.to_string()))?;
-
-        // Build glob matcher for custom ignore patterns
-        let mut ignore_builder = globset::GlobSetBuilder::new();
-        for pattern in &self.config.ignore.patterns {
-            let glob_pattern = if pattern.contains('*') || pattern.contains('?') {
-                pattern.clone()
-            } else {
-                format!("**/{}", pattern)
-            };
-            if let Ok(g) = globset::Glob::new(&glob_pattern) {
-                ignore_builder.add(g);
-            }
-            if let Ok(g) = globset::Glob::new(&format!("**/{}/**", pattern)) {
-                ignore_builder.add(g);
-            }
-        }
-        let ignore_set = ignore_builder
-            .build()
-            .map_err(|e| CanopyError::GlobPattern(e.to_string()))?;
+        let glob_matcher = self.path_style.glob(glob)?;
+
+        let ignore_set = ignore_glob_set(&self.config.ignore.patterns)?;
 
         let mut files = Vec::new();
 
@@ -177,13 +402,19 @@ impl RepoIndex {
                 continue;
             }
 
-            let relative = path.strip_prefix(&self.repo_root).unwrap_or(path);
+            // Without follow_symlinks, symlinked files are skipped like fd/rg do
+            if !follow_symlinks && entry.path_is_symlink() {
+                continue;
+            }
+
+            let relative = path.strip_prefix(self.repo_root).unwrap_or(path);
 
             if ignore_set.is_match(relative) {
                 continue;
             }
 
-            if glob_set.is_match(relative) {
+            // Match the display form, as search and invalidate do
+            if glob_matcher.is_match(self.path_style.display(relative)) {
                 files.push(path.to_path_buf());
             }
         }
@@ -192,6 +423,37 @@ impl RepoIndex {
     }
 }
 
+/// Whether the tool `name` is on `PATH` and answers `--version` in time.
+fn responds(name: &str) -> bool {
+    process::tool_command(name).is_some_and(|mut cmd| {
+        cmd.arg("--version");
+        process::capture(cmd, b'\n', OutputLimits::timeout(PROBE_TIMEOUT))
+            .is_ok_and(|output| output.success)
+    })
+}
+
+/// Matcher for `[ignore] patterns`: bare names match at any depth, as files or
+/// as directories.
+pub(super) fn ignore_glob_set(patterns: &[String]) -> crate::Result<globset::GlobSet> {
+    let mut ignore_builder = globset::GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob_pattern = if pattern.contains('*') || pattern.contains('?') {
+            pattern.clone()
+        } else {
+            format!("**/{}", pattern)
+        };
+        if let Ok(g) = globset::Glob::new(&glob_pattern) {
+            ignore_builder.add(g);
+        }
+        if let Ok(g) = globset::Glob::new(&format!("**/{}/**", pattern)) {
+            ignore_builder.add(g);
+        }
+    }
+    ignore_builder
+        .build()
+        .map_err(|e| CanopyError::GlobPattern(e.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -229,6 +491,24 @@ mod tests {
         assert_eq!(FileDiscovery::Ignore.name(), "ignore-crate");
     }
 
+    #[test]
+    fn file_discovery_from_name_accepts_aliases() {
+        assert_eq!(FileDiscovery::from_name("fd"), Some(FileDiscovery::Fd));
+        assert_eq!(FileDiscovery::from_name("rg"), Some(FileDiscovery::Ripgrep));
+        assert_eq!(
+            FileDiscovery::from_name(" Builtin "),
+            Some(FileDiscovery::Ignore)
+        );
+        for backend in [
+            FileDiscovery::Fd,
+            FileDiscovery::Ripgrep,
+            FileDiscovery::Ignore,
+        ] {
+            assert_eq!(FileDiscovery::from_name(backend.name()), Some(backend));
+        }
+        assert_eq!(FileDiscovery::from_name("find"), None);
+    }
+
     #[test]
     fn walk_files_finds_matching_files() {
         let dir = TempDir::new().unwrap();
@@ -276,4 +556,197 @@ mod tests {
         let files = index.walk_files("**/*.py").unwrap();
         assert!(files.is_empty(), "should find no .py files");
     }
+
+    #[test]
+    fn walk_globs_lists_each_file_once_under_its_first_glob() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("src/auth")).unwrap();
+        fs::create_dir_all(dir.path().join("lib/auth")).unwrap();
+        fs::write(dir.path().join("src/auth/login.rs"), "fn login() {}\n").unwrap();
+        fs::write(dir.path().join("lib/auth/token.rs"), "fn token() {}\n").unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+        RepoIndex::init(dir.path()).unwrap();
+        let index = RepoIndex::open(dir.path()).unwrap();
+
+        let globs: Vec<String> = [
+            "src/auth/**",
+            "**/auth/**",
+            "src/**/*.rs",
+            "**/*.py",
+            "[bad",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        let mut files = index.walk_globs(&globs, 100);
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                ("lib/auth/token.rs".to_string(), 1),
+                ("src/auth/login.rs".to_string(), 0),
+                ("src/main.rs".to_string(), 2),
+            ]
+        );
+
+        // The budget caps the merged set
+        assert_eq!(index.walk_globs(&globs, 2).len(), 2);
+    }
+
+    #[test]
+    fn walk_filter_judges_single_paths_like_the_walk() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::create_dir_all(root.join("src/gen")).unwrap();
+        fs::write(root.join(".gitignore"), "*.log\nbuild/\n").unwrap();
+        fs::write(root.join("src/.gitignore"), "gen/\n!keep.log\n").unwrap();
+        fs::write(root.join(".git/info/exclude"), "scratch.rs\n").unwrap();
+        RepoIndex::init(root).unwrap();
+        let index = RepoIndex::open(root).unwrap();
+
+        let filter = index.walk_filter("**/*.{rs,log}").unwrap();
+        let accepts = |path: &str| filter.accepts(Path::new(path));
+        assert!(accepts("src/main.rs"));
+        // Judged by path: it needn't exist
+        assert!(accepts("src/deleted.rs"));
+        assert!(!accepts("src/notes.txt"), "outside the glob");
+        assert!(!accepts("target/debug/out.rs"), "[ignore] patterns");
+        assert!(!accepts("app.log"), "root .gitignore");
+        assert!(!accepts("build/out.rs"), "ignored directory");
+        assert!(!accepts("src/gen/out.rs"), "nested .gitignore");
+        assert!(accepts("src/keep.log"), "re-included deeper down");
+        assert!(!accepts("scratch.rs"), ".git/info/exclude");
+
+        // Outside a git checkout the walk doesn't read .gitignore either
+        fs::remove_dir_all(root.join(".git")).unwrap();
+        let filter = index.walk_filter("**/*.rs").unwrap();
+        assert!(filter.accepts(Path::new("build/out.rs")));
+    }
+
+    /// Repo with `shared/util.rs`, a relative `linked -> shared` symlink, and a
+    /// `shared/loop -> ..` cycle.
+    #[cfg(unix)]
+    fn symlinked_repo(follow_symlinks: bool) -> TempDir {
+        use std::os::unix::fs::symlink;
+
+        let dir = TempDir::new().unwrap();
+        let shared = dir.path().join("shared");
+        fs::create_dir_all(&shared).unwrap();
+        fs::write(shared.join("util.rs"), "fn shared_util() {}\n").unwrap();
+        symlink("shared", dir.path().join("linked")).unwrap();
+        symlink("..", shared.join("loop")).unwrap();
+
+        RepoIndex::init(dir.path()).unwrap();
+        fs::write(
+            dir.path().join(".canopy/config.toml"),
+            format!("[indexing]\nfollow_symlinks = {follow_symlinks}\n"),
+        )
+        .unwrap();
+        dir
+    }
+
+    #[cfg(unix)]
+    fn relative_paths(index: &RepoIndex, glob: &str) -> Vec<String> {
+        let mut paths: Vec<String> = index
+            .walk_files(glob)
+            .unwrap()
+            .iter()
+            .map(|p| {
+                p.strip_prefix(&index.repo_root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_files_skips_symlinks_by_default() {
+        let dir = symlinked_repo(false);
+        let index = RepoIndex::open(dir.path()).unwrap();
+        assert_eq!(relative_paths(&index, "**/*.rs"), vec!["shared/util.rs"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_files_follows_symlinks_and_breaks_cycles() {
+        let dir = symlinked_repo(true);
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+
+        // The cycle (shared/loop -> ..) contributes nothing beyond the real files
+        assert_eq!(
+            relative_paths(&index, "**/*.rs"),
+            vec!["linked/util.rs", "shared/util.rs"]
+        );
+
+        // Symlinked files are stored and expanded under the symlink path
+        index.index("**/*.rs").unwrap();
+        let handles = index.search_code("shared_util", 10).unwrap();
+        let linked = handles
+            .iter()
+            .find(|h| h.file_path == "linked/util.rs")
+            .expect("symlinked copy should be indexed");
+        let expanded = index.expand(&[linked.id.to_string()]).unwrap();
+        assert!(expanded[0].1.contains("shared_util"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_external_falls_back_when_output_exceeds_the_caps() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("real.rs"), "fn real() {}\n").unwrap();
+        RepoIndex::init(dir.path()).unwrap();
+        let index = RepoIndex::open(dir.path()).unwrap();
+        let bin = TempDir::new().unwrap();
+        let tool = process::fake_tool(
+            bin.path(),
+            "fd",
+            "i=0; while [ $i -lt 50 ]; do printf 'fake%d.rs\\0' $i; i=$((i+1)); done",
+        );
+        let limits = |max_records| OutputLimits {
+            timeout: std::time::Duration::from_secs(5),
+            max_records,
+            max_bytes: usize::MAX,
+        };
+
+        let listed = index
+            .walker()
+            .walk_external(process::scrubbed_command(&tool), "**/*.rs", limits(100))
+            .unwrap();
+        assert_eq!(listed.len(), 50);
+
+        // Over the cap: the builtin walker lists what is really there
+        let fallback = index
+            .walker()
+            .walk_external(process::scrubbed_command(&tool), "**/*.rs", limits(10))
+            .unwrap();
+        assert_eq!(fallback, vec![dir.path().join("real.rs")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_external_times_out_on_a_hung_tool() {
+        let dir = TempDir::new().unwrap();
+        RepoIndex::init(dir.path()).unwrap();
+        let index = RepoIndex::open(dir.path()).unwrap();
+        let bin = TempDir::new().unwrap();
+        let tool = process::fake_tool(bin.path(), "rg", "exec sleep 5");
+
+        let err = index
+            .walker()
+            .walk_external(
+                process::scrubbed_command(&tool),
+                "**/*.rs",
+                OutputLimits::timeout(std::time::Duration::from_millis(200)),
+            )
+            .unwrap_err();
+        assert!(
+            matches!(&err, CanopyError::ExternalToolTimeout { command, .. } if command == "rg"),
+            "{err}"
+        );
+    }
 }
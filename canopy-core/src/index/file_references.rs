@@ -0,0 +1,218 @@
+//! References to one symbol grouped by file: one entry per referencing file
+//! with its count, ref type breakdown and lines, instead of a flat list that
+//! repeats each path once per reference.
+
+use crate::document::RefType;
+use crate::handle::RefHandle;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::RepoIndex;
+
+/// Default `limit` for [`RepoIndex::references_by_file`].
+pub const DEFAULT_REFERENCE_FILES_LIMIT: usize = 20;
+
+/// Most references read from each database before grouping; past it the
+/// totals undercount and the result is marked truncated.
+pub const MAX_GROUPED_REFERENCES: usize = 10_000;
+
+/// A symbol's references, one entry per referencing file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileReferences {
+    /// The symbol as asked for
+    pub symbol: String,
+    /// Most references first, then by path
+    pub files: Vec<ReferenceFile>,
+    /// Referencing files, before `limit`
+    pub total_files: usize,
+    /// References across every referencing file, before `limit`
+    pub total_references: usize,
+    /// Files were cut by `limit`, or references by [`MAX_GROUPED_REFERENCES`]
+    pub truncated: bool,
+}
+
+/// The references to a symbol in one file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReferenceFile {
+    pub file_path: String,
+    pub count: usize,
+    /// References per ref type (`call`, `import`, `type_ref`)
+    pub ref_types: BTreeMap<String, usize>,
+    /// Starting lines of the references, ascending and without repeats
+    pub lines: Vec<usize>,
+}
+
+impl FileReferences {
+    /// Group `refs` by file and keep the `limit` files referencing `symbol`
+    /// most.
+    pub fn group(symbol: &str, refs: Vec<RefHandle>, limit: usize) -> Self {
+        let mut by_file: BTreeMap<String, ReferenceFile> = BTreeMap::new();
+        for r in refs {
+            let file = by_file
+                .entry(r.file_path.clone())
+                .or_insert_with(|| ReferenceFile {
+                    file_path: r.file_path,
+                    count: 0,
+                    ref_types: BTreeMap::new(),
+                    lines: Vec::new(),
+                });
+            file.count += 1;
+            *file
+                .ref_types
+                .entry(r.ref_type.as_str().to_string())
+                .or_default() += 1;
+            file.lines.push(r.line_range.0);
+        }
+
+        let mut files: Vec<ReferenceFile> = by_file
+            .into_values()
+            .map(|mut file| {
+                file.lines.sort_unstable();
+                file.lines.dedup();
+                file
+            })
+            .collect();
+        files.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.file_path.cmp(&b.file_path))
+        });
+        let total_files = files.len();
+        let total_references = files.iter().map(|f| f.count).sum();
+        files.truncate(limit);
+        FileReferences {
+            symbol: symbol.to_string(),
+            truncated: files.len() < total_files,
+            files,
+            total_files,
+            total_references,
+        }
+    }
+}
+
+impl RepoIndex {
+    /// References to `symbol` of `ref_types` (all when empty) in files
+    /// matching `glob`, grouped by file, across every database the glob
+    /// reaches.
+    pub fn references_by_file(
+        &self,
+        symbol: &str,
+        ref_types: &[RefType],
+        glob: Option<&str>,
+        limit: usize,
+    ) -> crate::Result<FileReferences> {
+        let matcher = glob.map(|g| self.path_style().glob(g)).transpose()?;
+        let mut refs = Vec::new();
+        let mut capped = false;
+        for target in self.query_targets(glob) {
+            let mut found =
+                target.search_references(symbol, ref_types, MAX_GROUPED_REFERENCES + 1)?;
+            if found.len() > MAX_GROUPED_REFERENCES {
+                found.truncate(MAX_GROUPED_REFERENCES);
+                capped = true;
+            }
+            refs.extend(
+                found
+                    .into_iter()
+                    .filter(|r| matcher.as_ref().is_none_or(|m| m.is_match(&r.file_path))),
+            );
+        }
+        let mut grouped = FileReferences::group(symbol, refs, limit);
+        grouped.truncated |= capped;
+        Ok(grouped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+
+    /// `dispatch` is called twice in `src/server.rs` (once on a line of its
+    /// own) and once in `src/client.rs`, and imported in `tests/dispatch.rs`.
+    fn dispatch_repo() -> (FixtureRepo, RepoIndex) {
+        let repo = FixtureRepoBuilder::new()
+            .file("src/lib.rs", "pub fn dispatch(id: u32) -> u32 { id }\n")
+            .file(
+                "src/server.rs",
+                "pub fn serve() {\n    dispatch(1);\n    dispatch(2);\n}\n",
+            )
+            .file("src/client.rs", "pub fn send() {\n    dispatch(3);\n}\n")
+            .file(
+                "tests/dispatch.rs",
+                "use crate::dispatch;\n\nfn check() {\n    dispatch(4);\n}\n",
+            )
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        (repo, index)
+    }
+
+    fn counts(refs: &FileReferences) -> Vec<(&str, usize)> {
+        refs.files
+            .iter()
+            .map(|f| (f.file_path.as_str(), f.count))
+            .collect()
+    }
+
+    #[test]
+    fn groups_references_by_file_with_lines_and_types() {
+        let (_repo, index) = dispatch_repo();
+        let refs = index
+            .references_by_file("dispatch", &[], None, DEFAULT_REFERENCE_FILES_LIMIT)
+            .unwrap();
+        assert_eq!(refs.symbol, "dispatch");
+        assert_eq!(
+            counts(&refs),
+            [
+                ("src/server.rs", 2),
+                ("tests/dispatch.rs", 2),
+                ("src/client.rs", 1),
+            ]
+        );
+        assert_eq!(refs.total_files, 3);
+        assert_eq!(refs.total_references, 5);
+        assert!(!refs.truncated);
+
+        let server = &refs.files[0];
+        assert_eq!(server.lines, [2, 3]);
+        assert_eq!(server.ref_types, BTreeMap::from([("call".to_string(), 2)]));
+        let tests = &refs.files[1];
+        assert_eq!(tests.lines, [1, 4]);
+        assert_eq!(
+            tests.ref_types,
+            BTreeMap::from([("call".to_string(), 1), ("import".to_string(), 1)])
+        );
+
+        let top = index.references_by_file("dispatch", &[], None, 1).unwrap();
+        assert_eq!(counts(&top), [("src/server.rs", 2)]);
+        assert_eq!((top.total_files, top.total_references), (3, 5));
+        assert!(top.truncated);
+    }
+
+    #[test]
+    fn ref_types_and_glob_narrow_the_files() {
+        let (_repo, index) = dispatch_repo();
+        let imports = index
+            .references_by_file("dispatch", &[RefType::Import], None, 10)
+            .unwrap();
+        assert_eq!(counts(&imports), [("tests/dispatch.rs", 1)]);
+        assert_eq!(
+            imports.files[0].ref_types,
+            BTreeMap::from([("import".to_string(), 1)])
+        );
+
+        let calls = index
+            .references_by_file("dispatch", &[RefType::Call], Some("src/**"), 10)
+            .unwrap();
+        assert_eq!(counts(&calls), [("src/server.rs", 2), ("src/client.rs", 1)]);
+        assert_eq!(calls.total_references, 3);
+
+        let none = index
+            .references_by_file("dispatch", &[RefType::TypeRef], None, 10)
+            .unwrap();
+        assert!(none.files.is_empty());
+        assert_eq!((none.total_files, none.total_references), (0, 0));
+    }
+}
@@ -0,0 +1,378 @@
+//! Whole-file handles for `(file "glob")`, capped and paged.
+//!
+//! A directory-wide glob can match thousands of files, so a page stops at
+//! `max_files` or `max_total_tokens` and reports how many matches it left out.
+//! Matches are taken in path order, which keeps `offset` paging stable.
+
+use crate::config::Config;
+use crate::document::NodeType;
+use crate::handle::{generate_preview, Handle, HandleId, HandleSource};
+use crate::parse::estimate_tokens;
+use rusqlite::OptionalExtension;
+use std::collections::BTreeMap;
+
+use super::search::collect_row_results;
+use super::RepoIndex;
+
+/// Caps and paging for a whole-file query. Unset caps fall back to
+/// `[core] file_max_files` / `file_max_tokens`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileQueryOptions {
+    pub max_files: Option<usize>,
+    pub max_total_tokens: Option<usize>,
+    /// Matches to skip, in path order
+    pub offset: usize,
+    /// Build handles from stored metadata without reading the files.
+    /// Previews are left empty.
+    pub skip_preview: bool,
+}
+
+impl FileQueryOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_files(mut self, max_files: usize) -> Self {
+        self.max_files = Some(max_files);
+        self
+    }
+
+    pub fn with_max_total_tokens(mut self, max_total_tokens: usize) -> Self {
+        self.max_total_tokens = Some(max_total_tokens);
+        self
+    }
+
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_skip_preview(mut self, skip_preview: bool) -> Self {
+        self.skip_preview = skip_preview;
+        self
+    }
+
+    fn caps(&self, config: &Config) -> (usize, usize) {
+        (
+            self.max_files.unwrap_or(config.core.file_max_files),
+            self.max_total_tokens.unwrap_or(config.core.file_max_tokens),
+        )
+    }
+}
+
+/// One page of whole-file handles.
+#[derive(Debug, Clone, Default)]
+pub struct FilePage {
+    pub handles: Vec<Handle>,
+    /// Files matching the glob, across all pages
+    pub total_matches: usize,
+    /// Matches after this page
+    pub remaining: usize,
+    /// `offset` that fetches the next page
+    pub next_offset: usize,
+}
+
+impl FilePage {
+    /// Whether matches were left out of this page
+    pub fn truncated(&self) -> bool {
+        self.remaining > 0
+    }
+}
+
+/// `files` row metadata for a whole-file handle.
+struct StoredFile {
+    path: String,
+    path_bytes: Option<Vec<u8>>,
+    token_count: usize,
+    /// 0 for rows migrated from before v11, which must be read instead
+    line_count: usize,
+    byte_len: usize,
+}
+
+/// `StoredFile` from a `path, path_bytes, token_count, line_count, byte_len` row.
+fn stored_file_from_row(row: &rusqlite::Row) -> rusqlite::Result<StoredFile> {
+    let token_count: i64 = row.get(2)?;
+    let line_count: i64 = row.get(3)?;
+    let byte_len: i64 = row.get(4)?;
+    Ok(StoredFile {
+        path: row.get(0)?,
+        path_bytes: row.get(1)?,
+        token_count: token_count.max(0) as usize,
+        line_count: line_count.max(0) as usize,
+        byte_len: byte_len.max(0) as usize,
+    })
+}
+
+impl RepoIndex {
+    /// Whole-file handles for indexed files matching `path_pattern`, one page
+    /// at a time.
+    pub fn get_file(
+        &self,
+        path_pattern: &str,
+        options: &FileQueryOptions,
+    ) -> crate::Result<FilePage> {
+        page_files(&[self], path_pattern, options)
+    }
+
+    fn stored_files(&self, glob_matcher: &globset::GlobMatcher) -> crate::Result<Vec<StoredFile>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT f.path, f.path_bytes, f.token_count, f.line_count, f.byte_len
+             FROM files f WHERE 1{} ORDER BY f.path",
+            self.scope_filter()
+        ))?;
+        let rows = collect_row_results(stmt.query_map([], stored_file_from_row)?)?;
+        Ok(rows
+            .into_iter()
+            .filter(|file| glob_matcher.is_match(&file.path))
+            .collect())
+    }
+
+    /// Whether any indexed file, in any shard, matches `glob`, whatever the
+    /// current scopes.
+    pub(crate) fn glob_matches_any_file(&self, glob: &str) -> crate::Result<bool> {
+        let matcher = self.path_style.glob(glob)?;
+        for index in self.all_indexes() {
+            let mut stmt = index.conn.prepare_cached("SELECT path FROM files")?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                if matcher.is_match(row.get::<_, String>(0)?) {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Each directory holding indexed files, in any shard, with the number of
+    /// files anywhere below it, and the total file count.
+    pub(crate) fn indexed_directories(&self) -> crate::Result<(BTreeMap<String, usize>, usize)> {
+        let mut directories = BTreeMap::new();
+        let mut total = 0;
+        for index in self.all_indexes() {
+            let mut stmt = index.conn.prepare_cached("SELECT path FROM files")?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                let path: String = row.get(0)?;
+                total += 1;
+                for (end, _) in path.match_indices('/') {
+                    *directories.entry(path[..end].to_string()).or_insert(0) += 1;
+                }
+            }
+        }
+        Ok((directories, total))
+    }
+
+    /// Whole-file handle for the file stored here under `path`, if any.
+    pub(super) fn file_handle_at(&self, path: &str) -> crate::Result<Option<Handle>> {
+        let file = self
+            .conn
+            .query_row(
+                "SELECT path, path_bytes, token_count, line_count, byte_len
+                 FROM files WHERE path = ?",
+                [path],
+                stored_file_from_row,
+            )
+            .optional()?;
+        match file {
+            Some(file) => self.file_handle(file, false),
+            None => Ok(None),
+        }
+    }
+
+    /// Handle for a stored file, or `None` when it can't be read any more.
+    fn file_handle(&self, file: StoredFile, skip_preview: bool) -> crate::Result<Option<Handle>> {
+        let full_path = self.disk_path(&file.path, file.path_bytes.as_deref())?;
+        let (span, line_count, preview) = if skip_preview && file.line_count > 0 {
+            (0..file.byte_len, file.line_count, String::new())
+        } else {
+            let Ok(source) = std::fs::read_to_string(&full_path) else {
+                return Ok(None);
+            };
+            let source = crate::parse::indexed_source(&full_path, source);
+            let span = 0..source.len();
+            let preview = generate_preview(
+                &source,
+                &span,
+                self.config.indexing.preview_bytes,
+                self.redactor.for_path(&file.path),
+            );
+            (span, source.lines().count().max(1), preview)
+        };
+
+        Ok(Some(Handle {
+            id: HandleId::from_path_bytes(
+                file.path_bytes.as_deref().unwrap_or(file.path.as_bytes()),
+                NodeType::Chunk,
+                &span,
+            ),
+            file_path: file.path,
+            node_type: NodeType::Chunk,
+            span,
+            line_range: (1, line_count),
+            token_count: file.token_count,
+            preview_tokens: estimate_tokens(&preview),
+            preview,
+            content: None,
+            content_hash: None,
+            source: HandleSource::Local,
+            commit_sha: None,
+            generation: None,
+            possibly_stale: false,
+            rerank_score: None,
+            score: None,
+            match_line: None,
+            match_count_in_node: None,
+            via: None,
+            result_class: None,
+        }))
+    }
+}
+
+/// Page through the files matching `path_pattern` across `targets` (the
+/// catch-all database and any shards), merged in path order.
+pub(crate) fn page_files(
+    targets: &[&RepoIndex],
+    path_pattern: &str,
+    options: &FileQueryOptions,
+) -> crate::Result<FilePage> {
+    let style = targets.first().map(|t| t.path_style).unwrap_or_default();
+    let glob_matcher = style.glob(path_pattern)?;
+
+    let mut matches = Vec::new();
+    for target in targets {
+        for file in target.stored_files(&glob_matcher)? {
+            matches.push((*target, file));
+        }
+    }
+    matches.sort_by(|(_, a), (_, b)| a.path.cmp(&b.path));
+
+    let Some(first) = targets.first() else {
+        return Ok(FilePage::default());
+    };
+    let (max_files, max_tokens) = options.caps(first.config());
+    let total_matches = matches.len();
+    let mut next_offset = options.offset.min(total_matches);
+    let mut handles = Vec::new();
+    let mut tokens = 0usize;
+
+    for (target, file) in matches.into_iter().skip(next_offset) {
+        if handles.len() >= max_files {
+            break;
+        }
+        // The first file always fits, so paging makes progress
+        if !handles.is_empty() && tokens + file.token_count > max_tokens {
+            break;
+        }
+        next_offset += 1;
+        if let Some(handle) = target.file_handle(file, options.skip_preview)? {
+            tokens += handle.token_count;
+            handles.push(handle);
+        }
+    }
+
+    Ok(FilePage {
+        handles,
+        total_matches,
+        remaining: total_matches - next_offset,
+        next_offset,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::test_helpers::setup_repo;
+
+    fn indexed_repo(n: usize) -> (canopy_testutil::FixtureRepo, RepoIndex) {
+        let dir = setup_repo(n);
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        (dir, index)
+    }
+
+    #[test]
+    fn get_file_caps_at_configured_max_files() {
+        let (_dir, index) = indexed_repo(200);
+        let page = index.get_file("src/**", &FileQueryOptions::new()).unwrap();
+        assert_eq!(page.handles.len(), 50);
+        assert_eq!(page.total_matches, 200);
+        assert_eq!(page.remaining, 150);
+        assert!(page.truncated());
+        assert_eq!(page.next_offset, 50);
+    }
+
+    #[test]
+    fn get_file_pages_through_every_match_once() {
+        let (_dir, index) = indexed_repo(200);
+        let mut seen = Vec::new();
+        let mut options = FileQueryOptions::new().with_max_files(30);
+        loop {
+            let page = index.get_file("src/**", &options).unwrap();
+            seen.extend(page.handles.iter().map(|h| h.file_path.clone()));
+            if !page.truncated() {
+                break;
+            }
+            options = options.with_offset(page.next_offset);
+        }
+        assert_eq!(seen.len(), 200);
+        let mut sorted = seen.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted, seen, "pages come in path order without repeats");
+
+        let past_end = index
+            .get_file("src/**", &FileQueryOptions::new().with_offset(500))
+            .unwrap();
+        assert!(past_end.handles.is_empty());
+        assert_eq!(past_end.remaining, 0);
+    }
+
+    #[test]
+    fn get_file_stops_at_token_budget() {
+        let (_dir, index) = indexed_repo(200);
+        let per_file = index
+            .get_file("src/file_0.rs", &FileQueryOptions::new())
+            .unwrap()
+            .handles[0]
+            .token_count;
+
+        let options = FileQueryOptions::new().with_max_total_tokens(per_file * 5);
+        let page = index.get_file("src/**", &options).unwrap();
+        assert!(page.handles.len() <= 5 && page.handles.len() >= 4);
+        assert!(page.handles.iter().map(|h| h.token_count).sum::<usize>() <= per_file * 5);
+        assert_eq!(page.remaining, 200 - page.handles.len());
+
+        // A budget smaller than any file still returns one
+        let tiny = FileQueryOptions::new().with_max_total_tokens(1);
+        assert_eq!(index.get_file("src/**", &tiny).unwrap().handles.len(), 1);
+    }
+
+    #[test]
+    fn skip_preview_matches_read_handles_without_reading() {
+        let (dir, index) = indexed_repo(3);
+        let read = index.get_file("src/**", &FileQueryOptions::new()).unwrap();
+        let options = FileQueryOptions::new().with_skip_preview(true);
+        let meta = index.get_file("src/**", &options).unwrap();
+
+        assert_eq!(read.handles.len(), meta.handles.len());
+        for (r, m) in read.handles.iter().zip(&meta.handles) {
+            assert_eq!(r.id, m.id, "same id from stored metadata");
+            assert_eq!(r.span, m.span);
+            assert_eq!(r.line_range, m.line_range);
+            assert!(!r.preview.is_empty());
+            assert!(m.preview.is_empty());
+        }
+
+        // Unreadable files still get a handle from stored metadata
+        std::fs::remove_file(dir.path().join("src/file_0.rs")).unwrap();
+        assert_eq!(index.get_file("src/**", &options).unwrap().handles.len(), 3);
+        assert_eq!(
+            index
+                .get_file("src/**", &FileQueryOptions::new())
+                .unwrap()
+                .handles
+                .len(),
+            2
+        );
+    }
+}
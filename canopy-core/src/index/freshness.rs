@@ -0,0 +1,295 @@
+//! Skip checks deciding whether an already-indexed file must be reparsed.
+//!
+//! Shared by the sequential and pipeline index paths so both apply the same
+//! `[indexing] stat_ttl` / `verify` matrix.
+
+use crate::config::{Config, VerifyMode};
+use crate::parse::file_mtime;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Stored metadata for an indexed file, as needed by skip checks
+pub(super) struct FileMeta {
+    pub(super) mtime: i64,
+    pub(super) hash: [u8; 32],
+    pub(super) indexed_at: i64,
+    pub(super) tokens: usize,
+    /// Flagged by a schema migration that needs data the stored parse lacks
+    pub(super) reparse: bool,
+}
+
+/// Why an indexed file was not reparsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SkipReason {
+    /// Index row younger than `stat_ttl`; the file was not even stat'ed
+    Ttl,
+    /// mtime unchanged
+    Mtime,
+    /// Content hash unchanged
+    Hash,
+}
+
+impl SkipReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ttl => "ttl",
+            Self::Mtime => "mtime",
+            Self::Hash => "hash",
+        }
+    }
+}
+
+/// Files skipped during an index run, by reason
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SkipCounts {
+    pub ttl: usize,
+    pub mtime: usize,
+    pub hash: usize,
+}
+
+impl SkipCounts {
+    pub fn total(&self) -> usize {
+        self.ttl + self.mtime + self.hash
+    }
+
+    pub(crate) fn record(&mut self, reason: SkipReason) {
+        match reason {
+            SkipReason::Ttl => self.ttl += 1,
+            SkipReason::Mtime => self.mtime += 1,
+            SkipReason::Hash => self.hash += 1,
+        }
+    }
+
+    pub(crate) fn add(&mut self, other: SkipCounts) {
+        self.ttl += other.ttl;
+        self.mtime += other.mtime;
+        self.hash += other.hash;
+    }
+}
+
+/// [`SkipCounts`] plus skipped tokens, shared across rayon workers
+#[derive(Default)]
+pub(super) struct SkipTally {
+    ttl: AtomicUsize,
+    mtime: AtomicUsize,
+    hash: AtomicUsize,
+    tokens: AtomicUsize,
+}
+
+impl SkipTally {
+    pub(super) fn record(&self, reason: SkipReason, tokens: usize) {
+        let counter = match reason {
+            SkipReason::Ttl => &self.ttl,
+            SkipReason::Mtime => &self.mtime,
+            SkipReason::Hash => &self.hash,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.tokens.fetch_add(tokens, Ordering::Relaxed);
+    }
+
+    /// `(counts, skipped_tokens)`
+    pub(super) fn into_counts(self) -> (SkipCounts, usize) {
+        let counts = SkipCounts {
+            ttl: self.ttl.into_inner(),
+            mtime: self.mtime.into_inner(),
+            hash: self.hash.into_inner(),
+        };
+        (counts, self.tokens.into_inner())
+    }
+}
+
+/// File contents read for indexing, with the mtime captured before the read
+pub(super) struct SourceFile {
+    pub(super) mtime: i64,
+    pub(super) source: String,
+    pub(super) hash: [u8; 32],
+}
+
+impl SourceFile {
+    pub(super) fn read(path: &Path) -> Option<Self> {
+        // mtime captured before read: prevents TOCTOU where hash
+        // reflects old content but mtime reflects new write
+        let mtime = file_mtime(path);
+        let source = fs::read_to_string(path).ok()?;
+        let hash = Sha256::digest(source.as_bytes()).into();
+        Some(Self {
+            mtime,
+            source,
+            hash,
+        })
+    }
+}
+
+/// The `stat_ttl` / `verify` settings for one index run
+#[derive(Debug, Clone, Copy)]
+pub(super) struct SkipPolicy {
+    now_secs: i64,
+    stat_ttl_secs: i64,
+    verify: VerifyMode,
+}
+
+/// What an index run does with one candidate file
+pub(super) enum FileAction {
+    /// Keep the stored parse
+    Skip(SkipReason),
+    /// Parse the file: never indexed, or changed since
+    Parse(SourceFile),
+    /// Left alone: gone or not UTF-8 by the time it was read
+    Unreadable,
+}
+
+impl SkipPolicy {
+    pub(super) fn new(config: &Config, now_secs: i64) -> Self {
+        Self {
+            now_secs,
+            stat_ttl_secs: config.stat_ttl_duration().as_secs() as i64,
+            verify: config.indexing.verify,
+        }
+    }
+
+    /// Decide whether `file` can keep its stored parse.
+    ///
+    /// `content_hash` reads and hashes the file; it is only called when the
+    /// verify mode needs it, so callers can keep the contents they read for
+    /// parsing. `None` (unreadable) never skips.
+    pub(super) fn should_skip(
+        &self,
+        meta: &FileMeta,
+        file: &Path,
+        content_hash: impl FnOnce() -> Option<[u8; 32]>,
+    ) -> Option<SkipReason> {
+        if meta.reparse {
+            return None;
+        }
+        if self.now_secs - meta.indexed_at < self.stat_ttl_secs {
+            return Some(SkipReason::Ttl);
+        }
+
+        let mtime_matches = || file_mtime(file) == meta.mtime;
+        let hash_matches = || content_hash().is_some_and(|hash| hash == meta.hash);
+        match self.verify {
+            VerifyMode::Mtime => mtime_matches().then_some(SkipReason::Mtime),
+            VerifyMode::Hash => hash_matches().then_some(SkipReason::Hash),
+            VerifyMode::MtimeThenHash => {
+                if mtime_matches() {
+                    Some(SkipReason::Mtime)
+                } else {
+                    hash_matches().then_some(SkipReason::Hash)
+                }
+            }
+        }
+    }
+
+    /// Decide what to do with `file`, stored as `meta` if indexed before.
+    ///
+    /// The one decision shared by every index path and by
+    /// [`RepoIndex::plan_index`](super::RepoIndex::plan_index), so a plan
+    /// matches the run it previews.
+    pub(super) fn action(&self, meta: Option<&FileMeta>, file: &Path) -> FileAction {
+        let mut read = None;
+        if let Some(meta) = meta {
+            let skip = self.should_skip(meta, file, || {
+                let source = SourceFile::read(file)?;
+                let hash = source.hash;
+                read = Some(source);
+                Some(hash)
+            });
+            if let Some(reason) = skip {
+                return FileAction::Skip(reason);
+            }
+        }
+        match read.or_else(|| SourceFile::read(file)) {
+            Some(source) => FileAction::Parse(source),
+            None => FileAction::Unreadable,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(verify: VerifyMode, stat_ttl_secs: i64) -> SkipPolicy {
+        SkipPolicy {
+            now_secs: 1_000,
+            stat_ttl_secs,
+            verify,
+        }
+    }
+
+    #[test]
+    fn should_skip_applies_verify_matrix() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("a.rs");
+        fs::write(&file, "fn a() {}\n").unwrap();
+        let current = SourceFile::read(&file).unwrap();
+        let meta = |mtime, hash| FileMeta {
+            mtime,
+            hash,
+            indexed_at: 900,
+            tokens: 3,
+            reparse: false,
+        };
+        let same = meta(current.mtime, current.hash);
+        let touched = meta(current.mtime - 10, current.hash);
+        let rewritten = meta(current.mtime, [7u8; 32]);
+        let hash = || Some(current.hash);
+
+        // Row younger than stat_ttl: trusted without looking at the file
+        let ttl = policy(VerifyMode::Hash, 500);
+        assert_eq!(
+            ttl.should_skip(&rewritten, &file, hash),
+            Some(SkipReason::Ttl)
+        );
+
+        let mtime = policy(VerifyMode::Mtime, 0);
+        assert_eq!(
+            mtime.should_skip(&same, &file, hash),
+            Some(SkipReason::Mtime)
+        );
+        assert_eq!(mtime.should_skip(&touched, &file, hash), None);
+        assert_eq!(
+            mtime.should_skip(&rewritten, &file, hash),
+            Some(SkipReason::Mtime)
+        );
+
+        let strict = policy(VerifyMode::Hash, 0);
+        assert_eq!(
+            strict.should_skip(&touched, &file, hash),
+            Some(SkipReason::Hash)
+        );
+        assert_eq!(strict.should_skip(&rewritten, &file, hash), None);
+        assert_eq!(strict.should_skip(&same, &file, || None), None);
+
+        let layered = policy(VerifyMode::MtimeThenHash, 0);
+        assert_eq!(
+            layered.should_skip(&same, &file, hash),
+            Some(SkipReason::Mtime)
+        );
+        assert_eq!(
+            layered.should_skip(&touched, &file, hash),
+            Some(SkipReason::Hash)
+        );
+        assert_eq!(
+            layered.should_skip(&same, &file, || panic!("mtime match must not hash")),
+            Some(SkipReason::Mtime)
+        );
+
+        // Files a migration flagged are reparsed whatever the row says
+        let flagged = FileMeta {
+            reparse: true,
+            ..meta(current.mtime, current.hash)
+        };
+        for verify in [
+            VerifyMode::Mtime,
+            VerifyMode::Hash,
+            VerifyMode::MtimeThenHash,
+        ] {
+            assert_eq!(policy(verify, 500).should_skip(&flagged, &file, hash), None);
+        }
+    }
+}
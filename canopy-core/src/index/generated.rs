@@ -0,0 +1,281 @@
+//! Generated files: protobuf outputs, bundles, anything marked `@generated`.
+//!
+//! Indexing flags a file as generated when one of the `[generated] markers`
+//! appears in its first lines, its name matches one of the `patterns`, or its
+//! average line is longer than `max_avg_line_length`, as minified code's is.
+//! The flag is stored in `files.generated`.
+//!
+//! Queries leave generated files out unless `include_generated` is set. While
+//! such a query runs, every database holding generated files carries the
+//! exclusion (see [`GeneratedScope`]) and each search appends it through
+//! [`RepoIndex::scope_filter`], next to the recency cutoff, so the limit is
+//! spent on hand-written code. The executor then counts the hidden matches.
+
+use globset::GlobMatcher;
+
+use super::{PathStyle, RepoIndex};
+use crate::config::GeneratedConfig;
+
+/// Lines searched for a marker comment; generators put theirs in the header
+const MARKER_LINES: usize = 10;
+
+/// Smallest file the line-length check applies to, so a one-line config
+/// value doesn't count as minified
+const MIN_MINIFIED_BYTES: usize = 4096;
+
+/// Which files the searches of one database may return.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum GeneratedFilter {
+    #[default]
+    All,
+    /// Hand-written files only
+    Exclude,
+    /// Generated files only, to count what [`Exclude`](Self::Exclude) hid
+    Only,
+}
+
+/// `[generated]` config compiled for one indexing run.
+pub(crate) struct GeneratedDetector {
+    markers: Vec<String>,
+    /// Patterns without a `/`, matched against the file name
+    name_patterns: Vec<GlobMatcher>,
+    /// Patterns with a `/`, matched against the repo-relative path
+    path_patterns: Vec<GlobMatcher>,
+    max_avg_line_length: usize,
+}
+
+impl GeneratedDetector {
+    pub(crate) fn new(config: &GeneratedConfig, style: PathStyle) -> crate::Result<Self> {
+        let mut name_patterns = Vec::new();
+        let mut path_patterns = Vec::new();
+        for pattern in &config.patterns {
+            let matcher = style.glob(pattern)?;
+            if pattern.contains('/') {
+                path_patterns.push(matcher);
+            } else {
+                name_patterns.push(matcher);
+            }
+        }
+        Ok(Self {
+            markers: config.markers.clone(),
+            name_patterns,
+            path_patterns,
+            max_avg_line_length: config.max_avg_line_length,
+        })
+    }
+
+    /// Whether the file at `relative_path` holding `source` looks generated.
+    pub(crate) fn is_generated(&self, relative_path: &str, source: &str) -> bool {
+        let name = relative_path.rsplit('/').next().unwrap_or(relative_path);
+        self.name_patterns.iter().any(|p| p.is_match(name))
+            || self.path_patterns.iter().any(|p| p.is_match(relative_path))
+            || self.has_marker(source)
+            || self.is_minified(source)
+    }
+
+    fn has_marker(&self, source: &str) -> bool {
+        source
+            .lines()
+            .take(MARKER_LINES)
+            .any(|line| self.markers.iter().any(|m| line.contains(m.as_str())))
+    }
+
+    fn is_minified(&self, source: &str) -> bool {
+        self.max_avg_line_length > 0
+            && source.len() >= MIN_MINIFIED_BYTES
+            && source.len() / source.lines().count().max(1) > self.max_avg_line_length
+    }
+}
+
+/// Leaves generated files out of the searches of a set of databases;
+/// dropping it restores each one's previous filter.
+pub(crate) struct GeneratedScope<'a> {
+    /// Each database, its previous filter, and whether the scope excludes
+    /// anything from it
+    indexes: Vec<(&'a RepoIndex, GeneratedFilter, bool)>,
+}
+
+impl<'a> GeneratedScope<'a> {
+    /// Exclude generated files from `indexes`. Databases without any keep
+    /// their filter, so their searches still take the symbol cache path.
+    pub(crate) fn exclude(indexes: impl IntoIterator<Item = &'a RepoIndex>) -> crate::Result<Self> {
+        let mut scoped = Vec::new();
+        for index in indexes {
+            let excludes = index.has_generated_files()?;
+            scoped.push((index, index.generated_filter.get(), excludes));
+            if excludes {
+                index.generated_filter.set(GeneratedFilter::Exclude);
+            }
+        }
+        Ok(Self { indexes: scoped })
+    }
+
+    /// Whether the scope leaves anything out.
+    pub(crate) fn hides_any(&self) -> bool {
+        self.indexes.iter().any(|(_, _, excludes)| *excludes)
+    }
+
+    /// Run `f` with every database of the scope searching generated files
+    /// only, then put the exclusion back.
+    pub(crate) fn with_only_hidden<T>(&self, f: impl FnOnce() -> T) -> T {
+        for (index, _, _) in &self.indexes {
+            index.generated_filter.set(GeneratedFilter::Only);
+        }
+        let result = f();
+        for (index, previous, excludes) in &self.indexes {
+            index.generated_filter.set(if *excludes {
+                GeneratedFilter::Exclude
+            } else {
+                *previous
+            });
+        }
+        result
+    }
+}
+
+impl Drop for GeneratedScope<'_> {
+    fn drop(&mut self) {
+        for (index, previous, _) in &self.indexes {
+            index.generated_filter.set(*previous);
+        }
+    }
+}
+
+impl RepoIndex {
+    /// `AND` clause applying the current generated-file filter to rows whose
+    /// file is aliased `f`; empty when every file may be returned.
+    pub(crate) fn generated_filter(&self) -> &'static str {
+        match self.generated_filter.get() {
+            GeneratedFilter::All => "",
+            GeneratedFilter::Exclude => " AND f.generated = 0",
+            GeneratedFilter::Only => " AND f.generated = 1",
+        }
+    }
+
+    /// Whether any file in this database is flagged as generated.
+    pub(crate) fn has_generated_files(&self) -> crate::Result<bool> {
+        Ok(self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM files WHERE generated = 1)",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Flagged files in this database and their tokens.
+    pub(super) fn local_generated_counts(&self) -> crate::Result<(usize, usize)> {
+        let (files, tokens): (i64, i64) = self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(token_count), 0) FROM files WHERE generated = 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok((files.max(0) as usize, tokens.max(0) as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{QueryMode, QueryParams};
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+
+    fn detector() -> GeneratedDetector {
+        GeneratedDetector::new(&GeneratedConfig::default(), PathStyle::default()).unwrap()
+    }
+
+    /// `fetch_user` in a hand-written module, a marked one, and a minified bundle.
+    fn generated_repo() -> (FixtureRepo, RepoIndex) {
+        let minified = format!(
+            "function fetch_user(){{return 1}}{}\n",
+            "var a=[1,2,3].map(function(x){return x*2});".repeat(120)
+        );
+        let repo = FixtureRepoBuilder::new()
+            .file(
+                "src/user.rs",
+                "pub fn fetch_user() {}\nfn load() { fetch_user(); }\n",
+            )
+            .file(
+                "src/schema.rs",
+                "// @generated by schema-gen, do not edit by hand\npub fn fetch_user() {}\n",
+            )
+            .file("web/app.js", minified)
+            .build();
+
+        let mut index = RepoIndex::open_or_init(repo.path()).unwrap();
+        index.index("**/*").unwrap();
+        (repo, index)
+    }
+
+    fn paths(index: &RepoIndex, params: QueryParams) -> Vec<String> {
+        let result = index.query_params(params).unwrap();
+        let mut paths: Vec<String> = result.handles.into_iter().map(|h| h.file_path).collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    #[test]
+    fn markers_patterns_and_long_lines_flag_files() {
+        let detector = detector();
+        assert!(detector.is_generated("src/a.rs", "// Code generated by protoc. DO NOT EDIT.\n"));
+        assert!(detector.is_generated("proto/api_pb2.py", "import grpc\n"));
+        assert!(detector.is_generated("gen/api.pb.go", "package api\n"));
+        assert!(!detector.is_generated("src/a.rs", "fn main() {}\n"));
+        // A marker past the header is just a mention
+        let late = format!("{}// @generated\n", "fn f() {}\n".repeat(MARKER_LINES));
+        assert!(!detector.is_generated("src/a.rs", &late));
+        // Long lines only count in files big enough to be bundles
+        assert!(!detector.is_generated("a.json", &"x".repeat(1000)));
+        assert!(detector.is_generated("a.js", &"x".repeat(MIN_MINIFIED_BYTES)));
+
+        let config = GeneratedConfig {
+            markers: Vec::new(),
+            patterns: vec!["vendor/**".to_string()],
+            max_avg_line_length: 0,
+        };
+        let detector = GeneratedDetector::new(&config, PathStyle::default()).unwrap();
+        assert!(detector.is_generated("vendor/lib/x.rs", ""));
+        assert!(!detector.is_generated("src/a.rs", "// @generated\n"));
+        assert!(!detector.is_generated("a.js", &"x".repeat(MIN_MINIFIED_BYTES)));
+    }
+
+    #[test]
+    fn generated_files_are_counted_and_left_out_of_queries() {
+        let (_dir, index) = generated_repo();
+        let status = index.status().unwrap();
+        assert_eq!(status.files_generated, 2);
+        assert!(status.generated_tokens > 0 && status.generated_tokens < status.total_tokens);
+
+        let result = index
+            .query_params(QueryParams::pattern("fetch_user"))
+            .unwrap();
+        assert!(result.handles.iter().all(|h| h.file_path == "src/user.rs"));
+        assert_eq!(result.suppressed_generated, 2, "{result:?}");
+        assert_eq!(
+            paths(&index, QueryParams::symbol("fetch_user")),
+            vec!["src/user.rs"]
+        );
+        let count = index
+            .query_params(QueryParams::pattern("fetch_user").with_mode(QueryMode::Count))
+            .unwrap();
+        assert_eq!(count.total_matches, result.total_matches);
+
+        // The override takes them back and hides nothing
+        let all = index
+            .query_params(QueryParams::pattern("fetch_user").with_include_generated(true))
+            .unwrap();
+        assert_eq!(all.suppressed_generated, 0);
+        let mut all: Vec<_> = all.handles.into_iter().map(|h| h.file_path).collect();
+        all.sort();
+        all.dedup();
+        assert_eq!(all, vec!["src/schema.rs", "src/user.rs", "web/app.js"]);
+
+        // and the exclusion doesn't outlive the query that set it
+        assert_eq!(
+            paths(
+                &index,
+                QueryParams::symbol("fetch_user").with_include_generated(true)
+            ),
+            vec!["src/schema.rs", "src/user.rs", "web/app.js"]
+        );
+    }
+}
@@ -0,0 +1,368 @@
+//! Incremental node reindexing (`indexing.incremental_nodes`).
+//!
+//! A reparsed file is diffed against its stored nodes by (name, node type,
+//! content hash). A matched node keeps its row and its content/symbol FTS
+//! rows; if it only moved, its positional columns (and span-derived handle id)
+//! are updated in place. Stored nodes with no match are deleted along with
+//! their FTS rows, and parsed nodes with no match are inserted. Refs and
+//! annotations are plain rows keyed by span, so they are replaced wholesale.
+
+use super::pipeline::{FtsBytes, NodeRow};
+use super::symbol_cache::SymbolCacheDelta;
+use super::RepoIndex;
+use crate::document::ParsedFile;
+use crate::redaction::Redactor;
+use rusqlite::params;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+
+/// Identity of a node's content, independent of where it sits in the file.
+type NodeKey = (Option<String>, i32, Vec<u8>);
+
+/// The columns of a stored node that a move can change.
+struct StoredNode {
+    id: i64,
+    handle_id: String,
+    name_lower: Option<String>,
+    start_byte: i64,
+    end_byte: i64,
+    line_start: i64,
+    line_end: i64,
+    metadata: Option<String>,
+    parent_name: Option<String>,
+    parent_handle_id: Option<String>,
+    preview: Option<String>,
+    preview_tokens: i64,
+}
+
+impl StoredNode {
+    /// True when the stored row already matches `row` exactly.
+    fn is_current(&self, row: &NodeRow<'_>) -> bool {
+        self.handle_id == row.handle_id
+            && self.start_byte == row.span.start as i64
+            && self.end_byte == row.span.end as i64
+            && self.line_start == row.line_range.0 as i64
+            && self.line_end == row.line_range.1 as i64
+            && self.metadata.as_deref() == Some(row.metadata.as_str())
+            && self.parent_name.as_deref() == row.parent_name
+            && self.parent_handle_id == row.parent_handle_id
+            && self.preview.as_deref() == Some(row.preview.as_str())
+            && self.preview_tokens == row.preview_tokens as i64
+    }
+}
+
+impl RepoIndex {
+    /// Bring `file_id`'s nodes in line with `rows`, touching only what changed.
+    pub(super) fn reindex_nodes_in_tx(
+        tx: &rusqlite::Transaction<'_>,
+        file_id: i64,
+        relative_path: &str,
+        parsed: &ParsedFile,
+        rows: Vec<NodeRow<'_>>,
+        preview_bytes: usize,
+        redactor: Option<&Redactor>,
+    ) -> crate::Result<(SymbolCacheDelta, FtsBytes)> {
+        let mut stored = Self::load_stored_nodes(tx, file_id)?;
+        let matched: Vec<(NodeRow<'_>, Option<StoredNode>)> = rows
+            .into_iter()
+            .map(|row| {
+                let key = (
+                    row.name.clone(),
+                    row.node_type.as_int() as i32,
+                    row.content_hash.to_vec(),
+                );
+                let node = stored.get_mut(&key).and_then(VecDeque::pop_front);
+                (row, node)
+            })
+            .collect();
+
+        let mut delta = SymbolCacheDelta::default();
+
+        // Refs and annotations point at node spans; they are rebuilt below
+        tx.execute("DELETE FROM refs WHERE file_id = ?", params![file_id])?;
+        tx.execute(
+            "DELETE FROM annotations WHERE file_id = ?",
+            params![file_id],
+        )?;
+
+        for node in stored.into_values().flatten() {
+            tx.execute(
+                "DELETE FROM content_fts
+                 WHERE rowid IN (SELECT fts_rowid FROM fts_node_map WHERE node_id = ?1)",
+                params![node.id],
+            )?;
+            tx.execute(
+                "DELETE FROM symbol_fts
+                 WHERE rowid IN (SELECT fts_rowid FROM symbol_fts_map WHERE node_id = ?1)",
+                params![node.id],
+            )?;
+            tx.execute("DELETE FROM nodes WHERE id = ?", params![node.id])?;
+            if let Some(name_lower) = node.name_lower {
+                delta.removed.push((name_lower, node.handle_id));
+            }
+        }
+
+        // Handle ids are derived from spans, so a moved node can take over the
+        // id another moved node still holds. Park them before reassigning.
+        for (row, node) in &matched {
+            if let Some(node) = node {
+                if node.handle_id != row.handle_id {
+                    tx.execute(
+                        "UPDATE nodes SET handle_id = '~' || id WHERE id = ?",
+                        params![node.id],
+                    )?;
+                }
+            }
+        }
+
+        let mut node_spans: Vec<(Range<usize>, i64)> = Vec::with_capacity(matched.len());
+        let mut inserted = Vec::new();
+        for (row, node) in &matched {
+            let Some(node) = node else {
+                inserted.push(row);
+                continue;
+            };
+            node_spans.push((row.span.clone(), node.id));
+            if node.is_current(row) {
+                continue;
+            }
+            tx.execute(
+                "UPDATE nodes SET handle_id = ?, start_byte = ?, end_byte = ?,
+                                  line_start = ?, line_end = ?, metadata = ?,
+                                  parent_name = ?, parent_name_lower = ?,
+                                  parent_handle_id = ?, preview = ?, heading_path = ?,
+                                  preview_tokens = ?
+                 WHERE id = ?",
+                params![
+                    row.handle_id,
+                    row.span.start as i64,
+                    row.span.end as i64,
+                    row.line_range.0 as i64,
+                    row.line_range.1 as i64,
+                    row.metadata,
+                    row.parent_name,
+                    row.parent_name_lower,
+                    row.parent_handle_id,
+                    row.preview,
+                    row.heading_path,
+                    row.preview_tokens as i64,
+                    node.id
+                ],
+            )?;
+            if let Some(name_lower) = &node.name_lower {
+                delta
+                    .removed
+                    .push((name_lower.clone(), node.handle_id.clone()));
+            }
+            delta.added.extend(row.cache_entry(relative_path));
+        }
+
+        let mut fts = FtsBytes::default();
+        for row in inserted {
+            let node_id = Self::insert_node_in_tx(tx, file_id, row)?;
+            node_spans.push((row.span.clone(), node_id));
+            delta.added.extend(row.cache_entry(relative_path));
+            fts.add(row);
+        }
+
+        Self::insert_refs_in_tx(tx, file_id, parsed, &node_spans, preview_bytes, redactor)?;
+        Ok((delta, fts))
+    }
+
+    /// A file's stored nodes grouped by [`NodeKey`], each group in file order.
+    fn load_stored_nodes(
+        tx: &rusqlite::Transaction<'_>,
+        file_id: i64,
+    ) -> crate::Result<HashMap<NodeKey, VecDeque<StoredNode>>> {
+        let mut stmt = tx.prepare(
+            "SELECT id, handle_id, name, name_lower, node_type, content_hash,
+                    start_byte, end_byte, line_start, line_end, metadata,
+                    parent_name, parent_handle_id, preview, preview_tokens
+             FROM nodes WHERE file_id = ? ORDER BY start_byte, id",
+        )?;
+        let rows = stmt.query_map(params![file_id], |row| {
+            let key: NodeKey = (
+                row.get(2)?,
+                row.get(4)?,
+                row.get::<_, Option<Vec<u8>>>(5)?.unwrap_or_default(),
+            );
+            let node = StoredNode {
+                id: row.get(0)?,
+                handle_id: row.get(1)?,
+                name_lower: row.get(3)?,
+                start_byte: row.get(6)?,
+                end_byte: row.get(7)?,
+                line_start: row.get(8)?,
+                line_end: row.get(9)?,
+                metadata: row.get(10)?,
+                parent_name: row.get(11)?,
+                parent_handle_id: row.get(12)?,
+                preview: row.get(13)?,
+                preview_tokens: row.get(14)?,
+            };
+            Ok((key, node))
+        })?;
+
+        let mut stored: HashMap<NodeKey, VecDeque<StoredNode>> = HashMap::new();
+        for row in rows {
+            let (key, node) = row?;
+            stored.entry(key).or_default().push_back(node);
+        }
+        Ok(stored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{execute_query, parse_query};
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+
+    const SOURCE: &str =
+        "fn alpha() {\n    1\n}\n\nfn beta() {\n    2\n}\n\nfn gamma() {\n    3\n}\n";
+
+    fn repo_with(source: &str, incremental: bool) -> (FixtureRepo, RepoIndex) {
+        let repo = FixtureRepoBuilder::new().file("src/lib.rs", source).build();
+        RepoIndex::init(repo.path()).unwrap();
+        repo.write(
+            ".canopy/config.toml",
+            &format!("[indexing]\nincremental_nodes = {}\n", incremental),
+        );
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        (repo, index)
+    }
+
+    fn rewrite(repo: &FixtureRepo, index: &mut RepoIndex, source: &str) {
+        repo.write("src/lib.rs", source);
+        index.index("**/*.rs").unwrap();
+    }
+
+    fn max_fts_rowid(index: &RepoIndex) -> i64 {
+        index
+            .conn
+            .query_row("SELECT COALESCE(MAX(rowid), 0) FROM content_fts", [], |r| {
+                r.get(0)
+            })
+            .unwrap()
+    }
+
+    /// `(handle_id, node_type, start, end, line_start, line_end, preview, parent)`
+    type NodeSnapshot = (String, i32, i64, i64, i64, i64, String, Option<String>);
+
+    fn node_rows(index: &RepoIndex) -> Vec<NodeSnapshot> {
+        let mut stmt = index
+            .conn
+            .prepare(
+                "SELECT handle_id, node_type, start_byte, end_byte, line_start, line_end,
+                        preview, parent_handle_id
+                 FROM nodes ORDER BY start_byte, node_type",
+            )
+            .unwrap();
+        stmt.query_map([], |r| {
+            Ok((
+                r.get(0)?,
+                r.get(1)?,
+                r.get(2)?,
+                r.get(3)?,
+                r.get(4)?,
+                r.get(5)?,
+                r.get(6)?,
+                r.get(7)?,
+            ))
+        })
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+    }
+
+    fn node_ids(index: &RepoIndex) -> Vec<i64> {
+        let mut stmt = index
+            .conn
+            .prepare("SELECT id FROM nodes ORDER BY start_byte")
+            .unwrap();
+        stmt.query_map([], |r| r.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn whitespace_change_keeps_later_node_rows_and_fts() {
+        let (repo, mut index) = repo_with(SOURCE, true);
+        let ids_before = node_ids(&index);
+        let fts_before = max_fts_rowid(&index);
+
+        // A blank line at the top shifts every node without changing its content
+        let shifted = format!("\n{}", SOURCE);
+        rewrite(&repo, &mut index, &shifted);
+
+        assert_eq!(node_ids(&index), ids_before, "rows are kept in place");
+        assert_eq!(max_fts_rowid(&index), fts_before, "no FTS rows written");
+
+        // The stored rows match what a fresh full index of the new content holds
+        let (_fresh_repo, fresh) = repo_with(&shifted, false);
+        assert_eq!(node_rows(&index), node_rows(&fresh));
+
+        let result =
+            execute_query(&parse_query(r#"(code "gamma")"#).unwrap(), &index, None).unwrap();
+        assert_eq!(result.handles.len(), 1);
+        assert_eq!(result.handles[0].line_range, (10, 12));
+        let expanded = index.expand(&[result.handles[0].id.to_string()]).unwrap();
+        assert!(expanded[0].1.starts_with("fn gamma()"));
+    }
+
+    #[test]
+    fn editing_one_node_rewrites_only_that_node() {
+        let (repo, mut index) = repo_with(SOURCE, true);
+        let fts_before = max_fts_rowid(&index);
+
+        rewrite(
+            &repo,
+            &mut index,
+            &SOURCE.replace("    2\n", "    beta_edit\n"),
+        );
+        assert_eq!(max_fts_rowid(&index) - fts_before, 1);
+
+        let (full_repo, mut full) = repo_with(SOURCE, false);
+        let full_before = max_fts_rowid(&full);
+        rewrite(
+            &full_repo,
+            &mut full,
+            &SOURCE.replace("    2\n", "    beta_edit\n"),
+        );
+        assert_eq!(max_fts_rowid(&full) - full_before, 3);
+
+        // Old content is gone from FTS; the new content is searchable
+        let edited =
+            execute_query(&parse_query(r#"(grep "beta_edit")"#).unwrap(), &index, None).unwrap();
+        assert_eq!(edited.handles.len(), 1);
+        assert!(edited.handles[0].preview.contains("beta"));
+        let fts_rows: i64 = index
+            .conn
+            .query_row("SELECT COUNT(*) FROM content_fts", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(fts_rows, 3);
+    }
+
+    #[test]
+    fn removed_and_added_nodes_update_symbol_cache() {
+        let (repo, mut index) = repo_with(SOURCE, true);
+        assert!(index.symbol_cache.contains_key("beta"));
+
+        let edited = SOURCE.replace("fn beta() {\n    2\n}\n", "fn delta() {\n    4\n}\n");
+        rewrite(&repo, &mut index, &format!("// header\n{}", edited));
+
+        assert!(!index.symbol_cache.contains_key("beta"));
+        let delta = &index.symbol_cache["delta"];
+        assert_eq!(delta.len(), 1);
+        assert_eq!(delta[0].line_start, 6);
+
+        // Moved entries carry the new spans and ids
+        let gamma = &index.symbol_cache["gamma"][0];
+        assert_eq!(gamma.line_start, 10);
+        let (_fresh_repo, fresh) = repo_with(&format!("// header\n{}", edited), false);
+        assert_eq!(gamma.handle_id, fresh.symbol_cache["gamma"][0].handle_id);
+        assert_eq!(node_rows(&index), node_rows(&fresh));
+    }
+}
@@ -0,0 +1,140 @@
+-- Index database at schema v4, as written by canopy before in-place
+-- migrations existed. Covers the `src/lib.rs` and `README.md` fixtures in
+-- `migrations::tests`; content hashes match those files byte for byte.
+
+-- File metadata for cache invalidation
+CREATE TABLE IF NOT EXISTS files (
+    id INTEGER PRIMARY KEY,
+    path TEXT UNIQUE NOT NULL,
+    content_hash BLOB NOT NULL,
+    mtime INTEGER NOT NULL,
+    indexed_at INTEGER NOT NULL,
+    token_count INTEGER NOT NULL
+);
+
+-- Nodes (sections, code blocks, paragraphs, functions, etc.)
+CREATE TABLE IF NOT EXISTS nodes (
+    id INTEGER PRIMARY KEY,
+    file_id INTEGER REFERENCES files(id) ON DELETE CASCADE,
+    handle_id TEXT UNIQUE NOT NULL,
+    node_type INTEGER NOT NULL,
+    start_byte INTEGER NOT NULL,
+    end_byte INTEGER NOT NULL,
+    line_start INTEGER NOT NULL,
+    line_end INTEGER NOT NULL,
+    token_count INTEGER NOT NULL,
+    metadata TEXT,
+    -- NEW COLUMNS in v2:
+    name TEXT,
+    name_lower TEXT COLLATE NOCASE,
+    parent_name TEXT,
+    parent_name_lower TEXT COLLATE NOCASE,
+    parent_handle_id TEXT,
+    preview TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_nodes_file ON nodes(file_id);
+CREATE INDEX IF NOT EXISTS idx_nodes_handle ON nodes(handle_id);
+CREATE INDEX IF NOT EXISTS idx_nodes_type ON nodes(node_type);
+CREATE INDEX IF NOT EXISTS idx_nodes_name_lower ON nodes(name_lower);
+CREATE INDEX IF NOT EXISTS idx_nodes_parent_name_lower ON nodes(parent_name_lower);
+CREATE INDEX IF NOT EXISTS idx_nodes_parent_handle ON nodes(parent_handle_id);
+
+-- FTS5 index for text search. Underscores are token chars so
+-- identifiers stay whole; identifier_parts holds camel/snake sub-tokens.
+CREATE VIRTUAL TABLE IF NOT EXISTS content_fts USING fts5(
+    content,
+    identifier_parts,
+    tokenize = 'unicode61 tokenchars ''_'''
+);
+
+-- Mapping from FTS rowid to node
+CREATE TABLE IF NOT EXISTS fts_node_map (
+    fts_rowid INTEGER PRIMARY KEY,
+    node_id INTEGER REFERENCES nodes(id) ON DELETE CASCADE
+);
+
+-- References table (calls, imports, type refs)
+CREATE TABLE IF NOT EXISTS refs (
+    id INTEGER PRIMARY KEY,
+    file_id INTEGER REFERENCES files(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    name_lower TEXT COLLATE NOCASE,
+    qualifier TEXT,
+    ref_type TEXT NOT NULL,
+    source_node_id INTEGER REFERENCES nodes(id) ON DELETE CASCADE,
+    span_start INTEGER NOT NULL,
+    span_end INTEGER NOT NULL,
+    line_start INTEGER NOT NULL,
+    line_end INTEGER NOT NULL,
+    preview TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_refs_name_lower ON refs(name_lower);
+CREATE INDEX IF NOT EXISTS idx_refs_type ON refs(ref_type);
+CREATE INDEX IF NOT EXISTS idx_refs_source ON refs(source_node_id);
+CREATE INDEX IF NOT EXISTS idx_refs_file ON refs(file_id);
+
+-- Symbol FTS for fuzzy symbol search
+CREATE VIRTUAL TABLE IF NOT EXISTS symbol_fts USING fts5(
+    name,
+    name_parts,
+    tokenize = 'unicode61 tokenchars ''_'''
+);
+
+-- Mapping from symbol FTS rowid to node
+CREATE TABLE IF NOT EXISTS symbol_fts_map (
+    fts_rowid INTEGER PRIMARY KEY,
+    node_id INTEGER REFERENCES nodes(id) ON DELETE CASCADE
+);
+
+INSERT INTO files (id, path, content_hash, mtime, indexed_at, token_count) VALUES (1, 'src/lib.rs', X'f21bff9db8e803921dad997b19ea88b63087265834ce27bebf65e867d6382005', 1700000000, 1700000000, 40);
+INSERT INTO files (id, path, content_hash, mtime, indexed_at, token_count) VALUES (2, 'README.md', X'1bf9323ab0d2522ec323133a765cbf72c0a8987ede3cf347079d94202f2ee1b3', 1700000000, 1700000000, 14);
+
+INSERT INTO nodes (id, file_id, handle_id, node_type, start_byte, end_byte, line_start, line_end, token_count, metadata, name, name_lower, parent_name, parent_name_lower, parent_handle_id, preview) VALUES (1, 1, '22c2e00ccddca0bc09c67c1b', 3, 0, 54, 1, 3, 22, '{"name":"migrate_me","signature":"(a: i32)","type":"function"}', 'migrate_me', 'migrate_me', NULL, NULL, NULL, 'pub fn migrate_me(a: i32) -> i32 { helper(a) + 1 }');
+INSERT INTO nodes (id, file_id, handle_id, node_type, start_byte, end_byte, line_start, line_end, token_count, metadata, name, name_lower, parent_name, parent_name_lower, parent_handle_id, preview) VALUES (2, 1, '478ca9d7ea70c1af49e2f0bd', 3, 56, 94, 5, 7, 18, '{"name":"helper","signature":"(x: i32)","type":"function"}', 'helper', 'helper', NULL, NULL, NULL, 'fn helper(x: i32) -> i32 { x * 2 }');
+INSERT INTO nodes (id, file_id, handle_id, node_type, start_byte, end_byte, line_start, line_end, token_count, metadata, name, name_lower, parent_name, parent_name_lower, parent_handle_id, preview) VALUES (3, 2, '5e575a85dde635c8eaf741e4', 2, 9, 21, 3, 4, 3, '{"type":"paragraph"}', NULL, NULL, NULL, NULL, NULL, 'Intro text.');
+INSERT INTO nodes (id, file_id, handle_id, node_type, start_byte, end_byte, line_start, line_end, token_count, metadata, name, name_lower, parent_name, parent_name_lower, parent_handle_id, preview) VALUES (4, 2, 'fd013b4bae3754c0ae175091', 0, 0, 22, 1, 5, 6, '{"heading":"Guide","heading_path":"Guide","level":1,"type":"section"}', 'Guide', 'guide', NULL, NULL, NULL, '# Guide Intro text.');
+INSERT INTO nodes (id, file_id, handle_id, node_type, start_byte, end_byte, line_start, line_end, token_count, metadata, name, name_lower, parent_name, parent_name_lower, parent_handle_id, preview) VALUES (5, 2, '51e18e6d1e390023ffc37d83', 2, 32, 58, 7, 8, 5, '{"type":"paragraph"}', NULL, NULL, NULL, NULL, NULL, 'Run the legacy installer.');
+INSERT INTO nodes (id, file_id, handle_id, node_type, start_byte, end_byte, line_start, line_end, token_count, metadata, name, name_lower, parent_name, parent_name_lower, parent_handle_id, preview) VALUES (6, 2, '2ea8bb43e5edd86e9b24d186', 0, 22, 58, 5, 8, 8, '{"heading":"Setup","heading_path":"Guide > Setup","level":2,"type":"section"}', 'Setup', 'setup', NULL, NULL, NULL, '[Guide > Setup] ## Setup Run the legacy installer.');
+
+INSERT INTO content_fts (rowid, content, identifier_parts) VALUES (1, 'pub fn migrate_me(a: i32) -> i32 {
+    helper(a) + 1
+}', 'migrate me');
+INSERT INTO content_fts (rowid, content, identifier_parts) VALUES (2, 'fn helper(x: i32) -> i32 {
+    x * 2
+}', '');
+INSERT INTO content_fts (rowid, content, identifier_parts) VALUES (3, 'Intro text.
+', '');
+INSERT INTO content_fts (rowid, content, identifier_parts) VALUES (4, '# Guide
+
+Intro text.
+
+', '');
+INSERT INTO content_fts (rowid, content, identifier_parts) VALUES (5, 'Run the legacy installer.
+', '');
+INSERT INTO content_fts (rowid, content, identifier_parts) VALUES (6, '## Setup
+
+Run the legacy installer.
+', '');
+
+INSERT INTO fts_node_map (fts_rowid, node_id) VALUES (1, 1);
+INSERT INTO fts_node_map (fts_rowid, node_id) VALUES (2, 2);
+INSERT INTO fts_node_map (fts_rowid, node_id) VALUES (3, 3);
+INSERT INTO fts_node_map (fts_rowid, node_id) VALUES (4, 4);
+INSERT INTO fts_node_map (fts_rowid, node_id) VALUES (5, 5);
+INSERT INTO fts_node_map (fts_rowid, node_id) VALUES (6, 6);
+
+INSERT INTO refs (id, file_id, name, name_lower, qualifier, ref_type, source_node_id, span_start, span_end, line_start, line_end, preview) VALUES (1, 1, 'helper', 'helper', NULL, 'call', 1, 39, 48, 2, 2, 'helper(a) + 1');
+
+INSERT INTO symbol_fts (rowid, name, name_parts) VALUES (1, 'migrate_me', 'migrate me');
+INSERT INTO symbol_fts (rowid, name, name_parts) VALUES (2, 'helper', '');
+INSERT INTO symbol_fts (rowid, name, name_parts) VALUES (3, 'Guide', '');
+INSERT INTO symbol_fts (rowid, name, name_parts) VALUES (4, 'Setup', '');
+
+INSERT INTO symbol_fts_map (fts_rowid, node_id) VALUES (1, 1);
+INSERT INTO symbol_fts_map (fts_rowid, node_id) VALUES (2, 2);
+INSERT INTO symbol_fts_map (fts_rowid, node_id) VALUES (3, 4);
+INSERT INTO symbol_fts_map (fts_rowid, node_id) VALUES (4, 6);
+
+PRAGMA user_version = 4;
@@ -0,0 +1,659 @@
+//! In-place upgrades of older index databases.
+//!
+//! Each [`Migration`] takes the schema from one version to the next. When
+//! [`RepoIndex::open`](super::RepoIndex::open) finds an older `user_version`,
+//! every step up to [`SCHEMA_VERSION`] runs in a single transaction, so a
+//! failure leaves the database as it was. Steps that add derived data the old
+//! rows never had (annotations, heading paths) mark the affected files in
+//! `reparse_pending`; the old rows keep answering queries meanwhile and the
+//! next `canopy index` reparses those files, whatever their mtime and hash
+//! say. Databases older than [`MIN_MIGRATABLE_VERSION`] predate a breaking
+//! change and still need a reindex.
+
+use crate::document::NodeType;
+use crate::error::CanopyError;
+use rusqlite::{params, Connection, Transaction, TransactionBehavior};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::expand::time_ago;
+use super::moves::HANDLE_ALIASES_TABLE;
+use super::search::dir_prefix;
+use super::{RepoIndex, SCHEMA_VERSION};
+
+/// Oldest schema that can be upgraded in place.
+const MIN_MIGRATABLE_VERSION: i32 = 4;
+
+/// Why databases older than [`MIN_MIGRATABLE_VERSION`] can't be migrated.
+const PRE_V4_REASON: &str = "v4 changed the FTS tokenizer to keep identifiers whole, \
+     so the full-text index has to be rebuilt from source";
+
+/// Applied-migration log, and files a migration left for the next indexing
+/// run to reparse. Created on every open so databases from before migrations
+/// existed get them too.
+pub(super) const MIGRATION_TABLES: &str = "
+    CREATE TABLE IF NOT EXISTS schema_migrations (
+        version INTEGER PRIMARY KEY,
+        description TEXT NOT NULL,
+        applied_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS reparse_pending (
+        file_id INTEGER PRIMARY KEY REFERENCES files(id) ON DELETE CASCADE
+    );
+";
+
+/// One schema step, `to - 1` → `to`.
+struct Migration {
+    to: i32,
+    description: &'static str,
+    apply: fn(&Transaction<'_>) -> crate::Result<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        to: 5,
+        description: "files.dir_prefix for glob prefiltering",
+        apply: add_dir_prefix,
+    },
+    Migration {
+        to: 6,
+        description: "files.path_bytes for non-UTF-8 paths",
+        apply: |tx| Ok(tx.execute_batch("ALTER TABLE files ADD COLUMN path_bytes BLOB;")?),
+    },
+    Migration {
+        to: 7,
+        description: "annotations table (reparses every file)",
+        apply: add_annotations,
+    },
+    Migration {
+        to: 8,
+        description: "nodes.content_hash for incremental node reindex",
+        apply: |tx| Ok(tx.execute_batch("ALTER TABLE nodes ADD COLUMN content_hash BLOB;")?),
+    },
+    Migration {
+        to: 9,
+        description: "parse_warnings table",
+        apply: |tx| {
+            Ok(tx.execute_batch(
+                "CREATE TABLE IF NOT EXISTS parse_warnings (
+                    file_id INTEGER PRIMARY KEY REFERENCES files(id) ON DELETE CASCADE,
+                    reason TEXT NOT NULL
+                );",
+            )?)
+        },
+    },
+    Migration {
+        to: 10,
+        description: "nodes.heading_path (reparses files with sections)",
+        apply: add_heading_path,
+    },
+    Migration {
+        to: 11,
+        description: "files.line_count and files.byte_len for whole-file handles",
+        apply: |tx| {
+            Ok(tx.execute_batch(
+                "ALTER TABLE files ADD COLUMN line_count INTEGER NOT NULL DEFAULT 0;
+                 ALTER TABLE files ADD COLUMN byte_len INTEGER NOT NULL DEFAULT 0;",
+            )?)
+        },
+    },
+    Migration {
+        to: 12,
+        description: "files.commit_time for recency filters",
+        apply: |tx| Ok(tx.execute_batch("ALTER TABLE files ADD COLUMN commit_time INTEGER;")?),
+    },
+    Migration {
+        to: 13,
+        description: "nodes.preview_tokens (reparses every file)",
+        apply: add_preview_tokens,
+    },
+    Migration {
+        to: 14,
+        description: "files.generated for generated-file exclusion (reparses every file)",
+        apply: add_generated,
+    },
+    Migration {
+        to: 15,
+        description: "files.reindex_count and files.churn_since for churn warnings",
+        apply: |tx| {
+            Ok(tx.execute_batch(
+                "ALTER TABLE files ADD COLUMN reindex_count INTEGER NOT NULL DEFAULT 0;
+                 ALTER TABLE files ADD COLUMN churn_since INTEGER;",
+            )?)
+        },
+    },
+    Migration {
+        to: 16,
+        description: "nodes.fts_truncated for sampled full-text content",
+        apply: |tx| {
+            Ok(tx.execute_batch(
+                "ALTER TABLE nodes ADD COLUMN fts_truncated INTEGER NOT NULL DEFAULT 0;",
+            )?)
+        },
+    },
+    Migration {
+        to: 17,
+        description: "handle_aliases table for moved files",
+        apply: |tx| Ok(tx.execute_batch(HANDLE_ALIASES_TABLE)?),
+    },
+];
+
+/// A migration recorded in `schema_migrations`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedMigration {
+    /// Schema version the migration upgraded to
+    pub version: i32,
+    pub description: String,
+    /// Seconds since the UNIX epoch
+    pub applied_at: i64,
+}
+
+impl AppliedMigration {
+    /// `applied_at` relative to now, e.g. "3 days ago"
+    pub fn applied_ago(&self) -> String {
+        time_ago(self.applied_at)
+    }
+}
+
+/// `meta` key naming the binary that last set the schema, e.g. "canopy 0.5.0"
+const WRITER_META_KEY: &str = "schema_writer";
+
+/// This binary, as recorded under [`WRITER_META_KEY`].
+fn this_binary() -> String {
+    format!("canopy {}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Record this binary as the one that set the database's schema, so a binary
+/// of another schema can say who to ask. Called after creating or migrating.
+pub(super) fn record_writer(conn: &Connection) -> crate::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES (?, ?)",
+        params![WRITER_META_KEY, this_binary()],
+    )?;
+    Ok(())
+}
+
+/// The binary that last set the schema, if it recorded itself; databases
+/// from before the record (or without a `meta` table) have none.
+pub(super) fn writer(conn: &Connection) -> Option<String> {
+    conn.query_row(
+        "SELECT value FROM meta WHERE key = ?",
+        params![WRITER_META_KEY],
+        |row| row.get(0),
+    )
+    .ok()
+}
+
+/// The error for a database at `found`, written by `written_by`, that can't
+/// be brought to [`SCHEMA_VERSION`], or `None` if a migration path exists.
+pub(super) fn unmigratable(found: i32, written_by: Option<String>) -> Option<CanopyError> {
+    let (reason, hint) = if found > SCHEMA_VERSION {
+        let hint = format!(
+            "index was written by {} (schema {found}); this binary ({}) supports schema \
+             {SCHEMA_VERSION} — upgrade this binary or run `canopy invalidate && canopy index` \
+             with the newer one",
+            written_by.as_deref().unwrap_or("a newer canopy"),
+            this_binary(),
+        );
+        ("the database was written by a newer canopy", hint)
+    } else if found < MIN_MIGRATABLE_VERSION {
+        let hint = "Delete .canopy/index.db and run 'canopy index' to reindex.".to_string();
+        (PRE_V4_REASON, hint)
+    } else {
+        return None;
+    };
+    Some(CanopyError::SchemaVersionMismatch {
+        found,
+        expected: SCHEMA_VERSION,
+        reason: reason.to_string(),
+        written_by,
+        hint,
+    })
+}
+
+/// A database at a newer schema, open read-only.
+pub(crate) struct NewerSchema {
+    pub(crate) found: i32,
+    pub(crate) written_by: Option<String>,
+}
+
+impl NewerSchema {
+    /// The error writing to it gets.
+    pub(crate) fn mismatch(&self) -> CanopyError {
+        unmigratable(self.found, self.written_by.clone())
+            .expect("newer schemas are never migratable")
+    }
+}
+
+/// Whether a database at a newer schema still has every table and column
+/// this binary's schema does, so its reads work unchanged. Newer schemas
+/// usually only add columns; one that dropped or renamed any is unreadable.
+pub(super) fn newer_schema_readable(conn: &Connection) -> crate::Result<bool> {
+    let current = Connection::open_in_memory()?;
+    RepoIndex::create_schema(&current)?;
+    let ours = table_columns(&current)?;
+    let theirs = table_columns(conn)?;
+    Ok(ours.iter().all(|(table, columns)| {
+        theirs
+            .get(table)
+            .is_some_and(|found| columns.is_subset(found))
+    }))
+}
+
+/// Columns of every table in `conn`, by table name.
+fn table_columns(conn: &Connection) -> crate::Result<BTreeMap<String, BTreeSet<String>>> {
+    let mut stmt = conn.prepare(
+        "SELECT m.name, p.name FROM sqlite_master m, pragma_table_info(m.name) p
+         WHERE m.type = 'table'",
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    let mut tables: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for row in rows {
+        let (table, column) = row?;
+        tables.entry(table).or_default().insert(column);
+    }
+    Ok(tables)
+}
+
+/// Upgrade a database at schema `found` to [`SCHEMA_VERSION`].
+pub(super) fn migrate(conn: &Connection, found: i32) -> crate::Result<()> {
+    if let Some(err) = unmigratable(found, writer(conn)) {
+        return Err(err);
+    }
+
+    // IMMEDIATE takes the write lock up front; a concurrent opener waits on
+    // busy_timeout and then sees the upgraded version
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let version: i32 = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+    if let Some(err) = unmigratable(version, writer(&tx)) {
+        return Err(err);
+    }
+
+    tx.execute_batch(MIGRATION_TABLES)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    for migration in MIGRATIONS.iter().filter(|m| m.to > version) {
+        (migration.apply)(&tx)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO schema_migrations (version, description, applied_at)
+             VALUES (?, ?, ?)",
+            params![migration.to, migration.description, now],
+        )?;
+    }
+    tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Make the next indexing run reparse the files matched by `filter` (a
+/// `WHERE` clause over `files`), whatever their mtime and hash say.
+fn mark_for_reparse(tx: &Transaction<'_>, filter: &str) -> crate::Result<()> {
+    tx.execute(
+        &format!(
+            "INSERT OR IGNORE INTO reparse_pending (file_id) SELECT id FROM files WHERE {filter}"
+        ),
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_dir_prefix(tx: &Transaction<'_>) -> crate::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE files ADD COLUMN dir_prefix TEXT NOT NULL DEFAULT '';
+         CREATE INDEX IF NOT EXISTS idx_files_dir_prefix ON files(dir_prefix);",
+    )?;
+    let paths: Vec<(i64, String)> = {
+        let mut stmt = tx.prepare("SELECT id, path FROM files")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<Result<_, _>>()?
+    };
+    let mut update = tx.prepare("UPDATE files SET dir_prefix = ? WHERE id = ?")?;
+    for (id, path) in paths {
+        update.execute(params![dir_prefix(&path), id])?;
+    }
+    Ok(())
+}
+
+fn add_annotations(tx: &Transaction<'_>) -> crate::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS annotations (
+            id INTEGER PRIMARY KEY,
+            file_id INTEGER REFERENCES files(id) ON DELETE CASCADE,
+            line INTEGER NOT NULL,
+            marker TEXT NOT NULL,
+            text TEXT NOT NULL,
+            node_id INTEGER REFERENCES nodes(id) ON DELETE SET NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_annotations_file ON annotations(file_id);
+        CREATE INDEX IF NOT EXISTS idx_annotations_marker ON annotations(marker);",
+    )?;
+    // Any file may hold marker comments
+    mark_for_reparse(tx, "1")
+}
+
+fn add_heading_path(tx: &Transaction<'_>) -> crate::Result<()> {
+    tx.execute_batch("ALTER TABLE nodes ADD COLUMN heading_path TEXT;")?;
+    mark_for_reparse(
+        tx,
+        &format!(
+            "id IN (SELECT file_id FROM nodes WHERE node_type = {})",
+            NodeType::Section.as_int()
+        ),
+    )
+}
+
+fn add_preview_tokens(tx: &Transaction<'_>) -> crate::Result<()> {
+    tx.execute_batch("ALTER TABLE nodes ADD COLUMN preview_tokens INTEGER NOT NULL DEFAULT 0;")?;
+    // Stored previews predate whitespace normalization too
+    mark_for_reparse(tx, "1")
+}
+
+fn add_generated(tx: &Transaction<'_>) -> crate::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE files ADD COLUMN generated INTEGER NOT NULL DEFAULT 0;
+         CREATE INDEX IF NOT EXISTS idx_files_generated ON files(generated) WHERE generated = 1;",
+    )?;
+    // Detection reads the file, so any file may turn out generated
+    mark_for_reparse(tx, "1")
+}
+
+impl RepoIndex {
+    /// Migrations applied to this database, oldest first. Empty for databases
+    /// created at the current schema.
+    pub fn applied_migrations(&self) -> crate::Result<Vec<AppliedMigration>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT version, description, applied_at FROM schema_migrations ORDER BY version",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(AppliedMigration {
+                version: row.get(0)?,
+                description: row.get(1)?,
+                applied_at: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::FileQueryOptions;
+    use crate::query::parse_query;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::path::Path;
+
+    /// Written by canopy v4; see the header of the snapshot.
+    const V4_SNAPSHOT: &str = include_str!("index_v4.sql");
+    const LIB_RS: &str =
+        "pub fn migrate_me(a: i32) -> i32 {\n    helper(a) + 1\n}\n\nfn helper(x: i32) -> i32 {\n    x * 2\n}\n";
+    const README_MD: &str = "# Guide\n\nIntro text.\n\n## Setup\n\nRun the legacy installer.\n";
+
+    /// A repo whose `.canopy/index.db` is the v4 snapshot of its files.
+    fn v4_repo() -> tempfile::TempDir {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::create_dir_all(dir.path().join(".canopy")).unwrap();
+        fs::write(dir.path().join("src/lib.rs"), LIB_RS).unwrap();
+        fs::write(dir.path().join("README.md"), README_MD).unwrap();
+        let conn = Connection::open(dir.path().join(".canopy/index.db")).unwrap();
+        conn.execute_batch(V4_SNAPSHOT).unwrap();
+        dir
+    }
+
+    fn user_version(dir: &Path) -> i32 {
+        Connection::open(dir.join(".canopy/index.db"))
+            .unwrap()
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    /// Column names and declared types per table, plus index names.
+    fn schema_shape(conn: &Connection) -> BTreeMap<String, Vec<(String, String)>> {
+        let mut stmt = conn
+            .prepare("SELECT type, name FROM sqlite_master WHERE name NOT LIKE 'sqlite_%'")
+            .unwrap();
+        let objects: Vec<(String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let mut shape = BTreeMap::new();
+        for (kind, name) in objects {
+            let columns = if kind == "table" {
+                let mut info = conn
+                    .prepare(&format!(
+                        "SELECT name, type FROM pragma_table_info('{name}')"
+                    ))
+                    .unwrap();
+                let mut columns: Vec<(String, String)> = info
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                    .unwrap()
+                    .collect::<Result<_, _>>()
+                    .unwrap();
+                columns.sort();
+                columns
+            } else {
+                Vec::new()
+            };
+            shape.insert(format!("{kind} {name}"), columns);
+        }
+        shape
+    }
+
+    #[test]
+    fn v4_snapshot_migrates_and_keeps_data() {
+        let dir = v4_repo();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        assert_eq!(user_version(dir.path()), SCHEMA_VERSION);
+
+        let applied = index.applied_migrations().unwrap();
+        let versions: Vec<i32> = applied.iter().map(|m| m.version).collect();
+        assert_eq!(versions, (5..=SCHEMA_VERSION).collect::<Vec<_>>());
+        assert!(applied.iter().all(|m| m.applied_at > 0));
+
+        // Rows written by v4 still answer queries, before any reindex
+        let symbols = index.search_code("migrate_me", 10).unwrap();
+        assert_eq!(symbols.len(), 1);
+        let migrate_me = symbols[0].clone();
+        assert_eq!(migrate_me.preview_tokens, 0, "not stored before v13");
+        assert!(!index.fts_search("installer", 10).unwrap().is_empty());
+        assert_eq!(
+            index.search_in_files("src/**", "helper", 10).unwrap().len(),
+            2,
+            "dir_prefix was backfilled for glob-prefiltered search"
+        );
+        let expanded = index.expand(&[migrate_me.id.to_string()]).unwrap();
+        assert!(expanded[0].1.contains("helper(a) + 1"));
+        let files = index
+            .get_file("**", &FileQueryOptions::new().with_skip_preview(true))
+            .unwrap();
+        assert_eq!(files.handles.len(), 2);
+        assert!(files.handles.iter().all(|h| h.line_range.1 > 1));
+
+        // The next index run reparses the files the migrations marked and
+        // fills in data v4 never stored; handle ids are unchanged
+        let stats = index.index("**/*.{rs,md}").unwrap();
+        assert_eq!(stats.files_indexed, 2);
+        let reparsed = index.search_code("migrate_me", 10).unwrap();
+        assert_eq!(reparsed[0].id, migrate_me.id);
+        assert!(reparsed[0].preview_tokens > 0);
+        let sections = crate::query::execute_query(
+            &parse_query(r#"(section-path "Guide/Setup")"#).unwrap(),
+            &index,
+            None,
+        )
+        .unwrap();
+        assert_eq!(sections.handles.len(), 1);
+        assert_eq!(index.index("**/*.{rs,md}").unwrap().files_indexed, 0);
+
+        // Reopening doesn't migrate again
+        drop(index);
+        let index = RepoIndex::open(dir.path()).unwrap();
+        assert_eq!(index.applied_migrations().unwrap().len(), applied.len());
+    }
+
+    #[test]
+    fn migrated_schema_matches_fresh_schema() {
+        let dir = v4_repo();
+        RepoIndex::open(dir.path()).unwrap();
+        let migrated = Connection::open(dir.path().join(".canopy/index.db")).unwrap();
+
+        let fresh_dir = tempfile::TempDir::new().unwrap();
+        RepoIndex::init(fresh_dir.path()).unwrap();
+        let fresh = Connection::open(fresh_dir.path().join(".canopy/index.db")).unwrap();
+
+        assert_eq!(schema_shape(&migrated), schema_shape(&fresh));
+    }
+
+    #[test]
+    fn unmigratable_versions_explain_the_reindex() {
+        // An unreadable newer schema: one of the columns this binary reads is gone
+        let newer = "ALTER TABLE nodes DROP COLUMN preview_tokens;";
+        for (version, reason, hint) in [
+            (3, "FTS tokenizer", "reindex"),
+            (SCHEMA_VERSION + 1, "newer canopy", "upgrade this binary"),
+        ] {
+            let dir = crate::index::test_helpers::setup_repo(1);
+            let conn = Connection::open(dir.path().join(".canopy/index.db")).unwrap();
+            if version > SCHEMA_VERSION {
+                conn.execute_batch(newer).unwrap();
+            }
+            conn.pragma_update(None, "user_version", version).unwrap();
+
+            match RepoIndex::open(dir.path()) {
+                Err(err @ CanopyError::SchemaVersionMismatch { .. }) => {
+                    let message = err.to_string();
+                    assert!(message.contains(reason), "{message}");
+                    assert!(message.contains(hint), "{message}");
+                }
+                Err(other) => panic!("v{version}: expected schema mismatch, got {other}"),
+                Ok(_) => panic!("v{version}: expected schema mismatch, got an open index"),
+            }
+            assert_eq!(user_version(dir.path()), version, "left untouched");
+        }
+    }
+
+    #[test]
+    fn failed_migration_rolls_back() {
+        let dir = v4_repo();
+        // A column the v11 step is about to add makes it fail midway
+        Connection::open(dir.path().join(".canopy/index.db"))
+            .unwrap()
+            .execute_batch("ALTER TABLE files ADD COLUMN byte_len INTEGER;")
+            .unwrap();
+
+        assert!(RepoIndex::open(dir.path()).is_err());
+        let conn = Connection::open(dir.path().join(".canopy/index.db")).unwrap();
+        assert_eq!(user_version(dir.path()), 4);
+        let has_dir_prefix: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('files') WHERE name = 'dir_prefix'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(has_dir_prefix, 0, "earlier steps rolled back too");
+    }
+
+    /// An indexed repo whose database "canopy 9.9.0" took to the next schema
+    /// after running `sql`.
+    fn newer_repo(sql: &str) -> canopy_testutil::FixtureRepo {
+        let dir = crate::index::test_helpers::setup_repo(2);
+        RepoIndex::open(dir.path())
+            .unwrap()
+            .index("**/*.rs")
+            .unwrap();
+        let conn = Connection::open(dir.path().join(".canopy/index.db")).unwrap();
+        conn.execute_batch(sql).unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES (?, 'canopy 9.9.0')",
+            params![WRITER_META_KEY],
+        )
+        .unwrap();
+        dir
+    }
+
+    #[test]
+    fn newer_schema_with_extra_columns_is_read_in_compat_mode() {
+        let dir = newer_repo(
+            "ALTER TABLE nodes ADD COLUMN future_score REAL;
+             CREATE TABLE future_table (id INTEGER PRIMARY KEY);",
+        );
+        RepoIndex::probe(dir.path()).unwrap();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        assert!(index.compat_mode());
+
+        let result = index
+            .query_params(crate::QueryParams::symbol("func_1"))
+            .unwrap();
+        assert!(result.compat_mode);
+        assert_eq!(result.handles.len(), 1);
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["compat_mode"], true);
+
+        // Writes would go through a schema this binary doesn't know
+        match index.index("**/*.rs") {
+            Err(CanopyError::SchemaVersionMismatch {
+                found,
+                written_by,
+                hint,
+                ..
+            }) => {
+                assert_eq!(found, SCHEMA_VERSION + 1);
+                assert_eq!(written_by.as_deref(), Some("canopy 9.9.0"));
+                assert!(hint.contains("canopy 9.9.0"), "{hint}");
+                assert!(hint.contains(&format!("supports schema {SCHEMA_VERSION}")));
+                assert!(hint.contains("canopy invalidate && canopy index"));
+            }
+            other => panic!("expected schema mismatch, got {other:?}"),
+        }
+        assert!(index.invalidate(None).is_err());
+        assert_eq!(
+            user_version(dir.path()),
+            SCHEMA_VERSION + 1,
+            "left untouched"
+        );
+    }
+
+    #[test]
+    fn newer_schema_missing_a_column_names_its_writer() {
+        let dir = newer_repo("ALTER TABLE nodes DROP COLUMN preview_tokens;");
+        assert!(RepoIndex::probe(dir.path()).is_err());
+        match RepoIndex::open(dir.path()) {
+            Err(err @ CanopyError::SchemaVersionMismatch { .. }) => {
+                let message = err.to_string();
+                assert!(
+                    message.contains("index was written by canopy 9.9.0"),
+                    "{message}"
+                );
+                assert!(message.contains("upgrade this binary"), "{message}");
+            }
+            Err(other) => panic!("expected schema mismatch, got {other}"),
+            Ok(_) => panic!("expected schema mismatch, got an open index"),
+        }
+    }
+
+    #[test]
+    fn created_and_migrated_databases_record_their_writer() {
+        let dir = v4_repo();
+        let conn = Connection::open(dir.path().join(".canopy/index.db")).unwrap();
+        assert_eq!(writer(&conn), None);
+        RepoIndex::open(dir.path()).unwrap();
+        assert_eq!(writer(&conn), Some(this_binary()));
+
+        let fresh = crate::index::test_helpers::setup_repo(1);
+        RepoIndex::open(fresh.path()).unwrap();
+        let conn = Connection::open(fresh.path().join(".canopy/index.db")).unwrap();
+        assert_eq!(writer(&conn), Some(this_binary()));
+    }
+}
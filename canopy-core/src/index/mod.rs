@@ -1,49 +1,197 @@
 //! Repository index with SQLite FTS5
 
+mod annotations;
+mod auto_init;
+mod churn;
+mod copy;
+pub(crate) mod count;
+mod delta;
+mod dir_status;
 mod expand;
 mod file_discovery;
+mod file_references;
+pub(crate) mod files;
+mod freshness;
+mod generated;
+mod history;
+mod incremental;
+mod migrations;
+mod moves;
+mod node_stats;
+mod path_scope;
+mod paths;
 mod pipeline;
+mod plan;
+mod recency;
+mod related;
+mod scan_stats;
 pub(crate) mod search;
+pub(crate) mod sharding;
+mod statements;
+mod suggest;
+mod summary;
 pub(crate) mod symbol_cache;
+mod symbols;
 #[cfg(test)]
-mod test_helpers;
-
-pub use file_discovery::FileDiscovery;
+pub(crate) mod test_helpers;
+pub(crate) mod tokens;
+mod validate;
+mod warmup;
+
+pub use auto_init::{AutoInit, AutoInitReport, AUTO_INIT_ENV, AUTO_INIT_MAX_ENTRIES};
+pub use churn::{ChurningFile, CHURNING_FILES, CHURN_WARN_REINDEXES, CHURN_WINDOW_SECS};
+pub use delta::{
+    DeltaAnchor, DeltaSymbol, RenamedSymbol, SnapshotFile, SnapshotSymbol, SymbolDelta,
+    SymbolSnapshot,
+};
+pub use dir_status::{DirTokens, ScopedStatus, TokenTree};
+pub use file_discovery::{FileDiscovery, WalkFilter, FILE_DISCOVERY_ENV};
+pub use file_references::{
+    FileReferences, ReferenceFile, DEFAULT_REFERENCE_FILES_LIMIT, MAX_GROUPED_REFERENCES,
+};
+pub use files::{FilePage, FileQueryOptions};
+pub use freshness::{SkipCounts, SkipReason};
+pub(crate) use generated::GeneratedScope;
+pub use history::{show_commit, CommitDiff, CommitEntry};
+pub use migrations::AppliedMigration;
+use migrations::NewerSchema;
+pub use moves::{FileMove, MoveFixupReport};
+pub use node_stats::{LargeNode, NodeBreakdown, NodeTypeStats, LARGEST_NODES};
+pub(crate) use path_scope::PathScope;
+pub use paths::{PathSet, PathStyle};
+pub use plan::{IndexPlan, PlannedSkip};
+pub(crate) use recency::RecentScope;
+pub use related::{RelatedFile, RelatedFiles, SharedSymbol, DEFAULT_RELATED_LIMIT};
+pub use scan_stats::{FtsScan, FtsScanStats, ROWS_EXAMINED_BUCKETS};
+pub use sharding::ReshardStats;
+pub(crate) use suggest::sort_suggestions;
+pub use suggest::{SymbolSuggestion, MAX_SYMBOL_SUGGESTIONS};
+pub use summary::{
+    DirectorySummary, FileSummary, LanguageSummary, RepoSummary, DEFAULT_SUMMARY_TOKENS,
+};
+pub use symbol_cache::SymbolCacheStats;
+pub use symbols::{SymbolEntry, SymbolPage, DEFAULT_SYMBOL_LIMIT};
+pub use validate::{HandleStatus, HandleValidation};
+pub use warmup::WarmupReport;
 
-use crate::config::{default_config_toml, Config};
+use crate::config::{Config, Preset};
 use crate::document::NodeType;
 use crate::error::CanopyError;
-use crate::handle::generate_preview;
+use crate::handle::{generate_preview, Handle};
 use crate::query::{
-    execute_query_with_options, parse_query, QueryOptions, QueryParams, QueryResult,
+    execute_params, execute_query_with_options, parse_query, QueryOptions, QueryParams, QueryResult,
 };
+use crate::redaction::Redactor;
+use crate::schema::SchemaVersion;
 use rusqlite::Connection;
 use serde::Serialize;
-use std::collections::{HashMap, HashSet};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use symbol_cache::SymbolCacheEntry;
+use sharding::ShardRouter;
+use summary::CachedSummary;
+use symbol_cache::SymbolCache;
 
-const SCHEMA_VERSION: i32 = 3;
+const SCHEMA_VERSION: i32 = 17;
 
 /// Statistics from an indexing operation
 #[derive(Debug, Serialize)]
 pub struct IndexStats {
+    pub schema_version: SchemaVersion,
     pub files_indexed: usize,
+    /// Of `files_indexed`, files that failed to parse and were stored as plain chunks
+    pub files_degraded: usize,
+    /// Of `files_indexed`, files flagged as generated
+    pub files_generated: usize,
+    /// Tokens in `files_generated`
+    pub generated_tokens: usize,
     pub files_skipped: usize,
+    /// `files_skipped` broken down by skip reason
+    pub skipped: SkipCounts,
     pub total_tokens: usize,
     pub index_size_bytes: u64,
+    /// Listed paths whose files were gone, so their rows were dropped
+    /// ([`RepoIndex::index_paths`] only)
+    pub files_removed: usize,
+    /// Listed paths that couldn't be indexed ([`RepoIndex::index_paths`] only)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<IndexPathError>,
+    /// Whether the run merged the FTS segments, after enough reparsed tokens
+    /// (`[fts] optimize_after_tokens`)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub fts_optimized: bool,
+    /// Node content bytes written to the full-text index
+    pub fts_bytes_written: u64,
+    /// Node content bytes left out of the full-text index by sampling
+    /// (`[indexing] max_fts_bytes_per_node`)
+    pub fts_bytes_skipped: u64,
+    /// Commits added to the git-history layer (`[history] enabled`, or
+    /// [`RepoIndex::index_history`])
+    pub commits_indexed: usize,
+    /// Files git recorded as moved since the last run, whose old rows were
+    /// purged and handles aliased ([`RepoIndex::fixup_moves`])
+    pub moves_fixed: usize,
+}
+
+/// A path given to [`RepoIndex::index_paths`] that was left out, and why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IndexPathError {
+    /// As it was given
+    pub path: String,
+    pub reason: String,
 }
 
 /// Index status information
 #[derive(Debug, Serialize)]
 pub struct IndexStatus {
+    pub schema_version: SchemaVersion,
     pub files_indexed: usize,
     pub total_tokens: usize,
-    pub schema_version: i32,
+    /// Version of the index database's schema
+    pub index_schema_version: i32,
     pub index_size_bytes: u64,
     pub last_indexed: Option<String>,
+    /// Number of per-directory shard databases (0 when unsharded)
+    pub shards: usize,
+    /// Indexed annotations by marker (TODO, FIXME, ...)
+    pub annotations: BTreeMap<String, usize>,
+    /// Files indexed as plain chunks because they failed to parse
+    pub files_degraded: usize,
+    /// Files flagged as generated, which queries leave out by default
+    pub files_generated: usize,
+    /// Tokens in `files_generated`, part of `total_tokens`
+    pub generated_tokens: usize,
+    /// Nodes full-text indexed from a sample of their content
+    pub nodes_fts_sampled: usize,
+    /// Set when most files share one mtime, as after a fresh clone, which
+    /// leaves recency filters unable to tell them apart
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtime_warning: Option<String>,
+    /// Tokens per node type and the largest nodes; only from
+    /// [`RepoIndex::status_detailed`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_breakdown: Option<NodeBreakdown>,
+    /// Set when a file was reparsed more than [`CHURN_WARN_REINDEXES`] times
+    /// within [`CHURN_WINDOW_SECS`], suggesting it be ignored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub churn_warning: Option<String>,
+    /// The most-reparsed files within the churn window; only from
+    /// [`RepoIndex::status_detailed`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub churning_files: Option<Vec<ChurningFile>>,
+    /// When the FTS segments were last merged; only from
+    /// [`RepoIndex::status_detailed`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_fts_optimize: Option<String>,
+}
+
+/// A file indexed as plain chunks, and why structural parsing failed.
+#[derive(Debug, Clone, Serialize)]
+pub struct ParseWarning {
+    pub path: String,
+    pub reason: String,
 }
 
 /// Detail record returned when expanding a handle.
@@ -51,44 +199,152 @@ pub struct ExpandedHandleDetail {
     pub handle_id: String,
     pub file_path: String,
     pub node_type: NodeType,
+    /// Tokens of `content` as returned, i.e. after redaction
     pub token_count: usize,
     pub content: String,
+    /// Secrets `[redaction]` masked in `content`
+    pub redactions: usize,
+    /// Lines of `content`: the node's, or the part a line window asked for
+    pub line_range: (usize, usize),
+    /// Why a requested line window was ignored
+    pub note: Option<String>,
+}
+
+/// An indexed node with what identifies it beyond its handle id.
+#[derive(Debug, Clone)]
+pub struct IndexedNode {
+    pub handle: Handle,
+    /// Symbol or heading name, for named node types
+    pub name: Option<String>,
+    /// Enclosing class/impl, for methods
+    pub parent_name: Option<String>,
+    /// Hex SHA-256 of the node's source; `None` for rows from before v8
+    pub content_hash: Option<String>,
 }
-type ExpandedHandleDbRow = (String, i64, i64, i64, i64, Vec<u8>);
+
+type ExpandedHandleDbRow = (
+    String,
+    Option<Vec<u8>>,
+    i64,
+    i64,
+    i64,
+    i64,
+    Vec<u8>,
+    i64,
+    i64,
+);
 
 /// Repository index backed by SQLite
 pub struct RepoIndex {
     pub(crate) repo_root: PathBuf,
+    /// Database backing this index: `.canopy/index.db` or a shard database
+    pub(crate) db_path: PathBuf,
     pub(crate) conn: Connection,
     pub(crate) config: Config,
     /// Symbol cache: name_lower -> entries (preloaded at open for O(1) lookups)
-    pub(crate) symbol_cache: HashMap<String, Vec<SymbolCacheEntry>>,
-    /// Reverse index: file_path -> set of symbol name_lower keys in symbol_cache
-    pub(crate) symbol_cache_by_file: HashMap<String, HashSet<String>>,
+    pub(crate) symbol_cache: SymbolCache,
+    /// Per-directory shards routed through this (catch-all) index
+    pub(crate) shards: ShardRouter,
+    /// Last `repo_summary` result, reused until the index changes
+    pub(crate) summary_cache: RefCell<Option<CachedSummary>>,
+    /// Separator and case sensitivity of the repo's filesystem
+    pub(crate) path_style: PathStyle,
+    /// Oldest file change (UNIX seconds) searches return while a
+    /// `(recent ...)` query runs; see [`RecentScope`](recency::RecentScope)
+    pub(crate) modified_since: Cell<Option<i64>>,
+    /// Fragments every file path searches return must contain while a
+    /// `(path ...)` query runs; see [`PathScope`]
+    pub(crate) path_fragments: RefCell<Vec<String>>,
+    /// Whether searches return generated files; see [`GeneratedScope`]
+    pub(crate) generated_filter: Cell<generated::GeneratedFilter>,
+    /// `[redaction]` applied to previews and expanded content
+    pub(crate) redactor: Redactor,
+    /// Commit a copy of the index was built from (see [`open_copy`](Self::open_copy));
+    /// expansion reads files changed since then back from it
+    pub(crate) source_commit: Option<String>,
+    /// Set when the database is at a newer schema this binary can still
+    /// read; see [`compat_mode`](Self::compat_mode)
+    pub(crate) newer_schema: Option<NewerSchema>,
+    /// Full-text searches run since [`take_scan_stats`](Self::take_scan_stats)
+    pub(crate) scan_stats: RefCell<FtsScanStats>,
+}
+
+/// Side effects of initializing a repo beyond creating `.canopy/index.db`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InitOptions {
+    /// Add `.canopy/` to the repo's `.gitignore`
+    pub write_gitignore: bool,
+    /// Write `config.toml` for the preset; without one the index runs on
+    /// the defaults
+    pub write_default_config: bool,
+}
+
+impl Default for InitOptions {
+    fn default() -> Self {
+        Self {
+            write_gitignore: true,
+            write_default_config: true,
+        }
+    }
 }
 
 impl RepoIndex {
-    /// Initialize a new canopy repository
+    /// Initialize a new canopy repository, with the config preset detected
+    /// from the files in `repo_root`.
     pub fn init(repo_root: &Path) -> crate::Result<()> {
+        Self::init_with_preset(repo_root, Preset::detect(repo_root), false).map(|_| ())
+    }
+
+    /// Initialize a new canopy repository with `preset`'s config.
+    ///
+    /// An existing `config.toml` is an error unless `force` is set, in which case
+    /// it is moved aside first. Returns the backup path, if one was made.
+    pub fn init_with_preset(
+        repo_root: &Path,
+        preset: Preset,
+        force: bool,
+    ) -> crate::Result<Option<PathBuf>> {
+        Self::init_with_options(repo_root, preset, force, InitOptions::default())
+    }
+
+    /// [`init_with_preset`](Self::init_with_preset), with only the side
+    /// effects `options` asks for. Without `write_default_config` an existing
+    /// `config.toml` is left as it is.
+    pub fn init_with_options(
+        repo_root: &Path,
+        preset: Preset,
+        force: bool,
+        options: InitOptions,
+    ) -> crate::Result<Option<PathBuf>> {
         let canopy_dir = repo_root.join(".canopy");
         let config_path = canopy_dir.join("config.toml");
 
-        if config_path.exists() {
-            return Err(CanopyError::ConfigExists(config_path));
+        let mut backup = None;
+        if options.write_default_config && config_path.exists() {
+            if !force {
+                return Err(CanopyError::ConfigExists(config_path));
+            }
+            let path = config_backup_path(&config_path);
+            fs::rename(&config_path, &path)?;
+            backup = Some(path);
         }
 
         fs::create_dir_all(&canopy_dir)?;
-        fs::write(&config_path, default_config_toml())?;
+        if options.write_default_config {
+            fs::write(&config_path, preset.config_toml())?;
+        }
 
         // Add .canopy to .gitignore if not present
-        update_gitignore(repo_root)?;
+        if options.write_gitignore {
+            update_gitignore(repo_root)?;
+        }
 
         // Create the database
         let db_path = canopy_dir.join("index.db");
         let conn = Connection::open(&db_path)?;
         Self::init_schema(&conn)?;
 
-        Ok(())
+        Ok(backup)
     }
 
     /// Open an existing index, or initialize and then open if `.canopy` doesn't exist yet.
@@ -101,36 +357,95 @@ impl RepoIndex {
 
     /// Open an existing index at `.canopy/index.db`. Returns `NotInitialized` if `.canopy` is missing.
     pub fn open(repo_root: &Path) -> crate::Result<Self> {
+        let config = Self::load_config(repo_root)?;
+        let db_path = repo_root.join(".canopy").join("index.db");
+        let mut index = Self::open_db(repo_root, db_path, config)?;
+        index.shards = ShardRouter::open(repo_root, &index.config, false)?;
+        Ok(index)
+    }
+
+    /// `.canopy/config.toml`, or the defaults when there is none.
+    fn load_config(repo_root: &Path) -> crate::Result<Config> {
         let canopy_dir = repo_root.join(".canopy");
         let config_path = canopy_dir.join("config.toml");
-        let db_path = canopy_dir.join("index.db");
-
-        // Load config (use defaults if not present)
-        let config = if config_path.exists() {
-            Config::load(&config_path)?
+        if config_path.exists() {
+            Config::load(&config_path)
+        } else if !canopy_dir.exists() {
+            Err(CanopyError::NotInitialized)
         } else {
-            // Check if .canopy exists at all
-            if !canopy_dir.exists() {
-                return Err(CanopyError::NotInitialized);
+            Ok(Config::default())
+        }
+    }
+
+    /// Cheap health check: open `.canopy/index.db` read-only and confirm its
+    /// schema version, without loading config or the symbol cache.
+    pub fn probe(repo_root: &Path) -> crate::Result<()> {
+        let db_path = repo_root.join(".canopy").join("index.db");
+        if !db_path.exists() {
+            return Err(CanopyError::NotInitialized);
+        }
+        let conn = Connection::open_with_flags(
+            &db_path,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        // Older databases with a migration path are upgraded on the next open,
+        // and newer ones this binary can read are opened read-only
+        let version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        match migrations::unmigratable(version, migrations::writer(&conn)) {
+            Some(_) if version > SCHEMA_VERSION && migrations::newer_schema_readable(&conn)? => {
+                Ok(())
             }
-            Config::default()
-        };
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
 
+    /// Open a single database without shard routing.
+    fn open_db(repo_root: &Path, db_path: PathBuf, config: Config) -> crate::Result<Self> {
         // Open database
         let conn = Connection::open(&db_path)?;
 
-        // Initialize or migrate schema
-        Self::init_schema(&conn)?;
+        // Initialize or migrate schema. A newer schema that kept every column
+        // this binary reads is still served, read-only
+        let (conn, newer_schema) = match Self::init_schema(&conn) {
+            Ok(()) => (conn, None),
+            Err(CanopyError::SchemaVersionMismatch {
+                found, written_by, ..
+            }) if found > SCHEMA_VERSION && migrations::newer_schema_readable(&conn)? => {
+                let conn = Connection::open_with_flags(
+                    &db_path,
+                    rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY
+                        | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+                )?;
+                conn.execute_batch("PRAGMA busy_timeout = 5000; PRAGMA mmap_size = 268435456;")?;
+                (conn, Some(NewerSchema { found, written_by }))
+            }
+            Err(err) => return Err(err),
+        };
+        conn.set_prepared_statement_cache_capacity(statements::STATEMENT_CACHE_CAPACITY);
 
         // Load symbol cache for O(1) lookups
-        let (symbol_cache, symbol_cache_by_file) = Self::load_symbol_cache(&conn)?;
+        let symbol_cache = SymbolCache::load(&conn, config.core.symbol_cache_max_bytes)?;
+
+        let path_style = PathStyle::for_config(&config);
+        let redactor = Redactor::new(&config.redaction, path_style)?;
 
         Ok(Self {
             repo_root: repo_root.to_path_buf(),
+            db_path,
             conn,
+            path_style,
             config,
             symbol_cache,
-            symbol_cache_by_file,
+            shards: ShardRouter::default(),
+            summary_cache: RefCell::new(None),
+            scan_stats: RefCell::default(),
+            modified_since: Cell::new(None),
+            path_fragments: RefCell::default(),
+            generated_filter: Cell::default(),
+            redactor,
+            source_commit: None,
+            newer_schema,
         })
     }
 
@@ -150,106 +465,172 @@ impl RepoIndex {
         // Check schema version
         let version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
 
-        // Fail fast on older schema versions - require reindex
+        // Upgrade older schemas in place; breaking changes still require a reindex
         if version != 0 && version != SCHEMA_VERSION {
-            return Err(CanopyError::SchemaVersionMismatch {
-                found: version,
-                expected: SCHEMA_VERSION,
-            });
+            migrations::migrate(conn, version)?;
         }
 
         if version == 0 {
-            // Fresh database, create schema v2
-            conn.execute_batch(
-                "
-                -- File metadata for cache invalidation
-                CREATE TABLE IF NOT EXISTS files (
-                    id INTEGER PRIMARY KEY,
-                    path TEXT UNIQUE NOT NULL,
-                    content_hash BLOB NOT NULL,
-                    mtime INTEGER NOT NULL,
-                    indexed_at INTEGER NOT NULL,
-                    token_count INTEGER NOT NULL
-                );
+            Self::create_schema(conn)?;
+        }
+        conn.execute_batch(migrations::MIGRATION_TABLES)?;
+        // Derived data cached across opens, e.g. the status node breakdown
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);",
+        )?;
+        if version != SCHEMA_VERSION {
+            migrations::record_writer(conn)?;
+        }
 
-                -- Nodes (sections, code blocks, paragraphs, functions, etc.)
-                CREATE TABLE IF NOT EXISTS nodes (
-                    id INTEGER PRIMARY KEY,
-                    file_id INTEGER REFERENCES files(id) ON DELETE CASCADE,
-                    handle_id TEXT UNIQUE NOT NULL,
-                    node_type INTEGER NOT NULL,
-                    start_byte INTEGER NOT NULL,
-                    end_byte INTEGER NOT NULL,
-                    line_start INTEGER NOT NULL,
-                    line_end INTEGER NOT NULL,
-                    token_count INTEGER NOT NULL,
-                    metadata TEXT,
-                    -- NEW COLUMNS in v2:
-                    name TEXT,
-                    name_lower TEXT COLLATE NOCASE,
-                    parent_name TEXT,
-                    parent_name_lower TEXT COLLATE NOCASE,
-                    parent_handle_id TEXT,
-                    preview TEXT
-                );
+        Ok(())
+    }
 
-                CREATE INDEX IF NOT EXISTS idx_nodes_file ON nodes(file_id);
-                CREATE INDEX IF NOT EXISTS idx_nodes_handle ON nodes(handle_id);
-                CREATE INDEX IF NOT EXISTS idx_nodes_type ON nodes(node_type);
-                CREATE INDEX IF NOT EXISTS idx_nodes_name_lower ON nodes(name_lower);
-                CREATE INDEX IF NOT EXISTS idx_nodes_parent_name_lower ON nodes(parent_name_lower);
-                CREATE INDEX IF NOT EXISTS idx_nodes_parent_handle ON nodes(parent_handle_id);
-
-                -- FTS5 index for text search
-                CREATE VIRTUAL TABLE IF NOT EXISTS content_fts USING fts5(
-                    content,
-                    tokenize='unicode61'
-                );
+    /// Create the current schema in a fresh database.
+    pub(super) fn create_schema(conn: &Connection) -> crate::Result<()> {
+        conn.execute_batch(
+            "
+            -- File metadata for cache invalidation
+            CREATE TABLE IF NOT EXISTS files (
+                id INTEGER PRIMARY KEY,
+                path TEXT UNIQUE NOT NULL,
+                content_hash BLOB NOT NULL,
+                mtime INTEGER NOT NULL,
+                indexed_at INTEGER NOT NULL,
+                token_count INTEGER NOT NULL,
+                -- NEW COLUMN in v5: first two directory segments, for glob prefiltering
+                dir_prefix TEXT NOT NULL DEFAULT '',
+                -- NEW COLUMN in v6: raw path bytes when the path isn't valid UTF-8
+                -- (`path` then holds the escaped display form)
+                path_bytes BLOB,
+                -- NEW COLUMN in v11: line count and byte length, so whole-file
+                -- handles can be built without reading the file
+                line_count INTEGER NOT NULL DEFAULT 0,
+                byte_len INTEGER NOT NULL DEFAULT 0,
+                -- NEW COLUMN in v12: last commit touching the file, with
+                -- `[indexing] git_commit_times`; NULL for untracked or dirty files
+                commit_time INTEGER,
+                -- NEW COLUMN in v14: 1 for files matching the `[generated]` heuristics
+                generated INTEGER NOT NULL DEFAULT 0,
+                -- NEW COLUMN in v15: reparses since `churn_since`, for churn
+                -- warnings; NULL `churn_since` until the first reparse
+                reindex_count INTEGER NOT NULL DEFAULT 0,
+                churn_since INTEGER
+            );
 
-                -- Mapping from FTS rowid to node
-                CREATE TABLE IF NOT EXISTS fts_node_map (
-                    fts_rowid INTEGER PRIMARY KEY,
-                    node_id INTEGER REFERENCES nodes(id) ON DELETE CASCADE
-                );
+            CREATE INDEX IF NOT EXISTS idx_files_dir_prefix ON files(dir_prefix);
+            CREATE INDEX IF NOT EXISTS idx_files_generated ON files(generated) WHERE generated = 1;
+
+            -- Nodes (sections, code blocks, paragraphs, functions, etc.)
+            CREATE TABLE IF NOT EXISTS nodes (
+                id INTEGER PRIMARY KEY,
+                file_id INTEGER REFERENCES files(id) ON DELETE CASCADE,
+                handle_id TEXT UNIQUE NOT NULL,
+                node_type INTEGER NOT NULL,
+                start_byte INTEGER NOT NULL,
+                end_byte INTEGER NOT NULL,
+                line_start INTEGER NOT NULL,
+                line_end INTEGER NOT NULL,
+                token_count INTEGER NOT NULL,
+                metadata TEXT,
+                -- NEW COLUMNS in v2:
+                name TEXT,
+                name_lower TEXT COLLATE NOCASE,
+                parent_name TEXT,
+                parent_name_lower TEXT COLLATE NOCASE,
+                parent_handle_id TEXT,
+                preview TEXT,
+                -- NEW COLUMN in v8: SHA-256 of the node's source slice, so
+                -- incremental reindex can keep unchanged nodes
+                content_hash BLOB,
+                -- NEW COLUMN in v10: enclosing headings of a section,
+                -- e.g. 'Deployment > Auth > Configuration'
+                heading_path TEXT,
+                -- NEW COLUMN in v13: estimated tokens of `preview`
+                preview_tokens INTEGER NOT NULL DEFAULT 0,
+                -- NEW COLUMN in v16: 1 when content_fts holds a sample of the
+                -- content (over `[indexing] max_fts_bytes_per_node`)
+                fts_truncated INTEGER NOT NULL DEFAULT 0
+            );
 
-                -- References table (calls, imports, type refs)
-                CREATE TABLE IF NOT EXISTS refs (
-                    id INTEGER PRIMARY KEY,
-                    file_id INTEGER REFERENCES files(id) ON DELETE CASCADE,
-                    name TEXT NOT NULL,
-                    name_lower TEXT COLLATE NOCASE,
-                    qualifier TEXT,
-                    ref_type TEXT NOT NULL,
-                    source_node_id INTEGER REFERENCES nodes(id) ON DELETE CASCADE,
-                    span_start INTEGER NOT NULL,
-                    span_end INTEGER NOT NULL,
-                    line_start INTEGER NOT NULL,
-                    line_end INTEGER NOT NULL,
-                    preview TEXT
-                );
+            CREATE INDEX IF NOT EXISTS idx_nodes_file ON nodes(file_id);
+            CREATE INDEX IF NOT EXISTS idx_nodes_handle ON nodes(handle_id);
+            CREATE INDEX IF NOT EXISTS idx_nodes_type ON nodes(node_type);
+            CREATE INDEX IF NOT EXISTS idx_nodes_name_lower ON nodes(name_lower);
+            CREATE INDEX IF NOT EXISTS idx_nodes_parent_name_lower ON nodes(parent_name_lower);
+            CREATE INDEX IF NOT EXISTS idx_nodes_parent_handle ON nodes(parent_handle_id);
+
+            -- FTS5 index for text search. Underscores are token chars so
+            -- identifiers stay whole; identifier_parts holds camel/snake sub-tokens.
+            CREATE VIRTUAL TABLE IF NOT EXISTS content_fts USING fts5(
+                content,
+                identifier_parts,
+                tokenize = 'unicode61 tokenchars ''_'''
+            );
 
-                CREATE INDEX IF NOT EXISTS idx_refs_name_lower ON refs(name_lower);
-                CREATE INDEX IF NOT EXISTS idx_refs_type ON refs(ref_type);
-                CREATE INDEX IF NOT EXISTS idx_refs_source ON refs(source_node_id);
-                CREATE INDEX IF NOT EXISTS idx_refs_file ON refs(file_id);
+            -- Mapping from FTS rowid to node
+            CREATE TABLE IF NOT EXISTS fts_node_map (
+                fts_rowid INTEGER PRIMARY KEY,
+                node_id INTEGER REFERENCES nodes(id) ON DELETE CASCADE
+            );
 
-                -- Symbol FTS for fuzzy symbol search
-                CREATE VIRTUAL TABLE IF NOT EXISTS symbol_fts USING fts5(
-                    name,
-                    tokenize='unicode61'
-                );
+            -- References table (calls, imports, type refs)
+            CREATE TABLE IF NOT EXISTS refs (
+                id INTEGER PRIMARY KEY,
+                file_id INTEGER REFERENCES files(id) ON DELETE CASCADE,
+                name TEXT NOT NULL,
+                name_lower TEXT COLLATE NOCASE,
+                qualifier TEXT,
+                ref_type TEXT NOT NULL,
+                source_node_id INTEGER REFERENCES nodes(id) ON DELETE CASCADE,
+                span_start INTEGER NOT NULL,
+                span_end INTEGER NOT NULL,
+                line_start INTEGER NOT NULL,
+                line_end INTEGER NOT NULL,
+                preview TEXT
+            );
 
-                -- Mapping from symbol FTS rowid to node
-                CREATE TABLE IF NOT EXISTS symbol_fts_map (
-                    fts_rowid INTEGER PRIMARY KEY,
-                    node_id INTEGER REFERENCES nodes(id) ON DELETE CASCADE
-                );
+            CREATE INDEX IF NOT EXISTS idx_refs_name_lower ON refs(name_lower);
+            CREATE INDEX IF NOT EXISTS idx_refs_type ON refs(ref_type);
+            CREATE INDEX IF NOT EXISTS idx_refs_source ON refs(source_node_id);
+            CREATE INDEX IF NOT EXISTS idx_refs_file ON refs(file_id);
 
-                PRAGMA user_version = 3;
-                ",
-            )?;
-        }
+            -- Symbol FTS for fuzzy symbol search
+            CREATE VIRTUAL TABLE IF NOT EXISTS symbol_fts USING fts5(
+                name,
+                name_parts,
+                tokenize = 'unicode61 tokenchars ''_'''
+            );
+
+            -- Mapping from symbol FTS rowid to node
+            CREATE TABLE IF NOT EXISTS symbol_fts_map (
+                fts_rowid INTEGER PRIMARY KEY,
+                node_id INTEGER REFERENCES nodes(id) ON DELETE CASCADE
+            );
 
+            -- NEW TABLE in v7: marker comments (TODO, FIXME, ...)
+            CREATE TABLE IF NOT EXISTS annotations (
+                id INTEGER PRIMARY KEY,
+                file_id INTEGER REFERENCES files(id) ON DELETE CASCADE,
+                line INTEGER NOT NULL,
+                marker TEXT NOT NULL,
+                text TEXT NOT NULL,
+                node_id INTEGER REFERENCES nodes(id) ON DELETE SET NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_annotations_file ON annotations(file_id);
+            CREATE INDEX IF NOT EXISTS idx_annotations_marker ON annotations(marker);
+
+            -- Files indexed as plain chunks because structural parsing failed
+            CREATE TABLE IF NOT EXISTS parse_warnings (
+                file_id INTEGER PRIMARY KEY REFERENCES files(id) ON DELETE CASCADE,
+                reason TEXT NOT NULL
+            );
+
+            PRAGMA user_version = 17;
+            ",
+        )?;
+        // NEW TABLE in v17: handles of moved files, to their new nodes
+        conn.execute_batch(moves::HANDLE_ALIASES_TABLE)?;
         Ok(())
     }
 
@@ -258,6 +639,30 @@ impl RepoIndex {
         &self.config
     }
 
+    /// Database file backing this index.
+    pub fn db_path(&self) -> &Path {
+        &self.db_path
+    }
+
+    /// Whether a database (this one or a shard) was written by a newer
+    /// canopy and is open read-only: queries answer from it, marked
+    /// `compat_mode`, and indexing fails with the schema mismatch.
+    pub fn compat_mode(&self) -> bool {
+        self.all_indexes().any(|index| index.newer_schema.is_some())
+    }
+
+    /// The schema mismatch, for operations that write, when in
+    /// [`compat_mode`](Self::compat_mode).
+    pub(crate) fn ensure_writable(&self) -> crate::Result<()> {
+        match self
+            .all_indexes()
+            .find_map(|index| index.newer_schema.as_ref())
+        {
+            Some(newer) => Err(newer.mismatch()),
+            None => Ok(()),
+        }
+    }
+
     /// Query indexed content from a DSL string with full options.
     ///
     /// Prefer [`query_params`](Self::query_params) for structured input from MCP tools.
@@ -278,9 +683,8 @@ impl RepoIndex {
     /// let result = index.query_params(params)?;
     /// ```
     pub fn query_params(&self, params: QueryParams) -> crate::Result<QueryResult> {
-        let query = params.to_query()?;
         let options = params.to_options();
-        execute_query_with_options(&query, self, options)
+        execute_params(&params, self, options)
     }
 }
 
@@ -301,6 +705,7 @@ pub(crate) fn reference_preview(
     source: &str,
     span: &std::ops::Range<usize>,
     max_bytes: usize,
+    redactor: Option<&Redactor>,
 ) -> String {
     let line_start = source[..span.start.min(source.len())]
         .rfind('\n')
@@ -312,7 +717,7 @@ pub(crate) fn reference_preview(
         .unwrap_or(source.len());
 
     let preview_span = line_start..line_end;
-    generate_preview(source, &preview_span, max_bytes)
+    generate_preview(source, &preview_span, max_bytes, redactor)
 }
 
 /// Update .gitignore to include .canopy/
@@ -336,6 +741,18 @@ fn update_gitignore(repo_root: &Path) -> crate::Result<()> {
     Ok(())
 }
 
+/// First free `config.toml.bak`, `config.toml.bak.1`, ... next to `config_path`
+fn config_backup_path(config_path: &Path) -> PathBuf {
+    let base = config_path.with_extension("toml.bak");
+    (0..)
+        .map(|n| match n {
+            0 => base.clone(),
+            n => PathBuf::from(format!("{}.{n}", base.display())),
+        })
+        .find(|path| !path.exists())
+        .expect("unbounded range always yields a free path")
+}
+
 #[cfg(test)]
 mod tests {
     use super::test_helpers::setup_repo;
@@ -369,7 +786,7 @@ mod tests {
         assert_eq!(stats.files_skipped, 0);
         assert!(stats.total_tokens > 0);
 
-        // Reindex should skip all via mtime+TTL fast path
+        // Reindex should skip all via the mtime fast path
         let stats2 = index.index("**/*.rs").unwrap();
         assert_eq!(stats2.files_indexed, 0);
         assert_eq!(stats2.files_skipped, 80);
@@ -382,6 +799,62 @@ mod tests {
         );
     }
 
+    /// The methods the client, MCP server, CLI and service call, with the
+    /// signatures they compile against: moving code between the index
+    /// submodules must not drop or change any of them.
+    #[test]
+    fn public_api_keeps_the_signatures_other_crates_use() {
+        let _: fn(&Path) -> crate::Result<()> = RepoIndex::init;
+        let _: fn(&Path) -> crate::Result<RepoIndex> = RepoIndex::open;
+        let _: fn(&mut RepoIndex, &str) -> crate::Result<IndexStats> = RepoIndex::index;
+        let _: fn(&mut RepoIndex, &[PathBuf]) -> crate::Result<IndexStats> = RepoIndex::index_paths;
+        let _: fn(&RepoIndex, &str) -> crate::Result<IndexPlan> = RepoIndex::plan_index;
+        let _: fn(&RepoIndex, &str) -> crate::Result<Vec<PathBuf>> = RepoIndex::walk_files;
+        let _: fn(&RepoIndex, &str, QueryOptions) -> crate::Result<QueryResult> =
+            RepoIndex::query_with_options;
+        let _: fn(&RepoIndex, QueryParams) -> crate::Result<QueryResult> = RepoIndex::query_params;
+        let _: fn(&RepoIndex, &str, usize) -> crate::Result<Vec<Handle>> = RepoIndex::search_code;
+        type Contents = Vec<(String, String)>;
+        let _: fn(&RepoIndex, &[String]) -> crate::Result<Contents> = RepoIndex::expand;
+        let _: fn(&RepoIndex, &[String]) -> crate::Result<Vec<ExpandedHandleDetail>> =
+            RepoIndex::expand_with_details;
+        let _: fn(&mut RepoIndex, Option<&str>) -> crate::Result<usize> = RepoIndex::invalidate;
+        let _: fn(&RepoIndex) -> crate::Result<IndexStatus> = RepoIndex::status;
+        let _: fn(&RepoIndex) -> &Config = RepoIndex::config;
+    }
+
+    #[test]
+    fn sequential_and_pipeline_paths_serve_the_same_api() {
+        for files in [5, RepoIndex::SEQUENTIAL_THRESHOLD + 16] {
+            let dir = setup_repo(files);
+            let mut index = RepoIndex::open(dir.path()).unwrap();
+            assert_eq!(index.walk_files("**/*.rs").unwrap().len(), files);
+            assert_eq!(index.index("**/*.rs").unwrap().files_indexed, files);
+            assert_eq!(index.status().unwrap().files_indexed, files);
+
+            let result = index
+                .query_params(QueryParams::symbol("func_3").with_kind(crate::QueryKind::Definition))
+                .unwrap();
+            assert_eq!(result.handles.len(), 1, "{files} files");
+            let id = result.handles[0].id.to_string();
+            let details = index
+                .expand_with_details(std::slice::from_ref(&id))
+                .unwrap();
+            assert!(details[0].content.contains("hello from 3"), "{files} files");
+            let dsl = index
+                .query_with_options(r#"(grep "hello")"#, QueryOptions::new().with_limit(100))
+                .unwrap();
+            assert_eq!(dsl.total_matches, files, "{files} files");
+
+            assert_eq!(index.invalidate(Some("src/file_3.rs")).unwrap(), 1);
+            assert!(index.expand(&[id]).is_err());
+            let reindexed = index
+                .index_paths(&[dir.path().join("src/file_3.rs")])
+                .unwrap();
+            assert_eq!(reindexed.files_indexed, 1);
+        }
+    }
+
     #[test]
     fn test_symbol_cache_by_file_consistency() {
         let dir = setup_repo(3);
@@ -390,12 +863,12 @@ mod tests {
 
         // Verify reverse index tracks all files
         assert!(
-            !index.symbol_cache_by_file.is_empty(),
-            "symbol_cache_by_file should be populated"
+            !index.symbol_cache.by_file().is_empty(),
+            "the reverse index should be populated"
         );
 
         // Every file in reverse index should have matching entries in forward cache
-        for (file_path, names) in &index.symbol_cache_by_file {
+        for (file_path, names) in index.symbol_cache.by_file() {
             for name in names {
                 let entries = index
                     .symbol_cache
@@ -415,7 +888,7 @@ mod tests {
 
         // Reverse index should no longer have file_0
         assert!(
-            !index.symbol_cache_by_file.contains_key("src/file_0.rs"),
+            !index.symbol_cache.by_file().contains_key("src/file_0.rs"),
             "reverse index should not contain invalidated file"
         );
 
@@ -459,10 +932,211 @@ mod tests {
         let dir = setup_repo(5);
         let mut index = RepoIndex::open(dir.path()).unwrap();
         index.index("**/*.rs").unwrap();
-        assert!(!index.symbol_cache_by_file.is_empty());
+        assert!(!index.symbol_cache.by_file().is_empty());
 
         index.invalidate(None).unwrap();
         assert!(index.symbol_cache.is_empty());
-        assert!(index.symbol_cache_by_file.is_empty());
+        assert!(index.symbol_cache.by_file().is_empty());
+    }
+
+    #[test]
+    fn probe_checks_database_and_schema() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert!(matches!(
+            RepoIndex::probe(dir.path()),
+            Err(CanopyError::NotInitialized)
+        ));
+
+        RepoIndex::init(dir.path()).unwrap();
+        RepoIndex::open(dir.path()).unwrap();
+        RepoIndex::probe(dir.path()).unwrap();
+
+        // A migratable schema passes; the next open upgrades it
+        let conn = Connection::open(dir.path().join(".canopy/index.db")).unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION - 1)
+            .unwrap();
+        RepoIndex::probe(dir.path()).unwrap();
+
+        conn.pragma_update(None, "user_version", 3).unwrap();
+        assert!(matches!(
+            RepoIndex::probe(dir.path()),
+            Err(CanopyError::SchemaVersionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_open_rejects_unmigratable_schema_version() {
+        let dir = setup_repo(1);
+        {
+            let conn = Connection::open(dir.path().join(".canopy/index.db")).unwrap();
+            conn.pragma_update(None, "user_version", 3).unwrap();
+        }
+
+        match RepoIndex::open(dir.path()) {
+            Err(CanopyError::SchemaVersionMismatch {
+                found, expected, ..
+            }) => {
+                assert_eq!(found, 3);
+                assert_eq!(expected, SCHEMA_VERSION);
+            }
+            Err(other) => panic!("expected schema mismatch, got {other}"),
+            Ok(_) => panic!("expected schema mismatch, got an open index"),
+        }
+
+        // Removing the database (as the error hint suggests) recovers a fresh index
+        fs::remove_file(dir.path().join(".canopy/index.db")).unwrap();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        assert_eq!(index.index("**/*.rs").unwrap().files_indexed, 1);
+    }
+
+    /// Rewrite `path` but keep its mtime, as when restoring from a cache archive.
+    fn restore_with_same_mtime(path: &Path, content: &str) {
+        let mtime = fs::metadata(path).unwrap().modified().unwrap();
+        fs::write(path, content).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+    }
+
+    fn set_indexing_config(dir: &Path, settings: &str) {
+        fs::write(
+            dir.join(".canopy/config.toml"),
+            format!("[indexing]\n{settings}\n"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_hash_verify_reindexes_archive_restore() {
+        // 1 file takes the sequential path, 80 the pipeline path
+        for n in [1, 80] {
+            let dir = setup_repo(n);
+            set_indexing_config(dir.path(), "verify = \"hash\"");
+            let mut index = RepoIndex::open(dir.path()).unwrap();
+            index.index("**/*.rs").unwrap();
+
+            restore_with_same_mtime(
+                &dir.path().join("src/file_0.rs"),
+                "fn restored_from_archive() {}\n",
+            );
+            let stats = index.index("**/*.rs").unwrap();
+            assert_eq!(stats.files_indexed, 1, "n = {n}");
+            assert_eq!(stats.skipped.hash, n - 1, "n = {n}");
+            assert_eq!(stats.skipped.mtime, 0);
+            assert!(!index
+                .search_code("restored_from_archive", 10)
+                .unwrap()
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn test_default_verify_skips_by_mtime_then_hash() {
+        let dir = setup_repo(2);
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        // Same mtime is trusted, even for changed content
+        restore_with_same_mtime(&dir.path().join("src/file_0.rs"), "fn changed() {}\n");
+        // New mtime but identical content is caught by the hash
+        let touched = dir.path().join("src/file_1.rs");
+        let later = fs::metadata(&touched).unwrap().modified().unwrap()
+            + std::time::Duration::from_secs(120);
+        fs::File::options()
+            .write(true)
+            .open(&touched)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+
+        let stats = index.index("**/*.rs").unwrap();
+        assert_eq!(stats.files_indexed, 0);
+        assert_eq!(
+            stats.skipped,
+            SkipCounts {
+                ttl: 0,
+                mtime: 1,
+                hash: 1
+            }
+        );
+        assert_eq!(stats.files_skipped, 2);
+    }
+
+    #[test]
+    fn test_stat_ttl_skips_without_checking_files() {
+        let dir = setup_repo(1);
+        set_indexing_config(dir.path(), "stat_ttl = \"1h\"\nverify = \"hash\"");
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        fs::write(dir.path().join("src/file_0.rs"), "fn edited() {}\n").unwrap();
+        let stats = index.index("**/*.rs").unwrap();
+        assert_eq!(stats.files_indexed, 0);
+        assert_eq!(stats.skipped.ttl, 1);
+    }
+
+    #[test]
+    fn test_init_with_preset_refuses_existing_config_unless_forced() {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::write(dir.path().join("go.mod"), "module example.com/app\n").unwrap();
+        RepoIndex::init(dir.path()).unwrap();
+        let config_path = dir.path().join(".canopy/config.toml");
+        let detected = Config::load(&config_path).unwrap();
+        assert_eq!(detected.indexing.default_glob, "**/*.{go,mod,md,yaml,yml}");
+
+        assert!(matches!(
+            RepoIndex::init_with_preset(dir.path(), Preset::Node, false),
+            Err(CanopyError::ConfigExists(_))
+        ));
+
+        let backup = RepoIndex::init_with_preset(dir.path(), Preset::Node, true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(backup, dir.path().join(".canopy/config.toml.bak"));
+        assert!(fs::read_to_string(&backup).unwrap().contains("preset go"));
+        let config = Config::load(&config_path).unwrap();
+        assert!(config.indexing.default_glob.contains("tsx"));
+
+        // A second forced init keeps the first backup
+        let second = RepoIndex::init_with_preset(dir.path(), Preset::Rust, true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(second, dir.path().join(".canopy/config.toml.bak.1"));
+        assert!(backup.exists());
+    }
+
+    #[test]
+    fn test_node_preset_indexes_tsx_and_skips_build_output() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        fs::write(root.join("package.json"), "{\"name\": \"web\"}\n").unwrap();
+        for (path, source) in [
+            ("src/App.tsx", "export function App() { return null; }\n"),
+            ("src/util.ts", "export const add = (a: number) => a;\n"),
+            ("node_modules/react/index.js", "module.exports = {};\n"),
+            ("dist/app.js", "function App() {}\n"),
+        ] {
+            fs::create_dir_all(root.join(path).parent().unwrap()).unwrap();
+            fs::write(root.join(path), source).unwrap();
+        }
+
+        RepoIndex::init(root).unwrap();
+        let mut index = RepoIndex::open(root).unwrap();
+        let glob = index.config().default_glob().to_string();
+        index.index(&glob).unwrap();
+
+        let mut stmt = index.conn.prepare("SELECT path FROM files").unwrap();
+        let files: Vec<String> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert!(files.contains(&"src/App.tsx".to_string()), "{files:?}");
+        assert!(files.contains(&"src/util.ts".to_string()));
+        assert!(files.iter().all(|f| !f.starts_with("node_modules/")));
+        assert!(files.iter().all(|f| !f.starts_with("dist/")));
     }
 }
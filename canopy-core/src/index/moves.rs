@@ -0,0 +1,544 @@
+//! Move fixup: dropping the rows of files that moved, and aliasing their
+//! handles to the nodes at the new paths.
+//!
+//! Handle ids hash the file path, so a moved file indexes under new ids
+//! while its old rows linger: indexing walks what is on disk and never
+//! revisits the old path. [`RepoIndex::fixup_moves`] finds the moves, from
+//! git's rename records since the last run and, for indexed files still
+//! missing from disk, a file on disk with the same content hash. For each
+//! it purges the old rows, indexes the new path unless the walker ignores
+//! it, and records a `handle_aliases` row from each old node to the new
+//! node with the same content hash, or else the same kind, name and parent.
+//! Expand follows the aliases, so handles held across a refactor still
+//! resolve.
+//!
+//! [`RepoIndex::index`] runs the git half on its own when HEAD moved since
+//! the last run, as comparing two commits is cheap. Aliases live in the
+//! catch-all database, whatever the sharding.
+
+use rusqlite::{params, params_from_iter, OptionalExtension};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::expand::EXPAND_LOOKUP_CHUNK;
+use super::{IndexedNode, RepoIndex};
+use crate::git;
+
+/// Old handle → new handle, created in fresh databases and by the v17
+/// migration.
+pub(super) const HANDLE_ALIASES_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS handle_aliases (
+        old_handle_id TEXT PRIMARY KEY,
+        new_handle_id TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_handle_aliases_new ON handle_aliases(new_handle_id);
+";
+
+/// Meta key of the HEAD commit moves were last looked for at.
+const MOVES_COMMIT_META_KEY: &str = "moves_commit";
+
+/// A file [`RepoIndex::fixup_moves`] found moved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileMove {
+    pub from: String,
+    pub to: String,
+    /// Whether `to` is indexed; false when the walker ignores it (or it is
+    /// gone too), in which case the old rows were only purged
+    pub indexed: bool,
+    /// Old handles aliased to nodes at `to`
+    pub aliases: usize,
+}
+
+/// What [`RepoIndex::fixup_moves`] did.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MoveFixupReport {
+    pub moves_processed: usize,
+    pub aliases_created: usize,
+    /// Node rows deleted with the old paths, moved or removed
+    pub rows_purged: usize,
+    /// Indexed files gone from disk with nowhere found to have moved to
+    pub files_removed: usize,
+    pub moves: Vec<FileMove>,
+}
+
+/// (content hash, path_bytes) of an indexed file
+type IndexedFile = (Vec<u8>, Option<Vec<u8>>);
+
+impl RepoIndex {
+    /// Find files that moved since they were indexed and fix the index up:
+    /// old rows purged, new paths indexed, old handles aliased to the new
+    /// nodes. Indexed files gone from disk with no destination are purged
+    /// too.
+    ///
+    /// Moves come from git's renames (committed, staged or not) since the
+    /// last fixup or indexing run, when the repo is a git checkout, and
+    /// otherwise from files on disk whose content hash matches a missing
+    /// file's. A file both moved and edited is only found through git.
+    pub fn fixup_moves(&mut self) -> crate::Result<MoveFixupReport> {
+        self.ensure_writable()?;
+        let indexed = self.indexed_files()?;
+        let head = git::head_commit_sha(&self.repo_root);
+        let mut moves = match (&head, self.moves_commit()?) {
+            (Some(_), Some(since)) => {
+                git::renames_since(&self.repo_root, &since, None).unwrap_or_default()
+            }
+            _ => Vec::new(),
+        };
+        moves.retain(|(from, to)| indexed.contains_key(from) && self.on_disk(to, None));
+
+        let moved: HashSet<&str> = moves.iter().map(|(from, _)| from.as_str()).collect();
+        let mut gone: Vec<String> = indexed
+            .iter()
+            .filter(|(path, (_, path_bytes))| {
+                !moved.contains(path.as_str()) && !self.on_disk(path, path_bytes.as_deref())
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+        gone.sort_unstable();
+
+        let walked = self.walked_paths()?;
+        moves.extend(self.moves_by_content(&gone, &indexed, &walked));
+        let moved: HashSet<&str> = moves.iter().map(|(from, _)| from.as_str()).collect();
+        let removed: Vec<String> = gone
+            .iter()
+            .filter(|path| !moved.contains(path.as_str()))
+            .cloned()
+            .collect();
+
+        let mut report = self.apply_moves(&moves, &walked)?;
+        for path in &removed {
+            report.rows_purged += self.file_nodes(path)?.len();
+        }
+        self.purge_paths(&removed)?;
+        report.files_removed = removed.len();
+        if let Some(head) = head {
+            self.record_moves_commit(&head)?;
+        }
+        Ok(report)
+    }
+
+    /// Apply the renames git recorded between the last run and HEAD, for
+    /// [`index`](Self::index). Returns the moves processed; 0 outside git.
+    pub(super) fn fixup_committed_moves(&mut self) -> crate::Result<usize> {
+        let Some(head) = git::head_commit_sha(&self.repo_root) else {
+            return Ok(0);
+        };
+        let mut processed = 0;
+        if let Some(since) = self.moves_commit()?.filter(|since| *since != head) {
+            let mut renames =
+                git::renames_since(&self.repo_root, &since, Some(&head)).unwrap_or_default();
+            let mut indexed = HashSet::new();
+            for (from, _) in &renames {
+                if self.is_indexed(from)? {
+                    indexed.insert(from.clone());
+                }
+            }
+            renames.retain(|(from, _)| indexed.contains(from));
+            if !renames.is_empty() {
+                let walked = self.walked_paths()?;
+                processed = self.apply_moves(&renames, &walked)?.moves_processed;
+            }
+        }
+        self.record_moves_commit(&head)?;
+        Ok(processed)
+    }
+
+    /// Raw ids among `raw_ids` that alias another handle, to the id they
+    /// alias.
+    pub(super) fn handle_aliases(
+        &self,
+        raw_ids: &[&str],
+    ) -> crate::Result<HashMap<String, String>> {
+        let mut aliases = HashMap::new();
+        for chunk in raw_ids.chunks(EXPAND_LOOKUP_CHUNK) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let mut stmt = self.conn.prepare(&format!(
+                "SELECT old_handle_id, new_handle_id FROM handle_aliases
+                 WHERE old_handle_id IN ({placeholders})"
+            ))?;
+            let rows = stmt.query_map(params_from_iter(chunk.iter()), |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+            for row in rows {
+                let (old, new) = row?;
+                aliases.insert(old, new);
+            }
+        }
+        Ok(aliases)
+    }
+
+    /// Purge each move's old rows, index its destination when the walker
+    /// sees it, and alias the old handles to the new nodes.
+    fn apply_moves(
+        &mut self,
+        moves: &[(String, String)],
+        walked: &HashSet<String>,
+    ) -> crate::Result<MoveFixupReport> {
+        let mut report = MoveFixupReport::default();
+        let mut unindexed = Vec::new();
+        let mut old_nodes = Vec::with_capacity(moves.len());
+        for (from, to) in moves {
+            let nodes = self.file_nodes(from)?;
+            report.rows_purged += nodes.len();
+            old_nodes.push(nodes);
+            if walked.contains(to) && !self.is_indexed(to)? {
+                unindexed.push(self.path_style.native(to));
+            }
+        }
+        let from: Vec<String> = moves.iter().map(|(from, _)| from.clone()).collect();
+        self.purge_paths(&from)?;
+        if !unindexed.is_empty() {
+            self.index_paths(&unindexed)?;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        for ((from, to), old) in moves.iter().zip(&old_nodes) {
+            let indexed = walked.contains(to) && self.is_indexed(to)?;
+            let pairs = if indexed {
+                alias_pairs(old, &self.file_nodes(to)?)
+            } else {
+                Vec::new()
+            };
+            self.record_aliases(&pairs, now)?;
+            report.aliases_created += pairs.len();
+            report.moves.push(FileMove {
+                from: from.clone(),
+                to: to.to_string(),
+                indexed,
+                aliases: pairs.len(),
+            });
+        }
+        report.moves_processed = report.moves.len();
+        Ok(report)
+    }
+
+    /// `(gone, destination)` for files in `gone` whose indexed content hash
+    /// matches a walked file not yet indexed elsewhere under it. Only
+    /// destinations sharing an extension with some gone file are read, and
+    /// a destination with the gone file's name wins a tie.
+    fn moves_by_content(
+        &self,
+        gone: &[String],
+        indexed: &HashMap<String, IndexedFile>,
+        walked: &HashSet<String>,
+    ) -> Vec<(String, String)> {
+        if gone.is_empty() {
+            return Vec::new();
+        }
+        let extensions: HashSet<Option<&str>> = gone.iter().map(|p| extension(p)).collect();
+        let mut by_hash: HashMap<Vec<u8>, Vec<&str>> = HashMap::new();
+        for path in walked {
+            let hash = match indexed.get(path) {
+                Some((hash, _)) => hash.clone(),
+                None if extensions.contains(&extension(path)) => {
+                    match std::fs::read(self.repo_root.join(self.path_style.native(path))) {
+                        Ok(content) => Sha256::digest(&content).to_vec(),
+                        Err(_) => continue,
+                    }
+                }
+                None => continue,
+            };
+            by_hash.entry(hash).or_default().push(path);
+        }
+
+        let mut claimed = HashSet::new();
+        let mut moves = Vec::new();
+        for from in gone {
+            let Some(candidates) = indexed.get(from).and_then(|(hash, _)| by_hash.get(hash)) else {
+                continue;
+            };
+            let mut open = candidates.iter().filter(|to| !claimed.contains(**to));
+            let first = open.clone().next();
+            let to = open.find(|to| file_name(to) == file_name(from)).or(first);
+            if let Some(to) = to {
+                claimed.insert(*to);
+                moves.push((from.clone(), to.to_string()));
+            }
+        }
+        moves
+    }
+
+    /// Every indexed file across databases, by path.
+    fn indexed_files(&self) -> crate::Result<HashMap<String, IndexedFile>> {
+        let mut files = HashMap::new();
+        for index in self.all_indexes() {
+            let mut stmt = index
+                .conn
+                .prepare("SELECT path, content_hash, path_bytes FROM files")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, (row.get(1)?, row.get(2)?)))
+            })?;
+            for row in rows {
+                let (path, file) = row?;
+                files.insert(path, file);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Repo-relative paths the walker sees, i.e. what indexing would pick up.
+    fn walked_paths(&self) -> crate::Result<HashSet<String>> {
+        Ok(self
+            .walk_files("**/*")?
+            .iter()
+            .map(|path| self.relative_display_path(path))
+            .collect())
+    }
+
+    /// Whether the indexed `path` still names a file on disk. Paths that
+    /// can't be mapped back to disk count as present, so they aren't purged.
+    fn on_disk(&self, path: &str, path_bytes: Option<&[u8]>) -> bool {
+        self.disk_path(path, path_bytes)
+            .map(|full| full.is_file())
+            .unwrap_or(true)
+    }
+
+    fn is_indexed(&self, path: &str) -> crate::Result<bool> {
+        for index in self.all_indexes() {
+            let found = index
+                .conn
+                .query_row("SELECT 1 FROM files WHERE path = ?", params![path], |_| {
+                    Ok(())
+                })
+                .optional()?;
+            if found.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Remove `paths` from every database; only the owning one has rows.
+    fn purge_paths(&mut self, paths: &[String]) -> crate::Result<()> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        self.remove_paths(paths)?;
+        for shard in self.shards.reachable_mut(None) {
+            shard.index.remove_paths(paths)?;
+        }
+        Ok(())
+    }
+
+    /// Store `(old, new)` aliases, repointing aliases that led to an old
+    /// handle so chains of moves resolve in one step.
+    fn record_aliases(&mut self, pairs: &[(String, String)], now: i64) -> crate::Result<()> {
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        let tx = self.conn.transaction()?;
+        for (old, new) in pairs {
+            tx.execute(
+                "UPDATE handle_aliases SET new_handle_id = ?2 WHERE new_handle_id = ?1",
+                params![old, new],
+            )?;
+            tx.execute(
+                "INSERT OR REPLACE INTO handle_aliases (old_handle_id, new_handle_id, created_at)
+                 VALUES (?1, ?2, ?3)",
+                params![old, new, now],
+            )?;
+        }
+        // A file moved back answers under its own ids again
+        tx.execute(
+            "DELETE FROM handle_aliases WHERE old_handle_id = new_handle_id",
+            [],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn moves_commit(&self) -> crate::Result<Option<String>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?",
+                params![MOVES_COMMIT_META_KEY],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn record_moves_commit(&self, head: &str) -> crate::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES (?, ?)",
+            params![MOVES_COMMIT_META_KEY, head],
+        )?;
+        Ok(())
+    }
+}
+
+/// `(old, new)` raw handle ids pairing each old node with an unclaimed new
+/// one: same content hash first, else same kind, name and parent.
+fn alias_pairs(old: &[IndexedNode], new: &[IndexedNode]) -> Vec<(String, String)> {
+    let mut by_hash: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut by_name: HashMap<(u8, &str, Option<&str>), Vec<&str>> = HashMap::new();
+    for node in new {
+        let id = node.handle.id.raw();
+        if let Some(hash) = &node.content_hash {
+            by_hash.entry(hash).or_default().push(id);
+        }
+        if let Some(key) = name_key(node) {
+            by_name.entry(key).or_default().push(id);
+        }
+    }
+
+    let mut claimed: HashSet<&str> = HashSet::new();
+    let mut pairs = Vec::new();
+    for node in old {
+        let by_content = node
+            .content_hash
+            .as_deref()
+            .and_then(|hash| first_unclaimed(by_hash.get(hash), &claimed));
+        let matched =
+            by_content.or_else(|| first_unclaimed(by_name.get(&name_key(node)?), &claimed));
+        if let Some(new_id) = matched {
+            claimed.insert(new_id);
+            pairs.push((node.handle.id.raw().to_string(), new_id.to_string()));
+        }
+    }
+    pairs
+}
+
+/// Kind, name and parent of a named node.
+fn name_key(node: &IndexedNode) -> Option<(u8, &str, Option<&str>)> {
+    Some((
+        node.handle.node_type.as_int(),
+        node.name.as_deref()?,
+        node.parent_name.as_deref(),
+    ))
+}
+
+fn first_unclaimed<'a>(ids: Option<&Vec<&'a str>>, claimed: &HashSet<&str>) -> Option<&'a str> {
+    ids?.iter().copied().find(|id| !claimed.contains(id))
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn extension(path: &str) -> Option<&str> {
+    Path::new(file_name(path)).extension()?.to_str()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::QueryParams;
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+    use std::fs;
+
+    /// `src/net/{client,retry}.rs` and `src/main.rs`, indexed, and committed
+    /// first when `with_git`.
+    fn net_repo(with_git: bool) -> (FixtureRepo, RepoIndex) {
+        let builder = FixtureRepoBuilder::new()
+            .file(
+                "src/net/client.rs",
+                "pub struct Client;\n\npub fn connect() -> Client {\n    Client\n}\n",
+            )
+            .file(
+                "src/net/retry.rs",
+                "pub fn retry_with_backoff(attempts: u32) -> u32 {\n    attempts * 2\n}\n",
+            )
+            .file("src/main.rs", "fn main() {}\n");
+        let repo = if with_git {
+            builder.with_git_history().build()
+        } else {
+            builder.build()
+        };
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        (repo, index)
+    }
+
+    fn symbol_handle(index: &RepoIndex, symbol: &str) -> (String, String) {
+        let result = index.query_params(QueryParams::symbol(symbol)).unwrap();
+        assert_eq!(result.handles.len(), 1, "{symbol} indexed once");
+        let handle = &result.handles[0];
+        (handle.id.to_string(), handle.file_path.clone())
+    }
+
+    #[test]
+    fn git_moves_purge_old_rows_and_alias_old_handles() {
+        let (repo, mut index) = net_repo(true);
+        let root = repo.path();
+        // The first run only recorded where history stands
+        assert_eq!(index.index("**/*.rs").unwrap().moves_fixed, 0);
+        let (old_id, _) = symbol_handle(&index, "retry_with_backoff");
+        let before = index.expand(std::slice::from_ref(&old_id)).unwrap();
+
+        fs::create_dir_all(root.join("crates")).unwrap();
+        repo.git(&["mv", "src/net", "crates/net"]);
+        // An ignored destination is purged from, never indexed
+        fs::create_dir_all(root.join("node_modules")).unwrap();
+        repo.git(&["mv", "src/main.rs", "node_modules/main.rs"]);
+        repo.git(&["commit", "-qm", "move net"]);
+        let stats = index.index("**/*.rs").unwrap();
+        assert_eq!(stats.moves_fixed, 3);
+
+        let (new_id, path) = symbol_handle(&index, "retry_with_backoff");
+        assert_eq!(path, "crates/net/retry.rs");
+        assert_ne!(new_id, old_id);
+        let after = index.expand(std::slice::from_ref(&old_id)).unwrap();
+        assert_eq!(after, before, "the old handle still expands");
+        assert!(!index
+            .indexed_paths()
+            .unwrap()
+            .iter()
+            .any(|p| p.starts_with("src/") || p.starts_with("node_modules/")));
+
+        // Nothing is left for an explicit run
+        let report = index.fixup_moves().unwrap();
+        assert_eq!(report, MoveFixupReport::default());
+    }
+
+    #[test]
+    fn content_matches_find_moves_without_git() {
+        let (repo, mut index) = net_repo(false);
+        let root = repo.path();
+        let (old_id, _) = symbol_handle(&index, "connect");
+        fs::create_dir_all(root.join("lib")).unwrap();
+        fs::rename(root.join("src/net"), root.join("lib/net")).unwrap();
+        fs::remove_file(root.join("src/main.rs")).unwrap();
+        // Without git, a move into an ignored directory reads as a removal
+        fs::create_dir_all(root.join("node_modules")).unwrap();
+        fs::rename(
+            root.join("lib/net/retry.rs"),
+            root.join("node_modules/retry.rs"),
+        )
+        .unwrap();
+
+        let report = index.fixup_moves().unwrap();
+        let moves: Vec<(&str, &str, bool)> = report
+            .moves
+            .iter()
+            .map(|m| (m.from.as_str(), m.to.as_str(), m.indexed))
+            .collect();
+        assert_eq!(moves, [("src/net/client.rs", "lib/net/client.rs", true)]);
+        assert_eq!(report.moves_processed, 1);
+        assert_eq!(report.files_removed, 2);
+        assert_eq!(report.aliases_created, report.moves[0].aliases);
+        assert!(report.aliases_created >= 2, "struct and fn aliased");
+        assert!(report.rows_purged >= 4);
+
+        let (new_id, path) = symbol_handle(&index, "connect");
+        assert_eq!(path, "lib/net/client.rs");
+        let expanded = index.expand(&[old_id, new_id]).unwrap();
+        assert_eq!(expanded[0].1, expanded[1].1);
+        let mut paths = index.indexed_paths().unwrap();
+        paths.retain(|p| p.ends_with(".rs"));
+        assert_eq!(paths, ["lib/net/client.rs"]);
+        assert!(index
+            .query_params(QueryParams::symbol("retry_with_backoff"))
+            .unwrap()
+            .handles
+            .is_empty());
+    }
+}
@@ -0,0 +1,326 @@
+//! Where an index's tokens go: totals per node type and the largest nodes,
+//! for deciding what to leave out of indexing.
+//!
+//! The breakdown groups over every node row, which is slow on indexes with
+//! millions of nodes, so each database caches its own in the `meta` table.
+//! The cache is keyed by the files table's `MAX(indexed_at)`, file count and
+//! token total, which any reindex or removal changes.
+
+use crate::document::NodeType;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::churn::CHURNING_FILES;
+use super::expand::time_ago;
+use super::{IndexStatus, RepoIndex};
+
+/// Individual nodes listed in [`NodeBreakdown::largest`].
+pub const LARGEST_NODES: usize = 10;
+
+/// `meta` key of the cached breakdown
+const BREAKDOWN_META_KEY: &str = "node_breakdown";
+
+/// Prefix of the cache key; bumped when the cached breakdown's shape
+/// changes, so older entries are recomputed
+const BREAKDOWN_FORMAT: &str = "2";
+
+/// Node counts and tokens per node type, and the largest nodes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeBreakdown {
+    /// One entry per node type present, most tokens first
+    pub by_type: Vec<NodeTypeStats>,
+    /// Up to [`LARGEST_NODES`] nodes, most tokens first
+    pub largest: Vec<LargeNode>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeTypeStats {
+    pub node_type: NodeType,
+    pub nodes: usize,
+    pub total_tokens: usize,
+    /// `total_tokens / nodes`, rounded down
+    pub avg_tokens: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LargeNode {
+    pub handle_id: String,
+    pub file_path: String,
+    pub node_type: NodeType,
+    pub line_range: (usize, usize),
+    pub token_count: usize,
+}
+
+/// A database's breakdown and the files-table state it was computed from.
+#[derive(Serialize, Deserialize)]
+struct CachedBreakdown {
+    key: String,
+    breakdown: NodeBreakdown,
+}
+
+impl NodeBreakdown {
+    /// Fold another database's breakdown into this one.
+    fn merge(&mut self, other: NodeBreakdown) {
+        let mut totals: HashMap<NodeType, (usize, usize)> = self
+            .by_type
+            .iter()
+            .map(|s| (s.node_type, (s.nodes, s.total_tokens)))
+            .collect();
+        for stats in other.by_type {
+            let entry = totals.entry(stats.node_type).or_default();
+            entry.0 += stats.nodes;
+            entry.1 += stats.total_tokens;
+        }
+        self.by_type = totals
+            .into_iter()
+            .map(|(node_type, (nodes, total_tokens))| NodeTypeStats {
+                node_type,
+                nodes,
+                total_tokens,
+                avg_tokens: total_tokens / nodes.max(1),
+            })
+            .collect();
+        self.by_type.sort_by(|a, b| {
+            b.total_tokens
+                .cmp(&a.total_tokens)
+                .then(a.node_type.as_int().cmp(&b.node_type.as_int()))
+        });
+
+        self.largest.extend(other.largest);
+        self.largest.sort_by(|a, b| {
+            b.token_count
+                .cmp(&a.token_count)
+                .then_with(|| a.file_path.cmp(&b.file_path))
+                .then(a.line_range.cmp(&b.line_range))
+        });
+        self.largest.truncate(LARGEST_NODES);
+    }
+}
+
+impl RepoIndex {
+    /// [`status`](Self::status) plus the per-node-type breakdown, aggregated
+    /// across shards.
+    pub fn status_detailed(&self) -> crate::Result<IndexStatus> {
+        let mut status = self.status()?;
+        let mut breakdown = NodeBreakdown::default();
+        let mut churning = Vec::new();
+        let mut last_optimize: Option<i64> = None;
+        for index in self.all_indexes() {
+            breakdown.merge(index.local_node_breakdown()?);
+            churning.extend(index.local_churning_files(CHURNING_FILES)?);
+            last_optimize = last_optimize.max(index.local_last_fts_optimize()?);
+        }
+        churning.sort_by(|a, b| {
+            b.reindex_count
+                .cmp(&a.reindex_count)
+                .then(b.token_count.cmp(&a.token_count))
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        churning.truncate(CHURNING_FILES);
+        status.node_breakdown = Some(breakdown);
+        status.churning_files = Some(churning);
+        status.last_fts_optimize = last_optimize.map(time_ago);
+        Ok(status)
+    }
+
+    /// This database's breakdown, from the `meta` cache when the files
+    /// table hasn't changed since it was computed.
+    fn local_node_breakdown(&self) -> crate::Result<NodeBreakdown> {
+        let key: String = self.conn.query_row(
+            "SELECT ? || ':' || COALESCE(MAX(indexed_at), 0) || ':' || COUNT(*) || ':' ||
+                    COALESCE(SUM(token_count), 0)
+             FROM files",
+            params![BREAKDOWN_FORMAT],
+            |row| row.get(0),
+        )?;
+        let cached: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?",
+                params![BREAKDOWN_META_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(cached) = cached.and_then(|v| serde_json::from_str::<CachedBreakdown>(&v).ok())
+        {
+            if cached.key == key {
+                return Ok(cached.breakdown);
+            }
+        }
+
+        let breakdown = self.compute_node_breakdown()?;
+        let value = serde_json::to_string(&CachedBreakdown {
+            key,
+            breakdown: breakdown.clone(),
+        })?;
+        // A failed write (e.g. a read-only index) only costs the next call a recompute
+        let _ = self.conn.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES (?, ?)",
+            params![BREAKDOWN_META_KEY, value],
+        );
+        Ok(breakdown)
+    }
+
+    fn compute_node_breakdown(&self) -> crate::Result<NodeBreakdown> {
+        let mut stmt = self.conn.prepare(
+            "SELECT node_type, COUNT(*), COALESCE(SUM(token_count), 0)
+             FROM nodes GROUP BY node_type",
+        )?;
+        let by_type = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, u8>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter_map(|(node_type, nodes, tokens)| {
+                let (nodes, total_tokens) = (nodes.max(0) as usize, tokens.max(0) as usize);
+                Some(NodeTypeStats {
+                    node_type: NodeType::from_int(node_type)?,
+                    nodes,
+                    total_tokens,
+                    avg_tokens: total_tokens / nodes.max(1),
+                })
+            })
+            .collect();
+
+        let mut stmt = self.conn.prepare(
+            "SELECT n.handle_id, f.path, n.node_type, n.line_start, n.line_end, n.token_count
+             FROM nodes n JOIN files f ON n.file_id = f.id
+             ORDER BY n.token_count DESC, f.path, n.line_start
+             LIMIT ?",
+        )?;
+        let largest = stmt
+            .query_map(params![LARGEST_NODES as i64], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, u8>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, i64>(4)?,
+                    row.get::<_, i64>(5)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter_map(|(handle_id, file_path, node_type, start, end, tokens)| {
+                Some(LargeNode {
+                    handle_id: format!("h{handle_id}"),
+                    file_path,
+                    node_type: NodeType::from_int(node_type)?,
+                    line_range: (start.max(0) as usize, end.max(0) as usize),
+                    token_count: tokens.max(0) as usize,
+                })
+            })
+            .collect();
+
+        let mut breakdown = NodeBreakdown::default();
+        breakdown.merge(NodeBreakdown { by_type, largest });
+        Ok(breakdown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+
+    fn indexed_repo() -> (FixtureRepo, RepoIndex) {
+        let body = "    let total = values.iter().sum::<i64>();\n".repeat(40);
+        let repo = FixtureRepoBuilder::new()
+            .file(
+                "src/lib.rs",
+                format!("fn big(values: &[i64]) {{\n{body}}}\n\nfn small() {{}}\n\nstruct Point {{ x: i32 }}\n"),
+            )
+            .file(
+                "README.md",
+                "# Setup\n\nInstall the tool and run it.\n\n# Usage\n\nCall it with a path.\n",
+            )
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*").unwrap();
+        (repo, index)
+    }
+
+    #[test]
+    fn breakdown_totals_track_file_tokens() {
+        let (_dir, index) = indexed_repo();
+        let status = index.status_detailed().unwrap();
+        let breakdown = status.node_breakdown.unwrap();
+
+        let node_tokens: usize = breakdown.by_type.iter().map(|s| s.total_tokens).sum();
+        let file_tokens = status.total_tokens;
+        // Nodes leave out the blank lines between them, and each node's
+        // estimate rounds on its own
+        let slack = file_tokens / 10 + breakdown.largest.len();
+        assert!(
+            node_tokens.abs_diff(file_tokens) <= slack,
+            "nodes {node_tokens} vs files {file_tokens}"
+        );
+
+        let functions = breakdown
+            .by_type
+            .iter()
+            .find(|s| s.node_type == NodeType::Function)
+            .unwrap();
+        assert_eq!(functions.nodes, 2);
+        assert_eq!(functions.avg_tokens, functions.total_tokens / 2);
+        assert_eq!(breakdown.by_type[0].node_type, NodeType::Function);
+
+        let largest = &breakdown.largest[0];
+        assert_eq!(largest.file_path, "src/lib.rs");
+        assert_eq!(largest.line_range.0, 1);
+        assert!(breakdown
+            .largest
+            .windows(2)
+            .all(|w| w[0].token_count >= w[1].token_count));
+    }
+
+    #[test]
+    fn breakdown_is_cached_until_the_files_change() {
+        let (_dir, mut index) = indexed_repo();
+        let first = index.status_detailed().unwrap().node_breakdown.unwrap();
+
+        // A stale cache entry under the current key is served as is
+        let mut doctored = first.clone();
+        doctored.largest.clear();
+        let key: String = index
+            .conn
+            .query_row(
+                "SELECT json_extract(value, '$.key') FROM meta WHERE key = ?",
+                params![BREAKDOWN_META_KEY],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let value = serde_json::to_string(&CachedBreakdown {
+            key,
+            breakdown: doctored.clone(),
+        })
+        .unwrap();
+        index
+            .conn
+            .execute(
+                "UPDATE meta SET value = ? WHERE key = ?",
+                params![value, BREAKDOWN_META_KEY],
+            )
+            .unwrap();
+        assert_eq!(
+            index.status_detailed().unwrap().node_breakdown.unwrap(),
+            doctored
+        );
+
+        // Dropping a file changes the key, so the breakdown is recomputed
+        index.invalidate(Some("README.md")).unwrap();
+        let recomputed = index.status_detailed().unwrap().node_breakdown.unwrap();
+        assert!(!recomputed.largest.is_empty());
+        assert!(recomputed
+            .by_type
+            .iter()
+            .all(|s| s.node_type != NodeType::Section));
+    }
+}
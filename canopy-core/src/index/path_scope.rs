@@ -0,0 +1,139 @@
+//! Path fragment filters: `(path "session" ...)` and `path_contains`.
+//!
+//! Agents often know part of a path ("the session middleware") but not a
+//! glob that matches it: `session` as a glob matches only a top-level file of
+//! that name. A path scope keeps searches to files whose path contains the
+//! fragment, case-insensitively, by appending [`RepoIndex::path_filter`] to
+//! each search's SQL like the recency cutoff, so it applies before the limit.
+
+use super::search::escape_like;
+use super::RepoIndex;
+
+/// Restricts searches of a set of databases to files whose path contains a
+/// fragment; dropping it restores the previous restriction.
+pub(crate) struct PathScope<'a> {
+    indexes: Vec<&'a RepoIndex>,
+}
+
+impl<'a> PathScope<'a> {
+    /// Keep searches of `indexes` to paths containing `fragment`. Nested
+    /// scopes must all match.
+    pub(crate) fn new(indexes: impl IntoIterator<Item = &'a RepoIndex>, fragment: &str) -> Self {
+        let indexes: Vec<&RepoIndex> = indexes.into_iter().collect();
+        for index in &indexes {
+            index.path_fragments.borrow_mut().push(fragment.to_string());
+        }
+        Self { indexes }
+    }
+}
+
+impl Drop for PathScope<'_> {
+    fn drop(&mut self) {
+        for index in &self.indexes {
+            index.path_fragments.borrow_mut().pop();
+        }
+    }
+}
+
+impl RepoIndex {
+    /// `AND` clauses keeping rows whose file (aliased `f`) has every path
+    /// fragment of the current scopes in its path; empty outside one. Scope
+    /// clauses are inlined rather than bound, so each fragment goes in as a
+    /// quoted literal with its `'` doubled. SQLite's `LIKE` ignores ASCII case.
+    pub(crate) fn path_filter(&self) -> String {
+        self.path_fragments
+            .borrow()
+            .iter()
+            .map(|fragment| {
+                let pattern = format!("%{}%", escape_like(fragment)).replace('\'', "''");
+                format!(" AND f.path LIKE '{pattern}' ESCAPE '\\'")
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::QueryParams;
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+
+    fn layered_repo() -> (FixtureRepo, RepoIndex) {
+        let repo = FixtureRepoBuilder::new()
+            .files(&[
+                (
+                    "src/middleware/Session/store.ts",
+                    "export function persistToken() {}\n",
+                ),
+                ("src/auth/login.ts", "export function persistToken() {}\n"),
+                (
+                    "src/auth/100%_done.ts",
+                    "export function persistToken() {}\n",
+                ),
+            ])
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*").unwrap();
+        (repo, index)
+    }
+
+    fn paths(index: &RepoIndex, params: QueryParams) -> Vec<String> {
+        let result = index.query_params(params).unwrap();
+        let mut paths: Vec<String> = result.handles.into_iter().map(|h| h.file_path).collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    #[test]
+    fn path_contains_scopes_by_fragment_ignoring_case() {
+        let (_dir, index) = layered_repo();
+        let session = |params: QueryParams| params.with_path_contains("session");
+
+        assert_eq!(
+            paths(&index, session(QueryParams::pattern("persistToken"))),
+            vec!["src/middleware/Session/store.ts"]
+        );
+        assert_eq!(
+            paths(&index, session(QueryParams::symbol("persistToken"))),
+            vec!["src/middleware/Session/store.ts"]
+        );
+        // Alone it lists the matching files
+        assert_eq!(
+            paths(&index, QueryParams::default().with_path_contains("AUTH")),
+            vec!["src/auth/100%_done.ts", "src/auth/login.ts"]
+        );
+        // `%` and `_` are literal, not wildcards
+        assert_eq!(
+            paths(&index, QueryParams::default().with_path_contains("0%_d")),
+            vec!["src/auth/100%_done.ts"]
+        );
+        assert!(paths(&index, QueryParams::default().with_path_contains("n_d")).is_empty());
+    }
+
+    #[test]
+    fn path_contains_ands_with_glob() {
+        let (_dir, index) = layered_repo();
+        let params = QueryParams::pattern("persistToken")
+            .with_glob("src/auth/**")
+            .with_path_contains("login");
+        assert_eq!(paths(&index, params), vec!["src/auth/login.ts"]);
+
+        let params = QueryParams::pattern("persistToken")
+            .with_glob("src/auth/**")
+            .with_path_contains("session");
+        assert!(paths(&index, params).is_empty());
+    }
+
+    #[test]
+    fn unmatched_bare_glob_suggests_path_contains() {
+        let (_dir, index) = layered_repo();
+        let result = index
+            .query_params(QueryParams::pattern("persistToken").with_glob("session"))
+            .unwrap();
+        assert!(result.handles.is_empty());
+        let note = result.expand_note.unwrap();
+        assert!(note.contains("path_contains \"session\""), "{note}");
+    }
+}
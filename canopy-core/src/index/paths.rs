@@ -0,0 +1,521 @@
+//! Repo-relative paths: separators, case, and names that aren't valid UTF-8.
+//!
+//! `files.path` holds a display form: the path itself when it is valid UTF-8,
+//! otherwise the path with each invalid byte escaped as `\xNN`. Globs, shard
+//! routing and handle output all work on that form. For non-UTF-8 paths the
+//! raw bytes are kept in `files.path_bytes`; handle ids hash those bytes and
+//! expand opens the file through them.
+//!
+//! The display form always separates with `/`, whatever the host uses, so a
+//! repo indexed on Windows stores (and hashes into handle ids) the same paths
+//! as on Unix. A [`PathStyle`] converts at the walk boundary and on globs and
+//! paths from callers, and folds case where the filesystem does.
+
+use crate::config::Config;
+use crate::error::CanopyError;
+use rusqlite::{params, OptionalExtension};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::{Component, Path, PathBuf};
+
+use super::RepoIndex;
+
+/// How a repo's filesystem spells and compares paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathStyle {
+    /// Separator of paths from the walkers and from callers; stored paths use `/`
+    pub separator: char,
+    /// Whether `Src/Main.rs` and `src/main.rs` name the same file
+    pub case_insensitive: bool,
+}
+
+impl Default for PathStyle {
+    fn default() -> Self {
+        Self::host()
+    }
+}
+
+impl PathStyle {
+    /// This host's separator, with case folding where the default
+    /// filesystem is case-insensitive (Windows and macOS).
+    pub fn host() -> Self {
+        Self {
+            separator: std::path::MAIN_SEPARATOR,
+            case_insensitive: cfg!(any(windows, target_os = "macos")),
+        }
+    }
+
+    /// The host's style, with `[indexing] case_insensitive_paths` overriding
+    /// the detected case sensitivity.
+    pub fn for_config(config: &Config) -> Self {
+        let host = Self::host();
+        Self {
+            case_insensitive: config
+                .indexing
+                .case_insensitive_paths
+                .unwrap_or(host.case_insensitive),
+            ..host
+        }
+    }
+
+    /// `path` with this style's separator replaced by `/`.
+    pub fn normalize<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        if self.separator == '/' || !path.contains(self.separator) {
+            Cow::Borrowed(path)
+        } else {
+            Cow::Owned(path.replace(self.separator, "/"))
+        }
+    }
+
+    /// Key under which `path` compares equal to every spelling of the same file.
+    pub fn key(&self, path: &str) -> String {
+        let normalized = self.normalize(path);
+        if self.case_insensitive {
+            normalized.to_lowercase()
+        } else {
+            normalized.into_owned()
+        }
+    }
+
+    /// Whether `a` and `b` name the same file.
+    pub fn same_path(&self, a: &str, b: &str) -> bool {
+        a == b || self.key(a) == self.key(b)
+    }
+
+    /// Matcher for a glob over stored paths.
+    pub fn glob(&self, pattern: &str) -> crate::Result<globset::GlobMatcher> {
+        Ok(globset::GlobBuilder::new(&self.normalize(pattern))
+            .case_insensitive(self.case_insensitive)
+            .build()
+            .map_err(|e| CanopyError::GlobPattern(e.to_string()))?
+            .compile_matcher())
+    }
+
+    /// Stored (display) form of a repo-relative path from a walker.
+    pub(crate) fn display(&self, path: &Path) -> String {
+        let display = display_path(path);
+        match self.normalize(&display) {
+            Cow::Borrowed(_) => display,
+            Cow::Owned(normalized) => normalized,
+        }
+    }
+
+    /// Relative on-disk path of a stored path.
+    pub(crate) fn native(&self, path: &str) -> PathBuf {
+        if self.separator == '/' {
+            PathBuf::from(path)
+        } else {
+            PathBuf::from(path.replace('/', &self.separator.to_string()))
+        }
+    }
+}
+
+/// Repo-relative paths compared under a [`PathStyle`].
+#[derive(Debug, Clone, Default)]
+pub struct PathSet {
+    style: PathStyle,
+    keys: HashSet<String>,
+}
+
+impl PathSet {
+    pub fn new(style: PathStyle) -> Self {
+        Self {
+            style,
+            keys: HashSet::new(),
+        }
+    }
+
+    pub fn from_paths<I, S>(style: PathStyle, paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut set = Self::new(style);
+        for path in paths {
+            set.insert(path.as_ref());
+        }
+        set
+    }
+
+    pub fn style(&self) -> PathStyle {
+        self.style
+    }
+
+    /// Returns whether the path was new.
+    pub fn insert(&mut self, path: &str) -> bool {
+        self.keys.insert(self.style.key(path))
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.keys.contains(&self.style.key(path))
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Display form of `path`: unchanged when UTF-8, `\xNN`-escaped otherwise.
+pub(crate) fn display_path(path: &Path) -> String {
+    if let Some(s) = path.to_str() {
+        return s.to_string();
+    }
+    let bytes = path.as_os_str().as_encoded_bytes();
+    let mut display = String::with_capacity(bytes.len() + 8);
+    for chunk in bytes.utf8_chunks() {
+        display.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            let _ = write!(display, "\\x{byte:02x}");
+        }
+    }
+    display
+}
+
+/// Raw bytes of `path`, or `None` when its display form already is the path.
+pub(super) fn raw_path_bytes(path: &Path) -> Option<Vec<u8>> {
+    match path.to_str() {
+        Some(_) => None,
+        None => Some(path.as_os_str().as_encoded_bytes().to_vec()),
+    }
+}
+
+/// Rebuild a path from bytes stored by [`raw_path_bytes`].
+#[cfg(unix)]
+pub(super) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+/// Rebuild a path from bytes stored by [`raw_path_bytes`].
+///
+/// Only Unix can produce such bytes from a walk, so elsewhere this is a
+/// best-effort lossy conversion.
+#[cfg(not(unix))]
+pub(super) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Why a listed path that climbs out of the repo root can't be indexed.
+pub(super) const OUTSIDE_REPO: &str = "outside the repository";
+
+/// A path listed for [`RepoIndex::index_paths`], resolved against the repo.
+pub(super) enum ListedPath {
+    /// An existing file: `(absolute, relative display path)`
+    File(PathBuf, String),
+    /// No file there any more; its display path
+    Missing(String),
+}
+
+impl RepoIndex {
+    /// Resolve a caller-listed `path`, or say why it can't be indexed.
+    ///
+    /// Relative paths are taken from the repo root. `..` may not climb out of
+    /// it, and an existing file may not resolve (through symlinks) outside it.
+    pub(super) fn resolve_listed_path(&self, path: &Path) -> Result<ListedPath, &'static str> {
+        let canonical_root = self.repo_root.canonicalize().ok();
+        let relative = if path.is_absolute() {
+            path.strip_prefix(&self.repo_root)
+                .ok()
+                .or_else(|| path.strip_prefix(canonical_root.as_ref()?).ok())
+                .ok_or(OUTSIDE_REPO)?
+        } else {
+            path
+        };
+        let mut normalized = PathBuf::new();
+        for component in relative.components() {
+            match component {
+                Component::Normal(part) => normalized.push(part),
+                Component::CurDir => {}
+                Component::ParentDir if normalized.pop() => {}
+                _ => return Err(OUTSIDE_REPO),
+            }
+        }
+        if normalized.as_os_str().is_empty() {
+            return Err("is a directory");
+        }
+
+        let absolute = self.repo_root.join(&normalized);
+        let display = self.relative_display_path(&absolute);
+        let metadata = match std::fs::metadata(&absolute) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(ListedPath::Missing(display));
+            }
+            Err(_) => return Err("unreadable"),
+        };
+        if metadata.is_dir() {
+            return Err("is a directory");
+        }
+        let inside = match (absolute.canonicalize(), canonical_root) {
+            (Ok(resolved), Some(root)) => resolved.starts_with(root),
+            _ => false,
+        };
+        if !inside {
+            return Err(OUTSIDE_REPO);
+        }
+        Ok(ListedPath::File(absolute, display))
+    }
+
+    /// How this repo's paths are spelled and compared.
+    pub fn path_style(&self) -> PathStyle {
+        self.path_style
+    }
+
+    /// Display form of `file` relative to the repo root.
+    pub(crate) fn relative_display_path(&self, file: &Path) -> String {
+        self.path_style
+            .display(file.strip_prefix(&self.repo_root).unwrap_or(file))
+    }
+
+    /// On-disk location of an indexed file, given its stored columns.
+    ///
+    /// Stored paths come from the database (or a service), so they are not
+    /// trusted: an absolute path, a `..` component, or a symlink resolving
+    /// outside the repo root is [`CanopyError::PathOutsideRepo`].
+    pub(super) fn disk_path(
+        &self,
+        path: &str,
+        path_bytes: Option<&[u8]>,
+    ) -> crate::Result<PathBuf> {
+        let relative = match path_bytes {
+            Some(bytes) => path_from_bytes(bytes),
+            None => self.path_style.native(path),
+        };
+        let full_path = self.repo_root.join(&relative);
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(CanopyError::PathOutsideRepo(full_path));
+        }
+
+        // Missing files can't escape; let the caller's read report them
+        let resolved = match full_path.canonicalize() {
+            Ok(resolved) => resolved,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(full_path),
+            Err(e) => return Err(e.into()),
+        };
+        let root = self.repo_root.canonicalize()?;
+        if !resolved.starts_with(&root) {
+            return Err(CanopyError::PathOutsideRepo(full_path));
+        }
+        Ok(full_path)
+    }
+
+    /// On-disk location of a file indexed in this database under `path`.
+    pub(super) fn indexed_disk_path(&self, path: &str) -> crate::Result<PathBuf> {
+        let path_bytes: Option<Vec<u8>> = self
+            .conn
+            .query_row(
+                "SELECT path_bytes FROM files WHERE path = ?",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        self.disk_path(path, path_bytes.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_helpers::{odd_file_name, setup_repo};
+    use super::*;
+    use crate::index::FileQueryOptions;
+    use std::fs;
+
+    #[test]
+    fn utf8_paths_display_unchanged() {
+        let path = Path::new("src/módulo/lib.rs");
+        assert_eq!(display_path(path), "src/módulo/lib.rs");
+        assert_eq!(raw_path_bytes(path), None);
+    }
+
+    const WINDOWS: PathStyle = PathStyle {
+        separator: '\\',
+        case_insensitive: true,
+    };
+
+    #[test]
+    fn injected_separator_normalizes_to_forward_slashes() {
+        assert_eq!(WINDOWS.normalize("src\\net\\tcp.rs"), "src/net/tcp.rs");
+        assert_eq!(WINDOWS.display(Path::new("src\\tcp.rs")), "src/tcp.rs");
+        assert_eq!(
+            WINDOWS.native("src/net/tcp.rs"),
+            PathBuf::from("src\\net\\tcp.rs")
+        );
+
+        let glob = WINDOWS.glob("src\\**\\*.rs").unwrap();
+        assert!(glob.is_match("src/net/tcp.rs"));
+        assert!(glob.is_match("Src/Net/Tcp.RS"));
+
+        // A `/` host leaves backslashes alone: they are part of the name
+        let unix = PathStyle {
+            separator: '/',
+            case_insensitive: false,
+        };
+        assert_eq!(unix.normalize("odd\\name.rs"), "odd\\name.rs");
+        assert!(!unix.glob("src/*.rs").unwrap().is_match("Src/main.rs"));
+    }
+
+    #[test]
+    fn path_sets_compare_under_their_style() {
+        let folded = PathSet::from_paths(WINDOWS, ["Src\\Main.rs"]);
+        assert!(folded.contains("src/main.rs"));
+        assert!(WINDOWS.same_path("SRC/main.rs", "src\\Main.rs"));
+
+        let exact = PathSet::from_paths(
+            PathStyle {
+                separator: '/',
+                case_insensitive: false,
+            },
+            ["Src/Main.rs"],
+        );
+        assert!(exact.contains("Src/Main.rs"));
+        assert!(!exact.contains("src/main.rs"));
+    }
+
+    #[test]
+    fn backslash_paths_index_glob_expand_invalidate() {
+        let dir = setup_repo(0);
+        // One file named with a backslash on Unix; src/Main.rs on Windows
+        fs::write(dir.path().join("src\\Main.rs"), "fn sep_needle() {}\n").unwrap();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.path_style = WINDOWS;
+        assert_eq!(index.index("**/*.rs").unwrap().files_indexed, 1);
+
+        let handles = index.search_code("sep_needle", 10).unwrap();
+        assert_eq!(handles.len(), 1);
+        assert_eq!(handles[0].file_path, "src/Main.rs");
+
+        // Ids hash the stored form, so they match a Unix checkout of the same file
+        let unix = setup_repo(0);
+        fs::write(unix.path().join("src/Main.rs"), "fn sep_needle() {}\n").unwrap();
+        let mut unix_index = RepoIndex::open(unix.path()).unwrap();
+        unix_index.index("**/*.rs").unwrap();
+        assert_eq!(
+            unix_index.search_code("sep_needle", 10).unwrap()[0].id,
+            handles[0].id
+        );
+
+        // Globs match across separators and case
+        let file = index
+            .get_file("src\\main.rs", &FileQueryOptions::new())
+            .unwrap()
+            .handles;
+        assert_eq!(file.len(), 1);
+        assert_eq!(file[0].file_path, "src/Main.rs");
+
+        // Expand rebuilds the on-disk path with the style's separator
+        let expanded = index.expand(&[handles[0].id.to_string()]).unwrap();
+        assert!(expanded[0].1.contains("sep_needle"));
+
+        assert_eq!(index.invalidate(Some("SRC\\main.rs")).unwrap(), 1);
+        assert!(index.indexed_paths().unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn invalid_bytes_escape_and_roundtrip() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"fixtures/bad\xff\xfe.rs"));
+        assert_eq!(display_path(path), "fixtures/bad\\xff\\xfe.rs");
+
+        let bytes = raw_path_bytes(path).unwrap();
+        assert_eq!(path_from_bytes(&bytes), path);
+    }
+
+    #[test]
+    fn odd_file_names_index_query_expand_invalidate() {
+        let dir = setup_repo(0);
+        let name = odd_file_name("vendored", "rs");
+        fs::write(dir.path().join("src").join(&name), "fn odd_needle() {}\n").unwrap();
+
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        assert_eq!(index.index("**/*.rs").unwrap().files_indexed, 1);
+
+        let display = display_path(&Path::new("src").join(&name));
+        let handles = index.search_code("odd_needle", 10).unwrap();
+        assert_eq!(handles.len(), 1);
+        assert_eq!(handles[0].file_path, display);
+        #[cfg(unix)]
+        assert_eq!(handles[0].file_path, "src/vendored\\xff.rs");
+
+        let expanded = index.expand(&[handles[0].id.to_string()]).unwrap();
+        assert!(expanded[0].1.contains("odd_needle"));
+
+        // Ids are stable across a forced reindex
+        index.invalidate(None).unwrap();
+        index.index("**/*.rs").unwrap();
+        let again = index.search_code("odd_needle", 10).unwrap();
+        assert_eq!(again[0].id, handles[0].id);
+
+        // Globs see the display form
+        let file = index
+            .get_file("src/vendored*.rs", &FileQueryOptions::new())
+            .unwrap()
+            .handles;
+        assert_eq!(file.len(), 1);
+        assert_eq!(file[0].file_path, display);
+
+        assert_eq!(index.invalidate(Some("src/vendored*")).unwrap(), 1);
+        assert!(index.indexed_paths().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stored_paths_outside_repo_are_rejected() {
+        let dir = setup_repo(1);
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        let handle = index.search_code("func_0", 1).unwrap().remove(0);
+
+        for bad_path in ["../escape.rs", "/etc/passwd", "src/../../escape.rs"] {
+            index
+                .conn
+                .execute("UPDATE files SET path = ?", params![bad_path])
+                .unwrap();
+            let err = index.expand(&[handle.id.to_string()]).unwrap_err();
+            assert!(
+                matches!(err, CanopyError::PathOutsideRepo(_)),
+                "{bad_path}: {err:?}"
+            );
+            assert!(matches!(
+                index.get_file("**", &FileQueryOptions::new()).unwrap_err(),
+                CanopyError::PathOutsideRepo(_)
+            ));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_escaping_repo_are_rejected() {
+        let dir = setup_repo(1);
+        let outside = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("src/file_0.rs");
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        let handle = index.search_code("func_0", 1).unwrap().remove(0);
+
+        // Same bytes, so only the containment check can stop the read
+        let secret = outside.path().join("secret.rs");
+        fs::copy(&file, &secret).unwrap();
+        fs::remove_file(&file).unwrap();
+        std::os::unix::fs::symlink(&secret, &file).unwrap();
+
+        let err = index.expand(&[handle.id.to_string()]).unwrap_err();
+        assert!(matches!(err, CanopyError::PathOutsideRepo(_)), "{err:?}");
+
+        // A symlink that stays inside the repo is still fine
+        let inner = dir.path().join("src/real.rs");
+        fs::copy(&secret, &inner).unwrap();
+        fs::remove_file(&file).unwrap();
+        std::os::unix::fs::symlink(&inner, &file).unwrap();
+        assert!(index.expand(&[handle.id.to_string()]).is_ok());
+    }
+}
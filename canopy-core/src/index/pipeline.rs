@@ -1,28 +1,38 @@
 //! Indexing pipeline: sequential and parallel paths, DB insertion, batch flushing.
 
-use super::symbol_cache::SymbolCacheEntry;
+use super::churn::{add_reindexed_tokens, next_churn};
+use super::freshness::{
+    FileAction, FileMeta, SkipCounts, SkipPolicy, SkipReason, SkipTally, SourceFile,
+};
+use super::generated::GeneratedDetector;
+use super::paths::raw_path_bytes;
+use super::search::dir_prefix;
+use super::symbol_cache::{short_hash_bytes, SymbolCache, SymbolCacheDelta, SymbolCacheEntry};
+use super::tokens::{fts_sample, identifier_parts};
 use super::RepoIndex;
-use crate::document::{NodeType, ParsedFile};
+use crate::config::Config;
+use crate::document::{DocumentNode, NodeMetadata, NodeType, ParsedFile, HEADING_PATH_SEPARATOR};
 use crate::handle::{generate_preview, HandleId};
-use crate::parse::{estimate_tokens, file_mtime, parse_file_with_hash, warm_bpe};
+use crate::parse::{estimate_tokens, parse_file_with_hash, warm_bpe};
+use crate::redaction::Redactor;
+use crate::schema::SchemaVersion;
+use crate::CanopyError;
 use rayon::prelude::*;
 use rusqlite::{params, Connection, OptionalExtension};
 use sha2::{Digest, Sha256};
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use super::IndexStats;
+use super::paths::{ListedPath, OUTSIDE_REPO};
+use super::{IndexPathError, IndexStats};
 
-/// Cached file metadata for batch skip checks during indexing
-struct FileMetaCache {
-    mtime: i64,
-    hash: [u8; 32],
-    indexed_at: i64,
-    tokens: usize,
-}
+/// `files` columns read into [`FileMeta`], in `file_meta_from_row` order
+const FILE_META_COLUMNS: &str = "mtime, content_hash, indexed_at, token_count, \
+     EXISTS (SELECT 1 FROM reparse_pending p WHERE p.file_id = files.id)";
 
 impl RepoIndex {
     /// Threshold: batches with <= this many files use sequential indexing
@@ -34,124 +44,305 @@ impl RepoIndex {
     ///
     /// Dispatches to `index_sequential` for small batches or `index_pipeline`
     /// for large ones. The threshold is [`SEQUENTIAL_THRESHOLD`](Self::SEQUENTIAL_THRESHOLD).
+    /// With `shard_by` configured, files are routed to their shard databases.
     pub fn index(&mut self, glob: &str) -> crate::Result<IndexStats> {
-        let files = self.walk_files(glob)?;
+        self.ensure_writable()?;
+        let candidates = self.glob_candidates(glob)?;
 
-        let now_secs = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
-        let ttl_secs = self.config.ttl_duration().as_secs() as i64;
+        let mut stats = if self.shards.has_patterns() {
+            self.index_routed(candidates)?
+        } else {
+            self.index_candidates(&candidates)?
+        };
+        stats.moves_fixed = self.fixup_committed_moves()?;
+        stats.commits_indexed = self.index_history_if_enabled()?;
+        Ok(stats)
+    }
 
-        let candidates: Vec<(PathBuf, String)> = files
+    /// `(absolute, relative)` paths of the files [`index`](Self::index) walks for `glob`.
+    pub(super) fn glob_candidates(&self, glob: &str) -> crate::Result<Vec<(PathBuf, String)>> {
+        let files = self.walk_files(glob)?;
+        Ok(files
             .iter()
             .map(|file_path| {
-                let relative_path = file_path
-                    .strip_prefix(&self.repo_root)
-                    .unwrap_or(file_path)
-                    .to_string_lossy()
-                    .to_string();
+                let relative_path = self.relative_display_path(file_path);
                 (file_path.clone(), relative_path)
             })
-            .collect();
+            .collect())
+    }
+
+    /// Index exactly `paths` (relative to the repo root, or absolute within
+    /// it), with no file discovery.
+    ///
+    /// Files go through the same hash/mtime skip checks as [`index`](Self::index).
+    /// Listed files that no longer exist have their rows removed, as do the
+    /// files under a listed directory that no longer exists. Paths outside
+    /// the repo, directories, and missing files that were never indexed are
+    /// reported in [`IndexStats::errors`] rather than failing the run.
+    pub fn index_paths(&mut self, paths: &[PathBuf]) -> crate::Result<IndexStats> {
+        self.ensure_writable()?;
+        let mut candidates: Vec<(PathBuf, String)> = Vec::new();
+        let mut missing: Vec<(&PathBuf, String)> = Vec::new();
+        let mut errors = Vec::new();
+        let mut seen = HashSet::new();
+        for path in paths {
+            match self.resolve_listed_path(path) {
+                Ok(ListedPath::File(absolute, relative)) => {
+                    if seen.insert(relative.clone()) {
+                        candidates.push((absolute, relative));
+                    }
+                }
+                Ok(ListedPath::Missing(relative)) => missing.push((path, relative)),
+                Err(reason) => errors.push(IndexPathError {
+                    path: path.display().to_string(),
+                    reason: reason.to_string(),
+                }),
+            }
+        }
 
-        if candidates.len() <= Self::SEQUENTIAL_THRESHOLD {
-            self.index_sequential(&candidates, now_secs, ttl_secs)
+        let mut stats = if self.shards.has_patterns() {
+            self.index_routed(candidates)?
         } else {
-            self.index_pipeline(&candidates, now_secs, ttl_secs)
+            self.index_candidates(&candidates)?
+        };
+        for (path, relative) in missing {
+            let removed = self.remove_listed(relative)?;
+            if removed == 0 {
+                errors.push(IndexPathError {
+                    path: path.display().to_string(),
+                    reason: "not found".to_string(),
+                });
+            }
+            stats.files_removed += removed;
+        }
+        stats.errors = errors;
+        Ok(stats)
+    }
+
+    /// Index the one file at `path` (relative to the repo root, or absolute
+    /// within it), with no file discovery.
+    ///
+    /// Unlike [`index_paths`](Self::index_paths), only the content hash decides
+    /// whether the file is reparsed: an edit landing within the mtime
+    /// resolution or `stat_ttl` is still picked up. A file gone from disk has
+    /// its rows removed, and so has every file under a directory gone from
+    /// disk. A path outside the repo is
+    /// [`CanopyError::PathOutsideRepo`], and a missing file that was never
+    /// indexed [`CanopyError::FileNotFound`].
+    pub fn index_file(&mut self, path: &Path) -> crate::Result<IndexStats> {
+        self.ensure_writable()?;
+        let (absolute, relative) = match self.resolve_listed_path(path) {
+            Ok(ListedPath::File(absolute, relative)) => (absolute, relative),
+            Ok(ListedPath::Missing(relative)) => {
+                let files_removed = self.remove_listed(relative)?;
+                if files_removed == 0 {
+                    return Err(CanopyError::FileNotFound(path.to_path_buf()));
+                }
+                let mut stats = self.write_stats(WriteTally::default(), SkipCounts::default(), 0);
+                stats.files_removed = files_removed;
+                return Ok(stats);
+            }
+            Err(OUTSIDE_REPO) => return Err(CanopyError::PathOutsideRepo(path.to_path_buf())),
+            Err(reason) => {
+                return Err(CanopyError::Io(std::io::Error::other(format!(
+                    "{}: {}",
+                    path.display(),
+                    reason
+                ))))
+            }
+        };
+        match self.shards.route(&relative) {
+            Some(prefix) => self
+                .shards
+                .get_or_create(&self.repo_root, &self.config, &prefix)?
+                .index_one(&absolute, &relative),
+            None => self.index_one(&absolute, &relative),
+        }
+    }
+
+    /// Reparse `relative_path` into this database unless its content hash
+    /// matches the indexed one.
+    fn index_one(&mut self, file_path: &Path, relative_path: &str) -> crate::Result<IndexStats> {
+        let started = now_secs();
+        let meta = self.file_meta(relative_path)?;
+        let mut written = WriteTally::default();
+        let mut skipped = SkipCounts::default();
+        let Some(file) = SourceFile::read(file_path) else {
+            return Ok(self.write_stats(written, skipped, 0));
+        };
+        if let Some(meta) = meta.filter(|meta| meta.hash == file.hash && !meta.reparse) {
+            skipped.record(SkipReason::Hash);
+            return Ok(self.write_stats(written, skipped, meta.tokens));
+        }
+
+        warm_bpe();
+        let detector = GeneratedDetector::new(&self.config.generated, self.path_style)?;
+        let mut parsed =
+            parse_file_with_hash(file_path, &file.source, &self.config, file.hash, file.mtime);
+        parsed.generated = detector.is_generated(relative_path, &file.source);
+        let fts = self.index_parsed_file(relative_path, &parsed)?;
+        written.record(&parsed, fts);
+        let mut stats = self.write_stats(written, skipped, 0);
+        self.finish_writes(started, &mut stats)?;
+        Ok(stats)
+    }
+
+    /// Stats of a run that wrote `written`, sized by this database's file.
+    fn write_stats(
+        &self,
+        written: WriteTally,
+        skipped: SkipCounts,
+        skipped_tokens: usize,
+    ) -> IndexStats {
+        let index_size_bytes = fs::metadata(&self.db_path).map(|m| m.len()).unwrap_or(0);
+        written.into_stats(skipped, skipped_tokens, index_size_bytes)
+    }
+
+    /// Remove a listed path gone from disk from every database (only the
+    /// owning one has a row): the file itself, or every file under it when
+    /// it was a directory. Returns the rows removed.
+    fn remove_listed(&mut self, relative: String) -> crate::Result<usize> {
+        let mut removed = self.remove_listed_rows(&relative)?;
+        for shard in self.shards.reachable_mut(None) {
+            removed += shard.index.remove_listed_rows(&relative)?;
+        }
+        Ok(removed)
+    }
+
+    /// [`remove_listed`](Self::remove_listed) for this database alone.
+    fn remove_listed_rows(&mut self, relative: &str) -> crate::Result<usize> {
+        let paths: Vec<String> = self.with_statement(
+            "SELECT path FROM files WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+            |stmt| {
+                let rows =
+                    stmt.query_map(params![relative, format!("{relative}/")], |row| row.get(0))?;
+                Ok(rows.collect::<Result<_, _>>()?)
+            },
+        )?;
+        self.remove_paths(&paths)
+    }
+
+    /// Which of `paths` (repo-relative) the index is out of date for: their
+    /// content hash differs from the indexed one, they were never indexed, or
+    /// they were deleted since. Paths neither on disk nor indexed are left out.
+    pub fn changed_paths(&self, paths: &[String]) -> crate::Result<Vec<String>> {
+        let mut changed = Vec::new();
+        for path in paths {
+            let mut stored = None;
+            for index in self.all_indexes() {
+                stored = index
+                    .conn
+                    .query_row(
+                        "SELECT content_hash FROM files WHERE path = ?",
+                        params![path],
+                        |row| row.get::<_, Vec<u8>>(0),
+                    )
+                    .optional()?;
+                if stored.is_some() {
+                    break;
+                }
+            }
+            let current = SourceFile::read(&self.repo_root.join(path)).map(|file| file.hash);
+            if current.as_ref().map(|hash| hash.as_slice()) != stored.as_deref() {
+                changed.push(path.clone());
+            }
         }
+        Ok(changed)
+    }
+
+    /// Index already-discovered `(absolute, relative)` paths into this database.
+    pub(crate) fn index_candidates(
+        &mut self,
+        candidates: &[(PathBuf, String)],
+    ) -> crate::Result<IndexStats> {
+        let started = now_secs();
+        let policy = SkipPolicy::new(&self.config, started);
+
+        let mut stats = if candidates.len() <= Self::SEQUENTIAL_THRESHOLD {
+            self.index_sequential(candidates, policy)?
+        } else {
+            self.index_pipeline(candidates, policy)?
+        };
+        self.finish_writes(started, &mut stats)?;
+        Ok(stats)
+    }
+
+    /// Upkeep after files were written into this database since `started`,
+    /// however they were listed: their commit times, and an FTS merge once
+    /// enough has been reparsed.
+    fn finish_writes(&mut self, started: i64, stats: &mut IndexStats) -> crate::Result<()> {
+        self.refresh_commit_times(started)?;
+        stats.fts_optimized = self.optimize_fts_if_due(started)?;
+        Ok(())
     }
 
     /// Pipeline index path for large batches (> SEQUENTIAL_THRESHOLD files).
     ///
-    /// Batch-loads metadata (single SELECT), partitions by mtime/TTL, then spawns
-    /// rayon workers for parallel parse+hash with a bounded channel feeding a
-    /// single-threaded DB writer.
+    /// Batch-loads metadata (single SELECT), then spawns rayon workers that run
+    /// the skip checks and parse+hash in parallel, with a bounded channel
+    /// feeding a single-threaded DB writer.
     fn index_pipeline(
         &mut self,
         candidates: &[(PathBuf, String)],
-        now_secs: i64,
-        ttl_secs: i64,
+        policy: SkipPolicy,
     ) -> crate::Result<IndexStats> {
         // Amortize metadata lookup: single SELECT into HashMap vs N per-file queries
         let existing = self.batch_load_metadata()?;
 
         warm_bpe();
 
-        let mut files_skipped = 0usize;
-        let mut skipped_tokens = 0usize;
-        let mut to_index: Vec<(PathBuf, String)> = Vec::new();
-
-        for (file_path, relative_path) in candidates {
-            if let Some(meta) = existing.get(relative_path.as_str()) {
-                let current_mtime = file_mtime(file_path);
-
-                if current_mtime == meta.mtime && (now_secs - meta.indexed_at) < ttl_secs {
-                    files_skipped += 1;
-                    skipped_tokens += meta.tokens;
-                    continue;
-                }
-            }
-
-            to_index.push((file_path.clone(), relative_path.clone()));
-        }
-
         // Bounded channel (cap 64) provides backpressure so rayon workers don't
         // outpace the single-threaded DB writer.
         let (tx_ch, rx_ch) = crossbeam_channel::bounded::<(String, ParsedFile)>(64);
 
         let config = self.config.clone();
         let existing_ref = &existing;
+        let detector = GeneratedDetector::new(&self.config.generated, self.path_style)?;
+        let detector_ref = &detector;
 
-        let hash_skipped_count = AtomicUsize::new(0);
-        let hash_skipped_tokens = AtomicUsize::new(0);
-        let hash_skipped_count_ref = &hash_skipped_count;
-        let hash_skipped_tokens_ref = &hash_skipped_tokens;
+        let skipped = SkipTally::default();
+        let skipped_ref = &skipped;
 
         // Cancellation flag: set by writer on DB error so producers stop early
         let cancelled = AtomicBool::new(false);
         let cancelled_ref = &cancelled;
 
-        let mut files_indexed = 0usize;
-        let mut indexed_tokens = 0usize;
+        let mut written = WriteTally::default();
 
         // thread::scope lets rayon workers borrow `existing` and atomic counters
         let pipeline_result: crate::Result<()> = std::thread::scope(|s| {
             let producer_sender = tx_ch.clone();
             s.spawn(move || {
-                to_index.par_iter().for_each_with(
+                candidates.par_iter().for_each_with(
                     producer_sender,
                     |sender, (file_path, relative_path)| {
                         if cancelled_ref.load(Ordering::Relaxed) {
                             return;
                         }
 
-                        // mtime captured before read: prevents TOCTOU where hash
-                        // reflects old content but mtime reflects new write
-                        let mtime = file_mtime(file_path);
-
-                        let source = match fs::read_to_string(file_path) {
-                            Ok(s) => s,
-                            Err(_) => return,
-                        };
-
-                        let mut hasher = Sha256::new();
-                        hasher.update(source.as_bytes());
-                        let hash: [u8; 32] = hasher.finalize().into();
-
-                        if let Some(meta) = existing_ref.get(relative_path.as_str()) {
-                            if meta.hash == hash {
-                                hash_skipped_count_ref.fetch_add(1, Ordering::Relaxed);
-                                hash_skipped_tokens_ref.fetch_add(meta.tokens, Ordering::Relaxed);
+                        let meta = existing_ref.get(relative_path.as_str());
+                        let file = match policy.action(meta, file_path) {
+                            FileAction::Parse(file) => file,
+                            FileAction::Skip(reason) => {
+                                let tokens = meta.map_or(0, |meta| meta.tokens);
+                                skipped_ref.record(reason, tokens);
                                 return;
                             }
-                        }
+                            FileAction::Unreadable => return,
+                        };
 
                         if cancelled_ref.load(Ordering::Relaxed) {
                             return;
                         }
 
-                        let parsed = parse_file_with_hash(file_path, &source, &config, hash, mtime);
+                        let mut parsed = parse_file_with_hash(
+                            file_path,
+                            &file.source,
+                            &config,
+                            file.hash,
+                            file.mtime,
+                        );
+                        parsed.generated = detector_ref.is_generated(relative_path, &file.source);
                         if sender.send((relative_path.clone(), parsed)).is_err() {
                             cancelled_ref.store(true, Ordering::Relaxed);
                         }
@@ -161,7 +352,7 @@ impl RepoIndex {
 
             drop(tx_ch);
 
-            let preview_bytes = self.config.indexing.preview_bytes;
+            let options = WriteOptions::new(&self.config, &self.redactor);
             let mut batch: Vec<(String, ParsedFile)> = Vec::with_capacity(Self::BATCH_SIZE);
 
             for item in rx_ch.iter() {
@@ -169,12 +360,11 @@ impl RepoIndex {
                 if batch.len() >= Self::BATCH_SIZE {
                     let result = Self::flush_batch(
                         &mut self.conn,
+                        &self.repo_root,
                         &mut self.symbol_cache,
-                        &mut self.symbol_cache_by_file,
                         &mut batch,
-                        preview_bytes,
-                        &mut files_indexed,
-                        &mut indexed_tokens,
+                        options,
+                        &mut written,
                     );
                     if let Err(e) = result {
                         cancelled.store(true, Ordering::Relaxed);
@@ -186,12 +376,11 @@ impl RepoIndex {
             if !batch.is_empty() {
                 Self::flush_batch(
                     &mut self.conn,
+                    &self.repo_root,
                     &mut self.symbol_cache,
-                    &mut self.symbol_cache_by_file,
                     &mut batch,
-                    preview_bytes,
-                    &mut files_indexed,
-                    &mut indexed_tokens,
+                    options,
+                    &mut written,
                 )?;
             }
 
@@ -200,19 +389,10 @@ impl RepoIndex {
 
         pipeline_result?;
 
-        // Fold in hash-skip counters from rayon workers
-        files_skipped += hash_skipped_count.load(Ordering::Relaxed);
-        skipped_tokens += hash_skipped_tokens.load(Ordering::Relaxed);
-
-        let db_path = self.repo_root.join(".canopy").join("index.db");
-        let index_size_bytes = fs::metadata(&db_path).map(|m| m.len()).unwrap_or(0);
+        let (skipped, skipped_tokens) = skipped.into_counts();
+        let index_size_bytes = fs::metadata(&self.db_path).map(|m| m.len()).unwrap_or(0);
 
-        Ok(IndexStats {
-            files_indexed,
-            files_skipped,
-            total_tokens: indexed_tokens + skipped_tokens,
-            index_size_bytes,
-        })
+        Ok(written.into_stats(skipped, skipped_tokens, index_size_bytes))
     }
 
     /// Sequential index path for small batches (≤ SEQUENTIAL_THRESHOLD files).
@@ -220,99 +400,60 @@ impl RepoIndex {
     fn index_sequential(
         &mut self,
         candidates: &[(PathBuf, String)],
-        now_secs: i64,
-        ttl_secs: i64,
+        policy: SkipPolicy,
     ) -> crate::Result<IndexStats> {
         warm_bpe();
 
-        let mut files_indexed = 0usize;
-        let mut files_skipped = 0usize;
-        let mut indexed_tokens = 0usize;
+        let detector = GeneratedDetector::new(&self.config.generated, self.path_style)?;
+        let mut written = WriteTally::default();
+        let mut skipped = SkipCounts::default();
         let mut skipped_tokens = 0usize;
 
         for (file_path, relative_path) in candidates {
-            let row: Option<(i64, Vec<u8>, i64, i64)> = self
-                .conn
-                .query_row(
-                    "SELECT mtime, content_hash, indexed_at, token_count FROM files WHERE path = ?",
-                    params![relative_path],
-                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
-                )
-                .optional()?;
-
-            if let Some((db_mtime, _db_hash, indexed_at, db_tokens)) = &row {
-                let current_mtime = file_mtime(file_path);
-
-                if current_mtime == *db_mtime && (now_secs - indexed_at) < ttl_secs {
-                    files_skipped += 1;
-                    skipped_tokens += *db_tokens as usize;
-                    continue;
-                }
-            }
-
-            let mtime = file_mtime(file_path);
-
-            let source = match fs::read_to_string(file_path) {
-                Ok(s) => s,
-                Err(_) => continue,
-            };
-
-            let mut hasher = Sha256::new();
-            hasher.update(source.as_bytes());
-            let hash: [u8; 32] = hasher.finalize().into();
+            let meta = self.file_meta(relative_path)?;
 
-            if let Some((_db_mtime, db_hash, _indexed_at, db_tokens)) = &row {
-                if db_hash.as_slice() == hash.as_slice() {
-                    files_skipped += 1;
-                    skipped_tokens += *db_tokens as usize;
+            let file = match policy.action(meta.as_ref(), file_path) {
+                FileAction::Parse(file) => file,
+                FileAction::Skip(reason) => {
+                    skipped.record(reason);
+                    skipped_tokens += meta.map_or(0, |meta| meta.tokens);
                     continue;
                 }
-            }
+                FileAction::Unreadable => continue,
+            };
 
-            let parsed = parse_file_with_hash(file_path, &source, &self.config, hash, mtime);
-            self.index_parsed_file(relative_path, &parsed)?;
-            files_indexed += 1;
-            indexed_tokens += parsed.total_tokens;
+            let mut parsed =
+                parse_file_with_hash(file_path, &file.source, &self.config, file.hash, file.mtime);
+            parsed.generated = detector.is_generated(relative_path, &file.source);
+            let fts = self.index_parsed_file(relative_path, &parsed)?;
+            written.record(&parsed, fts);
         }
 
-        let db_path = self.repo_root.join(".canopy").join("index.db");
-        let index_size_bytes = fs::metadata(&db_path).map(|m| m.len()).unwrap_or(0);
+        let index_size_bytes = fs::metadata(&self.db_path).map(|m| m.len()).unwrap_or(0);
 
-        Ok(IndexStats {
-            files_indexed,
-            files_skipped,
-            total_tokens: indexed_tokens + skipped_tokens,
-            index_size_bytes,
-        })
+        Ok(written.into_stats(skipped, skipped_tokens, index_size_bytes))
+    }
+
+    /// Stored metadata of `relative_path` in this database, if indexed
+    pub(super) fn file_meta(&self, relative_path: &str) -> crate::Result<Option<FileMeta>> {
+        Ok(self
+            .conn
+            .query_row(
+                &format!("SELECT {FILE_META_COLUMNS} FROM files WHERE path = ?"),
+                params![relative_path],
+                |row| Self::file_meta_from_row(row, 0),
+            )
+            .optional()?)
     }
 
     /// Batch-load file metadata from DB for fast skip checks
-    fn batch_load_metadata(&self) -> crate::Result<HashMap<String, FileMetaCache>> {
+    fn batch_load_metadata(&self) -> crate::Result<HashMap<String, FileMeta>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT path, mtime, content_hash, indexed_at, token_count FROM files")?;
+            .prepare(&format!("SELECT path, {FILE_META_COLUMNS} FROM files"))?;
 
         let rows = stmt.query_map([], |row| {
-            let path: String = row.get(0)?;
-            let mtime: i64 = row.get(1)?;
-            let hash_blob: Vec<u8> = row.get(2)?;
-            let indexed_at: i64 = row.get(3)?;
-            let tokens: i64 = row.get(4)?;
-
-            let mut hash = [0u8; 32];
-            if hash_blob.len() == 32 {
-                hash.copy_from_slice(&hash_blob);
-            }
-
-            Ok((
-                path,
-                FileMetaCache {
-                    mtime,
-                    hash,
-                    indexed_at,
-                    tokens: tokens as usize,
-                },
-            ))
+            Ok((row.get::<_, String>(0)?, Self::file_meta_from_row(row, 1)?))
         })?;
 
         let mut map = HashMap::new();
@@ -323,32 +464,45 @@ impl RepoIndex {
         Ok(map)
     }
 
+    /// Read [`FILE_META_COLUMNS`] starting at column `first`
+    fn file_meta_from_row(row: &rusqlite::Row<'_>, first: usize) -> rusqlite::Result<FileMeta> {
+        let hash_blob: Vec<u8> = row.get(first + 1)?;
+        let mut hash = [0u8; 32];
+        if hash_blob.len() == 32 {
+            hash.copy_from_slice(&hash_blob);
+        }
+        Ok(FileMeta {
+            mtime: row.get(first)?,
+            hash,
+            indexed_at: row.get(first + 2)?,
+            tokens: row.get::<_, i64>(first + 3)? as usize,
+            reparse: row.get(first + 4)?,
+        })
+    }
+
     /// Flush a batch of parsed files in a single transaction
     fn flush_batch(
         conn: &mut Connection,
-        symbol_cache: &mut HashMap<String, Vec<SymbolCacheEntry>>,
-        symbol_cache_by_file: &mut HashMap<String, HashSet<String>>,
+        repo_root: &Path,
+        symbol_cache: &mut SymbolCache,
         batch: &mut Vec<(String, ParsedFile)>,
-        preview_bytes: usize,
-        files_indexed: &mut usize,
-        indexed_tokens: &mut usize,
+        options: WriteOptions<'_>,
+        written: &mut WriteTally,
     ) -> crate::Result<()> {
-        let mut all_new_entries: Vec<(String, Vec<(String, SymbolCacheEntry)>)> = Vec::new();
+        let mut deltas: Vec<(String, SymbolCacheDelta)> = Vec::new();
 
         let tx = conn.transaction()?;
         for (relative_path, parsed) in batch.drain(..) {
-            let entries =
-                Self::index_parsed_file_in_tx(&tx, &relative_path, &parsed, preview_bytes)?;
-            *files_indexed += 1;
-            *indexed_tokens += parsed.total_tokens;
-            all_new_entries.push((relative_path, entries));
+            let (delta, fts) =
+                Self::index_parsed_file_in_tx(&tx, repo_root, &relative_path, &parsed, options)?;
+            written.record(&parsed, fts);
+            deltas.push((relative_path, delta));
         }
         tx.commit()?;
 
         // Apply cache only after successful commit
-        for (relative_path, entries) in all_new_entries {
-            Self::remove_file_from_symbol_cache(symbol_cache, symbol_cache_by_file, &relative_path);
-            Self::add_to_symbol_cache(symbol_cache, symbol_cache_by_file, entries);
+        for (relative_path, delta) in deltas {
+            symbol_cache.apply_delta(&relative_path, delta);
         }
 
         Ok(())
@@ -359,177 +513,270 @@ impl RepoIndex {
         &mut self,
         relative_path: &str,
         parsed: &ParsedFile,
-    ) -> crate::Result<()> {
-        let preview_bytes = self.config.indexing.preview_bytes;
+    ) -> crate::Result<FtsBytes> {
+        let options = WriteOptions::new(&self.config, &self.redactor);
         let tx = self.conn.transaction()?;
-        let entries = Self::index_parsed_file_in_tx(&tx, relative_path, parsed, preview_bytes)?;
+        let (delta, fts) =
+            Self::index_parsed_file_in_tx(&tx, &self.repo_root, relative_path, parsed, options)?;
         tx.commit()?;
 
-        Self::remove_file_from_symbol_cache(
-            &mut self.symbol_cache,
-            &mut self.symbol_cache_by_file,
-            relative_path,
-        );
-        Self::add_to_symbol_cache(
-            &mut self.symbol_cache,
-            &mut self.symbol_cache_by_file,
-            entries,
-        );
+        self.symbol_cache.apply_delta(relative_path, delta);
 
-        Ok(())
+        Ok(fts)
     }
 
     /// Index a parsed file within an existing transaction.
-    /// Returns symbol cache entries to be applied after commit.
+    /// Returns the symbol cache changes to apply after commit, and what went
+    /// into `content_fts`.
+    ///
+    /// With `options.incremental_nodes`, an already-indexed file is diffed
+    /// against its stored nodes instead (see `incremental.rs`).
     fn index_parsed_file_in_tx(
         tx: &rusqlite::Transaction<'_>,
+        repo_root: &Path,
         relative_path: &str,
         parsed: &ParsedFile,
-        preview_bytes: usize,
-    ) -> crate::Result<Vec<(String, SymbolCacheEntry)>> {
-        tx.execute("DELETE FROM files WHERE path = ?", params![relative_path])?;
-        let mtime = parsed.mtime;
+        options: WriteOptions<'_>,
+    ) -> crate::Result<(SymbolCacheDelta, FtsBytes)> {
+        // Non-UTF-8 paths keep their raw bytes; handle ids hash those, not the display form
+        let path_bytes =
+            raw_path_bytes(parsed.path.strip_prefix(repo_root).unwrap_or(&parsed.path));
+        let id_path = path_bytes.as_deref().unwrap_or(relative_path.as_bytes());
+        // Only files holding a secret pay for redacting every preview
+        let redactor = options
+            .redactor
+            .for_path(relative_path)
+            .filter(|redactor| redactor.is_match(&parsed.source));
+        let rows: Vec<NodeRow<'_>> = parsed
+            .nodes
+            .iter()
+            .map(|node| NodeRow::new(parsed, node, id_path, options, redactor))
+            .collect();
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        let stored: Option<(i64, i64, Option<i64>)> = tx
+            .query_row(
+                "SELECT id, reindex_count, churn_since FROM files WHERE path = ?",
+                params![relative_path],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+        let now = now_secs();
+        let churn = next_churn(stored.map(|(_, count, since)| (count, since)), now);
+        if stored.is_some() {
+            // Counted whole even when incremental reuse leaves most FTS rows alone
+            add_reindexed_tokens(tx, parsed.total_tokens)?;
+        }
 
+        if options.incremental_nodes {
+            if let Some((file_id, ..)) = stored {
+                Self::update_file_row(
+                    tx,
+                    file_id,
+                    relative_path,
+                    parsed,
+                    path_bytes.as_deref(),
+                    now,
+                    churn,
+                )?;
+                Self::record_parse_warning_in_tx(tx, file_id, parsed)?;
+                return Self::reindex_nodes_in_tx(
+                    tx,
+                    file_id,
+                    relative_path,
+                    parsed,
+                    rows,
+                    options.preview_bytes,
+                    redactor,
+                );
+            }
+        }
+
+        tx.execute("DELETE FROM files WHERE path = ?", params![relative_path])?;
         tx.execute(
-            "INSERT INTO files (path, content_hash, mtime, indexed_at, token_count)
-             VALUES (?, ?, ?, ?, ?)",
+            "INSERT INTO files (path, content_hash, mtime, indexed_at, token_count, dir_prefix, path_bytes,
+                                line_count, byte_len, generated, reindex_count, churn_since)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
             params![
                 relative_path,
                 parsed.content_hash.as_slice(),
-                mtime,
+                parsed.mtime,
                 now,
-                parsed.total_tokens as i64
+                parsed.total_tokens as i64,
+                dir_prefix(relative_path),
+                path_bytes,
+                parsed.line_count() as i64,
+                parsed.source.len() as i64,
+                parsed.generated,
+                churn.0,
+                churn.1
             ],
         )?;
-
         let file_id = tx.last_insert_rowid();
+        Self::record_parse_warning_in_tx(tx, file_id, parsed)?;
 
-        let mut new_cache_entries: Vec<(String, SymbolCacheEntry)> = Vec::new();
-
-        for node in &parsed.nodes {
-            let handle_id = HandleId::new(relative_path, node.node_type, &node.span);
-            let node_tokens = estimate_tokens(&parsed.source[node.span.clone()]);
-
-            let name = node.metadata.searchable_name().map(String::from);
-            let name_lower = name.as_ref().map(|n| n.to_lowercase());
-            let preview = generate_preview(&parsed.source, &node.span, preview_bytes);
-
-            let parent_name: Option<&str> = node.parent_name.as_deref();
-            let parent_name_lower = parent_name.map(|p| p.to_lowercase());
-            let parent_handle_id = match (node.parent_node_type, node.parent_span.as_ref()) {
-                (Some(parent_node_type), Some(parent_span)) => Some(
-                    HandleId::new(relative_path, parent_node_type, parent_span)
-                        .raw()
-                        .to_string(),
-                ),
-                _ => None,
-            };
+        let mut delta = SymbolCacheDelta::replace_file();
+        let mut fts = FtsBytes::default();
+        // Span→node_id map lets us attribute each reference to its enclosing node
+        let mut node_spans: Vec<(Range<usize>, i64)> = Vec::with_capacity(rows.len());
+        for row in &rows {
+            let node_id = Self::insert_node_in_tx(tx, file_id, row)?;
+            node_spans.push((row.span.clone(), node_id));
+            delta.added.extend(row.cache_entry(relative_path));
+            fts.add(row);
+        }
+
+        Self::insert_refs_in_tx(
+            tx,
+            file_id,
+            parsed,
+            &node_spans,
+            options.preview_bytes,
+            redactor,
+        )?;
+        Ok((delta, fts))
+    }
+
+    /// Rewrite an existing `files` row in place, keeping its id (and so its nodes).
+    fn update_file_row(
+        tx: &rusqlite::Transaction<'_>,
+        file_id: i64,
+        relative_path: &str,
+        parsed: &ParsedFile,
+        path_bytes: Option<&[u8]>,
+        now: i64,
+        (reindex_count, churn_since): (i64, Option<i64>),
+    ) -> crate::Result<()> {
+        tx.execute(
+            "UPDATE files SET content_hash = ?, mtime = ?, indexed_at = ?, token_count = ?,
+                              dir_prefix = ?, path_bytes = ?, line_count = ?, byte_len = ?,
+                              generated = ?, reindex_count = ?, churn_since = ?
+             WHERE id = ?",
+            params![
+                parsed.content_hash.as_slice(),
+                parsed.mtime,
+                now,
+                parsed.total_tokens as i64,
+                dir_prefix(relative_path),
+                path_bytes,
+                parsed.line_count() as i64,
+                parsed.source.len() as i64,
+                parsed.generated,
+                reindex_count,
+                churn_since,
+                file_id
+            ],
+        )?;
+        tx.execute(
+            "DELETE FROM reparse_pending WHERE file_id = ?",
+            params![file_id],
+        )?;
+        Ok(())
+    }
 
+    /// Replace the file's parse warning: set when it fell back to plain chunks,
+    /// cleared once it parses cleanly again.
+    fn record_parse_warning_in_tx(
+        tx: &rusqlite::Transaction<'_>,
+        file_id: i64,
+        parsed: &ParsedFile,
+    ) -> crate::Result<()> {
+        tx.execute(
+            "DELETE FROM parse_warnings WHERE file_id = ?",
+            params![file_id],
+        )?;
+        if let Some(reason) = &parsed.parse_warning {
             tx.execute(
-                "INSERT INTO nodes (file_id, handle_id, node_type, start_byte, end_byte,
-                                   line_start, line_end, token_count, metadata,
-                                   name, name_lower, parent_name, parent_name_lower,
-                                   parent_handle_id, preview)
-                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-                params![
-                    file_id,
-                    handle_id.raw(),
-                    node.node_type.as_int() as i32,
-                    node.span.start as i64,
-                    node.span.end as i64,
-                    node.line_range.0 as i64,
-                    node.line_range.1 as i64,
-                    node_tokens as i64,
-                    node.metadata.to_json(),
-                    name,
-                    name_lower.clone(),
-                    parent_name,
-                    parent_name_lower,
-                    parent_handle_id,
-                    preview.clone()
-                ],
+                "INSERT INTO parse_warnings (file_id, reason) VALUES (?, ?)",
+                params![file_id, reason],
             )?;
+        }
+        Ok(())
+    }
+
+    /// Insert one node with its content FTS row and, when named, its symbol FTS row.
+    pub(super) fn insert_node_in_tx(
+        tx: &rusqlite::Transaction<'_>,
+        file_id: i64,
+        row: &NodeRow<'_>,
+    ) -> crate::Result<i64> {
+        tx.execute(
+            "INSERT INTO nodes (file_id, handle_id, node_type, start_byte, end_byte,
+                               line_start, line_end, token_count, metadata,
+                               name, name_lower, parent_name, parent_name_lower,
+                               parent_handle_id, preview, content_hash, heading_path,
+                               preview_tokens, fts_truncated)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                file_id,
+                row.handle_id,
+                row.node_type.as_int() as i32,
+                row.span.start as i64,
+                row.span.end as i64,
+                row.line_range.0 as i64,
+                row.line_range.1 as i64,
+                row.token_count as i64,
+                row.metadata,
+                row.name,
+                row.name_lower,
+                row.parent_name,
+                row.parent_name_lower,
+                row.parent_handle_id,
+                row.preview,
+                row.content_hash.as_slice(),
+                row.heading_path,
+                row.preview_tokens as i64,
+                row.fts_sample.is_some()
+            ],
+        )?;
+        let node_id = tx.last_insert_rowid();
 
-            let node_id = tx.last_insert_rowid();
+        let fts_content = row.fts_content();
+        tx.execute(
+            "INSERT INTO content_fts (content, identifier_parts) VALUES (?, ?)",
+            params![fts_content, identifier_parts(fts_content)],
+        )?;
+        let fts_rowid = tx.last_insert_rowid();
+        tx.execute(
+            "INSERT INTO fts_node_map (fts_rowid, node_id) VALUES (?, ?)",
+            params![fts_rowid, node_id],
+        )?;
 
-            let content = &parsed.source[node.span.clone()];
+        if let Some(ref sym_name) = row.name {
             tx.execute(
-                "INSERT INTO content_fts (content) VALUES (?)",
-                params![content],
+                "INSERT INTO symbol_fts (name, name_parts) VALUES (?, ?)",
+                params![sym_name, identifier_parts(sym_name)],
             )?;
-
-            let fts_rowid = tx.last_insert_rowid();
+            let symbol_fts_rowid = tx.last_insert_rowid();
             tx.execute(
-                "INSERT INTO fts_node_map (fts_rowid, node_id) VALUES (?, ?)",
-                params![fts_rowid, node_id],
+                "INSERT INTO symbol_fts_map (fts_rowid, node_id) VALUES (?, ?)",
+                params![symbol_fts_rowid, node_id],
             )?;
-
-            if let Some(ref sym_name) = name {
-                tx.execute(
-                    "INSERT INTO symbol_fts (name) VALUES (?)",
-                    params![sym_name],
-                )?;
-                let symbol_fts_rowid = tx.last_insert_rowid();
-                tx.execute(
-                    "INSERT INTO symbol_fts_map (fts_rowid, node_id) VALUES (?, ?)",
-                    params![symbol_fts_rowid, node_id],
-                )?;
-
-                // Only definition-like types go in the symbol cache (O(1) lookup path)
-                if matches!(
-                    node.node_type,
-                    NodeType::Function | NodeType::Class | NodeType::Struct | NodeType::Method
-                ) {
-                    if let Some(ref nl) = name_lower {
-                        new_cache_entries.push((
-                            nl.clone(),
-                            SymbolCacheEntry {
-                                handle_id: handle_id.raw().to_string(),
-                                file_path: relative_path.to_string(),
-                                node_type: node.node_type.as_int() as i32,
-                                start_byte: node.span.start,
-                                end_byte: node.span.end,
-                                line_start: node.line_range.0,
-                                line_end: node.line_range.1,
-                                token_count: node_tokens,
-                                preview: preview.clone(),
-                            },
-                        ));
-                    }
-                }
-            }
         }
 
-        // Span→node_id map lets us attribute each reference to its enclosing node
-        let node_spans: Vec<(std::ops::Range<usize>, i64)> = parsed
-            .nodes
-            .iter()
-            .filter_map(|node| {
-                let handle_id = HandleId::new(relative_path, node.node_type, &node.span);
-                let node_id: Option<i64> = tx
-                    .query_row(
-                        "SELECT id FROM nodes WHERE handle_id = ?",
-                        params![handle_id.raw()],
-                        |row| row.get(0),
-                    )
-                    .ok();
-                node_id.map(|id| (node.span.clone(), id))
-            })
-            .collect();
+        Ok(node_id)
+    }
 
+    /// Insert a file's refs and annotations, attributed to their smallest enclosing node.
+    pub(super) fn insert_refs_in_tx(
+        tx: &rusqlite::Transaction<'_>,
+        file_id: i64,
+        parsed: &ParsedFile,
+        node_spans: &[(Range<usize>, i64)],
+        preview_bytes: usize,
+        redactor: Option<&Redactor>,
+    ) -> crate::Result<()> {
         for reference in &parsed.refs {
             let name_lower = reference.name.to_lowercase();
 
             // Find the smallest enclosing node for this reference
-            let source_node_id = super::find_smallest_enclosing_node(&reference.span, &node_spans);
+            let source_node_id = super::find_smallest_enclosing_node(&reference.span, node_spans);
 
-            let preview =
-                super::reference_preview(&parsed.source, &reference.span, preview_bytes * 2);
+            let preview = super::reference_preview(
+                &parsed.source,
+                &reference.span,
+                preview_bytes * 2,
+                redactor,
+            );
 
             tx.execute(
                 "INSERT INTO refs (file_id, name, name_lower, qualifier, ref_type,
@@ -551,10 +798,257 @@ impl RepoIndex {
             )?;
         }
 
-        Ok(new_cache_entries)
+        for annotation in &parsed.annotations {
+            let node_id = super::find_smallest_enclosing_node(&annotation.span, node_spans);
+            tx.execute(
+                "INSERT INTO annotations (file_id, line, marker, text, node_id)
+                 VALUES (?, ?, ?, ?, ?)",
+                params![
+                    file_id,
+                    annotation.line as i64,
+                    annotation.marker,
+                    annotation.text,
+                    node_id,
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What the writer stored during one indexing run.
+#[derive(Default)]
+struct WriteTally {
+    files_indexed: usize,
+    files_degraded: usize,
+    files_generated: usize,
+    indexed_tokens: usize,
+    generated_tokens: usize,
+    fts: FtsBytes,
+}
+
+impl WriteTally {
+    fn record(&mut self, parsed: &ParsedFile, fts: FtsBytes) {
+        self.fts.written += fts.written;
+        self.fts.skipped += fts.skipped;
+        self.files_indexed += 1;
+        self.files_degraded += usize::from(parsed.parse_warning.is_some());
+        self.indexed_tokens += parsed.total_tokens;
+        if parsed.generated {
+            self.files_generated += 1;
+            self.generated_tokens += parsed.total_tokens;
+        }
+    }
+
+    fn into_stats(
+        self,
+        skipped: SkipCounts,
+        skipped_tokens: usize,
+        index_size_bytes: u64,
+    ) -> IndexStats {
+        IndexStats {
+            schema_version: SchemaVersion,
+            files_indexed: self.files_indexed,
+            files_degraded: self.files_degraded,
+            files_generated: self.files_generated,
+            generated_tokens: self.generated_tokens,
+            files_skipped: skipped.total(),
+            skipped,
+            total_tokens: self.indexed_tokens + skipped_tokens,
+            index_size_bytes,
+            files_removed: 0,
+            errors: Vec::new(),
+            fts_optimized: false,
+            fts_bytes_written: self.fts.written,
+            fts_bytes_skipped: self.fts.skipped,
+            commits_indexed: 0,
+            moves_fixed: 0,
+        }
+    }
+}
+
+/// Node content bytes a write put into `content_fts`, and bytes sampling
+/// left out.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct FtsBytes {
+    pub(crate) written: u64,
+    pub(crate) skipped: u64,
+}
+
+impl FtsBytes {
+    pub(super) fn add(&mut self, row: &NodeRow<'_>) {
+        let written = row.fts_content().len();
+        self.written += written as u64;
+        self.skipped += row.content.len().saturating_sub(written) as u64;
+    }
+}
+
+/// Settings the DB writer needs, copied out of the config so batches can be
+/// flushed while `self` is split-borrowed.
+#[derive(Clone, Copy)]
+pub(super) struct WriteOptions<'a> {
+    pub(super) preview_bytes: usize,
+    pub(super) max_fts_bytes: usize,
+    pub(super) incremental_nodes: bool,
+    pub(super) redactor: &'a Redactor,
+}
+
+impl<'a> WriteOptions<'a> {
+    fn new(config: &Config, redactor: &'a Redactor) -> Self {
+        Self {
+            preview_bytes: config.indexing.preview_bytes,
+            max_fts_bytes: config.indexing.max_fts_bytes_per_node,
+            incremental_nodes: config.indexing.incremental_nodes,
+            redactor,
+        }
+    }
+}
+
+/// Column values for one `nodes` row, derived from a parsed node.
+pub(super) struct NodeRow<'a> {
+    pub(super) handle_id: String,
+    pub(super) node_type: NodeType,
+    pub(super) span: Range<usize>,
+    pub(super) line_range: (usize, usize),
+    /// The node's source slice
+    pub(super) content: &'a str,
+    /// What `content_fts` holds instead of `content` when it is over
+    /// `[indexing] max_fts_bytes_per_node`
+    pub(super) fts_sample: Option<String>,
+    pub(super) content_hash: [u8; 32],
+    pub(super) token_count: usize,
+    pub(super) metadata: String,
+    pub(super) name: Option<String>,
+    pub(super) name_lower: Option<String>,
+    pub(super) parent_name: Option<&'a str>,
+    pub(super) parent_name_lower: Option<String>,
+    pub(super) parent_handle_id: Option<String>,
+    pub(super) heading_path: Option<String>,
+    pub(super) preview: String,
+    pub(super) preview_tokens: usize,
+}
+
+impl<'a> NodeRow<'a> {
+    fn new(
+        parsed: &'a ParsedFile,
+        node: &'a DocumentNode,
+        id_path: &[u8],
+        options: WriteOptions<'_>,
+        redactor: Option<&Redactor>,
+    ) -> Self {
+        let preview_bytes = options.preview_bytes;
+        let content = &parsed.source[node.span.clone()];
+        let name = node.metadata.searchable_name().map(String::from);
+        let name_lower = name.as_ref().map(|n| n.to_lowercase());
+        let parent_name = node.parent_name.as_deref();
+        let parent_handle_id = match (node.parent_node_type, node.parent_span.as_ref()) {
+            (Some(parent_node_type), Some(parent_span)) => Some(
+                HandleId::from_path_bytes(id_path, parent_node_type, parent_span)
+                    .raw()
+                    .to_string(),
+            ),
+            _ => None,
+        };
+        let heading_path = node.metadata.heading_path();
+        let preview = generate_preview(&parsed.source, &node.span, preview_bytes, redactor);
+        // Nested sections lead with their ancestry so same-named ones are
+        // distinguishable, and parts of a split section with which part they are
+        let label = match (&node.metadata, node.parent_node_type) {
+            (
+                NodeMetadata::Section {
+                    heading,
+                    heading_path,
+                    ..
+                },
+                Some(NodeType::Section),
+            ) => Some(match heading_path.rsplit_once(HEADING_PATH_SEPARATOR) {
+                Some((ancestors, _)) => format!("{ancestors}{HEADING_PATH_SEPARATOR}{heading}"),
+                None => heading.clone(),
+            }),
+            _ => heading_path
+                .filter(|path| path.contains(HEADING_PATH_SEPARATOR))
+                .map(String::from),
+        };
+        let preview = match label {
+            Some(label) => format!("[{label}] {preview}"),
+            None => preview,
+        };
+        // Notebook line ranges are cell-relative, so say which cell
+        let preview = match node.cell {
+            Some(cell) => format!("[cell {cell}] {preview}"),
+            None => preview,
+        };
+        Self {
+            handle_id: HandleId::from_path_bytes(id_path, node.node_type, &node.span)
+                .raw()
+                .to_string(),
+            node_type: node.node_type,
+            span: node.span.clone(),
+            line_range: node.line_range,
+            content,
+            fts_sample: fts_sample(content, options.max_fts_bytes),
+            content_hash: node_content_hash(content),
+            token_count: estimate_tokens(content),
+            metadata: node.metadata_json(),
+            name,
+            name_lower,
+            parent_name,
+            parent_name_lower: parent_name.map(|p| p.to_lowercase()),
+            parent_handle_id,
+            heading_path: heading_path.map(String::from),
+            preview_tokens: estimate_tokens(&preview),
+            preview,
+        }
+    }
+
+    /// The text `content_fts` indexes for this node.
+    pub(super) fn fts_content(&self) -> &str {
+        self.fts_sample.as_deref().unwrap_or(self.content)
+    }
+
+    /// Symbol cache entry for this node; only definition-like types are cached
+    /// (the O(1) lookup path).
+    pub(super) fn cache_entry(&self, relative_path: &str) -> Option<(String, SymbolCacheEntry)> {
+        if !matches!(
+            self.node_type,
+            NodeType::Function | NodeType::Class | NodeType::Struct | NodeType::Method
+        ) {
+            return None;
+        }
+        let (name, name_lower) = (self.name.as_ref()?, self.name_lower.as_ref()?);
+        Some((
+            name_lower.clone(),
+            SymbolCacheEntry {
+                name: name.clone(),
+                handle_id: self.handle_id.clone(),
+                file_path: relative_path.to_string(),
+                node_type: self.node_type.as_int() as i32,
+                start_byte: self.span.start,
+                end_byte: self.span.end,
+                line_start: self.line_range.0,
+                line_end: self.line_range.1,
+                token_count: self.token_count,
+                preview: self.preview.clone(),
+                preview_tokens: self.preview_tokens,
+                content_hash: short_hash_bytes(&self.content_hash),
+            },
+        ))
     }
 }
 
+/// SHA-256 of a node's source slice, stored as `nodes.content_hash`.
+pub(super) fn node_content_hash(content: &str) -> [u8; 32] {
+    Sha256::digest(content.as_bytes()).into()
+}
+
+fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::test_helpers::setup_repo;
@@ -642,4 +1136,253 @@ mod tests {
             stats.files_indexed
         );
     }
+
+    const BROKEN_RUST: &str = "pub fn broken(x: i32 {\n    let needle_text = x +;\n}\n";
+
+    /// Rewrite `path` with a later mtime so the skip checks can't treat it as unchanged.
+    fn rewrite(path: &Path, contents: &str) {
+        fs::write(path, contents).unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+    }
+
+    #[test]
+    fn giant_nodes_are_full_text_indexed_from_a_sample() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut log = String::new();
+        for i in 0..16_000 {
+            log.push_str("INFO request handled path=/api/items status=200 elapsed=12ms\n");
+            if i == 8_000 {
+                log.push_str("ERROR mid_file_needle exhausted the pool\n");
+            }
+        }
+        assert!(log.len() > 900_000);
+        fs::write(dir.path().join("server.log"), &log).unwrap();
+        RepoIndex::init(dir.path()).unwrap();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        let stats = index.index("**/*.log").unwrap();
+        assert_eq!(stats.files_indexed, 1);
+        let budget = index.config.indexing.max_fts_bytes_per_node as u64;
+        assert!(stats.fts_bytes_written <= budget, "{stats:?}");
+        assert_eq!(
+            stats.fts_bytes_written + stats.fts_bytes_skipped,
+            log.len() as u64
+        );
+        assert_eq!(index.status().unwrap().nodes_fts_sampled, 1);
+        let fts_bytes: i64 = index
+            .conn
+            .query_row(
+                "SELECT COALESCE(SUM(LENGTH(block)), 0) FROM content_fts_data",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(fts_bytes < 64 * 1024, "content_fts holds {fts_bytes} bytes");
+
+        // The mid-file line introduced new identifiers, so it was sampled
+        let hits = index.fts_search("mid_file_needle", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        // Expand still reads the whole node from disk
+        let expanded = index.expand(&[hits[0].id.to_string()]).unwrap();
+        assert_eq!(expanded[0].1.len(), log.len());
+    }
+
+    fn assert_degraded_then_cleared(incremental_nodes: bool) {
+        let dir = setup_repo(2);
+        let broken = dir.path().join("src/broken.rs");
+        fs::write(&broken, BROKEN_RUST).unwrap();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.config.indexing.incremental_nodes = incremental_nodes;
+
+        let stats = index.index("**/*.rs").unwrap();
+        assert_eq!(stats.files_indexed, 3);
+        assert_eq!(stats.files_degraded, 1);
+        assert_eq!(index.status().unwrap().files_degraded, 1);
+        let warnings = index.parse_warnings(10).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].path, "src/broken.rs");
+        assert!(warnings[0].reason.contains("syntax error at line 1"));
+
+        // Still searchable as text, as a single chunk
+        let hits = index.fts_search("needle_text", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].node_type, NodeType::Chunk);
+
+        rewrite(&broken, "pub fn broken(x: i32) -> i32 {\n    x + 1\n}\n");
+        let stats = index.index("**/*.rs").unwrap();
+        assert_eq!(stats.files_indexed, 1);
+        assert_eq!(stats.files_degraded, 0);
+        assert_eq!(index.status().unwrap().files_degraded, 0);
+        assert!(index.parse_warnings(10).unwrap().is_empty());
+        assert_eq!(index.search_code("broken", 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn unparseable_file_is_degraded_until_it_parses() {
+        assert_degraded_then_cleared(false);
+    }
+
+    #[test]
+    fn incremental_reindex_clears_parse_warning() {
+        assert_degraded_then_cleared(true);
+    }
+
+    #[test]
+    fn pipeline_counts_degraded_files() {
+        let dir = setup_repo(RepoIndex::SEQUENTIAL_THRESHOLD + 1);
+        fs::write(dir.path().join("src/broken.rs"), BROKEN_RUST).unwrap();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+
+        let stats = index.index("**/*.rs").unwrap();
+        assert_eq!(stats.files_indexed, RepoIndex::SEQUENTIAL_THRESHOLD + 2);
+        assert_eq!(stats.files_degraded, 1);
+        assert_eq!(index.parse_warnings(10).unwrap()[0].path, "src/broken.rs");
+    }
+
+    #[test]
+    fn index_paths_indexes_only_the_listed_files() {
+        let dir = setup_repo(3);
+        let root = dir.path();
+        let mut index = RepoIndex::open(root).unwrap();
+        let outside = tempfile::TempDir::new().unwrap();
+        fs::write(outside.path().join("loose.rs"), "fn loose() {}\n").unwrap();
+
+        let paths = [
+            PathBuf::from("src/file_0.rs"),
+            root.join("src/file_1.rs"),
+            PathBuf::from("./src/../src/file_0.rs"),
+            PathBuf::from("src/gone.rs"),
+            PathBuf::from("../escape.rs"),
+            outside.path().join("loose.rs"),
+            PathBuf::from("src"),
+        ];
+        let stats = index.index_paths(&paths).unwrap();
+        assert_eq!(stats.files_indexed, 2);
+        assert_eq!(stats.files_removed, 0);
+        let mut indexed = index.indexed_paths().unwrap();
+        indexed.sort();
+        assert_eq!(indexed, ["src/file_0.rs", "src/file_1.rs"]);
+        let errors: Vec<_> = stats
+            .errors
+            .iter()
+            .map(|e| (e.path.as_str(), e.reason.as_str()))
+            .collect();
+        let loose = outside.path().join("loose.rs").display().to_string();
+        assert_eq!(
+            errors,
+            [
+                ("../escape.rs", "outside the repository"),
+                (loose.as_str(), "outside the repository"),
+                ("src", "is a directory"),
+                ("src/gone.rs", "not found"),
+            ]
+        );
+
+        // Unchanged files are skipped; a deleted one loses its rows
+        fs::remove_file(root.join("src/file_1.rs")).unwrap();
+        let stats = index
+            .index_paths(&[
+                PathBuf::from("src/file_0.rs"),
+                PathBuf::from("src/file_1.rs"),
+            ])
+            .unwrap();
+        assert_eq!(stats.files_indexed, 0);
+        assert_eq!(stats.files_skipped, 1);
+        assert_eq!(stats.files_removed, 1);
+        assert!(stats.errors.is_empty());
+        assert_eq!(index.indexed_paths().unwrap(), ["src/file_0.rs"]);
+        assert!(index.search_code("func_1", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn index_file_reparses_one_changed_file() {
+        let dir = setup_repo(3);
+        let root = dir.path();
+        let mut index = RepoIndex::open(root).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        // Unchanged content is skipped by hash
+        let stats = index.index_file(Path::new("src/file_0.rs")).unwrap();
+        assert_eq!((stats.files_indexed, stats.skipped.hash), (0, 1));
+
+        // An edit is picked up even within the stat ttl, old rows and cache
+        // entries replaced
+        fs::write(root.join("src/file_0.rs"), "pub fn edited_alpha() {}\n").unwrap();
+        let stats = index.index_file(&root.join("src/file_0.rs")).unwrap();
+        assert_eq!(stats.files_indexed, 1);
+        assert!(index.symbol_cache.contains_key("edited_alpha"));
+        assert!(!index.symbol_cache.contains_key("func_0"));
+        assert_eq!(index.search_code("edited_alpha", 10).unwrap().len(), 1);
+
+        // A new file is indexed, a deleted one removed
+        fs::write(root.join("src/added.rs"), "fn added_beta() {}\n").unwrap();
+        assert_eq!(
+            index
+                .index_file(Path::new("src/added.rs"))
+                .unwrap()
+                .files_indexed,
+            1
+        );
+        fs::remove_file(root.join("src/file_1.rs")).unwrap();
+        let stats = index.index_file(Path::new("src/file_1.rs")).unwrap();
+        assert_eq!((stats.files_indexed, stats.files_removed), (0, 1));
+        let mut indexed = index.indexed_paths().unwrap();
+        indexed.sort();
+        assert_eq!(indexed, ["src/added.rs", "src/file_0.rs", "src/file_2.rs"]);
+
+        let outside = tempfile::TempDir::new().unwrap();
+        fs::write(outside.path().join("loose.rs"), "fn loose() {}\n").unwrap();
+        for path in [
+            outside.path().join("loose.rs"),
+            PathBuf::from("../loose.rs"),
+        ] {
+            let err = index.index_file(&path).unwrap_err();
+            assert!(matches!(err, CanopyError::PathOutsideRepo(_)), "{err:?}");
+        }
+        let err = index.index_file(Path::new("src/never.rs")).unwrap_err();
+        assert!(matches!(err, CanopyError::FileNotFound(_)), "{err:?}");
+        assert!(index.index_file(Path::new("src")).is_err());
+
+        // A deleted directory takes every file under it, and only those
+        fs::write(root.join("src.rs"), "fn sibling() {}\n").unwrap();
+        index.index_file(Path::new("src.rs")).unwrap();
+        fs::remove_dir_all(root.join("src")).unwrap();
+        let stats = index.index_file(Path::new("src")).unwrap();
+        assert_eq!(stats.files_removed, 3);
+        assert_eq!(index.indexed_paths().unwrap(), ["src.rs"]);
+    }
+
+    #[test]
+    fn changed_paths_compares_content_hashes() {
+        let dir = setup_repo(3);
+        let root = dir.path();
+        let mut index = RepoIndex::open(root).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        // Same content rewritten, new content, deleted, new file, never existed
+        let same = fs::read_to_string(root.join("src/file_0.rs")).unwrap();
+        fs::write(root.join("src/file_0.rs"), same).unwrap();
+        fs::write(root.join("src/file_1.rs"), "fn edited() {}\n").unwrap();
+        fs::remove_file(root.join("src/file_2.rs")).unwrap();
+        fs::write(root.join("src/new.rs"), "fn new() {}\n").unwrap();
+
+        let paths: Vec<String> = [
+            "src/file_0.rs",
+            "src/file_1.rs",
+            "src/file_2.rs",
+            "src/new.rs",
+            "src/never.rs",
+        ]
+        .map(String::from)
+        .into();
+        assert_eq!(
+            index.changed_paths(&paths).unwrap(),
+            ["src/file_1.rs", "src/file_2.rs", "src/new.rs"]
+        );
+    }
 }
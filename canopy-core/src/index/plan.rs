@@ -0,0 +1,179 @@
+//! Dry runs of index and invalidate: what a run would change, without writing.
+//!
+//! Plans walk the same candidates and take the same per-file decision
+//! ([`SkipPolicy::action`]) as the real runs, so a preview can't disagree
+//! with the run it stands for (short of files changing in between).
+
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::freshness::{FileAction, SkipCounts, SkipPolicy, SkipReason};
+use super::RepoIndex;
+use crate::parse::estimate_tokens;
+
+/// What [`RepoIndex::index`] or [`RepoIndex::invalidate`] would change.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct IndexPlan {
+    /// Files never indexed that would be parsed
+    pub new_files: Vec<String>,
+    /// Indexed files that changed (or a migration flagged) and would be reparsed
+    pub reindexed: Vec<String>,
+    /// Indexed files that would keep their stored parse
+    pub skipped: Vec<PlannedSkip>,
+    /// `skipped` by reason
+    pub skip_counts: SkipCounts,
+    /// Files whose rows would be dropped
+    pub removed: Vec<String>,
+    /// Candidates that couldn't be read, left as they are
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unreadable: Vec<String>,
+    /// Estimated change in indexed tokens
+    pub token_delta: i64,
+}
+
+/// A file a run would skip, and why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedSkip {
+    pub path: String,
+    pub reason: SkipReason,
+}
+
+impl IndexPlan {
+    /// Files the run would parse: `new_files` plus `reindexed`.
+    pub fn files_to_index(&self) -> usize {
+        self.new_files.len() + self.reindexed.len()
+    }
+}
+
+impl RepoIndex {
+    /// What [`index`](Self::index) would do for `glob`, without writing anything.
+    pub fn plan_index(&self, glob: &str) -> crate::Result<IndexPlan> {
+        let now_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let policy = SkipPolicy::new(&self.config, now_secs);
+
+        let mut plan = IndexPlan::default();
+        for (file_path, relative_path) in self.glob_candidates(glob)? {
+            let meta = match self.owning_index(&relative_path) {
+                Some(index) => index.file_meta(&relative_path)?,
+                None => None,
+            };
+            match policy.action(meta.as_ref(), &file_path) {
+                FileAction::Skip(reason) => {
+                    plan.skip_counts.record(reason);
+                    plan.skipped.push(PlannedSkip {
+                        path: relative_path,
+                        reason,
+                    });
+                }
+                FileAction::Parse(file) => {
+                    let tokens = estimate_tokens(&file.source) as i64;
+                    match meta {
+                        Some(meta) => {
+                            plan.token_delta += tokens - meta.tokens as i64;
+                            plan.reindexed.push(relative_path);
+                        }
+                        None => {
+                            plan.token_delta += tokens;
+                            plan.new_files.push(relative_path);
+                        }
+                    }
+                }
+                FileAction::Unreadable => plan.unreadable.push(relative_path),
+            }
+        }
+        Ok(plan)
+    }
+
+    /// What [`invalidate`](Self::invalidate) would remove for `glob`.
+    pub fn plan_invalidate(&self, glob: Option<&str>) -> crate::Result<IndexPlan> {
+        let glob = glob.map(|g| self.path_style.normalize(g).into_owned());
+        let glob = glob.as_deref();
+
+        let mut plan = IndexPlan::default();
+        for index in self.query_targets(self.invalidate_route(glob)) {
+            for (path, tokens) in index.invalidated_files(glob)? {
+                plan.token_delta -= tokens as i64;
+                plan.removed.push(path);
+            }
+        }
+        plan.removed.sort();
+        Ok(plan)
+    }
+
+    /// The database an index run writes `relative_path` to; `None` for a
+    /// shard not created yet.
+    fn owning_index(&self, relative_path: &str) -> Option<&RepoIndex> {
+        if !self.shards.has_patterns() {
+            return Some(self);
+        }
+        match self.shards.route(relative_path) {
+            Some(prefix) => self.shards.get(&prefix).map(|shard| &shard.index),
+            None => Some(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_helpers::setup_repo;
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn plan_index_matches_the_run() {
+        let dir = setup_repo(4);
+        let root = dir.path();
+        let mut index = RepoIndex::open(root).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        fs::write(
+            root.join("src/file_0.rs"),
+            "fn edited() { longer_body(1, 2, 3) }\n",
+        )
+        .unwrap();
+        // A later mtime, as the edit could land within the indexed second
+        fs::File::options()
+            .write(true)
+            .open(root.join("src/file_0.rs"))
+            .unwrap()
+            .set_modified(SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+        fs::write(root.join("src/new.rs"), "fn new() {}\n").unwrap();
+        let before = index.status().unwrap().total_tokens as i64;
+
+        let plan = index.plan_index("**/*.rs").unwrap();
+        assert_eq!(plan.new_files, ["src/new.rs"]);
+        assert_eq!(plan.reindexed, ["src/file_0.rs"]);
+        assert_eq!(plan.skipped.len(), 3);
+        assert!(plan.removed.is_empty());
+        // Nothing was written
+        assert_eq!(index.status().unwrap().total_tokens as i64, before);
+        assert_eq!(index.plan_index("**/*.rs").unwrap(), plan);
+
+        let stats = index.index("**/*.rs").unwrap();
+        assert_eq!(stats.files_indexed, plan.files_to_index());
+        assert_eq!(stats.files_skipped, plan.skipped.len());
+        assert_eq!(stats.skipped, plan.skip_counts);
+        let after = index.status().unwrap().total_tokens as i64;
+        assert_eq!(after - before, plan.token_delta);
+    }
+
+    #[test]
+    fn plan_invalidate_matches_the_run() {
+        let dir = setup_repo(3);
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        let before = index.status().unwrap().total_tokens as i64;
+
+        let plan = index.plan_invalidate(Some("src/file_[01].rs")).unwrap();
+        assert_eq!(plan.removed, ["src/file_0.rs", "src/file_1.rs"]);
+        assert_eq!(index.plan_invalidate(None).unwrap().removed.len(), 3);
+
+        assert_eq!(index.invalidate(Some("src/file_[01].rs")).unwrap(), 2);
+        let after = index.status().unwrap().total_tokens as i64;
+        assert_eq!(after - before, plan.token_delta);
+    }
+}
@@ -0,0 +1,421 @@
+//! Recency filters: `(recent "7d" ...)` and `modified_within`.
+//!
+//! A file changed at its last commit time when `[indexing] git_commit_times`
+//! recorded one, else at its stored mtime. While a recent query runs, every
+//! database it reads carries the cutoff (see [`RecentScope`]) and each search
+//! appends [`RepoIndex::scope_filter`] to its SQL, so the filter applies
+//! before the limit in FTS, symbol, section, reference, annotation and file
+//! searches alike.
+//!
+//! Mtimes are only as good as the checkout: a fresh clone stamps every file
+//! with the clone's time. [`RepoIndex::mtime_warning`] says so in status.
+
+use rusqlite::params;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::RepoIndex;
+use crate::git;
+
+/// When a `files` row aliased `f` last changed, in SQL
+const CHANGED_AT: &str = "COALESCE(f.commit_time, f.mtime)";
+
+/// `meta` key set once every file's commit time has been looked up
+const COMMIT_TIMES_META_KEY: &str = "commit_times_backfilled";
+
+/// Fewest files for a shared mtime to be worth a warning
+const MTIME_WARNING_MIN_FILES: usize = 10;
+
+/// Restricts searches of a set of databases to recently changed files;
+/// dropping it restores the previous restriction.
+pub(crate) struct RecentScope<'a> {
+    previous: Vec<(&'a RepoIndex, Option<i64>)>,
+}
+
+impl<'a> RecentScope<'a> {
+    /// Keep searches of `indexes` to files changed within `window` of now.
+    /// Nested scopes narrow the window, never widen it.
+    pub(crate) fn new(indexes: impl IntoIterator<Item = &'a RepoIndex>, window: Duration) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let cutoff = now.saturating_sub(window.as_secs()) as i64;
+        let previous = indexes
+            .into_iter()
+            .map(|index| {
+                let previous = index.modified_since.get();
+                index
+                    .modified_since
+                    .set(Some(previous.map_or(cutoff, |p| p.max(cutoff))));
+                (index, previous)
+            })
+            .collect();
+        Self { previous }
+    }
+}
+
+impl Drop for RecentScope<'_> {
+    fn drop(&mut self) {
+        for (index, previous) in &self.previous {
+            index.modified_since.set(*previous);
+        }
+    }
+}
+
+impl RepoIndex {
+    /// `AND` clause keeping rows whose file (aliased `f`) changed inside the
+    /// current recent scope; empty outside one. The cutoff is an integer we
+    /// computed, so it is inlined rather than bound.
+    pub(crate) fn modified_filter(&self) -> String {
+        match self.modified_since.get() {
+            Some(cutoff) => format!(" AND {CHANGED_AT} >= {cutoff}"),
+            None => String::new(),
+        }
+    }
+
+    /// `AND` clauses for everything that currently scopes searches (file
+    /// aliased `f`): the recent cutoff, path fragments and the
+    /// generated-file filter.
+    pub(crate) fn scope_filter(&self) -> String {
+        format!(
+            "{}{}{}",
+            self.modified_filter(),
+            self.path_filter(),
+            self.generated_filter()
+        )
+    }
+
+    /// With `git_commit_times` on, record the last commit time of files
+    /// indexed since `indexed_since`, or of every file the first time.
+    /// Files with uncommitted changes, and untracked ones, keep none and
+    /// fall back to their mtime. Without git this does nothing.
+    pub(super) fn refresh_commit_times(&mut self, indexed_since: i64) -> crate::Result<()> {
+        if !self.config.indexing.git_commit_times {
+            return Ok(());
+        }
+        let backfilled: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM meta WHERE key = ?)",
+            params![COMMIT_TIMES_META_KEY],
+            |row| row.get(0),
+        )?;
+        let paths: Vec<String> = {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT path FROM files WHERE ?1 OR indexed_at >= ?2")?;
+            let rows = stmt.query_map(params![!backfilled, indexed_since], |row| row.get(0))?;
+            rows.collect::<Result<_, _>>()?
+        };
+        if paths.is_empty() && backfilled {
+            return Ok(());
+        }
+        let Some(mut times) = git::last_commit_times(&self.repo_root, &paths) else {
+            return Ok(());
+        };
+        for path in git::changed_since_head(&self.repo_root).unwrap_or_default() {
+            times.remove(&path);
+        }
+
+        let tx = self.conn.transaction()?;
+        {
+            let mut update = tx.prepare("UPDATE files SET commit_time = ? WHERE path = ?")?;
+            for path in &paths {
+                update.execute(params![times.get(path), path])?;
+            }
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES (?, '1')",
+            params![COMMIT_TIMES_META_KEY],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// A warning when most files share one mtime, as after a fresh clone,
+    /// so recency filters can't tell them apart. None with commit times on.
+    pub(super) fn mtime_warning(&self) -> crate::Result<Option<String>> {
+        if self.config.indexing.git_commit_times {
+            return Ok(None);
+        }
+        let mut by_mtime: HashMap<i64, usize> = HashMap::new();
+        for index in self.all_indexes() {
+            let mut stmt = index
+                .conn
+                .prepare("SELECT mtime, COUNT(*) FROM files GROUP BY mtime")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)?)))?;
+            for row in rows {
+                let (mtime, count) = row?;
+                *by_mtime.entry(mtime).or_default() += count.max(0) as usize;
+            }
+        }
+        let files: usize = by_mtime.values().sum();
+        let shared = by_mtime.values().copied().max().unwrap_or(0);
+        if files < MTIME_WARNING_MIN_FILES || shared * 2 <= files {
+            return Ok(None);
+        }
+        Ok(Some(format!(
+            "{shared} of {files} files share one mtime (a fresh clone?), so recent-file \
+             filters can't tell them apart; set `git_commit_times = true` under [indexing] \
+             and reindex to filter by last commit time"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::QueryParams;
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+
+    const DAY: u64 = 86_400;
+
+    fn set_age(path: &Path, age: Duration) {
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    fn aged_repo() -> (FixtureRepo, RepoIndex) {
+        let repo = FixtureRepoBuilder::new()
+            .file(
+                "src/fresh.rs",
+                "pub fn session_token() {}\nfn login() { session_token(); }\n",
+            )
+            .file(
+                "src/stale.rs",
+                "pub fn session_token() {}\nfn logout() { session_token(); }\n",
+            )
+            .file("docs/fresh.md", "# Sessions\n\nsession_token rules\n")
+            .file("docs/stale.md", "# Sessions\n\nsession_token history\n")
+            .build();
+        let root = repo.path();
+        set_age(&root.join("src/stale.rs"), Duration::from_secs(30 * DAY));
+        set_age(&root.join("docs/stale.md"), Duration::from_secs(30 * DAY));
+        set_age(&root.join("src/fresh.rs"), Duration::from_secs(2 * DAY));
+        set_age(&root.join("docs/fresh.md"), Duration::from_secs(DAY));
+
+        RepoIndex::init(root).unwrap();
+        let mut index = RepoIndex::open(root).unwrap();
+        index.index("**/*").unwrap();
+        (repo, index)
+    }
+
+    fn paths(index: &RepoIndex, params: QueryParams) -> Vec<String> {
+        let result = index.query_params(params).unwrap();
+        let mut paths: Vec<String> = match result.ref_handles {
+            Some(refs) => refs.into_iter().map(|r| r.file_path).collect(),
+            None => result.handles.into_iter().map(|h| h.file_path).collect(),
+        };
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    #[test]
+    fn modified_within_filters_every_search_path() {
+        let (_dir, index) = aged_repo();
+        let week = |params: QueryParams| QueryParams {
+            modified_within: Some("7d".to_string()),
+            ..params
+        };
+
+        assert_eq!(
+            paths(&index, week(QueryParams::pattern("session_token"))),
+            vec!["docs/fresh.md", "src/fresh.rs"]
+        );
+        assert_eq!(
+            paths(&index, week(QueryParams::symbol("session_token"))),
+            vec!["src/fresh.rs"]
+        );
+        assert_eq!(
+            paths(&index, week(QueryParams::section("Sessions"))),
+            vec!["docs/fresh.md"]
+        );
+        let refs = QueryParams::symbol("session_token").with_kind(crate::QueryKind::Reference);
+        assert_eq!(paths(&index, week(refs)), vec!["src/fresh.rs"]);
+        assert_eq!(
+            paths(
+                &index,
+                week(QueryParams::pattern("session_token").with_glob("src/**"))
+            ),
+            vec!["src/fresh.rs"]
+        );
+
+        // A wider window takes the stale files back, and unscoped queries
+        // afterwards see everything
+        let month = QueryParams {
+            modified_within: Some("60d".to_string()),
+            ..QueryParams::symbol("session_token")
+        };
+        assert_eq!(paths(&index, month).len(), 2);
+        assert_eq!(
+            paths(&index, QueryParams::pattern("session_token")).len(),
+            4
+        );
+        assert_eq!(index.modified_since.get(), None);
+    }
+
+    #[test]
+    fn recent_dsl_composes_with_counts_and_nesting() {
+        let (_dir, index) = aged_repo();
+        let count = |dsl: &str| {
+            index
+                .query_params(QueryParams {
+                    dsl: Some(dsl.to_string()),
+                    mode: crate::QueryMode::Count,
+                    ..Default::default()
+                })
+                .unwrap()
+                .total_matches
+        };
+        assert_eq!(count(r#"(recent "7d" (code "session_token"))"#), 1);
+        assert_eq!(count(r#"(code "session_token")"#), 2);
+        // The narrower of nested windows wins
+        let day = count(r#"(recent "36h" (grep "session_token"))"#);
+        assert!(day > 0 && day < count(r#"(recent "7d" (grep "session_token"))"#));
+        assert_eq!(
+            count(r#"(recent "60d" (recent "36h" (grep "session_token")))"#),
+            day
+        );
+
+        let query = crate::query::parse_query(
+            r#"(union (recent "36h" (grep "session_token")) (definition "session_token"))"#,
+        )
+        .unwrap();
+        let result = crate::query::execute_query(&query, &index, None).unwrap();
+        let mut found: Vec<&str> = result
+            .handles
+            .iter()
+            .map(|h| h.file_path.as_str())
+            .collect();
+        found.sort();
+        found.dedup();
+        assert_eq!(found, vec!["docs/fresh.md", "src/fresh.rs", "src/stale.rs"]);
+    }
+
+    #[test]
+    fn bad_windows_are_rejected_clearly() {
+        let (_dir, index) = aged_repo();
+        let err = index
+            .query_params(QueryParams {
+                modified_within: Some("a week".to_string()),
+                ..QueryParams::pattern("session_token")
+            })
+            .unwrap_err();
+        assert!(err.to_string().contains("a week"), "{err}");
+        assert!(crate::query::parse_query(r#"(recent "soon" (grep "x"))"#).is_err());
+    }
+
+    #[test]
+    fn status_warns_when_most_files_share_an_mtime() {
+        let repo = (0..12)
+            .fold(FixtureRepoBuilder::new(), |builder, i| {
+                builder.file(format!("f{i}.txt"), format!("file {i}\n"))
+            })
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        for i in 0..12 {
+            set_age(
+                &repo.path().join(format!("f{i}.txt")),
+                Duration::from_secs(DAY),
+            );
+        }
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*.txt").unwrap();
+        let warning = index.status().unwrap().mtime_warning.unwrap();
+        assert!(warning.contains("12 of 12 files"), "{warning}");
+        assert!(warning.contains("git_commit_times"));
+
+        let (_dir, aged) = aged_repo();
+        assert!(aged.status().unwrap().mtime_warning.is_none());
+    }
+
+    fn git(root: &Path, args: &[&str], date: &str) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+            .args(args)
+            .env("GIT_AUTHOR_DATE", date)
+            .env("GIT_COMMITTER_DATE", date)
+            .current_dir(root)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?}");
+    }
+
+    #[test]
+    fn commit_times_replace_checkout_mtimes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        git(root, &["init", "-q"], "");
+        fs::write(root.join("old.rs"), "fn clone_target() {}\n").unwrap();
+        git(root, &["add", "old.rs"], "");
+        git(root, &["commit", "-qm", "old"], "2000-01-01T00:00:00Z");
+        fs::write(root.join("new.rs"), "fn clone_target() {}\n").unwrap();
+        git(root, &["add", "new.rs"], "");
+        git(
+            root,
+            &["commit", "-qm", "new"],
+            &format!("@{} +0000", {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+                    - DAY
+            }),
+        );
+        fs::write(root.join("dirty.rs"), "fn clone_target() {}\n").unwrap();
+
+        RepoIndex::init(root).unwrap();
+        fs::write(
+            root.join(".canopy/config.toml"),
+            "[indexing]\ngit_commit_times = true\n",
+        )
+        .unwrap();
+        let mut index = RepoIndex::open(root).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        // Every file was just written, but old.rs was committed in 2000
+        let recent = paths(
+            &index,
+            QueryParams {
+                modified_within: Some("7d".to_string()),
+                ..QueryParams::symbol("clone_target")
+            },
+        );
+        assert_eq!(recent, vec!["dirty.rs", "new.rs"]);
+        assert!(index.status().unwrap().mtime_warning.is_none());
+    }
+
+    #[test]
+    fn single_file_reindex_records_commit_times() {
+        let repo = FixtureRepoBuilder::new()
+            .file("fresh.rs", "fn clone_target() {}\n")
+            .build();
+        let root = repo.path();
+        git(root, &["init", "-q"], "");
+        RepoIndex::init(root).unwrap();
+        repo.write(
+            ".canopy/config.toml",
+            "[indexing]\ngit_commit_times = true\n",
+        );
+        let mut index = RepoIndex::open(root).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        // Written just now, but committed in 2000
+        repo.write("old.rs", "fn clone_target() {}\n");
+        git(root, &["add", "old.rs"], "");
+        git(root, &["commit", "-qm", "old"], "2000-01-01T00:00:00Z");
+        index.index_file(Path::new("old.rs")).unwrap();
+
+        let recent = paths(
+            &index,
+            QueryParams {
+                modified_within: Some("7d".to_string()),
+                ..QueryParams::symbol("clone_target")
+            },
+        );
+        assert_eq!(recent, vec!["fresh.rs"]);
+    }
+}
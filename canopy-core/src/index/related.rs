@@ -0,0 +1,450 @@
+//! Related files: the files that share the most symbols with one file, from
+//! the refs and definitions already indexed, without embeddings.
+//!
+//! Another file is related to `path` when `path` references symbols it
+//! defines, or when it references symbols defined in `path`. Names match
+//! exactly, case included. A shared name weighs `1 + ln(refs)`, so sharing
+//! many symbols beats calling one in a loop, split between every file
+//! defining that name, so a name like `new` that half the repo defines adds
+//! little to any one edge. A reference to a name the referencing file defines
+//! itself resolves there and counts for nothing. Sharing `path`'s directory
+//! adds a small bonus that only breaks near-ties.
+
+use crate::error::CanopyError;
+use crate::handle::Handle;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
+
+use super::search::code_type_params;
+use super::RepoIndex;
+
+/// Default `limit` for [`RepoIndex::related_files`].
+pub const DEFAULT_RELATED_LIMIT: usize = 10;
+
+/// Score added for sharing the file's directory.
+const SAME_DIRECTORY_BONUS: f64 = 0.25;
+
+/// Files ranked by the symbols they share with one file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelatedFiles {
+    /// The file the others relate to, as stored
+    pub path: String,
+    /// Highest score first
+    pub files: Vec<RelatedFile>,
+    /// Files sharing at least one symbol, before `limit`
+    pub total_related: usize,
+}
+
+/// One edge of the related files graph, with the symbols that make it up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelatedFile {
+    pub path: String,
+    pub score: f64,
+    /// Symbols defined here that the file references
+    pub defines: Vec<SharedSymbol>,
+    /// Symbols defined in the file that this one references
+    pub references: Vec<SharedSymbol>,
+    /// In the same directory as the file
+    pub same_directory: bool,
+}
+
+/// A symbol shared along an edge, and how many references cross it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SharedSymbol {
+    pub name: String,
+    pub refs: usize,
+}
+
+impl RepoIndex {
+    /// The `limit` files most related to the indexed file `path`, across
+    /// every database of the index.
+    pub fn related_files(&self, path: &str, limit: usize) -> crate::Result<RelatedFiles> {
+        let Some((home, file_id, stored)) = self.locate_file(path)? else {
+            return Err(CanopyError::FileNotFound(PathBuf::from(path)));
+        };
+        let refs = home.file_ref_counts(file_id)?;
+        let defined = home.file_definitions(file_id)?;
+
+        let names: BTreeSet<&String> = refs.keys().chain(&defined).collect();
+        let names = serde_json::to_string(&names)?;
+        let defined_names = serde_json::to_string(&defined)?;
+
+        // name -> files defining it
+        let mut definers: HashMap<String, BTreeSet<String>> = HashMap::new();
+        // referencing file -> name -> reference count
+        let mut referencers: BTreeMap<String, BTreeMap<String, usize>> = BTreeMap::new();
+        for index in self.all_indexes() {
+            index.collect_definers(&names, &mut definers)?;
+            index.collect_referencers(&defined_names, &stored, &mut referencers)?;
+        }
+
+        let mut edges: BTreeMap<String, RelatedFile> = BTreeMap::new();
+        for (name, count) in &refs {
+            let Some(files) = definers.get(name).filter(|files| !files.contains(&stored)) else {
+                continue;
+            };
+            let weight = shared_weight(*count) / files.len() as f64;
+            for file in files {
+                let related = related_entry(&mut edges, file);
+                related.score += weight;
+                related.defines.push(SharedSymbol {
+                    name: name.clone(),
+                    refs: *count,
+                });
+            }
+        }
+
+        for (file, names) in referencers {
+            for (name, count) in names {
+                let files = definers.get(&name);
+                if files.is_some_and(|files| files.contains(&file)) {
+                    continue;
+                }
+                let related = related_entry(&mut edges, &file);
+                related.score += shared_weight(count) / files.map_or(1, BTreeSet::len) as f64;
+                related.references.push(SharedSymbol { name, refs: count });
+            }
+        }
+
+        let directory = parent_directory(&stored);
+        let mut files: Vec<RelatedFile> = edges
+            .into_values()
+            .map(|mut file| {
+                file.same_directory = parent_directory(&file.path) == directory;
+                if file.same_directory {
+                    file.score += SAME_DIRECTORY_BONUS;
+                }
+                file.score = (file.score * 100.0).round() / 100.0;
+                sort_shared(&mut file.defines);
+                sort_shared(&mut file.references);
+                file
+            })
+            .collect();
+        files.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        let total_related = files.len();
+        files.truncate(limit);
+        Ok(RelatedFiles {
+            path: stored,
+            files,
+            total_related,
+        })
+    }
+
+    /// Whole-file handles for the files most related to `path`, in rank order.
+    pub(crate) fn related_file_handles(
+        &self,
+        path: &str,
+        limit: usize,
+    ) -> crate::Result<Vec<Handle>> {
+        let related = self.related_files(path, limit)?;
+        let mut handles = Vec::with_capacity(related.files.len());
+        for file in &related.files {
+            for index in self.all_indexes() {
+                if let Some(handle) = index.file_handle_at(&file.path)? {
+                    handles.push(handle);
+                    break;
+                }
+            }
+        }
+        Ok(handles)
+    }
+
+    /// The database storing `path`, with the file's id and stored path.
+    fn locate_file(&self, path: &str) -> crate::Result<Option<(&RepoIndex, i64, String)>> {
+        let normalized = self.path_style.normalize(path);
+        let path = normalized.trim_start_matches("./");
+        let collate = if self.path_style.case_insensitive {
+            " COLLATE NOCASE"
+        } else {
+            ""
+        };
+        for index in self.all_indexes() {
+            let found = index
+                .conn
+                .query_row(
+                    &format!("SELECT id, path FROM files WHERE path = ?{collate}"),
+                    params![path],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+            if let Some((id, stored)) = found {
+                return Ok(Some((index, id, stored)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Names the file references, with how often.
+    fn file_ref_counts(&self, file_id: i64) -> crate::Result<BTreeMap<String, usize>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT r.name, COUNT(*) FROM refs r WHERE r.file_id = ? GROUP BY r.name")?;
+        let rows = stmt.query_map(params![file_id], |row| {
+            let count: i64 = row.get(1)?;
+            Ok((row.get(0)?, count.max(0) as usize))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Symbols the file defines.
+    fn file_definitions(&self, file_id: i64) -> crate::Result<BTreeSet<String>> {
+        let code_types = code_type_params();
+        let mut stmt = self.conn.prepare(
+            "SELECT DISTINCT n.name FROM nodes n
+             WHERE n.file_id = ? AND n.name IS NOT NULL AND n.node_type IN (?, ?, ?, ?)",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                file_id,
+                code_types[0],
+                code_types[1],
+                code_types[2],
+                code_types[3]
+            ],
+            |row| row.get(0),
+        )?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Add the files here defining any of `names` (a JSON array).
+    fn collect_definers(
+        &self,
+        names: &str,
+        definers: &mut HashMap<String, BTreeSet<String>>,
+    ) -> crate::Result<()> {
+        let code_types = code_type_params();
+        // name_lower is indexed; the name check makes the match exact
+        let mut stmt = self.conn.prepare(
+            "SELECT DISTINCT n.name, f.path
+             FROM json_each(?) j
+             JOIN nodes n ON n.name_lower = j.value AND n.name = j.value
+             JOIN files f ON n.file_id = f.id
+             WHERE n.node_type IN (?, ?, ?, ?)",
+        )?;
+        let mut rows = stmt.query(params![
+            names,
+            code_types[0],
+            code_types[1],
+            code_types[2],
+            code_types[3]
+        ])?;
+        while let Some(row) = rows.next()? {
+            definers.entry(row.get(0)?).or_default().insert(row.get(1)?);
+        }
+        Ok(())
+    }
+
+    /// Add the files here other than `path` referencing any of `names` (a
+    /// JSON array), with how often.
+    fn collect_referencers(
+        &self,
+        names: &str,
+        path: &str,
+        referencers: &mut BTreeMap<String, BTreeMap<String, usize>>,
+    ) -> crate::Result<()> {
+        let mut stmt = self.conn.prepare(
+            "SELECT f.path, r.name, COUNT(*)
+             FROM json_each(?) j
+             JOIN refs r ON r.name_lower = j.value AND r.name = j.value
+             JOIN files f ON r.file_id = f.id
+             WHERE f.path != ?
+             GROUP BY f.path, r.name",
+        )?;
+        let mut rows = stmt.query(params![names, path])?;
+        while let Some(row) = rows.next()? {
+            let count: i64 = row.get(2)?;
+            *referencers
+                .entry(row.get(0)?)
+                .or_default()
+                .entry(row.get(1)?)
+                .or_default() += count.max(0) as usize;
+        }
+        Ok(())
+    }
+}
+
+/// Weight of one shared name referenced `refs` times.
+fn shared_weight(refs: usize) -> f64 {
+    1.0 + (refs.max(1) as f64).ln()
+}
+
+fn related_entry<'a>(
+    edges: &'a mut BTreeMap<String, RelatedFile>,
+    path: &str,
+) -> &'a mut RelatedFile {
+    edges
+        .entry(path.to_string())
+        .or_insert_with(|| RelatedFile {
+            path: path.to_string(),
+            score: 0.0,
+            defines: Vec::new(),
+            references: Vec::new(),
+            same_directory: false,
+        })
+}
+
+/// Most referenced first, then by name.
+fn sort_shared(symbols: &mut [SharedSymbol]) {
+    symbols.sort_by(|a, b| b.refs.cmp(&a.refs).then_with(|| a.name.cmp(&b.name)));
+}
+
+fn parent_directory(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{QueryMode, QueryParams};
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+
+    /// `src/auth/session.rs` is the hub: it calls into token.rs, crypto.rs
+    /// and legacy/verify.rs, and token.rs and api/handler.rs call into it.
+    fn hub_repo() -> (FixtureRepo, RepoIndex) {
+        let files = [
+            (
+                "src/auth/session.rs",
+                "pub struct Session { token: String }\n\
+                 pub fn load_session(token: &str) -> Session {\n    verify_token(token);\n    Session { token: hash_password(token) }\n}\n\
+                 pub fn refresh_session(session: &Session) {\n    verify_token(&session.token);\n}\n",
+            ),
+            (
+                "src/auth/token.rs",
+                "pub fn verify_token(token: &str) -> bool { !token.is_empty() }\n\
+                 pub fn issue_token() { load_session(\"seed\"); }\n",
+            ),
+            (
+                "src/legacy/verify.rs",
+                "pub fn verify_token(token: &str) -> bool { token.len() > 8 }\n",
+            ),
+            (
+                "src/crypto.rs",
+                "pub fn hash_password(password: &str) -> String { password.to_string() }\n",
+            ),
+            (
+                "src/api/handler.rs",
+                "pub fn handle() {\n    let session = load_session(\"t\");\n    refresh_session(&session);\n    log_request();\n}\n",
+            ),
+            ("src/log.rs", "pub fn log_request() {}\n"),
+        ];
+        let repo = FixtureRepoBuilder::new().files(&files).build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        (repo, index)
+    }
+
+    fn dsl(index: &RepoIndex, query: &str) -> crate::Result<crate::QueryResult> {
+        index.query_params(QueryParams {
+            dsl: Some(query.to_string()),
+            ..Default::default()
+        })
+    }
+
+    fn names(symbols: &[SharedSymbol]) -> Vec<(&str, usize)> {
+        symbols.iter().map(|s| (s.name.as_str(), s.refs)).collect()
+    }
+
+    #[test]
+    fn related_files_rank_the_hub_neighbours_with_explanations() {
+        let (_dir, index) = hub_repo();
+        let related = index.related_files("src/auth/session.rs", 10).unwrap();
+        assert_eq!(related.path, "src/auth/session.rs");
+        let ranked: Vec<(&str, f64)> = related
+            .files
+            .iter()
+            .map(|f| (f.path.as_str(), f.score))
+            .collect();
+        // verify_token's two calls weigh 1 + ln 2, split between its two
+        // definitions; token.rs also calls load_session and shares the directory
+        assert_eq!(
+            ranked,
+            [
+                ("src/auth/token.rs", 2.1),
+                ("src/api/handler.rs", 2.0),
+                ("src/crypto.rs", 1.0),
+                ("src/legacy/verify.rs", 0.85),
+            ]
+        );
+        assert_eq!(related.total_related, 4);
+
+        let token = &related.files[0];
+        assert!(token.same_directory);
+        assert_eq!(names(&token.defines), [("verify_token", 2)]);
+        assert_eq!(names(&token.references), [("load_session", 1)]);
+        let handler = &related.files[1];
+        assert!(!handler.same_directory);
+        assert!(handler.defines.is_empty());
+        assert_eq!(
+            names(&handler.references),
+            [("load_session", 1), ("refresh_session", 1)]
+        );
+        assert_eq!(names(&related.files[2].defines), [("hash_password", 1)]);
+
+        let top = index.related_files("./src/auth/session.rs", 2).unwrap();
+        assert_eq!(top.files.len(), 2);
+        assert_eq!(top.total_related, 4);
+
+        // log.rs shares nothing with the hub, only with handler.rs
+        let log = index.related_files("src/log.rs", 10).unwrap();
+        assert_eq!(log.files.len(), 1);
+        assert_eq!(log.files[0].path, "src/api/handler.rs");
+        assert_eq!(names(&log.files[0].references), [("log_request", 1)]);
+    }
+
+    #[test]
+    fn related_dsl_returns_whole_files_in_rank_order() {
+        let (_dir, index) = hub_repo();
+        let result = dsl(&index, r#"(related "src/auth/session.rs")"#).unwrap();
+        let paths: Vec<&str> = result
+            .handles
+            .iter()
+            .map(|h| h.file_path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "src/auth/token.rs",
+                "src/api/handler.rs",
+                "src/crypto.rs",
+                "src/legacy/verify.rs"
+            ]
+        );
+        assert!(result.handles.iter().all(|h| h.line_range.0 == 1));
+
+        let limited = dsl(&index, r#"(limit 1 (related "src/auth/session.rs"))"#).unwrap();
+        assert_eq!(limited.handles.len(), 1);
+        assert!(limited.truncated);
+
+        let count = index
+            .query_params(QueryParams {
+                dsl: Some(r#"(related "src/auth/session.rs")"#.to_string()),
+                mode: QueryMode::Count,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(count.total_matches, 4);
+    }
+
+    #[test]
+    fn unindexed_files_are_not_found() {
+        let (_dir, index) = hub_repo();
+        let err = index.related_files("src/missing.rs", 10).unwrap_err();
+        assert!(matches!(err, CanopyError::FileNotFound(_)));
+        assert!(dsl(&index, r#"(related "src/missing.rs")"#).is_err());
+
+        // Inside a union the other legs still answer
+        let result = dsl(
+            &index,
+            r#"(union (related "src/missing.rs") (definition "log_request"))"#,
+        )
+        .unwrap();
+        assert_eq!(result.handles.len(), 1);
+    }
+}
@@ -0,0 +1,135 @@
+//! FTS scan accounting — candidate rows read, time spent, and guards hit.
+//!
+//! Some patterns (single characters, tokens in most nodes) make FTS5 walk
+//! enormous posting lists. Each content search records an [`FtsScan`], which
+//! explain output shows per search, and adds it to its index's
+//! [`FtsScanStats`]; the service drains those into per-repo counters on
+//! `/metrics`. `[fts] max_fts_candidates` bounds the rows one search reads.
+
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+use super::RepoIndex;
+
+/// Upper bounds of the rows-examined histogram buckets; one more bucket
+/// counts larger scans.
+pub const ROWS_EXAMINED_BUCKETS: [u64; 5] = [10, 100, 1_000, 10_000, 100_000];
+
+/// One full-text search.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FtsScan {
+    /// Candidate rows SQLite returned, before glob and limit filtering
+    pub rows_examined: usize,
+    pub elapsed_us: u64,
+    /// Stopped at `[fts] max_fts_candidates`, so the other candidates were dropped
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub capped: bool,
+}
+
+impl FtsScan {
+    pub(crate) fn finished(started: Instant, rows_examined: usize, capped: bool) -> Self {
+        Self {
+            rows_examined,
+            elapsed_us: started.elapsed().as_micros() as u64,
+            capped,
+        }
+    }
+}
+
+/// Full-text searches run on an index since its stats were last taken.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FtsScanStats {
+    pub scans: u64,
+    pub rows_examined: u64,
+    pub elapsed_us: u64,
+    /// Scans per [`ROWS_EXAMINED_BUCKETS`] bucket, the last counting the rest
+    pub rows_examined_histogram: [u64; ROWS_EXAMINED_BUCKETS.len() + 1],
+    /// Queries whose single term tripped the high-frequency token guard
+    pub guarded: u64,
+    /// Scans stopped at `[fts] max_fts_candidates`
+    pub capped: u64,
+}
+
+impl FtsScanStats {
+    pub fn record(&mut self, scan: &FtsScan) {
+        let rows = scan.rows_examined as u64;
+        self.scans += 1;
+        self.rows_examined += rows;
+        self.elapsed_us += scan.elapsed_us;
+        let bucket = ROWS_EXAMINED_BUCKETS
+            .iter()
+            .position(|&bound| rows <= bound)
+            .unwrap_or(ROWS_EXAMINED_BUCKETS.len());
+        self.rows_examined_histogram[bucket] += 1;
+        self.capped += u64::from(scan.capped);
+    }
+
+    pub fn merge(&mut self, other: &FtsScanStats) {
+        self.scans += other.scans;
+        self.rows_examined += other.rows_examined;
+        self.elapsed_us += other.elapsed_us;
+        for (total, count) in self
+            .rows_examined_histogram
+            .iter_mut()
+            .zip(other.rows_examined_histogram)
+        {
+            *total += count;
+        }
+        self.guarded += other.guarded;
+        self.capped += other.capped;
+    }
+}
+
+impl RepoIndex {
+    /// Scan stats gathered since the last call, across shards, resetting them.
+    pub fn take_scan_stats(&self) -> FtsScanStats {
+        let mut stats = FtsScanStats::default();
+        for index in self.all_indexes() {
+            stats.merge(&index.scan_stats.take());
+        }
+        stats
+    }
+
+    pub(crate) fn record_scan(&self, scan: &FtsScan) {
+        self.scan_stats.borrow_mut().record(scan);
+    }
+
+    pub(crate) fn record_guarded(&self) {
+        self.scan_stats.borrow_mut().guarded += 1;
+    }
+
+    /// Most candidate rows one full-text search may read.
+    pub(crate) fn fts_candidate_cap(&self) -> usize {
+        match self.config.fts.max_fts_candidates {
+            0 => usize::MAX,
+            cap => cap,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scans_land_in_their_histogram_bucket() {
+        let mut stats = FtsScanStats::default();
+        for rows in [0, 10, 11, 5_000, 250_000] {
+            stats.record(&FtsScan {
+                rows_examined: rows,
+                elapsed_us: 5,
+                capped: rows > 100_000,
+            });
+        }
+        assert_eq!(stats.rows_examined_histogram, [2, 1, 0, 1, 0, 1]);
+        assert_eq!(stats.scans, 5);
+        assert_eq!(stats.elapsed_us, 25);
+        assert_eq!(stats.capped, 1);
+
+        let mut total = FtsScanStats::default();
+        total.merge(&stats);
+        total.merge(&stats);
+        assert_eq!(total.rows_examined, 2 * stats.rows_examined);
+        assert_eq!(total.rows_examined_histogram, [4, 2, 0, 2, 0, 2]);
+    }
+}
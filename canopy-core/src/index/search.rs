@@ -1,46 +1,156 @@
 //! Index search methods — FTS, symbol, section, reference, and file queries.
 
-use crate::document::{NodeType, RefType};
-use crate::error::CanopyError;
-use crate::handle::{generate_preview, Handle, HandleId, HandleSource, RefHandle};
-use rusqlite::params;
+use crate::document::{NodeType, RefType, HEADING_PATH_SEPARATOR};
+use crate::handle::{Handle, HandleId, HandleSource, RefHandle};
+use crate::query::SearchPath;
+use rusqlite::{params, params_from_iter, OptionalExtension};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::time::Instant;
 
+use super::expand::EXPAND_LOOKUP_CHUNK;
+use super::scan_stats::FtsScan;
+use super::statements::in_list;
 use super::symbol_cache::SymbolCacheEntry;
 use super::RepoIndex;
 
 /// Shared column list for handle queries — matches the `handle_from_row` column order.
 pub(super) const HANDLE_SELECT: &str =
     "n.handle_id, f.path, n.node_type, n.start_byte, n.end_byte, \
-     n.line_start, n.line_end, n.token_count, n.preview";
+     n.line_start, n.line_end, n.token_count, n.preview, n.preview_tokens, n.content_hash";
+
+/// Tie-break `ORDER BY` columns for handle queries, matching
+/// [`Handle::position_cmp`]. FTS queries order by `rank` first.
+pub(super) const HANDLE_ORDER: &str = "f.path, n.start_byte, n.handle_id";
+
+/// Bytes of a node's SHA-256 kept as [`Handle::content_hash`]
+pub(crate) const SHORT_HASH_BYTES: usize = 8;
 
 impl RepoIndex {
-    /// Execute a handle query and collect results.
+    /// Execute a handle query, through the statement cache, and collect results.
     fn query_handles(
         &self,
         sql: &str,
         params: &[&dyn rusqlite::types::ToSql],
     ) -> crate::Result<Vec<Handle>> {
-        let mut stmt = self.conn.prepare(sql)?;
-        let handles = collect_row_results(stmt.query_map(params, handle_from_row)?)?;
-        Ok(handles)
+        self.with_statement(sql, |stmt| {
+            Ok(collect_row_results(
+                stmt.query_map(params, handle_from_row)?,
+            )?)
+        })
     }
 
     /// FTS5 search (used by query executor)
     pub fn fts_search(&self, query: &str, limit: usize) -> crate::Result<Vec<Handle>> {
+        Ok(self.fts_scan(query, limit)?.0)
+    }
+
+    /// [`fts_search`](Self::fts_search), and how much of the full-text index
+    /// it read.
+    ///
+    /// Only the best-ranked [`fts_candidate_cap`](Self::fts_candidate_cap)
+    /// matches reach the node joins. FTS5 still scores every match to pick
+    /// them, but a relevant node late in rowid order is never cut off. One
+    /// extra match is read to tell a scan that hit the cap from one that
+    /// found exactly that many.
+    pub(crate) fn fts_scan(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> crate::Result<(Vec<Handle>, FtsScan)> {
+        let started = Instant::now();
+        let scope = self.scope_filter();
         let escaped = escape_fts5_query(query);
-        let limit = limit as i64;
-        self.query_handles(
-            &format!(
-                "SELECT {HANDLE_SELECT}
+        let cap = self.fts_candidate_cap();
+        let sql = format!(
+            "SELECT {HANDLE_SELECT}, fts.candidates, fts.rank
+             FROM (
+                 SELECT rowid, rank, COUNT(*) OVER () AS candidates,
+                        ROW_NUMBER() OVER (ORDER BY rank) AS position
+                 FROM (
+                     SELECT rowid, rank FROM content_fts WHERE content_fts MATCH ?
+                     ORDER BY rank LIMIT ?
+                 )
+             ) fts
+             JOIN fts_node_map m ON fts.rowid = m.fts_rowid
+             JOIN nodes n ON m.node_id = n.id
+             JOIN files f ON n.file_id = f.id
+             WHERE fts.position <= ?{scope}
+             ORDER BY fts.rank, {HANDLE_ORDER}
+             LIMIT ?"
+        );
+        let (handles, candidates) = self.with_statement(&sql, |stmt| {
+            let rows = stmt.query_map(
+                params![
+                    escaped,
+                    i64::try_from(cap.saturating_add(1)).unwrap_or(-1),
+                    i64::try_from(cap).unwrap_or(i64::MAX),
+                    limit as i64
+                ],
+                |row| Ok((scored_handle_from_row(row, 12)?, row.get::<_, i64>(11)?)),
+            )?;
+            let mut handles = Vec::new();
+            let mut candidates = 0;
+            for row in rows {
+                let (handle, count) = row?;
+                candidates = count.max(0) as usize;
+                handles.push(handle);
+            }
+            Ok((handles, candidates))
+        })?;
+        let scan = FtsScan::finished(started, candidates.min(cap), candidates > cap);
+        self.record_scan(&scan);
+        Ok((handles, scan))
+    }
+
+    /// Count FTS matches for `query`, stopping at `cap` so the probe stays cheap.
+    pub(crate) fn fts_match_count(&self, query: &str, cap: usize) -> crate::Result<usize> {
+        let escaped = escape_fts5_query(query);
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM (
+                 SELECT 1 FROM content_fts WHERE content_fts MATCH ? LIMIT ?
+             )",
+            params![escaped, cap as i64],
+            |row| row.get(0),
+        )?;
+        Ok(count.max(0) as usize)
+    }
+
+    /// Which of the nodes `raw_ids` full-text match `query`.
+    pub(crate) fn fts_matching_ids(
+        &self,
+        query: &str,
+        raw_ids: &[&str],
+    ) -> crate::Result<HashSet<String>> {
+        let escaped = escape_fts5_query(query);
+        let mut matching = HashSet::new();
+        for chunk in raw_ids.chunks(EXPAND_LOOKUP_CHUNK) {
+            let (placeholders, chunk) = in_list(chunk);
+            let sql = format!(
+                "SELECT n.handle_id
                  FROM content_fts fts
                  JOIN fts_node_map m ON fts.rowid = m.fts_rowid
                  JOIN nodes n ON m.node_id = n.id
-                 JOIN files f ON n.file_id = f.id
-                 WHERE fts.content MATCH ?
-                 LIMIT ?"
-            ),
-            &[&escaped as &dyn rusqlite::types::ToSql, &limit],
-        )
+                 WHERE content_fts MATCH ? AND n.handle_id IN ({placeholders})"
+            );
+            self.with_statement(&sql, |stmt| {
+                let params = std::iter::once(escaped.as_str()).chain(chunk);
+                let ids =
+                    stmt.query_map(params_from_iter(params), |row| row.get::<_, String>(0))?;
+                for id in ids {
+                    matching.insert(id?);
+                }
+                Ok(())
+            })?;
+        }
+        Ok(matching)
+    }
+
+    /// Total number of indexed nodes.
+    pub(crate) fn node_count(&self) -> crate::Result<usize> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM nodes", [], |row| row.get(0))?;
+        Ok(count.max(0) as usize)
     }
 
     /// Get all nodes of a specific type
@@ -49,13 +159,16 @@ impl RepoIndex {
         node_type: NodeType,
         limit: usize,
     ) -> crate::Result<Vec<Handle>> {
+        let scope = self.scope_filter();
         let nt = node_type.as_int() as i32;
         let limit = limit as i64;
         self.query_handles(
             &format!(
                 "SELECT {HANDLE_SELECT}
                  FROM nodes n JOIN files f ON n.file_id = f.id
-                 WHERE n.node_type = ? LIMIT ?"
+                 WHERE n.node_type = ?{scope}
+                 ORDER BY {HANDLE_ORDER}
+                 LIMIT ?"
             ),
             &[&nt as &dyn rusqlite::types::ToSql, &limit],
         )
@@ -63,6 +176,7 @@ impl RepoIndex {
 
     /// Search for sections by heading (fuzzy match)
     pub fn search_sections(&self, heading: &str, limit: usize) -> crate::Result<Vec<Handle>> {
+        let scope = self.scope_filter();
         let pattern = format!("%{}%", heading.to_lowercase());
         let nt = NodeType::Section.as_int() as i32;
         let limit = limit as i64;
@@ -71,46 +185,105 @@ impl RepoIndex {
                 "SELECT {HANDLE_SELECT}
                  FROM nodes n JOIN files f ON n.file_id = f.id
                  WHERE n.node_type = ?
-                   AND LOWER(json_extract(n.metadata, '$.heading')) LIKE ?
+                   AND LOWER(json_extract(n.metadata, '$.heading')) LIKE ?{scope}
+                 ORDER BY {HANDLE_ORDER}
                  LIMIT ?"
             ),
             &[&nt as &dyn rusqlite::types::ToSql, &pattern, &limit],
         )
     }
 
+    /// Search sections by heading path suffix: "auth > configuration" (or
+    /// "auth/configuration") matches "Deployment > Services > Auth > Configuration".
+    /// Segments compare case-insensitively and must line up with whole headings.
+    pub fn search_section_path(&self, path: &str, limit: usize) -> crate::Result<Vec<Handle>> {
+        let scope = self.scope_filter();
+        let segments = split_heading_path(path);
+        if segments.is_empty() {
+            return Ok(Vec::new());
+        }
+        let suffix = segments.join(HEADING_PATH_SEPARATOR).to_lowercase();
+        let nested = format!("%{}{}", HEADING_PATH_SEPARATOR, escape_like(&suffix));
+        let nt = NodeType::Section.as_int() as i32;
+        let limit = limit as i64;
+        self.query_handles(
+            &format!(
+                "SELECT {HANDLE_SELECT}
+                 FROM nodes n JOIN files f ON n.file_id = f.id
+                 WHERE n.node_type = ?
+                   AND (LOWER(n.heading_path) = ? OR LOWER(n.heading_path) LIKE ? ESCAPE '\\'){scope}
+                 ORDER BY {HANDLE_ORDER}
+                 LIMIT ?"
+            ),
+            &[&nt as &dyn rusqlite::types::ToSql, &suffix, &nested, &limit],
+        )
+    }
+
     /// Search for code symbols by name (exact match with fuzzy fallback).
     pub fn search_code(&self, symbol: &str, limit: usize) -> crate::Result<Vec<Handle>> {
-        let handles = self.search_symbol_exact(symbol, limit)?;
+        Ok(self.search_code_via(symbol, limit)?.0)
+    }
+
+    /// [`search_code`](Self::search_code), and which lookup answered it.
+    pub(crate) fn search_code_via(
+        &self,
+        symbol: &str,
+        limit: usize,
+    ) -> crate::Result<(Vec<Handle>, SearchPath)> {
+        let (handles, via) = self.search_symbol_exact(symbol, limit)?;
         if handles.is_empty() {
-            return self.search_symbol_fuzzy(symbol, limit);
+            return Ok((
+                self.search_symbol_fuzzy(symbol, limit)?,
+                SearchPath::SymbolFuzzy,
+            ));
         }
-        Ok(handles)
+        Ok((handles, via))
     }
 
     /// Exact symbol lookup: cache first, then DB fallback.
-    fn search_symbol_exact(&self, symbol: &str, limit: usize) -> crate::Result<Vec<Handle>> {
+    fn search_symbol_exact(
+        &self,
+        symbol: &str,
+        limit: usize,
+    ) -> crate::Result<(Vec<Handle>, SearchPath)> {
+        let scope = self.scope_filter();
         let symbol_lower = symbol.to_lowercase();
 
-        // Fast path: check symbol cache first (O(1) lookup)
-        if let Some(entries) = self.symbol_cache.get(&symbol_lower) {
+        // Fast path: check symbol cache first (O(1) lookup). It holds no file
+        // times or generated flags, so a scoped search goes to the database
+        let cached = self.symbol_cache.get(&symbol_lower);
+        if scope.is_empty() && cached.is_none() && self.symbol_cache.is_complete() {
+            return Ok((Vec::new(), SearchPath::SymbolCache));
+        }
+        if let Some(entries) = cached.filter(|_| scope.is_empty()) {
+            // Cache entries keep load/insertion order; sort like the SQL path
+            let mut entries: Vec<&SymbolCacheEntry> = entries.iter().collect();
+            entries.sort_by(|a, b| {
+                (&a.file_path, a.start_byte, &a.handle_id).cmp(&(
+                    &b.file_path,
+                    b.start_byte,
+                    &b.handle_id,
+                ))
+            });
             let handles: Vec<Handle> = entries
-                .iter()
+                .into_iter()
                 .take(limit)
                 .map(handle_from_cache_entry)
                 .collect();
             if !handles.is_empty() {
-                return Ok(handles);
+                return Ok((handles, SearchPath::SymbolCache));
             }
         }
 
-        // Slow path: database query
+        // Slow path: database query, also for a miss in a partial cache
         let code_types = code_type_params();
         let limit_i64 = limit as i64;
-        self.query_handles(
+        let handles = self.query_handles(
             &format!(
                 "SELECT {HANDLE_SELECT}
                  FROM nodes n JOIN files f ON n.file_id = f.id
-                 WHERE n.name_lower = ? AND n.node_type IN (?, ?, ?, ?)
+                 WHERE n.name_lower = ? AND n.node_type IN (?, ?, ?, ?){scope}
+                 ORDER BY {HANDLE_ORDER}
                  LIMIT ?"
             ),
             &[
@@ -121,10 +294,12 @@ impl RepoIndex {
                 &code_types[3],
                 &limit_i64,
             ],
-        )
+        )?;
+        Ok((handles, SearchPath::SymbolDb))
     }
 
     fn search_symbol_fuzzy(&self, symbol: &str, limit: usize) -> crate::Result<Vec<Handle>> {
+        let scope = self.scope_filter();
         let escaped = escape_fts5_query(symbol);
         let code_types = code_type_params();
         let limit = limit as i64;
@@ -135,7 +310,8 @@ impl RepoIndex {
                  JOIN symbol_fts_map m ON fts.rowid = m.fts_rowid
                  JOIN nodes n ON m.node_id = n.id
                  JOIN files f ON n.file_id = f.id
-                 WHERE fts.name MATCH ? AND n.node_type IN (?, ?, ?, ?)
+                 WHERE symbol_fts MATCH ? AND n.node_type IN (?, ?, ?, ?){scope}
+                 ORDER BY fts.rank, {HANDLE_ORDER}
                  LIMIT ?"
             ),
             &[
@@ -149,52 +325,32 @@ impl RepoIndex {
         )
     }
 
-    /// Get file as a single handle
-    pub fn get_file(&self, path_pattern: &str) -> crate::Result<Vec<Handle>> {
-        let glob_matcher = globset::Glob::new(path_pattern)
-            .map_err(|e| CanopyError::GlobPattern(e.to_string()))?
-            .compile_matcher();
-
-        let mut stmt = self
-            .conn
-            .prepare("SELECT f.path, f.token_count FROM files f")?;
-
-        let all_rows: Vec<(String, usize)> = collect_row_results(stmt.query_map([], |row| {
-            let path: String = row.get(0)?;
-            let tokens: i64 = row.get(1)?;
-            Ok((path, tokens.max(0) as usize))
-        })?)?;
-        let matches: Vec<(String, usize)> = all_rows
-            .into_iter()
-            .filter(|(path, _)| glob_matcher.is_match(path))
-            .collect();
-
-        let mut handles = Vec::new();
-        for (file_path, token_count) in matches {
-            // Read file to get line count and preview
-            let full_path = self.repo_root.join(&file_path);
-            if let Ok(source) = std::fs::read_to_string(&full_path) {
-                let line_count = source.lines().count().max(1);
-                let span = 0..source.len();
-                let preview = generate_preview(&source, &span, self.config.indexing.preview_bytes);
-
-                handles.push(Handle {
-                    id: HandleId::new(&file_path, NodeType::Chunk, &span),
-                    file_path,
-                    node_type: NodeType::Chunk,
-                    span,
-                    line_range: (1, line_count),
-                    token_count,
-                    preview,
-                    content: None,
-                    source: HandleSource::Local,
-                    commit_sha: None,
-                    generation: None,
-                });
+    /// Stored whole-file token counts for `paths`, across shards; paths
+    /// that aren't indexed are left out.
+    pub fn file_token_counts(
+        &self,
+        paths: &BTreeSet<&str>,
+    ) -> crate::Result<BTreeMap<String, usize>> {
+        let mut counts = BTreeMap::new();
+        if paths.is_empty() {
+            return Ok(counts);
+        }
+        for index in self.all_indexes() {
+            let mut stmt = index
+                .conn
+                .prepare_cached("SELECT token_count FROM files WHERE path = ?")?;
+            for path in paths {
+                if counts.contains_key(*path) {
+                    continue;
+                }
+                let tokens: Option<i64> =
+                    stmt.query_row(params![path], |row| row.get(0)).optional()?;
+                if let Some(tokens) = tokens {
+                    counts.insert(path.to_string(), tokens.max(0) as usize);
+                }
             }
         }
-
-        Ok(handles)
+        Ok(counts)
     }
 
     /// Search within specific files (in-file query)
@@ -204,41 +360,94 @@ impl RepoIndex {
         fts_query: &str,
         limit: usize,
     ) -> crate::Result<Vec<Handle>> {
-        let glob_matcher = globset::Glob::new(glob)
-            .map_err(|e| CanopyError::GlobPattern(e.to_string()))?
-            .compile_matcher();
+        Ok(self.scan_fts_in_glob(glob, fts_query, limit)?.0)
+    }
+
+    /// FTS matches under `glob`, and how many rows SQLite returned to find them.
+    ///
+    /// The glob's literal path prefix is pushed into SQL (`dir_prefix` + `path LIKE`)
+    /// so a common term outside the glob never leaves the database. Globs without a
+    /// directory prefix (`**/*.rs`) fall back to filtering every FTS match in Rust,
+    /// up to [`fts_candidate_cap`](Self::fts_candidate_cap) rows.
+    pub(crate) fn scan_fts_in_glob(
+        &self,
+        glob: &str,
+        fts_query: &str,
+        limit: usize,
+    ) -> crate::Result<(Vec<Handle>, FtsScan)> {
+        let started = Instant::now();
+        let glob_matcher = self.path_style.glob(glob)?;
         let escaped = escape_fts5_query(fts_query);
+        let cap = self.fts_candidate_cap();
 
-        // Can't use query_handles here — need post-query glob + take(limit) filtering
-        let mut stmt = self.conn.prepare(&format!(
-            "SELECT {HANDLE_SELECT}
+        let mut sql = format!(
+            "SELECT {HANDLE_SELECT}, fts.rank
              FROM content_fts fts
              JOIN fts_node_map m ON fts.rowid = m.fts_rowid
              JOIN nodes n ON m.node_id = n.id
              JOIN files f ON n.file_id = f.id
-             WHERE fts.content MATCH ?"
-        ))?;
+             WHERE content_fts MATCH ?"
+        );
+        let mut sql_params: Vec<String> = vec![escaped];
+        if let Some(prefix) = glob_path_prefix(glob) {
+            let dir = dir_prefix(&prefix);
+            if dir.contains('/') {
+                // Both indexed segments are fixed: exact match on the indexed column
+                sql.push_str(" AND f.dir_prefix = ?");
+                sql_params.push(dir);
+            } else {
+                // Only the top-level directory is fixed: files directly in it,
+                // or anywhere below it
+                sql.push_str(" AND (f.dir_prefix = ? OR f.dir_prefix LIKE ? ESCAPE '\\')");
+                sql_params.push(dir.clone());
+                sql_params.push(format!("{}/%", escape_like(&dir)));
+            }
+            sql.push_str(" AND f.path LIKE ? ESCAPE '\\'");
+            sql_params.push(format!("{}%", escape_like(&prefix)));
+        }
 
-        let all_handles: Vec<Handle> =
-            collect_row_results(stmt.query_map(params![escaped], handle_from_row)?)?;
-        let handles: Vec<Handle> = all_handles
-            .into_iter()
-            .filter(|h| glob_matcher.is_match(&h.file_path))
-            .take(limit)
-            .collect();
+        sql.push_str(&self.scope_filter());
+        sql.push_str(&format!(" ORDER BY fts.rank, {HANDLE_ORDER}"));
 
-        Ok(handles)
+        // Can't use query_handles here — need post-query glob + take(limit) filtering
+        let (handles, rows_read, capped) = self.with_statement(&sql, |stmt| {
+            let mut rows = stmt.query_map(params_from_iter(&sql_params), |row| {
+                scored_handle_from_row(row, 11)
+            })?;
+            let mut handles = Vec::new();
+            let mut rows_read = 0;
+            let mut capped = false;
+            while handles.len() < limit {
+                if rows_read == cap {
+                    capped = rows.next().is_some();
+                    break;
+                }
+                let Some(handle) = rows.next() else { break };
+                rows_read += 1;
+                let handle = handle?;
+                if glob_matcher.is_match(&handle.file_path) {
+                    handles.push(handle);
+                }
+            }
+            Ok((handles, rows_read, capped))
+        })?;
+        let scan = FtsScan::finished(started, rows_read, capped);
+        self.record_scan(&scan);
+        Ok((handles, scan))
     }
 
     /// Search for children of a parent symbol
     pub fn search_children(&self, parent: &str, limit: usize) -> crate::Result<Vec<Handle>> {
+        let scope = self.scope_filter();
         let parent_lower = parent.to_lowercase();
         let limit = limit as i64;
         self.query_handles(
             &format!(
                 "SELECT {HANDLE_SELECT}
                  FROM nodes n JOIN files f ON n.file_id = f.id
-                 WHERE n.parent_name_lower = ? LIMIT ?"
+                 WHERE n.parent_name_lower = ?{scope}
+                 ORDER BY {HANDLE_ORDER}
+                 LIMIT ?"
             ),
             &[&parent_lower as &dyn rusqlite::types::ToSql, &limit],
         )
@@ -251,6 +460,7 @@ impl RepoIndex {
         symbol: &str,
         limit: usize,
     ) -> crate::Result<Vec<Handle>> {
+        let scope = self.scope_filter();
         let parent_lower = parent.to_lowercase();
         let symbol_lower = symbol.to_lowercase();
         let limit = limit as i64;
@@ -258,7 +468,8 @@ impl RepoIndex {
             &format!(
                 "SELECT {HANDLE_SELECT}
                  FROM nodes n JOIN files f ON n.file_id = f.id
-                 WHERE n.parent_name_lower = ? AND n.name_lower = ?
+                 WHERE n.parent_name_lower = ? AND n.name_lower = ?{scope}
+                 ORDER BY {HANDLE_ORDER}
                  LIMIT ?"
             ),
             &[
@@ -271,69 +482,107 @@ impl RepoIndex {
 
     /// Search for symbol definitions (exact match only, no fuzzy fallback).
     pub fn search_definitions(&self, symbol: &str, limit: usize) -> crate::Result<Vec<Handle>> {
+        Ok(self.search_definitions_via(symbol, limit)?.0)
+    }
+
+    /// [`search_definitions`](Self::search_definitions), and which lookup
+    /// answered it.
+    pub(crate) fn search_definitions_via(
+        &self,
+        symbol: &str,
+        limit: usize,
+    ) -> crate::Result<(Vec<Handle>, SearchPath)> {
         self.search_symbol_exact(symbol, limit)
     }
 
-    /// Search for source nodes containing references to a symbol
+    /// Search for source nodes containing references to a symbol, keeping only
+    /// `ref_types` (all when empty)
     pub fn search_reference_sources(
         &self,
         symbol: &str,
+        ref_types: &[RefType],
         limit: usize,
     ) -> crate::Result<Vec<Handle>> {
+        let scope = self.scope_filter();
         let symbol_lower = symbol.to_lowercase();
         let limit = limit as i64;
+        let type_names = ref_type_names(ref_types);
+        let mut params: Vec<&dyn rusqlite::types::ToSql> = vec![&symbol_lower];
+        params.extend(type_names.iter().map(|t| t as &dyn rusqlite::types::ToSql));
+        params.push(&limit);
         self.query_handles(
             &format!(
                 "SELECT DISTINCT {HANDLE_SELECT}
                  FROM refs r
                  JOIN nodes n ON r.source_node_id = n.id
                  JOIN files f ON n.file_id = f.id
-                 WHERE r.name_lower = ? LIMIT ?"
+                 WHERE r.name_lower = ?{}{scope}
+                 ORDER BY {HANDLE_ORDER}
+                 LIMIT ?",
+                ref_type_clause(type_names.len())
             ),
-            &[&symbol_lower as &dyn rusqlite::types::ToSql, &limit],
+            &params,
         )
     }
 
-    /// Search for references to a symbol (returns RefHandles)
-    pub fn search_references(&self, symbol: &str, limit: usize) -> crate::Result<Vec<RefHandle>> {
+    /// Search for references to a symbol (returns RefHandles), keeping only
+    /// `ref_types` (all when empty)
+    pub fn search_references(
+        &self,
+        symbol: &str,
+        ref_types: &[RefType],
+        limit: usize,
+    ) -> crate::Result<Vec<RefHandle>> {
+        let scope = self.scope_filter();
         let symbol_lower = symbol.to_lowercase();
+        let limit = limit as i64;
+        let type_names = ref_type_names(ref_types);
+        let mut params: Vec<&dyn rusqlite::types::ToSql> = vec![&symbol_lower];
+        params.extend(type_names.iter().map(|t| t as &dyn rusqlite::types::ToSql));
+        params.push(&limit);
 
-        let mut stmt = self.conn.prepare(
+        let sql = format!(
             "SELECT f.path, r.span_start, r.span_end, r.line_start, r.line_end,
                     r.name, r.qualifier, r.ref_type, n.handle_id, r.preview
              FROM refs r
              JOIN files f ON r.file_id = f.id
              LEFT JOIN nodes n ON r.source_node_id = n.id
-             WHERE r.name_lower = ?
+             WHERE r.name_lower = ?{}{scope}
+             ORDER BY f.path, r.span_start, r.span_end, r.name, r.ref_type
              LIMIT ?",
-        )?;
+            ref_type_clause(type_names.len())
+        );
 
-        let raw_rows =
-            collect_row_results(stmt.query_map(params![symbol_lower, limit as i64], |row| {
-                let file_path: String = row.get(0)?;
-                let span_start: i64 = row.get(1)?;
-                let span_end: i64 = row.get(2)?;
-                let line_start: i64 = row.get(3)?;
-                let line_end: i64 = row.get(4)?;
-                let name: String = row.get(5)?;
-                let qualifier: Option<String> = row.get(6)?;
-                let ref_type_str: String = row.get(7)?;
-                let source_handle_id: Option<String> = row.get(8)?;
-                let preview: Option<String> = row.get(9)?;
-
-                Ok((
-                    file_path,
-                    span_start.max(0) as usize,
-                    span_end.max(0) as usize,
-                    line_start.max(0) as usize,
-                    line_end.max(0) as usize,
-                    name,
-                    qualifier,
-                    ref_type_str,
-                    source_handle_id,
-                    preview.unwrap_or_else(|| "...".to_string()),
-                ))
-            })?)?;
+        let raw_rows = self.with_statement(&sql, |stmt| {
+            Ok(collect_row_results(stmt.query_map(
+                params.as_slice(),
+                |row| {
+                    let file_path: String = row.get(0)?;
+                    let span_start: i64 = row.get(1)?;
+                    let span_end: i64 = row.get(2)?;
+                    let line_start: i64 = row.get(3)?;
+                    let line_end: i64 = row.get(4)?;
+                    let name: String = row.get(5)?;
+                    let qualifier: Option<String> = row.get(6)?;
+                    let ref_type_str: String = row.get(7)?;
+                    let source_handle_id: Option<String> = row.get(8)?;
+                    let preview: Option<String> = row.get(9)?;
+
+                    Ok((
+                        file_path,
+                        span_start.max(0) as usize,
+                        span_end.max(0) as usize,
+                        line_start.max(0) as usize,
+                        line_end.max(0) as usize,
+                        name,
+                        qualifier,
+                        ref_type_str,
+                        source_handle_id,
+                        preview.unwrap_or_else(|| "...".to_string()),
+                    ))
+                },
+            )?)?)
+        })?;
         let refs: Vec<RefHandle> = raw_rows
             .into_iter()
             .map(
@@ -361,6 +610,8 @@ impl RepoIndex {
                         ref_type,
                         source_handle: source_handle_id.map(HandleId::from_raw),
                         preview,
+                        occurrences: Vec::new(),
+                        occurrence_count: 0,
                     }
                 },
             )
@@ -369,14 +620,46 @@ impl RepoIndex {
         Ok(refs)
     }
 
+    /// References to a symbol counted by ref type (`call`, `import`, `type_ref`),
+    /// unfiltered, so callers can tell what a `ref_types` filter left out.
+    pub fn ref_type_counts(&self, symbol: &str) -> crate::Result<BTreeMap<String, usize>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT r.ref_type, COUNT(*) FROM refs r JOIN files f ON r.file_id = f.id
+             WHERE r.name_lower = ?{} GROUP BY r.ref_type",
+            self.scope_filter()
+        ))?;
+        let rows = stmt.query_map(params![symbol.to_lowercase()], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?;
+        let mut counts = BTreeMap::new();
+        for row in rows {
+            let (ref_type, count) = row?;
+            counts.insert(ref_type, count.max(0) as usize);
+        }
+        Ok(counts)
+    }
+
     /// Get default result limit
     pub fn default_limit(&self) -> usize {
         self.config.core.default_result_limit
     }
 }
 
+/// Stored names of `ref_types`, for binding into [`ref_type_clause`].
+pub(super) fn ref_type_names(ref_types: &[RefType]) -> Vec<&'static str> {
+    ref_types.iter().map(|t| t.as_str()).collect()
+}
+
+/// `AND r.ref_type IN (?, ...)` for `count` bound names; empty when unfiltered.
+pub(super) fn ref_type_clause(count: usize) -> String {
+    if count == 0 {
+        return String::new();
+    }
+    format!(" AND r.ref_type IN ({})", vec!["?"; count].join(", "))
+}
+
 /// The four code-like node types used by symbol search queries.
-fn code_type_params() -> [i32; 4] {
+pub(super) fn code_type_params() -> [i32; 4] {
     [
         NodeType::Function.as_int() as i32,
         NodeType::Class.as_int() as i32,
@@ -397,16 +680,31 @@ fn handle_from_cache_entry(e: &SymbolCacheEntry) -> Handle {
         line_range: (e.line_start, e.line_end),
         token_count: e.token_count,
         preview: e.preview.clone(),
+        preview_tokens: e.preview_tokens,
         content: None,
+        content_hash: e.content_hash.as_ref().map(hex::encode),
         source: HandleSource::Local,
         commit_sha: None,
         generation: None,
+        possibly_stale: false,
+        rerank_score: None,
+        score: None,
+        match_line: None,
+        match_count_in_node: None,
+        via: None,
+        result_class: None,
     }
 }
 
-/// Construct a Handle from a standard 9-column DB row:
-/// (handle_id, path, node_type, start_byte, end_byte, line_start, line_end, token_count, preview)
-fn handle_from_row(row: &rusqlite::Row) -> rusqlite::Result<Handle> {
+/// [`Handle::content_hash`] of a node's stored SHA-256: its first 8 bytes.
+pub(super) fn short_content_hash(hash: &[u8]) -> String {
+    hex::encode(&hash[..hash.len().min(SHORT_HASH_BYTES)])
+}
+
+/// Construct a Handle from a standard 11-column DB row:
+/// (handle_id, path, node_type, start_byte, end_byte, line_start, line_end, token_count, preview,
+/// preview_tokens, content_hash)
+pub(super) fn handle_from_row(row: &rusqlite::Row) -> rusqlite::Result<Handle> {
     let handle_id: String = row.get(0)?;
     let file_path: String = row.get(1)?;
     let node_type_int: i32 = row.get(2)?;
@@ -416,6 +714,8 @@ fn handle_from_row(row: &rusqlite::Row) -> rusqlite::Result<Handle> {
     let line_end: i64 = row.get(6)?;
     let token_count: i64 = row.get(7)?;
     let preview: Option<String> = row.get(8)?;
+    let preview_tokens: i64 = row.get(9)?;
+    let content_hash: Option<Vec<u8>> = row.get(10)?;
 
     let node_type = NodeType::from_int(node_type_int as u8).unwrap_or(NodeType::Chunk);
     let span = (start_byte.max(0) as usize)..(end_byte.max(0) as usize);
@@ -428,13 +728,30 @@ fn handle_from_row(row: &rusqlite::Row) -> rusqlite::Result<Handle> {
         line_range: (line_start.max(0) as usize, line_end.max(0) as usize),
         token_count: token_count.max(0) as usize,
         preview: preview.unwrap_or_else(|| "...".to_string()),
+        preview_tokens: preview_tokens.max(0) as usize,
         content: None,
+        content_hash: content_hash.as_deref().map(short_content_hash),
         source: HandleSource::Local,
         commit_sha: None,
         generation: None,
+        possibly_stale: false,
+        rerank_score: None,
+        score: None,
+        match_line: None,
+        match_count_in_node: None,
+        via: None,
+        result_class: None,
     })
 }
 
+/// [`handle_from_row`] for a full-text row carrying the FTS5 `rank` (bm25,
+/// lower is better) in column `rank_column`, kept as [`Handle::score`].
+fn scored_handle_from_row(row: &rusqlite::Row, rank_column: usize) -> rusqlite::Result<Handle> {
+    let mut handle = handle_from_row(row)?;
+    handle.score = Some(-row.get::<_, f64>(rank_column)?);
+    Ok(handle)
+}
+
 /// Collect row results, propagating the first row-level error.
 pub(super) fn collect_row_results<T>(
     rows: impl Iterator<Item = rusqlite::Result<T>>,
@@ -442,11 +759,55 @@ pub(super) fn collect_row_results<T>(
     rows.collect()
 }
 
+/// The first two directory segments of a relative file path (`src/auth/login.rs` ->
+/// `src/auth`, `src/lib.rs` -> `src`, `README.md` -> ``), stored as `files.dir_prefix`.
+pub(super) fn dir_prefix(path: &str) -> String {
+    let dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+    let mut segments = dir.splitn(3, '/');
+    match (segments.next(), segments.next()) {
+        (Some(first), Some(second)) => format!("{first}/{second}"),
+        (Some(first), None) => first.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// The literal path of `glob` up to the last `/` before its first wildcard
+/// (`src/auth/**/*.rs` -> `src/auth/`), or `None` if it has no fixed directory.
+fn glob_path_prefix(glob: &str) -> Option<String> {
+    let literal_end = glob.find(['*', '?', '[', '{', '\\']).unwrap_or(glob.len());
+    let dir_end = glob[..literal_end].rfind('/')?;
+    let prefix = &glob[..=dir_end];
+    (!prefix.starts_with('/') && !prefix.split('/').any(|s| s == "." || s == ".."))
+        .then(|| prefix.to_string())
+}
+
+/// Headings of a path query, split on `>` (or on `/` when there is no `>`,
+/// so headings like "CI/CD" still work in the `>` form).
+pub(super) fn split_heading_path(path: &str) -> Vec<&str> {
+    let separator = if path.contains('>') { '>' } else { '/' };
+    path.split(separator)
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+/// Escape `%`, `_` and `\` for a `LIKE ... ESCAPE '\'` pattern.
+pub(super) fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
 /// Escape FTS5 special characters
-pub(super) fn escape_fts5_query(query: &str) -> String {
+pub(crate) fn escape_fts5_query(query: &str) -> String {
     // For simple queries, wrap in quotes if it contains special chars
-    // FTS5 special chars: " ( ) - * < >
-    if query.contains(['"', '(', ')', '-', '*', '<', '>']) {
+    // FTS5 special chars: " ( ) - * < > and the bareword breakers . : /
+    if query.contains(['"', '(', ')', '-', '*', '<', '>', '.', ':', '/']) {
         // Quote the entire query for literal search
         format!("\"{}\"", query.replace('"', "\"\""))
     } else {
@@ -459,6 +820,129 @@ mod tests {
     use super::*;
     use crate::document::NodeType;
     use crate::handle::{HandleId, HandleSource};
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+    use std::fs;
+
+    fn identifier_repo() -> (FixtureRepo, RepoIndex) {
+        let repo = FixtureRepoBuilder::new()
+            .file(
+                "src/lib.rs",
+                "fn snake_case_name() {}\nfn caseName() {}\nfn unrelated() { let case = 1; }\n",
+            )
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        (repo, index)
+    }
+
+    #[test]
+    fn heading_path_splits_on_either_separator() {
+        assert_eq!(
+            split_heading_path("Auth / Configuration"),
+            ["Auth", "Configuration"]
+        );
+        assert_eq!(split_heading_path("CI/CD > Secrets"), ["CI/CD", "Secrets"]);
+        assert!(split_heading_path(" / / ").is_empty());
+    }
+
+    #[test]
+    fn section_path_matches_whole_heading_suffixes() {
+        let repo = FixtureRepoBuilder::new()
+            .file(
+                "DEPLOY.md",
+                "# Deployment\n## Services\n### Auth\n#### Configuration\nauth settings\n\
+                 ### Billing\n#### Configuration\nbilling settings\n\
+                 # Local\n## Configuration\nlocal settings\n### OAuth\n#### Configuration\noauth\n",
+            )
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*.md").unwrap();
+
+        assert_eq!(index.search_sections("configuration", 10).unwrap().len(), 4);
+
+        let auth = index
+            .search_section_path("auth > configuration", 10)
+            .unwrap();
+        assert_eq!(auth.len(), 1, "OAuth must not match Auth");
+        assert!(auth[0]
+            .preview
+            .starts_with("[Deployment > Services > Auth > Configuration]"));
+        assert!(auth[0].preview.contains("auth settings"));
+
+        let billing = index
+            .search_section_path("Billing/Configuration", 10)
+            .unwrap();
+        assert_eq!(billing.len(), 1);
+        assert!(billing[0].preview.contains("billing settings"));
+
+        // A full path matches exactly; a top-level heading matches itself
+        let local = index
+            .search_section_path("local > configuration", 10)
+            .unwrap();
+        assert_eq!(local.len(), 1);
+        assert!(local[0].preview.contains("local settings"));
+        assert_eq!(
+            index.search_section_path("deployment", 10).unwrap().len(),
+            1
+        );
+        assert!(index
+            .search_section_path("services > configuration", 10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn split_handbook_sections_stay_within_budget() {
+        let repo = FixtureRepoBuilder::new()
+            .file("HANDBOOK.md", include_str!("../parse/fixtures/handbook.md"))
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        repo.write(
+            ".canopy/config.toml",
+            "[indexing]\nmax_section_tokens = 150\n",
+        );
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*.md").unwrap();
+
+        let sections = index.get_nodes_by_type(NodeType::Section, 100).unwrap();
+        assert!(sections.iter().all(|h| h.token_count <= 150));
+
+        // The heading path resolves to the section, then its parts in order
+        let accounts = index
+            .search_section_path("onboarding > accounts", 10)
+            .unwrap();
+        assert!(accounts.len() > 2);
+        assert!(accounts[0].preview.contains("Accounts"));
+        assert!(accounts[1..]
+            .iter()
+            .enumerate()
+            .all(|(i, h)| h.preview.starts_with(&format!(
+                "[Handbook > Onboarding > Accounts (part {}/",
+                i + 1
+            ))));
+        let children: i64 = index
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM nodes WHERE parent_handle_id = ?",
+                [accounts[0].id.raw()],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(children as usize, accounts.len() - 1);
+        assert_eq!(
+            index.search_sections("accounts", 10).unwrap().len(),
+            accounts.len()
+        );
+        assert_eq!(
+            index
+                .search_section_path("handbook > engineering > code review > checklist", 10)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
 
     #[test]
     fn escape_fts5_plain_query_unchanged() {
@@ -477,6 +961,12 @@ mod tests {
         assert_eq!(escape_fts5_query("self-signed"), "\"self-signed\"");
         // Angle brackets
         assert_eq!(escape_fts5_query("Vec<T>"), "\"Vec<T>\"");
+        // Dotted key paths and qualified names
+        assert_eq!(
+            escape_fts5_query("server.timeouts.read"),
+            "\"server.timeouts.read\""
+        );
+        assert_eq!(escape_fts5_query("std::fs"), "\"std::fs\"");
         // Double quotes are escaped by doubling
         assert_eq!(escape_fts5_query("say \"hello\""), "\"say \"\"hello\"\"\"");
     }
@@ -494,6 +984,7 @@ mod tests {
     #[test]
     fn handle_from_cache_entry_roundtrip() {
         let entry = SymbolCacheEntry {
+            name: "test".to_string(),
             handle_id: "h_test".to_string(),
             file_path: "src/lib.rs".to_string(),
             node_type: NodeType::Function.as_int() as i32,
@@ -503,6 +994,8 @@ mod tests {
             line_end: 15,
             token_count: 42,
             preview: "fn test()".to_string(),
+            preview_tokens: 3,
+            content_hash: Some([7; 8]),
         };
 
         let handle = handle_from_cache_entry(&entry);
@@ -514,6 +1007,7 @@ mod tests {
         assert_eq!(handle.line_range, (1, 15));
         assert_eq!(handle.token_count, 42);
         assert_eq!(handle.preview, "fn test()");
+        assert_eq!(handle.preview_tokens, 3);
         assert!(handle.content.is_none());
         assert_eq!(handle.source, HandleSource::Local);
         assert!(handle.commit_sha.is_none());
@@ -544,4 +1038,239 @@ mod tests {
         let result = collect_row_results(items.into_iter());
         assert!(result.unwrap().is_empty());
     }
+
+    #[test]
+    fn fts_keeps_underscore_identifiers_whole() {
+        let (_dir, index) = identifier_repo();
+
+        let handles = index.fts_search("snake_case_name", 10).unwrap();
+        assert!(!handles.is_empty());
+        assert!(handles
+            .iter()
+            .all(|h| h.preview.contains("snake_case_name")));
+
+        // Sub-tokens are still reachable through identifier_parts
+        let handles = index.fts_search("snake", 10).unwrap();
+        assert!(handles
+            .iter()
+            .any(|h| h.preview.contains("snake_case_name")));
+    }
+
+    #[test]
+    fn fts_ranks_repeated_phrases_above_incidental_mentions() {
+        // a_server.rs sorts first by path, so position order would put it on top
+        let repo = FixtureRepoBuilder::new()
+            .file(
+                "src/a_server.rs",
+                "fn serve() {\n    // Starts the listener, parses flags, loads the TLS \
+                 certificates and the connection pool settings, then blocks forever\n    \
+                 listen();\n}\n",
+            )
+            .file(
+                "src/z_pool.rs",
+                "fn checkout() {\n    // The connection pool hands out a connection; \
+                 an empty connection pool waits for a connection\n    take();\n}\n",
+            )
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        for handles in [
+            index.fts_search("connection pool", 10).unwrap(),
+            index
+                .scan_fts_in_glob("src/*.rs", "connection pool", 10)
+                .unwrap()
+                .0,
+        ] {
+            let ranked: Vec<(&str, Option<f64>)> = handles
+                .iter()
+                .map(|h| (h.file_path.as_str(), h.score))
+                .collect();
+            assert_eq!(ranked.len(), 2, "{ranked:?}");
+            assert_eq!(ranked[0].0, "src/z_pool.rs");
+            assert_eq!(ranked[1].0, "src/a_server.rs");
+            let (best, incidental) = (ranked[0].1.unwrap(), ranked[1].1.unwrap());
+            assert!(best > incidental && incidental > 0.0, "{ranked:?}");
+        }
+        // Symbol lookups carry no full-text score
+        let defs = index.search_code("checkout", 10).unwrap();
+        assert!(defs.iter().all(|h| h.score.is_none()));
+    }
+
+    #[test]
+    fn capped_scans_keep_the_best_ranked_candidates() {
+        // Thirty incidental mentions index ahead of the one dense match
+        let repo = (0..30)
+            .fold(FixtureRepoBuilder::new(), |builder, i| {
+                builder.file(
+                    format!("src/a_{i:02}.rs"),
+                    format!(
+                        "fn step_{i}() {{\n    // Reads the config, opens the socket, \
+                         checks the cache and finally appends to the ledger\n}}\n"
+                    ),
+                )
+            })
+            .file(
+                "src/z_ledger.rs",
+                "fn settle() {\n    // ledger ledger: post the ledger entry, then balance the ledger\n}\n",
+            )
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        repo.write(".canopy/config.toml", "[fts]\nmax_fts_candidates = 10\n");
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let (handles, scan) = index.fts_scan("ledger", 1).unwrap();
+        assert!(scan.capped);
+        assert_eq!(handles[0].file_path, "src/z_ledger.rs");
+    }
+
+    #[test]
+    fn scans_finding_exactly_the_cap_are_not_capped() {
+        let repo = FixtureRepoBuilder::new()
+            .file("src/a.rs", "fn a() {\n    // posts to the ledger\n}\n")
+            .file("src/b.rs", "fn b() {\n    // balances the ledger\n}\n")
+            .file("src/c.rs", "fn c() {\n    // closes the ledger\n}\n")
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        let matches = index.fts_match_count("ledger", usize::MAX).unwrap();
+        assert!(matches > 1);
+
+        let scan_with_cap = |cap: usize| {
+            repo.write(
+                ".canopy/config.toml",
+                &format!("[fts]\nmax_fts_candidates = {cap}\n"),
+            );
+            let index = RepoIndex::open(repo.path()).unwrap();
+            index.fts_scan("ledger", 100).unwrap()
+        };
+        let (handles, scan) = scan_with_cap(matches);
+        assert!(!scan.capped);
+        assert_eq!(scan.rows_examined, matches);
+        assert_eq!(handles.len(), matches);
+
+        let (handles, scan) = scan_with_cap(matches - 1);
+        assert!(scan.capped);
+        assert_eq!(scan.rows_examined, matches - 1);
+        assert_eq!(handles.len(), matches - 1);
+    }
+
+    #[test]
+    fn fts_finds_camel_case_by_sub_tokens() {
+        let (_dir, index) = identifier_repo();
+
+        let handles = index.fts_search("case name", 10).unwrap();
+        assert!(handles.iter().any(|h| h.preview.contains("caseName")));
+        assert!(!handles.iter().any(|h| h.preview.contains("unrelated")));
+
+        let symbols = index.search_code("name", 10).unwrap();
+        assert!(symbols.iter().any(|h| h.preview.contains("caseName")));
+    }
+
+    #[test]
+    fn fts_match_count_stops_at_cap() {
+        let (_dir, index) = identifier_repo();
+        assert_eq!(index.fts_match_count("fn", 1).unwrap(), 1);
+        assert!(index.fts_match_count("fn", 100).unwrap() >= 3);
+        assert_eq!(index.fts_match_count("missing_token", 100).unwrap(), 0);
+    }
+
+    #[test]
+    fn dir_prefix_keeps_two_directory_segments() {
+        assert_eq!(dir_prefix("src/auth/login/mod.rs"), "src/auth");
+        assert_eq!(dir_prefix("src/auth/login.rs"), "src/auth");
+        assert_eq!(dir_prefix("src/lib.rs"), "src");
+        assert_eq!(dir_prefix("README.md"), "");
+    }
+
+    #[test]
+    fn glob_path_prefix_stops_at_first_wildcard() {
+        assert_eq!(
+            glob_path_prefix("src/auth/**").as_deref(),
+            Some("src/auth/")
+        );
+        assert_eq!(
+            glob_path_prefix("src/auth/**/*.rs").as_deref(),
+            Some("src/auth/")
+        );
+        assert_eq!(glob_path_prefix("src/lib*.rs").as_deref(), Some("src/"));
+        assert_eq!(glob_path_prefix("docs/guide.md").as_deref(), Some("docs/"));
+        assert_eq!(glob_path_prefix("**/*.rs"), None);
+        assert_eq!(glob_path_prefix("*.md"), None);
+        assert_eq!(glob_path_prefix("{src,lib}/**"), None);
+        assert_eq!(glob_path_prefix("../src/**"), None);
+    }
+
+    #[test]
+    fn escape_like_escapes_wildcards() {
+        assert_eq!(escape_like("my_dir/100%"), "my\\_dir/100\\%");
+    }
+
+    #[test]
+    fn search_in_files_prefilters_by_glob_prefix() {
+        let dir = tempfile::TempDir::new().unwrap();
+        // 10k matching nodes outside the glob...
+        for d in 0..10 {
+            let vendor = dir.path().join(format!("vendor/pkg_{d}"));
+            fs::create_dir_all(&vendor).unwrap();
+            for f in 0..10 {
+                let source: String = (0..100)
+                    .map(|i| format!("fn v_{f}_{i}() {{ refresh_token(); }}\n"))
+                    .collect();
+                fs::write(vendor.join(format!("file_{f}.rs")), source).unwrap();
+            }
+        }
+        // ...and 16 inside it, one directory below the literal prefix
+        fs::create_dir_all(dir.path().join("src/auth/session")).unwrap();
+        let auth: String = (0..16)
+            .map(|i| format!("fn auth_{i}() {{ refresh_token(); }}\n"))
+            .collect();
+        fs::write(dir.path().join("src/auth/session/login.rs"), auth).unwrap();
+        fs::write(
+            dir.path().join("src/authz.rs"),
+            "fn authz() { refresh_token(); }\n",
+        )
+        .unwrap();
+
+        RepoIndex::init(dir.path()).unwrap();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        assert!(index.fts_match_count("refresh_token", 20_000).unwrap() > 10_000);
+
+        let (handles, scan) = index
+            .scan_fts_in_glob("src/auth/**", "refresh_token", 100)
+            .unwrap();
+        assert_eq!(handles.len(), 16);
+        assert!(handles
+            .iter()
+            .all(|h| h.file_path == "src/auth/session/login.rs"));
+        assert_eq!(
+            scan.rows_examined, 16,
+            "only in-glob rows should leave SQLite"
+        );
+
+        // A one-segment prefix still excludes sibling directories and files
+        let (handles, scan) = index
+            .scan_fts_in_glob("src/**/login.rs", "refresh_token", 100)
+            .unwrap();
+        assert_eq!(handles.len(), 16);
+        assert!(scan.rows_examined <= 17);
+
+        // Globs without a directory prefix fall back to post-filtering
+        let (handles, scan) = index
+            .scan_fts_in_glob("**/auth/**", "refresh_token", 100)
+            .unwrap();
+        assert_eq!(handles.len(), 16);
+        assert!(scan.rows_examined >= 16);
+        assert_eq!(
+            index
+                .search_in_files("src/auth/**", "refresh_token", 5)
+                .unwrap()
+                .len(),
+            5
+        );
+    }
 }
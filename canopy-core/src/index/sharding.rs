@@ -0,0 +1,611 @@
+//! Per-directory index sharding.
+//!
+//! With `[indexing] shard_by = ["services/*", "libs/*"]`, every directory matching
+//! one of the patterns gets its own database at `.canopy/shards/<name>.db`, and
+//! everything else stays in the catch-all `.canopy/index.db`. The catch-all
+//! `RepoIndex` doubles as a thin router: queries fan out to the shards a glob can
+//! reach, index/invalidate touch only the shards owning the affected paths, and
+//! expand resolves each handle in the database that stores it. Every shard keeps
+//! its own symbol cache and schema check.
+
+use super::{IndexStats, RepoIndex};
+use crate::config::Config;
+use crate::error::CanopyError;
+use globset::{Glob, GlobMatcher};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory under `.canopy/` holding shard databases.
+pub(super) const SHARDS_DIR: &str = "shards";
+
+/// One shard: a repo-relative directory and the database indexing it.
+pub(crate) struct IndexShard {
+    /// Directory owned by this shard (e.g. `services/auth`), without trailing slash
+    pub(crate) prefix: String,
+    pub(crate) index: RepoIndex,
+}
+
+/// Routes repo-relative paths to shard databases.
+#[derive(Default)]
+pub(crate) struct ShardRouter {
+    /// (component depth, matcher) per `shard_by` pattern
+    patterns: Vec<(usize, GlobMatcher)>,
+    pub(crate) shards: Vec<IndexShard>,
+}
+
+/// Outcome of `canopy shard`: files whose database changes under the current `shard_by`.
+#[derive(Debug, Default, Serialize)]
+pub struct ReshardStats {
+    pub files_moved: usize,
+    /// Shard directories after the migration (or planned, when not applied)
+    pub shards: Vec<String>,
+    pub applied: bool,
+}
+
+impl ShardRouter {
+    /// Compile `shard_by` patterns and open existing shard databases.
+    ///
+    /// Shards on disk are only opened while `shard_by` is configured, unless
+    /// `include_unconfigured` is set (used when migrating them back).
+    pub(crate) fn open(
+        repo_root: &Path,
+        config: &Config,
+        include_unconfigured: bool,
+    ) -> crate::Result<Self> {
+        Self::open_in(
+            repo_root,
+            &shards_dir(repo_root),
+            config,
+            include_unconfigured,
+        )
+    }
+
+    /// [`open`](Self::open) with the shard databases read from `dir`.
+    pub(super) fn open_in(
+        repo_root: &Path,
+        dir: &Path,
+        config: &Config,
+        include_unconfigured: bool,
+    ) -> crate::Result<Self> {
+        let mut router = Self::default();
+        for pattern in &config.indexing.shard_by {
+            let pattern = pattern.trim_matches('/');
+            let matcher = Glob::new(pattern)
+                .map_err(|e| CanopyError::GlobPattern(e.to_string()))?
+                .compile_matcher();
+            router.patterns.push((pattern.split('/').count(), matcher));
+        }
+
+        if router.patterns.is_empty() && !include_unconfigured {
+            return Ok(router);
+        }
+        router.open_existing(repo_root, dir, config)?;
+        Ok(router)
+    }
+
+    /// Open every shard database in `dir` not already open.
+    fn open_existing(
+        &mut self,
+        repo_root: &Path,
+        dir: &Path,
+        config: &Config,
+    ) -> crate::Result<()> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Ok(());
+        };
+
+        let mut found: Vec<(String, PathBuf)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                shard_prefix_from_file_name(&name).map(|prefix| (prefix, entry.path()))
+            })
+            .collect();
+        found.sort();
+
+        for (prefix, db_path) in found {
+            if self.get(&prefix).is_none() {
+                let index = RepoIndex::open_db(repo_root, db_path, config.clone())?;
+                self.shards.push(IndexShard { prefix, index });
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn has_patterns(&self) -> bool {
+        !self.patterns.is_empty()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub(super) fn get(&self, prefix: &str) -> Option<&IndexShard> {
+        self.shards.iter().find(|s| s.prefix == prefix)
+    }
+
+    fn get_mut(&mut self, prefix: &str) -> Option<&mut IndexShard> {
+        self.shards.iter_mut().find(|s| s.prefix == prefix)
+    }
+
+    /// Shard for `prefix`, creating its database on first use.
+    pub(super) fn get_or_create(
+        &mut self,
+        repo_root: &Path,
+        config: &Config,
+        prefix: &str,
+    ) -> crate::Result<&mut RepoIndex> {
+        if let Some(pos) = self.shards.iter().position(|s| s.prefix == prefix) {
+            return Ok(&mut self.shards[pos].index);
+        }
+
+        let dir = shards_dir(repo_root);
+        fs::create_dir_all(&dir)?;
+        let db_path = dir.join(shard_file_name(prefix));
+        let index = RepoIndex::open_db(repo_root, db_path, config.clone())?;
+        self.shards.push(IndexShard {
+            prefix: prefix.to_string(),
+            index,
+        });
+        Ok(&mut self.shards.last_mut().expect("just pushed").index)
+    }
+
+    /// Shard directory owning `relative_path`, if a pattern matches one of its ancestors.
+    pub(crate) fn route(&self, relative_path: &str) -> Option<String> {
+        let components: Vec<&str> = relative_path.split('/').collect();
+        self.patterns.iter().find_map(|(depth, matcher)| {
+            if components.len() <= *depth {
+                return None;
+            }
+            let prefix = components[..*depth].join("/");
+            matcher.is_match(&prefix).then_some(prefix)
+        })
+    }
+
+    /// Whether anything matching `glob` can live in the catch-all database.
+    pub(crate) fn catch_all_reachable(&self, glob: Option<&str>) -> bool {
+        let Some(glob) = glob else {
+            return true;
+        };
+        let literal = literal_prefix(glob);
+        !self
+            .shards
+            .iter()
+            .any(|s| literal.starts_with(&format!("{}/", s.prefix)))
+    }
+
+    /// Shards that may hold files matching `glob` (all shards when `None`).
+    pub(crate) fn reachable<'a>(
+        &'a self,
+        glob: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a IndexShard> + 'a {
+        self.shards
+            .iter()
+            .filter(move |s| glob.is_none_or(|g| glob_reaches(g, &s.prefix)))
+    }
+
+    pub(crate) fn reachable_mut<'a>(
+        &'a mut self,
+        glob: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a mut IndexShard> + 'a {
+        self.shards
+            .iter_mut()
+            .filter(move |s| glob.is_none_or(|g| glob_reaches(g, &s.prefix)))
+    }
+
+    /// Close and delete shards no pattern routes to any more, provided they are empty.
+    fn prune_unconfigured(&mut self) -> crate::Result<()> {
+        let mut kept = Vec::with_capacity(self.shards.len());
+        for shard in std::mem::take(&mut self.shards) {
+            let routed =
+                self.route(&format!("{}/_", shard.prefix)).as_deref() == Some(&shard.prefix);
+            if routed || !shard.index.indexed_paths()?.is_empty() {
+                kept.push(shard);
+                continue;
+            }
+            let db_path = shard.index.db_path.clone();
+            drop(shard);
+            for suffix in ["", "-wal", "-shm"] {
+                let mut path = db_path.clone().into_os_string();
+                path.push(suffix);
+                let _ = fs::remove_file(PathBuf::from(path));
+            }
+        }
+        self.shards = kept;
+        Ok(())
+    }
+}
+
+impl RepoIndex {
+    /// This database followed by every open shard.
+    pub(crate) fn all_indexes(&self) -> impl Iterator<Item = &RepoIndex> {
+        std::iter::once(self).chain(self.shards.shards.iter().map(|s| &s.index))
+    }
+
+    /// Databases a query restricted to `glob` must consult, catch-all first.
+    pub(crate) fn query_targets<'a>(&'a self, glob: Option<&'a str>) -> Vec<&'a RepoIndex> {
+        let mut targets = Vec::new();
+        if self.shards.catch_all_reachable(glob) {
+            targets.push(self);
+        }
+        targets.extend(self.shards.reachable(glob).map(|s| &s.index));
+        targets
+    }
+
+    /// Index candidates, sending each file to the shard that owns its directory.
+    pub(super) fn index_routed(
+        &mut self,
+        candidates: Vec<(PathBuf, String)>,
+    ) -> crate::Result<IndexStats> {
+        let mut unrouted = Vec::new();
+        let mut routed: BTreeMap<String, Vec<(PathBuf, String)>> = BTreeMap::new();
+        for candidate in candidates {
+            match self.shards.route(&candidate.1) {
+                Some(prefix) => routed.entry(prefix).or_default().push(candidate),
+                None => unrouted.push(candidate),
+            }
+        }
+
+        let mut stats = self.index_candidates(&unrouted)?;
+        for (prefix, files) in routed {
+            let shard = self
+                .shards
+                .get_or_create(&self.repo_root, &self.config, &prefix)?;
+            let shard_stats = shard.index_candidates(&files)?;
+            stats.files_indexed += shard_stats.files_indexed;
+            stats.files_degraded += shard_stats.files_degraded;
+            stats.files_generated += shard_stats.files_generated;
+            stats.generated_tokens += shard_stats.generated_tokens;
+            stats.files_skipped += shard_stats.files_skipped;
+            stats.skipped.add(shard_stats.skipped);
+            stats.total_tokens += shard_stats.total_tokens;
+            stats.fts_optimized |= shard_stats.fts_optimized;
+            stats.fts_bytes_written += shard_stats.fts_bytes_written;
+            stats.fts_bytes_skipped += shard_stats.fts_bytes_skipped;
+        }
+
+        stats.index_size_bytes = self
+            .all_indexes()
+            .map(|index| fs::metadata(&index.db_path).map(|m| m.len()).unwrap_or(0))
+            .sum();
+        Ok(stats)
+    }
+
+    /// Move indexed files into the databases the current `shard_by` assigns them.
+    ///
+    /// Splits a monolithic `index.db` into shards, and folds shards that no longer
+    /// match a pattern back into the catch-all. Without `apply`, only reports the plan.
+    pub fn reshard(&mut self, apply: bool) -> crate::Result<ReshardStats> {
+        // Shards left behind by an earlier shard_by are still migration sources
+        self.shards
+            .open_existing(&self.repo_root, &shards_dir(&self.repo_root), &self.config)?;
+
+        // (source shard, path); None is the catch-all
+        let mut moves: Vec<(Option<String>, String)> = Vec::new();
+        for path in self.indexed_paths()? {
+            if self.shards.route(&path).is_some() {
+                moves.push((None, path));
+            }
+        }
+        for shard in &self.shards.shards {
+            for path in shard.index.indexed_paths()? {
+                if self.shards.route(&path).as_deref() != Some(shard.prefix.as_str()) {
+                    moves.push((Some(shard.prefix.clone()), path));
+                }
+            }
+        }
+
+        let mut stats = ReshardStats {
+            files_moved: moves.len(),
+            shards: Vec::new(),
+            applied: apply,
+        };
+
+        if apply {
+            let mut by_source: BTreeMap<Option<String>, Vec<String>> = BTreeMap::new();
+            for (source, path) in &moves {
+                by_source
+                    .entry(source.clone())
+                    .or_default()
+                    .push(path.clone());
+            }
+            // Resolve on-disk paths before the rows (and their raw bytes) are gone
+            let mut candidates: Vec<(PathBuf, String)> = Vec::with_capacity(moves.len());
+            for (source, path) in &moves {
+                let index = match source {
+                    None => Some(&*self),
+                    Some(prefix) => self.shards.get(prefix).map(|shard| &shard.index),
+                };
+                if let Some(index) = index {
+                    candidates.push((index.indexed_disk_path(path)?, path.clone()));
+                }
+            }
+            for (source, paths) in by_source {
+                match source {
+                    None => self.remove_paths(&paths)?,
+                    Some(prefix) => match self.shards.get_mut(&prefix) {
+                        Some(shard) => shard.index.remove_paths(&paths)?,
+                        None => 0,
+                    },
+                };
+            }
+
+            // Files deleted since they were indexed are simply dropped
+            candidates.retain(|(abs, _)| abs.is_file());
+            self.index_routed(candidates)?;
+            self.shards.prune_unconfigured()?;
+
+            stats.shards = self
+                .shards
+                .shards
+                .iter()
+                .map(|s| s.prefix.clone())
+                .collect();
+        } else {
+            let mut planned: Vec<String> = self
+                .shards
+                .shards
+                .iter()
+                .map(|s| s.prefix.clone())
+                .filter(|p| self.shards.route(&format!("{p}/_")).as_deref() == Some(p.as_str()))
+                .collect();
+            for (_, path) in &moves {
+                if let Some(prefix) = self.shards.route(path) {
+                    if !planned.contains(&prefix) {
+                        planned.push(prefix);
+                    }
+                }
+            }
+            planned.sort();
+            stats.shards = planned;
+        }
+
+        Ok(stats)
+    }
+}
+
+/// Interleave per-shard result lists so no single shard crowds out the others.
+pub(crate) fn interleave<T>(lists: Vec<Vec<T>>) -> Vec<T> {
+    if lists.len() == 1 {
+        return lists.into_iter().next().unwrap_or_default();
+    }
+    let total = lists.iter().map(Vec::len).sum();
+    let mut iters: Vec<_> = lists.into_iter().map(Vec::into_iter).collect();
+    let mut merged = Vec::with_capacity(total);
+    while merged.len() < total {
+        for iter in &mut iters {
+            if let Some(item) = iter.next() {
+                merged.push(item);
+            }
+        }
+    }
+    merged
+}
+
+fn shards_dir(repo_root: &Path) -> PathBuf {
+    repo_root.join(".canopy").join(SHARDS_DIR)
+}
+
+/// `services/auth` → `services%2Fauth.db` (reversible, flat file name).
+pub(super) fn shard_file_name(prefix: &str) -> String {
+    format!("{}.db", prefix.replace('%', "%25").replace('/', "%2F"))
+}
+
+fn shard_prefix_from_file_name(name: &str) -> Option<String> {
+    let stem = name.strip_suffix(".db")?;
+    if stem.is_empty() {
+        return None;
+    }
+    Some(stem.replace("%2F", "/").replace("%25", "%"))
+}
+
+/// Leading part of a glob before its first wildcard.
+fn literal_prefix(glob: &str) -> &str {
+    let glob = glob.strip_prefix("./").unwrap_or(glob);
+    let end = glob.find(['*', '?', '[', '{']).unwrap_or(glob.len());
+    &glob[..end]
+}
+
+/// Whether files under `prefix/` can match `glob`.
+fn glob_reaches(glob: &str, prefix: &str) -> bool {
+    let literal = literal_prefix(glob);
+    let dir = format!("{prefix}/");
+    literal.starts_with(&dir) || dir.starts_with(literal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::QueryParams;
+    use tempfile::TempDir;
+
+    fn write(root: &Path, rel: &str, content: &str) {
+        let path = root.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    /// Repo with two services, one lib, and a top-level file.
+    fn monorepo(shard_by: &str) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        write(root, "services/auth/src/login.rs", "fn auth_login() {}\n");
+        write(
+            root,
+            "services/billing/src/charge.rs",
+            "fn billing_charge() {}\n",
+        );
+        write(root, "libs/util/src/lib.rs", "fn util_helper() {}\n");
+        write(root, "src/main.rs", "fn main_entry() {}\n");
+        RepoIndex::init(root).unwrap();
+        write(
+            root,
+            ".canopy/config.toml",
+            &format!("[indexing]\nshard_by = {shard_by}\n"),
+        );
+        dir
+    }
+
+    fn symbol_files(index: &RepoIndex, params: QueryParams) -> Vec<String> {
+        let mut files: Vec<String> = index
+            .query_params(params)
+            .unwrap()
+            .handles
+            .into_iter()
+            .map(|h| h.file_path)
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn shard_file_name_roundtrips() {
+        for prefix in ["services/auth", "libs/a%2Fb", "top"] {
+            let name = shard_file_name(prefix);
+            assert!(!name.contains('/'));
+            assert_eq!(shard_prefix_from_file_name(&name).as_deref(), Some(prefix));
+        }
+        assert_eq!(shard_prefix_from_file_name("notes.txt"), None);
+    }
+
+    #[test]
+    fn route_matches_directory_ancestors() {
+        let mut config = Config::default();
+        config.indexing.shard_by = vec!["services/*".to_string(), "libs/*".to_string()];
+        let router = ShardRouter::open(Path::new("/nonexistent"), &config, false).unwrap();
+
+        assert_eq!(
+            router.route("services/auth/src/login.rs").as_deref(),
+            Some("services/auth")
+        );
+        assert_eq!(
+            router.route("libs/util/lib.rs").as_deref(),
+            Some("libs/util")
+        );
+        // A file directly under `services/` is not inside a matching directory
+        assert_eq!(router.route("services/README.md"), None);
+        assert_eq!(router.route("src/main.rs"), None);
+    }
+
+    #[test]
+    fn glob_reaches_uses_literal_prefix() {
+        assert!(glob_reaches("**/*.rs", "services/auth"));
+        assert!(glob_reaches("services/**", "services/auth"));
+        assert!(glob_reaches("services/auth/src/*.rs", "services/auth"));
+        assert!(!glob_reaches("services/billing/**", "services/auth"));
+        assert!(!glob_reaches("src/*.rs", "services/auth"));
+    }
+
+    #[test]
+    fn sharded_index_routes_files_and_fans_out_queries() {
+        let dir = monorepo(r#"["services/*", "libs/*"]"#);
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        let stats = index.index("**/*.rs").unwrap();
+        assert_eq!(stats.files_indexed, 4);
+
+        let shards_dir = dir.path().join(".canopy/shards");
+        assert!(shards_dir.join("services%2Fauth.db").exists());
+        assert!(shards_dir.join("services%2Fbilling.db").exists());
+        assert!(shards_dir.join("libs%2Futil.db").exists());
+        assert_eq!(index.indexed_paths().unwrap(), vec!["src/main.rs"]);
+
+        let status = index.status().unwrap();
+        assert_eq!(status.files_indexed, 4);
+        assert_eq!(status.shards, 3);
+
+        // No glob: every shard is consulted
+        let all = symbol_files(&index, QueryParams::pattern("fn"));
+        assert_eq!(all.len(), 4);
+
+        // Glob restricted to one service only reaches that shard
+        let auth_targets = index.query_targets(Some("services/auth/**"));
+        assert_eq!(auth_targets.len(), 1);
+        let auth = symbol_files(
+            &index,
+            QueryParams::pattern("fn").with_glob("services/auth/**"),
+        );
+        assert_eq!(auth, vec!["services/auth/src/login.rs"]);
+
+        // Expand resolves handles stored in a shard
+        let result = index
+            .query_params(QueryParams::symbol("billing_charge"))
+            .unwrap();
+        let id = result.handles[0].id.to_string();
+        let expanded = index.expand(&[id]).unwrap();
+        assert!(expanded[0].1.contains("billing_charge"));
+
+        // Reopening discovers existing shards
+        let reopened = RepoIndex::open(dir.path()).unwrap();
+        assert_eq!(reopened.status().unwrap().files_indexed, 4);
+    }
+
+    #[test]
+    fn sharded_invalidate_touches_only_matching_shards() {
+        let dir = monorepo(r#"["services/*"]"#);
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let removed = index.invalidate(Some("services/auth/**")).unwrap();
+        assert_eq!(removed, 1);
+        assert_eq!(index.status().unwrap().files_indexed, 3);
+        assert!(index
+            .query_params(QueryParams::symbol("auth_login"))
+            .unwrap()
+            .handles
+            .is_empty());
+
+        assert_eq!(index.invalidate(None).unwrap(), 3);
+        assert_eq!(index.status().unwrap().files_indexed, 0);
+    }
+
+    #[test]
+    fn reshard_splits_monolithic_index_and_folds_back() {
+        let dir = monorepo("[]");
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        assert_eq!(index.indexed_paths().unwrap().len(), 4);
+        drop(index);
+
+        write(
+            dir.path(),
+            ".canopy/config.toml",
+            "[indexing]\nshard_by = [\"services/*\"]\n",
+        );
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+
+        let plan = index.reshard(false).unwrap();
+        assert_eq!(plan.files_moved, 2);
+        assert_eq!(plan.shards, vec!["services/auth", "services/billing"]);
+        assert_eq!(index.indexed_paths().unwrap().len(), 4);
+
+        let applied = index.reshard(true).unwrap();
+        assert!(applied.applied);
+        assert_eq!(applied.files_moved, 2);
+        assert_eq!(index.indexed_paths().unwrap().len(), 2);
+        assert_eq!(index.status().unwrap().files_indexed, 4);
+        assert_eq!(
+            symbol_files(&index, QueryParams::symbol("auth_login")),
+            vec!["services/auth/src/login.rs"]
+        );
+        assert_eq!(index.reshard(false).unwrap().files_moved, 0);
+        drop(index);
+
+        // Dropping shard_by folds the shards back into index.db
+        write(
+            dir.path(),
+            ".canopy/config.toml",
+            "[indexing]\nshard_by = []\n",
+        );
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        let folded = index.reshard(true).unwrap();
+        assert_eq!(folded.files_moved, 2);
+        assert!(folded.shards.is_empty());
+        assert_eq!(index.indexed_paths().unwrap().len(), 4);
+        assert!(!dir
+            .path()
+            .join(".canopy/shards/services%2Fauth.db")
+            .exists());
+    }
+}
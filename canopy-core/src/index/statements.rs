@@ -0,0 +1,140 @@
+//! Prepared-statement reuse for the hot query paths.
+//!
+//! Search and expand SQL is built per call, but from a small set of shapes:
+//! the same text comes back for every query of one kind, so the connection's
+//! statement cache (keyed on the SQL text) can hand back the prepared
+//! statement instead of parsing and planning it again. `IN (...)` lists are
+//! padded to a few fixed arities so a batch lookup doesn't add one cache entry
+//! per batch size. Statements survive reindexing: SQLite re-prepares a cached
+//! statement by itself when the schema it was planned against changes.
+
+use rusqlite::Statement;
+
+use super::expand::EXPAND_LOOKUP_CHUNK;
+use super::RepoIndex;
+
+/// Statements kept prepared per connection: every query shape, under each
+/// generated-file filter, with room for the padded lookup variants.
+pub(super) const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+/// Arities `IN (...)` lookups are padded to.
+const IN_LIST_SIZES: [usize; 4] = [1, 8, 32, EXPAND_LOOKUP_CHUNK];
+
+impl RepoIndex {
+    /// Run `f` on the statement for `sql`, taken from and returned to the
+    /// statement cache. Inside a recent scope the cutoff is inlined into the
+    /// SQL, so those statements are dropped after use rather than crowding
+    /// out the reusable ones.
+    pub(super) fn with_statement<T>(
+        &self,
+        sql: &str,
+        f: impl FnOnce(&mut Statement<'_>) -> crate::Result<T>,
+    ) -> crate::Result<T> {
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let result = f(&mut stmt);
+        if self.modified_since.get().is_some() {
+            stmt.discard();
+        }
+        result
+    }
+}
+
+/// Placeholders for an `IN (...)` list holding `ids`, and the ids to bind,
+/// padded to the next of [`IN_LIST_SIZES`] by repeating the last id.
+/// `ids` must be non-empty and at most [`EXPAND_LOOKUP_CHUNK`] long.
+pub(super) fn in_list<'a>(ids: &[&'a str]) -> (String, Vec<&'a str>) {
+    let size = IN_LIST_SIZES
+        .into_iter()
+        .find(|size| *size >= ids.len())
+        .unwrap_or(ids.len());
+    let mut padded = ids.to_vec();
+    if let Some(last) = ids.last() {
+        padded.resize(size, last);
+    }
+    (vec!["?"; padded.len()].join(", "), padded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::test_helpers::setup_repo;
+    use std::time::{Duration, Instant, SystemTime};
+
+    #[test]
+    fn in_lists_pad_to_fixed_arities() {
+        let (placeholders, ids) = in_list(&["a"]);
+        assert_eq!((placeholders.as_str(), ids), ("?", vec!["a"]));
+        let (placeholders, ids) = in_list(&["a", "b", "c"]);
+        assert_eq!(placeholders.matches('?').count(), 8);
+        assert_eq!(ids[..3], ["a", "b", "c"]);
+        assert!(ids[3..].iter().all(|id| *id == "c"));
+        let full: Vec<&str> = vec!["x"; EXPAND_LOOKUP_CHUNK];
+        assert_eq!(in_list(&full).1.len(), EXPAND_LOOKUP_CHUNK);
+    }
+
+    /// Wall time of 1,000 symbol queries that miss the symbol cache and go
+    /// to SQL, best of three runs.
+    fn time_symbol_queries(index: &RepoIndex) -> Duration {
+        (0..3)
+            .map(|_| {
+                let start = Instant::now();
+                for i in 0..1_000 {
+                    let handles = index.search_code(&format!("missing_{}", i % 7), 5);
+                    assert!(handles.unwrap().is_empty());
+                }
+                start.elapsed()
+            })
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    fn cached_statements_beat_preparing_each_query() {
+        let dir = setup_repo(20);
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        index.conn.set_prepared_statement_cache_capacity(0);
+        let uncached = time_symbol_queries(&index);
+        index
+            .conn
+            .set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        let cached = time_symbol_queries(&index);
+        assert!(
+            cached < uncached,
+            "cached {cached:?} should beat uncached {uncached:?}"
+        );
+    }
+
+    #[test]
+    fn cached_statements_survive_reindex() {
+        let dir = setup_repo(3);
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        let before = index.fts_search("hello", 10).unwrap();
+        assert_eq!(before.len(), 3);
+        let raw: Vec<String> = before.iter().map(|h| h.id.to_string()).collect();
+        assert_eq!(index.expand(&raw).unwrap().len(), 3);
+
+        let path = dir.path().join("src/file_0.rs");
+        std::fs::write(&path, "fn renamed() { println!(\"hello again\"); }\n").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let after = index.fts_search("hello", 10).unwrap();
+        assert_eq!(after.len(), 3);
+        assert!(after.iter().any(|h| h.preview.contains("hello again")));
+        assert_eq!(index.search_code("renamed", 5).unwrap().len(), 1);
+        let raw: Vec<String> = after.iter().map(|h| h.id.to_string()).collect();
+        assert_eq!(index.expand(&raw).unwrap().len(), 3);
+
+        index.invalidate(None).unwrap();
+        index.index("**/*.rs").unwrap();
+        assert_eq!(index.fts_search("hello", 10).unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,148 @@
+//! "Did you mean" suggestions for symbol queries that find nothing.
+//!
+//! Scans the resident symbol cache keys with a bounded edit distance, so a
+//! misspelled symbol (`ClientRuntme`) can point at the real one without a
+//! database round-trip.
+
+use super::RepoIndex;
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of suggestions attached to a query result.
+pub const MAX_SYMBOL_SUGGESTIONS: usize = 5;
+
+/// A symbol name close to one that produced no results.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SymbolSuggestion {
+    pub name: String,
+    /// Number of indexed definitions with this name
+    pub definitions: usize,
+    /// Normalized similarity in (0, 1]; 1.0 is an exact case-insensitive match
+    pub similarity: f64,
+}
+
+impl RepoIndex {
+    /// Symbol names from the cache at least `min_similarity` similar to `symbol`,
+    /// best first.
+    pub(crate) fn suggest_symbols(
+        &self,
+        symbol: &str,
+        min_similarity: f64,
+        limit: usize,
+    ) -> Vec<SymbolSuggestion> {
+        let target: Vec<char> = symbol.to_lowercase().chars().collect();
+        if target.is_empty() || limit == 0 {
+            return Vec::new();
+        }
+
+        let mut suggestions: Vec<SymbolSuggestion> = self
+            .symbol_cache
+            .iter()
+            .filter_map(|(key, entries)| {
+                let candidate: Vec<char> = key.chars().collect();
+                let similarity = similarity(&target, &candidate, min_similarity)?;
+                let first = entries.first()?;
+                Some(SymbolSuggestion {
+                    name: first.name.clone(),
+                    definitions: entries.len(),
+                    similarity,
+                })
+            })
+            .collect();
+
+        sort_suggestions(&mut suggestions);
+        suggestions.truncate(limit);
+        suggestions
+    }
+}
+
+/// Best first: similarity, then definition count, then name for stable output.
+pub(crate) fn sort_suggestions(suggestions: &mut [SymbolSuggestion]) {
+    suggestions.sort_by(|a, b| {
+        b.similarity
+            .total_cmp(&a.similarity)
+            .then_with(|| b.definitions.cmp(&a.definitions))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+}
+
+/// `1 - distance / longer_len`, or `None` if below `min_similarity`.
+fn similarity(a: &[char], b: &[char], min_similarity: f64) -> Option<f64> {
+    let longer = a.len().max(b.len());
+    if longer == 0 {
+        return None;
+    }
+    let max_distance = ((1.0 - min_similarity.clamp(0.0, 1.0)) * longer as f64).floor() as usize;
+    let distance = bounded_levenshtein(a, b, max_distance)?;
+    let similarity = 1.0 - distance as f64 / longer as f64;
+    (similarity >= min_similarity).then_some(similarity)
+}
+
+/// Levenshtein distance, giving up (`None`) as soon as it must exceed `max`.
+fn bounded_levenshtein(a: &[char], b: &[char], max: usize) -> Option<usize> {
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        cur[0] = i + 1;
+        let mut row_min = cur[0];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != cb);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+            row_min = row_min.min(cur[j + 1]);
+        }
+        if row_min > max {
+            return None;
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    let distance = prev[b.len()];
+    (distance <= max).then_some(distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    #[test]
+    fn bounded_levenshtein_computes_and_bails_out() {
+        assert_eq!(
+            bounded_levenshtein(&chars("kitten"), &chars("sitting"), 5),
+            Some(3)
+        );
+        assert_eq!(
+            bounded_levenshtein(&chars("kitten"), &chars("sitting"), 2),
+            None
+        );
+        assert_eq!(bounded_levenshtein(&chars("a"), &chars("abcdef"), 2), None);
+    }
+
+    #[test]
+    fn suggest_symbols_ranks_close_names_first() {
+        let dir = super::super::test_helpers::setup_repo(0);
+        std::fs::write(
+            dir.path().join("src/lib.rs"),
+            "struct ClientRuntime;\nfn client_runtime() {}\nstruct ServiceClient;\nfn unrelated() {}\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("src/other.rs"), "struct ClientRuntime;\n").unwrap();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let suggestions = index.suggest_symbols("ClientRuntme", 0.6, MAX_SYMBOL_SUGGESTIONS);
+        assert_eq!(suggestions[0].name, "ClientRuntime");
+        assert_eq!(suggestions[0].definitions, 2);
+        assert!(suggestions[0].similarity > 0.9);
+        assert!(suggestions.iter().all(|s| s.name != "unrelated"));
+
+        // A strict threshold filters everything out
+        assert!(index.suggest_symbols("ClientRuntme", 0.99, 5).is_empty());
+    }
+}
@@ -0,0 +1,449 @@
+//! Repository-level summary for agent bootstrapping.
+//!
+//! One compact, budgeted overview (layout, sizes, languages, README intro) so an
+//! agent can orient itself without issuing a dozen exploratory queries first.
+
+use crate::document::NodeType;
+use crate::handle::Handle;
+use crate::parse::{estimate_tokens, FileType};
+use rusqlite::OptionalExtension;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::search::{handle_from_row, HANDLE_SELECT};
+use super::RepoIndex;
+
+/// Default `max_tokens` for [`RepoIndex::repo_summary`].
+pub const DEFAULT_SUMMARY_TOKENS: usize = 1500;
+
+/// Directories listed before budget trimming.
+const MAX_SUMMARY_DIRECTORIES: usize = 20;
+/// Largest files listed before budget trimming.
+const MAX_SUMMARY_LARGEST_FILES: usize = 10;
+
+/// Budgeted overview of an indexed repository.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepoSummary {
+    pub total_files: usize,
+    pub total_tokens: usize,
+    /// Languages by indexed tokens, largest first
+    pub languages: Vec<LanguageSummary>,
+    /// Top-level directories by indexed tokens, largest first (`.` is the repo root)
+    pub directories: Vec<DirectorySummary>,
+    /// Largest indexed files by tokens
+    pub largest_files: Vec<FileSummary>,
+    /// First section of the root README, if one is indexed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readme: Option<Handle>,
+    /// Whether lists were shortened to fit the token budget
+    pub truncated: bool,
+    /// Estimated tokens of this summary serialized as JSON
+    pub token_count: usize,
+    /// Files left out of every count and list because their path isn't
+    /// permitted (see [`RepoIndex::permitted_repo_summary`])
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub suppressed_by_policy: usize,
+}
+
+fn is_zero(v: &usize) -> bool {
+    *v == 0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LanguageSummary {
+    pub language: String,
+    pub files: usize,
+    pub tokens: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DirectorySummary {
+    pub path: String,
+    pub files: usize,
+    pub tokens: usize,
+    /// Functions and methods
+    pub functions: usize,
+    pub classes: usize,
+    pub structs: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileSummary {
+    pub path: String,
+    pub tokens: usize,
+}
+
+/// Cached summary, valid while no file was indexed or removed since.
+pub(crate) struct CachedSummary {
+    key: SummaryKey,
+    max_tokens: usize,
+    summary: RepoSummary,
+}
+
+/// (latest `indexed_at`, file count) across every database of the index.
+type SummaryKey = (Option<i64>, usize);
+
+impl RepoIndex {
+    /// Summarize the indexed repository within roughly `max_tokens`.
+    ///
+    /// Lists are trimmed lowest-value first (tail of the largest files, then
+    /// directories, then languages, then the README handle) until the JSON form
+    /// fits. Summaries are cached until the next indexing run changes the index.
+    pub fn repo_summary(&self, max_tokens: usize) -> crate::Result<RepoSummary> {
+        let key = self.summary_key()?;
+        if let Some(cached) = self.summary_cache.borrow().as_ref() {
+            if cached.key == key && cached.max_tokens == max_tokens {
+                return Ok(cached.summary.clone());
+            }
+        }
+
+        let mut summary = self.build_summary(&|_| true)?;
+        fit_to_budget(&mut summary, max_tokens);
+        *self.summary_cache.borrow_mut() = Some(CachedSummary {
+            key,
+            max_tokens,
+            summary: summary.clone(),
+        });
+        Ok(summary)
+    }
+
+    /// [`repo_summary`](Self::repo_summary) of only the files whose path
+    /// `permits`: totals, languages and directories are counted from them,
+    /// and the largest files and README come from them. Not cached, since
+    /// the filter may change between calls.
+    pub fn permitted_repo_summary(
+        &self,
+        max_tokens: usize,
+        permits: impl Fn(&str) -> bool,
+    ) -> crate::Result<RepoSummary> {
+        let mut summary = self.build_summary(&permits)?;
+        fit_to_budget(&mut summary, max_tokens);
+        Ok(summary)
+    }
+
+    fn summary_key(&self) -> crate::Result<SummaryKey> {
+        let mut latest: Option<i64> = None;
+        let mut files = 0usize;
+        for index in self.all_indexes() {
+            let (count, last): (i64, Option<i64>) =
+                index
+                    .conn
+                    .query_row("SELECT COUNT(*), MAX(indexed_at) FROM files", [], |row| {
+                        Ok((row.get(0)?, row.get(1)?))
+                    })?;
+            files += count.max(0) as usize;
+            latest = latest.max(last);
+        }
+        Ok((latest, files))
+    }
+
+    fn build_summary(&self, permits: &dyn Fn(&str) -> bool) -> crate::Result<RepoSummary> {
+        let mut directories: BTreeMap<String, DirectorySummary> = BTreeMap::new();
+        let mut languages: BTreeMap<String, LanguageSummary> = BTreeMap::new();
+        let mut files: Vec<FileSummary> = Vec::new();
+        let mut readme: Option<Handle> = None;
+        let mut suppressed_by_policy = 0;
+
+        for index in self.all_indexes() {
+            let mut stmt = index.conn.prepare("SELECT path, token_count FROM files")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?;
+            for row in rows {
+                let (path, tokens) = row?;
+                if !permits(&path) {
+                    suppressed_by_policy += 1;
+                    continue;
+                }
+                let tokens = tokens.max(0) as usize;
+                let dir = directories
+                    .entry(top_level_dir(&path).to_string())
+                    .or_default();
+                dir.files += 1;
+                dir.tokens += tokens;
+                let language = language_of(&path);
+                let lang = languages
+                    .entry(language.clone())
+                    .or_insert_with(|| LanguageSummary {
+                        language,
+                        files: 0,
+                        tokens: 0,
+                    });
+                lang.files += 1;
+                lang.tokens += tokens;
+                files.push(FileSummary { path, tokens });
+            }
+
+            let mut stmt = index.conn.prepare(
+                "SELECT f.path, n.node_type, COUNT(*) FROM nodes n
+                 JOIN files f ON n.file_id = f.id
+                 WHERE n.node_type IN (?1, ?2, ?3, ?4)
+                 GROUP BY f.path, n.node_type",
+            )?;
+            let rows = stmt.query_map(
+                [
+                    NodeType::Function.as_int(),
+                    NodeType::Method.as_int(),
+                    NodeType::Class.as_int(),
+                    NodeType::Struct.as_int(),
+                ],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, i32>(1)?,
+                        row.get::<_, i64>(2)?,
+                    ))
+                },
+            )?;
+            for row in rows {
+                let (path, node_type, count) = row?;
+                if !permits(&path) {
+                    continue;
+                }
+                let count = count.max(0) as usize;
+                let dir = directories
+                    .entry(top_level_dir(&path).to_string())
+                    .or_default();
+                match NodeType::from_int(node_type as u8) {
+                    Some(NodeType::Function | NodeType::Method) => dir.functions += count,
+                    Some(NodeType::Class) => dir.classes += count,
+                    Some(NodeType::Struct) => dir.structs += count,
+                    _ => {}
+                }
+            }
+
+            if readme.is_none() {
+                readme = index
+                    .conn
+                    .query_row(
+                        &format!(
+                            "SELECT {HANDLE_SELECT} FROM nodes n
+                             JOIN files f ON n.file_id = f.id
+                             WHERE lower(f.path) IN ('readme.md', 'readme.markdown')
+                               AND n.node_type = ?1
+                             ORDER BY n.start_byte LIMIT 1"
+                        ),
+                        [NodeType::Section.as_int()],
+                        handle_from_row,
+                    )
+                    .optional()?
+                    .filter(|handle| permits(&handle.file_path));
+            }
+        }
+
+        let total_files = files.len();
+        let total_tokens = files.iter().map(|f| f.tokens).sum();
+
+        let mut directories: Vec<DirectorySummary> = directories
+            .into_iter()
+            .map(|(path, mut dir)| {
+                dir.path = path;
+                dir
+            })
+            .collect();
+        directories.sort_by(|a, b| b.tokens.cmp(&a.tokens).then_with(|| a.path.cmp(&b.path)));
+        let mut truncated = directories.len() > MAX_SUMMARY_DIRECTORIES;
+        directories.truncate(MAX_SUMMARY_DIRECTORIES);
+
+        let mut languages: Vec<LanguageSummary> = languages.into_values().collect();
+        languages.sort_by(|a, b| {
+            b.tokens
+                .cmp(&a.tokens)
+                .then_with(|| a.language.cmp(&b.language))
+        });
+
+        files.sort_by(|a, b| b.tokens.cmp(&a.tokens).then_with(|| a.path.cmp(&b.path)));
+        truncated |= files.len() > MAX_SUMMARY_LARGEST_FILES;
+        files.truncate(MAX_SUMMARY_LARGEST_FILES);
+
+        Ok(RepoSummary {
+            total_files,
+            total_tokens,
+            languages,
+            directories,
+            largest_files: files,
+            readme,
+            truncated,
+            token_count: 0,
+            suppressed_by_policy,
+        })
+    }
+}
+
+/// Trim `summary` until its JSON form fits `max_tokens`, then record its size.
+fn fit_to_budget(summary: &mut RepoSummary, max_tokens: usize) {
+    loop {
+        let tokens = summary_tokens(summary);
+        if tokens <= max_tokens || !trim_once(summary) {
+            summary.token_count = tokens;
+            return;
+        }
+        summary.truncated = true;
+    }
+}
+
+/// Drop the lowest-value entry. Returns false once nothing is left to drop.
+fn trim_once(summary: &mut RepoSummary) -> bool {
+    if summary.largest_files.len() > 3 {
+        summary.largest_files.pop();
+    } else if summary.directories.len() > 5 {
+        summary.directories.pop();
+    } else if summary.languages.len() > 3 {
+        summary.languages.pop();
+    } else if summary.readme.is_some() {
+        summary.readme = None;
+    } else if !summary.largest_files.is_empty() {
+        summary.largest_files.pop();
+    } else if !summary.directories.is_empty() {
+        summary.directories.pop();
+    } else if !summary.languages.is_empty() {
+        summary.languages.pop();
+    } else {
+        return false;
+    }
+    true
+}
+
+fn summary_tokens(summary: &RepoSummary) -> usize {
+    serde_json::to_string(summary)
+        .map(|json| estimate_tokens(&json))
+        .unwrap_or(0)
+}
+
+/// First path segment of a relative path, or `.` for files at the repo root.
+fn top_level_dir(path: &str) -> &str {
+    path.split_once('/').map_or(".", |(first, _)| first)
+}
+
+/// Language label for a path: the parser's file type, else the extension.
+fn language_of(path: &str) -> String {
+    let label = match FileType::from_path(Path::new(path)) {
+        FileType::Markdown => "markdown",
+        FileType::Rust => "rust",
+        FileType::Python => "python",
+        FileType::JavaScript => "javascript",
+        FileType::TypeScript => "typescript",
+        FileType::Go => "go",
+        FileType::Notebook => "notebook",
+        FileType::Json => "json",
+        FileType::Yaml => "yaml",
+        FileType::Toml => "toml",
+        FileType::Other => {
+            return Path::new(path)
+                .extension()
+                .and_then(|e| e.to_str())
+                .map_or_else(|| "other".to_string(), str::to_ascii_lowercase);
+        }
+    };
+    label.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn summary_repo() -> tempfile::TempDir {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/auth")).unwrap();
+        fs::create_dir_all(root.join("web")).unwrap();
+        fs::write(
+            root.join("README.md"),
+            "# Demo\n\nA demo project.\n\n## Usage\n\nRun it.\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("src/lib.rs"),
+            "pub struct Config { a: u32 }\n\nimpl Config {\n    pub fn new() -> Self { Self { a: 1 } }\n}\n\npub fn run() {}\n",
+        )
+        .unwrap();
+        fs::write(root.join("src/auth/login.rs"), "pub fn login() {}\n").unwrap();
+        fs::write(
+            root.join("web/app.ts"),
+            "export class App {\n  start() { return 1; }\n}\n",
+        )
+        .unwrap();
+        RepoIndex::init(root).unwrap();
+        dir
+    }
+
+    #[test]
+    fn summary_counts_directories_languages_and_readme() {
+        let dir = summary_repo();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*").unwrap();
+
+        let summary = index.repo_summary(10_000).unwrap();
+        // The four sources plus the .gitignore written by init
+        assert_eq!(summary.total_files, 5);
+        assert!(!summary.truncated);
+
+        let src = summary
+            .directories
+            .iter()
+            .find(|d| d.path == "src")
+            .expect("src directory");
+        assert_eq!(src.files, 2);
+        // The struct and its impl block
+        assert_eq!(src.structs, 2);
+        assert!(src.functions >= 3, "run, login and Config::new");
+        let web = summary
+            .directories
+            .iter()
+            .find(|d| d.path == "web")
+            .unwrap();
+        assert!(web.classes >= 1);
+        assert!(summary.directories.iter().any(|d| d.path == "."));
+
+        let languages: Vec<&str> = summary
+            .languages
+            .iter()
+            .map(|l| l.language.as_str())
+            .collect();
+        for expected in ["rust", "typescript", "markdown"] {
+            assert!(languages.contains(&expected), "{expected} in {languages:?}");
+        }
+
+        let readme = summary.readme.expect("README section");
+        assert_eq!(readme.file_path, "README.md");
+        assert_eq!(readme.line_range.0, 1);
+        assert_eq!(summary.largest_files.len(), 5);
+    }
+
+    #[test]
+    fn summary_fits_budget_and_refreshes_after_reindex() {
+        let dir = summary_repo();
+        let root = dir.path();
+        for i in 0..30 {
+            fs::write(
+                root.join(format!("src/extra_{i}.rs")),
+                format!("pub fn extra_{i}() {{}}\n"),
+            )
+            .unwrap();
+            fs::create_dir_all(root.join(format!("pkg_{i}"))).unwrap();
+            fs::write(root.join(format!("pkg_{i}/mod.py")), "def f():\n    pass\n").unwrap();
+        }
+        let mut index = RepoIndex::open(root).unwrap();
+        index.index("**/*").unwrap();
+
+        let tight = index.repo_summary(300).unwrap();
+        assert!(tight.truncated);
+        assert!(tight.token_count <= 300, "{} tokens", tight.token_count);
+        assert!(tight.largest_files.len() < MAX_SUMMARY_LARGEST_FILES);
+        let roomy = index.repo_summary(DEFAULT_SUMMARY_TOKENS).unwrap();
+        assert!(roomy.directories.len() >= tight.directories.len());
+        assert_eq!(roomy.total_files, 65);
+
+        fs::write(root.join("src/late.rs"), "pub fn late() {}\n").unwrap();
+        index.index("src/late.rs").unwrap();
+        assert_eq!(
+            index
+                .repo_summary(DEFAULT_SUMMARY_TOKENS)
+                .unwrap()
+                .total_files,
+            66
+        );
+    }
+}
@@ -1,13 +1,17 @@
 //! Symbol cache: in-memory O(1) symbol lookups with forward + reverse indices.
 
+use super::search::SHORT_HASH_BYTES;
 use super::RepoIndex;
 use crate::document::NodeType;
 use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 
 /// Cached symbol entry for O(1) lookups
 #[derive(Clone)]
 pub(crate) struct SymbolCacheEntry {
+    /// Symbol name as written in source (keys are lowercased)
+    pub name: String,
     pub handle_id: String,
     pub file_path: String,
     pub node_type: i32,
@@ -17,29 +21,100 @@ pub(crate) struct SymbolCacheEntry {
     pub line_end: usize,
     pub token_count: usize,
     pub preview: String,
+    pub preview_tokens: usize,
+    /// Leading bytes of the node's content hash, for [`Handle::content_hash`](crate::Handle::content_hash)
+    pub content_hash: Option<[u8; SHORT_HASH_BYTES]>,
 }
 
-/// Symbol cache pair: (name_lower -> entries, file_path -> set of name_lower keys)
-pub(crate) type SymbolCachePair = (
-    HashMap<String, Vec<SymbolCacheEntry>>,
-    HashMap<String, HashSet<String>>,
-);
+/// The leading bytes of a stored node hash kept in the cache.
+pub(super) fn short_hash_bytes(hash: &[u8]) -> Option<[u8; SHORT_HASH_BYTES]> {
+    hash.get(..SHORT_HASH_BYTES)?.try_into().ok()
+}
 
-impl RepoIndex {
-    /// Load symbol cache from database (preload for fast lookups)
-    /// Returns (name_lower -> entries, file_path -> set of name_lower keys)
-    pub(crate) fn load_symbol_cache(conn: &Connection) -> crate::Result<SymbolCachePair> {
-        let mut cache: HashMap<String, Vec<SymbolCacheEntry>> = HashMap::new();
-        let mut by_file: HashMap<String, HashSet<String>> = HashMap::new();
+/// Symbol cache changes from indexing one file, applied after the commit.
+#[derive(Default)]
+pub(crate) struct SymbolCacheDelta {
+    /// Drop all of the file's cached entries first (full reindex of the file)
+    pub replace_file: bool,
+    /// `(name_lower, handle_id)` of entries that no longer exist
+    pub removed: Vec<(String, String)>,
+    pub added: Vec<(String, SymbolCacheEntry)>,
+}
+
+impl SymbolCacheDelta {
+    pub(crate) fn replace_file() -> Self {
+        Self {
+            replace_file: true,
+            ..Self::default()
+        }
+    }
+}
+
+/// Symbol cache: name_lower -> entries, with a reverse index of the names
+/// each file holds for O(symbols in file) removal.
+///
+/// The cache is held to an estimated byte budget (`[core]
+/// symbol_cache_max_bytes`). A name is cached with all of its definitions or
+/// not at all, so a hit is always the full answer. Once the budget turns a
+/// name away the cache is partial: a miss then says nothing, and lookups go
+/// on to the database.
+#[derive(Default)]
+pub(crate) struct SymbolCache {
+    entries: HashMap<String, Vec<SymbolCacheEntry>>,
+    by_file: HashMap<String, HashSet<String>>,
+    /// Estimated size of everything held, by [`entry_bytes`]
+    bytes: usize,
+    /// 0 for no cap
+    max_bytes: usize,
+    partial: bool,
+}
+
+/// Size and completeness of the symbol caches, for `canopy status --verbose`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolCacheStats {
+    pub entries: usize,
+    /// Estimated bytes the cached entries take
+    pub estimated_bytes: usize,
+    /// `[core] symbol_cache_max_bytes`, per database (0: no cap)
+    pub max_bytes: usize,
+    /// Some names were left to the database to stay within `max_bytes`
+    pub partial: bool,
+}
+
+/// Estimated bytes one entry cached under `name_lower` takes, counting its
+/// share of the map keys and the reverse index.
+fn entry_bytes(name_lower: &str, entry: &SymbolCacheEntry) -> usize {
+    std::mem::size_of::<SymbolCacheEntry>()
+        + entry.name.len()
+        + entry.handle_id.len()
+        + entry.file_path.len()
+        + entry.preview.len()
+        + 2 * (std::mem::size_of::<String>() + name_lower.len())
+}
+
+impl SymbolCache {
+    pub(crate) fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            ..Self::default()
+        }
+    }
+
+    /// Load code symbols (function, class, struct, method) from the
+    /// database, shortest names first, until the budget runs out.
+    pub(crate) fn load(conn: &Connection, max_bytes: usize) -> crate::Result<Self> {
+        let mut cache = Self::new(max_bytes);
 
-        // Only load code symbols (function, class, struct, method)
+        // Ordered by name so each name's definitions arrive together
         let mut stmt = conn.prepare(
             "SELECT n.name_lower, n.handle_id, f.path, n.node_type, n.start_byte, n.end_byte,
-                    n.line_start, n.line_end, n.token_count, n.preview
+                    n.line_start, n.line_end, n.token_count, n.preview, n.name,
+                    n.preview_tokens, n.content_hash
              FROM nodes n
              JOIN files f ON n.file_id = f.id
              WHERE n.name_lower IS NOT NULL
-               AND n.node_type IN (?, ?, ?, ?)",
+               AND n.node_type IN (?, ?, ?, ?)
+             ORDER BY length(n.name_lower), n.name_lower",
         )?;
 
         let rows = stmt.query_map(
@@ -60,10 +135,16 @@ impl RepoIndex {
                 let line_end: i64 = row.get(7)?;
                 let token_count: i64 = row.get(8)?;
                 let preview: Option<String> = row.get(9)?;
+                let name: String = row
+                    .get::<_, Option<String>>(10)?
+                    .unwrap_or_else(|| name_lower.clone());
+                let preview_tokens: i64 = row.get(11)?;
+                let content_hash: Option<Vec<u8>> = row.get(12)?;
 
                 Ok((
                     name_lower,
                     SymbolCacheEntry {
+                        name,
                         handle_id,
                         file_path,
                         node_type,
@@ -73,64 +154,261 @@ impl RepoIndex {
                         line_end: line_end as usize,
                         token_count: token_count as usize,
                         preview: preview.unwrap_or_else(|| "...".to_string()),
+                        preview_tokens: preview_tokens.max(0) as usize,
+                        content_hash: content_hash.as_deref().and_then(short_hash_bytes),
                     },
                 ))
             },
         )?;
 
+        let mut group: Vec<(String, SymbolCacheEntry)> = Vec::new();
         for (name, entry) in rows.flatten() {
-            by_file
-                .entry(entry.file_path.clone())
-                .or_default()
-                .insert(name.clone());
-            cache.entry(name).or_default().push(entry);
-        }
-
-        Ok((cache, by_file))
-    }
-
-    /// Remove a file's entries from the symbol cache using the reverse index (O(symbols in file))
-    pub(crate) fn remove_file_from_symbol_cache(
-        symbol_cache: &mut HashMap<String, Vec<SymbolCacheEntry>>,
-        symbol_cache_by_file: &mut HashMap<String, HashSet<String>>,
-        file_path: &str,
-    ) {
-        if let Some(names) = symbol_cache_by_file.remove(file_path) {
-            for name in &names {
-                if let Some(entries) = symbol_cache.get_mut(name) {
-                    entries.retain(|e| e.file_path != file_path);
-                    if entries.is_empty() {
-                        symbol_cache.remove(name);
+            if group.first().is_some_and(|(current, _)| *current != name)
+                && !cache.admit(std::mem::take(&mut group))
+            {
+                return Ok(cache);
+            }
+            group.push((name, entry));
+        }
+        cache.admit(group);
+
+        Ok(cache)
+    }
+
+    /// Cache all definitions of one name, if they fit; otherwise the cache
+    /// turns partial and `false` is returned.
+    fn admit(&mut self, group: Vec<(String, SymbolCacheEntry)>) -> bool {
+        let bytes: usize = group.iter().map(|(n, e)| entry_bytes(n, e)).sum();
+        if !self.fits(bytes) {
+            self.partial = true;
+            return false;
+        }
+        for (name, entry) in group {
+            self.insert(name, entry);
+        }
+        true
+    }
+
+    fn fits(&self, bytes: usize) -> bool {
+        self.max_bytes == 0 || self.bytes + bytes <= self.max_bytes
+    }
+
+    fn insert(&mut self, name_lower: String, entry: SymbolCacheEntry) {
+        self.bytes += entry_bytes(&name_lower, &entry);
+        self.by_file
+            .entry(entry.file_path.clone())
+            .or_default()
+            .insert(name_lower.clone());
+        self.entries.entry(name_lower).or_default().push(entry);
+    }
+
+    /// All cached definitions of a lowercased name. `None` is only
+    /// authoritative when the cache [`is_complete`](Self::is_complete).
+    pub(crate) fn get(&self, name_lower: &str) -> Option<&Vec<SymbolCacheEntry>> {
+        self.entries.get(name_lower)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn contains_key(&self, name_lower: &str) -> bool {
+        self.entries.contains_key(name_lower)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &Vec<SymbolCacheEntry>)> {
+        self.entries.iter()
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &Vec<SymbolCacheEntry>> {
+        self.entries.values()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Reverse index: file_path -> name_lower keys of its cached entries
+    #[cfg(test)]
+    pub(crate) fn by_file(&self) -> &HashMap<String, HashSet<String>> {
+        &self.by_file
+    }
+
+    /// Whether every code symbol in the database is cached, so a miss means
+    /// there is no such symbol.
+    pub(crate) fn is_complete(&self) -> bool {
+        !self.partial
+    }
+
+    pub(crate) fn stats(&self) -> SymbolCacheStats {
+        SymbolCacheStats {
+            entries: self.entries.values().map(Vec::len).sum(),
+            estimated_bytes: self.bytes,
+            max_bytes: self.max_bytes,
+            partial: self.partial,
+        }
+    }
+
+    /// Drop everything, as when the database is emptied; the cache is
+    /// complete again.
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.by_file.clear();
+        self.bytes = 0;
+        self.partial = false;
+    }
+
+    /// Remove a file's entries using the reverse index (O(symbols in file))
+    pub(crate) fn remove_file(&mut self, file_path: &str) {
+        let Some(names) = self.by_file.remove(file_path) else {
+            return;
+        };
+        for name in &names {
+            if let Some(entries) = self.entries.get_mut(name) {
+                entries.retain(|e| {
+                    let keep = e.file_path != file_path;
+                    if !keep {
+                        self.bytes -= entry_bytes(name, e);
                     }
+                    keep
+                });
+                if entries.is_empty() {
+                    self.entries.remove(name);
+                }
+            }
+        }
+    }
+
+    /// Apply one file's [`SymbolCacheDelta`]: removals first, then additions.
+    pub(crate) fn apply_delta(&mut self, file_path: &str, delta: SymbolCacheDelta) {
+        if delta.replace_file {
+            self.remove_file(file_path);
+        }
+        for (name_lower, handle_id) in &delta.removed {
+            let Some(entries) = self.entries.get_mut(name_lower) else {
+                continue;
+            };
+            entries.retain(|e| {
+                let keep = e.handle_id != *handle_id;
+                if !keep {
+                    self.bytes -= entry_bytes(name_lower, e);
                 }
+                keep
+            });
+            let still_in_file = entries.iter().any(|e| e.file_path == file_path);
+            if entries.is_empty() {
+                self.entries.remove(name_lower);
+            }
+            if !still_in_file {
+                self.unlink(file_path, name_lower);
             }
         }
+        self.add(delta.added);
     }
 
-    /// Add new symbol cache entries and update the reverse index
-    pub(crate) fn add_to_symbol_cache(
-        symbol_cache: &mut HashMap<String, Vec<SymbolCacheEntry>>,
-        symbol_cache_by_file: &mut HashMap<String, HashSet<String>>,
-        entries: Vec<(String, SymbolCacheEntry)>,
-    ) {
+    /// Add new entries and update the reverse index, within the budget.
+    ///
+    /// A name that's already cached takes its new definition, or is dropped
+    /// whole when that would go over budget. A new name is cached only while
+    /// the cache is complete: once partial, the name may already have
+    /// definitions in the database that the cache doesn't hold.
+    pub(crate) fn add(&mut self, entries: Vec<(String, SymbolCacheEntry)>) {
         for (name_lower, entry) in entries {
-            symbol_cache_by_file
-                .entry(entry.file_path.clone())
-                .or_default()
-                .insert(name_lower.clone());
-            symbol_cache.entry(name_lower).or_default().push(entry);
+            let bytes = entry_bytes(&name_lower, &entry);
+            let cached = self.entries.contains_key(&name_lower);
+            if self.fits(bytes) && (cached || !self.partial) {
+                self.insert(name_lower, entry);
+                continue;
+            }
+            self.partial = true;
+            if cached {
+                self.evict(&name_lower);
+            }
+        }
+    }
+
+    /// Drop every cached definition of a name.
+    fn evict(&mut self, name_lower: &str) {
+        let Some(entries) = self.entries.remove(name_lower) else {
+            return;
+        };
+        for entry in &entries {
+            self.bytes -= entry_bytes(name_lower, entry);
+            self.unlink(&entry.file_path, name_lower);
+        }
+    }
+
+    /// Remove `name_lower` from the names cached for `file_path`.
+    fn unlink(&mut self, file_path: &str, name_lower: &str) {
+        if let Some(names) = self.by_file.get_mut(file_path) {
+            names.remove(name_lower);
+            if names.is_empty() {
+                self.by_file.remove(file_path);
+            }
         }
     }
 }
 
+impl std::ops::Index<&str> for SymbolCache {
+    type Output = Vec<SymbolCacheEntry>;
+
+    fn index(&self, name_lower: &str) -> &Self::Output {
+        &self.entries[name_lower]
+    }
+}
+
+impl RepoIndex {
+    /// Estimated size and completeness of the symbol caches of every
+    /// database, summed.
+    pub fn symbol_cache_stats(&self) -> SymbolCacheStats {
+        let mut total = SymbolCacheStats::default();
+        for index in self.all_indexes() {
+            let stats = index.symbol_cache.stats();
+            total.entries += stats.entries;
+            total.estimated_bytes += stats.estimated_bytes;
+            total.max_bytes = total.max_bytes.max(stats.max_bytes);
+            total.partial |= stats.partial;
+        }
+        total
+    }
+
+    /// How each definition of a code symbol spells its name, from the cache,
+    /// or from the database for a name a partial cache doesn't hold.
+    pub(crate) fn symbol_spellings(&self, name_lower: &str) -> crate::Result<Vec<String>> {
+        if let Some(entries) = self.symbol_cache.get(name_lower) {
+            return Ok(entries.iter().map(|e| e.name.clone()).collect());
+        }
+        if self.symbol_cache.is_complete() {
+            return Ok(Vec::new());
+        }
+        self.with_statement(
+            "SELECT COALESCE(name, name_lower) FROM nodes
+             WHERE name_lower = ? AND node_type IN (?, ?, ?, ?)",
+            |stmt| {
+                let rows = stmt.query_map(
+                    params![
+                        name_lower,
+                        NodeType::Function.as_int() as i32,
+                        NodeType::Class.as_int() as i32,
+                        NodeType::Struct.as_int() as i32,
+                        NodeType::Method.as_int() as i32,
+                    ],
+                    |row| row.get(0),
+                )?;
+                Ok(super::search::collect_row_results(rows)?)
+            },
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::index::test_helpers::setup_repo;
 
     fn make_entry(name: &str, file: &str) -> (String, SymbolCacheEntry) {
         (
             name.to_lowercase(),
             SymbolCacheEntry {
+                name: name.to_string(),
                 handle_id: format!("h_{name}"),
                 file_path: file.to_string(),
                 node_type: NodeType::Function.as_int() as i32,
@@ -140,58 +418,179 @@ mod tests {
                 line_end: 10,
                 token_count: 50,
                 preview: format!("fn {name}()"),
+                preview_tokens: 3,
+                content_hash: None,
             },
         )
     }
 
     #[test]
     fn add_and_lookup_symbol_cache() {
-        let mut cache = HashMap::new();
-        let mut by_file = HashMap::new();
+        let mut cache = SymbolCache::new(0);
         let entries = vec![make_entry("foo", "src/lib.rs")];
-        RepoIndex::add_to_symbol_cache(&mut cache, &mut by_file, entries);
+        cache.add(entries);
         assert_eq!(cache["foo"].len(), 1);
         assert_eq!(cache["foo"][0].handle_id, "h_foo");
-        assert!(by_file["src/lib.rs"].contains("foo"));
+        assert!(cache.by_file()["src/lib.rs"].contains("foo"));
     }
 
     #[test]
     fn remove_file_clears_entries_and_reverse_index() {
-        let mut cache = HashMap::new();
-        let mut by_file = HashMap::new();
+        let mut cache = SymbolCache::new(0);
         let entries = vec![
             make_entry("foo", "src/a.rs"),
             make_entry("bar", "src/a.rs"),
             make_entry("baz", "src/b.rs"),
         ];
-        RepoIndex::add_to_symbol_cache(&mut cache, &mut by_file, entries);
-        assert_eq!(cache.len(), 3);
+        cache.add(entries);
+        assert_eq!(cache.stats().entries, 3);
 
-        RepoIndex::remove_file_from_symbol_cache(&mut cache, &mut by_file, "src/a.rs");
+        cache.remove_file("src/a.rs");
         assert!(!cache.contains_key("foo"));
         assert!(!cache.contains_key("bar"));
         assert!(cache.contains_key("baz"));
-        assert!(!by_file.contains_key("src/a.rs"));
-        assert!(by_file.contains_key("src/b.rs"));
+        assert!(!cache.by_file().contains_key("src/a.rs"));
+        assert!(cache.by_file().contains_key("src/b.rs"));
     }
 
     #[test]
     fn remove_nonexistent_file_is_noop() {
-        let mut cache = HashMap::new();
-        let mut by_file = HashMap::new();
-        RepoIndex::remove_file_from_symbol_cache(&mut cache, &mut by_file, "no/such/file.rs");
+        let mut cache = SymbolCache::new(0);
+        cache.remove_file("no/such/file.rs");
         assert!(cache.is_empty());
     }
 
+    #[test]
+    fn delta_removes_by_handle_and_keeps_reverse_index() {
+        let mut cache = SymbolCache::new(0);
+        let mut foo_b = make_entry("foo", "src/a.rs");
+        foo_b.1.handle_id = "h_foo_b".to_string();
+        let entries = vec![
+            make_entry("foo", "src/a.rs"),
+            foo_b,
+            make_entry("bar", "src/a.rs"),
+        ];
+        cache.add(entries);
+
+        let mut moved = make_entry("bar", "src/a.rs");
+        moved.1.handle_id = "h_bar_moved".to_string();
+        let delta = SymbolCacheDelta {
+            replace_file: false,
+            removed: vec![
+                ("foo".to_string(), "h_foo".to_string()),
+                ("bar".to_string(), "h_bar".to_string()),
+            ],
+            added: vec![moved],
+        };
+        cache.apply_delta("src/a.rs", delta);
+
+        assert_eq!(cache["foo"].len(), 1);
+        assert_eq!(cache["foo"][0].handle_id, "h_foo_b");
+        assert_eq!(cache["bar"].len(), 1);
+        assert_eq!(cache["bar"][0].handle_id, "h_bar_moved");
+        assert!(cache.by_file()["src/a.rs"].contains("foo"));
+        assert!(cache.by_file()["src/a.rs"].contains("bar"));
+
+        let delta = SymbolCacheDelta {
+            replace_file: false,
+            removed: vec![("foo".to_string(), "h_foo_b".to_string())],
+            added: Vec::new(),
+        };
+        cache.apply_delta("src/a.rs", delta);
+        assert!(!cache.contains_key("foo"));
+        assert!(!cache.by_file()["src/a.rs"].contains("foo"));
+    }
+
     #[test]
     fn add_multiple_entries_same_symbol_name() {
-        let mut cache = HashMap::new();
-        let mut by_file = HashMap::new();
+        let mut cache = SymbolCache::new(0);
         let entries = vec![
             make_entry("config", "src/a.rs"),
             make_entry("config", "src/b.rs"),
         ];
-        RepoIndex::add_to_symbol_cache(&mut cache, &mut by_file, entries);
+        cache.add(entries);
         assert_eq!(cache["config"].len(), 2);
     }
+
+    #[test]
+    fn budget_keeps_names_whole_and_turns_partial() {
+        let (name, entry) = make_entry("foo", "src/a.rs");
+        let mut cache = SymbolCache::new(2 * entry_bytes(&name, &entry));
+        cache.add(vec![
+            make_entry("foo", "src/a.rs"),
+            make_entry("foo", "src/b.rs"),
+        ]);
+        assert!(cache.is_complete());
+        assert_eq!(cache.stats().estimated_bytes, cache.max_bytes);
+
+        // A new name that doesn't fit is left to the database
+        cache.add(vec![make_entry("bar", "src/c.rs")]);
+        assert!(!cache.is_complete());
+        assert!(!cache.contains_key("bar"));
+        assert!(!cache.by_file().contains_key("src/c.rs"));
+        assert_eq!(cache["foo"].len(), 2);
+
+        // A cached name that outgrows the budget goes whole
+        cache.add(vec![make_entry("foo", "src/c.rs")]);
+        assert!(!cache.contains_key("foo"));
+        assert!(cache.by_file().is_empty());
+        assert_eq!(cache.stats().estimated_bytes, 0);
+
+        // Once partial, new names stay out even with room to spare
+        cache.add(vec![make_entry("baz", "src/d.rs")]);
+        assert!(cache.is_empty());
+
+        cache.clear();
+        assert!(cache.is_complete());
+    }
+
+    #[test]
+    fn removals_return_their_bytes() {
+        let mut cache = SymbolCache::new(0);
+        cache.add(vec![
+            make_entry("foo", "src/a.rs"),
+            make_entry("bar", "src/b.rs"),
+        ]);
+        let (name, entry) = make_entry("bar", "src/b.rs");
+        cache.remove_file("src/a.rs");
+        assert_eq!(cache.stats().estimated_bytes, entry_bytes(&name, &entry));
+        cache.apply_delta(
+            "src/b.rs",
+            SymbolCacheDelta {
+                replace_file: false,
+                removed: vec![("bar".to_string(), "h_bar".to_string())],
+                added: Vec::new(),
+            },
+        );
+        assert_eq!(cache.stats().estimated_bytes, 0);
+    }
+
+    #[test]
+    fn symbols_past_a_tiny_cap_are_found_in_the_database() {
+        let dir = setup_repo(40);
+        std::fs::write(
+            dir.path().join(".canopy/config.toml"),
+            "[core]\nsymbol_cache_max_bytes = 4000\n",
+        )
+        .unwrap();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        let indexed = index.symbol_cache_stats();
+        assert!(indexed.partial);
+        assert!(indexed.estimated_bytes <= 4000, "{indexed:?}");
+
+        let index = RepoIndex::open(dir.path()).unwrap();
+        let loaded = index.symbol_cache_stats();
+        assert!(loaded.partial);
+        assert!(loaded.estimated_bytes <= 4000, "{loaded:?}");
+        assert!((1..80).contains(&loaded.entries), "{loaded:?}");
+
+        for i in 0..40 {
+            for symbol in [format!("func_{i}"), format!("Struct{i}")] {
+                let handles = index.search_code(&symbol, 10).unwrap();
+                assert_eq!(handles.len(), 1, "{symbol}");
+                assert_eq!(handles[0].file_path, format!("src/file_{i}.rs"));
+            }
+        }
+    }
 }
@@ -0,0 +1,429 @@
+//! Index-wide symbol listing for autocomplete: every named node in name
+//! order, narrowed by a name prefix and paged with a keyset cursor.
+//!
+//! Names compare lowercased, as stored in `name_lower`. A prefix becomes the
+//! range `name_lower >= prefix AND name_lower < bound`, which
+//! `idx_nodes_name_lower` answers without a scan, and a page resumes after
+//! the `(name_lower, handle_id)` the previous one ended on, so pages neither
+//! repeat nor skip entries however many share a name. Unlike the symbol
+//! cache, which holds code definitions only, the listing reads the
+//! databases, so section headings list too.
+
+use crate::document::NodeType;
+use crate::handle::{Handle, HandleId};
+use rusqlite::params_from_iter;
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+
+use super::search::{collect_row_results, handle_from_row, HANDLE_SELECT};
+use super::RepoIndex;
+
+/// Default `limit` for [`RepoIndex::list_symbols`].
+pub const DEFAULT_SYMBOL_LIMIT: usize = 100;
+
+/// Length of a raw handle id, which ends a cursor
+const HANDLE_ID_LEN: usize = 24;
+
+/// One named node of the index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolEntry {
+    pub name: String,
+    pub node_type: NodeType,
+    pub file_path: String,
+    pub handle_id: HandleId,
+    /// 1-indexed
+    pub line_start: usize,
+}
+
+/// A page of [`RepoIndex::list_symbols`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolPage {
+    /// Ordered by lowercased name, then handle id
+    pub symbols: Vec<SymbolEntry>,
+    /// Pass as `after` for the next page; unset on the last one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_after: Option<String>,
+    /// Entries up to the end of the page left out because their path isn't
+    /// permitted (see [`RepoIndex::list_permitted_symbols`])
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub suppressed_by_policy: usize,
+}
+
+fn is_zero(v: &usize) -> bool {
+    *v == 0
+}
+
+impl RepoIndex {
+    /// Up to `limit` named nodes across every database of the index, in name
+    /// order, whose names start with `prefix` (ignoring case) and whose type
+    /// is one of `node_types`, when given.
+    ///
+    /// `after` is the `next_after` of the previous page, or a bare name to
+    /// list the names sorting after it.
+    pub fn list_symbols(
+        &self,
+        prefix: Option<&str>,
+        node_types: Option<&[NodeType]>,
+        after: Option<&str>,
+        limit: usize,
+    ) -> crate::Result<SymbolPage> {
+        self.list_permitted_symbols(prefix, node_types, after, limit, |_| true)
+    }
+
+    /// [`list_symbols`](Self::list_symbols) of the entries whose path
+    /// `permits`. Denied entries are skipped while paging, so a page is only
+    /// short at the end of the listing and `next_after` always names a
+    /// returned entry; those skipped within the page are counted in
+    /// `suppressed_by_policy`.
+    pub fn list_permitted_symbols(
+        &self,
+        prefix: Option<&str>,
+        node_types: Option<&[NodeType]>,
+        after: Option<&str>,
+        limit: usize,
+        permits: impl Fn(&str) -> bool,
+    ) -> crate::Result<SymbolPage> {
+        let mut cursor = after.map(str::to_string);
+        let mut symbols = Vec::new();
+        let mut denied = Vec::new();
+        // One past the page tells whether another follows; batches double
+        // while denied entries keep the page short
+        let mut wanted = limit.saturating_add(1);
+        while symbols.len() <= limit {
+            let batch = self.symbol_batch(prefix, node_types, cursor.as_deref(), wanted)?;
+            let exhausted = batch.len() < wanted;
+            if let Some((name_lower, last)) = batch.last() {
+                cursor = Some(cursor_of(name_lower, last));
+            }
+            for (name_lower, entry) in batch {
+                if permits(&entry.file_path) {
+                    symbols.push((name_lower, entry));
+                } else {
+                    denied.push((name_lower, entry.handle_id));
+                }
+            }
+            if exhausted {
+                break;
+            }
+            wanted = wanted.saturating_mul(2);
+        }
+
+        let next_after = (symbols.len() > limit && limit > 0).then(|| {
+            let (name_lower, last) = &symbols[limit - 1];
+            cursor_of(name_lower, last)
+        });
+        // Denied entries between the page's end and the one past it are
+        // counted by the next page
+        let suppressed_by_policy = match symbols.get(limit) {
+            Some((name_lower, next)) => denied
+                .iter()
+                .filter(|(name, id)| (name, id.raw()) < (name_lower, next.handle_id.raw()))
+                .count(),
+            None => denied.len(),
+        };
+        symbols.truncate(limit);
+        Ok(SymbolPage {
+            symbols: symbols.into_iter().map(|(_, entry)| entry).collect(),
+            next_after,
+            suppressed_by_policy,
+        })
+    }
+
+    /// The first `count` entries after `after` across every database, keyed
+    /// and ordered by lowercased name, then handle id.
+    fn symbol_batch(
+        &self,
+        prefix: Option<&str>,
+        node_types: Option<&[NodeType]>,
+        after: Option<&str>,
+        count: usize,
+    ) -> crate::Result<Vec<(String, SymbolEntry)>> {
+        let (mut filter, mut params) = prefix_filter(prefix.unwrap_or(""));
+        if let Some((name, handle_id)) = after.map(parse_cursor) {
+            match handle_id {
+                Some(handle_id) => {
+                    filter.push_str(
+                        " AND (n.name_lower > ? OR (n.name_lower = ? AND n.handle_id > ?))",
+                    );
+                    params.extend([
+                        Value::Text(name.clone()),
+                        Value::Text(name),
+                        Value::Text(handle_id.to_string()),
+                    ]);
+                }
+                None => {
+                    filter.push_str(" AND n.name_lower > ?");
+                    params.push(Value::Text(name));
+                }
+            }
+        }
+        if let Some(types) = node_types.filter(|types| !types.is_empty()) {
+            filter.push_str(&format!(
+                " AND n.node_type IN ({})",
+                vec!["?"; types.len()].join(", ")
+            ));
+            params.extend(types.iter().map(|t| Value::Integer(t.as_int().into())));
+        }
+        params.push(Value::Integer(count.min(i64::MAX as usize) as i64));
+
+        let mut symbols = Vec::new();
+        for index in self.all_indexes() {
+            symbols.extend(index.symbol_rows(&filter, params.clone())?);
+        }
+        symbols.sort_by(|a, b| (&a.0, a.1.handle_id.raw()).cmp(&(&b.0, b.1.handle_id.raw())));
+        symbols.truncate(count);
+        Ok(symbols)
+    }
+
+    /// Handles of the named nodes here starting with `prefix`, in name order.
+    pub(crate) fn symbol_handles(&self, prefix: &str, limit: usize) -> crate::Result<Vec<Handle>> {
+        let (filter, mut params) = prefix_filter(prefix);
+        params.push(Value::Integer(limit.min(i64::MAX as usize) as i64));
+        let sql = format!(
+            "SELECT {HANDLE_SELECT}
+             FROM nodes n JOIN files f ON n.file_id = f.id
+             WHERE {filter}{}
+             ORDER BY n.name_lower, n.handle_id
+             LIMIT ?",
+            self.scope_filter()
+        );
+        self.with_statement(&sql, |stmt| {
+            Ok(collect_row_results(
+                stmt.query_map(params_from_iter(params), handle_from_row)?,
+            )?)
+        })
+    }
+
+    /// Entries here matching `filter`, keyed by lowercased name; the last
+    /// parameter is the limit.
+    fn symbol_rows(
+        &self,
+        filter: &str,
+        params: Vec<Value>,
+    ) -> crate::Result<Vec<(String, SymbolEntry)>> {
+        let sql = format!(
+            "SELECT n.name, n.name_lower, n.node_type, f.path, n.handle_id, n.line_start
+             FROM nodes n JOIN files f ON n.file_id = f.id
+             WHERE {filter}{}
+             ORDER BY n.name_lower, n.handle_id
+             LIMIT ?",
+            self.scope_filter()
+        );
+        self.with_statement(&sql, |stmt| {
+            let rows = stmt.query_map(params_from_iter(params), |row| {
+                let node_type: i32 = row.get(2)?;
+                let line_start: i64 = row.get(5)?;
+                Ok((
+                    row.get(1)?,
+                    SymbolEntry {
+                        name: row.get(0)?,
+                        node_type: NodeType::from_int(node_type as u8).unwrap_or(NodeType::Chunk),
+                        file_path: row.get(3)?,
+                        handle_id: HandleId::from_raw(row.get(4)?),
+                        line_start: line_start.max(0) as usize,
+                    },
+                ))
+            })?;
+            Ok(collect_row_results(rows)?)
+        })
+    }
+}
+
+/// The `name_lower` range of names starting with `prefix`, on the `n` alias.
+/// The empty prefix keeps every named node.
+pub(super) fn prefix_filter(prefix: &str) -> (String, Vec<Value>) {
+    let lower = prefix.to_lowercase();
+    match prefix_bound(&lower) {
+        Some(bound) => (
+            "n.name_lower >= ? AND n.name_lower < ?".to_string(),
+            vec![Value::Text(lower), Value::Text(bound)],
+        ),
+        None => ("n.name_lower >= ?".to_string(), vec![Value::Text(lower)]),
+    }
+}
+
+/// The least string above every string starting with the lowercased
+/// `prefix`: its last character that can be incremented, incremented. None
+/// when nothing bounds the range, as for the empty prefix.
+///
+/// `name_lower` compares NOCASE, which folds ASCII capitals, so an
+/// increment landing on one moves past them; stored names have none.
+fn prefix_bound(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        let next = match last {
+            '@'..='Y' => Some('['),
+            '\u{D7FF}' => Some('\u{E000}'),
+            c => char::from_u32(c as u32 + 1),
+        };
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+/// The `next_after` cursor resuming after `entry`, keyed `name_lower`.
+fn cursor_of(name_lower: &str, entry: &SymbolEntry) -> String {
+    format!("{name_lower}:{}", entry.handle_id.raw())
+}
+
+/// The lowercased name and, for a full cursor, the raw handle id of `after`.
+fn parse_cursor(after: &str) -> (String, Option<&str>) {
+    match after.rsplit_once(':') {
+        Some((name, id))
+            if id.len() == HANDLE_ID_LEN && id.chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            (name.to_lowercase(), Some(id))
+        }
+        _ => (after.to_lowercase(), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{QueryMode, QueryParams};
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+    use std::collections::HashSet;
+
+    fn symbol_repo() -> (FixtureRepo, RepoIndex) {
+        let handlers: String = (0..12)
+            .map(|i| format!("pub fn handler_{i:02}() {{}}\npub struct Handler{i:02};\n"))
+            .collect();
+        let repo = FixtureRepoBuilder::new()
+            .file("src/handlers.rs", handlers)
+            .file(
+                "src/german.rs",
+                "pub fn händler() {}\npub fn hänsel() {}\npub struct Hanna;\npub fn hz() {}\npub fn handle() {}\n",
+            )
+            .file("src/dup.rs", "pub fn handle() {}\n")
+            .file("NOTES.md", "# Handling errors\n\nText.\n")
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*").unwrap();
+        (repo, index)
+    }
+
+    fn names(page: &SymbolPage) -> Vec<&str> {
+        page.symbols.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[test]
+    fn pages_cover_every_symbol_once_in_order() {
+        let (_dir, index) = symbol_repo();
+        let everything = index.list_symbols(Some("HAN"), None, None, 1000).unwrap();
+        assert!(everything.next_after.is_none());
+        // 12 handlers and 12 structs, Hanna, two handle() and the heading
+        assert_eq!(everything.symbols.len(), 28);
+
+        for page_size in [1, 2, 5, 7] {
+            let mut seen = Vec::new();
+            let mut after = None;
+            loop {
+                let page = index
+                    .list_symbols(Some("han"), None, after.as_deref(), page_size)
+                    .unwrap();
+                assert!(page.symbols.len() <= page_size);
+                seen.extend(page.symbols);
+                match page.next_after {
+                    Some(next) => after = Some(next),
+                    None => break,
+                }
+            }
+            assert_eq!(seen, everything.symbols, "page size {page_size}");
+        }
+
+        let lowered: Vec<String> = everything
+            .symbols
+            .iter()
+            .map(|s| s.name.to_lowercase())
+            .collect();
+        assert!(lowered.windows(2).all(|w| w[0] <= w[1]));
+        let ids: HashSet<_> = everything.symbols.iter().map(|s| &s.handle_id).collect();
+        assert_eq!(ids.len(), everything.symbols.len());
+
+        // Both handle() definitions list, one per page, without a gap
+        let first = index.list_symbols(Some("handle"), None, None, 1).unwrap();
+        assert_eq!(names(&first), ["handle"]);
+        let second = index
+            .list_symbols(Some("handle"), None, first.next_after.as_deref(), 1)
+            .unwrap();
+        assert_eq!(names(&second), ["handle"]);
+        assert_ne!(first.symbols[0].file_path, second.symbols[0].file_path);
+
+        // A bare name resumes after every entry with that name
+        let after_name = index
+            .list_symbols(Some("han"), None, Some("Handle"), 100)
+            .unwrap();
+        assert!(after_name.symbols.iter().all(|s| s.name != "handle"));
+        assert_eq!(after_name.symbols.len(), 26);
+    }
+
+    #[test]
+    fn multibyte_prefixes_bound_on_characters() {
+        let (_dir, index) = symbol_repo();
+        let umlaut = index.list_symbols(Some("HÄ"), None, None, 100).unwrap();
+        assert_eq!(names(&umlaut), ["händler", "hänsel"]);
+        let whole = index.list_symbols(Some("hän"), None, None, 100).unwrap();
+        assert_eq!(names(&whole), ["händler", "hänsel"]);
+        assert_eq!(
+            names(&index.list_symbols(Some("händ"), None, None, 100).unwrap()),
+            ["händler"]
+        );
+        // "ä" is not "a", and sorts after every ASCII letter
+        let ascii = index.list_symbols(Some("hann"), None, None, 100).unwrap();
+        assert_eq!(names(&ascii), ["Hanna"]);
+        let h = index.list_symbols(Some("h"), None, None, 100).unwrap();
+        assert_eq!(
+            &names(&h)[h.symbols.len() - 3..],
+            ["hz", "händler", "hänsel"]
+        );
+
+        assert_eq!(prefix_bound("hä").as_deref(), Some("hå"));
+        assert_eq!(prefix_bound("a@").as_deref(), Some("a["));
+        assert_eq!(prefix_bound("\u{D7FF}").as_deref(), Some("\u{E000}"));
+        assert_eq!(prefix_bound("a\u{10FFFF}").as_deref(), Some("b"));
+        assert_eq!(prefix_bound(""), None);
+    }
+
+    #[test]
+    fn node_types_and_dsl_narrow_the_listing() {
+        let (_dir, index) = symbol_repo();
+        let structs = index
+            .list_symbols(Some("han"), Some(&[NodeType::Struct]), None, 100)
+            .unwrap();
+        assert_eq!(structs.symbols.len(), 13);
+        assert!(structs
+            .symbols
+            .iter()
+            .all(|s| s.node_type == NodeType::Struct));
+        let sections = index
+            .list_symbols(None, Some(&[NodeType::Section]), None, 100)
+            .unwrap();
+        assert_eq!(names(&sections), ["Handling errors"]);
+        assert_eq!(sections.symbols[0].file_path, "NOTES.md");
+        assert_eq!(sections.symbols[0].line_start, 1);
+
+        let result = index
+            .query_params(QueryParams {
+                dsl: Some("(symbols \"hän\")".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        let previews: Vec<&str> = result.handles.iter().map(|h| h.preview.as_str()).collect();
+        assert_eq!(result.handles.len(), 2, "{previews:?}");
+        assert!(previews[0].contains("händler"));
+
+        let count = index
+            .query_params(QueryParams {
+                dsl: Some("(symbols \"handler_\")".to_string()),
+                mode: QueryMode::Count,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(count.total_matches, 12);
+    }
+}
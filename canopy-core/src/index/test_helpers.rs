@@ -1,26 +1,32 @@
 /// Shared test utilities for the index module.
 use super::RepoIndex;
-use std::fs;
-use std::io::Write;
-use tempfile::TempDir;
+use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+use std::ffi::OsString;
 
 /// Create a test repo with N Rust files containing a function and struct each.
-/// Returns the [`TempDir`] guard (dropping it cleans up the directory).
-pub(crate) fn setup_repo(n: usize) -> TempDir {
-    let dir = TempDir::new().unwrap();
-    let src = dir.path().join("src");
-    fs::create_dir_all(&src).unwrap();
+/// Returns the [`FixtureRepo`] guard (dropping it cleans up the directory).
+pub(crate) fn setup_repo(n: usize) -> FixtureRepo {
+    let repo = FixtureRepoBuilder::new()
+        .numbered_rust_files("src", n)
+        .build();
+    std::fs::create_dir_all(repo.path().join("src")).unwrap();
+    RepoIndex::init(repo.path()).unwrap();
+    repo
+}
 
-    for i in 0..n {
-        let path = src.join(format!("file_{}.rs", i));
-        let mut f = fs::File::create(&path).unwrap();
-        writeln!(
-            f,
-            "fn func_{i}() {{ println!(\"hello from {i}\"); }}\nstruct Struct{i} {{ x: i32 }}",
-        )
-        .unwrap();
+/// A `stem.ext` file name outside plain UTF-8 paths: an invalid byte on Unix,
+/// where such names are constructible, and a non-ASCII name elsewhere.
+pub(crate) fn odd_file_name(stem: &str, ext: &str) -> OsString {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        let mut bytes = stem.as_bytes().to_vec();
+        bytes.push(0xff);
+        bytes.extend_from_slice(format!(".{ext}").as_bytes());
+        OsString::from_vec(bytes)
+    }
+    #[cfg(not(unix))]
+    {
+        OsString::from(format!("{stem}\u{e9}.{ext}"))
     }
-
-    RepoIndex::init(dir.path()).unwrap();
-    dir
 }
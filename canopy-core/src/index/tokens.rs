@@ -0,0 +1,302 @@
+//! FTS token helpers — identifier sub-token expansion and high-frequency term detection.
+//!
+//! `content_fts` / `symbol_fts` use `unicode61 tokenchars '_'`, so identifiers like
+//! `snake_case_name` index as a single token. To keep sub-word search working
+//! (`case name` → `caseName`, `snake_case_name`), each FTS row also carries a
+//! supplementary column of lowercased identifier parts produced here.
+//!
+//! Content over `[indexing] max_fts_bytes_per_node` is indexed as a sample
+//! ([`fts_sample`]) rather than in full.
+
+use std::collections::HashSet;
+
+/// Single-term patterns matching at least this many nodes may trigger a narrowing hint.
+pub(crate) const HIGH_FREQUENCY_MIN_MATCHES: usize = 50;
+
+/// Non-keyword terms must match at least 1/N of all nodes to count as high-frequency.
+pub(crate) const HIGH_FREQUENCY_NODE_FRACTION: usize = 4;
+
+/// A sample spends up to 1/N of its budget on each of the content's head and tail.
+const SAMPLE_EDGE_DIVISOR: usize = 4;
+
+/// Sampled lines longer than this contribute only their new identifiers.
+const SAMPLE_MAX_LINE_BYTES: usize = 256;
+
+/// Shortest word a sample counts as an identifier.
+const SAMPLE_MIN_IDENTIFIER_LEN: usize = 3;
+
+/// Keywords common across the supported languages. These match nearly every node
+/// of their language, so they only need to clear [`HIGH_FREQUENCY_MIN_MATCHES`].
+const LANGUAGE_STOPWORDS: &[&str] = &[
+    // Rust
+    "fn",
+    "impl",
+    "self",
+    "pub",
+    "let",
+    "mut",
+    "use",
+    "mod",
+    "struct",
+    "enum",
+    "match",
+    "crate",
+    "super",
+    "where",
+    "trait",
+    // Python
+    "def",
+    "class",
+    "import",
+    "from",
+    "return",
+    "none",
+    "pass",
+    "elif",
+    "lambda",
+    // JS / TS
+    "function",
+    "const",
+    "var",
+    "this",
+    "export",
+    "async",
+    "await",
+    "new",
+    "interface",
+    "type",
+    // Go
+    "func",
+    "package",
+    "go",
+    "defer",
+    "nil",
+    // Shared control flow and literals
+    "for",
+    "if",
+    "else",
+    "while",
+    "in",
+    "true",
+    "false",
+];
+
+/// Whether `term` is a language keyword that is unlikely to narrow a search.
+pub(crate) fn is_language_stopword(term: &str) -> bool {
+    let lower = term.to_lowercase();
+    LANGUAGE_STOPWORDS.contains(&lower.as_str())
+}
+
+/// Whether a single-term match count should be reported as high-frequency.
+pub(crate) fn is_high_frequency(term: &str, match_count: usize, total_nodes: usize) -> bool {
+    if match_count < HIGH_FREQUENCY_MIN_MATCHES {
+        return false;
+    }
+    is_language_stopword(term) || match_count * HIGH_FREQUENCY_NODE_FRACTION >= total_nodes
+}
+
+/// Lowercased sub-tokens of every compound identifier in `text`, space-separated.
+///
+/// Splits on underscores and camelCase transitions (`HTTPServer` → `http server`).
+/// Simple words contribute nothing since the primary column already indexes them.
+pub(crate) fn identifier_parts(text: &str) -> String {
+    let mut seen = HashSet::new();
+    let mut out = String::new();
+
+    for word in text.split(|c: char| !(c.is_alphanumeric() || c == '_')) {
+        let parts = split_identifier(word);
+        if parts.len() < 2 {
+            continue;
+        }
+        for part in parts {
+            if seen.insert(part.clone()) {
+                if !out.is_empty() {
+                    out.push(' ');
+                }
+                out.push_str(&part);
+            }
+        }
+    }
+
+    out
+}
+
+/// What to full-text index for node content over `max_bytes` (0: no limit),
+/// or None when `content` fits.
+///
+/// Keeps the head and the tail of `content`, and in between each line that
+/// introduces an identifier not seen yet, in order, until the budget runs out.
+/// Matches deep inside a huge node then still find it by its identifiers,
+/// while the repetitive bulk stays out of the index.
+pub(crate) fn fts_sample(content: &str, max_bytes: usize) -> Option<String> {
+    if max_bytes == 0 || content.len() <= max_bytes {
+        return None;
+    }
+    let edge = max_bytes / SAMPLE_EDGE_DIVISOR;
+    // Cut at whitespace so neither edge indexes a partial word
+    let mut head_end = (0..=edge)
+        .rev()
+        .find(|&i| content.is_char_boundary(i))
+        .unwrap_or(0);
+    if let Some(space) = content[..head_end].rfind(char::is_whitespace) {
+        head_end = space;
+    }
+    let mut tail_start = (content.len() - edge..=content.len())
+        .find(|&i| content.is_char_boundary(i))
+        .unwrap_or(content.len());
+    if let Some(space) = content[tail_start..].find(char::is_whitespace) {
+        tail_start += space;
+    }
+    let (head, middle, tail) = (
+        &content[..head_end],
+        &content[head_end..tail_start],
+        &content[tail_start..],
+    );
+
+    let mut seen: HashSet<&str> = sample_identifiers(head)
+        .chain(sample_identifiers(tail))
+        .collect();
+    // One byte is kept for the newline before the tail
+    let mut budget = max_bytes.saturating_sub(head.len() + tail.len() + 1);
+    let mut sample = String::with_capacity(max_bytes);
+    sample.push_str(head);
+    for line in middle.lines() {
+        let mut fresh: Vec<&str> = Vec::new();
+        for identifier in sample_identifiers(line) {
+            if !seen.contains(identifier) && !fresh.contains(&identifier) {
+                fresh.push(identifier);
+            }
+        }
+        if fresh.is_empty() {
+            continue;
+        }
+        let line = line.trim();
+        let text = if line.len() <= SAMPLE_MAX_LINE_BYTES {
+            line.to_string()
+        } else {
+            fresh.join(" ")
+        };
+        if text.len() + 1 > budget {
+            continue;
+        }
+        budget -= text.len() + 1;
+        sample.push('\n');
+        sample.push_str(&text);
+        seen.extend(fresh);
+    }
+    sample.push('\n');
+    sample.push_str(tail);
+    Some(sample)
+}
+
+/// Words of `text` that look like identifiers: starting with a letter or `_`,
+/// at least [`SAMPLE_MIN_IDENTIFIER_LEN`] long.
+fn sample_identifiers(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| {
+            word.len() >= SAMPLE_MIN_IDENTIFIER_LEN
+                && word
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c.is_alphabetic() || c == '_')
+        })
+}
+
+/// Split one identifier into lowercased parts on `_` and case transitions.
+fn split_identifier(word: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+
+    for segment in word.split('_').filter(|s| !s.is_empty()) {
+        let chars: Vec<char> = segment.chars().collect();
+        let mut start = 0;
+        for i in 1..chars.len() {
+            let prev = chars[i - 1];
+            let cur = chars[i];
+            let next_is_lower = chars.get(i + 1).is_some_and(|c| c.is_lowercase());
+            // fooBar → foo|Bar; HTTPServer → HTTP|Server
+            let boundary = (prev.is_lowercase() && cur.is_uppercase())
+                || (prev.is_uppercase() && cur.is_uppercase() && next_is_lower);
+            if boundary {
+                parts.push(chars[start..i].iter().collect::<String>().to_lowercase());
+                start = i;
+            }
+        }
+        parts.push(chars[start..].iter().collect::<String>().to_lowercase());
+    }
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_identifier_handles_camel_and_snake_case() {
+        assert_eq!(split_identifier("caseName"), vec!["case", "name"]);
+        assert_eq!(
+            split_identifier("snake_case_name"),
+            vec!["snake", "case", "name"]
+        );
+        assert_eq!(split_identifier("HTTPServer"), vec!["http", "server"]);
+        assert_eq!(split_identifier("plain"), vec!["plain"]);
+        assert_eq!(split_identifier("__init__"), vec!["init"]);
+    }
+
+    #[test]
+    fn identifier_parts_skips_simple_words_and_dedupes() {
+        let parts = identifier_parts("fn parseConfig(config_path: &str) { parse(config_path) }");
+        assert_eq!(parts, "parse config path");
+        assert_eq!(identifier_parts("hello world"), "");
+    }
+
+    #[test]
+    fn fts_sample_keeps_edges_and_lines_with_new_identifiers() {
+        assert_eq!(fts_sample("short content", 100), None);
+        assert_eq!(fts_sample(&"x ".repeat(1000), 0), None);
+
+        let mut content = String::from("head_marker begins here\n");
+        for i in 0..2000 {
+            content.push_str("repeated filler line\n");
+            if i == 1000 {
+                content.push_str("let deep_needle = 1;\n");
+            }
+        }
+        content.push_str("tail_marker ends here\n");
+
+        let sample = fts_sample(&content, 1024).unwrap();
+        assert!(sample.len() <= 1024, "{} bytes", sample.len());
+        assert!(sample.starts_with("head_marker"));
+        assert!(sample.trim_end().ends_with("tail_marker ends here"));
+        assert!(sample.contains("let deep_needle = 1;"));
+        // The filler repeats words already sampled, so appears only in the edges
+        assert!(sample.matches("repeated filler line").count() < 30);
+    }
+
+    #[test]
+    fn fts_sample_reduces_long_lines_to_their_identifiers() {
+        let long_line = format!(
+            "{} minified_needle {}",
+            "a+b;".repeat(200),
+            "c-d;".repeat(200)
+        );
+        let content = format!("{}\n{long_line}\n{}", "w ".repeat(400), "z ".repeat(400));
+        let sample = fts_sample(&content, 600).unwrap();
+        assert!(sample.contains("\nminified_needle\n"), "{sample}");
+        assert!(!sample.contains("a+b;"));
+    }
+
+    #[test]
+    fn high_frequency_respects_stopwords_and_node_fraction() {
+        assert!(is_language_stopword("Self"));
+        assert!(!is_language_stopword("authenticate"));
+
+        // Below the floor: never high-frequency
+        assert!(!is_high_frequency("self", 10, 20));
+        // Keyword above the floor
+        assert!(is_high_frequency("self", 60, 10_000));
+        // Non-keyword needs to cover a quarter of the nodes
+        assert!(!is_high_frequency("token", 60, 10_000));
+        assert!(is_high_frequency("token", 60, 200));
+    }
+}
@@ -0,0 +1,205 @@
+//! Handle validation: whether handles still match their files, without
+//! reading content back out to the caller.
+
+use rusqlite::params_from_iter;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use super::expand::EXPAND_LOOKUP_CHUNK;
+use super::search::short_content_hash;
+use super::RepoIndex;
+use crate::handle::HandleId;
+
+/// Whether a handle's content is what the index recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HandleStatus {
+    /// The file is unchanged since indexing, so the handle expands to the
+    /// content `content_hash` describes
+    Fresh,
+    /// The file changed (or is gone) since indexing; reindex before trusting
+    /// the handle
+    Stale,
+    /// No indexed node has this id
+    Missing,
+}
+
+/// One handle's [`HandleStatus`], as returned by [`RepoIndex::validate_handles`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HandleValidation {
+    pub handle_id: String,
+    pub status: HandleStatus,
+    /// File holding the node; absent for missing handles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_path: Option<String>,
+    /// [`Handle::content_hash`](crate::Handle::content_hash) as indexed;
+    /// absent for missing handles and rows from before v8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+}
+
+/// (path, path_bytes, node content hash, file content hash) of a handle's node
+type ValidationRow = (String, Option<Vec<u8>>, Option<Vec<u8>>, Vec<u8>);
+
+impl RepoIndex {
+    /// Check `handle_ids` against the index and the files on disk.
+    ///
+    /// Each file is hashed once however many of its handles are asked
+    /// about, and nothing is returned but the status, so this is much
+    /// cheaper than expanding to compare. Results follow the order of
+    /// `handle_ids`; ids that don't parse are [`HandleStatus::Missing`].
+    pub fn validate_handles(&self, handle_ids: &[String]) -> crate::Result<Vec<HandleValidation>> {
+        let parsed: Vec<Option<HandleId>> = handle_ids.iter().map(|id| id.parse().ok()).collect();
+        let raw_ids: Vec<&str> = parsed.iter().flatten().map(|id| id.raw()).collect();
+        let rows = self.find_validation_rows(&raw_ids)?;
+
+        // Whether each file still hashes to what was indexed
+        let mut unchanged: HashMap<&str, bool> = HashMap::new();
+        let mut results = Vec::with_capacity(handle_ids.len());
+        for (handle_id, parsed) in handle_ids.iter().zip(&parsed) {
+            let row = parsed.as_ref().and_then(|id| rows.get(id.raw()));
+            let Some((path, path_bytes, node_hash, file_hash)) = row else {
+                results.push(HandleValidation {
+                    handle_id: handle_id.clone(),
+                    status: HandleStatus::Missing,
+                    file_path: None,
+                    content_hash: None,
+                });
+                continue;
+            };
+            let fresh = match unchanged.get(path.as_str()) {
+                Some(&fresh) => fresh,
+                None => {
+                    let fresh = self
+                        .disk_path(path, path_bytes.as_deref())
+                        .ok()
+                        .and_then(|full_path| std::fs::read(full_path).ok())
+                        .is_some_and(|source| Sha256::digest(&source).as_slice() == file_hash);
+                    *unchanged.entry(path).or_insert(fresh)
+                }
+            };
+            results.push(HandleValidation {
+                handle_id: handle_id.clone(),
+                status: if fresh {
+                    HandleStatus::Fresh
+                } else {
+                    HandleStatus::Stale
+                },
+                file_path: Some(path.clone()),
+                content_hash: node_hash.as_deref().map(short_content_hash),
+            });
+        }
+        Ok(results)
+    }
+
+    /// Validation rows for `raw_ids`, keyed by raw id, from whichever
+    /// databases own them; like `find_handle_rows`, without the span.
+    fn find_validation_rows(
+        &self,
+        raw_ids: &[&str],
+    ) -> crate::Result<HashMap<String, ValidationRow>> {
+        let mut rows: HashMap<String, ValidationRow> = HashMap::new();
+        let mut pending: Vec<&str> = raw_ids.to_vec();
+        pending.sort_unstable();
+        pending.dedup();
+
+        for index in self.all_indexes() {
+            if pending.is_empty() {
+                break;
+            }
+            for chunk in pending.chunks(EXPAND_LOOKUP_CHUNK) {
+                let placeholders = vec!["?"; chunk.len()].join(", ");
+                let mut stmt = index.conn.prepare(&format!(
+                    "SELECT n.handle_id, f.path, f.path_bytes, n.content_hash, f.content_hash
+                     FROM nodes n
+                     JOIN files f ON n.file_id = f.id
+                     WHERE n.handle_id IN ({placeholders})"
+                ))?;
+                let found = stmt.query_map(params_from_iter(chunk.iter()), |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        (row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?),
+                    ))
+                })?;
+                for row in found {
+                    let (id, row) = row?;
+                    rows.entry(id).or_insert(row);
+                }
+            }
+            pending.retain(|id| !rows.contains_key(*id));
+        }
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::QueryParams;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn validation_tracks_edits_and_reindexes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "fn retry() -> u32 { 1 }\n").unwrap();
+        fs::write(root.join("src/other.rs"), "fn other() {}\n").unwrap();
+        RepoIndex::init(root).unwrap();
+        let mut index = RepoIndex::open(root).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let result = index.query_params(QueryParams::symbol("retry")).unwrap();
+        let handle = &result.handles[0];
+        let before = handle
+            .content_hash
+            .clone()
+            .expect("query results carry a hash");
+        let ids = vec![
+            handle.id.to_string(),
+            index.search_code("other", 1).unwrap()[0].id.to_string(),
+            "h000000000000".to_string(),
+            "not a handle".to_string(),
+        ];
+        let statuses = |index: &RepoIndex| -> Vec<HandleStatus> {
+            let validations = index.validate_handles(&ids).unwrap();
+            validations.iter().map(|v| v.status).collect()
+        };
+        assert_eq!(
+            statuses(&index),
+            [
+                HandleStatus::Fresh,
+                HandleStatus::Fresh,
+                HandleStatus::Missing,
+                HandleStatus::Missing
+            ]
+        );
+
+        // Same span, different body: the id survives but the content doesn't
+        let path = root.join("src/lib.rs");
+        fs::write(&path, "fn retry() -> u32 { 2 }\n").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(
+            statuses(&index)[..2],
+            [HandleStatus::Stale, HandleStatus::Fresh]
+        );
+
+        index.index_paths(&[path]).unwrap();
+        let validation = &index.validate_handles(&ids[..1]).unwrap()[0];
+        assert_eq!(validation.status, HandleStatus::Fresh);
+        let after = validation.content_hash.clone().unwrap();
+        assert_ne!(after, before);
+        let result = index.query_params(QueryParams::symbol("retry")).unwrap();
+        assert_eq!(
+            result.handles[0].content_hash.as_deref(),
+            Some(after.as_str())
+        );
+    }
+}
@@ -13,21 +13,48 @@ pub mod git;
 pub mod handle;
 pub mod index;
 pub mod parse;
+pub(crate) mod process;
 pub mod protocol;
 pub mod query;
+pub mod redaction;
+pub mod schema;
 pub mod scoring;
 
-pub use config::Config;
-pub use document::{DocumentNode, NodeMetadata, NodeType, ParsedFile, RefType, Reference, Span};
-pub use error::{CanopyError, ErrorEnvelope};
-pub use generation::{Generation, RepoShard, ShardStatus};
-pub use handle::{Handle, HandleId, HandleSource, RefHandle};
-pub use index::{FileDiscovery, IndexStats, RepoIndex};
+pub use config::{
+    Config, EvidenceConfig, FeedbackConfig, GeneratedConfig, HistoryConfig, Preset, RankingConfig,
+    RedactionConfig, VerifyMode,
+};
+pub use document::{
+    Annotation, DocumentNode, NodeMetadata, NodeType, ParsedFile, RefType, Reference, Span,
+    HEADING_PATH_SEPARATOR,
+};
+pub use error::{CanopyError, ErrorEnvelope, FieldError};
+pub use generation::{Generation, RepoShard, RetainedGeneration, ShardStatus};
+pub use handle::{
+    AnnotationHandle, Handle, HandleId, HandleSource, LineWindow, RefHandle, RefOccurrence,
+};
+pub use index::{
+    show_commit, AppliedMigration, AutoInit, AutoInitReport, ChurningFile, CommitDiff, CommitEntry,
+    DeltaAnchor, DirTokens, DirectorySummary, FileDiscovery, FileMove, FilePage, FileQueryOptions,
+    FileReferences, FileSummary, FtsScan, FtsScanStats, HandleStatus, HandleValidation,
+    IndexPathError, IndexPlan, IndexStats, IndexedNode, InitOptions, LanguageSummary, LargeNode,
+    MoveFixupReport, NodeBreakdown, NodeTypeStats, ParseWarning, PathSet, PathStyle, PlannedSkip,
+    ReferenceFile, RelatedFile, RelatedFiles, RepoIndex, RepoSummary, ScopedStatus, SharedSymbol,
+    SkipCounts, SkipReason, SymbolCacheStats, SymbolDelta, SymbolEntry, SymbolPage,
+    SymbolSuggestion, TokenTree, WalkFilter, WarmupReport, DEFAULT_REFERENCE_FILES_LIMIT,
+    DEFAULT_RELATED_LIMIT, DEFAULT_SUMMARY_TOKENS, DEFAULT_SYMBOL_LIMIT, FILE_DISCOVERY_ENV,
+    MAX_GROUPED_REFERENCES, ROWS_EXAMINED_BUCKETS,
+};
+pub use process::GIT_TIMEOUT_ENV;
 pub use query::{
-    build_evidence_pack, split_terms, EvidenceAction, EvidenceConfidence, EvidenceFileSummary,
-    EvidenceGuidance, EvidenceHandle, EvidencePack, MatchMode, Query, QueryKind, QueryOptions,
-    QueryParams, QueryResult, DEFAULT_EXPAND_BUDGET,
+    apply_reranker, build_evidence_pack, build_evidence_pack_with_priors, split_terms,
+    CalibrationConfig, EvidenceAction, EvidenceConfidence, EvidenceFileSummary, EvidenceGuidance,
+    EvidenceHandle, EvidenceOverflow, EvidencePack, FileSlice, MatchMode, MergeStrategy,
+    PathPredicate, PatternError, Query, QueryExplain, QueryKind, QueryMode, QueryOptions,
+    QueryParams, QueryResult, Reranker, ResultClass, SearchExplain, SearchPath, SourceCounts,
+    TokenSavings, DEFAULT_EXPAND_BUDGET,
 };
+pub use schema::{output_schema, SchemaVersion, OUTPUT_SCHEMA_VERSION, SCHEMA_TYPES};
 
 /// Outcome of an expand operation — supports partial success.
 ///
@@ -37,6 +64,46 @@ pub struct ExpandOutcome {
     pub contents: Vec<(String, String)>,
     /// Handle IDs that could not be expanded.
     pub failed_ids: Vec<String>,
+    /// Compare-mode results, one per entry in `contents`; empty otherwise.
+    pub comparisons: Vec<ExpandComparison>,
+    /// Pinned handles among `failed_ids` whose node disappeared.
+    pub unresolved_pins: Vec<UnresolvedPin>,
+    /// Handle id → why its requested line window was ignored, in which
+    /// case `contents` holds the whole node.
+    pub notes: std::collections::BTreeMap<String, String>,
+}
+
+/// A pinned handle whose file or node is gone, so expand can't follow it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct UnresolvedPin {
+    pub handle_id: String,
+    pub file_path: String,
+    /// What disappeared, e.g. "src/auth.rs was deleted"
+    pub reason: String,
+}
+
+/// How an expanded handle compares to the caller's baseline content.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ExpandDelta {
+    /// The content hash matches the baseline.
+    Unchanged,
+    /// Unified diff from the baseline to the current content.
+    Diff { diff: String },
+    /// No baseline content was available; send the full text.
+    Full,
+}
+
+/// Compare-mode result for one expanded handle.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ExpandComparison {
+    pub handle_id: String,
+    /// sha256 of the current content, to pass as the next baseline.
+    pub content_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub baseline_hash: Option<String>,
+    #[serde(flatten)]
+    pub delta: ExpandDelta,
 }
 
 /// Result type alias for canopy operations
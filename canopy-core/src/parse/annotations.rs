@@ -0,0 +1,133 @@
+//! Marker comment extraction (TODO, FIXME, ...).
+//!
+//! Line-based and language-agnostic: a marker counts when it appears as a whole
+//! word after a comment leader on the same line, so it works for files without
+//! a grammar and for comments that sit outside any parsed node.
+
+use crate::document::Annotation;
+
+/// Tokens that open a line or trailing comment in the supported languages
+const COMMENT_LEADERS: &[&str] = &["//", "/*", "#", "--", "<!--"];
+
+/// Extract one annotation per line for the first marker found in a comment.
+pub(crate) fn extract_annotations(source: &str, markers: &[String]) -> Vec<Annotation> {
+    if markers.is_empty() {
+        return Vec::new();
+    }
+
+    let mut annotations = Vec::new();
+    let mut line_start = 0;
+    for (line_idx, raw_line) in source.split_inclusive('\n').enumerate() {
+        let line = raw_line.trim_end_matches(['\n', '\r']);
+        if let Some((marker, start)) = find_marker(line, markers) {
+            let text = trim_comment_close(&line[start..]);
+            let span_start = line_start + start;
+            annotations.push(Annotation {
+                marker: marker.to_string(),
+                text: text.to_string(),
+                span: span_start..span_start + text.len(),
+                line: line_idx + 1,
+            });
+        }
+        line_start += raw_line.len();
+    }
+    annotations
+}
+
+/// Earliest whole-word marker after the line's first comment leader.
+fn find_marker<'m>(line: &str, markers: &'m [String]) -> Option<(&'m str, usize)> {
+    let comment_start = comment_start(line)?;
+    let comment = &line[comment_start..];
+
+    markers
+        .iter()
+        .filter(|marker| !marker.is_empty())
+        .filter_map(|marker| {
+            comment
+                .match_indices(marker.as_str())
+                .find(|(pos, _)| is_whole_word(comment, *pos, marker.len()))
+                .map(|(pos, _)| (marker.as_str(), comment_start + pos))
+        })
+        .min_by_key(|(_, pos)| *pos)
+}
+
+/// Byte offset where the comment on this line begins, if any.
+fn comment_start(line: &str) -> Option<usize> {
+    // Block comment continuation: ` * TODO ...`
+    let trimmed = line.trim_start();
+    if trimmed.starts_with('*') {
+        return Some(line.len() - trimmed.len());
+    }
+    COMMENT_LEADERS
+        .iter()
+        .filter_map(|leader| line.find(leader))
+        .min()
+}
+
+fn is_whole_word(text: &str, start: usize, len: usize) -> bool {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let before = text[..start].chars().next_back();
+    let after = text[start + len..].chars().next();
+    !before.is_some_and(is_word) && !after.is_some_and(is_word)
+}
+
+fn trim_comment_close(text: &str) -> &str {
+    let text = text.trim_end();
+    text.strip_suffix("*/")
+        .or_else(|| text.strip_suffix("-->"))
+        .unwrap_or(text)
+        .trim_end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn markers() -> Vec<String> {
+        crate::config::Config::default().annotations.markers
+    }
+
+    #[test]
+    fn extracts_markers_from_comment_styles() {
+        let source = "\
+fn main() {} // TODO(auth): check session expiry
+# FIXME: python-style comment
+/* HACK around the tokenizer */
+ * XXX continuation line
+<!-- TODO: docs -->
+let todo_list = TODO_ITEMS; // unrelated
+";
+        let found = extract_annotations(source, &markers());
+        let summary: Vec<(usize, &str, &str)> = found
+            .iter()
+            .map(|a| (a.line, a.marker.as_str(), a.text.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, "TODO", "TODO(auth): check session expiry"),
+                (2, "FIXME", "FIXME: python-style comment"),
+                (3, "HACK", "HACK around the tokenizer"),
+                (4, "XXX", "XXX continuation line"),
+                (5, "TODO", "TODO: docs"),
+            ]
+        );
+        assert_eq!(&source[found[0].span.clone()], found[0].text);
+    }
+
+    #[test]
+    fn ignores_markers_outside_comments_and_partial_words() {
+        let source =
+            "let TODO = 1;\n// TODOS are not TODO-ish? FIXMELATER\nlet x = 1; // NOTE: fine\n";
+        let found = extract_annotations(source, &markers());
+        // "TODO-ish" is a whole-word TODO; "TODOS" and "FIXMELATER" are not
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].line, 2);
+        assert_eq!(found[0].text, "TODO-ish? FIXMELATER");
+
+        let custom = vec!["NOTE".to_string()];
+        let found = extract_annotations(source, &custom);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].marker, "NOTE");
+    }
+}
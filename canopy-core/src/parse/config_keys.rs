@@ -0,0 +1,694 @@
+//! Configuration files (JSON, YAML, TOML), indexed by key path.
+//!
+//! Each key down to `[indexing] config_key_depth` becomes a struct node named
+//! by its dotted path (`server.timeouts.read`) and spanning the key and its
+//! value, so symbol queries find keys by path or by name and expand returns
+//! just that subtree. Nested keys record the enclosing key as their parent.
+//! Files over `config_max_keys` keep their shallowest keys. JSON and TOML are
+//! validated with their parsers first; the scanners here only locate keys.
+
+use crate::config::Config;
+use crate::document::{DocumentNode, NodeMetadata, NodeType, Span};
+
+use super::{fallback_nodes, span_to_line_range, FileType};
+
+/// A key found in a config file.
+struct ConfigKey {
+    /// Key names from the root, joined with `.`
+    path: String,
+    /// 1 for top-level keys
+    depth: usize,
+    /// The key and its value
+    span: Span,
+    /// Index of the enclosing key in the scanned list
+    parent: Option<usize>,
+}
+
+/// Key-path nodes for a JSON, YAML or TOML file, or why it couldn't be parsed.
+/// A file without keys (e.g. a top-level JSON array) is indexed as plain chunks.
+pub(crate) fn parse_config(
+    source: &str,
+    config: &Config,
+    file_type: FileType,
+) -> Result<Vec<DocumentNode>, String> {
+    let keys = match file_type {
+        FileType::Json => scan_json(source)?,
+        FileType::Yaml => scan_yaml(source),
+        FileType::Toml => scan_toml(source)?,
+        _ => Vec::new(),
+    };
+    if keys.is_empty() {
+        return Ok(fallback_nodes(source, config));
+    }
+    Ok(key_nodes(
+        source,
+        &keys,
+        config.indexing.config_key_depth,
+        config.indexing.config_max_keys,
+    ))
+}
+
+/// Nodes for the keys within `max_depth`, shallowest first up to `max_keys`,
+/// in file order.
+fn key_nodes(
+    source: &str,
+    keys: &[ConfigKey],
+    max_depth: usize,
+    max_keys: usize,
+) -> Vec<DocumentNode> {
+    let mut selected: Vec<usize> = (0..keys.len())
+        .filter(|&i| keys[i].depth <= max_depth)
+        .collect();
+    selected.sort_by_key(|&i| (keys[i].depth, keys[i].span.start));
+    selected.truncate(max_keys);
+    selected.sort_by_key(|&i| keys[i].span.start);
+
+    selected
+        .into_iter()
+        .map(|i| {
+            let key = &keys[i];
+            // Selection is by depth, so a selected key's parent is selected too
+            let parent = key.parent.map(|p| &keys[p]);
+            DocumentNode {
+                node_type: NodeType::Struct,
+                span: key.span.clone(),
+                line_range: span_to_line_range(source, &key.span),
+                metadata: NodeMetadata::Struct {
+                    name: key.path.clone(),
+                },
+                parent_name: parent.map(|p| p.path.clone()),
+                parent_handle_id: None,
+                parent_node_type: parent.map(|_| NodeType::Struct),
+                parent_span: parent.map(|p| p.span.clone()),
+                cell: None,
+            }
+        })
+        .collect()
+}
+
+fn join_path(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{prefix}.{key}")
+    }
+}
+
+// ---------------------------------------------------------------------------
+// JSON
+// ---------------------------------------------------------------------------
+
+fn scan_json(source: &str) -> Result<Vec<ConfigKey>, String> {
+    serde_json::from_str::<serde_json::Value>(source)
+        .map_err(|e| format!("invalid JSON: {}", e))?;
+    let mut scanner = JsonScanner {
+        source,
+        pos: 0,
+        keys: Vec::new(),
+    };
+    scanner.skip_whitespace();
+    if scanner.peek() == Some(b'{') {
+        scanner.object("", 1, None);
+    }
+    Ok(scanner.keys)
+}
+
+/// Walks JSON already known to be valid, recording object keys.
+struct JsonScanner<'a> {
+    source: &'a str,
+    pos: usize,
+    keys: Vec<ConfigKey>,
+}
+
+impl JsonScanner<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.source.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// The object at `pos`, whose keys sit at `depth` under `prefix`.
+    fn object(&mut self, prefix: &str, depth: usize, parent: Option<usize>) {
+        self.pos += 1;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b'"') => {}
+                Some(b',') => {
+                    self.pos += 1;
+                    continue;
+                }
+                Some(_) => {
+                    self.pos += 1;
+                    return;
+                }
+                None => return,
+            }
+            let start = self.pos;
+            let key = self.string();
+            self.skip_whitespace();
+            self.pos += 1; // ':'
+            self.skip_whitespace();
+
+            let path = join_path(prefix, &key);
+            let index = self.keys.len();
+            self.keys.push(ConfigKey {
+                path: path.clone(),
+                depth,
+                span: start..start,
+                parent,
+            });
+            if self.peek() == Some(b'{') {
+                self.object(&path, depth + 1, Some(index));
+            } else {
+                self.skip_value();
+            }
+            self.keys[index].span.end = self.pos;
+        }
+    }
+
+    /// The string at `pos`, decoded.
+    fn string(&mut self) -> String {
+        let start = self.pos;
+        self.pos += 1;
+        while let Some(b) = self.peek() {
+            self.pos += if b == b'\\' { 2 } else { 1 };
+            if b == b'"' {
+                break;
+            }
+        }
+        let end = self.pos.min(self.source.len());
+        serde_json::from_str(&self.source[start..end]).unwrap_or_default()
+    }
+
+    fn skip_value(&mut self) {
+        match self.peek() {
+            Some(b'"') => {
+                self.string();
+            }
+            Some(b'{' | b'[') => {
+                let mut depth = 0usize;
+                while let Some(b) = self.peek() {
+                    match b {
+                        b'"' => {
+                            self.string();
+                            continue;
+                        }
+                        b'{' | b'[' => depth += 1,
+                        b'}' | b']' => depth -= 1,
+                        _ => {}
+                    }
+                    self.pos += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            _ => {
+                while self
+                    .peek()
+                    .is_some_and(|b| !matches!(b, b',' | b'}' | b']') && !b.is_ascii_whitespace())
+                {
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// YAML
+// ---------------------------------------------------------------------------
+
+/// Block-style mapping keys, by indentation. Keys inside sequence items,
+/// block scalars and flow collections are left to their enclosing key.
+fn scan_yaml(source: &str) -> Vec<ConfigKey> {
+    let mut keys: Vec<ConfigKey> = Vec::new();
+    // Open keys, outermost first: (indent, index, value starts on a later line)
+    let mut open: Vec<(usize, usize, bool)> = Vec::new();
+    let mut last_content_end = 0;
+    let mut block_scalar_indent: Option<usize> = None;
+    let mut sequence_indent: Option<usize> = None;
+
+    let close = |keys: &mut Vec<ConfigKey>, index: usize, end: usize| {
+        keys[index].span.end = end.max(keys[index].span.start);
+    };
+
+    for (line_start, line) in lines_with_offsets(source) {
+        let text = line.trim_end();
+        let content = text.trim_start();
+        if content.is_empty() || content.starts_with('#') {
+            continue;
+        }
+        let indent = text.len() - content.len();
+        let line_end = line_start + text.len();
+
+        if indent == 0 && (content.starts_with("---") || content.starts_with("...")) {
+            for (_, index, _) in open.drain(..).rev() {
+                close(&mut keys, index, last_content_end);
+            }
+            block_scalar_indent = None;
+            sequence_indent = None;
+            continue;
+        }
+
+        let is_item = content == "-" || content.starts_with("- ");
+        while let Some(&(open_indent, index, nested)) = open.last() {
+            let continues = indent > open_indent || (indent == open_indent && is_item && nested);
+            if continues {
+                break;
+            }
+            close(&mut keys, index, last_content_end);
+            open.pop();
+        }
+        last_content_end = line_end;
+
+        if block_scalar_indent.is_some_and(|b| indent > b) {
+            continue;
+        }
+        block_scalar_indent = None;
+        if sequence_indent.is_some_and(|s| indent > s) {
+            continue;
+        }
+        if is_item {
+            sequence_indent = Some(indent);
+            continue;
+        }
+        sequence_indent = None;
+
+        let Some((key, value)) = yaml_key(content) else {
+            continue;
+        };
+        let parent = open.last().map(|&(_, index, _)| index);
+        let prefix = parent.map_or("", |p| keys[p].path.as_str());
+        let value = value.split(" #").next().unwrap_or("").trim();
+        if value.starts_with('|') || value.starts_with('>') {
+            block_scalar_indent = Some(indent);
+        }
+        open.push((indent, keys.len(), value.is_empty()));
+        keys.push(ConfigKey {
+            path: join_path(prefix, &key),
+            depth: open.len(),
+            span: line_start + indent..line_end,
+            parent,
+        });
+    }
+    for (_, index, _) in open.into_iter().rev() {
+        close(&mut keys, index, last_content_end);
+    }
+    keys
+}
+
+/// `key: value` → (key, value). Quoted keys are unquoted.
+fn yaml_key(content: &str) -> Option<(String, &str)> {
+    let (key, rest) = if let Some(quote @ ('"' | '\'')) = content.chars().next() {
+        let close = content[1..].find(quote)? + 1;
+        (content[1..close].to_string(), &content[close + 1..])
+    } else {
+        let colon = content
+            .match_indices(':')
+            .map(|(i, _)| i)
+            .find(|&i| content[i + 1..].is_empty() || content[i + 1..].starts_with([' ', '\t']))?;
+        let key = content[..colon].trim_end();
+        if key.is_empty() || key.starts_with(['[', '{', '&', '*', '!', '?', '%', '@', '`']) {
+            return None;
+        }
+        (key.to_string(), &content[colon..])
+    };
+    let value = rest.trim_start().strip_prefix(':')?;
+    if !(value.is_empty() || value.starts_with([' ', '\t'])) {
+        return None;
+    }
+    Some((key, value))
+}
+
+// ---------------------------------------------------------------------------
+// TOML
+// ---------------------------------------------------------------------------
+
+/// Table headers and `key = value` lines. A table spans to the next header;
+/// inline tables and arrays stay inside their key.
+fn scan_toml(source: &str) -> Result<Vec<ConfigKey>, String> {
+    source
+        .parse::<toml::Table>()
+        .map_err(|e| format!("invalid TOML: {}", e.message()))?;
+
+    let mut keys: Vec<ConfigKey> = Vec::new();
+    let mut table: Option<usize> = None;
+    let mut table_path = String::new();
+    let mut last_content_end = 0;
+    // Index of the key whose multi-line value is still open, and what closes it
+    let mut pending: Option<(usize, TomlValueState)> = None;
+
+    for (line_start, line) in lines_with_offsets(source) {
+        let text = line.trim_end();
+        let content = text.trim_start();
+        let line_end = line_start + text.len();
+
+        if let Some((index, state)) = pending.take() {
+            let state = state.advance(text);
+            keys[index].span.end = line_end;
+            last_content_end = line_end;
+            if !state.is_closed() {
+                pending = Some((index, state));
+            }
+            continue;
+        }
+        if content.is_empty() || content.starts_with('#') {
+            continue;
+        }
+        let indent = text.len() - content.len();
+
+        if content.starts_with('[') {
+            if let Some(index) = table {
+                keys[index].span.end = last_content_end;
+            }
+            let inner = content.trim_start_matches('[');
+            let close = inner.find(']').unwrap_or(inner.len());
+            let segments = toml_key_segments(&inner[..close]);
+            table_path = segments.join(".");
+            let parent_path = segments[..segments.len().saturating_sub(1)].join(".");
+            let parent = keys
+                .iter()
+                .rposition(|k| k.path == parent_path)
+                .filter(|_| !parent_path.is_empty());
+            table = Some(keys.len());
+            keys.push(ConfigKey {
+                path: table_path.clone(),
+                depth: segments.len(),
+                span: line_start + indent..line_end,
+                parent,
+            });
+            last_content_end = line_end;
+            continue;
+        }
+
+        let Some(eq) = toml_key_end(content) else {
+            continue;
+        };
+        let segments = toml_key_segments(&content[..eq]);
+        let index = keys.len();
+        let base_depth = table.map_or(0, |t| keys[t].depth);
+        keys.push(ConfigKey {
+            path: join_path(&table_path, &segments.join(".")),
+            depth: base_depth + segments.len(),
+            span: line_start + indent..line_end,
+            parent: table.filter(|_| segments.len() == 1),
+        });
+        last_content_end = line_end;
+        let state = TomlValueState::default().advance(&content[eq + 1..]);
+        if !state.is_closed() {
+            pending = Some((index, state));
+        }
+    }
+    if let Some(index) = table {
+        keys[index].span.end = last_content_end;
+    }
+    Ok(keys)
+}
+
+/// Byte offset of the `=` ending a key, outside quotes.
+fn toml_key_end(content: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in content.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None, '=') => return Some(i),
+            (None, '#') => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `a."b.c".d` → `["a", "b.c", "d"]`
+fn toml_key_segments(key: &str) -> Vec<String> {
+    let mut segments = vec![String::new()];
+    let mut quote = None;
+    for c in key.trim().chars() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None, '.') => segments.push(String::new()),
+            (None, c) if c.is_whitespace() => {}
+            (_, c) => segments.last_mut().expect("never empty").push(c),
+        }
+    }
+    segments
+}
+
+/// Whether a TOML value is still open after the lines seen so far.
+#[derive(Default, Clone, Copy)]
+struct TomlValueState {
+    /// Unclosed `[` and `{`
+    brackets: usize,
+    /// Inside a `"""` or `'''` string
+    multiline: Option<char>,
+}
+
+impl TomlValueState {
+    fn is_closed(self) -> bool {
+        self.brackets == 0 && self.multiline.is_none()
+    }
+
+    fn advance(mut self, text: &str) -> Self {
+        let chars: Vec<char> = text.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let triple = |q: char| {
+                chars
+                    .get(i..i + 3)
+                    .is_some_and(|s| s.iter().all(|&x| x == q))
+            };
+            if let Some(q) = self.multiline {
+                if c == '\\' && q == '"' {
+                    i += 2;
+                    continue;
+                }
+                if triple(q) {
+                    self.multiline = None;
+                    i += 3;
+                    continue;
+                }
+                i += 1;
+                continue;
+            }
+            match c {
+                '#' => break,
+                '"' | '\'' if triple(c) => {
+                    self.multiline = Some(c);
+                    i += 3;
+                    continue;
+                }
+                '"' | '\'' => {
+                    i += 1;
+                    while i < chars.len() && chars[i] != c {
+                        i += if c == '"' && chars[i] == '\\' { 2 } else { 1 };
+                    }
+                }
+                '[' | '{' => self.brackets += 1,
+                ']' | '}' => self.brackets = self.brackets.saturating_sub(1),
+                _ => {}
+            }
+            i += 1;
+        }
+        self
+    }
+}
+
+/// Each line of `source` (without its newline) and its starting byte offset.
+fn lines_with_offsets(source: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut offset = 0;
+    source.split_inclusive('\n').map(move |line| {
+        let start = offset;
+        offset += line.len();
+        (start, line.trim_end_matches('\n'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON: &str = include_str!("fixtures/settings.json");
+    const YAML: &str = include_str!("fixtures/settings.yaml");
+    const TOML: &str = include_str!("fixtures/settings.toml");
+
+    fn nodes(source: &str, file_type: FileType) -> Vec<DocumentNode> {
+        parse_config(source, &Config::default(), file_type).unwrap()
+    }
+
+    fn named<'a>(nodes: &'a [DocumentNode], source: &'a str, name: &str) -> &'a str {
+        let node = nodes
+            .iter()
+            .find(|n| n.metadata.searchable_name() == Some(name))
+            .unwrap_or_else(|| panic!("no node named {name}"));
+        &source[node.span.clone()]
+    }
+
+    #[test]
+    fn every_format_yields_the_same_key_paths() {
+        for (source, file_type) in [
+            (JSON, FileType::Json),
+            (YAML, FileType::Yaml),
+            (TOML, FileType::Toml),
+        ] {
+            let nodes = nodes(source, file_type);
+            let names: Vec<&str> = nodes
+                .iter()
+                .filter_map(|n| n.metadata.searchable_name())
+                .collect();
+            for path in [
+                "name",
+                "server",
+                "server.host",
+                "server.timeouts",
+                "server.timeouts.read",
+                "server.timeouts.write",
+                "retry",
+                "retry.attempts",
+            ] {
+                assert!(
+                    names.contains(&path),
+                    "{file_type:?} missing {path}: {names:?}"
+                );
+            }
+            // Depth 3 by default: the backoff's fields stay inside it
+            assert!(
+                !names.contains(&"retry.policy.backoff.initial_ms"),
+                "{file_type:?}"
+            );
+            assert!(nodes.iter().all(|n| n.node_type == NodeType::Struct));
+
+            let read = named(&nodes, source, "server.timeouts.read");
+            assert!(
+                read.contains("read") && read.contains("30"),
+                "{file_type:?}: {read:?}"
+            );
+            assert!(!read.contains("write"), "{file_type:?}: {read:?}");
+
+            let timeouts = named(&nodes, source, "server.timeouts");
+            assert!(timeouts.contains("read") && timeouts.contains("write"));
+            assert!(!timeouts.contains("host"), "{file_type:?}: {timeouts:?}");
+
+            let read_node = nodes
+                .iter()
+                .find(|n| n.metadata.searchable_name() == Some("server.timeouts.read"))
+                .unwrap();
+            assert_eq!(read_node.parent_name.as_deref(), Some("server.timeouts"));
+        }
+    }
+
+    #[test]
+    fn depth_and_key_limits_are_configurable() {
+        let mut config = Config::default();
+        config.indexing.config_key_depth = 1;
+        let top = parse_config(JSON, &config, FileType::Json).unwrap();
+        assert!(top
+            .iter()
+            .all(|n| !n.metadata.searchable_name().unwrap().contains('.')));
+
+        config.indexing.config_key_depth = 10;
+        config.indexing.config_max_keys = 3;
+        let capped = parse_config(YAML, &config, FileType::Yaml).unwrap();
+        assert_eq!(capped.len(), 3);
+        // The shallowest keys are kept
+        assert!(capped.iter().all(|n| n.parent_name.is_none()));
+    }
+
+    #[test]
+    fn yaml_block_scalars_and_sequences_stay_inside_their_key() {
+        let source =
+            "script: |\n  step: one\n  two\nhosts:\n- name: a\n  port: 1\n- name: b\nlast: true\n";
+        let nodes = nodes(source, FileType::Yaml);
+        let names: Vec<&str> = nodes
+            .iter()
+            .filter_map(|n| n.metadata.searchable_name())
+            .collect();
+        assert_eq!(names, ["script", "hosts", "last"]);
+        assert_eq!(
+            named(&nodes, source, "hosts"),
+            "hosts:\n- name: a\n  port: 1\n- name: b"
+        );
+    }
+
+    #[test]
+    fn toml_multiline_values_and_dotted_keys() {
+        let source = "[server]\nports = [\n  80,\n  443,\n]\nlimits.body = 10\n\n[other]\nx = 1\n";
+        let nodes = nodes(source, FileType::Toml);
+        assert_eq!(
+            named(&nodes, source, "server.ports"),
+            "ports = [\n  80,\n  443,\n]"
+        );
+        assert_eq!(
+            named(&nodes, source, "server.limits.body"),
+            "limits.body = 10"
+        );
+        assert_eq!(
+            named(&nodes, source, "server"),
+            "[server]\nports = [\n  80,\n  443,\n]\nlimits.body = 10"
+        );
+    }
+
+    #[test]
+    fn symbol_queries_find_keys_by_leaf_name_and_path() {
+        use crate::query::{execute_query_with_options, QueryParams};
+        use crate::RepoIndex;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("config")).unwrap();
+        std::fs::write(root.join("config/settings.json"), JSON).unwrap();
+        std::fs::write(root.join("config/settings.yaml"), YAML).unwrap();
+        std::fs::write(root.join("config/settings.toml"), TOML).unwrap();
+        RepoIndex::init(root).unwrap();
+        let mut index = RepoIndex::open(root).unwrap();
+        index.index("**/*").unwrap();
+
+        let query = |params: QueryParams| {
+            let query = params.to_query().unwrap();
+            execute_query_with_options(&query, &index, params.to_options()).unwrap()
+        };
+        for symbol in ["server.timeouts.read", "read"] {
+            let result = query(QueryParams::symbol(symbol).with_limit(50));
+            for file in ["settings.json", "settings.yaml", "settings.toml"] {
+                let handle = result
+                    .handles
+                    .iter()
+                    .find(|h| h.file_path.ends_with(file))
+                    .unwrap_or_else(|| panic!("{symbol} not found in {file}"));
+                let expanded = index.expand(&[handle.id.to_string()]).unwrap();
+                let content = &expanded[0].1;
+                assert!(
+                    content.contains("read") && content.contains("30"),
+                    "{content}"
+                );
+                assert!(!content.contains("write"), "{file}: {content}");
+            }
+        }
+
+        // A mapping key expands to its whole subtree
+        let result = query(QueryParams::symbol("timeouts").with_glob("**/*.yaml"));
+        let handle = result.handles.first().expect("timeouts not found");
+        let expanded = index.expand(&[handle.id.to_string()]).unwrap();
+        assert_eq!(
+            expanded[0].1,
+            "timeouts:\n    read: 30   # seconds\n    write: 45"
+        );
+    }
+
+    #[test]
+    fn invalid_or_keyless_files_fall_back() {
+        assert!(parse_config("{\"a\": ", &Config::default(), FileType::Json).is_err());
+        assert!(parse_config("a = = 1", &Config::default(), FileType::Toml).is_err());
+        let array = nodes("[1, 2, 3]\n", FileType::Json);
+        assert_eq!(array.len(), 1);
+        assert_eq!(array[0].node_type, NodeType::Chunk);
+    }
+}
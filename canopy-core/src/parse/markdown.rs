@@ -1,17 +1,23 @@
 //! Markdown parsing using pulldown-cmark.
 
-use crate::document::{DocumentNode, NodeMetadata, NodeType};
+use crate::document::{DocumentNode, NodeMetadata, NodeType, Span, HEADING_PATH_SEPARATOR};
 use pulldown_cmark::{Event, HeadingLevel, Parser, Tag, TagEnd};
 
-use super::span_to_line_range;
+use super::{estimate_tokens, span_to_line_range};
 
-/// Parse markdown file using pulldown-cmark
-pub(crate) fn parse_markdown(source: &str) -> Vec<DocumentNode> {
+/// Parse markdown file using pulldown-cmark.
+///
+/// A section spans its heading and the text up to the next heading of any
+/// level, so a parent's span covers only its own preamble. A section over
+/// `max_section_tokens` (0: no limit) is split with [`split_large_sections`].
+pub(crate) fn parse_markdown(source: &str, max_section_tokens: usize) -> Vec<DocumentNode> {
     let mut nodes = Vec::new();
     let parser = Parser::new(source);
 
     let mut current_section_start: Option<usize> = None;
-    let mut current_heading: Option<(String, u8)> = None;
+    let mut current_heading: Option<(String, u8, String)> = None;
+    // Open headings above the current one, outermost first
+    let mut ancestors: Vec<(u8, String)> = Vec::new();
     let mut in_heading = false;
     let mut heading_text = String::new();
     let mut heading_level = 0u8;
@@ -21,13 +27,30 @@ pub(crate) fn parse_markdown(source: &str) -> Vec<DocumentNode> {
 
     let mut para_start: Option<usize> = None;
 
+    // Where top-level blocks other than headings start: the places an
+    // oversized section can be cut
+    let mut block_starts: Vec<usize> = Vec::new();
+    let mut depth = 0usize;
+
     for (event, range) in parser.into_offset_iter() {
         let offset = range.start;
 
+        match &event {
+            Event::Start(tag) => {
+                if depth == 0 && !matches!(tag, Tag::Heading { .. }) {
+                    block_starts.push(range.start);
+                }
+                depth += 1;
+            }
+            Event::End(_) => depth = depth.saturating_sub(1),
+            Event::Rule if depth == 0 => block_starts.push(range.start),
+            _ => {}
+        }
+
         match event {
             Event::Start(Tag::Heading { level, .. }) => {
                 // End previous section if any
-                if let (Some(start), Some((heading, lvl))) =
+                if let (Some(start), Some((heading, lvl, heading_path))) =
                     (current_section_start, current_heading.take())
                 {
                     let span = start..offset;
@@ -39,11 +62,13 @@ pub(crate) fn parse_markdown(source: &str) -> Vec<DocumentNode> {
                         metadata: NodeMetadata::Section {
                             heading,
                             level: lvl,
+                            heading_path,
                         },
                         parent_name: None,
                         parent_handle_id: None,
                         parent_node_type: None,
                         parent_span: None,
+                        cell: None,
                     });
                 }
 
@@ -54,7 +79,19 @@ pub(crate) fn parse_markdown(source: &str) -> Vec<DocumentNode> {
             Event::End(TagEnd::Heading(_)) => {
                 in_heading = false;
                 current_section_start = Some(range.start);
-                current_heading = Some((heading_text.clone(), heading_level));
+                while ancestors
+                    .last()
+                    .is_some_and(|(level, _)| *level >= heading_level)
+                {
+                    ancestors.pop();
+                }
+                ancestors.push((heading_level, heading_text.clone()));
+                let heading_path = ancestors
+                    .iter()
+                    .map(|(_, heading)| heading.as_str())
+                    .collect::<Vec<_>>()
+                    .join(HEADING_PATH_SEPARATOR);
+                current_heading = Some((heading_text.clone(), heading_level, heading_path));
             }
             Event::Text(text) if in_heading => {
                 heading_text.push_str(&text);
@@ -88,6 +125,7 @@ pub(crate) fn parse_markdown(source: &str) -> Vec<DocumentNode> {
                         parent_handle_id: None,
                         parent_node_type: None,
                         parent_span: None,
+                        cell: None,
                     });
                 }
             }
@@ -107,6 +145,7 @@ pub(crate) fn parse_markdown(source: &str) -> Vec<DocumentNode> {
                         parent_handle_id: None,
                         parent_node_type: None,
                         parent_span: None,
+                        cell: None,
                     });
                 }
             }
@@ -115,22 +154,138 @@ pub(crate) fn parse_markdown(source: &str) -> Vec<DocumentNode> {
     }
 
     // End final section if any
-    if let (Some(start), Some((heading, level))) = (current_section_start, current_heading) {
+    if let (Some(start), Some((heading, level, heading_path))) =
+        (current_section_start, current_heading)
+    {
         let span = start..source.len();
         let line_range = span_to_line_range(source, &span);
         nodes.push(DocumentNode {
             node_type: NodeType::Section,
             span,
             line_range,
-            metadata: NodeMetadata::Section { heading, level },
+            metadata: NodeMetadata::Section {
+                heading,
+                level,
+                heading_path,
+            },
             parent_name: None,
             parent_handle_id: None,
             parent_node_type: None,
             parent_span: None,
+            cell: None,
         });
     }
 
-    nodes
+    if max_section_tokens == 0 {
+        return nodes;
+    }
+    split_large_sections(source, nodes, &block_starts, max_section_tokens)
+}
+
+/// Split each section over `budget` tokens: the section keeps just its
+/// heading, and its text becomes `Heading (part i/n)` sections parented to it,
+/// cut at `block_starts` (or at line breaks within a block over budget on
+/// its own). Parts share the section's heading path, so path lookups return
+/// the section and its parts.
+fn split_large_sections(
+    source: &str,
+    nodes: Vec<DocumentNode>,
+    block_starts: &[usize],
+    budget: usize,
+) -> Vec<DocumentNode> {
+    let mut split = Vec::with_capacity(nodes.len());
+    for mut node in nodes {
+        let NodeMetadata::Section {
+            heading,
+            level,
+            heading_path,
+        } = &node.metadata
+        else {
+            split.push(node);
+            continue;
+        };
+        let span = node.span.clone();
+        let body_start = block_starts
+            .iter()
+            .copied()
+            .find(|&start| start > span.start && start < span.end);
+        let Some(body_start) =
+            body_start.filter(|_| estimate_tokens(&source[span.clone()]) > budget)
+        else {
+            split.push(node);
+            continue;
+        };
+
+        let cuts: Vec<usize> = block_starts
+            .iter()
+            .copied()
+            .filter(|&start| start >= body_start && start < span.end)
+            .chain(std::iter::once(span.end))
+            .collect();
+        let parts = pack_parts(source, &cuts, budget);
+        let (heading, level, heading_path) = (heading.clone(), *level, heading_path.clone());
+        let head = span.start..body_start;
+
+        node.span = head.clone();
+        node.line_range = span_to_line_range(source, &head);
+        split.push(node);
+        let count = parts.len();
+        for (i, part) in parts.into_iter().enumerate() {
+            split.push(DocumentNode {
+                node_type: NodeType::Section,
+                line_range: span_to_line_range(source, &part),
+                span: part,
+                metadata: NodeMetadata::Section {
+                    heading: format!("{} (part {}/{})", heading, i + 1, count),
+                    level,
+                    heading_path: heading_path.clone(),
+                },
+                parent_name: Some(heading.clone()),
+                parent_handle_id: None,
+                parent_node_type: Some(NodeType::Section),
+                parent_span: Some(head.clone()),
+                cell: None,
+            });
+        }
+    }
+    split
+}
+
+/// Group the blocks between consecutive `cuts` into spans of at most
+/// `budget` tokens each, breaking a block that alone exceeds it at lines.
+fn pack_parts(source: &str, cuts: &[usize], budget: usize) -> Vec<Span> {
+    let mut pieces: Vec<(Span, usize)> = Vec::new();
+    for window in cuts.windows(2) {
+        let block = window[0]..window[1];
+        let tokens = estimate_tokens(&source[block.clone()]);
+        if tokens <= budget {
+            pieces.push((block, tokens));
+            continue;
+        }
+        let mut start = block.start;
+        for line in source[block.clone()].split_inclusive('\n') {
+            let end = start + line.len();
+            pieces.push((start..end, estimate_tokens(line)));
+            start = end;
+        }
+    }
+
+    let mut parts: Vec<Span> = Vec::new();
+    let mut current: Option<(Span, usize)> = None;
+    for (piece, tokens) in pieces {
+        current = match current {
+            Some((span, total)) if total + tokens <= budget => {
+                Some((span.start..piece.end, total + tokens))
+            }
+            Some((span, _)) => {
+                parts.push(span);
+                Some((piece, tokens))
+            }
+            None => Some((piece, tokens)),
+        };
+    }
+    parts.extend(current.map(|(span, _)| span));
+    parts
 }
 
 fn heading_level_to_u8(level: HeadingLevel) -> u8 {
@@ -149,10 +304,19 @@ mod tests {
     use super::*;
     use crate::document::{NodeMetadata, NodeType};
 
+    const HANDBOOK: &str = include_str!("fixtures/handbook.md");
+
+    fn heading(node: &DocumentNode) -> &str {
+        match &node.metadata {
+            NodeMetadata::Section { heading, .. } => heading,
+            _ => panic!("Expected Section metadata"),
+        }
+    }
+
     #[test]
     fn parse_sections_with_headings() {
         let md = "# Introduction\n\nSome intro text.\n\n## Details\n\nMore details here.\n";
-        let nodes = parse_markdown(md);
+        let nodes = parse_markdown(md, 0);
 
         // Should have: paragraph, section (Introduction), paragraph, section (Details)
         let sections: Vec<_> = nodes
@@ -163,7 +327,7 @@ mod tests {
 
         // First section: Introduction (ends when Details heading starts)
         match &sections[0].metadata {
-            NodeMetadata::Section { heading, level } => {
+            NodeMetadata::Section { heading, level, .. } => {
                 assert_eq!(heading, "Introduction");
                 assert_eq!(*level, 1);
             }
@@ -172,9 +336,14 @@ mod tests {
 
         // Second section: Details (extends to end of document)
         match &sections[1].metadata {
-            NodeMetadata::Section { heading, level } => {
+            NodeMetadata::Section {
+                heading,
+                level,
+                heading_path,
+            } => {
                 assert_eq!(heading, "Details");
                 assert_eq!(*level, 2);
+                assert_eq!(heading_path, "Introduction > Details");
             }
             _ => panic!("Expected Section metadata"),
         }
@@ -183,7 +352,7 @@ mod tests {
     #[test]
     fn parse_fenced_code_blocks() {
         let md = "# Example\n\n```rust\nfn main() {}\n```\n\n```\nplain code\n```\n";
-        let nodes = parse_markdown(md);
+        let nodes = parse_markdown(md, 0);
 
         let code_blocks: Vec<_> = nodes
             .iter()
@@ -210,14 +379,14 @@ mod tests {
 
     #[test]
     fn parse_empty_input() {
-        let nodes = parse_markdown("");
+        let nodes = parse_markdown("", 0);
         assert!(nodes.is_empty());
     }
 
     #[test]
     fn parse_paragraphs_and_line_ranges() {
         let md = "First paragraph.\n\nSecond paragraph.\n";
-        let nodes = parse_markdown(md);
+        let nodes = parse_markdown(md, 0);
 
         let paragraphs: Vec<_> = nodes
             .iter()
@@ -233,4 +402,95 @@ mod tests {
         assert!(matches!(paragraphs[0].metadata, NodeMetadata::Paragraph));
         assert!(matches!(paragraphs[1].metadata, NodeMetadata::Paragraph));
     }
+
+    #[test]
+    fn heading_paths_follow_nesting() {
+        let md = "# Deployment\n## Services\n### Auth\n#### Configuration\n\
+                  ### Billing\n#### Configuration\n# Local\n## Configuration\n";
+        let paths: Vec<String> = parse_markdown(md, 0)
+            .iter()
+            .filter_map(|n| n.metadata.heading_path().map(String::from))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "Deployment",
+                "Deployment > Services",
+                "Deployment > Services > Auth",
+                "Deployment > Services > Auth > Configuration",
+                "Deployment > Services > Billing",
+                "Deployment > Services > Billing > Configuration",
+                "Local",
+                "Local > Configuration",
+            ]
+        );
+    }
+
+    #[test]
+    fn oversized_sections_split_into_budgeted_parts() {
+        let budget = 150;
+        let nodes = parse_markdown(HANDBOOK, budget);
+        let sections: Vec<_> = nodes
+            .iter()
+            .filter(|n| n.node_type == NodeType::Section)
+            .collect();
+        for section in &sections {
+            let tokens = estimate_tokens(&HANDBOOK[section.span.clone()]);
+            assert!(tokens <= budget, "{} has {tokens} tokens", heading(section));
+        }
+
+        // The long leaf section keeps its heading and hands its text to parts
+        let accounts = sections
+            .iter()
+            .position(|n| heading(n) == "Accounts")
+            .unwrap();
+        assert_eq!(
+            HANDBOOK[sections[accounts].span.clone()].trim(),
+            "### Accounts"
+        );
+        let parts: Vec<_> = sections[accounts + 1..]
+            .iter()
+            .take_while(|n| n.parent_name.as_deref() == Some("Accounts"))
+            .collect();
+        assert!(parts.len() > 1);
+        let count = parts.len();
+        for (i, part) in parts.iter().enumerate() {
+            assert_eq!(heading(part), format!("Accounts (part {}/{count})", i + 1));
+            assert_eq!(part.parent_node_type, Some(NodeType::Section));
+            assert_eq!(part.parent_span, Some(sections[accounts].span.clone()));
+            assert_eq!(
+                part.metadata.heading_path(),
+                Some("Handbook > Onboarding > Accounts")
+            );
+        }
+        // Parts cover the section's text without gaps, at paragraph boundaries
+        assert_eq!(parts[0].span.start, sections[accounts].span.end);
+        for pair in parts.windows(2) {
+            assert_eq!(pair[0].span.end, pair[1].span.start);
+            assert!(HANDBOOK[pair[0].span.clone()].ends_with("\n\n"));
+        }
+        assert!(HANDBOOK[parts[count - 1].span.clone()].contains("security channel"));
+
+        // Sections under budget are untouched; a parent spans only its preamble
+        let engineering = sections
+            .iter()
+            .find(|n| heading(n) == "Engineering")
+            .unwrap();
+        assert_eq!(
+            HANDBOOK[engineering.span.clone()].trim(),
+            "## Engineering\n\nHow we build and run software."
+        );
+        let on_call_parts = sections
+            .iter()
+            .filter(|n| n.parent_name.as_deref() == Some("On-call"))
+            .count();
+        assert!(on_call_parts > 1);
+        assert_eq!(
+            parse_markdown(HANDBOOK, 0)
+                .iter()
+                .filter(|n| n.node_type == NodeType::Section)
+                .count(),
+            sections.len() - count - on_call_parts
+        );
+    }
 }
@@ -5,16 +5,22 @@
 //! - `markdown` — Markdown parsing via pulldown-cmark
 //! - `tree_sitter_parse` — Tree-sitter code parsing and per-language classifiers
 //! - `references` — Reference extraction (calls, imports) from AST nodes
+//! - `annotations` — TODO/FIXME-style marker comments
+//! - `notebook` — Jupyter notebooks, indexed by cell
+//! - `config_keys` — JSON, YAML and TOML, indexed by key path
 
+mod annotations;
 mod bpe;
+mod config_keys;
 mod markdown;
+mod notebook;
 pub(crate) mod references;
 pub(crate) mod tree_sitter_parse;
 
 pub use bpe::{estimate_tokens, warm_bpe};
 
 use crate::config::Config;
-use crate::document::{DocumentNode, NodeMetadata, NodeType, ParsedFile, Span};
+use crate::document::{DocumentNode, NodeMetadata, NodeType, ParsedFile, Reference, Span};
 use sha2::{Digest, Sha256};
 use std::path::Path;
 use std::time::UNIX_EPOCH;
@@ -28,6 +34,10 @@ pub enum FileType {
     JavaScript,
     TypeScript,
     Go,
+    Notebook,
+    Json,
+    Yaml,
+    Toml,
     Other,
 }
 
@@ -40,6 +50,10 @@ impl FileType {
             Some("js" | "jsx" | "mjs" | "cjs") => Self::JavaScript,
             Some("ts" | "tsx" | "mts" | "cts") => Self::TypeScript,
             Some("go") => Self::Go,
+            Some("ipynb") => Self::Notebook,
+            Some("json") => Self::Json,
+            Some("yaml" | "yml") => Self::Yaml,
+            Some("toml") => Self::Toml,
             _ => Self::Other,
         }
     }
@@ -48,6 +62,11 @@ impl FileType {
         matches!(self, Self::Markdown)
     }
 
+    /// JSON, YAML or TOML, indexed by key path
+    pub fn is_config(self) -> bool {
+        matches!(self, Self::Json | Self::Yaml | Self::Toml)
+    }
+
     pub fn has_tree_sitter_grammar(self) -> bool {
         matches!(
             self,
@@ -79,26 +98,21 @@ pub fn parse_file_with_hash(
     mtime: i64,
 ) -> ParsedFile {
     let file_type = FileType::from_path(path);
+    if file_type == FileType::Notebook {
+        return notebook::parse_notebook(path, source, config, content_hash, mtime);
+    }
 
-    let (nodes, refs) = if file_type.is_markdown() {
-        (markdown::parse_markdown(source), Vec::new())
-    } else if file_type.has_tree_sitter_grammar() {
-        tree_sitter_parse::parse_code_with_tree_sitter(path, source, file_type)
-    } else if source.len() > config.indexing.chunk_threshold {
-        // Large file without grammar: chunk
-        (
-            parse_as_chunks(
-                source,
-                config.indexing.chunk_lines,
-                config.indexing.chunk_overlap,
-            ),
-            Vec::new(),
-        )
-    } else {
-        // Small file without grammar: single node
-        (parse_as_single_node(source), Vec::new())
+    // A file that can't be parsed (or crashes its grammar) is still indexed for
+    // text search, as plain chunks, with the reason kept as a warning
+    let (nodes, refs, parse_warning) = match guard_panics(|| {
+        parse_nodes(path, source, config, file_type)
+    }) {
+        Ok(Ok((nodes, refs))) => (nodes, refs, None),
+        Ok(Err(reason)) | Err(reason) => (fallback_nodes(source, config), Vec::new(), Some(reason)),
     };
 
+    let annotations = annotations::extract_annotations(source, &config.annotations.markers);
+
     // Compute total tokens
     let total_tokens = estimate_tokens(source);
 
@@ -108,11 +122,73 @@ pub fn parse_file_with_hash(
         content_hash,
         nodes,
         refs,
+        annotations,
         total_tokens,
         mtime,
+        parse_warning,
+        generated: false,
     }
 }
 
+/// The text node spans index into, given a file's contents: the cell layout
+/// for a notebook that parses, else `source` itself. Expand uses this to
+/// rebuild what was indexed from the file on disk.
+pub fn indexed_source(path: &Path, source: String) -> String {
+    if FileType::from_path(path) != FileType::Notebook {
+        return source;
+    }
+    notebook::Notebook::parse(&source).map_or(source, |notebook| notebook.source)
+}
+
+/// Structural nodes and references for `source`, or why it couldn't be parsed.
+fn parse_nodes(
+    path: &Path,
+    source: &str,
+    config: &Config,
+    file_type: FileType,
+) -> Result<(Vec<DocumentNode>, Vec<Reference>), String> {
+    if file_type.is_markdown() {
+        Ok((
+            markdown::parse_markdown(source, config.indexing.max_section_tokens),
+            Vec::new(),
+        ))
+    } else if file_type.has_tree_sitter_grammar() {
+        tree_sitter_parse::parse_code_with_tree_sitter(path, source, file_type)
+    } else if file_type.is_config() {
+        Ok((
+            config_keys::parse_config(source, config, file_type)?,
+            Vec::new(),
+        ))
+    } else {
+        Ok((fallback_nodes(source, config), Vec::new()))
+    }
+}
+
+/// Nodes for a file without structure: line chunks when large, else one node.
+pub(crate) fn fallback_nodes(source: &str, config: &Config) -> Vec<DocumentNode> {
+    if source.len() > config.indexing.chunk_threshold {
+        parse_as_chunks(
+            source,
+            config.indexing.chunk_lines,
+            config.indexing.chunk_overlap,
+        )
+    } else {
+        parse_as_single_node(source)
+    }
+}
+
+/// Run `f`, turning a panic into `Err` with its message.
+pub(crate) fn guard_panics<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).map_err(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        format!("parser panicked: {}", message)
+    })
+}
+
 /// Get file mtime as seconds since UNIX epoch (0 on error)
 pub fn file_mtime(path: &Path) -> i64 {
     std::fs::metadata(path)
@@ -160,6 +236,7 @@ fn parse_as_chunks(source: &str, chunk_lines: usize, chunk_overlap: usize) -> Ve
             parent_handle_id: None,
             parent_node_type: None,
             parent_span: None,
+            cell: None,
         });
 
         chunk_index += 1;
@@ -186,6 +263,7 @@ fn parse_as_single_node(source: &str) -> Vec<DocumentNode> {
         parent_handle_id: None,
         parent_node_type: None,
         parent_span: None,
+        cell: None,
     }]
 }
 
@@ -224,7 +302,7 @@ fn main() {}
 
 More text.
 "#;
-        let nodes = markdown::parse_markdown(source);
+        let nodes = markdown::parse_markdown(source, 0);
 
         // Should have sections, paragraphs, and code blocks
         assert!(nodes
@@ -259,7 +337,8 @@ impl Foo {
             Path::new("test.rs"),
             source,
             FileType::Rust,
-        );
+        )
+        .unwrap();
 
         // Should have function and struct
         assert!(nodes
@@ -270,6 +349,29 @@ impl Foo {
             .any(|n| matches!(n.node_type, NodeType::Struct)));
     }
 
+    #[test]
+    fn unparseable_code_falls_back_to_a_chunk_with_warning() {
+        let config = Config::default();
+        let parsed = parse_file(Path::new("bad.py"), "def f(:\n    return\n", &config);
+        assert_eq!(parsed.nodes.len(), 1);
+        assert_eq!(parsed.nodes[0].node_type, NodeType::Chunk);
+        assert!(parsed.refs.is_empty());
+        assert!(parsed
+            .parse_warning
+            .as_deref()
+            .is_some_and(|w| w.starts_with("syntax error")));
+
+        let clean = parse_file(Path::new("ok.py"), "def f():\n    return 1\n", &config);
+        assert!(clean.parse_warning.is_none());
+    }
+
+    #[test]
+    fn guard_panics_reports_the_message() {
+        assert_eq!(guard_panics(|| 7), Ok(7));
+        let err = guard_panics(|| -> usize { panic!("grammar blew up") }).unwrap_err();
+        assert_eq!(err, "parser panicked: grammar blew up");
+    }
+
     #[test]
     fn test_parse_chunks() {
         let source = (0..100)
@@ -301,6 +403,11 @@ impl Foo {
             FileType::JavaScript
         );
         assert_eq!(FileType::from_path(Path::new("main.go")), FileType::Go);
+        assert_eq!(
+            FileType::from_path(Path::new("deploy/values.yml")),
+            FileType::Yaml
+        );
+        assert_eq!(FileType::from_path(Path::new("Cargo.toml")), FileType::Toml);
         assert_eq!(FileType::from_path(Path::new("data.csv")), FileType::Other);
     }
 
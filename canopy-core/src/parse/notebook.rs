@@ -0,0 +1,505 @@
+//! Jupyter notebooks (`.ipynb`).
+//!
+//! A notebook is JSON, so indexing the file as text gives escaped previews
+//! and no symbols. Instead its code and markdown cells are laid out one after
+//! another as a virtual source that node spans point into. Code cells are
+//! parsed with the kernel's language grammar, markdown cells become sections,
+//! and each node records its `cell` with a cell-relative `line_range`.
+//! Expand rebuilds the same layout from the file (`indexed_source`), so it
+//! returns cell source rather than JSON.
+
+use crate::config::Config;
+use crate::document::{
+    Annotation, DocumentNode, NodeMetadata, NodeType, ParsedFile, Reference, Span,
+    HEADING_PATH_SEPARATOR,
+};
+use serde_json::Value;
+use std::path::Path;
+
+use super::annotations::extract_annotations;
+use super::tree_sitter_parse::parse_code_with_tree_sitter;
+use super::{estimate_tokens, fallback_nodes, guard_panics, FileType};
+
+/// Longest heading taken from the first line of a cell without one
+const MAX_CELL_TITLE: usize = 60;
+
+/// A notebook's indexed text and where each cell sits in it.
+pub(crate) struct Notebook {
+    /// Code and markdown cells in order, each on its own lines, separated by
+    /// a blank line
+    pub(crate) source: String,
+    cells: Vec<Cell>,
+    /// Kernel language, lowercased (`python`, `rust`, ...)
+    language: Option<String>,
+}
+
+struct Cell {
+    /// Index into the notebook's `cells`, counting raw and empty cells
+    index: usize,
+    kind: CellKind,
+    /// The cell's source within [`Notebook::source`]
+    span: Span,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CellKind {
+    Code,
+    Markdown,
+}
+
+impl Notebook {
+    /// Lay out the cells of notebook JSON, or say why it isn't a notebook.
+    pub(crate) fn parse(raw: &str) -> Result<Self, String> {
+        let json: Value =
+            serde_json::from_str(raw).map_err(|e| format!("invalid notebook JSON: {}", e))?;
+        let cells = json
+            .get("cells")
+            .and_then(Value::as_array)
+            .ok_or_else(|| "notebook has no cells array (nbformat 4 expected)".to_string())?;
+
+        let mut source = String::new();
+        let mut laid_out = Vec::new();
+        for (index, cell) in cells.iter().enumerate() {
+            let kind = match cell.get("cell_type").and_then(Value::as_str) {
+                Some("code") => CellKind::Code,
+                Some("markdown") => CellKind::Markdown,
+                _ => continue,
+            };
+            let text = cell_source(cell);
+            if text.trim().is_empty() {
+                continue;
+            }
+            if !source.is_empty() {
+                source.push('\n');
+            }
+            let start = source.len();
+            source.push_str(&text);
+            laid_out.push(Cell {
+                index,
+                kind,
+                span: start..source.len(),
+            });
+            if !text.ends_with('\n') {
+                source.push('\n');
+            }
+        }
+
+        Ok(Self {
+            source,
+            cells: laid_out,
+            language: kernel_language(json.get("metadata")),
+        })
+    }
+
+    fn cell_text(&self, cell: &Cell) -> &str {
+        &self.source[cell.span.clone()]
+    }
+
+    /// Grammar for the code cells, when the kernel language has one.
+    fn code_file_type(&self) -> Option<FileType> {
+        let file_type = match self.language.as_deref()? {
+            "python" => FileType::Python,
+            "rust" => FileType::Rust,
+            "javascript" => FileType::JavaScript,
+            "typescript" => FileType::TypeScript,
+            "go" => FileType::Go,
+            _ => return None,
+        };
+        Some(file_type)
+    }
+
+    /// Nodes and references for every cell, spans into [`Self::source`].
+    fn nodes(&self, path: &Path) -> (Vec<DocumentNode>, Vec<Reference>) {
+        let mut nodes = Vec::new();
+        let mut refs = Vec::new();
+        // Headings of earlier markdown cells still open, outermost first
+        let mut ancestors: Vec<(u8, String)> = Vec::new();
+
+        for cell in &self.cells {
+            match cell.kind {
+                CellKind::Code => self.code_cell(path, cell, &mut nodes, &mut refs),
+                CellKind::Markdown => nodes.push(self.markdown_cell(cell, &mut ancestors)),
+            }
+        }
+        (nodes, refs)
+    }
+
+    /// A code block for the whole cell (so top-level statements are
+    /// searchable), plus the definitions the grammar finds in it.
+    fn code_cell(
+        &self,
+        path: &Path,
+        cell: &Cell,
+        nodes: &mut Vec<DocumentNode>,
+        refs: &mut Vec<Reference>,
+    ) {
+        let text = self.cell_text(cell);
+        nodes.push(cell_node(
+            cell,
+            text,
+            NodeType::CodeBlock,
+            NodeMetadata::CodeBlock {
+                language: self.language.clone(),
+            },
+        ));
+
+        let Some(file_type) = self.code_file_type() else {
+            return;
+        };
+        let code = if file_type == FileType::Python {
+            mask_ipython_magics(text)
+        } else {
+            text.to_string()
+        };
+        // A cell that doesn't parse keeps just its code block
+        let Ok((cell_nodes, cell_refs)) = parse_code_with_tree_sitter(path, &code, file_type)
+        else {
+            return;
+        };
+        let offset = cell.span.start;
+        for mut node in cell_nodes {
+            // The grammar's whole-source fallback duplicates the code block
+            if node.node_type == NodeType::Chunk {
+                continue;
+            }
+            node.span = shift(&node.span, offset);
+            node.parent_span = node.parent_span.map(|span| shift(&span, offset));
+            node.cell = Some(cell.index);
+            nodes.push(node);
+        }
+        refs.extend(cell_refs.into_iter().map(|mut r| {
+            r.span = shift(&r.span, offset);
+            r
+        }));
+    }
+
+    /// The whole cell as one section, titled by its first heading (or its
+    /// first line) and nested under the headings of earlier cells.
+    fn markdown_cell(&self, cell: &Cell, ancestors: &mut Vec<(u8, String)>) -> DocumentNode {
+        let text = self.cell_text(cell);
+        let (heading, level) = match first_heading(text) {
+            Some((level, heading)) => {
+                while ancestors.last().is_some_and(|(l, _)| *l >= level) {
+                    ancestors.pop();
+                }
+                (heading, level)
+            }
+            None => {
+                let level = ancestors.last().map_or(1, |(l, _)| (l + 1).min(6));
+                (cell_title(text), level)
+            }
+        };
+        let heading_path = ancestors
+            .iter()
+            .map(|(_, h)| h.as_str())
+            .chain(std::iter::once(heading.as_str()))
+            .collect::<Vec<_>>()
+            .join(HEADING_PATH_SEPARATOR);
+        if first_heading(text).is_some() {
+            ancestors.push((level, heading.clone()));
+        }
+        cell_node(
+            cell,
+            text,
+            NodeType::Section,
+            NodeMetadata::Section {
+                heading,
+                level,
+                heading_path,
+            },
+        )
+    }
+
+    /// Marker comments per cell, with cell-relative lines.
+    fn annotations(&self, markers: &[String]) -> Vec<Annotation> {
+        self.cells
+            .iter()
+            .flat_map(|cell| {
+                extract_annotations(self.cell_text(cell), markers)
+                    .into_iter()
+                    .map(|mut a| {
+                        a.span = shift(&a.span, cell.span.start);
+                        a
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Parse a notebook file. JSON that isn't a notebook is indexed as plain
+/// text, with the reason kept as a parse warning.
+pub(super) fn parse_notebook(
+    path: &Path,
+    raw: &str,
+    config: &Config,
+    content_hash: [u8; 32],
+    mtime: i64,
+) -> ParsedFile {
+    let parsed = Notebook::parse(raw).and_then(|notebook| {
+        let (nodes, refs) = guard_panics(|| notebook.nodes(path))?;
+        let annotations = notebook.annotations(&config.annotations.markers);
+        Ok((notebook.source, nodes, refs, annotations))
+    });
+    let (source, nodes, refs, annotations, parse_warning) = match parsed {
+        Ok((source, nodes, refs, annotations)) => (source, nodes, refs, annotations, None),
+        Err(reason) => (
+            raw.to_string(),
+            fallback_nodes(raw, config),
+            Vec::new(),
+            extract_annotations(raw, &config.annotations.markers),
+            Some(reason),
+        ),
+    };
+
+    ParsedFile {
+        path: path.to_path_buf(),
+        total_tokens: estimate_tokens(&source),
+        source,
+        content_hash,
+        nodes,
+        refs,
+        annotations,
+        mtime,
+        parse_warning,
+        generated: false,
+    }
+}
+
+/// A node spanning a whole cell.
+fn cell_node(cell: &Cell, text: &str, node_type: NodeType, metadata: NodeMetadata) -> DocumentNode {
+    DocumentNode {
+        node_type,
+        span: cell.span.clone(),
+        line_range: (1, text.lines().count().max(1)),
+        metadata,
+        parent_name: None,
+        parent_handle_id: None,
+        parent_node_type: None,
+        parent_span: None,
+        cell: Some(cell.index),
+    }
+}
+
+/// A cell's `source`: one string, or a list of lines to concatenate.
+fn cell_source(cell: &Value) -> String {
+    match cell.get("source") {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(lines)) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+/// `metadata.kernelspec.language`, else `metadata.language_info.name`.
+fn kernel_language(metadata: Option<&Value>) -> Option<String> {
+    let metadata = metadata?;
+    metadata
+        .pointer("/kernelspec/language")
+        .or_else(|| metadata.pointer("/language_info/name"))
+        .and_then(Value::as_str)
+        .map(str::to_lowercase)
+}
+
+/// Blank out IPython magics and shell escapes (`%`, `%%`, `!`) byte for byte,
+/// so the Python grammar accepts the cell and spans still line up.
+fn mask_ipython_magics(text: &str) -> String {
+    text.split_inclusive('\n')
+        .map(|line| {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with('%') || trimmed.starts_with('!') {
+                let body = line.trim_end_matches(['\n', '\r']);
+                format!("{}{}", " ".repeat(body.len()), &line[body.len()..])
+            } else {
+                line.to_string()
+            }
+        })
+        .collect()
+}
+
+/// The first ATX heading in markdown text, as (level, text).
+fn first_heading(text: &str) -> Option<(u8, String)> {
+    text.lines().find_map(|line| {
+        let line = line.trim_start();
+        let level = line.bytes().take_while(|b| *b == b'#').count();
+        let rest = &line[level..];
+        if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+            return None;
+        }
+        let heading = rest.trim().trim_end_matches('#').trim();
+        (!heading.is_empty()).then(|| (level as u8, heading.to_string()))
+    })
+}
+
+/// First non-empty line of a cell, cut to [`MAX_CELL_TITLE`] bytes.
+fn cell_title(text: &str) -> String {
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .unwrap_or("");
+    let mut end = line.len().min(MAX_CELL_TITLE);
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    line[..end].to_string()
+}
+
+fn shift(span: &Span, offset: usize) -> Span {
+    span.start + offset..span.end + offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::RepoIndex;
+    use crate::CanopyError;
+
+    const FIXTURE: &str = include_str!("fixtures/analysis.ipynb");
+
+    fn parsed_fixture() -> ParsedFile {
+        let config = Config::default();
+        parse_notebook(
+            Path::new("notebooks/analysis.ipynb"),
+            FIXTURE,
+            &config,
+            [0; 32],
+            0,
+        )
+    }
+
+    fn named<'a>(parsed: &'a ParsedFile, name: &str) -> &'a DocumentNode {
+        parsed
+            .nodes
+            .iter()
+            .find(|n| n.metadata.searchable_name() == Some(name))
+            .unwrap_or_else(|| panic!("no node named {name}"))
+    }
+
+    #[test]
+    fn cells_are_laid_out_as_source_not_json() {
+        let parsed = parsed_fixture();
+        assert!(parsed.parse_warning.is_none());
+        assert!(parsed.source.starts_with("# Sales analysis\n\nLoads"));
+        assert!(parsed
+            .source
+            .contains("def load_data(path):\n    \"\"\"Read"));
+        assert!(!parsed.source.contains("\"cell_type\""));
+        assert!(
+            !parsed.source.contains("loaded 1200 rows"),
+            "outputs skipped"
+        );
+        assert!(!parsed.source.contains("not indexed"), "raw cells skipped");
+    }
+
+    #[test]
+    fn code_cells_yield_definitions_with_cell_relative_lines() {
+        let parsed = parsed_fixture();
+
+        let load = named(&parsed, "load_data");
+        assert_eq!(load.node_type, NodeType::Function);
+        assert_eq!(load.cell, Some(2));
+        assert_eq!(load.line_range, (1, 3));
+        assert!(parsed.source[load.span.clone()].starts_with("def load_data"));
+
+        let model = named(&parsed, "TrendModel");
+        assert_eq!(model.node_type, NodeType::Class);
+        assert_eq!(model.cell, Some(4));
+        let fit = named(&parsed, "fit");
+        assert_eq!(fit.node_type, NodeType::Method);
+        assert_eq!(fit.line_range, (3, 5));
+        assert_eq!(fit.parent_span, Some(model.span.clone()));
+
+        // The magic line doesn't stop the import cell from parsing
+        let blocks: Vec<_> = parsed
+            .nodes
+            .iter()
+            .filter(|n| n.node_type == NodeType::CodeBlock)
+            .map(|n| n.cell.unwrap())
+            .collect();
+        assert_eq!(blocks, vec![1, 2, 4, 6]);
+        assert!(parsed
+            .refs
+            .iter()
+            .any(|r| r.name == "load_data" && parsed.source[r.span.clone()].contains("load_data")));
+
+        let todo = &parsed.annotations[0];
+        assert_eq!(todo.line, 2, "cell-relative annotation line");
+        assert_eq!(&parsed.source[todo.span.clone()], "TODO: per-region slopes");
+    }
+
+    #[test]
+    fn markdown_cells_become_nested_sections() {
+        let parsed = parsed_fixture();
+        let sections: Vec<_> = parsed
+            .nodes
+            .iter()
+            .filter(|n| n.node_type == NodeType::Section)
+            .map(|n| {
+                (
+                    n.cell.unwrap(),
+                    n.metadata.heading_path().unwrap().to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            sections,
+            vec![
+                (0, "Sales analysis".to_string()),
+                (3, "Sales analysis > Model".to_string()),
+            ]
+        );
+        assert!(named(&parsed, "Model")
+            .metadata_json()
+            .contains("\"cell\":3"));
+    }
+
+    #[test]
+    fn unknown_kernels_and_broken_json_degrade() {
+        let config = Config::default();
+        let nb = r#"{"cells":[{"cell_type":"code","source":"(defn f [] 1)"}],
+                     "metadata":{"kernelspec":{"language":"clojure"}}}"#;
+        let parsed = parse_notebook(Path::new("a.ipynb"), nb, &config, [0; 32], 0);
+        assert_eq!(parsed.nodes.len(), 1);
+        assert_eq!(parsed.nodes[0].node_type, NodeType::CodeBlock);
+
+        let parsed = parse_notebook(Path::new("b.ipynb"), "{not json", &config, [0; 32], 0);
+        assert_eq!(parsed.source, "{not json");
+        assert!(parsed
+            .parse_warning
+            .as_deref()
+            .is_some_and(|w| w.starts_with("invalid notebook JSON")));
+    }
+
+    #[test]
+    fn expand_returns_cell_source_and_checks_the_raw_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("notebooks")).unwrap();
+        let path = dir.path().join("notebooks/analysis.ipynb");
+        std::fs::write(&path, FIXTURE).unwrap();
+        RepoIndex::init(dir.path()).unwrap();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.ipynb").unwrap();
+
+        let handle = index.search_code("load_data", 5).unwrap().remove(0);
+        assert_eq!(handle.node_type, NodeType::Function);
+        assert_eq!(handle.line_range, (1, 3));
+        assert!(handle.preview.starts_with("[cell 2] def load_data"));
+
+        let expanded = index.expand(&[handle.id.to_string()]).unwrap();
+        assert_eq!(
+            expanded[0].1,
+            "def load_data(path):\n    \"\"\"Read the quarterly export.\"\"\"\n    return pd.read_csv(path)"
+        );
+
+        let section = index
+            .search_section_path("sales analysis/model", 5)
+            .unwrap();
+        assert_eq!(section.len(), 1);
+        let expanded = index.expand(&[section[0].id.to_string()]).unwrap();
+        assert_eq!(expanded[0].1, "## Model\n\nA linear fit per region.");
+
+        // Any edit to the notebook file invalidates its handles
+        std::fs::write(&path, FIXTURE.replace("quarterly", "monthly")).unwrap();
+        let err = index.expand(&[handle.id.to_string()]).unwrap_err();
+        assert!(matches!(err, CanopyError::StaleIndex { .. }), "got {err:?}");
+    }
+}
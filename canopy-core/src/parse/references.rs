@@ -1,10 +1,10 @@
-//! Reference extraction (calls, imports) from tree-sitter nodes.
+//! Reference extraction (calls, imports, type usages) from tree-sitter nodes.
 
 use crate::document::{RefType, Reference};
 
 use super::tree_sitter_parse::{node_text, FileType};
 
-/// Extract references (calls, imports) from a tree-sitter node
+/// Extract references (calls, imports, type usages) from a tree-sitter node
 pub(crate) fn extract_references(
     node: &tree_sitter::Node,
     source: &str,
@@ -43,6 +43,8 @@ pub(crate) fn extract_references(
                         }
                     }
                 }
+                // Type annotations, extends/implements clauses (TypeScript)
+                "type_identifier" => push_type_ref(node, source, refs),
                 _ => {}
             }
         }
@@ -127,6 +129,8 @@ pub(crate) fn extract_references(
                         }
                     }
                 }
+                // Types in signatures, fields, impls and bounds
+                "type_identifier" => push_type_ref(node, source, refs),
                 _ => {}
             }
         }
@@ -176,6 +180,8 @@ pub(crate) fn extract_references(
                         }
                     }
                 }
+                // Types in signatures, fields and composite literals
+                "type_identifier" => push_type_ref(node, source, refs),
                 _ => {}
             }
         }
@@ -183,6 +189,35 @@ pub(crate) fn extract_references(
     }
 }
 
+/// Record a `type_identifier` as a type reference, unless it names the type
+/// being declared or a generic parameter.
+fn push_type_ref(node: &tree_sitter::Node, source: &str, refs: &mut Vec<Reference>) {
+    let Some(parent) = node.parent() else {
+        return;
+    };
+    let qualifier = match parent.kind() {
+        // a::b::Type / pkg.Type (the type is the path node's `name`)
+        "scoped_type_identifier" | "qualified_type" => parent
+            .child_by_field_name("path")
+            .or_else(|| parent.child_by_field_name("package"))
+            .map(|p| node_text(&p, source)),
+        "type_parameters" | "constrained_type_parameter" => return,
+        _ if parent.child_by_field_name("name") == Some(*node) => return,
+        _ => None,
+    };
+    let name = node_text(node, source);
+    if name.is_empty() {
+        return;
+    }
+    refs.push(Reference {
+        name,
+        qualifier,
+        ref_type: RefType::TypeRef,
+        span: node.start_byte()..node.end_byte(),
+        line_range: (node.start_position().row + 1, node.end_position().row + 1),
+    });
+}
+
 /// Extract the call target (function name and optional qualifier)
 fn extract_call_target(func_node: &tree_sitter::Node, source: &str) -> (String, Option<String>) {
     match func_node.kind() {
@@ -448,6 +483,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn type_references_skip_declared_names_and_generic_params() {
+        let source = "struct Widget { inner: Gear }\n\nfn spin<T: Clone>(w: Widget, t: T) -> gears::Gear {\n    todo!()\n}\n";
+        let refs = collect_refs(source, tree_sitter_rust::LANGUAGE.into(), FileType::Rust);
+        let types: Vec<(&str, Option<&str>)> = refs
+            .iter()
+            .filter(|r| r.ref_type == RefType::TypeRef)
+            .map(|r| (r.name.as_str(), r.qualifier.as_deref()))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                ("Gear", None),
+                ("Clone", None),
+                ("Widget", None),
+                ("T", None),
+                ("Gear", Some("gears")),
+            ]
+        );
+
+        let ts = "interface Shape { area(): number }\nclass Square implements Shape {}\nfunction draw(s: Shape): void {}\n";
+        let refs = collect_refs(
+            ts,
+            tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            FileType::TypeScript,
+        );
+        let shape_refs = refs
+            .iter()
+            .filter(|r| r.ref_type == RefType::TypeRef && r.name == "Shape")
+            .count();
+        assert_eq!(shape_refs, 2, "implements clause and parameter annotation");
+    }
+
     #[test]
     fn empty_source_yields_no_references() {
         let refs = collect_refs("", tree_sitter_rust::LANGUAGE.into(), FileType::Rust);
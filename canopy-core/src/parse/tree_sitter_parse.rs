@@ -6,12 +6,15 @@ use super::references::extract_references;
 
 pub use super::FileType;
 
-/// Parse code file with tree-sitter
+/// Parse code file with tree-sitter.
+///
+/// `Err` says why the file has no usable syntax tree (grammar failed to load,
+/// no tree, or syntax errors); the caller indexes it as plain chunks instead.
 pub(crate) fn parse_code_with_tree_sitter(
     path: &std::path::Path,
     source: &str,
     file_type: FileType,
-) -> (Vec<DocumentNode>, Vec<Reference>) {
+) -> Result<(Vec<DocumentNode>, Vec<Reference>), String> {
     let mut parser = tree_sitter::Parser::new();
 
     // Set language based on file type
@@ -28,17 +31,19 @@ pub(crate) fn parse_code_with_tree_sitter(
             }
         }
         FileType::Go => tree_sitter_go::LANGUAGE.into(),
-        _ => return (super::parse_as_single_node(source), Vec::new()),
+        _ => return Ok((super::parse_as_single_node(source), Vec::new())),
     };
 
-    if parser.set_language(&language).is_err() {
-        return (super::parse_as_single_node(source), Vec::new());
-    }
+    parser
+        .set_language(&language)
+        .map_err(|e| format!("{:?} grammar failed to load: {}", file_type, e))?;
 
-    let tree = match parser.parse(source, None) {
-        Some(t) => t,
-        None => return (super::parse_as_single_node(source), Vec::new()),
-    };
+    let tree = parser
+        .parse(source, None)
+        .ok_or_else(|| "tree-sitter produced no syntax tree".to_string())?;
+    if let Some(line) = first_error_line(&tree.root_node()) {
+        return Err(format!("syntax error at line {}", line));
+    }
 
     let mut nodes = Vec::new();
     let mut refs = Vec::new();
@@ -53,10 +58,32 @@ pub(crate) fn parse_code_with_tree_sitter(
 
     // If no nodes extracted, fall back to single node
     if nodes.is_empty() {
-        return (super::parse_as_single_node(source), refs);
+        return Ok((super::parse_as_single_node(source), refs));
     }
 
-    (nodes, refs)
+    Ok((nodes, refs))
+}
+
+/// 1-indexed line of the first ERROR or MISSING node, if the tree has any.
+fn first_error_line(root: &tree_sitter::Node) -> Option<usize> {
+    if !root.has_error() {
+        return None;
+    }
+    let mut node = *root;
+    'descend: loop {
+        if node.is_error() || node.is_missing() {
+            return Some(node.start_position().row + 1);
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            if child.has_error() {
+                node = child;
+                continue 'descend;
+            }
+        }
+        // has_error() without an offending child: report the node itself
+        return Some(node.start_position().row + 1);
+    }
 }
 
 /// Parent context passed down during tree-sitter traversal
@@ -121,6 +148,7 @@ fn extract_tree_sitter_nodes(
             parent_handle_id: None,
             parent_node_type: effective_parent.as_ref().and_then(|p| p.node_type),
             parent_span: effective_parent.as_ref().and_then(|p| p.span.clone()),
+            cell: None,
         });
     }
 
@@ -284,10 +312,12 @@ fn classify_rust_node(node: &tree_sitter::Node, source: &str) -> Option<(NodeTyp
         }
         "mod_item" => {
             let name = find_child_text(node, "identifier", source).unwrap_or_default();
+            let heading = format!("mod {}", name);
             Some((
                 NodeType::Section,
                 NodeMetadata::Section {
-                    heading: format!("mod {}", name),
+                    heading_path: heading.clone(),
+                    heading,
                     level: 1,
                 },
             ))
@@ -0,0 +1,340 @@
+//! Running external tools (fd, rg, git) without letting them hang canopy or
+//! flood memory.
+//!
+//! Tools are resolved to an absolute path on `PATH` once per process and run
+//! with a scrubbed environment, so wrappers and variables such as `GIT_DIR`
+//! or `RIPGREP_CONFIG_PATH` can't change what they do. Every run has a
+//! deadline: a tool still running at it is killed and the run fails with
+//! [`CanopyError::ExternalToolTimeout`]. Output is consumed as it arrives,
+//! one record at a time, and reading stops once [`OutputLimits`] are reached.
+
+use crate::config::parse_duration;
+use crate::error::CanopyError;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::{mpsc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Environment variable for the timeout of git metadata commands, e.g. "10s"
+pub const GIT_TIMEOUT_ENV: &str = "CANOPY_GIT_TIMEOUT";
+
+/// Default timeout of git metadata commands
+pub(crate) const GIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Timeout of the `--version` probe that picks a discovery backend
+pub(crate) const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The only variables a tool inherits
+const PASSTHROUGH_ENV: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USERPROFILE",
+    "XDG_CONFIG_HOME",
+    "SYSTEMROOT",
+    "TMPDIR",
+    "TEMP",
+    "TMP",
+];
+
+/// Resolved tool paths; None when the tool isn't on `PATH`.
+static RESOLVED: OnceLock<Mutex<HashMap<String, Option<PathBuf>>>> = OnceLock::new();
+
+/// How long a run may take and how much of its output is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct OutputLimits {
+    pub(crate) timeout: Duration,
+    pub(crate) max_records: usize,
+    pub(crate) max_bytes: usize,
+}
+
+impl OutputLimits {
+    /// `timeout`, keeping all output.
+    pub(crate) fn timeout(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            max_records: usize::MAX,
+            max_bytes: usize::MAX,
+        }
+    }
+}
+
+/// Output of [`capture`].
+#[derive(Debug, Default)]
+pub(crate) struct Captured {
+    /// Records of stdout, separators stripped
+    pub(crate) records: Vec<Vec<u8>>,
+    /// The tool exited successfully
+    pub(crate) success: bool,
+    /// Reading stopped at the limits; `records` is incomplete
+    pub(crate) truncated: bool,
+}
+
+/// A command running the tool `name` by its absolute path, with only
+/// [`PASSTHROUGH_ENV`] inherited. None when `name` isn't on `PATH`.
+pub(crate) fn tool_command(name: &str) -> Option<Command> {
+    Some(scrubbed_command(&resolve(name)?))
+}
+
+/// A command running `program` with only [`PASSTHROUGH_ENV`] inherited.
+pub(crate) fn scrubbed_command(program: &Path) -> Command {
+    let mut cmd = Command::new(program);
+    cmd.env_clear();
+    for name in PASSTHROUGH_ENV {
+        if let Some(value) = std::env::var_os(name) {
+            cmd.env(name, value);
+        }
+    }
+    cmd.env("LC_ALL", "C");
+    cmd
+}
+
+/// Timeout of git metadata commands: [`GIT_TIMEOUT_ENV`] when set to a valid
+/// duration, else [`GIT_TIMEOUT`].
+pub(crate) fn git_timeout() -> Duration {
+    static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+    *TIMEOUT.get_or_init(|| {
+        std::env::var(GIT_TIMEOUT_ENV)
+            .ok()
+            .and_then(|value| parse_duration(&value))
+            .filter(|timeout| !timeout.is_zero())
+            .unwrap_or(GIT_TIMEOUT)
+    })
+}
+
+/// Run `cmd` with stdin closed, collecting its stdout as records ended by
+/// `separator`, until it exits or `limits` are reached.
+pub(crate) fn capture(
+    cmd: Command,
+    separator: u8,
+    limits: OutputLimits,
+) -> crate::Result<Captured> {
+    let (mut captured, status) = run(cmd, None, limits.timeout, move |stdout| {
+        read_records(stdout, separator, limits)
+    })?;
+    captured.success = status.success();
+    Ok(captured)
+}
+
+/// Run `cmd`, feeding it `input` and handing its stdout to `read` on a
+/// thread. The tool is killed at `timeout`, whatever `read` is doing.
+///
+/// `read` may stop early; the tool then exits on the closed pipe, or is
+/// killed at the deadline.
+pub(crate) fn run<T: Send + 'static>(
+    mut cmd: Command,
+    input: Option<Vec<u8>>,
+    timeout: Duration,
+    read: impl FnOnce(ChildStdout) -> T + Send + 'static,
+) -> crate::Result<(T, ExitStatus)> {
+    let command = describe(&cmd);
+    let deadline = Instant::now() + timeout;
+    let mut child = cmd
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let timed_out = || CanopyError::ExternalToolTimeout {
+        command: command.clone(),
+        timeout,
+    };
+
+    // Feed input from a thread so a large batch can't deadlock on full pipes
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+    }
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(read(stdout));
+    });
+
+    let output = match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(output) => output,
+        Err(_) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(timed_out());
+        }
+    };
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((output, status));
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(timed_out());
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Records of `stdout` ended by `separator`, up to `limits`.
+fn read_records(stdout: impl Read, separator: u8, limits: OutputLimits) -> Captured {
+    let mut captured = Captured::default();
+    let mut bytes = 0usize;
+    for record in BufReader::new(stdout).split(separator) {
+        let Ok(record) = record else { break };
+        if record.is_empty() {
+            continue;
+        }
+        bytes = bytes.saturating_add(record.len() + 1);
+        if captured.records.len() == limits.max_records || bytes > limits.max_bytes {
+            captured.truncated = true;
+            break;
+        }
+        captured.records.push(record);
+    }
+    captured
+}
+
+/// Absolute path of `name` on `PATH`, looked up once per process.
+fn resolve(name: &str) -> Option<PathBuf> {
+    let resolved = RESOLVED.get_or_init(Default::default);
+    let mut resolved = resolved.lock().unwrap_or_else(|e| e.into_inner());
+    resolved
+        .entry(name.to_string())
+        .or_insert_with(|| find_on_path(name, &std::env::var_os("PATH")?))
+        .clone()
+}
+
+/// The first executable `name` in the directories of the `PATH` value `path`.
+fn find_on_path(name: &str, path: &OsStr) -> Option<PathBuf> {
+    std::env::split_paths(path)
+        .filter(|dir| dir.is_absolute())
+        .flat_map(|dir| executable_names(name).map(move |name| dir.join(name)))
+        .find(|candidate| is_executable(candidate))
+}
+
+fn executable_names(name: &str) -> impl Iterator<Item = OsString> {
+    let mut names = vec![OsString::from(name)];
+    if cfg!(windows) {
+        names.push(OsString::from(format!("{name}.exe")));
+    }
+    names.into_iter()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// `cmd` as the error names it: the tool's file name and its arguments.
+fn describe(cmd: &Command) -> String {
+    let program = Path::new(cmd.get_program());
+    let mut parts = vec![program
+        .file_name()
+        .unwrap_or(program.as_os_str())
+        .to_string_lossy()
+        .into_owned()];
+    parts.extend(cmd.get_args().map(|arg| arg.to_string_lossy().into_owned()));
+    parts.join(" ")
+}
+
+/// An executable shell script `name` in `dir` running `body`.
+#[cfg(all(test, unix))]
+pub(crate) fn fake_tool(dir: &Path, name: &str, body: &str) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+    let path = dir.join(name);
+    std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_tools_on_path_by_absolute_directory() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let tool = fake_tool(dir.path(), "fd", "exit 0");
+        std::fs::write(dir.path().join("rg"), "not executable").unwrap();
+
+        let path = std::env::join_paths(["relative/bin".as_ref(), dir.path()]).unwrap();
+        assert_eq!(find_on_path("fd", &path), Some(tool));
+        assert_eq!(find_on_path("rg", &path), None);
+        assert_eq!(find_on_path("git-nope", &path), None);
+    }
+
+    #[test]
+    fn slow_tools_are_killed_at_the_deadline() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let tool = fake_tool(dir.path(), "fd", "exec sleep 5");
+        let start = Instant::now();
+        let err = capture(
+            scrubbed_command(&tool),
+            0,
+            OutputLimits::timeout(Duration::from_millis(200)),
+        )
+        .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(3));
+        assert!(
+            matches!(&err, CanopyError::ExternalToolTimeout { command, .. } if command == "fd"),
+            "{err}"
+        );
+        assert!(err.to_string().contains("fd"), "{err}");
+    }
+
+    #[test]
+    fn output_stops_at_the_limits() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let tool = fake_tool(dir.path(), "rg", "printf 'a\\nbb\\nccc\\n'");
+        let limits = |max_records, max_bytes| OutputLimits {
+            timeout: Duration::from_secs(5),
+            max_records,
+            max_bytes,
+        };
+
+        let all = capture(scrubbed_command(&tool), b'\n', limits(10, 100)).unwrap();
+        assert_eq!(
+            all.records,
+            [b"a".to_vec(), b"bb".to_vec(), b"ccc".to_vec()]
+        );
+        assert!(all.success && !all.truncated);
+
+        let by_records = capture(scrubbed_command(&tool), b'\n', limits(2, 100)).unwrap();
+        assert_eq!(by_records.records.len(), 2);
+        assert!(by_records.truncated);
+
+        let by_bytes = capture(scrubbed_command(&tool), b'\n', limits(10, 5)).unwrap();
+        assert_eq!(by_bytes.records, [b"a".to_vec(), b"bb".to_vec()]);
+        assert!(by_bytes.truncated);
+    }
+
+    #[test]
+    fn tools_see_only_the_passthrough_environment() {
+        // cargo sets this for the test binary; tools must not inherit it
+        assert!(std::env::var_os("CARGO_PKG_NAME").is_some());
+        let dir = tempfile::TempDir::new().unwrap();
+        let tool = fake_tool(
+            dir.path(),
+            "git",
+            "echo \"pkg=${CARGO_PKG_NAME:-unset} path=${PATH:+set} lc=$LC_ALL\"",
+        );
+        let captured = capture(
+            scrubbed_command(&tool),
+            b'\n',
+            OutputLimits::timeout(Duration::from_secs(5)),
+        )
+        .unwrap();
+        assert_eq!(captured.records, [b"pkg=unset path=set lc=C".to_vec()]);
+    }
+}
@@ -3,9 +3,62 @@
 //! These types define the contract between canopy-service and canopy-client,
 //! ensuring both sides stay in sync without manual duplication.
 
-use crate::{QueryParams, RepoShard};
+use crate::{
+    Generation, HandleValidation, LineWindow, NodeType, QueryParams, RefType, RepoShard,
+    WarmupReport,
+};
 use serde::{Deserialize, Serialize};
 
+/// Header carrying [`ClientContext::session_id`]
+pub const SESSION_HEADER: &str = "x-canopy-session";
+/// Header carrying [`ClientContext::client_name`]
+pub const CLIENT_HEADER: &str = "x-canopy-client";
+/// Longest session id or client name kept; longer values are cut
+pub const MAX_CLIENT_FIELD_LEN: usize = 128;
+
+/// Who is asking: the tool and the agent session behind a request, so load
+/// and feedback can be attributed when several agents share one service.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientContext {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_name: Option<String>,
+}
+
+impl ClientContext {
+    /// A context from raw values, each [normalized](Self::normalize).
+    pub fn new(client_name: Option<&str>, session_id: Option<&str>) -> Self {
+        Self {
+            session_id: session_id.and_then(Self::normalize),
+            client_name: client_name.and_then(Self::normalize),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.session_id.is_none() && self.client_name.is_none()
+    }
+
+    /// `value` trimmed, cut to [`MAX_CLIENT_FIELD_LEN`] and with anything
+    /// but printable ASCII replaced by `_`, so it is a valid header value.
+    /// None when nothing is left.
+    pub fn normalize(value: &str) -> Option<String> {
+        let value: String = value
+            .trim()
+            .chars()
+            .take(MAX_CLIENT_FIELD_LEN)
+            .map(|c| {
+                if c.is_ascii_graphic() || c == ' ' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        (!value.is_empty()).then_some(value)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryRequest {
     pub repo: String,
@@ -37,6 +90,10 @@ pub struct EvidencePackRequest {
 pub struct ExpandRequest {
     pub repo: String,
     pub handles: Vec<ExpandHandle>,
+    /// Expanded on the caller's behalf (e.g. by explore) rather than asked
+    /// for; feedback records the expansions as automatic.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub auto_expanded: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,24 +101,96 @@ pub struct ExpandHandle {
     pub id: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub generation: Option<u64>,
+    /// Only these lines of the node, e.g. "10-40"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lines: Option<LineWindow>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExpandResponse {
     pub contents: Vec<ExpandedContent>,
+    /// Requested handles the repo's path policy refused to expand
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub suppressed_by_policy: usize,
+    /// Secrets `[redaction]` masked in `contents`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub redactions: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExpandedContent {
     pub handle_id: String,
     pub content: String,
+    /// Why the requested `lines` were ignored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
 }
 
+/// Request for the [`HandleStatus`](crate::HandleStatus) of handles of the
+/// live generation, without their content.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidateRequest {
+    pub repo: String,
+    pub handle_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidateResponse {
+    /// One entry per requested id, in request order
+    pub handles: Vec<HandleValidation>,
+    /// Generation the handles were checked against
+    pub generation: u64,
+}
+
+/// Register a repo: an existing checkout at `path`, or `git_url` for a
+/// checkout the service clones and keeps up to date itself.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AddRepoRequest {
-    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    /// Clone this instead of using `path` (needs an admin API key)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_url: Option<String>,
+    /// Branch to clone and follow; the remote's default when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Sparse-checkout only these directories
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sparse_paths: Option<Vec<String>>,
+    /// Paths the service may surface from this repo (needs an admin API key)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<PathPolicy>,
+}
+
+/// Which of a repo's paths the service may surface. Results under a path
+/// matching `deny`, or not matching a non-empty `allow`, are left out of
+/// query, evidence pack and related-file responses, and their handles are
+/// not expanded. Patterns are globs over repo-relative paths (`*` stays
+/// within one segment); a pattern also covers everything under the
+/// directory it names, so `secrets/` hides `secrets/prod/key.pem`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathPolicy {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+impl PathPolicy {
+    /// True when the policy lets everything through.
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+}
+
+/// Replace a repo's path policy; an empty policy removes it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetPolicyRequest {
+    pub repo: String,
+    #[serde(default)]
+    pub policy: PathPolicy,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -70,11 +199,66 @@ pub struct AddRepoResponse {
     pub name: String,
 }
 
+/// Request for a budgeted repository overview; the response is a `RepoSummary`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SummaryRequest {
+    pub repo: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+}
+
+/// Request for the files most related to one file; the response is a
+/// `RelatedFiles`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelatedRequest {
+    pub repo: String,
+    /// Repo-relative path of an indexed file
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+/// Request for a symbol's references grouped by file; the response is a
+/// `FileReferences`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferencesRequest {
+    pub repo: String,
+    pub symbol: String,
+    /// Only these ref types; all when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ref_types: Vec<RefType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub glob: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+/// Request for a page of the index's symbols in name order; the response
+/// is a `SymbolPage`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SymbolsRequest {
+    pub repo: String,
+    /// Case-insensitive name prefix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// Only these node types (empty = all)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub node_types: Vec<NodeType>,
+    /// `next_after` of the previous page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReindexRequest {
     pub repo: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub glob: Option<String>,
+    /// Reindex exactly these repo-relative paths instead of a glob walk
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -89,3 +273,60 @@ pub struct ServiceStatus {
     pub service: String,
     pub repos: Vec<RepoShard>,
 }
+
+/// Request to preload indexes; every ready repo when `repo_ids` is absent.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WarmupRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo_ids: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WarmupResponse {
+    pub repos: Vec<RepoWarmup>,
+}
+
+/// Request to drop retained generations (every repo's when `repo` is
+/// absent) beyond `retain`, or the service's `--retain-generations`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PruneGenerationsRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    /// Generations to keep per repo, the live one included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retain: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PruneGenerationsResponse {
+    pub pruned: Vec<PrunedGeneration>,
+    /// Disk the dropped copies used
+    pub freed_bytes: u64,
+}
+
+/// A retained generation dropped by a prune.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrunedGeneration {
+    pub repo_id: String,
+    pub generation: Generation,
+    pub size_bytes: u64,
+}
+
+fn is_zero(v: &usize) -> bool {
+    *v == 0
+}
+
+/// One repo's warm-up.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepoWarmup {
+    pub repo_id: String,
+    /// What was read; absent when the repo couldn't be warmed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report: Option<WarmupReport>,
+    /// Reader connections open afterwards, each with its symbol cache loaded
+    pub readers: usize,
+    /// Wall time, including opening the index and its readers
+    pub total_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
@@ -0,0 +1,369 @@
+//! Count and exists modes: a query's match count, or whether it matches at
+//! all, without building handles, previews or token counts.
+
+use crate::index::count::MatchCount;
+use crate::index::RepoIndex;
+use std::collections::BTreeMap;
+
+use super::dsl::{format_window, Query};
+use super::executor::{annotation_query, query_glob};
+use super::{PathPredicate, QueryMode, QueryResult};
+
+/// Answer `query` in count or exists mode.
+///
+/// Counts are exact: unlike `total_matches` in handles mode they aren't
+/// bounded by what the result limit let the search collect. An explicit
+/// `(limit N ...)` still caps them. With `permits`, matches in files it
+/// rejects aren't counted and never serve as the witness.
+pub(super) fn execute_count(
+    query: &Query,
+    index: &RepoIndex,
+    mode: QueryMode,
+    permits: Option<&dyn PathPredicate>,
+) -> crate::Result<QueryResult> {
+    let found = count_matches(query, index, mode, permits)?;
+
+    let match_counts = match mode {
+        QueryMode::Count => {
+            let mut counts = BTreeMap::new();
+            match searches(query).as_slice() {
+                [single] => {
+                    counts.insert(dsl(single), found.count);
+                }
+                searches => {
+                    for search in searches {
+                        let count = count_matches(search, index, mode, permits)?.count;
+                        *counts.entry(dsl(search)).or_default() += count;
+                    }
+                }
+            }
+            Some(counts)
+        }
+        _ => None,
+    };
+
+    Ok(QueryResult {
+        total_matches: found.count,
+        mode,
+        exists: (mode == QueryMode::Exists).then_some(found.count > 0),
+        witness_path: found.witness,
+        match_counts,
+        ..QueryResult::default()
+    })
+}
+
+/// Matches across every database the query can reach; exists mode stops at
+/// the first database with one.
+fn count_matches(
+    query: &Query,
+    index: &RepoIndex,
+    mode: QueryMode,
+    permits: Option<&dyn PathPredicate>,
+) -> crate::Result<MatchCount> {
+    let exists = mode == QueryMode::Exists;
+    let mut total = MatchCount::default();
+
+    if let Some((terms, glob, limit)) = annotation_query(query) {
+        let limit = if exists {
+            1
+        } else {
+            limit.unwrap_or(usize::MAX)
+        };
+        for target in index.query_targets(glob) {
+            let wanted = limit - total.count;
+            // Denied annotations mustn't use up the limit
+            let fetch = if permits.is_some() {
+                usize::MAX
+            } else {
+                wanted
+            };
+            let mut annotations = target.search_annotations(terms, glob, fetch)?;
+            annotations.retain(|a| permits.is_none_or(|p| p.permits(&a.file_path)));
+            annotations.truncate(wanted);
+            total.count += annotations.len();
+            if exists && total.count > 0 {
+                total.witness = annotations.into_iter().next().map(|a| a.file_path);
+            }
+            if total.count >= limit {
+                break;
+            }
+        }
+        return Ok(total);
+    }
+
+    if let Query::Limit(limit, inner) = query {
+        let mut found = count_matches(inner, index, mode, permits)?;
+        found.count = found.count.min(*limit);
+        if found.count == 0 {
+            found.witness = None;
+        }
+        return Ok(found);
+    }
+
+    let targets = match query {
+        Query::References(..) => index.query_targets(None),
+        // Relating reads every database itself
+        Query::Related(_) => vec![index],
+        _ => index.query_targets(query_glob(query)),
+    };
+    for target in targets {
+        let found = match query {
+            Query::References(symbol, ref_types) => {
+                target.count_references(symbol, ref_types, mode, permits)?
+            }
+            _ => target.count_query(query, mode, permits)?,
+        };
+        total.count += found.count;
+        if found.witness.is_some() {
+            total.witness = found.witness;
+            break;
+        }
+    }
+    Ok(total)
+}
+
+/// The searches a query combines: the members of its unions and intersections.
+fn searches(query: &Query) -> Vec<&Query> {
+    match query {
+        Query::Union(queries) | Query::Intersect(queries) => {
+            queries.iter().flat_map(searches).collect()
+        }
+        _ => vec![query],
+    }
+}
+
+/// `query` in DSL form, as the key of its count.
+pub(super) fn dsl(query: &Query) -> String {
+    let join = |queries: &[Query]| queries.iter().map(dsl).collect::<Vec<_>>().join(" ");
+    match query {
+        Query::Section(s) => format!("(section {s:?})"),
+        Query::SectionPath(s) => format!("(section-path {s:?})"),
+        Query::Grep(s) => format!("(grep {s:?})"),
+        Query::File(glob, _) => format!("(file {glob:?})"),
+        Query::Code(s) => format!("(code {s:?})"),
+        Query::InFile(glob, inner) => format!("(in-file {glob:?} {})", dsl(inner)),
+        Query::Recent(window, inner) => {
+            format!("(recent {:?} {})", format_window(*window), dsl(inner))
+        }
+        Query::PathContains(fragment, inner) => match inner.as_ref() {
+            Query::File(glob, _) if glob == "**" => format!("(path {fragment:?})"),
+            inner => format!("(path {fragment:?} {})", dsl(inner)),
+        },
+        Query::Union(queries) => format!("(union {})", join(queries)),
+        Query::Intersect(queries) => format!("(intersect {})", join(queries)),
+        Query::Limit(n, inner) => format!("(limit {n} {})", dsl(inner)),
+        Query::Children(s) => format!("(children {s:?})"),
+        Query::ChildrenNamed(parent, s) => format!("(children-named {parent:?} {s:?})"),
+        Query::Definition(s) => format!("(definition {s:?})"),
+        Query::References(s, ref_types) if ref_types.is_empty() => format!("(references {s:?})"),
+        Query::References(s, ref_types) => {
+            let types: Vec<&str> = ref_types.iter().map(|t| t.as_str()).collect();
+            format!("(references {s:?} :types ({}))", types.join(" "))
+        }
+        Query::Annotations(s) if s.is_empty() => "(todos)".to_string(),
+        Query::Annotations(s) => format!("(todos {s:?})"),
+        Query::Commits(s) if s.is_empty() => "(commits)".to_string(),
+        Query::Commits(s) => format!("(commits {s:?})"),
+        Query::Related(path) => format!("(related {path:?})"),
+        Query::Symbols(prefix) => format!("(symbols {prefix:?})"),
+        Query::Exclude(patterns, inner) => {
+            let patterns: Vec<String> = patterns.iter().map(|p| format!("{p:?}")).collect();
+            match inner.as_ref() {
+                Query::Grep(s) => format!("(grep {s:?} :exclude ({}))", patterns.join(" ")),
+                inner => format!("(exclude ({}) {})", patterns.join(" "), dsl(inner)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{execute_query, parse_query, MatchMode, QueryKind, QueryParams};
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+    use std::sync::Arc;
+
+    fn indexed_repo() -> (FixtureRepo, RepoIndex) {
+        let repo = FixtureRepoBuilder::new()
+            .file(
+                "src/auth/token.rs",
+                "fn refresh_token() {}\nfn check() { refresh_token(); }\n// TODO rotate keys\nfn rotate() {}\n",
+            )
+            .file(
+                "src/session.rs",
+                "fn start() { refresh_token(); }\nfn stop() { log_out(); }\n",
+            )
+            .file(
+                "README.md",
+                "# Auth\nTokens are refreshed.\n## Tokens\nrefresh_token rotates.\n",
+            )
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*").unwrap();
+        (repo, index)
+    }
+
+    fn run(index: &RepoIndex, params: &QueryParams) -> QueryResult {
+        let query = params.to_query().unwrap();
+        super::super::execute_query_with_options(&query, index, params.to_options()).unwrap()
+    }
+
+    #[test]
+    fn counts_match_handles_mode_totals() {
+        let (_dir, index) = indexed_repo();
+        let cases = [
+            QueryParams::pattern("refresh_token"),
+            QueryParams::symbol("refresh_token"),
+            QueryParams::symbol("refresh"),
+            QueryParams::section("tokens"),
+            QueryParams::patterns(vec!["refresh_token".into(), "log_out".into()]),
+            QueryParams::patterns(vec!["refresh_token".into(), "start".into()])
+                .with_match_mode(MatchMode::All),
+            QueryParams::pattern("refresh_token").with_glob("src/**"),
+            QueryParams::symbol("refresh_token").with_kind(QueryKind::Reference),
+            QueryParams::new().with_kind(QueryKind::Annotation),
+            QueryParams::pattern("refresh_token").with_exclude_patterns(vec!["start".into()]),
+            QueryParams::pattern("nothing_matches_this"),
+        ];
+        for params in cases {
+            let full = run(&index, &params.clone().with_limit(1000));
+            let counted = run(&index, &params.clone().with_mode(QueryMode::Count));
+            assert_eq!(
+                counted.total_matches, full.total_matches,
+                "count mode disagrees for {params:?}"
+            );
+            let expects_none = params.pattern.as_deref() == Some("nothing_matches_this");
+            assert_eq!(counted.total_matches == 0, expects_none, "{params:?}");
+            assert!(counted.handles.is_empty());
+            assert_eq!(counted.total_tokens, 0);
+            assert_eq!(counted.mode, QueryMode::Count);
+        }
+    }
+
+    #[test]
+    fn count_mode_reports_each_combined_search() {
+        let (_dir, index) = indexed_repo();
+        let params = QueryParams::patterns(vec!["refresh_token".into(), "log_out".into()])
+            .with_mode(QueryMode::Count);
+        let result = run(&index, &params);
+
+        let counts = result.match_counts.unwrap();
+        let single = |pattern: &str| {
+            run(
+                &index,
+                &QueryParams::pattern(pattern).with_mode(QueryMode::Count),
+            )
+            .total_matches
+        };
+        assert_eq!(counts[r#"(grep "refresh_token")"#], single("refresh_token"));
+        assert_eq!(counts[r#"(grep "log_out")"#], single("log_out"));
+        assert!(result.total_matches <= counts.values().sum::<usize>());
+    }
+
+    #[test]
+    fn dsl_limit_caps_counts() {
+        let (_dir, index) = indexed_repo();
+        let query = parse_query(r#"(limit 1 (grep "refresh_token"))"#).unwrap();
+        let result = execute_count(&query, &index, QueryMode::Count, None).unwrap();
+        assert_eq!(result.total_matches, 1);
+    }
+
+    #[test]
+    fn exists_mode_returns_one_witness() {
+        let (_dir, index) = indexed_repo();
+        let found = run(
+            &index,
+            &QueryParams::pattern("refresh_token").with_mode(QueryMode::Exists),
+        );
+        assert_eq!(found.exists, Some(true));
+        assert_eq!(found.total_matches, 1);
+        let witness = found.witness_path.unwrap();
+        let full = execute_query(
+            &parse_query(r#"(grep "refresh_token")"#).unwrap(),
+            &index,
+            None,
+        )
+        .unwrap();
+        assert!(full.handles.iter().any(|h| h.file_path == witness));
+
+        // A glob filter only accepts witnesses it matches
+        let scoped = run(
+            &index,
+            &QueryParams::pattern("refresh_token")
+                .with_glob("src/auth/**")
+                .with_mode(QueryMode::Exists),
+        );
+        assert_eq!(scoped.witness_path.as_deref(), Some("src/auth/token.rs"));
+
+        let missing = run(
+            &index,
+            &QueryParams::symbol("no_such_symbol").with_mode(QueryMode::Exists),
+        );
+        assert_eq!(missing.exists, Some(false));
+        assert_eq!(missing.total_matches, 0);
+        assert!(missing.witness_path.is_none());
+    }
+
+    #[derive(Debug)]
+    struct DenyAuth;
+
+    impl PathPredicate for DenyAuth {
+        fn permits(&self, path: &str) -> bool {
+            !path.starts_with("src/auth/")
+        }
+    }
+
+    #[test]
+    fn path_predicate_hides_denied_matches() {
+        let (_dir, index) = indexed_repo();
+        let run_denied = |params: QueryParams| {
+            let query = params.to_query().unwrap();
+            let options = params.to_options().with_path_predicate(Arc::new(DenyAuth));
+            super::super::execute_query_with_options(&query, &index, options).unwrap()
+        };
+
+        // Only src/auth/token.rs defines it or carries a TODO
+        for params in [
+            QueryParams::symbol("rotate"),
+            QueryParams::new().with_kind(QueryKind::Annotation),
+        ] {
+            let counted = run_denied(params.clone().with_mode(QueryMode::Count));
+            assert_eq!(counted.total_matches, 0, "{params:?}");
+            assert!(counted.match_counts.unwrap().values().all(|&n| n == 0));
+            let found = run_denied(params.with_mode(QueryMode::Exists));
+            assert_eq!(found.exists, Some(false));
+            assert!(found.witness_path.is_none());
+        }
+
+        let all = run(
+            &index,
+            &QueryParams::pattern("refresh_token").with_mode(QueryMode::Count),
+        );
+        let permitted =
+            run_denied(QueryParams::pattern("refresh_token").with_mode(QueryMode::Count));
+        assert!(permitted.total_matches > 0);
+        assert!(permitted.total_matches < all.total_matches);
+        let refs = run_denied(
+            QueryParams::symbol("refresh_token")
+                .with_kind(QueryKind::Reference)
+                .with_mode(QueryMode::Exists),
+        );
+        assert_eq!(refs.witness_path.as_deref(), Some("src/session.rs"));
+    }
+
+    #[test]
+    fn dsl_form_round_trips_through_the_parser() {
+        for input in [
+            r#"(union (grep "a b") (code "x"))"#,
+            r#"(in-file "src/**" (grep "y"))"#,
+            r#"(references "z" :types (call type_ref))"#,
+            "(todos)",
+            r#"(grep "retry" :exclude ("test" "bench"))"#,
+            r#"(exclude ("mock") (code "retry"))"#,
+        ] {
+            let query = parse_query(input).unwrap();
+            assert_eq!(dsl(&parse_query(&dsl(&query)).unwrap()), dsl(&query));
+        }
+    }
+}
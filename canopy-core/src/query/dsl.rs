@@ -1,20 +1,32 @@
 //! Query AST and S-expression DSL parser.
 
+use crate::config::parse_duration;
+use crate::document::RefType;
 use crate::error::CanopyError;
+use std::time::Duration;
 
 /// Query AST
 #[derive(Debug, Clone)]
 pub enum Query {
     /// (section "heading") - fuzzy match on section headings
     Section(String),
-    /// (grep "pattern") - FTS5 search
+    /// (section-path "auth/configuration") - heading path suffix match
+    SectionPath(String),
+    /// (grep "pattern") - FTS5 search; `(grep "retry" :exclude ("test" "bench"))`
+    /// drops nodes containing any excluded pattern
     Grep(String),
-    /// (file "path") - entire file as handle
-    File(String),
+    /// (file "path") - entire file as handle; `(file "src/**" :limit 20 :offset 20)`
+    /// pages through a broad glob
+    File(String, FileSlice),
     /// (code "symbol") - AST symbol search
     Code(String),
     /// (in-file "glob" query) - search within specific files
     InFile(String, Box<Query>),
+    /// (recent "7d" query) - only files changed within the window
+    Recent(Duration, Box<Query>),
+    /// (path "session" query) - only files whose path contains the fragment,
+    /// ignoring case; `(path "session")` alone returns those files whole
+    PathContains(String, Box<Query>),
     /// (union q1 q2 ...) - combine results
     Union(Vec<Query>),
     /// (intersect q1 q2 ...) - intersection of results
@@ -27,8 +39,30 @@ pub enum Query {
     ChildrenNamed(String, String),
     /// (definition "symbol") - exact match symbol definition
     Definition(String),
-    /// (references "symbol") - find references to a symbol
-    References(String),
+    /// (references "symbol") - find references to a symbol; `(refs "symbol" :types (call type))`
+    /// keeps only the listed ref types (empty = all)
+    References(String, Vec<RefType>),
+    /// (todos "terms") - marker comments (TODO, FIXME, ...); `(todos)` lists all
+    Annotations(String),
+    /// (related "path") - whole files sharing the most symbols with a file
+    Related(String),
+    /// (symbols "prefix") - named nodes whose names start with a prefix, in
+    /// name order
+    Symbols(String),
+    /// (exclude ("test" "bench") query) - results of a query whose node
+    /// content contains none of the patterns
+    Exclude(Vec<String>, Box<Query>),
+    /// (commits "retry backoff") - commit messages of the git-history layer;
+    /// `(commits)` lists the newest
+    Commits(String),
+}
+
+/// `:limit`/`:offset` of a `(file ...)` query. Unset fields fall back to the
+/// query's [`FileQueryOptions`](crate::FileQueryOptions).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileSlice {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
 }
 
 /// Parse a query string into a Query AST
@@ -45,6 +79,29 @@ pub fn parse_query(input: &str) -> crate::Result<Query> {
     parser.parse()
 }
 
+/// A recency window such as "30m", "48h", "7d" or "2w"; `position` locates
+/// it in the query for the error.
+pub(crate) fn parse_window(window: &str, position: usize) -> crate::Result<Duration> {
+    parse_duration(window).ok_or_else(|| CanopyError::QueryParse {
+        position,
+        message: format!(
+            "Invalid recency window {window:?}: expected a number and a unit \
+             (s, m, h, d or w), like \"48h\" or \"7d\""
+        ),
+    })
+}
+
+/// `window` in its largest whole unit, as [`parse_window`] reads it.
+pub(crate) fn format_window(window: Duration) -> String {
+    let secs = window.as_secs();
+    [(7 * 86_400, 'w'), (86_400, 'd'), (3_600, 'h'), (60, 'm')]
+        .into_iter()
+        .find(|(unit, _)| secs > 0 && secs.is_multiple_of(*unit))
+        .map_or(format!("{secs}s"), |(unit, suffix)| {
+            format!("{}{suffix}", secs / unit)
+        })
+}
+
 /// S-expression parser for the query DSL
 struct QueryParser<'a> {
     input: &'a str,
@@ -75,15 +132,33 @@ impl<'a> QueryParser<'a> {
                 let arg = self.parse_string()?;
                 Query::Section(arg)
             }
+            "section-path" => {
+                self.skip_whitespace();
+                let arg = self.parse_string()?;
+                Query::SectionPath(arg)
+            }
             "grep" => {
                 self.skip_whitespace();
                 let arg = self.parse_string()?;
-                Query::Grep(arg)
+                self.skip_whitespace();
+                if self.peek() == Some(':') {
+                    self.advance(); // consume ':'
+                    let keyword = self.parse_identifier()?;
+                    if keyword != "exclude" {
+                        return Err(self.error(&format!("Unknown option: :{}", keyword)));
+                    }
+                    self.skip_whitespace();
+                    let patterns = self.parse_string_list(":exclude")?;
+                    Query::Exclude(patterns, Box::new(Query::Grep(arg)))
+                } else {
+                    Query::Grep(arg)
+                }
             }
             "file" => {
                 self.skip_whitespace();
                 let arg = self.parse_string()?;
-                Query::File(arg)
+                let slice = self.parse_file_slice()?;
+                Query::File(arg, slice)
             }
             "code" => {
                 self.skip_whitespace();
@@ -97,6 +172,25 @@ impl<'a> QueryParser<'a> {
                 let subquery = self.parse()?;
                 Query::InFile(glob, Box::new(subquery))
             }
+            "recent" => {
+                self.skip_whitespace();
+                let start = self.pos;
+                let window = parse_window(&self.parse_string()?, start)?;
+                self.skip_whitespace();
+                let subquery = self.parse()?;
+                Query::Recent(window, Box::new(subquery))
+            }
+            "path" => {
+                self.skip_whitespace();
+                let fragment = self.parse_string()?;
+                self.skip_whitespace();
+                let subquery = if self.peek() == Some(')') {
+                    Query::File("**".to_string(), FileSlice::default())
+                } else {
+                    self.parse()?
+                };
+                Query::PathContains(fragment, Box::new(subquery))
+            }
             "union" => {
                 let mut queries = Vec::new();
                 loop {
@@ -143,10 +237,51 @@ impl<'a> QueryParser<'a> {
                 let symbol = self.parse_string()?;
                 Query::Definition(symbol)
             }
-            "references" => {
+            "references" | "refs" => {
                 self.skip_whitespace();
                 let symbol = self.parse_string()?;
-                Query::References(symbol)
+                self.skip_whitespace();
+                let ref_types = if self.peek() == Some(':') {
+                    self.parse_ref_types()?
+                } else {
+                    Vec::new()
+                };
+                Query::References(symbol, ref_types)
+            }
+            "todos" => {
+                self.skip_whitespace();
+                let terms = if self.peek() == Some(')') {
+                    String::new()
+                } else {
+                    self.parse_string()?
+                };
+                Query::Annotations(terms)
+            }
+            "commits" => {
+                self.skip_whitespace();
+                let terms = if self.peek() == Some(')') {
+                    String::new()
+                } else {
+                    self.parse_string()?
+                };
+                Query::Commits(terms)
+            }
+            "related" => {
+                self.skip_whitespace();
+                let path = self.parse_string()?;
+                Query::Related(path)
+            }
+            "symbols" => {
+                self.skip_whitespace();
+                let prefix = self.parse_string()?;
+                Query::Symbols(prefix)
+            }
+            "exclude" => {
+                self.skip_whitespace();
+                let patterns = self.parse_string_list("exclude")?;
+                self.skip_whitespace();
+                let subquery = self.parse()?;
+                Query::Exclude(patterns, Box::new(subquery))
             }
             _ => return Err(self.error(&format!("Unknown operator: {}", op))),
         };
@@ -161,6 +296,76 @@ impl<'a> QueryParser<'a> {
         Ok(query)
     }
 
+    /// `:limit N` and `:offset N`, in either order, after a file glob.
+    fn parse_file_slice(&mut self) -> crate::Result<FileSlice> {
+        let mut slice = FileSlice::default();
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(':') {
+                return Ok(slice);
+            }
+            self.advance(); // consume ':'
+            let keyword = self.parse_identifier()?;
+            self.skip_whitespace();
+            match keyword.as_str() {
+                "limit" => slice.limit = Some(self.parse_number()?),
+                "offset" => slice.offset = Some(self.parse_number()?),
+                _ => return Err(self.error(&format!("Unknown option: :{}", keyword))),
+            }
+        }
+    }
+
+    /// `:types (call import type)` after a references symbol.
+    fn parse_ref_types(&mut self) -> crate::Result<Vec<RefType>> {
+        self.advance(); // consume ':'
+        let keyword = self.parse_identifier()?;
+        if keyword != "types" {
+            return Err(self.error(&format!("Unknown option: :{}", keyword)));
+        }
+        self.skip_whitespace();
+        if self.peek() != Some('(') {
+            return Err(self.error("Expected '(' after :types"));
+        }
+        self.advance();
+        let mut ref_types = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.peek() == Some(')') {
+                self.advance();
+                break;
+            }
+            let name = self.parse_identifier()?;
+            let ref_type = RefType::parse(&name).ok_or_else(|| {
+                self.error(&format!(
+                    "Unknown ref type: {} (expected call, import or type)",
+                    name
+                ))
+            })?;
+            if !ref_types.contains(&ref_type) {
+                ref_types.push(ref_type);
+            }
+        }
+        Ok(ref_types)
+    }
+
+    /// A parenthesized list of strings, `("test" "bench")`, after `after`.
+    fn parse_string_list(&mut self, after: &str) -> crate::Result<Vec<String>> {
+        if self.peek() != Some('(') {
+            return Err(self.error(&format!("Expected '(' after {}", after)));
+        }
+        self.advance();
+        let mut strings = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.peek() == Some(')') {
+                self.advance();
+                break;
+            }
+            strings.push(self.parse_string()?);
+        }
+        Ok(strings)
+    }
+
     fn skip_whitespace(&mut self) {
         while let Some(c) = self.peek() {
             if c.is_whitespace() {
@@ -320,4 +525,106 @@ mod tests {
             _ => panic!("expected Limit"),
         }
     }
+
+    #[test]
+    fn parse_todos_with_and_without_terms() {
+        assert!(
+            matches!(parse_query(r#"(todos "auth")"#).unwrap(), Query::Annotations(s) if s == "auth")
+        );
+        assert!(matches!(parse_query("(todos)").unwrap(), Query::Annotations(s) if s.is_empty()));
+    }
+
+    #[test]
+    fn parse_commits_with_and_without_terms() {
+        assert!(matches!(
+            parse_query(r#"(commits "retry backoff")"#).unwrap(),
+            Query::Commits(s) if s == "retry backoff"
+        ));
+        assert!(matches!(parse_query("(commits)").unwrap(), Query::Commits(s) if s.is_empty()));
+    }
+
+    #[test]
+    fn parse_file_with_limit_and_offset() {
+        let q = parse_query(r#"(file "src/**" :limit 20 :offset 40)"#).unwrap();
+        assert!(matches!(
+            q,
+            Query::File(ref glob, FileSlice { limit: Some(20), offset: Some(40) }) if glob == "src/**"
+        ));
+        let q = parse_query(r#"(file "src/**" :offset 5)"#).unwrap();
+        assert!(matches!(
+            q,
+            Query::File(
+                _,
+                FileSlice {
+                    limit: None,
+                    offset: Some(5)
+                }
+            )
+        ));
+        assert!(parse_query(r#"(file "src/**" :types (call))"#).is_err());
+        assert!(parse_query(r#"(file "src/**" :limit)"#).is_err());
+    }
+
+    #[test]
+    fn parse_refs_with_type_filter() {
+        let q = parse_query(r#"(refs "Foo" :types (call type call))"#).unwrap();
+        assert!(matches!(
+            q,
+            Query::References(ref s, ref types)
+                if s == "Foo" && types == &[RefType::Call, RefType::TypeRef]
+        ));
+        assert!(matches!(
+            parse_query(r#"(references "Foo")"#).unwrap(),
+            Query::References(_, types) if types.is_empty()
+        ));
+        assert!(parse_query(r#"(refs "Foo" :types (macro))"#).is_err());
+        assert!(parse_query(r#"(refs "Foo" :kinds (call))"#).is_err());
+    }
+
+    #[test]
+    fn parse_grep_with_exclusions() {
+        let q = parse_query(r#"(grep "retry" :exclude ("test" "bench"))"#).unwrap();
+        match q {
+            Query::Exclude(patterns, inner) => {
+                assert_eq!(patterns, ["test", "bench"]);
+                assert!(matches!(*inner, Query::Grep(ref s) if s == "retry"));
+            }
+            _ => panic!("expected Exclude"),
+        }
+        let q = parse_query(r#"(exclude ("mock") (code "retry"))"#).unwrap();
+        assert!(matches!(q, Query::Exclude(ref p, ref inner)
+            if p == &["mock"] && matches!(inner.as_ref(), Query::Code(_))));
+        assert!(parse_query(r#"(grep "retry" :exclude "test")"#).is_err());
+        assert!(parse_query(r#"(grep "retry" :types (call))"#).is_err());
+    }
+
+    #[test]
+    fn parse_recent_window() {
+        let q = parse_query(r#"(recent "48h" (definition "Foo"))"#).unwrap();
+        match q {
+            Query::Recent(window, inner) => {
+                assert_eq!(window, Duration::from_secs(48 * 3600));
+                assert_eq!(format_window(window), "2d");
+                assert!(matches!(*inner, Query::Definition(_)));
+            }
+            _ => panic!("expected Recent"),
+        }
+        assert_eq!(format_window(Duration::from_secs(90)), "90s");
+        assert_eq!(format_window(Duration::from_secs(14 * 86_400)), "2w");
+
+        let err = parse_query(r#"(recent "7 days" (grep "x"))"#).unwrap_err();
+        assert!(
+            matches!(err, CanopyError::QueryParse { position: 8, ref message } if message.contains("\"7 days\""))
+        );
+    }
+
+    #[test]
+    fn parse_path_fragment() {
+        let q = parse_query(r#"(path "Session" (grep "store"))"#).unwrap();
+        assert!(matches!(q, Query::PathContains(ref fragment, ref inner)
+            if fragment == "Session" && matches!(inner.as_ref(), Query::Grep(_))));
+        let q = parse_query(r#"(path "session")"#).unwrap();
+        assert!(matches!(q, Query::PathContains(_, ref inner)
+            if matches!(inner.as_ref(), Query::File(glob, _) if glob == "**")));
+    }
 }
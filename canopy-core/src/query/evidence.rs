@@ -1,18 +1,23 @@
 //! Evidence pack types and builder.
 
 use crate::document::NodeType;
-use crate::handle::HandleSource;
+use crate::handle::{Handle, HandleSource};
+use crate::index::SymbolSuggestion;
+use crate::schema::SchemaVersion;
 use crate::scoring::HandleScorer;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 
-use super::QueryResult;
+use super::{is_zero, split_terms, QueryResult, ResultClass};
 
 /// Compact evidence view derived from query results.
 ///
 /// Intentionally excludes full snippets/content to keep context payloads small.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EvidencePack {
+    #[serde(default)]
+    pub schema_version: SchemaVersion,
     pub query_text: String,
     pub total_matches: usize,
     pub truncated: bool,
@@ -25,6 +30,21 @@ pub struct EvidencePack {
     /// Action guidance so agents can stop exploring and start synthesis.
     #[serde(default)]
     pub guidance: EvidenceGuidance,
+    /// Nearby symbol names when a symbol query matched nothing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<SymbolSuggestion>,
+    /// Matches left out of the pack by `max_handles`/`max_per_file`.
+    #[serde(default)]
+    pub overflow: EvidenceOverflow,
+    /// Paths of files with unselected matches, spread across directories.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overflow_samples: Vec<String>,
+    /// Service mode: matches hidden by the repo's path policy
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub suppressed_by_policy: usize,
+    /// Matches the query's exclude patterns removed
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub excluded_matches: usize,
 }
 
 impl EvidencePack {
@@ -85,6 +105,9 @@ pub struct EvidenceHandle {
     pub commit_sha: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub generation: Option<u64>,
+    /// [`Handle::content_hash`](crate::Handle::content_hash) of the handle
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
     pub score: f64,
 }
 
@@ -95,11 +118,24 @@ pub struct EvidenceFileSummary {
     pub total_tokens: usize,
 }
 
+/// Scale of the matches a pack didn't select.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EvidenceOverflow {
+    /// Files with matches but no selected handle
+    pub files: usize,
+    /// Matching handles not selected
+    pub handles: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum EvidenceAction {
     RefineQuery,
     ExpandThenAnswer,
+    /// Rerun the query with the top "did you mean" suggestion.
+    RetrySuggestion,
+    /// Matches span more files than a pack can hold; scope queries per directory.
+    NarrowScope,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -126,6 +162,16 @@ pub struct EvidenceGuidance {
     pub rationale: String,
     /// One-line instruction intended for direct agent consumption.
     pub next_step: String,
+    /// Share of the query's terms found in the selected handles' paths and
+    /// previews (1 for a query without terms)
+    #[serde(default)]
+    pub term_coverage: f64,
+    /// Whether a selected handle is a definition of the queried symbol
+    #[serde(default)]
+    pub definition_present: bool,
+    /// Share of the pack's `max_handles` slots that were filled
+    #[serde(default)]
+    pub result_density: f64,
 }
 
 impl Default for EvidenceGuidance {
@@ -141,10 +187,100 @@ impl Default for EvidenceGuidance {
             next_step:
                 "Refine the query with more specific symbols, paths, or terms before expanding."
                     .to_string(),
+            term_coverage: 0.0,
+            definition_present: false,
+            result_density: 0.0,
+        }
+    }
+}
+
+/// How guidance turns a pack into a confidence band and a `stop_querying`
+/// verdict. [`Default`] is tuned for small repos; `[evidence]` in the repo
+/// config picks a preset and overrides single values (see
+/// [`EvidenceConfig`](crate::config::EvidenceConfig)).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationConfig {
+    /// Confidence below this is the low band
+    pub medium_threshold: f64,
+    /// Confidence at or above this is the high band
+    pub high_threshold: f64,
+    /// Confidence at or above this stops querying
+    pub stop_threshold: f64,
+    /// Fewer selected handles than this are never high confidence and
+    /// never stop querying, however well they score
+    pub min_handles_for_high: usize,
+    /// Likewise for a lower `term_coverage`
+    pub min_term_coverage: f64,
+    /// Subtracted when the query result was truncated
+    pub truncation_penalty: f64,
+    /// Subtracted when matches were left out of the pack
+    pub overflow_penalty: f64,
+    /// Added when a definition of the queried symbol was selected
+    pub definition_boost: f64,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        Self {
+            medium_threshold: 0.35,
+            high_threshold: 0.70,
+            stop_threshold: 0.55,
+            min_handles_for_high: 1,
+            min_term_coverage: 0.0,
+            truncation_penalty: 0.10,
+            overflow_penalty: 0.0,
+            definition_boost: 0.0,
+        }
+    }
+}
+
+impl CalibrationConfig {
+    /// Names accepted by [`preset`](Self::preset)
+    pub const PRESETS: [&'static str; 3] = ["default", "strict", "lenient"];
+
+    /// A named calibration: `default`, `strict` (large repos; wants several
+    /// handles covering the query before stopping) or `lenient` (trusts a
+    /// definition hit).
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            "strict" => Some(Self {
+                medium_threshold: 0.40,
+                high_threshold: 0.75,
+                stop_threshold: 0.65,
+                min_handles_for_high: 3,
+                min_term_coverage: 0.75,
+                truncation_penalty: 0.20,
+                overflow_penalty: 0.10,
+                definition_boost: 0.0,
+            }),
+            "lenient" => Some(Self {
+                medium_threshold: 0.30,
+                high_threshold: 0.60,
+                stop_threshold: 0.45,
+                min_handles_for_high: 1,
+                min_term_coverage: 0.0,
+                truncation_penalty: 0.05,
+                overflow_penalty: 0.0,
+                definition_boost: 0.10,
+            }),
+            _ => None,
         }
     }
 }
 
+/// Minimum suggestion similarity for guidance to recommend retrying with it.
+const RETRY_SUGGESTION_SIMILARITY: f64 = 0.8;
+
+/// Maximum overflow sample paths in a pack.
+const OVERFLOW_SAMPLE_LIMIT: usize = 5;
+
+/// Fewer matches than this are a weak signal.
+const FEW_MATCHES: usize = 3;
+
+/// Confidence ceiling for widespread overflow: at most the medium band.
+const WIDESPREAD_CONFIDENCE_CAP: f64 = 0.65;
+
 /// Build a compact, ranked evidence pack from a query result.
 ///
 /// This keeps model context small by returning metadata and handle IDs only.
@@ -153,10 +289,49 @@ pub fn build_evidence_pack(
     query_text: &str,
     max_handles: usize,
     max_per_file: usize,
+) -> EvidencePack {
+    build_evidence_pack_with_priors(
+        result,
+        query_text,
+        max_handles,
+        max_per_file,
+        None,
+        &CalibrationConfig::default(),
+    )
+}
+
+/// [`build_evidence_pack`], ranking with per-query node type priors (see
+/// [`QueryParams::node_type_priors`](super::QueryParams::node_type_priors))
+/// and judging confidence by `calibration`.
+pub fn build_evidence_pack_with_priors(
+    result: &QueryResult,
+    query_text: &str,
+    max_handles: usize,
+    max_per_file: usize,
+    node_type_priors: Option<HashMap<NodeType, f64>>,
+    calibration: &CalibrationConfig,
 ) -> EvidencePack {
     if result.handles.is_empty() || max_handles == 0 || max_per_file == 0 {
-        let guidance = EvidenceGuidance::default();
+        let mut guidance = match result.suggestions.first() {
+            Some(top)
+                if result.handles.is_empty() && top.similarity >= RETRY_SUGGESTION_SIMILARITY =>
+            {
+                EvidenceGuidance {
+                    recommended_action: EvidenceAction::RetrySuggestion,
+                    max_additional_queries: 1,
+                    rationale: format!(
+                        "No symbol matched; '{}' is a close match ({} definitions).",
+                        top.name, top.definitions
+                    ),
+                    next_step: format!("Retry the query with symbol '{}'.", top.name),
+                    ..Default::default()
+                }
+            }
+            _ => EvidenceGuidance::default(),
+        };
+        apply_exclusion_guidance(&mut guidance, result.excluded_matches, result.total_matches);
         return EvidencePack {
+            schema_version: SchemaVersion,
             query_text: query_text.to_string(),
             total_matches: result.total_matches,
             truncated: result.truncated,
@@ -166,10 +341,17 @@ pub fn build_evidence_pack(
             files: Vec::new(),
             expand_suggestion: Vec::new(),
             guidance,
+            suggestions: result.suggestions.clone(),
+            overflow: EvidenceOverflow::default(),
+            overflow_samples: Vec::new(),
+            suppressed_by_policy: result.suppressed_by_policy,
+            excluded_matches: result.excluded_matches,
         };
     }
 
-    let scorer = HandleScorer::new(query_text);
+    let scorer = HandleScorer::new(query_text)
+        .with_node_type_priors(node_type_priors)
+        .with_fts_scores(&result.handles);
     let mut ranked: Vec<(usize, f64)> = result
         .handles
         .iter()
@@ -177,12 +359,12 @@ pub fn build_evidence_pack(
         .map(|(idx, h)| (idx, scorer.score(h)))
         .collect();
     ranked.sort_by(|a, b| {
-        b.1.total_cmp(&a.1).then_with(|| {
-            result.handles[a.0]
-                .token_count
-                .cmp(&result.handles[b.0].token_count)
-        })
+        let (ha, hb) = (&result.handles[a.0], &result.handles[b.0]);
+        b.1.total_cmp(&a.1)
+            .then_with(|| ha.token_count.cmp(&hb.token_count))
+            .then_with(|| ha.position_cmp(hb))
     });
+    let ranked_order: Vec<usize> = ranked.iter().map(|(idx, _)| *idx).collect();
 
     let mut file_counts: HashMap<&str, usize> = HashMap::new();
     let mut selected: Vec<(usize, f64)> = Vec::new();
@@ -211,6 +393,7 @@ pub fn build_evidence_pack(
                 source: h.source.clone(),
                 commit_sha: h.commit_sha.clone(),
                 generation: h.generation,
+                content_hash: h.content_hash.clone(),
                 score: *score,
             }
         })
@@ -240,16 +423,32 @@ pub fn build_evidence_pack(
         .take(6)
         .map(|h| h.id.clone())
         .collect::<Vec<_>>();
-    let guidance = build_evidence_guidance(
-        &selected,
-        handles.len(),
-        files.len(),
-        result.total_matches,
-        result.truncated,
-        max_handles.max(1),
-    );
+
+    let (overflow, overflow_samples) = summarize_overflow(result, &selected, &ranked_order);
+
+    let selected_handles: Vec<&Handle> = selected
+        .iter()
+        .map(|(idx, _)| &result.handles[*idx])
+        .collect();
+    let signals = PackSignals {
+        selected_count: handles.len(),
+        file_count: files.len(),
+        total_matches: result.total_matches,
+        excluded_matches: result.excluded_matches,
+        truncated: result.truncated,
+        overflowing: overflow.handles > 0,
+        max_handles: max_handles.max(1),
+        term_coverage: term_coverage(query_text, &selected_handles),
+        definition_present: selected_handles
+            .iter()
+            .any(|h| h.result_class == Some(ResultClass::Definition)),
+    };
+    let mut guidance = build_evidence_guidance(&selected, &signals, calibration);
+    apply_overflow_guidance(&mut guidance, overflow, files.len(), max_handles.max(1));
+    apply_exclusion_guidance(&mut guidance, result.excluded_matches, result.total_matches);
 
     EvidencePack {
+        schema_version: SchemaVersion,
         query_text: query_text.to_string(),
         total_matches: result.total_matches,
         truncated: result.truncated,
@@ -259,17 +458,177 @@ pub fn build_evidence_pack(
         files,
         expand_suggestion,
         guidance,
+        suggestions: Vec::new(),
+        overflow,
+        overflow_samples,
+        suppressed_by_policy: result.suppressed_by_policy,
+        excluded_matches: result.excluded_matches,
     }
 }
 
-fn build_evidence_guidance(
+/// Count what selection left out and sample overflow files for directory spread.
+///
+/// Samples take the best-ranked file from each directory in turn, so a pack
+/// whose handles cluster in one module still points at the others.
+fn summarize_overflow(
+    result: &QueryResult,
     selected: &[(usize, f64)],
+    ranked_order: &[usize],
+) -> (EvidenceOverflow, Vec<String>) {
+    let selected_files: HashSet<&str> = selected
+        .iter()
+        .map(|(idx, _)| result.handles[*idx].file_path.as_str())
+        .collect();
+
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut overflow_files: Vec<&str> = Vec::new();
+    for &idx in ranked_order {
+        let file = result.handles[idx].file_path.as_str();
+        if !selected_files.contains(file) && seen.insert(file) {
+            overflow_files.push(file);
+        }
+    }
+
+    let overflow = EvidenceOverflow {
+        files: overflow_files.len(),
+        handles: result
+            .total_matches
+            .max(result.handles.len())
+            .saturating_sub(selected.len()),
+    };
+
+    // Round-robin over directories in first-seen (rank) order
+    let mut by_dir: Vec<(&str, Vec<&str>)> = Vec::new();
+    for file in overflow_files {
+        let dir = Path::new(file)
+            .parent()
+            .and_then(Path::to_str)
+            .unwrap_or("");
+        match by_dir.iter_mut().find(|(d, _)| *d == dir) {
+            Some((_, files)) => files.push(file),
+            None => by_dir.push((dir, vec![file])),
+        }
+    }
+    let mut samples = Vec::new();
+    let mut round = 0;
+    while samples.len() < OVERFLOW_SAMPLE_LIMIT {
+        let before = samples.len();
+        for (_, files) in &by_dir {
+            if samples.len() >= OVERFLOW_SAMPLE_LIMIT {
+                break;
+            }
+            if let Some(file) = files.get(round) {
+                samples.push(file.to_string());
+            }
+        }
+        if samples.len() == before {
+            break;
+        }
+        round += 1;
+    }
+
+    (overflow, samples)
+}
+
+/// Steer widespread matches toward per-directory queries instead of answering
+/// from a pack that only shows a slice of them.
+fn apply_overflow_guidance(
+    guidance: &mut EvidenceGuidance,
+    overflow: EvidenceOverflow,
+    selected_files: usize,
+    max_handles: usize,
+) {
+    // Widespread: more files left out than the pack could show at all
+    if overflow.files < max_handles {
+        return;
+    }
+
+    let total_files = selected_files + overflow.files;
+    guidance.confidence = guidance.confidence.min(WIDESPREAD_CONFIDENCE_CAP);
+    if guidance.confidence_band == EvidenceConfidence::High {
+        guidance.confidence_band = EvidenceConfidence::Medium;
+    }
+    guidance.stop_querying = false;
+    guidance.recommended_action = EvidenceAction::NarrowScope;
+    guidance.max_additional_queries = guidance.max_additional_queries.max(3);
+    guidance.rationale = format!(
+        "Matches are widespread ({} files, {} handles not shown); the pack holds only the top {}.",
+        total_files, overflow.handles, max_handles
+    );
+    guidance.next_step = format!(
+        "Matches are widespread ({} files); narrow by directory or iterate per-module before answering.",
+        total_files
+    );
+}
+
+/// Say when exclude patterns account for a thin result, so a low count
+/// reads as the caller's narrowing rather than a query that missed.
+fn apply_exclusion_guidance(
+    guidance: &mut EvidenceGuidance,
+    excluded_matches: usize,
+    total_matches: usize,
+) {
+    if excluded_matches == 0 || total_matches >= FEW_MATCHES {
+        return;
+    }
+    guidance.rationale = format!(
+        "{} Exclude patterns removed {} matches.",
+        guidance.rationale, excluded_matches
+    );
+    if total_matches == 0 && guidance.recommended_action == EvidenceAction::RefineQuery {
+        guidance.next_step =
+            "Loosen exclude_patterns if they removed what you need; otherwise refine the query."
+                .to_string();
+    }
+}
+
+/// What guidance knows about a pack besides its selected scores.
+struct PackSignals {
     selected_count: usize,
     file_count: usize,
     total_matches: usize,
+    excluded_matches: usize,
     truncated: bool,
+    /// Matches were left out of the pack
+    overflowing: bool,
     max_handles: usize,
+    term_coverage: f64,
+    definition_present: bool,
+}
+
+/// Share of `query_text`'s terms found in `handles`' paths and previews.
+fn term_coverage(query_text: &str, handles: &[&Handle]) -> f64 {
+    let terms = split_terms(query_text);
+    if terms.is_empty() {
+        return 1.0;
+    }
+    let haystacks: Vec<String> = handles
+        .iter()
+        .map(|h| format!("{} {}", h.file_path, h.preview).to_lowercase())
+        .collect();
+    let covered = terms
+        .iter()
+        .filter(|term| haystacks.iter().any(|hay| hay.contains(term.as_str())))
+        .count();
+    covered as f64 / terms.len() as f64
+}
+
+fn build_evidence_guidance(
+    selected: &[(usize, f64)],
+    signals: &PackSignals,
+    calibration: &CalibrationConfig,
 ) -> EvidenceGuidance {
+    let &PackSignals {
+        selected_count,
+        file_count,
+        total_matches,
+        excluded_matches,
+        truncated,
+        overflowing,
+        max_handles,
+        term_coverage,
+        definition_present,
+    } = signals;
     if selected_count == 0 {
         return EvidenceGuidance {
             rationale: "No ranked evidence available for this query.".to_string(),
@@ -284,28 +643,52 @@ fn build_evidence_guidance(
     let avg_top_score = selected.iter().take(top_n).map(|(_, s)| *s).sum::<f64>() / top_n as f64;
     let file_coverage = (file_count.min(3) as f64) / 3.0;
     let fill_ratio = (selected_count as f64 / max_handles as f64).min(1.0);
+    // Matches the caller excluded still show the query found its topic
+    let signal_matches = total_matches + excluded_matches;
     let match_signal = if total_matches == 0 {
         0.0
-    } else if total_matches < 3 {
+    } else if signal_matches < FEW_MATCHES {
         0.4
     } else {
         1.0
     };
-    let truncation_penalty = if truncated { 0.10 } else { 0.0 };
-    let confidence =
-        (0.55 * avg_top_score + 0.20 * file_coverage + 0.15 * fill_ratio + 0.10 * match_signal
-            - truncation_penalty)
-            .clamp(0.0, 1.0);
+    let truncation_penalty = if truncated {
+        calibration.truncation_penalty
+    } else {
+        0.0
+    };
+    let overflow_penalty = if overflowing {
+        calibration.overflow_penalty
+    } else {
+        0.0
+    };
+    let definition_boost = if definition_present {
+        calibration.definition_boost
+    } else {
+        0.0
+    };
+    let confidence = (0.55 * avg_top_score
+        + 0.20 * file_coverage
+        + 0.15 * fill_ratio
+        + 0.10 * match_signal
+        + definition_boost
+        - truncation_penalty
+        - overflow_penalty)
+        .clamp(0.0, 1.0);
 
-    let confidence_band = if confidence < 0.35 {
+    // A pack too thin to be conclusive tops out at the medium band
+    let conclusive = selected_count >= calibration.min_handles_for_high
+        && term_coverage >= calibration.min_term_coverage;
+    let confidence_band = if confidence < calibration.medium_threshold {
         EvidenceConfidence::Low
-    } else if confidence < 0.70 {
+    } else if confidence < calibration.high_threshold || !conclusive {
         EvidenceConfidence::Medium
     } else {
         EvidenceConfidence::High
     };
 
-    let stop_querying = confidence >= 0.55 || (selected_count >= 4 && file_count >= 2);
+    let stop_querying = conclusive
+        && (confidence >= calibration.stop_threshold || (selected_count >= 4 && file_count >= 2));
     let suggested_expand_count = if confidence >= 0.75 {
         selected_count.min(2)
     } else if confidence >= 0.50 {
@@ -354,6 +737,9 @@ fn build_evidence_guidance(
         max_additional_queries,
         rationale,
         next_step,
+        term_coverage,
+        definition_present,
+        result_density: fill_ratio,
     }
 }
 
@@ -379,6 +765,27 @@ mod tests {
         )
     }
 
+    /// Signals of a pack with full term coverage, no exclusions or overflow
+    fn signals(
+        selected_count: usize,
+        file_count: usize,
+        total_matches: usize,
+        truncated: bool,
+        max_handles: usize,
+    ) -> PackSignals {
+        PackSignals {
+            selected_count,
+            file_count,
+            total_matches,
+            excluded_matches: 0,
+            truncated,
+            overflowing: false,
+            max_handles,
+            term_coverage: 1.0,
+            definition_present: false,
+        }
+    }
+
     fn make_query_result(handles: Vec<Handle>) -> QueryResult {
         let total_tokens = handles.iter().map(|h| h.token_count).sum();
         let total_matches = handles.len();
@@ -407,6 +814,38 @@ mod tests {
         assert!(!pack.guidance.stop_querying);
     }
 
+    #[test]
+    fn build_evidence_pack_prefers_full_text_scores() {
+        // Both previews name every term; only the FTS score tells them apart
+        let mut incidental = make_handle(
+            "src/a_server.rs",
+            NodeType::Function,
+            0..200,
+            40,
+            "fn serve() { // ... connection pool settings ...",
+        );
+        incidental.score = Some(0.4);
+        let mut repeated = make_handle(
+            "src/z_pool.rs",
+            NodeType::Function,
+            0..200,
+            40,
+            "fn checkout() { // The connection pool hands out a connection",
+        );
+        repeated.score = Some(1.6);
+        let result = make_query_result(vec![incidental, repeated]);
+
+        let pack = build_evidence_pack(&result, "connection pool", 10, 3);
+        assert_eq!(pack.handles[0].file_path, "src/z_pool.rs");
+        assert!(pack.handles[0].score > pack.handles[1].score);
+
+        // Unscored handles fall back to query terms in the preview, then position
+        let mut unscored = result;
+        unscored.handles.iter_mut().for_each(|h| h.score = None);
+        let pack = build_evidence_pack(&unscored, "connection pool", 10, 3);
+        assert_eq!(pack.handles[0].file_path, "src/a_server.rs");
+    }
+
     #[test]
     fn build_evidence_pack_respects_max_per_file() {
         // 4 handles from the same file, max_per_file=2 should keep only 2
@@ -453,17 +892,131 @@ mod tests {
         assert_eq!(file_a.total_tokens, 100); // 30 + 70
     }
 
+    #[test]
+    fn broad_matches_report_overflow_and_narrow_scope() {
+        // "handle_error" in 30 files spread over three modules
+        let handles = (0..30)
+            .map(|i| {
+                let module = ["api", "db", "worker"][i % 3];
+                make_handle(
+                    &format!("src/{module}/file_{i}.rs"),
+                    NodeType::Function,
+                    0..50,
+                    40,
+                    "fn handle_error() -> Result<()>",
+                )
+            })
+            .collect();
+        let result = make_query_result(handles);
+        let pack = build_evidence_pack(&result, "handle_error", 8, 2);
+
+        assert_eq!(pack.selected_count, 8);
+        assert_eq!(
+            pack.overflow,
+            EvidenceOverflow {
+                files: 22,
+                handles: 22
+            }
+        );
+
+        // Samples are unselected files drawn from every module
+        assert_eq!(pack.overflow_samples.len(), OVERFLOW_SAMPLE_LIMIT);
+        for module in ["src/api/", "src/db/", "src/worker/"] {
+            assert!(pack.overflow_samples.iter().any(|p| p.starts_with(module)));
+        }
+        assert!(pack
+            .overflow_samples
+            .iter()
+            .all(|p| !pack.files.iter().any(|f| &f.file_path == p)));
+
+        assert_eq!(
+            pack.guidance.recommended_action,
+            EvidenceAction::NarrowScope
+        );
+        assert!(!pack.guidance.stop_querying);
+        assert_ne!(pack.guidance.confidence_band, EvidenceConfidence::High);
+        assert!(pack.guidance.confidence <= WIDESPREAD_CONFIDENCE_CAP);
+        assert!(pack.guidance.next_step.contains("30 files"));
+    }
+
+    #[test]
+    fn exclusions_explain_thin_packs() {
+        let handle = make_handle("src/retry.rs", NodeType::Function, 0..50, 40, "fn retry()");
+        let plain = build_evidence_pack(&make_query_result(vec![handle.clone()]), "retry", 8, 2);
+        let excluded = build_evidence_pack(
+            &QueryResult {
+                excluded_matches: 5,
+                ..make_query_result(vec![handle])
+            },
+            "retry",
+            8,
+            2,
+        );
+        assert_eq!(excluded.excluded_matches, 5);
+        assert!(excluded.guidance.rationale.contains("removed 5 matches"));
+        // The one match left is the caller's narrowing, not a weak query
+        assert!(excluded.guidance.confidence > plain.guidance.confidence);
+        assert!(!plain.guidance.rationale.contains("Exclude"));
+
+        let empty = build_evidence_pack(
+            &QueryResult {
+                excluded_matches: 3,
+                ..QueryResult::default()
+            },
+            "retry",
+            8,
+            2,
+        );
+        assert!(empty.guidance.rationale.contains("removed 3 matches"));
+        assert!(empty.guidance.next_step.contains("exclude_patterns"));
+    }
+
+    #[test]
+    fn small_overflow_keeps_normal_guidance() {
+        let handles = (0..4)
+            .map(|i| {
+                make_handle(
+                    &format!("src/auth_{i}.rs"),
+                    NodeType::Function,
+                    0..50,
+                    40,
+                    "fn auth_check()",
+                )
+            })
+            .collect();
+        let result = make_query_result(handles);
+        let pack = build_evidence_pack(&result, "auth_check", 3, 1);
+
+        assert_eq!(
+            pack.overflow,
+            EvidenceOverflow {
+                files: 1,
+                handles: 1
+            }
+        );
+        assert_eq!(pack.overflow_samples.len(), 1);
+        assert!(!pack
+            .files
+            .iter()
+            .any(|f| f.file_path == pack.overflow_samples[0]));
+        assert_ne!(
+            pack.guidance.recommended_action,
+            EvidenceAction::NarrowScope
+        );
+    }
+
     #[test]
     fn guidance_confidence_bands_are_correct() {
         // Zero selected -> default guidance
-        let g0 = build_evidence_guidance(&[], 0, 0, 0, false, 10);
+        let defaults = CalibrationConfig::default();
+        let g0 = build_evidence_guidance(&[], &signals(0, 0, 0, false, 10), &defaults);
         assert_eq!(g0.confidence_band, EvidenceConfidence::Low);
         assert!(!g0.stop_querying);
         assert_eq!(g0.recommended_action, EvidenceAction::RefineQuery);
 
         // High scores, multiple files, good fill -> High confidence
         let selected: Vec<(usize, f64)> = vec![(0, 0.95), (1, 0.90), (2, 0.85), (3, 0.80)];
-        let g_high = build_evidence_guidance(&selected, 4, 3, 10, false, 4);
+        let g_high = build_evidence_guidance(&selected, &signals(4, 3, 10, false, 4), &defaults);
         assert!(
             g_high.confidence >= 0.70,
             "expected High band, got {:.2}",
@@ -476,7 +1029,7 @@ mod tests {
 
         // Low scores, single file, sparse matches -> Low/Medium
         let selected_low: Vec<(usize, f64)> = vec![(0, 0.15)];
-        let g_low = build_evidence_guidance(&selected_low, 1, 1, 1, true, 10);
+        let g_low = build_evidence_guidance(&selected_low, &signals(1, 1, 1, true, 10), &defaults);
         assert!(
             g_low.confidence < 0.35,
             "expected Low band, got {:.2}",
@@ -485,6 +1038,77 @@ mod tests {
         assert_eq!(g_low.confidence_band, EvidenceConfidence::Low);
     }
 
+    fn preset_pack(result: &QueryResult, query_text: &str, preset: &str) -> EvidencePack {
+        let calibration = CalibrationConfig::preset(preset).unwrap();
+        build_evidence_pack_with_priors(result, query_text, 8, 2, None, &calibration)
+    }
+
+    #[test]
+    fn presets_disagree_on_a_single_definition_hit() {
+        let mut handle = make_handle(
+            "src/retry.rs",
+            NodeType::Function,
+            0..50,
+            40,
+            "fn retry_with_backoff()",
+        );
+        handle.result_class = Some(ResultClass::Definition);
+        let result = make_query_result(vec![handle]);
+
+        let lenient = preset_pack(&result, "retry_with_backoff", "lenient");
+        assert!(lenient.guidance.definition_present);
+        assert_eq!(lenient.guidance.term_coverage, 1.0);
+        assert_eq!(lenient.guidance.result_density, 1.0 / 8.0);
+        assert_eq!(lenient.guidance.confidence_band, EvidenceConfidence::High);
+        assert!(lenient.guidance.stop_querying);
+
+        // One lucky hit is not enough evidence on a large repo
+        let strict = preset_pack(&result, "retry_with_backoff", "strict");
+        assert_eq!(strict.guidance.confidence_band, EvidenceConfidence::Medium);
+        assert!(!strict.guidance.stop_querying);
+        assert_eq!(
+            strict.guidance.recommended_action,
+            EvidenceAction::RefineQuery
+        );
+        assert!(strict.guidance.confidence < lenient.guidance.confidence);
+    }
+
+    #[test]
+    fn strict_preset_wants_the_query_covered() {
+        let handles: Vec<Handle> = ["src/a.rs", "src/a.rs", "src/b.rs", "src/b.rs"]
+            .iter()
+            .enumerate()
+            .map(|(i, file)| {
+                let start = i * 100;
+                make_handle(
+                    file,
+                    NodeType::Function,
+                    start..start + 50,
+                    30,
+                    &format!("fn retry_{i}()"),
+                )
+            })
+            .collect();
+        let result = make_query_result(handles);
+
+        // Only one of three terms turns up anywhere in the pack
+        let lenient = preset_pack(&result, "retry jitter backoff", "lenient");
+        assert!((lenient.guidance.term_coverage - 1.0 / 3.0).abs() < 1e-9);
+        assert!(!lenient.guidance.definition_present);
+        assert!(lenient.guidance.stop_querying);
+        let defaults = preset_pack(&result, "retry jitter backoff", "default");
+        assert!(defaults.guidance.stop_querying);
+
+        let strict = preset_pack(&result, "retry jitter backoff", "strict");
+        assert!(!strict.guidance.stop_querying);
+        assert_ne!(strict.guidance.confidence_band, EvidenceConfidence::High);
+
+        // Covering the terms satisfies it
+        let covered = preset_pack(&result, "retry", "strict");
+        assert_eq!(covered.guidance.term_coverage, 1.0);
+        assert!(covered.guidance.stop_querying);
+    }
+
     #[test]
     fn reorder_expand_suggestions_demotes_recent() {
         let handles = vec![
@@ -497,6 +1121,7 @@ mod tests {
                 source: HandleSource::Local,
                 commit_sha: None,
                 generation: None,
+                content_hash: None,
                 score: 0.9,
             },
             EvidenceHandle {
@@ -508,11 +1133,13 @@ mod tests {
                 source: HandleSource::Local,
                 commit_sha: None,
                 generation: None,
+                content_hash: None,
                 score: 0.8,
             },
         ];
 
         let mut pack = EvidencePack {
+            schema_version: SchemaVersion,
             query_text: "test".to_string(),
             total_matches: 2,
             truncated: false,
@@ -522,6 +1149,11 @@ mod tests {
             files: Vec::new(),
             expand_suggestion: vec!["a".to_string(), "b".to_string()],
             guidance: EvidenceGuidance::default(),
+            suggestions: Vec::new(),
+            overflow: EvidenceOverflow::default(),
+            overflow_samples: Vec::new(),
+            suppressed_by_policy: 0,
+            excluded_matches: 0,
         };
 
         // "a" was recently expanded, so it should be demoted
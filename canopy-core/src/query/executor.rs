@@ -2,19 +2,45 @@
 
 use crate::error::CanopyError;
 use crate::handle::Handle;
-use crate::index::RepoIndex;
+use crate::index::files::page_files;
+use crate::index::sharding::interleave;
+use crate::index::tokens::{
+    is_high_frequency, HIGH_FREQUENCY_MIN_MATCHES, HIGH_FREQUENCY_NODE_FRACTION,
+};
+use crate::index::{
+    sort_suggestions, FilePage, FileQueryOptions, GeneratedScope, PathScope, RecentScope,
+    RepoIndex, SymbolSuggestion, MAX_SYMBOL_SUGGESTIONS,
+};
 use crate::parse::estimate_tokens;
+use crate::schema::SchemaVersion;
 use crate::scoring::{select_for_expansion, HandleScorer};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
 
-use super::dsl::Query;
+use super::count::dsl;
+use super::dsl::{FileSlice, Query};
+use super::explain::{ExplainCollector, SearchExplain, SearchPath};
+use super::infer_glob::{dropped_glob_note, infer_glob};
+use super::matches::annotate_match_lines;
 use super::params::split_terms;
-use super::QueryOptions;
-use super::QueryResult;
+use super::references::group_references;
+use super::rerank::apply_reranker;
+use super::result_class::order_by_class;
+use super::savings::token_savings;
+use super::{PatternError, QueryParams, QueryResult};
+use super::{QueryMode, QueryOptions};
 
 /// Default expand budget for optional auto-expansion.
 pub const DEFAULT_EXPAND_BUDGET: usize = 0;
 
+/// Most candidates an exclusion checks by reading their indexed text; more
+/// ask the full-text index which of them match instead
+const EXCLUDE_SCAN_MAX_CANDIDATES: usize = 64;
+
+/// Most candidates a filtered search (exclusions, a glob checked after the
+/// search, `match: all`) fetches while refilling its limit
+const FILTER_MAX_CANDIDATES: usize = 2_000;
+
 /// Execute a query against the index
 pub fn execute_query(
     query: &Query,
@@ -28,6 +54,13 @@ pub fn execute_query(
             limit: limit_override,
             expand_budget: None,
             node_type_priors: None,
+            reranker: None,
+            files: Default::default(),
+            mode: QueryMode::Handles,
+            group_references: None,
+            include_generated: false,
+            explain: false,
+            path_predicate: None,
         },
     )
 }
@@ -37,22 +70,192 @@ pub fn execute_query_with_options(
     query: &Query,
     index: &RepoIndex,
     options: QueryOptions,
+) -> crate::Result<QueryResult> {
+    let mut result = execute_query_scoped(query, index, options)?;
+    result.compat_mode = index.compat_mode();
+    Ok(result)
+}
+
+/// Execute `params` with `options`. With `infer_glob` set, the query runs
+/// first within the glob inferred from its terms, then, if nothing matched
+/// there, over the whole repo with a note saying the scope was dropped.
+pub fn execute_params(
+    params: &QueryParams,
+    index: &RepoIndex,
+    options: QueryOptions,
+) -> crate::Result<QueryResult> {
+    let Some(glob) = infer_glob(params, index)? else {
+        return execute_query_with_options(&params.to_query()?, index, options);
+    };
+    let scoped = params.clone().with_glob(glob.clone());
+    let mut result = execute_query_with_options(&scoped.to_query()?, index, options.clone())?;
+    if result.total_matches > 0 {
+        result.inferred_glob = Some(glob);
+        return Ok(result);
+    }
+    let mut result = execute_query_with_options(&params.to_query()?, index, options)?;
+    let note = dropped_glob_note(&glob);
+    result.expand_note = Some(match result.expand_note.take() {
+        Some(existing) => format!("{note} {existing}"),
+        None => note,
+    });
+    Ok(result)
+}
+
+fn execute_query_scoped(
+    query: &Query,
+    index: &RepoIndex,
+    options: QueryOptions,
+) -> crate::Result<QueryResult> {
+    // A top-level `(limit N ...)` is the result limit, whether it came from
+    // the DSL or from params, so every path below applies it the same way;
+    // count mode caps its counts with it instead
+    if let (true, Query::Limit(n, inner)) = (options.mode.is_handles(), query) {
+        let limit = options.limit.map_or(*n, |limit| limit.min(*n));
+        let options = QueryOptions {
+            limit: Some(limit),
+            ..options
+        };
+        return execute_query_scoped(inner, index, options);
+    }
+    // A top-level window scopes every database the query reads, so the
+    // reference, annotation and file paths below honor it too
+    if let Some((window, inner)) = split_recent(query) {
+        let _scope = RecentScope::new(index.all_indexes(), window);
+        return execute_query_scoped(&inner, index, options);
+    }
+    // Likewise a path fragment, which also lets `(path "x")` page its files
+    if let Some((fragment, inner)) = split_path(query) {
+        let _scope = PathScope::new(index.all_indexes(), &fragment);
+        return execute_query_scoped(&inner, index, options);
+    }
+    // Commits aren't files, so the generated scope and savings don't apply
+    if let Some((terms, limit)) = commit_query(query) {
+        return execute_commits(query, terms, limit, index, &options);
+    }
+    let generated = if options.include_generated {
+        None
+    } else {
+        Some(GeneratedScope::exclude(index.all_indexes())?)
+    };
+    if !options.mode.is_handles() {
+        let permits = options.path_predicate.as_deref();
+        return super::count::execute_count(query, index, options.mode, permits);
+    }
+    let permits = options.path_predicate.clone();
+    let mut result = execute_query_unmeasured(query, index, options)?;
+    if let Some(scope) = generated.as_ref().filter(|s| s.hides_any()) {
+        result.suppressed_generated = scope
+            .with_only_hidden(|| {
+                super::count::execute_count(query, index, QueryMode::Count, permits.as_deref())
+            })?
+            .total_matches;
+    }
+    result.savings = token_savings(index, &result)?;
+    Ok(result)
+}
+
+fn execute_query_unmeasured(
+    query: &Query,
+    index: &RepoIndex,
+    options: QueryOptions,
 ) -> crate::Result<QueryResult> {
     let default_limit = index.default_limit();
-    let effective_limit = options.limit.unwrap_or(default_limit);
+    let mut effective_limit = options.limit.unwrap_or(default_limit);
+    let mut explain = ExplainCollector::new(options.explain);
 
-    if let Query::References(symbol) = query {
-        let mut refs = index.search_references(symbol, effective_limit * 2)?;
+    if let Query::References(symbol, ref_types) = query {
+        let targets = index.query_targets(None);
+        let per_shard = targets
+            .iter()
+            .map(|target| {
+                let refs = target.search_references(symbol, ref_types, effective_limit * 2)?;
+                let search = SearchExplain::new(SearchPath::Refs, symbol, effective_limit * 2);
+                explain.record(target, search, refs.len());
+                Ok(refs)
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+        let mut ref_type_counts: BTreeMap<String, usize> = BTreeMap::new();
+        for target in &targets {
+            for (ref_type, count) in target.ref_type_counts(symbol)? {
+                *ref_type_counts.entry(ref_type).or_default() += count;
+            }
+        }
+        let mut refs = group_references(interleave(per_shard), options.group_references);
         let total_matches = refs.len();
         let truncated = refs.len() > effective_limit;
         refs.truncate(effective_limit);
 
         let total_tokens = refs.iter().map(|r| estimate_tokens(&r.preview)).sum();
+        let explain = explain.finish(query, index, &[], !refs.is_empty(), None);
 
         return Ok(QueryResult {
+            schema_version: SchemaVersion,
             handles: Vec::new(),
             ref_handles: Some(refs),
+            ref_type_counts: Some(ref_type_counts),
+            annotations: None,
+            commits: None,
+            total_tokens,
+            preview_tokens: 0,
+            truncated,
+            total_matches,
+            auto_expanded: false,
+            expand_note: None,
+            expanded_count: 0,
+            expanded_tokens: 0,
+            expanded_handle_ids: Vec::new(),
+            suppressed_service_handles: 0,
+            suppressed_by_policy: 0,
+            suppressed_generated: 0,
+            excluded_matches: 0,
+            redactions: 0,
+            suggestions: Vec::new(),
+            savings: None,
+            sources: None,
+            mode: QueryMode::Handles,
+            exists: None,
+            witness_path: None,
+            match_counts: None,
+            pattern_errors: Vec::new(),
+            explain,
+            compat_mode: false,
+            truncated_scan: false,
+            inferred_glob: None,
+        });
+    }
+
+    if let Some((terms, glob, limit)) = annotation_query(query) {
+        let limit = limit.map_or(effective_limit, |l| l.min(effective_limit));
+        let per_shard = index
+            .query_targets(glob)
+            .into_iter()
+            .map(|target| {
+                let annotations = target.search_annotations(terms, glob, limit + 1)?;
+                let mut search = SearchExplain::new(SearchPath::Annotations, terms, limit + 1);
+                search.glob = glob.map(str::to_string);
+                explain.record(target, search, annotations.len());
+                Ok(annotations)
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+        let mut annotations: Vec<_> = per_shard.into_iter().flatten().collect();
+        annotations.sort_by(|a, b| (&a.file_path, a.line).cmp(&(&b.file_path, b.line)));
+        let total_matches = annotations.len();
+        let truncated = annotations.len() > limit;
+        annotations.truncate(limit);
+
+        let total_tokens = annotations.iter().map(|a| estimate_tokens(&a.text)).sum();
+        let explain = explain.finish(query, index, &[], !annotations.is_empty(), None);
+
+        return Ok(QueryResult {
+            schema_version: SchemaVersion,
+            handles: Vec::new(),
+            ref_handles: None,
+            ref_type_counts: None,
+            annotations: Some(annotations),
+            commits: None,
             total_tokens,
+            preview_tokens: 0,
             truncated,
             total_matches,
             auto_expanded: false,
@@ -60,34 +263,99 @@ pub fn execute_query_with_options(
             expanded_count: 0,
             expanded_tokens: 0,
             expanded_handle_ids: Vec::new(),
+            suppressed_service_handles: 0,
+            suppressed_by_policy: 0,
+            suppressed_generated: 0,
+            excluded_matches: 0,
+            redactions: 0,
+            suggestions: Vec::new(),
+            savings: None,
+            sources: None,
+            mode: QueryMode::Handles,
+            exists: None,
+            witness_path: None,
+            match_counts: None,
+            pattern_errors: Vec::new(),
+            explain,
+            compat_mode: false,
+            truncated_scan: false,
+            inferred_glob: None,
         });
     }
 
-    let handles = dedupe_handles(execute_query_internal(query, index, effective_limit * 2)?);
+    // A top-level file query pages across shards in path order; everything
+    // else fans out to every database the query's glob can reach (just
+    // `index` when unsharded)
+    let mut file_page = None;
+    let mut pattern_errors = Vec::new();
+    let mut handles = if let Some((glob, slice, limit)) = file_query(query) {
+        let limit = limit.map_or(effective_limit, |l| l.min(effective_limit));
+        let files = file_options(slice, &options.files, limit, index);
+        let mut page = page_files(&index.query_targets(Some(glob)), glob, &files)?;
+        let handles = std::mem::take(&mut page.handles);
+        file_page = Some((files.offset, page));
+        let search = SearchExplain::new(SearchPath::File, glob, limit);
+        explain.tagged(index, search, handles)
+    } else if let Some((path, limit)) = related_query(query) {
+        // Relating reads every database itself, so it runs once here
+        effective_limit = limit.map_or(effective_limit, |l| l.min(effective_limit));
+        let search = SearchExplain::new(SearchPath::Related, path, effective_limit + 1);
+        explain.tagged(
+            index,
+            search,
+            index.related_file_handles(path, effective_limit + 1)?,
+        )
+    } else {
+        let per_shard = index
+            .query_targets(query_glob(query))
+            .into_iter()
+            .map(|target| {
+                execute_query_internal(
+                    query,
+                    target,
+                    effective_limit * 2,
+                    &options.files,
+                    &mut pattern_errors,
+                    &mut explain,
+                )
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+        let handles = order_by_score(dedupe_handles(interleave(per_shard)));
+        order_by_class(handles, effective_limit, &index.config().ranking)
+    };
+    // Every shard reports the same malformed pattern
+    let mut seen_patterns = HashSet::new();
+    pattern_errors.retain(|e: &PatternError| seen_patterns.insert(e.pattern.clone()));
+    let rerank_note = options.reranker.as_deref().and_then(|reranker| {
+        apply_reranker(
+            reranker,
+            &extract_query_terms(query).join(" "),
+            &mut handles,
+        )
+    });
 
-    let total_matches = handles.len();
-    let truncated = handles.len() > effective_limit;
+    let (total_matches, truncated) = match &file_page {
+        Some((_, page)) => (page.total_matches, page.truncated()),
+        None => (handles.len(), handles.len() > effective_limit),
+    };
 
     let mut handles: Vec<Handle> = handles.into_iter().take(effective_limit).collect();
+    annotate_match_lines(index, query, &mut handles)?;
     let total_tokens: usize = handles.iter().map(|h| h.token_count).sum();
+    let preview_tokens = handles.iter().map(|h| h.preview_tokens).sum();
 
     let mut expanded_count = 0usize;
     let mut expanded_tokens = 0usize;
+    let mut redactions = 0usize;
 
     // Auto-expand if budget allows
     let expand_budget = options.expand_budget.unwrap_or(0);
     let (auto_expanded, expand_note) = if expand_budget > 0 {
         if total_tokens <= expand_budget {
             // Expand all handles
-            let handle_ids: Vec<String> = handles.iter().map(|h| h.id.to_string()).collect();
-            if let Ok(contents) = index.expand(&handle_ids) {
-                let content_map: std::collections::HashMap<String, String> =
-                    contents.into_iter().collect();
-                for handle in &mut handles {
-                    if let Some(content) = content_map.get(&handle.id.to_string()) {
-                        handle.content = Some(content.clone());
-                    }
-                }
+            let all: Vec<usize> = (0..handles.len()).collect();
+            if let Ok(redacted) = fill_contents(index, &mut handles, &all) {
+                redactions = redacted;
                 (expanded_count, expanded_tokens) = expanded_stats(&handles);
                 (true, None)
             } else {
@@ -96,7 +364,8 @@ pub fn execute_query_with_options(
         } else {
             let query_text = extract_query_terms(query).join(" ");
             let scorer = HandleScorer::new(&query_text)
-                .with_node_type_priors(options.node_type_priors.clone());
+                .with_node_type_priors(options.node_type_priors.clone())
+                .with_fts_scores(&handles);
             let selected = select_for_expansion(&handles, expand_budget, &scorer);
 
             if selected.is_empty() {
@@ -109,20 +378,8 @@ pub fn execute_query_with_options(
                     )),
                 )
             } else {
-                let handle_ids: Vec<String> = selected
-                    .iter()
-                    .map(|idx| handles[*idx].id.to_string())
-                    .collect();
-
-                if let Ok(contents) = index.expand(&handle_ids) {
-                    let content_map: std::collections::HashMap<String, String> =
-                        contents.into_iter().collect();
-                    for idx in selected {
-                        let id = handles[idx].id.to_string();
-                        if let Some(content) = content_map.get(&id) {
-                            handles[idx].content = Some(content.clone());
-                        }
-                    }
+                if let Ok(redacted) = fill_contents(index, &mut handles, &selected) {
+                    redactions = redacted;
                     (expanded_count, expanded_tokens) = expanded_stats(&handles);
                     (
                         false,
@@ -143,12 +400,52 @@ pub fn execute_query_with_options(
         (false, None)
     };
 
+    let page_note = file_page
+        .as_ref()
+        .and_then(|(offset, page)| file_page_note(*offset, page));
+    let truncated_scan = explain.truncated_scan();
+    let scan_note = truncated_scan.then(|| truncated_scan_note(index));
+    let glob_note = match query_glob(query) {
+        Some(glob) if handles.is_empty() => unmatched_glob_note(glob, index)?,
+        _ => None,
+    };
+    let notes: Vec<String> = [
+        expand_note,
+        high_frequency_note(query, index)?,
+        scan_note,
+        glob_note,
+        rerank_note,
+        page_note,
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let expand_note = (!notes.is_empty()).then(|| notes.join(" "));
+
+    let excluded_matches = explain.excluded_matches();
+    let explain = explain.finish(
+        query,
+        index,
+        &handles,
+        !handles.is_empty(),
+        options.node_type_priors.as_ref(),
+    );
     let expanded_handle_ids = expanded_handle_ids(&handles);
+    let suggestions = if handles.is_empty() {
+        symbol_suggestions(query, index)
+    } else {
+        Vec::new()
+    };
 
     Ok(QueryResult {
+        schema_version: SchemaVersion,
         handles,
         ref_handles: None,
+        ref_type_counts: None,
+        annotations: None,
+        commits: None,
         total_tokens,
+        preview_tokens,
         truncated,
         total_matches,
         auto_expanded,
@@ -156,9 +453,288 @@ pub fn execute_query_with_options(
         expanded_count,
         expanded_tokens,
         expanded_handle_ids,
+        suppressed_service_handles: 0,
+        suppressed_by_policy: 0,
+        suppressed_generated: 0,
+        excluded_matches,
+        redactions,
+        suggestions,
+        savings: None,
+        sources: None,
+        mode: QueryMode::Handles,
+        exists: None,
+        witness_path: None,
+        match_counts: None,
+        pattern_errors,
+        explain,
+        compat_mode: false,
+        truncated_scan,
+        inferred_glob: None,
+    })
+}
+
+/// "Did you mean" names for an empty symbol query; pattern/FTS queries get none
+/// since an empty result there is a meaningful answer.
+fn symbol_suggestions(query: &Query, index: &RepoIndex) -> Vec<SymbolSuggestion> {
+    let symbol = match query {
+        Query::Code(symbol) | Query::Definition(symbol) => symbol,
+        Query::Limit(_, inner) | Query::InFile(_, inner) | Query::Exclude(_, inner) => {
+            return symbol_suggestions(inner, index)
+        }
+        _ => return Vec::new(),
+    };
+
+    let threshold = index.config().core.suggestion_threshold;
+    let mut suggestions: Vec<SymbolSuggestion> = Vec::new();
+    for target in index.all_indexes() {
+        for suggestion in target.suggest_symbols(symbol, threshold, MAX_SYMBOL_SUGGESTIONS) {
+            match suggestions.iter_mut().find(|s| s.name == suggestion.name) {
+                Some(existing) => existing.definitions += suggestion.definitions,
+                None => suggestions.push(suggestion),
+            }
+        }
+    }
+    sort_suggestions(&mut suggestions);
+    suggestions.truncate(MAX_SYMBOL_SUGGESTIONS);
+    suggestions
+}
+
+/// Warn when a single-term grep is a high-frequency token that matches most nodes.
+fn high_frequency_note(query: &Query, index: &RepoIndex) -> crate::Result<Option<String>> {
+    let pattern = match query {
+        Query::Grep(pattern) => pattern,
+        Query::Limit(_, inner) | Query::Exclude(_, inner) => {
+            return high_frequency_note(inner, index)
+        }
+        _ => return Ok(None),
+    };
+    let terms = split_terms(pattern);
+    let [term] = terms.as_slice() else {
+        return Ok(None);
+    };
+
+    let targets = index.query_targets(None);
+    let mut total_nodes = 0;
+    for target in &targets {
+        total_nodes += target.node_count()?;
+    }
+    let cap = HIGH_FREQUENCY_MIN_MATCHES.max(total_nodes.div_ceil(HIGH_FREQUENCY_NODE_FRACTION));
+    let mut match_count = 0;
+    for target in &targets {
+        match_count += target.fts_match_count(term, cap)?;
+    }
+    let match_count = match_count.min(cap);
+    if !is_high_frequency(term, match_count, total_nodes) {
+        return Ok(None);
+    }
+    index.record_guarded();
+
+    Ok(Some(format!(
+        "'{term}' is a high-frequency token ({match_count}+ matching nodes); add a second term or a glob to narrow results."
+    )))
+}
+
+/// Hint for a query whose full-text search stopped at the candidate cap.
+fn truncated_scan_note(index: &RepoIndex) -> String {
+    format!(
+        "Full-text search stopped after {} candidates ([fts] max_fts_candidates), so results come from those; add a glob or more terms to narrow it.",
+        index.config().fts.max_fts_candidates
+    )
+}
+
+/// Hint for a glob that matches no indexed file at all, as a bare word like
+/// `session` does; suggests `path_contains` with its literal text.
+pub(super) fn unmatched_glob_note(glob: &str, index: &RepoIndex) -> crate::Result<Option<String>> {
+    if index.glob_matches_any_file(glob)? {
+        return Ok(None);
+    }
+    let literal = glob.trim_matches(|c| c == '*' || c == '/');
+    let suggestion = if literal.is_empty() || literal.contains(['*', '?', '[', '{']) {
+        "to scope by part of a path, use path_contains".to_string()
+    } else {
+        format!("to scope by part of a path, use path_contains {literal:?} (--path, or (path {literal:?} ...))")
+    };
+    Ok(Some(format!(
+        "Glob {glob:?} matches no indexed files; {suggestion}."
+    )))
+}
+
+/// Paging hint for a file query that left matches out.
+fn file_page_note(offset: usize, page: &FilePage) -> Option<String> {
+    if !page.truncated() {
+        return None;
+    }
+    Some(format!(
+        "Showing {} of {} matching files from offset {}; {} more. Narrow the glob or page on with :offset {}.",
+        page.handles.len(),
+        page.total_matches,
+        offset,
+        page.remaining,
+        page.next_offset
+    ))
+}
+
+/// Glob, slice and outer limit of a top-level file query, looking through
+/// a `Limit` wrapper.
+fn file_query(query: &Query) -> Option<(&str, &FileSlice, Option<usize>)> {
+    match query {
+        Query::File(glob, slice) => Some((glob, slice, None)),
+        Query::Limit(limit, inner) => file_query(inner).map(|(glob, slice, inner_limit)| {
+            (
+                glob,
+                slice,
+                Some(inner_limit.map_or(*limit, |l| l.min(*limit))),
+            )
+        }),
+        _ => None,
+    }
+}
+
+/// `base` with a file query's `:limit`/`:offset` applied and the file cap
+/// held to the query's result `limit`.
+fn file_options(
+    slice: &FileSlice,
+    base: &FileQueryOptions,
+    limit: usize,
+    index: &RepoIndex,
+) -> FileQueryOptions {
+    let max_files = slice
+        .limit
+        .or(base.max_files)
+        .unwrap_or(index.config().core.file_max_files);
+    FileQueryOptions {
+        max_files: Some(max_files.min(limit)),
+        offset: slice.offset.unwrap_or(base.offset),
+        ..base.clone()
+    }
+}
+
+/// Terms and outer limit of a top-level commit query, looking through a
+/// `Limit` wrapper.
+fn commit_query(query: &Query) -> Option<(&str, Option<usize>)> {
+    match query {
+        Query::Commits(terms) => Some((terms, None)),
+        Query::Limit(limit, inner) => commit_query(inner).map(|(terms, inner_limit)| {
+            (terms, Some(inner_limit.map_or(*limit, |l| l.min(*limit))))
+        }),
+        _ => None,
+    }
+}
+
+/// A commit query, answered from the catch-all database's git-history
+/// layer: commit entries in handles mode, else their count or existence.
+fn execute_commits(
+    query: &Query,
+    terms: &str,
+    limit: Option<usize>,
+    index: &RepoIndex,
+    options: &QueryOptions,
+) -> crate::Result<QueryResult> {
+    let effective_limit = options.limit.unwrap_or(index.default_limit());
+    let limit = match options.mode {
+        QueryMode::Handles => limit.map_or(effective_limit, |l| l.min(effective_limit)),
+        QueryMode::Count => limit.unwrap_or(usize::MAX),
+        QueryMode::Exists => 1,
+    };
+    let mut explain = ExplainCollector::new(options.explain);
+    let fetch = limit.saturating_add(1);
+    let mut commits = index.search_commits(terms, fetch)?;
+    explain.record(
+        index,
+        SearchExplain::new(SearchPath::Commits, terms, fetch),
+        commits.len(),
+    );
+
+    if !options.mode.is_handles() {
+        let count = commits.len().min(limit);
+        return Ok(QueryResult {
+            total_matches: count,
+            mode: options.mode,
+            exists: (options.mode == QueryMode::Exists).then_some(count > 0),
+            match_counts: (options.mode == QueryMode::Count)
+                .then(|| BTreeMap::from([(super::count::dsl(query), count)])),
+            ..QueryResult::default()
+        });
+    }
+
+    let total_matches = commits.len();
+    let truncated = commits.len() > limit;
+    commits.truncate(limit);
+    let total_tokens = commits.iter().map(|c| estimate_tokens(&c.subject)).sum();
+    let explain = explain.finish(query, index, &[], !commits.is_empty(), None);
+    Ok(QueryResult {
+        commits: Some(commits),
+        total_tokens,
+        truncated,
+        total_matches,
+        explain,
+        ..QueryResult::default()
     })
 }
 
+/// `(commits ...)` inside another query: commits aren't handles, so they
+/// can't be combined with, scoped to or filtered like node results.
+pub(crate) fn combined_commits_error() -> CanopyError {
+    CanopyError::QueryParse {
+        position: 0,
+        message: "(commits ...) returns commits, not handles, so only (limit ...) and \
+                  (recent ...) can wrap it"
+            .to_string(),
+    }
+}
+
+/// Path and outer limit of a top-level related query, looking through a
+/// `Limit` wrapper.
+fn related_query(query: &Query) -> Option<(&str, Option<usize>)> {
+    match query {
+        Query::Related(path) => Some((path, None)),
+        Query::Limit(limit, inner) => related_query(inner)
+            .map(|(path, inner_limit)| (path, Some(inner_limit.map_or(*limit, |l| l.min(*limit))))),
+        _ => None,
+    }
+}
+
+/// The window of a top-level `(recent ...)`, looking through `Limit`/`InFile`
+/// wrappers, and the query without it.
+fn split_recent(query: &Query) -> Option<(Duration, Query)> {
+    match query {
+        Query::Recent(window, inner) => Some((*window, inner.as_ref().clone())),
+        Query::Limit(n, inner) => {
+            split_recent(inner).map(|(window, q)| (window, Query::Limit(*n, Box::new(q))))
+        }
+        Query::InFile(glob, inner) => split_recent(inner)
+            .map(|(window, q)| (window, Query::InFile(glob.clone(), Box::new(q)))),
+        _ => None,
+    }
+}
+
+/// The fragment of a top-level `(path ...)`, looking through `Limit`,
+/// `InFile` and `Exclude` wrappers, and the query without it.
+fn split_path(query: &Query) -> Option<(String, Query)> {
+    match query {
+        Query::PathContains(fragment, inner) => Some((fragment.clone(), inner.as_ref().clone())),
+        Query::Limit(n, inner) => {
+            split_path(inner).map(|(fragment, q)| (fragment, Query::Limit(*n, Box::new(q))))
+        }
+        Query::InFile(glob, inner) => split_path(inner)
+            .map(|(fragment, q)| (fragment, Query::InFile(glob.clone(), Box::new(q)))),
+        Query::Exclude(patterns, inner) => split_path(inner)
+            .map(|(fragment, q)| (fragment, Query::Exclude(patterns.clone(), Box::new(q)))),
+        _ => None,
+    }
+}
+
+/// Path glob a query is restricted to, used to skip unreachable shards.
+pub(super) fn query_glob(query: &Query) -> Option<&str> {
+    match query {
+        Query::InFile(glob, _) | Query::File(glob, _) => Some(glob),
+        Query::Limit(_, inner) | Query::Exclude(_, inner) | Query::PathContains(_, inner) => {
+            query_glob(inner)
+        }
+        _ => None,
+    }
+}
+
 fn dedupe_handles(handles: Vec<Handle>) -> Vec<Handle> {
     let mut seen = HashSet::new();
     handles
@@ -167,6 +743,42 @@ fn dedupe_handles(handles: Vec<Handle>) -> Vec<Handle> {
         .collect()
 }
 
+/// Full-text results best first across shards, position breaking ties.
+/// Results without a [`Handle::score`] keep their interleaved order.
+fn order_by_score(mut handles: Vec<Handle>) -> Vec<Handle> {
+    if handles.iter().all(|h| h.score.is_some()) {
+        handles.sort_by(|a, b| {
+            b.score
+                .unwrap_or_default()
+                .total_cmp(&a.score.unwrap_or_default())
+                .then_with(|| a.position_cmp(b))
+        });
+    }
+    handles
+}
+
+/// Expand `handles[selected]` in place, each taking the token count of its
+/// (redacted) content. Returns how many secrets were masked.
+fn fill_contents(
+    index: &RepoIndex,
+    handles: &mut [Handle],
+    selected: &[usize],
+) -> crate::Result<usize> {
+    let handle_ids: Vec<String> = selected
+        .iter()
+        .map(|idx| handles[*idx].id.to_string())
+        .collect();
+    let details = index.expand_with_details(&handle_ids)?;
+    let mut redactions = 0;
+    for (idx, detail) in selected.iter().zip(details) {
+        redactions += detail.redactions;
+        let handle = &mut handles[*idx];
+        handle.token_count = detail.token_count;
+        handle.content = Some(detail.content);
+    }
+    Ok(redactions)
+}
+
 fn expanded_stats(handles: &[Handle]) -> (usize, usize) {
     let expanded_count = handles.iter().filter(|h| h.content.is_some()).count();
     let expanded_tokens = handles
@@ -185,6 +797,22 @@ fn expanded_handle_ids(handles: &[Handle]) -> Vec<String> {
         .collect()
 }
 
+/// Terms, glob and limit of a top-level annotation query, looking through
+/// `Limit`/`InFile` wrappers.
+pub(super) fn annotation_query(query: &Query) -> Option<(&str, Option<&str>, Option<usize>)> {
+    match query {
+        Query::Annotations(terms) => Some((terms, None, None)),
+        Query::Limit(limit, inner) => {
+            annotation_query(inner).map(|(terms, glob, _)| (terms, glob, Some(*limit)))
+        }
+        Query::InFile(glob, inner) => match inner.as_ref() {
+            Query::Annotations(terms) => Some((terms, Some(glob.as_str()), None)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 pub(crate) fn extract_query_terms(query: &Query) -> Vec<String> {
     let mut terms = Vec::new();
     collect_query_terms(query, &mut terms);
@@ -197,12 +825,17 @@ pub(crate) fn extract_query_terms(query: &Query) -> Vec<String> {
 fn collect_query_terms(query: &Query, terms: &mut Vec<String>) {
     match query {
         Query::Section(s)
+        | Query::SectionPath(s)
         | Query::Grep(s)
-        | Query::File(s)
+        | Query::File(s, _)
+        | Query::Related(s)
+        | Query::Symbols(s)
         | Query::Code(s)
         | Query::Children(s)
         | Query::Definition(s)
-        | Query::References(s) => add_terms(s, terms),
+        | Query::References(s, _)
+        | Query::Annotations(s)
+        | Query::Commits(s) => add_terms(s, terms),
         Query::ChildrenNamed(parent, symbol) => {
             add_terms(parent, terms);
             add_terms(symbol, terms);
@@ -216,7 +849,14 @@ fn collect_query_terms(query: &Query, terms: &mut Vec<String>) {
                 collect_query_terms(q, terms);
             }
         }
-        Query::Limit(_, q) => collect_query_terms(q, terms),
+        // Excluded patterns are what the caller doesn't want; they don't rank
+        Query::PathContains(fragment, subquery) => {
+            add_terms(fragment, terms);
+            collect_query_terms(subquery, terms);
+        }
+        Query::Limit(_, q) | Query::Recent(_, q) | Query::Exclude(_, q) => {
+            collect_query_terms(q, terms)
+        }
     }
 }
 
@@ -224,10 +864,415 @@ fn add_terms(text: &str, terms: &mut Vec<String>) {
     terms.extend(split_terms(text));
 }
 
+/// Run each leg of a union or intersection on its own. A failed leg is
+/// recorded in `errors` and comes back as None; Err only if every leg fails,
+/// in which case the legs' errors are left to the caller to report.
+fn execute_legs(
+    queries: &[Query],
+    index: &RepoIndex,
+    limit: usize,
+    files: &FileQueryOptions,
+    errors: &mut Vec<PatternError>,
+    explain: &mut ExplainCollector,
+) -> crate::Result<Vec<Option<Vec<Handle>>>> {
+    let recorded = errors.len();
+    let mut first_err = None;
+    let mut legs = Vec::with_capacity(queries.len());
+    for q in queries {
+        match execute_query_internal(q, index, limit, files, errors, explain) {
+            Ok(handles) => legs.push(Some(handles)),
+            Err(e) => {
+                errors.push(PatternError {
+                    pattern: match q {
+                        Query::Grep(pattern) => pattern.clone(),
+                        other => dsl(other),
+                    },
+                    message: e.to_string(),
+                });
+                first_err.get_or_insert(e);
+                legs.push(None);
+            }
+        }
+    }
+    match first_err {
+        Some(e) if legs.iter().all(Option::is_none) => {
+            errors.truncate(recorded);
+            Err(e)
+        }
+        _ => Ok(legs),
+    }
+}
+
+fn execute_query_internal(
+    query: &Query,
+    index: &RepoIndex,
+    limit: usize,
+    files: &FileQueryOptions,
+    pattern_errors: &mut Vec<PatternError>,
+    explain: &mut ExplainCollector,
+) -> crate::Result<Vec<Handle>> {
+    match query {
+        Query::Section(heading) => Ok(explain.tagged(
+            index,
+            SearchExplain::new(SearchPath::Sections, heading, limit),
+            index.search_sections(heading, limit)?,
+        )),
+
+        Query::SectionPath(path) => Ok(explain.tagged(
+            index,
+            SearchExplain::new(SearchPath::Sections, path, limit),
+            index.search_section_path(path, limit)?,
+        )),
+
+        Query::Grep(pattern) => {
+            let (handles, scan) = index.fts_scan(pattern, limit)?;
+            Ok(explain.tagged(
+                index,
+                SearchExplain::new(SearchPath::Fts, pattern, limit)
+                    .with_fts(pattern)
+                    .with_scan(scan),
+                handles,
+            ))
+        }
+
+        Query::File(path, slice) => Ok(explain.tagged(
+            index,
+            SearchExplain::new(SearchPath::File, path, limit),
+            index
+                .get_file(path, &file_options(slice, files, limit, index))?
+                .handles,
+        )),
+
+        Query::Code(symbol) => {
+            let (handles, via) = index.search_code_via(symbol, limit)?;
+            Ok(explain.tagged(index, SearchExplain::new(via, symbol, limit), handles))
+        }
+
+        Query::Children(parent) => Ok(explain.tagged(
+            index,
+            SearchExplain::new(SearchPath::Children, parent, limit),
+            index.search_children(parent, limit)?,
+        )),
+
+        Query::ChildrenNamed(parent, symbol) => Ok(explain.tagged(
+            index,
+            SearchExplain::new(SearchPath::Children, &format!("{parent}.{symbol}"), limit),
+            index.search_children_named(parent, symbol, limit)?,
+        )),
+
+        Query::Definition(symbol) => {
+            let (handles, via) = index.search_definitions_via(symbol, limit)?;
+            Ok(explain.tagged(index, SearchExplain::new(via, symbol, limit), handles))
+        }
+
+        Query::References(symbol, ref_types) => {
+            // References return RefHandles, but for now we convert to regular Handles
+            // by returning nodes that contain the reference
+            Ok(explain.tagged(
+                index,
+                SearchExplain::new(SearchPath::Refs, symbol, limit),
+                index.search_reference_sources(symbol, ref_types, limit)?,
+            ))
+        }
+
+        Query::Annotations(terms) => Ok(explain.tagged(
+            index,
+            SearchExplain::new(SearchPath::Annotations, terms, limit),
+            index.search_annotation_sources(terms, limit)?,
+        )),
+
+        // A shard not storing the file has nothing related to it
+        Query::Related(path) => {
+            let handles = match index.related_file_handles(path, limit) {
+                Err(CanopyError::FileNotFound(_)) => Vec::new(),
+                other => other?,
+            };
+            Ok(explain.tagged(
+                index,
+                SearchExplain::new(SearchPath::Related, path, limit),
+                handles,
+            ))
+        }
+
+        Query::Symbols(prefix) => Ok(explain.tagged(
+            index,
+            SearchExplain::new(SearchPath::Symbols, prefix, limit),
+            index.symbol_handles(prefix, limit)?,
+        )),
+
+        Query::Commits(_) => Err(combined_commits_error()),
+
+        Query::InFile(glob, subquery) => {
+            // Only support grep inside in-file for now
+            match subquery.as_ref() {
+                Query::Grep(pattern) => {
+                    let (handles, scan) = index.scan_fts_in_glob(glob, pattern, limit)?;
+                    explain.glob_filtered(scan.rows_examined - handles.len());
+                    Ok(explain.tagged(
+                        index,
+                        SearchExplain::new(SearchPath::InFile, pattern, limit)
+                            .with_fts(pattern)
+                            .with_glob(glob)
+                            .with_scan(scan),
+                        handles,
+                    ))
+                }
+                _ => {
+                    // For other queries, filter results by glob
+                    let mark = explain.mark();
+                    let glob_matcher = index.path_style().glob(glob)?;
+                    let filtered = fill_after_filter(limit, |fetch| {
+                        let results = execute_query_internal(
+                            subquery,
+                            index,
+                            fetch,
+                            files,
+                            pattern_errors,
+                            explain,
+                        )?;
+                        let exhausted = results.len() < fetch;
+                        let read = results.len();
+                        let kept: Vec<Handle> = results
+                            .into_iter()
+                            .filter(|h| glob_matcher.is_match(&h.file_path))
+                            .collect();
+                        Ok(Filtered {
+                            removed: read - kept.len(),
+                            kept,
+                            exhausted,
+                        })
+                    })?;
+                    explain.glob_filtered_since(mark, glob, filtered.removed);
+                    Ok(filtered.kept)
+                }
+            }
+        }
+
+        Query::Union(queries) => {
+            let mut seen = HashSet::new();
+            let mut results = Vec::new();
+
+            let legs = execute_legs(queries, index, limit, files, pattern_errors, explain)?;
+            for handles in legs.into_iter().flatten() {
+                for handle in handles {
+                    if seen.insert(handle.id.raw().to_string()) {
+                        results.push(handle);
+                    }
+                }
+            }
+
+            // Legs of different classes share the limit instead of the
+            // first leg taking it all
+            let mut results = order_by_class(results, limit, &index.config().ranking);
+            results.truncate(limit);
+            Ok(results)
+        }
+
+        Query::Intersect(queries) => {
+            if queries.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            // A failed leg can't be satisfied, so nothing matches all of them
+            let filtered = fill_after_filter(limit, |fetch| {
+                let legs = execute_legs(queries, index, fetch, files, pattern_errors, explain)?;
+                let Some(mut legs) = legs.into_iter().collect::<Option<Vec<_>>>() else {
+                    return Ok(Filtered {
+                        kept: Vec::new(),
+                        removed: 0,
+                        exhausted: true,
+                    });
+                };
+                // Fetching more can still add to the intersection while any
+                // leg has more to give
+                let exhausted = legs.iter().all(|leg| leg.len() < fetch);
+
+                let first_results = legs.remove(0);
+                let mut result_ids: HashSet<String> = first_results
+                    .iter()
+                    .map(|h| h.id.raw().to_string())
+                    .collect();
+
+                // Intersect with remaining queries
+                for handles in legs {
+                    let ids: HashSet<String> =
+                        handles.iter().map(|h| h.id.raw().to_string()).collect();
+                    result_ids = result_ids.intersection(&ids).cloned().collect();
+                }
+
+                // Return handles that are in the intersection
+                let read = first_results.len();
+                let kept: Vec<Handle> = first_results
+                    .into_iter()
+                    .filter(|h| result_ids.contains(h.id.raw()))
+                    .collect();
+                Ok(Filtered {
+                    removed: read - kept.len(),
+                    kept,
+                    exhausted,
+                })
+            })?;
+            Ok(filtered.kept)
+        }
+
+        Query::Limit(n, subquery) => {
+            let results =
+                execute_query_internal(subquery, index, *n, files, pattern_errors, explain)?;
+            Ok(results.into_iter().take(*n).collect())
+        }
+
+        Query::Recent(window, subquery) => {
+            let _scope = RecentScope::new([index], *window);
+            execute_query_internal(subquery, index, limit, files, pattern_errors, explain)
+        }
+
+        Query::PathContains(fragment, subquery) => {
+            let _scope = PathScope::new([index], fragment);
+            execute_query_internal(subquery, index, limit, files, pattern_errors, explain)
+        }
+
+        Query::Exclude(patterns, subquery) => {
+            let filtered = fill_after_filter(limit, |fetch| {
+                let candidates =
+                    execute_query_internal(subquery, index, fetch, files, pattern_errors, explain)?;
+                let exhausted = candidates.len() < fetch;
+                let (kept, removed) = exclude_matching(index, candidates, patterns)?;
+                Ok(Filtered {
+                    kept,
+                    removed,
+                    exhausted,
+                })
+            })?;
+            explain.record_excluded(filtered.removed);
+            Ok(filtered.kept)
+        }
+    }
+}
+
+/// One round of a search whose candidates are filtered after it runs.
+struct Filtered {
+    kept: Vec<Handle>,
+    /// Candidates the filter left out
+    removed: usize,
+    /// The search found fewer candidates than it was asked for, so asking
+    /// for more finds nothing new
+    exhausted: bool,
+}
+
+/// Up to `limit` handles kept by `round`, which searches for as many
+/// candidates as it's given and filters them.
+///
+/// Fetches past the limit so filtered-out candidates don't starve it,
+/// widening until enough survive, the search runs dry or
+/// [`FILTER_MAX_CANDIDATES`] are fetched.
+fn fill_after_filter(
+    limit: usize,
+    mut round: impl FnMut(usize) -> crate::Result<Filtered>,
+) -> crate::Result<Filtered> {
+    let mut fetch = limit.saturating_mul(2).max(1);
+    loop {
+        let mut filtered = round(fetch)?;
+        if filtered.kept.len() >= limit || filtered.exhausted || fetch >= FILTER_MAX_CANDIDATES {
+            filtered.kept.truncate(limit);
+            return Ok(filtered);
+        }
+        fetch = fetch.saturating_mul(4).min(FILTER_MAX_CANDIDATES);
+    }
+}
+
+/// `handles` without those whose node text contains any of `patterns`, and
+/// how many were removed.
+///
+/// Up to [`EXCLUDE_SCAN_MAX_CANDIDATES`] candidates are checked by reading
+/// their indexed text, where a pattern matches when each of its terms occurs
+/// in it, ignoring case. Larger sets ask the full-text index which of them
+/// match each pattern, one lookup per pattern rather than per candidate.
+fn exclude_matching(
+    index: &RepoIndex,
+    handles: Vec<Handle>,
+    patterns: &[String],
+) -> crate::Result<(Vec<Handle>, usize)> {
+    let patterns: Vec<&String> = patterns
+        .iter()
+        .filter(|p| !split_terms(p).is_empty())
+        .collect();
+    if handles.is_empty() || patterns.is_empty() {
+        return Ok((handles, 0));
+    }
+    let raw_ids: Vec<&str> = handles.iter().map(|h| h.id.raw()).collect();
+    let excluded = if handles.len() <= EXCLUDE_SCAN_MAX_CANDIDATES {
+        excluded_by_scan(index, &raw_ids, &patterns)?
+    } else {
+        excluded_by_fts(index, &raw_ids, &patterns)?
+    };
+    let before = handles.len();
+    let kept: Vec<Handle> = handles
+        .into_iter()
+        .filter(|h| !excluded.contains(h.id.raw()))
+        .collect();
+    let removed = before - kept.len();
+    Ok((kept, removed))
+}
+
+fn excluded_by_scan(
+    index: &RepoIndex,
+    raw_ids: &[&str],
+    patterns: &[&String],
+) -> crate::Result<HashSet<String>> {
+    let patterns: Vec<Vec<String>> = patterns.iter().map(|p| split_terms(p)).collect();
+    Ok(index
+        .indexed_contents(raw_ids)?
+        .into_iter()
+        .filter(|(_, text)| {
+            let text = text.to_lowercase();
+            patterns
+                .iter()
+                .any(|terms| terms.iter().all(|term| text.contains(term.as_str())))
+        })
+        .map(|(id, _)| id)
+        .collect())
+}
+
+fn excluded_by_fts(
+    index: &RepoIndex,
+    raw_ids: &[&str],
+    patterns: &[&String],
+) -> crate::Result<HashSet<String>> {
+    let mut excluded = HashSet::new();
+    for pattern in patterns {
+        excluded.extend(index.fts_matching_ids(pattern, raw_ids)?);
+    }
+    Ok(excluded)
+}
+
+/// Up to `limit` matches of `query` across every database, without
+/// recording anything; explain mode checks what a glob left out with it.
+pub(super) fn unglobbed_matches(
+    query: &Query,
+    index: &RepoIndex,
+    limit: usize,
+) -> crate::Result<Vec<Handle>> {
+    let mut handles = Vec::new();
+    for target in index.query_targets(None) {
+        handles.extend(execute_query_internal(
+            query,
+            target,
+            limit,
+            &FileQueryOptions::default(),
+            &mut Vec::new(),
+            &mut ExplainCollector::default(),
+        )?);
+    }
+    handles.truncate(limit);
+    Ok(handles)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{Handle, NodeType, Span};
+    use crate::query::{parse_query, MatchMode, QueryKind, QueryParams, ResultClass};
+    use crate::{Handle, NodeType, RefType, Span};
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
 
     fn make_handle(file: &str, span: Span, content: Option<&str>) -> Handle {
         let mut h = Handle::new(
@@ -244,6 +1289,40 @@ mod tests {
         h
     }
 
+    #[test]
+    fn order_by_score_merges_full_text_results_best_first() {
+        let scored = |file: &str, score: f64| {
+            let mut h = make_handle(file, 0..50, None);
+            h.score = Some(score);
+            h
+        };
+        // Interleaved from two shards, each already best first
+        let handles = vec![
+            scored("a/one.rs", 2.0),
+            scored("b/one.rs", 5.0),
+            scored("a/two.rs", 1.0),
+            scored("b/two.rs", 2.0),
+        ];
+        let files = |handles: &[Handle]| {
+            handles
+                .iter()
+                .map(|h| h.file_path.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            files(&order_by_score(handles.clone())),
+            ["b/one.rs", "a/one.rs", "b/two.rs", "a/two.rs"]
+        );
+
+        // One handle without a score: the interleaved order stands
+        let mut mixed = handles;
+        mixed[3].score = None;
+        assert_eq!(
+            files(&order_by_score(mixed)),
+            ["a/one.rs", "b/one.rs", "a/two.rs", "b/two.rs"]
+        );
+    }
+
     #[test]
     fn dedupe_handles_removes_duplicate_ids() {
         let h1 = make_handle("a.rs", 0..50, None);
@@ -315,103 +1394,532 @@ mod tests {
         assert!(terms.contains(&"rs".to_string()));
         assert!(terms.contains(&"validate".to_string()));
     }
-}
 
-fn execute_query_internal(
-    query: &Query,
-    index: &RepoIndex,
-    limit: usize,
-) -> crate::Result<Vec<Handle>> {
-    match query {
-        Query::Section(heading) => index.search_sections(heading, limit),
+    /// One `verify_token` definition, sorted after its eight call sites
+    fn one_definition_many_callers_repo() -> (FixtureRepo, RepoIndex) {
+        let callers = (0..8).fold(FixtureRepoBuilder::new(), |builder, i| {
+            builder.file(
+                format!("src/a_caller{i}.rs"),
+                format!("pub fn handler{i}(t: &str) -> bool {{\n    verify_token(t)\n}}\n"),
+            )
+        });
+        let repo = callers
+            .file(
+                "src/z_auth.rs",
+                "pub fn verify_token(token: &str) -> bool {\n    !token.is_empty()\n}\n",
+            )
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        (repo, index)
+    }
 
-        Query::Grep(pattern) => index.fts_search(pattern, limit),
+    #[test]
+    fn definitions_rank_first_in_mixed_results() {
+        let (_dir, index) = one_definition_many_callers_repo();
+        let legs = [
+            r#"(grep "verify_token")"#,
+            r#"(refs "verify_token")"#,
+            r#"(code "verify_token")"#,
+        ];
+        // Whatever order the legs come in, the definition leads
+        for (first, second, third) in [(0, 1, 2), (1, 0, 2), (2, 1, 0), (1, 2, 0)] {
+            let dsl = format!(
+                "(limit 3 (union {} {} {}))",
+                legs[first], legs[second], legs[third]
+            );
+            let query = parse_query(&dsl).unwrap();
+            let result = execute_query(&query, &index, None).unwrap();
+            assert_eq!(result.handles.len(), 3, "{dsl}");
+            assert_eq!(result.handles[0].file_path, "src/z_auth.rs", "{dsl}");
+            assert_eq!(
+                result.handles[0].result_class,
+                Some(ResultClass::Definition),
+                "{dsl}"
+            );
+            assert!(result.handles[1..]
+                .iter()
+                .all(|h| h.result_class > Some(ResultClass::Definition)));
+        }
 
-        Query::File(path) => index.get_file(path),
+        // A symbol query is all definitions
+        let params = QueryParams::symbol("verify_token");
+        let result = execute_query(&params.to_query().unwrap(), &index, None).unwrap();
+        assert_eq!(result.handles.len(), 1);
+        assert_eq!(
+            result.handles[0].result_class,
+            Some(ResultClass::Definition)
+        );
+    }
 
-        Query::Code(symbol) => index.search_code(symbol, limit),
+    fn widget_refs_repo() -> (FixtureRepo, RepoIndex) {
+        // One import, two type refs and one (tuple-struct constructor) call of Widget
+        let repo = FixtureRepoBuilder::new()
+            .file(
+                "src/lib.rs",
+                "use crate::widget::Widget;\n\npub fn build(size: u32) -> Widget {\n    Widget(size)\n}\n\npub fn resize(w: Widget) -> u32 {\n    w.0\n}\n",
+            )
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        (repo, index)
+    }
 
-        Query::Children(parent) => index.search_children(parent, limit),
+    #[test]
+    fn reference_query_filters_by_ref_type_and_counts_all() {
+        let (_dir, index) = widget_refs_repo();
+        let expected: BTreeMap<String, usize> = [("call", 1), ("import", 1), ("type_ref", 2)]
+            .into_iter()
+            .map(|(t, n)| (t.to_string(), n))
+            .collect();
 
-        Query::ChildrenNamed(parent, symbol) => index.search_children_named(parent, symbol, limit),
+        let all = QueryParams::symbol("Widget").with_kind(QueryKind::Reference);
+        let result = execute_query(&all.to_query().unwrap(), &index, None).unwrap();
+        assert_eq!(result.ref_handles.as_ref().unwrap().len(), 4);
+        assert_eq!(result.ref_type_counts.as_ref(), Some(&expected));
 
-        Query::Definition(symbol) => index.search_definitions(symbol, limit),
+        let calls = all.clone().with_ref_types(vec![RefType::Call]);
+        let result = execute_query(&calls.to_query().unwrap(), &index, None).unwrap();
+        let refs = result.ref_handles.unwrap();
+        assert_eq!(refs.len(), 1);
+        assert_eq!(refs[0].ref_type, RefType::Call);
+        assert_eq!(
+            result.ref_type_counts,
+            Some(expected.clone()),
+            "counts ignore the filter"
+        );
 
-        Query::References(symbol) => {
-            // References return RefHandles, but for now we convert to regular Handles
-            // by returning nodes that contain the reference
-            index.search_reference_sources(symbol, limit)
-        }
+        let dsl = parse_query(r#"(refs "Widget" :types (call type))"#).unwrap();
+        let result = execute_query(&dsl, &index, None).unwrap();
+        assert_eq!(result.ref_handles.unwrap().len(), 3);
 
-        Query::InFile(glob, subquery) => {
-            // Only support grep inside in-file for now
-            match subquery.as_ref() {
-                Query::Grep(pattern) => index.search_in_files(glob, pattern, limit),
-                _ => {
-                    // For other queries, filter results by glob
-                    let results = execute_query_internal(subquery, index, limit * 2)?;
-                    let glob_matcher = globset::Glob::new(glob)
-                        .map_err(|e| CanopyError::GlobPattern(e.to_string()))?
-                        .compile_matcher();
-
-                    Ok(results
-                        .into_iter()
-                        .filter(|h| glob_matcher.is_match(&h.file_path))
-                        .take(limit)
-                        .collect())
-                }
+        let sources = index
+            .search_reference_sources("Widget", &[RefType::Call], 10)
+            .unwrap();
+        assert_eq!(sources.len(), 1);
+        assert!(sources[0].preview.contains("build"));
+        let typed = index
+            .search_reference_sources("Widget", &[RefType::TypeRef], 10)
+            .unwrap();
+        assert_eq!(typed.len(), 2, "build and resize both name the type");
+    }
+
+    #[test]
+    fn file_query_pages_with_dsl_slice() {
+        let dir = crate::index::test_helpers::setup_repo(200);
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let unbounded = parse_query(r#"(file "src/**")"#).unwrap();
+        let result = execute_query(&unbounded, &index, None).unwrap();
+        assert_eq!(result.handles.len(), 50, "default file cap");
+        assert_eq!(result.total_matches, 200);
+        assert!(result.truncated);
+
+        let first = parse_query(r#"(file "src/**" :limit 20)"#).unwrap();
+        let second = parse_query(r#"(file "src/**" :limit 20 :offset 20)"#).unwrap();
+        let first = execute_query(&first, &index, None).unwrap();
+        let second = execute_query(&second, &index, None).unwrap();
+        assert_eq!(second.handles.len(), 20);
+        assert!(second.truncated);
+        assert!(second
+            .expand_note
+            .as_deref()
+            .unwrap()
+            .contains("160 more. Narrow the glob or page on with :offset 40"));
+        let first_paths: HashSet<_> = first.handles.iter().map(|h| &h.file_path).collect();
+        assert!(second
+            .handles
+            .iter()
+            .all(|h| !first_paths.contains(&h.file_path)));
+
+        // The result limit still bounds the page
+        let capped = execute_query(&unbounded, &index, Some(10)).unwrap();
+        assert_eq!(capped.handles.len(), 10);
+        assert_eq!(capped.total_matches, 200);
+
+        let last = parse_query(r#"(file "src/**" :offset 190)"#).unwrap();
+        let last = execute_query(&last, &index, None).unwrap();
+        assert_eq!(last.handles.len(), 10);
+        assert!(!last.truncated);
+        assert!(last.expand_note.is_none());
+    }
+
+    #[test]
+    fn file_query_options_thread_through_query_options() {
+        let dir = crate::index::test_helpers::setup_repo(200);
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let options = QueryOptions::new().with_file_options(
+            FileQueryOptions::new()
+                .with_max_files(5)
+                .with_offset(100)
+                .with_skip_preview(true),
+        );
+        let query = parse_query(r#"(file "src/**")"#).unwrap();
+        let result = execute_query_with_options(&query, &index, options).unwrap();
+        assert_eq!(result.handles.len(), 5);
+        assert!(result.handles.iter().all(|h| h.preview.is_empty()));
+        assert!(result
+            .expand_note
+            .as_deref()
+            .unwrap()
+            .contains("from offset 100; 95 more"));
+
+        // Nested file queries honour the options too
+        let nested =
+            parse_query(r#"(union (file "src/file_1*.rs" :limit 3) (code "func_0"))"#).unwrap();
+        let result = execute_query(&nested, &index, None).unwrap();
+        assert_eq!(
+            result
+                .handles
+                .iter()
+                .filter(|h| h.node_type == NodeType::Chunk)
+                .count(),
+            3
+        );
+    }
+
+    /// 40 files whose matches tie on every score, written as `src/tie_NN.rs`.
+    fn tie_repo(indexed_one_at_a_time_in_reverse: bool) -> (FixtureRepo, RepoIndex) {
+        let repo = (0..40)
+            .fold(FixtureRepoBuilder::new(), |builder, i| {
+                builder.file(
+                    format!("src/tie_{i:02}.rs"),
+                    format!("// file {i}\nfn shared() {{ helper(); }}\n"),
+                )
+            })
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        if indexed_one_at_a_time_in_reverse {
+            for i in (0..40).rev() {
+                index.index(&format!("src/tie_{i:02}.rs")).unwrap();
             }
+        } else {
+            index.index("**/*.rs").unwrap();
         }
+        (repo, index)
+    }
 
-        Query::Union(queries) => {
-            let mut seen = HashSet::new();
-            let mut results = Vec::new();
+    fn tie_queries() -> Vec<QueryParams> {
+        vec![
+            QueryParams::pattern("helper").with_limit(16),
+            QueryParams::symbol("shared").with_limit(16),
+            QueryParams::symbol("helper")
+                .with_kind(QueryKind::Reference)
+                .with_limit(16),
+        ]
+    }
 
-            for q in queries {
-                let handles = execute_query_internal(q, index, limit)?;
-                for handle in handles {
-                    if seen.insert(handle.id.raw().to_string()) {
-                        results.push(handle);
-                    }
-                }
+    fn serialized(index: &RepoIndex, params: &QueryParams) -> String {
+        serde_json::to_string(&index.query_params(params.clone()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn tied_results_serialize_identically_across_runs() {
+        let (dir, index) = tie_repo(false);
+        for params in tie_queries() {
+            let first = serialized(&index, &params);
+            for _ in 0..20 {
+                // Reopen so the symbol cache is rebuilt each run
+                let reopened = RepoIndex::open(dir.path()).unwrap();
+                assert_eq!(serialized(&reopened, &params), first, "{params:?}");
             }
 
-            results.truncate(limit);
-            Ok(results)
+            let result = index.query_params(params.clone()).unwrap();
+            let paths: Vec<_> = match &result.ref_handles {
+                Some(refs) => refs.iter().map(|r| r.file_path.clone()).collect(),
+                None => result.handles.iter().map(|h| h.file_path.clone()).collect(),
+            };
+            let expected: Vec<_> = (0..16).map(|i| format!("src/tie_{i:02}.rs")).collect();
+            assert_eq!(paths, expected, "ties break by path: {params:?}");
         }
+    }
 
-        Query::Intersect(queries) => {
-            if queries.is_empty() {
-                return Ok(Vec::new());
-            }
+    #[test]
+    fn insertion_order_does_not_change_results() {
+        let (_a, forward) = tie_repo(false);
+        let (_b, reversed) = tie_repo(true);
+        for params in tie_queries() {
+            assert_eq!(
+                serialized(&forward, &params),
+                serialized(&reversed, &params),
+                "{params:?}"
+            );
+        }
+    }
 
-            // Execute first query
-            let first_results = execute_query_internal(&queries[0], index, limit * 2)?;
-            let mut result_ids: HashSet<String> = first_results
-                .iter()
-                .map(|h| h.id.raw().to_string())
-                .collect();
-
-            // Intersect with remaining queries
-            for q in &queries[1..] {
-                let handles = execute_query_internal(q, index, limit * 2)?;
-                let ids: HashSet<String> = handles.iter().map(|h| h.id.raw().to_string()).collect();
-                result_ids = result_ids.intersection(&ids).cloned().collect();
-            }
+    #[test]
+    fn failed_pattern_leaves_the_others_answering() {
+        let (_dir, index) = widget_refs_repo();
+        // A dangling operator is an FTS5 syntax error
+        let bad = "resize AND";
+        assert!(index.fts_search(bad, 10).is_err());
 
-            // Return handles that are in the intersection
-            let results: Vec<Handle> = first_results
-                .into_iter()
-                .filter(|h| result_ids.contains(h.id.raw()))
-                .take(limit)
-                .collect();
+        let any = index
+            .query_params(QueryParams::patterns(vec!["build".into(), bad.into()]))
+            .unwrap();
+        assert!(!any.handles.is_empty());
+        assert!(any.handles.iter().all(|h| h.preview.contains("build")));
+        assert_eq!(any.pattern_errors.len(), 1);
+        assert_eq!(any.pattern_errors[0].pattern, bad);
+        assert!(any.pattern_errors[0].message.contains("fts5"));
+        let json = serde_json::to_value(&any).unwrap();
+        assert_eq!(json["pattern_errors"][0]["pattern"], bad);
 
-            Ok(results)
+        // Under match all the failed pattern can't be satisfied
+        let mut params = QueryParams::patterns(vec!["build".into(), bad.into()]);
+        params.match_mode = MatchMode::All;
+        let all = index.query_params(params).unwrap();
+        assert!(all.handles.is_empty());
+        assert_eq!(all.total_matches, 0);
+        assert_eq!(all.pattern_errors.len(), 1);
+        assert_eq!(all.pattern_errors[0].pattern, bad);
+
+        let clean = index
+            .query_params(QueryParams::patterns(vec!["build".into(), "resize".into()]))
+            .unwrap();
+        assert!(clean.pattern_errors.is_empty());
+        assert!(serde_json::to_value(&clean)
+            .unwrap()
+            .get("pattern_errors")
+            .is_none());
+    }
+
+    #[test]
+    fn query_fails_when_every_pattern_fails() {
+        let (_dir, index) = widget_refs_repo();
+        for match_mode in [MatchMode::Any, MatchMode::All] {
+            let mut params = QueryParams::patterns(vec!["resize AND".into(), "OR build".into()]);
+            params.match_mode = match_mode.clone();
+            assert!(index.query_params(params).is_err(), "{match_mode:?}");
         }
+    }
 
-        Query::Limit(n, subquery) => {
-            let results = execute_query_internal(subquery, index, *n)?;
-            Ok(results.into_iter().take(*n).collect())
+    #[test]
+    fn nested_failures_are_reported_once() {
+        let (_dir, index) = widget_refs_repo();
+        let query =
+            parse_query(r#"(union (grep "build") (union (grep "NOT") (grep "AND")))"#).unwrap();
+        let result = execute_query(&query, &index, None).unwrap();
+        assert!(!result.handles.is_empty());
+        // The inner union failed as a whole, so it is the one reported
+        let patterns: Vec<_> = result.pattern_errors.iter().map(|e| &e.pattern).collect();
+        assert_eq!(patterns, [r#"(union (grep "NOT") (grep "AND"))"#]);
+    }
+
+    /// Eighty `retry` call sites across forty files. The half that mention
+    /// tests rank first
+    fn retry_repo() -> (FixtureRepo, RepoIndex) {
+        let repo = (0..40)
+            .fold(FixtureRepoBuilder::new(), |builder, i| {
+                builder.file(
+                    format!("src/client{i:02}.rs"),
+                    format!(
+                        "pub fn send{i}(payload: &[u8]) {{\n    let framed = frame(payload);\n    \
+                         retry(framed);\n}}\n\n\
+                         pub fn check{i}() {{\n    // test only\n    retry(retry);\n}}\n"
+                    ),
+                )
+            })
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        (repo, index)
+    }
+
+    fn excluding_tests(limit: usize) -> QueryParams {
+        QueryParams::pattern("retry")
+            .with_exclude_patterns(vec!["test".into()])
+            .with_limit(limit)
+    }
+
+    fn mentions_test(index: &RepoIndex, result: &QueryResult) -> bool {
+        let ids: Vec<&str> = result.handles.iter().map(|h| h.id.raw()).collect();
+        let contents = index.indexed_contents(&ids).unwrap();
+        contents.values().any(|text| text.contains("test"))
+    }
+
+    #[test]
+    fn exclusions_apply_before_the_limit() {
+        let (_dir, index) = retry_repo();
+        let all = index
+            .query_params(QueryParams::pattern("retry").with_limit(200))
+            .unwrap();
+        assert_eq!(all.handles.len(), 80);
+        let top = index
+            .query_params(QueryParams::pattern("retry").with_limit(5))
+            .unwrap();
+        assert!(mentions_test(&index, &top));
+
+        // The excluded half ranks first, yet doesn't use up the limit
+        for limit in [5, 40] {
+            let result = index.query_params(excluding_tests(limit)).unwrap();
+            assert_eq!(result.handles.len(), limit);
+            assert!(!mentions_test(&index, &result));
+            assert_eq!(result.excluded_matches, 40);
         }
+
+        // Every candidate excluded: nothing left, but the count explains it
+        let none = index
+            .query_params(
+                QueryParams::pattern("retry")
+                    .with_exclude_patterns(vec!["retry".into()])
+                    .with_explain(true),
+            )
+            .unwrap();
+        assert!(none.handles.is_empty());
+        assert!(none.excluded_matches > 0);
+        let checklist = none.explain.unwrap().checklist;
+        assert!(checklist
+            .iter()
+            .any(|c| c.contains("Exclude patterns removed")));
+    }
+
+    #[test]
+    fn scan_and_full_text_exclusions_agree() {
+        let (_dir, index) = retry_repo();
+        let candidates = index.fts_search("retry", 200).unwrap();
+        let raw_ids: Vec<&str> = candidates.iter().map(|h| h.id.raw()).collect();
+        let test = "test".to_string();
+        let only = "test only".to_string();
+        for patterns in [vec![&test], vec![&only]] {
+            let scanned = excluded_by_scan(&index, &raw_ids, &patterns).unwrap();
+            let matched = excluded_by_fts(&index, &raw_ids, &patterns).unwrap();
+            assert_eq!(scanned.len(), 40, "{patterns:?}");
+            assert_eq!(scanned, matched, "{patterns:?}");
+        }
+
+        // Blank patterns exclude nothing
+        let (kept, removed) = exclude_matching(&index, candidates, &[" ".to_string()]).unwrap();
+        assert_eq!((kept.len(), removed), (80, 0));
+    }
+
+    #[test]
+    fn exclusions_reject_reference_and_annotation_kinds() {
+        for kind in [QueryKind::Reference, QueryKind::Annotation] {
+            let params = QueryParams::symbol("retry")
+                .with_kind(kind)
+                .with_exclude_patterns(vec!["test".into()]);
+            assert!(params.to_query().is_err());
+        }
+        // The dsl form takes the exclusion too
+        let params = QueryParams {
+            dsl: Some(r#"(code "retry")"#.to_string()),
+            ..QueryParams::new().with_exclude_patterns(vec!["test".into()])
+        };
+        assert!(matches!(params.to_query().unwrap(), Query::Exclude(..)));
+    }
+
+    fn crowded_repo() -> (FixtureRepo, RepoIndex) {
+        let repo = canopy_testutil::crowded_glob().build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*").unwrap();
+        (repo, index)
+    }
+
+    /// Results returned, `truncated` and `total_matches`.
+    fn counted(result: &QueryResult) -> (usize, bool, usize) {
+        let returned = match &result.ref_handles {
+            Some(refs) => refs.len(),
+            None => result.handles.len(),
+        };
+        (returned, result.truncated, result.total_matches)
+    }
+
+    #[test]
+    fn limits_count_results_after_filters() {
+        use canopy_testutil::presets::CROWDED_INSIDE;
+        let (_repo, index) = crowded_repo();
+        let dsl = |query: &str| QueryParams {
+            dsl: Some(query.to_string()),
+            ..QueryParams::new()
+        };
+        let in_src = QueryParams::symbol("handler").with_glob("src/**");
+        let both = QueryParams::patterns(vec!["handler".into(), "src_only".into()])
+            .with_match_mode(MatchMode::All);
+        let refs = QueryParams::symbol("dispatch").with_kind(QueryKind::Reference);
+        let cases = [
+            (in_src, r#"(in-file "src/**" (code "handler"))"#),
+            (both, r#"(intersect (grep "handler") (grep "src_only"))"#),
+            (refs, r#"(refs "dispatch")"#),
+        ];
+        for (params, query) in cases {
+            for limit in [5, 16] {
+                let expected = counted(
+                    &index
+                        .query_params(params.clone().with_limit(limit))
+                        .unwrap(),
+                );
+                let entries = [
+                    dsl(&format!("(limit {limit} {query})")),
+                    dsl(query).with_limit(limit),
+                    dsl(&format!("(limit {} {query})", limit + 10)).with_limit(limit),
+                ];
+                for params in entries {
+                    let result = index.query_params(params.clone()).unwrap();
+                    assert_eq!(counted(&result), expected, "{params:?}");
+                }
+            }
+        }
+
+        // The glob and the second pattern leave the eight in src/, though
+        // thirty outside it come first
+        for params in [
+            QueryParams::symbol("handler").with_glob("src/**"),
+            QueryParams::patterns(vec!["handler".into(), "src_only".into()])
+                .with_match_mode(MatchMode::All),
+        ] {
+            let capped = index.query_params(params.clone().with_limit(5)).unwrap();
+            assert_eq!(counted(&capped).0, 5);
+            assert!(capped.truncated);
+            assert!(capped
+                .handles
+                .iter()
+                .all(|h| h.file_path.starts_with("src/")));
+            let all = index.query_params(params.with_limit(16)).unwrap();
+            assert_eq!(counted(&all), (CROWDED_INSIDE, false, CROWDED_INSIDE));
+        }
+
+        // A limited reference query still returns references
+        let refs = index
+            .query_params(
+                QueryParams::symbol("dispatch")
+                    .with_kind(QueryKind::Reference)
+                    .with_limit(5),
+            )
+            .unwrap();
+        assert_eq!(refs.ref_handles.map(|r| r.len()), Some(5));
+        assert!(refs.truncated && refs.handles.is_empty());
+    }
+
+    #[test]
+    fn dsl_without_a_limit_takes_the_configured_default() {
+        let (repo, _) = crowded_repo();
+        std::fs::write(
+            repo.path().join(".canopy/config.toml"),
+            "[core]\ndefault_result_limit = 7\n",
+        )
+        .unwrap();
+        let index = RepoIndex::open(repo.path()).unwrap();
+        let params = QueryParams {
+            dsl: Some(r#"(code "handler")"#.to_string()),
+            ..QueryParams::new()
+        };
+        let result = index.query_params(params).unwrap();
+        assert_eq!(result.handles.len(), 7);
+        assert!(result.truncated);
+        // An explicit limit wins over the default, whichever side sets it
+        let query = parse_query(r#"(limit 20 (code "handler"))"#).unwrap();
+        assert_eq!(
+            execute_query(&query, &index, None).unwrap().handles.len(),
+            20
+        );
     }
 }
@@ -0,0 +1,496 @@
+//! Explain mode: which search produced each result, and why a query missed.
+//!
+//! With `explain` set, the executor records every index search it runs on an
+//! [`ExplainCollector`] and tags the handles that search returned with
+//! [`Handle::via`]. The record comes back as [`QueryResult::explain`]; when
+//! nothing matched it also carries a checklist of likely causes (how the
+//! pattern was tokenized, whether the symbol exists at all, what the glob and
+//! the recency/generated scopes left out).
+//!
+//! [`QueryResult::explain`]: super::QueryResult::explain
+
+use crate::document::NodeType;
+use crate::handle::Handle;
+use crate::index::search::escape_fts5_query;
+use crate::index::{FtsScan, RepoIndex};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use super::dsl::Query;
+use super::params::split_terms;
+use super::result_class::ResultClass;
+
+/// Candidates read when checking what a miss's glob left out
+const GLOB_CHECK_LIMIT: usize = 100;
+
+/// The index search that produced a result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchPath {
+    /// Full-text search over node content
+    Fts,
+    /// Exact symbol name, answered from the in-memory symbol cache
+    SymbolCache,
+    /// Exact symbol name, answered by the database (scoped searches, or a
+    /// cache miss)
+    SymbolDb,
+    /// Full-text search over symbol names, after no exact match
+    SymbolFuzzy,
+    /// Markdown section headings or heading paths
+    Sections,
+    /// Nodes containing references to a symbol
+    Refs,
+    /// Full-text search restricted to a glob
+    InFile,
+    /// Children of a parent symbol
+    Children,
+    /// Annotation comments
+    Annotations,
+    /// Whole files
+    File,
+    /// Files related to a path
+    Related,
+    /// Named nodes by name prefix
+    Symbols,
+    /// Commit messages of the git-history layer
+    Commits,
+}
+
+impl SearchPath {
+    pub const ALL: [SearchPath; 13] = [
+        SearchPath::Fts,
+        SearchPath::SymbolCache,
+        SearchPath::SymbolDb,
+        SearchPath::SymbolFuzzy,
+        SearchPath::Sections,
+        SearchPath::Refs,
+        SearchPath::InFile,
+        SearchPath::Children,
+        SearchPath::Annotations,
+        SearchPath::File,
+        SearchPath::Related,
+        SearchPath::Symbols,
+        SearchPath::Commits,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Fts => "fts",
+            Self::SymbolCache => "symbol_cache",
+            Self::SymbolDb => "symbol_db",
+            Self::SymbolFuzzy => "symbol_fuzzy",
+            Self::Sections => "sections",
+            Self::Refs => "refs",
+            Self::InFile => "in_file",
+            Self::Children => "children",
+            Self::Annotations => "annotations",
+            Self::File => "file",
+            Self::Related => "related",
+            Self::Symbols => "symbols",
+            Self::Commits => "commits",
+        }
+    }
+}
+
+/// How a query was answered, returned with `explain` set.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueryExplain {
+    /// Every index search run, in order; sharded indexes run one per database
+    pub searches: Vec<SearchExplain>,
+    /// Candidates glob filtering rejected before the limit was reached
+    #[serde(default)]
+    pub glob_filtered: usize,
+    /// Node type priors that weighted which handles to auto-expand
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_type_priors: Option<BTreeMap<String, f64>>,
+    /// An external reranker reordered the candidates
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reranked: bool,
+    /// With no results: what to check
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checklist: Vec<String>,
+}
+
+/// One index search of an explained query.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchExplain {
+    pub via: SearchPath,
+    /// The pattern, symbol, heading or path searched for
+    pub input: String,
+    /// MATCH string after escaping, for full-text searches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fts_query: Option<String>,
+    /// SQL conditions appended for the recency window and generated files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    /// Glob the results had to match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub glob: Option<String>,
+    pub limit: usize,
+    /// Results the search returned
+    pub returned: usize,
+    /// Candidate rows read and time taken, for full-text searches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scan: Option<FtsScan>,
+}
+
+impl SearchExplain {
+    pub(crate) fn new(via: SearchPath, input: &str, limit: usize) -> Self {
+        Self {
+            via,
+            input: input.to_string(),
+            fts_query: None,
+            filter: None,
+            glob: None,
+            limit,
+            returned: 0,
+            scan: None,
+        }
+    }
+
+    /// Record the escaped MATCH string for `pattern`.
+    pub(crate) fn with_fts(mut self, pattern: &str) -> Self {
+        self.fts_query = Some(escape_fts5_query(pattern));
+        self
+    }
+
+    pub(crate) fn with_scan(mut self, scan: FtsScan) -> Self {
+        self.scan = Some(scan);
+        self
+    }
+
+    pub(crate) fn with_glob(mut self, glob: &str) -> Self {
+        self.glob = Some(glob.to_string());
+        self
+    }
+}
+
+/// Searches recorded while a query runs; does nothing unless enabled, apart
+/// from counting exclusions and capped scans, which every result reports.
+#[derive(Debug, Default)]
+pub(crate) struct ExplainCollector {
+    enabled: bool,
+    explain: QueryExplain,
+    excluded: usize,
+    truncated_scan: bool,
+}
+
+impl ExplainCollector {
+    pub(crate) fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            explain: QueryExplain::default(),
+            excluded: 0,
+            truncated_scan: false,
+        }
+    }
+
+    /// Add candidates exclude patterns removed.
+    pub(crate) fn record_excluded(&mut self, removed: usize) {
+        self.excluded += removed;
+    }
+
+    /// Candidates exclude patterns removed, for
+    /// [`QueryResult::excluded_matches`](super::QueryResult::excluded_matches).
+    pub(crate) fn excluded_matches(&self) -> usize {
+        self.excluded
+    }
+
+    /// Whether a full-text search stopped at `[fts] max_fts_candidates`, for
+    /// [`QueryResult::truncated_scan`](super::QueryResult::truncated_scan).
+    pub(crate) fn truncated_scan(&self) -> bool {
+        self.truncated_scan
+    }
+
+    /// Record `search` of `index` and tag `handles` with its path and
+    /// result class.
+    pub(crate) fn tagged(
+        &mut self,
+        index: &RepoIndex,
+        search: SearchExplain,
+        mut handles: Vec<Handle>,
+    ) -> Vec<Handle> {
+        // The class ranks mixed results, so it is set whether or not explain is on
+        let class = ResultClass::of(search.via);
+        self.truncated_scan |= search.scan.is_some_and(|scan| scan.capped);
+        for handle in &mut handles {
+            handle.result_class = Some(class);
+        }
+        if self.enabled {
+            for handle in &mut handles {
+                handle.via = Some(search.via);
+            }
+            self.record(index, search, handles.len());
+        }
+        handles
+    }
+
+    /// Record `search` of `index`, which returned `returned` results.
+    pub(crate) fn record(&mut self, index: &RepoIndex, mut search: SearchExplain, returned: usize) {
+        if !self.enabled {
+            return;
+        }
+        let filter = index.scope_filter();
+        search.filter =
+            (!filter.is_empty()).then(|| filter.trim_start_matches(" AND ").to_string());
+        search.returned = returned;
+        self.explain.searches.push(search);
+    }
+
+    /// Position to pass to [`glob_filtered_since`](Self::glob_filtered_since).
+    pub(crate) fn mark(&self) -> usize {
+        self.explain.searches.len()
+    }
+
+    /// Note that `glob` filtered the results of the searches recorded since
+    /// `mark`, rejecting `rejected` of them.
+    pub(crate) fn glob_filtered_since(&mut self, mark: usize, glob: &str, rejected: usize) {
+        if !self.enabled {
+            return;
+        }
+        for search in &mut self.explain.searches[mark..] {
+            search.glob.get_or_insert_with(|| glob.to_string());
+        }
+        self.explain.glob_filtered += rejected;
+    }
+
+    /// Add candidates a glob rejected inside a search.
+    pub(crate) fn glob_filtered(&mut self, rejected: usize) {
+        self.explain.glob_filtered += rejected;
+    }
+
+    /// The finished record for `query`, or None when explain is off.
+    pub(crate) fn finish(
+        self,
+        query: &Query,
+        index: &RepoIndex,
+        handles: &[Handle],
+        matched: bool,
+        node_type_priors: Option<&HashMap<NodeType, f64>>,
+    ) -> Option<QueryExplain> {
+        if !self.enabled {
+            return None;
+        }
+        let mut explain = self.explain;
+        explain.node_type_priors = node_type_priors.map(|priors| {
+            priors
+                .iter()
+                .map(|(node_type, weight)| (node_type.as_str().to_string(), *weight))
+                .collect()
+        });
+        explain.reranked = handles.iter().any(|h| h.rerank_score.is_some());
+        if !matched {
+            explain.checklist = miss_checklist(query, index, &explain.searches);
+            if self.excluded > 0 {
+                explain.checklist.push(format!(
+                    "Exclude patterns removed {} matching nodes; loosen them to see those.",
+                    self.excluded
+                ));
+            }
+        }
+        Some(explain)
+    }
+}
+
+/// Likely causes of a query matching nothing.
+fn miss_checklist(query: &Query, index: &RepoIndex, searches: &[SearchExplain]) -> Vec<String> {
+    let mut checklist = Vec::new();
+    check_misses(query, index, &mut checklist);
+    if searches.iter().any(|s| s.fts_query.is_some()) {
+        let sampled = index.fts_sampled_nodes().unwrap_or(0);
+        if sampled > 0 {
+            checklist.push(format!(
+                "{sampled} nodes are full-text indexed from a sample of their content (over `[indexing] max_fts_bytes_per_node`); text deep inside them matches only on lines introducing new identifiers."
+            ));
+        }
+    }
+    if let Some(filter) = searches.iter().find_map(|s| s.filter.as_deref()) {
+        checklist.push(format!(
+            "Searches were scoped by `{filter}`: a recency window, a path fragment, or generated files left out (include_generated searches them)."
+        ));
+    }
+    checklist.dedup();
+    checklist
+}
+
+fn check_misses(query: &Query, index: &RepoIndex, checklist: &mut Vec<String>) {
+    match query {
+        Query::Grep(pattern) => checklist.push(tokenization(pattern)),
+        Query::Code(symbol) | Query::Definition(symbol) | Query::ChildrenNamed(_, symbol) => {
+            checklist.push(symbol_check(symbol, index));
+        }
+        Query::InFile(glob, inner) => {
+            check_misses(inner, index, checklist);
+            checklist.push(glob_check(glob, inner, index));
+        }
+        Query::Limit(_, inner)
+        | Query::Recent(_, inner)
+        | Query::PathContains(_, inner)
+        | Query::Exclude(_, inner) => check_misses(inner, index, checklist),
+        Query::Union(queries) | Query::Intersect(queries) => {
+            for query in queries {
+                check_misses(query, index, checklist);
+            }
+        }
+        Query::Commits(_) => checklist.push(history_check(index)),
+        _ => {}
+    }
+}
+
+fn history_check(index: &RepoIndex) -> String {
+    match index.commit_count().unwrap_or(0) {
+        0 => "No commits are indexed; the git-history layer is filled by index runs with `[history] enabled`, or by `canopy index --git-history`.".to_string(),
+        count => format!(
+            "{count} commits are indexed, from within `[history] since` ({}); older ones aren't searched.",
+            index.config().history.since
+        ),
+    }
+}
+
+fn tokenization(pattern: &str) -> String {
+    let terms = split_terms(pattern);
+    format!(
+        "Pattern {pattern:?} ran as FTS query `{}` over the terms [{}]; every term has to appear in one node.",
+        escape_fts5_query(pattern),
+        terms.join(", ")
+    )
+}
+
+fn symbol_check(symbol: &str, index: &RepoIndex) -> String {
+    let lower = symbol.to_lowercase();
+    let mut definitions = 0;
+    let mut spelled = None;
+    for target in index.all_indexes() {
+        let spellings = target.symbol_spellings(&lower).unwrap_or_default();
+        definitions += spellings.len();
+        if spelled.is_none() {
+            spelled = spellings.into_iter().find(|n| n != symbol);
+        }
+    }
+    match (definitions, spelled) {
+        (0, _) => format!(
+            "No code symbol is named {symbol:?} in any case; try a pattern search or a suggestion."
+        ),
+        (n, Some(name)) => format!(
+            "Symbol {symbol:?} is indexed as {name:?} ({n} definitions); lookups ignore case, so a glob or scope left them out."
+        ),
+        (n, None) => format!(
+            "Symbol {symbol:?} has {n} indexed definitions; a glob or scope left them out."
+        ),
+    }
+}
+
+fn glob_check(glob: &str, inner: &Query, index: &RepoIndex) -> String {
+    let outside = super::executor::unglobbed_matches(inner, index, GLOB_CHECK_LIMIT)
+        .map(|handles| handles.len())
+        .unwrap_or(0);
+    let check = if outside == 0 {
+        format!("Glob {glob:?} excluded nothing: the search matched no files at all.")
+    } else {
+        format!("Glob {glob:?} excluded {outside} matching nodes in other files.")
+    };
+    match super::executor::unmatched_glob_note(glob, index) {
+        Ok(Some(note)) => format!("{note} {check}"),
+        _ => check,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::QueryParams;
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+
+    fn auth_repo() -> (FixtureRepo, RepoIndex) {
+        let repo = FixtureRepoBuilder::new()
+            .file(
+                "src/auth.rs",
+                "pub fn verify_token() {}\nfn login() { verify_token(); }\n",
+            )
+            .file("docs/auth.md", "# Tokens\n\nverify_token rules\n")
+            .build();
+        let mut index = RepoIndex::open_or_init(repo.path()).unwrap();
+        index.index("**/*").unwrap();
+        (repo, index)
+    }
+
+    fn explained(index: &RepoIndex, params: QueryParams) -> crate::query::QueryResult {
+        index.query_params(params.with_explain(true)).unwrap()
+    }
+
+    fn vias(result: &crate::query::QueryResult) -> Vec<SearchPath> {
+        result.handles.iter().filter_map(|h| h.via).collect()
+    }
+
+    #[test]
+    fn handles_say_which_search_found_them() {
+        let (_dir, index) = auth_repo();
+        let result = explained(&index, QueryParams::symbol("verify_token"));
+        assert_eq!(vias(&result), vec![SearchPath::SymbolCache]);
+        let explain = result.explain.unwrap();
+        assert_eq!(explain.searches.len(), 1);
+        assert_eq!(explain.searches[0].returned, 1);
+        assert!(explain.checklist.is_empty());
+
+        // A scoped lookup can't use the cache and goes to the database
+        let recent = QueryParams {
+            modified_within: Some("7d".to_string()),
+            ..QueryParams::symbol("verify_token")
+        };
+        let result = explained(&index, recent);
+        assert_eq!(vias(&result), vec![SearchPath::SymbolDb]);
+        assert!(result.explain.unwrap().searches[0].filter.is_some());
+
+        let result = explained(&index, QueryParams::symbol("verify"));
+        assert!(!result.handles.is_empty());
+        assert!(vias(&result).iter().all(|v| *v == SearchPath::SymbolFuzzy));
+
+        let result = explained(&index, QueryParams::pattern("verify_token rules"));
+        assert!(!result.handles.is_empty());
+        assert!(vias(&result).iter().all(|v| *v == SearchPath::Fts));
+        let search = &result.explain.unwrap().searches[0];
+        assert_eq!(
+            search.fts_query.as_deref(),
+            Some(escape_fts5_query("verify_token rules").as_str())
+        );
+
+        // Without explain, nothing is tagged or recorded
+        let plain = index
+            .query_params(QueryParams::symbol("verify_token"))
+            .unwrap();
+        assert!(plain.explain.is_none());
+        assert!(plain.handles.iter().all(|h| h.via.is_none()));
+    }
+
+    #[test]
+    fn misses_come_with_a_checklist() {
+        let (_dir, index) = auth_repo();
+        let result = explained(
+            &index,
+            QueryParams::symbol("VERIFY_TOKEN").with_glob("lib/**"),
+        );
+        assert!(result.handles.is_empty());
+        let explain = result.explain.unwrap();
+        assert!(explain
+            .checklist
+            .iter()
+            .any(|c| c.contains("\"verify_token\"")));
+        assert!(explain
+            .checklist
+            .iter()
+            .any(|c| c.contains("excluded 1 matching nodes")));
+
+        let result = explained(&index, QueryParams::pattern("session cookie"));
+        let checklist = result.explain.unwrap().checklist;
+        assert!(checklist[0].contains("[session, cookie]"), "{checklist:?}");
+
+        let result = explained(
+            &index,
+            QueryParams::pattern("verify_token").with_glob("**/*.py"),
+        );
+        let explain = result.explain.unwrap();
+        assert_eq!(explain.searches[0].via, SearchPath::InFile);
+        assert_eq!(explain.searches[0].glob.as_deref(), Some("**/*.py"));
+        assert!(explain.glob_filtered > 0);
+    }
+}
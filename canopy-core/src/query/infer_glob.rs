@@ -0,0 +1,337 @@
+//! Glob inference: scoping a query to the directories its terms name.
+//!
+//! With `infer_glob` set and no glob given, a query for "websocket reconnect"
+//! in a repo with `src/websocket/` runs against `src/websocket/**` first. The
+//! terms are matched against the names of the indexed directories, directly
+//! or through [`KEYWORD_PATTERNS`], the synonyms predictive indexing uses.
+
+use crate::index::RepoIndex;
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::params::QueryKind;
+use super::QueryParams;
+
+/// Most directories an inferred glob may cover; a query naming more isn't
+/// about any one part of the repo.
+pub const MAX_INFERRED_DIRECTORIES: usize = 3;
+
+/// Keyword to directory pattern mappings
+/// Patterns use ** for recursive matching, will be combined with extensions
+pub const KEYWORD_PATTERNS: &[(&[&str], &[&str])] = &[
+    // Auth-related
+    (
+        &[
+            "auth",
+            "login",
+            "logout",
+            "session",
+            "jwt",
+            "oauth",
+            "password",
+            "credential",
+        ],
+        &[
+            "**/auth/**",
+            "**/login/**",
+            "**/session/**",
+            "**/authentication/**",
+        ],
+    ),
+    // Database-related
+    (
+        &[
+            "database",
+            "db",
+            "query",
+            "sql",
+            "orm",
+            "repository",
+            "migration",
+        ],
+        &[
+            "**/db/**",
+            "**/database/**",
+            "**/repositories/**",
+            "**/repo/**",
+        ],
+    ),
+    // API-related
+    (
+        &[
+            "api",
+            "endpoint",
+            "route",
+            "controller",
+            "handler",
+            "rest",
+            "graphql",
+        ],
+        &[
+            "**/api/**",
+            "**/routes/**",
+            "**/controllers/**",
+            "**/handlers/**",
+            "**/endpoints/**",
+        ],
+    ),
+    // Config-related
+    (
+        &["config", "configuration", "env", "settings", "options"],
+        &["**/config/**", "**/settings/**", "**/conf/**"],
+    ),
+    // Middleware
+    (
+        &["middleware", "interceptor", "filter", "guard"],
+        &[
+            "**/middleware/**",
+            "**/middlewares/**",
+            "**/interceptors/**",
+            "**/guards/**",
+        ],
+    ),
+    // Workflow/execution
+    (
+        &[
+            "workflow",
+            "execution",
+            "engine",
+            "runner",
+            "worker",
+            "job",
+            "queue",
+        ],
+        &[
+            "**/workflow/**",
+            "**/workflows/**",
+            "**/execution/**",
+            "**/engine/**",
+            "**/workers/**",
+            "**/jobs/**",
+        ],
+    ),
+    // Core/shared
+    (
+        &["core", "shared", "common", "utils", "helpers", "lib"],
+        &[
+            "**/core/**",
+            "**/shared/**",
+            "**/common/**",
+            "**/utils/**",
+            "**/lib/**",
+        ],
+    ),
+    // Service layer
+    (
+        &["service", "services"],
+        &["**/services/**", "**/service/**"],
+    ),
+];
+
+/// The glob to scope `params` to, when it asks for inference, has no scope
+/// of its own and its terms name a few indexed directories.
+pub(crate) fn infer_glob(params: &QueryParams, index: &RepoIndex) -> crate::Result<Option<String>> {
+    if params.infer_glob != Some(true)
+        || params.glob.is_some()
+        || params.path_contains.is_some()
+        || params.dsl.is_some()
+        || params.kind == QueryKind::Commit
+    {
+        return Ok(None);
+    }
+    let names = directory_names(&params.to_text());
+    if names.is_empty() {
+        return Ok(None);
+    }
+    let (directories, total_files) = index.indexed_directories()?;
+    Ok(glob_for(&names, &directories, total_files))
+}
+
+/// Directory names the query text points at: each term of three or more
+/// characters, plus the directories of the keyword groups any term is in.
+fn directory_names(text: &str) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    for term in text.split(|c: char| !c.is_alphanumeric()) {
+        let term = term.to_lowercase();
+        for (keywords, patterns) in KEYWORD_PATTERNS {
+            if keywords.contains(&term.as_str()) {
+                names.extend(
+                    patterns
+                        .iter()
+                        .map(|p| p.trim_matches(['*', '/']).to_string()),
+                );
+            }
+        }
+        if term.chars().count() >= 3 {
+            names.insert(term);
+        }
+    }
+    names
+}
+
+/// Glob over the directories named by `names`, if one to
+/// [`MAX_INFERRED_DIRECTORIES`] of them match. A directory nested in another
+/// match adds nothing, and one holding every file doesn't narrow anything.
+fn glob_for(
+    names: &BTreeSet<String>,
+    directories: &BTreeMap<String, usize>,
+    total_files: usize,
+) -> Option<String> {
+    let mut matched: Vec<&str> = Vec::new();
+    for (dir, files) in directories {
+        if *files == total_files || dir.contains(['*', '?', '[', ']', '{', '}']) {
+            continue;
+        }
+        if matched
+            .iter()
+            .any(|parent| dir.starts_with(&format!("{parent}/")))
+        {
+            continue;
+        }
+        let name = dir.rsplit('/').next().unwrap_or(dir).to_lowercase();
+        let singular = name.strip_suffix('s').unwrap_or(&name);
+        if names.contains(&name) || names.contains(singular) || names.contains(&format!("{name}s"))
+        {
+            matched.push(dir);
+        }
+    }
+    match matched.as_slice() {
+        [] => None,
+        [dir] => Some(format!("{dir}/**")),
+        dirs if dirs.len() <= MAX_INFERRED_DIRECTORIES => Some(format!(
+            "{{{}}}",
+            dirs.iter()
+                .map(|dir| format!("{dir}/**"))
+                .collect::<Vec<_>>()
+                .join(",")
+        )),
+        _ => None,
+    }
+}
+
+/// Note for a result that fell back to the whole repo after `glob` found
+/// nothing.
+pub(crate) fn dropped_glob_note(glob: &str) -> String {
+    format!("Nothing matched in the inferred glob '{glob}', so it was dropped and the whole repo searched.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::QueryResult;
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+
+    fn websocket_repo() -> (FixtureRepo, RepoIndex) {
+        let repo = FixtureRepoBuilder::new()
+            .file(
+                "src/websocket/reconnect.rs",
+                "/// Reopen a dropped websocket with backoff.\npub fn reconnect_websocket() {}\n",
+            )
+            .file(
+                "src/http/server.rs",
+                "/// Serve plain requests.\npub fn serve_http() {}\n",
+            )
+            .file(
+                "docs/websocket.md",
+                "# WebSocket\n\nEach websocket reconnect starts with a new handshake.\n",
+            )
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let mut index = RepoIndex::open(repo.path()).unwrap();
+        index.index("**/*").unwrap();
+        (repo, index)
+    }
+
+    fn files(result: &QueryResult) -> Vec<&str> {
+        let mut files: Vec<&str> = result
+            .handles
+            .iter()
+            .map(|h| h.file_path.as_str())
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    #[test]
+    fn scopes_to_the_named_directory_first() {
+        let (_repo, index) = websocket_repo();
+        let params = QueryParams::pattern("websocket reconnect");
+        let unscoped = index.query_params(params.clone()).unwrap();
+        assert_eq!(
+            files(&unscoped),
+            vec!["docs/websocket.md", "src/websocket/reconnect.rs"]
+        );
+        assert_eq!(unscoped.inferred_glob, None);
+
+        let scoped = index.query_params(params.with_infer_glob(true)).unwrap();
+        assert_eq!(files(&scoped), vec!["src/websocket/reconnect.rs"]);
+        assert_eq!(scoped.inferred_glob.as_deref(), Some("src/websocket/**"));
+
+        // A glob of the caller's own is left alone
+        let own = index
+            .query_params(
+                QueryParams::pattern("websocket reconnect")
+                    .with_glob("docs/**")
+                    .with_infer_glob(true),
+            )
+            .unwrap();
+        assert_eq!(files(&own), vec!["docs/websocket.md"]);
+        assert_eq!(own.inferred_glob, None);
+    }
+
+    #[test]
+    fn drops_an_inferred_glob_that_matches_nothing() {
+        let (_repo, index) = websocket_repo();
+        let result = index
+            .query_params(QueryParams::pattern("websocket handshake").with_infer_glob(true))
+            .unwrap();
+        assert_eq!(files(&result), vec!["docs/websocket.md"]);
+        assert_eq!(result.inferred_glob, None);
+        let note = result.expand_note.unwrap();
+        assert!(note.contains("'src/websocket/**'"), "{note}");
+        assert!(note.contains("dropped"), "{note}");
+    }
+
+    fn directories(entries: &[(&str, usize)]) -> BTreeMap<String, usize> {
+        entries
+            .iter()
+            .map(|(dir, files)| (dir.to_string(), *files))
+            .collect()
+    }
+
+    #[test]
+    fn terms_and_their_synonyms_name_directories() {
+        let names = directory_names("WebSocket reconnect in a db");
+        assert!(names.contains("websocket") && names.contains("reconnect"));
+        assert!(!names.contains("in"));
+        // Short terms still pull in their keyword group
+        assert!(names.contains("db") && names.contains("repositories"));
+    }
+
+    #[test]
+    fn globs_one_to_three_outermost_directories() {
+        let dirs = directories(&[
+            ("docs", 4),
+            ("src", 10),
+            ("src/websocket", 3),
+            ("src/websocket/frames", 1),
+            ("web/handlers", 2),
+        ]);
+        let names = directory_names("websocket handler");
+        assert_eq!(
+            glob_for(&names, &dirs, 14).as_deref(),
+            Some("{src/websocket/**,web/handlers/**}")
+        );
+        assert_eq!(
+            glob_for(&directory_names("websockets"), &dirs, 14).as_deref(),
+            Some("src/websocket/**")
+        );
+
+        // A directory holding everything doesn't narrow the query
+        assert_eq!(glob_for(&directory_names("src"), &dirs, 10), None);
+        assert_eq!(glob_for(&directory_names("reconnect"), &dirs, 14), None);
+
+        let many = directories(&[("a/api", 1), ("b/api", 1), ("c/api", 1), ("d/api", 1)]);
+        assert_eq!(glob_for(&directory_names("api"), &many, 8), None);
+    }
+}
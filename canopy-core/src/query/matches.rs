@@ -0,0 +1,154 @@
+//! Where a grep/pattern match falls inside each result node.
+//!
+//! FTS only says a node matched, so handles carry the node's whole line range.
+//! This locates the first occurrence of the query's grep terms in the indexed
+//! node text and converts it to an absolute line for editor jumps.
+
+use crate::handle::Handle;
+use crate::index::RepoIndex;
+
+use super::dsl::Query;
+use super::params::split_terms;
+
+/// Grep terms anywhere in `query`, lowercased and deduplicated. Symbol,
+/// section and other non-FTS leaves contribute none.
+pub(crate) fn grep_terms(query: &Query) -> Vec<String> {
+    let mut terms = Vec::new();
+    collect_grep_terms(query, &mut terms);
+    let mut seen = std::collections::HashSet::new();
+    terms.retain(|term| seen.insert(term.clone()));
+    terms
+}
+
+fn collect_grep_terms(query: &Query, terms: &mut Vec<String>) {
+    match query {
+        Query::Grep(pattern) => terms.extend(split_terms(pattern)),
+        Query::InFile(_, inner) | Query::Limit(_, inner) | Query::Exclude(_, inner) => {
+            collect_grep_terms(inner, terms)
+        }
+        Query::Union(queries) | Query::Intersect(queries) => {
+            for q in queries {
+                collect_grep_terms(q, terms);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Byte offset of the first occurrence of any of `terms` in `text`, ignoring
+/// ASCII case, and the total number of occurrences.
+pub(crate) fn locate_terms(text: &str, terms: &[String]) -> Option<(usize, usize)> {
+    // ASCII lowercasing keeps byte offsets aligned with `text`
+    let haystack = text.to_ascii_lowercase();
+    let mut first: Option<usize> = None;
+    let mut count = 0;
+    for term in terms.iter().filter(|t| !t.is_empty()) {
+        let needle = term.to_ascii_lowercase();
+        for (offset, _) in haystack.match_indices(&needle) {
+            first = Some(first.map_or(offset, |f| f.min(offset)));
+            count += 1;
+        }
+    }
+    first.map(|offset| (offset, count))
+}
+
+/// Zero-based line of `offset` within `text`.
+pub(crate) fn line_of_offset(text: &str, offset: usize) -> usize {
+    let end = offset.min(text.len());
+    text.as_bytes()[..end]
+        .iter()
+        .filter(|&&b| b == b'\n')
+        .count()
+}
+
+/// Fill `match_line`/`match_count_in_node` on `handles` for `query`'s grep terms.
+/// Handles whose text doesn't contain a term literally (e.g. FTS matched a
+/// camelCase sub-token) keep both unset.
+pub(crate) fn annotate_match_lines(
+    index: &RepoIndex,
+    query: &Query,
+    handles: &mut [Handle],
+) -> crate::Result<()> {
+    let terms = grep_terms(query);
+    if terms.is_empty() || handles.is_empty() {
+        return Ok(());
+    }
+    let raw_ids: Vec<&str> = handles.iter().map(|h| h.id.raw()).collect();
+    let contents = index.indexed_contents(&raw_ids)?;
+    for handle in handles {
+        let Some(text) = contents.get(handle.id.raw()) else {
+            continue;
+        };
+        if let Some((offset, count)) = locate_terms(text, &terms) {
+            handle.match_line = Some(handle.line_range.0 + line_of_offset(text, offset));
+            handle.match_count_in_node = Some(count);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{execute_query, parse_query};
+    use std::fs;
+
+    #[test]
+    fn locate_is_case_insensitive_and_counts_all_terms() {
+        let text = "fn a() {\n    Token::new();\n    // token, TOKEN\n}\n";
+        let (offset, count) = locate_terms(text, &["token".to_string()]).unwrap();
+        assert_eq!(line_of_offset(text, offset), 1);
+        assert_eq!(count, 3);
+        assert!(locate_terms(text, &["absent".to_string()]).is_none());
+    }
+
+    #[test]
+    fn grep_terms_skip_symbol_leaves() {
+        let query = parse_query(r#"(union (grep "needle") (code "Other"))"#).unwrap();
+        assert_eq!(grep_terms(&query), vec!["needle"]);
+        assert!(grep_terms(&parse_query(r#"(code "needle")"#).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn match_line_is_absolute_within_the_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        let body = (2..=6)
+            .map(|i| format!("    let step_{i} = {i};\n"))
+            .collect::<String>();
+        // Two lines precede the function; the term sits on its 7th line
+        fs::write(
+            dir.path().join("src/lib.rs"),
+            format!(
+                "// header\n\npub fn long_function() {{\n{body}    rare_marker_call();\n    rare_marker_call();\n}}\n"
+            ),
+        )
+        .unwrap();
+        RepoIndex::init(dir.path()).unwrap();
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let result = execute_query(
+            &parse_query(r#"(grep "rare_marker_call")"#).unwrap(),
+            &index,
+            None,
+        )
+        .unwrap();
+        let handle = result
+            .handles
+            .iter()
+            .find(|h| h.node_type == crate::NodeType::Function)
+            .expect("function handle");
+        assert_eq!(handle.line_range.0, 3);
+        assert_eq!(handle.match_line, Some(9));
+        assert_eq!(handle.match_count_in_node, Some(2));
+
+        let symbol = execute_query(
+            &parse_query(r#"(code "long_function")"#).unwrap(),
+            &index,
+            None,
+        )
+        .unwrap();
+        assert!(symbol.handles.iter().all(|h| h.match_line.is_none()));
+    }
+}
@@ -4,34 +4,94 @@
 //! - `dsl` — Query AST and S-expression parser
 //! - `params` — QueryParams builder API and match/kind types
 //! - `executor` — Query execution against a RepoIndex
+//! - `count` — Count and exists modes, answered without building handles
 //! - `evidence` — Evidence pack types and ranked evidence builder
-
+//! - `explain` — Which search produced each result, for explain mode
+//! - `infer_glob` — Scoping a query to the directories its terms name
+//! - `matches` — Matched line within each grep result node
+//! - `references` — Folding reference results with identical previews
+//! - `rerank` — Pluggable candidate reranking
+//! - `savings` — Token savings versus reading result files whole
+
+mod count;
 pub mod dsl;
 pub mod evidence;
 pub mod executor;
+pub mod explain;
+pub mod infer_glob;
+mod matches;
 pub mod params;
+mod references;
+pub mod rerank;
+mod result_class;
+pub mod savings;
 
-pub use dsl::{parse_query, Query};
+pub use dsl::{parse_query, FileSlice, Query};
 pub use evidence::{
-    build_evidence_pack, EvidenceAction, EvidenceConfidence, EvidenceFileSummary, EvidenceGuidance,
-    EvidenceHandle, EvidencePack,
+    build_evidence_pack, build_evidence_pack_with_priors, CalibrationConfig, EvidenceAction,
+    EvidenceConfidence, EvidenceFileSummary, EvidenceGuidance, EvidenceHandle, EvidenceOverflow,
+    EvidencePack,
+};
+pub use executor::{
+    execute_params, execute_query, execute_query_with_options, DEFAULT_EXPAND_BUDGET,
 };
-pub use executor::{execute_query, execute_query_with_options, DEFAULT_EXPAND_BUDGET};
-pub use params::{split_terms, MatchMode, QueryKind, QueryParams};
+pub use explain::{QueryExplain, SearchExplain, SearchPath};
+pub use params::{split_terms, MatchMode, MergeStrategy, QueryKind, QueryMode, QueryParams};
+#[cfg(feature = "external")]
+pub use rerank::ExternalReranker;
+pub use rerank::{apply_reranker, Reranker};
+pub use result_class::ResultClass;
+pub use savings::TokenSavings;
 
 use crate::document::NodeType;
-use crate::handle::{Handle, RefHandle};
+use crate::handle::{AnnotationHandle, Handle, RefHandle};
+use crate::index::{CommitEntry, FileQueryOptions, SymbolSuggestion};
+use crate::schema::SchemaVersion;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 
 /// Query result with handles
+///
+/// Ordering is total and repeatable: handles come by score where the query
+/// has one (FTS rank), then by path, start byte and handle id
+/// ([`Handle::position_cmp`]). The same query against the same index returns
+/// the same handles in the same order, however the index was built.
+///
+/// The limit is the same whichever way the query came in (DSL, params, CLI
+/// flags or the service): up to N results after every filter (glob,
+/// `path_contains`, exclusions, `match: all`), where N is the smaller of the
+/// caller's `limit` and the query's own `(limit N ...)`, or
+/// `default_result_limit` when neither is given. Results are the handles, or
+/// the refs, annotations or commits of those kinds of query; `truncated` and
+/// `total_matches` count them the same way for every kind.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct QueryResult {
+    #[serde(default)]
+    pub schema_version: SchemaVersion,
     pub handles: Vec<Handle>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ref_handles: Option<Vec<RefHandle>>,
+    /// Reference queries: matches per ref type before any `ref_types` filter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ref_type_counts: Option<BTreeMap<String, usize>>,
+    /// Marker comments matched by an annotation query
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Vec<AnnotationHandle>>,
+    /// Commits matched by a `(commits ...)` query, from the git-history layer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commits: Option<Vec<CommitEntry>>,
+    /// Sum of the handles' `token_count`: what expanding all of them costs
     pub total_tokens: usize,
+    /// Sum of the handles' `preview_tokens`: what the previews in this
+    /// response cost
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub preview_tokens: usize,
+    /// More results matched than the limit let through
     pub truncated: bool,
+    /// Results the search found: exact when not `truncated`, otherwise a
+    /// lower bound, since the search stops a little past the limit (count
+    /// mode gives the exact figure)
     pub total_matches: usize,
     /// True if handles have content populated (auto-expanded)
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
@@ -48,14 +108,102 @@ pub struct QueryResult {
     /// Handle IDs that already include `content` in this response.
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub expanded_handle_ids: Vec<String>,
+    /// Service handles dropped during dirty-file merge because the local index superseded them
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub suppressed_service_handles: usize,
+    /// Service mode: results hidden by the repo's path policy (handles, refs
+    /// and their grouped occurrences, annotations)
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub suppressed_by_policy: usize,
+    /// Matches in generated files, left out because `include_generated`
+    /// wasn't set
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub suppressed_generated: usize,
+    /// Candidates left out because their content matched one of the query's
+    /// exclude patterns; they don't count towards `total_matches`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub excluded_matches: usize,
+    /// Secrets `[redaction]` masked in the expanded content of this response
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub redactions: usize,
+    /// Nearby symbol names when a symbol query matched nothing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<SymbolSuggestion>,
+    /// Tokens returned versus reading every result file whole
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub savings: Option<TokenSavings>,
+    /// Service mode: where the merged handles came from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sources: Option<SourceCounts>,
+    /// Count and exists modes carry no handles, only `total_matches` and
+    /// the fields below
+    #[serde(default, skip_serializing_if = "QueryMode::is_handles")]
+    pub mode: QueryMode,
+    /// Exists mode: whether anything matched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exists: Option<bool>,
+    /// Exists mode: a file holding a match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub witness_path: Option<String>,
+    /// Count mode: matches of each search the query combines (each pattern
+    /// of a multi-pattern query), keyed by its DSL form
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_counts: Option<BTreeMap<String, usize>>,
+    /// Legs of a union or intersection that failed (e.g. a malformed
+    /// pattern). The result is built from the rest: a failed union leg adds
+    /// nothing, a failed intersection leg matches nothing.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pattern_errors: Vec<PatternError>,
+    /// Which searches ran and what they returned; set in explain mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explain: Option<QueryExplain>,
+    /// Answered read-only from an index written by a newer canopy, whose
+    /// extra data this binary ignores
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub compat_mode: bool,
+    /// A full-text search stopped at `[fts] max_fts_candidates` and answered
+    /// from the candidates read so far
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated_scan: bool,
+    /// Glob the query was scoped to by `infer_glob`; unset when none was
+    /// inferred or the scoped query matched nothing and was rerun unscoped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inferred_glob: Option<String>,
+}
+
+/// A pattern of a multi-pattern query that failed to run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PatternError {
+    /// The pattern as given, or the DSL form of a non-grep leg
+    pub pattern: String,
+    pub message: String,
+}
+
+/// Per-source breakdown of a merged local + service result.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceCounts {
+    /// Handles from the local index of dirty files
+    pub local: usize,
+    /// Handles from the service
+    pub service: usize,
+    /// The local query hit its limit, or the overall limit cut local handles
+    pub local_truncated: bool,
+    /// The service query hit its limit, or the overall limit cut service handles
+    pub service_truncated: bool,
 }
 
 fn is_zero(v: &usize) -> bool {
     *v == 0
 }
 
+/// Decides which files count and exists queries may count matches in.
+pub trait PathPredicate: std::fmt::Debug + Send + Sync {
+    /// Whether matches under the repo-relative `path` count.
+    fn permits(&self, path: &str) -> bool;
+}
+
 /// Query options for executing queries
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct QueryOptions {
     /// Override default result limit
     pub limit: Option<usize>,
@@ -63,6 +211,24 @@ pub struct QueryOptions {
     pub expand_budget: Option<usize>,
     /// Learned node type priors for scoring partial auto-expansion
     pub node_type_priors: Option<HashMap<NodeType, f64>>,
+    /// Reorders collected candidates before the limit is applied
+    pub reranker: Option<Arc<dyn Reranker>>,
+    /// Caps and paging for `(file ...)` queries; the DSL's `:limit`/`:offset`
+    /// take precedence
+    pub files: FileQueryOptions,
+    /// Handles, or only a count / existence check
+    pub mode: QueryMode,
+    /// Fold reference results with identical previews into one entry
+    /// listing the others; unset groups imports only
+    pub group_references: Option<bool>,
+    /// Also search files flagged as generated
+    pub include_generated: bool,
+    /// Record which searches ran and tag each handle with the one that
+    /// found it
+    pub explain: bool,
+    /// Count and exists modes count only matches in files this permits;
+    /// handles mode leaves filtering handles to the caller
+    pub path_predicate: Option<Arc<dyn PathPredicate>>,
 }
 
 impl QueryOptions {
@@ -84,6 +250,31 @@ impl QueryOptions {
         self.node_type_priors = Some(priors);
         self
     }
+
+    pub fn with_reranker(mut self, reranker: Arc<dyn Reranker>) -> Self {
+        self.reranker = Some(reranker);
+        self
+    }
+
+    pub fn with_file_options(mut self, files: FileQueryOptions) -> Self {
+        self.files = files;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: QueryMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_group_references(mut self, group: bool) -> Self {
+        self.group_references = Some(group);
+        self
+    }
+
+    pub fn with_path_predicate(mut self, predicate: Arc<dyn PathPredicate>) -> Self {
+        self.path_predicate = Some(predicate);
+        self
+    }
 }
 
 #[cfg(test)]
@@ -105,6 +296,12 @@ mod tests {
         assert!(matches!(query, Query::Section(s) if s == "auth"));
     }
 
+    #[test]
+    fn test_parse_section_path() {
+        let query = parse_query("(section-path \"auth/configuration\")").unwrap();
+        assert!(matches!(query, Query::SectionPath(s) if s == "auth/configuration"));
+    }
+
     #[test]
     fn test_parse_grep() {
         let query = parse_query("(grep \"TODO\")").unwrap();
@@ -114,7 +311,7 @@ mod tests {
     #[test]
     fn test_parse_file() {
         let query = parse_query("(file \"README.md\")").unwrap();
-        assert!(matches!(query, Query::File(s) if s == "README.md"));
+        assert!(matches!(query, Query::File(s, _) if s == "README.md"));
     }
 
     #[test]
@@ -230,6 +427,12 @@ mod tests {
         let params = QueryParams::section("auth");
         let query = params.to_query().unwrap();
         assert!(matches!(query, Query::Section(s) if s == "auth"));
+
+        let mut params = QueryParams::section_path("auth > configuration");
+        params.section = Some("ignored".to_string());
+        assert!(params.has_search_target());
+        let query = params.to_query().unwrap();
+        assert!(matches!(query, Query::SectionPath(s) if s == "auth > configuration"));
     }
 
     #[test]
@@ -472,7 +675,7 @@ fn target_large() {
             parse_query("(union (grep \"authenticate\") (grep \"validate_token\"))").unwrap();
         let result = execute_query(&query, &index, None).unwrap();
         assert!(
-            result.handles.len() >= 1,
+            !result.handles.is_empty(),
             "union should return at least one handle"
         );
     }
@@ -512,6 +715,13 @@ fn target_large() {
                 limit: None,
                 expand_budget: Some(100_000),
                 node_type_priors: None,
+                reranker: None,
+                files: Default::default(),
+                mode: QueryMode::Handles,
+                group_references: None,
+                include_generated: false,
+                explain: false,
+                path_predicate: None,
             },
         )
         .unwrap();
@@ -520,4 +730,115 @@ fn target_large() {
             "large budget should trigger expansion"
         );
     }
+
+    #[test]
+    fn execute_grep_warns_on_high_frequency_single_term() {
+        let root = crate::temp_test_dir("exec-test-high-frequency");
+        fs::create_dir_all(root.join("src")).unwrap();
+        let source: String = (0..60)
+            .map(|i| format!("fn handler_{i}() {{ validate({i}); }}\n"))
+            .collect();
+        fs::write(root.join("src/lib.rs"), source).unwrap();
+        RepoIndex::init(&root).unwrap();
+        let mut index = RepoIndex::open(&root).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let common = index.query_params(QueryParams::pattern("fn")).unwrap();
+        let note = common.expand_note.unwrap_or_default();
+        assert!(note.contains("high-frequency"), "note: {note}");
+
+        let narrowed = index
+            .query_params(QueryParams::pattern("handler_7"))
+            .unwrap();
+        assert!(narrowed.expand_note.is_none());
+    }
+
+    #[test]
+    fn common_token_scan_stops_at_the_candidate_cap() {
+        let root = crate::temp_test_dir("exec-test-fts-cap");
+        fs::create_dir_all(root.join("src")).unwrap();
+        for f in 0..30 {
+            let source: String = (0..100)
+                .map(|i| format!("fn handler_{f}_{i}() {{ validate({i}); }}\n"))
+                .collect();
+            fs::write(root.join(format!("src/mod_{f}.rs")), source).unwrap();
+        }
+        RepoIndex::init(&root).unwrap();
+        fs::write(
+            root.join(".canopy/config.toml"),
+            "[fts]\nmax_fts_candidates = 500\n",
+        )
+        .unwrap();
+        let mut index = RepoIndex::open(&root).unwrap();
+        index.index("**/*.rs").unwrap();
+        index.take_scan_stats();
+
+        let capped = index
+            .query_params(QueryParams::pattern("validate").with_explain(true))
+            .unwrap();
+        assert!(capped.truncated_scan);
+        assert!(!capped.handles.is_empty(), "capped scans still answer");
+        let note = capped.expand_note.unwrap_or_default();
+        assert!(note.contains("max_fts_candidates"), "note: {note}");
+        let scan = capped.explain.unwrap().searches[0].scan.unwrap();
+        assert_eq!(scan.rows_examined, 500);
+        assert!(scan.capped);
+
+        // Fewer in-glob matches than the limit: reading on would pass the cap
+        let in_glob = index
+            .query_params(
+                QueryParams::pattern("validate")
+                    .with_glob("**/mod_1.rs")
+                    .with_limit(200),
+            )
+            .unwrap();
+        assert!(in_glob.truncated_scan);
+        assert!(!in_glob.handles.is_empty());
+
+        let narrow = index
+            .query_params(QueryParams::pattern("handler_7_7"))
+            .unwrap();
+        assert!(!narrow.truncated_scan);
+
+        let stats = index.take_scan_stats();
+        assert_eq!(stats.capped, 2);
+        assert!(stats.scans >= 3);
+        assert!(stats.rows_examined >= 1_000);
+        assert_eq!(index.take_scan_stats(), Default::default());
+    }
+
+    #[test]
+    fn execute_symbol_miss_suggests_nearby_names() {
+        let root = crate::temp_test_dir("exec-test-suggestions");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(
+            root.join("src/lib.rs"),
+            "struct ClientRuntime;\nstruct ServiceClient;\nfn helper() {}\n",
+        )
+        .unwrap();
+        RepoIndex::init(&root).unwrap();
+        let mut index = RepoIndex::open(&root).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let miss = index
+            .query_params(QueryParams::symbol("ClientRuntim"))
+            .unwrap();
+        assert!(miss.handles.is_empty());
+        assert_eq!(miss.suggestions[0].name, "ClientRuntime");
+        assert_eq!(miss.suggestions[0].definitions, 1);
+
+        let pack = build_evidence_pack(&miss, "ClientRuntim", 8, 2);
+        assert_eq!(
+            pack.guidance.recommended_action,
+            EvidenceAction::RetrySuggestion
+        );
+        assert!(pack.guidance.next_step.contains("ClientRuntime"));
+
+        // Empty pattern results are meaningful and get no suggestions
+        let grep_miss = index
+            .query_params(QueryParams::pattern("ClientRuntim"))
+            .unwrap();
+        assert!(grep_miss.handles.is_empty());
+        assert!(grep_miss.suggestions.is_empty());
+    }
 }
@@ -1,10 +1,12 @@
 //! Query parameter types and builder API.
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use super::dsl::Query;
+use crate::document::{NodeType, RefType};
 use crate::error::CanopyError;
+use crate::handle::HandleSource;
 
 /// Split text into unique lowercase terms, splitting on non-alphanumeric/underscore.
 pub fn split_terms(text: &str) -> Vec<String> {
@@ -43,6 +45,35 @@ impl MatchMode {
     }
 }
 
+/// How merged (local + service) results are ordered.
+///
+/// Whatever the strategy, each source keeps its own order, and local handles
+/// for a dirty file take the place of the service handles they replace.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// Interleave by each source's normalized rank position, or by rerank
+    /// score when every handle carries one; the service wins ties
+    #[default]
+    RankInterleave,
+    /// Other local handles before every service handle
+    LocalFirst,
+    /// Other local handles after every service handle
+    ServiceFirst,
+}
+
+impl MergeStrategy {
+    /// Parse "rank_interleave", "local_first" or "service_first".
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "rank_interleave" => Some(Self::RankInterleave),
+            "local_first" => Some(Self::LocalFirst),
+            "service_first" => Some(Self::ServiceFirst),
+            _ => None,
+        }
+    }
+}
+
 /// Query kind for filtering results
 #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -54,6 +85,39 @@ pub enum QueryKind {
     Definition,
     /// Only match references (calls, imports, type usages)
     Reference,
+    /// Marker comments (TODO, FIXME, ...) whose text matches `pattern`
+    Annotation,
+    /// Commits of the git-history layer whose message matches `pattern`
+    Commit,
+}
+
+/// What a query returns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryMode {
+    /// Ranked handles with previews (default)
+    #[default]
+    Handles,
+    /// Only `total_matches`, counted in SQL without building handles
+    Count,
+    /// Only whether anything matches, plus one file that does
+    Exists,
+}
+
+impl QueryMode {
+    /// Parse from a string value. "count" → Count, "exists" → Exists,
+    /// anything else → Handles.
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "count" => Self::Count,
+            "exists" => Self::Exists,
+            _ => Self::Handles,
+        }
+    }
+
+    pub fn is_handles(&self) -> bool {
+        *self == Self::Handles
+    }
 }
 
 /// Simplified query parameters (params-only API)
@@ -78,26 +142,62 @@ pub struct QueryParams {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub section: Option<String>,
 
+    /// Heading path suffix for nested sections ("auth > configuration" or
+    /// "auth/configuration"); takes precedence over `section`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section_path: Option<String>,
+
     /// Parent symbol to scope results (e.g., class name for methods)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub parent: Option<String>,
 
-    /// Query kind: definition, reference, or any (default)
+    /// Query kind: definition, reference, annotation, commit, or any (default)
     #[serde(default)]
     pub kind: QueryKind,
 
+    /// Reference kinds to keep for `kind=reference` (all when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ref_types: Option<Vec<RefType>>,
+
     /// File glob filter
     #[serde(skip_serializing_if = "Option::is_none")]
     pub glob: Option<String>,
 
+    /// Only files whose path contains this text, ignoring case; no glob
+    /// syntax. Alone, returns those files whole. ANDs with `glob`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_contains: Option<String>,
+
+    /// Only files changed within this window of now: "48h", "7d", "2w"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_within: Option<String>,
+
     /// Match mode for multi-pattern: any (OR) or all (AND)
     #[serde(default)]
     pub match_mode: MatchMode,
 
+    /// Leave out results whose content matches any of these patterns;
+    /// checked before the limit, so excluded results don't use it up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude_patterns: Option<Vec<String>>,
+
     /// Maximum number of results
     #[serde(skip_serializing_if = "Option::is_none")]
     pub limit: Option<usize>,
 
+    /// Merged (local + service) mode: results taken from the local index of
+    /// dirty files before merging; defaults to `limit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_limit: Option<usize>,
+
+    /// Merged mode: results taken from the service before merging; defaults to `limit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_limit: Option<usize>,
+
+    /// Merged mode: how local and service results are ordered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merge_strategy: Option<MergeStrategy>,
+
     /// Auto-expand results if total tokens fit within budget
     #[serde(skip_serializing_if = "Option::is_none")]
     pub expand_budget: Option<usize>,
@@ -105,6 +205,37 @@ pub struct QueryParams {
     /// Raw s-expression DSL query (takes precedence over structured fields when set)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub dsl: Option<String>,
+
+    /// Return handles (default), only a match count, or only whether
+    /// anything matches
+    #[serde(default, skip_serializing_if = "QueryMode::is_handles")]
+    pub mode: QueryMode,
+
+    /// Reference queries: fold refs with identical previews into one entry
+    /// listing the others (default: imports only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_references: Option<bool>,
+
+    /// Also search files flagged as generated (default: left out)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include_generated: Option<bool>,
+
+    /// Report which searches ran and tag each handle with the one that
+    /// found it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explain: Option<bool>,
+
+    /// Without a glob, scope the query to the directories its terms name
+    /// (`src/websocket/` for "websocket"), dropping the scope if nothing
+    /// matches there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub infer_glob: Option<bool>,
+
+    /// Ranking multipliers by node type name, e.g. `{"section": 2.0}`,
+    /// applied to the default type weights. When set they replace the priors
+    /// learned from feedback for this query; unnamed types keep their default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priors: Option<BTreeMap<String, f64>>,
 }
 
 impl QueryParams {
@@ -144,6 +275,14 @@ impl QueryParams {
         }
     }
 
+    /// Create a heading path search for nested sections
+    pub fn section_path(path: impl Into<String>) -> Self {
+        Self {
+            section_path: Some(path.into()),
+            ..Default::default()
+        }
+    }
+
     /// Create a parent search (get all children)
     pub fn parent(parent: impl Into<String>) -> Self {
         Self {
@@ -164,45 +303,143 @@ impl QueryParams {
         self
     }
 
+    /// Keep only these reference kinds (for `kind=reference`)
+    pub fn with_ref_types(mut self, ref_types: Vec<RefType>) -> Self {
+        self.ref_types = Some(ref_types);
+        self
+    }
+
     /// Filter by file glob
     pub fn with_glob(mut self, glob: impl Into<String>) -> Self {
         self.glob = Some(glob.into());
         self
     }
 
+    /// Keep only files whose path contains `fragment`
+    pub fn with_path_contains(mut self, fragment: impl Into<String>) -> Self {
+        self.path_contains = Some(fragment.into());
+        self
+    }
+
+    /// Keep only files changed within `window` ("48h", "7d")
+    pub fn with_modified_within(mut self, window: impl Into<String>) -> Self {
+        self.modified_within = Some(window.into());
+        self
+    }
+
     /// Set match mode for multi-pattern queries
     pub fn with_match_mode(mut self, mode: MatchMode) -> Self {
         self.match_mode = mode;
         self
     }
 
+    /// Leave out results whose content matches any of `patterns`
+    pub fn with_exclude_patterns(mut self, patterns: Vec<String>) -> Self {
+        self.exclude_patterns = Some(patterns);
+        self
+    }
+
     /// Set result limit
     pub fn with_limit(mut self, limit: usize) -> Self {
         self.limit = Some(limit);
         self
     }
 
+    /// Cap the local results fed into a merge
+    pub fn with_local_limit(mut self, limit: usize) -> Self {
+        self.local_limit = Some(limit);
+        self
+    }
+
+    /// Cap the service results fed into a merge
+    pub fn with_service_limit(mut self, limit: usize) -> Self {
+        self.service_limit = Some(limit);
+        self
+    }
+
+    /// Order merged results by `strategy`
+    pub fn with_merge_strategy(mut self, strategy: MergeStrategy) -> Self {
+        self.merge_strategy = Some(strategy);
+        self
+    }
+
+    /// Params for one side of a merged query: `limit` becomes that source's
+    /// limit and the merge-only fields are cleared.
+    pub fn for_source(&self, source: &HandleSource) -> Self {
+        let source_limit = match source {
+            HandleSource::Local => self.local_limit,
+            HandleSource::Service => self.service_limit,
+        };
+        Self {
+            limit: source_limit.or(self.limit),
+            local_limit: None,
+            service_limit: None,
+            merge_strategy: None,
+            ..self.clone()
+        }
+    }
+
+    /// Set the result mode (handles, count, exists)
+    pub fn with_mode(mut self, mode: QueryMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Group (or stop grouping) reference results with identical previews
+    pub fn with_group_references(mut self, group: bool) -> Self {
+        self.group_references = Some(group);
+        self
+    }
+
+    /// Search (or leave out) files flagged as generated
+    pub fn with_include_generated(mut self, include: bool) -> Self {
+        self.include_generated = Some(include);
+        self
+    }
+
+    /// Explain (or stop explaining) how the query was answered
+    pub fn with_explain(mut self, explain: bool) -> Self {
+        self.explain = Some(explain);
+        self
+    }
+
+    /// Infer (or stop inferring) a glob from the query terms
+    pub fn with_infer_glob(mut self, infer: bool) -> Self {
+        self.infer_glob = Some(infer);
+        self
+    }
+
     /// Set expand budget for auto-expansion
     pub fn with_expand_budget(mut self, budget: usize) -> Self {
         self.expand_budget = Some(budget);
         self
     }
 
-    /// Parse a kind string ("definition", "reference", "any") into a QueryKind.
+    /// Parse a kind string ("definition", "reference", "annotation",
+    /// "commit", "any") into a QueryKind.
     pub fn parse_kind(s: &str) -> QueryKind {
         match s {
             "definition" => QueryKind::Definition,
             "reference" => QueryKind::Reference,
+            "annotation" => QueryKind::Annotation,
+            "commit" => QueryKind::Commit,
             _ => QueryKind::Any,
         }
     }
 
     /// Returns true if at least one search target field is set.
+    ///
+    /// Annotation and commit queries need no target: without one they list
+    /// every marker, or the newest commits. A path fragment alone lists the
+    /// files it matches.
     pub fn has_search_target(&self) -> bool {
-        self.pattern.is_some()
+        matches!(self.kind, QueryKind::Annotation | QueryKind::Commit)
+            || self.path_fragment().is_some()
+            || self.pattern.is_some()
             || self.patterns.is_some()
             || self.symbol.is_some()
             || self.section.is_some()
+            || self.section_path.is_some()
             || self.parent.is_some()
             || self.dsl.is_some()
     }
@@ -224,12 +461,18 @@ impl QueryParams {
         if let Some(s) = &self.section {
             parts.push(s.clone());
         }
+        if let Some(s) = &self.section_path {
+            parts.push(s.clone());
+        }
         if let Some(s) = &self.parent {
             parts.push(s.clone());
         }
         if let Some(s) = &self.glob {
             parts.push(s.clone());
         }
+        if let Some(s) = &self.path_contains {
+            parts.push(s.clone());
+        }
         parts.join(" ")
     }
 
@@ -241,6 +484,7 @@ impl QueryParams {
         if self.patterns.is_some()
             || self.symbol.is_some()
             || self.section.is_some()
+            || self.section_path.is_some()
             || self.parent.is_some()
         {
             return None;
@@ -259,11 +503,52 @@ impl QueryParams {
         Some(fallback)
     }
 
+    /// `priors` as type weights for [`QueryOptions::node_type_priors`]
+    /// (each multiplier times the type's default weight). Unknown type names
+    /// and multipliers that aren't positive are errors.
+    ///
+    /// [`QueryOptions::node_type_priors`]: super::QueryOptions::node_type_priors
+    pub fn node_type_priors(&self) -> crate::Result<Option<HashMap<NodeType, f64>>> {
+        let Some(priors) = &self.priors else {
+            return Ok(None);
+        };
+        let invalid = |message: String| CanopyError::QueryParse {
+            position: 0,
+            message,
+        };
+        priors
+            .iter()
+            .map(|(name, multiplier)| {
+                let node_type = NodeType::parse(name).ok_or_else(|| {
+                    let valid: Vec<&str> = NodeType::ALL.iter().map(|t| t.as_str()).collect();
+                    invalid(format!(
+                        "Unknown node type '{}' in priors (expected one of: {})",
+                        name,
+                        valid.join(", ")
+                    ))
+                })?;
+                if !(multiplier.is_finite() && *multiplier > 0.0) {
+                    return Err(invalid(format!(
+                        "Prior for '{}' must be a positive number, got {}",
+                        name, multiplier
+                    )));
+                }
+                Ok((
+                    node_type,
+                    crate::scoring::default_type_weight(node_type) * multiplier,
+                ))
+            })
+            .collect::<crate::Result<_>>()
+            .map(Some)
+    }
+
     /// Convert params to Query AST
     pub fn to_query(&self) -> crate::Result<Query> {
+        self.node_type_priors()?;
         // DSL takes precedence over structured fields
         if let Some(ref dsl) = self.dsl {
-            return super::dsl::parse_query(dsl);
+            let query = self.exclude(super::dsl::parse_query(dsl)?);
+            return self.scope_recent(query);
         }
 
         // Validate: pattern and patterns are mutually exclusive
@@ -275,8 +560,36 @@ impl QueryParams {
             });
         }
 
-        // Validate: kind requires symbol
-        if !matches!(self.kind, QueryKind::Any) && self.symbol.is_none() {
+        // Validate: exclusions filter nodes, which these kinds don't return
+        if self.exclude_patterns.is_some()
+            && matches!(self.kind, QueryKind::Reference | QueryKind::Annotation)
+        {
+            return Err(CanopyError::QueryParse {
+                position: 0,
+                message:
+                    "exclude_patterns can't be combined with kind=reference or kind=annotation"
+                        .to_string(),
+            });
+        }
+
+        // Validate: commits have no path or content to filter by
+        if self.kind == QueryKind::Commit
+            && (self.glob.is_some()
+                || self.path_contains.is_some()
+                || self.exclude_patterns.is_some())
+        {
+            return Err(CanopyError::QueryParse {
+                position: 0,
+                message:
+                    "glob, path_contains and exclude_patterns can't be combined with kind=commit"
+                        .to_string(),
+            });
+        }
+
+        // Validate: definition/reference kinds require symbol
+        if matches!(self.kind, QueryKind::Definition | QueryKind::Reference)
+            && self.symbol.is_none()
+        {
             return Err(CanopyError::QueryParse {
                 position: 0,
                 message: "kind parameter requires symbol to be specified".to_string(),
@@ -296,7 +609,23 @@ impl QueryParams {
             }
             QueryKind::Reference => {
                 let symbol = self.symbol.as_ref().unwrap(); // validated above
-                Query::References(symbol.clone())
+                Query::References(symbol.clone(), self.ref_types.clone().unwrap_or_default())
+            }
+            QueryKind::Annotation => {
+                let terms = match (&self.pattern, &self.patterns) {
+                    (Some(pattern), _) => pattern.clone(),
+                    (None, Some(patterns)) => patterns.join(" "),
+                    (None, None) => String::new(),
+                };
+                Query::Annotations(terms)
+            }
+            QueryKind::Commit => {
+                let terms = match (&self.pattern, &self.patterns) {
+                    (Some(pattern), _) => pattern.clone(),
+                    (None, Some(patterns)) => patterns.join(" "),
+                    (None, None) => String::new(),
+                };
+                Query::Commits(terms)
             }
             QueryKind::Any => {
                 // Check for parent + symbol combination
@@ -307,6 +636,8 @@ impl QueryParams {
                     Query::Children(parent.clone())
                 } else if let Some(symbol) = &self.symbol {
                     Query::Code(symbol.clone())
+                } else if let Some(path) = &self.section_path {
+                    Query::SectionPath(path.clone())
                 } else if let Some(section) = &self.section {
                     Query::Section(section.clone())
                 } else if let Some(pattern) = &self.pattern {
@@ -324,11 +655,15 @@ impl QueryParams {
                         MatchMode::Any => Query::Union(queries),
                         MatchMode::All => Query::Intersect(queries),
                     }
+                } else if self.path_fragment().is_some() {
+                    // The files themselves, scoped below
+                    Query::File("**".to_string(), Default::default())
                 } else {
                     return Err(CanopyError::QueryParse {
                         position: 0,
-                        message: "Must specify pattern, patterns, symbol, section, or parent"
-                            .to_string(),
+                        message:
+                            "Must specify pattern, patterns, symbol, section, parent, or path_contains"
+                                .to_string(),
                     });
                 }
             }
@@ -341,6 +676,14 @@ impl QueryParams {
             base_query
         };
 
+        // Then the path fragment, which ANDs with the glob
+        let query = match self.path_fragment() {
+            Some(fragment) => Query::PathContains(fragment.to_string(), Box::new(query)),
+            None => query,
+        };
+
+        let query = self.scope_recent(self.exclude(query))?;
+
         // Apply limit if specified
         let query = if let Some(limit) = self.limit {
             Query::Limit(limit, Box::new(query))
@@ -351,12 +694,53 @@ impl QueryParams {
         Ok(query)
     }
 
+    /// `path_contains`, unless blank
+    fn path_fragment(&self) -> Option<&str> {
+        self.path_contains
+            .as_deref()
+            .filter(|f| !f.trim().is_empty())
+    }
+
+    /// `query` without results matching `exclude_patterns`, if any are set
+    fn exclude(&self, query: Query) -> Query {
+        let patterns: Vec<String> = self
+            .exclude_patterns
+            .iter()
+            .flatten()
+            .filter(|p| !p.trim().is_empty())
+            .cloned()
+            .collect();
+        if patterns.is_empty() {
+            query
+        } else {
+            Query::Exclude(patterns, Box::new(query))
+        }
+    }
+
+    /// `query` restricted to `modified_within`, if set
+    fn scope_recent(&self, query: Query) -> crate::Result<Query> {
+        match &self.modified_within {
+            Some(window) => Ok(Query::Recent(
+                super::dsl::parse_window(window, 0)?,
+                Box::new(query),
+            )),
+            None => Ok(query),
+        }
+    }
+
     /// Convert to QueryOptions for execution
     pub fn to_options(&self) -> super::QueryOptions {
         super::QueryOptions {
             limit: self.limit,
             expand_budget: self.expand_budget,
-            node_type_priors: None,
+            node_type_priors: self.node_type_priors().ok().flatten(),
+            reranker: None,
+            files: Default::default(),
+            mode: self.mode,
+            group_references: self.group_references,
+            include_generated: self.include_generated.unwrap_or(false),
+            explain: self.explain.unwrap_or(false),
+            path_predicate: None,
         }
     }
 }
@@ -398,6 +782,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn node_type_priors_scale_default_weights() {
+        let mut params = QueryParams::pattern("retry");
+        assert!(params.node_type_priors().unwrap().is_none());
+
+        params.priors = Some(BTreeMap::from([
+            ("section".to_string(), 2.0),
+            ("function".to_string(), 0.5),
+        ]));
+        let priors = params.node_type_priors().unwrap().unwrap();
+        assert_eq!(priors.len(), 2);
+        assert!((priors[&NodeType::Section] - 1.2).abs() < 1e-9);
+        assert!((priors[&NodeType::Function] - 0.5).abs() < 1e-9);
+        assert!(params.to_options().node_type_priors.is_some());
+    }
+
+    #[test]
+    fn node_type_priors_reject_unknown_types_and_bad_weights() {
+        let mut params = QueryParams::pattern("retry");
+        params.priors = Some(BTreeMap::from([("fucntion".to_string(), 2.0)]));
+        let err = params.to_query().unwrap_err();
+        assert!(
+            matches!(err, CanopyError::QueryParse { ref message, .. }
+                if message.contains("'fucntion'") && message.contains("function, class")),
+            "{err}"
+        );
+
+        params.priors = Some(BTreeMap::from([("section".to_string(), 0.0)]));
+        let err = params.node_type_priors().unwrap_err();
+        assert!(err.to_string().contains("positive"), "{err}");
+    }
+
     #[test]
     fn to_query_definition_kind_requires_symbol() {
         let params = QueryParams {
@@ -411,6 +827,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn to_query_annotation_kind_does_not_require_symbol() {
+        let q = QueryParams::pattern("auth")
+            .with_kind(QueryKind::Annotation)
+            .to_query()
+            .unwrap();
+        assert!(matches!(q, Query::Annotations(ref s) if s == "auth"));
+
+        let params = QueryParams::new().with_kind(QueryKind::Annotation);
+        assert!(params.has_search_target());
+        assert!(matches!(params.to_query().unwrap(), Query::Annotations(ref s) if s.is_empty()));
+        assert_eq!(QueryParams::parse_kind("annotation"), QueryKind::Annotation);
+    }
+
+    #[test]
+    fn to_query_commit_kind_searches_messages() {
+        let q = QueryParams::pattern("retry backoff")
+            .with_kind(QueryKind::Commit)
+            .to_query()
+            .unwrap();
+        assert!(matches!(q, Query::Commits(ref s) if s == "retry backoff"));
+        let newest = QueryParams::new().with_kind(QueryKind::Commit);
+        assert!(newest.has_search_target());
+        assert!(matches!(newest.to_query().unwrap(), Query::Commits(ref s) if s.is_empty()));
+        assert_eq!(QueryParams::parse_kind("commit"), QueryKind::Commit);
+
+        let globbed = QueryParams {
+            glob: Some("src/**".to_string()),
+            ..QueryParams::pattern("retry").with_kind(QueryKind::Commit)
+        };
+        assert!(globbed.to_query().is_err());
+    }
+
     #[test]
     fn to_query_definition_kind_with_parent_produces_children_named() {
         let params = QueryParams::symbol("do_work")
@@ -430,7 +879,17 @@ mod tests {
     fn to_query_reference_kind_produces_references() {
         let params = QueryParams::symbol("authenticate").with_kind(QueryKind::Reference);
         let q = params.to_query().unwrap();
-        assert!(matches!(q, Query::References(s) if s == "authenticate"));
+        assert!(
+            matches!(q, Query::References(s, types) if s == "authenticate" && types.is_empty())
+        );
+
+        let filtered = QueryParams::symbol("authenticate")
+            .with_kind(QueryKind::Reference)
+            .with_ref_types(vec![RefType::Import]);
+        assert!(matches!(
+            filtered.to_query().unwrap(),
+            Query::References(_, types) if types == [RefType::Import]
+        ));
     }
 
     #[test]
@@ -461,6 +920,37 @@ mod tests {
         assert_eq!(opts.limit, Some(42));
         assert_eq!(opts.expand_budget, Some(8000));
         assert!(opts.node_type_priors.is_none());
+        assert_eq!(opts.mode, QueryMode::Handles);
+
+        let opts = params.with_mode(QueryMode::Count).to_options();
+        assert_eq!(opts.mode, QueryMode::Count);
+    }
+
+    #[test]
+    fn for_source_applies_per_source_limits() {
+        let params = QueryParams::pattern("x")
+            .with_limit(20)
+            .with_service_limit(10)
+            .with_merge_strategy(MergeStrategy::LocalFirst);
+
+        let service = params.for_source(&HandleSource::Service);
+        assert_eq!(service.limit, Some(10));
+        let local = params.for_source(&HandleSource::Local);
+        assert_eq!(
+            local.limit,
+            Some(20),
+            "unset source limit falls back to limit"
+        );
+        for side in [service, local] {
+            assert!(side.local_limit.is_none() && side.service_limit.is_none());
+            assert!(side.merge_strategy.is_none());
+            assert_eq!(side.pattern.as_deref(), Some("x"));
+        }
+
+        let json = serde_json::to_value(&params).unwrap();
+        assert_eq!(json["service_limit"], 10);
+        assert_eq!(json["merge_strategy"], "local_first");
+        assert!(json.get("local_limit").is_none());
     }
 
     #[test]
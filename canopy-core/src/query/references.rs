@@ -0,0 +1,194 @@
+//! Folding identical reference previews.
+//!
+//! A popular symbol is imported the same way from dozens of files, so a
+//! reference query would spend most of its response repeating one
+//! `use crate::foo::Bar;` line. Grouped references keep the first such ref as
+//! a representative and list where the others sit.
+
+use crate::document::RefType;
+use crate::handle::{RefHandle, RefOccurrence};
+use std::collections::HashMap;
+
+/// Locations listed on a grouped reference; the rest are only counted.
+pub(crate) const MAX_REF_OCCURRENCES: usize = 50;
+
+/// Whether refs of `ref_type` are grouped: `group` when set, otherwise only
+/// imports.
+fn groups_ref_type(group: Option<bool>, ref_type: RefType) -> bool {
+    group.unwrap_or(ref_type == RefType::Import)
+}
+
+/// Fold refs sharing a ref type and whitespace-normalized preview into the
+/// first of them, keeping the order of the representatives.
+pub(crate) fn group_references(refs: Vec<RefHandle>, group: Option<bool>) -> Vec<RefHandle> {
+    let mut grouped: Vec<RefHandle> = Vec::with_capacity(refs.len());
+    let mut representatives: HashMap<(&'static str, String), usize> = HashMap::new();
+    for reference in refs {
+        if !groups_ref_type(group, reference.ref_type) {
+            grouped.push(reference);
+            continue;
+        }
+        let key = (
+            reference.ref_type.as_str(),
+            normalize_preview(&reference.preview),
+        );
+        match representatives.get(&key) {
+            Some(&at) => {
+                let representative = &mut grouped[at];
+                representative.occurrence_count += 1 + reference.occurrence_count;
+                fold_occurrences(representative, reference);
+            }
+            None => {
+                representatives.insert(key, grouped.len());
+                grouped.push(reference);
+            }
+        }
+    }
+    grouped
+}
+
+/// Add `reference` and whatever was already folded into it to
+/// `representative`'s listed occurrences, up to the cap.
+fn fold_occurrences(representative: &mut RefHandle, reference: RefHandle) {
+    let incoming = std::iter::once(RefOccurrence {
+        file_path: reference.file_path,
+        line: reference.line_range.0,
+    })
+    .chain(reference.occurrences);
+    let room = MAX_REF_OCCURRENCES.saturating_sub(representative.occurrences.len());
+    representative.occurrences.extend(incoming.take(room));
+}
+
+/// `preview` trimmed, with whitespace runs collapsed to one space.
+fn normalize_preview(preview: &str) -> String {
+    preview.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{execute_query, QueryKind, QueryParams};
+    use crate::RepoIndex;
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+
+    /// `files` modules importing `Widget` the same way and calling `make`.
+    fn importers_repo(files: usize) -> (FixtureRepo, RepoIndex) {
+        let builder = FixtureRepoBuilder::new().file(
+            "src/widget.rs",
+            "pub struct Widget;\npub fn make() -> Widget { Widget }\n",
+        );
+        let repo = (0..files)
+            .fold(builder, |builder, i| {
+                builder.file(
+                    format!("src/user_{i:02}.rs"),
+                    format!("use crate::widget::Widget;\n\npub fn run_{i}() {{\n    make();\n}}\n"),
+                )
+            })
+            .build();
+        let mut index = RepoIndex::open_or_init(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        (repo, index)
+    }
+
+    fn refs(index: &RepoIndex, params: QueryParams) -> Vec<RefHandle> {
+        let query = params.to_query().unwrap();
+        let mut options = params.to_options();
+        options.limit = Some(100);
+        crate::query::execute_query_with_options(&query, index, options)
+            .unwrap()
+            .ref_handles
+            .unwrap()
+    }
+
+    #[test]
+    fn identical_imports_fold_into_one_reference() {
+        let (_dir, index) = importers_repo(20);
+        let imports = refs(
+            &index,
+            QueryParams::symbol("Widget").with_kind(QueryKind::Reference),
+        );
+        let imports: Vec<_> = imports
+            .iter()
+            .filter(|r| r.ref_type == RefType::Import)
+            .collect();
+        assert_eq!(imports.len(), 1);
+        let import = imports[0];
+        assert_eq!(import.file_path, "src/user_00.rs");
+        assert_eq!(import.occurrence_count, 19);
+        assert_eq!(import.occurrences.len(), 19);
+        assert_eq!(import.occurrences[0].file_path, "src/user_01.rs");
+        assert_eq!(import.occurrences[0].line, 1);
+
+        // Calls are not grouped by default, even with identical previews
+        let calls = refs(
+            &index,
+            QueryParams::symbol("make").with_kind(QueryKind::Reference),
+        );
+        assert_eq!(calls.len(), 20);
+        assert!(calls.iter().all(|r| r.occurrence_count == 0));
+
+        let ungrouped = QueryParams::symbol("Widget")
+            .with_kind(QueryKind::Reference)
+            .with_group_references(false);
+        let ungrouped = refs(&index, ungrouped);
+        assert_eq!(
+            ungrouped
+                .iter()
+                .filter(|r| r.ref_type == RefType::Import)
+                .count(),
+            20
+        );
+
+        let grouped_calls = QueryParams::symbol("make")
+            .with_kind(QueryKind::Reference)
+            .with_group_references(true);
+        let grouped_calls = refs(&index, grouped_calls);
+        assert_eq!(grouped_calls.len(), 1);
+        assert_eq!(grouped_calls[0].occurrence_count, 19);
+    }
+
+    #[test]
+    fn grouped_entries_count_against_the_limit() {
+        let (_dir, index) = importers_repo(20);
+        let params = QueryParams::symbol("Widget").with_kind(QueryKind::Reference);
+        let result = execute_query(&params.to_query().unwrap(), &index, Some(3)).unwrap();
+        let refs = result.ref_handles.unwrap();
+        assert!(refs.len() <= 3);
+        assert_eq!(
+            refs.iter()
+                .filter(|r| r.ref_type == RefType::Import)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn occurrences_are_capped_but_counted() {
+        let reference = |path: &str| RefHandle {
+            file_path: path.to_string(),
+            span: 0..10,
+            line_range: (3, 3),
+            name: "Widget".to_string(),
+            qualifier: None,
+            ref_type: RefType::Import,
+            source_handle: None,
+            preview: "use  crate::Widget; ".to_string(),
+            occurrences: Vec::new(),
+            occurrence_count: 0,
+        };
+        let refs = (0..MAX_REF_OCCURRENCES + 10)
+            .map(|i| reference(&format!("f{i}.rs")))
+            .collect();
+        let grouped = group_references(refs, None);
+        assert_eq!(grouped.len(), 1);
+        assert_eq!(grouped[0].occurrences.len(), MAX_REF_OCCURRENCES);
+        assert_eq!(grouped[0].occurrence_count, MAX_REF_OCCURRENCES + 9);
+
+        let mut different = reference("g.rs");
+        different.preview = "use crate::other::Widget;".to_string();
+        assert_eq!(
+            group_references(vec![reference("a.rs"), different], None).len(),
+            2
+        );
+    }
+}
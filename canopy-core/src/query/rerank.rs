@@ -0,0 +1,377 @@
+//! Pluggable reranking of query candidates.
+//!
+//! A [`Reranker`] registered on [`QueryOptions`](super::QueryOptions) reorders
+//! the collected candidates before the result limit is applied. Canopy ships no
+//! model of its own; with the `external` feature, [`ExternalReranker`] delegates
+//! to a user-supplied command such as a script in front of an embedding service.
+
+use crate::handle::Handle;
+
+/// Reorders query candidates, best first.
+pub trait Reranker: std::fmt::Debug + Send + Sync {
+    /// Reorder `handles` in place for `query`, optionally setting
+    /// [`Handle::rerank_score`]. On error the caller keeps the original order.
+    fn rerank(&self, query: &str, handles: &mut Vec<Handle>) -> crate::Result<()>;
+}
+
+/// Run `reranker` over `handles`, leaving them untouched if it fails.
+///
+/// Returns a warning for the result's `expand_note` on failure; reranking never
+/// fails the query.
+pub fn apply_reranker(
+    reranker: &dyn Reranker,
+    query: &str,
+    handles: &mut Vec<Handle>,
+) -> Option<String> {
+    if handles.is_empty() {
+        return None;
+    }
+    let mut reranked = handles.clone();
+    match reranker.rerank(query, &mut reranked) {
+        Ok(()) => {
+            *handles = reranked;
+            None
+        }
+        Err(e) => Some(format!("Reranking skipped, original order kept: {e}")),
+    }
+}
+
+#[cfg(feature = "external")]
+pub use external::ExternalReranker;
+
+#[cfg(feature = "external")]
+mod external {
+    use super::Reranker;
+    use crate::config::RerankConfig;
+    use crate::document::NodeType;
+    use crate::error::CanopyError;
+    use crate::handle::Handle;
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::process::{Command, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Reranker that shells out to a command.
+    ///
+    /// The command receives `{"query": ..., "candidates": [{"id", "file_path",
+    /// "node_type", "preview"}]}` on stdin and must print a JSON array of
+    /// `{"id": ..., "score": ...}` in the desired order. Candidates it omits
+    /// follow in their original order.
+    #[derive(Debug, Clone)]
+    pub struct ExternalReranker {
+        command: String,
+        timeout: Duration,
+    }
+
+    #[derive(Serialize)]
+    struct RerankRequest<'a> {
+        query: &'a str,
+        candidates: Vec<RerankCandidate<'a>>,
+    }
+
+    #[derive(Serialize)]
+    struct RerankCandidate<'a> {
+        id: String,
+        file_path: &'a str,
+        node_type: NodeType,
+        preview: &'a str,
+    }
+
+    #[derive(Deserialize)]
+    struct RankedId {
+        id: String,
+        #[serde(default)]
+        score: Option<f64>,
+    }
+
+    impl ExternalReranker {
+        pub fn new(command: impl Into<String>) -> Self {
+            Self {
+                command: command.into(),
+                timeout: Duration::from_millis(RerankConfig::default().timeout_ms),
+            }
+        }
+
+        pub fn with_timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = timeout;
+            self
+        }
+
+        /// Reranker for `[rerank] command`, if one is configured.
+        pub fn from_config(config: &RerankConfig) -> Option<Self> {
+            let command = config.command.as_deref()?.trim();
+            if command.is_empty() {
+                return None;
+            }
+            Some(Self::new(command).with_timeout(Duration::from_millis(config.timeout_ms)))
+        }
+
+        fn run(&self, input: Vec<u8>) -> crate::Result<Vec<u8>> {
+            let mut child = shell_command(&self.command)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| {
+                    CanopyError::Rerank(format!("failed to run '{}': {e}", self.command))
+                })?;
+
+            // Pipe I/O on threads so a chatty or stalled command cannot block the deadline
+            let mut stdin = child.stdin.take().expect("stdin is piped");
+            thread::spawn(move || {
+                let _ = stdin.write_all(&input);
+            });
+            let stdout = read_on_thread(child.stdout.take().expect("stdout is piped"));
+            let stderr = read_on_thread(child.stderr.take().expect("stderr is piped"));
+
+            let deadline = Instant::now() + self.timeout;
+            let status = loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if Instant::now() >= deadline {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(CanopyError::Rerank(format!(
+                        "'{}' timed out after {}ms",
+                        self.command,
+                        self.timeout.as_millis()
+                    )));
+                }
+                thread::sleep(Duration::from_millis(5));
+            };
+
+            if !status.success() {
+                let stderr = stderr.join().unwrap_or_default();
+                let stderr = String::from_utf8_lossy(&stderr);
+                let mut message = format!("'{}' exited with {status}", self.command);
+                if !stderr.trim().is_empty() {
+                    message = format!("{message}: {}", stderr.trim());
+                }
+                return Err(CanopyError::Rerank(message));
+            }
+            Ok(stdout.join().unwrap_or_default())
+        }
+    }
+
+    impl Reranker for ExternalReranker {
+        fn rerank(&self, query: &str, handles: &mut Vec<Handle>) -> crate::Result<()> {
+            let request = RerankRequest {
+                query,
+                candidates: handles
+                    .iter()
+                    .map(|h| RerankCandidate {
+                        id: h.id.to_string(),
+                        file_path: &h.file_path,
+                        node_type: h.node_type,
+                        preview: &h.preview,
+                    })
+                    .collect(),
+            };
+            let output = self.run(serde_json::to_vec(&request)?)?;
+            let ranked: Vec<RankedId> = serde_json::from_slice(&output)
+                .map_err(|e| CanopyError::Rerank(format!("invalid output: {e}")))?;
+            reorder(handles, ranked);
+            Ok(())
+        }
+    }
+
+    fn reorder(handles: &mut Vec<Handle>, ranked: Vec<RankedId>) {
+        let positions: HashMap<String, usize> = handles
+            .iter()
+            .enumerate()
+            .map(|(i, h)| (h.id.to_string(), i))
+            .collect();
+        let mut remaining: Vec<Option<Handle>> = handles.drain(..).map(Some).collect();
+        for entry in ranked {
+            let Some(&i) = positions.get(&entry.id) else {
+                continue;
+            };
+            if let Some(mut handle) = remaining[i].take() {
+                handle.rerank_score = entry.score;
+                handles.push(handle);
+            }
+        }
+        handles.extend(remaining.into_iter().flatten());
+    }
+
+    fn read_on_thread(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = pipe.read_to_end(&mut buf);
+            buf
+        })
+    }
+
+    #[cfg(unix)]
+    fn shell_command(command: &str) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    }
+
+    #[cfg(windows)]
+    fn shell_command(command: &str) -> Command {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{execute_query_with_options, parse_query, QueryOptions};
+    use crate::RepoIndex;
+    use std::fs;
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    struct Reverse;
+
+    impl Reranker for Reverse {
+        fn rerank(&self, _query: &str, handles: &mut Vec<Handle>) -> crate::Result<()> {
+            handles.reverse();
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct Broken;
+
+    impl Reranker for Broken {
+        fn rerank(&self, _query: &str, handles: &mut Vec<Handle>) -> crate::Result<()> {
+            handles.clear();
+            Err(crate::CanopyError::Rerank("model unavailable".to_string()))
+        }
+    }
+
+    fn indexed_repo() -> RepoIndex {
+        let root = crate::temp_test_dir("rerank-test");
+        fs::create_dir_all(root.join("src")).unwrap();
+        let source: String = (0..4)
+            .map(|i| format!("fn step_{i}() {{ pipeline_stage({i}); }}\n\n"))
+            .collect();
+        fs::write(root.join("src/lib.rs"), source).unwrap();
+        RepoIndex::init(&root).unwrap();
+        let mut index = RepoIndex::open(&root).unwrap();
+        index.index("**/*.rs").unwrap();
+        index
+    }
+
+    fn ids(handles: &[Handle]) -> Vec<String> {
+        handles.iter().map(|h| h.id.to_string()).collect()
+    }
+
+    fn query_ids(index: &RepoIndex, options: QueryOptions) -> (Vec<String>, Option<String>) {
+        let query = parse_query("(grep \"pipeline_stage\")").unwrap();
+        let result = execute_query_with_options(&query, index, options).unwrap();
+        (ids(&result.handles), result.expand_note)
+    }
+
+    #[test]
+    fn registered_reranker_reorders_candidates() {
+        let index = indexed_repo();
+        let (baseline, _) = query_ids(&index, QueryOptions::new());
+        assert!(baseline.len() >= 2);
+
+        let (reranked, note) =
+            query_ids(&index, QueryOptions::new().with_reranker(Arc::new(Reverse)));
+        let mut expected = baseline.clone();
+        expected.reverse();
+        assert_eq!(reranked, expected);
+        assert!(note.is_none());
+    }
+
+    #[test]
+    fn failing_reranker_keeps_original_order_with_warning() {
+        let index = indexed_repo();
+        let (baseline, _) = query_ids(&index, QueryOptions::new());
+
+        let (result, note) = query_ids(&index, QueryOptions::new().with_reranker(Arc::new(Broken)));
+        assert_eq!(result, baseline);
+        assert!(note.unwrap_or_default().contains("model unavailable"));
+    }
+
+    #[cfg(all(feature = "external", unix))]
+    mod external_command {
+        use super::*;
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::Duration;
+
+        /// Mock reranker: echoes candidate ids back in reverse, scored by position.
+        const REVERSE_SCRIPT: &str = r#"#!/bin/sh
+grep -o '"id":"[^"]*"' | cut -d'"' -f4 | awk '{ ids[NR] = $0 } END {
+  printf "[";
+  for (i = NR; i >= 1; i--) printf "%s{\"id\":\"%s\",\"score\":%d}", (i < NR ? "," : ""), ids[i], i;
+  print "]";
+}'
+"#;
+
+        fn script(name: &str, body: &str) -> String {
+            let path = crate::temp_test_dir("rerank-script").join(name);
+            fs::write(&path, body).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            path.to_string_lossy().into_owned()
+        }
+
+        #[test]
+        fn external_command_reverses_order_and_sets_scores() {
+            let index = indexed_repo();
+            let (baseline, _) = query_ids(&index, QueryOptions::new());
+            let reranker = ExternalReranker::new(script("reverse.sh", REVERSE_SCRIPT));
+
+            let query = parse_query("(grep \"pipeline_stage\")").unwrap();
+            let result = execute_query_with_options(
+                &query,
+                &index,
+                QueryOptions::new().with_reranker(Arc::new(reranker)),
+            )
+            .unwrap();
+
+            let mut expected = baseline.clone();
+            expected.reverse();
+            assert_eq!(ids(&result.handles), expected);
+            assert_eq!(result.handles[0].rerank_score, Some(baseline.len() as f64));
+            assert!(result.expand_note.is_none());
+        }
+
+        #[test]
+        fn external_command_timeout_and_failure_degrade() {
+            let index = indexed_repo();
+            let (baseline, _) = query_ids(&index, QueryOptions::new());
+
+            let slow = ExternalReranker::new(script("slow.sh", "#!/bin/sh\nexec sleep 5\n"))
+                .with_timeout(Duration::from_millis(100));
+            let (result, note) =
+                query_ids(&index, QueryOptions::new().with_reranker(Arc::new(slow)));
+            assert_eq!(result, baseline);
+            assert!(note.unwrap_or_default().contains("timed out"));
+
+            let failing =
+                ExternalReranker::new(script("fail.sh", "#!/bin/sh\necho boom >&2\nexit 3\n"));
+            let (result, note) =
+                query_ids(&index, QueryOptions::new().with_reranker(Arc::new(failing)));
+            assert_eq!(result, baseline);
+            assert!(note.unwrap_or_default().contains("boom"));
+
+            let garbage = ExternalReranker::new(script("garbage.sh", "#!/bin/sh\necho not-json\n"));
+            let (result, note) =
+                query_ids(&index, QueryOptions::new().with_reranker(Arc::new(garbage)));
+            assert_eq!(result, baseline);
+            assert!(note.unwrap_or_default().contains("invalid output"));
+        }
+
+        #[test]
+        fn from_config_requires_a_command() {
+            let mut config = crate::config::RerankConfig::default();
+            assert!(ExternalReranker::from_config(&config).is_none());
+            config.command = Some("  ".to_string());
+            assert!(ExternalReranker::from_config(&config).is_none());
+            config.command = Some("rerank --model small".to_string());
+            assert!(ExternalReranker::from_config(&config).is_some());
+        }
+    }
+}
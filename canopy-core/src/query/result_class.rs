@@ -0,0 +1,205 @@
+//! Result classes: what kind of match each handle is.
+//!
+//! A symbol query can mix definitions with members of a parent, nodes that
+//! merely reference the symbol and plain content hits, in whatever order the
+//! searches returned them. Every search tags its handles with a
+//! [`ResultClass`] (see [`ResultClass::of`]), and [`order_by_class`] puts
+//! mixed results in class order, sharing the limit between the classes by
+//! their `[ranking]` weights so a few definitions aren't pushed out by many
+//! call sites.
+
+use crate::config::RankingConfig;
+use crate::handle::Handle;
+use serde::{Deserialize, Serialize};
+
+use super::explain::SearchPath;
+
+/// What kind of match a handle is, best first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultClass {
+    /// A code definition whose name is exactly the symbol
+    Definition,
+    /// A child of a parent symbol (methods, fields, ...)
+    Member,
+    /// A node referencing the symbol
+    Reference,
+    /// A full-text, fuzzy-name, section or whole-file hit
+    Content,
+}
+
+impl ResultClass {
+    pub const ALL: [ResultClass; 4] = [
+        ResultClass::Definition,
+        ResultClass::Member,
+        ResultClass::Reference,
+        ResultClass::Content,
+    ];
+
+    /// The class of the results `via` returns.
+    pub fn of(via: SearchPath) -> Self {
+        match via {
+            SearchPath::SymbolCache | SearchPath::SymbolDb | SearchPath::Symbols => {
+                Self::Definition
+            }
+            SearchPath::Children => Self::Member,
+            SearchPath::Refs => Self::Reference,
+            SearchPath::Fts
+            | SearchPath::SymbolFuzzy
+            | SearchPath::Sections
+            | SearchPath::InFile
+            | SearchPath::Annotations
+            | SearchPath::File
+            | SearchPath::Related
+            | SearchPath::Commits => Self::Content,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Definition => "definition",
+            Self::Member => "member",
+            Self::Reference => "reference",
+            Self::Content => "content",
+        }
+    }
+
+    /// Share of the limit the class gets in mixed results
+    fn weight(&self, ranking: &RankingConfig) -> f64 {
+        match self {
+            Self::Definition => ranking.definition_weight,
+            Self::Member => ranking.member_weight,
+            Self::Reference => ranking.reference_weight,
+            Self::Content => ranking.content_weight,
+        }
+        .max(0.0)
+    }
+
+    /// Bonus the handle scorer adds for the class, up to 1
+    pub(crate) fn score(&self) -> f64 {
+        match self {
+            Self::Definition => 1.0,
+            Self::Member => 0.6,
+            Self::Reference => 0.3,
+            Self::Content => 0.0,
+        }
+    }
+}
+
+/// Reorder `handles` so the first `limit` are the best of each class, in
+/// class order, each class getting its weighted share of the limit (slots a
+/// class can't fill go to the others). Each class keeps its own order, and
+/// nothing is dropped: what doesn't fit follows in class order. Untagged
+/// handles count as content. Results of a single class keep their order.
+pub(crate) fn order_by_class(
+    handles: Vec<Handle>,
+    limit: usize,
+    ranking: &RankingConfig,
+) -> Vec<Handle> {
+    let class = |h: &Handle| h.result_class.unwrap_or(ResultClass::Content);
+    let Some(first) = handles.first().map(class) else {
+        return handles;
+    };
+    if handles.iter().all(|h| class(h) == first) {
+        return handles;
+    }
+
+    let mut buckets: [Vec<Handle>; 4] = Default::default();
+    for handle in handles {
+        buckets[class(&handle) as usize].push(handle);
+    }
+
+    let present: Vec<ResultClass> = ResultClass::ALL
+        .into_iter()
+        .filter(|c| !buckets[*c as usize].is_empty())
+        .collect();
+    let total_weight: f64 = present.iter().map(|c| c.weight(ranking)).sum();
+    let mut quotas = [0usize; 4];
+    for c in &present {
+        let share = if total_weight > 0.0 {
+            c.weight(ranking) / total_weight
+        } else {
+            1.0 / present.len() as f64
+        };
+        quotas[*c as usize] =
+            ((limit as f64 * share).floor() as usize).min(buckets[*c as usize].len());
+    }
+    // Hand out what rounding and short classes left over, best class first
+    let mut spare = limit.saturating_sub(quotas.iter().sum());
+    for c in &present {
+        let room = buckets[*c as usize].len() - quotas[*c as usize];
+        let extra = room.min(spare);
+        quotas[*c as usize] += extra;
+        spare -= extra;
+    }
+
+    let mut ordered = Vec::new();
+    let mut rest = Vec::new();
+    for c in present {
+        let mut bucket = std::mem::take(&mut buckets[c as usize]);
+        let overflow = bucket.split_off(quotas[c as usize]);
+        ordered.extend(bucket);
+        rest.extend(overflow);
+    }
+    ordered.extend(rest);
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NodeType, Span};
+
+    fn handle(name: &str, class: ResultClass) -> Handle {
+        let mut handle = Handle::new(
+            format!("src/{name}.rs"),
+            NodeType::Function,
+            Span { start: 0, end: 10 },
+            (1, 1),
+            10,
+            name.to_string(),
+        );
+        handle.result_class = Some(class);
+        handle
+    }
+
+    fn previews(handles: &[Handle]) -> Vec<&str> {
+        handles.iter().map(|h| h.preview.as_str()).collect()
+    }
+
+    #[test]
+    fn classes_share_the_limit_by_weight_and_keep_their_order() {
+        let mut handles: Vec<Handle> = (0..6)
+            .map(|i| handle(&format!("ref{i}"), ResultClass::Reference))
+            .collect();
+        handles.push(handle("def", ResultClass::Definition));
+        handles.push(handle("text", ResultClass::Content));
+
+        let ordered = order_by_class(handles.clone(), 4, &RankingConfig::default());
+        assert_eq!(ordered.len(), handles.len());
+        assert_eq!(
+            previews(&ordered),
+            vec!["def", "ref0", "ref1", "ref2", "ref3", "ref4", "ref5", "text"]
+        );
+
+        // Equal weights give content its share even at a small limit
+        let even = RankingConfig {
+            definition_weight: 1.0,
+            member_weight: 1.0,
+            reference_weight: 1.0,
+            content_weight: 1.0,
+        };
+        let ordered = order_by_class(handles, 3, &even);
+        assert_eq!(previews(&ordered)[..3], ["def", "ref0", "text"]);
+    }
+
+    #[test]
+    fn single_class_results_are_left_alone() {
+        let handles = vec![
+            handle("b", ResultClass::Content),
+            handle("a", ResultClass::Content),
+        ];
+        let ordered = order_by_class(handles, 1, &RankingConfig::default());
+        assert_eq!(previews(&ordered), vec!["b", "a"]);
+    }
+}
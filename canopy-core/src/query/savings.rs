@@ -0,0 +1,146 @@
+//! Token savings estimate: tokens returned versus reading each result file whole.
+
+use crate::index::RepoIndex;
+use crate::parse::estimate_tokens;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::QueryResult;
+
+/// What a query cost compared with dumping every file it touched.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenSavings {
+    /// Full-file token count of each distinct file in the results
+    pub files: BTreeMap<String, usize>,
+    /// Sum of `files`: the cost of reading them all whole
+    pub file_tokens: usize,
+    /// Tokens actually returned: previews, or content where expanded
+    pub returned_tokens: usize,
+    /// `file_tokens / returned_tokens` (0 when nothing was returned)
+    pub ratio: f64,
+}
+
+impl TokenSavings {
+    pub fn new(files: BTreeMap<String, usize>, returned_tokens: usize) -> Self {
+        let file_tokens = files.values().sum();
+        let ratio = if returned_tokens > 0 {
+            file_tokens as f64 / returned_tokens as f64
+        } else {
+            0.0
+        };
+        Self {
+            files,
+            file_tokens,
+            returned_tokens,
+            ratio,
+        }
+    }
+
+    /// Tokens not spent by returning handles instead of whole files.
+    pub fn tokens_avoided(&self) -> usize {
+        self.file_tokens.saturating_sub(self.returned_tokens)
+    }
+}
+
+impl QueryResult {
+    /// Distinct file paths across handles, refs (with their grouped
+    /// occurrences) and annotations.
+    pub fn file_paths(&self) -> BTreeSet<&str> {
+        let refs = self.ref_handles.iter().flatten().flat_map(|r| {
+            std::iter::once(&r.file_path).chain(r.occurrences.iter().map(|o| &o.file_path))
+        });
+        let annotations = self.annotations.iter().flatten().map(|a| &a.file_path);
+        self.handles
+            .iter()
+            .map(|h| &h.file_path)
+            .chain(refs)
+            .chain(annotations)
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Tokens this result puts in front of the caller.
+    pub fn returned_tokens(&self) -> usize {
+        let handles: usize = self
+            .handles
+            .iter()
+            .map(|h| match &h.content {
+                Some(_) => h.token_count,
+                None => estimate_tokens(&h.preview),
+            })
+            .sum();
+        let refs: usize = self
+            .ref_handles
+            .iter()
+            .flatten()
+            .map(|r| estimate_tokens(&r.preview))
+            .sum();
+        let annotations: usize = self
+            .annotations
+            .iter()
+            .flatten()
+            .map(|a| estimate_tokens(&a.text))
+            .sum();
+        handles + refs + annotations
+    }
+}
+
+/// Savings for `result`, or `None` when it names no indexed files.
+pub(crate) fn token_savings(
+    index: &RepoIndex,
+    result: &QueryResult,
+) -> crate::Result<Option<TokenSavings>> {
+    let files = index.file_token_counts(&result.file_paths())?;
+    if files.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(TokenSavings::new(files, result.returned_tokens())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::test_helpers::setup_repo;
+    use crate::query::{execute_query, parse_query};
+
+    #[test]
+    fn savings_count_each_file_once() {
+        let dir = setup_repo(3);
+        let mut index = RepoIndex::open(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        // func_0 and Struct0 share file_0.rs
+        let query =
+            parse_query(r#"(union (code "func_0") (code "Struct0") (code "func_1"))"#).unwrap();
+        let result = execute_query(&query, &index, None).unwrap();
+        assert_eq!(result.handles.len(), 3);
+
+        let savings = result.savings.clone().unwrap();
+        assert_eq!(
+            savings.files.keys().collect::<Vec<_>>(),
+            vec!["src/file_0.rs", "src/file_1.rs"]
+        );
+        assert_eq!(savings.file_tokens, savings.files.values().sum::<usize>());
+        assert_eq!(savings.returned_tokens, result.returned_tokens());
+        assert!(savings.ratio > 0.0);
+
+        let empty = execute_query(
+            &parse_query(r#"(code "nothing_here")"#).unwrap(),
+            &index,
+            None,
+        )
+        .unwrap();
+        assert!(empty.savings.is_none());
+    }
+
+    #[test]
+    fn ratio_and_avoided_tokens() {
+        let files = BTreeMap::from([("a.rs".to_string(), 900), ("b.rs".to_string(), 100)]);
+        let savings = TokenSavings::new(files, 50);
+        assert_eq!(savings.file_tokens, 1000);
+        assert_eq!(savings.tokens_avoided(), 950);
+        assert_eq!(savings.ratio, 20.0);
+
+        assert_eq!(TokenSavings::new(BTreeMap::new(), 0).ratio, 0.0);
+    }
+}
@@ -0,0 +1,782 @@
+//! JSON Schemas of canopy's JSON outputs, so downstream tools can validate
+//! what they read.
+//!
+//! The schemas are maintained by hand next to the types they describe.
+//! Every output type carries a `schema_version` field holding
+//! [`OUTPUT_SCHEMA_VERSION`]: additive changes (a new optional field) bump
+//! its minor number; removing, renaming or retyping a field, or making an
+//! optional one required, bumps the major. Consumers should ignore fields
+//! they don't know. Snapshots under `canopy-core/schemas/` are compared
+//! against [`output_schema`] by a test, so changing a schema means updating
+//! them on purpose (`CANOPY_UPDATE_SCHEMAS=1 cargo test -p canopy-core schema`).
+//!
+//! A field is required when it is always serialized, even if empty or
+//! null; fields skipped when unset or zero are optional.
+
+use crate::document::{NodeType, RefType};
+use crate::query::{ResultClass, SearchPath};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// Version of the output schemas, embedded in each output as `schema_version`
+///
+/// - 1.1: `dirty_rebuilds` on index status
+/// - 1.2: `commits` on query results, `commits_indexed` on index stats
+/// - 1.3: `content_hash` on query results and evidence packs
+/// - 1.4: `term_coverage`, `definition_present` and `result_density` on evidence pack guidance
+/// - 1.5: `moves_fixed` on index stats
+/// - 1.6: `compat_mode` on query results
+/// - 1.7: `scan` and `truncated_scan` on query results
+/// - 1.8: `inferred_glob` on query results
+/// - 1.9: `score` on handles
+pub const OUTPUT_SCHEMA_VERSION: &str = "1.9";
+
+/// Output types with a schema, by the name [`output_schema`] takes
+pub const SCHEMA_TYPES: [&str; 4] = [
+    "query_result",
+    "evidence_pack",
+    "index_stats",
+    "index_status",
+];
+
+/// The `schema_version` field: always serializes as [`OUTPUT_SCHEMA_VERSION`],
+/// the version of the types doing the serializing. Any version deserializes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SchemaVersion;
+
+impl Serialize for SchemaVersion {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(OUTPUT_SCHEMA_VERSION)
+    }
+}
+
+impl<'de> Deserialize<'de> for SchemaVersion {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer).map(|_| SchemaVersion)
+    }
+}
+
+/// The JSON Schema of the output type `name` (one of [`SCHEMA_TYPES`]).
+pub fn output_schema(name: &str) -> Option<Value> {
+    let (title, root, defs): (&str, Value, &[&str]) = match name {
+        "query_result" => (
+            "QueryResult",
+            query_result(),
+            &[
+                "Handle",
+                "HandleId",
+                "Span",
+                "LineRange",
+                "RefHandle",
+                "RefOccurrence",
+                "AnnotationHandle",
+                "CommitEntry",
+                "SymbolSuggestion",
+                "TokenSavings",
+                "SourceCounts",
+                "PatternError",
+                "QueryExplain",
+                "SearchExplain",
+                "FtsScan",
+            ],
+        ),
+        "evidence_pack" => (
+            "EvidencePack",
+            evidence_pack(),
+            &[
+                "EvidenceHandle",
+                "EvidenceFileSummary",
+                "EvidenceGuidance",
+                "EvidenceOverflow",
+                "HandleId",
+                "LineRange",
+                "SymbolSuggestion",
+            ],
+        ),
+        "index_stats" => ("IndexStats", index_stats(), &[]),
+        "index_status" => (
+            "IndexStatus",
+            index_status(),
+            &[
+                "NodeBreakdown",
+                "NodeTypeStats",
+                "LargeNode",
+                "HandleId",
+                "LineRange",
+            ],
+        ),
+        _ => return None,
+    };
+    let mut schema = json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": format!("urn:canopy:schema:{name}:{OUTPUT_SCHEMA_VERSION}"),
+        "title": title,
+    });
+    let schema_map = schema.as_object_mut().expect("schema is an object");
+    schema_map.extend(root.as_object().expect("root is an object").clone());
+    if !defs.is_empty() {
+        let defs: Map<String, Value> = defs
+            .iter()
+            .map(|name| (name.to_string(), definition(name)))
+            .collect();
+        schema_map.insert("$defs".to_string(), Value::Object(defs));
+    }
+    Some(schema)
+}
+
+fn query_result() -> Value {
+    object(
+        &[
+            ("schema_version", schema_version()),
+            ("handles", array(reference("Handle"))),
+            ("total_tokens", integer()),
+            ("truncated", boolean()),
+            ("total_matches", integer()),
+        ],
+        &[
+            ("ref_handles", array(reference("RefHandle"))),
+            ("ref_type_counts", map_of(integer())),
+            ("annotations", array(reference("AnnotationHandle"))),
+            ("commits", array(reference("CommitEntry"))),
+            ("preview_tokens", integer()),
+            ("auto_expanded", boolean()),
+            ("expand_note", string()),
+            ("expanded_count", integer()),
+            ("expanded_tokens", integer()),
+            ("expanded_handle_ids", array(reference("HandleId"))),
+            ("suppressed_service_handles", integer()),
+            ("suppressed_by_policy", integer()),
+            ("suppressed_generated", integer()),
+            ("excluded_matches", integer()),
+            ("redactions", integer()),
+            ("suggestions", array(reference("SymbolSuggestion"))),
+            ("savings", reference("TokenSavings")),
+            ("sources", reference("SourceCounts")),
+            ("mode", one_of_strings(["handles", "count", "exists"])),
+            ("exists", boolean()),
+            ("witness_path", string()),
+            ("match_counts", map_of(integer())),
+            ("pattern_errors", array(reference("PatternError"))),
+            ("explain", reference("QueryExplain")),
+            ("compat_mode", boolean()),
+            ("truncated_scan", boolean()),
+            ("inferred_glob", string()),
+        ],
+    )
+}
+
+fn evidence_pack() -> Value {
+    object(
+        &[
+            ("schema_version", schema_version()),
+            ("query_text", string()),
+            ("total_matches", integer()),
+            ("truncated", boolean()),
+            ("selected_count", integer()),
+            ("selected_tokens", integer()),
+            ("handles", array(reference("EvidenceHandle"))),
+            ("files", array(reference("EvidenceFileSummary"))),
+            ("expand_suggestion", array(reference("HandleId"))),
+            ("guidance", reference("EvidenceGuidance")),
+            ("overflow", reference("EvidenceOverflow")),
+        ],
+        &[
+            ("suggestions", array(reference("SymbolSuggestion"))),
+            ("overflow_samples", array(string())),
+            ("suppressed_by_policy", integer()),
+            ("excluded_matches", integer()),
+        ],
+    )
+}
+
+fn index_stats() -> Value {
+    object(
+        &[
+            ("schema_version", schema_version()),
+            ("files_indexed", integer()),
+            ("files_degraded", integer()),
+            ("files_generated", integer()),
+            ("generated_tokens", integer()),
+            ("files_skipped", integer()),
+            (
+                "skipped",
+                object(
+                    &[
+                        ("ttl", integer()),
+                        ("mtime", integer()),
+                        ("hash", integer()),
+                    ],
+                    &[],
+                ),
+            ),
+            ("total_tokens", integer()),
+            ("index_size_bytes", integer()),
+            ("files_removed", integer()),
+            ("fts_bytes_written", integer()),
+            ("fts_bytes_skipped", integer()),
+            ("commits_indexed", integer()),
+            ("moves_fixed", integer()),
+        ],
+        &[
+            ("errors", array(path_reason())),
+            ("fts_optimized", boolean()),
+            // Added by the MCP canopy_index tool
+            ("repo_root", string()),
+        ],
+    )
+}
+
+fn index_status() -> Value {
+    object(
+        &[
+            ("schema_version", schema_version()),
+            ("files_indexed", integer()),
+            ("total_tokens", integer()),
+            ("index_schema_version", integer()),
+            ("index_size_bytes", integer()),
+            ("last_indexed", nullable(string())),
+            ("shards", integer()),
+            ("annotations", map_of(integer())),
+            ("files_degraded", integer()),
+            ("files_generated", integer()),
+            ("generated_tokens", integer()),
+            ("nodes_fts_sampled", integer()),
+        ],
+        &[
+            ("mtime_warning", string()),
+            ("node_breakdown", reference("NodeBreakdown")),
+            ("churn_warning", string()),
+            (
+                "churning_files",
+                array(object(
+                    &[
+                        ("path", string()),
+                        ("reindex_count", integer()),
+                        ("token_count", integer()),
+                    ],
+                    &[],
+                )),
+            ),
+            ("last_fts_optimize", string()),
+            // Added by `canopy status --verbose`
+            ("parse_warnings", array(path_reason())),
+            (
+                "migrations",
+                array(object(
+                    &[
+                        ("version", integer()),
+                        ("description", string()),
+                        ("applied_at", json!({ "type": "integer" })),
+                    ],
+                    &[],
+                )),
+            ),
+            // Added by the MCP canopy_status tool
+            ("repo_root", string()),
+            ("file_discovery", string()),
+            ("feedback", json!({ "type": "object" })),
+            (
+                "dirty_rebuilds",
+                object(
+                    &[
+                        ("rebuilds", integer()),
+                        ("debounced", integer()),
+                        ("files_reindexed", integer()),
+                    ],
+                    &[],
+                ),
+            ),
+        ],
+    )
+}
+
+/// The shared type `name`, referenced as `#/$defs/<name>`.
+fn definition(name: &str) -> Value {
+    match name {
+        "HandleId" => json!({
+            "type": "string",
+            "pattern": "^h[0-9a-f]+$",
+            "description": "Handle id, 'h' followed by hex digits",
+        }),
+        "Span" => object(&[("start", integer()), ("end", integer())], &[]),
+        "LineRange" => json!({
+            "type": "array",
+            "items": integer(),
+            "minItems": 2,
+            "maxItems": 2,
+            "description": "First and last line, 1-indexed",
+        }),
+        "Handle" => object(
+            &[
+                ("id", reference("HandleId")),
+                ("file_path", string()),
+                ("node_type", node_type()),
+                ("span", reference("Span")),
+                ("line_range", reference("LineRange")),
+                ("token_count", integer()),
+                ("preview", string()),
+                ("preview_tokens", integer()),
+                ("source", one_of_strings(["local", "service"])),
+            ],
+            &[
+                ("content", string()),
+                ("content_hash", string()),
+                ("commit_sha", string()),
+                ("generation", integer()),
+                ("possibly_stale", boolean()),
+                ("rerank_score", number()),
+                ("score", number()),
+                ("match_line", integer()),
+                ("match_count_in_node", integer()),
+                ("via", search_path()),
+                (
+                    "result_class",
+                    one_of_strings(ResultClass::ALL.map(|class| class.as_str())),
+                ),
+            ],
+        ),
+        "RefHandle" => object(
+            &[
+                ("file_path", string()),
+                ("span", reference("Span")),
+                ("line_range", reference("LineRange")),
+                ("name", string()),
+                ("qualifier", nullable(string())),
+                (
+                    "ref_type",
+                    one_of_strings(RefType::ALL.map(|ref_type| ref_type.as_str())),
+                ),
+                ("source_handle", nullable(reference("HandleId"))),
+                ("preview", string()),
+            ],
+            &[
+                ("occurrences", array(reference("RefOccurrence"))),
+                ("occurrence_count", integer()),
+            ],
+        ),
+        "RefOccurrence" => object(&[("file_path", string()), ("line", integer())], &[]),
+        "AnnotationHandle" => object(
+            &[
+                ("file_path", string()),
+                ("line", integer()),
+                ("marker", string()),
+                ("text", string()),
+            ],
+            &[("source_handle", reference("HandleId"))],
+        ),
+        "CommitEntry" => object(
+            &[
+                ("sha", string()),
+                ("date", string()),
+                ("timestamp", integer()),
+                ("author", string()),
+                ("subject", string()),
+                ("files_touched", integer()),
+            ],
+            &[],
+        ),
+        "SymbolSuggestion" => object(
+            &[
+                ("name", string()),
+                ("definitions", integer()),
+                ("similarity", number()),
+            ],
+            &[],
+        ),
+        "TokenSavings" => object(
+            &[
+                ("files", map_of(integer())),
+                ("file_tokens", integer()),
+                ("returned_tokens", integer()),
+                ("ratio", number()),
+            ],
+            &[],
+        ),
+        "SourceCounts" => object(
+            &[
+                ("local", integer()),
+                ("service", integer()),
+                ("local_truncated", boolean()),
+                ("service_truncated", boolean()),
+            ],
+            &[],
+        ),
+        "PatternError" => object(&[("pattern", string()), ("message", string())], &[]),
+        "QueryExplain" => object(
+            &[
+                ("searches", array(reference("SearchExplain"))),
+                ("glob_filtered", integer()),
+            ],
+            &[
+                ("node_type_priors", map_of(number())),
+                ("reranked", boolean()),
+                ("checklist", array(string())),
+            ],
+        ),
+        "SearchExplain" => object(
+            &[
+                ("via", search_path()),
+                ("input", string()),
+                ("limit", integer()),
+                ("returned", integer()),
+            ],
+            &[
+                ("fts_query", string()),
+                ("filter", string()),
+                ("glob", string()),
+                ("scan", reference("FtsScan")),
+            ],
+        ),
+        "FtsScan" => object(
+            &[("rows_examined", integer()), ("elapsed_us", integer())],
+            &[("capped", boolean())],
+        ),
+        "EvidenceHandle" => object(
+            &[
+                ("id", reference("HandleId")),
+                ("file_path", string()),
+                ("node_type", node_type()),
+                ("line_range", reference("LineRange")),
+                ("token_count", integer()),
+                ("source", one_of_strings(["local", "service"])),
+                ("score", number()),
+            ],
+            &[
+                ("commit_sha", string()),
+                ("generation", integer()),
+                ("content_hash", string()),
+            ],
+        ),
+        "EvidenceFileSummary" => object(
+            &[
+                ("file_path", string()),
+                ("handle_ids", array(reference("HandleId"))),
+                ("total_tokens", integer()),
+            ],
+            &[],
+        ),
+        "EvidenceGuidance" => object(
+            &[
+                ("confidence", number()),
+                ("confidence_band", one_of_strings(["low", "medium", "high"])),
+                ("stop_querying", boolean()),
+                (
+                    "recommended_action",
+                    one_of_strings([
+                        "refine_query",
+                        "expand_then_answer",
+                        "retry_suggestion",
+                        "narrow_scope",
+                    ]),
+                ),
+                ("suggested_expand_count", integer()),
+                ("max_additional_queries", integer()),
+                ("rationale", string()),
+                ("next_step", string()),
+                ("term_coverage", number()),
+                ("definition_present", boolean()),
+                ("result_density", number()),
+            ],
+            &[],
+        ),
+        "EvidenceOverflow" => object(&[("files", integer()), ("handles", integer())], &[]),
+        "NodeBreakdown" => object(
+            &[
+                ("by_type", array(reference("NodeTypeStats"))),
+                ("largest", array(reference("LargeNode"))),
+            ],
+            &[],
+        ),
+        "NodeTypeStats" => object(
+            &[
+                ("node_type", node_type()),
+                ("nodes", integer()),
+                ("total_tokens", integer()),
+                ("avg_tokens", integer()),
+            ],
+            &[],
+        ),
+        "LargeNode" => object(
+            &[
+                ("handle_id", reference("HandleId")),
+                ("file_path", string()),
+                ("node_type", node_type()),
+                ("line_range", reference("LineRange")),
+                ("token_count", integer()),
+            ],
+            &[],
+        ),
+        _ => unreachable!("no schema definition {name}"),
+    }
+}
+
+/// An object with `required` and `optional` properties, in that order.
+fn object(required: &[(&str, Value)], optional: &[(&str, Value)]) -> Value {
+    let properties: Map<String, Value> = required
+        .iter()
+        .chain(optional)
+        .map(|(name, schema)| (name.to_string(), schema.clone()))
+        .collect();
+    let required: Vec<&str> = required.iter().map(|(name, _)| *name).collect();
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+fn schema_version() -> Value {
+    json!({ "type": "string", "description": "Output schema version, e.g. \"1.0\"" })
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+fn number() -> Value {
+    json!({ "type": "number" })
+}
+
+fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+fn map_of(values: Value) -> Value {
+    json!({ "type": "object", "additionalProperties": values })
+}
+
+fn nullable(schema: Value) -> Value {
+    json!({ "anyOf": [schema, { "type": "null" }] })
+}
+
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/$defs/{name}") })
+}
+
+fn one_of_strings<'a>(values: impl IntoIterator<Item = &'a str>) -> Value {
+    let values: Vec<&str> = values.into_iter().collect();
+    json!({ "type": "string", "enum": values })
+}
+
+fn node_type() -> Value {
+    one_of_strings(NodeType::ALL.map(|node_type| node_type.as_str()))
+}
+
+fn search_path() -> Value {
+    one_of_strings(SearchPath::ALL.map(|via| via.as_str()))
+}
+
+fn path_reason() -> Value {
+    object(&[("path", string()), ("reason", string())], &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::build_evidence_pack;
+    use crate::{QueryParams, RepoIndex};
+    use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+    use std::path::PathBuf;
+
+    /// Errors validating `value` against `schema` (the subset of JSON Schema
+    /// [`output_schema`] uses). Stricter than the published contract:
+    /// properties the schema doesn't declare are errors, so a new field
+    /// can't ship without a schema change.
+    fn violations(value: &Value, schema: &Value, root: &Value, path: &str) -> Vec<String> {
+        if let Some(target) = schema["$ref"].as_str() {
+            let name = target.trim_start_matches("#/$defs/");
+            return violations(value, &root["$defs"][name], root, path);
+        }
+        if let Some(options) = schema["anyOf"].as_array() {
+            return if options
+                .iter()
+                .any(|option| violations(value, option, root, path).is_empty())
+            {
+                Vec::new()
+            } else {
+                vec![format!("{path}: matches no alternative")]
+            };
+        }
+        let mut errors = Vec::new();
+        let type_ok = match schema["type"].as_str() {
+            Some("object") => value.is_object(),
+            Some("array") => value.is_array(),
+            Some("string") => value.is_string(),
+            Some("integer") => value.is_u64() || value.is_i64(),
+            Some("number") => value.is_number(),
+            Some("boolean") => value.is_boolean(),
+            Some("null") => value.is_null(),
+            _ => true,
+        };
+        if !type_ok {
+            return vec![format!("{path}: expected {}, got {value}", schema["type"])];
+        }
+        if let Some(allowed) = schema["enum"].as_array() {
+            if !allowed.contains(value) {
+                errors.push(format!("{path}: {value} not in {allowed:?}"));
+            }
+        }
+        if let (Some(pattern), Some(s)) = (schema["pattern"].as_str(), value.as_str()) {
+            assert_eq!(pattern, "^h[0-9a-f]+$");
+            let hex = s.strip_prefix('h').unwrap_or("");
+            if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                errors.push(format!("{path}: {s} doesn't match {pattern}"));
+            }
+        }
+        if let Some(items) = value.as_array() {
+            for (i, item) in items.iter().enumerate() {
+                errors.extend(violations(
+                    item,
+                    &schema["items"],
+                    root,
+                    &format!("{path}[{i}]"),
+                ));
+            }
+        }
+        if let Some(fields) = value.as_object() {
+            for required in schema["required"].as_array().into_iter().flatten() {
+                if !fields.contains_key(required.as_str().unwrap()) {
+                    errors.push(format!("{path}: missing {required}"));
+                }
+            }
+            for (name, field) in fields {
+                let field_path = format!("{path}.{name}");
+                let declared = schema["properties"]
+                    .get(name)
+                    .or_else(|| schema.get("additionalProperties"));
+                match declared {
+                    Some(declared) => errors.extend(violations(field, declared, root, &field_path)),
+                    None if schema.get("properties").is_some() => {
+                        errors.push(format!("{field_path}: not in the schema"))
+                    }
+                    None => {}
+                }
+            }
+        }
+        errors
+    }
+
+    fn assert_valid(name: &str, value: &Value) {
+        let schema = output_schema(name).unwrap();
+        let errors = violations(value, &schema, &schema, name);
+        assert!(
+            errors.is_empty(),
+            "{name} doesn't match its schema: {errors:#?}"
+        );
+    }
+
+    fn indexed_repo() -> (FixtureRepo, RepoIndex) {
+        let repo = FixtureRepoBuilder::new()
+            .file(
+                "src/auth.rs",
+                "// TODO: rotate keys\npub fn verify_token(token: &str) -> bool {\n    !token.is_empty()\n}\n",
+            )
+            .file(
+                "src/handler.rs",
+                "pub fn handle(t: &str) -> bool {\n    verify_token(t)\n}\n",
+            )
+            .file("README.md", "# Auth\n\nVerifies tokens.\n")
+            .build();
+        RepoIndex::init(repo.path()).unwrap();
+        let index = RepoIndex::open(repo.path()).unwrap();
+        (repo, index)
+    }
+
+    #[test]
+    fn outputs_match_their_schemas() {
+        let (_dir, mut index) = indexed_repo();
+        let stats = index.index("**/*").unwrap();
+        assert_valid("index_stats", &serde_json::to_value(&stats).unwrap());
+        assert_valid(
+            "index_status",
+            &serde_json::to_value(index.status_detailed().unwrap()).unwrap(),
+        );
+
+        let queries = [
+            QueryParams::pattern("verify_token")
+                .with_expand_budget(5_000)
+                .with_explain(true),
+            QueryParams::symbol("verify_token").with_kind(crate::QueryKind::Reference),
+            QueryParams::symbol("verify_tokne"),
+            QueryParams::pattern("TODO").with_kind(crate::QueryKind::Annotation),
+            QueryParams::pattern("token").with_kind(crate::QueryKind::Commit),
+            QueryParams::pattern("token").with_mode(crate::QueryMode::Count),
+        ];
+        for params in queries {
+            let result = index.query_params(params).unwrap();
+            assert_valid("query_result", &serde_json::to_value(&result).unwrap());
+            let pack = build_evidence_pack(&result, "verify token", 8, 2);
+            assert_valid("evidence_pack", &serde_json::to_value(&pack).unwrap());
+        }
+
+        // Undeclared fields and raw handle ids are caught
+        let schema = output_schema("query_result").unwrap();
+        let mut result = serde_json::to_value(
+            index
+                .query_params(QueryParams::pattern("verify_token"))
+                .unwrap(),
+        )
+        .unwrap();
+        result["handles"][0]["id"] = json!("1a2b");
+        result["renamed_field"] = json!(1);
+        let errors = violations(&result, &schema, &schema, "query_result");
+        assert_eq!(errors.len(), 2, "{errors:#?}");
+    }
+
+    #[test]
+    fn outputs_carry_the_schema_version() {
+        let (_dir, mut index) = indexed_repo();
+        let stats = serde_json::to_value(index.index("**/*").unwrap()).unwrap();
+        let result = index
+            .query_params(QueryParams::pattern("verify_token"))
+            .unwrap();
+        let pack = build_evidence_pack(&result, "verify_token", 8, 2);
+        for value in [
+            stats,
+            serde_json::to_value(index.status().unwrap()).unwrap(),
+            serde_json::to_value(&result).unwrap(),
+            serde_json::to_value(&pack).unwrap(),
+        ] {
+            assert_eq!(value["schema_version"], OUTPUT_SCHEMA_VERSION);
+        }
+        // Bumped together with the history on OUTPUT_SCHEMA_VERSION
+        assert_eq!(OUTPUT_SCHEMA_VERSION, "1.9");
+        // Outputs read back from a service of another version still parse
+        let mut remote = serde_json::to_value(&result).unwrap();
+        remote["schema_version"] = json!("0.9");
+        assert!(serde_json::from_value::<crate::QueryResult>(remote).is_ok());
+    }
+
+    #[test]
+    fn schemas_match_snapshots() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("schemas");
+        let update = std::env::var_os("CANOPY_UPDATE_SCHEMAS").is_some();
+        for name in SCHEMA_TYPES {
+            let schema =
+                serde_json::to_string_pretty(&output_schema(name).unwrap()).unwrap() + "\n";
+            let path = dir.join(format!("{name}.json"));
+            if update {
+                std::fs::create_dir_all(&dir).unwrap();
+                std::fs::write(&path, &schema).unwrap();
+                continue;
+            }
+            let snapshot = std::fs::read_to_string(&path).unwrap_or_default();
+            assert!(
+                snapshot == schema,
+                "schemas/{name}.json is out of date; if the change is intended, bump \
+                 OUTPUT_SCHEMA_VERSION as needed and rerun with CANOPY_UPDATE_SCHEMAS=1"
+            );
+        }
+        assert_eq!(output_schema("handle"), None);
+    }
+}
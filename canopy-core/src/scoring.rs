@@ -7,11 +7,29 @@ use std::collections::HashMap;
 
 const NEARBY_LINE_GAP: usize = 2;
 const MAX_EXPANSIONS_PER_FILE: usize = 2;
+/// Score a handle's result class adds at most (see [`ResultClass`])
+///
+/// [`ResultClass`]: crate::query::ResultClass
+const CLASS_WEIGHT: f64 = 0.2;
+
+/// Type weight of a node type without learned or per-query priors.
+pub(crate) fn default_type_weight(node_type: NodeType) -> f64 {
+    match node_type {
+        NodeType::Function | NodeType::Method => 1.0,
+        NodeType::Class | NodeType::Struct => 0.8,
+        NodeType::Section => 0.6,
+        NodeType::CodeBlock => 0.5,
+        NodeType::Chunk => 0.3,
+        NodeType::Paragraph => 0.2,
+    }
+}
 
 /// Scores handles for expansion relevance and cost-efficiency.
 pub struct HandleScorer {
     query_terms: Vec<String>,
     node_type_priors: Option<HashMap<NodeType, f64>>,
+    /// Best [`Handle::score`] among the handles being ranked
+    best_fts_score: Option<f64>,
 }
 
 impl HandleScorer {
@@ -20,6 +38,7 @@ impl HandleScorer {
         Self {
             query_terms,
             node_type_priors: None,
+            best_fts_score: None,
         }
     }
 
@@ -31,6 +50,18 @@ impl HandleScorer {
         self
     }
 
+    /// Judge the relevance of full-text results in `handles` by their FTS5
+    /// score relative to the best one, instead of by query terms in the
+    /// preview.
+    pub fn with_fts_scores(mut self, handles: &[Handle]) -> Self {
+        self.best_fts_score = handles
+            .iter()
+            .filter_map(|h| h.score)
+            .filter(|score| *score > 0.0)
+            .max_by(f64::total_cmp);
+        self
+    }
+
     pub fn score(&self, handle: &Handle) -> f64 {
         let haystack = format!(
             "{} {}",
@@ -38,7 +69,9 @@ impl HandleScorer {
             handle.preview.to_lowercase()
         );
 
-        let relevance = if self.query_terms.is_empty() {
+        let relevance = if let (Some(best), Some(score)) = (self.best_fts_score, handle.score) {
+            (score / best).clamp(0.1, 1.0)
+        } else if self.query_terms.is_empty() {
             1.0
         } else {
             let hits = self
@@ -50,24 +83,19 @@ impl HandleScorer {
             raw.max(0.1)
         };
 
-        let default_type_weight = match handle.node_type {
-            NodeType::Function | NodeType::Method => 1.0,
-            NodeType::Class | NodeType::Struct => 0.8,
-            NodeType::Section => 0.6,
-            NodeType::CodeBlock => 0.5,
-            NodeType::Chunk => 0.3,
-            NodeType::Paragraph => 0.2,
-        };
         let type_weight = self
             .node_type_priors
             .as_ref()
             .and_then(|p| p.get(&handle.node_type).copied())
-            .unwrap_or(default_type_weight);
+            .unwrap_or_else(|| default_type_weight(handle.node_type));
 
         let token_count = handle.token_count.max(1) as f64;
         let cost_efficiency = 1.0 / (1.0 + token_count.ln());
 
-        0.6 * relevance + 0.25 * type_weight + 0.15 * cost_efficiency
+        // A definition outranks a call site that mentions the same terms
+        let class_bonus = handle.result_class.map_or(0.0, |c| c.score());
+
+        0.6 * relevance + 0.25 * type_weight + 0.15 * cost_efficiency + CLASS_WEIGHT * class_bonus
     }
 }
 
@@ -89,8 +117,13 @@ pub fn select_for_expansion(
         .map(|(idx, handle)| (idx, scorer.score(handle), handle.token_count))
         .collect();
 
-    // Higher score first; tie-break smaller handles to better pack the budget.
-    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.2.cmp(&b.2)));
+    // Higher score first; tie-break smaller handles to better pack the budget,
+    // then by position so equal candidates always pack the same way.
+    ranked.sort_by(|a, b| {
+        b.1.total_cmp(&a.1)
+            .then_with(|| a.2.cmp(&b.2))
+            .then_with(|| handles[a.0].position_cmp(&handles[b.0]))
+    });
 
     let mut selected = Vec::new();
     let mut used_tokens = 0usize;
@@ -237,4 +270,16 @@ mod tests {
         let function = make_handle("src/auth.rs", "auth flow", NodeType::Function, 80);
         assert!(scorer.score(&paragraph) > scorer.score(&function));
     }
+
+    #[test]
+    fn definitions_outscore_reference_sources_with_the_same_terms() {
+        use crate::query::ResultClass;
+        let scorer = HandleScorer::new("verify_token");
+        let mut definition =
+            make_handle("src/auth.rs", "fn verify_token()", NodeType::Function, 60);
+        definition.result_class = Some(ResultClass::Definition);
+        let mut caller = make_handle("src/api.rs", "verify_token(t)", NodeType::Function, 20);
+        caller.result_class = Some(ResultClass::Reference);
+        assert!(scorer.score(&definition) > scorer.score(&caller));
+    }
 }
@@ -0,0 +1,231 @@
+//! End-to-end reference and parent/child queries over the shared fixture
+//! presets, indexed the way `canopy index` would.
+
+use canopy_core::{Handle, QueryKind, QueryParams, RefType, RepoIndex};
+use canopy_testutil::presets::{medium_stem, MEDIUM_RUST_MODULES};
+use canopy_testutil::{FixtureRepo, FixtureRepoBuilder};
+
+fn indexed(builder: FixtureRepoBuilder) -> (FixtureRepo, RepoIndex) {
+    let repo = builder.build();
+    RepoIndex::init(repo.path()).unwrap();
+    let mut index = RepoIndex::open(repo.path()).unwrap();
+    index.index("**/*").unwrap();
+    (repo, index)
+}
+
+/// `(file, first line of preview)` of each handle, sorted.
+fn located(handles: &[Handle]) -> Vec<(String, String)> {
+    let mut located: Vec<(String, String)> = handles
+        .iter()
+        .map(|h| {
+            let first = h.preview.lines().next().unwrap_or("").trim().to_string();
+            (h.file_path.clone(), first)
+        })
+        .collect();
+    located.sort();
+    located
+}
+
+fn children_previews(index: &RepoIndex, parent: &str) -> Vec<String> {
+    located(&index.search_children(parent, 20).unwrap())
+        .into_iter()
+        .map(|(_, preview)| preview)
+        .collect()
+}
+
+#[test]
+fn small_preset_resolves_references_and_children_across_languages() {
+    let (_repo, index) = indexed(canopy_testutil::small());
+
+    // A call and an import of a function defined in another file
+    let refs = index
+        .search_references("check_session_token", &[], 20)
+        .unwrap();
+    let kinds: Vec<(String, RefType)> = refs
+        .iter()
+        .map(|r| (r.file_path.clone(), r.ref_type))
+        .collect();
+    assert!(kinds.contains(&("src/session/store.rs".to_string(), RefType::Call)));
+    assert!(kinds.contains(&("src/session/store.rs".to_string(), RefType::Import)));
+    assert!(refs.iter().all(|r| r.file_path != "src/session/token.rs"));
+
+    // The calling method, not the whole impl, is the reference source
+    let sources = index
+        .search_reference_sources("check_session_token", &[RefType::Call], 20)
+        .unwrap();
+    assert_eq!(
+        located(&sources),
+        vec![(
+            "src/session/store.rs".to_string(),
+            "pub fn validate(&self) -> bool {".to_string()
+        )]
+    );
+
+    let result = index
+        .query_params(QueryParams::symbol("formatProfileRequest").with_kind(QueryKind::Reference))
+        .unwrap();
+    let ref_files: Vec<&str> = result
+        .ref_handles
+        .iter()
+        .flatten()
+        .map(|r| r.file_path.as_str())
+        .collect();
+    assert!(
+        ref_files.contains(&"web/profile/client.ts"),
+        "{ref_files:?}"
+    );
+
+    // Methods hang off their class or impl in every language
+    let rust = children_previews(&index, "SessionStore");
+    for method in ["pub fn new()", "pub fn insert(", "pub fn validate("] {
+        assert!(rust.iter().any(|p| p.starts_with(method)), "{rust:?}");
+    }
+    let python = children_previews(&index, "BillingRecord");
+    for member in ["class Meta:", "def __init__(", "def serialize("] {
+        assert!(python.iter().any(|p| p.starts_with(member)), "{python:?}");
+    }
+    let typescript = children_previews(&index, "ProfileClient");
+    for method in ["send(", "close("] {
+        assert!(
+            typescript.iter().any(|p| p.starts_with(method)),
+            "{typescript:?}"
+        );
+    }
+
+    let named = index
+        .query_params(
+            QueryParams::symbol("serialize")
+                .with_parent("BillingRecord")
+                .with_kind(QueryKind::Definition),
+        )
+        .unwrap();
+    assert_eq!(
+        located(&named.handles),
+        vec![(
+            "app/billing/models.py".to_string(),
+            "def serialize(self):".to_string()
+        )]
+    );
+
+    // Nested markdown sections resolve by heading path
+    let section = index
+        .query_params(QueryParams::section_path("Part 1 > Part 1.2 > Part 1.2.1"))
+        .unwrap();
+    assert_eq!(section.handles.len(), 1);
+    assert_eq!(section.handles[0].file_path, "docs/guide.md");
+}
+
+#[test]
+fn medium_preset_keeps_references_within_their_module() {
+    let (_repo, index) = indexed(canopy_testutil::medium());
+    assert!(index.status().unwrap().files_indexed >= 200);
+
+    for i in [0, MEDIUM_RUST_MODULES / 2, MEDIUM_RUST_MODULES - 1] {
+        let stem = medium_stem(i);
+        let store = format!("crates/core/src/{stem}/store.rs");
+        let refs = index
+            .search_references(&format!("check_{stem}_token"), &[RefType::Call], 50)
+            .unwrap();
+        assert!(!refs.is_empty(), "no calls of check_{stem}_token");
+        assert!(refs.iter().all(|r| r.file_path == store), "{stem}");
+
+        let parent = format!("{}Store", capitalized(&stem));
+        let methods = index.search_children(&parent, 20).unwrap();
+        assert_eq!(methods.len(), 3, "{parent}");
+        assert!(methods.iter().all(|h| h.file_path == store));
+    }
+
+    // The same method name under many parents only answers for the one asked
+    let named = index
+        .query_params(
+            QueryParams::symbol("send")
+                .with_parent("Audit1Client")
+                .with_kind(QueryKind::Definition),
+        )
+        .unwrap();
+    assert_eq!(named.handles.len(), 1);
+    assert_eq!(named.handles[0].file_path, "web/audit1/client.ts");
+}
+
+#[test]
+fn edge_case_preset_indexes_odd_files_without_losing_structure() {
+    let (_repo, index) = indexed(canopy_testutil::edge_cases());
+
+    // Trait default methods and a same-named parent in two languages
+    let shape = children_previews(&index, "Shape");
+    assert!(shape.iter().any(|p| p.starts_with("fn area(")), "{shape:?}");
+    assert!(
+        shape.iter().any(|p| p.starts_with("fn scale(")),
+        "{shape:?}"
+    );
+    let configs = located(&index.search_children("Config", 20).unwrap());
+    let files: Vec<&str> = configs.iter().map(|(f, _)| f.as_str()).collect();
+    assert!(files.contains(&"src/config.rs") && files.contains(&"app/config.py"));
+
+    // A call through a trait method resolves to its caller
+    let sources = located(
+        &index
+            .search_reference_sources("area", &[RefType::Call], 20)
+            .unwrap(),
+    );
+    assert_eq!(sources.len(), 1);
+    assert!(sources[0].1.starts_with("fn scale("), "{sources:?}");
+
+    // The generated file is indexed but left out unless asked for
+    let plain = index
+        .query_params(QueryParams::pattern("ENTRY_00042"))
+        .unwrap();
+    assert!(plain.handles.is_empty());
+    assert!(plain.suppressed_generated > 0);
+    let generated = index
+        .query_params(QueryParams::pattern("ENTRY_00042").with_include_generated(true))
+        .unwrap();
+    assert_eq!(generated.handles[0].file_path, "src/generated/tables.rs");
+
+    for symbol in ["crlf_line_endings", "deeply_nested_leaf"] {
+        let result = index.query_params(QueryParams::symbol(symbol)).unwrap();
+        assert_eq!(result.handles.len(), 1, "{symbol}");
+    }
+    // A single very long line reads as minified output
+    let long_line = QueryParams::pattern("LONG_LINE");
+    assert!(index
+        .query_params(long_line.clone())
+        .unwrap()
+        .handles
+        .is_empty());
+    let long_line = index
+        .query_params(long_line.with_include_generated(true))
+        .unwrap();
+    assert_eq!(long_line.handles[0].file_path, "src/long_line.rs");
+    let cafe = index
+        .query_params(QueryParams::section("Crème brûlée"))
+        .unwrap();
+    assert_eq!(cafe.handles[0].file_path, "docs/café.md");
+    let deepest = index
+        .query_params(QueryParams::section("Part 1.1.1.1.1"))
+        .unwrap();
+    assert_eq!(deepest.handles[0].file_path, "docs/deep.md");
+}
+
+#[test]
+fn git_history_preset_shows_the_change_as_a_commit() {
+    let repo = canopy_testutil::small().with_git_history().build();
+    let diff = repo.git(&["diff", "--name-only", "HEAD~1", "HEAD"]);
+    assert_eq!(
+        String::from_utf8_lossy(&diff.stdout),
+        "src/session/token.rs\n"
+    );
+    RepoIndex::init(repo.path()).unwrap();
+    let mut index = RepoIndex::open(repo.path()).unwrap();
+    index.index("**/*").unwrap();
+    let revoke = index
+        .query_params(QueryParams::symbol("revoke_session_token"))
+        .unwrap();
+    assert_eq!(revoke.handles.len(), 1);
+}
+
+fn capitalized(stem: &str) -> String {
+    let mut chars = stem.chars();
+    let first = chars.next().unwrap().to_ascii_uppercase();
+    std::iter::once(first).chain(chars).collect()
+}
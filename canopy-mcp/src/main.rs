@@ -1,26 +1,43 @@
 //! Canopy MCP Server - MCP interface for token-efficient codebase queries
 
+mod notifications;
 mod protocol;
 mod schema;
 mod tools;
 
-use canopy_client::ClientRuntime;
+use canopy_client::{retry, ClientRuntime, RetryPolicy, SessionLog};
+use canopy_core::protocol::ClientContext;
+use canopy_core::AutoInit;
+use notifications::{client_supports_index_changed, IndexChange, Notifier};
 use protocol::{JsonRpcError, JsonRpcRequest, JsonRpcResponse, McpError};
-use schema::{query_input_schema, query_param_properties};
+use schema::{query_input_schema, query_param_properties, session_id_property};
 use serde_json::{json, Value};
 use std::io::{BufRead, BufReader, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() {
     let stdin = std::io::stdin();
     let mut stdout = std::io::stdout();
     let reader = BufReader::new(stdin.lock());
 
-    // Parse --service-url from CLI args (falls back to CANOPY_SERVICE_URL env var)
+    // Parse --service-url from CLI args (falls back to CANOPY_SERVICE_URL env
+    // var, then to a service started by `canopy service run`)
     let service_url = parse_service_url();
-    let api_key = parse_api_key();
     let default_repo_root = parse_root_path();
+    let api_key = resolve_api_key(service_url.is_some(), default_repo_root.as_deref());
     let mut server = McpServer::with_service_url(service_url, api_key, default_repo_root);
+    server.default_session_id = parse_arg(
+        "--session-id",
+        canopy_client::client_context::SESSION_ID_ENV,
+    );
+    server
+        .runtime
+        .set_session_log(parse_session_log().map(SessionLog::new));
+    server.runtime.set_auto_init(
+        AutoInit::from_env(true).with_update_gitignore(has_flag("--update-gitignore")),
+    );
+    server.runtime.set_retry_policy(parse_retry_policy());
+    server.infer_glob = has_flag("--infer-glob");
 
     for line in reader.lines() {
         let line = match line {
@@ -33,16 +50,28 @@ fn main() {
         }
 
         let response = server.handle_request(&line);
-        if let Some(resp) = response {
-            let _ = writeln!(stdout, "{}", resp);
-            let _ = stdout.flush();
+        let notifications = server.take_notifications();
+        if response.is_none() && notifications.is_empty() {
+            continue;
+        }
+        // Response first, then any notifications it produced, one message per line
+        for message in response.into_iter().chain(notifications) {
+            let _ = writeln!(stdout, "{}", message);
         }
+        let _ = stdout.flush();
     }
 }
 
 pub(crate) struct McpServer {
     pub(crate) runtime: ClientRuntime,
     pub(crate) default_repo_root: Option<PathBuf>,
+    pub(crate) notifier: Notifier,
+    /// `clientInfo.name` from `initialize`
+    pub(crate) client_name: Option<String>,
+    /// Session of tool calls that don't pass `session_id`
+    pub(crate) default_session_id: Option<String>,
+    /// `infer_glob` of queries that don't pass it (`--infer-glob`)
+    pub(crate) infer_glob: bool,
 }
 
 /// Parse a CLI argument by flag name, falling back to an environment variable.
@@ -60,8 +89,16 @@ fn parse_arg(flag: &str, env_var: &str) -> Option<String> {
     std::env::var(env_var).ok()
 }
 
+/// Whether a boolean flag was passed.
+fn has_flag(flag: &str) -> bool {
+    std::env::args().any(|arg| arg == flag)
+}
+
 fn parse_service_url() -> Option<String> {
-    parse_arg("--service-url", "CANOPY_SERVICE_URL")
+    canopy_client::local_service::resolve_service_url(parse_arg(
+        "--service-url",
+        "CANOPY_SERVICE_URL",
+    ))
 }
 
 fn parse_root_path() -> Option<PathBuf> {
@@ -69,7 +106,40 @@ fn parse_root_path() -> Option<PathBuf> {
 }
 
 fn parse_api_key() -> Option<String> {
-    parse_arg("--api-key", "CANOPY_API_KEY")
+    parse_arg("--api-key", canopy_client::credentials::API_KEY_ENV)
+}
+
+/// `--api-key`/CANOPY_API_KEY, else the credentials file of the default root
+/// (or the working directory). A broken credentials file is reported on
+/// stderr and the server starts without a key.
+fn resolve_api_key(uses_service: bool, default_repo_root: Option<&Path>) -> Option<String> {
+    let flag = parse_api_key();
+    if !uses_service {
+        return flag;
+    }
+    let cwd = std::env::current_dir().ok();
+    let repo_root = default_repo_root.or(cwd.as_deref());
+    canopy_client::credentials::resolve_api_key(flag, repo_root).unwrap_or_else(|e| {
+        eprintln!("canopy-mcp: ignoring credentials file: {e}");
+        None
+    })
+}
+
+/// `--service-retries` / `--service-timeout` (or their env vars); bad values
+/// are reported on stderr and the defaults used.
+fn parse_retry_policy() -> RetryPolicy {
+    RetryPolicy::parse(
+        parse_arg("--service-retries", retry::SERVICE_RETRIES_ENV).as_deref(),
+        parse_arg("--service-timeout", retry::SERVICE_TIMEOUT_ENV).as_deref(),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("canopy-mcp: using default service retries: {e}");
+        RetryPolicy::default()
+    })
+}
+
+fn parse_session_log() -> Option<PathBuf> {
+    parse_arg("--session-log", canopy_client::session_log::SESSION_LOG_ENV).map(PathBuf::from)
 }
 
 impl McpServer {
@@ -81,9 +151,27 @@ impl McpServer {
         Self {
             runtime: ClientRuntime::new(service_url.as_deref(), api_key),
             default_repo_root,
+            notifier: Notifier::default(),
+            client_name: None,
+            default_session_id: None,
+            infer_glob: false,
         }
     }
 
+    /// Notification frames to write after the current response.
+    fn take_notifications(&mut self) -> Vec<String> {
+        for change in self.runtime.take_generation_changes() {
+            self.notifier.index_changed(
+                &change.repo_root,
+                IndexChange::GenerationBump {
+                    old_generation: change.old_generation,
+                    new_generation: change.new_generation,
+                },
+            );
+        }
+        self.notifier.drain()
+    }
+
     fn handle_request(&mut self, line: &str) -> Option<String> {
         let req: JsonRpcRequest = match serde_json::from_str(line) {
             Ok(r) => r,
@@ -136,11 +224,21 @@ impl McpServer {
         }))
     }
 
-    fn handle_initialize(&self, _params: &Option<Value>) -> Result<Value, McpError> {
+    fn handle_initialize(&mut self, params: &Option<Value>) -> Result<Value, McpError> {
+        self.notifier
+            .set_enabled(client_supports_index_changed(params));
+        self.client_name = params
+            .as_ref()
+            .and_then(|p| p.pointer("/clientInfo/name"))
+            .and_then(Value::as_str)
+            .map(str::to_string);
         Ok(json!({
             "protocolVersion": "2024-11-05",
             "capabilities": {
-                "tools": {}
+                "tools": {},
+                "experimental": {
+                    "canopy": { "indexChanged": true }
+                }
             },
             "serverInfo": {
                 "name": "canopy-mcp",
@@ -150,11 +248,11 @@ impl McpServer {
     }
 
     fn handle_tools_list(&self) -> Result<Value, McpError> {
-        Ok(json!({
+        let mut list = json!({
             "tools": [
                 {
                     "name": "canopy_index",
-                    "description": "Index files matching glob pattern for efficient querying",
+                    "description": "Index files matching a glob pattern, or exactly the listed paths, for efficient querying",
                     "inputSchema": {
                         "type": "object",
                         "properties": {
@@ -165,21 +263,37 @@ impl McpServer {
                             "glob": {
                                 "type": "string",
                                 "description": "Glob pattern for files to index (e.g., '**/*.rs')"
+                            },
+                            "paths": {
+                                "type": "array",
+                                "items": { "type": "string" },
+                                "description": "Index exactly these files (repo-relative or absolute) instead of a glob; listed files that were deleted are dropped from the index"
+                            },
+                            "dry_run": {
+                                "type": "boolean",
+                                "description": "With 'glob': report the files that would be indexed, reindexed and skipped, and the estimated token change, without indexing (local index only)"
                             }
-                        },
-                        "required": ["glob"]
+                        }
                     }
                 },
                 {
                     "name": "canopy_query",
                     "description": "Query indexed content by pattern, symbol, section, or glob. Returns handles with optional auto-expansion.",
-                    "inputSchema": query_input_schema(&query_param_properties(), &["limit"]),
+                    "inputSchema": query_input_schema(
+                        &query_param_properties(),
+                        &["limit", "local_limit", "service_limit", "merge_strategy", "mode"],
+                    ),
                 },
                 {
                     "name": "canopy_evidence_pack",
                     "description": "Build a compact, ranked evidence pack for a query. Returns handles, file summaries, and guidance on whether to expand or refine.",
                     "inputSchema": query_input_schema(&query_param_properties(), &["max_handles", "max_per_file", "plan"]),
                 },
+                {
+                    "name": "canopy_explore",
+                    "description": "Evidence pack plus expanded content in one call: expands the pack's handles in rank order within token_budget, truncating the last one that doesn't fit. Returns the pack, the contents, tokens spent, and what to do with the rest of the budget.",
+                    "inputSchema": query_input_schema(&query_param_properties(), &["token_budget", "max_handles", "max_per_file"]),
+                },
                 {
                     "name": "canopy_expand",
                     "description": "Expand handles to full source content.",
@@ -190,15 +304,66 @@ impl McpServer {
                                 "type": "string",
                                 "description": "Repository path (optional if --root or CANOPY_ROOT is set)"
                             },
+                            "repo_id": {
+                                "type": "string",
+                                "description": "Service mode: target this service repo_id directly instead of resolving 'path' (for callers without a checkout; no dirty-file merging). Mutually exclusive with 'path'"
+                            },
                             "handle_ids": {
                                 "type": "array",
                                 "items": { "type": "string" },
                                 "description": "Handle IDs to expand (e.g., ['h1a2b3c4d5e6', 'h5d6e7f8a9b0'])"
+                            },
+                            "compare": {
+                                "type": "boolean",
+                                "description": "Compare each handle against the content last expanded for it: returns 'unchanged' or a unified diff instead of full content when possible"
+                            },
+                            "baseline_hashes": {
+                                "type": "object",
+                                "additionalProperties": { "type": "string" },
+                                "description": "Map of handle_id to the sha256 of the content last seen (from a previous compare-mode expand); implies compare"
+                            },
+                            "ranges": {
+                                "type": "object",
+                                "additionalProperties": { "type": "string" },
+                                "description": "Map of handle_id to a \"start-end\" file line range (e.g., {\"h1a2b3c4d5e6\": \"120-160\"}): returns only those lines of the node, clamped to it. A range outside the node returns the whole node with a note. Not with compare or baseline_hashes"
+                            }
+                        },
+                        "required": ["handle_ids"]
+                    }
+                },
+                {
+                    "name": "canopy_pin",
+                    "description": "Pin handles so canopy_expand keeps returning the same symbol or section after edits and reindexes move it. Pinned ids stay valid; expand follows them to the node's current handle.",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "path": {
+                                "type": "string",
+                                "description": "Repository path (optional if --root or CANOPY_ROOT is set)"
+                            },
+                            "handle_ids": {
+                                "type": "array",
+                                "items": { "type": "string" },
+                                "description": "Handle IDs to pin"
                             }
                         },
                         "required": ["handle_ids"]
                     }
                 },
+                {
+                    "name": "canopy_list_pins",
+                    "description": "List pinned handles with their status: fresh (id unchanged), remapped (expand follows current_id), or missing (with the reason).",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "path": {
+                                "type": "string",
+                                "description": "Repository path (optional if --root or CANOPY_ROOT is set)"
+                            }
+                        },
+                        "required": []
+                    }
+                },
                 {
                     "name": "canopy_status",
                     "description": "Get index status including file count, token count, and last indexed time",
@@ -208,11 +373,189 @@ impl McpServer {
                             "path": {
                                 "type": "string",
                                 "description": "Repository path (optional if --root or CANOPY_ROOT is set)"
+                            },
+                            "repo_id": {
+                                "type": "string",
+                                "description": "Service mode: target this service repo_id directly instead of resolving 'path' (for callers without a checkout; no dirty-file merging). Mutually exclusive with 'path'"
+                            },
+                            "detailed": {
+                                "type": "boolean",
+                                "description": "Include node_breakdown: nodes and tokens per node type, and the 10 largest nodes (default: false)"
+                            },
+                            "scope": {
+                                "type": "string",
+                                "description": "Only files under this repo-relative path (e.g., 'src/auth') or matching this glob: returns their files_indexed, total_tokens, nodes per type (by_type) and last_indexed instead of the full status"
+                            }
+                        },
+                        "required": []
+                    }
+                },
+                {
+                    "name": "canopy_repo_summary",
+                    "description": "Budgeted overview of the indexed repo: top-level directories with file/token/symbol counts, largest files, languages, and a handle for the README's first section. Call once at the start of a session.",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "path": {
+                                "type": "string",
+                                "description": "Repository path (optional if --root or CANOPY_ROOT is set)"
+                            },
+                            "max_tokens": {
+                                "type": "integer",
+                                "description": "Approximate token budget for the summary (default: 1500)"
+                            }
+                        },
+                        "required": []
+                    }
+                },
+                {
+                    "name": "canopy_related_files",
+                    "description": "Files most related to one file by the symbols they share: the file's references to symbols defined elsewhere, and references elsewhere to symbols it defines. Each result lists the shared symbols, so the ranking is explainable.",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "path": {
+                                "type": "string",
+                                "description": "Repository path (optional if --root or CANOPY_ROOT is set)"
+                            },
+                            "file": {
+                                "type": "string",
+                                "description": "Repo-relative path of an indexed file (e.g., 'src/auth/session.rs')"
+                            },
+                            "limit": {
+                                "type": "integer",
+                                "description": "Max related files (default: 10)"
+                            }
+                        },
+                        "required": ["file"]
+                    }
+                },
+                {
+                    "name": "canopy_references",
+                    "description": "References to a symbol grouped by file: one entry per referencing file with its count, ref type breakdown and line numbers, most references first. Cheaper than canopy_query with kind='reference' when a symbol is used across many files.",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "path": {
+                                "type": "string",
+                                "description": "Repository path (optional if --root or CANOPY_ROOT is set)"
+                            },
+                            "symbol": {
+                                "type": "string",
+                                "description": "Referenced name, matched ignoring case (e.g., 'expand')"
+                            },
+                            "ref_type": {
+                                "type": "string",
+                                "enum": ["call", "import", "type"],
+                                "description": "Only references of this kind (default: all)"
+                            },
+                            "glob": {
+                                "type": "string",
+                                "description": "Only files matching this glob (e.g., 'src/**/*.rs')"
+                            },
+                            "limit": {
+                                "type": "integer",
+                                "description": "Max files (default: 20)"
+                            }
+                        },
+                        "required": ["symbol"]
+                    }
+                },
+                {
+                    "name": "canopy_symbols",
+                    "description": "Index-wide symbol names in name order, for autocomplete: functions, classes, structs, methods and section headings, narrowed by a case-insensitive name prefix and node types. Pass the returned next_after as after for the next page.",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "path": {
+                                "type": "string",
+                                "description": "Repository path (optional if --root or CANOPY_ROOT is set)"
+                            },
+                            "prefix": {
+                                "type": "string",
+                                "description": "Only names starting with this, ignoring case (e.g., 'Han')"
+                            },
+                            "node_types": {
+                                "type": "array",
+                                "items": { "type": "string" },
+                                "description": "Only these node types: section, code_block, paragraph, function, class, struct, method, chunk"
+                            },
+                            "after": {
+                                "type": "string",
+                                "description": "next_after of the previous page"
+                            },
+                            "limit": {
+                                "type": "integer",
+                                "description": "Max symbols (default: 100)"
                             }
                         },
                         "required": []
                     }
                 },
+                {
+                    "name": "canopy_commit",
+                    "description": "A commit's message, touched files, and diff against its parent, cut to whole lines within max_tokens. Use after a kind='commit' query to see what a commit changed, optionally in one file.",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "path": {
+                                "type": "string",
+                                "description": "Repository path (optional if --root or CANOPY_ROOT is set)"
+                            },
+                            "sha": {
+                                "type": "string",
+                                "description": "Commit sha from a kind='commit' query, or any revision git resolves to a commit"
+                            },
+                            "file": {
+                                "type": "string",
+                                "description": "Only the diff of this repo-relative path"
+                            },
+                            "max_tokens": {
+                                "type": "integer",
+                                "description": "Approximate token cap on the diff (default: [history] max_diff_tokens, 2000)"
+                            }
+                        },
+                        "required": ["sha"]
+                    }
+                },
+                {
+                    "name": "canopy_validate_handles",
+                    "description": "Check whether handles still match their files without expanding them: fresh (unchanged since indexing), stale (file edited; reindex before trusting it) or missing (no such node). Compare content_hash with the one from the query to tell whether cached content is still current.",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "path": {
+                                "type": "string",
+                                "description": "Repository path (optional if --root or CANOPY_ROOT is set)"
+                            },
+                            "handle_ids": {
+                                "type": "array",
+                                "items": { "type": "string" },
+                                "description": "Handle IDs from query results"
+                            }
+                        },
+                        "required": ["handle_ids"]
+                    }
+                },
+                {
+                    "name": "canopy_refresh_handles",
+                    "description": "Reindex just the files behind stale handles (e.g. after expand reports a stale index) and report per handle: refreshed (with new_handle_id when the node's id changed), unchanged, file_missing or not_found. Cheaper than canopy_index or a hand-built canopy_invalidate glob.",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "path": {
+                                "type": "string",
+                                "description": "Repository path (optional if --root or CANOPY_ROOT is set)"
+                            },
+                            "handle_ids": {
+                                "type": "array",
+                                "items": { "type": "string" },
+                                "description": "Handle IDs whose files to reindex"
+                            }
+                        },
+                        "required": ["handle_ids"]
+                    }
+                },
                 {
                     "name": "canopy_invalidate",
                     "description": "Force reindex of files matching glob pattern",
@@ -226,6 +569,10 @@ impl McpServer {
                             "glob": {
                                 "type": "string",
                                 "description": "Glob pattern to invalidate (all files if omitted)"
+                            },
+                            "dry_run": {
+                                "type": "boolean",
+                                "description": "Report the files that would be removed, without removing them"
                             }
                         },
                         "required": []
@@ -240,7 +587,12 @@ impl McpServer {
                     }
                 }
             ]
-        }))
+        });
+        // Every tool takes the caller's session, for attributing feedback
+        for tool in list["tools"].as_array_mut().into_iter().flatten() {
+            tool["inputSchema"]["properties"]["session_id"] = session_id_property();
+        }
+        Ok(list)
     }
 
     fn handle_tools_call(&mut self, params: &Option<Value>) -> Result<Value, McpError> {
@@ -254,16 +606,38 @@ impl McpServer {
             .ok_or(McpError::InvalidParams("Missing tool name".to_string()))?;
 
         let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+        let session_id = arguments
+            .get("session_id")
+            .and_then(Value::as_str)
+            .or(self.default_session_id.as_deref());
+        let client = ClientContext::new(self.client_name.as_deref(), session_id);
+        if &client != self.runtime.client_context() {
+            self.runtime.set_client_context(client);
+        }
 
-        match name {
+        let result = match name {
             "canopy_index" => self.tool_index(&arguments),
             "canopy_query" => self.tool_query(&arguments),
             "canopy_evidence_pack" => self.tool_evidence_pack(&arguments),
+            "canopy_explore" => self.tool_explore(&arguments),
             "canopy_expand" => self.tool_expand(&arguments),
+            "canopy_pin" => self.tool_pin(&arguments),
+            "canopy_list_pins" => self.tool_list_pins(&arguments),
             "canopy_status" => self.tool_status(&arguments),
+            "canopy_repo_summary" => self.tool_repo_summary(&arguments),
+            "canopy_related_files" => self.tool_related_files(&arguments),
+            "canopy_references" => self.tool_references(&arguments),
+            "canopy_symbols" => self.tool_symbols(&arguments),
+            "canopy_commit" => self.tool_commit(&arguments),
+            "canopy_validate_handles" => self.tool_validate_handles(&arguments),
+            "canopy_refresh_handles" => self.tool_refresh_handles(&arguments),
             "canopy_invalidate" => self.tool_invalidate(&arguments),
             "canopy_agent_readme" => self.tool_agent_readme(),
             _ => Err(McpError::InvalidParams(format!("Unknown tool: {}", name))),
+        };
+        match result {
+            Err(McpError::Tool(body)) => Ok(McpError::tool_result(&body)),
+            result => result,
         }
     }
 }
@@ -278,7 +652,7 @@ mod tests {
 
     #[test]
     fn handle_initialize_returns_protocol_version() {
-        let server = test_server();
+        let mut server = test_server();
         let result = server.handle_initialize(&None).unwrap();
         assert_eq!(result["protocolVersion"], "2024-11-05");
         assert_eq!(result["serverInfo"]["name"], "canopy-mcp");
@@ -293,6 +667,95 @@ mod tests {
         let tool_names: Vec<&str> = tools.iter().map(|t| t["name"].as_str().unwrap()).collect();
         assert!(tool_names.contains(&"canopy_query"));
         assert!(tool_names.contains(&"canopy_expand"));
+        assert!(tool_names.contains(&"canopy_repo_summary"));
+        assert!(tool_names.contains(&"canopy_related_files"));
+        assert!(tool_names.contains(&"canopy_references"));
+        assert!(tool_names.contains(&"canopy_symbols"));
+        assert!(tool_names.contains(&"canopy_commit"));
+        assert!(tool_names.contains(&"canopy_validate_handles"));
+        assert!(tool_names.contains(&"canopy_refresh_handles"));
+    }
+
+    #[test]
+    fn repo_id_is_offered_wherever_a_service_repo_can_be_targeted() {
+        let server = test_server();
+        let result = server.handle_tools_list().unwrap();
+        for tool in result["tools"].as_array().unwrap() {
+            let name = tool["name"].as_str().unwrap();
+            let has_repo_id = tool["inputSchema"]["properties"]["repo_id"].is_object();
+            let expected = matches!(
+                name,
+                "canopy_query"
+                    | "canopy_evidence_pack"
+                    | "canopy_explore"
+                    | "canopy_expand"
+                    | "canopy_status"
+            );
+            assert_eq!(has_repo_id, expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn every_tool_accepts_a_session_id() {
+        let server = test_server();
+        let result = server.handle_tools_list().unwrap();
+        for tool in result["tools"].as_array().unwrap() {
+            let session_id = &tool["inputSchema"]["properties"]["session_id"];
+            assert_eq!(session_id["type"], "string", "{}", tool["name"]);
+        }
+    }
+
+    #[test]
+    fn client_info_and_session_id_become_the_runtime_context() {
+        let mut server = test_server();
+        server.default_session_id = Some("from-env".to_string());
+        server
+            .handle_initialize(&Some(json!({
+                "clientInfo": { "name": "editor-agent", "version": "1.2" }
+            })))
+            .unwrap();
+        let call = |server: &mut McpServer, arguments: Value| {
+            server
+                .handle_tools_call(&Some(json!({
+                    "name": "canopy_agent_readme",
+                    "arguments": arguments
+                })))
+                .unwrap();
+        };
+
+        call(&mut server, json!({ "session_id": "s-1" }));
+        let client = server.runtime.client_context();
+        assert_eq!(client.client_name.as_deref(), Some("editor-agent"));
+        assert_eq!(client.session_id.as_deref(), Some("s-1"));
+
+        call(&mut server, json!({}));
+        assert_eq!(
+            server.runtime.client_context().session_id.as_deref(),
+            Some("from-env")
+        );
+    }
+
+    #[test]
+    fn schema_mismatch_is_a_structured_tool_error() {
+        let err = canopy_core::CanopyError::SchemaVersionMismatch {
+            found: 18,
+            expected: 17,
+            reason: "the database was written by a newer canopy".to_string(),
+            written_by: Some("canopy 9.9.0".to_string()),
+            hint: "upgrade this binary".to_string(),
+        };
+        let McpError::Tool(body) = McpError::from(err) else {
+            panic!("expected a tool error");
+        };
+        assert_eq!(body["error"], "schema_version_mismatch");
+        assert_eq!(body["written_by"], "canopy 9.9.0");
+        assert_eq!(body["hint"], "upgrade this binary");
+
+        let result = McpError::tool_result(&body);
+        assert_eq!(result["isError"], true);
+        let text: Value =
+            serde_json::from_str(result["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(text, body);
     }
 
     #[test]
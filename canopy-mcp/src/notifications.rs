@@ -0,0 +1,305 @@
+//! Server-initiated `notifications/canopy/index_changed` messages.
+//!
+//! Only sent to clients that declare `capabilities.experimental.canopy.indexChanged`
+//! in `initialize`. Frames are buffered and flushed after the response line of
+//! the request that produced them, so they never interleave with a response.
+
+use serde_json::{json, Value};
+use std::path::Path;
+
+pub(crate) const INDEX_CHANGED_METHOD: &str = "notifications/canopy/index_changed";
+
+const STALE_HANDLES_HINT: &str =
+    "Handles from earlier results may be stale; re-run queries before expanding them.";
+
+/// An operation that changed what the index would return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IndexChange {
+    /// `canopy_index` finished locally
+    Indexed { files_affected: usize },
+    /// `canopy_index` triggered a service reindex
+    Reindexed { new_generation: u64 },
+    /// A service query came back from a newer generation than the last one seen
+    GenerationBump {
+        old_generation: u64,
+        new_generation: u64,
+    },
+    /// `canopy_invalidate` dropped files from the local index
+    Invalidated { files_affected: usize },
+}
+
+/// Whether the client opted in to index-change notifications.
+pub(crate) fn client_supports_index_changed(params: &Option<Value>) -> bool {
+    params
+        .as_ref()
+        .and_then(|p| p.pointer("/capabilities/experimental/canopy/indexChanged"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+/// Buffer of notification frames waiting to be written.
+#[derive(Debug, Default)]
+pub(crate) struct Notifier {
+    enabled: bool,
+    pending: Vec<String>,
+}
+
+impl Notifier {
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.pending.clear();
+        }
+    }
+
+    pub(crate) fn index_changed(&mut self, repo_root: &Path, change: IndexChange) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut params = json!({
+            "repo_root": repo_root.display().to_string(),
+            "hint": STALE_HANDLES_HINT,
+        });
+        let fields = match change {
+            IndexChange::Indexed { files_affected } => {
+                json!({ "reason": "indexed", "files_affected": files_affected })
+            }
+            IndexChange::Reindexed { new_generation } => {
+                json!({ "reason": "reindexed", "new_generation": new_generation })
+            }
+            IndexChange::GenerationBump {
+                old_generation,
+                new_generation,
+            } => json!({
+                "reason": "generation_bump",
+                "old_generation": old_generation,
+                "new_generation": new_generation,
+            }),
+            IndexChange::Invalidated { files_affected } => {
+                json!({ "reason": "invalidated", "files_affected": files_affected })
+            }
+        };
+        if let (Some(params), Value::Object(fields)) = (params.as_object_mut(), fields) {
+            params.extend(fields);
+        }
+
+        self.pending.push(
+            json!({
+                "jsonrpc": "2.0",
+                "method": INDEX_CHANGED_METHOD,
+                "params": params,
+            })
+            .to_string(),
+        );
+    }
+
+    /// Take the buffered frames, one JSON-RPC message per line.
+    pub(crate) fn drain(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::McpServer;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::process::Command;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn capability_must_be_declared() {
+        assert!(!client_supports_index_changed(&None));
+        assert!(!client_supports_index_changed(&Some(json!({}))));
+        assert!(client_supports_index_changed(&Some(json!({
+            "capabilities": { "experimental": { "canopy": { "indexChanged": true } } }
+        }))));
+    }
+
+    #[test]
+    fn disabled_notifier_buffers_nothing() {
+        let mut notifier = Notifier::default();
+        notifier.index_changed(
+            Path::new("/repo"),
+            IndexChange::Indexed { files_affected: 3 },
+        );
+        assert!(notifier.drain().is_empty());
+
+        notifier.set_enabled(true);
+        notifier.index_changed(
+            Path::new("/repo"),
+            IndexChange::Indexed { files_affected: 3 },
+        );
+        let frames = notifier.drain();
+        assert_eq!(frames.len(), 1);
+        let frame: Value = serde_json::from_str(&frames[0]).unwrap();
+        assert_eq!(frame["method"], INDEX_CHANGED_METHOD);
+        assert_eq!(frame["params"]["reason"], "indexed");
+        assert_eq!(frame["params"]["files_affected"], 3);
+        assert!(frame.get("id").is_none());
+        assert!(notifier.drain().is_empty());
+    }
+
+    fn git(root: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=canopy", "-c", "user.email=canopy@test"])
+            .args(args)
+            .current_dir(root)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    }
+
+    fn clean_repo() -> PathBuf {
+        let root = canopy_core::temp_test_dir("mcp-notify");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join("src/lib.rs"), "fn needle() {}\n").unwrap();
+        std::fs::write(root.join(".gitignore"), ".canopy/\n").unwrap();
+        git(&root, &["init", "-q"]);
+        git(&root, &["add", "."]);
+        git(&root, &["commit", "-q", "-m", "init"]);
+        std::fs::canonicalize(root).unwrap()
+    }
+
+    /// Minimal stand-in for canopy-service: one ready repo whose query results
+    /// report whatever generation `generation` currently holds.
+    fn fake_service(repo_root: &Path, generation: Arc<AtomicU64>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let repo_root = repo_root.display().to_string();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { break };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let gen = generation.load(Ordering::SeqCst);
+                let response = if request_line.contains("/repos/add") {
+                    json!({ "repo_id": "r1", "name": "repo" })
+                } else if request_line.contains("/repos") {
+                    json!([{
+                        "repo_id": "r1",
+                        "repo_root": repo_root,
+                        "name": "repo",
+                        "commit_sha": null,
+                        "generation": gen,
+                        "status": "ready",
+                    }])
+                } else {
+                    let mut handle = canopy_core::Handle::new(
+                        "src/lib.rs".to_string(),
+                        canopy_core::NodeType::Function,
+                        canopy_core::Span { start: 0, end: 15 },
+                        (1, 1),
+                        4,
+                        "fn needle() {}".to_string(),
+                    );
+                    handle.source = canopy_core::HandleSource::Service;
+                    handle.generation = Some(gen);
+                    serde_json::to_value(canopy_core::QueryResult {
+                        total_tokens: handle.token_count,
+                        total_matches: 1,
+                        handles: vec![handle],
+                        ..Default::default()
+                    })
+                    .unwrap()
+                }
+                .to_string();
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.len(),
+                    response
+                );
+            }
+        });
+        base_url
+    }
+
+    fn call(server: &mut McpServer, id: u64, method: &str, params: Value) -> Vec<String> {
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let mut lines = vec![server.handle_request(&request.to_string()).unwrap()];
+        lines.extend(server.take_notifications());
+        lines
+    }
+
+    fn index_changed_frames(lines: &[String]) -> Vec<Value> {
+        lines
+            .iter()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .filter(|msg| msg["method"] == INDEX_CHANGED_METHOD)
+            .collect()
+    }
+
+    #[test]
+    fn generation_bump_during_query_notifies_once() {
+        let repo = clean_repo();
+        let generation = Arc::new(AtomicU64::new(1));
+        let base_url = fake_service(&repo, generation.clone());
+        let mut server = McpServer::with_service_url(Some(base_url), None, Some(repo.clone()));
+
+        call(
+            &mut server,
+            1,
+            "initialize",
+            json!({ "capabilities": { "experimental": { "canopy": { "indexChanged": true } } } }),
+        );
+        let query = json!({ "name": "canopy_query", "arguments": { "pattern": "needle" } });
+
+        // First query only establishes the baseline generation
+        let lines = call(&mut server, 2, "tools/call", query.clone());
+        assert!(index_changed_frames(&lines).is_empty());
+
+        generation.store(2, Ordering::SeqCst);
+        let lines = call(&mut server, 3, "tools/call", query.clone());
+        // The response line comes first and is a complete JSON-RPC response
+        let response: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(response["id"], 3);
+        let frames = index_changed_frames(&lines);
+        assert_eq!(frames.len(), 1, "lines: {lines:?}");
+        assert_eq!(frames[0]["params"]["reason"], "generation_bump");
+        assert_eq!(frames[0]["params"]["old_generation"], 1);
+        assert_eq!(frames[0]["params"]["new_generation"], 2);
+        assert_eq!(frames[0]["params"]["repo_root"], repo.display().to_string());
+
+        // Same generation again: nothing new to report
+        let lines = call(&mut server, 4, "tools/call", query);
+        assert!(index_changed_frames(&lines).is_empty());
+    }
+
+    #[test]
+    fn clients_without_capability_get_no_notifications() {
+        let repo = clean_repo();
+        let generation = Arc::new(AtomicU64::new(1));
+        let base_url = fake_service(&repo, generation.clone());
+        let mut server = McpServer::with_service_url(Some(base_url), None, Some(repo));
+
+        call(&mut server, 1, "initialize", json!({}));
+        let query = json!({ "name": "canopy_query", "arguments": { "pattern": "needle" } });
+        call(&mut server, 2, "tools/call", query.clone());
+        generation.store(2, Ordering::SeqCst);
+        let lines = call(&mut server, 3, "tools/call", query);
+        assert_eq!(lines.len(), 1);
+    }
+}
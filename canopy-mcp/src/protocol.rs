@@ -1,7 +1,7 @@
 //! JSON-RPC protocol types and MCP error definitions.
 
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 
 #[derive(Deserialize)]
 #[allow(dead_code)]
@@ -39,6 +39,19 @@ pub(crate) enum McpError {
     InvalidParams(String),
     /// -32000: Application-level error (index, query, expand failures)
     Application(String),
+    /// A failed tool call the agent can act on: returned as a tool result
+    /// with `isError` and this JSON as its text, not as a JSON-RPC error
+    Tool(Value),
+}
+
+impl McpError {
+    /// The tool result carrying a [`McpError::Tool`] body.
+    pub(crate) fn tool_result(body: &Value) -> Value {
+        json!({
+            "content": [{ "type": "text", "text": body.to_string() }],
+            "isError": true
+        })
+    }
 }
 
 impl From<McpError> for JsonRpcError {
@@ -48,6 +61,7 @@ impl From<McpError> for JsonRpcError {
             McpError::MethodNotFound(m) => (-32601, m),
             McpError::InvalidParams(m) => (-32602, m),
             McpError::Application(m) => (-32000, m),
+            McpError::Tool(body) => (-32000, body.to_string()),
         };
         JsonRpcError { code, message }
     }
@@ -55,6 +69,22 @@ impl From<McpError> for JsonRpcError {
 
 impl From<canopy_core::CanopyError> for McpError {
     fn from(e: canopy_core::CanopyError) -> Self {
-        McpError::Application(e.to_string())
+        match &e {
+            canopy_core::CanopyError::SchemaVersionMismatch {
+                found,
+                expected,
+                written_by,
+                hint,
+                ..
+            } => McpError::Tool(json!({
+                "error": "schema_version_mismatch",
+                "message": e.to_string(),
+                "found": found,
+                "expected": expected,
+                "written_by": written_by,
+                "hint": hint,
+            })),
+            _ => McpError::Application(e.to_string()),
+        }
     }
 }
@@ -4,6 +4,14 @@ use serde_json::{json, Value};
 
 pub(crate) const DEFAULT_MCP_QUERY_LIMIT: usize = 16;
 
+/// The optional `session_id` every tool accepts.
+pub(crate) fn session_id_property() -> Value {
+    json!({
+        "type": "string",
+        "description": "Agent session making the call; recorded with feedback and sent to the service so load can be attributed per session"
+    })
+}
+
 /// Shared query parameter JSON schema properties used by canopy_query and canopy_evidence_pack.
 pub(crate) fn query_param_properties() -> Value {
     json!({
@@ -11,6 +19,10 @@ pub(crate) fn query_param_properties() -> Value {
             "type": "string",
             "description": "Repository path (optional if --root or CANOPY_ROOT is set)"
         },
+        "repo_id": {
+            "type": "string",
+            "description": "Service mode: target this service repo_id directly instead of resolving 'path' (for callers without a checkout; no dirty-file merging). Mutually exclusive with 'path'"
+        },
         "pattern": {
             "type": "string",
             "description": "Single text pattern to search (FTS5 search)"
@@ -18,7 +30,7 @@ pub(crate) fn query_param_properties() -> Value {
         "patterns": {
             "type": "array",
             "items": { "type": "string" },
-            "description": "Multiple text patterns to search"
+            "description": "Multiple text patterns to search; patterns that fail to run are reported in pattern_errors instead of failing the query"
         },
         "symbol": {
             "type": "string",
@@ -28,18 +40,61 @@ pub(crate) fn query_param_properties() -> Value {
             "type": "string",
             "description": "Section heading search (markdown sections)"
         },
+        "section_path": {
+            "type": "string",
+            "description": "Nested section by heading path suffix, e.g. 'auth > configuration' or 'auth/configuration'; disambiguates same-named sections"
+        },
         "parent": {
             "type": "string",
             "description": "Filter by parent symbol (e.g., class name for methods)"
         },
         "kind": {
             "type": "string",
-            "enum": ["definition", "reference", "any"],
-            "description": "Query kind: 'definition' for exact symbol match, 'reference' for usages, 'any' (default)"
+            "enum": ["definition", "reference", "annotation", "commit", "any"],
+            "description": "Query kind: 'definition' for exact symbol match, 'reference' for usages, 'annotation' for TODO/FIXME marker comments matching pattern (omit pattern to list all), 'commit' for commit messages of the git-history layer (omit pattern for the newest; expand one with canopy_commit), 'any' (default)"
+        },
+        "ref_types": {
+            "type": "array",
+            "items": { "type": "string", "enum": ["call", "import", "type"] },
+            "description": "With kind='reference': keep only these reference kinds. Results include ref_type_counts over all kinds either way."
+        },
+        "group_references": {
+            "type": "boolean",
+            "description": "With kind='reference': fold refs with identical previews into one entry whose occurrences list the other file/line locations (default: imports only; true groups every kind, false none)"
         },
         "glob": {
             "type": "string",
-            "description": "File glob filter (e.g., 'src/**/*.rs')"
+            "description": "File glob filter (e.g., 'src/**/*.rs'). Globs match whole paths, so 'session' matches only a top-level file named session; use path_contains to scope by part of a path"
+        },
+        "path_contains": {
+            "type": "string",
+            "description": "Only files whose path contains this text, case-insensitively (e.g. 'session' for src/middleware/Session/store.ts); no glob syntax. The recommended way to scope by directory or file name. Alone it returns the matching files; with glob both must match"
+        },
+        "infer_glob": {
+            "type": "boolean",
+            "description": "Without glob or path_contains, search first in the directories the query terms name (e.g. 'src/websocket/**' for 'websocket reconnect'), reported as inferred_glob; if nothing matches there the whole repo is searched and expand_note says so. Defaults to the server's --infer-glob setting"
+        },
+        "exclude_patterns": {
+            "type": "array",
+            "items": { "type": "string" },
+            "description": "Leave out results whose content matches any of these patterns (e.g. ['test', 'mock']); applied before the limit, results report excluded_matches. Not for kind='reference', 'annotation' or 'commit'"
+        },
+        "include_generated": {
+            "type": "boolean",
+            "description": "Also search files flagged as generated (marker comments like '@generated', protobuf/bundle name patterns, minified lines). Left out by default; results report suppressed_generated."
+        },
+        "explain": {
+            "type": "boolean",
+            "description": "Report how the query was answered: each search run (fts, symbol_cache, symbol_db, symbol_fuzzy, sections, refs, in_file, ...) with its escaped FTS query, scope filter, glob and result count, a 'via' tag on every handle, and for misses a checklist of likely causes"
+        },
+        "priors": {
+            "type": "object",
+            "additionalProperties": { "type": "number", "exclusiveMinimum": 0 },
+            "description": "Ranking multipliers by node type (function, class, struct, method, section, code_block, paragraph, chunk), e.g. {\"section\": 2.0, \"function\": 0.5}; applied to the default type weights for auto-expand and evidence pack ranking. Replaces the priors learned from feedback; unnamed types keep their defaults"
+        },
+        "modified_within": {
+            "type": "string",
+            "description": "Only files changed within this window of now, e.g. '48h', '7d', '2w' (by last commit time with [indexing] git_commit_times, else mtime)"
         },
         "match": {
             "type": "string",
@@ -62,6 +117,24 @@ pub(crate) fn query_input_schema(shared: &Value, extras: &[&str]) -> Value {
                 "type": "integer",
                 "description": "Maximum number of results (default: 16)"
             }),
+            "local_limit" => json!({
+                "type": "integer",
+                "description": "Service mode: max results from the local dirty-file index before merging (default: limit)"
+            }),
+            "service_limit" => json!({
+                "type": "integer",
+                "description": "Service mode: max results from the service before merging (default: limit)"
+            }),
+            "merge_strategy" => json!({
+                "type": "string",
+                "enum": ["rank_interleave", "local_first", "service_first"],
+                "description": "Service mode: order of merged results. 'rank_interleave' (default) interleaves by each side's rank; 'local_first' / 'service_first' put the other side's handles after. Dirty-file overrides always take their service counterpart's place"
+            }),
+            "mode" => json!({
+                "type": "string",
+                "enum": ["handles", "count", "exists"],
+                "description": "'handles' (default) returns handles; 'count' returns only total_matches plus match_counts per combined search; 'exists' returns exists and one witness_path"
+            }),
             "max_handles" => json!({
                 "type": "integer",
                 "description": "Maximum ranked handles in evidence pack (default: 8)"
@@ -70,6 +143,10 @@ pub(crate) fn query_input_schema(shared: &Value, extras: &[&str]) -> Value {
                 "type": "integer",
                 "description": "Maximum handles selected from a single file (default: 2)"
             }),
+            "token_budget" => json!({
+                "type": "integer",
+                "description": "Tokens of expanded content to spend (default: 4000); the last handle that doesn't fit is truncated"
+            }),
             "plan" => json!({
                 "type": "boolean",
                 "description": "Override server-side recursive evidence planning (default: auto: only when confidence is low)"
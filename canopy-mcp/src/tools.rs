@@ -1,14 +1,19 @@
 //! Tool implementation methods for the MCP server.
 
+use crate::notifications::IndexChange;
 use crate::protocol::McpError;
 use crate::schema::DEFAULT_MCP_QUERY_LIMIT;
 use crate::McpServer;
 
 use canopy_client::predict::extract_query_text;
-use canopy_client::IndexResult;
+use canopy_client::{lock_index, IndexResult, SharedIndex};
 use canopy_core::feedback::FeedbackStore;
-use canopy_core::{MatchMode, QueryParams, RepoIndex};
+use canopy_core::{
+    show_commit, ExpandDelta, HistoryConfig, LineWindow, MatchMode, MergeStrategy, NodeType,
+    QueryMode, QueryParams, RefType,
+};
 use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
 /// Wrap a text string into an MCP content response.
@@ -25,18 +30,52 @@ fn mcp_json<T: serde::Serialize>(val: &T) -> Result<Value, McpError> {
 
 impl McpServer {
     pub(crate) fn tool_index(&mut self, args: &Value) -> Result<Value, McpError> {
-        let glob = args
-            .get("glob")
-            .and_then(|v| v.as_str())
-            .ok_or(McpError::InvalidParams(
-                "Missing 'glob' parameter".to_string(),
-            ))?;
+        let glob = args.get("glob").and_then(|v| v.as_str());
+        let paths: Option<Vec<PathBuf>> = args.get("paths").and_then(|v| v.as_array()).map(|a| {
+            a.iter()
+                .filter_map(|v| v.as_str().map(PathBuf::from))
+                .collect()
+        });
+
+        let dry_run = args
+            .get("dry_run")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
 
         let repo_root = self.get_repo_root(args)?;
-        let result = self.runtime.index(&repo_root, Some(glob))?;
+        if dry_run {
+            if paths.is_some() {
+                return Err(McpError::InvalidParams(
+                    "'dry_run' previews a 'glob', not 'paths'".to_string(),
+                ));
+            }
+            return mcp_json(&self.runtime.plan_index(&repo_root, glob)?);
+        }
+        let result = match (glob, paths) {
+            (Some(_), Some(_)) => {
+                return Err(McpError::InvalidParams(
+                    "Give either 'glob' or 'paths', not both".to_string(),
+                ))
+            }
+            (Some(glob), None) => self.runtime.index(&repo_root, Some(glob))?,
+            (None, Some(paths)) => self.runtime.index_paths(&repo_root, &paths)?,
+            (None, None) => {
+                return Err(McpError::InvalidParams(
+                    "Missing 'glob' or 'paths' parameter".to_string(),
+                ))
+            }
+        };
 
         let result_json = match result {
             IndexResult::Local(stats) => {
+                if stats.files_indexed > 0 {
+                    self.notifier.index_changed(
+                        &repo_root,
+                        IndexChange::Indexed {
+                            files_affected: stats.files_indexed,
+                        },
+                    );
+                }
                 let mut val = serde_json::to_value(&stats)
                     .map_err(|e| McpError::Application(format!("Serialization error: {}", e)))?;
                 if let Some(obj) = val.as_object_mut() {
@@ -48,6 +87,12 @@ impl McpServer {
                 val
             }
             IndexResult::Service(resp) => {
+                self.notifier.index_changed(
+                    &repo_root,
+                    IndexChange::Reindexed {
+                        new_generation: resp.generation,
+                    },
+                );
                 json!({
                     "generation": resp.generation,
                     "status": resp.status,
@@ -61,20 +106,22 @@ impl McpServer {
     }
 
     pub(crate) fn tool_query(&mut self, args: &Value) -> Result<Value, McpError> {
+        if let Some(repo_id) = repo_id_arg(args)? {
+            let params = self.query_params(args)?;
+            return mcp_json(&self.runtime.query_by_repo_id(repo_id, params)?);
+        }
         let repo_root = self.get_repo_root(args)?;
         self.ensure_predictive_index(&repo_root, args)?;
 
-        let params = build_query_params(args)?;
+        let params = self.query_params(args)?;
         let result = self.runtime.query(&repo_root, params)?;
 
         mcp_json(&result)
     }
 
     pub(crate) fn tool_evidence_pack(&mut self, args: &Value) -> Result<Value, McpError> {
-        let repo_root = self.get_repo_root(args)?;
-        self.ensure_predictive_index(&repo_root, args)?;
-
-        let params = build_query_params(args)?;
+        let repo_id = repo_id_arg(args)?;
+        let params = self.query_params(args)?;
         let max_handles = args
             .get("max_handles")
             .and_then(|v| v.as_u64())
@@ -87,13 +134,61 @@ impl McpServer {
             .unwrap_or(2);
         let plan = args.get("plan").and_then(|v| v.as_bool());
 
-        let pack =
+        let pack = if let Some(repo_id) = repo_id {
+            self.runtime.evidence_pack_by_repo_id(
+                repo_id,
+                params,
+                max_handles,
+                max_per_file,
+                plan,
+            )?
+        } else {
+            let repo_root = self.get_repo_root(args)?;
+            self.ensure_predictive_index(&repo_root, args)?;
             self.runtime
-                .evidence_pack(&repo_root, params, max_handles, max_per_file, plan)?;
+                .evidence_pack(&repo_root, params, max_handles, max_per_file, plan)?
+        };
 
         mcp_json(&pack)
     }
 
+    pub(crate) fn tool_explore(&mut self, args: &Value) -> Result<Value, McpError> {
+        let repo_id = repo_id_arg(args)?;
+        let params = self.query_params(args)?;
+        let token_budget = args
+            .get("token_budget")
+            .and_then(|v| v.as_u64())
+            .map(|v| v as usize)
+            .unwrap_or(4000);
+        let max_handles = args
+            .get("max_handles")
+            .and_then(|v| v.as_u64())
+            .map(|v| v as usize)
+            .unwrap_or(8);
+        let max_per_file = args
+            .get("max_per_file")
+            .and_then(|v| v.as_u64())
+            .map(|v| v as usize)
+            .unwrap_or(2);
+
+        let exploration = if let Some(repo_id) = repo_id {
+            self.runtime.explore_by_repo_id(
+                repo_id,
+                params,
+                token_budget,
+                max_handles,
+                max_per_file,
+            )?
+        } else {
+            let repo_root = self.get_repo_root(args)?;
+            self.ensure_predictive_index(&repo_root, args)?;
+            self.runtime
+                .explore(&repo_root, params, token_budget, max_handles, max_per_file)?
+        };
+
+        mcp_json(&exploration)
+    }
+
     pub(crate) fn tool_expand(&mut self, args: &Value) -> Result<Value, McpError> {
         let handle_ids: Vec<String> = args
             .get("handle_ids")
@@ -111,38 +206,158 @@ impl McpServer {
             ));
         }
 
-        let repo_root = self.get_repo_root(args)?;
-        let outcome = self.runtime.expand(&repo_root, &handle_ids)?;
+        let compare = args
+            .get("compare")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let baselines: HashMap<String, String> = match args.get("baseline_hashes") {
+            Some(Value::Object(map)) => map
+                .iter()
+                .filter_map(|(id, hash)| hash.as_str().map(|h| (id.clone(), h.to_string())))
+                .collect(),
+            Some(Value::Null) | None => HashMap::new(),
+            Some(_) => {
+                return Err(McpError::InvalidParams(
+                    "'baseline_hashes' must be an object of handle_id to sha256".to_string(),
+                ))
+            }
+        };
+
+        let ranges = line_ranges(args)?;
+        if !ranges.is_empty() && (compare || !baselines.is_empty()) {
+            return Err(McpError::InvalidParams(
+                "'ranges' can't be combined with 'compare' or 'baseline_hashes', which compare whole nodes"
+                    .to_string(),
+            ));
+        }
+
+        let outcome = if let Some(repo_id) = repo_id_arg(args)? {
+            if compare || !baselines.is_empty() || !ranges.is_empty() {
+                return Err(McpError::InvalidParams(
+                    "'compare', 'baseline_hashes' and 'ranges' need a local checkout; omit them with 'repo_id'"
+                        .to_string(),
+                ));
+            }
+            self.runtime.expand_by_repo_id(repo_id, &handle_ids)?
+        } else if !ranges.is_empty() {
+            let repo_root = self.get_repo_root(args)?;
+            self.runtime
+                .expand_lines(&repo_root, &handle_ids, &ranges)?
+        } else if !baselines.is_empty() {
+            let repo_root = self.get_repo_root(args)?;
+            self.runtime
+                .expand_against(&repo_root, &handle_ids, &baselines)?
+        } else {
+            let repo_root = self.get_repo_root(args)?;
+            self.runtime.expand(&repo_root, &handle_ids, compare)?
+        };
 
-        // Format as readable text
+        // Format as readable text; compare mode swaps content for a diff or
+        // an "unchanged" marker where it can
         let mut text = outcome
             .contents
             .iter()
-            .map(|(id, content)| format!("// {}\n{}", id, content))
+            .enumerate()
+            .map(|(i, (id, content))| match outcome.comparisons.get(i) {
+                Some(c) => match &c.delta {
+                    ExpandDelta::Unchanged => {
+                        format!("// {} unchanged (sha256 {})", id, c.content_hash)
+                    }
+                    ExpandDelta::Diff { diff } => {
+                        format!("// {} diff (sha256 {})\n{}", id, c.content_hash, diff)
+                    }
+                    ExpandDelta::Full => {
+                        format!("// {} (sha256 {})\n{}", id, c.content_hash, content)
+                    }
+                },
+                None => {
+                    let header = match ranges.get(id) {
+                        Some(window) => format!("// {} lines {}", id, window),
+                        None => format!("// {}", id),
+                    };
+                    match outcome.notes.get(id) {
+                        Some(note) => format!("{}\n// {}\n{}", header, note, content),
+                        None => format!("{}\n{}", header, content),
+                    }
+                }
+            })
             .collect::<Vec<_>>()
             .join("\n\n");
 
         // Include failed_ids in response
         if !outcome.failed_ids.is_empty() {
             text.push_str(&format!(
-                "\n\n// Failed to expand: {}",
+                "\n\n// Failed to expand: {} (if stale, canopy_refresh_handles reindexes their files)",
                 outcome.failed_ids.join(", ")
             ));
         }
+        for pin in &outcome.unresolved_pins {
+            text.push_str(&format!(
+                "\n// Pinned {} no longer resolves: {}",
+                pin.handle_id, pin.reason
+            ));
+        }
 
         Ok(json!({
             "content": [{
                 "type": "text",
                 "text": text
             }],
-            "failed_ids": outcome.failed_ids
+            "failed_ids": outcome.failed_ids,
+            "comparisons": outcome.comparisons,
+            "unresolved_pins": outcome.unresolved_pins,
+            "notes": outcome.notes
         }))
     }
 
+    pub(crate) fn tool_pin(&mut self, args: &Value) -> Result<Value, McpError> {
+        let handle_ids: Vec<String> = args
+            .get("handle_ids")
+            .and_then(|v| v.as_array())
+            .ok_or(McpError::InvalidParams(
+                "Missing 'handle_ids' parameter".to_string(),
+            ))?
+            .iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect();
+
+        if handle_ids.is_empty() {
+            return Err(McpError::InvalidParams(
+                "Empty handle_ids array".to_string(),
+            ));
+        }
+
+        let repo_root = self.get_repo_root(args)?;
+        let pins = self.runtime.pin_handles(&repo_root, &handle_ids)?;
+
+        mcp_json(&pins)
+    }
+
+    pub(crate) fn tool_list_pins(&mut self, args: &Value) -> Result<Value, McpError> {
+        let repo_root = self.get_repo_root(args)?;
+        let pins = self.runtime.list_pins(&repo_root)?;
+
+        mcp_json(&pins)
+    }
+
     pub(crate) fn tool_status(&self, args: &Value) -> Result<Value, McpError> {
+        if let Some(repo_id) = repo_id_arg(args)? {
+            return mcp_json(&self.runtime.repo_status_by_id(repo_id)?);
+        }
         let repo_root = self.get_repo_root(args)?;
         let index = self.open_index_at(&repo_root)?;
-        let status = index.status()?;
+        if let Some(scope) = args.get("scope").and_then(|v| v.as_str()) {
+            return mcp_json(&lock_index(&index).status_scoped(scope)?);
+        }
+        let detailed = args
+            .get("detailed")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let status = if detailed {
+            lock_index(&index).status_detailed()?
+        } else {
+            lock_index(&index).status()?
+        };
 
         let mut result = serde_json::to_value(&status)
             .map_err(|e| McpError::Application(format!("Serialization error: {}", e)))?;
@@ -155,6 +370,10 @@ impl McpServer {
                 "file_discovery".to_string(),
                 json!(canopy_core::FileDiscovery::detect().name()),
             );
+            obj.insert(
+                "dirty_rebuilds".to_string(),
+                json!(self.runtime.dirty_rebuild_stats()),
+            );
             if let Ok(feedback_store) = FeedbackStore::open(&repo_root) {
                 if let Ok(metrics) = feedback_store.compute_metrics(7.0) {
                     obj.insert(
@@ -162,6 +381,8 @@ impl McpServer {
                         json!({
                             "glob_hit_rate_at_k": metrics.glob_hit_rate_at_k,
                             "handle_expand_accept_rate": metrics.handle_expand_accept_rate,
+                            "manual_expand_accept_rate": metrics.manual_expand_accept_rate,
+                            "auto_expand_share": metrics.auto_expand_share,
                             "avg_tokens_per_expand": metrics.avg_tokens_per_expand,
                             "sample_count": metrics.sample_count,
                         }),
@@ -173,12 +394,156 @@ impl McpServer {
         mcp_json(&result)
     }
 
-    pub(crate) fn tool_invalidate(&self, args: &Value) -> Result<Value, McpError> {
+    pub(crate) fn tool_repo_summary(&mut self, args: &Value) -> Result<Value, McpError> {
+        let repo_root = self.get_repo_root(args)?;
+        let max_tokens = args
+            .get("max_tokens")
+            .and_then(|v| v.as_u64())
+            .map(|v| (v as usize).max(1));
+        let summary = self.runtime.repo_summary(&repo_root, max_tokens)?;
+
+        mcp_json(&summary)
+    }
+
+    pub(crate) fn tool_related_files(&mut self, args: &Value) -> Result<Value, McpError> {
+        let repo_root = self.get_repo_root(args)?;
+        let file = args
+            .get("file")
+            .and_then(|v| v.as_str())
+            .ok_or(McpError::InvalidParams(
+                "Missing 'file' parameter".to_string(),
+            ))?;
+        let limit = args
+            .get("limit")
+            .and_then(|v| v.as_u64())
+            .map(|v| (v as usize).max(1));
+        let related = self.runtime.related_files(&repo_root, file, limit)?;
+
+        mcp_json(&related)
+    }
+
+    pub(crate) fn tool_references(&mut self, args: &Value) -> Result<Value, McpError> {
+        let repo_root = self.get_repo_root(args)?;
+        let symbol = args
+            .get("symbol")
+            .and_then(|v| v.as_str())
+            .ok_or(McpError::InvalidParams(
+                "Missing 'symbol' parameter".to_string(),
+            ))?;
+        let ref_types = ref_type_filter(args)?;
         let glob = args.get("glob").and_then(|v| v.as_str());
+        let limit = args
+            .get("limit")
+            .and_then(|v| v.as_u64())
+            .map(|v| (v as usize).max(1));
+        let references = self
+            .runtime
+            .references(&repo_root, symbol, &ref_types, glob, limit)?;
 
+        mcp_json(&references)
+    }
+
+    pub(crate) fn tool_symbols(&mut self, args: &Value) -> Result<Value, McpError> {
         let repo_root = self.get_repo_root(args)?;
-        let mut index = self.open_index_at(&repo_root)?;
-        let count = index.invalidate(glob)?;
+        let prefix = args.get("prefix").and_then(|v| v.as_str());
+        let node_types = node_types(args)?;
+        let after = args.get("after").and_then(|v| v.as_str());
+        let limit = args
+            .get("limit")
+            .and_then(|v| v.as_u64())
+            .map(|v| (v as usize).max(1));
+        let page = self
+            .runtime
+            .list_symbols(&repo_root, prefix, &node_types, after, limit)?;
+
+        mcp_json(&page)
+    }
+
+    pub(crate) fn tool_commit(&mut self, args: &Value) -> Result<Value, McpError> {
+        let repo_root = self.get_repo_root(args)?;
+        let sha = args
+            .get("sha")
+            .and_then(|v| v.as_str())
+            .ok_or(McpError::InvalidParams(
+                "Missing 'sha' parameter".to_string(),
+            ))?;
+        let file = args.get("file").and_then(|v| v.as_str());
+        let max_tokens = match args.get("max_tokens").and_then(|v| v.as_u64()) {
+            Some(max_tokens) => max_tokens as usize,
+            None => HistoryConfig::for_repo(&repo_root)?.max_diff_tokens,
+        };
+
+        mcp_json(&show_commit(&repo_root, sha, file, max_tokens)?)
+    }
+
+    pub(crate) fn tool_validate_handles(&mut self, args: &Value) -> Result<Value, McpError> {
+        let handle_ids: Vec<String> = args
+            .get("handle_ids")
+            .and_then(|v| v.as_array())
+            .ok_or(McpError::InvalidParams(
+                "Missing 'handle_ids' parameter".to_string(),
+            ))?
+            .iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect();
+        let repo_root = self.get_repo_root(args)?;
+        let handles = self.runtime.validate_handles(&repo_root, &handle_ids)?;
+
+        mcp_json(&json!({ "handles": handles }))
+    }
+
+    pub(crate) fn tool_refresh_handles(&mut self, args: &Value) -> Result<Value, McpError> {
+        let handle_ids: Vec<String> = args
+            .get("handle_ids")
+            .and_then(|v| v.as_array())
+            .ok_or(McpError::InvalidParams(
+                "Missing 'handle_ids' parameter".to_string(),
+            ))?
+            .iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect();
+        if handle_ids.is_empty() {
+            return Err(McpError::InvalidParams(
+                "Empty handle_ids array".to_string(),
+            ));
+        }
+        let repo_root = self.get_repo_root(args)?;
+        let report = self.runtime.refresh_handles(&repo_root, &handle_ids)?;
+
+        if report.files_reindexed > 0 {
+            let change = match report.generation {
+                Some(new_generation) => IndexChange::Reindexed { new_generation },
+                None => IndexChange::Indexed {
+                    files_affected: report.files_reindexed,
+                },
+            };
+            self.notifier.index_changed(&repo_root, change);
+        }
+        mcp_json(&report)
+    }
+
+    pub(crate) fn tool_invalidate(&mut self, args: &Value) -> Result<Value, McpError> {
+        let glob = args.get("glob").and_then(|v| v.as_str());
+
+        let dry_run = args
+            .get("dry_run")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        let repo_root = self.get_repo_root(args)?;
+        let index = self.open_index_at(&repo_root)?;
+        if dry_run {
+            return mcp_json(&lock_index(&index).plan_invalidate(glob)?);
+        }
+        let count = lock_index(&index).invalidate(glob)?;
+        if count > 0 {
+            self.notifier.index_changed(
+                &repo_root,
+                IndexChange::Invalidated {
+                    files_affected: count,
+                },
+            );
+        }
 
         Ok(mcp_text(format!("Invalidated {} files", count)))
     }
@@ -195,16 +560,19 @@ impl McpServer {
     ) -> Result<(), McpError> {
         if !self.runtime.is_service_mode() {
             let query_text = extract_query_text(args);
-            let mut index = self.open_index_at(repo_root)?;
-            self.runtime
-                .predictive_index_for_query(repo_root, &mut index, &query_text)?;
+            let index = self.open_index_at(repo_root)?;
+            self.runtime.predictive_index_for_query(
+                repo_root,
+                &mut lock_index(&index),
+                &query_text,
+            )?;
         }
         Ok(())
     }
 
-    /// Open index at a specific path (with auto-init).
-    pub(crate) fn open_index_at(&self, root: &std::path::Path) -> Result<RepoIndex, McpError> {
-        Ok(RepoIndex::open_or_init(root)?)
+    /// The runtime's shared index at a specific path (with auto-init).
+    pub(crate) fn open_index_at(&self, root: &std::path::Path) -> Result<SharedIndex, McpError> {
+        Ok(self.runtime.open_local_index(root)?)
     }
 
     /// Get repo root from args (required parameter)
@@ -219,6 +587,28 @@ impl McpServer {
             "Missing 'path' parameter and no default --root/CANOPY_ROOT configured".to_string(),
         ))
     }
+
+    /// [`build_query_params`], with the server's `infer_glob` default for
+    /// queries that don't set it.
+    pub(crate) fn query_params(&self, args: &Value) -> Result<QueryParams, McpError> {
+        let mut params = build_query_params(args)?;
+        if params.dsl.is_none() && params.infer_glob.is_none() && self.infer_glob {
+            params.infer_glob = Some(true);
+        }
+        Ok(params)
+    }
+}
+
+/// The `repo_id` argument: a service shard to target directly, bypassing
+/// path resolution. Can't be combined with `path`.
+fn repo_id_arg(args: &Value) -> Result<Option<&str>, McpError> {
+    let repo_id = args.get("repo_id").and_then(|v| v.as_str());
+    if repo_id.is_some() && args.get("path").is_some() {
+        return Err(McpError::InvalidParams(
+            "'path' and 'repo_id' are mutually exclusive: pass 'path' for a local checkout or 'repo_id' for a service repo".to_string(),
+        ));
+    }
+    Ok(repo_id)
 }
 
 /// Build [`QueryParams`] from MCP JSON-RPC arguments.
@@ -228,6 +618,7 @@ impl McpServer {
 /// - Individual keys (`pattern`, `symbol`, etc.) → `QueryParams`
 pub(crate) fn build_query_params(args: &Value) -> Result<QueryParams, McpError> {
     let mut params = QueryParams::new();
+    params.priors = priors(args)?;
 
     // DSL query takes precedence
     if let Some(query_str) = args.get("query").and_then(|v| v.as_str()) {
@@ -238,6 +629,7 @@ pub(crate) fn build_query_params(args: &Value) -> Result<QueryParams, McpError>
                 .map(|v| v as usize)
                 .unwrap_or(DEFAULT_MCP_QUERY_LIMIT),
         );
+        params.mode = query_mode(args)?;
         return Ok(params);
     }
 
@@ -261,6 +653,10 @@ pub(crate) fn build_query_params(args: &Value) -> Result<QueryParams, McpError>
         params.section = Some(section.to_string());
     }
 
+    if let Some(path) = args.get("section_path").and_then(|v| v.as_str()) {
+        params.section_path = Some(path.to_string());
+    }
+
     if let Some(parent) = args.get("parent").and_then(|v| v.as_str()) {
         params.parent = Some(parent.to_string());
     }
@@ -269,10 +665,39 @@ pub(crate) fn build_query_params(args: &Value) -> Result<QueryParams, McpError>
         params.kind = QueryParams::parse_kind(kind);
     }
 
+    if let Some(ref_types) = args.get("ref_types").and_then(|v| v.as_array()) {
+        let ref_types = ref_types
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(parse_ref_type)
+            .collect::<Result<Vec<_>, _>>()?;
+        if !ref_types.is_empty() {
+            params.ref_types = Some(ref_types);
+        }
+    }
+
     if let Some(glob) = args.get("glob").and_then(|v| v.as_str()) {
         params.glob = Some(glob.to_string());
     }
 
+    if let Some(fragment) = args.get("path_contains").and_then(|v| v.as_str()) {
+        params.path_contains = Some(fragment.to_string());
+    }
+
+    if let Some(window) = args.get("modified_within").and_then(|v| v.as_str()) {
+        params.modified_within = Some(window.to_string());
+    }
+
+    if let Some(excluded) = args.get("exclude_patterns").and_then(|v| v.as_array()) {
+        let excluded: Vec<String> = excluded
+            .iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect();
+        if !excluded.is_empty() {
+            params.exclude_patterns = Some(excluded);
+        }
+    }
+
     if let Some(match_mode) = args.get("match").and_then(|v| v.as_str()) {
         params.match_mode = MatchMode::parse(match_mode);
     }
@@ -283,16 +708,150 @@ pub(crate) fn build_query_params(args: &Value) -> Result<QueryParams, McpError>
             .map(|v| v as usize)
             .unwrap_or(DEFAULT_MCP_QUERY_LIMIT),
     );
+    params.local_limit = args
+        .get("local_limit")
+        .and_then(|v| v.as_u64())
+        .map(|v| v as usize);
+    params.service_limit = args
+        .get("service_limit")
+        .and_then(|v| v.as_u64())
+        .map(|v| v as usize);
+    params.merge_strategy = merge_strategy(args)?;
+    params.mode = query_mode(args)?;
+    params.group_references = args.get("group_references").and_then(|v| v.as_bool());
+    params.include_generated = args.get("include_generated").and_then(|v| v.as_bool());
+    params.explain = args.get("explain").and_then(|v| v.as_bool());
+    params.infer_glob = args.get("infer_glob").and_then(|v| v.as_bool());
 
     if !params.has_search_target() {
         return Err(McpError::InvalidParams(
-            "Must specify one of: pattern, patterns, symbol, section, parent, or query".to_string(),
+            "Must specify one of: pattern, patterns, symbol, section, section_path, parent, path_contains, or query".to_string(),
         ));
     }
 
     Ok(params)
 }
 
+/// `priors`, checked against the node type names so a typo is an error
+/// rather than a silently unweighted type.
+fn priors(args: &Value) -> Result<Option<BTreeMap<String, f64>>, McpError> {
+    let Some(value) = args.get("priors").filter(|v| !v.is_null()) else {
+        return Ok(None);
+    };
+    let object = value.as_object().ok_or_else(|| {
+        McpError::InvalidParams("priors must be an object of node type to multiplier".to_string())
+    })?;
+    let mut priors = BTreeMap::new();
+    for (name, multiplier) in object {
+        let multiplier = multiplier.as_f64().ok_or_else(|| {
+            McpError::InvalidParams(format!("Prior for '{}' must be a number", name))
+        })?;
+        priors.insert(name.clone(), multiplier);
+    }
+    let params = QueryParams {
+        priors: Some(priors),
+        ..QueryParams::default()
+    };
+    params
+        .node_type_priors()
+        .map_err(|e| McpError::InvalidParams(e.to_string()))?;
+    Ok(params.priors)
+}
+
+fn parse_ref_type(name: &str) -> Result<RefType, McpError> {
+    RefType::parse(name).ok_or_else(|| {
+        McpError::InvalidParams(format!(
+            "Unknown ref type '{}' (expected call, import or type)",
+            name
+        ))
+    })
+}
+
+/// The `ref_type` argument of `canopy_references`: one kind, or all when
+/// absent.
+fn ref_type_filter(args: &Value) -> Result<Vec<RefType>, McpError> {
+    match args.get("ref_type").filter(|v| !v.is_null()) {
+        None => Ok(Vec::new()),
+        Some(Value::String(name)) => Ok(vec![parse_ref_type(name)?]),
+        Some(other) => Err(McpError::InvalidParams(format!(
+            "ref_type must be one of call, import or type, got {}",
+            other
+        ))),
+    }
+}
+
+/// The `ranges` argument of `canopy_expand`: handle id to `"start-end"`.
+fn line_ranges(args: &Value) -> Result<HashMap<String, LineWindow>, McpError> {
+    let Some(value) = args.get("ranges").filter(|v| !v.is_null()) else {
+        return Ok(HashMap::new());
+    };
+    let map = value.as_object().ok_or_else(|| {
+        McpError::InvalidParams(
+            "'ranges' must be an object of handle_id to \"start-end\"".to_string(),
+        )
+    })?;
+    map.iter()
+        .map(|(id, range)| {
+            let window = range
+                .as_str()
+                .ok_or_else(|| {
+                    McpError::InvalidParams(format!(
+                        "Range for {} must be a \"start-end\" string, got {}",
+                        id, range
+                    ))
+                })?
+                .parse::<LineWindow>()
+                .map_err(|e| McpError::InvalidParams(format!("{}: {}", id, e)))?;
+            Ok((id.clone(), window))
+        })
+        .collect()
+}
+
+fn node_types(args: &Value) -> Result<Vec<NodeType>, McpError> {
+    let Some(value) = args.get("node_types").filter(|v| !v.is_null()) else {
+        return Ok(Vec::new());
+    };
+    let names = value.as_array().ok_or_else(|| {
+        McpError::InvalidParams("node_types must be an array of node type names".to_string())
+    })?;
+    names
+        .iter()
+        .map(|name| {
+            name.as_str().and_then(NodeType::parse).ok_or_else(|| {
+                let valid: Vec<&str> = NodeType::ALL.iter().map(|t| t.as_str()).collect();
+                McpError::InvalidParams(format!(
+                    "Unknown node type {} (expected one of: {})",
+                    name,
+                    valid.join(", ")
+                ))
+            })
+        })
+        .collect()
+}
+
+fn merge_strategy(args: &Value) -> Result<Option<MergeStrategy>, McpError> {
+    match args.get("merge_strategy").and_then(|v| v.as_str()) {
+        None => Ok(None),
+        Some(strategy) => MergeStrategy::parse(strategy).map(Some).ok_or_else(|| {
+            McpError::InvalidParams(format!(
+                "Unknown merge_strategy '{}' (expected rank_interleave, local_first or service_first)",
+                strategy
+            ))
+        }),
+    }
+}
+
+fn query_mode(args: &Value) -> Result<QueryMode, McpError> {
+    match args.get("mode").and_then(|v| v.as_str()) {
+        None | Some("handles") => Ok(QueryMode::Handles),
+        Some(mode @ ("count" | "exists")) => Ok(QueryMode::parse(mode)),
+        Some(other) => Err(McpError::InvalidParams(format!(
+            "Unknown mode '{}' (expected handles, count or exists)",
+            other
+        ))),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -314,6 +873,74 @@ mod tests {
         assert!(p.pattern.is_none());
     }
 
+    #[test]
+    fn build_query_params_priors() {
+        let args = json!({"pattern": "retry", "priors": {"section": 2.0, "function": 0.5}});
+        let p = build_query_params(&args).unwrap();
+        let priors = p.priors.unwrap();
+        assert_eq!(priors["section"], 2.0);
+        assert_eq!(priors["function"], 0.5);
+
+        // Also with DSL, which returns before the structured fields
+        let args = json!({"query": "(grep \"retry\")", "priors": {"section": 2.0}});
+        assert!(build_query_params(&args).unwrap().priors.is_some());
+
+        for bad in [
+            json!({"pattern": "retry", "priors": {"fucntion": 2.0}}),
+            json!({"pattern": "retry", "priors": {"section": -1}}),
+            json!({"pattern": "retry", "priors": {"section": "high"}}),
+            json!({"pattern": "retry", "priors": [2.0]}),
+        ] {
+            let err = build_query_params(&bad).err().unwrap();
+            assert!(matches!(err, McpError::InvalidParams(_)), "{bad}");
+        }
+    }
+
+    #[test]
+    fn node_types_parse_names() {
+        assert!(node_types(&json!({})).unwrap().is_empty());
+        assert_eq!(
+            node_types(&json!({"node_types": ["struct", "section"]})).unwrap(),
+            [NodeType::Struct, NodeType::Section]
+        );
+        for bad in [
+            json!({"node_types": ["structs"]}),
+            json!({"node_types": "struct"}),
+        ] {
+            let err = node_types(&bad).err().unwrap();
+            assert!(matches!(err, McpError::InvalidParams(_)), "{bad}");
+        }
+    }
+
+    #[test]
+    fn ref_type_filter_takes_one_kind() {
+        assert!(ref_type_filter(&json!({})).unwrap().is_empty());
+        assert_eq!(
+            ref_type_filter(&json!({"ref_type": "type"})).unwrap(),
+            [RefType::TypeRef]
+        );
+        for bad in [json!({"ref_type": "calls"}), json!({"ref_type": ["call"]})] {
+            let err = ref_type_filter(&bad).err().unwrap();
+            assert!(matches!(err, McpError::InvalidParams(_)), "{bad}");
+        }
+    }
+
+    #[test]
+    fn line_ranges_key_windows_by_handle() {
+        assert!(line_ranges(&json!({})).unwrap().is_empty());
+        let ranges = line_ranges(&json!({"ranges": {"h1a2b": "10-40", "h3c4d": "7"}})).unwrap();
+        assert_eq!(ranges["h1a2b"], LineWindow { start: 10, end: 40 });
+        assert_eq!(ranges["h3c4d"], LineWindow { start: 7, end: 7 });
+        for bad in [
+            json!({"ranges": ["10-40"]}),
+            json!({"ranges": {"h1a2b": [10, 40]}}),
+            json!({"ranges": {"h1a2b": "40-10"}}),
+        ] {
+            let err = line_ranges(&bad).err().unwrap();
+            assert!(matches!(err, McpError::InvalidParams(_)), "{bad}");
+        }
+    }
+
     #[test]
     fn build_query_params_symbol() {
         let args = json!({"symbol": "Config"});
@@ -321,11 +948,53 @@ mod tests {
         assert_eq!(p.symbol.as_deref(), Some("Config"));
     }
 
+    #[test]
+    fn repo_id_excludes_path() {
+        assert_eq!(repo_id_arg(&json!({"repo_id": "r1"})).unwrap(), Some("r1"));
+        assert_eq!(repo_id_arg(&json!({"path": "/repo"})).unwrap(), None);
+        let err = repo_id_arg(&json!({"repo_id": "r1", "path": "/repo"})).unwrap_err();
+        assert!(matches!(err, McpError::InvalidParams(m) if m.contains("mutually exclusive")));
+    }
+
+    #[test]
+    fn build_query_params_mode() {
+        let p = build_query_params(&json!({"symbol": "Config", "mode": "count"})).unwrap();
+        assert_eq!(p.mode, QueryMode::Count);
+        let p = build_query_params(&json!({"query": "(grep \"x\")", "mode": "exists"})).unwrap();
+        assert_eq!(p.mode, QueryMode::Exists);
+        assert!(build_query_params(&json!({"symbol": "Config"}))
+            .unwrap()
+            .mode
+            .is_handles());
+        assert!(build_query_params(&json!({"symbol": "Config", "mode": "all"})).is_err());
+    }
+
+    #[test]
+    fn query_params_take_the_server_infer_glob_default() {
+        let mut server = McpServer::with_service_url(None, None, None);
+        let args = json!({"pattern": "websocket"});
+        assert_eq!(server.query_params(&args).unwrap().infer_glob, None);
+
+        server.infer_glob = true;
+        assert_eq!(server.query_params(&args).unwrap().infer_glob, Some(true));
+        let opted_out = json!({"pattern": "websocket", "infer_glob": false});
+        assert_eq!(
+            server.query_params(&opted_out).unwrap().infer_glob,
+            Some(false)
+        );
+        let dsl = json!({"query": "(grep \"websocket\")"});
+        assert_eq!(server.query_params(&dsl).unwrap().infer_glob, None);
+    }
+
     #[test]
     fn build_query_params_section() {
         let args = json!({"section": "imports"});
         let p = build_query_params(&args).unwrap();
         assert_eq!(p.section.as_deref(), Some("imports"));
+
+        let args = json!({"section_path": "auth > configuration"});
+        let p = build_query_params(&args).unwrap();
+        assert_eq!(p.section_path.as_deref(), Some("auth > configuration"));
     }
 
     #[test]
@@ -333,13 +1002,26 @@ mod tests {
         let args = json!({
             "symbol": "Config",
             "glob": "src/**/*.rs",
+            "path_contains": "session",
+            "modified_within": "7d",
             "kind": "function",
-            "limit": 5
+            "limit": 5,
+            "include_generated": true,
+            "explain": true,
+            "exclude_patterns": ["test", 7, "mock"]
         });
         let p = build_query_params(&args).unwrap();
         assert_eq!(p.symbol.as_deref(), Some("Config"));
         assert_eq!(p.glob.as_deref(), Some("src/**/*.rs"));
+        assert_eq!(p.path_contains.as_deref(), Some("session"));
+        assert_eq!(p.modified_within.as_deref(), Some("7d"));
         assert_eq!(p.limit, Some(5));
+        assert_eq!(p.include_generated, Some(true));
+        assert_eq!(p.explain, Some(true));
+        assert_eq!(
+            p.exclude_patterns,
+            Some(vec!["test".to_string(), "mock".to_string()])
+        );
     }
 
     #[test]
@@ -361,6 +1043,22 @@ mod tests {
         assert_eq!(p.limit, Some(3));
     }
 
+    #[test]
+    fn build_query_params_per_source_limits() {
+        let args = json!({"pattern": "x", "limit": 20, "service_limit": 10});
+        let p = build_query_params(&args).unwrap();
+        assert_eq!(p.limit, Some(20));
+        assert_eq!(p.service_limit, Some(10));
+        assert!(p.local_limit.is_none());
+        assert!(p.merge_strategy.is_none());
+
+        let args = json!({"pattern": "x", "merge_strategy": "service_first"});
+        let p = build_query_params(&args).unwrap();
+        assert_eq!(p.merge_strategy, Some(MergeStrategy::ServiceFirst));
+        let args = json!({"pattern": "x", "merge_strategy": "interleave"});
+        assert!(build_query_params(&args).is_err());
+    }
+
     #[test]
     fn build_query_params_no_search_target_fails() {
         let args = json!({"limit": 10});
@@ -0,0 +1,354 @@
+//! API keys and the roles they carry.
+//!
+//! Keys come from `--api-key` (an implicit admin key named [`FLAG_KEY_NAME`])
+//! and from `--api-keys-file`, a TOML file of named keys:
+//!
+//! ```toml
+//! [[keys]]
+//! name = "ci"
+//! key = "..."
+//! role = "reindex"
+//! ```
+//!
+//! Every guarded route belongs to a [`RouteClass`], and [`require_role`]
+//! lets a request through when its key's [`Role`] covers the class. The
+//! resolved key name goes into the request extensions as [`KeyName`] and is
+//! counted in `/metrics` under `requests_by_key`. The file is read again on
+//! SIGHUP or `POST /admin/reload-keys`; a file that fails to load leaves the
+//! previous keys in place.
+
+use crate::error::AppError;
+use crate::state::SharedState;
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use canopy_core::ErrorEnvelope;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use tracing::{info, warn};
+
+/// Name of the key given by `--api-key` / `CANOPY_API_KEY`
+pub(crate) const FLAG_KEY_NAME: &str = "api-key";
+
+/// What a key may call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Role {
+    /// Every guarded route
+    Admin,
+    /// `/reindex` only, e.g. for CI
+    Reindex,
+    /// The query routes, none of the admin ones
+    Read,
+}
+
+impl Role {
+    fn permits(self, class: RouteClass) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Reindex => class == RouteClass::Reindex,
+            Role::Read => class == RouteClass::Query,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Reindex => "reindex",
+            Role::Read => "read",
+        }
+    }
+}
+
+/// The guarded routes, by the roles that may call them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RouteClass {
+    /// `/query`, `/expand` and the other read-only data routes
+    Query,
+    /// `/reindex`
+    Reindex,
+    /// Repo management and operational control
+    Admin,
+}
+
+impl RouteClass {
+    /// The roles allowed in, for the `forbidden` hint.
+    fn allowed_roles(self) -> &'static str {
+        match self {
+            RouteClass::Query => "read or admin",
+            RouteClass::Reindex => "reindex or admin",
+            RouteClass::Admin => "admin",
+        }
+    }
+}
+
+/// Name of the key a request authenticated with, in its extensions.
+#[derive(Debug, Clone)]
+pub(crate) struct KeyName(pub String);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ApiKey {
+    name: String,
+    key: String,
+    role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeysFile {
+    #[serde(default)]
+    keys: Vec<ApiKey>,
+}
+
+/// A configured key, without its secret.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct KeySummary {
+    pub name: String,
+    pub role: Role,
+}
+
+/// Response of `POST /admin/reload-keys`.
+#[derive(Debug, Serialize)]
+pub(crate) struct ReloadKeysResponse {
+    pub keys: Vec<KeySummary>,
+}
+
+/// The service's keys: the `--api-key` one, plus the keys file's as last loaded.
+pub(crate) struct KeyStore {
+    flag_key: Option<String>,
+    file: Option<PathBuf>,
+    keys: RwLock<Vec<ApiKey>>,
+}
+
+impl KeyStore {
+    /// Keys from `--api-key` and `--api-keys-file`, or `None` when neither
+    /// is given and the guarded routes are open.
+    pub(crate) fn load(
+        flag_key: Option<String>,
+        file: Option<PathBuf>,
+    ) -> Result<Option<Self>, String> {
+        if flag_key.is_none() && file.is_none() {
+            return Ok(None);
+        }
+        let store = Self {
+            flag_key,
+            file,
+            keys: RwLock::new(Vec::new()),
+        };
+        store.reload()?;
+        Ok(Some(store))
+    }
+
+    /// Read the keys file again. On error the previous keys stay in effect.
+    pub(crate) fn reload(&self) -> Result<Vec<KeySummary>, String> {
+        let mut keys: Vec<ApiKey> = self
+            .flag_key
+            .iter()
+            .map(|key| ApiKey {
+                name: FLAG_KEY_NAME.to_string(),
+                key: key.clone(),
+                role: Role::Admin,
+            })
+            .collect();
+        if let Some(file) = &self.file {
+            keys.extend(read_keys_file(file)?);
+        }
+        check_unique(&keys)?;
+        let summaries = summarize(&keys);
+        *self.keys.write().unwrap_or_else(|e| e.into_inner()) = keys;
+        Ok(summaries)
+    }
+
+    /// Whether a keys file is configured, so there is something to reload.
+    pub(crate) fn has_file(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Name and role of the key `provided` matches.
+    fn resolve(&self, provided: &str) -> Option<(String, Role)> {
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+        keys.iter()
+            .find(|k| k.key == provided)
+            .map(|k| (k.name.clone(), k.role))
+    }
+}
+
+fn read_keys_file(path: &Path) -> Result<Vec<ApiKey>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read keys file {}: {e}", path.display()))?;
+    let file: KeysFile =
+        toml::from_str(&text).map_err(|e| format!("invalid keys file {}: {e}", path.display()))?;
+    Ok(file.keys)
+}
+
+/// Names and secrets must be non-empty and distinct, or a request couldn't
+/// be attributed to one key.
+fn check_unique(keys: &[ApiKey]) -> Result<(), String> {
+    let mut names = HashSet::new();
+    let mut secrets = HashSet::new();
+    for key in keys {
+        if key.name.trim().is_empty() || key.key.is_empty() {
+            return Err("every key needs a non-empty name and key".to_string());
+        }
+        if !names.insert(key.name.as_str()) {
+            return Err(format!("key name {:?} is used twice", key.name));
+        }
+        if !secrets.insert(key.key.as_str()) {
+            return Err(format!("key {:?} reuses another key's secret", key.name));
+        }
+    }
+    Ok(())
+}
+
+fn summarize(keys: &[ApiKey]) -> Vec<KeySummary> {
+    keys.iter()
+        .map(|k| KeySummary {
+            name: k.name.clone(),
+            role: k.role,
+        })
+        .collect()
+}
+
+/// Middleware letting requests through whose X-Api-Key names a key whose
+/// role covers `class`: 401 `unauthorized` for a missing or unknown key,
+/// 403 `forbidden` for a known key without the role.
+pub(crate) async fn require_role(
+    state: SharedState,
+    class: RouteClass,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let Some(keys) = state.keys() else {
+        return next.run(req).await;
+    };
+    let provided = req.headers().get("x-api-key").and_then(|v| v.to_str().ok());
+    let Some((name, role)) = provided.and_then(|key| keys.resolve(key)) else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorEnvelope::new(
+                "unauthorized",
+                "Missing or invalid API key",
+                "Set the X-Api-Key header to the configured CANOPY_API_KEY or a key from the keys file",
+            )),
+        )
+            .into_response();
+    };
+    if !role.permits(class) {
+        warn!(key = %name, path = %req.uri().path(), "forbidden");
+        return (
+            StatusCode::FORBIDDEN,
+            Json(ErrorEnvelope::new(
+                "forbidden",
+                format!(
+                    "Key {name:?} has role {}, which may not call {}",
+                    role.name(),
+                    req.uri().path()
+                ),
+                format!("Use a key with role {}", class.allowed_roles()),
+            )),
+        )
+            .into_response();
+    }
+
+    if let Ok(mut analytics) = state.metrics.analytics.lock() {
+        *analytics.requests_by_key.entry(name.clone()).or_insert(0) += 1;
+    }
+    req.extensions_mut().insert(KeyName(name));
+    next.run(req).await
+}
+
+/// `POST /admin/reload-keys`: read the keys file again.
+pub(crate) async fn reload_keys(
+    State(state): State<SharedState>,
+) -> Result<Json<ReloadKeysResponse>, AppError> {
+    let keys = state
+        .keys()
+        .filter(|keys| keys.has_file())
+        .ok_or_else(AppError::keys_file_not_configured)?;
+    let keys = keys.reload().map_err(AppError::invalid_keys_file)?;
+    info!(keys = keys.len(), "reloaded API keys");
+    Ok(Json(ReloadKeysResponse { keys }))
+}
+
+/// Reload the keys file on every SIGHUP.
+#[cfg(unix)]
+pub(crate) async fn reload_on_hangup(state: SharedState) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!(error = %e, "failed to listen for SIGHUP; keys reload only via /admin/reload-keys");
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        let Some(keys) = state.keys() else { return };
+        match keys.reload() {
+            Ok(keys) => info!(keys = keys.len(), "reloaded API keys on SIGHUP"),
+            Err(e) => warn!(error = %e, "keys reload failed; keeping the previous keys"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_keys(dir: &Path, body: &str) -> PathBuf {
+        let path = dir.join("keys.toml");
+        std::fs::write(&path, body).unwrap();
+        path
+    }
+
+    #[test]
+    fn roles_cover_their_route_classes() {
+        use RouteClass::*;
+        let allowed = |role: Role| {
+            [Query, Reindex, Admin]
+                .into_iter()
+                .filter(|class| role.permits(*class))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(allowed(Role::Admin), [Query, Reindex, Admin]);
+        assert_eq!(allowed(Role::Reindex), [Reindex]);
+        assert_eq!(allowed(Role::Read), [Query]);
+    }
+
+    #[test]
+    fn flag_key_is_admin_and_bad_files_keep_old_keys() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = write_keys(
+            dir.path(),
+            "[[keys]]\nname = \"ci\"\nkey = \"ci-secret\"\nrole = \"reindex\"\n",
+        );
+        let store = KeyStore::load(Some("flag".to_string()), Some(path.clone()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            store.resolve("flag"),
+            Some((FLAG_KEY_NAME.to_string(), Role::Admin))
+        );
+        assert_eq!(
+            store.resolve("ci-secret"),
+            Some(("ci".to_string(), Role::Reindex))
+        );
+        assert_eq!(store.resolve("nope"), None);
+
+        write_keys(
+            dir.path(),
+            "[[keys]]\nname = \"ci\"\nkey = \"flag\"\nrole = \"read\"\n",
+        );
+        let err = store.reload().unwrap_err();
+        assert!(err.contains("reuses another key's secret"), "{err}");
+        assert!(store.resolve("ci-secret").is_some());
+
+        write_keys(dir.path(), "[[keys]]\nname = \"ci\"\nrole = \"root\"\n");
+        assert!(store.reload().unwrap_err().contains("invalid keys file"));
+        assert!(KeyStore::load(None, None).unwrap().is_none());
+    }
+}
@@ -0,0 +1,141 @@
+//! Checkouts the service owns: repos added by `git_url` rather than a path.
+//!
+//! Each one is a shallow (depth 1) clone of a single branch under
+//! `<state dir>/checkouts/<repo id>`, sparse when `sparse_paths` is given.
+//! Reindexing such a repo first fetches the branch and hard-resets the
+//! checkout to it. Git failures come back as `CanopyError::Git`, which the
+//! reindex task reports as an `error` shard status.
+
+use canopy_core::{CanopyError, RepoIndex};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A repo the service clones and updates itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagedCheckout {
+    pub git_url: String,
+    /// Followed branch; the remote's default when unset
+    pub branch: Option<String>,
+    /// Directories to check out; everything when empty
+    pub sparse_paths: Vec<String>,
+    /// Where the clone lives (the shard's `repo_root`)
+    pub root: PathBuf,
+}
+
+impl ManagedCheckout {
+    /// Bring the checkout to the tip of its branch, cloning it if it isn't
+    /// there yet (first add, or a clone that failed earlier), and make sure
+    /// it has a canopy index to build into.
+    pub fn sync(&self) -> Result<(), CanopyError> {
+        if self.root.join(".git").exists() {
+            self.update()?;
+        } else {
+            self.clone_fresh()?;
+        }
+        if !self.root.join(".canopy").exists() {
+            RepoIndex::init(&self.root)?;
+        }
+        Ok(())
+    }
+
+    fn clone_fresh(&self) -> Result<(), CanopyError> {
+        // Leftovers of an interrupted clone would make git refuse the directory
+        if self.root.exists() {
+            std::fs::remove_dir_all(&self.root)?;
+        }
+        let parent = self.root.parent().unwrap_or(Path::new("."));
+        std::fs::create_dir_all(parent)?;
+        let mut args = vec!["clone", "--depth", "1", "--single-branch"];
+        if let Some(branch) = &self.branch {
+            args.extend(["--branch", branch]);
+        }
+        if !self.sparse_paths.is_empty() {
+            args.extend(["--sparse", "--filter=blob:none"]);
+        }
+        args.extend(["--end-of-options", &self.git_url]);
+        let root = self.root.to_string_lossy();
+        args.push(&root);
+        git(parent, &args)?;
+
+        if !self.sparse_paths.is_empty() {
+            let mut args = vec!["sparse-checkout", "set", "--end-of-options"];
+            args.extend(self.sparse_paths.iter().map(String::as_str));
+            git(&self.root, &args)?;
+        }
+        Ok(())
+    }
+
+    fn update(&self) -> Result<(), CanopyError> {
+        let branch = match &self.branch {
+            Some(branch) => branch.clone(),
+            None => git(&self.root, &["rev-parse", "--abbrev-ref", "HEAD"])?,
+        };
+        let refspec = format!("+refs/heads/{branch}:refs/remotes/origin/{branch}");
+        git(
+            &self.root,
+            &[
+                "fetch",
+                "--depth",
+                "1",
+                "origin",
+                "--end-of-options",
+                &refspec,
+            ],
+        )?;
+        git(
+            &self.root,
+            &["reset", "--hard", &format!("refs/remotes/origin/{branch}")],
+        )?;
+        Ok(())
+    }
+}
+
+/// Name for a repo cloned from `git_url`: its last path segment without `.git`.
+pub fn repo_name_from_url(git_url: &str) -> String {
+    let last = git_url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or_default();
+    let name = last.strip_suffix(".git").unwrap_or(last);
+    if name.is_empty() {
+        "unnamed".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Run git in `dir` without prompting for credentials, returning trimmed
+/// stdout, or its stderr as the error.
+fn git(dir: &Path, args: &[&str]) -> Result<String, CanopyError> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .map_err(|e| CanopyError::Git(format!("failed to run git: {e}")))?;
+    if !output.status.success() {
+        return Err(CanopyError::Git(format!(
+            "git {} failed: {}",
+            args.first().copied().unwrap_or_default(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repo_names_come_from_the_last_url_segment() {
+        assert_eq!(
+            repo_name_from_url("https://github.com/acme/widgets.git"),
+            "widgets"
+        );
+        assert_eq!(repo_name_from_url("git@github.com:acme/widgets"), "widgets");
+        assert_eq!(repo_name_from_url("file:///srv/mono.git/"), "mono");
+        assert_eq!(repo_name_from_url("git@host:"), "unnamed");
+    }
+}
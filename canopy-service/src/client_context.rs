@@ -0,0 +1,159 @@
+//! Client context headers: which agent session and tool sent a request.
+//!
+//! Request logs carry the raw session id. Metrics never do: they count
+//! requests per client name, for the first [`MAX_CLIENT_LABELS`] names seen
+//! (later ones count as [`OTHER_CLIENTS`]), and per session bucket, the
+//! session id hashed into one of [`SESSION_BUCKETS`] labels. However many
+//! sessions share the service, the metrics stay the same size.
+
+use crate::state::{QueryAnalytics, SharedState};
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::request::Parts;
+use axum::http::HeaderMap;
+use axum::middleware::Next;
+use axum::response::Response;
+use canopy_core::protocol::{ClientContext, CLIENT_HEADER, SESSION_HEADER};
+use std::convert::Infallible;
+
+/// Buckets session ids hash into for metrics
+pub(crate) const SESSION_BUCKETS: u64 = 16;
+/// Distinct client names metrics count separately
+pub(crate) const MAX_CLIENT_LABELS: usize = 32;
+/// Label of requests from client names past [`MAX_CLIENT_LABELS`]
+pub(crate) const OTHER_CLIENTS: &str = "other";
+/// Label of requests without a client name or session id
+pub(crate) const UNLABELED: &str = "none";
+
+/// The request's [`ClientContext`], from its X-Canopy-Session and
+/// X-Canopy-Client headers; empty when they are absent.
+pub(crate) struct RequestClient(pub ClientContext);
+
+impl<S: Send + Sync> FromRequestParts<S> for RequestClient {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(client_context(&parts.headers)))
+    }
+}
+
+pub(crate) fn client_context(headers: &HeaderMap) -> ClientContext {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    ClientContext::new(header(CLIENT_HEADER), header(SESSION_HEADER))
+}
+
+/// Metrics label of `session_id`'s bucket, e.g. "s07".
+pub(crate) fn session_bucket(session_id: Option<&str>) -> String {
+    let Some(session_id) = session_id else {
+        return UNLABELED.to_string();
+    };
+    // FNV-1a: stable across processes, unlike the std hasher
+    let hash = session_id
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("s{:02}", hash % SESSION_BUCKETS)
+}
+
+/// Count a request from `client` under its client and session labels.
+pub(crate) fn record_request(analytics: &mut QueryAnalytics, client: &ClientContext) {
+    let name = client.client_name.as_deref().unwrap_or(UNLABELED);
+    let label = if analytics.requests_by_client.contains_key(name)
+        || analytics.requests_by_client.len() < MAX_CLIENT_LABELS
+    {
+        name
+    } else {
+        OTHER_CLIENTS
+    };
+    *analytics
+        .requests_by_client
+        .entry(label.to_string())
+        .or_insert(0) += 1;
+    *analytics
+        .requests_by_session_bucket
+        .entry(session_bucket(client.session_id.as_deref()))
+        .or_insert(0) += 1;
+}
+
+/// Middleware counting each request under its client labels.
+pub(crate) async fn track_client(
+    State(state): State<SharedState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let client = client_context(req.headers());
+    if let Ok(mut analytics) = state.metrics.analytics.lock() {
+        record_request(&mut analytics, &client);
+    }
+    next.run(req).await
+}
+
+/// `client` for request log lines: ` session=... client=...`, or nothing.
+pub(crate) fn log_fields(client: &ClientContext) -> String {
+    let mut fields = String::new();
+    if let Some(session_id) = &client.session_id {
+        fields.push_str(&format!(" session={session_id}"));
+    }
+    if let Some(client_name) = &client.client_name {
+        fields.push_str(&format!(" client={client_name:?}"));
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(name: Option<&str>, session: Option<&str>) -> ClientContext {
+        ClientContext::new(name, session)
+    }
+
+    #[test]
+    fn session_buckets_are_stable_and_bounded() {
+        assert_eq!(session_bucket(Some("abc")), session_bucket(Some("abc")));
+        assert_eq!(session_bucket(None), UNLABELED);
+        let mut analytics = QueryAnalytics::new();
+        for i in 0..1000 {
+            record_request(&mut analytics, &client(None, Some(&format!("session-{i}"))));
+        }
+        assert!(analytics.requests_by_session_bucket.len() as u64 <= SESSION_BUCKETS);
+        assert!(analytics.requests_by_session_bucket.len() > 1);
+        assert_eq!(
+            analytics.requests_by_session_bucket.values().sum::<u64>(),
+            1000
+        );
+        assert!(analytics
+            .requests_by_session_bucket
+            .keys()
+            .all(|label| !label.contains("session")));
+    }
+
+    #[test]
+    fn client_labels_are_capped() {
+        let mut analytics = QueryAnalytics::new();
+        for i in 0..100 {
+            let name = format!("agent-{i}");
+            record_request(&mut analytics, &client(Some(&name), None));
+        }
+        // Names seen before the cap keep counting under their own label
+        record_request(&mut analytics, &client(Some("agent-0"), None));
+        assert_eq!(analytics.requests_by_client.len(), MAX_CLIENT_LABELS + 1);
+        assert_eq!(analytics.requests_by_client["agent-0"], 2);
+        assert_eq!(
+            analytics.requests_by_client[OTHER_CLIENTS],
+            100 - MAX_CLIENT_LABELS as u64
+        );
+    }
+
+    #[test]
+    fn context_is_read_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(SESSION_HEADER, "s-1".parse().unwrap());
+        headers.insert(CLIENT_HEADER, " editor-agent ".parse().unwrap());
+        let context = client_context(&headers);
+        assert_eq!(context.session_id.as_deref(), Some("s-1"));
+        assert_eq!(context.client_name.as_deref(), Some("editor-agent"));
+        assert_eq!(log_fields(&context), " session=s-1 client=\"editor-agent\"");
+        assert!(client_context(&HeaderMap::new()).is_empty());
+    }
+}
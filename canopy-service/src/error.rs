@@ -1,6 +1,6 @@
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
-pub use canopy_core::ErrorEnvelope;
+pub use canopy_core::{ErrorEnvelope, FieldError};
 
 #[derive(Debug)]
 pub struct AppError {
@@ -31,6 +31,90 @@ impl AppError {
         }
     }
 
+    pub fn file_not_found(path: &std::path::Path) -> Self {
+        Self {
+            status: StatusCode::NOT_FOUND,
+            body: ErrorEnvelope::new(
+                "file_not_found",
+                format!("File {} is not indexed", path.display()),
+                "Pass a repo-relative path of an indexed file",
+            ),
+        }
+    }
+
+    pub fn schema_not_found(name: &str) -> Self {
+        Self {
+            status: StatusCode::NOT_FOUND,
+            body: ErrorEnvelope::new(
+                "schema_not_found",
+                format!("No output schema named {}", name),
+                format!("Use one of: {}", canopy_core::SCHEMA_TYPES.join(", ")),
+            ),
+        }
+    }
+
+    pub fn invalid_repo(hint: &str) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            body: ErrorEnvelope::new("invalid_repo", "Not a git repository", hint),
+        }
+    }
+
+    pub fn managed_checkouts_disabled() -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            body: ErrorEnvelope::new(
+                "managed_checkouts_disabled",
+                "This service does not clone repos",
+                "Start canopy-service with --api-key and --state-dir to add repos by git_url",
+            ),
+        }
+    }
+
+    pub fn policies_need_api_key() -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            body: ErrorEnvelope::new(
+                "policies_disabled",
+                "Path policies need admin routes behind an API key",
+                "Start canopy-service with --api-key to set repo path policies",
+            ),
+        }
+    }
+
+    pub fn keys_file_not_configured() -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            body: ErrorEnvelope::new(
+                "keys_file_not_configured",
+                "This service has no API keys file to reload",
+                "Start canopy-service with --api-keys-file to manage keys in a file",
+            ),
+        }
+    }
+
+    pub fn invalid_keys_file(message: String) -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            body: ErrorEnvelope::new(
+                "invalid_keys_file",
+                message,
+                "Fix the keys file and reload again; the previous keys stay in effect",
+            ),
+        }
+    }
+
+    pub fn invalid_policy(message: String) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            body: ErrorEnvelope::new(
+                "invalid_policy",
+                message,
+                "Policy patterns are globs over repo-relative paths, like secrets/ or customers/*/exports",
+            ),
+        }
+    }
+
     pub fn stale(expected: u64, found: u64) -> Self {
         Self {
             status: StatusCode::CONFLICT,
@@ -38,6 +122,25 @@ impl AppError {
         }
     }
 
+    /// A handle's generation is neither live nor retained; same code as
+    /// [`stale`](Self::stale), with the generations that would still expand.
+    pub fn generation_not_retained(current: u64, found: u64, retained: &[u64]) -> Self {
+        let mut body = ErrorEnvelope::stale_generation(current, found);
+        body.hint = if retained.is_empty() {
+            format!(
+                "Generation {found} is no longer retained; query again for handles of generation {current}"
+            )
+        } else {
+            format!(
+                "Generation {found} is no longer retained (still expanding: {retained:?} and {current}); query again for fresh handles"
+            )
+        };
+        Self {
+            status: StatusCode::CONFLICT,
+            body,
+        }
+    }
+
     pub fn repo_not_ready(repo: &str, status: &str) -> Self {
         Self {
             status: StatusCode::SERVICE_UNAVAILABLE,
@@ -49,6 +152,30 @@ impl AppError {
         }
     }
 
+    pub fn path_outside_repo(path: &std::path::Path) -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            body: ErrorEnvelope::new(
+                "path_outside_repo",
+                format!("Indexed path {} resolves outside the repo", path.display()),
+                "The index may be corrupted; reindex the repo",
+            ),
+        }
+    }
+
+    pub fn invalid_request(errors: Vec<FieldError>) -> Self {
+        let mut body = ErrorEnvelope::new(
+            "invalid_request",
+            format!("Request body has {} invalid field(s)", errors.len()),
+            "Fix the listed fields, or send X-Canopy-Lenient: 1 to skip strict checks",
+        );
+        body.errors = errors;
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            body,
+        }
+    }
+
     pub fn internal(msg: impl std::fmt::Display) -> Self {
         Self {
             status: StatusCode::INTERNAL_SERVER_ERROR,
@@ -67,9 +194,11 @@ impl From<canopy_core::CanopyError> for AppError {
     fn from(err: canopy_core::CanopyError) -> Self {
         match &err {
             canopy_core::CanopyError::HandleNotFound(id) => AppError::handle_not_found(id),
+            canopy_core::CanopyError::FileNotFound(path) => AppError::file_not_found(path),
             canopy_core::CanopyError::StaleGeneration { expected, found } => {
                 AppError::stale(*expected, *found)
             }
+            canopy_core::CanopyError::PathOutsideRepo(path) => AppError::path_outside_repo(path),
             _ => AppError::internal(err),
         }
     }
@@ -95,6 +224,16 @@ mod tests {
         assert!(err.body.message.contains("h_abc123"));
     }
 
+    #[test]
+    fn file_not_found_is_a_404_naming_the_path() {
+        let err = AppError::from(canopy_core::CanopyError::FileNotFound(
+            std::path::PathBuf::from("src/gone.rs"),
+        ));
+        assert_eq!(err.status, StatusCode::NOT_FOUND);
+        assert_eq!(err.body.code, "file_not_found");
+        assert!(err.body.message.contains("src/gone.rs"));
+    }
+
     #[test]
     fn stale_has_conflict_status() {
         let err = AppError::stale(5, 3);
@@ -104,6 +243,16 @@ mod tests {
         assert!(err.body.message.contains("3"));
     }
 
+    #[test]
+    fn generation_not_retained_lists_what_still_expands() {
+        let err = AppError::generation_not_retained(5, 2, &[3, 4]);
+        assert_eq!(err.status, StatusCode::CONFLICT);
+        assert_eq!(err.body.code, "stale_generation");
+        assert!(err.body.hint.contains("[3, 4]"), "{}", err.body.hint);
+        let err = AppError::generation_not_retained(5, 2, &[]);
+        assert!(err.body.hint.contains("generation 5"), "{}", err.body.hint);
+    }
+
     #[test]
     fn internal_has_500_status() {
         let err = AppError::internal("something broke");
@@ -132,6 +281,15 @@ mod tests {
         assert_eq!(app_err.body.code, "stale_generation");
     }
 
+    #[test]
+    fn from_canopy_path_outside_repo() {
+        let canopy_err =
+            canopy_core::CanopyError::PathOutsideRepo(std::path::PathBuf::from("/repo/../etc"));
+        let app_err = AppError::from(canopy_err);
+        assert_eq!(app_err.status, StatusCode::FORBIDDEN);
+        assert_eq!(app_err.body.code, "path_outside_repo");
+    }
+
     #[test]
     fn from_canopy_other_error_maps_to_internal() {
         let canopy_err = canopy_core::CanopyError::InvalidHandle("bad".to_string());
@@ -5,7 +5,7 @@ pub mod symbol_extraction;
 
 pub use planning::run_evidence_plan;
 
-use canopy_core::QueryParams;
+use canopy_core::{QueryMode, QueryParams};
 
 pub const SERVICE_DEFAULT_QUERY_LIMIT: usize = 16;
 pub const SERVICE_MAX_QUERY_LIMIT: usize = 50;
@@ -19,6 +19,7 @@ pub fn normalize_query_params(mut params: QueryParams, force_preview_only: bool)
     params.limit = Some(limit);
     if force_preview_only {
         params.expand_budget = Some(0);
+        params.mode = QueryMode::Handles;
     }
     params
 }
@@ -96,6 +97,7 @@ mod tests {
 
     fn make_test_pack(suggestions: Vec<&str>) -> EvidencePack {
         EvidencePack {
+            schema_version: Default::default(),
             query_text: String::new(),
             total_matches: 0,
             truncated: false,
@@ -113,7 +115,15 @@ mod tests {
                 max_additional_queries: 0,
                 rationale: String::new(),
                 next_step: String::new(),
+                term_coverage: 0.0,
+                definition_present: false,
+                result_density: 0.0,
             },
+            suggestions: vec![],
+            overflow: Default::default(),
+            overflow_samples: vec![],
+            suppressed_by_policy: 0,
+            excluded_matches: 0,
         }
     }
 }
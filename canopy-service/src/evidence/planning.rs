@@ -1,8 +1,11 @@
 //! Iterative evidence planning loop.
 
 use super::symbol_extraction::extract_symbol_candidates_from_handles;
-use canopy_core::{build_evidence_pack, EvidenceConfidence, Handle, QueryParams, QueryResult};
-use std::collections::{HashSet, VecDeque};
+use canopy_core::{
+    build_evidence_pack_with_priors, CalibrationConfig, EvidenceConfidence, Handle, QueryMode,
+    QueryParams, QueryResult, SchemaVersion, TokenSavings,
+};
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::future::Future;
 use std::pin::Pin;
 
@@ -17,6 +20,8 @@ pub fn symbol_followup_params(base: &QueryParams, symbol: String) -> QueryParams
     params.kind = QueryKind::Definition;
     params.limit = Some(base.limit.unwrap_or(16).min(12));
     params.glob = base.glob.clone();
+    params.path_contains = base.path_contains.clone();
+    params.infer_glob = base.infer_glob;
     params
 }
 
@@ -41,6 +46,7 @@ pub async fn run_evidence_plan<E: Send>(
     plan_override: Option<bool>,
     max_handles: usize,
     max_per_file: usize,
+    calibration: &CalibrationConfig,
     mut execute_query: impl FnMut(QueryParams) -> Pin<Box<dyn Future<Output = Result<(QueryResult, bool), E>> + Send>>
         + Send,
 ) -> Result<EvidencePlanResult, E> {
@@ -54,12 +60,21 @@ pub async fn run_evidence_plan<E: Send>(
     let mut aggregate_handles: Vec<Handle> = Vec::new();
     let mut aggregate_tokens = 0usize;
     let mut aggregate_truncated = false;
+    let mut truncated_scan = false;
+    let mut inferred_glob = None;
     let mut total_matches = 0usize;
+    let mut suppressed_by_policy = 0usize;
+    let mut suppressed_generated = 0usize;
+    let mut excluded_matches = 0usize;
+    let mut redactions = 0usize;
+    let mut included_generated = false;
     let mut expanded_ids: Vec<String> = Vec::new();
     let mut expanded_tokens = 0usize;
     let mut cache_hits = 0usize;
     let mut cache_misses = 0usize;
     let mut plan_steps = 0usize;
+    let mut suggestions = Vec::new();
+    let mut file_tokens: BTreeMap<String, usize> = BTreeMap::new();
 
     while let Some(current_params) = pending.pop_front() {
         let max_steps = if planning_enabled {
@@ -67,7 +82,11 @@ pub async fn run_evidence_plan<E: Send>(
         } else {
             2
         };
-        if plan_steps >= max_steps {
+        // Generated files only make it into a pack when nothing else matched,
+        // so that retry may go past the step cap
+        let generated_retry = current_params.include_generated == Some(true)
+            && seed_params.include_generated.is_none();
+        if plan_steps >= max_steps && !generated_retry {
             break;
         }
         let key = serde_json::to_string(&current_params).unwrap_or_default();
@@ -84,7 +103,22 @@ pub async fn run_evidence_plan<E: Send>(
         }
 
         total_matches += result.total_matches;
+        suppressed_by_policy += result.suppressed_by_policy;
+        suppressed_generated += result.suppressed_generated;
+        excluded_matches += result.excluded_matches;
+        redactions += result.redactions;
+        included_generated |= generated_retry;
         aggregate_truncated |= result.truncated;
+        truncated_scan |= result.truncated_scan;
+        if inferred_glob.is_none() {
+            inferred_glob = result.inferred_glob.clone();
+        }
+        if suggestions.is_empty() {
+            suggestions = result.suggestions;
+        }
+        if let Some(savings) = result.savings {
+            file_tokens.extend(savings.files);
+        }
 
         let mut new_handle_count = 0usize;
         for handle in result.handles {
@@ -100,9 +134,14 @@ pub async fn run_evidence_plan<E: Send>(
         }
 
         let provisional = QueryResult {
+            schema_version: SchemaVersion,
             handles: aggregate_handles.clone(),
             ref_handles: None,
+            ref_type_counts: None,
+            annotations: None,
+            commits: None,
             total_tokens: aggregate_tokens,
+            preview_tokens: aggregate_handles.iter().map(|h| h.preview_tokens).sum(),
             truncated: aggregate_truncated,
             total_matches,
             auto_expanded: false,
@@ -110,9 +149,36 @@ pub async fn run_evidence_plan<E: Send>(
             expanded_count: expanded_ids.len(),
             expanded_tokens,
             expanded_handle_ids: expanded_ids.clone(),
+            suppressed_service_handles: 0,
+            suppressed_by_policy,
+            suppressed_generated,
+            excluded_matches,
+            redactions,
+            suggestions: if aggregate_handles.is_empty() {
+                suggestions.clone()
+            } else {
+                Vec::new()
+            },
+            savings: None,
+            sources: None,
+            mode: QueryMode::Handles,
+            exists: None,
+            witness_path: None,
+            match_counts: None,
+            pattern_errors: Vec::new(),
+            explain: None,
+            compat_mode: false,
+            truncated_scan,
+            inferred_glob: inferred_glob.clone(),
         };
-        let provisional_pack =
-            build_evidence_pack(&provisional, &query_text, max_handles, max_per_file);
+        let provisional_pack = build_evidence_pack_with_priors(
+            &provisional,
+            &query_text,
+            max_handles,
+            max_per_file,
+            None,
+            calibration,
+        );
 
         if !auto_plan_decided {
             planning_enabled = provisional_pack.guidance.confidence_band == EvidenceConfidence::Low
@@ -131,6 +197,13 @@ pub async fn run_evidence_plan<E: Send>(
                 pending.push_back(fallback);
             }
         }
+        if pending.is_empty()
+            && aggregate_handles.is_empty()
+            && suppressed_generated > 0
+            && seed_params.include_generated.is_none()
+        {
+            pending.push_back(seed_params.clone().with_include_generated(true));
+        }
 
         if !planning_enabled {
             continue;
@@ -161,10 +234,19 @@ pub async fn run_evidence_plan<E: Send>(
 
     let auto_expanded =
         !aggregate_handles.is_empty() && expanded_ids.len() == aggregate_handles.len();
-    let result = QueryResult {
+    if !aggregate_handles.is_empty() {
+        suggestions.clear();
+    }
+    let preview_tokens = aggregate_handles.iter().map(|h| h.preview_tokens).sum();
+    let mut result = QueryResult {
+        schema_version: SchemaVersion,
         handles: aggregate_handles,
         ref_handles: None,
+        ref_type_counts: None,
+        annotations: None,
+        commits: None,
         total_tokens: aggregate_tokens,
+        preview_tokens,
         truncated: aggregate_truncated,
         total_matches,
         auto_expanded,
@@ -172,7 +254,31 @@ pub async fn run_evidence_plan<E: Send>(
         expanded_count: expanded_ids.len(),
         expanded_tokens,
         expanded_handle_ids: expanded_ids,
+        suppressed_service_handles: 0,
+        suppressed_by_policy,
+        suppressed_generated: if included_generated {
+            0
+        } else {
+            suppressed_generated
+        },
+        excluded_matches,
+        redactions,
+        suggestions,
+        savings: None,
+        sources: None,
+        mode: QueryMode::Handles,
+        exists: None,
+        witness_path: None,
+        match_counts: None,
+        pattern_errors: Vec::new(),
+        explain: None,
+        compat_mode: false,
+        truncated_scan,
+        inferred_glob,
     };
+    if !file_tokens.is_empty() {
+        result.savings = Some(TokenSavings::new(file_tokens, result.returned_tokens()));
+    }
 
     Ok(EvidencePlanResult {
         result,
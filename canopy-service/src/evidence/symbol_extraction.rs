@@ -77,7 +77,11 @@ pub fn extract_symbol_candidates_from_handles(
     }
 
     let mut ranked: Vec<(String, usize)> = scores.into_iter().collect();
-    ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| b.0.len().cmp(&a.0.len())));
+    ranked.sort_by(|a, b| {
+        b.1.cmp(&a.1)
+            .then_with(|| b.0.len().cmp(&a.0.len()))
+            .then_with(|| a.0.cmp(&b.0))
+    });
     ranked.into_iter().take(limit).map(|(sym, _)| sym).collect()
 }
 
@@ -96,10 +100,19 @@ mod tests {
             line_range: (1, 1),
             token_count: 5,
             preview: preview.to_string(),
+            preview_tokens: 0,
             content: None,
+            content_hash: None,
             source: HandleSource::Local,
             commit_sha: None,
             generation: None,
+            possibly_stale: false,
+            rerank_score: None,
+            score: None,
+            match_line: None,
+            match_count_in_node: None,
+            via: None,
+            result_class: None,
         }
     }
 
@@ -2,6 +2,7 @@
 
 use canopy_core::feedback::{ExpandEvent, FeedbackStore, QueryEvent, QueryHandle};
 use canopy_core::index::ExpandedHandleDetail;
+use canopy_core::protocol::ClientContext;
 use canopy_core::{QueryParams, QueryResult};
 use std::collections::HashMap;
 use tracing::warn;
@@ -13,6 +14,7 @@ pub fn try_record_feedback_query(
     feedback_store: Option<&std::sync::Arc<std::sync::Mutex<FeedbackStore>>>,
     params: &QueryParams,
     result: &QueryResult,
+    client: &ClientContext,
 ) -> Option<i64> {
     let feedback_store = feedback_store?;
     let Ok(store) = feedback_store.lock() else {
@@ -26,6 +28,10 @@ pub fn try_record_feedback_query(
         files_indexed: 0,
         handles_returned: result.handles.len(),
         total_tokens: result.total_tokens,
+        file_tokens: result.savings.as_ref().map_or(0, |s| s.file_tokens),
+        returned_tokens: result.savings.as_ref().map_or(0, |s| s.returned_tokens),
+        match_count: (!result.mode.is_handles()).then_some(result.total_matches),
+        client: client.clone(),
     };
 
     let query_event_id = match store.record_query_event(&event) {
@@ -53,6 +59,7 @@ pub fn try_record_feedback_query(
             node_type: handle.node_type,
             token_count: handle.token_count,
             auto_expanded: true,
+            client: client.clone(),
         }) {
             warn!("[canopy-service] feedback: failed to record auto-expand event: {e}");
         }
@@ -61,13 +68,16 @@ pub fn try_record_feedback_query(
     Some(query_event_id)
 }
 
-/// Record expand events to the feedback store.
+/// Record expand events to the feedback store; `auto_expanded` marks
+/// expansions made on the caller's behalf.
 ///
 /// Returns `true` if at least one event was recorded.
 pub fn try_record_feedback_expand(
     feedback_store: Option<&std::sync::Arc<std::sync::Mutex<FeedbackStore>>>,
     rows: &[ExpandedHandleDetail],
     recent_query_event_ids: &HashMap<String, i64>,
+    auto_expanded: bool,
+    client: &ClientContext,
 ) -> bool {
     let Some(feedback_store) = feedback_store else {
         return false;
@@ -85,7 +95,8 @@ pub fn try_record_feedback_expand(
             file_path: row.file_path.clone(),
             node_type: row.node_type,
             token_count: row.token_count,
-            auto_expanded: false,
+            auto_expanded,
+            client: client.clone(),
         }) {
             Ok(_) => wrote_any = true,
             Err(e) => warn!("[canopy-service] feedback: failed to record expand event: {e}"),
@@ -103,13 +114,21 @@ mod tests {
     fn try_record_feedback_query_returns_none_when_no_store() {
         let params = QueryParams::default();
         let result = QueryResult::default();
-        assert!(try_record_feedback_query(None, &params, &result).is_none());
+        assert!(
+            try_record_feedback_query(None, &params, &result, &ClientContext::default()).is_none()
+        );
     }
 
     #[test]
     fn try_record_feedback_expand_returns_false_when_no_store() {
         let rows: Vec<ExpandedHandleDetail> = vec![];
         let ids = HashMap::new();
-        assert!(!try_record_feedback_expand(None, &rows, &ids));
+        assert!(!try_record_feedback_expand(
+            None,
+            &rows,
+            &ids,
+            false,
+            &ClientContext::default()
+        ));
     }
 }
@@ -1,16 +1,29 @@
+mod auth;
+mod checkout;
+mod client_context;
 mod error;
 mod evidence;
 mod feedback_recording;
 mod metrics;
+mod policy;
+mod reader_pool;
+mod retention;
 mod routes;
 mod state;
+mod validation;
 
-use axum::extract::DefaultBodyLimit;
+use auth::RouteClass;
+use axum::extract::{DefaultBodyLimit, Request, State};
+use axum::http::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use axum::http::Method;
+use axum::middleware::Next;
 use axum::routing::{get, post};
 use axum::Router;
 use clap::Parser;
 use state::{AppState, SharedState};
 use std::sync::Arc;
+use std::time::Duration;
+use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
 use tracing::{error, info, warn};
 
@@ -26,9 +39,58 @@ struct Args {
     #[arg(long, default_value = "127.0.0.1")]
     bind: String,
 
-    /// API key for admin routes (also reads CANOPY_API_KEY env var)
+    /// API key for admin routes (also reads CANOPY_API_KEY env var); an
+    /// implicit admin key alongside any from --api-keys-file
     #[arg(long, env = "CANOPY_API_KEY")]
     api_key: Option<String>,
+
+    /// TOML file of named keys with roles (admin, reindex, read), reloaded
+    /// on SIGHUP or POST /admin/reload-keys
+    #[arg(long, env = "CANOPY_API_KEYS_FILE")]
+    api_keys_file: Option<std::path::PathBuf>,
+
+    /// Serve a read-only query UI at /ui
+    #[arg(long)]
+    ui: bool,
+
+    /// List registered repos in the UI (otherwise users type a repo id)
+    #[arg(long, requires = "ui")]
+    ui_allow_repo_list: bool,
+
+    /// Allow cross-origin requests from this origin, for a UI hosted elsewhere (repeatable)
+    #[arg(long = "ui-cors-origin", requires = "ui")]
+    ui_cors_origins: Vec<String>,
+
+    /// On SIGTERM/Ctrl-C, report not-ready on /readyz for this many seconds
+    /// before the listener closes, so load balancers stop routing here first
+    #[arg(long, default_value = "5")]
+    shutdown_drain_secs: u64,
+
+    /// Concurrent blocking readers (and pooled SQLite connections) per repo
+    /// for query, evidence-pack, expand and summary (default: CPU count)
+    #[arg(long)]
+    max_readers_per_repo: Option<usize>,
+
+    /// Generations kept per repo, the live one included: handles from the
+    /// older ones still expand after a reindex. 1 keeps no history
+    #[arg(long, default_value_t = retention::DEFAULT_RETAIN_GENERATIONS)]
+    retain_generations: usize,
+
+    /// Disk each repo's retained generations may use, e.g. 500MB or 2GB;
+    /// the oldest are dropped first
+    #[arg(long, value_parser = retention::parse_size)]
+    max_disk_per_repo: Option<u64>,
+
+    /// Warm each repo (all reader connections and their symbol caches, plus
+    /// a pass over the index) as soon as its first index after startup is
+    /// ready; `POST /warmup` does the same on demand
+    #[arg(long)]
+    warmup_on_start: bool,
+
+    /// Directory for the service's own data. With an API key set, repos may
+    /// then be added by `git_url` and are cloned under `<dir>/checkouts`
+    #[arg(long, env = "CANOPY_SERVICE_STATE_DIR")]
+    state_dir: Option<std::path::PathBuf>,
 }
 
 #[tokio::main]
@@ -38,88 +100,641 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Require API key when binding beyond localhost
     let is_localhost = args.bind == "127.0.0.1" || args.bind == "::1" || args.bind == "localhost";
-    if !is_localhost && args.api_key.is_none() {
+    if !is_localhost && !has_keys(&args) {
         error!(
             bind = %args.bind,
-            "binding to non-localhost requires an API key — set CANOPY_API_KEY or pass --api-key"
+            "binding to non-localhost requires an API key — set CANOPY_API_KEY, pass --api-key or --api-keys-file"
         );
         std::process::exit(1);
     }
 
-    let state: SharedState = Arc::new(AppState::new());
+    let state: SharedState = Arc::new(app_state(&args)?);
+    let app = build_app(state.clone(), &args)?;
+    // Nothing is restored from disk yet; startup is done once the router exists
+    state.lifecycle.mark_started();
+    #[cfg(unix)]
+    if state.keys().is_some_and(|keys| keys.has_file()) {
+        tokio::spawn(auth::reload_on_hangup(state.clone()));
+    }
 
+    let addr = format!("{}:{}", args.bind, args.port);
+    if has_keys(&args) {
+        info!(addr = %addr, "listening (admin routes require API key)");
+    } else {
+        warn!(
+            addr = %addr,
+            "listening — no API key configured, admin routes are unprotected"
+        );
+    }
+
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    let drain = Duration::from_secs(args.shutdown_drain_secs);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(drain_then_stop(state, drain, termination_signal()))
+        .await?;
+    info!("shut down");
+    Ok(())
+}
+
+/// Whether any API key is configured, so the guarded routes need one.
+fn has_keys(args: &Args) -> bool {
+    args.api_key.is_some() || args.api_keys_file.is_some()
+}
+
+/// Service state for `args`; fails on an unreadable keys file.
+fn app_state(args: &Args) -> Result<AppState, Box<dyn std::error::Error>> {
+    let mut app_state = AppState::new();
+    if let Some(max_readers) = args.max_readers_per_repo {
+        app_state = app_state.with_max_readers_per_repo(max_readers);
+    }
+    if let Some(max_disk) = args.max_disk_per_repo {
+        app_state = app_state.with_max_disk_per_repo(max_disk);
+    }
+    app_state = app_state
+        .with_retain_generations(args.retain_generations)
+        .with_warmup_on_start(args.warmup_on_start);
+    if let Some(keys) = auth::KeyStore::load(args.api_key.clone(), args.api_keys_file.clone())? {
+        app_state = app_state.with_keys(keys);
+    }
+    if let Some(dir) = checkout_dir(args)? {
+        app_state = app_state.with_checkout_dir(dir);
+    }
+    Ok(app_state)
+}
+
+/// Where managed checkouts go: only with both a state dir and an API key,
+/// since cloning arbitrary URLs is an admin operation.
+fn checkout_dir(args: &Args) -> std::io::Result<Option<std::path::PathBuf>> {
+    let Some(state_dir) = &args.state_dir else {
+        return Ok(None);
+    };
+    if !has_keys(args) {
+        warn!("--state-dir without an API key: adding repos by git_url is disabled");
+        return Ok(None);
+    }
+    let dir = state_dir.join("checkouts");
+    std::fs::create_dir_all(&dir)?;
+    std::fs::canonicalize(dir).map(Some)
+}
+
+/// Resolves on SIGTERM (Unix) or Ctrl-C.
+async fn termination_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!(error = %e, "failed to listen for Ctrl-C");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                error!(error = %e, "failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Graceful shutdown future: once `signal` fires, flip readiness (and wake
+/// reindex tasks), keep serving for `drain`, then let the listener close.
+async fn drain_then_stop(
+    state: SharedState,
+    drain: Duration,
+    signal: impl std::future::Future<Output = ()>,
+) {
+    signal.await;
+    info!(drain_secs = drain.as_secs(), "shutdown requested, draining");
+    state.lifecycle.begin_shutdown();
+    tokio::time::sleep(drain).await;
+}
+
+/// Assemble the router for `args`.
+fn build_app(state: SharedState, args: &Args) -> Result<Router, Box<dyn std::error::Error>> {
     // Query routes: read-only data surface
     let query_routes = Router::new()
         .route("/query", post(routes::query))
         .route("/evidence_pack", post(routes::evidence_pack))
-        .route("/expand", post(routes::expand));
+        .route("/expand", post(routes::expand))
+        .route("/summary", post(routes::summary))
+        .route("/related", post(routes::related))
+        .route("/references", post(routes::references))
+        .route("/symbols", post(routes::symbols))
+        .route("/validate", post(routes::validate))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            client_context::track_client,
+        ));
+
+    // Reindex: the one admin route a `reindex` key may call
+    let reindex_routes = Router::new().route("/reindex", post(routes::reindex));
 
     // Admin routes: repo management and operational control
     let admin_routes = Router::new()
         .route("/repos/add", post(routes::add_repo))
         .route("/repos", get(routes::list_repos))
-        .route("/reindex", post(routes::reindex));
+        .route("/repos/policy", post(routes::set_policy))
+        .route("/warmup", post(routes::warmup))
+        .route("/generations/prune", post(routes::prune_generations))
+        .route("/admin/reload-keys", post(auth::reload_keys));
 
-    // Health/metrics: always public (no sensitive data)
+    // Health/metrics/schemas: always public (no sensitive data)
     let ops_routes = Router::new()
+        .route("/healthz", get(routes::healthz))
+        .route("/readyz", get(routes::readyz))
         .route("/status", get(routes::status))
-        .route("/metrics", get(metrics::metrics));
+        .route("/metrics", get(metrics::metrics))
+        .route("/schema/{name}", get(routes::schema));
 
-    // Apply API key guard to query + admin routes when configured.
+    // Guard query + admin routes by key role when keys are configured.
     // ops_routes remain public (health/metrics contain no sensitive data).
-    let guarded_routes = Router::new().merge(query_routes).merge(admin_routes);
-    let guarded_routes = if let Some(ref key) = args.api_key {
-        let key = key.clone();
-        guarded_routes.layer(axum::middleware::from_fn(move |req, next| {
-            let expected = key.clone();
-            api_key_guard(req, next, expected)
-        }))
-    } else {
-        guarded_routes
+    let guard = |routes: Router<SharedState>, class: RouteClass| {
+        if state.keys().is_none() {
+            return routes;
+        }
+        routes.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            move |State(state): State<SharedState>, req: Request, next: Next| {
+                auth::require_role(state, class, req, next)
+            },
+        ))
     };
+    let guarded_routes = Router::new()
+        .merge(guard(query_routes, RouteClass::Query))
+        .merge(guard(reindex_routes, RouteClass::Reindex))
+        .merge(guard(admin_routes, RouteClass::Admin));
 
-    let app = Router::new()
-        .merge(guarded_routes)
-        .merge(ops_routes)
+    // UI: static page plus its config, public like ops_routes; the page's
+    // /query and /expand calls still pass through the guard above
+    let mut app = Router::new().merge(guarded_routes).merge(ops_routes);
+    if args.ui {
+        app = app.merge(routes::ui_routes(routes::UiOptions {
+            allow_repo_list: args.ui_allow_repo_list,
+            api_key_required: state.keys().is_some(),
+        }));
+    }
+    if !args.ui_cors_origins.is_empty() {
+        let origins = args
+            .ui_cors_origins
+            .iter()
+            .map(|o| o.parse::<HeaderValue>())
+            .collect::<Result<Vec<_>, _>>()?;
+        app = app.layer(
+            CorsLayer::new()
+                .allow_origin(origins)
+                .allow_methods([Method::GET, Method::POST])
+                .allow_headers([CONTENT_TYPE, HeaderName::from_static("x-api-key")]),
+        );
+    }
+
+    Ok(app
         .layer(DefaultBodyLimit::max(2 * 1024 * 1024)) // 2 MB
         .layer(TraceLayer::new_for_http())
-        .with_state(state);
+        .with_state(state))
+}
 
-    let addr = format!("{}:{}", args.bind, args.port);
-    if args.api_key.is_some() {
-        info!(addr = %addr, "listening (admin routes require API key)");
-    } else {
-        warn!(
-            addr = %addr,
-            "listening — no API key configured, admin routes are unprotected"
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canopy_core::ErrorEnvelope;
+
+    /// Serve `build_app` for `flags` on an ephemeral port and return its base URL.
+    async fn spawn_app(flags: &[&str]) -> String {
+        let args = Args::parse_from(std::iter::once("canopy-service").chain(flags.iter().copied()));
+        let state = Arc::new(app_state(&args).unwrap());
+        let app = build_app(state.clone(), &args).unwrap();
+        state.lifecycle.mark_started();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn ui_routes_absent_without_flag() {
+        let base = spawn_app(&[]).await;
+        let client = reqwest::Client::new();
+        for path in ["/ui", "/ui/config.json"] {
+            let resp = client.get(format!("{base}{path}")).send().await.unwrap();
+            assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn ui_serves_page_and_config() {
+        let base = spawn_app(&["--ui", "--ui-allow-repo-list", "--api-key", "k"]).await;
+        let client = reqwest::Client::new();
+
+        let page = client.get(format!("{base}/ui")).send().await.unwrap();
+        assert!(page.status().is_success());
+        assert!(page.text().await.unwrap().contains("/ui/config.json"));
+
+        // Public even with an API key; the page's query calls are still guarded
+        let config: serde_json::Value = client
+            .get(format!("{base}/ui/config.json"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(config["api_key_required"], true);
+        assert_eq!(config["repos"], serde_json::json!([]));
+
+        let query = client
+            .post(format!("{base}/query"))
+            .json(&serde_json::json!({"repo": "r", "pattern": "x"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(query.status(), reqwest::StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn ui_cors_origin_answers_preflight() {
+        let base = spawn_app(&["--ui", "--ui-cors-origin", "http://docs.example"]).await;
+        let resp = reqwest::Client::new()
+            .request(reqwest::Method::OPTIONS, format!("{base}/query"))
+            .header("Origin", "http://docs.example")
+            .header("Access-Control-Request-Method", "POST")
+            .header("Access-Control-Request-Headers", "content-type,x-api-key")
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+        assert_eq!(
+            resp.headers()["access-control-allow-origin"],
+            "http://docs.example"
         );
     }
 
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
-    Ok(())
-}
+    #[tokio::test]
+    async fn strict_validation_rejects_bad_bodies_per_route() {
+        let base = spawn_app(&[]).await;
+        let client = reqwest::Client::new();
+        let cases = [
+            (
+                "/query",
+                serde_json::json!({"repo": "r", "pattern": "x", "limt": 5}),
+                "limt",
+            ),
+            (
+                "/evidence_pack",
+                serde_json::json!({"repo": "r", "pattern": "x", "config": {"max_handles": 100}}),
+                "config.max_handles",
+            ),
+            (
+                "/expand",
+                serde_json::json!({"repo": "r", "handles": []}),
+                "handles",
+            ),
+            (
+                "/summary",
+                serde_json::json!({"repo": "r", "max_tokens": 0}),
+                "max_tokens",
+            ),
+            (
+                "/related",
+                serde_json::json!({"repo": "r", "path": "src/lib.rs", "limit": 0}),
+                "limit",
+            ),
+            (
+                "/references",
+                serde_json::json!({"repo": "r", "symbol": "dispatch", "ref_types": "call"}),
+                "ref_types",
+            ),
+            (
+                "/symbols",
+                serde_json::json!({"repo": "r", "node_types": ["structs"]}),
+                "node_types[0]",
+            ),
+            (
+                "/validate",
+                serde_json::json!({"repo": "r", "handle_ids": "h1"}),
+                "handle_ids",
+            ),
+            (
+                "/reindex",
+                serde_json::json!({"repo": "r", "globs": "*.rs"}),
+                "globs",
+            ),
+            ("/repos/add", serde_json::json!({"name": "x"}), "path"),
+            ("/warmup", serde_json::json!({"repo_ids": "r"}), "repo_ids"),
+            (
+                "/generations/prune",
+                serde_json::json!({"retain": 0}),
+                "retain",
+            ),
+        ];
+        for (path, body, field) in &cases {
+            let resp = client
+                .post(format!("{base}{path}"))
+                .json(body)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST, "{path}");
+            let envelope: ErrorEnvelope = resp.json().await.unwrap();
+            assert_eq!(envelope.code, "invalid_request", "{path}");
+            assert_eq!(envelope.errors.len(), 1, "{path}");
+            assert_eq!(envelope.errors[0].field, *field, "{path}");
+        }
+
+        let metrics: serde_json::Value = client
+            .get(format!("{base}/metrics"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            metrics["performance"]["invalid_requests"],
+            cases.len() as u64
+        );
+    }
+
+    #[tokio::test]
+    async fn lenient_header_skips_strict_validation() {
+        let base = spawn_app(&[]).await;
+        let client = reqwest::Client::new();
+        let body = serde_json::json!({"repo": "r", "pattern": "x", "limt": 5});
+
+        let strict = client
+            .post(format!("{base}/query"))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(strict.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        // Unknown fields pass through to the handler, which finds no such repo
+        let lenient = client
+            .post(format!("{base}/query"))
+            .header(validation::LENIENT_HEADER, "1")
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(lenient.status(), reqwest::StatusCode::NOT_FOUND);
+        let envelope: ErrorEnvelope = lenient.json().await.unwrap();
+        assert_eq!(envelope.code, "repo_not_found");
+
+        // Lenient still needs a body serde can read
+        let broken = client
+            .post(format!("{base}/query"))
+            .header(validation::LENIENT_HEADER, "1")
+            .json(&serde_json::json!({"pattern": "x"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(broken.status(), reqwest::StatusCode::BAD_REQUEST);
+        let envelope: ErrorEnvelope = broken.json().await.unwrap();
+        assert_eq!(envelope.code, "invalid_request");
+    }
+
+    #[tokio::test]
+    async fn probes_are_public_with_api_key() {
+        let base = spawn_app(&["--api-key", "k"]).await;
+        let client = reqwest::Client::new();
 
-async fn api_key_guard(
-    req: axum::extract::Request,
-    next: axum::middleware::Next,
-    expected_key: String,
-) -> axum::response::Response {
-    use axum::http::StatusCode;
-    use axum::response::IntoResponse;
-
-    let provided = req.headers().get("x-api-key").and_then(|v| v.to_str().ok());
-
-    match provided {
-        Some(key) if key == expected_key => next.run(req).await,
-        _ => (
-            StatusCode::UNAUTHORIZED,
-            axum::Json(canopy_core::ErrorEnvelope::new(
-                "unauthorized",
-                "Missing or invalid API key",
-                "Set the X-Api-Key header to the configured CANOPY_API_KEY",
-            )),
+        let live = client.get(format!("{base}/healthz")).send().await.unwrap();
+        assert_eq!(live.status(), reqwest::StatusCode::OK);
+
+        let ready = client.get(format!("{base}/readyz")).send().await.unwrap();
+        assert_eq!(ready.status(), reqwest::StatusCode::OK);
+        let body: serde_json::Value = ready.json().await.unwrap();
+        assert_eq!(body["ready"], true);
+    }
+
+    #[tokio::test]
+    async fn schemas_are_served_publicly() {
+        let base = spawn_app(&["--api-key", "k"]).await;
+        let client = reqwest::Client::new();
+
+        for name in canopy_core::SCHEMA_TYPES {
+            let resp = client
+                .get(format!("{base}/schema/{name}"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), reqwest::StatusCode::OK, "{name}");
+            let schema: serde_json::Value = resp.json().await.unwrap();
+            assert_eq!(schema, canopy_core::output_schema(name).unwrap());
+        }
+
+        let missing = client
+            .get(format!("{base}/schema/handle"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+        let envelope: ErrorEnvelope = missing.json().await.unwrap();
+        assert_eq!(envelope.code, "schema_not_found");
+    }
+
+    #[tokio::test]
+    async fn readyz_flips_during_drain_before_listener_closes() {
+        let state = Arc::new(AppState::new());
+        let args = Args::parse_from(["canopy-service"]);
+        let app = build_app(state.clone(), &args).unwrap();
+        state.lifecycle.mark_started();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        let (trigger, signal) = tokio::sync::oneshot::channel::<()>();
+        let shutdown = drain_then_stop(state.clone(), Duration::from_millis(500), async {
+            let _ = signal.await;
+        });
+        let server = tokio::spawn(async move {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await
+                .unwrap()
+        });
+
+        let client = reqwest::Client::new();
+        let ready = client.get(format!("{base}/readyz")).send().await.unwrap();
+        assert_eq!(ready.status(), reqwest::StatusCode::OK);
+
+        trigger.send(()).unwrap();
+        state.lifecycle.shutdown_requested().await;
+
+        // Still listening during the drain, but no longer ready
+        let draining = client.get(format!("{base}/readyz")).send().await.unwrap();
+        assert_eq!(draining.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        let live = client.get(format!("{base}/healthz")).send().await.unwrap();
+        assert_eq!(live.status(), reqwest::StatusCode::OK);
+
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server stops after the drain")
+            .unwrap();
+    }
+
+    const KEYS_FILE: &str = r#"
+[[keys]]
+name = "ops"
+key = "ops-secret"
+role = "admin"
+
+[[keys]]
+name = "ci"
+key = "ci-secret"
+role = "reindex"
+
+[[keys]]
+name = "dash"
+key = "dash-secret"
+role = "read"
+"#;
+
+    #[tokio::test]
+    async fn key_roles_gate_each_route_class() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let keys = dir.path().join("keys.toml");
+        std::fs::write(&keys, KEYS_FILE).unwrap();
+        let keys_arg = keys.to_str().unwrap();
+        let base = spawn_app(&["--api-key", "flag-secret", "--api-keys-file", keys_arg]).await;
+        let client = reqwest::Client::new();
+
+        let query = serde_json::json!({"repo": "r", "pattern": "x"});
+        let reindex = serde_json::json!({"repo": "r"});
+        let empty = serde_json::json!({});
+        let routes: [(&str, Option<&serde_json::Value>); 4] = [
+            ("/query", Some(&query)),
+            ("/reindex", Some(&reindex)),
+            ("/repos", None),
+            ("/admin/reload-keys", Some(&empty)),
+        ];
+        // Which of the routes above each key may call
+        let cases: [(Option<&str>, [bool; 4]); 6] = [
+            (Some("flag-secret"), [true, true, true, true]),
+            (Some("ops-secret"), [true, true, true, true]),
+            (Some("ci-secret"), [false, true, false, false]),
+            (Some("dash-secret"), [true, false, false, false]),
+            (Some("wrong"), [false; 4]),
+            (None, [false; 4]),
+        ];
+        for (key, allowed) in &cases {
+            for ((path, body), allowed) in routes.iter().zip(allowed) {
+                let mut req = match body {
+                    Some(body) => client.post(format!("{base}{path}")).json(body),
+                    None => client.get(format!("{base}{path}")),
+                };
+                if let Some(key) = key {
+                    req = req.header("x-api-key", *key);
+                }
+                let resp = req.send().await.unwrap();
+                let status = resp.status();
+                let label = format!("{key:?} {path}");
+                if *allowed {
+                    assert!(
+                        status != reqwest::StatusCode::UNAUTHORIZED
+                            && status != reqwest::StatusCode::FORBIDDEN,
+                        "{label}: {status}"
+                    );
+                    continue;
+                }
+                let envelope: ErrorEnvelope = resp.json().await.unwrap();
+                if key.is_some_and(|k| k.ends_with("-secret")) {
+                    assert_eq!(status, reqwest::StatusCode::FORBIDDEN, "{label}");
+                    assert_eq!(envelope.code, "forbidden", "{label}");
+                    assert!(envelope.hint.contains("Use a key with role"), "{label}");
+                } else {
+                    assert_eq!(status, reqwest::StatusCode::UNAUTHORIZED, "{label}");
+                    assert_eq!(envelope.code, "unauthorized", "{label}");
+                }
+            }
+        }
+
+        let metrics: serde_json::Value = client
+            .get(format!("{base}/metrics"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let by_key = &metrics["analytics"]["requests_by_key"];
+        assert_eq!(by_key[auth::FLAG_KEY_NAME], 4);
+        assert_eq!(by_key["ops"], 4);
+        assert_eq!(by_key["ci"], 1);
+        assert_eq!(by_key["dash"], 1);
+    }
+
+    #[tokio::test]
+    async fn reload_keys_picks_up_file_changes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let keys = dir.path().join("keys.toml");
+        std::fs::write(&keys, KEYS_FILE).unwrap();
+        let base = spawn_app(&["--api-keys-file", keys.to_str().unwrap()]).await;
+        let client = reqwest::Client::new();
+        let reload = |key: &'static str| {
+            client
+                .post(format!("{base}/admin/reload-keys"))
+                .header("x-api-key", key)
+                .send()
+        };
+
+        // Promote "dash" to admin and drop "ci"
+        std::fs::write(
+            &keys,
+            "[[keys]]\nname = \"ops\"\nkey = \"ops-secret\"\nrole = \"admin\"\n\n\
+             [[keys]]\nname = \"dash\"\nkey = \"dash-secret\"\nrole = \"admin\"\n",
         )
-            .into_response(),
+        .unwrap();
+        let resp = reload("ops-secret").await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(
+            body["keys"],
+            serde_json::json!([
+                {"name": "ops", "role": "admin"},
+                {"name": "dash", "role": "admin"},
+            ])
+        );
+        let repos = client
+            .get(format!("{base}/repos"))
+            .header("x-api-key", "dash-secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(repos.status(), reqwest::StatusCode::OK);
+        let gone = reload("ci-secret").await.unwrap();
+        assert_eq!(gone.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        // A broken file is rejected and the loaded keys stay in effect
+        std::fs::write(&keys, "[[keys]]\nname = \"ops\"\n").unwrap();
+        let resp = reload("dash-secret").await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        let envelope: ErrorEnvelope = resp.json().await.unwrap();
+        assert_eq!(envelope.code, "invalid_keys_file");
+        let still = reload("dash-secret").await.unwrap();
+        assert_eq!(still.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn reload_keys_without_a_file_is_rejected() {
+        let base = spawn_app(&["--api-key", "k"]).await;
+        let resp = reqwest::Client::new()
+            .post(format!("{base}/admin/reload-keys"))
+            .header("x-api-key", "k")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        let envelope: ErrorEnvelope = resp.json().await.unwrap();
+        assert_eq!(envelope.code, "keys_file_not_configured");
     }
 }
@@ -1,8 +1,10 @@
 //! Metrics response types and the GET /metrics handler.
 
+use crate::reader_pool::ReaderPoolStats;
 use crate::state::SharedState;
 use axum::extract::State;
 use axum::Json;
+use canopy_core::{FtsScanStats, ROWS_EXAMINED_BUCKETS};
 use serde::Serialize;
 use std::collections::HashMap;
 use std::sync::atomic::Ordering;
@@ -13,6 +15,61 @@ const TOP_N: usize = 20;
 pub struct MetricsResponse {
     pub performance: PerformanceMetrics,
     pub analytics: AnalyticsMetrics,
+    pub readers: ReaderMetrics,
+    /// Full-text scans per repo id
+    pub fts_scans: HashMap<String, FtsScanMetrics>,
+}
+
+/// Full-text searches on one repo, to catch patterns that scan huge
+/// posting lists.
+#[derive(Serialize)]
+pub struct FtsScanMetrics {
+    pub scans: u64,
+    pub avg_rows_examined: u64,
+    pub avg_scan_us: u64,
+    /// Scans by candidate rows read, in ascending buckets
+    pub rows_examined_histogram: Vec<RowsBucket>,
+    /// Queries that tripped the high-frequency token guard
+    pub guarded_queries: u64,
+    /// Scans stopped at `[fts] max_fts_candidates`
+    pub capped_scans: u64,
+}
+
+#[derive(Serialize)]
+pub struct RowsBucket {
+    /// Most rows a scan in this bucket read; absent for the last bucket
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub le: Option<u64>,
+    pub count: u64,
+}
+
+impl From<&FtsScanStats> for FtsScanMetrics {
+    fn from(stats: &FtsScanStats) -> Self {
+        let bounds = ROWS_EXAMINED_BUCKETS.iter().copied().map(Some);
+        Self {
+            scans: stats.scans,
+            avg_rows_examined: stats.rows_examined.checked_div(stats.scans).unwrap_or(0),
+            avg_scan_us: stats.elapsed_us.checked_div(stats.scans).unwrap_or(0),
+            rows_examined_histogram: bounds
+                .chain([None])
+                .zip(stats.rows_examined_histogram)
+                .map(|(le, count)| RowsBucket { le, count })
+                .collect(),
+            guarded_queries: stats.guarded,
+            capped_scans: stats.capped,
+        }
+    }
+}
+
+/// Blocking reader pools: configured bound and per-repo saturation.
+#[derive(Serialize)]
+pub struct ReaderMetrics {
+    pub max_readers_per_repo: usize,
+    /// Repos whose every reader permit is currently held
+    pub saturated_repos: usize,
+    /// Requests currently queued for a reader, across repos
+    pub waiting: usize,
+    pub pools: HashMap<String, ReaderPoolStats>,
 }
 
 #[derive(Serialize)]
@@ -27,6 +84,7 @@ pub struct PerformanceMetrics {
     pub reindexes: u64,
     pub avg_query_ms: u64,
     pub avg_expand_ms: u64,
+    pub invalid_requests: u64,
 }
 
 #[derive(Serialize)]
@@ -53,6 +111,12 @@ pub struct AnalyticsMetrics {
     pub top_patterns: Vec<PatternCount>,
     pub top_expanded_files: Vec<PathCount>,
     pub requests_by_repo: HashMap<String, u64>,
+    /// Query requests per X-Canopy-Client, capped in distinct names
+    pub requests_by_client: HashMap<String, u64>,
+    /// Query requests per hashed X-Canopy-Session bucket
+    pub requests_by_session_bucket: HashMap<String, u64>,
+    /// Guarded requests per API key name, when keys are configured
+    pub requests_by_key: HashMap<String, u64>,
     pub feedback_by_repo: HashMap<String, FeedbackSummary>,
 }
 
@@ -60,85 +124,19 @@ pub struct AnalyticsMetrics {
 pub struct FeedbackSummary {
     pub glob_hit_rate_at_k: f64,
     pub handle_expand_accept_rate: f64,
+    pub manual_expand_accept_rate: f64,
+    pub auto_expand_share: f64,
     pub avg_tokens_per_expand: f64,
     pub sample_count: usize,
 }
 
 pub(crate) fn top_n_sorted(map: &HashMap<String, u64>, n: usize) -> Vec<(String, u64)> {
     let mut entries: Vec<_> = map.iter().map(|(k, v)| (k.clone(), *v)).collect();
-    entries.sort_by(|a, b| b.1.cmp(&a.1));
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
     entries.truncate(n);
     entries
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn top_n_sorted_returns_sorted_by_count_descending() {
-        let mut map = HashMap::new();
-        map.insert("a".to_string(), 10);
-        map.insert("b".to_string(), 30);
-        map.insert("c".to_string(), 20);
-        let result = top_n_sorted(&map, 3);
-        assert_eq!(result[0], ("b".to_string(), 30));
-        assert_eq!(result[1], ("c".to_string(), 20));
-        assert_eq!(result[2], ("a".to_string(), 10));
-    }
-
-    #[test]
-    fn top_n_sorted_truncates_to_n() {
-        let mut map = HashMap::new();
-        map.insert("a".to_string(), 10);
-        map.insert("b".to_string(), 30);
-        map.insert("c".to_string(), 20);
-        let result = top_n_sorted(&map, 2);
-        assert_eq!(result.len(), 2);
-        assert_eq!(result[0].1, 30);
-        assert_eq!(result[1].1, 20);
-    }
-
-    #[test]
-    fn top_n_sorted_handles_empty_map() {
-        let map: HashMap<String, u64> = HashMap::new();
-        let result = top_n_sorted(&map, 5);
-        assert!(result.is_empty());
-    }
-
-    #[test]
-    fn metrics_response_serializes_to_json() {
-        let resp = MetricsResponse {
-            performance: PerformanceMetrics {
-                queries: 100,
-                query_cache_hit_rate: 0.75,
-                query_cache_hits: 75,
-                query_cache_misses: 25,
-                expands: 50,
-                index_cache_hits: 40,
-                index_cache_misses: 10,
-                reindexes: 3,
-                avg_query_ms: 15,
-                avg_expand_ms: 5,
-            },
-            analytics: AnalyticsMetrics {
-                top_symbols: vec![NamedCount {
-                    name: "Config".to_string(),
-                    count: 42,
-                }],
-                top_patterns: vec![],
-                top_expanded_files: vec![],
-                requests_by_repo: HashMap::new(),
-                feedback_by_repo: HashMap::new(),
-            },
-        };
-        let json = serde_json::to_value(&resp).unwrap();
-        assert_eq!(json["performance"]["queries"], 100);
-        assert_eq!(json["performance"]["query_cache_hit_rate"], 0.75);
-        assert_eq!(json["analytics"]["top_symbols"][0]["name"], "Config");
-    }
-}
-
 pub async fn metrics(State(state): State<SharedState>) -> Json<MetricsResponse> {
     let queries = state.metrics.query_count.load(Ordering::Relaxed);
     let query_cache_hits = state.metrics.query_cache_hits.load(Ordering::Relaxed);
@@ -149,6 +147,7 @@ pub async fn metrics(State(state): State<SharedState>) -> Json<MetricsResponse>
     let reindexes = state.metrics.reindex_count.load(Ordering::Relaxed);
     let total_query_ms = state.metrics.total_query_ms.load(Ordering::Relaxed);
     let total_expand_ms = state.metrics.total_expand_ms.load(Ordering::Relaxed);
+    let invalid_requests = state.metrics.invalid_requests.load(Ordering::Relaxed);
 
     let query_cache_total = query_cache_hits + query_cache_misses;
     let query_cache_hit_rate = if query_cache_total > 0 {
@@ -157,16 +156,8 @@ pub async fn metrics(State(state): State<SharedState>) -> Json<MetricsResponse>
         0.0
     };
 
-    let avg_query_ms = if queries > 0 {
-        total_query_ms / queries
-    } else {
-        0
-    };
-    let avg_expand_ms = if expands > 0 {
-        total_expand_ms / expands
-    } else {
-        0
-    };
+    let avg_query_ms = total_query_ms.checked_div(queries).unwrap_or(0);
+    let avg_expand_ms = total_expand_ms.checked_div(expands).unwrap_or(0);
 
     let feedback_by_repo = {
         // Clone shard metadata under the lock, then drop it before async work.
@@ -190,6 +181,8 @@ pub async fn metrics(State(state): State<SharedState>) -> Json<MetricsResponse>
                         FeedbackSummary {
                             glob_hit_rate_at_k: m.glob_hit_rate_at_k,
                             handle_expand_accept_rate: m.handle_expand_accept_rate,
+                            manual_expand_accept_rate: m.manual_expand_accept_rate,
+                            auto_expand_share: m.auto_expand_share,
                             avg_tokens_per_expand: m.avg_tokens_per_expand,
                             sample_count: m.sample_count,
                         },
@@ -215,6 +208,9 @@ pub async fn metrics(State(state): State<SharedState>) -> Json<MetricsResponse>
                 .map(|(path, count)| PathCount { path, count })
                 .collect(),
             requests_by_repo: a.requests_by_repo.clone(),
+            requests_by_client: a.requests_by_client.clone(),
+            requests_by_session_bucket: a.requests_by_session_bucket.clone(),
+            requests_by_key: a.requests_by_key.clone(),
             feedback_by_repo: feedback_by_repo.clone(),
         }
     } else {
@@ -223,10 +219,29 @@ pub async fn metrics(State(state): State<SharedState>) -> Json<MetricsResponse>
             top_patterns: Vec::new(),
             top_expanded_files: Vec::new(),
             requests_by_repo: HashMap::new(),
+            requests_by_client: HashMap::new(),
+            requests_by_session_bucket: HashMap::new(),
+            requests_by_key: HashMap::new(),
             feedback_by_repo,
         }
     };
 
+    let fts_scans = match state.metrics.fts_scans.lock() {
+        Ok(scans) => scans
+            .iter()
+            .map(|(repo_id, stats)| (repo_id.clone(), FtsScanMetrics::from(stats)))
+            .collect(),
+        Err(_) => HashMap::new(),
+    };
+
+    let pools = state.reader_pool_stats().await;
+    let readers = ReaderMetrics {
+        max_readers_per_repo: state.max_readers_per_repo(),
+        saturated_repos: pools.values().filter(|p| p.in_use >= p.max_readers).count(),
+        waiting: pools.values().map(|p| p.waiting).sum(),
+        pools,
+    };
+
     Json(MetricsResponse {
         performance: PerformanceMetrics {
             queries,
@@ -239,7 +254,114 @@ pub async fn metrics(State(state): State<SharedState>) -> Json<MetricsResponse>
             reindexes,
             avg_query_ms,
             avg_expand_ms,
+            invalid_requests,
         },
         analytics,
+        readers,
+        fts_scans,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn top_n_sorted_returns_sorted_by_count_descending() {
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), 10);
+        map.insert("b".to_string(), 30);
+        map.insert("c".to_string(), 20);
+        let result = top_n_sorted(&map, 3);
+        assert_eq!(result[0], ("b".to_string(), 30));
+        assert_eq!(result[1], ("c".to_string(), 20));
+        assert_eq!(result[2], ("a".to_string(), 10));
+    }
+
+    #[test]
+    fn top_n_sorted_truncates_to_n() {
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), 10);
+        map.insert("b".to_string(), 30);
+        map.insert("c".to_string(), 20);
+        let result = top_n_sorted(&map, 2);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].1, 30);
+        assert_eq!(result[1].1, 20);
+    }
+
+    #[test]
+    fn top_n_sorted_handles_empty_map() {
+        let map: HashMap<String, u64> = HashMap::new();
+        let result = top_n_sorted(&map, 5);
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn metrics_response_serializes_to_json() {
+        let resp = MetricsResponse {
+            performance: PerformanceMetrics {
+                queries: 100,
+                query_cache_hit_rate: 0.75,
+                query_cache_hits: 75,
+                query_cache_misses: 25,
+                expands: 50,
+                index_cache_hits: 40,
+                index_cache_misses: 10,
+                reindexes: 3,
+                avg_query_ms: 15,
+                avg_expand_ms: 5,
+                invalid_requests: 2,
+            },
+            analytics: AnalyticsMetrics {
+                top_symbols: vec![NamedCount {
+                    name: "Config".to_string(),
+                    count: 42,
+                }],
+                top_patterns: vec![],
+                top_expanded_files: vec![],
+                requests_by_repo: HashMap::new(),
+                requests_by_client: HashMap::new(),
+                requests_by_session_bucket: HashMap::new(),
+                requests_by_key: HashMap::new(),
+                feedback_by_repo: HashMap::new(),
+            },
+            readers: ReaderMetrics {
+                max_readers_per_repo: 8,
+                saturated_repos: 0,
+                waiting: 0,
+                pools: HashMap::new(),
+            },
+            fts_scans: HashMap::new(),
+        };
+        let json = serde_json::to_value(&resp).unwrap();
+        assert_eq!(json["performance"]["queries"], 100);
+        assert_eq!(json["performance"]["query_cache_hit_rate"], 0.75);
+        assert_eq!(json["performance"]["invalid_requests"], 2);
+        assert_eq!(json["analytics"]["top_symbols"][0]["name"], "Config");
+        assert_eq!(json["readers"]["max_readers_per_repo"], 8);
+    }
+
+    #[test]
+    fn fts_scan_metrics_label_histogram_buckets() {
+        let mut stats = FtsScanStats::default();
+        for rows_examined in [5, 60_000, 200_000] {
+            stats.record(&canopy_core::FtsScan {
+                rows_examined,
+                elapsed_us: 300,
+                capped: rows_examined > 100_000,
+            });
+        }
+        stats.guarded = 1;
+        let json = serde_json::to_value(FtsScanMetrics::from(&stats)).unwrap();
+        assert_eq!(json["scans"], 3);
+        assert_eq!(json["avg_scan_us"], 300);
+        assert_eq!(json["guarded_queries"], 1);
+        assert_eq!(json["capped_scans"], 1);
+        let histogram = json["rows_examined_histogram"].as_array().unwrap();
+        assert_eq!(histogram.len(), ROWS_EXAMINED_BUCKETS.len() + 1);
+        assert_eq!(histogram[0], serde_json::json!({"le": 10, "count": 1}));
+        assert_eq!(histogram[4], serde_json::json!({"le": 100_000, "count": 1}));
+        assert_eq!(histogram[5], serde_json::json!({"count": 1}));
+    }
+}
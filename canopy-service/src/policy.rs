@@ -0,0 +1,272 @@
+//! Per-repo path policies: which paths a shared service may surface.
+//!
+//! A [`PathPolicy`] is set by an admin when the repo is added or through
+//! `POST /repos/policy`, and enforced here on every response, whatever the
+//! client asked for. Results are filtered after the query cache, so a policy
+//! change applies to the next request without invalidating anything; counts
+//! and exists checks are taken with the policy applied and skip the cache.
+//! Hidden results are only counted, never named.
+
+use canopy_core::protocol::PathPolicy;
+use canopy_core::{PathPredicate, QueryResult, TokenSavings};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::collections::BTreeMap;
+
+/// A compiled [`PathPolicy`].
+#[derive(Debug)]
+pub(crate) struct PathFilter {
+    /// `None` when the policy has no allow list, which allows everything
+    allow: Option<GlobSet>,
+    deny: GlobSet,
+}
+
+impl PathFilter {
+    /// Compile `policy`, or say which pattern is malformed.
+    pub(crate) fn compile(policy: &PathPolicy) -> Result<Self, String> {
+        let allow = if policy.allow.is_empty() {
+            None
+        } else {
+            Some(glob_set(&policy.allow)?)
+        };
+        Ok(Self {
+            allow,
+            deny: glob_set(&policy.deny)?,
+        })
+    }
+
+    /// Whether results under the repo-relative `path` may be returned.
+    pub(crate) fn permits(&self, path: &str) -> bool {
+        !self.deny.is_match(path) && self.allow.as_ref().is_none_or(|a| a.is_match(path))
+    }
+
+    /// Drop everything under paths the policy hides from `result`, recount
+    /// its totals, and add what was dropped to `suppressed_by_policy`.
+    ///
+    /// Did-you-mean suggestions don't say where a name is defined, so they're
+    /// cleared rather than risk naming a hidden symbol.
+    pub(crate) fn filter_result(&self, result: &mut QueryResult) {
+        // `dropped` counts removed entries; `suppressed` adds the grouped ref
+        // occurrences hidden from entries that stay
+        let mut suppressed = 0;
+        let mut dropped = 0;
+
+        let before = result.handles.len();
+        result.handles.retain(|h| self.permits(&h.file_path));
+        dropped += before - result.handles.len();
+
+        if let Some(refs) = result.ref_handles.take() {
+            let mut kept = Vec::with_capacity(refs.len());
+            for reference in refs {
+                let locations = 1 + reference.occurrence_count;
+                match reference.retain_paths(|path| self.permits(path)) {
+                    Some(reference) => {
+                        suppressed += locations - 1 - reference.occurrence_count;
+                        kept.push(reference);
+                    }
+                    None => {
+                        suppressed += locations - 1;
+                        dropped += 1;
+                    }
+                }
+            }
+            // Per-type counts cover the whole repo; only what's kept is safe
+            if result.ref_type_counts.is_some() {
+                let mut counts = BTreeMap::new();
+                for reference in &kept {
+                    *counts
+                        .entry(reference.ref_type.as_str().to_string())
+                        .or_default() += 1 + reference.occurrence_count;
+                }
+                result.ref_type_counts = Some(counts);
+            }
+            result.ref_handles = Some(kept);
+        }
+        result.suggestions.clear();
+
+        if let Some(annotations) = result.annotations.as_mut() {
+            let before = annotations.len();
+            annotations.retain(|a| self.permits(&a.file_path));
+            dropped += before - annotations.len();
+        }
+        suppressed += dropped;
+
+        if result
+            .witness_path
+            .as_deref()
+            .is_some_and(|path| !self.permits(path))
+        {
+            result.witness_path = None;
+        }
+
+        if suppressed == 0 {
+            return;
+        }
+        let expanded: Vec<_> = result
+            .handles
+            .iter()
+            .filter(|h| h.content.is_some())
+            .collect();
+        result.expanded_count = expanded.len();
+        result.expanded_tokens = expanded.iter().map(|h| h.token_count).sum();
+        result.expanded_handle_ids = expanded.iter().map(|h| h.id.to_string()).collect();
+        result.total_tokens = result.handles.iter().map(|h| h.token_count).sum();
+        result.preview_tokens = result.handles.iter().map(|h| h.preview_tokens).sum();
+        if let Some(mut savings) = result.savings.take() {
+            savings.files.retain(|path, _| self.permits(path));
+            result.savings = (!savings.files.is_empty())
+                .then(|| TokenSavings::new(savings.files, result.returned_tokens()));
+        }
+        result.total_matches = result.total_matches.saturating_sub(dropped);
+        result.suppressed_by_policy += suppressed;
+    }
+}
+
+impl PathPredicate for PathFilter {
+    fn permits(&self, path: &str) -> bool {
+        PathFilter::permits(self, path)
+    }
+}
+
+/// A matcher for `patterns`, each of which also covers everything under the
+/// directory it names.
+fn glob_set(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let trimmed = pattern.trim_matches('/');
+        if trimmed.is_empty() {
+            return Err(format!("invalid pattern {pattern:?}: names no path"));
+        }
+        for glob in [trimmed.to_string(), format!("{trimmed}/**")] {
+            let glob = GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| format!("invalid pattern {pattern:?}: {e}"))?;
+            builder.add(glob);
+        }
+    }
+    builder
+        .build()
+        .map_err(|e| format!("invalid policy patterns: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canopy_core::query::execute_params;
+    use canopy_core::{QueryKind, QueryMode, QueryParams, RepoIndex};
+    use canopy_testutil::FixtureRepoBuilder;
+    use std::sync::Arc;
+
+    fn filter(allow: &[&str], deny: &[&str]) -> PathFilter {
+        PathFilter::compile(&PathPolicy {
+            allow: allow.iter().map(|p| p.to_string()).collect(),
+            deny: deny.iter().map(|p| p.to_string()).collect(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn patterns_cover_directories_and_deny_wins() {
+        let deny = filter(&[], &["secrets/", "*.pem"]);
+        assert!(!deny.permits("secrets/keys.rs"));
+        assert!(!deny.permits("secrets/nested/keys.rs"));
+        assert!(!deny.permits("server.pem"));
+        // `*` stays within one path segment
+        assert!(deny.permits("certs/server.pem"));
+        assert!(deny.permits("src/secrets.rs"));
+
+        let allow = filter(&["src", "docs/*.md"], &["src/internal"]);
+        assert!(allow.permits("src/lib.rs"));
+        assert!(allow.permits("docs/guide.md"));
+        assert!(!allow.permits("docs/old/guide.md"));
+        assert!(!allow.permits("build.rs"));
+        assert!(!allow.permits("src/internal/mod.rs"));
+    }
+
+    #[test]
+    fn malformed_patterns_are_rejected() {
+        let policy = |deny: &str| PathPolicy {
+            allow: Vec::new(),
+            deny: vec![deny.to_string()],
+        };
+        assert!(PathFilter::compile(&policy("src/[")).is_err());
+        assert!(PathFilter::compile(&policy("/")).is_err());
+    }
+
+    #[test]
+    fn filtered_results_drop_and_count_denied_paths() {
+        let repo = FixtureRepoBuilder::new()
+            .file(
+                "src/lib.rs",
+                "pub fn open_vault() {}\nuse crate::vault::Vault;\n",
+            )
+            .file(
+                "secrets/vault.rs",
+                "pub fn read_key() {\n    open_vault();\n}\nuse crate::vault::Vault;\n",
+            )
+            .build();
+        let mut index = RepoIndex::open_or_init(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        let deny = Arc::new(filter(&[], &["secrets"]));
+
+        let mut result = index
+            .query_params(QueryParams::pattern("open_vault"))
+            .unwrap();
+        let matched = result.total_matches;
+        assert!(result
+            .handles
+            .iter()
+            .any(|h| h.file_path.starts_with("secrets/")));
+        deny.filter_result(&mut result);
+        assert!(!result.handles.is_empty());
+        assert!(result.handles.iter().all(|h| h.file_path == "src/lib.rs"));
+        assert!(result.suppressed_by_policy > 0);
+        assert_eq!(
+            result.total_matches,
+            matched - result.suppressed_by_policy,
+            "{result:?}"
+        );
+        assert_eq!(
+            result.total_tokens,
+            result.handles.iter().map(|h| h.token_count).sum::<usize>()
+        );
+
+        // The grouped import keeps its src occurrence and loses the other
+        let params = QueryParams::symbol("Vault")
+            .with_kind(QueryKind::Reference)
+            .with_group_references(true);
+        let mut refs = index.query_params(params).unwrap();
+        deny.filter_result(&mut refs);
+        let kept = refs.ref_handles.unwrap();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].file_path, "src/lib.rs");
+        assert_eq!(kept[0].occurrence_count, 0);
+        assert_eq!(refs.suppressed_by_policy, 1);
+        let import = canopy_core::RefType::Import.as_str().to_string();
+        assert_eq!(refs.ref_type_counts, Some(BTreeMap::from([(import, 1)])));
+
+        // Counts are taken with the policy applied, the way the service runs them
+        let counted = |params: QueryParams| {
+            let options = params.to_options().with_path_predicate(deny.clone());
+            let mut result = execute_params(&params, &index, options).unwrap();
+            deny.filter_result(&mut result);
+            result
+        };
+        let found = counted(QueryParams::pattern("read_key").with_mode(QueryMode::Exists));
+        assert_eq!(found.exists, Some(false));
+        assert!(found.witness_path.is_none());
+        let counts = counted(
+            QueryParams::patterns(vec!["read_key".into(), "open_vault".into()])
+                .with_mode(QueryMode::Count),
+        );
+        let counts = counts.match_counts.unwrap();
+        assert_eq!(counts[r#"(grep "read_key")"#], 0);
+        assert_eq!(counts[r#"(grep "open_vault")"#], 1);
+
+        // A hidden definition isn't offered as a did-you-mean either
+        let mut miss = index.query_params(QueryParams::symbol("read_kee")).unwrap();
+        assert!(miss.suggestions.iter().any(|s| s.name == "read_key"));
+        deny.filter_result(&mut miss);
+        assert!(miss.suggestions.is_empty());
+    }
+}
@@ -0,0 +1,293 @@
+//! Per-repo pool of `RepoIndex` read connections.
+//!
+//! Sharing one `RepoIndex` behind a mutex serializes every query, expand and
+//! summary on a repo. SQLite in WAL mode serves concurrent readers, so each
+//! blocking task checks out its own instance instead. A semaphore bounds how
+//! many tasks run at once; instances open lazily up to that bound (each loads
+//! its own symbol cache) and go back on the idle list when the task finishes.
+
+use canopy_core::{CanopyError, RepoIndex};
+use serde::Serialize;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Default bound on concurrent readers per repo: one per available CPU.
+pub fn default_max_readers() -> usize {
+    std::thread::available_parallelism().map_or(4, |n| n.get())
+}
+
+/// Point-in-time pool occupancy, reported on `/metrics`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReaderPoolStats {
+    pub max_readers: usize,
+    /// Permits currently held by running tasks
+    pub in_use: usize,
+    /// Opened connections waiting for a task
+    pub idle: usize,
+    /// Connections opened over the pool's lifetime, each loading its own
+    /// symbol cache
+    pub opened: u64,
+    /// Tasks currently blocked on the semaphore
+    pub waiting: usize,
+    /// Acquisitions that found every permit taken
+    pub waits: u64,
+    /// Most permits held at once
+    pub peak_in_use: usize,
+}
+
+pub struct ReaderPool {
+    repo_root: PathBuf,
+    /// Copy of a retained generation this pool reads, and its commit;
+    /// `None` for the live index
+    copy: Option<(PathBuf, Option<String>)>,
+    max_readers: usize,
+    permits: Arc<Semaphore>,
+    idle: Mutex<Vec<RepoIndex>>,
+    opened: AtomicU64,
+    waiting: AtomicUsize,
+    waits: AtomicU64,
+    peak_in_use: AtomicUsize,
+}
+
+impl ReaderPool {
+    /// Pool for `repo_root` seeded with an already-open `index`.
+    pub fn new(repo_root: PathBuf, index: RepoIndex, max_readers: usize) -> Self {
+        let max_readers = max_readers.max(1);
+        Self {
+            repo_root,
+            copy: None,
+            max_readers,
+            permits: Arc::new(Semaphore::new(max_readers)),
+            idle: Mutex::new(vec![index]),
+            opened: AtomicU64::new(1),
+            waiting: AtomicUsize::new(0),
+            waits: AtomicU64::new(0),
+            peak_in_use: AtomicUsize::new(0),
+        }
+    }
+
+    /// Pool over the index copy in `dir` (see `RepoIndex::open_copy`),
+    /// seeded with an already-open `index` of it.
+    pub fn for_copy(
+        repo_root: PathBuf,
+        dir: PathBuf,
+        commit_sha: Option<String>,
+        index: RepoIndex,
+        max_readers: usize,
+    ) -> Self {
+        Self {
+            copy: Some((dir, commit_sha)),
+            ..Self::new(repo_root, index, max_readers)
+        }
+    }
+
+    /// Wait for a reader slot. Call before `spawn_blocking` so queued requests
+    /// wait on the runtime rather than tying up blocking threads.
+    pub async fn acquire(self: &Arc<Self>) -> ReaderLease {
+        let permit = match Arc::clone(&self.permits).try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                self.waits.fetch_add(1, Ordering::Relaxed);
+                let _waiting = WaitingGuard::enter(&self.waiting);
+                Arc::clone(&self.permits)
+                    .acquire_owned()
+                    .await
+                    .expect("reader semaphore is never closed")
+            }
+        };
+        let in_use = self.max_readers - self.permits.available_permits();
+        self.peak_in_use.fetch_max(in_use, Ordering::Relaxed);
+        ReaderLease {
+            pool: Arc::clone(self),
+            _permit: permit,
+        }
+    }
+
+    /// Wait for every reader slot. Checking out an index from each lease
+    /// opens connections up to the bound, each loading its symbol cache, so
+    /// later requests find them idle instead of opening their own.
+    pub async fn acquire_all(self: &Arc<Self>) -> Vec<ReaderLease> {
+        let mut leases = Vec::with_capacity(self.max_readers);
+        for _ in 0..self.max_readers {
+            leases.push(self.acquire().await);
+        }
+        leases
+    }
+
+    pub fn stats(&self) -> ReaderPoolStats {
+        ReaderPoolStats {
+            max_readers: self.max_readers,
+            in_use: self.max_readers - self.permits.available_permits(),
+            idle: self.lock_idle().len(),
+            opened: self.opened.load(Ordering::Relaxed),
+            waiting: self.waiting.load(Ordering::Relaxed),
+            waits: self.waits.load(Ordering::Relaxed),
+            peak_in_use: self.peak_in_use.load(Ordering::Relaxed),
+        }
+    }
+
+    fn lock_idle(&self) -> std::sync::MutexGuard<'_, Vec<RepoIndex>> {
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Counts a task as waiting until dropped, including when the request is
+/// cancelled mid-wait.
+struct WaitingGuard<'a>(&'a AtomicUsize);
+
+impl<'a> WaitingGuard<'a> {
+    fn enter(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A reader slot. Move into the blocking task and check out the index there.
+pub struct ReaderLease {
+    pool: Arc<ReaderPool>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl ReaderLease {
+    /// Take an idle connection, opening a new one if none is free. Blocking.
+    pub fn index(&self) -> Result<PooledIndex<'_>, CanopyError> {
+        let idle = self.pool.lock_idle().pop();
+        let index = match idle {
+            Some(index) => index,
+            None => {
+                let index = match &self.pool.copy {
+                    Some((dir, commit_sha)) => {
+                        RepoIndex::open_copy(&self.pool.repo_root, dir, commit_sha.clone())?
+                    }
+                    None => RepoIndex::open(&self.pool.repo_root)?,
+                };
+                self.pool.opened.fetch_add(1, Ordering::Relaxed);
+                index
+            }
+        };
+        Ok(PooledIndex {
+            pool: &self.pool,
+            index: Some(index),
+        })
+    }
+}
+
+/// A checked-out `RepoIndex`, returned to the pool on drop.
+pub struct PooledIndex<'a> {
+    pool: &'a ReaderPool,
+    index: Option<RepoIndex>,
+}
+
+impl Deref for PooledIndex<'_> {
+    type Target = RepoIndex;
+
+    fn deref(&self) -> &RepoIndex {
+        self.index.as_ref().expect("index present until drop")
+    }
+}
+
+impl Drop for PooledIndex<'_> {
+    fn drop(&mut self) {
+        // A task that panicked mid-query may leave caches half-updated; let the
+        // connection close and the next checkout open a fresh one.
+        if std::thread::panicking() {
+            return;
+        }
+        if let Some(index) = self.index.take() {
+            self.pool.lock_idle().push(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::AppState;
+    use canopy_core::query::execute_query_with_options;
+    use canopy_core::{QueryOptions, QueryParams, RepoIndex};
+    use std::sync::Arc;
+
+    fn indexed_repo(files: usize) -> tempfile::TempDir {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        for i in 0..files {
+            std::fs::write(
+                dir.path().join(format!("src/mod_{i}.rs")),
+                format!("pub fn pooled_fn_{i}() {{ let value_{i} = {i}; }}\n"),
+            )
+            .unwrap();
+        }
+        let mut index = RepoIndex::open_or_init(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        dir
+    }
+
+    /// Fire `tasks` concurrent symbol queries through the shared state's pool
+    /// and return the pool's stats afterwards.
+    async fn run_concurrent_queries(max_readers: usize, tasks: usize) -> super::ReaderPoolStats {
+        let repo = indexed_repo(8);
+        let root = repo.path().to_string_lossy().into_owned();
+        let state = Arc::new(AppState::new().with_max_readers_per_repo(max_readers));
+
+        let handles: Vec<_> = (0..tasks)
+            .map(|i| {
+                let state = Arc::clone(&state);
+                let root = root.clone();
+                tokio::spawn(async move {
+                    let cached = state.get_or_open_index("demo", &root, 1).await.unwrap();
+                    let lease = cached.acquire().await;
+                    tokio::task::spawn_blocking(move || {
+                        let index = lease.index().unwrap();
+                        let mut found = 0;
+                        // Enough work per task for overlapping tasks to be observed
+                        for round in 0..20 {
+                            let name = format!("pooled_fn_{}", (i + round) % 8);
+                            let query = QueryParams::symbol(&name).to_query().unwrap();
+                            let result =
+                                execute_query_with_options(&query, &index, QueryOptions::default())
+                                    .unwrap();
+                            found += result.handles.len();
+                        }
+                        found
+                    })
+                    .await
+                    .unwrap()
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.await.unwrap(), 20);
+        }
+
+        let pools = state.reader_pool_stats().await;
+        let stats = pools.get("demo").expect("pool for demo").clone();
+        assert_eq!(stats.in_use, 0, "every lease released");
+        assert_eq!(stats.waiting, 0);
+        assert_eq!(stats.idle as u64, stats.opened, "connections returned");
+        stats
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn single_reader_serializes_queries() {
+        let stats = run_concurrent_queries(1, 32).await;
+        assert_eq!(stats.peak_in_use, 1);
+        assert_eq!(stats.opened, 1);
+        assert!(stats.waits > 0, "queued behind the single reader");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn pooled_readers_run_queries_in_parallel() {
+        let stats = run_concurrent_queries(4, 32).await;
+        assert_eq!(stats.max_readers, 4);
+        assert!(stats.peak_in_use > 1, "stats: {stats:?}");
+        assert!(stats.opened > 1 && stats.opened <= 4, "stats: {stats:?}");
+    }
+}
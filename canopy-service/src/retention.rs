@@ -0,0 +1,199 @@
+//! Retained generations: copies of earlier indexes kept for `/expand`.
+//!
+//! Before a reindex replaces generation G, the live index is copied to
+//! `.canopy/generations/<G>` in the repo and tagged with the commit it was
+//! built from, so handles an agent got from G still expand while G+1 is
+//! served to new queries. A repo keeps at most `--retain-generations`
+//! generations counting the live one, and its copies stay within
+//! `--max-disk-per-repo`; the oldest go first.
+
+use crate::state::SharedState;
+use canopy_core::RetainedGeneration;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Generations kept per repo, the live one included.
+pub const DEFAULT_RETAIN_GENERATIONS: usize = 2;
+
+/// Directory under `.canopy/` holding retained generations.
+const GENERATIONS_DIR: &str = "generations";
+
+/// Where the copy of `repo_root`'s `generation` lives.
+pub(crate) fn generation_dir(repo_root: &Path, generation: u64) -> PathBuf {
+    repo_root
+        .join(".canopy")
+        .join(GENERATIONS_DIR)
+        .join(generation.to_string())
+}
+
+/// Split `retained` (oldest first) into the generations to keep and those to
+/// drop: at most `retain - 1` beside the live index, together within
+/// `max_disk` bytes. Both lists stay oldest first.
+pub(crate) fn split_retained(
+    mut retained: Vec<RetainedGeneration>,
+    retain: usize,
+    max_disk: Option<u64>,
+) -> (Vec<RetainedGeneration>, Vec<RetainedGeneration>) {
+    let slots = retain.saturating_sub(1);
+    let mut used = 0u64;
+    // Newest first: keep while there is room, and drop everything older
+    // than the first that doesn't fit
+    let keep = retained
+        .iter()
+        .rev()
+        .take(slots)
+        .take_while(|generation| {
+            used += generation.size_bytes;
+            max_disk.is_none_or(|max| used <= max)
+        })
+        .count();
+    let kept = retained.split_off(retained.len() - keep);
+    (kept, retained)
+}
+
+/// Drop `repo_id`'s retained generations beyond `retain` and `max_disk`:
+/// they leave the shard's list, and their copies are closed and deleted.
+/// Returns the dropped generations, or `None` for an unknown repo.
+pub(crate) async fn prune_repo(
+    state: &SharedState,
+    repo_id: &str,
+    retain: usize,
+    max_disk: Option<u64>,
+) -> Option<Vec<RetainedGeneration>> {
+    let mut shards = state.shards.write().await;
+    let shard = shards.get_mut(repo_id)?;
+    let retained = std::mem::take(&mut shard.retained_generations);
+    let (kept, dropped) = split_retained(retained, retain, max_disk);
+    shard.retained_generations = kept.clone();
+    let repo_root = PathBuf::from(&shard.repo_root);
+    drop(shards);
+
+    state.evict_retained(repo_id, &kept).await;
+    let dirs: Vec<PathBuf> = dropped
+        .iter()
+        .map(|g| generation_dir(&repo_root, g.generation.value()))
+        .collect();
+    let removed = tokio::task::spawn_blocking(move || {
+        dirs.iter()
+            .try_for_each(|dir| match std::fs::remove_dir_all(dir) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            })
+    })
+    .await;
+    match removed {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => warn!("[canopy-service] pruning generations of {repo_id}: {e}"),
+        Err(e) => warn!("[canopy-service] pruning generations of {repo_id} panicked: {e}"),
+    }
+    Some(dropped)
+}
+
+/// Delete every generation copy of `repo_root` not in `kept`, such as
+/// leftovers of an earlier service run. Only safe while no copy is being
+/// written, i.e. from the repo's reindex task.
+pub(crate) fn remove_unretained(repo_root: &Path, kept: &[RetainedGeneration]) -> io::Result<()> {
+    let dir = repo_root.join(".canopy").join(GENERATIONS_DIR);
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let retained = name
+            .to_str()
+            .and_then(|name| name.parse::<u64>().ok())
+            .is_some_and(|generation| kept.iter().any(|g| g.generation.value() == generation));
+        if !retained {
+            std::fs::remove_dir_all(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Parse a disk budget such as "500MB", "2GB" or "1048576" (binary units).
+pub(crate) fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, unit) = s.split_at(split);
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| format!("expected a size like 500MB, got {s:?}"))?;
+    let shift = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        _ => return Err(format!("unknown size unit in {s:?}; use B, KB, MB or GB")),
+    };
+    value
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size {s:?} is too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canopy_core::Generation;
+
+    fn retained(sizes: &[u64]) -> Vec<RetainedGeneration> {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &size_bytes)| RetainedGeneration {
+                generation: Generation::from_value(i as u64 + 1),
+                commit_sha: None,
+                size_bytes,
+            })
+            .collect()
+    }
+
+    fn generations(list: &[RetainedGeneration]) -> Vec<u64> {
+        list.iter().map(|g| g.generation.value()).collect()
+    }
+
+    #[test]
+    fn split_keeps_the_newest_within_count_and_budget() {
+        let (kept, dropped) = split_retained(retained(&[10, 10, 10]), 2, None);
+        assert_eq!(generations(&kept), [3]);
+        assert_eq!(generations(&dropped), [1, 2]);
+
+        let (kept, dropped) = split_retained(retained(&[10, 10, 10]), 10, Some(25));
+        assert_eq!(generations(&kept), [2, 3]);
+        assert_eq!(generations(&dropped), [1]);
+
+        // A newer copy over budget takes the older ones with it
+        let (kept, _) = split_retained(retained(&[1, 100, 1]), 10, Some(50));
+        assert_eq!(generations(&kept), [3]);
+
+        let (kept, dropped) = split_retained(retained(&[10]), 1, None);
+        assert!(kept.is_empty());
+        assert_eq!(generations(&dropped), [1]);
+    }
+
+    #[test]
+    fn remove_unretained_deletes_other_generation_dirs() {
+        let repo = tempfile::TempDir::new().unwrap();
+        for generation in 1..=3 {
+            std::fs::create_dir_all(generation_dir(repo.path(), generation)).unwrap();
+        }
+        remove_unretained(repo.path(), &retained(&[0, 0])[1..]).unwrap();
+        assert!(!generation_dir(repo.path(), 1).exists());
+        assert!(generation_dir(repo.path(), 2).exists());
+        assert!(!generation_dir(repo.path(), 3).exists());
+        remove_unretained(&repo.path().join("missing"), &[]).unwrap();
+    }
+
+    #[test]
+    fn parse_size_accepts_binary_units() {
+        assert_eq!(parse_size("1048576"), Ok(1 << 20));
+        assert_eq!(parse_size("500MB"), Ok(500 << 20));
+        assert_eq!(parse_size("2 gb"), Ok(2 << 30));
+        assert_eq!(parse_size("64k"), Ok(64 << 10));
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("5TB").is_err());
+    }
+}
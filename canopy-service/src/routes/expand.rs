@@ -1,11 +1,15 @@
 //! Expand route handler.
 
+use crate::client_context::{log_fields, RequestClient};
 use crate::error::AppError;
 use crate::feedback_recording::try_record_feedback_expand;
 use crate::state::SharedState;
+use crate::validation::Validated;
 use axum::extract::State;
 use axum::Json;
 use canopy_core::protocol::{ExpandRequest, ExpandResponse, ExpandedContent};
+use canopy_core::LineWindow;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::atomic::Ordering;
 use std::time::Instant;
 
@@ -14,22 +18,43 @@ use tracing::info;
 
 pub(crate) async fn expand(
     State(state): State<SharedState>,
-    Json(req): Json<ExpandRequest>,
+    RequestClient(client): RequestClient,
+    Validated(req): Validated<ExpandRequest>,
 ) -> Result<Json<ExpandResponse>, AppError> {
     let start = Instant::now();
     let repo_label = req.repo.clone();
     let handle_count = req.handles.len();
+    let auto_expanded = req.auto_expanded;
 
     let shard = resolve_ready_shard(&state, &req.repo).await?;
     let current_gen = shard.generation;
 
-    // Validate generation if provided
-    for h in &req.handles {
-        if let Some(gen) = h.generation {
-            if gen != current_gen {
-                return Err(AppError::stale(current_gen, gen));
-            }
+    // Handles expand against the index of their generation: the live one,
+    // or a retained copy of an earlier one. Positions keep the request order.
+    let mut by_generation: BTreeMap<u64, Vec<(usize, String)>> = BTreeMap::new();
+    let windows: HashMap<String, LineWindow> = req
+        .handles
+        .iter()
+        .filter_map(|h| Some((h.id.clone(), h.lines?)))
+        .collect();
+    for (position, h) in req.handles.iter().enumerate() {
+        let gen = h.generation.unwrap_or(current_gen);
+        let retained = shard
+            .retained_generations
+            .iter()
+            .any(|g| g.generation.value() == gen);
+        if gen != current_gen && !retained {
+            let kept: Vec<u64> = shard
+                .retained_generations
+                .iter()
+                .map(|g| g.generation.value())
+                .collect();
+            return Err(AppError::generation_not_retained(current_gen, gen, &kept));
         }
+        by_generation
+            .entry(gen)
+            .or_default()
+            .push((position, h.id.clone()));
     }
     let feedback_store = state
         .feedback_store_for_repo(&shard.repo_id, &shard.repo_root)
@@ -48,18 +73,49 @@ pub(crate) async fn expand(
             .or_insert(0) += 1;
     }
 
-    let handle_ids: Vec<String> = req.handles.iter().map(|h| h.id.clone()).collect();
-    let cached_index = state
-        .get_or_open_index(&repo_id, &repo_root, current_gen)
-        .await
+    let mut positioned = Vec::with_capacity(handle_count);
+    for (gen, handles) in by_generation {
+        let retained = shard
+            .retained_generations
+            .iter()
+            .find(|g| g.generation.value() == gen);
+        let cached_index = match retained {
+            Some(retained) if gen != current_gen => {
+                state
+                    .get_or_open_retained(&repo_id, &repo_root, retained)
+                    .await
+            }
+            _ => {
+                state
+                    .get_or_open_index(&repo_id, &repo_root, current_gen)
+                    .await
+            }
+        }
         .map_err(AppError::from)?;
 
-    let expanded_details = tokio::task::spawn_blocking(move || {
-        let index = cached_index.lock_index()?;
-        index.expand_with_details(&handle_ids)
-    })
-    .await
-    .map_err(AppError::internal)??;
+        let (positions, handle_ids): (Vec<usize>, Vec<String>) = handles.into_iter().unzip();
+        let lease = cached_index.acquire().await;
+        let windows = windows.clone();
+        let details = tokio::task::spawn_blocking(move || {
+            let index = lease.index()?;
+            index.expand_with_windows(&handle_ids, &windows)
+        })
+        .await
+        .map_err(AppError::internal)??;
+        positioned.extend(positions.into_iter().zip(details));
+    }
+    positioned.sort_by_key(|(position, _)| *position);
+    let mut expanded_details: Vec<_> = positioned.into_iter().map(|(_, d)| d).collect();
+
+    let path_filter = state.path_filter(&repo_id).await;
+
+    // Denied handles are dropped and counted, before anything records them
+    let mut suppressed_by_policy = 0;
+    if let Some(filter) = &path_filter {
+        let before = expanded_details.len();
+        expanded_details.retain(|d| filter.permits(&d.file_path));
+        suppressed_by_policy = before - expanded_details.len();
+    }
 
     // Track expanded file paths
     if let Ok(mut analytics) = state.metrics.analytics.lock() {
@@ -84,21 +140,25 @@ pub(crate) async fn expand(
         feedback_store.as_ref(),
         &expanded_details,
         &recent_query_event_ids,
+        auto_expanded,
+        &client,
     ) {
         state.invalidate_node_type_priors_cache(&repo_id).await;
     }
 
+    let redactions = expanded_details.iter().map(|d| d.redactions).sum();
     let duration_ms = start.elapsed().as_millis();
     state
         .metrics
         .total_expand_ms
         .fetch_add(duration_ms as u64, Ordering::Relaxed);
     info!(
-        "[{}] POST /expand repo={} duration_ms={} handles={}",
+        "[{}] POST /expand repo={} duration_ms={} handles={}{}",
         utc_log_timestamp(),
         repo_label,
         duration_ms,
-        handle_count
+        handle_count,
+        log_fields(&client)
     );
 
     Ok(Json(ExpandResponse {
@@ -107,8 +167,11 @@ pub(crate) async fn expand(
             .map(|d| ExpandedContent {
                 handle_id: d.handle_id,
                 content: d.content,
+                note: d.note,
             })
             .collect(),
+        suppressed_by_policy,
+        redactions,
     }))
 }
 
@@ -124,9 +187,11 @@ mod tests {
         let state = test_state();
         let result = expand(
             State(state),
-            Json(ExpandRequest {
+            RequestClient(Default::default()),
+            Validated(ExpandRequest {
                 repo: "nonexistent".to_string(),
                 handles: vec![],
+                auto_expanded: false,
             }),
         )
         .await;
@@ -148,15 +213,127 @@ mod tests {
 
         let result = expand(
             State(state),
-            Json(ExpandRequest {
+            RequestClient(Default::default()),
+            Validated(ExpandRequest {
                 repo: repo_id.to_string(),
                 handles: vec![ExpandHandle {
                     id: "h_abc".to_string(),
                     generation: Some(3),
+                    lines: None,
                 }],
+                auto_expanded: false,
             }),
         )
         .await;
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn expand_cuts_handles_to_their_requested_lines() {
+        let repo = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            repo.path().join("lib.rs"),
+            "pub fn long() {\n    let a = 1;\n    let b = 2;\n    a + b\n}\n",
+        )
+        .unwrap();
+        let mut index = canopy_core::RepoIndex::open_or_init(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        let handle = index.search_code("long", 1).unwrap().remove(0);
+
+        let state = test_state();
+        insert_test_shard(
+            &state,
+            "demo",
+            "demo",
+            ShardStatus::Ready,
+            Generation::from_value(1),
+        )
+        .await;
+        state
+            .shards
+            .write()
+            .await
+            .get_mut("demo")
+            .unwrap()
+            .repo_root = repo.path().to_string_lossy().into_owned();
+
+        let request = |lines: &str| ExpandRequest {
+            repo: "demo".to_string(),
+            handles: vec![ExpandHandle {
+                id: handle.id.to_string(),
+                generation: None,
+                lines: Some(lines.parse().unwrap()),
+            }],
+            auto_expanded: false,
+        };
+        let Json(cut) = expand(
+            State(state.clone()),
+            RequestClient(Default::default()),
+            Validated(request("2-3")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(cut.contents[0].content, "    let a = 1;\n    let b = 2;\n");
+        assert_eq!(cut.contents[0].note, None);
+
+        let Json(whole) = expand(
+            State(state),
+            RequestClient(Default::default()),
+            Validated(request("20-30")),
+        )
+        .await
+        .unwrap();
+        assert!(whole.contents[0].content.starts_with("pub fn long()"));
+        assert!(whole.contents[0].note.as_ref().unwrap().contains("20-30"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn expand_refuses_symlink_escaping_repo() {
+        let repo = tempfile::TempDir::new().unwrap();
+        let outside = tempfile::TempDir::new().unwrap();
+        let file = repo.path().join("lib.rs");
+        std::fs::write(&file, "fn leaked() {}\n").unwrap();
+        let mut index = canopy_core::RepoIndex::open_or_init(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        let handle = index.search_code("leaked", 1).unwrap().remove(0);
+
+        let secret = outside.path().join("secret.rs");
+        std::fs::rename(&file, &secret).unwrap();
+        std::os::unix::fs::symlink(&secret, &file).unwrap();
+
+        let state = test_state();
+        state.shards.write().await.insert(
+            "escape".to_string(),
+            canopy_core::RepoShard {
+                repo_id: "escape".to_string(),
+                repo_root: repo.path().to_string_lossy().into_owned(),
+                name: "escape".to_string(),
+                commit_sha: None,
+                generation: Generation::from_value(1),
+                status: ShardStatus::Ready,
+                error_message: None,
+                last_warmup_at: None,
+                policy: None,
+                retained_generations: Vec::new(),
+            },
+        );
+
+        let err = expand(
+            State(state),
+            RequestClient(Default::default()),
+            Validated(ExpandRequest {
+                repo: "escape".to_string(),
+                handles: vec![ExpandHandle {
+                    id: handle.id.to_string(),
+                    generation: None,
+                    lines: None,
+                }],
+                auto_expanded: false,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.body.code, "path_outside_repo");
+    }
 }
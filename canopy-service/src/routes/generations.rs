@@ -0,0 +1,128 @@
+//! Generation prune route: drop retained generations on demand, e.g. after
+//! lowering `--retain-generations` or to free disk (see [`crate::retention`]).
+
+use crate::error::AppError;
+use crate::retention::prune_repo;
+use crate::state::SharedState;
+use crate::validation::Validated;
+use axum::extract::State;
+use axum::Json;
+use canopy_core::protocol::{PruneGenerationsRequest, PruneGenerationsResponse, PrunedGeneration};
+use tracing::info;
+
+use super::utc_log_timestamp;
+
+pub(crate) async fn prune_generations(
+    State(state): State<SharedState>,
+    Validated(req): Validated<PruneGenerationsRequest>,
+) -> Result<Json<PruneGenerationsResponse>, AppError> {
+    let repo_ids = match req.repo {
+        Some(repo) => {
+            if !state.shards.read().await.contains_key(&repo) {
+                return Err(AppError::repo_not_found());
+            }
+            vec![repo]
+        }
+        None => {
+            let mut repo_ids: Vec<String> = state.shards.read().await.keys().cloned().collect();
+            repo_ids.sort();
+            repo_ids
+        }
+    };
+    let retain = req.retain.unwrap_or(state.retain_generations());
+
+    let mut pruned = Vec::new();
+    for repo_id in repo_ids {
+        let dropped = prune_repo(&state, &repo_id, retain, state.max_disk_per_repo()).await;
+        pruned.extend(
+            dropped
+                .unwrap_or_default()
+                .into_iter()
+                .map(|g| PrunedGeneration {
+                    repo_id: repo_id.clone(),
+                    generation: g.generation,
+                    size_bytes: g.size_bytes,
+                }),
+        );
+    }
+    let freed_bytes = pruned.iter().map(|g| g.size_bytes).sum();
+    info!(
+        "[{}] POST /generations/prune retain={} pruned={} freed_bytes={}",
+        utc_log_timestamp(),
+        retain,
+        pruned.len(),
+        freed_bytes
+    );
+    Ok(Json(PruneGenerationsResponse {
+        pruned,
+        freed_bytes,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retention::generation_dir;
+    use crate::routes::{insert_test_shard, test_state};
+    use canopy_core::{Generation, RetainedGeneration, ShardStatus};
+
+    #[tokio::test]
+    async fn prune_drops_the_oldest_generations_and_their_copies() {
+        let state = test_state();
+        insert_test_shard(
+            &state,
+            "r",
+            "r",
+            ShardStatus::Ready,
+            Generation::from_value(4),
+        )
+        .await;
+        let repo = tempfile::TempDir::new().unwrap();
+        {
+            let mut shards = state.shards.write().await;
+            let shard = shards.get_mut("r").unwrap();
+            shard.repo_root = repo.path().to_string_lossy().into_owned();
+            shard.retained_generations = (1..=3)
+                .map(|generation| RetainedGeneration {
+                    generation: Generation::from_value(generation),
+                    commit_sha: None,
+                    size_bytes: 100,
+                })
+                .collect();
+        }
+        for generation in 1..=3 {
+            std::fs::create_dir_all(generation_dir(repo.path(), generation)).unwrap();
+        }
+
+        let Json(resp) = prune_generations(
+            State(state.clone()),
+            Validated(PruneGenerationsRequest {
+                repo: None,
+                retain: Some(2),
+            }),
+        )
+        .await
+        .unwrap();
+        let dropped: Vec<u64> = resp.pruned.iter().map(|g| g.generation.value()).collect();
+        assert_eq!(dropped, [1, 2]);
+        assert_eq!(resp.freed_bytes, 200);
+        assert!(!generation_dir(repo.path(), 1).exists());
+        assert!(generation_dir(repo.path(), 3).exists());
+        let shards = state.shards.read().await;
+        assert_eq!(shards["r"].retained_generations.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn prune_unknown_repo_is_not_found() {
+        let err = prune_generations(
+            State(test_state()),
+            Validated(PruneGenerationsRequest {
+                repo: Some("missing".to_string()),
+                retain: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.body.code, "repo_not_found");
+    }
+}
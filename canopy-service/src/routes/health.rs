@@ -0,0 +1,224 @@
+//! Orchestration probes: `/healthz` (liveness) and `/readyz` (readiness).
+
+use crate::state::SharedState;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use canopy_core::{RepoIndex, ShardStatus};
+use serde::Serialize;
+use std::path::Path;
+
+/// `/readyz` body, returned with 200 when `ready` and 503 otherwise.
+#[derive(Debug, Serialize)]
+pub(crate) struct Readiness {
+    pub(crate) ready: bool,
+    /// Why the service is not ready; empty when it is
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) reasons: Vec<String>,
+    /// Registered repos that cannot serve queries right now
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) failing_repos: Vec<FailingRepo>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct FailingRepo {
+    pub(crate) repo_id: String,
+    pub(crate) name: String,
+    pub(crate) status: ShardStatus,
+    pub(crate) error: String,
+}
+
+/// Liveness: the process is up and serving requests. Does no work.
+pub(crate) async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Readiness: startup finished, not shutting down, and either no repos are
+/// registered or at least one is ready, with every ready repo's index readable.
+pub(crate) async fn readyz(State(state): State<SharedState>) -> (StatusCode, Json<Readiness>) {
+    let readiness = check_readiness(&state).await;
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
+
+pub(crate) async fn check_readiness(state: &SharedState) -> Readiness {
+    let mut reasons = Vec::new();
+    if !state.lifecycle.is_started() {
+        reasons.push("service state is still loading".to_string());
+    }
+    if state.lifecycle.is_shutting_down() {
+        reasons.push("service is shutting down".to_string());
+    }
+
+    // Copy shard metadata out so the probes below run without the lock
+    let shards: Vec<_> = {
+        let shards = state.shards.read().await;
+        shards
+            .values()
+            .map(|s| {
+                (
+                    s.repo_id.clone(),
+                    s.name.clone(),
+                    s.repo_root.clone(),
+                    s.status.clone(),
+                    s.error_message.clone(),
+                )
+            })
+            .collect()
+    };
+    let registered = shards.len();
+
+    let probes = tokio::task::spawn_blocking(move || {
+        shards
+            .into_iter()
+            .map(|(repo_id, name, repo_root, status, error_message)| {
+                let error = if status == ShardStatus::Ready {
+                    RepoIndex::probe(Path::new(&repo_root))
+                        .err()
+                        .map(|e| format!("index unreadable: {}", e))
+                } else {
+                    Some(error_message.unwrap_or_else(|| format!("repo is {:?}", status)))
+                };
+                (repo_id, name, status, error)
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_default();
+
+    let mut serving = 0;
+    let mut unreadable = 0;
+    let mut failing_repos = Vec::new();
+    for (repo_id, name, status, error) in probes {
+        match error {
+            None => serving += 1,
+            Some(error) => {
+                if status == ShardStatus::Ready {
+                    unreadable += 1;
+                }
+                failing_repos.push(FailingRepo {
+                    repo_id,
+                    name,
+                    status,
+                    error,
+                });
+            }
+        }
+    }
+    failing_repos.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if unreadable > 0 {
+        reasons.push(format!(
+            "{} ready repo(s) have an unreadable index",
+            unreadable
+        ));
+    } else if registered > 0 && serving == 0 {
+        reasons.push("no registered repo is ready".to_string());
+    }
+
+    Readiness {
+        ready: reasons.is_empty(),
+        reasons,
+        failing_repos,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::{insert_test_shard, test_state};
+    use canopy_core::Generation;
+    use tempfile::TempDir;
+
+    fn indexed_repo() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        RepoIndex::init(dir.path()).unwrap();
+        RepoIndex::open(dir.path()).unwrap();
+        dir
+    }
+
+    async fn set_shard(state: &SharedState, repo_id: &str, status: ShardStatus, root: &str) {
+        insert_test_shard(state, repo_id, repo_id, status, Generation::from_value(1)).await;
+        state
+            .shards
+            .write()
+            .await
+            .get_mut(repo_id)
+            .unwrap()
+            .repo_root = root.to_string();
+    }
+
+    #[tokio::test]
+    async fn not_ready_until_started() {
+        let state = test_state();
+        let readiness = check_readiness(&state).await;
+        assert!(!readiness.ready);
+        assert_eq!(readiness.reasons, vec!["service state is still loading"]);
+
+        state.lifecycle.mark_started();
+        let readiness = check_readiness(&state).await;
+        assert!(readiness.ready, "zero registered repos is ready");
+        assert!(readiness.failing_repos.is_empty());
+    }
+
+    #[tokio::test]
+    async fn readiness_follows_shard_states() {
+        let state = test_state();
+        state.lifecycle.mark_started();
+        let repo = indexed_repo();
+        let root = repo.path().to_string_lossy().to_string();
+
+        set_shard(&state, "r1", ShardStatus::Indexing, &root).await;
+        let readiness = check_readiness(&state).await;
+        assert!(!readiness.ready);
+        assert_eq!(readiness.failing_repos.len(), 1);
+        assert_eq!(readiness.failing_repos[0].status, ShardStatus::Indexing);
+
+        set_shard(&state, "r1", ShardStatus::Ready, &root).await;
+        assert!(check_readiness(&state).await.ready);
+
+        // Another repo mid-reindex doesn't make the service unready
+        set_shard(&state, "r2", ShardStatus::Indexing, &root).await;
+        let readiness = check_readiness(&state).await;
+        assert!(readiness.ready);
+        assert_eq!(readiness.failing_repos.len(), 1);
+        assert_eq!(readiness.failing_repos[0].repo_id, "r2");
+    }
+
+    #[tokio::test]
+    async fn ready_repo_with_unreadable_index_fails() {
+        let state = test_state();
+        state.lifecycle.mark_started();
+        let repo = indexed_repo();
+        set_shard(
+            &state,
+            "good",
+            ShardStatus::Ready,
+            &repo.path().to_string_lossy(),
+        )
+        .await;
+        set_shard(&state, "gone", ShardStatus::Ready, "/tmp/fake").await;
+
+        let (status, Json(readiness)) = readyz(State(state)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(readiness.failing_repos.len(), 1);
+        assert_eq!(readiness.failing_repos[0].repo_id, "gone");
+        assert!(readiness.failing_repos[0].error.contains("unreadable"));
+    }
+
+    #[tokio::test]
+    async fn shutdown_fails_readiness() {
+        let state = test_state();
+        state.lifecycle.mark_started();
+        state.lifecycle.begin_shutdown();
+        let (status, Json(readiness)) = readyz(State(state.clone())).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(readiness.reasons, vec!["service is shutting down"]);
+        // Already-requested shutdown resolves immediately
+        state.lifecycle.shutdown_requested().await;
+    }
+}
@@ -1,17 +1,37 @@
 //! HTTP route handlers for the canopy service.
 
 mod expand;
+mod generations;
+mod health;
 mod query;
+mod references;
+mod related;
 mod repos;
+mod schema;
+mod summary;
+mod symbols;
+mod ui;
+mod validate;
+mod warmup;
 
 pub(crate) use expand::expand;
+pub(crate) use generations::prune_generations;
+pub(crate) use health::{healthz, readyz};
 pub(crate) use query::{evidence_pack, query};
-pub(crate) use repos::{add_repo, list_repos, reindex, status};
+pub(crate) use references::references;
+pub(crate) use related::related;
+pub(crate) use repos::{add_repo, list_repos, reindex, set_policy, status};
+pub(crate) use schema::schema;
+pub(crate) use summary::summary;
+pub(crate) use symbols::symbols;
+pub(crate) use ui::{ui_routes, UiOptions};
+pub(crate) use validate::validate;
+pub(crate) use warmup::warmup;
 
 use crate::error::AppError;
 use crate::state::SharedState;
 use canopy_core::{
-    query::execute_query_with_options, HandleSource, NodeType, QueryParams, QueryResult,
+    query::execute_params, HandleSource, NodeType, QueryParams, QueryResult, RetainedGeneration,
     ShardStatus,
 };
 use std::collections::HashMap;
@@ -23,6 +43,8 @@ pub(crate) struct ReadyShard {
     pub(crate) repo_root: String,
     pub(crate) commit_sha: Option<String>,
     pub(crate) generation: u64,
+    /// Earlier generations `/expand` still serves
+    pub(crate) retained_generations: Vec<RetainedGeneration>,
 }
 
 /// Look up a shard by repo name and validate it is ready.
@@ -43,6 +65,7 @@ pub(crate) async fn resolve_ready_shard(
         repo_root: shard.repo_root.clone(),
         commit_sha: shard.commit_sha.clone(),
         generation: shard.generation.value(),
+        retained_generations: shard.retained_generations.clone(),
     })
 }
 
@@ -62,11 +85,22 @@ async fn query_with_cache(
     params: &QueryParams,
     node_type_priors: Option<HashMap<NodeType, f64>>,
 ) -> Result<(QueryResult, bool), AppError> {
+    // Counts see only what the repo's policy permits, so they depend on the
+    // policy as well as the index and bypass the cache
+    let permits = match params.mode.is_handles() {
+        true => None,
+        false => state.path_filter(repo_id).await,
+    };
     let cache_key = serde_json::to_string(params).map_err(AppError::internal)?;
-    if let Some(result) = state
-        .get_cached_query(repo_id, &cache_key, generation)
-        .await
-    {
+    let cached = match permits {
+        Some(_) => None,
+        None => {
+            state
+                .get_cached_query(repo_id, &cache_key, generation)
+                .await
+        }
+    };
+    if let Some(result) = cached {
         state
             .metrics
             .query_cache_hits
@@ -86,25 +120,30 @@ async fn query_with_cache(
 
     let params = params.clone();
     let commit_sha = commit_sha.clone();
-    let result = tokio::task::spawn_blocking(move || {
-        let index = cached_index.lock_index()?;
-        let query = params.to_query()?;
+    let uncached = permits.is_some();
+    let lease = cached_index.acquire().await;
+    let (result, scans) = tokio::task::spawn_blocking(move || {
+        let index = lease.index()?;
         let mut options = params.to_options();
         if options.node_type_priors.is_none() {
             options.node_type_priors = node_type_priors;
         }
-        let mut result = execute_query_with_options(&query, &index, options)?;
+        if let Some(permits) = permits {
+            options = options.with_path_predicate(permits);
+        }
+        let mut result = execute_params(&params, &index, options)?;
         for handle in &mut result.handles {
             handle.source = HandleSource::Service;
             handle.commit_sha = commit_sha.clone();
             handle.generation = Some(generation);
         }
-        Ok::<_, canopy_core::CanopyError>(result)
+        Ok::<_, canopy_core::CanopyError>((result, index.take_scan_stats()))
     })
     .await
     .map_err(AppError::internal)??;
+    state.metrics.record_fts_scans(repo_id, &scans);
 
-    if !result.auto_expanded {
+    if !result.auto_expanded && !uncached {
         state
             .insert_cached_query(repo_id, cache_key, result.clone(), generation)
             .await;
@@ -137,6 +176,9 @@ pub(super) async fn insert_test_shard(
             generation,
             status,
             error_message: None,
+            last_warmup_at: None,
+            policy: None,
+            retained_generations: Vec::new(),
         },
     );
 }
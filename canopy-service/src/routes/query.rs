@@ -1,13 +1,18 @@
 //! Query and evidence_pack route handlers.
 
+use crate::client_context::{log_fields, RequestClient};
 use crate::error::AppError;
 use crate::evidence::{normalize_query_params, run_evidence_plan};
 use crate::feedback_recording::try_record_feedback_query;
 use crate::state::SharedState;
+use crate::validation::Validated;
 use axum::extract::State;
 use axum::Json;
 use canopy_core::protocol::{EvidencePackRequest, QueryRequest};
-use canopy_core::{build_evidence_pack, EvidencePack, QueryParams, QueryResult};
+use canopy_core::{
+    build_evidence_pack_with_priors, EvidenceConfig, EvidencePack, QueryParams, QueryResult,
+};
+use std::path::Path;
 use std::sync::atomic::Ordering;
 use std::time::Instant;
 
@@ -16,7 +21,8 @@ use tracing::info;
 
 pub(crate) async fn query(
     State(state): State<SharedState>,
-    Json(req): Json<QueryRequest>,
+    RequestClient(client): RequestClient,
+    Validated(req): Validated<QueryRequest>,
 ) -> Result<Json<QueryResult>, AppError> {
     let start = Instant::now();
     let repo_label = req.repo.clone();
@@ -47,7 +53,8 @@ pub(crate) async fn query(
 
     state.metrics.query_count.fetch_add(1, Ordering::Relaxed);
 
-    let (result, was_hit) = query_with_cache(
+    let path_filter = state.path_filter(&shard.repo_id).await;
+    let (mut result, was_hit) = query_with_cache(
         &state,
         &shard.repo_id,
         &shard.repo_root,
@@ -57,9 +64,12 @@ pub(crate) async fn query(
         node_type_priors,
     )
     .await?;
+    if let Some(filter) = &path_filter {
+        filter.filter_result(&mut result);
+    }
 
     if let Some(query_event_id) =
-        try_record_feedback_query(feedback_store.as_ref(), &params, &result)
+        try_record_feedback_query(feedback_store.as_ref(), &params, &result, &client)
     {
         let handle_ids: Vec<String> = result.handles.iter().map(|h| h.id.to_string()).collect();
         state
@@ -77,11 +87,12 @@ pub(crate) async fn query(
         .fetch_add(duration_ms as u64, Ordering::Relaxed);
     let cache_state = if was_hit { "hit" } else { "miss" };
     info!(
-        "[{}] POST /query repo={} duration_ms={} cache={}",
+        "[{}] POST /query repo={} duration_ms={} cache={}{}",
         utc_log_timestamp(),
         repo_label,
         duration_ms,
-        cache_state
+        cache_state,
+        log_fields(&client)
     );
 
     Ok(Json(result))
@@ -89,7 +100,8 @@ pub(crate) async fn query(
 
 pub(crate) async fn evidence_pack(
     State(state): State<SharedState>,
-    Json(req): Json<EvidencePackRequest>,
+    RequestClient(client): RequestClient,
+    Validated(req): Validated<EvidencePackRequest>,
 ) -> Result<Json<EvidencePack>, AppError> {
     let start = Instant::now();
     let repo_label = req.repo.clone();
@@ -109,11 +121,17 @@ pub(crate) async fn evidence_pack(
 
     state.metrics.query_count.fetch_add(1, Ordering::Relaxed);
 
+    // Each step is filtered, so planning only sees what the policy permits
+    let path_filter = state.path_filter(&shard.repo_id).await;
+    let calibration = EvidenceConfig::for_repo(Path::new(&shard.repo_root))
+        .and_then(|config| config.calibration())
+        .map_err(AppError::from)?;
     let plan_result = run_evidence_plan(
         seed_params,
         req.config.plan,
         max_handles,
         max_per_file,
+        &calibration,
         |params: QueryParams| {
             let s = state.clone();
             let rid = shard.repo_id.clone();
@@ -121,8 +139,14 @@ pub(crate) async fn evidence_pack(
             let csha = shard.commit_sha.clone();
             let gen = shard.generation;
             let priors = node_type_priors.clone();
+            let filter = path_filter.clone();
             Box::pin(async move {
-                query_with_cache(&s, &rid, &rroot, gen, &csha, &params, priors).await
+                let (mut result, was_hit) =
+                    query_with_cache(&s, &rid, &rroot, gen, &csha, &params, priors).await?;
+                if let Some(filter) = &filter {
+                    filter.filter_result(&mut result);
+                }
+                Ok::<_, AppError>((result, was_hit))
             })
         },
     )
@@ -132,6 +156,7 @@ pub(crate) async fn evidence_pack(
         feedback_store.as_ref(),
         &plan_result.seed_params,
         &plan_result.result,
+        &client,
     ) {
         let handle_ids: Vec<String> = plan_result
             .result
@@ -147,11 +172,16 @@ pub(crate) async fn evidence_pack(
             .await;
     }
 
-    let mut pack = build_evidence_pack(
+    let mut pack = build_evidence_pack_with_priors(
         &plan_result.result,
         &plan_result.query_text,
         max_handles,
         max_per_file,
+        plan_result
+            .seed_params
+            .node_type_priors()
+            .map_err(AppError::from)?,
+        &calibration,
     );
     let suggested_ids = pack.expand_suggestion.clone();
     let recent_expanded = state
@@ -172,14 +202,15 @@ pub(crate) async fn evidence_pack(
         "miss"
     };
     info!(
-        "[{}] POST /evidence_pack repo={} duration_ms={} cache={} plan={} steps={} selected={}",
+        "[{}] POST /evidence_pack repo={} duration_ms={} cache={} plan={} steps={} selected={}{}",
         utc_log_timestamp(),
         repo_label,
         duration_ms,
         cache_state,
         plan_result.planning_enabled,
         plan_result.plan_steps,
-        pack.selected_count
+        pack.selected_count,
+        log_fields(&client)
     );
 
     Ok(Json(pack))
@@ -196,7 +227,8 @@ mod tests {
         let state = test_state();
         let result = query(
             State(state),
-            Json(QueryRequest {
+            RequestClient(Default::default()),
+            Validated(QueryRequest {
                 repo: "nonexistent".to_string(),
                 params: QueryParams::new(),
             }),
@@ -220,7 +252,8 @@ mod tests {
 
         let result = query(
             State(state),
-            Json(QueryRequest {
+            RequestClient(Default::default()),
+            Validated(QueryRequest {
                 repo: repo_id.to_string(),
                 params: QueryParams::new(),
             }),
@@ -0,0 +1,126 @@
+//! References-by-file route handler.
+
+use crate::error::AppError;
+use crate::state::SharedState;
+use crate::validation::Validated;
+use axum::extract::State;
+use axum::Json;
+use canopy_core::protocol::ReferencesRequest;
+use canopy_core::{FileReferences, DEFAULT_REFERENCE_FILES_LIMIT};
+use std::time::Instant;
+
+use super::{resolve_ready_shard, utc_log_timestamp};
+use tracing::info;
+
+pub(crate) async fn references(
+    State(state): State<SharedState>,
+    Validated(req): Validated<ReferencesRequest>,
+) -> Result<Json<FileReferences>, AppError> {
+    let start = Instant::now();
+    let shard = resolve_ready_shard(&state, &req.repo).await?;
+    let limit = req.limit.unwrap_or(DEFAULT_REFERENCE_FILES_LIMIT);
+
+    let cached_index = state
+        .get_or_open_index(&shard.repo_id, &shard.repo_root, shard.generation)
+        .await
+        .map_err(AppError::from)?;
+    let path_filter = state.path_filter(&shard.repo_id).await;
+    let lease = cached_index.acquire().await;
+    let (symbol, ref_types, glob) = (req.symbol.clone(), req.ref_types.clone(), req.glob.clone());
+    // Group every file first so denied files don't eat into the limit
+    let fetch = if path_filter.is_some() {
+        usize::MAX
+    } else {
+        limit
+    };
+    let mut references = tokio::task::spawn_blocking(move || {
+        let index = lease.index()?;
+        index.references_by_file(&symbol, &ref_types, glob.as_deref(), fetch)
+    })
+    .await
+    .map_err(AppError::internal)??;
+    if let Some(filter) = &path_filter {
+        let denied: Vec<usize> = references
+            .files
+            .iter()
+            .filter(|file| !filter.permits(&file.file_path))
+            .map(|file| file.count)
+            .collect();
+        references
+            .files
+            .retain(|file| filter.permits(&file.file_path));
+        references.total_files -= denied.len();
+        references.total_references -= denied.iter().sum::<usize>();
+        references.truncated |= references.files.len() > limit;
+        references.files.truncate(limit);
+    }
+
+    info!(
+        "[{}] POST /references repo={} symbol={} duration_ms={} files={}",
+        utc_log_timestamp(),
+        req.repo,
+        req.symbol,
+        start.elapsed().as_millis(),
+        references.files.len()
+    );
+    Ok(Json(references))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::{insert_test_shard, test_state};
+    use canopy_core::{Generation, RefType, RepoIndex, ShardStatus};
+
+    #[tokio::test]
+    async fn references_group_by_file_in_a_ready_repo() {
+        let repo = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            repo.path().join("server.rs"),
+            "pub fn serve() {\n    dispatch();\n    dispatch();\n}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            repo.path().join("client.rs"),
+            "pub fn send() { dispatch(); }\n",
+        )
+        .unwrap();
+        let mut index = RepoIndex::open_or_init(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let state = test_state();
+        insert_test_shard(
+            &state,
+            "demo",
+            "demo",
+            ShardStatus::Ready,
+            Generation::from_value(1),
+        )
+        .await;
+        state
+            .shards
+            .write()
+            .await
+            .get_mut("demo")
+            .unwrap()
+            .repo_root = repo.path().to_string_lossy().into_owned();
+
+        let Json(body) = references(
+            State(state),
+            Validated(ReferencesRequest {
+                repo: "demo".to_string(),
+                symbol: "dispatch".to_string(),
+                ref_types: vec![RefType::Call],
+                glob: None,
+                limit: Some(1),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(body.files.len(), 1);
+        assert_eq!(body.files[0].file_path, "server.rs");
+        assert_eq!(body.files[0].lines, [2, 3]);
+        assert_eq!((body.total_files, body.total_references), (2, 3));
+        assert!(body.truncated);
+    }
+}
@@ -0,0 +1,118 @@
+//! Related files route handler.
+
+use crate::error::AppError;
+use crate::state::SharedState;
+use crate::validation::Validated;
+use axum::extract::State;
+use axum::Json;
+use canopy_core::protocol::RelatedRequest;
+use canopy_core::{RelatedFiles, DEFAULT_RELATED_LIMIT};
+use std::time::Instant;
+
+use super::{resolve_ready_shard, utc_log_timestamp};
+use tracing::info;
+
+pub(crate) async fn related(
+    State(state): State<SharedState>,
+    Validated(req): Validated<RelatedRequest>,
+) -> Result<Json<RelatedFiles>, AppError> {
+    let start = Instant::now();
+    let shard = resolve_ready_shard(&state, &req.repo).await?;
+    let limit = req.limit.unwrap_or(DEFAULT_RELATED_LIMIT);
+
+    let cached_index = state
+        .get_or_open_index(&shard.repo_id, &shard.repo_root, shard.generation)
+        .await
+        .map_err(AppError::from)?;
+    let path_filter = state.path_filter(&shard.repo_id).await;
+    let lease = cached_index.acquire().await;
+    let path = req.path.clone();
+    let mut related = tokio::task::spawn_blocking(move || {
+        let index = lease.index()?;
+        index.related_files(&path, limit)
+    })
+    .await
+    .map_err(AppError::internal)??;
+    if let Some(filter) = &path_filter {
+        // A denied file answers as if it weren't indexed
+        if !filter.permits(&related.path) {
+            return Err(AppError::file_not_found(std::path::Path::new(&req.path)));
+        }
+        let before = related.files.len();
+        related.files.retain(|file| filter.permits(&file.path));
+        related.total_related -= before - related.files.len();
+    }
+
+    info!(
+        "[{}] POST /related repo={} path={} duration_ms={} files={}",
+        utc_log_timestamp(),
+        req.repo,
+        req.path,
+        start.elapsed().as_millis(),
+        related.files.len()
+    );
+    Ok(Json(related))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::{insert_test_shard, test_state};
+    use canopy_core::{Generation, RepoIndex, ShardStatus};
+
+    #[tokio::test]
+    async fn related_ranks_files_of_a_ready_repo() {
+        let repo = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            repo.path().join("session.rs"),
+            "pub fn load_session() { verify_token(); }\n",
+        )
+        .unwrap();
+        std::fs::write(repo.path().join("token.rs"), "pub fn verify_token() {}\n").unwrap();
+        let mut index = RepoIndex::open_or_init(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let state = test_state();
+        insert_test_shard(
+            &state,
+            "demo",
+            "demo",
+            ShardStatus::Ready,
+            Generation::from_value(1),
+        )
+        .await;
+        state
+            .shards
+            .write()
+            .await
+            .get_mut("demo")
+            .unwrap()
+            .repo_root = repo.path().to_string_lossy().into_owned();
+
+        let Json(body) = related(
+            State(state.clone()),
+            Validated(RelatedRequest {
+                repo: "demo".to_string(),
+                path: "session.rs".to_string(),
+                limit: None,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(body.files.len(), 1);
+        assert_eq!(body.files[0].path, "token.rs");
+        assert_eq!(body.files[0].defines[0].name, "verify_token");
+
+        let missing = related(
+            State(state),
+            Validated(RelatedRequest {
+                repo: "demo".to_string(),
+                path: "nope.rs".to_string(),
+                limit: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(missing.body.code, "file_not_found");
+    }
+}
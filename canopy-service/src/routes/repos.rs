@@ -1,39 +1,101 @@
-//! Repo management route handlers: add_repo, list_repos, status, reindex.
-
+//! Repo management route handlers: add_repo, list_repos, set_policy, status,
+//! reindex.
+//!
+//! A repo is either an existing checkout at a path or, added by `git_url`,
+//! one the service clones and updates itself (see [`crate::checkout`]).
+//! Either may carry a path policy (see [`crate::policy`]).
+
+use crate::auth::KeyName;
+use crate::checkout::repo_name_from_url;
 use crate::error::AppError;
+use crate::policy::PathFilter;
+use crate::retention::{generation_dir, prune_repo, remove_unretained};
 use crate::state::SharedState;
+use crate::validation::Validated;
 use axum::extract::State;
-use axum::Json;
+use axum::{Extension, Json};
 use canopy_core::protocol::{
-    AddRepoRequest, AddRepoResponse, ReindexRequest, ReindexResponse, ServiceStatus,
+    AddRepoRequest, AddRepoResponse, PathPolicy, ReindexRequest, ReindexResponse, ServiceStatus,
+    SetPolicyRequest,
 };
-use canopy_core::{Generation, RepoIndex, RepoShard, ShardStatus};
-use std::path::Path;
+use canopy_core::{Generation, RepoIndex, RepoShard, RetainedGeneration, ShardStatus};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 
 use super::utc_log_timestamp;
-use tracing::info;
+use super::warmup::warm_repo;
+use tracing::{info, warn};
 
 pub(crate) async fn add_repo(
     State(state): State<SharedState>,
-    Json(req): Json<AddRepoRequest>,
+    Validated(req): Validated<AddRepoRequest>,
 ) -> Result<Json<AddRepoResponse>, AppError> {
-    let path = std::path::Path::new(&req.path);
+    // Checked before registering, so a bad policy doesn't leave a repo behind
+    let policy = match req.policy.clone() {
+        Some(policy) => Some(compile_policy(&state, policy)?),
+        None => None,
+    };
+    let added = match (&req.git_url, &req.path) {
+        (Some(git_url), _) => add_managed_repo(&state, git_url, &req).await?,
+        (None, Some(path)) => add_local_repo(&state, path, req.name.clone()).await?,
+        (None, None) => return Err(AppError::invalid_repo("Provide a path or a git_url")),
+    };
+    // The shard isn't queryable before its first index, so nothing is
+    // served without the policy
+    if let Some((policy, filter)) = policy {
+        state.set_policy(&added.repo_id, policy, filter).await;
+    }
+    Ok(added)
+}
+
+/// Replace a repo's path policy (admin only; an empty policy clears it).
+pub(crate) async fn set_policy(
+    State(state): State<SharedState>,
+    Validated(req): Validated<SetPolicyRequest>,
+) -> Result<Json<RepoShard>, AppError> {
+    let (policy, filter) = compile_policy(&state, req.policy)?;
+    let shard = state
+        .set_policy(&req.repo, policy, filter)
+        .await
+        .ok_or_else(AppError::repo_not_found)?;
+    info!(
+        "[{}] POST /repos/policy repo={} allow={} deny={}",
+        utc_log_timestamp(),
+        req.repo,
+        shard.policy.as_ref().map_or(0, |p| p.allow.len()),
+        shard.policy.as_ref().map_or(0, |p| p.deny.len())
+    );
+    Ok(Json(shard))
+}
+
+/// `policy` with its compiled filter, provided this service may set policies.
+fn compile_policy(
+    state: &SharedState,
+    policy: PathPolicy,
+) -> Result<(PathPolicy, PathFilter), AppError> {
+    if !state.admin_key() {
+        return Err(AppError::policies_need_api_key());
+    }
+    let filter = PathFilter::compile(&policy).map_err(AppError::invalid_policy)?;
+    Ok((policy, filter))
+}
+
+async fn add_local_repo(
+    state: &SharedState,
+    repo_path: &str,
+    name: Option<String>,
+) -> Result<Json<AddRepoResponse>, AppError> {
+    let path = std::path::Path::new(repo_path);
 
     // Validate it's a git repo
     if !path.join(".git").exists() {
-        return Err(AppError {
-            status: axum::http::StatusCode::BAD_REQUEST,
-            body: crate::error::ErrorEnvelope::new(
-                "invalid_repo",
-                "Not a git repository",
-                "Provide a path to a git repository root",
-            ),
-        });
+        return Err(AppError::invalid_repo(
+            "Provide a path to a git repository root",
+        ));
     }
 
     // Canonicalize path ONCE before taking the lock
-    let canonical = std::fs::canonicalize(&req.path)
+    let canonical = std::fs::canonicalize(repo_path)
         .map_err(AppError::internal)?
         .to_string_lossy()
         .to_string();
@@ -48,7 +110,7 @@ pub(crate) async fn add_repo(
         .map_err(AppError::internal)??;
     }
 
-    let name = req.name.unwrap_or_else(|| {
+    let name = name.unwrap_or_else(|| {
         path.file_name()
             .map(|n| n.to_string_lossy().to_string())
             .unwrap_or_else(|| "unnamed".to_string())
@@ -81,6 +143,9 @@ pub(crate) async fn add_repo(
         generation: Generation::new(),
         status: ShardStatus::Pending,
         error_message: None,
+        last_warmup_at: None,
+        policy: None,
+        retained_generations: Vec::new(),
     };
 
     shards.insert(repo_id.clone(), shard);
@@ -96,6 +161,87 @@ pub(crate) async fn add_repo(
     Ok(Json(AddRepoResponse { repo_id, name }))
 }
 
+/// Register `git_url` as a checkout the service owns and clone it. A failed
+/// clone still registers the repo, in `error` status with git's message;
+/// a reindex retries the clone.
+async fn add_managed_repo(
+    state: &SharedState,
+    git_url: &str,
+    req: &AddRepoRequest,
+) -> Result<Json<AddRepoResponse>, AppError> {
+    let dir = state
+        .checkout_dir()
+        .ok_or_else(AppError::managed_checkouts_disabled)?;
+    let sparse_paths = req.sparse_paths.clone().unwrap_or_default();
+    let (repo_id, checkout, created) = state
+        .register_checkout(dir, git_url, req.branch.as_deref(), &sparse_paths)
+        .await;
+
+    if !created {
+        let shards = state.shards.read().await;
+        let name = shards
+            .get(&repo_id)
+            .map(|shard| shard.name.clone())
+            .unwrap_or_default();
+        info!(
+            "[{}] POST /repos/add git_url={} repo_id={} (existing)",
+            utc_log_timestamp(),
+            git_url,
+            repo_id
+        );
+        return Ok(Json(AddRepoResponse { repo_id, name }));
+    }
+
+    let name = req
+        .name
+        .clone()
+        .unwrap_or_else(|| repo_name_from_url(git_url));
+    state.shards.write().await.insert(
+        repo_id.clone(),
+        RepoShard {
+            repo_id: repo_id.clone(),
+            repo_root: checkout.root.to_string_lossy().into_owned(),
+            name: name.clone(),
+            commit_sha: None,
+            generation: Generation::new(),
+            status: ShardStatus::Pending,
+            error_message: None,
+            last_warmup_at: None,
+            policy: None,
+            retained_generations: Vec::new(),
+        },
+    );
+
+    let root = checkout.root.clone();
+    let cloned = tokio::task::spawn_blocking(move || {
+        checkout.sync()?;
+        Ok::<_, canopy_core::CanopyError>(canopy_core::git::head_commit_sha(&root))
+    })
+    .await
+    .map_err(AppError::internal)?;
+
+    let mut shards = state.shards.write().await;
+    if let Some(shard) = shards.get_mut(&repo_id) {
+        match cloned {
+            Ok(commit_sha) => shard.commit_sha = commit_sha,
+            Err(e) => {
+                shard.status = ShardStatus::Error;
+                shard.error_message = Some(e.to_string());
+            }
+        }
+    }
+    drop(shards);
+
+    info!(
+        "[{}] POST /repos/add name={} git_url={} repo_id={}",
+        utc_log_timestamp(),
+        name,
+        git_url,
+        repo_id
+    );
+    Ok(Json(AddRepoResponse { repo_id, name }))
+}
+
 pub(crate) async fn list_repos(State(state): State<SharedState>) -> Json<Vec<RepoShard>> {
     let shards = state.shards.read().await;
     Json(shards.values().cloned().collect())
@@ -103,15 +249,24 @@ pub(crate) async fn list_repos(State(state): State<SharedState>) -> Json<Vec<Rep
 
 pub(crate) async fn status(State(state): State<SharedState>) -> Json<ServiceStatus> {
     let shards = state.shards.read().await;
+    // /status is public; policies are only listed on the admin /repos route
+    let repos = shards
+        .values()
+        .map(|shard| RepoShard {
+            policy: None,
+            ..shard.clone()
+        })
+        .collect();
     Json(ServiceStatus {
         service: "canopy-service".to_string(),
-        repos: shards.values().cloned().collect(),
+        repos,
     })
 }
 
 pub(crate) async fn reindex(
     State(state): State<SharedState>,
-    Json(req): Json<ReindexRequest>,
+    key: Option<Extension<KeyName>>,
+    Validated(req): Validated<ReindexRequest>,
 ) -> Result<Json<ReindexResponse>, AppError> {
     let repo_label = req.repo.clone();
     let mut shards = state.shards.write().await;
@@ -137,43 +292,126 @@ pub(crate) async fn reindex(
     let repo_root = shard.repo_root.clone();
     let repo_id = shard.repo_id.clone();
     let glob = req.glob;
+    let paths: Vec<PathBuf> = req.paths.iter().map(PathBuf::from).collect();
+    // The generation being replaced is copied first, so its handles keep
+    // expanding once the next one is served
+    let outgoing = (state.retain_generations() > 1 && shard.generation.value() > 0).then(|| {
+        RetainedGeneration {
+            generation: shard.generation,
+            commit_sha: shard.commit_sha.clone(),
+            size_bytes: 0,
+        }
+    });
+    let retained_before = shard.retained_generations.clone();
     drop(shards);
+    let checkout = state.checkout(&repo_id).await;
 
     state.metrics.reindex_count.fetch_add(1, Ordering::Relaxed);
     info!(
-        "[{}] POST /reindex repo={} status=started",
+        "[{}] POST /reindex repo={} status=started paths={} key={}",
         utc_log_timestamp(),
-        repo_label
+        repo_label,
+        paths.len(),
+        key.as_ref()
+            .map_or("-", |Extension(KeyName(name))| name.as_str())
     );
 
     let state_clone = state.clone();
 
     tokio::task::spawn(async move {
-        let result = tokio::task::spawn_blocking({
+        let work = tokio::task::spawn_blocking({
             let repo_root = repo_root.clone();
             let glob = glob.clone();
+            let repo_id_for_log = repo_id.clone();
             move || {
+                // Managed checkouts follow their branch: fetch and reset first
+                if let Some(checkout) = checkout {
+                    checkout.sync()?;
+                }
                 let commit_sha = canopy_core::git::head_commit_sha(Path::new(&repo_root));
 
                 let mut index = RepoIndex::open(Path::new(&repo_root))?;
-                let default_glob = index.config().default_glob().to_string();
-                let glob_str = glob.as_deref().unwrap_or(&default_glob);
-                let _stats = index.index(glob_str)?;
+                let retained = outgoing.and_then(|outgoing| {
+                    retain_copy(&index, Path::new(&repo_root), outgoing, &retained_before)
+                });
+                if paths.is_empty() {
+                    let default_glob = index.config().default_glob().to_string();
+                    let glob_str = glob.as_deref().unwrap_or(&default_glob);
+                    index.index(glob_str)?;
+                } else {
+                    let stats = index.index_paths(&paths)?;
+                    for error in &stats.errors {
+                        info!(
+                            "[{}] reindex repo={} skipped path={} reason={}",
+                            utc_log_timestamp(),
+                            repo_id_for_log,
+                            error.path,
+                            error.reason
+                        );
+                    }
+                }
 
-                Ok::<_, canopy_core::CanopyError>(commit_sha)
+                Ok::<_, canopy_core::CanopyError>((commit_sha, retained))
             }
-        })
-        .await;
+        });
+
+        // On shutdown, stop waiting: the blocking indexer can't be interrupted,
+        // but the shard must not be reported ready with a half-written index
+        let result = tokio::select! {
+            result = work => result,
+            _ = state_clone.lifecycle.shutdown_requested() => {
+                info!("[{}] reindex repo={} interrupted by shutdown", utc_log_timestamp(), repo_id);
+                let mut shards = state_clone.shards.write().await;
+                if let Some(shard) = shards.get_mut(&repo_id) {
+                    shard.status = ShardStatus::Error;
+                    shard.error_message = Some("reindex interrupted by shutdown".to_string());
+                }
+                return;
+            }
+        };
 
         match result {
-            Ok(Ok(commit_sha)) => {
+            Ok(Ok((commit_sha, retained))) => {
                 state_clone.invalidate_repo(&repo_id).await;
                 let mut shards = state_clone.shards.write().await;
+                let mut first_ready = false;
                 if let Some(shard) = shards.get_mut(&repo_id) {
                     shard.generation = shard.generation.next();
                     shard.commit_sha = commit_sha;
                     shard.status = ShardStatus::Ready;
                     shard.error_message = None;
+                    shard.retained_generations.extend(retained);
+                    first_ready = shard.last_warmup_at.is_none();
+                }
+                drop(shards);
+                let pruned = prune_repo(
+                    &state_clone,
+                    &repo_id,
+                    state_clone.retain_generations(),
+                    state_clone.max_disk_per_repo(),
+                )
+                .await
+                .unwrap_or_default();
+                if !pruned.is_empty() {
+                    info!(
+                        "[{}] reindex repo={} pruned_generations={:?}",
+                        utc_log_timestamp(),
+                        repo_id,
+                        pruned
+                            .iter()
+                            .map(|g| g.generation.value())
+                            .collect::<Vec<_>>()
+                    );
+                }
+                if first_ready && state_clone.warmup_on_start() {
+                    let warmed = warm_repo(&state_clone, &repo_id).await;
+                    info!(
+                        "[{}] warmup repo={} ms={} error={:?}",
+                        utc_log_timestamp(),
+                        repo_id,
+                        warmed.total_ms,
+                        warmed.error
+                    );
                 }
             }
             Ok(Err(e)) => {
@@ -203,6 +441,36 @@ pub(crate) async fn reindex(
     }))
 }
 
+/// Copy `index` as the `outgoing` generation, after clearing copies not in
+/// `retained`. A failed copy only costs the generation's history.
+fn retain_copy(
+    index: &RepoIndex,
+    repo_root: &Path,
+    mut outgoing: RetainedGeneration,
+    retained: &[RetainedGeneration],
+) -> Option<RetainedGeneration> {
+    let generation = outgoing.generation.value();
+    let copied = remove_unretained(repo_root, retained)
+        .map_err(canopy_core::CanopyError::from)
+        .and_then(|()| index.copy_to(&generation_dir(repo_root, generation)));
+    match copied {
+        Ok(size_bytes) => {
+            outgoing.size_bytes = size_bytes;
+            Some(outgoing)
+        }
+        Err(e) => {
+            warn!(
+                "[{}] reindex root={} generation={} not retained: {}",
+                utc_log_timestamp(),
+                repo_root.display(),
+                generation,
+                e
+            );
+            None
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -221,9 +489,9 @@ mod tests {
         let dir = TempDir::new().unwrap();
         let result = add_repo(
             State(state),
-            Json(AddRepoRequest {
-                path: dir.path().to_string_lossy().to_string(),
-                name: None,
+            Validated(AddRepoRequest {
+                path: Some(dir.path().to_string_lossy().to_string()),
+                ..AddRepoRequest::default()
             }),
         )
         .await;
@@ -236,9 +504,10 @@ mod tests {
         let dir = make_git_repo();
         let result = add_repo(
             State(state),
-            Json(AddRepoRequest {
-                path: dir.path().to_string_lossy().to_string(),
+            Validated(AddRepoRequest {
+                path: Some(dir.path().to_string_lossy().to_string()),
                 name: Some("test-repo".to_string()),
+                ..AddRepoRequest::default()
             }),
         )
         .await
@@ -255,21 +524,133 @@ mod tests {
 
         let first = add_repo(
             State(state.clone()),
-            Json(AddRepoRequest {
-                path: path.clone(),
-                name: None,
+            Validated(AddRepoRequest {
+                path: Some(path.clone()),
+                ..AddRepoRequest::default()
             }),
         )
         .await
         .unwrap();
 
-        let second = add_repo(State(state), Json(AddRepoRequest { path, name: None }))
-            .await
-            .unwrap();
+        let second = add_repo(
+            State(state),
+            Validated(AddRepoRequest {
+                path: Some(path),
+                ..AddRepoRequest::default()
+            }),
+        )
+        .await
+        .unwrap();
 
         assert_eq!(first.repo_id, second.repo_id);
     }
 
+    #[tokio::test]
+    async fn add_by_git_url_needs_managed_checkouts() {
+        let state = test_state();
+        let err = add_repo(
+            State(state.clone()),
+            Validated(AddRepoRequest {
+                git_url: Some("https://example.com/repo.git".to_string()),
+                ..AddRepoRequest::default()
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status, axum::http::StatusCode::FORBIDDEN);
+        assert_eq!(err.body.code, "managed_checkouts_disabled");
+        assert!(state.shards.read().await.is_empty());
+    }
+
+    fn deny(patterns: &[&str]) -> PathPolicy {
+        PathPolicy {
+            allow: Vec::new(),
+            deny: patterns.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn policies_need_an_api_key() {
+        let state = test_state();
+        let dir = make_git_repo();
+        let err = add_repo(
+            State(state.clone()),
+            Validated(AddRepoRequest {
+                path: Some(dir.path().to_string_lossy().to_string()),
+                policy: Some(deny(&["secrets"])),
+                ..AddRepoRequest::default()
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status, axum::http::StatusCode::FORBIDDEN);
+        assert_eq!(err.body.code, "policies_disabled");
+        assert!(state.shards.read().await.is_empty());
+
+        insert_test_shard(
+            &state,
+            "demo",
+            "demo",
+            ShardStatus::Ready,
+            Generation::from_value(1),
+        )
+        .await;
+        let err = set_policy(
+            State(state),
+            Validated(SetPolicyRequest {
+                repo: "demo".to_string(),
+                policy: deny(&["secrets"]),
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.body.code, "policies_disabled");
+    }
+
+    #[tokio::test]
+    async fn policies_are_listed_to_admins_only() {
+        let state = std::sync::Arc::new(crate::state::AppState::new().with_admin_key(true));
+        let dir = make_git_repo();
+        let Json(added) = add_repo(
+            State(state.clone()),
+            Validated(AddRepoRequest {
+                path: Some(dir.path().to_string_lossy().to_string()),
+                policy: Some(deny(&["secrets"])),
+                ..AddRepoRequest::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert!(state.path_filter(&added.repo_id).await.is_some());
+        let Json(repos) = list_repos(State(state.clone())).await;
+        assert_eq!(repos[0].policy, Some(deny(&["secrets"])));
+        let Json(status) = status(State(state.clone())).await;
+        assert_eq!(status.repos[0].policy, None);
+
+        let err = set_policy(
+            State(state.clone()),
+            Validated(SetPolicyRequest {
+                repo: added.repo_id.clone(),
+                policy: deny(&["src/["]),
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.body.code, "invalid_policy");
+
+        let Json(cleared) = set_policy(
+            State(state.clone()),
+            Validated(SetPolicyRequest {
+                repo: added.repo_id.clone(),
+                policy: PathPolicy::default(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(cleared.policy, None);
+        assert!(state.path_filter(&added.repo_id).await.is_none());
+    }
+
     #[tokio::test]
     async fn list_repos_empty_initially() {
         let state = test_state();
@@ -283,9 +664,10 @@ mod tests {
         let dir = make_git_repo();
         let _ = add_repo(
             State(state.clone()),
-            Json(AddRepoRequest {
-                path: dir.path().to_string_lossy().to_string(),
+            Validated(AddRepoRequest {
+                path: Some(dir.path().to_string_lossy().to_string()),
                 name: Some("my-repo".to_string()),
+                ..AddRepoRequest::default()
             }),
         )
         .await
@@ -301,9 +683,11 @@ mod tests {
         let state = test_state();
         let result = reindex(
             State(state),
-            Json(ReindexRequest {
+            None,
+            Validated(ReindexRequest {
                 repo: "nonexistent".to_string(),
                 glob: None,
+                paths: Vec::new(),
             }),
         )
         .await;
@@ -325,9 +709,11 @@ mod tests {
 
         let result = reindex(
             State(state),
-            Json(ReindexRequest {
+            None,
+            Validated(ReindexRequest {
                 repo: repo_id.to_string(),
                 glob: None,
+                paths: Vec::new(),
             }),
         )
         .await
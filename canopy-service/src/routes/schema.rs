@@ -0,0 +1,15 @@
+//! Output schema route handler: the JSON Schemas remote clients validate
+//! responses against.
+
+use crate::error::AppError;
+use axum::extract::Path;
+use axum::Json;
+use serde_json::Value;
+
+/// `GET /schema/{name}`: the JSON Schema of the output type `name`, e.g.
+/// `query_result` (see `canopy_core::SCHEMA_TYPES`).
+pub(crate) async fn schema(Path(name): Path<String>) -> Result<Json<Value>, AppError> {
+    canopy_core::output_schema(&name)
+        .map(Json)
+        .ok_or_else(|| AppError::schema_not_found(&name))
+}
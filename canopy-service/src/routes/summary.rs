@@ -0,0 +1,170 @@
+//! Repository summary route handler.
+
+use crate::error::AppError;
+use crate::state::SharedState;
+use crate::validation::Validated;
+use axum::extract::State;
+use axum::Json;
+use canopy_core::protocol::SummaryRequest;
+use canopy_core::{RepoSummary, DEFAULT_SUMMARY_TOKENS};
+use std::time::Instant;
+
+use super::{resolve_ready_shard, utc_log_timestamp};
+use tracing::info;
+
+pub(crate) async fn summary(
+    State(state): State<SharedState>,
+    Validated(req): Validated<SummaryRequest>,
+) -> Result<Json<RepoSummary>, AppError> {
+    let start = Instant::now();
+    let shard = resolve_ready_shard(&state, &req.repo).await?;
+    let max_tokens = req.max_tokens.unwrap_or(DEFAULT_SUMMARY_TOKENS);
+
+    let cached_index = state
+        .get_or_open_index(&shard.repo_id, &shard.repo_root, shard.generation)
+        .await
+        .map_err(AppError::from)?;
+    let path_filter = state.path_filter(&shard.repo_id).await;
+    let lease = cached_index.acquire().await;
+    let mut summary = tokio::task::spawn_blocking(move || {
+        let index = lease.index()?;
+        match path_filter {
+            Some(filter) => index.permitted_repo_summary(max_tokens, |path| filter.permits(path)),
+            None => index.repo_summary(max_tokens),
+        }
+    })
+    .await
+    .map_err(AppError::internal)??;
+    if let Some(readme) = summary.readme.as_mut() {
+        readme.source = canopy_core::HandleSource::Service;
+        readme.commit_sha = shard.commit_sha.clone();
+        readme.generation = Some(shard.generation);
+    }
+
+    info!(
+        "[{}] POST /summary repo={} duration_ms={} tokens={}",
+        utc_log_timestamp(),
+        req.repo,
+        start.elapsed().as_millis(),
+        summary.token_count
+    );
+    Ok(Json(summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::PathFilter;
+    use crate::routes::test_state;
+    use canopy_core::protocol::PathPolicy;
+    use canopy_core::{Generation, RepoShard, ShardStatus};
+
+    fn ready_shard(repo_root: &std::path::Path) -> RepoShard {
+        RepoShard {
+            repo_id: "demo".to_string(),
+            repo_root: repo_root.to_string_lossy().into_owned(),
+            name: "demo".to_string(),
+            commit_sha: None,
+            generation: Generation::from_value(4),
+            status: ShardStatus::Ready,
+            error_message: None,
+            last_warmup_at: None,
+            policy: None,
+            retained_generations: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn summary_unknown_repo_returns_error() {
+        let result = summary(
+            State(test_state()),
+            Validated(SummaryRequest {
+                repo: "nonexistent".to_string(),
+                max_tokens: None,
+            }),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().body.code, "repo_not_found");
+    }
+
+    #[tokio::test]
+    async fn summary_reports_ready_repo_with_generation_on_readme() {
+        let repo = tempfile::TempDir::new().unwrap();
+        std::fs::write(repo.path().join("README.md"), "# Intro\n\nHello.\n").unwrap();
+        std::fs::write(repo.path().join("lib.rs"), "pub fn hello() {}\n").unwrap();
+        let mut index = canopy_core::RepoIndex::open_or_init(repo.path()).unwrap();
+        index.index("**/*").unwrap();
+
+        let state = test_state();
+        state
+            .shards
+            .write()
+            .await
+            .insert("demo".to_string(), ready_shard(repo.path()));
+
+        let Json(body) = summary(
+            State(state),
+            Validated(SummaryRequest {
+                repo: "demo".to_string(),
+                max_tokens: Some(2000),
+            }),
+        )
+        .await
+        .unwrap();
+        assert!(body.total_files >= 2);
+        assert!(body.token_count <= 2000);
+        let readme = body.readme.expect("readme handle");
+        assert_eq!(readme.generation, Some(4));
+    }
+
+    #[tokio::test]
+    async fn summary_counts_only_permitted_paths() {
+        let repo = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(repo.path().join("secrets")).unwrap();
+        std::fs::write(repo.path().join("README.md"), "# Intro\n\nHello.\n").unwrap();
+        std::fs::write(repo.path().join("lib.rs"), "pub fn hello() {}\n").unwrap();
+        std::fs::write(
+            repo.path().join("secrets/vault.rs"),
+            "pub fn read_key() {}\npub fn rotate_key() {}\npub struct Vault;\n",
+        )
+        .unwrap();
+        let mut index = canopy_core::RepoIndex::open_or_init(repo.path()).unwrap();
+        index.index("**/*.{rs,md}").unwrap();
+
+        let state = test_state();
+        state
+            .shards
+            .write()
+            .await
+            .insert("demo".to_string(), ready_shard(repo.path()));
+        let policy = PathPolicy {
+            allow: Vec::new(),
+            deny: vec!["secrets".to_string(), "README.md".to_string()],
+        };
+        let filter = PathFilter::compile(&policy).unwrap();
+        state.set_policy("demo", policy, filter).await.unwrap();
+
+        let Json(body) = summary(
+            State(state),
+            Validated(SummaryRequest {
+                repo: "demo".to_string(),
+                max_tokens: Some(2000),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(body.total_files, 1);
+        assert_eq!(body.suppressed_by_policy, 2);
+        assert!(body.readme.is_none());
+        let files: Vec<&str> = body.largest_files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(files, ["lib.rs"]);
+        assert_eq!(body.directories.len(), 1);
+        let root = &body.directories[0];
+        assert_eq!(
+            (root.path.as_str(), root.files, root.functions),
+            (".", 1, 1)
+        );
+        assert_eq!(root.structs, 0);
+        assert!(body.languages.iter().all(|l| l.language == "rust"));
+    }
+}
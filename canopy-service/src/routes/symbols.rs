@@ -0,0 +1,196 @@
+//! Symbol listing route handler.
+
+use crate::error::AppError;
+use crate::state::SharedState;
+use crate::validation::Validated;
+use axum::extract::State;
+use axum::Json;
+use canopy_core::protocol::SymbolsRequest;
+use canopy_core::{SymbolPage, DEFAULT_SYMBOL_LIMIT};
+use std::time::Instant;
+
+use super::{resolve_ready_shard, utc_log_timestamp};
+use tracing::info;
+
+pub(crate) async fn symbols(
+    State(state): State<SharedState>,
+    Validated(req): Validated<SymbolsRequest>,
+) -> Result<Json<SymbolPage>, AppError> {
+    let start = Instant::now();
+    let shard = resolve_ready_shard(&state, &req.repo).await?;
+    let limit = req.limit.unwrap_or(DEFAULT_SYMBOL_LIMIT);
+
+    let cached_index = state
+        .get_or_open_index(&shard.repo_id, &shard.repo_root, shard.generation)
+        .await
+        .map_err(AppError::from)?;
+    let path_filter = state.path_filter(&shard.repo_id).await;
+    let lease = cached_index.acquire().await;
+    let (prefix, node_types, after) = (
+        req.prefix.clone(),
+        req.node_types.clone(),
+        req.after.clone(),
+    );
+    // Denied entries are skipped while paging, so the cursor never names one
+    let page = tokio::task::spawn_blocking(move || {
+        let index = lease.index()?;
+        let node_types = (!node_types.is_empty()).then_some(node_types.as_slice());
+        index.list_permitted_symbols(
+            prefix.as_deref(),
+            node_types,
+            after.as_deref(),
+            limit,
+            |path| path_filter.as_ref().is_none_or(|f| f.permits(path)),
+        )
+    })
+    .await
+    .map_err(AppError::internal)??;
+
+    info!(
+        "[{}] POST /symbols repo={} prefix={:?} duration_ms={} symbols={}",
+        utc_log_timestamp(),
+        req.repo,
+        req.prefix.as_deref().unwrap_or(""),
+        start.elapsed().as_millis(),
+        page.symbols.len()
+    );
+    Ok(Json(page))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::PathFilter;
+    use crate::routes::{insert_test_shard, test_state};
+    use canopy_core::protocol::PathPolicy;
+    use canopy_core::{Generation, NodeType, RepoIndex, ShardStatus};
+
+    #[tokio::test]
+    async fn symbols_pages_through_a_ready_repo() {
+        let repo = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            repo.path().join("lib.rs"),
+            "pub fn handle_a() {}\npub fn handle_b() {}\npub struct Handler;\npub fn other() {}\n",
+        )
+        .unwrap();
+        let mut index = RepoIndex::open_or_init(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let state = test_state();
+        insert_test_shard(
+            &state,
+            "demo",
+            "demo",
+            ShardStatus::Ready,
+            Generation::from_value(1),
+        )
+        .await;
+        state
+            .shards
+            .write()
+            .await
+            .get_mut("demo")
+            .unwrap()
+            .repo_root = repo.path().to_string_lossy().into_owned();
+
+        let request = |after: Option<String>, node_types: Vec<NodeType>| SymbolsRequest {
+            repo: "demo".to_string(),
+            prefix: Some("Hand".to_string()),
+            node_types,
+            after,
+            limit: Some(2),
+        };
+        let Json(first) = symbols(State(state.clone()), Validated(request(None, Vec::new())))
+            .await
+            .unwrap();
+        let names: Vec<&str> = first.symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["handle_a", "handle_b"]);
+        let Json(second) = symbols(
+            State(state.clone()),
+            Validated(request(first.next_after.clone(), Vec::new())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(second.symbols.len(), 1);
+        assert_eq!(second.symbols[0].name, "Handler");
+        assert!(second.next_after.is_none());
+
+        let Json(structs) = symbols(
+            State(state),
+            Validated(request(None, vec![NodeType::Struct])),
+        )
+        .await
+        .unwrap();
+        assert_eq!(structs.symbols.len(), 1);
+        assert_eq!(structs.symbols[0].node_type, NodeType::Struct);
+    }
+
+    #[tokio::test]
+    async fn denied_paths_never_end_a_page() {
+        let repo = tempfile::TempDir::new().unwrap();
+        for dir in ["src", "secrets"] {
+            std::fs::create_dir_all(repo.path().join(dir)).unwrap();
+        }
+        std::fs::write(
+            repo.path().join("src/lib.rs"),
+            "pub fn key_a() {}\npub fn key_d() {}\npub fn key_e() {}\n",
+        )
+        .unwrap();
+        // key_b ends the first page before filtering
+        std::fs::write(
+            repo.path().join("secrets/vault.rs"),
+            "pub fn key_b() {}\npub fn key_c() {}\n",
+        )
+        .unwrap();
+        let mut index = RepoIndex::open_or_init(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let state = test_state();
+        insert_test_shard(
+            &state,
+            "demo",
+            "demo",
+            ShardStatus::Ready,
+            Generation::from_value(1),
+        )
+        .await;
+        state
+            .shards
+            .write()
+            .await
+            .get_mut("demo")
+            .unwrap()
+            .repo_root = repo.path().to_string_lossy().into_owned();
+        let policy = PathPolicy {
+            allow: Vec::new(),
+            deny: vec!["secrets".to_string()],
+        };
+        let filter = PathFilter::compile(&policy).unwrap();
+        state.set_policy("demo", policy, filter).await.unwrap();
+
+        let request = |after: Option<String>| SymbolsRequest {
+            repo: "demo".to_string(),
+            prefix: Some("key_".to_string()),
+            node_types: Vec::new(),
+            after,
+            limit: Some(2),
+        };
+        let Json(first) = symbols(State(state.clone()), Validated(request(None)))
+            .await
+            .unwrap();
+        let names: Vec<&str> = first.symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["key_a", "key_d"]);
+        assert_eq!(first.suppressed_by_policy, 2);
+        let cursor = first.next_after.clone().unwrap();
+        assert!(cursor.starts_with("key_d:"), "{cursor}");
+        assert!(cursor.ends_with(first.symbols[1].handle_id.raw()));
+
+        let Json(second) = symbols(State(state), Validated(request(Some(cursor))))
+            .await
+            .unwrap();
+        let names: Vec<&str> = second.symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["key_e"]);
+        assert_eq!(second.suppressed_by_policy, 0);
+        assert!(second.next_after.is_none());
+    }
+}
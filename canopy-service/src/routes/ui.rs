@@ -0,0 +1,108 @@
+//! Read-only query UI, mounted at `/ui` with `--ui`.
+//!
+//! A single static page that calls `/query` and `/expand` with fetch, so it
+//! goes through the same routes (and API key guard) as any other client.
+
+use crate::state::SharedState;
+use axum::extract::State;
+use axum::response::Html;
+use axum::routing::get;
+use axum::{Json, Router};
+use canopy_core::ShardStatus;
+use serde::Serialize;
+
+const UI_HTML: &str = include_str!("../../assets/ui.html");
+
+/// What the UI routes may reveal.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct UiOptions {
+    /// List registered repos in `/ui/config.json`; otherwise the page asks for a repo id
+    pub(crate) allow_repo_list: bool,
+    /// The page should prompt for an API key to send with its requests
+    pub(crate) api_key_required: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct UiConfig {
+    pub(crate) api_key_required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) repos: Option<Vec<UiRepo>>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct UiRepo {
+    pub(crate) repo_id: String,
+    pub(crate) name: String,
+    pub(crate) status: ShardStatus,
+}
+
+/// `/ui` and `/ui/config.json`.
+pub(crate) fn ui_routes(options: UiOptions) -> Router<SharedState> {
+    Router::new()
+        .route("/ui", get(|| async { Html(UI_HTML) }))
+        .route(
+            "/ui/config.json",
+            get(move |State(state): State<SharedState>| ui_config(state, options)),
+        )
+}
+
+async fn ui_config(state: SharedState, options: UiOptions) -> Json<UiConfig> {
+    let repos = if options.allow_repo_list {
+        let shards = state.shards.read().await;
+        let mut repos: Vec<UiRepo> = shards
+            .values()
+            .map(|shard| UiRepo {
+                repo_id: shard.repo_id.clone(),
+                name: shard.name.clone(),
+                status: shard.status.clone(),
+            })
+            .collect();
+        repos.sort_by(|a, b| a.name.cmp(&b.name));
+        Some(repos)
+    } else {
+        None
+    };
+    Json(UiConfig {
+        api_key_required: options.api_key_required,
+        repos,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::{insert_test_shard, test_state};
+    use canopy_core::Generation;
+
+    #[tokio::test]
+    async fn config_lists_repos_only_when_allowed() {
+        let state = test_state();
+        insert_test_shard(
+            &state,
+            "r1",
+            "alpha",
+            ShardStatus::Ready,
+            Generation::from_value(1),
+        )
+        .await;
+
+        let hidden = ui_config(state.clone(), UiOptions::default()).await.0;
+        assert!(hidden.repos.is_none());
+        assert!(!hidden.api_key_required);
+
+        let listed = ui_config(
+            state,
+            UiOptions {
+                allow_repo_list: true,
+                api_key_required: true,
+            },
+        )
+        .await
+        .0;
+        let repos = listed.repos.unwrap();
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].repo_id, "r1");
+        assert_eq!(repos[0].name, "alpha");
+        assert!(listed.api_key_required);
+    }
+}
@@ -0,0 +1,127 @@
+//! Handle validation route: which handles are fresh, stale or missing,
+//! without expanding them.
+
+use crate::error::AppError;
+use crate::state::SharedState;
+use crate::validation::Validated;
+use axum::extract::State;
+use axum::Json;
+use canopy_core::protocol::{ValidateRequest, ValidateResponse};
+use canopy_core::HandleStatus;
+use std::time::Instant;
+
+use super::{resolve_ready_shard, utc_log_timestamp};
+use tracing::info;
+
+pub(crate) async fn validate(
+    State(state): State<SharedState>,
+    Validated(req): Validated<ValidateRequest>,
+) -> Result<Json<ValidateResponse>, AppError> {
+    let start = Instant::now();
+    let shard = resolve_ready_shard(&state, &req.repo).await?;
+
+    let cached_index = state
+        .get_or_open_index(&shard.repo_id, &shard.repo_root, shard.generation)
+        .await
+        .map_err(AppError::from)?;
+    let path_filter = state.path_filter(&shard.repo_id).await;
+    let lease = cached_index.acquire().await;
+    let handle_ids = req.handle_ids;
+    let mut handles = tokio::task::spawn_blocking(move || {
+        let index = lease.index()?;
+        index.validate_handles(&handle_ids)
+    })
+    .await
+    .map_err(AppError::internal)??;
+    if let Some(filter) = &path_filter {
+        // A denied handle answers as if it weren't indexed
+        for handle in &mut handles {
+            if handle
+                .file_path
+                .as_ref()
+                .is_some_and(|p| !filter.permits(p))
+            {
+                handle.status = HandleStatus::Missing;
+                handle.file_path = None;
+                handle.content_hash = None;
+            }
+        }
+    }
+
+    let stale = handles
+        .iter()
+        .filter(|h| h.status == HandleStatus::Stale)
+        .count();
+    info!(
+        "[{}] POST /validate repo={} duration_ms={} handles={} stale={}",
+        utc_log_timestamp(),
+        req.repo,
+        start.elapsed().as_millis(),
+        handles.len(),
+        stale
+    );
+    Ok(Json(ValidateResponse {
+        handles,
+        generation: shard.generation,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::{insert_test_shard, test_state};
+    use canopy_core::{Generation, QueryParams, RepoIndex, ShardStatus};
+
+    #[tokio::test]
+    async fn validate_reports_which_handles_changed_on_disk() {
+        let repo = tempfile::TempDir::new().unwrap();
+        std::fs::write(repo.path().join("a.rs"), "pub fn alpha() {}\n").unwrap();
+        std::fs::write(repo.path().join("b.rs"), "pub fn beta() {}\n").unwrap();
+        let mut index = RepoIndex::open_or_init(repo.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+        let id = |symbol: &str| {
+            let result = index.query_params(QueryParams::symbol(symbol)).unwrap();
+            result.handles[0].id.to_string()
+        };
+        let handle_ids = vec![id("alpha"), id("beta"), "h000000000000".to_string()];
+
+        let state = test_state();
+        insert_test_shard(
+            &state,
+            "demo",
+            "demo",
+            ShardStatus::Ready,
+            Generation::from_value(3),
+        )
+        .await;
+        state
+            .shards
+            .write()
+            .await
+            .get_mut("demo")
+            .unwrap()
+            .repo_root = repo.path().to_string_lossy().into_owned();
+        std::fs::write(repo.path().join("a.rs"), "pub fn alpha() { 1 }\n").unwrap();
+
+        let Json(body) = validate(
+            State(state),
+            Validated(ValidateRequest {
+                repo: "demo".to_string(),
+                handle_ids,
+            }),
+        )
+        .await
+        .unwrap();
+        let statuses: Vec<HandleStatus> = body.handles.iter().map(|h| h.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                HandleStatus::Stale,
+                HandleStatus::Fresh,
+                HandleStatus::Missing
+            ]
+        );
+        assert_eq!(body.handles[1].file_path.as_deref(), Some("b.rs"));
+        assert_eq!(body.generation, 3);
+    }
+}
@@ -0,0 +1,209 @@
+//! Warm-up route: preload repo indexes so the first queries after a restart
+//! don't pay for cold caches.
+//!
+//! Warming a repo opens its index with every reader connection the pool
+//! allows, each loading its own symbol cache, then reads the index through
+//! once (see [`canopy_core::RepoIndex::warm_up`]) to fault SQLite's pages in.
+
+use crate::error::AppError;
+use crate::state::SharedState;
+use crate::validation::Validated;
+use axum::extract::State;
+use axum::Json;
+use canopy_core::protocol::{RepoWarmup, WarmupRequest, WarmupResponse};
+use canopy_core::{CanopyError, ShardStatus, WarmupReport};
+use std::time::Instant;
+use tracing::info;
+
+use super::{resolve_ready_shard, utc_log_timestamp};
+
+pub(crate) async fn warmup(
+    State(state): State<SharedState>,
+    Validated(req): Validated<WarmupRequest>,
+) -> Json<WarmupResponse> {
+    let repo_ids = match req.repo_ids {
+        Some(repo_ids) => repo_ids,
+        None => {
+            let shards = state.shards.read().await;
+            let mut ready: Vec<String> = shards
+                .values()
+                .filter(|shard| shard.status == ShardStatus::Ready)
+                .map(|shard| shard.repo_id.clone())
+                .collect();
+            ready.sort();
+            ready
+        }
+    };
+
+    let mut repos = Vec::with_capacity(repo_ids.len());
+    for repo_id in &repo_ids {
+        repos.push(warm_repo(&state, repo_id).await);
+    }
+    info!(
+        "[{}] POST /warmup repos={} failed={}",
+        utc_log_timestamp(),
+        repos.len(),
+        repos.iter().filter(|r| r.error.is_some()).count()
+    );
+    Json(WarmupResponse { repos })
+}
+
+/// Warm one repo. A repo that is unknown, not ready or fails to open is
+/// reported in the result rather than failing the request.
+pub(crate) async fn warm_repo(state: &SharedState, repo_id: &str) -> RepoWarmup {
+    let started = Instant::now();
+    let outcome = warm_ready_repo(state, repo_id).await;
+    let total_ms = started.elapsed().as_millis() as u64;
+    match outcome {
+        Ok((report, readers)) => {
+            if let Some(shard) = state.shards.write().await.get_mut(repo_id) {
+                shard.last_warmup_at = Some(time::OffsetDateTime::now_utc().unix_timestamp());
+            }
+            RepoWarmup {
+                repo_id: repo_id.to_string(),
+                report: Some(report),
+                readers,
+                total_ms,
+                error: None,
+            }
+        }
+        Err(err) => RepoWarmup {
+            repo_id: repo_id.to_string(),
+            report: None,
+            readers: 0,
+            total_ms,
+            error: Some(err.body.message),
+        },
+    }
+}
+
+async fn warm_ready_repo(
+    state: &SharedState,
+    repo_id: &str,
+) -> Result<(WarmupReport, usize), AppError> {
+    let shard = resolve_ready_shard(state, repo_id).await?;
+    let cached = state
+        .get_or_open_index(&shard.repo_id, &shard.repo_root, shard.generation)
+        .await?;
+    let leases = cached.readers.acquire_all().await;
+    tokio::task::spawn_blocking(move || {
+        let indexes = leases
+            .iter()
+            .map(|lease| lease.index())
+            .collect::<Result<Vec<_>, _>>()?;
+        // Pages are shared through the OS cache; one pass is enough
+        let report = indexes[0].warm_up()?;
+        Ok::<_, CanopyError>((report, indexes.len()))
+    })
+    .await
+    .map_err(AppError::internal)?
+    .map_err(AppError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::{insert_test_shard, query_with_cache, test_state};
+    use crate::state::AppState;
+    use canopy_core::{Generation, QueryParams, RepoIndex, RepoShard};
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn queries_after_warmup_reuse_the_loaded_readers() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        std::fs::write(
+            dir.path().join("src/lib.rs"),
+            "pub fn warm_target() {}\npub fn other() { warm_target(); }\n",
+        )
+        .unwrap();
+        let mut index = RepoIndex::open_or_init(dir.path()).unwrap();
+        index.index("**/*.rs").unwrap();
+
+        let state: SharedState = Arc::new(AppState::new().with_max_readers_per_repo(3));
+        let root = dir.path().to_string_lossy().into_owned();
+        state.shards.write().await.insert(
+            "warm".to_string(),
+            RepoShard {
+                repo_id: "warm".to_string(),
+                repo_root: root.clone(),
+                name: "warm".to_string(),
+                commit_sha: None,
+                generation: Generation::from_value(1),
+                status: ShardStatus::Ready,
+                error_message: None,
+                last_warmup_at: None,
+                policy: None,
+                retained_generations: Vec::new(),
+            },
+        );
+
+        let Json(response) =
+            warmup(State(state.clone()), Validated(WarmupRequest::default())).await;
+        let warmed = &response.repos[0];
+        assert_eq!(warmed.repo_id, "warm");
+        assert!(warmed.error.is_none(), "{:?}", warmed.error);
+        assert_eq!(warmed.readers, 3);
+        let report = warmed.report.as_ref().unwrap();
+        assert_eq!(report.symbols, 2);
+        assert!(state.shards.read().await["warm"].last_warmup_at.is_some());
+
+        // Every reader connection has its symbol cache loaded already
+        assert_eq!(state.reader_pool_stats().await["warm"].opened, 3);
+        let misses = state.metrics.index_cache_misses.load(Ordering::Relaxed);
+
+        for _ in 0..2 {
+            let (result, _) = query_with_cache(
+                &state,
+                "warm",
+                &root,
+                1,
+                &None,
+                &QueryParams::symbol("warm_target"),
+                None,
+            )
+            .await
+            .unwrap();
+            assert_eq!(result.handles.len(), 1);
+        }
+        assert_eq!(state.reader_pool_stats().await["warm"].opened, 3);
+        assert_eq!(
+            state.metrics.index_cache_misses.load(Ordering::Relaxed),
+            misses
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_and_unready_repos_are_reported_not_raised() {
+        let state = test_state();
+        insert_test_shard(
+            &state,
+            "busy",
+            "busy",
+            ShardStatus::Indexing,
+            Generation::from_value(1),
+        )
+        .await;
+
+        let Json(response) = warmup(
+            State(state.clone()),
+            Validated(WarmupRequest {
+                repo_ids: Some(vec!["missing".to_string(), "busy".to_string()]),
+            }),
+        )
+        .await;
+        assert_eq!(response.repos.len(), 2);
+        assert!(response.repos.iter().all(|r| r.report.is_none()));
+        assert!(response.repos[1]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("not ready"));
+        assert!(state.shards.read().await["busy"].last_warmup_at.is_none());
+
+        // Without repo_ids only ready repos are warmed
+        let Json(all) = warmup(State(state), Validated(WarmupRequest::default())).await;
+        assert!(all.repos.is_empty());
+    }
+}
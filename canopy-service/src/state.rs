@@ -1,16 +1,23 @@
 use canopy_core::capped_map::{CappedMap, CappedSet};
 use canopy_core::{
     feedback::{FeedbackStore, NODE_TYPE_PRIOR_CACHE_TTL},
-    CanopyError, NodeType, QueryResult, RepoIndex, RepoShard,
+    protocol::PathPolicy,
+    CanopyError, FtsScanStats, NodeType, QueryResult, RepoIndex, RepoShard, RetainedGeneration,
 };
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::io;
-use std::path::Path;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
-use tokio::sync::RwLock;
-use tracing::warn;
+use tokio::sync::{watch, RwLock};
+
+use crate::auth::KeyStore;
+use crate::checkout::ManagedCheckout;
+use crate::policy::PathFilter;
+use crate::reader_pool::{default_max_readers, ReaderLease, ReaderPool, ReaderPoolStats};
+use crate::retention::{generation_dir, DEFAULT_RETAIN_GENERATIONS};
+use tracing::{info, warn};
 
 pub type SharedState = Arc<AppState>;
 pub const QUERY_CACHE_MAX_ENTRIES: usize = 128;
@@ -29,7 +36,11 @@ pub struct ServiceMetrics {
     pub reindex_count: AtomicU64,
     pub total_query_ms: AtomicU64,
     pub total_expand_ms: AtomicU64,
+    /// Request bodies rejected by strict validation
+    pub invalid_requests: AtomicU64,
     pub analytics: Mutex<QueryAnalytics>,
+    /// Full-text scans per repo id, drained from the reader each query ran on
+    pub fts_scans: Mutex<HashMap<String, FtsScanStats>>,
 }
 
 impl ServiceMetrics {
@@ -44,7 +55,19 @@ impl ServiceMetrics {
             reindex_count: AtomicU64::new(0),
             total_query_ms: AtomicU64::new(0),
             total_expand_ms: AtomicU64::new(0),
+            invalid_requests: AtomicU64::new(0),
             analytics: Mutex::new(QueryAnalytics::new()),
+            fts_scans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Add scans taken from one of `repo_id`'s readers to its totals.
+    pub fn record_fts_scans(&self, repo_id: &str, scans: &FtsScanStats) {
+        if *scans == FtsScanStats::default() {
+            return;
+        }
+        if let Ok(mut totals) = self.fts_scans.lock() {
+            totals.entry(repo_id.to_string()).or_default().merge(scans);
         }
     }
 }
@@ -54,6 +77,12 @@ pub struct QueryAnalytics {
     pub top_patterns: HashMap<String, u64>,
     pub top_expanded_files: HashMap<String, u64>,
     pub requests_by_repo: HashMap<String, u64>,
+    /// Query requests per client name (see `client_context`)
+    pub requests_by_client: HashMap<String, u64>,
+    /// Query requests per hashed session bucket
+    pub requests_by_session_bucket: HashMap<String, u64>,
+    /// Guarded requests per API key name (see `auth`)
+    pub requests_by_key: HashMap<String, u64>,
 }
 
 impl QueryAnalytics {
@@ -63,21 +92,22 @@ impl QueryAnalytics {
             top_patterns: HashMap::new(),
             top_expanded_files: HashMap::new(),
             requests_by_repo: HashMap::new(),
+            requests_by_client: HashMap::new(),
+            requests_by_session_bucket: HashMap::new(),
+            requests_by_key: HashMap::new(),
         }
     }
 }
 
 pub struct CachedIndex {
-    pub index: Mutex<RepoIndex>,
+    pub readers: Arc<ReaderPool>,
     pub generation: u64,
 }
 
 impl CachedIndex {
-    /// Lock the index mutex, mapping poison errors to `CanopyError`.
-    pub fn lock_index(&self) -> Result<std::sync::MutexGuard<'_, RepoIndex>, CanopyError> {
-        self.index.lock().map_err(|err| {
-            CanopyError::Io(io::Error::other(format!("Index mutex poisoned: {err}")))
-        })
+    /// Wait for a reader slot on this repo; see `ReaderPool::acquire`.
+    pub async fn acquire(&self) -> ReaderLease {
+        self.readers.acquire().await
     }
 }
 
@@ -139,6 +169,8 @@ impl RepoQueryCache {
 /// Lock ordering: acquire `index_state` before `feedback_state`.
 struct IndexState {
     indexes: HashMap<String, Arc<CachedIndex>>,
+    /// Copies of retained generations, keyed by repo id and generation
+    retained: HashMap<(String, u64), Arc<CachedIndex>>,
     query_caches: HashMap<String, RepoQueryCache>,
 }
 
@@ -150,9 +182,69 @@ struct FeedbackState {
     expanded_handles: CappedSet<(String, String)>,
 }
 
+/// Startup and shutdown flags read by `/readyz`.
+pub struct Lifecycle {
+    started: AtomicBool,
+    /// Flips to `true` once on SIGTERM/Ctrl-C; reindex tasks watch it
+    shutdown: watch::Sender<bool>,
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self {
+            started: AtomicBool::new(false),
+            shutdown: watch::Sender::new(false),
+        }
+    }
+
+    /// Mark startup (state restoration) complete.
+    pub fn mark_started(&self) {
+        self.started.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started.load(Ordering::Relaxed)
+    }
+
+    /// Start graceful shutdown: readiness fails and subscribers are woken.
+    pub fn begin_shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Resolves once shutdown has begun (immediately if it already has).
+    pub async fn shutdown_requested(&self) {
+        let mut rx = self.shutdown.subscribe();
+        // Only errors if the sender is dropped, which outlives every caller
+        let _ = rx.wait_for(|stopping| *stopping).await;
+    }
+}
+
 pub struct AppState {
     pub shards: RwLock<HashMap<String, RepoShard>>,
     pub metrics: ServiceMetrics,
+    pub lifecycle: Lifecycle,
+    /// Bound on concurrent blocking readers per repo
+    max_readers_per_repo: usize,
+    /// Generations kept per repo for `/expand`, the live one included
+    retain_generations: usize,
+    /// Disk budget for each repo's retained generation copies
+    max_disk_per_repo: Option<u64>,
+    /// Warm each repo when it first becomes ready after startup
+    warmup_on_start: bool,
+    /// Where repos added by `git_url` are cloned; unset disables them
+    checkout_dir: Option<PathBuf>,
+    /// Repos the service clones itself, keyed by repo id
+    checkouts: RwLock<HashMap<String, ManagedCheckout>>,
+    /// Whether admin routes sit behind an API key; path policies need one
+    admin_key: bool,
+    /// API keys guarding the query and admin routes; unset leaves them open
+    keys: Option<Arc<KeyStore>>,
+    /// Compiled path policies, keyed by repo id (see `RepoShard::policy`)
+    policies: RwLock<HashMap<String, Arc<PathFilter>>>,
     index_state: RwLock<IndexState>,
     feedback_state: RwLock<FeedbackState>,
 }
@@ -162,8 +254,19 @@ impl AppState {
         Self {
             shards: RwLock::new(HashMap::new()),
             metrics: ServiceMetrics::new(),
+            lifecycle: Lifecycle::new(),
+            max_readers_per_repo: default_max_readers(),
+            retain_generations: DEFAULT_RETAIN_GENERATIONS,
+            max_disk_per_repo: None,
+            warmup_on_start: false,
+            checkout_dir: None,
+            checkouts: RwLock::new(HashMap::new()),
+            admin_key: false,
+            keys: None,
+            policies: RwLock::new(HashMap::new()),
             index_state: RwLock::new(IndexState {
                 indexes: HashMap::new(),
+                retained: HashMap::new(),
                 query_caches: HashMap::new(),
             }),
             feedback_state: RwLock::new(FeedbackState {
@@ -175,6 +278,144 @@ impl AppState {
         }
     }
 
+    pub fn with_max_readers_per_repo(mut self, max_readers: usize) -> Self {
+        self.max_readers_per_repo = max_readers.max(1);
+        self
+    }
+
+    pub fn max_readers_per_repo(&self) -> usize {
+        self.max_readers_per_repo
+    }
+
+    pub fn with_retain_generations(mut self, retain: usize) -> Self {
+        self.retain_generations = retain.max(1);
+        self
+    }
+
+    pub fn retain_generations(&self) -> usize {
+        self.retain_generations
+    }
+
+    pub fn with_max_disk_per_repo(mut self, max_bytes: u64) -> Self {
+        self.max_disk_per_repo = Some(max_bytes);
+        self
+    }
+
+    pub fn max_disk_per_repo(&self) -> Option<u64> {
+        self.max_disk_per_repo
+    }
+
+    pub fn with_warmup_on_start(mut self, warmup_on_start: bool) -> Self {
+        self.warmup_on_start = warmup_on_start;
+        self
+    }
+
+    pub fn warmup_on_start(&self) -> bool {
+        self.warmup_on_start
+    }
+
+    pub fn with_checkout_dir(mut self, dir: PathBuf) -> Self {
+        self.checkout_dir = Some(dir);
+        self
+    }
+
+    pub fn checkout_dir(&self) -> Option<&Path> {
+        self.checkout_dir.as_deref()
+    }
+
+    #[cfg(test)]
+    pub fn with_admin_key(mut self, admin_key: bool) -> Self {
+        self.admin_key = admin_key;
+        self
+    }
+
+    pub fn admin_key(&self) -> bool {
+        self.admin_key
+    }
+
+    /// Guard the query and admin routes with `keys`; admin routes then sit
+    /// behind a key, as [`admin_key`](Self::admin_key) reports.
+    pub fn with_keys(mut self, keys: KeyStore) -> Self {
+        self.keys = Some(Arc::new(keys));
+        self.admin_key = true;
+        self
+    }
+
+    pub(crate) fn keys(&self) -> Option<&Arc<KeyStore>> {
+        self.keys.as_ref()
+    }
+
+    /// Set `repo_id`'s path policy, or clear it when `policy` is empty.
+    /// `filter` is `policy` compiled. Returns the updated shard, or `None`
+    /// for an unknown repo.
+    pub async fn set_policy(
+        &self,
+        repo_id: &str,
+        policy: PathPolicy,
+        filter: PathFilter,
+    ) -> Option<RepoShard> {
+        // Shard and compiled filter change together under the shards lock
+        let mut shards = self.shards.write().await;
+        let shard = shards.get_mut(repo_id)?;
+        let mut policies = self.policies.write().await;
+        if policy.is_empty() {
+            shard.policy = None;
+            policies.remove(repo_id);
+        } else {
+            shard.policy = Some(policy);
+            policies.insert(repo_id.to_string(), Arc::new(filter));
+        }
+        Some(shard.clone())
+    }
+
+    /// The path policy enforced on `repo_id`'s responses, if it has one.
+    pub async fn path_filter(&self, repo_id: &str) -> Option<Arc<PathFilter>> {
+        self.policies.read().await.get(repo_id).cloned()
+    }
+
+    /// The managed checkout behind `repo_id`, if the service cloned it.
+    pub async fn checkout(&self, repo_id: &str) -> Option<ManagedCheckout> {
+        self.checkouts.read().await.get(repo_id).cloned()
+    }
+
+    /// Repo id of the checkout of `git_url` / `branch` / `sparse_paths`,
+    /// registering a new one under `dir` when there is none yet. The flag is
+    /// true when it was registered by this call.
+    pub async fn register_checkout(
+        &self,
+        dir: &Path,
+        git_url: &str,
+        branch: Option<&str>,
+        sparse_paths: &[String],
+    ) -> (String, ManagedCheckout, bool) {
+        let mut checkouts = self.checkouts.write().await;
+        let existing = checkouts.iter().find(|(_, c)| {
+            c.git_url == git_url && c.branch.as_deref() == branch && c.sparse_paths == sparse_paths
+        });
+        if let Some((repo_id, checkout)) = existing {
+            return (repo_id.clone(), checkout.clone(), false);
+        }
+        let repo_id = uuid::Uuid::new_v4().to_string();
+        let checkout = ManagedCheckout {
+            git_url: git_url.to_string(),
+            branch: branch.map(str::to_string),
+            sparse_paths: sparse_paths.to_vec(),
+            root: dir.join(&repo_id),
+        };
+        checkouts.insert(repo_id.clone(), checkout.clone());
+        (repo_id, checkout, true)
+    }
+
+    /// Reader pool occupancy for every open repo index, keyed by repo id.
+    pub async fn reader_pool_stats(&self) -> HashMap<String, ReaderPoolStats> {
+        let state = self.index_state.read().await;
+        state
+            .indexes
+            .iter()
+            .map(|(repo_id, cached)| (repo_id.clone(), cached.readers.stats()))
+            .collect()
+    }
+
     pub async fn get_or_open_index(
         &self,
         repo_id: &str,
@@ -196,8 +437,9 @@ impl AppState {
             .index_cache_misses
             .fetch_add(1, Ordering::Relaxed);
 
-        let repo_root = repo_root.to_string();
-        let index = tokio::task::spawn_blocking(move || RepoIndex::open(Path::new(&repo_root)))
+        let root = PathBuf::from(repo_root);
+        let open_root = root.clone();
+        let index = tokio::task::spawn_blocking(move || RepoIndex::open(&open_root))
             .await
             .map_err(|err| {
                 CanopyError::Io(io::Error::other(format!(
@@ -206,7 +448,7 @@ impl AppState {
             })??;
 
         let candidate = Arc::new(CachedIndex {
-            index: Mutex::new(index),
+            readers: Arc::new(ReaderPool::new(root, index, self.max_readers_per_repo)),
             generation,
         });
 
@@ -223,6 +465,64 @@ impl AppState {
         Ok(candidate)
     }
 
+    /// Index of `repo_id`'s retained generation, opening its copy on first use.
+    pub async fn get_or_open_retained(
+        &self,
+        repo_id: &str,
+        repo_root: &str,
+        retained: &RetainedGeneration,
+    ) -> Result<Arc<CachedIndex>, CanopyError> {
+        let generation = retained.generation.value();
+        let key = (repo_id.to_string(), generation);
+        if let Some(cached) = self.index_state.read().await.retained.get(&key) {
+            return Ok(Arc::clone(cached));
+        }
+
+        let root = PathBuf::from(repo_root);
+        let dir = generation_dir(&root, generation);
+        let commit_sha = retained.commit_sha.clone();
+        let pool = tokio::task::spawn_blocking({
+            let root = root.clone();
+            let max_readers = self.max_readers_per_repo;
+            move || {
+                let index = RepoIndex::open_copy(&root, &dir, commit_sha.clone())?;
+                Ok::<_, CanopyError>(ReaderPool::for_copy(
+                    root,
+                    dir,
+                    commit_sha,
+                    index,
+                    max_readers,
+                ))
+            }
+        })
+        .await
+        .map_err(|err| {
+            CanopyError::Io(io::Error::other(format!(
+                "RepoIndex copy open task failed: {err}"
+            )))
+        })??;
+
+        let mut state = self.index_state.write().await;
+        let cached = state.retained.entry(key).or_insert_with(|| {
+            Arc::new(CachedIndex {
+                readers: Arc::new(pool),
+                generation,
+            })
+        });
+        Ok(Arc::clone(cached))
+    }
+
+    /// Close the copies of `repo_id`'s retained generations not in `kept`.
+    pub async fn evict_retained(&self, repo_id: &str, kept: &[RetainedGeneration]) {
+        self.index_state
+            .write()
+            .await
+            .retained
+            .retain(|(r, generation), _| {
+                r != repo_id || kept.iter().any(|g| g.generation.value() == *generation)
+            });
+    }
+
     pub async fn get_cached_query(
         &self,
         repo_id: &str,
@@ -283,7 +583,12 @@ impl AppState {
         }
 
         let opened = match FeedbackStore::open(Path::new(repo_root)) {
-            Ok(store) => Arc::new(Mutex::new(store)),
+            Ok(store) => {
+                if let Some(report) = store.auto_pruned() {
+                    info!("[canopy-service] feedback for repo {}: {}", repo_id, report);
+                }
+                Arc::new(Mutex::new(store))
+            }
             Err(err) => {
                 warn!(
                     "[canopy-service] feedback disabled for repo {}: {}",
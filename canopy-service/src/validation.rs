@@ -0,0 +1,815 @@
+//! Strict request body validation for the JSON routes.
+//!
+//! serde ignores unknown fields and the handlers clamp ranges, so a typo like
+//! `"limt": 5` or an out-of-range `max_handles` is silently dropped. Routes take
+//! [`Validated<T>`] instead of `Json<T>`: the body is checked against a field
+//! table first and rejected with `invalid_request` and one [`FieldError`] per
+//! problem. Sending `X-Canopy-Lenient: 1` skips the table and keeps the old
+//! serde behaviour.
+
+use crate::error::{AppError, FieldError};
+use crate::state::SharedState;
+use axum::extract::{FromRequest, Request};
+use axum::Json;
+use canopy_core::protocol::{
+    AddRepoRequest, EvidencePackRequest, ExpandRequest, PruneGenerationsRequest, QueryRequest,
+    ReferencesRequest, ReindexRequest, RelatedRequest, SetPolicyRequest, SummaryRequest,
+    SymbolsRequest, ValidateRequest, WarmupRequest,
+};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::sync::atomic::Ordering;
+
+/// Header that opts a request out of strict validation.
+pub(crate) const LENIENT_HEADER: &str = "x-canopy-lenient";
+
+/// Largest `limit` a request may ask for.
+pub(crate) const MAX_REQUEST_LIMIT: u64 = 500;
+/// Largest evidence pack `max_handles` a request may ask for.
+pub(crate) const MAX_EVIDENCE_HANDLES: u64 = 64;
+
+/// Expected JSON type (and range) of one field.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum FieldKind {
+    Str,
+    StrList,
+    Bool,
+    /// Non-negative integer within `min..=max`
+    Int {
+        min: u64,
+        max: u64,
+    },
+    /// String from a fixed set
+    OneOf(&'static [&'static str]),
+    /// Window such as "48h" or "7d"
+    Duration,
+    /// Line range such as "10-40"
+    Lines,
+    /// Object of node type names to positive multipliers
+    NodeTypePriors,
+    /// Array of node type names
+    NodeTypes,
+    Object(&'static [Field]),
+    ObjectList {
+        fields: &'static [Field],
+        non_empty: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Field {
+    pub(crate) name: &'static str,
+    pub(crate) kind: FieldKind,
+    /// Optional fields may also be `null`
+    pub(crate) required: bool,
+}
+
+const fn required(name: &'static str, kind: FieldKind) -> Field {
+    Field {
+        name,
+        kind,
+        required: true,
+    }
+}
+
+const fn optional(name: &'static str, kind: FieldKind) -> Field {
+    Field {
+        name,
+        kind,
+        required: false,
+    }
+}
+
+const REPO: Field = required("repo", FieldKind::Str);
+
+/// The flattened [`canopy_core::QueryParams`] fields.
+const QUERY_PARAM_FIELDS: &[Field] = &[
+    optional("pattern", FieldKind::Str),
+    optional("patterns", FieldKind::StrList),
+    optional("symbol", FieldKind::Str),
+    optional("section", FieldKind::Str),
+    optional("section_path", FieldKind::Str),
+    optional("parent", FieldKind::Str),
+    optional(
+        "kind",
+        FieldKind::OneOf(&["any", "definition", "reference", "annotation", "commit"]),
+    ),
+    optional("ref_types", FieldKind::StrList),
+    optional("group_references", FieldKind::Bool),
+    optional("glob", FieldKind::Str),
+    optional("path_contains", FieldKind::Str),
+    optional("modified_within", FieldKind::Duration),
+    optional("exclude_patterns", FieldKind::StrList),
+    optional("include_generated", FieldKind::Bool),
+    optional("explain", FieldKind::Bool),
+    optional("infer_glob", FieldKind::Bool),
+    optional("match_mode", FieldKind::OneOf(&["any", "all"])),
+    optional(
+        "limit",
+        FieldKind::Int {
+            min: 1,
+            max: MAX_REQUEST_LIMIT,
+        },
+    ),
+    optional(
+        "expand_budget",
+        FieldKind::Int {
+            min: 0,
+            max: u64::MAX,
+        },
+    ),
+    optional("dsl", FieldKind::Str),
+    optional("mode", FieldKind::OneOf(&["handles", "count", "exists"])),
+    optional("priors", FieldKind::NodeTypePriors),
+];
+
+/// Fields that count as a search criterion; annotation queries need none.
+const SEARCH_TARGETS: &[&str] = &[
+    "pattern",
+    "patterns",
+    "symbol",
+    "section",
+    "section_path",
+    "parent",
+    "dsl",
+];
+
+const EVIDENCE_CONFIG_FIELDS: &[Field] = &[
+    optional(
+        "max_handles",
+        FieldKind::Int {
+            min: 1,
+            max: MAX_EVIDENCE_HANDLES,
+        },
+    ),
+    optional(
+        "max_per_file",
+        FieldKind::Int {
+            min: 1,
+            max: u64::MAX,
+        },
+    ),
+    optional("plan", FieldKind::Bool),
+];
+
+const EXPAND_HANDLE_FIELDS: &[Field] = &[
+    required("id", FieldKind::Str),
+    optional(
+        "generation",
+        FieldKind::Int {
+            min: 0,
+            max: u64::MAX,
+        },
+    ),
+    optional("lines", FieldKind::Lines),
+];
+
+/// A [`canopy_core::protocol::PathPolicy`]; patterns are compiled by the route.
+const POLICY_FIELDS: &[Field] = &[
+    optional("allow", FieldKind::StrList),
+    optional("deny", FieldKind::StrList),
+];
+
+/// A request body with a known field table.
+pub(crate) trait RequestSchema: DeserializeOwned {
+    /// Top-level fields, in groups so flattened structs can share a table.
+    const FIELDS: &'static [&'static [Field]];
+
+    /// Rules spanning several fields, run after the per-field checks.
+    fn check(_body: &Map<String, Value>, _errors: &mut Vec<FieldError>) {}
+}
+
+impl RequestSchema for QueryRequest {
+    const FIELDS: &'static [&'static [Field]] = &[&[REPO], QUERY_PARAM_FIELDS];
+
+    fn check(body: &Map<String, Value>, errors: &mut Vec<FieldError>) {
+        require_search_target(body, errors);
+    }
+}
+
+impl RequestSchema for EvidencePackRequest {
+    const FIELDS: &'static [&'static [Field]] = &[
+        &[
+            REPO,
+            optional("config", FieldKind::Object(EVIDENCE_CONFIG_FIELDS)),
+        ],
+        QUERY_PARAM_FIELDS,
+    ];
+
+    fn check(body: &Map<String, Value>, errors: &mut Vec<FieldError>) {
+        require_search_target(body, errors);
+    }
+}
+
+impl RequestSchema for ExpandRequest {
+    const FIELDS: &'static [&'static [Field]] = &[&[
+        REPO,
+        required(
+            "handles",
+            FieldKind::ObjectList {
+                fields: EXPAND_HANDLE_FIELDS,
+                non_empty: true,
+            },
+        ),
+        optional("auto_expanded", FieldKind::Bool),
+    ]];
+}
+
+impl RequestSchema for SummaryRequest {
+    const FIELDS: &'static [&'static [Field]] = &[&[
+        REPO,
+        optional(
+            "max_tokens",
+            FieldKind::Int {
+                min: 1,
+                max: u64::MAX,
+            },
+        ),
+    ]];
+}
+
+impl RequestSchema for ReindexRequest {
+    const FIELDS: &'static [&'static [Field]] = &[&[
+        REPO,
+        optional("glob", FieldKind::Str),
+        optional("paths", FieldKind::StrList),
+    ]];
+
+    /// `glob` and `paths` are alternatives
+    fn check(body: &Map<String, Value>, errors: &mut Vec<FieldError>) {
+        let present = |name: &str| !matches!(body.get(name), None | Some(Value::Null));
+        if present("glob") && present("paths") {
+            errors.push(FieldError::new(
+                "paths",
+                "give either glob or paths, not both",
+            ));
+        }
+    }
+}
+
+impl RequestSchema for RelatedRequest {
+    const FIELDS: &'static [&'static [Field]] = &[&[
+        REPO,
+        required("path", FieldKind::Str),
+        optional(
+            "limit",
+            FieldKind::Int {
+                min: 1,
+                max: MAX_REQUEST_LIMIT,
+            },
+        ),
+    ]];
+}
+
+impl RequestSchema for ReferencesRequest {
+    const FIELDS: &'static [&'static [Field]] = &[&[
+        REPO,
+        required("symbol", FieldKind::Str),
+        optional("ref_types", FieldKind::StrList),
+        optional("glob", FieldKind::Str),
+        optional(
+            "limit",
+            FieldKind::Int {
+                min: 1,
+                max: MAX_REQUEST_LIMIT,
+            },
+        ),
+    ]];
+}
+
+impl RequestSchema for SymbolsRequest {
+    const FIELDS: &'static [&'static [Field]] = &[&[
+        REPO,
+        optional("prefix", FieldKind::Str),
+        optional("node_types", FieldKind::NodeTypes),
+        optional("after", FieldKind::Str),
+        optional(
+            "limit",
+            FieldKind::Int {
+                min: 1,
+                max: MAX_REQUEST_LIMIT,
+            },
+        ),
+    ]];
+}
+
+impl RequestSchema for ValidateRequest {
+    const FIELDS: &'static [&'static [Field]] =
+        &[&[REPO, required("handle_ids", FieldKind::StrList)]];
+}
+
+impl RequestSchema for WarmupRequest {
+    const FIELDS: &'static [&'static [Field]] = &[&[optional("repo_ids", FieldKind::StrList)]];
+}
+
+/// Largest generation history a prune may keep per repo.
+pub(crate) const MAX_RETAIN_GENERATIONS: u64 = 100;
+
+impl RequestSchema for PruneGenerationsRequest {
+    const FIELDS: &'static [&'static [Field]] = &[&[
+        optional("repo", FieldKind::Str),
+        optional(
+            "retain",
+            FieldKind::Int {
+                min: 1,
+                max: MAX_RETAIN_GENERATIONS,
+            },
+        ),
+    ]];
+}
+
+impl RequestSchema for AddRepoRequest {
+    const FIELDS: &'static [&'static [Field]] = &[&[
+        optional("path", FieldKind::Str),
+        optional("name", FieldKind::Str),
+        optional("git_url", FieldKind::Str),
+        optional("branch", FieldKind::Str),
+        optional("sparse_paths", FieldKind::StrList),
+        optional("policy", FieldKind::Object(POLICY_FIELDS)),
+    ]];
+
+    /// Exactly one of `path` and `git_url`; clone options only with `git_url`
+    fn check(body: &Map<String, Value>, errors: &mut Vec<FieldError>) {
+        let present = |name: &str| !matches!(body.get(name), None | Some(Value::Null));
+        match (present("path"), present("git_url")) {
+            (false, false) => errors.push(FieldError::new(
+                "path",
+                "one of path or git_url is required",
+            )),
+            (true, true) => errors.push(FieldError::new(
+                "git_url",
+                "give either path or git_url, not both",
+            )),
+            (true, false) => {
+                for name in ["branch", "sparse_paths"] {
+                    if present(name) {
+                        errors.push(FieldError::new(name, "only applies with git_url"));
+                    }
+                }
+            }
+            (false, true) => {}
+        }
+    }
+}
+
+impl RequestSchema for SetPolicyRequest {
+    const FIELDS: &'static [&'static [Field]] =
+        &[&[REPO, optional("policy", FieldKind::Object(POLICY_FIELDS))]];
+}
+
+fn require_search_target(body: &Map<String, Value>, errors: &mut Vec<FieldError>) {
+    // Annotation and commit kinds list everything without a target
+    let lists_all = matches!(
+        body.get("kind").and_then(Value::as_str),
+        Some("annotation" | "commit")
+    );
+    let has_target = SEARCH_TARGETS.iter().any(|name| match body.get(*name) {
+        None | Some(Value::Null) => false,
+        Some(Value::Array(items)) => !items.is_empty(),
+        Some(_) => true,
+    });
+    if !lists_all && !has_target {
+        errors.push(FieldError::new(
+            "pattern",
+            format!("at least one of {} is required", SEARCH_TARGETS.join(", ")),
+        ));
+    }
+}
+
+/// Every problem with `body` against `T`'s field table; empty when it is valid.
+pub(crate) fn validate<T: RequestSchema>(body: &Value) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    let Some(object) = body.as_object() else {
+        errors.push(FieldError::new("body", "expected a JSON object"));
+        return errors;
+    };
+    validate_object("", object, T::FIELDS, &mut errors);
+    if errors.is_empty() {
+        T::check(object, &mut errors);
+    }
+    errors
+}
+
+fn field_path(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
+fn validate_object(
+    prefix: &str,
+    object: &Map<String, Value>,
+    groups: &[&[Field]],
+    errors: &mut Vec<FieldError>,
+) {
+    let fields = || groups.iter().flat_map(|group| group.iter());
+
+    for key in object.keys() {
+        if !fields().any(|field| field.name == key) {
+            let known: Vec<&str> = fields().map(|field| field.name).collect();
+            errors.push(FieldError::new(
+                field_path(prefix, key),
+                format!("unknown field (expected one of: {})", known.join(", ")),
+            ));
+        }
+    }
+
+    for field in fields() {
+        let path = field_path(prefix, field.name);
+        match object.get(field.name) {
+            None | Some(Value::Null) if field.required => {
+                errors.push(FieldError::new(path, "is required"));
+            }
+            None | Some(Value::Null) => {}
+            Some(value) => validate_value(&path, value, field.kind, errors),
+        }
+    }
+}
+
+fn validate_value(path: &str, value: &Value, kind: FieldKind, errors: &mut Vec<FieldError>) {
+    match kind {
+        FieldKind::Str => {
+            if !value.is_string() {
+                errors.push(FieldError::new(path, "expected a string"));
+            }
+        }
+        FieldKind::StrList => match value.as_array() {
+            Some(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if !item.is_string() {
+                        errors.push(FieldError::new(
+                            format!("{}[{}]", path, i),
+                            "expected a string",
+                        ));
+                    }
+                }
+            }
+            None => errors.push(FieldError::new(path, "expected an array of strings")),
+        },
+        FieldKind::Bool => {
+            if !value.is_boolean() {
+                errors.push(FieldError::new(path, "expected a boolean"));
+            }
+        }
+        FieldKind::Int { min, max } => match value.as_u64() {
+            Some(n) if (min..=max).contains(&n) => {}
+            Some(_) if max == u64::MAX => {
+                errors.push(FieldError::new(path, format!("must be at least {}", min)));
+            }
+            Some(_) => errors.push(FieldError::new(
+                path,
+                format!("must be between {} and {}", min, max),
+            )),
+            None => errors.push(FieldError::new(path, "expected a non-negative integer")),
+        },
+        FieldKind::OneOf(allowed) => match value.as_str() {
+            Some(s) if allowed.contains(&s) => {}
+            _ => errors.push(FieldError::new(
+                path,
+                format!("expected one of: {}", allowed.join(", ")),
+            )),
+        },
+        FieldKind::Duration => match value.as_str() {
+            Some(s) if canopy_core::config::parse_duration(s).is_some() => {}
+            _ => errors.push(FieldError::new(
+                path,
+                "expected a number and a unit (s, m, h, d or w), like \"48h\" or \"7d\"",
+            )),
+        },
+        FieldKind::Lines => match value.as_str() {
+            Some(s) if s.parse::<canopy_core::LineWindow>().is_ok() => {}
+            _ => errors.push(FieldError::new(
+                path,
+                "expected 1-indexed lines start-end, like \"10-40\"",
+            )),
+        },
+        FieldKind::NodeTypePriors => match value.as_object() {
+            Some(priors) => {
+                for (name, multiplier) in priors {
+                    let path = format!("{}.{}", path, name);
+                    if canopy_core::NodeType::parse(name).is_none() {
+                        errors.push(unknown_node_type(path));
+                    } else if !multiplier.as_f64().is_some_and(|m| m > 0.0) {
+                        errors.push(FieldError::new(path, "expected a positive number"));
+                    }
+                }
+            }
+            None => errors.push(FieldError::new(path, "expected an object")),
+        },
+        FieldKind::NodeTypes => match value.as_array() {
+            Some(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    let path = format!("{}[{}]", path, i);
+                    match item.as_str() {
+                        Some(name) if canopy_core::NodeType::parse(name).is_some() => {}
+                        Some(_) => errors.push(unknown_node_type(path)),
+                        None => errors.push(FieldError::new(path, "expected a string")),
+                    }
+                }
+            }
+            None => errors.push(FieldError::new(path, "expected an array of strings")),
+        },
+        FieldKind::Object(fields) => match value.as_object() {
+            Some(object) => validate_object(path, object, &[fields], errors),
+            None => errors.push(FieldError::new(path, "expected an object")),
+        },
+        FieldKind::ObjectList { fields, non_empty } => match value.as_array() {
+            Some(items) if non_empty && items.is_empty() => {
+                errors.push(FieldError::new(path, "must not be empty"));
+            }
+            Some(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    let item_path = format!("{}[{}]", path, i);
+                    match item.as_object() {
+                        Some(object) => validate_object(&item_path, object, &[fields], errors),
+                        None => errors.push(FieldError::new(item_path, "expected an object")),
+                    }
+                }
+            }
+            None => errors.push(FieldError::new(path, "expected an array of objects")),
+        },
+    }
+}
+
+fn unknown_node_type(path: String) -> FieldError {
+    let valid: Vec<&str> = canopy_core::NodeType::ALL
+        .iter()
+        .map(|t| t.as_str())
+        .collect();
+    FieldError::new(
+        path,
+        format!("unknown node type; expected one of: {}", valid.join(", ")),
+    )
+}
+
+/// JSON body extractor that applies [`RequestSchema`] unless the request is lenient.
+pub(crate) struct Validated<T>(pub(crate) T);
+
+impl<T: RequestSchema> FromRequest<SharedState> for Validated<T> {
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &SharedState) -> Result<Self, Self::Rejection> {
+        let lenient = req
+            .headers()
+            .get(LENIENT_HEADER)
+            .is_some_and(|value| value == "1");
+        let reject = |errors: Vec<FieldError>| {
+            state
+                .metrics
+                .invalid_requests
+                .fetch_add(1, Ordering::Relaxed);
+            AppError::invalid_request(errors)
+        };
+
+        let Json(body) = Json::<Value>::from_request(req, state)
+            .await
+            .map_err(|rejection| reject(vec![FieldError::new("body", rejection.body_text())]))?;
+
+        if !lenient {
+            let errors = validate::<T>(&body);
+            if !errors.is_empty() {
+                return Err(reject(errors));
+            }
+        }
+
+        serde_json::from_value(body)
+            .map(Validated)
+            .map_err(|e| reject(vec![FieldError::new("body", e.to_string())]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canopy_core::protocol::{EvidencePackConfig, ExpandHandle};
+    use canopy_core::QueryParams;
+    use serde_json::json;
+
+    fn fields(errors: &[FieldError]) -> Vec<&str> {
+        errors.iter().map(|e| e.field.as_str()).collect()
+    }
+
+    #[test]
+    fn query_lists_every_unknown_field() {
+        let errors = validate::<QueryRequest>(&json!({
+            "repo": "r", "pattern": "auth", "limt": 5, "globs": ["*.rs"]
+        }));
+        assert_eq!(fields(&errors), vec!["globs", "limt"]);
+        assert!(errors[0].message.contains("unknown field"));
+        assert!(errors[0].message.contains("glob"));
+    }
+
+    #[test]
+    fn query_checks_types_and_ranges() {
+        let errors = validate::<QueryRequest>(&json!({
+            "repo": 7, "pattern": "auth", "limit": 501, "kind": "function",
+            "patterns": ["a", 2], "expand_budget": -1
+        }));
+        assert_eq!(
+            fields(&errors),
+            vec!["repo", "patterns[1]", "kind", "limit", "expand_budget"]
+        );
+        assert_eq!(errors[3].message, "must be between 1 and 500");
+
+        let zero = validate::<QueryRequest>(&json!({"repo": "r", "pattern": "a", "limit": 0}));
+        assert_eq!(fields(&zero), vec!["limit"]);
+
+        let window = validate::<QueryRequest>(&json!({
+            "repo": "r", "pattern": "a", "modified_within": "a week"
+        }));
+        assert_eq!(fields(&window), vec!["modified_within"]);
+        assert!(window[0].message.contains("\"7d\""));
+        assert!(validate::<QueryRequest>(&json!({
+            "repo": "r", "pattern": "a", "modified_within": "48h"
+        }))
+        .is_empty());
+    }
+
+    #[test]
+    fn query_checks_priors() {
+        let errors = validate::<QueryRequest>(&json!({
+            "repo": "r", "pattern": "a",
+            "priors": {"section": 2.0, "fn": 1.5, "method": 0, "class": "high"}
+        }));
+        assert_eq!(
+            fields(&errors),
+            vec!["priors.class", "priors.fn", "priors.method"]
+        );
+        assert!(errors[1].message.contains("section, code_block"));
+        assert_eq!(errors[2].message, "expected a positive number");
+        assert!(validate::<QueryRequest>(&json!({
+            "repo": "r", "pattern": "a", "priors": {"function": 0.5}
+        }))
+        .is_empty());
+    }
+
+    #[test]
+    fn query_requires_a_search_target() {
+        let errors = validate::<QueryRequest>(&json!({"repo": "r", "glob": "*.rs"}));
+        assert_eq!(fields(&errors), vec!["pattern"]);
+        assert!(errors[0].message.contains("symbol"));
+
+        let empty = validate::<QueryRequest>(&json!({"repo": "r", "patterns": []}));
+        assert_eq!(fields(&empty), vec!["pattern"]);
+
+        assert!(validate::<QueryRequest>(&json!({"repo": "r", "kind": "annotation"})).is_empty());
+        assert!(validate::<QueryRequest>(&json!({"repo": "r", "kind": "commit"})).is_empty());
+        assert!(validate::<QueryRequest>(&json!({"repo": "r", "symbol": "main"})).is_empty());
+    }
+
+    #[test]
+    fn optional_fields_accept_null_required_do_not() {
+        assert!(validate::<QueryRequest>(&json!({
+            "repo": "r", "pattern": "a", "glob": null, "limit": null
+        }))
+        .is_empty());
+
+        let errors = validate::<QueryRequest>(&json!({"repo": null, "pattern": "a"}));
+        assert_eq!(fields(&errors), vec!["repo"]);
+        assert_eq!(errors[0].message, "is required");
+    }
+
+    #[test]
+    fn evidence_pack_checks_nested_config() {
+        let errors = validate::<EvidencePackRequest>(&json!({
+            "repo": "r", "pattern": "auth",
+            "config": {"max_handles": 65, "max_per_file": 0, "plan": "yes", "depth": 2}
+        }));
+        assert_eq!(
+            fields(&errors),
+            vec![
+                "config.depth",
+                "config.max_handles",
+                "config.max_per_file",
+                "config.plan"
+            ]
+        );
+        assert_eq!(errors[1].message, "must be between 1 and 64");
+
+        let errors = validate::<EvidencePackRequest>(&json!({"repo": "r", "config": {}}));
+        assert_eq!(fields(&errors), vec!["pattern"]);
+    }
+
+    #[test]
+    fn expand_requires_non_empty_handles() {
+        let errors = validate::<ExpandRequest>(&json!({"repo": "r", "handles": []}));
+        assert_eq!(fields(&errors), vec!["handles"]);
+        assert_eq!(errors[0].message, "must not be empty");
+
+        let errors = validate::<ExpandRequest>(&json!({
+            "repo": "r",
+            "handles": [{"id": "h1"}, {"generation": 3}, "h3", {"id": "h4", "gen": 1}]
+        }));
+        assert_eq!(
+            fields(&errors),
+            vec!["handles[1].id", "handles[2]", "handles[3].gen"]
+        );
+
+        let errors = validate::<ExpandRequest>(&json!({"repo": "r"}));
+        assert_eq!(fields(&errors), vec!["handles"]);
+
+        let errors = validate::<ExpandRequest>(&json!({
+            "repo": "r",
+            "handles": [{"id": "h1", "lines": "10-40"}, {"id": "h2", "lines": "40-10"}]
+        }));
+        assert_eq!(fields(&errors), vec!["handles[1].lines"]);
+    }
+
+    #[test]
+    fn repo_management_bodies() {
+        assert!(validate::<ReindexRequest>(&json!({"repo": "r", "glob": "**/*.rs"})).is_empty());
+        let errors = validate::<ReindexRequest>(&json!({"repo": "r", "force": true}));
+        assert_eq!(fields(&errors), vec!["force"]);
+        let errors =
+            validate::<ReindexRequest>(&json!({"repo": "r", "glob": "*", "paths": ["a.rs"]}));
+        assert_eq!(fields(&errors), vec!["paths"]);
+
+        let errors = validate::<AddRepoRequest>(&json!({"name": "x"}));
+        assert_eq!(fields(&errors), vec!["path"]);
+
+        let errors = validate::<AddRepoRequest>(&json!(["path"]));
+        assert_eq!(fields(&errors), vec!["body"]);
+
+        let errors = validate::<AddRepoRequest>(&json!({"path": "/r", "git_url": "u"}));
+        assert_eq!(fields(&errors), vec!["git_url"]);
+        let errors = validate::<AddRepoRequest>(&json!({"path": "/r", "branch": "main"}));
+        assert_eq!(fields(&errors), vec!["branch"]);
+        assert!(validate::<AddRepoRequest>(&json!({"git_url": "u", "branch": "main"})).is_empty());
+
+        assert!(validate::<PruneGenerationsRequest>(&json!({})).is_empty());
+        let errors = validate::<PruneGenerationsRequest>(&json!({"repo": "r", "retain": 0}));
+        assert_eq!(fields(&errors), vec!["retain"]);
+    }
+
+    #[test]
+    fn protocol_structs_pass_strict_validation() {
+        // ServiceClient serializes these structs; what it sends must always validate.
+        let params = QueryParams {
+            patterns: Some(vec!["a".to_string(), "b".to_string()]),
+            glob: Some("src/**".to_string()),
+            limit: Some(20),
+            expand_budget: Some(0),
+            ..QueryParams::default()
+        };
+        let query = QueryRequest {
+            repo: "r".to_string(),
+            params: params.clone(),
+        };
+        let pack = EvidencePackRequest {
+            repo: "r".to_string(),
+            params,
+            config: EvidencePackConfig {
+                max_handles: Some(8),
+                max_per_file: Some(2),
+                plan: Some(true),
+            },
+        };
+        let expand = ExpandRequest {
+            repo: "r".to_string(),
+            handles: vec![ExpandHandle {
+                id: "h1".to_string(),
+                generation: Some(3),
+                lines: Some("10-40".parse().unwrap()),
+            }],
+            auto_expanded: true,
+        };
+        let reindex = ReindexRequest {
+            repo: "r".to_string(),
+            glob: None,
+            paths: vec!["src/lib.rs".to_string()],
+        };
+        let add = AddRepoRequest {
+            path: Some("/repo".to_string()),
+            name: Some("repo".to_string()),
+            ..AddRepoRequest::default()
+        };
+        let clone = AddRepoRequest {
+            git_url: Some("https://example.com/repo.git".to_string()),
+            branch: Some("main".to_string()),
+            sparse_paths: Some(vec!["services/api".to_string()]),
+            ..AddRepoRequest::default()
+        };
+
+        let valid = |errors: Vec<FieldError>| assert!(errors.is_empty(), "{:?}", errors);
+        valid(validate::<QueryRequest>(
+            &serde_json::to_value(&query).unwrap(),
+        ));
+        valid(validate::<EvidencePackRequest>(
+            &serde_json::to_value(&pack).unwrap(),
+        ));
+        valid(validate::<ExpandRequest>(
+            &serde_json::to_value(&expand).unwrap(),
+        ));
+        valid(validate::<ReindexRequest>(
+            &serde_json::to_value(&reindex).unwrap(),
+        ));
+        valid(validate::<AddRepoRequest>(
+            &serde_json::to_value(&add).unwrap(),
+        ));
+        valid(validate::<AddRepoRequest>(
+            &serde_json::to_value(&clone).unwrap(),
+        ));
+    }
+}
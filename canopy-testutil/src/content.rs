@@ -0,0 +1,173 @@
+//! Source text for the builder's generated modules.
+
+/// `session` -> `Session`
+pub(crate) fn pascal(stem: &str) -> String {
+    let mut chars = stem.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+pub(crate) fn rust_module(dir: &str, stem: &str) -> Vec<(String, String)> {
+    let name = pascal(stem);
+    vec![
+        (
+            format!("{dir}/{stem}/mod.rs"),
+            "pub mod store;\npub mod token;\n".to_string(),
+        ),
+        (
+            format!("{dir}/{stem}/token.rs"),
+            format!(
+                r#"/// A {stem} credential.
+pub struct {name}Token {{
+    pub value: String,
+}}
+
+/// Whether a {stem} token may be stored.
+pub fn check_{stem}_token(token: &{name}Token) -> bool {{
+    !token.value.is_empty()
+}}
+"#
+            ),
+        ),
+        (
+            format!("{dir}/{stem}/store.rs"),
+            format!(
+                r#"use super::token::{{check_{stem}_token, {name}Token}};
+
+/// Holds the {stem} tokens handed out so far.
+pub struct {name}Store {{
+    tokens: Vec<{name}Token>,
+}}
+
+impl {name}Store {{
+    pub fn new() -> Self {{
+        Self {{ tokens: Vec::new() }}
+    }}
+
+    pub fn insert(&mut self, token: {name}Token) {{
+        self.tokens.push(token);
+    }}
+
+    pub fn validate(&self) -> bool {{
+        self.tokens.iter().all(|token| check_{stem}_token(token))
+    }}
+}}
+"#
+            ),
+        ),
+    ]
+}
+
+pub(crate) fn python_package(dir: &str, stem: &str) -> Vec<(String, String)> {
+    let name = pascal(stem);
+    vec![
+        (
+            format!("{dir}/{stem}/__init__.py"),
+            format!("from .service import load_{stem}_record\n"),
+        ),
+        (
+            format!("{dir}/{stem}/models.py"),
+            format!(
+                r#"class {name}Record:
+    """A stored {stem} record."""
+
+    class Meta:
+        table = "{stem}_records"
+
+    def __init__(self, key):
+        self.key = key
+
+    def serialize(self):
+        return {{"key": self.key, "table": self.Meta.table}}
+"#
+            ),
+        ),
+        (
+            format!("{dir}/{stem}/service.py"),
+            format!(
+                r#"from .models import {name}Record
+
+
+def load_{stem}_record(key):
+    record = {name}Record(key)
+    return record.serialize()
+"#
+            ),
+        ),
+    ]
+}
+
+pub(crate) fn typescript_module(dir: &str, stem: &str) -> Vec<(String, String)> {
+    let name = pascal(stem);
+    vec![
+        (
+            format!("{dir}/{stem}/format.ts"),
+            format!(
+                r#"export function format{name}Request(base: string, path: string): string {{
+  return `${{base}}/{stem}/${{path}}`;
+}}
+"#
+            ),
+        ),
+        (
+            format!("{dir}/{stem}/client.ts"),
+            format!(
+                r#"import {{ format{name}Request }} from "./format";
+
+export class {name}Client {{
+  private baseUrl: string;
+
+  constructor(baseUrl: string) {{
+    this.baseUrl = baseUrl;
+  }}
+
+  send(path: string): string {{
+    return format{name}Request(this.baseUrl, path);
+  }}
+
+  close(): void {{
+    this.baseUrl = "";
+  }}
+}}
+"#
+            ),
+        ),
+    ]
+}
+
+pub(crate) fn markdown_tree(title: &str, depth: usize, breadth: usize) -> String {
+    let mut out = format!("# {title}\n\nOverview of {title}.\n");
+    push_sections(&mut out, "", 2, depth + 1, breadth);
+    out
+}
+
+fn push_sections(out: &mut String, prefix: &str, level: usize, max: usize, breadth: usize) {
+    if level > max {
+        return;
+    }
+    for i in 1..=breadth {
+        let number = if prefix.is_empty() {
+            i.to_string()
+        } else {
+            format!("{prefix}.{i}")
+        };
+        let hashes = "#".repeat(level.min(6));
+        out.push_str(&format!(
+            "\n{hashes} Part {number}\n\nDetails for part {number}.\n"
+        ));
+        push_sections(out, &number, level + 1, max, breadth);
+    }
+}
+
+pub(crate) fn generated_rust(lines: usize) -> String {
+    let mut out = String::from("// @generated by fixture-gen. Do not edit by hand.\n\n");
+    for i in 0..lines {
+        out.push_str(&format!(
+            "pub const ENTRY_{i:05}: u32 = {};\n",
+            (i * 7919) % 100_000
+        ));
+    }
+    out
+}
@@ -0,0 +1,284 @@
+//! Fixture repositories for canopy tests.
+//!
+//! [`FixtureRepoBuilder`] lays out a temp repo declaratively: Rust, Python and
+//! TypeScript modules whose classes hold methods and whose files import and
+//! call each other, deep markdown heading trees, a large generated file, and
+//! optionally a git history of an initial commit plus a change. The
+//! [`presets`] bundle these into `small`, `medium`, `edge_cases` and
+//! `crowded_glob` repos.
+//!
+//! The crate only writes files and runs git. It doesn't depend on
+//! canopy-core, so canopy-core's own unit tests can use it too.
+
+mod content;
+pub mod presets;
+
+pub use presets::{crowded_glob, edge_cases, medium, small};
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+
+/// Run `git args` in `root`, asserting it succeeded.
+pub fn git(root: &Path, args: &[&str]) -> Output {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(root)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// Declarative description of a fixture repo; [`build`](Self::build) writes
+/// it to a fresh temp directory.
+#[derive(Debug, Clone, Default)]
+pub struct FixtureRepoBuilder {
+    files: BTreeMap<String, String>,
+    changes: BTreeMap<String, String>,
+    git: bool,
+}
+
+impl FixtureRepoBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file at a repo-relative path, replacing any earlier one.
+    pub fn file(mut self, path: impl Into<String>, content: impl Into<String>) -> Self {
+        self.files.insert(path.into(), content.into());
+        self
+    }
+
+    pub fn files(self, files: &[(&str, &str)]) -> Self {
+        files.iter().fold(self, |builder, (path, content)| {
+            builder.file(*path, *content)
+        })
+    }
+
+    /// `n` one-line Rust files `{dir}/file_{i}.rs`, each defining `func_{i}`
+    /// and `Struct{i}`.
+    pub fn numbered_rust_files(self, dir: &str, n: usize) -> Self {
+        (0..n).fold(self, |builder, i| {
+            builder.file(
+                format!("{dir}/file_{i}.rs"),
+                format!("fn func_{i}() {{ println!(\"hello from {i}\"); }}\nstruct Struct{i} {{ x: i32 }}\n"),
+            )
+        })
+    }
+
+    /// A Rust module `{dir}/{stem}/` for a lowercase `stem` like `session`:
+    /// `token.rs` defines `SessionToken` and `check_session_token`;
+    /// `store.rs` imports both and defines `SessionStore`, whose impl holds
+    /// `new`, `insert` and `validate`, the last calling `check_session_token`.
+    pub fn rust_module(mut self, dir: &str, stem: &str) -> Self {
+        self.files.extend(content::rust_module(dir, stem));
+        self
+    }
+
+    /// A Python package `{dir}/{stem}/`: `models.py` defines class
+    /// `BillingRecord` (for `billing`) with a nested class `Meta` and methods
+    /// `__init__` and `serialize`; `service.py` imports it and defines
+    /// `load_billing_record`, which calls both.
+    pub fn python_package(mut self, dir: &str, stem: &str) -> Self {
+        self.files.extend(content::python_package(dir, stem));
+        self
+    }
+
+    /// A TypeScript module `{dir}/{stem}/`: `format.ts` exports
+    /// `formatProfileRequest` (for `profile`); `client.ts` imports it and
+    /// exports class `ProfileClient` with methods `send`, which calls it, and
+    /// `close`.
+    pub fn typescript_module(mut self, dir: &str, stem: &str) -> Self {
+        self.files.extend(content::typescript_module(dir, stem));
+        self
+    }
+
+    /// A markdown file titled `title` whose sections nest `depth` levels
+    /// below the title, `breadth` per level: `Part 1`, `Part 1.2`,
+    /// `Part 1.2.1`, ... each with a paragraph naming its part.
+    pub fn markdown_tree(self, path: &str, title: &str, depth: usize, breadth: usize) -> Self {
+        let content = content::markdown_tree(title, depth, breadth);
+        self.file(path, content)
+    }
+
+    /// A Rust file of `lines` constant definitions behind an `@generated`
+    /// marker, the shape of a large checked-in code generator output.
+    pub fn generated_file(self, path: &str, lines: usize) -> Self {
+        let content = content::generated_rust(lines);
+        self.file(path, content)
+    }
+
+    /// Make the repo a git repo: every file lands in an initial commit, and
+    /// files given to [`change`](Self::change) in a second one.
+    pub fn with_git_history(mut self) -> Self {
+        self.git = true;
+        self
+    }
+
+    /// Write `content` at `path` after the initial files: in its own commit
+    /// with [`with_git_history`](Self::with_git_history), else simply in
+    /// their place.
+    pub fn change(mut self, path: impl Into<String>, content: impl Into<String>) -> Self {
+        self.changes.insert(path.into(), content.into());
+        self
+    }
+
+    /// Repo-relative paths of the files the initial commit holds.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+
+    pub fn build(self) -> FixtureRepo {
+        let repo = FixtureRepo {
+            dir: TempDir::new().unwrap(),
+        };
+        if self.git {
+            repo.init_git();
+        }
+        for (path, content) in &self.files {
+            repo.write_file(path, content);
+        }
+        if self.git {
+            repo.commit("initial");
+        }
+        if !self.changes.is_empty() {
+            for (path, content) in &self.changes {
+                repo.write(path, content);
+            }
+            if self.git {
+                repo.commit("change");
+            }
+        }
+        repo
+    }
+}
+
+/// A fixture repo in a temp directory, removed on drop.
+pub struct FixtureRepo {
+    dir: TempDir,
+}
+
+impl FixtureRepo {
+    /// A git repo holding `files` (repo-relative path, content) in one commit.
+    pub fn new(files: &[(&str, &str)]) -> Self {
+        FixtureRepoBuilder::new()
+            .files(files)
+            .with_git_history()
+            .build()
+    }
+
+    /// Git repo of two Rust files: `src/main.rs` (hello_world, add, Config)
+    /// and `src/lib.rs` (multiply, Database).
+    pub fn rust_sample() -> Self {
+        Self::new(&[
+            (
+                "src/main.rs",
+                r#"
+fn hello_world() {
+    println!("Hello, world!");
+}
+
+fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+struct Config {
+    name: String,
+    port: u16,
+}
+"#,
+            ),
+            (
+                "src/lib.rs",
+                r#"
+pub fn multiply(a: i32, b: i32) -> i32 {
+    a * b
+}
+
+pub struct Database {
+    url: String,
+}
+"#,
+            ),
+        ])
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Write a file without committing it. The mtime is pushed forward so an
+    /// edit made within the same timestamp tick as the last index still
+    /// registers as changed.
+    pub fn write(&self, rel: &str, content: &str) {
+        let path = self.write_file(rel, content);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+    }
+
+    pub fn remove_file(&self, rel: &str) {
+        std::fs::remove_file(self.path().join(rel)).unwrap();
+    }
+
+    /// Commit everything in the working tree.
+    pub fn commit(&self, message: &str) {
+        git(self.path(), &["add", "-A"]);
+        git(self.path(), &["commit", "-q", "-m", message]);
+    }
+
+    pub fn git(&self, args: &[&str]) -> Output {
+        git(self.path(), args)
+    }
+
+    /// Delete the working tree out from under whatever indexed it.
+    pub fn remove(&self) {
+        std::fs::remove_dir_all(self.dir.path()).unwrap();
+    }
+
+    fn init_git(&self) {
+        git(self.path(), &["init", "-q"]);
+        git(self.path(), &["config", "user.email", "test@test.com"]);
+        git(self.path(), &["config", "user.name", "Test"]);
+        git(self.path(), &["config", "commit.gpgsign", "false"]);
+    }
+
+    fn write_file(&self, rel: &str, content: &str) -> std::path::PathBuf {
+        let path = self.path().join(rel);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn git_history_holds_the_initial_files_then_the_change() {
+        let repo = FixtureRepoBuilder::new()
+            .rust_module("src", "session")
+            .change("src/session/token.rs", "pub struct SessionToken;\n")
+            .with_git_history()
+            .build();
+        let log = repo.git(&["log", "--format=%s"]);
+        assert_eq!(String::from_utf8_lossy(&log.stdout), "change\ninitial\n");
+        let status = repo.git(&["status", "--porcelain"]);
+        assert!(status.stdout.is_empty());
+        assert!(repo.path().join("src/session/store.rs").exists());
+    }
+}
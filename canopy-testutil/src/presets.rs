@@ -0,0 +1,210 @@
+//! Canned fixture repos. Each returns a builder, so a test can add files or
+//! [`with_git_history`](FixtureRepoBuilder::with_git_history) before building.
+
+use crate::FixtureRepoBuilder;
+
+/// Stems of the medium preset's modules; module `i` of each language is
+/// named `{MEDIUM_STEMS[i % 8]}{i / 8}`, e.g. `session0`, `audit3`.
+pub const MEDIUM_STEMS: [&str; 8] = [
+    "session", "billing", "profile", "audit", "cache", "search", "upload", "notify",
+];
+
+/// Per-language module counts of the medium preset.
+pub const MEDIUM_RUST_MODULES: usize = 40;
+pub const MEDIUM_PYTHON_PACKAGES: usize = 25;
+pub const MEDIUM_TYPESCRIPT_MODULES: usize = 25;
+
+/// `handler`s outside and inside `src/` in the crowded-glob preset.
+pub const CROWDED_OUTSIDE: usize = 30;
+pub const CROWDED_INSIDE: usize = 8;
+
+/// Name of module `i` of a language in the medium preset.
+pub fn medium_stem(i: usize) -> String {
+    format!(
+        "{}{}",
+        MEDIUM_STEMS[i % MEDIUM_STEMS.len()],
+        i / MEDIUM_STEMS.len()
+    )
+}
+
+/// One module per language and a markdown guide:
+///
+/// - `src/session/`: Rust `SessionStore` (`new`, `insert`, `validate`),
+///   `SessionToken`, `check_session_token`, and `revoke_session_token`,
+///   added by the change commit
+/// - `app/billing/`: Python `BillingRecord` (`Meta`, `__init__`,
+///   `serialize`) and `load_billing_record`
+/// - `web/profile/`: TypeScript `ProfileClient` (`send`, `close`) and
+///   `formatProfileRequest`
+/// - `docs/guide.md`: `Guide` with parts three levels deep, two per level
+pub fn small() -> FixtureRepoBuilder {
+    let builder = FixtureRepoBuilder::new()
+        .file("README.md", "# Fixture\n\nA small multi-language repo.\n")
+        .rust_module("src", "session")
+        .python_package("app", "billing")
+        .typescript_module("web", "profile")
+        .markdown_tree("docs/guide.md", "Guide", 3, 2);
+    let token = revoked("session");
+    builder.change("src/session/token.rs", token)
+}
+
+/// A few hundred files: [`MEDIUM_RUST_MODULES`] Rust modules under
+/// `crates/core/src/`, [`MEDIUM_PYTHON_PACKAGES`] Python packages under
+/// `app/`, [`MEDIUM_TYPESCRIPT_MODULES`] TypeScript modules under `web/`,
+/// and a markdown guide per stem under `docs/`. The change commit adds
+/// `revoke_session0_token`.
+pub fn medium() -> FixtureRepoBuilder {
+    let mut builder =
+        FixtureRepoBuilder::new().file("README.md", "# Fixture\n\nA medium multi-language repo.\n");
+    for i in 0..MEDIUM_RUST_MODULES {
+        builder = builder.rust_module("crates/core/src", &medium_stem(i));
+    }
+    for i in 0..MEDIUM_PYTHON_PACKAGES {
+        builder = builder.python_package("app", &medium_stem(i));
+    }
+    for i in 0..MEDIUM_TYPESCRIPT_MODULES {
+        builder = builder.typescript_module("web", &medium_stem(i));
+    }
+    for stem in MEDIUM_STEMS {
+        builder = builder.markdown_tree(&format!("docs/{stem}.md"), stem, 3, 3);
+    }
+    let token = revoked("session0");
+    builder.change("crates/core/src/session0/token.rs", token)
+}
+
+/// Files that stress parsing and indexing rather than ranking:
+///
+/// - `src/generated/tables.rs`: 3,000 constants behind an `@generated` marker
+/// - `src/shapes.rs`: trait `Shape` with default methods `area` and `scale`,
+///   implemented for `Circle`
+/// - `src/config.rs` and `app/config.py`: a `Config` with a `load` method in
+///   each language
+/// - `src/crlf.rs`: `crlf_line_endings`, with `\r\n` line endings
+/// - `src/long_line.rs`: `LONG_LINE`, a single 20k-character line, which
+///   reads as minified output
+/// - `src/a/b/c/d/e/f/leaf.rs`: `deeply_nested_leaf`
+/// - `docs/café.md`: non-ASCII path and headings (`Crème brûlée`)
+/// - `docs/deep.md`: `Deep` with one part per level down to `######`
+/// - `src/empty.rs` and `docs/empty.md`: empty files
+pub fn edge_cases() -> FixtureRepoBuilder {
+    FixtureRepoBuilder::new()
+        .generated_file("src/generated/tables.rs", 3_000)
+        .file(
+            "src/shapes.rs",
+            r#"pub trait Shape {
+    fn radius(&self) -> f64;
+
+    fn area(&self) -> f64 {
+        3.14 * self.radius() * self.radius()
+    }
+
+    fn scale(&self, factor: f64) -> f64 {
+        self.area() * factor
+    }
+}
+
+pub struct Circle {
+    pub r: f64,
+}
+
+impl Shape for Circle {
+    fn radius(&self) -> f64 {
+        self.r
+    }
+}
+"#,
+        )
+        .file(
+            "src/config.rs",
+            r#"pub struct Config {
+    pub path: String,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Self {
+        Config {
+            path: path.to_string(),
+        }
+    }
+}
+"#,
+        )
+        .file(
+            "app/config.py",
+            r#"class Config:
+    def load(self, path):
+        self.path = path
+        return self
+"#,
+        )
+        .file(
+            "src/crlf.rs",
+            "pub fn crlf_line_endings() -> u32 {\r\n    7\r\n}\r\n",
+        )
+        .file(
+            "src/long_line.rs",
+            format!("pub const LONG_LINE: &str = \"{}\";\n", "ab".repeat(10_000)),
+        )
+        .file(
+            "src/a/b/c/d/e/f/leaf.rs",
+            "pub fn deeply_nested_leaf() -> &'static str {\n    \"leaf\"\n}\n",
+        )
+        .file(
+            "docs/café.md",
+            "# Café Menu\n\n## Crème brûlée\n\nServed with a naïve résumé of the day.\n",
+        )
+        .markdown_tree("docs/deep.md", "Deep", 5, 1)
+        .file("src/empty.rs", "")
+        .file("docs/empty.md", "")
+}
+
+/// Matches outside `src/` in the way of matches inside it, for limits
+/// applied after a glob or a second pattern filters the search:
+///
+/// - `aaa/handler_{i}.rs`, [`CROWDED_OUTSIDE`] of them, sorting first: a
+///   `handler` calling `dispatch`
+/// - `src/handler_{i}.rs`, [`CROWDED_INSIDE`] of them: the same, with a
+///   `src_only` comment in its body
+/// - `lib/dispatch.rs`: `dispatch`
+pub fn crowded_glob() -> FixtureRepoBuilder {
+    let mut builder = FixtureRepoBuilder::new().file("lib/dispatch.rs", "pub fn dispatch() {}\n");
+    for i in 0..CROWDED_OUTSIDE {
+        builder = builder.file(
+            format!("aaa/handler_{i}.rs"),
+            "pub fn handler() {\n    dispatch();\n}\n",
+        );
+    }
+    for i in 0..CROWDED_INSIDE {
+        builder = builder.file(
+            format!("src/handler_{i}.rs"),
+            "pub fn handler() {\n    // src_only\n    dispatch();\n}\n",
+        );
+    }
+    builder
+}
+
+/// `token.rs` of the Rust module `stem` with a revoke function added.
+fn revoked(stem: &str) -> String {
+    let (_, token) = crate::content::rust_module("", stem)
+        .into_iter()
+        .find(|(path, _)| path.ends_with("token.rs"))
+        .unwrap();
+    format!(
+        "{token}\n/// Empty a {stem} token so it no longer validates.\n\
+         pub fn revoke_{stem}_token(token: &mut {name}Token) {{\n    token.value.clear();\n}}\n",
+        name = crate::content::pascal(stem)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn medium_holds_a_few_hundred_files() {
+        let count = medium().paths().count();
+        assert!((200..500).contains(&count), "{count} files");
+        assert_eq!(medium_stem(0), "session0");
+        assert_eq!(medium_stem(11), "audit1");
+    }
+}